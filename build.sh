@@ -7,6 +7,6 @@ cargo build -p relay --release
 cargo build -p riscv-vm --release
 
 cargo build -p mkfs --release --target wasm32-unknown-unknown --no-default-features
-cargo run -p mkfs -- --output target/riscv64gc-unknown-none-elf/release/fs.img --dir mkfs/root --size 2
+cargo run -p mkfs -- build --output target/riscv64gc-unknown-none-elf/release/fs.img --dir mkfs/root --reserve 1
 
 cd riscv-vm && yarn build && cd ..
@@ -1,6 +1,32 @@
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant), valid for any date after 1970
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
 
 fn main() {
     println!("cargo:rerun-if-changed=memory.x");
@@ -14,4 +40,21 @@ fn main() {
     println!("cargo:rustc-link-arg=-T{}", target_script.display());
     println!("cargo:rustc-link-arg=-T{}", link_script.display());
     println!("cargo:rerun-if-changed=build.rs");
+
+    // Build timestamp for `uname -v`; SOURCE_DATE_EPOCH keeps builds reproducible
+    let build_secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+    println!(
+        "cargo:rustc-env=KERNEL_BUILD_DATE={}",
+        format_utc(build_secs)
+    );
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Restamp whenever the kernel sources change
+    println!("cargo:rerun-if-changed=src");
 }
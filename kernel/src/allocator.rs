@@ -1,12 +1,35 @@
+use core::alloc::{GlobalAlloc, Layout};
+
 use linked_list_allocator::LockedHeap;
 
+use crate::lock::{preempt_disable, preempt_enable};
+
 unsafe extern "C" {
     static mut _sheap: u8;
     static mut _eheap: u8;
 }
 
+/// The heap, which like a [`crate::Spinlock`] keeps the hart from being
+/// preempted while it is locked
+struct KernelHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        preempt_disable();
+        let ptr = self.0.alloc(layout);
+        preempt_enable();
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        preempt_disable();
+        self.0.dealloc(ptr, layout);
+        preempt_enable();
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
 
 /// Initialize the heap allocator.
 /// Must be called before any heap allocations occur.
@@ -15,16 +38,18 @@ pub fn init() {
         let heap_start = &raw mut _sheap as *mut u8;
         let heap_end = &raw const _eheap as usize;
         let heap_size = heap_end - (heap_start as usize);
-        ALLOCATOR.lock().init(heap_start, heap_size);
+        ALLOCATOR.0.lock().init(heap_start, heap_size);
     }
 }
 
 /// Returns (used, free) bytes in the heap, if the allocator supports introspection.
 pub fn heap_stats() -> (usize, usize) {
-    let allocator = ALLOCATOR.lock();
-    let used = allocator.used();
-    let free = allocator.free();
-    (used, free)
+    preempt_disable();
+    let allocator = ALLOCATOR.0.lock();
+    let stats = (allocator.used(), allocator.free());
+    drop(allocator);
+    preempt_enable();
+    stats
 }
 
 /// Returns the total heap size.
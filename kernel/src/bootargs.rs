@@ -0,0 +1,91 @@
+//! Kernel command line
+//!
+//! The boot ROM enters the kernel with the address of a flattened device
+//! tree in a1 (0 if there is none). riscv-vm puts its `--bootargs` in the
+//! tree's `/chosen/bootargs` property, as space-separated options:
+//! - `plain`: plain-text output (see `setopt`) from the first line of the
+//!   boot log on
+//!
+//! Unknown options are ignored.
+
+use crate::uart;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// Largest device tree accepted; the boot ROM's is a few KiB
+const FDT_MAX_SIZE: usize = 64 * 1024;
+
+/// Apply the options in the device tree at `fdt_addr`.
+pub fn apply(fdt_addr: usize) {
+    let Some(args) = read(fdt_addr) else {
+        return;
+    };
+    for option in args.split_whitespace() {
+        if option == "plain" {
+            uart::set_plain(true);
+        }
+    }
+}
+
+/// `/chosen/bootargs` of the device tree at `fdt_addr`, if it has one.
+fn read(fdt_addr: usize) -> Option<&'static str> {
+    if fdt_addr == 0 || !fdt_addr.is_multiple_of(4) {
+        return None;
+    }
+    let header = unsafe { core::slice::from_raw_parts(fdt_addr as *const u8, 40) };
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let size = be32(header, 4)? as usize;
+    if !(40..=FDT_MAX_SIZE).contains(&size) {
+        return None;
+    }
+    let blob = unsafe { core::slice::from_raw_parts(fdt_addr as *const u8, size) };
+    let strings = blob.get(be32(blob, 12)? as usize..)?;
+
+    let mut pos = be32(blob, 8)? as usize;
+    let mut depth = 0;
+    let mut in_chosen = false;
+    loop {
+        let token = be32(blob, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(blob.get(pos..)?)?;
+                depth += 1;
+                in_chosen = depth == 2 && name == "chosen";
+                pos = (pos + name.len() + 4) & !3;
+            }
+            FDT_END_NODE => {
+                depth -= 1;
+                in_chosen = false;
+            }
+            FDT_PROP => {
+                let len = be32(blob, pos)? as usize;
+                let name = c_str(strings.get(be32(blob, pos + 4)? as usize..)?)?;
+                let value = blob.get(pos + 8..pos + 8 + len)?;
+                pos = (pos + 8 + len + 3) & !3;
+                if in_chosen && name == "bootargs" {
+                    return c_str(value);
+                }
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+/// The NUL-terminated string at the start of `bytes`.
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    let end = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..end]).ok()
+}
@@ -0,0 +1,96 @@
+//! Wall clock
+//!
+//! The CLINT only counts time since boot. The wall clock adds an offset to
+//! it, read from the goldfish RTC at boot (see `init_from_rtc`) and kept
+//! right by the SNTP client (see `sntp`). On a machine with neither the
+//! offset is zero and the clock reads midnight on Thursday 1 January 1970
+//! at boot, which keeps schedules and timestamps counting from a fixed
+//! point.
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+/// Unix time in milliseconds when the system booted
+static BOOT_UNIX_MS: AtomicI64 = AtomicI64::new(0);
+
+/// Whether the offset came from the RTC or a time server
+static SYNCED: AtomicBool = AtomicBool::new(false);
+
+/// Goldfish RTC, as riscv-vm and QEMU's virt board map it: nanoseconds
+/// since the epoch, with the high word latched when the low one is read
+const RTC_TIME_LOW: usize = 0x0010_1000;
+const RTC_TIME_HIGH: usize = 0x0010_1004;
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Milliseconds since the Unix epoch
+pub fn now_ms() -> i64 {
+    BOOT_UNIX_MS.load(Ordering::Relaxed) + crate::get_time_ms()
+}
+
+/// Seconds since the Unix epoch
+pub fn now_secs() -> i64 {
+    now_ms() / 1000
+}
+
+/// Whether the clock has been set, from the RTC or a time server
+pub fn is_synced() -> bool {
+    SYNCED.load(Ordering::Relaxed)
+}
+
+/// Set the clock to `unix_ms` as of uptime `at_ms`, returning how far it
+/// moved in milliseconds
+pub fn set(unix_ms: i64, at_ms: i64) -> i64 {
+    let old = BOOT_UNIX_MS.swap(unix_ms - at_ms, Ordering::Relaxed);
+    SYNCED.store(true, Ordering::Relaxed);
+    unix_ms - at_ms - old
+}
+
+/// Set the clock from the RTC, returning whether it had the time
+pub fn init_from_rtc() -> bool {
+    let ns = unsafe {
+        let low = core::ptr::read_volatile(RTC_TIME_LOW as *const u32);
+        let high = core::ptr::read_volatile(RTC_TIME_HIGH as *const u32);
+        (high as u64) << 32 | low as u64
+    };
+    if ns == 0 {
+        return false;
+    }
+    set((ns / 1_000_000) as i64, crate::get_time_ms());
+    true
+}
+
+/// Year, month (1-12) and day (1-31) of a day count since 1970-01-01
+pub fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// `secs` since the epoch in the style of `date`: `Thu Jan  1 00:00:00 UTC 1970`
+pub fn format_date(secs: i64) -> String {
+    let secs = secs.max(0) as u64;
+    let days = secs / 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{} {} {:>2} {:02}:{:02}:{:02} UTC {}",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        year
+    )
+}
@@ -0,0 +1,682 @@
+//! Long help pages for the shell commands in the registry.
+//!
+//! This file is data only and has no kernel dependencies: each registry
+//! entry points at its page here for `help <command>`, and mkfs includes the
+//! same file to write the pages to /usr/share/help/ in the disk image, so
+//! WASM programs and scripts can show them too.
+
+/// A worked example shown under `Examples:`.
+pub struct Example {
+    /// Command line as typed at the prompt.
+    pub command: &'static str,
+    /// What it does, one line.
+    pub explanation: &'static str,
+}
+
+/// Long help for one command.
+pub struct Manual {
+    /// Paragraphs separated by blank lines, pre-wrapped to 72 columns.
+    pub description: &'static str,
+    pub examples: &'static [Example],
+}
+
+/// Every page with its command name, in registry order.
+#[allow(dead_code)] // Read by mkfs; the kernel reaches pages through the registry
+pub static ALL: &[(&str, &Manual)] = &[
+    ("cd", &CD),
+    ("pwd", &PWD),
+    ("clear", &CLEAR),
+    ("help", &HELP),
+    ("setopt", &SETOPT),
+    ("setup", &SETUP),
+    ("shutdown", &SHUTDOWN),
+    ("node", &NODE),
+    ("jobs", &JOBS),
+    ("fg", &FG),
+    ("bg", &BG),
+    ("ping", &PING),
+    ("nslookup", &NSLOOKUP),
+    ("ip", &IP),
+    ("ifup", &IFUP),
+    ("dhclient", &DHCLIENT),
+    ("netstat", &NETSTAT),
+    ("nc", &NC),
+    ("ps", &PS),
+    ("top", &TOP),
+    ("kill", &KILL),
+    ("dmesg", &DMESG),
+    ("console2", &CONSOLE2),
+    ("memstats", &MEMSTATS),
+    ("df", &DF),
+    ("fsck", &FSCK),
+    ("sysinfo", &SYSINFO),
+    ("uname", &UNAME),
+    ("date", &DATE),
+    ("service", &SERVICE),
+    ("crontab", &CRONTAB),
+    ("ipc", &IPC),
+    ("wasm", &WASM),
+    ("mkdir", &MKDIR),
+    ("rm", &RM),
+    ("mv", &MV),
+    ("readsec", &READSEC),
+    ("alloc", &ALLOC),
+    ("memtest", &MEMTEST),
+    ("cputest", &CPUTEST),
+];
+
+// ── Built-ins ───────────────────────────────────────────────────────────────
+
+pub static CD: Manual = Manual {
+    description: "\
+Change the shell's working directory. Relative paths are resolved
+against the current directory; with no argument or `~` the shell
+returns to the root directory `/`.",
+    examples: &[
+        Example {
+            command: "cd /usr/bin",
+            explanation: "Enter the directory holding WASM programs",
+        },
+        Example {
+            command: "cd ..",
+            explanation: "Go up one level",
+        },
+    ],
+};
+
+pub static PWD: Manual = Manual {
+    description: "Print the absolute path of the working directory.",
+    examples: &[Example {
+        command: "pwd",
+        explanation: "Show where relative paths are resolved from",
+    }],
+};
+
+pub static CLEAR: Manual = Manual {
+    description: "\
+Scroll the terminal clear by printing blank lines. Works on any
+terminal, including ones without ANSI escape support.",
+    examples: &[Example {
+        command: "clear",
+        explanation: "Clear the screen",
+    }],
+};
+
+pub static HELP: Manual = Manual {
+    description: "\
+Without arguments, list every command grouped by category. With a
+command name (or alias), show its usage, flags, a longer description
+and examples, one screen at a time. Commands that are not built in,
+such as ls or cat, are described by the help program in /usr/bin.
+
+The same pages are installed as plain text in /usr/share/help/, and
+`<command> --help` prints a short usage summary.",
+    examples: &[
+        Example {
+            command: "help",
+            explanation: "List all commands",
+        },
+        Example {
+            command: "help top",
+            explanation: "Read the full page for top",
+        },
+        Example {
+            command: "cat /usr/share/help/ps",
+            explanation: "Read a page without the pager",
+        },
+    ],
+};
+
+pub static SETOPT: Manual = Manual {
+    description: "\
+Show or change how the shell renders output. `fancy` uses colours and
+box-drawing characters; `plain` strips ANSI escapes and replaces
+box-drawing with ASCII, for dumb terminals and log capture.
+`TERM=dumb` is accepted as a synonym for `plain`.",
+    examples: &[
+        Example {
+            command: "setopt",
+            explanation: "Show the current output mode",
+        },
+        Example {
+            command: "setopt plain",
+            explanation: "Switch to plain ASCII output",
+        },
+    ],
+};
+
+pub static SETUP: Manual = Manual {
+    description: "\
+Re-run the first-boot wizard that sets the hostname, user name, output
+theme and whether networking is brought up. Answers are saved to disk
+and used on the next boot.",
+    examples: &[Example {
+        command: "setup",
+        explanation: "Change the hostname or networking choice",
+    }],
+};
+
+pub static SHUTDOWN: Manual = Manual {
+    description: "\
+Stop all harts and power off the virtual machine through the test
+finisher device. Unsaved data in /tmp is lost.",
+    examples: &[Example {
+        command: "poweroff",
+        explanation: "Same as shutdown",
+    }],
+};
+
+pub static NODE: Manual = Manual {
+    description: "\
+The old script runner has been removed. Programs now ship as WASM
+binaries in /usr/bin/ and run by name.",
+    examples: &[Example {
+        command: "ls /usr/bin",
+        explanation: "List the installed programs",
+    }],
+};
+
+pub static JOBS: Manual = Manual {
+    description: "\
+List background jobs with their job number, PID, state and command
+line. `+` marks the current job, the default for `fg` and `bg`. Jobs
+that have finished are listed once more as Done and then forgotten.
+
+End a command line with `&` to start it as a job on a secondary hart;
+the prompt returns at once. Output redirection and pipes are not
+available for background jobs.",
+    examples: &[
+        Example {
+            command: "cowsay moo &",
+            explanation: "Run a program in the background",
+        },
+        Example {
+            command: "jobs",
+            explanation: "See which jobs are still running",
+        },
+    ],
+};
+
+pub static FG: Manual = Manual {
+    description: "\
+Bring a job to the foreground and wait for it to finish, resuming it
+if it was stopped. While waiting, Ctrl+Z stops the job and returns to
+the prompt, and Ctrl+C kills it. A WASM program stops at its next
+system call; native commands run to completion.",
+    examples: &[
+        Example {
+            command: "fg",
+            explanation: "Wait for the current job",
+        },
+        Example {
+            command: "fg %2",
+            explanation: "Wait for job 2",
+        },
+    ],
+};
+
+pub static BG: Manual = Manual {
+    description: "Resume a job stopped with Ctrl+Z, leaving it in the background.",
+    examples: &[Example {
+        command: "bg %1",
+        explanation: "Let job 1 continue behind the prompt",
+    }],
+};
+
+// ── Network ─────────────────────────────────────────────────────────────────
+
+pub static PING: Manual = Manual {
+    description: "\
+Send ICMP echo requests once per second and report round-trip times.
+Host names are resolved through DNS first. Press Ctrl+C to stop and
+print statistics.",
+    examples: &[
+        Example {
+            command: "ping 10.0.2.2",
+            explanation: "Ping the gateway",
+        },
+        Example {
+            command: "ping example.com",
+            explanation: "Resolve a name, then ping it",
+        },
+    ],
+};
+
+pub static NSLOOKUP: Manual = Manual {
+    description: "Resolve a host name to an IPv4 address using the configured DNS server.",
+    examples: &[Example {
+        command: "nslookup example.com",
+        explanation: "Look up an address",
+    }],
+};
+
+pub static IP: Manual = Manual {
+    description: "Show the interface's IPv4 address, gateway and MAC address.",
+    examples: &[Example {
+        command: "ip addr",
+        explanation: "Show the network configuration",
+    }],
+};
+
+pub static IFUP: Manual = Manual {
+    description: "\
+Probe the VirtIO network device and start the network stack if it is
+not running yet, e.g. after a safe-mode boot, which skips networking.",
+    examples: &[Example {
+        command: "ifup",
+        explanation: "Start networking",
+    }],
+};
+
+pub static DHCLIENT: Manual = Manual {
+    description: "\
+Ask the network's DHCP server for an address, gateway and DNS server
+and switch the interface to them. Networking does this at boot; the
+lease is then renewed in the background before it runs out. When no
+server answers, the static configuration stays in place. With `-r`
+the lease is given back and the static configuration restored.",
+    examples: &[
+        Example {
+            command: "dhclient",
+            explanation: "Get a new lease",
+        },
+        Example {
+            command: "dhclient -r",
+            explanation: "Release the lease",
+        },
+    ],
+};
+
+pub static NETSTAT: Manual = Manual {
+    description: "\
+Show the VirtIO network device and its configuration: MAC, IP
+address, gateway, DNS server and where they came from (a DHCP lease
+or the static settings), followed by the open TCP sockets of `nc`,
+`httpd` and other long-lived users.",
+    examples: &[Example {
+        command: "netstat",
+        explanation: "Check whether networking is up",
+    }],
+};
+
+pub static NC: Manual = Manual {
+    description: "\
+Open a raw TCP connection to <host> (an address or a name resolved
+through DNS) and <port>, or with `-l` wait for one client to connect.
+Lines typed are sent when Enter is pressed and whatever the peer sends
+is printed. Ctrl+D closes the sending side and keeps printing until the
+peer closes; Ctrl+C drops the connection.",
+    examples: &[
+        Example {
+            command: "nc example.com 80",
+            explanation: "Talk HTTP by hand",
+        },
+        Example {
+            command: "nc -l 7000",
+            explanation: "Wait for a connection on port 7000",
+        },
+    ],
+};
+
+// ── Native utilities ────────────────────────────────────────────────────────
+
+pub static PS: Manual = Manual {
+    description: "\
+List kernel tasks with their PID, state, priority, CPU time, uptime
+and name. States: R+ running, R ready, S sleeping, T stopped,
+Z finished.",
+    examples: &[Example {
+        command: "ps",
+        explanation: "List processes",
+    }],
+};
+
+pub static TOP: Manual = Manual {
+    description: "\
+Show a live view of processes, harts and memory, refreshed every
+second until Ctrl+C. Batch mode prints successive snapshots without
+clearing the screen, which suits redirection to a file.",
+    examples: &[
+        Example {
+            command: "top",
+            explanation: "Monitor interactively",
+        },
+        Example {
+            command: "top -b -n 3 > /tmp/top.log",
+            explanation: "Log three snapshots",
+        },
+    ],
+};
+
+pub static KILL: Manual = Manual {
+    description: "\
+Terminate the task with the given PID. Init (PID 1) cannot be killed;
+use `service` to stop the services it manages.",
+    examples: &[Example {
+        command: "kill 7",
+        explanation: "Terminate PID 7",
+    }],
+};
+
+pub static DMESG: Manual = Manual {
+    description: "\
+Print the kernel log ring buffer, oldest entry first. Each entry shows
+the time since boot, its level, the hart that logged it and the
+subsystem. The buffer holds the last 128 entries; critical ones and
+worse are also kept across reboots in /var/log/kern.log.
+
+Levels, most severe first: emerg, alert, crit, err, warn, notice,
+info, debug, trace.",
+    examples: &[
+        Example {
+            command: "dmesg -n 20",
+            explanation: "Show the last 20 entries",
+        },
+        Example {
+            command: "dmesg -l warn",
+            explanation: "Show warnings and errors only",
+        },
+        Example {
+            command: "dmesg -f",
+            explanation: "Watch new entries as they are logged",
+        },
+        Example {
+            command: "cat /var/log/kern.log",
+            explanation: "Critical entries from earlier boots",
+        },
+    ],
+};
+
+pub static CONSOLE2: Manual = Manual {
+    description: "\
+Show or change what the second console (a VirtIO console, e.g. the
+emulator's --console2) carries. The interactive shell stays on the
+UART either way.
+
+  log      Kernel log entries as plain lines, as they are logged (default)
+  control  Each line received is run as a shell command; the reply is
+           `OK <len>`, a newline and <len> bytes of output without colours
+  off      Nothing is sent and input is discarded
+
+Control commands run while the shell is idle at its prompt.",
+    examples: &[
+        Example {
+            command: "console2",
+            explanation: "Show whether there is a second console and its mode",
+        },
+        Example {
+            command: "console2 control",
+            explanation: "Let a test harness run commands over it",
+        },
+    ],
+};
+
+pub static MEMSTATS: Manual = Manual {
+    description: "Show kernel heap usage: total size, bytes in use and free bytes.",
+    examples: &[Example {
+        command: "memstats",
+        explanation: "Check heap usage",
+    }],
+};
+
+pub static DF: Manual = Manual {
+    description: "Show used and free space on the disk filesystem and /tmp.",
+    examples: &[Example {
+        command: "df",
+        explanation: "Check free disk space",
+    }],
+};
+
+pub static FSCK: Manual = Manual {
+    description: "\
+Walk every file's blocks on the disk filesystem (block chains on SFS,
+block pointers on ext2). Files whose blocks lie outside the disk or
+end early are reported as broken, and blocks in use that the bitmap
+lists as free are marked used again.
+Safe-mode boots run this automatically.",
+    examples: &[Example {
+        command: "fsck",
+        explanation: "Check the disk",
+    }],
+};
+
+pub static SYSINFO: Manual = Manual {
+    description: "\
+Display a summary of the system: kernel version, architecture,
+network and filesystem status, memory and uptime. Under riscv-vm it
+also shows the emulator's version, commit, host platform and enabled
+features; include this output when reporting a bug.",
+    examples: &[Example {
+        command: "sysinfo",
+        explanation: "Show the system summary",
+    }],
+};
+
+pub static UNAME: Manual = Manual {
+    description: "\
+Print the kernel name, hostname, release, build timestamp, machine and
+hardware platform. Under riscv-vm the platform names the emulator
+release; on other machines it is `unknown`. Flags can be combined and
+fields always print in `-a` order. The same line is reported to the
+emulator at boot, so hosts can tell which kernel a VM is running.",
+    examples: &[
+        Example {
+            command: "uname -a",
+            explanation: "Identify the system for a bug report",
+        },
+        Example {
+            command: "uname -r",
+            explanation: "Print just the kernel release",
+        },
+    ],
+};
+
+pub static DATE: Manual = Manual {
+    description: "\
+Print the date and time in UTC. The clock is read from the machine's
+RTC at boot, and the ntpd service sets it over SNTP once the network is
+up and every hour after, from the first `server <host>` line of
+/etc/ntp.conf (pool.ntp.org without one). With neither the clock starts
+at midnight on 1 January 1970 when the system boots, and date says it
+is not set. `-n` syncs right away.",
+    examples: &[
+        Example {
+            command: "date",
+            explanation: "Show the current time",
+        },
+        Example {
+            command: "date -n time.google.com",
+            explanation: "Set the clock from a given server",
+        },
+        Example {
+            command: "date +%s",
+            explanation: "Print a Unix timestamp",
+        },
+    ],
+};
+
+pub static SERVICE: Manual = Manual {
+    description: "\
+Control the services managed by init. Service definitions live in
+/etc/init.d/; `--list` shows them and `--status-all` shows which are
+running.",
+    examples: &[
+        Example {
+            command: "service --status-all",
+            explanation: "Show every service's state",
+        },
+        Example {
+            command: "service klogd restart",
+            explanation: "Restart the kernel log daemon",
+        },
+        Example {
+            command: "service httpd start",
+            explanation: "Serve the files in /www on port 80",
+        },
+    ],
+};
+
+pub static CRONTAB: Manual = Manual {
+    description: "\
+Manage /etc/crontab, the schedule the crond service runs commands from.
+Each entry is five fields and a command:
+
+  minute hour day-of-month month day-of-week command
+
+Fields take *, numbers, ranges (1-5), steps (*/15) and lists (1,30).
+Day-of-week 0 and 7 are Sunday. @reboot, @hourly, @daily, @weekly and
+@monthly replace the five fields. Commands run one at a time on hart 0,
+like background jobs (no pipes or redirection), and crond logs each run.
+
+Times are UTC. Until ntpd has set the clock (see `date`), it starts at
+midnight on 1 January 1970 when the system boots.",
+    examples: &[
+        Example {
+            command: "crontab -a */5 * * * * sysinfo",
+            explanation: "Run sysinfo every five minutes",
+        },
+        Example {
+            command: "crontab -a @reboot service httpd start",
+            explanation: "Start the web server on every boot",
+        },
+        Example {
+            command: "crontab -d 2",
+            explanation: "Delete the second entry",
+        },
+    ],
+};
+
+pub static IPC: Manual = Manual {
+    description: "\
+Work with named IPC endpoints. An endpoint is a message queue with a
+name; `bind` creates one owned by the shell, `send` queues a message
+to any bound endpoint, and `recv` takes the oldest message. With no
+arguments (or `ls`) bound endpoints are listed.
+
+Init listens on the `init` endpoint for `start|stop|restart <svc>`.",
+    examples: &[
+        Example {
+            command: "ipc bind demo",
+            explanation: "Create an endpoint",
+        },
+        Example {
+            command: "ipc send demo hello",
+            explanation: "Queue a message",
+        },
+        Example {
+            command: "ipc send init restart klogd",
+            explanation: "Ask init to restart a service",
+        },
+    ],
+};
+
+pub static WASM: Manual = Manual {
+    description: "\
+Run a WASM program from any path, not just /usr/bin, or inspect one.
+Programs import their system calls from the `env` module (see
+mkfs/src/lib.rs); `check` validates a module and lists its imports,
+flagging any the kernel doesn't provide, and `api` lists the ones it
+does.",
+    examples: &[
+        Example {
+            command: "wasm run /home/hello.wasm world",
+            explanation: "Run a program with one argument",
+        },
+        Example {
+            command: "wasm check /usr/bin/cowsay",
+            explanation: "Show what a program imports",
+        },
+    ],
+};
+
+pub static MKDIR: Manual = Manual {
+    description: "\
+Create one or more directories. Without -p, the parent must already
+exist.",
+    examples: &[
+        Example {
+            command: "mkdir /home/notes",
+            explanation: "Create a directory",
+        },
+        Example {
+            command: "mkdir -pv /tmp/a/b/c",
+            explanation: "Create a nested path, printing each step",
+        },
+    ],
+};
+
+pub static RM: Manual = Manual {
+    description: "\
+Remove files. Directories need -r, which removes everything below
+them first. -f ignores paths that do not exist.",
+    examples: &[
+        Example {
+            command: "rm /tmp/top.log",
+            explanation: "Remove a file",
+        },
+        Example {
+            command: "rm -rf /tmp/a",
+            explanation: "Remove a directory tree",
+        },
+    ],
+};
+
+pub static MV: Manual = Manual {
+    description: "\
+Move or rename a file or directory. A file already at the destination
+is replaced; if the destination is a directory, the source moves into
+it. Moves into or out of /tmp copy the file.",
+    examples: &[
+        Example {
+            command: "mv notes.txt /home/notes.txt",
+            explanation: "Move a file",
+        },
+        Example {
+            command: "mv /home/old /home/new",
+            explanation: "Rename a directory",
+        },
+    ],
+};
+
+// ── Debugging ───────────────────────────────────────────────────────────────
+
+pub static READSEC: Manual = Manual {
+    description: "Hex-dump one 512-byte sector of the VirtIO block device.",
+    examples: &[Example {
+        command: "readsec 0",
+        explanation: "Dump the filesystem superblock",
+    }],
+};
+
+pub static ALLOC: Manual = Manual {
+    description: "\
+Allocate a zeroed heap buffer and leak it on purpose, to test
+behaviour under memory pressure. The memory is only reclaimed by a
+reboot.",
+    examples: &[Example {
+        command: "alloc 1048576",
+        explanation: "Leak 1 MiB",
+    }],
+};
+
+pub static MEMTEST: Manual = Manual {
+    description: "\
+Allocate, fill and verify a 1 KiB buffer per iteration, then compare
+heap usage before and after to spot leaks. Defaults to 10 iterations.",
+    examples: &[Example {
+        command: "memtest 100",
+        explanation: "Run 100 iterations",
+    }],
+};
+
+pub static CPUTEST: Manual = Manual {
+    description: "\
+Count primes below a limit, first on one hart and then split across
+all harts online, and report the speedup. The serial run also reports
+the cycles and instructions counted by the hart's `mcycle` and
+`minstret`. Defaults to 100000.",
+    examples: &[Example {
+        command: "cputest 1000000",
+        explanation: "Benchmark with a larger range",
+    }],
+};
@@ -3,65 +3,21 @@ use core::ptr;
 use core::sync::atomic::Ordering;
 
 use crate::{
-    allocator, dns, net, scheduler, uart, BenchmarkMode, PingState, BENCHMARK, BLK_DEV,
+    allocator, dns, ident, net, scheduler, uart, BenchmarkMode, PingState, BENCHMARK, BLK_DEV,
     COMMAND_RUNNING, FS_STATE, HARTS_ONLINE, NET_STATE, PING_STATE, TEST_FINISHER,
 };
 use crate::{count_primes_in_range, cwd_get, cwd_set, get_time_ms, resolve_path, send_ipi};
+use crate::klog::{LogEntry, LogLevel, KLOG};
 use crate::{out_line, out_str};
 
+pub mod manual;
+pub mod pager;
+pub mod registry;
+
 // ═══════════════════════════════════════════════════════════════════════════════
 // NATIVE COMMANDS - Fast implementations in Rust (no scripting overhead)
 // ═══════════════════════════════════════════════════════════════════════════════
 
-/// Try to execute a native command. Returns true if handled, false if not found.
-/// Note: Many commands (ls, cat, echo, grep, tail, uptime, write) have been moved
-/// to WASM binaries in /usr/bin/ for better modularity.
-pub fn try_native(cmd: &str, args: &str) -> bool {
-    match cmd {
-        "ps" => {
-            native_ps();
-            true
-        }
-        "memstats" => {
-            native_memstats();
-            true
-        }
-        "kill" => {
-            native_kill(args);
-            true
-        }
-        "sysinfo" => {
-            native_sysinfo();
-            true
-        }
-        "ip" => {
-            native_ip(args);
-            true
-        }
-        "mkdir" => {
-            native_mkdir(args);
-            true
-        }
-        "netstat" => {
-            native_netstat();
-            true
-        }
-        "rm" => {
-            native_rm(args);
-            true
-        }
-        "service" => {
-            native_service(args);
-            true
-        }
-        "top" => {
-            native_top(args);
-            true
-        }
-        _ => false,
-    }
-}
-
 // NOTE: ls, cat, echo have been moved to WASM binaries in /usr/bin/
 
 /// ps - List processes (native implementation)
@@ -98,7 +54,7 @@ fn native_ps() {
     }
 
     out_line("");
-    out_line("\x1b[90mStates: R=Ready R+=Running S=Sleeping Z=Zombie\x1b[0m");
+    out_line("\x1b[90mStates: R=Ready R+=Running S=Sleeping T=Stopped Z=Zombie\x1b[0m");
 }
 
 // NOTE: uptime has been moved to WASM binary in /usr/bin/
@@ -173,11 +129,91 @@ fn native_memstats() {
     out_line("");
 }
 
+/// fsck - Check the disk filesystem and repair the bitmap
+fn native_fsck() {
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+        out_line("\x1b[1;31mError:\x1b[0m Filesystem not available");
+        return;
+    };
+    match fs.fsck(dev) {
+        Ok(report) => {
+            out_line(&format!(
+                "{} files, {} blocks in use",
+                report.files, report.blocks
+            ));
+            if report.repaired > 0 {
+                out_line(&format!(
+                    "Repaired: {} blocks marked used in the bitmap",
+                    report.repaired
+                ));
+            }
+            for name in &report.broken {
+                out_line(&format!("\x1b[1;31mBroken:\x1b[0m {}", name));
+            }
+            if report.broken.is_empty() {
+                out_line("\x1b[1;32mFilesystem OK\x1b[0m");
+            }
+        }
+        Err(e) => out_line(&format!("\x1b[1;31mError:\x1b[0m {}", e)),
+    }
+}
+
+/// df - Show filesystem usage (native implementation)
+fn native_df() {
+    let (disk_kind, disk_used, disk_total) = {
+        let fs_guard = FS_STATE.lock();
+        fs_guard.as_ref().map_or(("", 0, 0), |fs| {
+            let (used, total) = fs.disk_usage_bytes();
+            (fs.kind(), used, total)
+        })
+    };
+    let tmp = crate::tmpfs::stats();
+
+    out_line("\x1b[1;36mFilesystem      Size      Used     Avail  Use%  Mounted on\x1b[0m");
+    let row = |name: &str, total: u64, used: u64, mount: &str| {
+        let percent = if total > 0 { used * 100 / total } else { 0 };
+        out_line(&format!(
+            "{:<10} {:>6} KiB {:>5} KiB {:>5} KiB  {:>3}%  {}",
+            name,
+            total / 1024,
+            used / 1024,
+            total.saturating_sub(used) / 1024,
+            percent,
+            mount
+        ));
+    };
+    if disk_total > 0 {
+        row(disk_kind, disk_total, disk_used, "/");
+    }
+    row(
+        "tmpfs",
+        tmp.max_bytes as u64,
+        tmp.stored_bytes as u64,
+        "/tmp",
+    );
+
+    let ratio = if tmp.stored_bytes > 0 {
+        tmp.logical_bytes * 100 / tmp.stored_bytes
+    } else {
+        100
+    };
+    out_line(&format!(
+        "\x1b[90mtmpfs: {} entries, {} bytes stored as {} bytes (ratio {}.{:02}x)\x1b[0m",
+        tmp.files,
+        tmp.logical_bytes,
+        tmp.stored_bytes,
+        ratio / 100,
+        ratio % 100
+    ));
+}
+
 /// kill - Terminate a process (native implementation)
 fn native_kill(args: &str) {
     let pid_str = args.trim();
     if pid_str.is_empty() {
-        out_line("Usage: kill <pid>");
+        registry::print_usage("kill");
         out_line("");
         out_line("Terminate a process by its PID.");
         out_line("Use 'ps' to list running processes.");
@@ -202,9 +238,20 @@ fn native_kill(args: &str) {
     }
 }
 
+/// One `│  Label:  value  │` line of the sysinfo box
+fn sysinfo_row(label: &str, value: &str) {
+    out_str(&format!(
+        "\x1b[1;35m│\x1b[0m  {:<14}\x1b[1;97m{}\x1b[0m",
+        label, value
+    ));
+    for _ in 0..45usize.saturating_sub(value.len()) {
+        out_str(" ");
+    }
+    out_line("\x1b[1;35m│\x1b[0m");
+}
+
 /// sysinfo - Display system information (native implementation)
 fn native_sysinfo() {
-    let version = env!("CARGO_PKG_VERSION");
     let (used, _free) = allocator::heap_stats();
     let total = allocator::heap_size();
     let uptime_ms = get_time_ms();
@@ -215,19 +262,35 @@ fn native_sysinfo() {
     out_line("\x1b[1;35m│\x1b[0m              \x1b[1;97mBAVY OS System Information\x1b[0m                     \x1b[1;35m│\x1b[0m");
     out_line("\x1b[1;35m├─────────────────────────────────────────────────────────────┤\x1b[0m");
 
-    out_str(&format!(
-        "\x1b[1;35m│\x1b[0m  Kernel:       \x1b[1;97mBAVY OS v{}\x1b[0m",
-        version
-    ));
-    let pad = 44 - format!("BAVY OS v{}", version).len();
-    for _ in 0..pad {
-        out_str(" ");
-    }
-    out_line("\x1b[1;35m│\x1b[0m");
-
-    out_line("\x1b[1;35m│\x1b[0m  Architecture: \x1b[1;97mRISC-V 64-bit (RV64GC)\x1b[0m                       \x1b[1;35m│\x1b[0m");
+    sysinfo_row("Kernel:", &ident::os_version());
+    sysinfo_row("Built:", ident::BUILD_DATE);
+    sysinfo_row("Architecture:", ident::ARCHITECTURE);
     out_line("\x1b[1;35m│\x1b[0m  Mode:         \x1b[1;97mMachine Mode (M-Mode)\x1b[0m                        \x1b[1;35m│\x1b[0m");
-    out_line("\x1b[1;35m│\x1b[0m  Runtime:      \x1b[1;97mJavaScript + Native\x1b[0m                          \x1b[1;35m│\x1b[0m");
+
+    // Emulator build, so bug reports carry its exact provenance
+    match ident::emulator_info() {
+        Some(emu) => {
+            sysinfo_row(
+                "Emulator:",
+                &format!("riscv-vm {} ({})", emu.version, emu.git_hash),
+            );
+            sysinfo_row("Host:", &emu.host);
+            let jit = if emu.features & ident::EMU_FEATURE_JIT_ENABLED != 0 {
+                "on"
+            } else if emu.features & ident::EMU_FEATURE_JIT_BUILTIN != 0 {
+                "off"
+            } else {
+                "n/a"
+            };
+            sysinfo_row(
+                "Features:",
+                &format!("{} harts, JIT {}, net {}", emu.harts, jit, emu.network),
+            );
+        }
+        None => {
+            out_line("\x1b[1;35m│\x1b[0m  Runtime:      \x1b[1;97mJavaScript + Native\x1b[0m                          \x1b[1;35m│\x1b[0m");
+        }
+    }
     out_line("\x1b[1;35m│\x1b[0m                                                             \x1b[1;35m│\x1b[0m");
 
     // Network status
@@ -263,33 +326,116 @@ fn native_sysinfo() {
     out_line("\x1b[1;35m│\x1b[0m                                                             \x1b[1;35m│\x1b[0m");
 
     // Memory
-    let mem_str = format!("{} / {} KiB", used / 1024, total / 1024);
-    out_str(&format!(
-        "\x1b[1;35m│\x1b[0m  Memory:       \x1b[1;97m{}\x1b[0m",
-        mem_str
-    ));
-    let pad = 44 - mem_str.len();
-    for _ in 0..pad {
-        out_str(" ");
-    }
-    out_line("\x1b[1;35m│\x1b[0m");
+    sysinfo_row(
+        "Memory:",
+        &format!("{} / {} KiB", used / 1024, total / 1024),
+    );
 
     // Uptime
-    let uptime_str = format!("{} seconds", uptime_sec);
-    out_str(&format!(
-        "\x1b[1;35m│\x1b[0m  Uptime:       \x1b[1;97m{}\x1b[0m",
-        uptime_str
-    ));
-    let pad = 44 - uptime_str.len();
-    for _ in 0..pad {
-        out_str(" ");
-    }
-    out_line("\x1b[1;35m│\x1b[0m");
+    sysinfo_row("Uptime:", &format!("{} seconds", uptime_sec));
 
     out_line("\x1b[1;35m└─────────────────────────────────────────────────────────────┘\x1b[0m");
     out_line("");
 }
 
+/// uname - Print kernel and machine identification (native implementation)
+fn native_uname(args: &str) {
+    // Fields in `uname -a` order
+    const LONG: [&str; 7] = [
+        "--kernel-name",
+        "--nodename",
+        "--kernel-release",
+        "--kernel-version",
+        "--machine",
+        "--hardware-platform",
+        "--operating-system",
+    ];
+    const SHORT: &[u8; 7] = b"snrvmio";
+
+    let mut wanted = [false; 7];
+    for arg in args.split_whitespace() {
+        if arg == "--all" {
+            wanted = [true; 7];
+        } else if let Some(i) = LONG.iter().position(|&l| l == arg) {
+            wanted[i] = true;
+        } else if let Some(flags) = arg
+            .strip_prefix('-')
+            .filter(|f| !f.is_empty() && !f.starts_with('-'))
+        {
+            for c in flags.bytes() {
+                match SHORT.iter().position(|&s| s == c) {
+                    Some(i) => wanted[i] = true,
+                    None if c == b'a' => wanted = [true; 7],
+                    None => {
+                        out_str("\x1b[1;31muname:\x1b[0m unknown option -");
+                        out_line(&format!("{}", c as char));
+                        registry::print_usage("uname");
+                        return;
+                    }
+                }
+            }
+        } else {
+            out_str("\x1b[1;31muname:\x1b[0m unknown option ");
+            out_line(arg);
+            registry::print_usage("uname");
+            return;
+        }
+    }
+    if !wanted.contains(&true) {
+        wanted[0] = true;
+    }
+
+    let uname = ident::Uname::current();
+    let values: [&str; 7] = [
+        uname.sysname,
+        &uname.nodename,
+        uname.release,
+        &uname.version,
+        uname.machine,
+        &uname.platform,
+        uname.os,
+    ];
+    let fields: Vec<&str> = values
+        .iter()
+        .zip(wanted)
+        .filter(|(_, w)| *w)
+        .map(|(v, _)| *v)
+        .collect();
+    out_line(&fields.join(" "));
+}
+
+/// date - Print the wall clock, or set it over SNTP
+fn native_date(args: &str) {
+    use crate::{clock, sntp};
+
+    let mut parts = args.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (None, _, _) => {
+            let date = clock::format_date(clock::now_secs());
+            if clock::is_synced() {
+                out_line(&date);
+            } else {
+                out_line(&format!("{}  \x1b[0;90m(clock not set)\x1b[0m", date));
+            }
+        }
+        (Some("+%s"), None, _) => out_line(&format!("{}", clock::now_secs())),
+        (Some("-n" | "--ntp"), server, None) => {
+            let server = server.map_or_else(sntp::configured_server, String::from);
+            match sntp::sync(&server) {
+                Ok(adjust) => {
+                    out_line(&format!(
+                        "\x1b[1;32m✓\x1b[0m Clock set from {} ({:+} ms)",
+                        server, adjust
+                    ));
+                    out_line(&clock::format_date(clock::now_secs()));
+                }
+                Err(e) => out_line(&format!("\x1b[1;31mdate:\x1b[0m {}: {}", server, e)),
+            }
+        }
+        _ => registry::print_usage("date"),
+    }
+}
+
 // NOTE: grep has been moved to WASM binary in /usr/bin/
 
 /// ip - Show network configuration (native implementation)
@@ -297,7 +443,7 @@ fn native_ip(args: &str) {
     let show_addr = args.trim().is_empty() || args.trim() == "addr";
 
     if !show_addr {
-        out_line("Usage: ip addr");
+        registry::print_usage("ip");
         return;
     }
 
@@ -314,7 +460,7 @@ fn native_ip(args: &str) {
     let ip_str = core::str::from_utf8(&ip_buf[..ip_len]).unwrap_or("?");
 
     let mut gw_buf = [0u8; 16];
-    let gw_len = net::format_ipv4(net::GATEWAY, &mut gw_buf);
+    let gw_len = net::format_ipv4(net::get_gateway(), &mut gw_buf);
     let gw_str = core::str::from_utf8(&gw_buf[..gw_len]).unwrap_or("?");
 
     let net_guard = NET_STATE.lock();
@@ -336,7 +482,7 @@ fn native_ip(args: &str) {
     for _ in 0..pad { out_str(" "); }
     out_line("\x1b[1;34m│\x1b[0m");
 
-    let inet_str = format!("{}/{}", ip_str, net::PREFIX_LEN);
+    let inet_str = format!("{}/{}", ip_str, net::ip_config().prefix_len);
     out_str(&format!("\x1b[1;34m│\x1b[0m  \x1b[1;33minet\x1b[0m        {}", inet_str));
     let pad = 47 - inet_str.len();
     for _ in 0..pad { out_str(" "); }
@@ -374,7 +520,7 @@ fn native_mkdir(args: &str) {
     }
 
     if dirs.is_empty() {
-        out_line("Usage: mkdir [-pv] <directory...>");
+        registry::print_usage("mkdir");
         return;
     }
 
@@ -433,6 +579,73 @@ fn native_mkdir(args: &str) {
     }
 }
 
+/// ifup - Start networking if it is not already running
+fn native_ifup() {
+    if NET_STATE.lock().is_some() {
+        out_line("Network is already up");
+        return;
+    }
+    crate::init_network();
+    if NET_STATE.lock().is_none() {
+        out_line("\x1b[1;31mError:\x1b[0m Network could not be started");
+    }
+}
+
+/// dhclient - Get a DHCP lease, or give it back with -r
+fn native_dhclient(args: &str) {
+    let release = match args.trim() {
+        "" => false,
+        "-r" => true,
+        _ => {
+            registry::print_usage("dhclient");
+            return;
+        }
+    };
+
+    let mut net_guard = NET_STATE.lock();
+    let Some(state) = net_guard.as_mut() else {
+        out_line("\x1b[1;31m✗\x1b[0m Network not initialized");
+        return;
+    };
+
+    let format_ip = |ip| {
+        let mut buf = [0u8; 16];
+        let len = net::format_ipv4(ip, &mut buf);
+        String::from(core::str::from_utf8(&buf[..len]).unwrap_or("?"))
+    };
+
+    if release {
+        if state.dhcp_release(get_time_ms()) {
+            out_line(&format!(
+                "Lease released, using static address {}",
+                format_ip(net::get_my_ip())
+            ));
+        } else {
+            out_line("dhclient: no lease to release");
+        }
+        return;
+    }
+
+    out_line("Requesting DHCP lease...");
+    match state.dhcp_acquire(get_time_ms()) {
+        Some(lease) => {
+            out_line(&format!(
+                "\x1b[1;32m✓\x1b[0m Bound to \x1b[1;97m{}/{}\x1b[0m from {} for {}s",
+                format_ip(lease.addr),
+                lease.prefix_len,
+                format_ip(lease.server),
+                lease.lease_secs
+            ));
+            out_line(&format!("  Gateway: {}", format_ip(lease.gateway)));
+            out_line(&format!("  DNS:     {}", format_ip(lease.dns)));
+        }
+        None => out_line(&format!(
+            "\x1b[1;33m!\x1b[0m No DHCP server answered, keeping {}",
+            format_ip(net::get_my_ip())
+        )),
+    }
+}
+
 /// netstat - Show network statistics (native implementation)
 fn native_netstat() {
     let net_guard = NET_STATE.lock();
@@ -455,11 +668,11 @@ fn native_netstat() {
     let ip_str = core::str::from_utf8(&ip_buf[..ip_len]).unwrap_or("?");
 
     let mut gw_buf = [0u8; 16];
-    let gw_len = net::format_ipv4(net::GATEWAY, &mut gw_buf);
+    let gw_len = net::format_ipv4(net::get_gateway(), &mut gw_buf);
     let gw_str = core::str::from_utf8(&gw_buf[..gw_len]).unwrap_or("?");
 
     let mut dns_buf = [0u8; 16];
-    let dns_len = net::format_ipv4(net::DNS_SERVER, &mut dns_buf);
+    let dns_len = net::format_ipv4(net::get_dns_server(), &mut dns_buf);
     let dns_str = core::str::from_utf8(&dns_buf[..dns_len]).unwrap_or("?");
 
     out_line("");
@@ -478,7 +691,7 @@ fn native_netstat() {
     for _ in 0..pad { out_str(" "); }
     out_line("\x1b[1;35m│\x1b[0m");
 
-    let ip_full = format!("{}/{}", ip_str, net::PREFIX_LEN);
+    let ip_full = format!("{}/{}", ip_str, net::ip_config().prefix_len);
     out_str(&format!("\x1b[1;35m│\x1b[0m    IP:       \x1b[1;97m{}\x1b[0m", ip_full));
     let pad = 45 - ip_full.len();
     for _ in 0..pad { out_str(" "); }
@@ -494,14 +707,260 @@ fn native_netstat() {
     for _ in 0..pad { out_str(" "); }
     out_line("\x1b[1;35m│\x1b[0m");
 
+    let lease = NET_STATE
+        .lock()
+        .as_ref()
+        .and_then(|state| state.dhcp_lease(get_time_ms()));
+    let lease_str = match lease {
+        Some((lease, remaining)) => {
+            let mut buf = [0u8; 16];
+            let len = net::format_ipv4(lease.server, &mut buf);
+            let server = core::str::from_utf8(&buf[..len]).unwrap_or("?");
+            format!("DHCP from {}, {}s left", server, remaining)
+        }
+        None => String::from("static"),
+    };
+    out_str(&format!("\x1b[1;35m│\x1b[0m    Lease:    \x1b[1;97m{}\x1b[0m", lease_str));
+    let pad = 45usize.saturating_sub(lease_str.len());
+    for _ in 0..pad { out_str(" "); }
+    out_line("\x1b[1;35m│\x1b[0m");
+
     out_line("\x1b[1;35m│\x1b[0m                                                             \x1b[1;35m│\x1b[0m");
     out_line("\x1b[1;35m│\x1b[0m  \x1b[1;33mProtocol Stack:\x1b[0m                                            \x1b[1;35m│\x1b[0m");
     out_line("\x1b[1;35m│\x1b[0m    \x1b[1;97msmoltcp\x1b[0m - Lightweight TCP/IP stack                       \x1b[1;35m│\x1b[0m");
-    out_line("\x1b[1;35m│\x1b[0m    Protocols: ICMP, UDP, TCP, ARP                           \x1b[1;35m│\x1b[0m");
+    out_line("\x1b[1;35m│\x1b[0m    Protocols: ICMP, UDP, TCP, ARP, DHCP                     \x1b[1;35m│\x1b[0m");
+
+    let sockets = NET_STATE
+        .lock()
+        .as_mut()
+        .map(|state| state.tcp_list())
+        .unwrap_or_default();
+    if !sockets.is_empty() {
+        out_line("\x1b[1;35m│\x1b[0m                                                             \x1b[1;35m│\x1b[0m");
+        out_line("\x1b[1;35m│\x1b[0m  \x1b[1;33mTCP Sockets:\x1b[0m                                               \x1b[1;35m│\x1b[0m");
+        for socket in sockets {
+            let remote = match socket.remote {
+                Some((ip, port)) => {
+                    let mut buf = [0u8; 16];
+                    let len = net::format_ipv4(ip, &mut buf);
+                    format!("{}:{}", core::str::from_utf8(&buf[..len]).unwrap_or("?"), port)
+                }
+                None => String::from("*"),
+            };
+            let row = format!(
+                "    {:<8}:{:<6} {:<22} {:?}",
+                socket.owner, socket.local_port, remote, socket.state
+            );
+            out_str(&format!("\x1b[1;35m│\x1b[0m{}", row));
+            let pad = 61usize.saturating_sub(row.chars().count());
+            for _ in 0..pad { out_str(" "); }
+            out_line("\x1b[1;35m│\x1b[0m");
+        }
+    }
+
     out_line("\x1b[1;35m└─────────────────────────────────────────────────────────────┘\x1b[0m");
     out_line("");
 }
 
+/// Resolve a host given as an IPv4 address or a DNS name
+fn resolve_host(host: &str) -> Option<smoltcp::wire::Ipv4Address> {
+    if let Some(ip) = net::parse_ipv4(host.as_bytes()) {
+        return Some(ip);
+    }
+    let mut net_guard = NET_STATE.lock();
+    let state = net_guard.as_mut()?;
+    dns::resolve(state, host.as_bytes(), net::get_dns_server(), 5000, get_time_ms)
+}
+
+/// nc - Raw TCP connection: dial <host> <port>, or wait for a client with -l
+fn native_nc(args: &str) {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let (target, port) = match parts.as_slice() {
+        ["-l", port] => (None, *port),
+        [host, port] if !host.starts_with('-') => (Some(*host), *port),
+        _ => {
+            registry::print_usage("nc");
+            return;
+        }
+    };
+    let Ok(port) = port.parse::<u16>() else {
+        out_line("\x1b[1;31mnc:\x1b[0m invalid port");
+        return;
+    };
+    if NET_STATE.lock().is_none() {
+        out_line("\x1b[1;31m✗\x1b[0m Network not initialized");
+        return;
+    }
+
+    let remote = match target {
+        Some(host) => match resolve_host(host) {
+            Some(ip) => Some(ip),
+            None => {
+                out_str("\x1b[1;31mnc:\x1b[0m cannot resolve ");
+                out_line(host);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let opened = {
+        let mut net_guard = NET_STATE.lock();
+        let Some(state) = net_guard.as_mut() else {
+            return;
+        };
+        state.tcp_open("nc").and_then(|id| {
+            let started = match remote {
+                Some(ip) => state.tcp_dial(id, ip, port),
+                None => state.tcp_listen(id, port),
+            };
+            if started.is_err() {
+                state.tcp_release(id);
+            }
+            started.map(|()| id)
+        })
+    };
+    let id = match opened {
+        Ok(id) => id,
+        Err(e) => {
+            out_str("\x1b[1;31mnc:\x1b[0m ");
+            out_line(e);
+            return;
+        }
+    };
+
+    nc_session(id, remote.is_none());
+
+    if let Some(state) = NET_STATE.lock().as_mut() {
+        state.tcp_release(id);
+    }
+}
+
+/// Wait for the connection, then relay between it and the console
+fn nc_session(id: net::TcpId, listening: bool) {
+    use net::TcpState;
+
+    let status = |id| {
+        NET_STATE
+            .lock()
+            .as_mut()
+            .map_or(TcpState::Closed, |state| state.tcp_status(id))
+    };
+
+    let console = uart::Console::new();
+    let started = get_time_ms();
+    let mut last_task_run = started;
+    if listening {
+        out_line("\x1b[0;90mWaiting for a connection (Ctrl+C to cancel)...\x1b[0m");
+    }
+    loop {
+        crate::poll_network();
+        match status(id) {
+            TcpState::Established | TcpState::CloseWait => break,
+            TcpState::Closed if !listening => {
+                out_line("\x1b[1;31mnc:\x1b[0m connection refused");
+                return;
+            }
+            _ if !listening && get_time_ms() - started > 10_000 => {
+                out_line("\x1b[1;31mnc:\x1b[0m connection timed out");
+                return;
+            }
+            _ => {}
+        }
+        let now = get_time_ms();
+        if now - last_task_run >= 100 {
+            last_task_run = now;
+            crate::run_hart0_tasks();
+        }
+        if console.read_byte() == 0x03 {
+            out_line("^C");
+            return;
+        }
+    }
+
+    let peer = NET_STATE.lock().as_mut().and_then(|state| state.tcp_peer(id));
+    if let Some((ip, port)) = peer {
+        let mut buf = [0u8; 16];
+        let len = net::format_ipv4(ip, &mut buf);
+        let addr = core::str::from_utf8(&buf[..len]).unwrap_or("?");
+        let verb = if listening { "Connection from" } else { "Connected to" };
+        out_line(&format!("\x1b[0;90m{} {}:{}\x1b[0m", verb, addr, port));
+    }
+
+    let mut line: Vec<u8> = Vec::new();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        crate::poll_network();
+        let now = get_time_ms();
+        if now - last_task_run >= 100 {
+            last_task_run = now;
+            crate::run_hart0_tasks();
+        }
+
+        let (received, closed) = {
+            let mut net_guard = NET_STATE.lock();
+            let Some(state) = net_guard.as_mut() else {
+                return;
+            };
+            if !pending.is_empty() {
+                match state.tcp_write(id, &pending, now) {
+                    Ok(n) => {
+                        pending.drain(..n);
+                    }
+                    Err(_) => pending.clear(),
+                }
+            }
+            let mut received = Vec::new();
+            let closed = loop {
+                match state.tcp_read(id, &mut buf) {
+                    Ok(0) => break false,
+                    Ok(n) => received.extend_from_slice(&buf[..n]),
+                    Err(_) => break true,
+                }
+            };
+            (received, closed)
+        };
+        if !received.is_empty() {
+            crate::out_bytes(&received);
+        }
+        if closed {
+            out_line("\x1b[0;90mConnection closed by peer\x1b[0m");
+            return;
+        }
+
+        match console.read_byte() {
+            0 => {}
+            // Ctrl+C
+            0x03 => {
+                out_line("^C");
+                return;
+            }
+            // Ctrl+D: close our side, keep printing until the peer closes
+            0x04 => {
+                if let Some(state) = NET_STATE.lock().as_mut() {
+                    state.tcp_shutdown(id, now);
+                }
+            }
+            b'\r' | b'\n' => {
+                uart::write_str("\n");
+                line.push(b'\n');
+                pending.append(&mut line);
+            }
+            0x7f | 0x08 => {
+                if line.pop().is_some() {
+                    uart::write_str("\x08 \x08");
+                }
+            }
+            b if b >= 0x20 => {
+                uart::write_bytes(&[b]);
+                line.push(b);
+            }
+            _ => {}
+        }
+    }
+}
+
 /// rm - Remove files or directories (native implementation)
 fn native_rm(args: &str) {
     let mut recursive = false;
@@ -525,7 +984,7 @@ fn native_rm(args: &str) {
     }
 
     if files.is_empty() {
-        out_line("Usage: rm [-rfv] <file...>");
+        registry::print_usage("rm");
         return;
     }
 
@@ -564,8 +1023,10 @@ fn native_rm(args: &str) {
                     .filter(|f| f.name.starts_with(&prefix))
                     .map(|f| f.name.clone())
                     .collect();
-                // Sort by depth (deepest first)
-                children.sort_by(|a, b| b.matches('/').count().cmp(&a.matches('/').count()));
+                // Sort by depth (deepest first), a directory's own entry
+                // counting as deep as its contents
+                let depth = |name: &String| name.trim_end_matches('/').matches('/').count();
+                children.sort_by(|a, b| depth(b).cmp(&depth(a)));
 
                 for child in children {
                     if fs.remove(dev, &child).is_ok() && verbose {
@@ -605,12 +1066,60 @@ fn native_rm(args: &str) {
     }
 }
 
+/// mv - Move or rename a file or directory (native implementation)
+fn native_mv(args: &str) {
+    let mut verbose = false;
+    let mut paths: Vec<&str> = Vec::new();
+    for arg in args.split_whitespace() {
+        if arg == "-v" {
+            verbose = true;
+        } else {
+            paths.push(arg);
+        }
+    }
+    let [from, to] = paths[..] else {
+        registry::print_usage("mv");
+        return;
+    };
+
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+        out_line("\x1b[1;31mError:\x1b[0m Filesystem not available");
+        return;
+    };
+
+    let from = resolve_path(from);
+    let mut to = resolve_path(to);
+    // Moving onto a directory puts the source inside it
+    if fs.is_dir(dev, &to) {
+        let name = from.rsplit('/').next().unwrap_or("");
+        to = if to == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", to, name)
+        };
+    }
+
+    match fs.rename(dev, &from, &to) {
+        Ok(()) => {
+            if verbose {
+                out_line(&format!("renamed '{}' -> '{}'", from, to));
+            }
+        }
+        Err(e) => out_line(&format!(
+            "\x1b[1;31mmv:\x1b[0m cannot move '{}' to '{}': {}",
+            from, to, e
+        )),
+    }
+}
+
 /// service - Service management (native implementation)
 fn native_service(args: &str) {
     let parts: Vec<&str> = args.split_whitespace().collect();
 
     if parts.is_empty() {
-        out_line("Usage: service <name> {start|stop|restart|status}");
+        registry::print_usage("service");
         out_line("       service --list");
         return;
     }
@@ -720,6 +1229,302 @@ fn native_service(args: &str) {
     }
 }
 
+/// ipc - Inspect and use named IPC endpoints (native implementation)
+fn native_ipc(args: &str) {
+    let args = args.trim();
+    let (sub, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let (name, payload) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    match sub {
+        "" | "ls" | "list" => {
+            out_line("\x1b[1;36m   ID  OWNER  QUEUED  NAME\x1b[0m");
+            let channels = crate::ipc::IPC.list_channels();
+            if channels.is_empty() {
+                out_line("\x1b[90m  (no endpoints)\x1b[0m");
+            }
+            for (id, name, pending) in channels {
+                let owner = crate::ipc::IPC.get_channel(id).map_or(0, |ch| ch.owner());
+                out_line(&format!(
+                    "{:>5}  {:>5}  {:>6}  {}",
+                    id, owner, pending, name
+                ));
+            }
+        }
+        "bind" if !name.is_empty() => match crate::ipc::bind(name) {
+            Ok(id) => out_line(&format!("Bound '{}' (id={})", name, id)),
+            Err(e) => out_line(&format!("\x1b[1;31mipc:\x1b[0m {}: {}", name, e)),
+        },
+        "send" if !name.is_empty() => {
+            if let Err(e) = crate::ipc::send(name, payload.as_bytes()) {
+                out_line(&format!("\x1b[1;31mipc:\x1b[0m {}: {}", name, e));
+            }
+        }
+        "recv" if !name.is_empty() => match crate::ipc::recv(name) {
+            Ok(Some(msg)) => {
+                out_str(&format!("\x1b[90m[pid {}]\x1b[0m ", msg.sender));
+                out_line(&msg.as_str());
+            }
+            Ok(None) => out_line("\x1b[90m(no messages)\x1b[0m"),
+            Err(e) => out_line(&format!("\x1b[1;31mipc:\x1b[0m {}: {}", name, e)),
+        },
+        "close" if !name.is_empty() => {
+            if let Err(e) = crate::ipc::unbind(name) {
+                out_line(&format!("\x1b[1;31mipc:\x1b[0m {}: {}", name, e));
+            }
+        }
+        _ => registry::print_usage("ipc"),
+    }
+}
+
+/// dmesg - Print or follow the kernel log (native implementation)
+fn native_dmesg(args: &str) {
+    let mut count = usize::MAX;
+    let mut max_level = LogLevel::Trace;
+    let mut follow = false;
+    let mut clear = false;
+
+    let mut iter = args.split_whitespace();
+    while let Some(arg) = iter.next() {
+        match arg {
+            "-f" => follow = true,
+            "-c" => clear = true,
+            "-n" => match iter.next().and_then(|n| n.parse().ok()) {
+                Some(n) => count = n,
+                None => {
+                    out_line("\x1b[1;31mdmesg:\x1b[0m -n needs a count");
+                    return;
+                }
+            },
+            "-l" => match iter.next().and_then(LogLevel::parse) {
+                Some(level) => max_level = level,
+                None => {
+                    out_line("\x1b[1;31mdmesg:\x1b[0m unknown level (try err, warn, info)");
+                    return;
+                }
+            },
+            _ => {
+                registry::print_usage("dmesg");
+                return;
+            }
+        }
+    }
+
+    let print = |entries: &[LogEntry]| {
+        for entry in entries.iter().filter(|e| e.level <= max_level) {
+            out_line(&entry.format_colored());
+        }
+    };
+
+    let entries: Vec<LogEntry> = KLOG
+        .all()
+        .into_iter()
+        .filter(|e| e.level <= max_level)
+        .collect();
+    print(&entries[entries.len().saturating_sub(count)..]);
+    if clear {
+        KLOG.clear();
+    }
+    if !follow {
+        return;
+    }
+
+    let console = uart::Console::new();
+    let mut next = KLOG.sequence();
+    let mut last_task_run = get_time_ms();
+    loop {
+        crate::poll_network();
+        let now = get_time_ms();
+        if now - last_task_run >= 100 {
+            last_task_run = now;
+            crate::run_hart0_tasks();
+        }
+        let new = KLOG.since(next);
+        if let Some(last) = new.last() {
+            next = last.seq + 1;
+            print(&new);
+        }
+        if crate::jobs::checkpoint().is_err() {
+            return;
+        }
+        if console.read_byte() == 0x03 {
+            out_line("^C");
+            return;
+        }
+    }
+}
+
+/// crontab - List or edit /etc/crontab
+fn native_crontab(args: &str) {
+    use crate::crond;
+
+    let args = args.trim();
+    let (flag, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let text = crond::read_crontab().unwrap_or_default();
+    // Line numbers of the entries, skipping comments and blank lines
+    let entries: Vec<usize> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(crond::parse_line(line), Ok(None)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let new_text = match flag {
+        "" | "-l" if rest.is_empty() => {
+            if entries.is_empty() {
+                out_line("crontab: no entries");
+            }
+            for (n, &i) in entries.iter().enumerate() {
+                let line = text.lines().nth(i).unwrap_or("");
+                let mark = if crond::parse_line(line).is_err() {
+                    "  \x1b[1;31m(invalid)\x1b[0m"
+                } else {
+                    ""
+                };
+                out_line(&format!("{:>3}  {}{}", n + 1, line.trim(), mark));
+            }
+            return;
+        }
+        "-a" if !rest.is_empty() => {
+            if let Err(e) = crond::parse_line(rest) {
+                out_line(&format!("\x1b[1;31mcrontab:\x1b[0m {}", e));
+                return;
+            }
+            let mut new_text = text.clone();
+            if !new_text.is_empty() && !new_text.ends_with('\n') {
+                new_text.push('\n');
+            }
+            new_text.push_str(rest);
+            new_text.push('\n');
+            new_text
+        }
+        "-d" => {
+            let Some(&line) = rest
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| entries.get(n.wrapping_sub(1)))
+            else {
+                out_line("\x1b[1;31mcrontab:\x1b[0m no such entry (see crontab -l)");
+                return;
+            };
+            let mut new_text: String = text
+                .lines()
+                .enumerate()
+                .filter(|&(i, _)| i != line)
+                .map(|(_, l)| l)
+                .collect::<Vec<_>>()
+                .join("\n");
+            new_text.push('\n');
+            new_text
+        }
+        "-r" if rest.is_empty() => String::new(),
+        _ => {
+            registry::print_usage("crontab");
+            return;
+        }
+    };
+
+    if let Err(e) = crond::write_crontab(&new_text) {
+        out_line(&format!("\x1b[1;31mcrontab:\x1b[0m {}", e));
+    }
+}
+
+/// console2 - Show or set the mode of the second console
+fn native_console2(args: &str) {
+    use crate::virtio_console::{self, Mode};
+
+    let args = args.trim();
+    if !virtio_console::present() {
+        out_line("\x1b[1;31mconsole2:\x1b[0m no second console (start the VM with --console2)");
+        return;
+    }
+    if args.is_empty() {
+        out_line(&format!("console2: {}", virtio_console::mode().as_str()));
+        return;
+    }
+    match Mode::parse(args) {
+        Some(mode) => {
+            virtio_console::set_mode(mode);
+            out_line(&format!("console2: {}", mode.as_str()));
+        }
+        None => registry::print_usage("console2"),
+    }
+}
+
+/// wasm - Run or inspect a WASM program by path
+fn native_wasm(args: &str) {
+    let args = args.trim();
+    let (sub, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let (file, program_args) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    if sub == "api" {
+        for name in crate::wasm::HOST_FUNCTIONS {
+            out_line(&format!("env.{}", name));
+        }
+        return;
+    }
+    if !matches!(sub, "run" | "check") || file.is_empty() {
+        registry::print_usage("wasm");
+        return;
+    }
+
+    let path = resolve_path(file);
+    let bytes = {
+        let mut fs_guard = FS_STATE.lock();
+        let mut blk_guard = BLK_DEV.lock();
+        match (fs_guard.as_mut(), blk_guard.as_mut()) {
+            (Some(fs), Some(dev)) => fs.read_file(dev, &path),
+            _ => {
+                out_line("\x1b[1;31mwasm:\x1b[0m no filesystem");
+                return;
+            }
+        }
+    };
+    let Some(bytes) = bytes else {
+        out_line(&format!("\x1b[1;31mwasm:\x1b[0m {}: no such file", file));
+        return;
+    };
+    if !crate::wasm::is_wasm(&bytes) {
+        out_line(&format!("\x1b[1;31mwasm:\x1b[0m {}: not a WASM module", file));
+        return;
+    }
+
+    if sub == "run" {
+        let argv: Vec<&str> = program_args.split_whitespace().collect();
+        if let Err(e) = crate::wasm::execute(&bytes, &argv, None) {
+            out_str("\x1b[1;31mError:\x1b[0m ");
+            out_line(&e);
+        }
+        return;
+    }
+
+    match crate::wasm::imports(&bytes) {
+        Ok(imports) => {
+            let missing = imports.iter().filter(|(_, provided)| !provided).count();
+            for (name, provided) in &imports {
+                if *provided {
+                    out_line(&format!("  {}", name));
+                } else {
+                    out_line(&format!("  \x1b[1;31m{}\x1b[0m (not provided)", name));
+                }
+            }
+            if missing == 0 {
+                out_line(&format!("{}: OK, {} imports", file, imports.len()));
+            } else {
+                out_line(&format!(
+                    "{}: {} of {} imports not provided",
+                    file,
+                    missing,
+                    imports.len()
+                ));
+            }
+        }
+        Err(e) => out_line(&format!("\x1b[1;31mwasm:\x1b[0m {}: {}", file, e)),
+    }
+}
+
 // NOTE: tail has been moved to WASM binary in /usr/bin/
 
 /// Format uptime for display
@@ -763,17 +1568,17 @@ fn native_top(args: &str) {
         }
 
         let uptime = get_time_ms();
-        let version = env!("CARGO_PKG_VERSION");
+        let version = ident::os_version();
         let harts = HARTS_ONLINE.load(Ordering::Relaxed);
 
         // Header
         if batch_mode {
             out_line("═══════════════════════════════════════════════════════════════════");
-            out_str(&format!("BAVY OS v{} - {} up, {} hart(s)", version, format_uptime(uptime), harts));
+            out_str(&format!("{} - {} up, {} hart(s)", version, format_uptime(uptime), harts));
             out_line("");
         } else {
             out_line("\x1b[1;36m═══════════════════════════════════════════════════════════════════\x1b[0m");
-            out_str(&format!("\x1b[1;97m BAVY OS v{}\x1b[0m - {} up, \x1b[1;32m{}\x1b[0m hart(s)", version, format_uptime(uptime), harts));
+            out_str(&format!("\x1b[1;97m {}\x1b[0m - {} up, \x1b[1;32m{}\x1b[0m hart(s)", version, format_uptime(uptime), harts));
             out_line("");
         }
 
@@ -842,6 +1647,9 @@ fn native_top(args: &str) {
             // Sleep between iterations (1 second)
             let start = get_time_ms();
             while get_time_ms() - start < 1000 {
+                if crate::jobs::checkpoint().is_err() {
+                    return;
+                }
                 core::hint::spin_loop();
             }
             if !batch_mode {
@@ -858,74 +1666,51 @@ pub fn node(_args: &[u8]) {
     out_line("\x1b[1;33mNote:\x1b[0m The 'node' command (Rhai scripting) has been removed.");
     out_line("");
     out_line("Scripts are now WASM binaries in /usr/bin/");
-    out_line("Run them directly by name, e.g.: \x1b[1mhelp\x1b[0m, \x1b[1mcowsay\x1b[0m, \x1b[1mdmesg\x1b[0m");
+    out_line("Run them directly by name, e.g.: \x1b[1mhelp\x1b[0m, \x1b[1mcowsay\x1b[0m, \x1b[1mwget\x1b[0m");
     out_line("");
 }
 
+/// help - List commands, or show usage for a single command
+fn help_cmd(args: &str) {
+    let topic = args.trim();
+    if let Some(cmd) = registry::find(topic) {
+        registry::print_manual(cmd);
+        return;
+    }
+
+    // Prefer a help WASM binary if one is installed; it also documents
+    // the commands under /usr/bin
+    if let Some(script_bytes) = crate::scripting::find_script("help") {
+        crate::run_script_bytes("help", &script_bytes, args, None);
+        return;
+    }
+    if topic.is_empty() {
+        help();
+    } else {
+        out_str("\x1b[1;31mhelp:\x1b[0m no built-in command named ");
+        out_line(topic);
+    }
+}
+
+/// setopt - Show or change shell output options
+fn setopt(args: &str) {
+    match args.trim() {
+        "" => {
+            out_str("output: ");
+            out_line(if uart::is_plain() { "plain" } else { "fancy" });
+        }
+        "plain" | "TERM=dumb" => uart::set_plain(true),
+        "fancy" => uart::set_plain(false),
+        other => {
+            out_str("\x1b[1;31msetopt:\x1b[0m unknown option ");
+            out_line(other);
+            registry::print_usage("setopt");
+        }
+    }
+}
+
 pub fn help() {
-    out_line("\x1b[1;36m┌─────────────────────────────────────────────────────────────┐\x1b[0m");
-    out_line(
-        "\x1b[1;36m│\x1b[0m                   \x1b[1;97mBAVY OS Commands\x1b[0m                        \x1b[1;36m│\x1b[0m",
-    );
-    out_line("\x1b[1;36m├─────────────────────────────────────────────────────────────┤\x1b[0m");
-    out_line(
-        "\x1b[1;36m│\x1b[0m  \x1b[1;33mBuilt-in:\x1b[0m                                                 \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    cd <dir>        Change directory                         \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    pwd             Print working directory                  \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    clear           Clear the screen                         \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    shutdown        Power off the system                     \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    ping <host>     Ping host (Ctrl+C to stop)               \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    nslookup <host> DNS lookup                               \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m                                                             \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m  \x1b[1;33mWASM Programs:\x1b[0m  \x1b[0;90m(in /usr/bin/)\x1b[0m                          \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    ls, cat, echo, grep, tail, uptime, write                 \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    help, cowsay, dmesg, nano, pkg, wget, ...                \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m                                                             \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m  \x1b[1;33mNative Commands:\x1b[0m                                          \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    ps, top, memstats, sysinfo, kill, service                \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m    ip, netstat, mkdir, rm                                   \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m                                                             \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m  \x1b[1;33mRedirection:\x1b[0m  cmd > file | cmd >> file                    \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m                                                             \x1b[1;36m│\x1b[0m",
-    );
-    out_line(
-        "\x1b[1;36m│\x1b[0m  \x1b[1;32mTip:\x1b[0m  \x1b[1;97mCtrl+C\x1b[0m cancel  |  \x1b[1;97m↑/↓\x1b[0m history                    \x1b[1;36m│\x1b[0m",
-    );
-    out_line("\x1b[1;36m└─────────────────────────────────────────────────────────────┘\x1b[0m");
+    registry::print_overview();
 }
 
 pub fn alloc(args: &[u8]) {
@@ -938,7 +1723,7 @@ pub fn alloc(args: &[u8]) {
         uart::write_u64(n as u64);
         uart::write_line(" bytes (leaked).");
     } else {
-        uart::write_line("Usage: alloc <bytes>");
+        registry::print_usage("alloc");
     }
 }
 
@@ -990,6 +1775,9 @@ pub fn memtest(args: &[u8]) {
     let mut fail_count = 0usize;
 
     for i in 0..iterations {
+        if crate::jobs::checkpoint().is_err() {
+            return;
+        }
         let size = 1024;
         let pattern = ((i % 256) as u8).wrapping_add(0x42);
 
@@ -1034,6 +1822,22 @@ pub fn memtest(args: &[u8]) {
     }
 }
 
+/// Current `mcycle` and `minstret`.
+fn perf_counters() -> (u64, u64) {
+    let cycles: u64;
+    let instret: u64;
+    unsafe {
+        core::arch::asm!(
+            "csrr {}, mcycle",
+            "csrr {}, minstret",
+            out(reg) cycles,
+            out(reg) instret,
+            options(nomem, nostack),
+        );
+    }
+    (cycles, instret)
+}
+
 pub fn cputest(args: &[u8]) {
     let limit = {
         let n = parse_usize(args);
@@ -1072,9 +1876,14 @@ pub fn cputest(args: &[u8]) {
     uart::write_str("        Computing primes...");
 
     let serial_start = get_time_ms();
+    let (cycles_start, instret_start) = perf_counters();
     let serial_count = count_primes_in_range(2, limit as u64);
+    let (cycles_end, instret_end) = perf_counters();
     let serial_end = get_time_ms();
     let serial_time = serial_end - serial_start;
+    if uart::interrupted() {
+        return;
+    }
 
     uart::write_line(" done!");
     uart::write_str("        Result: \x1b[1;97m");
@@ -1082,6 +1891,24 @@ pub fn cputest(args: &[u8]) {
     uart::write_str("\x1b[0m primes found in \x1b[1;97m");
     uart::write_u64(serial_time as u64);
     uart::write_line("\x1b[0m ms");
+
+    let cycles = cycles_end.wrapping_sub(cycles_start);
+    let instret = instret_end.wrapping_sub(instret_start);
+    uart::write_str("        Cycles: ");
+    uart::write_u64(cycles);
+    uart::write_str(", instructions: ");
+    uart::write_u64(instret);
+    if instret > 0 {
+        let cpi_x100 = cycles * 100 / instret;
+        uart::write_str(", CPI: ");
+        uart::write_u64(cpi_x100 / 100);
+        uart::write_str(".");
+        if cpi_x100 % 100 < 10 {
+            uart::write_str("0");
+        }
+        uart::write_u64(cpi_x100 % 100);
+    }
+    uart::write_line("");
     uart::write_line("");
 
     if num_harts > 1 {
@@ -1120,6 +1947,9 @@ pub fn cputest(args: &[u8]) {
         let parallel_count = BENCHMARK.total_result();
 
         BENCHMARK.clear();
+        if uart::interrupted() {
+            return;
+        }
 
         uart::write_line(" done!");
         uart::write_str("        Result: \x1b[1;97m");
@@ -1219,7 +2049,7 @@ pub fn cputest(args: &[u8]) {
 
 pub fn ping(args: &[u8]) {
     if args.is_empty() {
-        uart::write_line("Usage: ping <ip|hostname>");
+        registry::print_usage("ping");
         uart::write_line("\x1b[0;90mExamples:\x1b[0m");
         uart::write_line("  ping 10.0.2.2");
         uart::write_line("  ping google.com");
@@ -1243,7 +2073,7 @@ pub fn ping(args: &[u8]) {
             let resolve_result = {
                 let mut net_guard = NET_STATE.lock();
                 if let Some(ref mut state) = *net_guard {
-                    dns::resolve(state, trimmed_args, net::DNS_SERVER, 5000, get_time_ms)
+                    dns::resolve(state, trimmed_args, net::get_dns_server(), 5000, get_time_ms)
                 } else {
                     uart::write_line("\x1b[1;31m✗\x1b[0m Network not initialized");
                     return;
@@ -1308,7 +2138,7 @@ pub fn ping(args: &[u8]) {
 
 pub fn nslookup(args: &[u8]) {
     if args.is_empty() {
-        uart::write_line("Usage: nslookup <hostname>");
+        registry::print_usage("nslookup");
         uart::write_line("\x1b[0;90mExample: nslookup google.com\x1b[0m");
         return;
     }
@@ -1322,7 +2152,7 @@ pub fn nslookup(args: &[u8]) {
     uart::write_line("");
     uart::write_str("\x1b[1;33mServer:\x1b[0m  ");
     let mut ip_buf = [0u8; 16];
-    let dns_len = net::format_ipv4(net::DNS_SERVER, &mut ip_buf);
+    let dns_len = net::format_ipv4(net::get_dns_server(), &mut ip_buf);
     uart::write_bytes(&ip_buf[..dns_len]);
     uart::write_line("");
     uart::write_line("\x1b[1;33mPort:\x1b[0m    53");
@@ -1335,7 +2165,7 @@ pub fn nslookup(args: &[u8]) {
     let resolve_result = {
         let mut net_guard = NET_STATE.lock();
         if let Some(ref mut state) = *net_guard {
-            dns::resolve(state, hostname, net::DNS_SERVER, 5000, get_time_ms)
+            dns::resolve(state, hostname, net::get_dns_server(), 5000, get_time_ms)
         } else {
             uart::write_line("\x1b[1;31m✗\x1b[0m Network not initialized");
             return;
@@ -0,0 +1,44 @@
+//! `more`-style pager for long command output.
+//!
+//! On the console, output stops after each screenful and waits for a key:
+//! space shows the next page, Enter the next line, and q or Ctrl+C quits.
+//! Redirected output is written straight through.
+
+use crate::uart;
+use crate::{out_line, out_str};
+
+/// Lines per screen, leaving a row for the prompt on a 24-row terminal.
+const PAGE_LINES: usize = 23;
+
+const PROMPT: &str = "\x1b[7m--More-- (space: page, enter: line, q: quit)\x1b[0m";
+
+/// Write `lines`, pausing after each screenful.
+pub fn page(lines: &[impl AsRef<str>]) {
+    let interactive = !crate::output_capturing();
+    let mut budget = PAGE_LINES;
+    for line in lines {
+        if interactive && budget == 0 {
+            out_str(PROMPT);
+            let key = wait_key();
+            // Erase the prompt
+            out_str("\r\x1b[K");
+            match key {
+                b'q' | b'Q' | 0x03 => return,
+                b'\r' | b'\n' => budget = 1,
+                _ => budget = PAGE_LINES,
+            }
+        }
+        out_line(line.as_ref());
+        budget = budget.saturating_sub(1);
+    }
+}
+
+fn wait_key() -> u8 {
+    let console = uart::Console::new();
+    loop {
+        match console.read_byte() {
+            0 => core::hint::spin_loop(),
+            key => return key,
+        }
+    }
+}
@@ -0,0 +1,765 @@
+//! Declarative command registry.
+//!
+//! Every shell command implemented inside the kernel is described once in
+//! [`COMMANDS`]. The table is the single source of truth for dispatch,
+//! the built-in `help` screen, per-command `--help` output and shell tab
+//! completion, so adding a command means adding one entry here (plus its
+//! long help page in [`super::manual`]).
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::manual::{self, Manual};
+use super::pager;
+use crate::{out_line, out_str};
+
+/// Groups used to lay out the `help` screen.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// Shell built-ins that need direct kernel or shell state.
+    Builtin,
+    /// Network diagnostics driven by the event loop.
+    Network,
+    /// Native utilities implemented in Rust for speed.
+    Native,
+    /// Low-level debugging and benchmarking helpers.
+    Debug,
+}
+
+impl Category {
+    const ALL: [Category; 4] = [
+        Category::Builtin,
+        Category::Network,
+        Category::Native,
+        Category::Debug,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Category::Builtin => "Built-in:",
+            Category::Network => "Network:",
+            Category::Native => "Native Commands:",
+            Category::Debug => "Debugging:",
+        }
+    }
+}
+
+/// A single command-line flag accepted by a command.
+pub struct Flag {
+    /// Flag spelling as typed, e.g. `-p` or `-n <count>`.
+    pub spec: &'static str,
+    /// One-line description.
+    pub help: &'static str,
+}
+
+/// Metadata and entry point for one shell command.
+pub struct Command {
+    pub name: &'static str,
+    /// Alternative names that dispatch to the same handler.
+    pub aliases: &'static [&'static str],
+    pub category: Category,
+    /// One-line description shown in `help`.
+    pub summary: &'static str,
+    /// Synopsis without the leading `Usage: `.
+    pub usage: &'static str,
+    pub flags: &'static [Flag],
+    /// Long description and examples shown by `help <command>`.
+    pub manual: &'static Manual,
+    /// Handler receiving the raw argument string.
+    pub handler: fn(&str),
+}
+
+impl Command {
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| *a == name)
+    }
+}
+
+/// All kernel-implemented commands.
+pub static COMMANDS: &[Command] = &[
+    // ── Built-ins ───────────────────────────────────────────────────────────
+    Command {
+        name: "cd",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Change directory",
+        usage: "cd [dir|~]",
+        flags: &[],
+        manual: &manual::CD,
+        handler: super::cd,
+    },
+    Command {
+        name: "pwd",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Print working directory",
+        usage: "pwd",
+        flags: &[],
+        manual: &manual::PWD,
+        handler: |_| out_line(&crate::cwd_get()),
+    },
+    Command {
+        name: "clear",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Clear the screen",
+        usage: "clear",
+        flags: &[],
+        manual: &manual::CLEAR,
+        handler: |_| {
+            for _ in 0..50 {
+                out_line("");
+            }
+        },
+    },
+    Command {
+        name: "help",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Show commands, or usage for one command",
+        usage: "help [command]",
+        flags: &[],
+        manual: &manual::HELP,
+        handler: super::help_cmd,
+    },
+    Command {
+        name: "setopt",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Show or set output mode",
+        usage: "setopt [plain|fancy]",
+        flags: &[],
+        manual: &manual::SETOPT,
+        handler: super::setopt,
+    },
+    Command {
+        name: "setup",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Re-run the first-boot setup wizard",
+        usage: "setup",
+        flags: &[],
+        manual: &manual::SETUP,
+        handler: |_| crate::setup::run_wizard(),
+    },
+    Command {
+        name: "shutdown",
+        aliases: &["poweroff"],
+        category: Category::Builtin,
+        summary: "Power off the system",
+        usage: "shutdown",
+        flags: &[],
+        manual: &manual::SHUTDOWN,
+        handler: |_| super::shutdown(),
+    },
+    Command {
+        name: "node",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Legacy script runner (removed)",
+        usage: "node",
+        flags: &[],
+        manual: &manual::NODE,
+        handler: |a| super::node(a.as_bytes()),
+    },
+    Command {
+        name: "jobs",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "List background jobs",
+        usage: "jobs",
+        flags: &[],
+        manual: &manual::JOBS,
+        handler: crate::jobs::list,
+    },
+    Command {
+        name: "fg",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Wait for a job in the foreground",
+        usage: "fg [%job]",
+        flags: &[],
+        manual: &manual::FG,
+        handler: crate::jobs::fg,
+    },
+    Command {
+        name: "bg",
+        aliases: &[],
+        category: Category::Builtin,
+        summary: "Resume a stopped job in the background",
+        usage: "bg [%job]",
+        flags: &[],
+        manual: &manual::BG,
+        handler: crate::jobs::bg,
+    },
+    // ── Network ─────────────────────────────────────────────────────────────
+    Command {
+        name: "ping",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Ping host (Ctrl+C to stop)",
+        usage: "ping <ip|hostname>",
+        flags: &[],
+        manual: &manual::PING,
+        handler: |a| super::ping(a.as_bytes()),
+    },
+    Command {
+        name: "nslookup",
+        aliases: &[],
+        category: Category::Network,
+        summary: "DNS lookup",
+        usage: "nslookup <hostname>",
+        flags: &[],
+        manual: &manual::NSLOOKUP,
+        handler: |a| super::nslookup(a.as_bytes()),
+    },
+    Command {
+        name: "ip",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Show network configuration",
+        usage: "ip addr",
+        flags: &[],
+        manual: &manual::IP,
+        handler: super::native_ip,
+    },
+    Command {
+        name: "ifup",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Bring up networking",
+        usage: "ifup",
+        flags: &[],
+        manual: &manual::IFUP,
+        handler: |_| super::native_ifup(),
+    },
+    Command {
+        name: "dhclient",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Get or release a DHCP lease",
+        usage: "dhclient",
+        flags: &[Flag {
+            spec: "-r",
+            help: "Release the lease and use the static configuration",
+        }],
+        manual: &manual::DHCLIENT,
+        handler: super::native_dhclient,
+    },
+    Command {
+        name: "netstat",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Show network status",
+        usage: "netstat",
+        flags: &[],
+        manual: &manual::NETSTAT,
+        handler: |_| super::native_netstat(),
+    },
+    Command {
+        name: "nc",
+        aliases: &[],
+        category: Category::Network,
+        summary: "Raw TCP connection (client or server)",
+        usage: "nc <host> <port>",
+        flags: &[Flag {
+            spec: "-l <port>",
+            help: "Wait for one incoming connection on <port>",
+        }],
+        manual: &manual::NC,
+        handler: super::native_nc,
+    },
+    // ── Native utilities ────────────────────────────────────────────────────
+    Command {
+        name: "ps",
+        aliases: &[],
+        category: Category::Native,
+        summary: "List processes",
+        usage: "ps",
+        flags: &[],
+        manual: &manual::PS,
+        handler: |_| super::native_ps(),
+    },
+    Command {
+        name: "top",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Process monitor",
+        usage: "top [-b] [-n <count>]",
+        flags: &[
+            Flag {
+                spec: "-b",
+                help: "Batch mode (no screen clearing)",
+            },
+            Flag {
+                spec: "-n <count>",
+                help: "Number of iterations",
+            },
+        ],
+        manual: &manual::TOP,
+        handler: super::native_top,
+    },
+    Command {
+        name: "kill",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Terminate a process by PID",
+        usage: "kill <pid>",
+        flags: &[],
+        manual: &manual::KILL,
+        handler: super::native_kill,
+    },
+    Command {
+        name: "dmesg",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Show the kernel log",
+        usage: "dmesg [-c] [-f] [-l <level>] [-n <count>]",
+        flags: &[
+            Flag {
+                spec: "-f",
+                help: "Follow: keep printing new entries until Ctrl+C",
+            },
+            Flag {
+                spec: "-l <level>",
+                help: "Only entries at this level or more severe",
+            },
+            Flag {
+                spec: "-n <count>",
+                help: "Only the last <count> entries",
+            },
+            Flag {
+                spec: "-c",
+                help: "Clear the log after printing it",
+            },
+        ],
+        manual: &manual::DMESG,
+        handler: super::native_dmesg,
+    },
+    Command {
+        name: "console2",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Show or set what the second console carries",
+        usage: "console2 [log|control|off]",
+        flags: &[],
+        manual: &manual::CONSOLE2,
+        handler: super::native_console2,
+    },
+    Command {
+        name: "memstats",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Show heap statistics",
+        usage: "memstats",
+        flags: &[],
+        manual: &manual::MEMSTATS,
+        handler: |_| super::native_memstats(),
+    },
+    Command {
+        name: "df",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Show filesystem usage",
+        usage: "df",
+        flags: &[],
+        manual: &manual::DF,
+        handler: |_| super::native_df(),
+    },
+    Command {
+        name: "fsck",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Check and repair the disk filesystem",
+        usage: "fsck",
+        flags: &[],
+        manual: &manual::FSCK,
+        handler: |_| super::native_fsck(),
+    },
+    Command {
+        name: "sysinfo",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Display system information",
+        usage: "sysinfo",
+        flags: &[],
+        manual: &manual::SYSINFO,
+        handler: |_| super::native_sysinfo(),
+    },
+    Command {
+        name: "uname",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Print kernel and machine identification",
+        usage: "uname [-asnrvmio]",
+        flags: &[
+            Flag {
+                spec: "-a, --all",
+                help: "All fields, in the order below",
+            },
+            Flag {
+                spec: "-s, --kernel-name",
+                help: "Kernel name (default)",
+            },
+            Flag {
+                spec: "-n, --nodename",
+                help: "Hostname",
+            },
+            Flag {
+                spec: "-r, --kernel-release",
+                help: "Kernel release",
+            },
+            Flag {
+                spec: "-v, --kernel-version",
+                help: "Kernel build timestamp",
+            },
+            Flag {
+                spec: "-m, --machine",
+                help: "Machine hardware name",
+            },
+            Flag {
+                spec: "-i, --hardware-platform",
+                help: "Emulator build, or unknown",
+            },
+            Flag {
+                spec: "-o, --operating-system",
+                help: "Operating system name",
+            },
+        ],
+        manual: &manual::UNAME,
+        handler: super::native_uname,
+    },
+    Command {
+        name: "date",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Print the date and time, or set them from a time server",
+        usage: "date [+%s] | -n [server]",
+        flags: &[
+            Flag {
+                spec: "+%s",
+                help: "Seconds since the Unix epoch",
+            },
+            Flag {
+                spec: "-n, --ntp [server]",
+                help: "Set the clock over SNTP (default: /etc/ntp.conf)",
+            },
+        ],
+        manual: &manual::DATE,
+        handler: super::native_date,
+    },
+    Command {
+        name: "service",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Manage init services",
+        usage: "service <name> {start|stop|restart|status}",
+        flags: &[
+            Flag {
+                spec: "-l, --list",
+                help: "List available service definitions",
+            },
+            Flag {
+                spec: "-a, --status-all",
+                help: "Show status of all services",
+            },
+        ],
+        manual: &manual::SERVICE,
+        handler: super::native_service,
+    },
+    Command {
+        name: "crontab",
+        aliases: &[],
+        category: Category::Native,
+        summary: "List or edit the commands crond runs on schedule",
+        usage: "crontab [-l] | -a <schedule> <command> | -d <n> | -r",
+        flags: &[
+            Flag {
+                spec: "-l",
+                help: "List the entries, numbered (the default)",
+            },
+            Flag {
+                spec: "-a <entry>",
+                help: "Add an entry after checking it",
+            },
+            Flag {
+                spec: "-d <n>",
+                help: "Delete entry <n>",
+            },
+            Flag {
+                spec: "-r",
+                help: "Remove every entry",
+            },
+        ],
+        manual: &manual::CRONTAB,
+        handler: super::native_crontab,
+    },
+    Command {
+        name: "ipc",
+        aliases: &[],
+        category: Category::Native,
+        summary: "List, bind and use named IPC endpoints",
+        usage: "ipc [ls | bind <name> | send <name> <message> | recv <name> | close <name>]",
+        flags: &[],
+        manual: &manual::IPC,
+        handler: super::native_ipc,
+    },
+    Command {
+        name: "wasm",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Run or inspect a WASM program",
+        usage: "wasm {run <file> [args...] | check <file> | api}",
+        flags: &[],
+        manual: &manual::WASM,
+        handler: super::native_wasm,
+    },
+    Command {
+        name: "mkdir",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Create directories",
+        usage: "mkdir [-pv] <directory...>",
+        flags: &[
+            Flag {
+                spec: "-p",
+                help: "Create parent directories as needed",
+            },
+            Flag {
+                spec: "-v",
+                help: "Print each directory created",
+            },
+        ],
+        manual: &manual::MKDIR,
+        handler: super::native_mkdir,
+    },
+    Command {
+        name: "rm",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Remove files or directories",
+        usage: "rm [-rfv] <file...>",
+        flags: &[
+            Flag {
+                spec: "-r",
+                help: "Remove directories recursively",
+            },
+            Flag {
+                spec: "-f",
+                help: "Ignore missing files",
+            },
+            Flag {
+                spec: "-v",
+                help: "Print each file removed",
+            },
+        ],
+        manual: &manual::RM,
+        handler: super::native_rm,
+    },
+    Command {
+        name: "mv",
+        aliases: &[],
+        category: Category::Native,
+        summary: "Move or rename files and directories",
+        usage: "mv [-v] <source> <destination>",
+        flags: &[Flag {
+            spec: "-v",
+            help: "Print the move",
+        }],
+        manual: &manual::MV,
+        handler: super::native_mv,
+    },
+    // ── Debugging ───────────────────────────────────────────────────────────
+    Command {
+        name: "readsec",
+        aliases: &[],
+        category: Category::Debug,
+        summary: "Dump a disk sector",
+        usage: "readsec <sector>",
+        flags: &[],
+        manual: &manual::READSEC,
+        handler: |a| super::readsec(a.as_bytes()),
+    },
+    Command {
+        name: "alloc",
+        aliases: &[],
+        category: Category::Debug,
+        summary: "Allocate a heap buffer (leaked)",
+        usage: "alloc <bytes>",
+        flags: &[],
+        manual: &manual::ALLOC,
+        handler: |a| super::alloc(a.as_bytes()),
+    },
+    Command {
+        name: "memtest",
+        aliases: &[],
+        category: Category::Debug,
+        summary: "Heap allocator stress test",
+        usage: "memtest [iterations]",
+        flags: &[],
+        manual: &manual::MEMTEST,
+        handler: |a| super::memtest(a.as_bytes()),
+    },
+    Command {
+        name: "cputest",
+        aliases: &["cpuTest"],
+        category: Category::Debug,
+        summary: "Multi-hart prime counting benchmark",
+        usage: "cputest [limit]",
+        flags: &[],
+        manual: &manual::CPUTEST,
+        handler: |a| super::cputest(a.as_bytes()),
+    },
+];
+
+/// Look up a command by name or alias.
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.matches(name))
+}
+
+/// Dispatch `name` through the registry. Returns false if no command matched.
+///
+/// A `--help` argument anywhere on the line prints the command's usage
+/// instead of running it.
+pub fn dispatch(name: &str, args: &str) -> bool {
+    let Some(cmd) = find(name) else {
+        return false;
+    };
+    if args.split_whitespace().any(|a| a == "--help") {
+        print_help(cmd);
+    } else {
+        (cmd.handler)(args);
+    }
+    true
+}
+
+/// Print the one-line usage synopsis for a registered command.
+pub fn print_usage(name: &str) {
+    if let Some(cmd) = find(name) {
+        out_str("Usage: ");
+        out_line(cmd.usage);
+    }
+}
+
+/// Print usage, summary, aliases and flags for a command.
+pub fn print_help(cmd: &Command) {
+    out_str("\x1b[1mUsage:\x1b[0m ");
+    out_line(cmd.usage);
+    out_line("");
+    out_str("  ");
+    out_line(cmd.summary);
+    if !cmd.aliases.is_empty() {
+        out_line("");
+        out_str("\x1b[1mAliases:\x1b[0m ");
+        out_line(&cmd.aliases.join(", "));
+    }
+    if !cmd.flags.is_empty() {
+        out_line("");
+        out_line("\x1b[1mFlags:\x1b[0m");
+        for flag in cmd.flags {
+            out_line(&format!("  {:<18} {}", flag.spec, flag.help));
+        }
+    }
+}
+
+/// Show the full page for a command (usage, description, aliases, flags
+/// and examples) through the pager.
+pub fn print_manual(cmd: &Command) {
+    let mut lines: Vec<String> = Vec::new();
+    lines.push(format!("\x1b[1;97m{}\x1b[0m - {}", cmd.name, cmd.summary));
+    lines.push(String::new());
+    lines.push(format!("\x1b[1;33mUsage:\x1b[0m {}", cmd.usage));
+    lines.push(String::new());
+    lines.push(String::from("\x1b[1;33mDescription:\x1b[0m"));
+    for line in cmd.manual.description.lines() {
+        if line.is_empty() {
+            lines.push(String::new());
+        } else {
+            lines.push(format!("  {}", line));
+        }
+    }
+    if !cmd.aliases.is_empty() {
+        lines.push(String::new());
+        lines.push(format!(
+            "\x1b[1;33mAliases:\x1b[0m {}",
+            cmd.aliases.join(", ")
+        ));
+    }
+    if !cmd.flags.is_empty() {
+        lines.push(String::new());
+        lines.push(String::from("\x1b[1;33mFlags:\x1b[0m"));
+        for flag in cmd.flags {
+            lines.push(format!("  \x1b[1m{:<18}\x1b[0m {}", flag.spec, flag.help));
+        }
+    }
+    if !cmd.manual.examples.is_empty() {
+        lines.push(String::new());
+        lines.push(String::from("\x1b[1;33mExamples:\x1b[0m"));
+        for example in cmd.manual.examples {
+            lines.push(format!("  \x1b[1;32m$\x1b[0m {}", example.command));
+            lines.push(format!("      \x1b[0;90m{}\x1b[0m", example.explanation));
+        }
+    }
+    pager::page(&lines);
+}
+
+/// Command names (not aliases) starting with `prefix`, in table order.
+pub fn complete<'a>(prefix: &'a str) -> impl Iterator<Item = &'static str> + 'a {
+    COMMANDS
+        .iter()
+        .map(|c| c.name)
+        .filter(move |n| n.starts_with(prefix))
+}
+
+/// Render the built-in `help` screen from the registry.
+pub fn print_overview() {
+    const WIDTH: usize = 61;
+    let border = "\x1b[1;36m│\x1b[0m";
+    let row = |text: &str, styled: &str| {
+        let pad = WIDTH.saturating_sub(text.chars().count());
+        let mut line = String::from(border);
+        line.push_str(styled);
+        for _ in 0..pad {
+            line.push(' ');
+        }
+        line.push_str(border);
+        out_line(&line);
+    };
+
+    out_line("\x1b[1;36m┌─────────────────────────────────────────────────────────────┐\x1b[0m");
+    row(
+        "                   BAVY OS Commands",
+        "                   \x1b[1;97mBAVY OS Commands\x1b[0m",
+    );
+    out_line("\x1b[1;36m├─────────────────────────────────────────────────────────────┤\x1b[0m");
+    for category in Category::ALL {
+        let title = format!("  {}", category.title());
+        row(&title, &format!("  \x1b[1;33m{}\x1b[0m", category.title()));
+        for cmd in COMMANDS.iter().filter(|c| c.category == category) {
+            let text = format!("    {:<16}{}", cmd.name, cmd.summary);
+            row(&text, &text);
+        }
+        row("", "");
+    }
+    row(
+        "  WASM Programs:  (in /usr/bin/)",
+        "  \x1b[1;33mWASM Programs:\x1b[0m  \x1b[0;90m(in /usr/bin/)\x1b[0m",
+    );
+    let wasm = "    ls, cat, echo, grep, tail, wc, uptime, write, cowsay, ...";
+    row(wasm, wasm);
+    row("", "");
+    row(
+        "  Redirection:  cmd > file, cmd >> file",
+        "  \x1b[1;33mRedirection:\x1b[0m  cmd > file, cmd >> file",
+    );
+    row(
+        "  Pipes:        cmd | grep text | wc -l",
+        "  \x1b[1;33mPipes:\x1b[0m        cmd | grep text | wc -l",
+    );
+    row(
+        "  Tip:  <command> --help  |  Ctrl+C cancel  |  ↑/↓ history",
+        "  \x1b[1;32mTip:\x1b[0m  \x1b[1;97m<command> --help\x1b[0m  |  \x1b[1;97mCtrl+C\x1b[0m cancel  |  \x1b[1;97m↑/↓\x1b[0m history",
+    );
+    out_line("\x1b[1;36m└─────────────────────────────────────────────────────────────┘\x1b[0m");
+}
@@ -0,0 +1,286 @@
+//! Scheduled commands
+//!
+//! The `crond` service runs the commands in `/etc/crontab` when their
+//! schedule matches, checking once a minute. Each line is
+//!
+//! ```text
+//! # minute hour day-of-month month day-of-week command
+//! */5 * * * * sysinfo
+//! 0 3 * * 0 /usr/bin/cleanup.wasm --old
+//! @reboot echo booted
+//! ```
+//!
+//! Fields take `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, and
+//! comma-separated lists of those; day-of-week counts from 0 (Sunday, also
+//! 7). When both day fields are restricted either one matching is enough.
+//! `@reboot`, `@hourly`, `@daily`, `@weekly` and `@monthly` stand in for
+//! the five fields.
+//!
+//! Commands are anything a background job can run: built-in commands or
+//! programs found on the `PATH`, without pipes or redirection. They run one
+//! after another inside the service task on hart 0, and their output goes
+//! to the console.
+//!
+//! Schedules follow the wall clock in UTC (see `clock`). Until `ntpd` has
+//! set it, the clock reads midnight on Thursday 1 January 1970 at boot.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::klog::{klog_info, klog_warning};
+use crate::{BLK_DEV, FS_STATE};
+
+/// Service name, as used with `service crond start`
+pub const SERVICE: &str = "crond";
+
+/// File the schedule is read from
+pub const CRONTAB: &str = "/etc/crontab";
+
+/// Pause between checks of the clock
+const POLL_INTERVAL_MS: u64 = 1000;
+
+/// Minute last checked, so a restarted service doesn't run a minute twice
+static LAST_MINUTE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Whether the `@reboot` entries have run this boot
+static REBOOT_DONE: AtomicBool = AtomicBool::new(false);
+
+/// When an entry runs
+enum Schedule {
+    Reboot,
+    At {
+        /// Bit n set when minute n matches
+        minutes: u64,
+        hours: u64,
+        days: u64,
+        months: u64,
+        weekdays: u64,
+        /// Whether the day fields were `*`
+        any_day: bool,
+        any_weekday: bool,
+    },
+}
+
+/// One line of the crontab
+pub struct Entry {
+    schedule: Schedule,
+    pub command: String,
+}
+
+/// Broken-down time on crond's clock
+struct Time {
+    minute: u32,
+    hour: u32,
+    day: u32,
+    month: u32,
+    weekday: u32,
+}
+
+impl Time {
+    fn from_minutes(minutes: u64) -> Self {
+        let days = minutes / (24 * 60);
+        let (_, month, day) = crate::clock::civil_from_days(days);
+        Time {
+            minute: (minutes % 60) as u32,
+            hour: (minutes / 60 % 24) as u32,
+            day,
+            month,
+            // 1 January 1970 was a Thursday
+            weekday: ((days + 4) % 7) as u32,
+        }
+    }
+}
+
+fn bit(mask: u64, n: u32) -> bool {
+    mask & (1 << n) != 0
+}
+
+impl Entry {
+    fn is_due(&self, time: &Time) -> bool {
+        let Schedule::At {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        } = self.schedule
+        else {
+            return false;
+        };
+        let day_matches = match (any_day, any_weekday) {
+            (false, false) => bit(days, time.day) || bit(weekdays, time.weekday),
+            _ => bit(days, time.day) && bit(weekdays, time.weekday),
+        };
+        bit(minutes, time.minute) && bit(hours, time.hour) && bit(months, time.month) && day_matches
+    }
+}
+
+/// Parse one field into a bit mask of the values in `min..=max` it matches
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, &'static str> {
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| "bad step")?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err("bad step");
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a.parse::<u32>().map_err(|_| "bad range")?;
+            let b = b.parse::<u32>().map_err(|_| "bad range")?;
+            (a, b)
+        } else {
+            let n = range.parse::<u32>().map_err(|_| "bad number")?;
+            // `5/15` means from 5 to the end in steps of 15
+            (n, if step > 1 { max } else { n })
+        };
+        if start < min || end > max || start > end {
+            return Err("value out of range");
+        }
+        let mut n = start;
+        while n <= end {
+            mask |= 1 << n;
+            n += step;
+        }
+    }
+    Ok(mask)
+}
+
+/// Parse a crontab line. Blank lines and `#` comments give `None`.
+pub fn parse_line(line: &str) -> Result<Option<Entry>, &'static str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (fields, command) = if let Some(rest) = line.strip_prefix('@') {
+        let (name, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let fields = match name {
+            "reboot" => {
+                let command = command.trim();
+                if command.is_empty() {
+                    return Err("missing command");
+                }
+                return Ok(Some(Entry {
+                    schedule: Schedule::Reboot,
+                    command: String::from(command),
+                }));
+            }
+            "hourly" => "0 * * * *",
+            "daily" | "midnight" => "0 0 * * *",
+            "weekly" => "0 0 * * 0",
+            "monthly" => "0 0 1 * *",
+            _ => return Err("unknown @ schedule"),
+        };
+        (String::from(fields), command.trim())
+    } else {
+        let mut rest = line;
+        let mut fields = Vec::new();
+        for _ in 0..5 {
+            let (field, tail) = rest
+                .split_once(char::is_whitespace)
+                .ok_or("missing command")?;
+            fields.push(field);
+            rest = tail.trim_start();
+        }
+        (fields.join(" "), rest)
+    };
+    if command.is_empty() {
+        return Err("missing command");
+    }
+
+    let fields: Vec<&str> = fields.split(' ').collect();
+    let mut weekdays = parse_field(fields[4], 0, 7)?;
+    // 7 is Sunday too
+    if bit(weekdays, 7) {
+        weekdays |= 1;
+    }
+    Ok(Some(Entry {
+        schedule: Schedule::At {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        },
+        command: String::from(command),
+    }))
+}
+
+/// Read the crontab. `None` if there is none or no filesystem.
+pub fn read_crontab() -> Option<String> {
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (fs, dev) = (fs_guard.as_mut()?, blk_guard.as_mut()?);
+    let data = fs.read_file(dev, CRONTAB)?;
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Replace the crontab; the service picks it up at the next minute
+pub fn write_crontab(text: &str) -> Result<(), &'static str> {
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+        return Err("Filesystem not available");
+    };
+    fs.write_file(dev, CRONTAB, text.as_bytes())?;
+    fs.sync(dev).map(|_| ())
+}
+
+fn run(entry: &Entry) {
+    klog_info(SERVICE, &format!("Running '{}'", entry.command));
+    if let Err(name) = crate::jobs::run_line(&entry.command) {
+        klog_warning(SERVICE, &format!("{}: command not found", name));
+    }
+}
+
+/// Run the entries due this minute, if it hasn't been checked yet
+pub fn tick() {
+    let minute = crate::clock::now_ms() as u64 / 60_000;
+    let last = LAST_MINUTE.swap(minute, Ordering::Relaxed);
+    if last == minute {
+        return;
+    }
+    let first_boot_run = !REBOOT_DONE.swap(true, Ordering::Relaxed);
+
+    let Some(text) = read_crontab() else {
+        return;
+    };
+    let time = Time::from_minutes(minute);
+    for (number, line) in text.lines().enumerate() {
+        match parse_line(line) {
+            Ok(Some(entry)) => {
+                let due = match entry.schedule {
+                    Schedule::Reboot => first_boot_run,
+                    Schedule::At { .. } => entry.is_due(&time),
+                };
+                if due {
+                    run(&entry);
+                }
+            }
+            Ok(None) => {}
+            // Reported once, when the file is first read
+            Err(e) if first_boot_run => {
+                klog_warning(SERVICE, &format!("{} line {}: {}", CRONTAB, number + 1, e));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Entry point registered with init
+pub fn crond_service() {
+    loop {
+        tick();
+        crate::scheduler::sleep_ms(POLL_INTERVAL_MS);
+    }
+}
@@ -0,0 +1,418 @@
+//! DHCP client
+//!
+//! Getting a lease follows the usual DISCOVER → OFFER → REQUEST → ACK
+//! exchange. It happens before the interface has an address smoltcp could
+//! send from, so those packets are built and read as raw frames on the
+//! VirtIO device, like ARP and ping. Once bound, [`Client`] keeps the lease
+//! alive over a UDP socket: it renews with the server that granted it at
+//! half the lease time, asks any server from 7/8 of it, and gives the lease
+//! up when it runs out.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::wire::Ipv4Address;
+
+use crate::virtio_net::VirtioNet;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed BOOTP header before the magic cookie
+const BOOTP_LEN: usize = 236;
+/// Asks the server to broadcast its replies
+const FLAG_BROADCAST: u16 = 0x8000;
+
+// Message types (option 53)
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+
+// Options
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+const OPT_PAD: u8 = 0;
+
+/// How long to wait for the server while getting a lease
+const ACQUIRE_TIMEOUT_MS: i64 = 4000;
+/// Resend interval while getting a lease
+const RESEND_MS: i64 = 1000;
+/// Resend interval while renewing or rebinding
+const RENEW_RESEND_MS: i64 = 10_000;
+/// Lease time assumed when the server doesn't give one
+const DEFAULT_LEASE_SECS: u32 = 3600;
+
+/// Transaction ID counter
+static mut XID: u32 = 0;
+
+fn next_xid(mac: &[u8; 6]) -> u32 {
+    unsafe {
+        if XID == 0 {
+            XID =
+                u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]) ^ crate::get_time_ms() as u32;
+        }
+        XID = XID.wrapping_add(1);
+        XID
+    }
+}
+
+/// Configuration handed out by a DHCP server
+#[derive(Clone, Copy)]
+pub struct Lease {
+    pub addr: Ipv4Address,
+    pub prefix_len: u8,
+    pub gateway: Ipv4Address,
+    pub dns: Ipv4Address,
+    /// Server that granted the lease
+    pub server: Ipv4Address,
+    pub lease_secs: u32,
+}
+
+/// A DHCP reply addressed to us
+struct Reply {
+    msg_type: u8,
+    yiaddr: Ipv4Address,
+    server: Option<Ipv4Address>,
+    lease: Lease,
+}
+
+/// Build a client message. `ciaddr` is set when we already own the address
+/// (renewing and releasing); otherwise `requested` and `server` select an
+/// offer.
+fn build_message(
+    mac: &[u8; 6],
+    xid: u32,
+    msg_type: u8,
+    ciaddr: Option<Ipv4Address>,
+    requested: Option<Ipv4Address>,
+    server: Option<Ipv4Address>,
+) -> Vec<u8> {
+    let mut msg = vec![0u8; BOOTP_LEN];
+    msg[0] = BOOTREQUEST;
+    msg[1] = 1; // htype = Ethernet
+    msg[2] = 6; // hlen
+    msg[4..8].copy_from_slice(&xid.to_be_bytes());
+    if let Some(ciaddr) = ciaddr {
+        msg[12..16].copy_from_slice(&ciaddr.0);
+    } else {
+        msg[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    }
+    msg[28..34].copy_from_slice(mac); // chaddr
+    msg.extend_from_slice(&MAGIC_COOKIE);
+    push_option(&mut msg, OPT_MESSAGE_TYPE, &[msg_type]);
+    if let Some(requested) = requested {
+        push_option(&mut msg, OPT_REQUESTED_IP, &requested.0);
+    }
+    if let Some(server) = server {
+        push_option(&mut msg, OPT_SERVER_ID, &server.0);
+    }
+    if msg_type != RELEASE {
+        push_option(
+            &mut msg,
+            OPT_PARAMETER_LIST,
+            &[OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS, OPT_LEASE_TIME],
+        );
+    }
+    msg.push(OPT_END);
+    // Pad to the minimum BOOTP message size
+    msg.resize(msg.len().max(300), OPT_PAD);
+    msg
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u8, value: &[u8]) {
+    buf.push(code);
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value);
+}
+
+/// Parse a server reply, ignoring anything not meant for `xid`
+fn parse_reply(msg: &[u8], xid: u32) -> Option<Reply> {
+    if msg.len() < BOOTP_LEN + 4
+        || msg[0] != BOOTREPLY
+        || msg[4..8] != xid.to_be_bytes()
+        || msg[236..240] != MAGIC_COOKIE
+    {
+        return None;
+    }
+    let yiaddr = Ipv4Address::from_bytes(&msg[16..20]);
+    let mut reply = Reply {
+        msg_type: 0,
+        yiaddr,
+        server: None,
+        lease: Lease {
+            addr: yiaddr,
+            prefix_len: crate::net::PREFIX_LEN,
+            gateway: crate::net::GATEWAY,
+            dns: crate::net::DNS_SERVER,
+            server: Ipv4Address::UNSPECIFIED,
+            lease_secs: DEFAULT_LEASE_SECS,
+        },
+    };
+
+    let mut options = &msg[240..];
+    while let Some((&code, rest)) = options.split_first() {
+        if code == OPT_END {
+            break;
+        }
+        if code == OPT_PAD {
+            options = rest;
+            continue;
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        options = &rest[len as usize..];
+        let addr = (value.len() >= 4).then(|| Ipv4Address::from_bytes(&value[..4]));
+        match code {
+            OPT_MESSAGE_TYPE => reply.msg_type = *value.first()?,
+            OPT_SUBNET_MASK => {
+                if let Some(mask) = addr {
+                    reply.lease.prefix_len = u32::from_be_bytes(mask.0).count_ones() as u8;
+                }
+            }
+            OPT_ROUTER => reply.lease.gateway = addr.unwrap_or(reply.lease.gateway),
+            OPT_DNS => reply.lease.dns = addr.unwrap_or(reply.lease.dns),
+            OPT_SERVER_ID => reply.server = addr,
+            OPT_LEASE_TIME => {
+                if let Ok(secs) = <[u8; 4]>::try_from(value) {
+                    reply.lease.lease_secs = u32::from_be_bytes(secs).max(60);
+                }
+            }
+            _ => {}
+        }
+    }
+    if reply.msg_type == 0 {
+        return None;
+    }
+    reply.lease.server = reply
+        .server
+        .unwrap_or(Ipv4Address::from_bytes(&msg[20..24]));
+    Some(reply)
+}
+
+/// Wrap a client message in a broadcast Ethernet/IPv4/UDP frame
+fn build_frame(mac: &[u8; 6], src: Ipv4Address, msg: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + msg.len();
+    let ip_len = 20 + udp_len;
+    let mut frame = vec![0u8; 14 + ip_len];
+
+    frame[0..6].copy_from_slice(&[0xff; 6]); // dst = broadcast
+    frame[6..12].copy_from_slice(mac);
+    frame[12..14].copy_from_slice(&[0x08, 0x00]); // ethertype = IPv4
+
+    frame[14] = 0x45; // version + IHL
+    frame[16..18].copy_from_slice(&(ip_len as u16).to_be_bytes());
+    frame[22] = 64; // TTL
+    frame[23] = 17; // protocol = UDP
+    frame[26..30].copy_from_slice(&src.0);
+    frame[30..34].copy_from_slice(&[255; 4]);
+    let mut sum: u32 = 0;
+    for i in (14..34).step_by(2) {
+        sum += u16::from_be_bytes([frame[i], frame[i + 1]]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    frame[24..26].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+
+    // UDP checksum is optional over IPv4 and left at zero
+    frame[34..36].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+    frame[36..38].copy_from_slice(&SERVER_PORT.to_be_bytes());
+    frame[38..40].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    frame[42..].copy_from_slice(msg);
+    frame
+}
+
+/// The UDP payload of a frame sent to the DHCP client port
+fn frame_payload(frame: &[u8]) -> Option<&[u8]> {
+    if frame.len() < 42 || frame[12..14] != [0x08, 0x00] || frame[23] != 17 {
+        return None;
+    }
+    let udp = 14 + (frame[14] & 0x0f) as usize * 4;
+    let header = frame.get(udp..udp + 8)?;
+    if u16::from_be_bytes([header[2], header[3]]) != CLIENT_PORT {
+        return None;
+    }
+    frame.get(udp + 8..)
+}
+
+/// Send `msg` until a reply of type `want` (or a NAK) arrives or the
+/// timeout passes. Other frames received meanwhile are dropped.
+fn exchange(device: &mut VirtioNet, msg: &[u8], xid: u32, want: u8) -> Option<Reply> {
+    let frame = build_frame(&device.mac, Ipv4Address::UNSPECIFIED, msg);
+    let start = crate::get_time_ms();
+    let mut last_sent = None;
+    loop {
+        let now = crate::get_time_ms();
+        if now - start > ACQUIRE_TIMEOUT_MS {
+            return None;
+        }
+        if last_sent.is_none_or(|sent| now - sent >= RESEND_MS) {
+            device.send(&frame).ok()?;
+            last_sent = Some(now);
+        }
+
+        device.poll();
+        while let Some((desc_idx, data)) = device.recv_with_desc() {
+            let reply = frame_payload(data).and_then(|msg| parse_reply(msg, xid));
+            device.recycle_rx(desc_idx);
+            match reply {
+                Some(reply) if reply.msg_type == want || reply.msg_type == NAK => {
+                    return Some(reply)
+                }
+                _ => {}
+            }
+        }
+        // Give the host (or the browser's event loop) time to answer
+        for _ in 0..10_000 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Get a lease with the full DISCOVER/OFFER/REQUEST/ACK exchange. Returns
+/// `None` if no server answers or the offer is withdrawn.
+pub fn acquire(device: &mut VirtioNet) -> Option<Lease> {
+    let mac = device.mac;
+    let xid = next_xid(&mac);
+
+    let discover = build_message(&mac, xid, DISCOVER, None, None, None);
+    let offer = exchange(device, &discover, xid, OFFER)?;
+    if offer.msg_type != OFFER {
+        return None;
+    }
+
+    let request = build_message(&mac, xid, REQUEST, None, Some(offer.yiaddr), offer.server);
+    let ack = exchange(device, &request, xid, ACK)?;
+    (ack.msg_type == ACK).then_some(ack.lease)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Bound,
+    /// Asking the server that granted the lease to extend it
+    Renewing,
+    /// Asking any server, after the granting one stayed silent
+    Rebinding,
+}
+
+/// What the network stack should do for the client
+pub enum Action {
+    /// Send a message to the server port of this address
+    Send(Ipv4Address, Vec<u8>),
+    /// The lease was extended, possibly with new settings
+    Renewed(Lease),
+    /// The lease ran out or the server refused it; the address must go
+    Expired,
+}
+
+/// A bound lease and its renewal timers
+pub struct Client {
+    pub lease: Lease,
+    mac: [u8; 6],
+    /// When the lease was granted
+    since: i64,
+    state: State,
+    xid: u32,
+    last_sent: i64,
+}
+
+impl Client {
+    pub fn new(lease: Lease, mac: [u8; 6], now: i64) -> Self {
+        Self {
+            lease,
+            mac,
+            since: now,
+            state: State::Bound,
+            xid: 0,
+            last_sent: 0,
+        }
+    }
+
+    /// Seconds left on the lease
+    pub fn remaining_secs(&self, now: i64) -> i64 {
+        (self.lease.lease_secs as i64 - (now - self.since) / 1000).max(0)
+    }
+
+    /// Advance the timers, returning what to send when one fires
+    pub fn poll(&mut self, now: i64) -> Option<Action> {
+        let elapsed = now - self.since;
+        let lease_ms = self.lease.lease_secs as i64 * 1000;
+        if elapsed >= lease_ms {
+            return Some(Action::Expired);
+        }
+        let state = if elapsed >= lease_ms / 8 * 7 {
+            State::Rebinding
+        } else if elapsed >= lease_ms / 2 {
+            State::Renewing
+        } else {
+            State::Bound
+        };
+        if state == State::Bound {
+            return None;
+        }
+        if state == self.state && now - self.last_sent < RENEW_RESEND_MS {
+            return None;
+        }
+        if state != self.state {
+            self.state = state;
+            self.xid = next_xid(&self.mac);
+        }
+        self.last_sent = now;
+
+        let msg = build_message(
+            &self.mac,
+            self.xid,
+            REQUEST,
+            Some(self.lease.addr),
+            None,
+            None,
+        );
+        let dest = match state {
+            State::Renewing => self.lease.server,
+            _ => Ipv4Address::BROADCAST,
+        };
+        Some(Action::Send(dest, msg))
+    }
+
+    /// Handle a message received on the client port
+    pub fn handle(&mut self, msg: &[u8], now: i64) -> Option<Action> {
+        if self.state == State::Bound {
+            return None;
+        }
+        let reply = parse_reply(msg, self.xid)?;
+        match reply.msg_type {
+            ACK => {
+                self.lease = reply.lease;
+                self.since = now;
+                self.state = State::Bound;
+                Some(Action::Renewed(reply.lease))
+            }
+            NAK => Some(Action::Expired),
+            _ => None,
+        }
+    }
+
+    /// The message giving the lease back to its server
+    pub fn release(&self) -> Vec<u8> {
+        build_message(
+            &self.mac,
+            next_xid(&self.mac),
+            RELEASE,
+            Some(self.lease.addr),
+            None,
+            Some(self.lease.server),
+        )
+    }
+}
@@ -270,6 +270,9 @@ pub fn resolve(
             uart::write_line("DNS query timed out");
             return None;
         }
+        if crate::jobs::checkpoint().is_err() {
+            return None;
+        }
 
         // Poll network
         net.poll(now);
@@ -0,0 +1,112 @@
+//! ELF executable parsing
+//!
+//! Accepts statically linked little-endian RV64 executables (`ET_EXEC`), as
+//! produced by `riscv64-linux-musl-gcc -static` or a bare-metal toolchain
+//! linking at a user address. Position-independent and dynamically linked
+//! programs are refused, since there is no loader to relocate them.
+
+use alloc::vec::Vec;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+
+/// Segment permission flags
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// A `PT_LOAD` segment: `file_size` bytes from `offset` in the file go to
+/// `vaddr`, and the rest of `mem_size` is zeroed
+pub struct Segment {
+    pub vaddr: u64,
+    pub mem_size: u64,
+    pub offset: usize,
+    pub file_size: usize,
+    pub flags: u32,
+}
+
+/// A parsed executable
+pub struct Executable {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+    le32(buf, offset) as u64 | (le32(buf, offset + 4) as u64) << 32
+}
+
+/// Whether `bytes` starts with the ELF magic number
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(ELF_MAGIC)
+}
+
+/// Parse the header and loadable segments of an executable
+pub fn parse(bytes: &[u8]) -> Result<Executable, &'static str> {
+    if bytes.len() < 64 || !is_elf(bytes) {
+        return Err("Not an ELF file");
+    }
+    if bytes[4] != ELFCLASS64 || bytes[5] != ELFDATA2LSB || le16(bytes, 18) != EM_RISCV {
+        return Err("Not a little-endian RV64 executable");
+    }
+    match le16(bytes, 16) {
+        ET_EXEC => {}
+        ET_DYN => return Err("Position-independent executables are not supported"),
+        _ => return Err("Not an executable"),
+    }
+
+    let entry = le64(bytes, 24);
+    let phoff = le64(bytes, 32) as usize;
+    let phentsize = le16(bytes, 54) as usize;
+    let phnum = le16(bytes, 56) as usize;
+    if phentsize < 56 || phoff.saturating_add(phentsize * phnum) > bytes.len() {
+        return Err("Truncated program headers");
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = &bytes[phoff + i * phentsize..];
+        match le32(ph, 0) {
+            PT_INTERP => return Err("Dynamically linked programs are not supported"),
+            PT_LOAD => {}
+            _ => continue,
+        }
+        let segment = Segment {
+            flags: le32(ph, 4),
+            offset: le64(ph, 8) as usize,
+            vaddr: le64(ph, 16),
+            file_size: le64(ph, 32) as usize,
+            mem_size: le64(ph, 40),
+        };
+        if segment.file_size as u64 > segment.mem_size
+            || segment.offset.saturating_add(segment.file_size) > bytes.len()
+        {
+            return Err("Segment extends past the end of the file");
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err("No loadable segments");
+    }
+
+    Ok(Executable { entry, segments })
+}
@@ -0,0 +1,1268 @@
+//! ext2 filesystem driver
+//!
+//! Mounts disks formatted by host tools (`mke2fs -t ext2`, `genext2fs`) with
+//! real directory inodes, timestamps and rename. Revision 0 and 1 layouts
+//! with 1-4 KiB blocks are supported, and files use direct and single,
+//! double and triple indirect blocks. Directories are read and written as
+//! linear lists; an htree index is dropped when its directory changes.
+//!
+//! A disk with incompatible features other than directory file types is not
+//! mounted, and one with unknown read-only features is mounted read-only.
+//!
+//! Writes go straight to the disk, with the superblock and group descriptors
+//! updated before each call returns, so the image is consistent for host
+//! tools whenever the kernel isn't in the middle of a call. Timestamps come
+//! from the wall clock once `ntpd` has set it, and count from the image's
+//! last write time before that.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fs::{FileInfo, FsckReport};
+use crate::virtio_blk::VirtioBlock;
+
+/// Byte offset of the superblock
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+
+/// Inode of the root directory
+const ROOT_INO: u32 = 2;
+
+/// Size of the inode fields this driver knows about (revision 0 inodes)
+const INODE_BASE_SIZE: usize = 128;
+
+/// Direct block pointers in an inode
+const DIRECT_BLOCKS: usize = 12;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+
+/// Directory entry file types
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+
+/// Directory entries carry a file type
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// Inode flag: the directory has an htree index
+const INDEX_FL: u32 = 0x1000;
+
+/// Deepest directory nesting walked when listing or checking
+const MAX_DEPTH: usize = 32;
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn put16(buf: &mut [u8], offset: usize, val: u16) {
+    buf[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// Size a directory entry with a name of `name_len` bytes takes up
+fn entry_len(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+/// Path components, resolving `.` and `..`
+fn components(path: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts
+}
+
+/// An inode as stored on disk
+struct Inode {
+    raw: Vec<u8>,
+}
+
+impl Inode {
+    fn mode(&self) -> u16 {
+        le16(&self.raw, 0)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode() & S_IFMT == S_IFDIR
+    }
+
+    fn is_file(&self) -> bool {
+        self.mode() & S_IFMT == S_IFREG
+    }
+
+    fn size(&self) -> u64 {
+        let high = if self.is_file() {
+            le32(&self.raw, 108)
+        } else {
+            0
+        };
+        ((high as u64) << 32) | le32(&self.raw, 4) as u64
+    }
+
+    fn set_size(&mut self, size: u64) {
+        put32(&mut self.raw, 4, size as u32);
+        if self.is_file() {
+            put32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    fn links(&self) -> u16 {
+        le16(&self.raw, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        put16(&mut self.raw, 26, links);
+    }
+
+    fn flags(&self) -> u32 {
+        le32(&self.raw, 32)
+    }
+
+    fn block(&self, i: usize) -> u32 {
+        le32(&self.raw, 40 + 4 * i)
+    }
+
+    fn set_block(&mut self, i: usize, block: u32) {
+        put32(&mut self.raw, 40 + 4 * i, block);
+    }
+
+    /// Set the access, change and modification times
+    fn touch(&mut self, now: u32) {
+        put32(&mut self.raw, 8, now);
+        put32(&mut self.raw, 12, now);
+        put32(&mut self.raw, 16, now);
+    }
+}
+
+/// A directory entry
+struct DirEntry {
+    ino: u32,
+    name: String,
+}
+
+pub struct Ext2 {
+    superblock: Vec<u8>,
+    /// Raw block group descriptor table
+    groups: Vec<u8>,
+    group_count: u32,
+    block_size: usize,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    first_ino: u32,
+    /// Directory entries carry a file type
+    filetype: bool,
+    read_only: bool,
+    /// Image time when mounted, in seconds since the epoch
+    clock_base: u32,
+}
+
+impl Ext2 {
+    /// Mount the ext2 filesystem on `dev`, if it holds a supported one
+    pub fn mount(dev: &mut VirtioBlock) -> Option<Self> {
+        let mut superblock = vec![0u8; SUPERBLOCK_SIZE];
+        for (i, chunk) in superblock.chunks_mut(512).enumerate() {
+            dev.read_sector(SUPERBLOCK_OFFSET / 512 + i as u64, chunk)
+                .ok()?;
+        }
+        if le16(&superblock, 56) != MAGIC {
+            return None;
+        }
+
+        let rev = le32(&superblock, 76);
+        let (inode_size, first_ino, incompat, ro_compat) = if rev == 0 {
+            (INODE_BASE_SIZE, 11, 0, 0)
+        } else {
+            (
+                le16(&superblock, 88) as usize,
+                le32(&superblock, 84),
+                le32(&superblock, 96),
+                le32(&superblock, 100),
+            )
+        };
+        let log_block_size = le32(&superblock, 24);
+        if incompat & !INCOMPAT_FILETYPE != 0 || log_block_size > 2 || inode_size < INODE_BASE_SIZE
+        {
+            return None;
+        }
+
+        let blocks_count = le32(&superblock, 4);
+        let first_data_block = le32(&superblock, 20);
+        let blocks_per_group = le32(&superblock, 32);
+        let inodes_per_group = le32(&superblock, 40);
+        if blocks_per_group == 0 || inodes_per_group == 0 || blocks_count <= first_data_block {
+            return None;
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+        let clock_base = le32(&superblock, 44).max(le32(&superblock, 48));
+
+        let mut fs = Self {
+            superblock,
+            groups: Vec::new(),
+            group_count,
+            block_size: 1024 << log_block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            read_only: ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0,
+            clock_base,
+        };
+
+        let table_len = (group_count as usize * 32).div_ceil(fs.block_size);
+        for i in 0..table_len {
+            let block = fs.read_block(dev, first_data_block + 1 + i as u32).ok()?;
+            fs.groups.extend_from_slice(&block);
+        }
+        let root = fs.read_inode(dev, ROOT_INO).ok()?;
+        root.is_dir().then_some(fs)
+    }
+
+    /// Whether writes are refused because of unknown read-only features
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Used and total bytes
+    pub fn usage_bytes(&self) -> (u64, u64) {
+        let total = self.blocks_count as u64;
+        let free = le32(&self.superblock, 12) as u64;
+        let block_size = self.block_size as u64;
+        ((total - free.min(total)) * block_size, total * block_size)
+    }
+
+    /// Seconds since the epoch: the wall clock if it has been set, or
+    /// counted from the image's last write time
+    fn now(&self) -> u32 {
+        if crate::clock::is_synced() {
+            return crate::clock::now_secs() as u32;
+        }
+        self.clock_base + (crate::get_time_ms() / 1000) as u32
+    }
+
+    // ─── Block I/O ──────────────────────────────────────────────────────────
+
+    fn read_block(&self, dev: &mut VirtioBlock, block: u32) -> Result<Vec<u8>, &'static str> {
+        if block >= self.blocks_count {
+            return Err("Block out of range");
+        }
+        let mut buf = vec![0u8; self.block_size];
+        let first = block as u64 * (self.block_size / 512) as u64;
+        for (i, chunk) in buf.chunks_mut(512).enumerate() {
+            dev.read_sector(first + i as u64, chunk)?;
+        }
+        Ok(buf)
+    }
+
+    fn write_block(
+        &self,
+        dev: &mut VirtioBlock,
+        block: u32,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        if block >= self.blocks_count {
+            return Err("Block out of range");
+        }
+        let first = block as u64 * (self.block_size / 512) as u64;
+        for (i, chunk) in data.chunks(512).enumerate() {
+            dev.write_sector(first + i as u64, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Write the superblock and group descriptors back
+    fn flush(&mut self, dev: &mut VirtioBlock) -> Result<(), &'static str> {
+        let now = self.now();
+        put32(&mut self.superblock, 48, now);
+        for (i, chunk) in self.superblock.chunks(512).enumerate() {
+            dev.write_sector(SUPERBLOCK_OFFSET / 512 + i as u64, chunk)?;
+        }
+        for (i, block) in self.groups.chunks(self.block_size).enumerate() {
+            self.write_block(dev, self.first_data_block + 1 + i as u32, block)?;
+        }
+        Ok(())
+    }
+
+    /// Everything is written as it changes; this only records the time
+    pub fn sync(&mut self, dev: &mut VirtioBlock) -> Result<usize, &'static str> {
+        if !self.read_only {
+            self.flush(dev)?;
+        }
+        Ok(0)
+    }
+
+    // ─── Group descriptors ─────────────────────────────────────────────────
+
+    fn group_field(&self, group: u32, offset: usize) -> u32 {
+        le32(&self.groups, group as usize * 32 + offset)
+    }
+
+    /// Adjust one of a group's 16-bit counters, and the superblock's total
+    /// for it if there is one
+    fn adjust_count(&mut self, group: u32, offset: usize, delta: i32) {
+        let at = group as usize * 32 + offset;
+        let count = le16(&self.groups, at) as i32 + delta;
+        put16(&mut self.groups, at, count.max(0) as u16);
+        let total_at = match offset {
+            12 => 12,
+            14 => 16,
+            _ => return,
+        };
+        let total = le32(&self.superblock, total_at) as i64 + delta as i64;
+        put32(&mut self.superblock, total_at, total.max(0) as u32);
+    }
+
+    // ─── Inodes ────────────────────────────────────────────────────────────
+
+    fn inode_location(&self, ino: u32) -> (u32, usize) {
+        let index = ino - 1;
+        let table = self.group_field(index / self.inodes_per_group, 8);
+        let byte = (index % self.inodes_per_group) as usize * self.inode_size;
+        (
+            table + (byte / self.block_size) as u32,
+            byte % self.block_size,
+        )
+    }
+
+    fn read_inode(&self, dev: &mut VirtioBlock, ino: u32) -> Result<Inode, &'static str> {
+        if ino == 0 || ino > self.group_count * self.inodes_per_group {
+            return Err("Inode out of range");
+        }
+        let (block, offset) = self.inode_location(ino);
+        let buf = self.read_block(dev, block)?;
+        Ok(Inode {
+            raw: buf[offset..offset + self.inode_size].to_vec(),
+        })
+    }
+
+    fn write_inode(
+        &self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        inode: &Inode,
+    ) -> Result<(), &'static str> {
+        let (block, offset) = self.inode_location(ino);
+        let mut buf = self.read_block(dev, block)?;
+        buf[offset..offset + self.inode_size].copy_from_slice(&inode.raw);
+        self.write_block(dev, block, &buf)
+    }
+
+    /// Allocate an inode, preferring the group of `near`
+    fn alloc_inode(
+        &mut self,
+        dev: &mut VirtioBlock,
+        near: u32,
+        dir: bool,
+    ) -> Result<u32, &'static str> {
+        let start = (near - 1) / self.inodes_per_group;
+        for i in 0..self.group_count {
+            let group = (start + i) % self.group_count;
+            if self.group_field(group, 12) >> 16 == 0 {
+                continue;
+            }
+            let bitmap_block = self.group_field(group, 4);
+            let mut bitmap = self.read_block(dev, bitmap_block)?;
+            for bit in 0..self.inodes_per_group as usize {
+                let ino = group * self.inodes_per_group + bit as u32 + 1;
+                if bitmap[bit / 8] & (1 << (bit % 8)) != 0 || ino < self.first_ino {
+                    continue;
+                }
+                bitmap[bit / 8] |= 1 << (bit % 8);
+                self.write_block(dev, bitmap_block, &bitmap)?;
+                self.adjust_count(group, 14, -1);
+                if dir {
+                    self.adjust_count(group, 16, 1);
+                }
+                return Ok(ino);
+            }
+        }
+        Err("No free inodes")
+    }
+
+    fn free_inode(
+        &mut self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        dir: bool,
+    ) -> Result<(), &'static str> {
+        let group = (ino - 1) / self.inodes_per_group;
+        let bit = ((ino - 1) % self.inodes_per_group) as usize;
+        let bitmap_block = self.group_field(group, 4);
+        let mut bitmap = self.read_block(dev, bitmap_block)?;
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        self.write_block(dev, bitmap_block, &bitmap)?;
+        self.adjust_count(group, 14, 1);
+        if dir {
+            self.adjust_count(group, 16, -1);
+        }
+        Ok(())
+    }
+
+    // ─── Blocks ────────────────────────────────────────────────────────────
+
+    /// Block pointers per indirect block
+    fn pointers(&self) -> usize {
+        self.block_size / 4
+    }
+
+    /// Allocate `count` blocks, preferring the group of inode `near`
+    fn alloc_blocks(
+        &mut self,
+        dev: &mut VirtioBlock,
+        count: usize,
+        near: u32,
+    ) -> Result<Vec<u32>, &'static str> {
+        if count > le32(&self.superblock, 12) as usize {
+            return Err("Disk full");
+        }
+        let mut blocks = Vec::with_capacity(count);
+        let start = (near - 1) / self.inodes_per_group;
+        for i in 0..self.group_count {
+            if blocks.len() == count {
+                break;
+            }
+            let group = (start + i) % self.group_count;
+            if self.group_field(group, 12) & 0xFFFF == 0 {
+                continue;
+            }
+            let bitmap_block = self.group_field(group, 0);
+            let mut bitmap = self.read_block(dev, bitmap_block)?;
+            let first = self.first_data_block + group * self.blocks_per_group;
+            let in_group = self.blocks_per_group.min(self.blocks_count - first) as usize;
+            let mut taken = 0;
+            for bit in 0..in_group {
+                if blocks.len() == count {
+                    break;
+                }
+                if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+                    bitmap[bit / 8] |= 1 << (bit % 8);
+                    blocks.push(first + bit as u32);
+                    taken += 1;
+                }
+            }
+            if taken > 0 {
+                self.write_block(dev, bitmap_block, &bitmap)?;
+                self.adjust_count(group, 12, -taken);
+            }
+        }
+        if blocks.len() < count {
+            // The counters were off; give back what was taken
+            self.free_blocks(dev, &blocks)?;
+            return Err("Disk full");
+        }
+        Ok(blocks)
+    }
+
+    fn free_blocks(&mut self, dev: &mut VirtioBlock, blocks: &[u32]) -> Result<(), &'static str> {
+        let mut blocks: Vec<u32> = blocks.iter().copied().filter(|&b| b != 0).collect();
+        blocks.sort_unstable();
+        let (first, per_group) = (self.first_data_block, self.blocks_per_group);
+        for chunk in blocks.chunk_by(|a, b| (a - first) / per_group == (b - first) / per_group) {
+            let group = (chunk[0] - self.first_data_block) / self.blocks_per_group;
+            let bitmap_block = self.group_field(group, 0);
+            let mut bitmap = self.read_block(dev, bitmap_block)?;
+            for &block in chunk {
+                let bit = ((block - self.first_data_block) % self.blocks_per_group) as usize;
+                bitmap[bit / 8] &= !(1 << (bit % 8));
+            }
+            self.write_block(dev, bitmap_block, &bitmap)?;
+            self.adjust_count(group, 12, chunk.len() as i32);
+        }
+        Ok(())
+    }
+
+    /// Collect the data blocks (0 for holes) and the indirect blocks under
+    /// `block`, a tree of `level` levels of indirection
+    fn collect_tree(
+        &self,
+        dev: &mut VirtioBlock,
+        block: u32,
+        level: u32,
+        remaining: &mut usize,
+        data: &mut Vec<u32>,
+        meta: &mut Vec<u32>,
+    ) -> Result<(), &'static str> {
+        if *remaining == 0 {
+            return Ok(());
+        }
+        if block == 0 {
+            let span = self.pointers().pow(level).min(*remaining);
+            data.resize(data.len() + span, 0);
+            *remaining -= span;
+            return Ok(());
+        }
+        if block >= self.blocks_count {
+            return Err("Corrupt block pointer");
+        }
+        if level == 0 {
+            data.push(block);
+            *remaining -= 1;
+            return Ok(());
+        }
+        meta.push(block);
+        let buf = self.read_block(dev, block)?;
+        for i in 0..self.pointers() {
+            if *remaining == 0 {
+                break;
+            }
+            self.collect_tree(dev, le32(&buf, 4 * i), level - 1, remaining, data, meta)?;
+        }
+        Ok(())
+    }
+
+    /// Data blocks of an inode in file order, and its indirect blocks
+    fn inode_blocks(
+        &self,
+        dev: &mut VirtioBlock,
+        inode: &Inode,
+    ) -> Result<(Vec<u32>, Vec<u32>), &'static str> {
+        let mut remaining = inode.size().div_ceil(self.block_size as u64) as usize;
+        let (mut data, mut meta) = (Vec::new(), Vec::new());
+        for i in 0..DIRECT_BLOCKS {
+            self.collect_tree(dev, inode.block(i), 0, &mut remaining, &mut data, &mut meta)?;
+        }
+        for level in 1..=3 {
+            let root = inode.block(DIRECT_BLOCKS + level as usize - 1);
+            self.collect_tree(dev, root, level, &mut remaining, &mut data, &mut meta)?;
+        }
+        Ok((data, meta))
+    }
+
+    /// Indirect blocks needed to map `count` data blocks
+    fn meta_needed(&self, count: usize) -> Result<usize, &'static str> {
+        let p = self.pointers();
+        let mut left = count.saturating_sub(DIRECT_BLOCKS);
+        let mut meta = 0;
+        for level in 1..=3u32 {
+            if left == 0 {
+                return Ok(meta);
+            }
+            let here = left.min(p.pow(level));
+            // The root, then every level of pointer blocks below it
+            meta += 1;
+            for below in 1..level {
+                meta += here.div_ceil(p.pow(level - below));
+            }
+            left -= here;
+        }
+        if left > 0 {
+            return Err("File too large");
+        }
+        Ok(meta)
+    }
+
+    /// Write the pointer blocks mapping `data` at `level` levels of
+    /// indirection, taking them from `pool`. Returns the root.
+    fn write_tree(
+        &self,
+        dev: &mut VirtioBlock,
+        level: u32,
+        data: &[u32],
+        pool: &mut Vec<u32>,
+    ) -> Result<u32, &'static str> {
+        let block = pool.pop().ok_or("Out of indirect blocks")?;
+        let mut buf = vec![0u8; self.block_size];
+        if level == 1 {
+            for (i, &b) in data.iter().enumerate() {
+                put32(&mut buf, 4 * i, b);
+            }
+        } else {
+            let span = self.pointers().pow(level - 1);
+            for (i, chunk) in data.chunks(span).enumerate() {
+                let child = self.write_tree(dev, level - 1, chunk, pool)?;
+                put32(&mut buf, 4 * i, child);
+            }
+        }
+        self.write_block(dev, block, &buf)?;
+        Ok(block)
+    }
+
+    /// Point `inode` at `data`, replacing its indirect blocks (but not its
+    /// old data blocks, which the caller frees or keeps)
+    fn set_blocks(
+        &mut self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        inode: &mut Inode,
+        data: &[u32],
+    ) -> Result<(), &'static str> {
+        let (_, old_meta) = self.inode_blocks(dev, inode)?;
+        self.free_blocks(dev, &old_meta)?;
+
+        let mut pool = self.alloc_blocks(dev, self.meta_needed(data.len())?, ino)?;
+        let meta_count = pool.len();
+        for i in 0..DIRECT_BLOCKS + 3 {
+            inode.set_block(i, 0);
+        }
+        for (i, &b) in data.iter().take(DIRECT_BLOCKS).enumerate() {
+            inode.set_block(i, b);
+        }
+        let mut rest = data.get(DIRECT_BLOCKS..).unwrap_or(&[]);
+        for level in 1..=3u32 {
+            if rest.is_empty() {
+                break;
+            }
+            let here = rest.len().min(self.pointers().pow(level));
+            let root = self.write_tree(dev, level, &rest[..here], &mut pool)?;
+            inode.set_block(DIRECT_BLOCKS + level as usize - 1, root);
+            rest = &rest[here..];
+        }
+        let allocated = data.iter().filter(|&&b| b != 0).count() + meta_count;
+        let sectors = allocated * (self.block_size / 512);
+        put32(&mut inode.raw, 28, sectors as u32);
+        Ok(())
+    }
+
+    /// Free every block of an inode
+    fn truncate(&mut self, dev: &mut VirtioBlock, inode: &mut Inode) -> Result<(), &'static str> {
+        let (data, meta) = self.inode_blocks(dev, inode)?;
+        self.free_blocks(dev, &data)?;
+        self.free_blocks(dev, &meta)?;
+        for i in 0..DIRECT_BLOCKS + 3 {
+            inode.set_block(i, 0);
+        }
+        put32(&mut inode.raw, 28, 0);
+        inode.set_size(0);
+        Ok(())
+    }
+
+    fn read_data(&self, dev: &mut VirtioBlock, inode: &Inode) -> Result<Vec<u8>, &'static str> {
+        let size = inode.size() as usize;
+        let (blocks, _) = self.inode_blocks(dev, inode)?;
+        let mut data = Vec::with_capacity(size);
+        for block in blocks {
+            let chunk = (size - data.len()).min(self.block_size);
+            if block == 0 {
+                data.resize(data.len() + chunk, 0);
+            } else {
+                data.extend_from_slice(&self.read_block(dev, block)?[..chunk]);
+            }
+        }
+        Ok(data)
+    }
+
+    // ─── Directories ───────────────────────────────────────────────────────
+
+    fn dir_entries(
+        &self,
+        dev: &mut VirtioBlock,
+        dir: &Inode,
+    ) -> Result<Vec<DirEntry>, &'static str> {
+        let (blocks, _) = self.inode_blocks(dev, dir)?;
+        let mut entries = Vec::new();
+        for block in blocks.into_iter().filter(|&b| b != 0) {
+            let buf = self.read_block(dev, block)?;
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let rec_len = le16(&buf, offset + 4) as usize;
+                let name_len = buf[offset + 6] as usize;
+                if rec_len < 8 || offset + rec_len > self.block_size || 8 + name_len > rec_len {
+                    return Err("Corrupt directory");
+                }
+                let ino = le32(&buf, offset);
+                if ino != 0 {
+                    let name = &buf[offset + 8..offset + 8 + name_len];
+                    entries.push(DirEntry {
+                        ino,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    });
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn lookup_in(
+        &self,
+        dev: &mut VirtioBlock,
+        dir: &Inode,
+        name: &str,
+    ) -> Result<Option<u32>, &'static str> {
+        Ok(self
+            .dir_entries(dev, dir)?
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| e.ino))
+    }
+
+    /// Inode number of `path`, if it exists
+    fn lookup(&self, dev: &mut VirtioBlock, path: &str) -> Result<Option<u32>, &'static str> {
+        let mut ino = ROOT_INO;
+        for part in components(path) {
+            let dir = self.read_inode(dev, ino)?;
+            if !dir.is_dir() {
+                return Ok(None);
+            }
+            match self.lookup_in(dev, &dir, part)? {
+                Some(next) => ino = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(ino))
+    }
+
+    /// Directory holding `path` (created with its parents if missing) and
+    /// the final name
+    fn parent_of<'a>(
+        &mut self,
+        dev: &mut VirtioBlock,
+        path: &'a str,
+        create: bool,
+    ) -> Result<(u32, &'a str), &'static str> {
+        let parts = components(path);
+        let (&name, dirs) = parts.split_last().ok_or("Invalid path")?;
+        if name.len() > 255 {
+            return Err("Name too long");
+        }
+        let mut ino = ROOT_INO;
+        for &part in dirs {
+            let dir = self.read_inode(dev, ino)?;
+            if !dir.is_dir() {
+                return Err("Not a directory");
+            }
+            ino = match self.lookup_in(dev, &dir, part)? {
+                Some(next) => next,
+                None if create => self.make_dir(dev, ino, part)?,
+                None => return Err("No such directory"),
+            };
+        }
+        if !self.read_inode(dev, ino)?.is_dir() {
+            return Err("Not a directory");
+        }
+        Ok((ino, name))
+    }
+
+    /// Write a directory entry at `offset` of `buf`
+    fn put_entry(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        rec_len: usize,
+        ino: u32,
+        name: &str,
+        file_type: u8,
+    ) {
+        put32(buf, offset, ino);
+        put16(buf, offset + 4, rec_len as u16);
+        buf[offset + 6] = name.len() as u8;
+        buf[offset + 7] = if self.filetype { file_type } else { 0 };
+        buf[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// Drop a directory's htree index, which its changes would leave stale
+    fn drop_index(inode: &mut Inode) {
+        let flags = inode.flags() & !INDEX_FL;
+        put32(&mut inode.raw, 32, flags);
+    }
+
+    fn add_entry(
+        &mut self,
+        dev: &mut VirtioBlock,
+        dir_ino: u32,
+        name: &str,
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), &'static str> {
+        let mut dir = self.read_inode(dev, dir_ino)?;
+        let needed = entry_len(name.len());
+        let (blocks, _) = self.inode_blocks(dev, &dir)?;
+        let now = self.now();
+
+        for &block in blocks.iter().filter(|&&b| b != 0) {
+            let mut buf = self.read_block(dev, block)?;
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let rec_len = le16(&buf, offset + 4) as usize;
+                if rec_len < 8 || offset + rec_len > self.block_size {
+                    return Err("Corrupt directory");
+                }
+                let used = if le32(&buf, offset) == 0 {
+                    0
+                } else {
+                    entry_len(buf[offset + 6] as usize)
+                };
+                if rec_len - used >= needed {
+                    let at = if used == 0 {
+                        offset
+                    } else {
+                        put16(&mut buf, offset + 4, used as u16);
+                        offset + used
+                    };
+                    self.put_entry(&mut buf, at, rec_len - used, ino, name, file_type);
+                    self.write_block(dev, block, &buf)?;
+                    Self::drop_index(&mut dir);
+                    dir.touch(now);
+                    return self.write_inode(dev, dir_ino, &dir);
+                }
+                offset += rec_len;
+            }
+        }
+
+        // No room: give the directory another block
+        let block = self.alloc_blocks(dev, 1, dir_ino)?[0];
+        let mut buf = vec![0u8; self.block_size];
+        self.put_entry(&mut buf, 0, self.block_size, ino, name, file_type);
+        self.write_block(dev, block, &buf)?;
+        let mut data = blocks;
+        data.push(block);
+        self.set_blocks(dev, dir_ino, &mut dir, &data)?;
+        dir.set_size(data.len() as u64 * self.block_size as u64);
+        Self::drop_index(&mut dir);
+        dir.touch(now);
+        self.write_inode(dev, dir_ino, &dir)
+    }
+
+    fn remove_entry(
+        &mut self,
+        dev: &mut VirtioBlock,
+        dir_ino: u32,
+        name: &str,
+    ) -> Result<(), &'static str> {
+        let mut dir = self.read_inode(dev, dir_ino)?;
+        let (blocks, _) = self.inode_blocks(dev, &dir)?;
+        for &block in blocks.iter().filter(|&&b| b != 0) {
+            let mut buf = self.read_block(dev, block)?;
+            let mut offset = 0;
+            let mut prev: Option<usize> = None;
+            while offset + 8 <= self.block_size {
+                let rec_len = le16(&buf, offset + 4) as usize;
+                if rec_len < 8 || offset + rec_len > self.block_size {
+                    return Err("Corrupt directory");
+                }
+                let name_len = buf[offset + 6] as usize;
+                if le32(&buf, offset) != 0
+                    && &buf[offset + 8..offset + 8 + name_len] == name.as_bytes()
+                {
+                    match prev {
+                        // Merge into the entry before it
+                        Some(p) => {
+                            let merged = le16(&buf, p + 4) as usize + rec_len;
+                            put16(&mut buf, p + 4, merged as u16);
+                        }
+                        None => put32(&mut buf, offset, 0),
+                    }
+                    self.write_block(dev, block, &buf)?;
+                    Self::drop_index(&mut dir);
+                    dir.touch(self.now());
+                    return self.write_inode(dev, dir_ino, &dir);
+                }
+                prev = Some(offset);
+                offset += rec_len;
+            }
+        }
+        Err("File not found")
+    }
+
+    /// Create directory `name` in `parent`, returning its inode
+    fn make_dir(
+        &mut self,
+        dev: &mut VirtioBlock,
+        parent: u32,
+        name: &str,
+    ) -> Result<u32, &'static str> {
+        let ino = self.alloc_inode(dev, parent, true)?;
+        let block = self.alloc_blocks(dev, 1, ino)?[0];
+        let mut buf = vec![0u8; self.block_size];
+        self.put_entry(&mut buf, 0, 12, ino, ".", FT_DIR);
+        self.put_entry(&mut buf, 12, self.block_size - 12, parent, "..", FT_DIR);
+        self.write_block(dev, block, &buf)?;
+
+        let mut inode = Inode {
+            raw: vec![0u8; self.inode_size],
+        };
+        put16(&mut inode.raw, 0, S_IFDIR | 0o755);
+        inode.set_links(2);
+        inode.touch(self.now());
+        inode.set_size(self.block_size as u64);
+        inode.set_block(0, block);
+        put32(&mut inode.raw, 28, (self.block_size / 512) as u32);
+        self.write_inode(dev, ino, &inode)?;
+
+        self.add_entry(dev, parent, name, ino, FT_DIR)?;
+        let mut parent_inode = self.read_inode(dev, parent)?;
+        parent_inode.set_links(parent_inode.links() + 1);
+        self.write_inode(dev, parent, &parent_inode)?;
+        Ok(ino)
+    }
+
+    /// Drop a link to `ino`, freeing it with the last one
+    fn unlink(&mut self, dev: &mut VirtioBlock, ino: u32) -> Result<(), &'static str> {
+        let mut inode = self.read_inode(dev, ino)?;
+        let links = inode.links().saturating_sub(1);
+        inode.set_links(links);
+        if links == 0 {
+            self.truncate(dev, &mut inode)?;
+            put32(&mut inode.raw, 20, self.now());
+            self.write_inode(dev, ino, &inode)?;
+            self.free_inode(dev, ino, false)
+        } else {
+            self.write_inode(dev, ino, &inode)
+        }
+    }
+
+    /// Free an empty directory that is no longer linked from its parent
+    fn drop_dir(
+        &mut self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        parent: u32,
+    ) -> Result<(), &'static str> {
+        let mut inode = self.read_inode(dev, ino)?;
+        self.truncate(dev, &mut inode)?;
+        inode.set_links(0);
+        put32(&mut inode.raw, 20, self.now());
+        self.write_inode(dev, ino, &inode)?;
+        self.free_inode(dev, ino, true)?;
+
+        let mut parent_inode = self.read_inode(dev, parent)?;
+        parent_inode.set_links(parent_inode.links().saturating_sub(1));
+        self.write_inode(dev, parent, &parent_inode)
+    }
+
+    fn is_empty_dir(&self, dev: &mut VirtioBlock, dir: &Inode) -> Result<bool, &'static str> {
+        Ok(self
+            .dir_entries(dev, dir)?
+            .iter()
+            .all(|e| e.name == "." || e.name == ".."))
+    }
+
+    // ─── Public API (paths as used by `FileSystem`) ─────────────────────────
+
+    pub fn read_file(&self, dev: &mut VirtioBlock, path: &str) -> Option<Vec<u8>> {
+        let ino = self.lookup(dev, path).ok()??;
+        let inode = self.read_inode(dev, ino).ok()?;
+        if !inode.is_file() {
+            return None;
+        }
+        self.read_data(dev, &inode).ok()
+    }
+
+    /// Create or replace a file, creating missing parent directories
+    pub fn write_file(
+        &mut self,
+        dev: &mut VirtioBlock,
+        path: &str,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Read-only filesystem");
+        }
+        let (parent, name) = self.parent_of(dev, path, true)?;
+        let dir = self.read_inode(dev, parent)?;
+        let now = self.now();
+        let (ino, mut inode) = match self.lookup_in(dev, &dir, name)? {
+            Some(ino) => {
+                let mut inode = self.read_inode(dev, ino)?;
+                if !inode.is_file() {
+                    return Err("Is a directory");
+                }
+                self.truncate(dev, &mut inode)?;
+                (ino, inode)
+            }
+            None => {
+                let ino = self.alloc_inode(dev, parent, false)?;
+                let mut inode = Inode {
+                    raw: vec![0u8; self.inode_size],
+                };
+                put16(&mut inode.raw, 0, S_IFREG | 0o644);
+                inode.set_links(1);
+                self.write_inode(dev, ino, &inode)?;
+                self.add_entry(dev, parent, name, ino, FT_REG_FILE)?;
+                (ino, inode)
+            }
+        };
+
+        let blocks = self.alloc_blocks(dev, data.len().div_ceil(self.block_size), ino)?;
+        for (&block, chunk) in blocks.iter().zip(data.chunks(self.block_size)) {
+            let mut buf = vec![0u8; self.block_size];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.write_block(dev, block, &buf)?;
+        }
+        self.set_blocks(dev, ino, &mut inode, &blocks)?;
+        inode.set_size(data.len() as u64);
+        inode.touch(now);
+        self.write_inode(dev, ino, &inode)?;
+        self.flush(dev)
+    }
+
+    pub fn mkdir(&mut self, dev: &mut VirtioBlock, path: &str) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Read-only filesystem");
+        }
+        let (parent, name) = self.parent_of(dev, path, false)?;
+        let dir = self.read_inode(dev, parent)?;
+        if self.lookup_in(dev, &dir, name)?.is_some() {
+            return Err("Directory already exists");
+        }
+        self.make_dir(dev, parent, name)?;
+        self.flush(dev)
+    }
+
+    /// Remove a file or empty directory
+    pub fn remove(&mut self, dev: &mut VirtioBlock, path: &str) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Read-only filesystem");
+        }
+        let (parent, name) = self.parent_of(dev, path, false)?;
+        let dir = self.read_inode(dev, parent)?;
+        let ino = self.lookup_in(dev, &dir, name)?.ok_or("File not found")?;
+        let inode = self.read_inode(dev, ino)?;
+        if inode.is_dir() {
+            if !self.is_empty_dir(dev, &inode)? {
+                return Err("Directory not empty");
+            }
+            self.remove_entry(dev, parent, name)?;
+            self.drop_dir(dev, ino, parent)?;
+        } else {
+            self.remove_entry(dev, parent, name)?;
+            self.unlink(dev, ino)?;
+        }
+        self.flush(dev)
+    }
+
+    /// Move `from` to `to`, replacing a file or empty directory there
+    pub fn rename(
+        &mut self,
+        dev: &mut VirtioBlock,
+        from: &str,
+        to: &str,
+    ) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Read-only filesystem");
+        }
+        let (from_parent, from_name) = self.parent_of(dev, from, false)?;
+        let from_dir = self.read_inode(dev, from_parent)?;
+        let ino = self
+            .lookup_in(dev, &from_dir, from_name)?
+            .ok_or("File not found")?;
+        let is_dir = self.read_inode(dev, ino)?.is_dir();
+
+        let (to_parent, to_name) = self.parent_of(dev, to, false)?;
+        if is_dir {
+            // A directory can't move below itself
+            let mut at = to_parent;
+            while at != ROOT_INO {
+                if at == ino {
+                    return Err("Cannot move a directory into itself");
+                }
+                let dir = self.read_inode(dev, at)?;
+                at = self
+                    .lookup_in(dev, &dir, "..")?
+                    .ok_or("Corrupt directory")?;
+            }
+        }
+
+        let to_dir = self.read_inode(dev, to_parent)?;
+        match self.lookup_in(dev, &to_dir, to_name)? {
+            Some(existing) if existing == ino => return Ok(()),
+            Some(existing) => {
+                let target = self.read_inode(dev, existing)?;
+                match (is_dir, target.is_dir()) {
+                    (true, true) => {
+                        if !self.is_empty_dir(dev, &target)? {
+                            return Err("Directory not empty");
+                        }
+                        self.remove_entry(dev, to_parent, to_name)?;
+                        self.drop_dir(dev, existing, to_parent)?;
+                    }
+                    (false, false) => {
+                        self.remove_entry(dev, to_parent, to_name)?;
+                        self.unlink(dev, existing)?;
+                    }
+                    (true, false) => return Err("Not a directory"),
+                    (false, true) => return Err("Is a directory"),
+                }
+            }
+            None => {}
+        }
+
+        let file_type = if is_dir { FT_DIR } else { FT_REG_FILE };
+        self.add_entry(dev, to_parent, to_name, ino, file_type)?;
+        self.remove_entry(dev, from_parent, from_name)?;
+
+        if is_dir && from_parent != to_parent {
+            // Repoint `..` and move the link it counts for
+            self.remove_entry(dev, ino, "..")?;
+            self.add_entry(dev, ino, "..", to_parent, FT_DIR)?;
+            let mut old = self.read_inode(dev, from_parent)?;
+            old.set_links(old.links().saturating_sub(1));
+            self.write_inode(dev, from_parent, &old)?;
+            let mut new = self.read_inode(dev, to_parent)?;
+            new.set_links(new.links() + 1);
+            self.write_inode(dev, to_parent, &new)?;
+        }
+        let mut inode = self.read_inode(dev, ino)?;
+        put32(&mut inode.raw, 12, self.now());
+        self.write_inode(dev, ino, &inode)?;
+        self.flush(dev)
+    }
+
+    pub fn exists(&self, dev: &mut VirtioBlock, path: &str) -> bool {
+        matches!(self.lookup(dev, path), Ok(Some(_)))
+    }
+
+    pub fn is_dir(&self, dev: &mut VirtioBlock, path: &str) -> bool {
+        match self.lookup(dev, path) {
+            Ok(Some(ino)) => self.read_inode(dev, ino).is_ok_and(|i| i.is_dir()),
+            _ => false,
+        }
+    }
+
+    /// Every file and directory, by full path. Directories end in `/`, as
+    /// SFS directory placeholders do.
+    pub fn list(&self, dev: &mut VirtioBlock) -> Vec<FileInfo> {
+        let mut out = Vec::new();
+        self.list_under(dev, ROOT_INO, "", 0, &mut out);
+        out
+    }
+
+    fn list_under(
+        &self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        prefix: &str,
+        depth: usize,
+        out: &mut Vec<FileInfo>,
+    ) {
+        let Ok(dir) = self.read_inode(dev, ino) else {
+            return;
+        };
+        let Ok(entries) = self.dir_entries(dev, &dir) else {
+            return;
+        };
+        for entry in entries {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let Ok(inode) = self.read_inode(dev, entry.ino) else {
+                continue;
+            };
+            let path = format!("{}/{}", prefix, entry.name);
+            if inode.is_dir() {
+                out.push(FileInfo {
+                    name: format!("{}/", path),
+                    size: 0,
+                    is_dir: true,
+                });
+                if depth < MAX_DEPTH {
+                    self.list_under(dev, entry.ino, &path, depth + 1, out);
+                }
+            } else if inode.is_file() {
+                out.push(FileInfo {
+                    name: path,
+                    size: inode.size().min(u32::MAX as u64) as u32,
+                    is_dir: false,
+                });
+            }
+        }
+    }
+
+    /// Walk the tree from the root, checking every block pointer and marking
+    /// blocks in use that the bitmaps list as free
+    pub fn fsck(&mut self, dev: &mut VirtioBlock) -> Result<FsckReport, &'static str> {
+        let mut report = FsckReport::default();
+        let mut reachable = Vec::new();
+        self.check_under(dev, ROOT_INO, "", 0, &mut report, &mut reachable)?;
+
+        reachable.sort_unstable();
+        reachable.dedup();
+        let mut bitmaps: Vec<(u32, Vec<u8>, i32)> = Vec::new();
+        for block in reachable {
+            let group = (block - self.first_data_block) / self.blocks_per_group;
+            if bitmaps.last().map(|b| b.0) != Some(group) {
+                let bitmap = self.read_block(dev, self.group_field(group, 0))?;
+                bitmaps.push((group, bitmap, 0));
+            }
+            let (_, bitmap, repaired) = bitmaps.last_mut().unwrap();
+            let bit = ((block - self.first_data_block) % self.blocks_per_group) as usize;
+            if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+                *repaired += 1;
+            }
+        }
+        for (group, bitmap, repaired) in bitmaps {
+            if repaired > 0 && !self.read_only {
+                self.write_block(dev, self.group_field(group, 0), &bitmap)?;
+                self.adjust_count(group, 12, -repaired);
+                report.repaired += repaired as usize;
+            }
+        }
+        if report.repaired > 0 {
+            self.flush(dev)?;
+        }
+        Ok(report)
+    }
+
+    fn check_under(
+        &self,
+        dev: &mut VirtioBlock,
+        ino: u32,
+        prefix: &str,
+        depth: usize,
+        report: &mut FsckReport,
+        reachable: &mut Vec<u32>,
+    ) -> Result<(), &'static str> {
+        let dir = self.read_inode(dev, ino)?;
+        let (data, meta) = self.inode_blocks(dev, &dir)?;
+        let before = reachable.len();
+        reachable.extend(data.iter().chain(meta.iter()).filter(|&&b| b != 0));
+        report.blocks += reachable.len() - before;
+
+        for entry in self.dir_entries(dev, &dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let path = format!("{}/{}", prefix, entry.name);
+            let Ok(inode) = self.read_inode(dev, entry.ino) else {
+                report.broken.push(path);
+                continue;
+            };
+            report.files += 1;
+            if inode.is_dir() {
+                if depth < MAX_DEPTH
+                    && self
+                        .check_under(dev, entry.ino, &path, depth + 1, report, reachable)
+                        .is_err()
+                {
+                    report.broken.push(path);
+                }
+            } else if inode.is_file() {
+                match self.inode_blocks(dev, &inode) {
+                    Ok((data, meta)) => {
+                        let before = reachable.len();
+                        reachable.extend(data.iter().chain(meta.iter()).filter(|&&b| b != 0));
+                        report.blocks += reachable.len() - before;
+                    }
+                    Err(_) => report.broken.push(path),
+                }
+            }
+        }
+        Ok(())
+    }
+}
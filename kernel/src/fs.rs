@@ -5,9 +5,15 @@
 //! - Block-level write caching (BufferCache)
 //! - Dirty block tracking for efficient sync
 //! - LRU eviction for cache management
+//!
+//! A disk formatted as ext2 is mounted with the [`crate::ext2`] driver
+//! instead, behind the same `FileSystem` calls; SFS is the fallback.
 
+use crate::ext2::Ext2;
+use crate::tmpfs;
 use crate::virtio_blk::VirtioBlock;
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
@@ -18,6 +24,7 @@ const SEC_SUPER: u64 = 0;
 const SEC_MAP_START: u64 = 1;
 pub const SEC_DIR_START: u64 = 65;
 pub const SEC_DIR_COUNT: u64 = 64;
+const SEC_DATA_START: u64 = 129;
 
 /// Maximum number of cached blocks
 const CACHE_MAX_BLOCKS: usize = 64;
@@ -33,6 +40,20 @@ struct DirEntry {
     head: u32,
 }
 
+/// Result of a filesystem check
+#[derive(Default)]
+pub struct FsckReport {
+    /// Directory entries examined
+    pub files: usize,
+    /// Data blocks reachable from directory entries
+    pub blocks: usize,
+    /// Files whose block chain leaves the disk, enters the metadata area or
+    /// ends before the recorded size
+    pub broken: Vec<String>,
+    /// Reachable blocks that the bitmap listed as free (marked used again)
+    pub repaired: usize,
+}
+
 /// Information about a file in the filesystem
 /// Used by the scripting engine to expose directory listing
 #[derive(Clone)]
@@ -260,10 +281,21 @@ pub struct FileSystem {
     bitmap_dirty: bool,
     /// Block cache for improved performance
     cache: BufferCache,
+    /// Set when the disk holds ext2, which then serves every call
+    ext2: Option<Ext2>,
 }
 
 impl FileSystem {
     pub fn init(dev: &mut VirtioBlock) -> Option<Self> {
+        if let Some(ext2) = Ext2::mount(dev) {
+            return Some(Self {
+                bitmap_cache: [0; 512],
+                bitmap_dirty: false,
+                cache: BufferCache::new(),
+                ext2: Some(ext2),
+            });
+        }
+
         let mut buf = [0u8; 512];
         if dev.read_sector(SEC_SUPER, &mut buf).is_err() {
             return None;
@@ -283,11 +315,29 @@ impl FileSystem {
             bitmap_cache: buf,
             bitmap_dirty: false,
             cache: BufferCache::new(),
+            ext2: None,
         })
     }
 
+    /// Filesystem type on the disk, as shown by `df`
+    pub fn kind(&self) -> &'static str {
+        if self.ext2.is_some() {
+            "ext2"
+        } else {
+            "sfs"
+        }
+    }
+
+    /// Whether the disk can only be read
+    pub fn is_read_only(&self) -> bool {
+        self.ext2.as_ref().is_some_and(|e| e.is_read_only())
+    }
+
     /// Sync all cached data to disk
     pub fn sync(&mut self, dev: &mut VirtioBlock) -> Result<usize, &'static str> {
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.sync(dev);
+        }
         // Sync bitmap if dirty
         if self.bitmap_dirty {
             dev.write_sector(SEC_MAP_START, &self.bitmap_cache)?;
@@ -328,13 +378,84 @@ impl FileSystem {
 
     /// Get disk usage in bytes: (used_bytes, total_bytes)
     pub fn disk_usage_bytes(&self) -> (u64, u64) {
+        if let Some(ext2) = self.ext2.as_ref() {
+            return ext2.usage_bytes();
+        }
         let (used_blocks, total_blocks) = self.disk_stats();
         (used_blocks * 512, total_blocks * 512)
     }
 
+    /// Check every directory entry's block chain and mark blocks in use
+    /// that the bitmap lists as free, so they can't be handed out twice.
+    ///
+    /// Blocks leaked by overwritten or removed files are not reclaimed.
+    pub fn fsck(&mut self, dev: &mut VirtioBlock) -> Result<FsckReport, &'static str> {
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.fsck(dev);
+        }
+        let mut sector = [0u8; 512];
+        dev.read_sector(SEC_SUPER, &mut sector)?;
+        let total_sectors = u32::from_le_bytes(sector[4..8].try_into().unwrap()) as u64;
+
+        let mut entries = Vec::new();
+        for i in 0..SEC_DIR_COUNT {
+            let buf = self.cache.read(dev, SEC_DIR_START + i)?;
+            for j in 0..16 {
+                let offset = j * 32;
+                if buf[offset] != 0 {
+                    entries
+                        .push(unsafe { *(buf[offset..offset + 32].as_ptr() as *const DirEntry) });
+                }
+            }
+        }
+
+        let mut report = FsckReport::default();
+        for entry in entries {
+            report.files += 1;
+            let mut remaining = entry.size as usize;
+            let mut next = entry.head as u64;
+            let mut ok = true;
+            while remaining > 0 {
+                if next < SEC_DATA_START || next >= total_sectors {
+                    ok = false;
+                    break;
+                }
+                report.blocks += 1;
+                let (byte, bit) = ((next / 8) as usize, next % 8);
+                if byte < self.bitmap_cache.len() && self.bitmap_cache[byte] & (1 << bit) == 0 {
+                    self.bitmap_cache[byte] |= 1 << bit;
+                    self.bitmap_dirty = true;
+                    report.repaired += 1;
+                }
+                let buf = self.cache.read(dev, next)?;
+                remaining = remaining.saturating_sub(508);
+                next = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as u64;
+            }
+            if !ok {
+                let len = entry.name.iter().position(|&c| c == 0).unwrap_or(24);
+                report.broken.push(String::from(
+                    core::str::from_utf8(&entry.name[..len]).unwrap_or("???"),
+                ));
+            }
+        }
+
+        if self.bitmap_dirty {
+            self.sync(dev)?;
+        }
+        Ok(report)
+    }
+
     /// List all files in the root directory
     /// Returns a Vec of FileInfo structs for use by the scripting engine
+    /// Entries under /tmp come from tmpfs; SFS entries there are hidden
     pub fn list_dir(&mut self, dev: &mut VirtioBlock, _path: &str) -> Vec<FileInfo> {
+        if let Some(ext2) = self.ext2.as_ref() {
+            let mut entries = ext2.list(dev);
+            entries.retain(|f| !tmpfs::owns(&f.name));
+            entries.extend(tmpfs::list());
+            return entries;
+        }
+
         let mut entries = Vec::new();
         let mut consecutive_empty = 0;
 
@@ -359,9 +480,12 @@ impl FileSystem {
 
                 // Decode Name
                 let name_len = entry.name.iter().position(|&c| c == 0).unwrap_or(24);
-                let name = core::str::from_utf8(&entry.name[..name_len])
+                let name: String = core::str::from_utf8(&entry.name[..name_len])
                     .unwrap_or("???")
                     .into();
+                if tmpfs::owns(&name) {
+                    continue;
+                }
 
                 entries.push(FileInfo {
                     name,
@@ -381,6 +505,7 @@ impl FileSystem {
                 consecutive_empty = 0;
             }
         }
+        entries.extend(tmpfs::list());
         entries
     }
 
@@ -436,6 +561,12 @@ impl FileSystem {
     }
 
     pub fn read_file(&self, dev: &mut VirtioBlock, filename: &str) -> Option<Vec<u8>> {
+        if tmpfs::owns(filename) {
+            return tmpfs::read(filename);
+        }
+        if let Some(ext2) = self.ext2.as_ref() {
+            return ext2.read_file(dev, filename);
+        }
         let entry = self.find_entry(dev, filename)?;
         let mut data = Vec::with_capacity(entry.size as usize);
         let mut next = entry.head;
@@ -460,6 +591,13 @@ impl FileSystem {
         filename: &str,
         data: &[u8],
     ) -> Result<(), &'static str> {
+        if tmpfs::owns(filename) {
+            return tmpfs::write(filename, data);
+        }
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.write_file(dev, filename, data);
+        }
+
         // Simple implementation: Overwrite existing or Create new
         let (sector, index) = match self.find_entry_pos(dev, filename) {
             Some(pos) => pos,
@@ -624,6 +762,13 @@ impl FileSystem {
     /// In SFS, directories are represented by files with names ending in /
     /// and containing references to their children
     pub fn mkdir(&mut self, dev: &mut VirtioBlock, path: &str) -> Result<(), &'static str> {
+        if tmpfs::owns(path) {
+            return tmpfs::mkdir(path);
+        }
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.mkdir(dev, path);
+        }
+
         // Normalize path - ensure it ends with /
         let dir_path = if path.ends_with('/') {
             String::from(path)
@@ -647,6 +792,13 @@ impl FileSystem {
 
     /// Remove a file or empty directory
     pub fn remove(&mut self, dev: &mut VirtioBlock, path: &str) -> Result<(), &'static str> {
+        if tmpfs::owns(path) {
+            return tmpfs::remove(path);
+        }
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.remove(dev, path);
+        }
+
         let (sector, index) = self.find_entry_pos(dev, path).ok_or("File not found")?;
 
         // Check if it's a directory with children
@@ -673,13 +825,93 @@ impl FileSystem {
         Ok(())
     }
 
+    /// Move a file or directory, replacing a file at the destination.
+    /// Moving into or out of /tmp copies the file.
+    pub fn rename(&mut self, dev: &mut VirtioBlock, from: &str, to: &str) -> Result<(), &'static str> {
+        if tmpfs::owns(from) || tmpfs::owns(to) {
+            if self.is_dir(dev, from) {
+                return Err("Cannot move a directory in or out of /tmp");
+            }
+            let data = self.read_file(dev, from).ok_or("File not found")?;
+            self.write_file(dev, to, &data)?;
+            return self.remove(dev, from);
+        }
+        if let Some(ext2) = self.ext2.as_mut() {
+            return ext2.rename(dev, from, to);
+        }
+
+        // SFS names are full paths, so a directory moves by renaming its
+        // placeholder and everything under it
+        let from = from.trim_end_matches('/');
+        let to = to.trim_end_matches('/');
+        let dir_prefix = format!("{}/", from);
+        let mut renames = Vec::new();
+        let mut replaced = None;
+        for i in 0..SEC_DIR_COUNT {
+            let sector = SEC_DIR_START + i;
+            let buf = self.cache.read(dev, sector)?;
+            for j in 0..16 {
+                let offset = j * 32;
+                if buf[offset] == 0 {
+                    continue;
+                }
+                let entry = unsafe { &*(buf[offset..offset + 32].as_ptr() as *const DirEntry) };
+                let len = entry.name.iter().position(|&c| c == 0).unwrap_or(24);
+                let name = core::str::from_utf8(&entry.name[..len]).unwrap_or("");
+                if name == from {
+                    renames.push((sector, j, String::from(to)));
+                } else if let Some(rest) = name.strip_prefix(&dir_prefix) {
+                    renames.push((sector, j, format!("{}/{}", to, rest)));
+                } else if name == to {
+                    replaced = Some((sector, j));
+                }
+            }
+        }
+        if renames.is_empty() {
+            return Err("File not found");
+        }
+        if renames.iter().any(|(_, _, name)| name.len() > 24) {
+            return Err("Name too long");
+        }
+        if let Some((sector, index)) = replaced {
+            if renames.len() > 1 {
+                return Err("Not a directory");
+            }
+            let buf = self.cache.read_mut(dev, sector)?;
+            buf[index * 32..index * 32 + 32].fill(0);
+            self.cache.mark_dirty(sector);
+        }
+        for (sector, index, name) in renames {
+            let buf = self.cache.read_mut(dev, sector)?;
+            let field = &mut buf[index * 32..index * 32 + 24];
+            field.fill(0);
+            field[..name.len()].copy_from_slice(name.as_bytes());
+            self.cache.mark_dirty(sector);
+        }
+        self.cache.sync(dev)?;
+        Ok(())
+    }
+
     /// Check if a path exists
     pub fn exists(&self, dev: &mut VirtioBlock, path: &str) -> bool {
+        if tmpfs::owns(path) {
+            return tmpfs::exists(path);
+        }
+        if let Some(ext2) = self.ext2.as_ref() {
+            return ext2.exists(dev, path);
+        }
         self.find_entry_pos(dev, path).is_some()
     }
 
     /// Check if a path is a directory
     pub fn is_dir(&mut self, dev: &mut VirtioBlock, path: &str) -> bool {
+        if tmpfs::owns(path) {
+            return tmpfs::is_dir(path);
+        }
+        if let Some(ext2) = self.ext2.as_ref() {
+            return ext2.is_dir(dev, path);
+        }
+
         // Check if path ends with / or has children
         if path.ends_with('/') {
             return self.find_entry_pos(dev, path).is_some();
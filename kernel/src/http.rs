@@ -67,7 +67,7 @@ impl HttpRequest {
         headers.insert("Host".to_string(), parsed.host.clone());
         headers.insert(
             "User-Agent".to_string(),
-            format!("BAVY OS/{}", env!("CARGO_PKG_VERSION")),
+            crate::ident::user_agent(),
         );
         headers.insert("Accept".to_string(), "*/*".to_string());
         headers.insert("Connection".to_string(), "close".to_string());
@@ -347,6 +347,10 @@ pub fn http_request(
             net.tcp_abort();
             return Err("Connection timeout");
         }
+        if let Err(e) = crate::jobs::checkpoint() {
+            net.tcp_abort();
+            return Err(e);
+        }
 
         net.poll(now);
 
@@ -405,6 +409,10 @@ pub fn http_request(
             net.tcp_abort();
             return Err("Receive timeout");
         }
+        if let Err(e) = crate::jobs::checkpoint() {
+            net.tcp_abort();
+            return Err(e);
+        }
 
         net.poll(now);
 
@@ -548,7 +556,7 @@ pub fn http_request_follow_redirects(
         new_headers.insert("Host".to_string(), parsed.host.clone());
         new_headers.insert(
             "User-Agent".to_string(),
-            format!("BAVY OS/{}", env!("CARGO_PKG_VERSION")),
+            crate::ident::user_agent(),
         );
         new_headers.insert("Accept".to_string(), "*/*".to_string());
         new_headers.insert("Connection".to_string(), "close".to_string());
@@ -588,7 +596,7 @@ fn resolve_host(
     crate::dns::resolve(
         net,
         host.as_bytes(),
-        crate::net::DNS_SERVER,
+        crate::net::get_dns_server(),
         timeout_ms,
         get_time_ms,
     )
@@ -698,6 +706,7 @@ fn https_request(
                 crate::tls::TlsError::NotConnected => "HTTPS: Not connected",
                 crate::tls::TlsError::DnsError => "HTTPS: DNS resolution failed",
                 crate::tls::TlsError::InternalError => "HTTPS: Internal TLS error",
+                crate::tls::TlsError::Interrupted => "Interrupted",
             })?
         }
     };
@@ -0,0 +1,290 @@
+//! Tiny HTTP server
+//!
+//! The `httpd` service serves files from `/www` on port 80. It answers
+//! `GET` and `HEAD`, maps directories to their `index.html`, and closes
+//! each connection after one response (HTTP/1.0 style).
+//!
+//! Like the other daemons it is a task pinned to hart 0, since only hart 0
+//! can reach VirtIO in the browser. Its sockets come from the network
+//! stack's TCP pool, one per client it can serve at a time.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::init::ServiceStatus;
+use crate::klog::{klog_error, klog_info};
+use crate::net::{TcpId, TcpState};
+use crate::{Spinlock, BLK_DEV, FS_STATE, NET_STATE};
+
+/// Service name, as used with `service httpd start`
+pub const SERVICE: &str = "httpd";
+
+/// Port the server listens on
+const PORT: u16 = 80;
+
+/// Directory files are served from
+const ROOT: &str = "/www";
+
+/// Pause between steps of the server
+const POLL_INTERVAL_MS: u64 = 20;
+
+/// Clients served at the same time
+const MAX_CONNECTIONS: usize = 2;
+
+/// Largest request header accepted
+const MAX_REQUEST_LEN: usize = 2048;
+
+/// A client that hasn't sent a full request or taken its response by then
+/// is dropped
+const CONNECTION_TIMEOUT_MS: i64 = 10_000;
+
+struct Connection {
+    id: TcpId,
+    request: Vec<u8>,
+    response: Option<Vec<u8>>,
+    sent: usize,
+    /// When the client connected, 0 while listening
+    since: i64,
+}
+
+impl Connection {
+    fn reset(&mut self) {
+        self.request.clear();
+        self.response = None;
+        self.sent = 0;
+        self.since = 0;
+    }
+}
+
+struct Server {
+    connections: Vec<Connection>,
+}
+
+static SERVER: Spinlock<Option<Server>> = Spinlock::new(None);
+
+/// Run one step of the server, starting or stopping it to follow the
+/// service state.
+pub fn tick() {
+    let running = crate::init::service_status(SERVICE) == Some(ServiceStatus::Running);
+    let mut server = SERVER.lock();
+    if !running {
+        if let Some(server) = server.take() {
+            stop(server);
+        }
+        return;
+    }
+    if server.is_none() {
+        *server = start();
+    }
+    if let Some(server) = server.as_mut() {
+        serve(server);
+    }
+}
+
+/// Entry point registered with init
+pub fn httpd_service() {
+    loop {
+        tick();
+        crate::scheduler::sleep_ms(POLL_INTERVAL_MS);
+    }
+}
+
+/// Release the sockets of a server whose service was stopped, since its
+/// task is gone. Called from the hart 0 ticks.
+pub fn reap() {
+    if crate::init::service_status(SERVICE) == Some(ServiceStatus::Running) {
+        return;
+    }
+    let server = SERVER.lock().take();
+    if let Some(server) = server {
+        stop(server);
+    }
+}
+
+fn start() -> Option<Server> {
+    let mut net_guard = NET_STATE.lock();
+    // Wait for `ifup` if networking isn't up yet
+    let net = net_guard.as_mut()?;
+
+    let mut connections = Vec::new();
+    for _ in 0..MAX_CONNECTIONS {
+        let opened = net
+            .tcp_open(SERVICE)
+            .and_then(|id| match net.tcp_listen(id, PORT) {
+                Ok(()) => Ok(id),
+                Err(e) => {
+                    net.tcp_release(id);
+                    Err(e)
+                }
+            });
+        match opened {
+            Ok(id) => connections.push(Connection {
+                id,
+                request: Vec::new(),
+                response: None,
+                sent: 0,
+                since: 0,
+            }),
+            Err(e) => {
+                for conn in connections {
+                    net.tcp_release(conn.id);
+                }
+                drop(net_guard);
+                klog_error(SERVICE, &format!("Cannot listen on port {}: {}", PORT, e));
+                let _ = crate::init::stop_service(SERVICE);
+                return None;
+            }
+        }
+    }
+    klog_info(SERVICE, &format!("Serving {} on port {}", ROOT, PORT));
+    Some(Server { connections })
+}
+
+fn stop(server: Server) {
+    if let Some(net) = NET_STATE.lock().as_mut() {
+        for conn in server.connections {
+            net.tcp_release(conn.id);
+        }
+    }
+    klog_info(SERVICE, "Stopped");
+}
+
+fn serve(server: &mut Server) {
+    let now = crate::get_time_ms();
+
+    // Read requests; the network lock is released before touching the disk
+    {
+        let mut net_guard = NET_STATE.lock();
+        let Some(net) = net_guard.as_mut() else {
+            return;
+        };
+        for conn in server.connections.iter_mut() {
+            let state = net.tcp_status(conn.id);
+            let timed_out = conn.since != 0 && now - conn.since > CONNECTION_TIMEOUT_MS;
+            if matches!(state, TcpState::Closed | TcpState::TimeWait) || timed_out {
+                // Done with this client (or gave up on it): take the next one
+                conn.reset();
+                let _ = net.tcp_listen(conn.id, PORT);
+                continue;
+            }
+            if !matches!(state, TcpState::Established | TcpState::CloseWait) {
+                continue;
+            }
+            if conn.since == 0 {
+                conn.since = now;
+            }
+            if conn.response.is_some() {
+                continue;
+            }
+            let mut buf = [0u8; 512];
+            while conn.request.len() < MAX_REQUEST_LEN {
+                match net.tcp_read(conn.id, &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => conn.request.extend_from_slice(&buf[..n]),
+                }
+            }
+        }
+    }
+
+    for conn in server.connections.iter_mut() {
+        let complete = conn.request.windows(4).any(|w| w == b"\r\n\r\n")
+            || conn.request.len() >= MAX_REQUEST_LEN;
+        if conn.response.is_none() && complete {
+            conn.response = Some(respond(&conn.request));
+        }
+    }
+
+    // Send responses, closing connections that are fully written
+    let mut net_guard = NET_STATE.lock();
+    let Some(net) = net_guard.as_mut() else {
+        return;
+    };
+    for conn in server.connections.iter_mut() {
+        let Some(response) = conn.response.as_ref() else {
+            continue;
+        };
+        if conn.sent < response.len() {
+            match net.tcp_write(conn.id, &response[conn.sent..], now) {
+                Ok(n) => conn.sent += n,
+                Err(_) => conn.sent = response.len(), // Client went away
+            }
+            if conn.sent == response.len() {
+                net.tcp_shutdown(conn.id, now);
+            }
+        }
+    }
+}
+
+/// Build the full response to a request
+fn respond(request: &[u8]) -> Vec<u8> {
+    let head = String::from_utf8_lossy(request);
+    let mut parts = head.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+
+    let (status, content_type, body) = match method {
+        "GET" | "HEAD" if !path.starts_with('/') || path.split('/').any(|p| p == "..") => {
+            error_page("400 Bad Request")
+        }
+        "GET" | "HEAD" => match read_file(path) {
+            Some(Ok((file, data))) => ("200 OK", content_type(&file), data),
+            Some(Err(())) => error_page("404 Not Found"),
+            None => error_page("503 Service Unavailable"),
+        },
+        _ => error_page("405 Method Not Allowed"),
+    };
+    klog_info(SERVICE, &format!("{} {} {}", method, target, status));
+
+    let mut response = format!(
+        "HTTP/1.0 {}\r\nServer: bavy-httpd\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .into_bytes();
+    if method != "HEAD" {
+        response.extend_from_slice(&body);
+    }
+    response
+}
+
+fn error_page(status: &'static str) -> (&'static str, &'static str, Vec<u8>) {
+    let body = format!(
+        "<html><body><h1>{}</h1><hr><p>bavy-httpd</p></body></html>\n",
+        status
+    );
+    (status, "text/html", body.into_bytes())
+}
+
+/// Read the file for a request path, returning its full name. `None` if
+/// the filesystem isn't available.
+fn read_file(path: &str) -> Option<Result<(String, Vec<u8>), ()>> {
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (fs, dev) = (fs_guard.as_mut()?, blk_guard.as_mut()?);
+
+    let mut file = String::from(ROOT);
+    file.push_str(path.trim_end_matches('/'));
+    if path.ends_with('/') || fs.is_dir(dev, &file) {
+        file.push_str("/index.html");
+    }
+    Some(fs.read_file(dev, &file).map(|data| (file, data)).ok_or(()))
+}
+
+fn content_type(file: &str) -> &'static str {
+    let ext = file.rsplit_once('.').map_or("", |(_, ext)| ext);
+    match ext {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "txt" | "md" | "log" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
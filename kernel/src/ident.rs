@@ -0,0 +1,159 @@
+//! Kernel and machine identification
+//!
+//! One place for the names and versions the kernel reports about itself:
+//! `uname`, `sysinfo`, `top`, the HTTP User-Agent and the boot banner all
+//! read them from here. Emulator details come from the read-only BuildInfo
+//! page riscv-vm maps at 0x0013_0000; other platforms (e.g. QEMU) leave it
+//! unmapped and report "unknown".
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::{setup, SYSINFO_KERNEL_ID, SYSINFO_KERNEL_ID_LEN};
+
+/// Kernel name (`uname -s`)
+pub const SYSNAME: &str = "BAVY";
+/// Operating system name (`uname -o`)
+pub const OS_NAME: &str = "BAVY OS";
+/// Kernel release (`uname -r`)
+pub const RELEASE: &str = env!("CARGO_PKG_VERSION");
+/// Build timestamp, UTC (set by build.rs)
+pub const BUILD_DATE: &str = env!("KERNEL_BUILD_DATE");
+/// Machine hardware name (`uname -m`)
+pub const MACHINE: &str = "riscv64";
+/// Human-readable architecture description
+pub const ARCHITECTURE: &str = "RISC-V 64-bit (RV64GC)";
+
+/// "BAVY OS v0.1.0", for banners and headers
+pub fn os_version() -> String {
+    format!("{} v{}", OS_NAME, RELEASE)
+}
+
+/// HTTP User-Agent product token
+pub fn user_agent() -> String {
+    format!("{}/{}", OS_NAME, RELEASE)
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// BUILDINFO MMIO DEVICE - emulator identification, read-only
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Base address for the BuildInfo MMIO device (must match emulator)
+const BUILDINFO_BASE: usize = 0x0013_0000;
+/// "RVBI" at offset 0 when the page is present
+const BUILDINFO_MAGIC: u32 = u32::from_le_bytes(*b"RVBI");
+
+/// BuildInfo register offsets
+const BUILDINFO_HARTS: usize = BUILDINFO_BASE + 0x0c;
+const BUILDINFO_FEATURES: usize = BUILDINFO_BASE + 0x10;
+const BUILDINFO_VERSION_STR: usize = BUILDINFO_BASE + 0x40;
+const BUILDINFO_GIT_HASH: usize = BUILDINFO_BASE + 0x60;
+const BUILDINFO_HOST: usize = BUILDINFO_BASE + 0x80;
+const BUILDINFO_NETWORK: usize = BUILDINFO_BASE + 0xa0;
+
+/// Feature bits in BUILDINFO_FEATURES
+pub const EMU_FEATURE_JIT_BUILTIN: u64 = 1 << 0;
+pub const EMU_FEATURE_JIT_ENABLED: u64 = 1 << 1;
+
+/// Emulator version, commit and configuration, for bug reports
+pub struct EmulatorInfo {
+    pub version: String,
+    pub git_hash: String,
+    pub host: String,
+    pub network: String,
+    pub harts: u32,
+    pub features: u64,
+}
+
+/// Read the emulator identification page, if the emulator provides one
+pub fn emulator_info() -> Option<EmulatorInfo> {
+    // Each string field is 32 bytes of NUL-padded ASCII
+    fn read_str(addr: usize) -> String {
+        let mut s = String::new();
+        for i in 0..32 {
+            let b = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+            if b == 0 {
+                break;
+            }
+            s.push(b as char);
+        }
+        s
+    }
+
+    unsafe {
+        if core::ptr::read_volatile(BUILDINFO_BASE as *const u32) != BUILDINFO_MAGIC {
+            return None;
+        }
+        Some(EmulatorInfo {
+            version: read_str(BUILDINFO_VERSION_STR),
+            git_hash: read_str(BUILDINFO_GIT_HASH),
+            host: read_str(BUILDINFO_HOST),
+            network: read_str(BUILDINFO_NETWORK),
+            harts: core::ptr::read_volatile(BUILDINFO_HARTS as *const u32),
+            features: core::ptr::read_volatile(BUILDINFO_FEATURES as *const u64),
+        })
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// UNAME
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// The fields `uname` prints
+pub struct Uname {
+    pub sysname: &'static str,
+    pub nodename: String,
+    pub release: &'static str,
+    pub version: String,
+    pub machine: &'static str,
+    /// Hardware platform: the emulator build, or "unknown"
+    pub platform: String,
+    pub os: &'static str,
+}
+
+impl Uname {
+    /// Collect the current identification
+    pub fn current() -> Self {
+        let platform = match emulator_info() {
+            Some(emu) => format!("riscv-vm-{}", emu.version),
+            None => String::from("unknown"),
+        };
+        Uname {
+            sysname: SYSNAME,
+            nodename: setup::hostname(),
+            release: RELEASE,
+            version: format!("#1 {}", BUILD_DATE),
+            machine: MACHINE,
+            platform,
+            os: OS_NAME,
+        }
+    }
+
+    /// All fields, in `uname -a` order
+    pub fn all(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {}",
+            self.sysname,
+            self.nodename,
+            self.release,
+            self.version,
+            self.machine,
+            self.platform,
+            self.os
+        )
+    }
+}
+
+/// Publish the `uname -a` line on the SysInfo telemetry page
+pub fn report() {
+    let line = Uname::current().all();
+    let bytes = line.as_bytes();
+    // Keep at least one NUL so the emulator finds the end
+    let len = bytes.len().min(SYSINFO_KERNEL_ID_LEN - 1);
+    for i in 0..SYSINFO_KERNEL_ID_LEN {
+        let b = if i < len { bytes[i] } else { 0 };
+        unsafe {
+            core::ptr::write_volatile((SYSINFO_KERNEL_ID + i) as *mut u8, b);
+        }
+    }
+}
@@ -27,6 +27,12 @@ static INIT_COMPLETE: AtomicBool = AtomicBool::new(false);
 /// Number of services started
 static SERVICES_STARTED: AtomicUsize = AtomicUsize::new(0);
 
+/// IPC endpoint the service manager takes control requests on
+pub const INIT_ENDPOINT: &str = "init";
+
+/// Control requests handled per tick, so a flood can't stall the shell
+const CONTROL_BATCH: usize = 8;
+
 /// Service status
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ServiceStatus {
@@ -99,6 +105,12 @@ pub fn init_main() {
     // Phase 2: Start system services
     klog_info("init", "Phase 2: Starting system services");
     start_system_services();
+    if let Err(e) = crate::ipc::bind(INIT_ENDPOINT) {
+        klog_error(
+            "init",
+            &format!("Cannot bind '{}' endpoint: {}", INIT_ENDPOINT, e),
+        );
+    }
 
     // Phase 3: Run init scripts
     klog_info("init", "Phase 3: Running init scripts");
@@ -164,6 +176,31 @@ fn start_system_services() {
         Some(0), // Pin to hart 0 - has VirtIO access in both native and WASM
     );
 
+    register_service_def(
+        crate::crond::SERVICE,
+        "Cron daemon - runs the commands in /etc/crontab on schedule",
+        crate::crond::crond_service,
+        Priority::Normal,
+        Some(0), // Pin to hart 0 - reads the disk and runs commands
+    );
+
+    register_service_def(
+        crate::sntp::SERVICE,
+        "NTP client - sets the wall clock from the server in /etc/ntp.conf",
+        crate::sntp::ntpd_service,
+        Priority::Normal,
+        Some(0), // Pin to hart 0 - needs the network
+    );
+
+    // Started on demand with `service httpd start`
+    register_service_def(
+        crate::httpd::SERVICE,
+        "HTTP server - serves files from /www on port 80",
+        crate::httpd::httpd_service,
+        Priority::Normal,
+        Some(0), // Pin to hart 0 - needs the network and the disk
+    );
+
     // Auto-start daemons (they're pinned to hart 0, safe in all modes)
     if let Ok(()) = start_service("klogd") {
         klog_info("init", "Auto-started klogd on hart 0");
@@ -171,6 +208,12 @@ fn start_system_services() {
     if let Ok(()) = start_service("sysmond") {
         klog_info("init", "Auto-started sysmond on hart 0");
     }
+    if let Ok(()) = start_service(crate::crond::SERVICE) {
+        klog_info("init", "Auto-started crond on hart 0");
+    }
+    if let Ok(()) = start_service(crate::sntp::SERVICE) {
+        klog_info("init", "Auto-started ntpd on hart 0");
+    }
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -365,7 +408,7 @@ fn run_init_scripts() {
                         drop(fs_guard);
                         
                         // Execute WASM binary
-                        if let Err(e) = crate::wasm::execute(&content, &[]) {
+                        if let Err(e) = crate::wasm::execute(&content, &[], None) {
                             klog_error("init", &format!("Init script error: {}", e));
                         }
                         return; // Re-acquire locks would be complex, just return
@@ -462,13 +505,16 @@ fn append_to_log(line: &str) -> bool {
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
-// COOPERATIVE DAEMON TICKS
-// These functions do one unit of work and return immediately.
-// Called from the shell loop on hart 0.
+// DAEMON TICKS
+// These functions do one unit of work and return immediately. The service
+// tasks call them in a loop; control_tick runs from the shell loop on hart 0.
 // ═══════════════════════════════════════════════════════════════════════════════
 
 use core::sync::atomic::AtomicI64;
 
+/// Pause between ticks of the klogd and sysmond tasks
+const SERVICE_POLL_MS: u64 = 500;
+
 /// State for klogd daemon
 static KLOGD_LAST_RUN: AtomicI64 = AtomicI64::new(0);
 static KLOGD_TICK: AtomicUsize = AtomicUsize::new(0);
@@ -479,8 +525,46 @@ static SYSMOND_LAST_RUN: AtomicI64 = AtomicI64::new(0);
 static SYSMOND_TICK: AtomicUsize = AtomicUsize::new(0);
 static SYSMOND_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// Handle service control requests sent to the `init` endpoint
+///
+/// Each message is `<start|stop|restart> <service> [reply-endpoint]`. When a
+/// reply endpoint is given, the result is sent back to it as `ok` or
+/// `error: <reason>`.
+pub fn control_tick() {
+    for _ in 0..CONTROL_BATCH {
+        let Ok(Some(msg)) = crate::ipc::recv(INIT_ENDPOINT) else {
+            return;
+        };
+        let request = msg.as_str();
+        let parts: Vec<&str> = request.split_whitespace().collect();
+        let result = match parts.as_slice() {
+            ["start", name, ..] => start_service(name),
+            ["stop", name, ..] => stop_service(name),
+            ["restart", name, ..] => restart_service(name),
+            _ => Err("Malformed request"),
+        };
+        klog_debug(
+            "init",
+            &format!(
+                "Control request from PID {}: {} ({:?})",
+                msg.sender, request, result
+            ),
+        );
+        if let Some(reply_to) = parts.get(2) {
+            let reply = match result {
+                Ok(()) => String::from("ok"),
+                Err(e) => format!("error: {}", e),
+            };
+            let _ = crate::ipc::send(reply_to, reply.as_bytes());
+        }
+    }
+}
+
 /// Run klogd work if 5 seconds have passed since last run
 pub fn klogd_tick() {
+    // Critical entries logged while the filesystem was busy
+    crate::klog::KLOG.flush_pending();
+
     let now = crate::get_time_ms();
     let last = KLOGD_LAST_RUN.load(Ordering::Relaxed);
 
@@ -584,16 +668,20 @@ pub fn sysmond_tick() {
     }
 }
 
-/// Legacy service entry points (for task scheduler compatibility)
-/// These are no longer used directly but kept for API compatibility
+/// Service entry points: each tick checks its own timing, so the tasks
+/// only sleep in between
 pub fn klogd_service() {
-    // Single tick - for scheduler-based execution
-    klogd_tick();
+    loop {
+        klogd_tick();
+        crate::scheduler::sleep_ms(SERVICE_POLL_MS);
+    }
 }
 
 pub fn sysmond_service() {
-    // Single tick - for scheduler-based execution
-    sysmond_tick();
+    loop {
+        sysmond_tick();
+        crate::scheduler::sleep_ms(SERVICE_POLL_MS);
+    }
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -3,6 +3,8 @@
 //! Provides message-passing primitives for task communication:
 //! - Channels: Unidirectional, bounded message queues
 //! - Pipes: Byte-stream communication (like Unix pipes)
+//! - Endpoints: Named channels with seqpacket-style datagram semantics,
+//!   addressed by name from native commands, services and WASM programs
 //!
 //! Tasks can block waiting for data, enabling efficient IPC without polling.
 
@@ -10,7 +12,7 @@ use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 
 use crate::task::Pid;
 use crate::Spinlock;
@@ -89,6 +91,8 @@ pub struct Channel {
     waiters: Spinlock<VecDeque<Pid>>,
     /// Channel is closed
     closed: AtomicBool,
+    /// PID of the task that bound the channel (0 = kernel)
+    owner: AtomicU32,
 }
 
 impl Channel {
@@ -108,9 +112,15 @@ impl Channel {
             receiver_active: AtomicBool::new(true),
             waiters: Spinlock::new(VecDeque::new()),
             closed: AtomicBool::new(false),
+            owner: AtomicU32::new(0),
         }
     }
 
+    /// PID of the task that bound the channel
+    pub fn owner(&self) -> Pid {
+        self.owner.load(Ordering::Relaxed)
+    }
+
     /// Send a message to the channel (non-blocking)
     /// Returns Err if channel is full or closed
     pub fn send(&self, msg: Message) -> Result<(), &'static str> {
@@ -398,6 +408,8 @@ pub struct IpcRegistry {
     next_channel_id: AtomicUsize,
     /// Next pipe ID
     next_pipe_id: AtomicUsize,
+    /// Serializes endpoint binds so a name is claimed at most once
+    bind_lock: Spinlock<()>,
 }
 
 impl IpcRegistry {
@@ -408,6 +420,7 @@ impl IpcRegistry {
             pipes: Spinlock::new(BTreeMap::new()),
             next_channel_id: AtomicUsize::new(1),
             next_pipe_id: AtomicUsize::new(1),
+            bind_lock: Spinlock::new(()),
         }
     }
 
@@ -432,6 +445,21 @@ impl IpcRegistry {
         channel
     }
 
+    /// Create a named channel owned by `owner`, unless a live channel
+    /// already holds the name
+    pub fn bind_channel(&self, name: &str, owner: Pid) -> Result<Arc<Channel>, &'static str> {
+        let _guard = self.bind_lock.lock();
+        if let Some(existing) = self.get_channel_by_name(name) {
+            if !existing.is_closed() {
+                return Err("Address in use");
+            }
+            self.remove_channel(existing.id);
+        }
+        let channel = self.create_channel(name);
+        channel.owner.store(owner, Ordering::Relaxed);
+        Ok(channel)
+    }
+
     /// Get a channel by name
     pub fn get_channel_by_name(&self, name: &str) -> Option<Arc<Channel>> {
         let id = *self.channel_names.lock().get(name)?;
@@ -538,3 +566,54 @@ impl IpcRegistry {
 
 /// Global IPC registry
 pub static IPC: IpcRegistry = IpcRegistry::new();
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// NAMED ENDPOINTS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Longest endpoint name accepted by [`bind`]
+pub const MAX_ENDPOINT_NAME: usize = 64;
+
+/// PID of the calling task (0 for kernel code such as the shell)
+fn current_pid() -> Pid {
+    crate::scheduler::SCHEDULER.current_pid(crate::get_hart_id())
+}
+
+/// Bind a named endpoint owned by the calling task
+///
+/// Endpoints behave like SOCK_SEQPACKET sockets: every [`send`] is queued as
+/// one message, delivered whole and in order by a single [`recv`], and
+/// rejected (never split) when the queue is full.
+pub fn bind(name: &str) -> Result<ChannelId, &'static str> {
+    if name.is_empty() || name.len() > MAX_ENDPOINT_NAME || name.contains(char::is_whitespace) {
+        return Err("Invalid endpoint name");
+    }
+    Ok(IPC.bind_channel(name, current_pid())?.id)
+}
+
+/// Close a named endpoint, dropping any queued messages
+pub fn unbind(name: &str) -> Result<(), &'static str> {
+    let channel = IPC.get_channel_by_name(name).ok_or("No such endpoint")?;
+    IPC.remove_channel(channel.id);
+    Ok(())
+}
+
+/// Send one message to a named endpoint
+pub fn send(name: &str, data: &[u8]) -> Result<(), &'static str> {
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err("Message too long");
+    }
+    let channel = IPC.get_channel_by_name(name).ok_or("Connection refused")?;
+    channel.send(Message::new(current_pid(), Vec::from(data), 0))?;
+    crate::task::wake_ipc(channel.id as u64);
+    Ok(())
+}
+
+/// Take the oldest message queued on a named endpoint, if any
+pub fn recv(name: &str) -> Result<Option<Message>, &'static str> {
+    let channel = IPC.get_channel_by_name(name).ok_or("No such endpoint")?;
+    if channel.is_closed() {
+        return Err("Channel closed");
+    }
+    Ok(channel.try_recv())
+}
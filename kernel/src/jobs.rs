@@ -0,0 +1,330 @@
+//! Shell job control
+//!
+//! A command line ending in `&` starts as a scheduler task on a secondary
+//! hart (hart 0 when it is the only one, sharing it with the shell) and the
+//! prompt comes back at once. Jobs are numbered from 1 in the
+//! order they start:
+//! - `jobs` lists them
+//! - `fg [%n]` waits for one in the foreground; Ctrl+Z stops it and returns
+//!   to the prompt, Ctrl+C kills it
+//! - `bg [%n]` resumes a stopped job in the background
+//!
+//! Stopping is cooperative: a WASM program gives up its hart at its next
+//! host call (see [`checkpoint`]), while native commands are short and run
+//! to completion. Programs are read from disk on hart 0 before the job starts,
+//! but in the browser secondary harts have no VirtIO access, so programs
+//! that use the disk or network there belong in the foreground.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+use crate::cmd::registry;
+use crate::scheduler::SCHEDULER;
+use crate::task::{Pid, Priority, TaskState};
+use crate::{out_line, out_str, uart, Spinlock, HARTS_ONLINE};
+
+/// What a job runs, resolved on hart 0 when it is started
+enum Work {
+    Native { name: String, args: String },
+    Script {
+        name: String,
+        bytes: Vec<u8>,
+        args: String,
+    },
+}
+
+struct Job {
+    id: usize,
+    pid: Pid,
+    hart: usize,
+    /// Command line without the `&`
+    command: String,
+    /// Taken by the task when it starts
+    work: Option<Work>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+impl JobState {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobState::Running => "Running",
+            JobState::Stopped => "Stopped",
+            JobState::Done => "Done",
+        }
+    }
+}
+
+/// Jobs in start order
+static JOBS: Spinlock<Vec<Job>> = Spinlock::new(Vec::new());
+
+fn job_state(pid: Pid) -> JobState {
+    match SCHEDULER.get_task(pid).map(|t| t.get_state()) {
+        Some(TaskState::Stopped) => JobState::Stopped,
+        Some(TaskState::Zombie) | None => JobState::Done,
+        Some(_) => JobState::Running,
+    }
+}
+
+/// Look up what `line` runs: a registered command or a program on disk.
+/// Fails with the command name if there is neither.
+fn resolve(line: &str) -> Result<Work, &str> {
+    let (name, args) = match line.split_once(|c: char| c == ' ' || c == '\t') {
+        Some((name, args)) => (name, args.trim_start()),
+        None => (line, ""),
+    };
+    if registry::find(name).is_some() {
+        Ok(Work::Native {
+            name: String::from(name),
+            args: String::from(args),
+        })
+    } else if let Some(bytes) = crate::scripting::find_script(name) {
+        Ok(Work::Script {
+            name: String::from(name),
+            bytes,
+            args: String::from(args),
+        })
+    } else {
+        Err(name)
+    }
+}
+
+fn run(work: Work) {
+    match work {
+        Work::Native { name, args } => {
+            registry::dispatch(&name, &args);
+        }
+        Work::Script { name, bytes, args } => {
+            crate::run_script_bytes(&name, &bytes, &args, None)
+        }
+    }
+}
+
+/// Run `line` to completion in the calling task, the way a job would run
+/// it (no pipes or redirection). Fails with the command name if it isn't
+/// found.
+pub fn run_line(line: &str) -> Result<(), String> {
+    let work = resolve(line).map_err(String::from)?;
+    run(work);
+    Ok(())
+}
+
+/// Start `line` (without the trailing `&`) as a background job.
+pub fn spawn(line: &str) {
+    let online = HARTS_ONLINE.load(Ordering::Relaxed);
+    let harts = if online < 2 { 0..1 } else { 1..online };
+
+    let name = line.split([' ', '\t']).next().unwrap_or(line);
+    let work = match resolve(line) {
+        Ok(work) => work,
+        Err(name) => {
+            out_str("\x1b[1;31mCommand not found:\x1b[0m ");
+            out_line(name);
+            return;
+        }
+    };
+
+    // Jobs on one hart share its time, so use the one with fewest jobs
+    let mut jobs = JOBS.lock();
+    let fallback = harts.start;
+    let hart = harts
+        .min_by_key(|&hart| {
+            jobs.iter()
+                .filter(|j| j.hart == hart && job_state(j.pid) != JobState::Done)
+                .count()
+        })
+        .unwrap_or(fallback);
+    let id = jobs.last().map_or(1, |j| j.id + 1);
+
+    // The task looks itself up in JOBS, which stays locked until it is there
+    let pid = SCHEDULER.spawn_on_hart(name, job_main, Priority::Normal, Some(hart));
+    jobs.push(Job {
+        id,
+        pid,
+        hart,
+        command: String::from(line),
+        work: Some(work),
+    });
+    out_line(&format!("[{}] {}", id, pid));
+}
+
+/// Task entry of every job
+fn job_main() {
+    let pid = SCHEDULER.current_pid(crate::get_hart_id());
+    let work = JOBS
+        .lock()
+        .iter_mut()
+        .find(|j| j.pid == pid)
+        .and_then(|j| j.work.take());
+    if let Some(work) = work {
+        run(work);
+    }
+}
+
+/// Called by long-running programs between steps: gives up the hart while
+/// the task running it is stopped, and fails once it has been killed.
+/// Outside tasks (in the foreground on the shell) it fails once Ctrl+C has
+/// been typed.
+pub fn checkpoint() -> Result<(), &'static str> {
+    let pid = SCHEDULER.current_pid(crate::get_hart_id());
+    if pid == 0 {
+        if crate::uart::interrupted() {
+            return Err("interrupted");
+        }
+        return Ok(());
+    }
+    loop {
+        match job_state(pid) {
+            JobState::Running => return Ok(()),
+            JobState::Done => return Err("killed"),
+            JobState::Stopped => crate::scheduler::yield_now(),
+        }
+    }
+}
+
+/// Job selected by a `%n` (or `n`) argument, or the most recent one
+fn select(cmd: &str, args: &str) -> Option<(usize, Pid, usize, String)> {
+    let jobs = JOBS.lock();
+    let spec = args.trim();
+    let job = if spec.is_empty() {
+        jobs.iter().rev().find(|j| job_state(j.pid) != JobState::Done)
+    } else {
+        let id = spec.trim_start_matches('%').parse::<usize>().ok();
+        jobs.iter().find(|j| Some(j.id) == id)
+    };
+    match job {
+        Some(j) => Some((j.id, j.pid, j.hart, j.command.clone())),
+        None if spec.is_empty() => {
+            out_line(&format!("{}: no current job", cmd));
+            None
+        }
+        None => {
+            out_line(&format!("{}: {}: no such job", cmd, spec));
+            None
+        }
+    }
+}
+
+fn stop(pid: Pid) {
+    if let Some(task) = SCHEDULER.get_task(pid) {
+        task.set_state(TaskState::Stopped);
+    }
+}
+
+fn resume(pid: Pid, hart: usize) {
+    if let Some(task) = SCHEDULER.get_task(pid) {
+        // A job stopped before its hart picked it up is still queued
+        if task.get_current_hart().is_some() {
+            task.set_state(TaskState::Running);
+        } else {
+            task.set_state(TaskState::Ready);
+            crate::send_ipi(hart);
+        }
+    }
+}
+
+fn remove(id: usize) {
+    JOBS.lock().retain(|j| j.id != id);
+}
+
+/// `jobs`: list jobs, forgetting those that are done
+pub fn list(_args: &str) {
+    let mut jobs = JOBS.lock();
+    let current = jobs.last().map(|j| j.id);
+    for job in jobs.iter() {
+        let marker = if Some(job.id) == current { '+' } else { ' ' };
+        let state = job_state(job.pid);
+        let suffix = if state == JobState::Running { " &" } else { "" };
+        out_line(&format!(
+            "[{}]{} {:<6} {:<8} {}{}",
+            job.id,
+            marker,
+            job.pid,
+            state.as_str(),
+            job.command,
+            suffix
+        ));
+    }
+    jobs.retain(|j| job_state(j.pid) != JobState::Done);
+}
+
+/// Print the jobs that finished since the last prompt and forget them.
+pub fn report_done() {
+    let mut jobs = JOBS.lock();
+    if jobs.is_empty() {
+        return;
+    }
+    jobs.retain(|job| {
+        let done = job_state(job.pid) == JobState::Done;
+        if done {
+            uart::write_line(&format!("[{}]  Done     {}", job.id, job.command));
+        }
+        !done
+    });
+}
+
+/// `bg`: resume a stopped job in the background
+pub fn bg(args: &str) {
+    let Some((id, pid, hart, command)) = select("bg", args) else {
+        return;
+    };
+    match job_state(pid) {
+        JobState::Stopped => {
+            resume(pid, hart);
+            out_line(&format!("[{}]+ {} &", id, command));
+        }
+        JobState::Running => out_line(&format!("bg: job {} already in background", id)),
+        JobState::Done => out_line(&format!("bg: job {} has terminated", id)),
+    }
+}
+
+/// `fg`: wait for a job in the foreground, resuming it if it is stopped
+pub fn fg(args: &str) {
+    let Some((id, pid, hart, command)) = select("fg", args) else {
+        return;
+    };
+    out_line(&command);
+    if job_state(pid) == JobState::Stopped {
+        resume(pid, hart);
+    }
+
+    // Hart 0 keeps serving the network and its daemons while waiting
+    let console = uart::Console::new();
+    let mut last_task_run = crate::get_time_ms();
+    loop {
+        if job_state(pid) == JobState::Done {
+            remove(id);
+            return;
+        }
+        crate::poll_network();
+        let now = crate::get_time_ms();
+        if now - last_task_run >= 100 {
+            last_task_run = now;
+            crate::run_hart0_tasks();
+        }
+        match console.read_byte() {
+            // Ctrl+Z
+            0x1a => {
+                stop(pid);
+                out_line("^Z");
+                out_line(&format!("[{}]+  Stopped  {}", id, command));
+                return;
+            }
+            // Ctrl+C
+            0x03 => {
+                SCHEDULER.kill(pid);
+                out_line("^C");
+                remove(id);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
@@ -2,8 +2,12 @@
 //!
 //! Provides a ring buffer for kernel messages that can be:
 //! - Written to by any subsystem via klog!() macro
-//! - Flushed to /var/log/kernel.log by the klogd daemon
-//! - Viewed via dmesg command
+//! - Viewed via the dmesg command, or followed with `dmesg -f`
+//! - Read by WASM programs through the klog_get syscall
+//!
+//! Critical entries and worse are also appended to /var/log/kern.log as
+//! they are logged, so they survive a reboot. If the filesystem is busy or
+//! not mounted yet they are kept and written by a later log call or klogd.
 
 use alloc::collections::VecDeque;
 use alloc::format;
@@ -19,6 +23,18 @@ const LOG_BUFFER_SIZE: usize = 128;
 /// Maximum length of a single log message
 const MAX_MESSAGE_LEN: usize = 256;
 
+/// Entries at this level or more severe are written through to disk
+const PERSIST_LEVEL: LogLevel = LogLevel::Critical;
+
+/// File critical entries are appended to
+pub const PERSIST_PATH: &str = "/var/log/kern.log";
+
+/// Size the persisted log is trimmed to (its newest bytes are kept)
+const PERSIST_MAX_LEN: usize = 16384;
+
+/// Most entries waiting to be written to disk
+const PERSIST_MAX_PENDING: usize = 64;
+
 /// Log levels (similar to Linux kernel log levels)
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
@@ -58,6 +74,24 @@ impl LogLevel {
         }
     }
 
+    /// Parse a level name as shown by [`LogLevel::as_str`], ignoring case.
+    /// `err` and `warning` are accepted too.
+    pub fn parse(name: &str) -> Option<LogLevel> {
+        let level = match name.to_ascii_lowercase().as_str() {
+            "emerg" => LogLevel::Emergency,
+            "alert" => LogLevel::Alert,
+            "crit" => LogLevel::Critical,
+            "err" | "error" => LogLevel::Error,
+            "warn" | "warning" => LogLevel::Warning,
+            "notice" => LogLevel::Notice,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            "trace" => LogLevel::Trace,
+            _ => return None,
+        };
+        Some(level)
+    }
+
     pub fn color(&self) -> &'static str {
         match self {
             LogLevel::Emergency | LogLevel::Alert | LogLevel::Critical => "\x1b[1;31m",
@@ -74,6 +108,8 @@ impl LogLevel {
 /// A single log entry
 #[derive(Clone)]
 pub struct LogEntry {
+    /// Position in the log, counting from 0 at boot
+    pub seq: usize,
     /// Timestamp (ms since boot)
     pub timestamp: u64,
     /// Log level
@@ -127,6 +163,8 @@ pub struct LogBuffer {
     console_enabled: AtomicBool,
     /// Whether logging is enabled
     enabled: AtomicBool,
+    /// Formatted entries not yet written to [`PERSIST_PATH`]
+    pending: Spinlock<Vec<String>>,
 }
 
 impl LogBuffer {
@@ -137,6 +175,7 @@ impl LogBuffer {
             level_filter: AtomicUsize::new(LogLevel::Info as usize),
             console_enabled: AtomicBool::new(true),
             enabled: AtomicBool::new(true),
+            pending: Spinlock::new(Vec::new()),
         }
     }
 
@@ -149,6 +188,7 @@ impl LogBuffer {
             level_filter: AtomicUsize::new(LogLevel::Debug as usize),
             console_enabled: AtomicBool::new(false),
             enabled: AtomicBool::new(true),
+            pending: Spinlock::new(Vec::new()),
         }
     }
 
@@ -175,7 +215,8 @@ impl LogBuffer {
             String::from(message)
         };
 
-        let entry = LogEntry {
+        let mut entry = LogEntry {
+            seq: 0,
             timestamp,
             level,
             subsystem: String::from(subsystem),
@@ -188,14 +229,64 @@ impl LogBuffer {
             crate::uart::write_line(&entry.format_colored());
         }
 
+        if level <= PERSIST_LEVEL {
+            let mut pending = self.pending.lock();
+            if pending.len() < PERSIST_MAX_PENDING {
+                pending.push(entry.format());
+            }
+        }
+
         // Add to buffer
-        let mut buffer = self.entries.lock();
-        if buffer.len() >= LOG_BUFFER_SIZE {
-            buffer.pop_front(); // Drop oldest
+        {
+            let mut buffer = self.entries.lock();
+            if buffer.len() >= LOG_BUFFER_SIZE {
+                buffer.pop_front(); // Drop oldest
+            }
+            entry.seq = self.sequence.fetch_add(1, Ordering::Relaxed);
+            buffer.push_back(entry);
         }
-        buffer.push_back(entry);
 
-        self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.flush_pending();
+    }
+
+    /// Append entries waiting for the disk to [`PERSIST_PATH`]. Does nothing
+    /// if the filesystem is locked, since the caller may hold it.
+    pub fn flush_pending(&self) {
+        if self.pending.lock().is_empty() {
+            return;
+        }
+        let Some(mut fs_guard) = crate::FS_STATE.try_lock() else {
+            return;
+        };
+        let Some(mut blk_guard) = crate::BLK_DEV.try_lock() else {
+            return;
+        };
+        let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+            return;
+        };
+
+        let lines = core::mem::take(&mut *self.pending.lock());
+        let mut content = fs.read_file(dev, PERSIST_PATH).unwrap_or_default();
+        for line in &lines {
+            content.extend_from_slice(line.as_bytes());
+            content.push(b'\n');
+        }
+        if content.len() > PERSIST_MAX_LEN {
+            // Keep whole lines
+            let cut = content.len() - PERSIST_MAX_LEN;
+            let start = content[cut..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(cut, |i| cut + i + 1);
+            content.drain(..start);
+        }
+        if fs.write_file(dev, PERSIST_PATH, &content).is_ok() {
+            let _ = fs.sync(dev);
+        } else {
+            let mut pending = self.pending.lock();
+            let room = PERSIST_MAX_PENDING.saturating_sub(pending.len());
+            pending.splice(0..0, lines.into_iter().take(room));
+        }
     }
 
     /// Drain all entries for writing to log file
@@ -215,6 +306,17 @@ impl LogBuffer {
         self.entries.lock().iter().cloned().collect()
     }
 
+    /// Entries with a sequence number of `seq` or later, oldest first
+    pub fn since(&self, seq: usize) -> Vec<LogEntry> {
+        let buffer = self.entries.lock();
+        buffer.iter().filter(|e| e.seq >= seq).cloned().collect()
+    }
+
+    /// Remove all entries
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
     /// Set the log level filter
     pub fn set_level(&self, level: LogLevel) {
         self.level_filter.store(level as usize, Ordering::Release);
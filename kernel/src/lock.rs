@@ -1,14 +1,15 @@
 //! Spinlock implementation for SMP synchronization.
 //!
 //! Provides mutual exclusion primitives based on spinning (busy-waiting).
-//! Appropriate for kernel code without a scheduler.
+//! A hart holding a lock is not preempted (see [`preempt_disable`]), so a
+//! lock is never left held by a task that is switched out.
 
 use core::cell::UnsafeCell;
 use core::hint::spin_loop;
 use core::ops::{Deref, DerefMut};
-#[cfg(debug_assertions)]
-use core::sync::atomic::AtomicUsize;
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::MAX_HARTS;
 
 // Lock states as u32 for 32-bit atomic operations.
 // On RISC-V, AtomicBool uses byte operations which may not be properly
@@ -17,6 +18,45 @@ use core::sync::atomic::{AtomicU32, Ordering};
 const UNLOCKED: u32 = 0;
 const LOCKED: u32 = 1;
 
+/// mstatus.MIE: global machine interrupt enable
+const MSTATUS_MIE: usize = 1 << 3;
+
+const NOT_HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// Locks held (or being waited for) on each hart. The timer interrupt
+/// doesn't switch tasks while a hart's count is non-zero.
+static PREEMPT_COUNT: [AtomicUsize; MAX_HARTS] = [NOT_HELD; MAX_HARTS];
+
+/// Keep the scheduler from switching tasks on this hart until the matching
+/// [`preempt_enable`].
+#[inline]
+pub fn preempt_disable() {
+    // Interrupts are masked until the count is up, so the caller can't be
+    // switched out and resumed on another hart in between
+    let mstatus: usize;
+    unsafe {
+        core::arch::asm!("csrrci {}, mstatus, 8", out(reg) mstatus, options(nostack));
+    }
+    PREEMPT_COUNT[get_hart_id()].fetch_add(1, Ordering::Relaxed);
+    if mstatus & MSTATUS_MIE != 0 {
+        unsafe {
+            core::arch::asm!("csrsi mstatus, 8", options(nostack));
+        }
+    }
+}
+
+/// Undo one [`preempt_disable`].
+#[inline]
+pub fn preempt_enable() {
+    PREEMPT_COUNT[get_hart_id()].fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Whether the code running on `hart` may be switched out
+#[inline]
+pub fn preemptible(hart: usize) -> bool {
+    PREEMPT_COUNT[hart].load(Ordering::Relaxed) == 0
+}
+
 /// A mutual exclusion primitive based on spinning.
 ///
 /// # Example
@@ -65,6 +105,7 @@ impl<T> Spinlock<T> {
     #[inline]
     pub fn lock(&self) -> SpinlockGuard<T> {
         let mut spin_count = 0u32;
+        preempt_disable();
 
         loop {
             // Try to acquire using swap (AMOSWAP.W instruction on RISC-V)
@@ -127,6 +168,7 @@ impl<T> Spinlock<T> {
     /// Returns `Some(guard)` if successful, `None` if lock is held.
     #[inline]
     pub fn try_lock(&self) -> Option<SpinlockGuard<T>> {
+        preempt_disable();
         // Use swap instead of compare_exchange to ensure AMOSWAP.W is used
         if self.locked.swap(LOCKED, Ordering::Acquire) == UNLOCKED {
             #[cfg(debug_assertions)]
@@ -136,6 +178,7 @@ impl<T> Spinlock<T> {
                 _not_send: core::marker::PhantomData,
             })
         } else {
+            preempt_enable();
             None
         }
     }
@@ -165,13 +208,13 @@ impl<T> Spinlock<T> {
 }
 
 /// Get current hart ID.
-#[cfg(debug_assertions)]
+#[inline]
 fn get_hart_id() -> usize {
     let id: usize;
     unsafe {
         core::arch::asm!("csrr {}, mhartid", out(reg) id, options(nomem, nostack));
     }
-    id
+    id.min(MAX_HARTS - 1)
 }
 
 /// RAII guard that releases the spinlock when dropped.
@@ -209,6 +252,7 @@ impl<T> Drop for SpinlockGuard<'_, T> {
         // Using swap instead of store because the emulator serializes AMO operations
         // but may not properly synchronize regular store visibility across hart threads.
         self.lock.locked.swap(UNLOCKED, Ordering::Release);
+        preempt_enable();
     }
 }
 
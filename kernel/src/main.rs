@@ -7,8 +7,15 @@
 core::arch::global_asm!(".global _max_hart_id", "_max_hart_id = 127");
 
 mod allocator;
+mod bootargs;
+mod clock;
 mod cmd;
+mod context;
+mod crond;
+mod dhcp;
 mod dns;
+mod elf;
+mod ext2;
 mod lock;
 mod wasm;
 
@@ -16,17 +23,26 @@ mod wasm;
 pub use lock::Spinlock;
 mod fs;
 mod http;
+mod httpd;
+mod ident;
 mod net;
+mod process;
+mod safemode;
 mod scripting;
+mod setup;
+mod sntp;
+mod tmpfs;
 mod tls;
 mod tls12;
 mod uart;
 mod virtio_blk;
+mod virtio_console;
 mod virtio_net;
 
 // Process management modules
 mod init;
 mod ipc;
+mod jobs;
 mod klog;
 mod scheduler;
 mod task;
@@ -227,6 +243,10 @@ fn is_prime(n: u64) -> bool {
 fn count_primes_in_range(start: u64, end: u64) -> u64 {
     let mut count = 0u64;
     for n in start..end {
+        // Hart 0 stops early on Ctrl+C; the caller checks for it too
+        if n % 1024 == 0 && uart::interrupted() {
+            break;
+        }
         if is_prime(n) {
             count += 1;
         }
@@ -324,63 +344,41 @@ fn secondary_hart_entry(hart_id: usize) -> ! {
 
 /// Secondary hart idle loop.
 ///
-/// Secondary harts wait for work (IPI wakeup), then check for:
+/// Secondary harts sleep until an IPI or their timer interrupt, then check for:
 /// 1. Benchmark tasks (high priority, checked first)
-/// 2. Scheduler tasks (including long-running daemons)
+/// 2. Scheduler tasks (including long-running daemons), run until none is runnable
 fn secondary_hart_idle(hart_id: usize) -> ! {
+    scheduler::start_hart(hart_id);
     loop {
-        // Wait for work via IPI - this is the primary coordination mechanism
         unsafe {
             core::arch::asm!("wfi", options(nomem, nostack));
         }
 
-        // Check if we were woken by an IPI
-        if !is_my_msip_pending() {
-            // Spurious wakeup - go back to sleep
-            continue;
-        }
-
-        // Clear the IPI
-        clear_my_msip();
-
-        // Check for benchmark work first (high priority)
-        if BENCHMARK.is_active() {
-            let mode = BENCHMARK.mode.load(Ordering::Acquire);
-            if mode == BenchmarkMode::PrimeCount as usize {
-                // Get our work range
-                let (start, end) = BENCHMARK.get_work_range(hart_id);
-                if start < end {
-                    // Count primes in our range
-                    let count = count_primes_in_range(start, end);
-                    // Report result
-                    BENCHMARK.report_result(hart_id, count);
-                } else {
-                    // No work for this hart
-                    BENCHMARK.report_result(hart_id, 0);
+        // Check for benchmark work first (high priority), announced by IPI
+        if is_my_msip_pending() {
+            clear_my_msip();
+            if BENCHMARK.is_active() {
+                let mode = BENCHMARK.mode.load(Ordering::Acquire);
+                if mode == BenchmarkMode::PrimeCount as usize {
+                    // Get our work range
+                    let (start, end) = BENCHMARK.get_work_range(hart_id);
+                    if start < end {
+                        // Count primes in our range
+                        let count = count_primes_in_range(start, end);
+                        // Report result
+                        BENCHMARK.report_result(hart_id, count);
+                    } else {
+                        // No work for this hart
+                        BENCHMARK.report_result(hart_id, 0);
+                    }
+                    continue;
                 }
-                continue;
             }
         }
 
-        // Check for scheduler tasks
+        // Run scheduler tasks; the timer takes the hart back between slices
         if SCHEDULER.is_running() {
-            if let Some(task) = SCHEDULER.pick_next(hart_id) {
-                // Mark task as running on this hart
-                task.mark_running(hart_id);
-
-                let start_time = get_time_ms() as u64;
-
-                // Execute the task's entry point
-                // Note: Daemon tasks have infinite loops and won't return
-                (task.entry)();
-
-                // If we get here, the task returned (non-daemon or daemon that exited)
-                let elapsed = (get_time_ms() as u64).saturating_sub(start_time);
-                task.add_cpu_time(elapsed);
-
-                // Mark task as finished
-                SCHEDULER.finish_task(task.pid, 0);
-            }
+            SCHEDULER.schedule(hart_id);
         }
     }
 }
@@ -485,6 +483,9 @@ const SYSINFO_DISK_TOTAL: usize = SYSINFO_BASE + 0x18;
 const SYSINFO_CPU_COUNT: usize = SYSINFO_BASE + 0x20;
 // 0x24 is padding for 8-byte alignment
 const SYSINFO_UPTIME: usize = SYSINFO_BASE + 0x28;
+/// `uname -a` line, NUL-padded (written by ident::report)
+const SYSINFO_KERNEL_ID: usize = SYSINFO_BASE + 0x40;
+const SYSINFO_KERNEL_ID_LEN: usize = 128;
 
 /// Write system statistics to the MMIO SysInfo device
 /// This allows the emulator to read kernel stats and display them in the UI
@@ -645,7 +646,7 @@ fn cwd_set(path: &str) {
 }
 
 // ─── OUTPUT CAPTURE FOR REDIRECTION ────────────────────────────────────────────
-const OUTPUT_BUFFER_SIZE: usize = 4096;
+const OUTPUT_BUFFER_SIZE: usize = 65536;
 
 /// Output capture state for redirection
 struct OutputCapture {
@@ -681,21 +682,14 @@ fn output_capture_stop() -> Vec<u8> {
     Vec::from(&cap.buffer[..cap.len])
 }
 
+/// Whether output is currently captured for redirection
+fn output_capturing() -> bool {
+    OUTPUT_CAPTURE.lock().capturing
+}
+
 /// Write a string - respects capture mode
 fn out_str(s: &str) {
-    let mut cap = OUTPUT_CAPTURE.lock();
-    if cap.capturing {
-        for &b in s.as_bytes() {
-            let idx = cap.len;
-            if idx < OUTPUT_BUFFER_SIZE {
-                cap.buffer[idx] = b;
-                cap.len += 1;
-            }
-        }
-    } else {
-        drop(cap); // Release lock before UART
-        uart::write_str(s);
-    }
+    out_bytes(s.as_bytes());
 }
 
 /// Write a string with newline - respects capture mode
@@ -708,12 +702,18 @@ fn out_line(s: &str) {
 fn out_bytes(bytes: &[u8]) {
     let mut cap = OUTPUT_CAPTURE.lock();
     if cap.capturing {
-        for &b in bytes {
+        let mut push = |b: u8| {
             let idx = cap.len;
             if idx < OUTPUT_BUFFER_SIZE {
                 cap.buffer[idx] = b;
                 cap.len += 1;
             }
+        };
+        // Plain mode applies to redirected output as well as the console
+        if uart::is_plain() {
+            uart::plain_filter(bytes, push);
+        } else {
+            bytes.iter().for_each(|&b| push(b));
         }
     } else {
         drop(cap); // Release lock before UART
@@ -803,16 +803,16 @@ pub fn get_time_ms() -> i64 {
     (mtime / 10_000) as i64
 }
 
-/// Run periodic daemon work on hart 0
+/// Run periodic kernel work on hart 0
 ///
-/// Services like klogd and sysmond need VirtIO access for filesystem writes.
-/// Since VirtIO is only accessible from hart 0 (which runs the shell loop),
-/// we call their tick functions directly from the shell loop.
+/// Services are tasks pinned to hart 0, which runs them between slices of
+/// the shell. What is left here is shell-side housekeeping.
 fn run_hart0_tasks() {
-    // Run daemon tick functions (they check their own timing internally)
-    init::klogd_tick();
-    init::sysmond_tick();
-    
+    init::control_tick();
+    httpd::reap();
+    virtio_console::tick();
+
+
     // Update system info MMIO device (for emulator UI)
     update_sysinfo();
 }
@@ -989,7 +989,7 @@ fn print_boot_info(key: &str, value: &str) {
 }
 
 #[entry]
-fn main() -> ! {
+fn main(_a0: usize, fdt_addr: usize) -> ! {
     // ═══════════════════════════════════════════════════════════════════
     // VERIFY WE'RE THE PRIMARY HART
     // ═══════════════════════════════════════════════════════════════════
@@ -1009,13 +1009,19 @@ fn main() -> ! {
     // ═══════════════════════════════════════════════════════════════════
     // Must be done before any output. Sets up 8N1, enables FIFOs, etc.
     uart::Console::init();
+    bootargs::apply(fdt_addr);
 
     // ─── CPU & ARCHITECTURE INFO ──────────────────────────────────────────────
     print_section("CPU & ARCHITECTURE");
     print_boot_info("Primary Hart", "0");
-    print_boot_info("Architecture", "RISC-V 64-bit (RV64GC)");
+    print_boot_info("Architecture", ident::ARCHITECTURE);
     print_boot_info("Mode", "Machine Mode (M-Mode)");
     print_boot_info("Timer Source", "CLINT @ 0x02000000");
+    if clock::init_from_rtc() {
+        print_boot_info("Wall Clock", "Goldfish RTC @ 0x00101000");
+    } else {
+        print_boot_info("Wall Clock", "not set (no RTC)");
+    }
     print_boot_status("CPU initialized", true);
 
     // ─── MEMORY SUBSYSTEM ─────────────────────────────────────────────────────
@@ -1032,10 +1038,25 @@ fn main() -> ! {
 
     // ─── STORAGE SUBSYSTEM ────────────────────────────────────────────────────
     init_storage();
+    init_console2();
+    let safe_mode = safemode::begin_boot();
+    let provisioned = setup::load();
+    if safe_mode {
+        init_safe_mode();
+    }
 
     // ─── NETWORK SUBSYSTEM ────────────────────────────────────────────────────
     print_section("NETWORK SUBSYSTEM");
-    init_network();
+    if safe_mode {
+        print_boot_status(
+            "Networking skipped in safe mode (start it with `ifup`)",
+            false,
+        );
+    } else if setup::network_enabled() {
+        init_network();
+    } else {
+        print_boot_status("Networking disabled by setup", false);
+    }
 
     // ═══════════════════════════════════════════════════════════════════
     // SMP INITIALIZATION
@@ -1097,23 +1118,45 @@ fn main() -> ! {
     print_boot_status("Scheduler initialized", true);
     print_boot_info("Run queues", &format!("{} (one per hart)", online));
 
-    // Run init directly on primary hart (spawns daemons to secondary harts)
-    // Note: We don't spawn init as a task - it runs synchronously during boot
-    print_boot_info("Init process", "running");
-    init::init_main();
+    if safe_mode {
+        print_boot_status("Init and system services skipped in safe mode", false);
+    } else {
+        // Run init directly on primary hart (spawns daemons to secondary harts)
+        // Note: We don't spawn init as a task - it runs synchronously during boot
+        print_boot_info("Init process", "running");
+        init::init_main();
 
-    // Report services started
-    let services = init::service_count();
-    print_boot_status(
-        &format!("System services started ({})", services),
-        services > 0,
-    );
+        // Report services started
+        let services = init::service_count();
+        print_boot_status(
+            &format!("System services started ({})", services),
+            services > 0,
+        );
+    }
 
     // ─── BOOT COMPLETE ────────────────────────────────────────────────────────
-    print_section(&format!("\x1b[1;97mBAVY OS BOOT COMPLETE!\x1b[0m"));
+    safemode::boot_complete();
+    if safe_mode {
+        print_section("\x1b[1;97mBAVY OS BOOT COMPLETE (SAFE MODE)\x1b[0m");
+        uart::write_line("");
+        uart::write_line(
+            "    \x1b[1;33m[!]\x1b[0m Running in safe mode. Reboot to try a full boot again.",
+        );
+    } else {
+        print_section(&format!("\x1b[1;97mBAVY OS BOOT COMPLETE!\x1b[0m"));
+    }
     uart::write_line("");
     uart::write_line("");
 
+    if !provisioned && !safe_mode {
+        setup::run_wizard();
+    }
+    // Hostname is final now; let the emulator know who it is running
+    ident::report();
+
+    // From here on hart 0 shares its time between the shell and its tasks
+    scheduler::start_hart(0);
+
     cwd_init();
     print_prompt();
 
@@ -1152,8 +1195,7 @@ fn main() -> ! {
 
         // 0 means "no input" in our UART model
         if byte == 0 {
-            // While idle, periodically run scheduled tasks on hart 0
-            // Services like klogd/sysmond are pinned to hart 0 for VirtIO access
+            // While idle, periodically run shell-side housekeeping on hart 0
             let now = get_time_ms();
             if now - last_task_run >= 100 {
                 // Every 100ms
@@ -1172,6 +1214,11 @@ fn main() -> ! {
                 }
             }
 
+            // Commands from the second console run only here, between shell lines
+            if let Some(line) = virtio_console::next_command() {
+                serve_control_line(&line);
+            }
+
             continue;
         }
 
@@ -1196,6 +1243,11 @@ fn main() -> ! {
             continue;
         }
 
+        // Ctrl+Z (0x1a) only stops jobs waited on with `fg` (see jobs::fg)
+        if byte == 0x1a {
+            continue;
+        }
+
         // In follow mode, 'q' also exits
         if tail_follow_mode && (byte == b'q' || byte == b'Q') {
             tail_follow_mode = false;
@@ -1410,16 +1462,9 @@ fn handle_tab_completion(buffer: &mut [u8], len: usize) -> usize {
     let mut matches: Vec<String> = Vec::new();
 
     if is_command {
-        // Complete commands - check built-ins first
-        let builtins = [
-            "clear", "shutdown", "cd", "pwd", "ping", "nslookup", "node", "help", "ls", "cat",
-            "echo", "cowsay", "sysinfo", "ip", "netstat", "memstats", "uptime", "write", "wget",
-        ];
-
-        for cmd in builtins.iter() {
-            if cmd.starts_with(word_to_complete) {
-                matches.push(String::from(*cmd));
-            }
+        // Complete commands - check the kernel command registry first
+        for cmd in cmd::registry::complete(word_to_complete) {
+            matches.push(String::from(cmd));
         }
 
         // Also check /usr/bin/ for scripts
@@ -1647,12 +1692,61 @@ fn init_storage() {
     let mut blk_guard = BLK_DEV.lock();
     if let Some(ref mut blk) = *blk_guard {
         if let Some(fs) = fs::FileSystem::init(blk) {
-            uart::write_line("    \x1b[1;32m[✓]\x1b[0m SFS Mounted (R/W)");
+            let mode = if fs.is_read_only() { "R/O" } else { "R/W" };
+            uart::write_line(&format!(
+                "    \x1b[1;32m[✓]\x1b[0m {} Mounted ({})",
+                fs.kind().to_uppercase(),
+                mode
+            ));
             *FS_STATE.lock() = Some(fs);
         }
     }
 }
 
+/// Attach the second console, if the emulator provides one
+fn init_console2() {
+    if let Some(base) = virtio_console::init() {
+        uart::write_str("    \x1b[0;90m├─\x1b[0m VirtIO-Console found at: \x1b[1;97m0x");
+        uart::write_hex(base as u64);
+        uart::write_line("\x1b[0m");
+        print_boot_status("Second console carrying the kernel log", true);
+    }
+}
+
+/// Announce safe mode and check the filesystem before anything writes to it
+fn init_safe_mode() {
+    print_section("\x1b[1;33mSAFE MODE\x1b[0m");
+    uart::write_str("    \x1b[1;33m[!]\x1b[0m The previous boot did not complete (");
+    uart::write_u64(safemode::failed_boots() as u64);
+    uart::write_line(" failed in a row)");
+    print_boot_info("Skipping", "network, system services, init scripts");
+
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+        return;
+    };
+    match fs.fsck(dev) {
+        Ok(report) => {
+            print_boot_info(
+                "fsck",
+                &format!("{} files, {} blocks in use", report.files, report.blocks),
+            );
+            if report.repaired > 0 {
+                print_boot_info(
+                    "fsck",
+                    &format!("{} blocks marked used in the bitmap", report.repaired),
+                );
+            }
+            for name in &report.broken {
+                print_boot_status(&format!("fsck: broken block chain in '{}'", name), false);
+            }
+            print_boot_status("Filesystem checked", report.broken.is_empty());
+        }
+        Err(e) => print_boot_status(&format!("Filesystem check failed: {}", e), false),
+    }
+}
+
 fn init_fs() {
     if let Some(blk) = virtio_blk::VirtioBlock::probe() {
         uart::write_line("    \x1b[1;32m[✓]\x1b[0m VirtIO Block found");
@@ -1688,6 +1782,14 @@ fn init_network() {
                         if let Some(ref mut s) = *net_guard {
                             s.finalize();
 
+                            // Prefer a DHCP lease over the static configuration
+                            uart::write_str("    \x1b[0;90m├─\x1b[0m Requesting DHCP lease...");
+                            if s.dhcp_acquire(get_time_ms()).is_some() {
+                                uart::write_line(" \x1b[1;32m[OK]\x1b[0m");
+                            } else {
+                                uart::write_line(" \x1b[1;33m[STATIC]\x1b[0m");
+                            }
+
                             // Print network configuration
                             uart::write_line("");
                             uart::write_str("    \x1b[0m  MAC Address:   \x1b[1;97m");
@@ -1700,15 +1802,15 @@ fn init_network() {
                             uart::write_str("    \x1b[0m  IPv4 Address:  \x1b[1;97m");
                             uart::write_bytes(&ip_buf[..ip_len]);
                             uart::write_str("/");
-                            uart::write_u64(net::PREFIX_LEN as u64);
+                            uart::write_u64(net::ip_config().prefix_len as u64);
                             uart::write_line("\x1b[0m                   \x1b[0m");
 
-                            let gw_len = net::format_ipv4(net::GATEWAY, &mut ip_buf);
+                            let gw_len = net::format_ipv4(net::get_gateway(), &mut ip_buf);
                             uart::write_str("    \x1b[0m  Gateway:       \x1b[1;97m");
                             uart::write_bytes(&ip_buf[..gw_len]);
                             uart::write_line("\x1b[0m                       \x1b[0m");
 
-                            let dns_len = net::format_ipv4(net::DNS_SERVER, &mut ip_buf);
+                            let dns_len = net::format_ipv4(net::get_dns_server(), &mut ip_buf);
                             uart::write_str("    \x1b[0m  DNS Server:    \x1b[1;97m");
                             uart::write_bytes(&ip_buf[..dns_len]);
                             uart::write_line("\x1b[0m                       \x1b[0m");
@@ -1881,6 +1983,8 @@ fn poll_network() {
 }
 
 fn print_prompt() {
+    jobs::report_done();
+
     let cwd = cwd_get();
     let prompt_path = if cwd == "/" {
         String::new()
@@ -1888,8 +1992,16 @@ fn print_prompt() {
         format!(" {}", cwd)
     };
 
+    let safe = if safemode::is_active() {
+        " \x1b[1;33m(safe)\x1b[0m"
+    } else {
+        ""
+    };
+
     uart::write_str(&format!(
-        "\x1b[1;35mBavy\x1b[0m\x1b[1;34m{}\x1b[0m # ",
+        "\x1b[1;35m{}\x1b[0m{}\x1b[1;34m{}\x1b[0m # ",
+        setup::hostname(),
+        safe,
         prompt_path
     ));
 }
@@ -1933,6 +2045,21 @@ fn trim_bytes(bytes: &[u8]) -> &[u8] {
     &bytes[start..end]
 }
 
+/// Split a command line into command and arguments (at the first whitespace)
+fn split_command(line: &[u8]) -> (&[u8], &[u8]) {
+    let mut i = 0;
+    while i < line.len() && line[i] != b' ' && line[i] != b'\t' {
+        i += 1;
+    }
+    let cmd = &line[..i];
+
+    let mut arg_start = i;
+    while arg_start < line.len() && (line[arg_start] == b' ' || line[arg_start] == b'\t') {
+        arg_start += 1;
+    }
+    (cmd, &line[arg_start..])
+}
+
 fn handle_line(buffer: &[u8], len: usize, _count: &mut usize) {
     // Trim leading/trailing whitespace (spaces and tabs only)
     let mut start = 0;
@@ -1951,6 +2078,18 @@ fn handle_line(buffer: &[u8], len: usize, _count: &mut usize) {
     }
 
     let full_line = &buffer[start..end];
+    uart::clear_interrupt();
+
+    // A trailing & runs the line as a background job
+    if full_line.ends_with(b"&") && !full_line.ends_with(b"&&") {
+        let line = trim_bytes(&full_line[..full_line.len() - 1]);
+        if parse_redirection(line).1 != RedirectMode::None || line.contains(&b'|') {
+            uart::write_line("\x1b[1;31mError:\x1b[0m Background jobs can't redirect or pipe output");
+        } else if !line.is_empty() {
+            jobs::spawn(core::str::from_utf8(line).unwrap_or(""));
+        }
+        return;
+    }
 
     // Parse for redirection
     let (line, redirect_mode, redirect_file) = parse_redirection(full_line);
@@ -1962,26 +2101,38 @@ fn handle_line(buffer: &[u8], len: usize, _count: &mut usize) {
         return;
     }
 
-    // Split into command and arguments (first whitespace)
-    let mut i = 0;
-    while i < line.len() && line[i] != b' ' && line[i] != b'\t' {
-        i += 1;
+    // Split into pipeline stages; redirection applies to the last one
+    let stages: Vec<&[u8]> = line.split(|&b| b == b'|').map(trim_bytes).collect();
+    if stages.iter().any(|stage| stage.is_empty()) {
+        uart::write_line("");
+        uart::write_line("\x1b[1;31mError:\x1b[0m Missing command in pipeline");
+        return;
     }
-    let cmd = &line[..i];
 
-    let mut arg_start = i;
-    while arg_start < line.len() && (line[arg_start] == b' ' || line[arg_start] == b'\t') {
-        arg_start += 1;
-    }
-    let args = &line[arg_start..];
+    // Each stage before the last runs captured, and its output becomes the
+    // next stage's stdin
+    let mut input: Option<Vec<u8>> = None;
+    for (i, stage) in stages.iter().enumerate() {
+        let (cmd, args) = split_command(stage);
+        let last = i + 1 == stages.len();
+        if !last || redirect_mode != RedirectMode::None {
+            output_capture_start();
+        }
 
-    // Start capturing if redirecting
-    if redirect_mode != RedirectMode::None {
-        output_capture_start();
-    }
+        execute_command(cmd, args, input.as_deref());
+
+        if !last {
+            // Consumers match on text, so drop colours between stages
+            let mut piped = Vec::new();
+            uart::plain_filter(&output_capture_stop(), |b| piped.push(b));
+            input = Some(piped);
 
-    // Execute the command
-    execute_command(cmd, args);
+            // Ctrl+C ends the whole pipeline
+            if uart::interrupted() {
+                return;
+            }
+        }
+    }
 
     // Handle redirection output
     if redirect_mode != RedirectMode::None {
@@ -2033,102 +2184,50 @@ fn handle_line(buffer: &[u8], len: usize, _count: &mut usize) {
     }
 }
 
+/// Run a line received on the second console's control channel and send
+/// back `OK <len>` and the output. Stages of a pipeline run as they do in
+/// the shell, but every one is captured.
+fn serve_control_line(line: &[u8]) {
+    let line = trim_bytes(line);
+    uart::clear_interrupt();
+    let mut input: Option<Vec<u8>> = None;
+    for stage in line.split(|&b| b == b'|').map(trim_bytes) {
+        let (cmd, args) = split_command(stage);
+        output_capture_start();
+        if !cmd.is_empty() {
+            execute_command(cmd, args, input.as_deref());
+        }
+        let mut plain = Vec::new();
+        uart::plain_filter(&output_capture_stop(), |b| plain.push(b));
+        input = Some(plain);
+    }
+
+    let output = input.unwrap_or_default();
+    let mut reply = format!("OK {}\n", output.len()).into_bytes();
+    reply.extend_from_slice(&output);
+    virtio_console::write(&reply);
+}
+
 /// Execute a command (separated for cleaner redirection handling)
 ///
 /// Commands are resolved in this order:
 /// 1. Essential built-in commands (that require direct kernel access)
 /// 2. Native commands (fast Rust implementations of common utilities)
 /// 3. Scripts: searched in root, then /usr/bin/ directory (PATH-like)
-fn execute_command(cmd: &[u8], args: &[u8]) {
+///
+/// `stdin` is the output of the previous pipeline stage. Only WASM and ELF
+/// programs read it; built-in commands ignore it.
+fn execute_command(cmd: &[u8], args: &[u8], stdin: Option<&[u8]>) {
     let cmd_str = core::str::from_utf8(cmd).unwrap_or("");
     let args_str = core::str::from_utf8(args).unwrap_or("");
 
     // ═══════════════════════════════════════════════════════════════════════════
-    // ESSENTIAL BUILT-IN COMMANDS
-    // These require direct kernel access or cannot be implemented in scripts
-    // ═══════════════════════════════════════════════════════════════════════════
-
-    match cmd_str {
-        // System control - requires direct hardware access
-        "shutdown" | "poweroff" => {
-            cmd::shutdown();
-            return;
-        }
-        "clear" => {
-            for _ in 0..50 {
-                out_line("");
-            }
-            return;
-        }
-
-        // Directory navigation - requires shell state
-        "cd" => {
-            cmd::cd(args_str);
-            return;
-        }
-        "pwd" => {
-            out_line(&cwd_get());
-            return;
-        }
-
-        // Scripting engine control
-        "node" => {
-            cmd::node(args);
-            return;
-        }
-
-        // Async network commands - require event loop integration
-        "ping" => {
-            cmd::ping(args);
-            return;
-        }
-        "nslookup" => {
-            cmd::nslookup(args);
-            return;
-        }
-
-        // Low-level debugging commands
-        "readsec" => {
-            cmd::readsec(args);
-            return;
-        }
-        "alloc" => {
-            cmd::alloc(args);
-            return;
-        }
-        "memtest" => {
-            cmd::memtest(args);
-            return;
-        }
-
-        // CPU benchmark
-        "cpuTest" | "cputest" => {
-            cmd::cputest(args);
-            return;
-        }
-
-        // Help - try WASM script first, fall back to built-in
-        "help" => {
-            // First try to run help WASM binary
-            if let Some(script_bytes) = scripting::find_script("help") {
-                run_script_bytes(&script_bytes, args_str);
-                return;
-            }
-            // Fallback to built-in help
-            cmd::help();
-            return;
-        }
-
-        _ => {}
-    }
-
-    // ═══════════════════════════════════════════════════════════════════════════
-    // NATIVE COMMANDS (Fast implementations)
-    // These are common utilities implemented in Rust for maximum performance.
+    // KERNEL COMMANDS
+    // Built-ins and native utilities, dispatched through cmd::registry.
     // Scripts in /usr/bin/ are still available for customization.
     // ═══════════════════════════════════════════════════════════════════════════
 
-    if cmd::try_native(cmd_str, args_str) {
+    if cmd::registry::dispatch(cmd_str, args_str) {
         return;
     }
 
@@ -2138,7 +2237,7 @@ fn execute_command(cmd: &[u8], args: &[u8]) {
     // ═══════════════════════════════════════════════════════════════════════════
 
     if let Some(script_bytes) = scripting::find_script(cmd_str) {
-        run_script_bytes(&script_bytes, args_str);
+        run_script_bytes(cmd_str, &script_bytes, args_str, stdin);
         return;
     }
 
@@ -2151,26 +2250,37 @@ fn execute_command(cmd: &[u8], args: &[u8]) {
     out_line("\x1b[0;90mTry 'help' for available commands, or check /usr/bin/ for scripts\x1b[0m");
 }
 
-/// Run a script from its bytes (WASM only)
-fn run_script_bytes(bytes: &[u8], args: &str) {
-    // Detect \0asm magic header for WASM binaries
-    if bytes.len() >= 4
-        && bytes[0] == 0x00
-        && bytes[1] == 0x61
-        && bytes[2] == 0x73
-        && bytes[3] == 0x6D
-    {
+/// Run a program from its bytes (WASM module or RV64 ELF executable), with
+/// optional piped input. `name` becomes `argv[0]` of ELF programs.
+fn run_script_bytes(name: &str, bytes: &[u8], args: &str, stdin: Option<&[u8]>) {
+    if wasm::is_wasm(bytes) {
         let args_vec: Vec<&str> = args.split_whitespace().collect();
-        if let Err(e) = wasm::execute(bytes, &args_vec) {
-            out_str("\x1b[1;31mError:\x1b[0m ");
-            out_line(&e);
+        // After Ctrl+C the echoed ^C says it all
+        if let Err(e) = wasm::execute(bytes, &args_vec, stdin) {
+            if !uart::interrupted() {
+                out_str("\x1b[1;31mError:\x1b[0m ");
+                out_line(&e);
+            }
+        }
+        return;
+    }
+
+    // Native programs run as user processes
+    if elf::is_elf(bytes) {
+        let mut args_vec: Vec<&str> = args.split_whitespace().collect();
+        args_vec.insert(0, name);
+        if let Err(e) = process::execute(bytes, &args_vec, stdin) {
+            if !uart::interrupted() {
+                out_str("\x1b[1;31mError:\x1b[0m ");
+                out_line(&e);
+            }
         }
         return;
     }
 
-    // Not a WASM binary
-    out_line("\x1b[1;31mError:\x1b[0m Not a valid WASM binary");
-    out_line("\x1b[0;90mScripts must be compiled to WASM (wasm32-unknown-unknown)\x1b[0m");
+    // Neither WASM nor ELF
+    out_line("\x1b[1;31mError:\x1b[0m Not a valid WASM or ELF binary");
+    out_line("\x1b[0;90mPrograms must be WASM (wasm32-unknown-unknown) or static RV64 ELF\x1b[0m");
 }
 
 /// Resolve a path relative to CWD
@@ -2,6 +2,7 @@
 //!
 //! This module provides the TCP/IP stack for the kernel using the smoltcp crate.
 
+use crate::dhcp;
 use crate::virtio_net::VirtioNet;
 use alloc::collections::VecDeque;
 use alloc::vec;
@@ -12,23 +13,52 @@ use smoltcp::socket::{icmp, tcp, udp};
 use smoltcp::time::Instant;
 use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address};
 
-/// Network configuration
+/// Static network configuration, used when no DHCP server answers
 /// Default IP address (used as fallback if no IP is assigned by relay)
 pub const DEFAULT_IP_ADDR: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
 pub const GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
 pub const PREFIX_LEN: u8 = 24;
+/// DNS server (Google Public DNS)
+pub const DNS_SERVER: Ipv4Address = Ipv4Address::new(8, 8, 8, 8);
+
+/// Addressing of the interface, from a DHCP lease or the static settings
+#[derive(Clone, Copy)]
+pub struct IpConfig {
+    pub addr: Ipv4Address,
+    pub prefix_len: u8,
+    pub gateway: Ipv4Address,
+    pub dns: Ipv4Address,
+}
 
-/// Dynamic IP address assigned by the relay/network controller
-/// This is set during network initialization
-pub static mut MY_IP_ADDR: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+/// Current configuration, set during network initialization and whenever
+/// the DHCP lease changes
+static mut IP_CONFIG: IpConfig = IpConfig {
+    addr: DEFAULT_IP_ADDR,
+    prefix_len: PREFIX_LEN,
+    gateway: GATEWAY,
+    dns: DNS_SERVER,
+};
+
+/// Get the current configuration (safe wrapper)
+pub fn ip_config() -> IpConfig {
+    unsafe { IP_CONFIG }
+}
 
 /// Get the current IP address (safe wrapper)
 pub fn get_my_ip() -> Ipv4Address {
-    unsafe { MY_IP_ADDR }
+    ip_config().addr
+}
+
+/// Get the current default gateway
+pub fn get_gateway() -> Ipv4Address {
+    ip_config().gateway
+}
+
+/// Get the current DNS server
+pub fn get_dns_server() -> Ipv4Address {
+    ip_config().dns
 }
 
-/// DNS server (Google Public DNS)
-pub const DNS_SERVER: Ipv4Address = Ipv4Address::new(8, 8, 8, 8);
 /// DNS port
 pub const DNS_PORT: u16 = 53;
 
@@ -63,10 +93,46 @@ static mut UDP_TX_META: [udp::PacketMetadata; 8] = [udp::PacketMetadata::EMPTY;
 static mut UDP_RX_DATA: [u8; 1024] = [0; 1024];
 static mut UDP_TX_DATA: [u8; 1024] = [0; 1024];
 
+/// DHCP client socket buffers (renewals once a lease is bound)
+static mut DHCP_RX_META: [udp::PacketMetadata; 2] = [udp::PacketMetadata::EMPTY; 2];
+static mut DHCP_TX_META: [udp::PacketMetadata; 2] = [udp::PacketMetadata::EMPTY; 2];
+static mut DHCP_RX_DATA: [u8; 1024] = [0; 1024];
+static mut DHCP_TX_DATA: [u8; 1024] = [0; 1024];
+
 /// Static storage for TCP buffers (for HTTP connections)
 static mut TCP_RX_DATA: [u8; 8192] = [0; 8192];
 static mut TCP_TX_DATA: [u8; 4096] = [0; 4096];
 
+/// Number of general-purpose TCP sockets (see [`NetState::tcp_open`])
+pub const TCP_POOL_SIZE: usize = 4;
+
+/// Static storage for the TCP socket pool
+static mut TCP_POOL_RX_DATA: [[u8; 4096]; TCP_POOL_SIZE] = [[0; 4096]; TCP_POOL_SIZE];
+static mut TCP_POOL_TX_DATA: [[u8; 4096]; TCP_POOL_SIZE] = [[0; 4096]; TCP_POOL_SIZE];
+
+/// State of a pooled TCP socket
+pub use smoltcp::socket::tcp::State as TcpState;
+
+/// A socket taken from the TCP pool, valid until released
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TcpId(usize);
+
+/// Pool slot: the smoltcp socket and who owns it
+struct PoolSocket {
+    handle: SocketHandle,
+    owner: Option<&'static str>,
+    /// Port last dialled from or listened on
+    port: u16,
+}
+
+/// One pooled socket as listed by [`NetState::tcp_list`]
+pub struct TcpInfo {
+    pub owner: &'static str,
+    pub state: TcpState,
+    pub local_port: u16,
+    pub remote: Option<(Ipv4Address, u16)>,
+}
+
 /// Cached ARP entry
 struct ArpCache {
     ip: [u8; 4],
@@ -81,6 +147,12 @@ pub struct NetState {
     icmp_handle: SocketHandle,
     udp_handle: SocketHandle,
     tcp_handle: SocketHandle,
+    dhcp_handle: SocketHandle,
+    /// Lease being kept alive, `None` on the static configuration
+    dhcp: Option<dhcp::Client>,
+    tcp_pool: Vec<PoolSocket>,
+    /// Next ephemeral port handed out by `tcp_dial`
+    next_port: u16,
     arp_cache: Option<ArpCache>,
     /// Pending loopback ping replies (delivered on next poll)
     loopback_replies: VecDeque<LoopbackReply>,
@@ -133,7 +205,7 @@ impl NetState {
 
         // Save to global for other modules to use
         unsafe {
-            MY_IP_ADDR = my_ip;
+            IP_CONFIG.addr = my_ip;
         }
 
         let mac = device.mac;
@@ -185,6 +257,14 @@ impl NetState {
         // Bind UDP socket to local port for DNS
         udp_socket.bind(DNS_LOCAL_PORT).ok();
 
+        // Create UDP socket for DHCP lease renewals
+        let dhcp_rx_buffer =
+            unsafe { udp::PacketBuffer::new(&mut DHCP_RX_META[..], &mut DHCP_RX_DATA[..]) };
+        let dhcp_tx_buffer =
+            unsafe { udp::PacketBuffer::new(&mut DHCP_TX_META[..], &mut DHCP_TX_DATA[..]) };
+        let mut dhcp_socket = udp::Socket::new(dhcp_rx_buffer, dhcp_tx_buffer);
+        dhcp_socket.bind(dhcp::CLIENT_PORT).ok();
+
         // Create TCP socket for HTTP connections
         let tcp_rx_buffer = unsafe { tcp::SocketBuffer::new(&mut TCP_RX_DATA[..]) };
         let tcp_tx_buffer = unsafe { tcp::SocketBuffer::new(&mut TCP_TX_DATA[..]) };
@@ -197,6 +277,10 @@ impl NetState {
             icmp_handle: SocketHandle::default(),
             udp_handle: SocketHandle::default(),
             tcp_handle: SocketHandle::default(),
+            dhcp_handle: SocketHandle::default(),
+            dhcp: None,
+            tcp_pool: Vec::with_capacity(TCP_POOL_SIZE),
+            next_port: 49152,
             arp_cache: None,
             loopback_replies: VecDeque::new(),
         };
@@ -204,6 +288,19 @@ impl NetState {
         state.icmp_handle = state.sockets.add(icmp_socket);
         state.udp_handle = state.sockets.add(udp_socket);
         state.tcp_handle = state.sockets.add(tcp_socket);
+        state.dhcp_handle = state.sockets.add(dhcp_socket);
+
+        // Sockets for commands and services that keep their own connections
+        for i in 0..TCP_POOL_SIZE {
+            let rx = unsafe { tcp::SocketBuffer::new(&mut TCP_POOL_RX_DATA[i][..]) };
+            let tx = unsafe { tcp::SocketBuffer::new(&mut TCP_POOL_TX_DATA[i][..]) };
+            let handle = state.sockets.add(tcp::Socket::new(rx, tx));
+            state.tcp_pool.push(PoolSocket {
+                handle,
+                owner: None,
+                port: 0,
+            });
+        }
 
         Ok(state)
     }
@@ -226,6 +323,8 @@ impl NetState {
             &mut DeviceWrapper(&mut self.device),
             &mut self.sockets,
         );
+
+        self.dhcp_maintain(timestamp_ms);
     }
 
     /// Send a raw ARP request
@@ -329,10 +428,13 @@ impl NetState {
         addr.0 == my_ip.0
     }
 
-    /// Check if an address is on the local subnet (10.0.2.x/24)
+    /// Check if an address is on the local subnet
     fn is_on_local_subnet(addr: &Ipv4Address) -> bool {
-        let my_ip = get_my_ip();
-        addr.0[0] == my_ip.0[0] && addr.0[1] == my_ip.0[1] && addr.0[2] == my_ip.0[2]
+        let config = ip_config();
+        let mask = u32::MAX
+            .checked_shl(32 - config.prefix_len as u32)
+            .unwrap_or(0);
+        u32::from_be_bytes(addr.0) & mask == u32::from_be_bytes(config.addr.0) & mask
     }
 
     /// Send an ICMP echo request (ping) - directly via VirtIO or loopback
@@ -359,7 +461,7 @@ impl NetState {
         let next_hop = if Self::is_on_local_subnet(&target) {
             target_bytes
         } else {
-            GATEWAY.0 // Use gateway for external destinations
+            get_gateway().0 // Use gateway for external destinations
         };
 
         // Resolve MAC address for the next hop (gateway or direct target)
@@ -554,6 +656,135 @@ impl NetState {
         socket.can_recv()
     }
 
+    // ═══════════════════════════════════════════════════════════════════════════
+    // DHCP
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Get a DHCP lease and switch the interface to it. Blocks for a few
+    /// seconds at most; the current configuration stays if no server
+    /// answers.
+    pub fn dhcp_acquire(&mut self, timestamp_ms: i64) -> Option<dhcp::Lease> {
+        let lease = dhcp::acquire(&mut self.device)?;
+        self.apply_config(IpConfig {
+            addr: lease.addr,
+            prefix_len: lease.prefix_len,
+            gateway: lease.gateway,
+            dns: lease.dns,
+        });
+        self.dhcp = Some(dhcp::Client::new(lease, self.device.mac, timestamp_ms));
+        Some(lease)
+    }
+
+    /// Give the lease back and return to the static configuration
+    pub fn dhcp_release(&mut self, timestamp_ms: i64) -> bool {
+        let Some(client) = self.dhcp.take() else {
+            return false;
+        };
+        let msg = client.release();
+        let _ = self.dhcp_send(client.lease.server, &msg, timestamp_ms);
+        self.apply_static_config();
+        true
+    }
+
+    /// The current lease and its remaining seconds, if there is one
+    pub fn dhcp_lease(&self, timestamp_ms: i64) -> Option<(dhcp::Lease, i64)> {
+        self.dhcp
+            .as_ref()
+            .map(|client| (client.lease, client.remaining_secs(timestamp_ms)))
+    }
+
+    /// Renew the lease when due and handle the server's answers
+    fn dhcp_maintain(&mut self, timestamp_ms: i64) {
+        let Some(client) = self.dhcp.as_mut() else {
+            return;
+        };
+        let mut action = client.poll(timestamp_ms);
+
+        let socket = self.sockets.get_mut::<udp::Socket>(self.dhcp_handle);
+        let mut buf = [0u8; 1024];
+        while action.is_none() {
+            let Ok((len, _)) = socket.recv_slice(&mut buf) else {
+                break;
+            };
+            action = client.handle(&buf[..len], timestamp_ms);
+        }
+
+        match action {
+            Some(dhcp::Action::Send(dest, msg)) => {
+                let _ = self.dhcp_send(dest, &msg, timestamp_ms);
+            }
+            Some(dhcp::Action::Renewed(lease)) => {
+                self.apply_config(IpConfig {
+                    addr: lease.addr,
+                    prefix_len: lease.prefix_len,
+                    gateway: lease.gateway,
+                    dns: lease.dns,
+                });
+                crate::klog::klog_info("dhcp", "Lease renewed");
+            }
+            Some(dhcp::Action::Expired) => {
+                self.dhcp = None;
+                self.apply_static_config();
+                crate::klog::klog_warning("dhcp", "Lease lost, using the static configuration");
+            }
+            None => {}
+        }
+    }
+
+    fn dhcp_send(
+        &mut self,
+        dest: Ipv4Address,
+        msg: &[u8],
+        timestamp_ms: i64,
+    ) -> Result<(), &'static str> {
+        let socket = self.sockets.get_mut::<udp::Socket>(self.dhcp_handle);
+        socket
+            .send_slice(
+                msg,
+                IpEndpoint::new(IpAddress::Ipv4(dest), dhcp::SERVER_PORT),
+            )
+            .map_err(|_| "Failed to send DHCP message")?;
+        self.iface.poll(
+            Instant::from_millis(timestamp_ms),
+            &mut DeviceWrapper(&mut self.device),
+            &mut self.sockets,
+        );
+        Ok(())
+    }
+
+    /// Static settings, with the address the relay assigned if there is one
+    fn apply_static_config(&mut self) {
+        let addr = self
+            .device
+            .get_config_ip()
+            .map_or(DEFAULT_IP_ADDR, |ip| Ipv4Address::from_bytes(&ip));
+        self.apply_config(IpConfig {
+            addr,
+            prefix_len: PREFIX_LEN,
+            gateway: GATEWAY,
+            dns: DNS_SERVER,
+        });
+    }
+
+    /// Switch the interface to a new address, route and DNS server
+    fn apply_config(&mut self, config: IpConfig) {
+        self.iface.update_ip_addrs(|addrs| {
+            addrs.clear();
+            addrs
+                .push(IpCidr::new(IpAddress::Ipv4(config.addr), config.prefix_len))
+                .ok();
+        });
+        self.iface.routes_mut().remove_default_ipv4_route();
+        self.iface
+            .routes_mut()
+            .add_default_ipv4_route(config.gateway)
+            .ok();
+        self.arp_cache = None;
+        unsafe {
+            IP_CONFIG = config;
+        }
+    }
+
     // ═══════════════════════════════════════════════════════════════════════════
     // TCP METHODS (for HTTP connections)
     // ═══════════════════════════════════════════════════════════════════════════
@@ -698,6 +929,137 @@ impl NetState {
         socket.abort();
     }
 
+    // ═══════════════════════════════════════════════════════════════════════════
+    // TCP SOCKET POOL (for nc, httpd and other long-lived connections)
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Take a free socket from the pool on behalf of `owner`
+    pub fn tcp_open(&mut self, owner: &'static str) -> Result<TcpId, &'static str> {
+        let index = self
+            .tcp_pool
+            .iter()
+            .position(|slot| slot.owner.is_none())
+            .ok_or("No free TCP sockets")?;
+        self.tcp_pool[index].owner = Some(owner);
+        Ok(TcpId(index))
+    }
+
+    /// Abort any connection on a pooled socket and return it to the pool
+    pub fn tcp_release(&mut self, id: TcpId) {
+        let slot = &mut self.tcp_pool[id.0];
+        slot.owner = None;
+        slot.port = 0;
+        self.sockets.get_mut::<tcp::Socket>(slot.handle).abort();
+    }
+
+    fn pool_socket(&mut self, id: TcpId) -> &mut tcp::Socket<'static> {
+        self.sockets
+            .get_mut::<tcp::Socket>(self.tcp_pool[id.0].handle)
+    }
+
+    /// Connect a pooled socket to a remote host
+    pub fn tcp_dial(
+        &mut self,
+        id: TcpId,
+        dest_ip: Ipv4Address,
+        dest_port: u16,
+    ) -> Result<(), &'static str> {
+        let local_port = self.next_port;
+        self.next_port = if local_port == u16::MAX {
+            49152
+        } else {
+            local_port + 1
+        };
+
+        let remote = IpEndpoint::new(IpAddress::Ipv4(dest_ip), dest_port);
+        self.tcp_pool[id.0].port = local_port;
+        let handle = self.tcp_pool[id.0].handle;
+        let socket = self.sockets.get_mut::<tcp::Socket>(handle);
+        socket.abort();
+        socket
+            .connect(self.iface.context(), remote, local_port)
+            .map_err(|_| "Failed to initiate TCP connection")
+    }
+
+    /// Wait for one incoming connection on `port`
+    pub fn tcp_listen(&mut self, id: TcpId, port: u16) -> Result<(), &'static str> {
+        self.tcp_pool[id.0].port = port;
+        let socket = self.pool_socket(id);
+        socket.abort();
+        socket.listen(port).map_err(|_| "Failed to listen on port")
+    }
+
+    /// Current state of a pooled socket
+    pub fn tcp_status(&mut self, id: TcpId) -> TcpState {
+        self.pool_socket(id).state()
+    }
+
+    /// Address and port of the connected peer
+    pub fn tcp_peer(&mut self, id: TcpId) -> Option<(Ipv4Address, u16)> {
+        self.pool_socket(id).remote_endpoint().map(|ep| {
+            let IpAddress::Ipv4(ip) = ep.addr;
+            (ip, ep.port)
+        })
+    }
+
+    /// Queue data on a pooled socket, returning how much fit in its buffer
+    pub fn tcp_write(
+        &mut self,
+        id: TcpId,
+        data: &[u8],
+        timestamp_ms: i64,
+    ) -> Result<usize, &'static str> {
+        let socket = self.pool_socket(id);
+        if !socket.may_send() {
+            return Err("TCP socket cannot send");
+        }
+        let sent = socket
+            .send_slice(data)
+            .map_err(|_| "Failed to send TCP data")?;
+        self.poll(timestamp_ms);
+        Ok(sent)
+    }
+
+    /// Read received data from a pooled socket (non-blocking). Fails once the
+    /// peer has closed its side and everything it sent has been read.
+    pub fn tcp_read(&mut self, id: TcpId, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let socket = self.pool_socket(id);
+        if !socket.may_recv() && socket.recv_queue() == 0 {
+            return match socket.state() {
+                TcpState::Listen | TcpState::SynSent | TcpState::SynReceived => Ok(0),
+                _ => Err("Connection closed by peer"),
+            };
+        }
+        Ok(socket.recv_slice(buf).unwrap_or(0))
+    }
+
+    /// Close the sending side of a pooled socket
+    pub fn tcp_shutdown(&mut self, id: TcpId, timestamp_ms: i64) {
+        self.pool_socket(id).close();
+        self.poll(timestamp_ms);
+    }
+
+    /// Pooled sockets currently in use
+    pub fn tcp_list(&mut self) -> Vec<TcpInfo> {
+        let mut list = Vec::new();
+        for slot in &self.tcp_pool {
+            let Some(owner) = slot.owner else {
+                continue;
+            };
+            let socket = self.sockets.get::<tcp::Socket>(slot.handle);
+            list.push(TcpInfo {
+                owner,
+                state: socket.state(),
+                local_port: slot.port,
+                remote: socket.remote_endpoint().map(|ep| {
+                    let IpAddress::Ipv4(ip) = ep.addr;
+                    (ip, ep.port)
+                }),
+            });
+        }
+        list
+    }
+
     /// Get TCP socket state (for debugging)
     pub fn tcp_state(&mut self) -> &'static str {
         let socket = self.sockets.get_mut::<tcp::Socket>(self.tcp_handle);
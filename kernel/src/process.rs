@@ -0,0 +1,971 @@
+//! User processes
+//!
+//! Runs statically linked RV64 ELF programs (see [`crate::elf`]) in U-mode.
+//! The kernel runs in M-mode with no translation, so each process gets its
+//! own Sv39 page table in `satp`, which only applies to U-mode: the program
+//! sees its segments at their link addresses and a stack at the top of the
+//! lower half of the address space, and nothing of the kernel.
+//!
+//! A process runs on the task (or shell) that started it. [`execute`]
+//! enters U-mode through `enter_user`, which returns when the program traps
+//! back: for a system call, a fault or an interrupt. An interrupt is left
+//! pending and taken by the usual handler as soon as interrupts are back on,
+//! so a program is preempted like any other task.
+//!
+//! System calls follow the Linux RISC-V ABI: number in `a7`, arguments in
+//! `a0`-`a5`, result or negated errno in `a0`. Supported are `read`,
+//! `write`, `writev`, `openat`, `close`, `lseek`, `brk`, `exit` and
+//! `exit_group`; anything else fails with `ENOSYS`. Files are read whole
+//! when opened and written back when closed.
+
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm};
+use core::ptr::NonNull;
+
+use crate::elf::{self, Executable};
+use crate::fs::FileSystem;
+use crate::virtio_blk::VirtioBlock;
+use crate::{out_bytes, out_line, uart, BLK_DEV, FS_STATE};
+
+const PAGE_SIZE: usize = 4096;
+const PAGE_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(PAGE_SIZE, PAGE_SIZE) };
+
+/// End of the user half of the Sv39 address space
+const USER_TOP: u64 = 1 << 38;
+
+/// Top of the initial stack, leaving a guard page above it
+const STACK_TOP: u64 = USER_TOP - PAGE_SIZE as u64;
+const STACK_SIZE: u64 = 64 * 1024;
+
+/// Most memory a process can map, page tables aside (16 MiB)
+const MAX_PAGES: usize = 4096;
+
+/// Most bytes moved by one `read` or `write`
+const MAX_IO: usize = 1 << 20;
+
+/// Open files per process, besides stdin, stdout and stderr
+const MAX_FILES: usize = 16;
+
+const SATP_SV39: usize = 8 << 60;
+
+// Page table entry bits
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+
+/// pmpcfg: NAPOT address matching, read/write/execute
+const PMP_NAPOT_RWX: usize = 0x1f;
+
+const MCAUSE_INTERRUPT: usize = 1 << 63;
+const MCAUSE_ECALL_U: usize = 8;
+
+// System call numbers
+const SYS_OPENAT: usize = 56;
+const SYS_CLOSE: usize = 57;
+const SYS_LSEEK: usize = 62;
+const SYS_READ: usize = 63;
+const SYS_WRITE: usize = 64;
+const SYS_WRITEV: usize = 66;
+const SYS_EXIT: usize = 93;
+const SYS_EXIT_GROUP: usize = 94;
+const SYS_BRK: usize = 214;
+
+// Error numbers
+const ENOENT: i64 = 2;
+const EIO: i64 = 5;
+const EBADF: i64 = 9;
+const ENOMEM: i64 = 12;
+const EFAULT: i64 = 14;
+const EISDIR: i64 = 21;
+const EINVAL: i64 = 22;
+const EMFILE: i64 = 24;
+const ESPIPE: i64 = 29;
+const ENOSYS: i64 = 38;
+
+// openat flags
+const AT_FDCWD: i64 = -100;
+const O_ACCMODE: usize = 3;
+const O_RDONLY: usize = 0;
+const O_WRONLY: usize = 1;
+const O_CREAT: usize = 0o100;
+const O_TRUNC: usize = 0o1000;
+const O_APPEND: usize = 0o2000;
+
+// Auxiliary vector entries
+const AT_NULL: u64 = 0;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+/// Registers of a process, and what `enter_user` needs to get back to the
+/// kernel. The offsets are used by the assembly below.
+#[repr(C)]
+struct UserContext {
+    /// x0-x31 (x0 unused)
+    regs: [usize; 32],
+    pc: usize,
+    satp: usize,
+    kernel_sp: usize,
+    kernel_mtvec: usize,
+    /// mcause and mtval of the last trap
+    cause: usize,
+    tval: usize,
+    f: [u64; 32],
+    fcsr: usize,
+}
+
+// enter_user(context: *mut UserContext)
+//
+// Saves the kernel's callee-saved registers on its stack, points the trap
+// vector at `user_trap` and `mret`s into the process. `user_trap` saves the
+// process registers and returns from `enter_user` with interrupts off.
+global_asm!(
+    r#"
+    .section .text.enter_user
+    .global enter_user
+    .align 2
+enter_user:
+    csrci mstatus, 8
+    addi sp, sp, -128
+    sd ra, 0(sp)
+    sd s0, 8(sp)
+    sd s1, 16(sp)
+    sd s2, 24(sp)
+    sd s3, 32(sp)
+    sd s4, 40(sp)
+    sd s5, 48(sp)
+    sd s6, 56(sp)
+    sd s7, 64(sp)
+    sd s8, 72(sp)
+    sd s9, 80(sp)
+    sd s10, 88(sp)
+    sd s11, 96(sp)
+    sd gp, 104(sp)
+    sd tp, 112(sp)
+    sd sp, 272(a0)
+
+    csrr t0, mtvec
+    sd t0, 280(a0)
+    la t0, user_trap
+    csrw mtvec, t0
+    csrw mscratch, a0
+    ld t0, 264(a0)
+    csrw satp, t0
+    sfence.vma
+    ld t0, 256(a0)
+    csrw mepc, t0
+    # MPP = U, MPIE = 1, FS = Initial
+    li t0, 0x1800
+    csrc mstatus, t0
+    li t0, 0x2080
+    csrs mstatus, t0
+
+    fld f0, 304(a0)
+    fld f1, 312(a0)
+    fld f2, 320(a0)
+    fld f3, 328(a0)
+    fld f4, 336(a0)
+    fld f5, 344(a0)
+    fld f6, 352(a0)
+    fld f7, 360(a0)
+    fld f8, 368(a0)
+    fld f9, 376(a0)
+    fld f10, 384(a0)
+    fld f11, 392(a0)
+    fld f12, 400(a0)
+    fld f13, 408(a0)
+    fld f14, 416(a0)
+    fld f15, 424(a0)
+    fld f16, 432(a0)
+    fld f17, 440(a0)
+    fld f18, 448(a0)
+    fld f19, 456(a0)
+    fld f20, 464(a0)
+    fld f21, 472(a0)
+    fld f22, 480(a0)
+    fld f23, 488(a0)
+    fld f24, 496(a0)
+    fld f25, 504(a0)
+    fld f26, 512(a0)
+    fld f27, 520(a0)
+    fld f28, 528(a0)
+    fld f29, 536(a0)
+    fld f30, 544(a0)
+    fld f31, 552(a0)
+    ld t0, 560(a0)
+    fscsr t0
+
+    ld x1, 8(a0)
+    ld x2, 16(a0)
+    ld x3, 24(a0)
+    ld x4, 32(a0)
+    ld x5, 40(a0)
+    ld x6, 48(a0)
+    ld x7, 56(a0)
+    ld x8, 64(a0)
+    ld x9, 72(a0)
+    ld x11, 88(a0)
+    ld x12, 96(a0)
+    ld x13, 104(a0)
+    ld x14, 112(a0)
+    ld x15, 120(a0)
+    ld x16, 128(a0)
+    ld x17, 136(a0)
+    ld x18, 144(a0)
+    ld x19, 152(a0)
+    ld x20, 160(a0)
+    ld x21, 168(a0)
+    ld x22, 176(a0)
+    ld x23, 184(a0)
+    ld x24, 192(a0)
+    ld x25, 200(a0)
+    ld x26, 208(a0)
+    ld x27, 216(a0)
+    ld x28, 224(a0)
+    ld x29, 232(a0)
+    ld x30, 240(a0)
+    ld x31, 248(a0)
+    ld x10, 80(a0)
+    mret
+
+    .align 2
+user_trap:
+    csrrw a0, mscratch, a0
+    sd x1, 8(a0)
+    sd x2, 16(a0)
+    sd x3, 24(a0)
+    sd x4, 32(a0)
+    sd x5, 40(a0)
+    sd x6, 48(a0)
+    sd x7, 56(a0)
+    sd x8, 64(a0)
+    sd x9, 72(a0)
+    sd x11, 88(a0)
+    sd x12, 96(a0)
+    sd x13, 104(a0)
+    sd x14, 112(a0)
+    sd x15, 120(a0)
+    sd x16, 128(a0)
+    sd x17, 136(a0)
+    sd x18, 144(a0)
+    sd x19, 152(a0)
+    sd x20, 160(a0)
+    sd x21, 168(a0)
+    sd x22, 176(a0)
+    sd x23, 184(a0)
+    sd x24, 192(a0)
+    sd x25, 200(a0)
+    sd x26, 208(a0)
+    sd x27, 216(a0)
+    sd x28, 224(a0)
+    sd x29, 232(a0)
+    sd x30, 240(a0)
+    sd x31, 248(a0)
+    csrr t0, mscratch
+    sd t0, 80(a0)
+
+    fsd f0, 304(a0)
+    fsd f1, 312(a0)
+    fsd f2, 320(a0)
+    fsd f3, 328(a0)
+    fsd f4, 336(a0)
+    fsd f5, 344(a0)
+    fsd f6, 352(a0)
+    fsd f7, 360(a0)
+    fsd f8, 368(a0)
+    fsd f9, 376(a0)
+    fsd f10, 384(a0)
+    fsd f11, 392(a0)
+    fsd f12, 400(a0)
+    fsd f13, 408(a0)
+    fsd f14, 416(a0)
+    fsd f15, 424(a0)
+    fsd f16, 432(a0)
+    fsd f17, 440(a0)
+    fsd f18, 448(a0)
+    fsd f19, 456(a0)
+    fsd f20, 464(a0)
+    fsd f21, 472(a0)
+    fsd f22, 480(a0)
+    fsd f23, 488(a0)
+    fsd f24, 496(a0)
+    fsd f25, 504(a0)
+    fsd f26, 512(a0)
+    fsd f27, 520(a0)
+    fsd f28, 528(a0)
+    fsd f29, 536(a0)
+    fsd f30, 544(a0)
+    fsd f31, 552(a0)
+    frcsr t0
+    sd t0, 560(a0)
+
+    csrr t0, mepc
+    sd t0, 256(a0)
+    csrr t0, mcause
+    sd t0, 288(a0)
+    csrr t0, mtval
+    sd t0, 296(a0)
+    ld t0, 280(a0)
+    csrw mtvec, t0
+
+    ld sp, 272(a0)
+    ld ra, 0(sp)
+    ld s0, 8(sp)
+    ld s1, 16(sp)
+    ld s2, 24(sp)
+    ld s3, 32(sp)
+    ld s4, 40(sp)
+    ld s5, 48(sp)
+    ld s6, 56(sp)
+    ld s7, 64(sp)
+    ld s8, 72(sp)
+    ld s9, 80(sp)
+    ld s10, 88(sp)
+    ld s11, 96(sp)
+    ld gp, 104(sp)
+    ld tp, 112(sp)
+    addi sp, sp, 128
+    ret
+"#
+);
+
+extern "C" {
+    fn enter_user(context: *mut UserContext);
+}
+
+/// A zeroed, page-aligned page from the kernel heap
+struct Page(NonNull<u8>);
+
+impl Page {
+    fn new() -> Result<Self, &'static str> {
+        let ptr = unsafe { alloc_zeroed(PAGE_LAYOUT) };
+        NonNull::new(ptr).map(Page).ok_or("Out of memory")
+    }
+
+    fn addr(&self) -> u64 {
+        self.0.as_ptr() as u64
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.0.as_ptr(), PAGE_SIZE) }
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.0.as_ptr(), PAGE_LAYOUT) };
+    }
+}
+
+fn page_up(addr: u64) -> u64 {
+    (addr + PAGE_SIZE as u64 - 1) & !(PAGE_SIZE as u64 - 1)
+}
+
+fn page_down(addr: u64) -> u64 {
+    addr & !(PAGE_SIZE as u64 - 1)
+}
+
+/// The pages of a process and the Sv39 table mapping them
+struct AddressSpace {
+    root: Page,
+    /// Second and third level tables
+    tables: Vec<Page>,
+    /// User pages by virtual page number, with their permission bits
+    pages: BTreeMap<u64, (Page, u64)>,
+}
+
+impl AddressSpace {
+    fn new() -> Result<Self, &'static str> {
+        Ok(Self {
+            root: Page::new()?,
+            tables: Vec::new(),
+            pages: BTreeMap::new(),
+        })
+    }
+
+    fn satp(&self) -> usize {
+        SATP_SV39 | (self.root.addr() >> 12) as usize
+    }
+
+    /// Leaf entry for page `vpn`, creating the tables leading to it
+    fn leaf(&mut self, vpn: u64) -> Result<*mut u64, &'static str> {
+        let mut table = self.root.addr() as *mut u64;
+        for level in [2, 1] {
+            let entry = unsafe { table.add(((vpn >> (9 * level)) & 0x1ff) as usize) };
+            if unsafe { *entry } & PTE_V == 0 {
+                let page = Page::new()?;
+                unsafe { *entry = (page.addr() >> 12) << 10 | PTE_V };
+                self.tables.push(page);
+            }
+            table = ((unsafe { *entry } >> 10) << 12) as *mut u64;
+        }
+        Ok(unsafe { table.add((vpn & 0x1ff) as usize) })
+    }
+
+    /// Map the page holding `va`, adding `perms` to it if it already is
+    fn map(&mut self, va: u64, perms: u64) -> Result<(), &'static str> {
+        if va >= USER_TOP {
+            return Err("Address outside user space");
+        }
+        let vpn = va >> 12;
+        if !self.pages.contains_key(&vpn) {
+            if self.pages.len() >= MAX_PAGES {
+                return Err("Out of memory");
+            }
+            self.pages.insert(vpn, (Page::new()?, 0));
+        }
+        let leaf = self.leaf(vpn)?;
+        let (page, page_perms) = self.pages.get_mut(&vpn).unwrap();
+        *page_perms |= perms;
+        unsafe {
+            *leaf = (page.addr() >> 12) << 10 | *page_perms | PTE_V | PTE_U | PTE_A | PTE_D;
+        }
+        Ok(())
+    }
+
+    fn unmap(&mut self, va: u64) {
+        let vpn = va >> 12;
+        if self.pages.remove(&vpn).is_some() {
+            if let Ok(leaf) = self.leaf(vpn) {
+                unsafe { *leaf = 0 };
+            }
+        }
+    }
+
+    /// Run `f` on each page-sized piece of `[va, va + len)` with the offset
+    /// of the piece, failing if a page is missing or lacks `perms`
+    fn for_each_chunk(
+        &mut self,
+        va: u64,
+        len: usize,
+        perms: u64,
+        mut f: impl FnMut(&mut [u8], usize),
+    ) -> Result<(), i64> {
+        let mut done = 0;
+        while done < len {
+            let addr = va.checked_add(done as u64).ok_or(EFAULT)?;
+            let (page, page_perms) = self.pages.get_mut(&(addr >> 12)).ok_or(EFAULT)?;
+            if *page_perms & perms != perms {
+                return Err(EFAULT);
+            }
+            let offset = (addr & 0xfff) as usize;
+            let n = (PAGE_SIZE - offset).min(len - done);
+            f(&mut page.bytes()[offset..offset + n], done);
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn copy_in(&mut self, va: u64, len: usize) -> Result<Vec<u8>, i64> {
+        let mut data = vec![0u8; len];
+        self.for_each_chunk(va, len, PTE_R, |chunk, at| {
+            data[at..at + chunk.len()].copy_from_slice(chunk)
+        })?;
+        Ok(data)
+    }
+
+    /// Copy `data` to `va`, into pages that have at least `perms`
+    fn copy_out(&mut self, va: u64, data: &[u8], perms: u64) -> Result<(), i64> {
+        self.for_each_chunk(va, data.len(), perms, |chunk, at| {
+            chunk.copy_from_slice(&data[at..at + chunk.len()])
+        })
+    }
+
+    /// Read a NUL-terminated string of at most `max` bytes
+    fn read_str(&mut self, va: u64, max: usize) -> Result<String, i64> {
+        let mut bytes = Vec::new();
+        for i in 0..max as u64 {
+            let byte = self.copy_in(va + i, 1)?[0];
+            if byte == 0 {
+                return String::from_utf8(bytes).map_err(|_| EINVAL);
+            }
+            bytes.push(byte);
+        }
+        Err(EINVAL)
+    }
+}
+
+/// A file opened by a process, held in memory until it is closed
+struct OpenFile {
+    path: String,
+    data: Vec<u8>,
+    pos: usize,
+    readable: bool,
+    writable: bool,
+    append: bool,
+    /// Changed since it was opened, so written back on close
+    dirty: bool,
+}
+
+struct Process {
+    context: UserContext,
+    memory: AddressSpace,
+    /// Start and current end of the heap
+    brk_start: u64,
+    brk: u64,
+    /// Descriptors from 3 up
+    files: Vec<Option<OpenFile>>,
+    /// Output of the previous pipeline stage, if the program is piped into
+    stdin: Option<Vec<u8>>,
+    stdin_pos: usize,
+}
+
+/// Run the program in `bytes` with `args` (program name first) and piped
+/// input, returning its exit status
+pub fn execute(bytes: &[u8], args: &[&str], stdin: Option<&[u8]>) -> Result<i32, String> {
+    let exe = elf::parse(bytes).map_err(String::from)?;
+    let mut process = Process::load(&exe, bytes, args).map_err(String::from)?;
+    process.stdin = stdin.map(Vec::from);
+
+    let result = process.run();
+    process.close_all();
+    unsafe {
+        asm!("csrw satp, zero", "sfence.vma", options(nostack));
+    }
+    result
+}
+
+/// Let U-mode reach all of memory, leaving protection to the page tables.
+/// Without a matching PMP entry, U-mode accesses fault.
+fn allow_user_memory() {
+    unsafe {
+        asm!("csrw pmpaddr0, {}", in(reg) usize::MAX >> 10, options(nomem, nostack));
+        asm!("csrw pmpcfg0, {}", in(reg) PMP_NAPOT_RWX, options(nomem, nostack));
+    }
+}
+
+/// Some bytes for `AT_RANDOM`, from the cycle counter
+fn random_bytes() -> [u8; 16] {
+    let mut seed = unsafe { core::ptr::read_volatile(crate::CLINT_MTIME as *const u64) };
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    bytes
+}
+
+fn fault_name(cause: usize) -> &'static str {
+    match cause {
+        0 => "Misaligned instruction",
+        1 => "Instruction access fault",
+        2 => "Illegal instruction",
+        3 => "Breakpoint",
+        4 => "Misaligned load",
+        5 => "Load access fault",
+        6 => "Misaligned store",
+        7 => "Store access fault",
+        12 => "Instruction page fault",
+        13 => "Load page fault",
+        15 => "Store page fault",
+        _ => "Unexpected trap",
+    }
+}
+
+impl Process {
+    fn load(exe: &Executable, bytes: &[u8], args: &[&str]) -> Result<Self, &'static str> {
+        let mut memory = AddressSpace::new()?;
+
+        let mut end = 0;
+        for segment in &exe.segments {
+            let seg_end = segment
+                .vaddr
+                .checked_add(segment.mem_size)
+                .filter(|&e| e <= STACK_TOP - STACK_SIZE - PAGE_SIZE as u64)
+                .ok_or("Segment overlaps the stack")?;
+            let mut perms = 0;
+            if segment.flags & elf::PF_R != 0 {
+                perms |= PTE_R;
+            }
+            if segment.flags & elf::PF_W != 0 {
+                perms |= PTE_W;
+            }
+            if segment.flags & elf::PF_X != 0 {
+                perms |= PTE_X;
+            }
+            for va in (page_down(segment.vaddr)..page_up(seg_end)).step_by(PAGE_SIZE) {
+                memory.map(va, perms)?;
+            }
+            let file = &bytes[segment.offset..segment.offset + segment.file_size];
+            memory
+                .copy_out(segment.vaddr, file, 0)
+                .map_err(|_| "Cannot load segment")?;
+            end = end.max(page_up(seg_end));
+        }
+
+        for va in (STACK_TOP - STACK_SIZE..STACK_TOP).step_by(PAGE_SIZE) {
+            memory.map(va, PTE_R | PTE_W)?;
+        }
+
+        let mut process = Self {
+            context: UserContext {
+                regs: [0; 32],
+                pc: exe.entry as usize,
+                satp: memory.satp(),
+                kernel_sp: 0,
+                kernel_mtvec: 0,
+                cause: 0,
+                tval: 0,
+                f: [0; 32],
+                fcsr: 0,
+            },
+            memory,
+            brk_start: end,
+            brk: end,
+            files: Vec::new(),
+            stdin: None,
+            stdin_pos: 0,
+        };
+        process.context.regs[2] = process
+            .build_stack(exe.entry, args)
+            .map_err(|_| "Arguments don't fit on the stack")?
+            as usize;
+        Ok(process)
+    }
+
+    /// Lay out `argc`, `argv`, an empty environment and the auxiliary vector
+    /// at the top of the stack, as the System V ABI has it. Returns `sp`.
+    fn build_stack(&mut self, entry: u64, args: &[&str]) -> Result<u64, i64> {
+        let mut sp = STACK_TOP;
+        let mut argv = Vec::new();
+        for arg in args {
+            sp -= arg.len() as u64 + 1;
+            self.memory.copy_out(sp, arg.as_bytes(), PTE_W)?;
+            self.memory.copy_out(sp + arg.len() as u64, &[0], PTE_W)?;
+            argv.push(sp);
+        }
+        sp -= 16;
+        let random = sp;
+        self.memory.copy_out(random, &random_bytes(), PTE_W)?;
+
+        let mut words = vec![args.len() as u64];
+        words.extend_from_slice(&argv);
+        words.push(0); // End of argv
+        words.push(0); // End of envp
+        words.extend_from_slice(&[
+            AT_PAGESZ,
+            PAGE_SIZE as u64,
+            AT_ENTRY,
+            entry,
+            AT_RANDOM,
+            random,
+            AT_NULL,
+            0,
+        ]);
+        sp = (sp - words.len() as u64 * 8) & !0xf;
+        if sp < STACK_TOP - STACK_SIZE {
+            return Err(ENOMEM);
+        }
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        self.memory.copy_out(sp, &bytes, PTE_W)?;
+        Ok(sp)
+    }
+
+    fn run(&mut self) -> Result<i32, String> {
+        allow_user_memory();
+        loop {
+            unsafe { enter_user(&mut self.context) };
+            // Back with interrupts off: a pending interrupt is taken here
+            unsafe { asm!("csrsi mstatus, 8", options(nostack)) };
+            crate::jobs::checkpoint().map_err(String::from)?;
+
+            let cause = self.context.cause;
+            if cause & MCAUSE_INTERRUPT != 0 {
+                continue;
+            }
+            if cause != MCAUSE_ECALL_U {
+                return Err(format!(
+                    "{} at {:#x} (address {:#x})",
+                    fault_name(cause),
+                    self.context.pc,
+                    self.context.tval
+                ));
+            }
+            self.context.pc += 4;
+            if let Some(status) = self.syscall()? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Handle the system call in the registers, returning the exit status
+    /// once the program exits
+    fn syscall(&mut self) -> Result<Option<i32>, String> {
+        let regs = self.context.regs;
+        let (a0, a1, a2) = (regs[10], regs[11], regs[12]);
+        let result = match regs[17] {
+            SYS_READ => self.sys_read(a0, a1 as u64, a2),
+            SYS_WRITE => self.sys_write(a0, a1 as u64, a2),
+            SYS_WRITEV => self.sys_writev(a0, a1 as u64, a2),
+            SYS_OPENAT => self.sys_openat(a0 as i64, a1 as u64, a2),
+            SYS_CLOSE => self.sys_close(a0),
+            SYS_LSEEK => self.sys_lseek(a0, a1 as i64, a2),
+            SYS_BRK => Ok(self.sys_brk(a0 as u64) as usize),
+            SYS_EXIT | SYS_EXIT_GROUP => return Ok(Some(a0 as i32)),
+            _ => Err(ENOSYS),
+        };
+        self.context.regs[10] = match result {
+            Ok(value) => value,
+            Err(0) => return Err(String::from("interrupted")),
+            Err(errno) => (-errno) as usize,
+        };
+        Ok(None)
+    }
+
+    fn file(&mut self, fd: usize) -> Result<&mut OpenFile, i64> {
+        fd.checked_sub(3)
+            .and_then(|i| self.files.get_mut(i))
+            .and_then(|f| f.as_mut())
+            .ok_or(EBADF)
+    }
+
+    /// `read`. Fails with errno 0 when Ctrl+C interrupts a console read.
+    fn sys_read(&mut self, fd: usize, buf: u64, len: usize) -> Result<usize, i64> {
+        let len = len.min(MAX_IO);
+        let data = match fd {
+            0 => match self.stdin.as_ref() {
+                Some(stdin) => {
+                    let end = stdin.len().min(self.stdin_pos + len);
+                    let chunk = stdin[self.stdin_pos..end].to_vec();
+                    self.stdin_pos = end;
+                    chunk
+                }
+                None => read_console(len).ok_or(0)?,
+            },
+            1 | 2 => return Err(EBADF),
+            _ => {
+                let file = self.file(fd)?;
+                if !file.readable {
+                    return Err(EBADF);
+                }
+                let start = file.pos.min(file.data.len());
+                let end = file.data.len().min(start + len);
+                file.pos = end;
+                file.data[start..end].to_vec()
+            }
+        };
+        self.memory.copy_out(buf, &data, PTE_W)?;
+        Ok(data.len())
+    }
+
+    fn sys_write(&mut self, fd: usize, buf: u64, len: usize) -> Result<usize, i64> {
+        let data = self.memory.copy_in(buf, len.min(MAX_IO))?;
+        match fd {
+            // Through the shell's capture so redirects and pipes see it
+            1 | 2 => out_bytes(&data),
+            0 => return Err(EBADF),
+            _ => {
+                let file = self.file(fd)?;
+                if !file.writable {
+                    return Err(EBADF);
+                }
+                if file.append {
+                    file.pos = file.data.len();
+                }
+                let end = file.pos + data.len();
+                if file.data.len() < end {
+                    file.data.resize(end, 0);
+                }
+                file.data[file.pos..end].copy_from_slice(&data);
+                file.pos = end;
+                file.dirty = true;
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn sys_writev(&mut self, fd: usize, iov: u64, count: usize) -> Result<usize, i64> {
+        if count > 1024 {
+            return Err(EINVAL);
+        }
+        let vecs = self.memory.copy_in(iov, count * 16)?;
+        let mut total = 0;
+        for vec in vecs.chunks(16) {
+            let base = u64::from_le_bytes(vec[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(vec[8..].try_into().unwrap()) as usize;
+            total += self.sys_write(fd, base, len)?;
+        }
+        Ok(total)
+    }
+
+    fn sys_openat(&mut self, dirfd: i64, path: u64, flags: usize) -> Result<usize, i64> {
+        let path = self.memory.read_str(path, 256)?;
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(EBADF);
+        }
+        let path = crate::resolve_path(&path);
+        let access = flags & O_ACCMODE;
+        let slot = match self.files.iter().position(|f| f.is_none()) {
+            Some(slot) => slot,
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(EMFILE),
+        };
+
+        let existing = with_fs(|fs, dev| {
+            if fs.is_dir(dev, &path) {
+                Err(EISDIR)
+            } else {
+                Ok(fs.read_file(dev, &path))
+            }
+        })
+        .ok_or(EIO)??;
+        let created = existing.is_none();
+        let mut data = match existing {
+            Some(data) => data,
+            None if flags & O_CREAT != 0 => Vec::new(),
+            None => return Err(ENOENT),
+        };
+        let writable = access != O_RDONLY;
+        let truncated = writable && flags & O_TRUNC != 0;
+        if truncated {
+            data.clear();
+        }
+
+        self.files[slot] = Some(OpenFile {
+            path,
+            data,
+            pos: 0,
+            readable: access != O_WRONLY,
+            writable,
+            append: flags & O_APPEND != 0,
+            dirty: created || truncated,
+        });
+        Ok(slot + 3)
+    }
+
+    fn sys_close(&mut self, fd: usize) -> Result<usize, i64> {
+        self.file(fd)?;
+        let file = self.files[fd - 3].take().unwrap();
+        flush(&file)?;
+        Ok(0)
+    }
+
+    fn sys_lseek(&mut self, fd: usize, offset: i64, whence: usize) -> Result<usize, i64> {
+        if fd < 3 {
+            return Err(ESPIPE);
+        }
+        let file = self.file(fd)?;
+        let base = match whence {
+            0 => 0,
+            1 => file.pos as i64,
+            2 => file.data.len() as i64,
+            _ => return Err(EINVAL),
+        };
+        let pos = base.checked_add(offset).filter(|&p| p >= 0).ok_or(EINVAL)?;
+        file.pos = pos as usize;
+        Ok(file.pos)
+    }
+
+    /// `brk`: move the end of the heap, returning the new end (or the old
+    /// one if it can't move)
+    fn sys_brk(&mut self, addr: u64) -> u64 {
+        if addr < self.brk_start || addr > STACK_TOP - STACK_SIZE - PAGE_SIZE as u64 {
+            return self.brk;
+        }
+        let (old_end, new_end) = (page_up(self.brk), page_up(addr));
+        for va in (old_end..new_end).step_by(PAGE_SIZE) {
+            if self.memory.map(va, PTE_R | PTE_W).is_err() {
+                for va in (old_end..va).step_by(PAGE_SIZE) {
+                    self.memory.unmap(va);
+                }
+                return self.brk;
+            }
+        }
+        for va in (new_end..old_end).step_by(PAGE_SIZE) {
+            self.memory.unmap(va);
+        }
+        self.brk = addr;
+        self.brk
+    }
+
+    /// Write back the files the program left open
+    fn close_all(&mut self) {
+        for file in self.files.iter_mut().filter_map(|f| f.take()) {
+            if flush(&file).is_err() {
+                out_line(&format!(
+                    "\x1b[1;31mError:\x1b[0m cannot save {}",
+                    file.path
+                ));
+            }
+        }
+    }
+}
+
+fn with_fs<T>(f: impl FnOnce(&mut FileSystem, &mut VirtioBlock) -> T) -> Option<T> {
+    let mut fs_guard = FS_STATE.lock();
+    let mut blk_guard = BLK_DEV.lock();
+    Some(f(fs_guard.as_mut()?, blk_guard.as_mut()?))
+}
+
+fn flush(file: &OpenFile) -> Result<(), i64> {
+    if !file.dirty {
+        return Ok(());
+    }
+    with_fs(|fs, dev| fs.write_file(dev, &file.path, &file.data))
+        .ok_or(EIO)?
+        .map_err(|_| EIO)
+}
+
+/// Read a line typed on the console, echoing it, or `None` on Ctrl+C.
+/// Returns nothing (end of file) on Ctrl+D and off hart 0, which doesn't
+/// own the console.
+fn read_console(max: usize) -> Option<Vec<u8>> {
+    let mut line = Vec::new();
+    if crate::get_hart_id() != 0 || max == 0 {
+        return Some(line);
+    }
+
+    // Hart 0 keeps serving the network and its daemons while waiting
+    let console = uart::Console::new();
+    let mut last_task_run = crate::get_time_ms();
+    while line.len() < max {
+        crate::poll_network();
+        let now = crate::get_time_ms();
+        if now - last_task_run >= 100 {
+            last_task_run = now;
+            crate::run_hart0_tasks();
+        }
+        match console.read_byte() {
+            0 => {}
+            b'\r' | b'\n' => {
+                uart::write_str("\n");
+                line.push(b'\n');
+                break;
+            }
+            // Ctrl+C
+            0x03 => {
+                out_line("^C");
+                return None;
+            }
+            // Ctrl+D: end of input
+            0x04 if line.is_empty() => break,
+            0x08 | 0x7f => {
+                if line.pop().is_some() {
+                    uart::write_str("\x08 \x08");
+                }
+            }
+            byte if byte >= 0x20 => {
+                line.push(byte);
+                uart::write_bytes(&[byte]);
+            }
+            _ => {}
+        }
+    }
+    Some(line)
+}
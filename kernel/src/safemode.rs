@@ -0,0 +1,143 @@
+//! Safe mode after a crashed boot
+//!
+//! A small boot record lives in the otherwise unused tail of the SFS
+//! superblock (sector 0, from byte 256; mkfs leaves it zeroed). Every boot
+//! sets its "in progress" flag before starting services and clears it once
+//! the boot completes. Finding the flag still set means the previous boot
+//! never got that far, so this one comes up in safe mode: no network, no
+//! services or init scripts, and a filesystem check before the shell starts.
+//!
+//! A safe-mode boot that completes also clears the flag, so the next boot
+//! tries a full boot again.
+//!
+//! ## Record layout
+//!
+//! | Offset | Field                                               |
+//! |--------|-----------------------------------------------------|
+//! | 0x00   | Magic `"BREC"`                                      |
+//! | 0x04   | Flags (bit 0: boot in progress)                     |
+//! | 0x08   | Boots started since the image was created           |
+//! | 0x0c   | Consecutive boots that did not complete a full boot |
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::klog::{klog_critical, klog_info};
+use crate::{BLK_DEV, FS_STATE};
+
+/// Sector holding the boot record (the SFS superblock)
+const RECORD_SECTOR: u64 = 0;
+/// Byte offset of the record within that sector
+const RECORD_OFFSET: usize = 256;
+const RECORD_MAGIC: &[u8; 4] = b"BREC";
+
+const FLAG_IN_PROGRESS: u32 = 1 << 0;
+
+/// Set when this boot runs in safe mode
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Failed boots recorded before this one
+static FAILED_BOOTS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Default)]
+struct BootRecord {
+    flags: u32,
+    boots: u32,
+    failed: u32,
+}
+
+impl BootRecord {
+    fn decode(sector: &[u8; 512]) -> Option<Self> {
+        let rec = &sector[RECORD_OFFSET..RECORD_OFFSET + 16];
+        if &rec[0..4] != RECORD_MAGIC {
+            return None;
+        }
+        let word = |i: usize| u32::from_le_bytes(rec[i..i + 4].try_into().unwrap());
+        Some(Self {
+            flags: word(4),
+            boots: word(8),
+            failed: word(12),
+        })
+    }
+
+    fn encode(&self, sector: &mut [u8; 512]) {
+        let rec = &mut sector[RECORD_OFFSET..RECORD_OFFSET + 16];
+        rec[0..4].copy_from_slice(RECORD_MAGIC);
+        rec[4..8].copy_from_slice(&self.flags.to_le_bytes());
+        rec[8..12].copy_from_slice(&self.boots.to_le_bytes());
+        rec[12..16].copy_from_slice(&self.failed.to_le_bytes());
+    }
+}
+
+/// Read-modify-write the boot record. Only touches disks carrying a
+/// mounted SFS, so foreign images are never written.
+fn update(f: impl FnOnce(Option<BootRecord>) -> BootRecord) -> Option<BootRecord> {
+    if FS_STATE.lock().is_none() {
+        return None;
+    }
+    let mut guard = BLK_DEV.lock();
+    let dev = guard.as_mut()?;
+    let mut sector = [0u8; 512];
+    dev.read_sector(RECORD_SECTOR, &mut sector).ok()?;
+    let old = BootRecord::decode(&sector);
+    f(old).encode(&mut sector);
+    dev.write_sector(RECORD_SECTOR, &sector).ok()?;
+    old
+}
+
+/// Mark a boot as started. Returns true if the previous boot crashed and
+/// this one should come up in safe mode.
+pub fn begin_boot() -> bool {
+    let previous = update(|old| {
+        let old = old.unwrap_or_default();
+        let crashed = old.flags & FLAG_IN_PROGRESS != 0;
+        BootRecord {
+            flags: FLAG_IN_PROGRESS,
+            boots: old.boots.wrapping_add(1),
+            failed: if crashed {
+                old.failed.saturating_add(1)
+            } else {
+                old.failed
+            },
+        }
+    });
+
+    let Some(previous) = previous else {
+        return false;
+    };
+    if previous.flags & FLAG_IN_PROGRESS == 0 {
+        return false;
+    }
+    FAILED_BOOTS.store(previous.failed.saturating_add(1), Ordering::Relaxed);
+    ACTIVE.store(true, Ordering::Release);
+    klog_critical(
+        "boot",
+        "Previous boot did not complete; starting in safe mode",
+    );
+    true
+}
+
+/// Mark the boot as complete. A full boot also resets the failure count.
+pub fn boot_complete() {
+    let safe = is_active();
+    update(|old| {
+        let old = old.unwrap_or_default();
+        BootRecord {
+            flags: old.flags & !FLAG_IN_PROGRESS,
+            boots: old.boots,
+            failed: if safe { old.failed } else { 0 },
+        }
+    });
+    if !safe {
+        klog_info("boot", "Boot complete");
+    }
+}
+
+/// Whether this boot runs in safe mode.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Consecutive boots that failed to complete, counted when safe mode was
+/// entered.
+pub fn failed_boots() -> u32 {
+    FAILED_BOOTS.load(Ordering::Relaxed)
+}
@@ -4,18 +4,176 @@
 //! available harts. Features:
 //! - Per-hart run queues
 //! - Priority-based scheduling
+//! - Preemption from the machine timer (see [`start_hart`])
+//! - Sleeping on the timer wait queue (see [`sleep_ms`])
 //! - Work stealing (idle harts can take work from busy ones)
 //! - Hart affinity support
+//!
+//! Every task has its own stack. A hart runs tasks from its scheduler loop
+//! (the "kernel context"): secondary harts from their idle loop, hart 0
+//! from the timer interrupt, taking turns with the shell. When a time slice
+//! ends, a task keeps its hart unless another runnable task of at least its
+//! priority is queued there; on hart 0 it always goes back to the shell.
+//! A hart holding a [`Spinlock`] is never switched.
 
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::arch::asm;
 use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
 
-use crate::task::{Pid, Priority, Task, TaskEntry, TaskInfo, TaskState};
+use crate::context::{self, Context};
+use crate::lock::preemptible;
+use crate::task::{self, Pid, Priority, Task, TaskEntry, TaskInfo, TaskState};
 use crate::Spinlock;
 use crate::MAX_HARTS;
 
+/// Length of a time slice
+const TIME_SLICE_MS: u64 = 10;
+
+/// mtime ticks per millisecond
+const TICKS_PER_MS: u64 = 10_000;
+
+/// CLINT mtimecmp registers, one per hart
+const CLINT_MTIMECMP_BASE: usize = 0x0200_4000;
+
+/// mie.MTIE: machine timer interrupt enable
+const MIE_MTIE: usize = 1 << 7;
+
+/// mstatus.MIE: global machine interrupt enable
+const MSTATUS_MIE: usize = 1 << 3;
+
+/// Each hart's scheduler loop, saved while the hart runs a task
+static mut KERNEL_CONTEXT: [Context; MAX_HARTS] = [Context::EMPTY; MAX_HARTS];
+
+fn kernel_context(hart: usize) -> *mut Context {
+    unsafe { (core::ptr::addr_of_mut!(KERNEL_CONTEXT) as *mut Context).add(hart) }
+}
+
+/// Disable interrupts, returning whether they were enabled
+fn interrupts_off() -> bool {
+    let mstatus: usize;
+    unsafe {
+        asm!("csrrci {}, mstatus, 8", out(reg) mstatus, options(nostack));
+    }
+    mstatus & MSTATUS_MIE != 0
+}
+
+fn interrupts_restore(enabled: bool) {
+    if enabled {
+        unsafe {
+            asm!("csrsi mstatus, 8", options(nostack));
+        }
+    }
+}
+
+/// Request the next timer interrupt one time slice from now
+fn arm_timer(hart: usize) {
+    unsafe {
+        let now = core::ptr::read_volatile(crate::CLINT_MTIME as *const u64);
+        let mtimecmp = (CLINT_MTIMECMP_BASE + 8 * hart) as *mut u64;
+        core::ptr::write_volatile(mtimecmp, now + TIME_SLICE_MS * TICKS_PER_MS);
+    }
+}
+
+/// Start taking timer interrupts on this hart, so that the tasks it runs
+/// are preempted
+pub fn start_hart(hart: usize) {
+    arm_timer(hart);
+    unsafe {
+        asm!("csrs mie, {}", in(reg) MIE_MTIE, options(nomem, nostack));
+        asm!("csrsi mstatus, 8", options(nostack));
+    }
+}
+
+/// Machine timer interrupt: end of a time slice
+#[export_name = "MachineTimer"]
+extern "C" fn machine_timer() {
+    let hart = crate::get_hart_id();
+    arm_timer(hart);
+    if !SCHEDULER.is_running() || !preemptible(hart) {
+        return;
+    }
+    if hart == 0 {
+        task::check_all_timeouts(crate::get_time_ms() as u64);
+    }
+
+    // Another trap can happen before this one returns (in the task switched
+    // to), so keep what `mret` needs
+    let (mepc, mstatus): (usize, usize);
+    unsafe {
+        asm!("csrr {}, mepc", out(reg) mepc, options(nomem, nostack));
+        asm!("csrr {}, mstatus", out(reg) mstatus, options(nomem, nostack));
+    }
+    SCHEDULER.preempt(hart);
+    unsafe {
+        asm!("csrw mepc, {}", in(reg) mepc, options(nomem, nostack));
+        asm!("csrw mstatus, {}", in(reg) mstatus, options(nomem, nostack));
+    }
+}
+
+/// First code run by every task: runs its entry point, then leaves the
+/// task for the scheduler to drop
+pub extern "C" fn task_start() -> ! {
+    // No Arc is held here, since this stack is never unwound
+    let (pid, entry) = {
+        let queue = SCHEDULER.queues[crate::get_hart_id()].lock();
+        let task = queue
+            .current
+            .as_ref()
+            .expect("task started outside the scheduler");
+        (task.pid, task.entry)
+    };
+    interrupts_restore(true);
+
+    entry();
+
+    SCHEDULER.finish_task(pid, 0);
+    interrupts_off();
+    let hart = crate::get_hart_id();
+    let current = SCHEDULER.queues[hart]
+        .lock()
+        .current
+        .as_ref()
+        .map(|t| t.context());
+    if let Some(current) = current {
+        unsafe { context::switch(current, kernel_context(hart)) };
+    }
+    unreachable!("finished task resumed");
+}
+
+/// Give up the rest of the time slice. Does nothing outside a task.
+pub fn yield_now() {
+    let enabled = interrupts_off();
+    SCHEDULER.switch_to_kernel(crate::get_hart_id());
+    interrupts_restore(enabled);
+}
+
+/// Sleep for `ms` milliseconds, letting other tasks run. Outside a task
+/// this busy-waits.
+pub fn sleep_ms(ms: u64) {
+    let hart = crate::get_hart_id();
+    let current = SCHEDULER.queues[hart].lock().current.clone();
+    match current {
+        Some(task) if preemptible(hart) => {
+            // Interrupts stay off until the task is switched out, so the
+            // wake-up can't be missed
+            let enabled = interrupts_off();
+            task.set_state(TaskState::Sleeping);
+            task::wait_timer(task.pid, ms);
+            drop(task);
+            SCHEDULER.switch_to_kernel(hart);
+            interrupts_restore(enabled);
+        }
+        _ => {
+            let deadline = crate::get_time_ms() + ms as i64;
+            while crate::get_time_ms() < deadline {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
 /// Per-hart run queue
 pub struct RunQueue {
     /// Tasks waiting to run (priority sorted)
@@ -48,6 +206,9 @@ impl RunQueue {
 
     /// Get next task to run
     pub fn dequeue(&mut self) -> Option<Arc<Task>> {
+        // Killed tasks are dropped once they're switched out
+        self.tasks.retain(|t| t.get_state() != TaskState::Zombie);
+
         // Find first runnable task
         for i in 0..self.tasks.len() {
             if self.tasks[i].is_runnable() {
@@ -67,11 +228,22 @@ impl RunQueue {
         self.tasks.is_empty()
     }
 
+    /// Whether a task of at least `priority` is waiting to run
+    fn has_runnable(&self, priority: Priority) -> bool {
+        self.tasks
+            .iter()
+            .any(|t| t.priority >= priority && t.is_runnable())
+    }
+
     /// Steal a task from this queue (for work stealing)
     pub fn steal(&mut self) -> Option<Arc<Task>> {
-        // Steal lowest priority task from back of queue
+        // Steal lowest priority task from back of queue, leaving pinned ones
         if self.tasks.len() > 1 {
-            self.tasks.pop_back()
+            let i = self
+                .tasks
+                .iter()
+                .rposition(|t| t.hart_affinity.is_none() && t.is_runnable())?;
+            self.tasks.remove(i)
         } else {
             None
         }
@@ -113,6 +285,7 @@ pub static SCHEDULER: Scheduler = Scheduler {
 impl Scheduler {
     /// Initialize the scheduler with the number of available harts
     pub fn init(&self, num_harts: usize) {
+        task::init_wait_queues();
         self.num_harts.store(num_harts, Ordering::Release);
         self.running.store(true, Ordering::Release);
         fence(Ordering::SeqCst);
@@ -257,6 +430,78 @@ impl Scheduler {
         None
     }
 
+    /// Run tasks on `hart` until none is runnable. Called from the idle loop
+    /// of secondary harts.
+    pub fn schedule(&self, hart_id: usize) {
+        while let Some(task) = self.pick_next(hart_id) {
+            self.run_task(hart_id, task);
+        }
+    }
+
+    /// Switch to `task` until its time slice ends, it yields or it exits
+    fn run_task(&self, hart_id: usize, task: Arc<Task>) {
+        let enabled = interrupts_off();
+        task.mark_running(hart_id);
+        let context = task.context();
+        self.queues[hart_id].lock().current = Some(task);
+        let start_time = crate::get_time_ms() as u64;
+
+        unsafe { context::switch(kernel_context(hart_id), context) };
+
+        let task = self.queues[hart_id].lock().current.take();
+        if let Some(task) = task {
+            let elapsed = (crate::get_time_ms() as u64).saturating_sub(start_time);
+            task.add_cpu_time(elapsed);
+            task.current_hart.store(usize::MAX, Ordering::Release);
+            match task.get_state() {
+                TaskState::Running => self.requeue(task, hart_id),
+                TaskState::Zombie => {}
+                // Sleeping or stopped: queued until woken or resumed
+                _ => self.queues[hart_id].lock().enqueue(task),
+            }
+        }
+        interrupts_restore(enabled);
+    }
+
+    /// Switch from the task running on `hart_id` back to the hart's
+    /// scheduler loop. Returns at once outside a task or while the hart
+    /// holds a lock. Interrupts must be disabled.
+    fn switch_to_kernel(&self, hart_id: usize) {
+        if !preemptible(hart_id) {
+            return;
+        }
+        let current = self.queues[hart_id]
+            .lock()
+            .current
+            .as_ref()
+            .map(|t| t.context());
+        if let Some(current) = current {
+            unsafe { context::switch(current, kernel_context(hart_id)) };
+        }
+    }
+
+    /// End of a time slice on `hart_id`, from the timer interrupt
+    fn preempt(&self, hart_id: usize) {
+        let queue = self.queues[hart_id].lock();
+        let Some(task) = queue.current.as_ref() else {
+            // Hart 0 runs its tasks between slices of the shell
+            drop(queue);
+            if hart_id == 0 {
+                if let Some(task) = self.pick_next(hart_id) {
+                    self.run_task(hart_id, task);
+                }
+            }
+            return;
+        };
+        let switch = hart_id == 0
+            || task.get_state() != TaskState::Running
+            || queue.has_runnable(task.priority);
+        drop(queue);
+        if switch {
+            self.switch_to_kernel(hart_id);
+        }
+    }
+
     /// Requeue a task (e.g., after time slice expires)
     pub fn requeue(&self, task: Arc<Task>, hart_id: usize) {
         task.set_state(TaskState::Ready);
@@ -314,6 +559,16 @@ impl Scheduler {
         self.tasks.lock().get(&pid).cloned()
     }
 
+    /// PID of the task running on `hart_id`, or 0 when the hart is running
+    /// kernel code outside any task (e.g. the shell on hart 0)
+    pub fn current_pid(&self, hart_id: usize) -> Pid {
+        self.queues[hart_id]
+            .lock()
+            .current
+            .as_ref()
+            .map_or(0, |t| t.pid)
+    }
+
     /// List all tasks
     pub fn list_tasks(&self) -> Vec<TaskInfo> {
         let current_time = crate::get_time_ms() as u64;
@@ -0,0 +1,305 @@
+//! First-boot setup wizard
+//!
+//! On a fresh image (no /etc/provision.json) the wizard runs over UART before
+//! the shell starts and asks for:
+//! - Hostname (shown in the shell prompt, written to /etc/hostname)
+//! - A user account (written to /etc/passwd)
+//! - Whether networking is enabled
+//! - Output theme (fancy or plain, see `setopt`)
+//!
+//! The answers are stored in /etc/provision.json, which also marks setup as
+//! complete. Later boots load that file before the network comes up.
+//!
+//! Images built by mkfs ship a default provision.json, so the wizard only
+//! runs at boot on images without one; `setup` runs it on demand. If no key
+//! is pressed for [`IDLE_TIMEOUT_MS`], the remaining questions take their
+//! defaults so an unattended boot still reaches the shell.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use crate::{uart, Spinlock};
+
+/// Provisioning file; its presence means setup has been completed
+pub const PROVISION_PATH: &str = "/etc/provision.json";
+
+const HOSTNAME_PATH: &str = "/etc/hostname";
+const PASSWD_PATH: &str = "/etc/passwd";
+
+const DEFAULT_HOSTNAME: &str = "Bavy";
+const DEFAULT_USER: &str = "guest";
+
+/// Longest accepted hostname or user name
+const MAX_NAME_LEN: usize = 32;
+
+/// How long a question waits for a key before the defaults are taken
+pub const IDLE_TIMEOUT_MS: i64 = 30_000;
+
+/// Settings chosen during setup
+#[derive(Clone)]
+pub struct Provision {
+    pub hostname: String,
+    pub user: String,
+    pub network: bool,
+    pub plain: bool,
+}
+
+impl Provision {
+    const fn defaults() -> Self {
+        Self {
+            hostname: String::new(),
+            user: String::new(),
+            network: true,
+            plain: false,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\n  \"hostname\": \"{}\",\n  \"user\": \"{}\",\n  \"network\": {},\n  \"theme\": \"{}\",\n  \"complete\": true\n}}\n",
+            self.hostname,
+            self.user,
+            self.network,
+            if self.plain { "plain" } else { "fancy" }
+        )
+    }
+
+    fn from_json(text: &str) -> Self {
+        Self {
+            hostname: json_field(text, "hostname")
+                .filter(|v| valid_name(v))
+                .unwrap_or(DEFAULT_HOSTNAME)
+                .to_string(),
+            user: json_field(text, "user")
+                .filter(|v| valid_name(v))
+                .unwrap_or(DEFAULT_USER)
+                .to_string(),
+            network: json_field(text, "network") != Some("false"),
+            plain: json_field(text, "theme") == Some("plain"),
+        }
+    }
+}
+
+/// Active provisioning settings (defaults until loaded or set up)
+static PROVISION: Spinlock<Provision> = Spinlock::new(Provision::defaults());
+
+/// Look up a top-level string or scalar value in a flat JSON object.
+fn json_field<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("\"{}\"", key);
+    let start = text.find(&pattern)? + pattern.len();
+    let rest = text[start..].trim_start().strip_prefix(':')?.trim_start();
+    if let Some(quoted) = rest.strip_prefix('"') {
+        quoted.find('"').map(|end| &quoted[..end])
+    } else {
+        let end = rest
+            .find(|c: char| c == ',' || c == '}' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        Some(&rest[..end])
+    }
+}
+
+/// Hostnames and user names: letters, digits, '-', '_' and '.'.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Load /etc/provision.json if present and apply the saved theme.
+///
+/// Returns true when the system has already been set up.
+pub fn load() -> bool {
+    let content = {
+        let fs_guard = crate::FS_STATE.lock();
+        let mut blk_guard = crate::BLK_DEV.lock();
+        match (fs_guard.as_ref(), blk_guard.as_mut()) {
+            (Some(fs), Some(dev)) => fs.read_file(dev, PROVISION_PATH),
+            _ => None,
+        }
+    };
+
+    let Some(bytes) = content else {
+        return false;
+    };
+    let provision = Provision::from_json(core::str::from_utf8(&bytes).unwrap_or(""));
+    uart::set_plain(provision.plain);
+    *PROVISION.lock() = provision;
+    true
+}
+
+/// Hostname shown in the shell prompt
+pub fn hostname() -> String {
+    let provision = PROVISION.lock();
+    if provision.hostname.is_empty() {
+        String::from(DEFAULT_HOSTNAME)
+    } else {
+        provision.hostname.clone()
+    }
+}
+
+/// Whether networking should be brought up
+pub fn network_enabled() -> bool {
+    PROVISION.lock().network
+}
+
+/// Read one line from the UART with echo and backspace handling.
+///
+/// Gives up with an empty line, and sets `idle`, after [`IDLE_TIMEOUT_MS`]
+/// without a key; once `idle` is set it returns at once.
+fn read_line(max: usize, idle: &mut bool) -> String {
+    let console = uart::Console::new();
+    let mut line = String::new();
+    let mut last_key = crate::get_time_ms();
+    while !*idle {
+        let byte = console.read_byte();
+        if byte == 0 {
+            if crate::get_time_ms() - last_key >= IDLE_TIMEOUT_MS {
+                uart::write_line("");
+                *idle = true;
+            } else {
+                core::hint::spin_loop();
+            }
+            continue;
+        }
+        last_key = crate::get_time_ms();
+        match byte {
+            b'\r' | b'\n' => {
+                uart::write_line("");
+                return line;
+            }
+            8 | 0x7f => {
+                if line.pop().is_some() {
+                    uart::write_str("\x08 \x08");
+                }
+            }
+            b if (0x20..0x7f).contains(&b) && line.len() < max => {
+                line.push(b as char);
+                uart::write_bytes(&[b]);
+            }
+            _ => {}
+        }
+    }
+    String::new()
+}
+
+/// Prompt for a name, falling back to `default` on an empty answer.
+fn ask_name(question: &str, default: &str, idle: &mut bool) -> String {
+    loop {
+        uart::write_str(&format!(
+            "    {} \x1b[0;90m[{}]\x1b[0m: ",
+            question, default
+        ));
+        let answer = read_line(MAX_NAME_LEN, idle);
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return default.to_string();
+        }
+        if valid_name(answer) {
+            return answer.to_string();
+        }
+        uart::write_line("    \x1b[1;31m✗\x1b[0m Use letters, digits, '-', '_' or '.'");
+    }
+}
+
+/// Prompt for one of two choices; returns true for `first`.
+fn ask_choice(
+    question: &str,
+    first: &str,
+    second: &str,
+    default_first: bool,
+    idle: &mut bool,
+) -> bool {
+    let default = if default_first { first } else { second };
+    loop {
+        uart::write_str(&format!(
+            "    {} ({}/{}) \x1b[0;90m[{}]\x1b[0m: ",
+            question, first, second, default
+        ));
+        let answer = read_line(MAX_NAME_LEN, idle);
+        match answer.trim() {
+            "" => return default_first,
+            a if a.eq_ignore_ascii_case(first) => return true,
+            a if a.eq_ignore_ascii_case(second) => return false,
+            _ => uart::write_line(&format!(
+                "    \x1b[1;31m✗\x1b[0m Answer {} or {}",
+                first, second
+            )),
+        }
+    }
+}
+
+/// Write the chosen settings to /etc and mark setup complete.
+fn save(provision: &Provision) -> Result<(), &'static str> {
+    let mut fs_guard = crate::FS_STATE.lock();
+    let mut blk_guard = crate::BLK_DEV.lock();
+    let (Some(fs), Some(dev)) = (fs_guard.as_mut(), blk_guard.as_mut()) else {
+        return Err("no filesystem");
+    };
+
+    if !fs.exists(dev, "/etc") {
+        fs.mkdir(dev, "/etc")?;
+    }
+    fs.write_file(
+        dev,
+        HOSTNAME_PATH,
+        format!("{}\n", provision.hostname).as_bytes(),
+    )?;
+    let passwd = format!(
+        "root:x:0:0:root:/:/bin/sh\n{}:x:1000:1000:{}:/home/{}:/bin/sh\n",
+        provision.user, provision.user, provision.user
+    );
+    fs.write_file(dev, PASSWD_PATH, passwd.as_bytes())?;
+    // Written last so an interrupted setup runs again on the next boot
+    fs.write_file(dev, PROVISION_PATH, provision.to_json().as_bytes())?;
+    fs.sync(dev)?;
+    Ok(())
+}
+
+/// Run the interactive wizard and persist the answers.
+///
+/// Without a filesystem nothing could be saved, so the wizard is skipped.
+pub fn run_wizard() {
+    if crate::FS_STATE.lock().is_none() {
+        return;
+    }
+
+    uart::write_line("");
+    uart::write_line("    \x1b[1;97mFirst boot setup\x1b[0m");
+    uart::write_line("    \x1b[0;90mPress Enter to accept the default shown in brackets.\x1b[0m");
+    uart::write_line("");
+
+    let mut idle = false;
+    let provision = Provision {
+        hostname: ask_name("Hostname", DEFAULT_HOSTNAME, &mut idle),
+        user: ask_name("Create user", DEFAULT_USER, &mut idle),
+        network: ask_choice("Enable networking", "yes", "no", true, &mut idle),
+        plain: !ask_choice("Theme", "fancy", "plain", true, &mut idle),
+    };
+    if idle {
+        uart::write_line("    \x1b[0;90mNo answer, using the defaults.\x1b[0m");
+    }
+
+    uart::set_plain(provision.plain);
+    if !provision.network {
+        *crate::NET_STATE.lock() = None;
+    }
+
+    match save(&provision) {
+        Ok(()) => {
+            uart::write_line(&format!(
+                "    \x1b[1;32m✓\x1b[0m Setup complete, saved to {}",
+                PROVISION_PATH
+            ));
+        }
+        Err(e) => {
+            uart::write_line(&format!(
+                "    \x1b[1;31m✗\x1b[0m Could not save setup: {}",
+                e
+            ));
+        }
+    }
+    uart::write_line("");
+    *PROVISION.lock() = provision;
+}
@@ -0,0 +1,167 @@
+//! SNTP client
+//!
+//! Sets the wall clock (see `clock`) from a time server over UDP, using the
+//! simple client mode of RFC 4330. The `ntpd` service syncs once the
+//! network is up and then every hour; `date -n` syncs on demand.
+//!
+//! The server is the first `server <host>` line of `/etc/ntp.conf`, or
+//! `pool.ntp.org` without one.
+
+use alloc::format;
+use alloc::string::String;
+use smoltcp::wire::Ipv4Address;
+
+use crate::klog::{klog_info, klog_warning};
+use crate::{BLK_DEV, FS_STATE, NET_STATE};
+
+/// Service name, as used with `service ntpd start`
+pub const SERVICE: &str = "ntpd";
+
+/// File the server is configured in
+pub const CONFIG: &str = "/etc/ntp.conf";
+
+/// Server used when none is configured
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+
+const NTP_PORT: u16 = 123;
+
+/// Seconds from 1900 (NTP's epoch) to 1970
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// How long to wait for a reply
+const TIMEOUT_MS: i64 = 3000;
+
+/// Pause between syncs once the clock is set
+const SYNC_INTERVAL_MS: u64 = 3_600_000;
+
+/// First pause after a failed attempt, doubled after each further failure
+const RETRY_INTERVAL_MS: u64 = 60_000;
+
+/// Server named in the config file, or the default
+pub fn configured_server() -> String {
+    let text = {
+        let mut fs_guard = FS_STATE.lock();
+        let mut blk_guard = BLK_DEV.lock();
+        match (fs_guard.as_mut(), blk_guard.as_mut()) {
+            (Some(fs), Some(dev)) => fs.read_file(dev, CONFIG),
+            _ => None,
+        }
+    };
+    text.and_then(|data| {
+        String::from_utf8_lossy(&data).lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("server"), Some(host)) => Some(String::from(host)),
+                _ => None,
+            }
+        })
+    })
+    .unwrap_or_else(|| String::from(DEFAULT_SERVER))
+}
+
+/// NTP timestamp (seconds since 1900, 32.32 fixed point) in Unix milliseconds
+fn ntp_to_unix_ms(bytes: &[u8]) -> i64 {
+    let secs = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64;
+    let frac = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as u64;
+    ((secs.wrapping_sub(NTP_UNIX_OFFSET)) * 1000 + (frac * 1000 >> 32)) as i64
+}
+
+/// Ask `server` for the time and set the clock, returning how far it moved
+/// in milliseconds
+pub fn sync(server: &str) -> Result<i64, &'static str> {
+    let mut net_guard = NET_STATE.lock();
+    let net = net_guard.as_mut().ok_or("Network not initialized")?;
+    let ip = match crate::net::parse_ipv4(server.as_bytes()) {
+        Some(ip) => ip,
+        None => crate::dns::resolve(
+            net,
+            server.as_bytes(),
+            crate::net::get_dns_server(),
+            5000,
+            crate::get_time_ms,
+        )
+        .ok_or("Cannot resolve server")?,
+    };
+    query(net, ip)
+}
+
+fn query(net: &mut crate::net::NetState, server: Ipv4Address) -> Result<i64, &'static str> {
+    // LI 0, version 4, mode 3 (client). The transmit timestamp only has to
+    // be unique: the server echoes it back as the originate timestamp.
+    let sent_at = crate::get_time_ms();
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    request[40..48].copy_from_slice(&(sent_at as u64).to_be_bytes());
+    net.udp_send(server, NTP_PORT, &request, sent_at)?;
+
+    let mut buf = [0u8; 128];
+    loop {
+        let now = crate::get_time_ms();
+        if now - sent_at > TIMEOUT_MS {
+            return Err("No reply from server");
+        }
+        crate::jobs::checkpoint()?;
+        net.poll(now);
+
+        let Some((src, port, len)) = net.udp_recv(&mut buf, now) else {
+            core::hint::spin_loop();
+            continue;
+        };
+        let reply = &buf[..len];
+        if src != server || port != NTP_PORT || len < 48 || reply[24..32] != request[40..48] {
+            continue;
+        }
+        if reply[0] & 0x07 != 4 || reply[1] == 0 || reply[0] >> 6 == 3 {
+            return Err("Server is not synchronized");
+        }
+
+        // Server receive and transmit times; half of the remaining round
+        // trip is the delay of the reply
+        let received = ntp_to_unix_ms(&reply[32..40]);
+        let transmitted = ntp_to_unix_ms(&reply[40..48]);
+        let round_trip = (now - sent_at) - (transmitted - received);
+        return Ok(crate::clock::set(transmitted + round_trip.max(0) / 2, now));
+    }
+}
+
+/// Sync with the configured server, logging the result
+pub fn tick() -> bool {
+    if NET_STATE.lock().is_none() {
+        return false;
+    }
+    let server = configured_server();
+    match sync(&server) {
+        Ok(adjust) => {
+            klog_info(
+                SERVICE,
+                &format!(
+                    "Clock set from {} ({:+} ms): {}",
+                    server,
+                    adjust,
+                    crate::clock::format_date(crate::clock::now_secs())
+                ),
+            );
+            true
+        }
+        Err(e) => {
+            klog_warning(SERVICE, &format!("{}: {}", server, e));
+            false
+        }
+    }
+}
+
+/// Entry point registered with init
+pub fn ntpd_service() {
+    let mut retry = RETRY_INTERVAL_MS;
+    loop {
+        let interval = if tick() {
+            retry = RETRY_INTERVAL_MS;
+            SYNC_INTERVAL_MS
+        } else {
+            let interval = retry;
+            retry = (retry * 2).min(SYNC_INTERVAL_MS);
+            interval
+        };
+        crate::scheduler::sleep_ms(interval);
+    }
+}
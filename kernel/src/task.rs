@@ -5,14 +5,21 @@
 //! - Task states (Ready, Running, Sleeping, Zombie)
 //! - Priority levels for scheduling
 //! - CPU time tracking
+//! - Own stack and saved registers, so tasks can be switched out mid-run
 //! - WaitQueues for event-based blocking
 
+use crate::context::Context;
 use crate::Spinlock;
 use alloc::collections::VecDeque;
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
+/// Stack size of each task, the same as a hart's boot stack
+pub const TASK_STACK_SIZE: usize = 128 * 1024;
+
 /// Process identifier type
 pub type Pid = u32;
 
@@ -112,11 +119,21 @@ pub struct Task {
     pub is_daemon: bool,
     /// Whether task should restart on exit
     pub restart_on_exit: bool,
+    /// Stack the task runs on
+    _stack: Vec<u8>,
+    /// Registers saved while the task is switched out, only touched by the
+    /// scheduler on the hart that runs it
+    context: UnsafeCell<Context>,
 }
 
+// `context` is only accessed by the hart the task is switched on
+unsafe impl Sync for Task {}
+
 impl Task {
     /// Create a new task
     pub fn new(pid: Pid, name: &str, entry: TaskEntry, priority: Priority) -> Self {
+        let stack = vec![0u8; TASK_STACK_SIZE];
+        let stack_top = stack.as_ptr() as usize + TASK_STACK_SIZE;
         Self {
             pid,
             name: String::from(name),
@@ -130,6 +147,8 @@ impl Task {
             exit_code: AtomicUsize::new(0),
             is_daemon: false,
             restart_on_exit: false,
+            _stack: stack,
+            context: UnsafeCell::new(Context::new(crate::scheduler::task_start, stack_top)),
         }
     }
 
@@ -179,6 +198,11 @@ impl Task {
         self.cpu_time.load(Ordering::Relaxed)
     }
 
+    /// Saved registers, for switching to and from the task
+    pub fn context(&self) -> *mut Context {
+        self.context.get()
+    }
+
     /// Get current hart (if running)
     pub fn get_current_hart(&self) -> Option<usize> {
         let hart = self.current_hart.load(Ordering::Acquire);
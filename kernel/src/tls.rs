@@ -126,6 +126,8 @@ pub enum TlsError {
     DnsError,
     /// Internal error
     InternalError,
+    /// Cancelled with Ctrl+C, or the job was killed
+    Interrupted,
 }
 
 impl core::fmt::Display for TlsError {
@@ -140,6 +142,7 @@ impl core::fmt::Display for TlsError {
             TlsError::NotConnected => write!(f, "Not connected"),
             TlsError::DnsError => write!(f, "DNS error"),
             TlsError::InternalError => write!(f, "Internal error"),
+            TlsError::Interrupted => write!(f, "Interrupted"),
         }
     }
 }
@@ -149,6 +152,7 @@ impl embedded_io::Error for TlsError {
         match self {
             TlsError::ConnectionClosed => embedded_io::ErrorKind::ConnectionReset,
             TlsError::Timeout => embedded_io::ErrorKind::TimedOut,
+            TlsError::Interrupted => embedded_io::ErrorKind::Interrupted,
             _ => embedded_io::ErrorKind::Other,
         }
     }
@@ -159,6 +163,7 @@ impl From<EmbeddedTlsError> for TlsError {
         match e {
             EmbeddedTlsError::ConnectionClosed => TlsError::ConnectionClosed,
             EmbeddedTlsError::IoError => TlsError::Io,
+            EmbeddedTlsError::Io(embedded_io::ErrorKind::Interrupted) => TlsError::Interrupted,
             EmbeddedTlsError::Io(_) => TlsError::Io,
             _ => TlsError::TlsProtocolError,
         }
@@ -231,6 +236,10 @@ impl<'a> BlockingTcpSocket<'a> {
                 self.net.tcp_abort();
                 return Err(TlsError::Timeout);
             }
+            if crate::jobs::checkpoint().is_err() {
+                self.net.tcp_abort();
+                return Err(TlsError::Interrupted);
+            }
 
             self.poll_network();
 
@@ -277,6 +286,9 @@ impl Read for BlockingTcpSocket<'_> {
                 crate::uart::write_line(self.net.tcp_state());
                 return Err(TlsError::Timeout);
             }
+            if crate::jobs::checkpoint().is_err() {
+                return Err(TlsError::Interrupted);
+            }
 
             self.poll_network();
             poll_count += 1;
@@ -638,13 +650,13 @@ pub fn https_get(
     let request = alloc::format!(
         "GET {} HTTP/1.1\r\n\
          Host: {}\r\n\
-         User-Agent: BAVY OS/{}\r\n\
+         User-Agent: {}\r\n\
          Accept: */*\r\n\
          Connection: close\r\n\
          \r\n",
         path,
         hostname,
-        env!("CARGO_PKG_VERSION")
+        crate::ident::user_agent()
     );
 
     https_request(
@@ -678,7 +690,7 @@ pub fn https_get_url(
     let ip = crate::dns::resolve(
         net,
         hostname.as_bytes(),
-        crate::net::DNS_SERVER,
+        crate::net::get_dns_server(),
         timeout_ms,
         get_time,
     )
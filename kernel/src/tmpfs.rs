@@ -0,0 +1,336 @@
+//! Compressed RAM filesystem mounted at /tmp
+//!
+//! Scratch files live in kernel heap instead of the SFS image, so they don't
+//! consume disk blocks or cause virtio-blk writes, and disappear on reboot.
+//! File contents are stored LZ4-compressed (block format, no frame), falling
+//! back to raw storage when compression doesn't help.
+//!
+//! `FileSystem` routes every path under /tmp here, so shell commands and WASM
+//! programs see a single namespace. Capacity is bounded by `MAX_STORED_BYTES`
+//! of compressed data and `MAX_FILES` entries.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fs::FileInfo;
+use crate::Spinlock;
+
+/// Mount point (directory entries use the SFS trailing-slash convention)
+pub const MOUNT_POINT: &str = "/tmp/";
+
+/// Maximum compressed bytes held by /tmp
+pub const MAX_STORED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Maximum number of files and directories in /tmp
+pub const MAX_FILES: usize = 256;
+
+/// A file (or directory marker) held in memory
+struct TmpFile {
+    /// Compressed contents, or raw contents if `compressed` is false
+    data: Vec<u8>,
+    /// Uncompressed size
+    size: usize,
+    compressed: bool,
+}
+
+/// Usage statistics for /tmp
+#[derive(Clone, Copy, Default)]
+pub struct TmpfsStats {
+    pub files: usize,
+    /// Sum of uncompressed file sizes
+    pub logical_bytes: usize,
+    /// Bytes actually held in memory
+    pub stored_bytes: usize,
+    pub max_bytes: usize,
+}
+
+struct TmpFs {
+    files: BTreeMap<String, TmpFile>,
+    stored_bytes: usize,
+}
+
+static TMPFS: Spinlock<TmpFs> = Spinlock::new(TmpFs {
+    files: BTreeMap::new(),
+    stored_bytes: 0,
+});
+
+/// Whether `path` belongs to the /tmp mount
+pub fn owns(path: &str) -> bool {
+    path == "/tmp" || path.starts_with(MOUNT_POINT)
+}
+
+fn is_root(path: &str) -> bool {
+    path == "/tmp" || path == MOUNT_POINT
+}
+
+/// Read a file's contents
+pub fn read(path: &str) -> Option<Vec<u8>> {
+    let fs = TMPFS.lock();
+    let file = fs.files.get(path)?;
+    if file.compressed {
+        decompress(&file.data, file.size)
+    } else {
+        Some(file.data.clone())
+    }
+}
+
+/// Create or replace a file
+pub fn write(path: &str, data: &[u8]) -> Result<(), &'static str> {
+    if is_root(path) || path.ends_with('/') {
+        return Err("Is a directory");
+    }
+
+    let packed = compress(data);
+    let file = if packed.len() < data.len() {
+        TmpFile {
+            data: packed,
+            size: data.len(),
+            compressed: true,
+        }
+    } else {
+        TmpFile {
+            data: Vec::from(data),
+            size: data.len(),
+            compressed: false,
+        }
+    };
+
+    let mut fs = TMPFS.lock();
+    let old = fs.files.get(path).map(|f| f.data.len());
+    if old.is_none() && fs.files.len() >= MAX_FILES {
+        return Err("tmpfs: too many files");
+    }
+    let stored = fs.stored_bytes - old.unwrap_or(0) + file.data.len();
+    if stored > MAX_STORED_BYTES {
+        return Err("tmpfs: no space left");
+    }
+    fs.stored_bytes = stored;
+    fs.files.insert(String::from(path), file);
+    Ok(())
+}
+
+/// Create a directory
+pub fn mkdir(path: &str) -> Result<(), &'static str> {
+    let mut dir = String::from(path);
+    if !dir.ends_with('/') {
+        dir.push('/');
+    }
+    if is_root(&dir) || exists(&dir) {
+        return Err("Directory already exists");
+    }
+    let mut fs = TMPFS.lock();
+    if fs.files.len() >= MAX_FILES {
+        return Err("tmpfs: too many files");
+    }
+    fs.files.insert(
+        dir,
+        TmpFile {
+            data: Vec::new(),
+            size: 0,
+            compressed: false,
+        },
+    );
+    Ok(())
+}
+
+/// Remove a file or empty directory
+pub fn remove(path: &str) -> Result<(), &'static str> {
+    if is_root(path) {
+        return Err("Device or resource busy");
+    }
+    let mut fs = TMPFS.lock();
+    if path.ends_with('/') && fs.files.keys().any(|k| k != path && k.starts_with(path)) {
+        return Err("Directory not empty");
+    }
+    let file = fs.files.remove(path).ok_or("File not found")?;
+    fs.stored_bytes -= file.data.len();
+    Ok(())
+}
+
+/// Whether a file or directory exists
+pub fn exists(path: &str) -> bool {
+    is_root(path) || TMPFS.lock().files.contains_key(path)
+}
+
+/// Whether `path` is a directory
+pub fn is_dir(path: &str) -> bool {
+    if is_root(path) {
+        return true;
+    }
+    let mut dir = String::from(path);
+    if !dir.ends_with('/') {
+        dir.push('/');
+    }
+    TMPFS.lock().files.keys().any(|k| k.starts_with(&dir))
+}
+
+/// All entries, including the mount point itself
+pub fn list() -> Vec<FileInfo> {
+    let fs = TMPFS.lock();
+    let mut entries = Vec::with_capacity(fs.files.len() + 1);
+    entries.push(FileInfo {
+        name: String::from(MOUNT_POINT),
+        size: 0,
+        is_dir: false,
+    });
+    for (name, file) in fs.files.iter() {
+        entries.push(FileInfo {
+            name: name.clone(),
+            size: file.size as u32,
+            is_dir: false,
+        });
+    }
+    entries
+}
+
+/// Current usage statistics
+pub fn stats() -> TmpfsStats {
+    let fs = TMPFS.lock();
+    TmpfsStats {
+        files: fs.files.len(),
+        logical_bytes: fs.files.values().map(|f| f.size).sum(),
+        stored_bytes: fs.stored_bytes,
+        max_bytes: MAX_STORED_BYTES,
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// LZ4 BLOCK COMPRESSION
+// ═══════════════════════════════════════════════════════════════════════════════
+
+const MIN_MATCH: usize = 4;
+/// The last match must start at least this far from the end of input
+const MF_LIMIT: usize = 12;
+/// The block always ends with at least this many literals
+const LAST_LITERALS: usize = 5;
+const HASH_LOG: u32 = 12;
+const MAX_OFFSET: usize = 0xFFFF;
+
+#[inline]
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[inline]
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+/// Append an LZ4 length continuation (bytes of 255 then the remainder)
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Emit one sequence: literals followed by a match (or none for the last)
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let lit_len = literals.len();
+    let match_code = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((lit_len.min(15) as u8) << 4) | match_code.min(15) as u8);
+    if lit_len >= 15 {
+        push_length(out, lit_len - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code >= 15 {
+            push_length(out, match_code - 15);
+        }
+    }
+}
+
+/// Compress `input` into an LZ4 block
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    if input.len() > MF_LIMIT {
+        let limit = input.len() - MF_LIMIT;
+        while pos <= limit {
+            let seq = read_u32(input, pos);
+            let slot = hash(seq);
+            let candidate = table[slot];
+            table[slot] = pos;
+
+            if candidate != usize::MAX
+                && pos - candidate <= MAX_OFFSET
+                && read_u32(input, candidate) == seq
+            {
+                let max_len = input.len() - LAST_LITERALS - pos;
+                let mut len = MIN_MATCH;
+                while len < max_len && input[candidate + len] == input[pos + len] {
+                    len += 1;
+                }
+                push_sequence(&mut out, &input[anchor..pos], Some((pos - candidate, len)));
+                pos += len;
+                anchor = pos;
+            } else {
+                pos += 1;
+            }
+        }
+    }
+
+    push_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// Decompress an LZ4 block that expands to exactly `size` bytes
+pub fn decompress(input: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    let mut pos = 0;
+
+    let read_length = |pos: &mut usize, mut len: usize| -> Option<usize> {
+        loop {
+            let b = *input.get(*pos)?;
+            *pos += 1;
+            len += b as usize;
+            if b != 255 {
+                return Some(len);
+            }
+        }
+    };
+
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 15 {
+            lit_len = read_length(&mut pos, lit_len)?;
+        }
+        out.extend_from_slice(input.get(pos..pos + lit_len)?);
+        pos += lit_len;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+        let mut match_len = (token & 0x0F) as usize;
+        if match_len == 15 {
+            match_len = read_length(&mut pos, match_len)?;
+        }
+        match_len += MIN_MATCH;
+        if out.len() + match_len > size {
+            return None;
+        }
+        // Byte-wise copy: the match may overlap the bytes it produces
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            let b = out[start + i];
+            out.push(b);
+        }
+    }
+
+    (out.len() == size).then_some(out)
+}
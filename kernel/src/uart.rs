@@ -1,4 +1,7 @@
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Spinlock;
 
 const UART_BASE: usize = 0x1000_0000;
 
@@ -14,6 +17,142 @@ const LSR: usize = 0x05; // Line Status Register
 const LSR_RX_READY: u8 = 0x01; // Data ready
 const LSR_TX_IDLE: u8 = 0x20;  // THR empty (Transmitter Holding Register Empty)
 
+/// Plain-text output mode (`setopt plain`, or `plain` on the kernel command line).
+///
+/// When set, everything written through this module has its SGR colour
+/// escapes removed and its box-drawing and symbol characters replaced by
+/// single ASCII characters, so column alignment is kept on dumb terminals
+/// and screen readers.
+static PLAIN_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable plain-text output.
+pub fn set_plain(enabled: bool) {
+    PLAIN_MODE.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether plain-text output is active.
+pub fn is_plain() -> bool {
+    PLAIN_MODE.load(Ordering::Relaxed)
+}
+
+/// ASCII stand-in for a decorative character, or `None` to pass it through.
+///
+/// Every replacement is exactly one column wide, like the original glyph.
+fn plain_char(c: char) -> Option<u8> {
+    Some(match c {
+        '─' | '━' | '┄' | '┈' | '╌' => b'-',
+        '═' => b'=',
+        '│' | '┃' | '┆' | '┊' | '╎' | '║' => b'|',
+        '\u{2500}'..='\u{257F}' => b'+',
+        '█' | '▓' | '▒' => b'#',
+        '░' => b'.',
+        '●' | '◆' | '•' => b'*',
+        '✓' => b'+',
+        '✗' => b'!',
+        '↑' => b'^',
+        '↓' => b'v',
+        '←' => b'<',
+        '→' => b'>',
+        _ => return None,
+    })
+}
+
+/// Run `bytes` through the plain-text filter, handing each output byte to `emit`.
+///
+/// SGR sequences (`ESC [ ... m`) are dropped; other control sequences such
+/// as cursor movement are kept so line editing still works.
+pub fn plain_filter(bytes: &[u8], mut emit: impl FnMut(u8)) {
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == 0x1b && bytes.get(i + 1) == Some(&b'[') {
+            let mut end = i + 2;
+            while end < bytes.len() && !(0x40..=0x7e).contains(&bytes[end]) {
+                end += 1;
+            }
+            if bytes.get(end) == Some(&b'm') {
+                i = end + 1;
+                continue;
+            }
+        } else if b >= 0x80 {
+            let width = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mapped = bytes
+                .get(i..i + width)
+                .and_then(|s| core::str::from_utf8(s).ok())
+                .and_then(|s| s.chars().next())
+                .and_then(plain_char);
+            if let Some(ascii) = mapped {
+                emit(ascii);
+                i += width;
+                continue;
+            }
+        }
+        emit(b);
+        i += 1;
+    }
+}
+
+/// Set when Ctrl+C is seen by [`interrupted`], until the shell starts the
+/// next command (see [`clear_interrupt`]).
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Longest type-ahead kept while a command checks for Ctrl+C
+const TYPEAHEAD_LEN: usize = 64;
+
+/// Input read while looking for Ctrl+C, handed out by `read_byte` before
+/// anything still in the UART
+struct TypeAhead {
+    buf: [u8; TYPEAHEAD_LEN],
+    start: usize,
+    len: usize,
+}
+
+static TYPEAHEAD: Spinlock<TypeAhead> = Spinlock::new(TypeAhead {
+    buf: [0; TYPEAHEAD_LEN],
+    start: 0,
+    len: 0,
+});
+
+/// Whether Ctrl+C was typed since the current command started.
+///
+/// Long-running commands call this (usually through `jobs::checkpoint`)
+/// between steps and stop when it returns true. Other input read on the
+/// way is kept for the shell. Ctrl+C is echoed as `^C` when first seen.
+/// Only the shell on hart 0 reads the console, so elsewhere this is false.
+pub fn interrupted() -> bool {
+    if crate::get_hart_id() != 0 {
+        return false;
+    }
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return true;
+    }
+    while Console::is_rx_ready() {
+        let byte = unsafe { core::ptr::read_volatile((UART_BASE + RBR) as *const u8) };
+        if byte == 0x03 {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            write_line("^C");
+            return true;
+        }
+        let mut typeahead = TYPEAHEAD.lock();
+        if typeahead.len < TYPEAHEAD_LEN {
+            let end = (typeahead.start + typeahead.len) % TYPEAHEAD_LEN;
+            typeahead.buf[end] = byte;
+            typeahead.len += 1;
+        }
+    }
+    false
+}
+
+/// Forget an earlier Ctrl+C; called as each command line starts
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
 pub struct Console;
 
 impl Console {
@@ -77,6 +216,15 @@ impl Console {
     }
 
     pub fn read_byte(&self) -> u8 {
+        {
+            let mut typeahead = TYPEAHEAD.lock();
+            if typeahead.len > 0 {
+                let byte = typeahead.buf[typeahead.start];
+                typeahead.start = (typeahead.start + 1) % TYPEAHEAD_LEN;
+                typeahead.len -= 1;
+                return byte;
+            }
+        }
         // Only return a byte if data is ready, otherwise return 0
         if Self::is_rx_ready() {
             unsafe { core::ptr::read_volatile((UART_BASE + RBR) as *const u8) }
@@ -86,11 +234,22 @@ impl Console {
     }
 }
 
+impl Console {
+    /// Write a byte slice, applying the plain-text filter when it is enabled.
+    pub fn write_filtered(&mut self, bytes: &[u8]) {
+        if is_plain() {
+            plain_filter(bytes, |b| self.write_byte(b));
+        } else {
+            for &b in bytes {
+                self.write_byte(b);
+            }
+        }
+    }
+}
+
 impl Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            self.write_byte(byte);
-        }
+        self.write_filtered(s.as_bytes());
         Ok(())
     }
 }
@@ -109,10 +268,7 @@ pub fn write_line(s: &str) {
 
 /// Write a raw byte slice to the UART.
 pub fn write_bytes(bytes: &[u8]) {
-    let mut console = Console::new();
-    for &b in bytes {
-        console.write_byte(b);
-    }
+    Console::new().write_filtered(bytes);
 }
 
 /// Write an unsigned integer in decimal.
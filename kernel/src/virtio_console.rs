@@ -0,0 +1,281 @@
+//! VirtIO console driver: a second console beside the UART
+//!
+//! The UART stays the interactive shell. This stream carries either the
+//! kernel log as plain lines, or a control channel a test harness drives:
+//! each line received is run as a shell command and answered with
+//! `OK <len>\n` followed by `<len>` bytes of its output, without colours.
+//! The device has a single port and the driver polls it.
+
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::virtio_net::{VirtQueue, QUEUE_SIZE, VIRTIO_BASE, VIRTIO_MAX_DEVICES, VIRTIO_STRIDE};
+use crate::Spinlock;
+
+const VIRTIO_CONSOLE_DEVICE_ID: u32 = 3;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// Size of each receive buffer
+const RX_BUF_LEN: usize = 64;
+
+/// Largest chunk sent in one transmit request
+const TX_BUF_LEN: usize = 512;
+
+/// Longest control line held; a longer one is dropped
+const MAX_LINE: usize = 256;
+
+/// What the second console carries
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Nothing; input is discarded
+    Off,
+    /// Kernel log entries, one per line
+    Log,
+    /// Shell commands in, their output out
+    Control,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Log => "log",
+            Mode::Control => "control",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Mode> {
+        match name {
+            "off" => Some(Mode::Off),
+            "log" => Some(Mode::Log),
+            "control" => Some(Mode::Control),
+            _ => None,
+        }
+    }
+}
+
+// Static storage for the queues and their buffers (must not move)
+#[repr(C, align(4096))]
+struct QueueMem {
+    data: [u8; 4096 * 2],
+}
+static mut RX_QUEUE_MEM: QueueMem = QueueMem {
+    data: [0; 4096 * 2],
+};
+static mut TX_QUEUE_MEM: QueueMem = QueueMem {
+    data: [0; 4096 * 2],
+};
+static mut RX_BUFS: [[u8; RX_BUF_LEN]; QUEUE_SIZE] = [[0; RX_BUF_LEN]; QUEUE_SIZE];
+static mut TX_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+
+pub struct VirtioConsole {
+    base: usize,
+    rx_queue: VirtQueue,
+    tx_queue: VirtQueue,
+    /// Control bytes received since the last newline
+    line: Vec<u8>,
+}
+
+static CONSOLE: Spinlock<Option<VirtioConsole>> = Spinlock::new(None);
+static MODE: AtomicU8 = AtomicU8::new(Mode::Log as u8);
+/// Sequence number of the next log entry to forward
+static LOG_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+impl VirtioConsole {
+    fn probe() -> Option<Self> {
+        for i in 0..VIRTIO_MAX_DEVICES {
+            let addr = VIRTIO_BASE + i * VIRTIO_STRIDE;
+            let magic = unsafe { read_volatile(addr as *const u32) };
+            let device_id = unsafe { read_volatile((addr + 0x08) as *const u32) };
+
+            if magic == 0x7472_6976 && device_id == VIRTIO_CONSOLE_DEVICE_ID {
+                return Some(unsafe { Self::new(addr) });
+            }
+        }
+        None
+    }
+
+    unsafe fn new(base: usize) -> Self {
+        let mut dev = VirtioConsole {
+            base,
+            rx_queue: VirtQueue::new((&raw mut RX_QUEUE_MEM.data) as *mut u8, RX_QUEUE),
+            tx_queue: VirtQueue::new((&raw mut TX_QUEUE_MEM.data) as *mut u8, TX_QUEUE),
+            line: Vec::new(),
+        };
+        dev.init();
+        dev
+    }
+
+    unsafe fn init(&mut self) {
+        self.write32(0x070, 0); // Reset
+        self.write32(0x070, 1 | 2); // ACK | DRIVER
+        self.write32(0x020, 0); // No features
+        self.write32(0x070, 1 | 2 | 8); // FEATURES_OK
+
+        self.write32(0x028, 4096);
+        for (queue, mem) in [
+            (RX_QUEUE, &raw const RX_QUEUE_MEM.data),
+            (TX_QUEUE, &raw const TX_QUEUE_MEM.data),
+        ] {
+            self.write32(0x030, queue as u32);
+            self.write32(0x038, QUEUE_SIZE as u32);
+            self.write32(0x040, (mem as u64 / 4096) as u32);
+        }
+        self.write32(0x070, 1 | 2 | 4 | 8); // DRIVER_OK
+
+        // Every receive descriptor owns the buffer with its index
+        while let Some(idx) = self.rx_queue.alloc_desc() {
+            self.offer_rx(idx);
+        }
+        self.write32(0x050, RX_QUEUE as u32);
+    }
+
+    fn offer_rx(&mut self, idx: u16) {
+        let desc = &mut self.rx_queue.desc[idx as usize];
+        desc.addr = unsafe { &raw const RX_BUFS[idx as usize] } as u64;
+        desc.len = RX_BUF_LEN as u32;
+        desc.flags = 2; // WRITE
+        self.rx_queue.push_avail(idx);
+    }
+
+    /// Send `bytes`, waiting for the device to take each chunk
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(TX_BUF_LEN) {
+            let Some(idx) = self.tx_queue.alloc_desc() else {
+                return;
+            };
+            unsafe {
+                TX_BUF[..chunk.len()].copy_from_slice(chunk);
+                let desc = &mut self.tx_queue.desc[idx as usize];
+                desc.addr = (&raw const TX_BUF) as u64;
+                desc.len = chunk.len() as u32;
+                desc.flags = 0;
+            }
+            self.tx_queue.push_avail(idx);
+            self.write32(0x050, TX_QUEUE as u32);
+
+            while self.tx_queue.pop_used().is_none() {
+                core::hint::spin_loop();
+            }
+            self.tx_queue.free_desc(idx);
+        }
+    }
+
+    /// Take whatever the host has sent, handing the buffers back
+    fn read(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut returned = false;
+        while let Some((idx, len)) = self.rx_queue.pop_used() {
+            let len = (len as usize).min(RX_BUF_LEN);
+            bytes.extend_from_slice(unsafe { &RX_BUFS[idx as usize][..len] });
+            self.offer_rx(idx);
+            returned = true;
+        }
+        if returned {
+            self.write32(0x050, RX_QUEUE as u32);
+        }
+        bytes
+    }
+
+    fn write32(&self, offset: usize, val: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, val) }
+    }
+}
+
+/// Probe for the device, returning its base address if one was found.
+/// Log mode starts with the entries already in the log.
+pub fn init() -> Option<usize> {
+    let dev = VirtioConsole::probe()?;
+    let base = dev.base;
+    *CONSOLE.lock() = Some(dev);
+    Some(base)
+}
+
+/// Whether a second console was found
+pub fn present() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::Relaxed) {
+        0 => Mode::Off,
+        1 => Mode::Log,
+        _ => Mode::Control,
+    }
+}
+
+/// Switch what the console carries. Log mode continues from the newest
+/// entry, and a half-received control line is dropped.
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+    LOG_SEQ.store(crate::klog::KLOG.sequence(), Ordering::Relaxed);
+    if let Some(dev) = CONSOLE.lock().as_mut() {
+        dev.line.clear();
+    }
+}
+
+/// Write raw bytes to the console, if there is one
+pub fn write(bytes: &[u8]) {
+    if let Some(dev) = CONSOLE.lock().as_mut() {
+        dev.write(bytes);
+    }
+}
+
+/// Forward new log entries in log mode and discard input otherwise.
+/// Called from hart 0's periodic work.
+pub fn tick() {
+    let mut guard = CONSOLE.lock();
+    let Some(dev) = guard.as_mut() else {
+        return;
+    };
+    match mode() {
+        Mode::Log => {
+            let _ = dev.read();
+            let seq = LOG_SEQ.load(Ordering::Relaxed);
+            let entries = crate::klog::KLOG.since(seq);
+            let Some(last) = entries.last() else {
+                return;
+            };
+            LOG_SEQ.store(last.seq + 1, Ordering::Relaxed);
+            for entry in &entries {
+                let mut line = entry.format().into_bytes();
+                line.push(b'\n');
+                dev.write(&line);
+            }
+        }
+        Mode::Off => {
+            let _ = dev.read();
+        }
+        Mode::Control => {}
+    }
+}
+
+/// In control mode, the next complete command line received, if any.
+/// Lines are taken only here, at the shell's idle point, so a command is
+/// never run from inside another.
+pub fn next_command() -> Option<Vec<u8>> {
+    if mode() != Mode::Control {
+        return None;
+    }
+    let mut guard = CONSOLE.lock();
+    let dev = guard.as_mut()?;
+    let input = dev.read();
+    dev.line.extend_from_slice(&input);
+    let Some(end) = dev.line.iter().position(|&b| b == b'\n') else {
+        if dev.line.len() > MAX_LINE {
+            dev.line.clear();
+        }
+        return None;
+    };
+    // Anything after the newline waits for the next call
+    let rest = dev.line.split_off(end + 1);
+    let mut line = core::mem::replace(&mut dev.line, rest);
+    line.truncate(end);
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Some(line)
+}
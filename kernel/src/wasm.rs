@@ -1,20 +1,170 @@
 use alloc::{format, string::String, vec, vec::Vec};
-use wasmi::{Caller, Engine, Func, Linker, Module, Store};
+use smoltcp::wire::Ipv4Address;
+use wasmi::{CallHook, Caller, Engine, Error, Func, Linker, Module, Store};
 
-use crate::uart;
+use crate::net::{TcpId, TcpState};
+
+/// Host functions provided in the `env` module, matching the syscall API
+/// in `mkfs/src/lib.rs`
+pub const HOST_FUNCTIONS: &[&str] = &[
+    "print",
+    "time",
+    "time_unix",
+    "date_get",
+    "arg_count",
+    "arg_get",
+    "stdin_read",
+    "cwd_get",
+    "fs_exists",
+    "fs_read",
+    "fs_write",
+    "fs_list",
+    "klog_get",
+    "net_available",
+    "http_get",
+    "ipc_bind",
+    "ipc_send",
+    "ipc_recv",
+    "udp_send",
+    "udp_recv",
+    "tcp_connect",
+    "tcp_send",
+    "tcp_recv",
+    "tcp_close",
+];
+
+/// How long `tcp_connect` waits for the handshake
+const TCP_CONNECT_TIMEOUT_MS: i64 = 10_000;
+
+/// How long `tcp_close` waits for queued data to be acknowledged
+const TCP_CLOSE_TIMEOUT_MS: i64 = 1_000;
+
+/// Whether `bytes` starts with the `\0asm` magic number
+pub fn is_wasm(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\0asm")
+}
+
+/// Validate a module and return its imports as `module.name`, with whether
+/// the kernel provides each
+pub fn imports(wasm_bytes: &[u8]) -> Result<Vec<(String, bool)>, String> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm_bytes).map_err(|e| format!("Invalid WASM: {:?}", e))?;
+    Ok(module
+        .imports()
+        .map(|import| {
+            let provided = import.module() == "env" && HOST_FUNCTIONS.contains(&import.name());
+            (format!("{}.{}", import.module(), import.name()), provided)
+        })
+        .collect())
+}
 
 /// State to pass to host functions - includes command arguments
 struct WasmContext {
     args: Vec<String>,
+    /// Output of the previous pipeline stage, if the program is piped into
+    stdin: Option<Vec<u8>>,
+    /// How much of `stdin` has been read
+    stdin_pos: usize,
+    /// TCP sockets opened by the program, indexed by the handle it was given
+    sockets: Vec<Option<TcpId>>,
+}
+
+/// Copy `len` bytes out of the program's memory
+fn read_guest(caller: &Caller<'_, WasmContext>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let mem = caller.get_export("memory").and_then(|e| e.into_memory())?;
+    let mut buf = vec![0u8; len.max(0) as usize];
+    mem.read(caller, ptr as usize, &mut buf).ok()?;
+    Some(buf)
+}
+
+/// Copy `bytes` into the program's memory
+fn write_guest(caller: &mut Caller<'_, WasmContext>, ptr: i32, bytes: &[u8]) -> bool {
+    match caller.get_export("memory").and_then(|e| e.into_memory()) {
+        Some(mem) => mem.write(caller, ptr as usize, bytes).is_ok(),
+        None => false,
+    }
+}
+
+/// Resolve a host given as an IPv4 address or a DNS name
+fn resolve_host(host: &[u8]) -> Option<Ipv4Address> {
+    if let Some(ip) = crate::net::parse_ipv4(host) {
+        return Some(ip);
+    }
+    let mut net_guard = crate::NET_STATE.lock();
+    let net = net_guard.as_mut()?;
+    crate::dns::resolve(
+        net,
+        host,
+        crate::net::get_dns_server(),
+        5000,
+        crate::get_time_ms,
+    )
 }
 
-/// Execute a WASM binary with the given arguments
-pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
+/// The socket behind a handle the program was given
+fn socket(caller: &Caller<'_, WasmContext>, handle: i32) -> Option<TcpId> {
+    let sockets = &caller.data().sockets;
+    usize::try_from(handle)
+        .ok()
+        .and_then(|i| sockets.get(i).copied().flatten())
+}
+
+/// Wait for a dialled socket to connect, polling the network
+fn wait_connected(id: TcpId) -> bool {
+    let started = crate::get_time_ms();
+    loop {
+        if crate::jobs::checkpoint().is_err() {
+            return false;
+        }
+        crate::poll_network();
+        let state = crate::NET_STATE
+            .lock()
+            .as_mut()
+            .map_or(TcpState::Closed, |net| net.tcp_status(id));
+        match state {
+            TcpState::Established | TcpState::CloseWait => return true,
+            TcpState::SynSent | TcpState::SynReceived
+                if crate::get_time_ms() - started < TCP_CONNECT_TIMEOUT_MS => {}
+            _ => return false,
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Give a socket being closed a moment to deliver what is still queued
+fn wait_closed(id: TcpId) {
+    let started = crate::get_time_ms();
+    while crate::get_time_ms() - started < TCP_CLOSE_TIMEOUT_MS {
+        crate::poll_network();
+        let state = crate::NET_STATE
+            .lock()
+            .as_mut()
+            .map_or(TcpState::Closed, |net| net.tcp_status(id));
+        if matches!(
+            state,
+            TcpState::FinWait2 | TcpState::TimeWait | TcpState::Closed
+        ) {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Execute a WASM binary with the given arguments and piped input
+pub fn execute(wasm_bytes: &[u8], args: &[&str], stdin: Option<&[u8]>) -> Result<String, String> {
     let engine = Engine::default();
     let ctx = WasmContext {
         args: args.iter().map(|s| String::from(*s)).collect(),
+        stdin: stdin.map(Vec::from),
+        stdin_pos: 0,
+        sockets: Vec::new(),
     };
     let mut store = Store::new(&engine, ctx);
+    // Let job control pause or end a background program at its host calls
+    store.call_hook(|_, hook| match hook {
+        CallHook::CallingHost => crate::jobs::checkpoint().map_err(Error::new),
+        _ => Ok(()),
+    });
     let mut linker = Linker::new(&engine);
 
     // Syscall: print(ptr, len)
@@ -28,7 +178,8 @@ pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
                     if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
                         let mut buffer = vec![0u8; len as usize];
                         if mem.read(&caller, ptr as usize, &mut buffer).is_ok() {
-                            uart::write_str(&String::from_utf8_lossy(&buffer));
+                            // Through the shell's capture so redirects and pipes see it
+                            crate::out_bytes(&buffer);
                         }
                     }
                 },
@@ -47,6 +198,40 @@ pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
         )
         .map_err(|e| format!("define time: {:?}", e))?;
 
+    // Syscall: time_unix() -> i64 (seconds since the Unix epoch)
+    linker
+        .define(
+            "env",
+            "time_unix",
+            Func::wrap(&mut store, |_caller: Caller<'_, WasmContext>| -> i64 {
+                crate::clock::now_secs()
+            }),
+        )
+        .map_err(|e| format!("define time_unix: {:?}", e))?;
+
+    // Syscall: date_get(buf_ptr, buf_len) -> i32
+    // Writes the date as `date` prints it; returns its length or -1
+    linker
+        .define(
+            "env",
+            "date_get",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>, buf_ptr: i32, buf_len: i32| -> i32 {
+                    let date = crate::clock::format_date(crate::clock::now_secs());
+                    if date.len() > buf_len as usize {
+                        return -1;
+                    }
+                    if write_guest(&mut caller, buf_ptr, date.as_bytes()) {
+                        date.len() as i32
+                    } else {
+                        -1
+                    }
+                },
+            ),
+        )
+        .map_err(|e| format!("define date_get: {:?}", e))?;
+
     // Syscall: arg_count() -> i32
     linker
         .define(
@@ -98,6 +283,36 @@ pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
         )
         .map_err(|e| format!("define arg_get: {:?}", e))?;
 
+    // Syscall: stdin_read(buf_ptr, buf_len) -> i32
+    // Returns the bytes read (0 once all input is consumed), or -1 if the
+    // program isn't reading from a pipe
+    linker
+        .define(
+            "env",
+            "stdin_read",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>, buf_ptr: i32, buf_len: i32| -> i32 {
+                    let chunk = {
+                        let ctx = caller.data();
+                        let Some(stdin) = ctx.stdin.as_ref() else {
+                            return -1;
+                        };
+                        let end = stdin.len().min(ctx.stdin_pos + buf_len.max(0) as usize);
+                        stdin[ctx.stdin_pos..end].to_vec()
+                    };
+                    if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
+                        if mem.write(&mut caller, buf_ptr as usize, &chunk).is_ok() {
+                            caller.data_mut().stdin_pos += chunk.len();
+                            return chunk.len() as i32;
+                        }
+                    }
+                    -1
+                },
+            ),
+        )
+        .map_err(|e| format!("define stdin_read: {:?}", e))?;
+
     // Syscall: cwd_get(buf_ptr, buf_len) -> i32
     linker
         .define(
@@ -372,6 +587,345 @@ pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
         )
         .map_err(|e| format!("define http_get: {:?}", e))?;
 
+    // Syscall: ipc_bind(name_ptr, name_len) -> i32 (0 ok, -1 error)
+    linker
+        .define(
+            "env",
+            "ipc_bind",
+            Func::wrap(
+                &mut store,
+                |caller: Caller<'_, WasmContext>, name_ptr: i32, name_len: i32| -> i32 {
+                    if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
+                        let mut name_buf = vec![0u8; name_len as usize];
+                        if mem.read(&caller, name_ptr as usize, &mut name_buf).is_ok() {
+                            if let Ok(name) = core::str::from_utf8(&name_buf) {
+                                if crate::ipc::bind(name).is_ok() {
+                                    return 0;
+                                }
+                            }
+                        }
+                    }
+                    -1
+                },
+            ),
+        )
+        .map_err(|e| format!("define ipc_bind: {:?}", e))?;
+
+    // Syscall: ipc_send(name_ptr, name_len, data_ptr, data_len) -> i32 (0 ok, -1 error)
+    linker
+        .define(
+            "env",
+            "ipc_send",
+            Func::wrap(
+                &mut store,
+                |caller: Caller<'_, WasmContext>,
+                 name_ptr: i32,
+                 name_len: i32,
+                 data_ptr: i32,
+                 data_len: i32|
+                 -> i32 {
+                    if data_len as usize > crate::ipc::MAX_MESSAGE_SIZE {
+                        return -1;
+                    }
+                    if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
+                        let mut name_buf = vec![0u8; name_len as usize];
+                        let mut data_buf = vec![0u8; data_len as usize];
+                        if mem.read(&caller, name_ptr as usize, &mut name_buf).is_ok()
+                            && mem.read(&caller, data_ptr as usize, &mut data_buf).is_ok()
+                        {
+                            if let Ok(name) = core::str::from_utf8(&name_buf) {
+                                if crate::ipc::send(name, &data_buf).is_ok() {
+                                    return 0;
+                                }
+                            }
+                        }
+                    }
+                    -1
+                },
+            ),
+        )
+        .map_err(|e| format!("define ipc_send: {:?}", e))?;
+
+    // Syscall: ipc_recv(name_ptr, name_len, buf_ptr, buf_len) -> i32
+    // Returns the message length (truncated to buf_len), -1 if no message is
+    // queued, or -2 if the endpoint doesn't exist
+    linker
+        .define(
+            "env",
+            "ipc_recv",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>,
+                 name_ptr: i32,
+                 name_len: i32,
+                 buf_ptr: i32,
+                 buf_len: i32|
+                 -> i32 {
+                    if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
+                        let mut name_buf = vec![0u8; name_len as usize];
+                        if mem.read(&caller, name_ptr as usize, &mut name_buf).is_ok() {
+                            if let Ok(name) = core::str::from_utf8(&name_buf) {
+                                return match crate::ipc::recv(name) {
+                                    Ok(Some(msg)) => {
+                                        let to_copy = msg.data.len().min(buf_len as usize);
+                                        if mem
+                                            .write(
+                                                &mut caller,
+                                                buf_ptr as usize,
+                                                &msg.data[..to_copy],
+                                            )
+                                            .is_ok()
+                                        {
+                                            to_copy as i32
+                                        } else {
+                                            -2
+                                        }
+                                    }
+                                    Ok(None) => -1,
+                                    Err(_) => -2,
+                                };
+                            }
+                        }
+                    }
+                    -2
+                },
+            ),
+        )
+        .map_err(|e| format!("define ipc_recv: {:?}", e))?;
+
+    // Syscall: udp_send(host_ptr, host_len, port, data_ptr, data_len) -> i32
+    // Sends one datagram from the kernel's UDP socket; 0 ok, -1 error
+    linker
+        .define(
+            "env",
+            "udp_send",
+            Func::wrap(
+                &mut store,
+                |caller: Caller<'_, WasmContext>,
+                 host_ptr: i32,
+                 host_len: i32,
+                 port: i32,
+                 data_ptr: i32,
+                 data_len: i32|
+                 -> i32 {
+                    let (Some(host), Some(data)) = (
+                        read_guest(&caller, host_ptr, host_len),
+                        read_guest(&caller, data_ptr, data_len),
+                    ) else {
+                        return -1;
+                    };
+                    let (Ok(port), Some(ip)) = (u16::try_from(port), resolve_host(&host)) else {
+                        return -1;
+                    };
+                    let sent = match crate::NET_STATE.lock().as_mut() {
+                        Some(net) => net.udp_send(ip, port, &data, crate::get_time_ms()),
+                        None => Err("Network not initialized"),
+                    };
+                    if sent.is_ok() {
+                        0
+                    } else {
+                        -1
+                    }
+                },
+            ),
+        )
+        .map_err(|e| format!("define udp_send: {:?}", e))?;
+
+    // Syscall: udp_recv(buf_ptr, buf_len, from_ptr) -> i32
+    // Returns the datagram length (truncated to buf_len) or -1 if none is
+    // queued. Unless from_ptr is 0, the sender's address and port (big
+    // endian) are written there as 6 bytes.
+    linker
+        .define(
+            "env",
+            "udp_recv",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>,
+                 buf_ptr: i32,
+                 buf_len: i32,
+                 from_ptr: i32|
+                 -> i32 {
+                    let mut buf = vec![0u8; buf_len.max(0) as usize];
+                    let received = crate::NET_STATE
+                        .lock()
+                        .as_mut()
+                        .and_then(|net| net.udp_recv(&mut buf, crate::get_time_ms()));
+                    let Some((ip, port, len)) = received else {
+                        return -1;
+                    };
+                    let mut from = [0u8; 6];
+                    from[..4].copy_from_slice(ip.as_bytes());
+                    from[4..].copy_from_slice(&port.to_be_bytes());
+                    if from_ptr != 0 && !write_guest(&mut caller, from_ptr, &from) {
+                        return -1;
+                    }
+                    if write_guest(&mut caller, buf_ptr, &buf[..len]) {
+                        len as i32
+                    } else {
+                        -1
+                    }
+                },
+            ),
+        )
+        .map_err(|e| format!("define udp_recv: {:?}", e))?;
+
+    // Syscall: tcp_connect(host_ptr, host_len, port) -> i32
+    // Blocks until connected; returns a handle, -1 if the host can't be
+    // resolved or no socket is free, or -2 if the connection failed
+    linker
+        .define(
+            "env",
+            "tcp_connect",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>,
+                 host_ptr: i32,
+                 host_len: i32,
+                 port: i32|
+                 -> i32 {
+                    let Some(host) = read_guest(&caller, host_ptr, host_len) else {
+                        return -1;
+                    };
+                    let (Ok(port), Some(ip)) = (u16::try_from(port), resolve_host(&host)) else {
+                        return -1;
+                    };
+                    let opened = {
+                        let mut net_guard = crate::NET_STATE.lock();
+                        let Some(net) = net_guard.as_mut() else {
+                            return -1;
+                        };
+                        net.tcp_open("wasm").and_then(|id| {
+                            let dialled = net.tcp_dial(id, ip, port);
+                            if dialled.is_err() {
+                                net.tcp_release(id);
+                            }
+                            dialled.map(|()| id)
+                        })
+                    };
+                    let Ok(id) = opened else {
+                        return -1;
+                    };
+                    if !wait_connected(id) {
+                        if let Some(net) = crate::NET_STATE.lock().as_mut() {
+                            net.tcp_release(id);
+                        }
+                        return -2;
+                    }
+                    let sockets = &mut caller.data_mut().sockets;
+                    let handle = match sockets.iter().position(Option::is_none) {
+                        Some(free) => free,
+                        None => {
+                            sockets.push(None);
+                            sockets.len() - 1
+                        }
+                    };
+                    sockets[handle] = Some(id);
+                    handle as i32
+                },
+            ),
+        )
+        .map_err(|e| format!("define tcp_connect: {:?}", e))?;
+
+    // Syscall: tcp_send(handle, data_ptr, data_len) -> i32
+    // Blocks until everything is queued; returns data_len or -1 on error
+    linker
+        .define(
+            "env",
+            "tcp_send",
+            Func::wrap(
+                &mut store,
+                |caller: Caller<'_, WasmContext>,
+                 handle: i32,
+                 data_ptr: i32,
+                 data_len: i32|
+                 -> i32 {
+                    let (Some(id), Some(data)) = (
+                        socket(&caller, handle),
+                        read_guest(&caller, data_ptr, data_len),
+                    ) else {
+                        return -1;
+                    };
+                    let mut sent = 0;
+                    while sent < data.len() {
+                        if crate::jobs::checkpoint().is_err() {
+                            return -1;
+                        }
+                        let written = match crate::NET_STATE.lock().as_mut() {
+                            Some(net) => net.tcp_write(id, &data[sent..], crate::get_time_ms()),
+                            None => Err("Network not initialized"),
+                        };
+                        match written {
+                            Ok(n) => sent += n,
+                            Err(_) => return -1,
+                        }
+                        if sent < data.len() {
+                            crate::poll_network();
+                        }
+                    }
+                    sent as i32
+                },
+            ),
+        )
+        .map_err(|e| format!("define tcp_send: {:?}", e))?;
+
+    // Syscall: tcp_recv(handle, buf_ptr, buf_len) -> i32
+    // Returns the bytes read, 0 if nothing has arrived yet, or -1 once the
+    // peer has closed the connection and everything it sent has been read
+    linker
+        .define(
+            "env",
+            "tcp_recv",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>,
+                 handle: i32,
+                 buf_ptr: i32,
+                 buf_len: i32|
+                 -> i32 {
+                    let Some(id) = socket(&caller, handle) else {
+                        return -1;
+                    };
+                    crate::poll_network();
+                    let mut buf = vec![0u8; buf_len.max(0) as usize];
+                    let read = match crate::NET_STATE.lock().as_mut() {
+                        Some(net) => net.tcp_read(id, &mut buf),
+                        None => Err("Network not initialized"),
+                    };
+                    match read {
+                        Ok(len) if write_guest(&mut caller, buf_ptr, &buf[..len]) => len as i32,
+                        _ => -1,
+                    }
+                },
+            ),
+        )
+        .map_err(|e| format!("define tcp_recv: {:?}", e))?;
+
+    // Syscall: tcp_close(handle) -> i32 (0 ok, -1 bad handle)
+    linker
+        .define(
+            "env",
+            "tcp_close",
+            Func::wrap(
+                &mut store,
+                |mut caller: Caller<'_, WasmContext>, handle: i32| -> i32 {
+                    let Some(id) = socket(&caller, handle) else {
+                        return -1;
+                    };
+                    caller.data_mut().sockets[handle as usize] = None;
+                    if let Some(net) = crate::NET_STATE.lock().as_mut() {
+                        net.tcp_shutdown(id, crate::get_time_ms());
+                    }
+                    wait_closed(id);
+                    if let Some(net) = crate::NET_STATE.lock().as_mut() {
+                        net.tcp_release(id);
+                    }
+                    0
+                },
+            ),
+        )
+        .map_err(|e| format!("define tcp_close: {:?}", e))?;
+
     let module = Module::new(&engine, wasm_bytes).map_err(|e| format!("Invalid WASM: {:?}", e))?;
 
     let instance = linker
@@ -384,8 +938,19 @@ pub fn execute(wasm_bytes: &[u8], args: &[&str]) -> Result<String, String> {
         .get_typed_func::<(), ()>(&store, "_start")
         .map_err(|e| format!("Missing _start: {:?}", e))?;
 
-    run.call(&mut store, ())
-        .map_err(|e| format!("Runtime: {:?}", e))?;
+    let result = run
+        .call(&mut store, ())
+        .map_err(|e| format!("Runtime: {:?}", e));
+
+    // Sockets the program left open go back to the pool
+    let open: Vec<TcpId> = store.data().sockets.iter().flatten().copied().collect();
+    if !open.is_empty() {
+        if let Some(net) = crate::NET_STATE.lock().as_mut() {
+            for id in open {
+                net.tcp_release(id);
+            }
+        }
+    }
 
-    Ok(String::new())
+    result.map(|()| String::new())
 }
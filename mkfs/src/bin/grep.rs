@@ -5,6 +5,7 @@
 //   grep -i <pattern> <file>     Case-insensitive search
 //   grep -n <pattern> <file>     Show line numbers
 //   grep -v <pattern> <file>     Invert match (show non-matching lines)
+//   cmd | grep <pattern>         Search piped input
 
 #![cfg_attr(target_arch = "wasm32", no_std)]
 #![cfg_attr(target_arch = "wasm32", no_main)]
@@ -18,6 +19,7 @@ mod wasm {
         fn print(ptr: *const u8, len: usize);
         fn arg_count() -> i32;
         fn arg_get(index: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        fn stdin_read(buf_ptr: *mut u8, buf_len: i32) -> i32;
         fn cwd_get(buf_ptr: *mut u8, buf_len: i32) -> i32;
         fn fs_read(path_ptr: *const u8, path_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
     }
@@ -67,6 +69,79 @@ mod wasm {
         None
     }
 
+    /// Read all piped input into `buf`, or -1 if there is none
+    fn read_stdin(buf: &mut [u8]) -> i32 {
+        let mut total = 0usize;
+        while total < buf.len() {
+            let len = unsafe { stdin_read(buf[total..].as_mut_ptr(), (buf.len() - total) as i32) };
+            if len < 0 {
+                return -1;
+            }
+            if len == 0 {
+                break;
+            }
+            total += len as usize;
+        }
+        total as i32
+    }
+
+    struct Options<'a> {
+        pattern: &'a [u8],
+        case_insensitive: bool,
+        show_line_numbers: bool,
+        invert_match: bool,
+    }
+
+    /// Print the matching lines of `content`, prefixed with `name` if given
+    fn grep_content(content: &[u8], name: Option<&[u8]>, opts: &Options) {
+        let pattern = opts.pattern;
+        let mut line_num = 1usize;
+        let mut line_start = 0;
+
+        for (i, &c) in content.iter().enumerate() {
+            if c == b'\n' || i == content.len() - 1 {
+                let end = if c == b'\n' { i } else { i + 1 };
+                let line = &content[line_start..end];
+
+                let match_pos = contains_pattern(line, pattern, opts.case_insensitive);
+                let matches = match_pos.is_some();
+                let should_print = if opts.invert_match { !matches } else { matches };
+
+                if should_print {
+                    if let Some(name) = name {
+                        log("\x1b[1;35m");
+                        unsafe { print(name.as_ptr(), name.len()) };
+                        log("\x1b[0m:");
+                    }
+                    if opts.show_line_numbers {
+                        log("\x1b[1;32m");
+                        print_num(line_num);
+                        log("\x1b[0m:");
+                    }
+
+                    if !opts.invert_match {
+                        if let Some(pos) = match_pos {
+                            // Highlight match
+                            unsafe { print(line[..pos].as_ptr(), pos) };
+                            log("\x1b[1;31m");
+                            unsafe { print(line[pos..pos + pattern.len()].as_ptr(), pattern.len()) };
+                            log("\x1b[0m");
+                            unsafe { print(line[pos + pattern.len()..].as_ptr(), line.len() - pos - pattern.len()) };
+                        } else {
+                            unsafe { print(line.as_ptr(), line.len()) };
+                        }
+                    } else {
+                        unsafe { print(line.as_ptr(), line.len()) };
+                    }
+                    log("\n");
+                }
+
+                line_num += 1;
+                line_start = i + 1;
+            }
+        }
+    }
+
     fn resolve_path(arg: &[u8], out: &mut [u8]) -> usize {
         let mut cwd = [0u8; 256];
         let cwd_len = unsafe { cwd_get(cwd.as_mut_ptr(), cwd.len() as i32) };
@@ -104,7 +179,7 @@ mod wasm {
     pub extern "C" fn _start() {
         let argc = unsafe { arg_count() };
         
-        if argc < 2 {
+        if argc < 1 {
             log("Usage: grep [OPTIONS] <pattern> [file...]\n");
             log("Options: -i (case-insensitive), -n (line numbers), -v (invert)\n");
             return;
@@ -159,13 +234,26 @@ mod wasm {
             log("Usage: grep [OPTIONS] <pattern> <file...>\n");
             return;
         }
-        
+
+        let opts = Options {
+            pattern: &pattern_buf[..pattern_len],
+            case_insensitive,
+            show_line_numbers,
+            invert_match,
+        };
+
+        // Without files, search piped input
         if file_count == 0 {
-            log("Usage: grep [OPTIONS] <pattern> <file...>\n");
+            let mut content = [0u8; 65536];
+            let read_len = read_stdin(&mut content);
+            if read_len < 0 {
+                log("Usage: grep [OPTIONS] <pattern> <file...>\n");
+                return;
+            }
+            grep_content(&content[..read_len as usize], None, &opts);
             return;
         }
-        
-        let pattern = &pattern_buf[..pattern_len];
+
         let show_filename = file_count > 1;
         
         // Process each file
@@ -190,52 +278,8 @@ mod wasm {
                 continue;
             }
             
-            let content = &content[..read_len as usize];
-            let mut line_num = 1usize;
-            let mut line_start = 0;
-            
-            for (i, &c) in content.iter().enumerate() {
-                if c == b'\n' || i == content.len() - 1 {
-                    let end = if c == b'\n' { i } else { i + 1 };
-                    let line = &content[line_start..end];
-                    
-                    let match_pos = contains_pattern(line, pattern, case_insensitive);
-                    let matches = match_pos.is_some();
-                    let should_print = if invert_match { !matches } else { matches };
-                    
-                    if should_print {
-                        if show_filename {
-                            log("\x1b[1;35m");
-                            unsafe { print(path_buf.as_ptr(), path_len) };
-                            log("\x1b[0m:");
-                        }
-                        if show_line_numbers {
-                            log("\x1b[1;32m");
-                            print_num(line_num);
-                            log("\x1b[0m:");
-                        }
-                        
-                        if !invert_match {
-                            if let Some(pos) = match_pos {
-                                // Highlight match
-                                unsafe { print(line[..pos].as_ptr(), pos) };
-                                log("\x1b[1;31m");
-                                unsafe { print(line[pos..pos + pattern_len].as_ptr(), pattern_len) };
-                                log("\x1b[0m");
-                                unsafe { print(line[pos + pattern_len..].as_ptr(), line.len() - pos - pattern_len) };
-                            } else {
-                                unsafe { print(line.as_ptr(), line.len()) };
-                            }
-                        } else {
-                            unsafe { print(line.as_ptr(), line.len()) };
-                        }
-                        log("\n");
-                    }
-                    
-                    line_num += 1;
-                    line_start = i + 1;
-                }
-            }
+            let name = if show_filename { Some(&path_buf[..path_len]) } else { None };
+            grep_content(&content[..read_len as usize], name, &opts);
         }
     }
 }
@@ -71,6 +71,7 @@ mod wasm {
                 log("Examples:\n");
                 log("  grep error /var/log/kernel.log\n");
                 log("  grep -i -n TODO *.rs\n");
+                log("  ls /usr/bin | grep a\n");
             }
             b"tail" => {
                 log("\x1b[1mtail\x1b[0m - Show last lines of a file\n\n");
@@ -80,6 +81,18 @@ mod wasm {
                 log("Examples:\n");
                 log("  tail /var/log/kernel.log\n");
                 log("  tail -n 20 /var/log/kernel.log\n");
+                log("  dmesg | tail -n 5\n");
+            }
+            b"wc" => {
+                log("\x1b[1mwc\x1b[0m - Count lines, words and bytes\n\n");
+                log("Usage: wc [-lwc] <file...>\n\n");
+                log("Options:\n");
+                log("  -l  Count lines\n");
+                log("  -w  Count words\n");
+                log("  -c  Count bytes\n\n");
+                log("Examples:\n");
+                log("  wc README.md\n");
+                log("  ls /usr/bin | wc -l\n");
             }
             b"uptime" => {
                 log("\x1b[1muptime\x1b[0m - Show system uptime\n\n");
@@ -96,8 +109,10 @@ mod wasm {
             b"wget" => {
                 log("\x1b[1mwget\x1b[0m - Download files from the web\n\n");
                 log("Usage: wget <url> [-O <file>]\n\n");
+                log("Saves to the file named in the URL unless -O is given.\n\n");
                 log("Options:\n");
-                log("  -O <file>  Save to specified file\n\n");
+                log("  -O <file>  Save to specified file\n");
+                log("  -O -       Print to stdout\n\n");
                 log("Examples:\n");
                 log("  wget https://example.com/file.txt\n");
                 log("  wget https://example.com/app.wasm -O /usr/bin/app\n");
@@ -117,9 +132,12 @@ mod wasm {
             }
             b"dmesg" => {
                 log("\x1b[1mdmesg\x1b[0m - Display kernel log\n\n");
-                log("Usage: dmesg [-n <count>]\n\n");
+                log("Usage: dmesg [-c] [-f] [-l <level>] [-n <count>]\n\n");
                 log("Options:\n");
+                log("  -f          Follow new messages until Ctrl+C\n");
+                log("  -l <level>  Only this level or more severe\n");
                 log("  -n <count>  Show last N messages\n");
+                log("  -c          Clear the log after printing\n");
             }
             b"cowsay" => {
                 log("\x1b[1mcowsay\x1b[0m - ASCII art cow\n\n");
@@ -173,10 +191,11 @@ mod wasm {
         log("\x1b[32m│\x1b[0m  \x1b[1mecho\x1b[0m [-n] txt Print text to stdout                    \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mgrep\x1b[0m pat file Search for patterns in files            \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mtail\x1b[0m [-n] f   Show last lines of a file              \x1b[32m│\x1b[0m\n");
+        log("\x1b[32m│\x1b[0m  \x1b[1mwc\x1b[0m [-lwc] f   Count lines, words and bytes            \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1muptime\x1b[0m        Show system uptime                      \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mwrite\x1b[0m f txt   Write content to a file                \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mhelp\x1b[0m [cmd]    Show help (this screen)                 \x1b[32m│\x1b[0m\n");
-        log("\x1b[32m│\x1b[0m  \x1b[1mdmesg\x1b[0m [-n N]  Display kernel log messages              \x1b[32m│\x1b[0m\n");
+        log("\x1b[32m│\x1b[0m  \x1b[1mdmesg\x1b[0m [-f]    Display kernel log messages              \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mnano\x1b[0m <file>   View file with line numbers             \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mwget\x1b[0m <url>    Download files from the web             \x1b[32m│\x1b[0m\n");
         log("\x1b[32m│\x1b[0m  \x1b[1mpkg\x1b[0m <cmd>     Package manager                         \x1b[32m│\x1b[0m\n");
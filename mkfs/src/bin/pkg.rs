@@ -74,7 +74,6 @@ mod wasm {
             log("\x1b[33mNote: Directory listing not available.\x1b[0m\n");
             log("\x1b[33mKnown system packages:\x1b[0m\n\n");
             log("  \x1b[32m●\x1b[0m cowsay     ASCII art cow\n");
-            log("  \x1b[32m●\x1b[0m dmesg      Kernel log viewer\n");
             log("  \x1b[32m●\x1b[0m hello      Test WASM binary\n");
             log("  \x1b[32m●\x1b[0m help       Show available commands\n");
             log("  \x1b[32m●\x1b[0m nano       Text file viewer\n");
@@ -4,6 +4,7 @@
 //   tail <file>           Show last 10 lines
 //   tail -n <N> <file>    Show last N lines
 //   tail -<N> <file>      Show last N lines (shorthand)
+//   cmd | tail [-n <N>]   Show last lines of piped input
 
 #![cfg_attr(target_arch = "wasm32", no_std)]
 #![cfg_attr(target_arch = "wasm32", no_main)]
@@ -17,6 +18,7 @@ mod wasm {
         fn print(ptr: *const u8, len: usize);
         fn arg_count() -> i32;
         fn arg_get(index: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        fn stdin_read(buf_ptr: *mut u8, buf_len: i32) -> i32;
         fn cwd_get(buf_ptr: *mut u8, buf_len: i32) -> i32;
         fn fs_read(path_ptr: *const u8, path_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
     }
@@ -39,6 +41,63 @@ mod wasm {
         Some(result)
     }
 
+    /// Read all piped input into `buf`, or -1 if there is none
+    fn read_stdin(buf: &mut [u8]) -> i32 {
+        let mut total = 0usize;
+        while total < buf.len() {
+            let len = unsafe { stdin_read(buf[total..].as_mut_ptr(), (buf.len() - total) as i32) };
+            if len < 0 {
+                return -1;
+            }
+            if len == 0 {
+                break;
+            }
+            total += len as usize;
+        }
+        total as i32
+    }
+
+    /// Print the last `num_lines` lines of `content`
+    fn tail_content(content: &[u8], num_lines: usize) {
+        // Count lines and find positions
+        let mut line_positions: [usize; 1024] = [0; 1024];
+        let mut line_count = 0usize;
+        line_positions[0] = 0;
+        
+        for (idx, &c) in content.iter().enumerate() {
+            if c == b'\n' && idx + 1 < content.len() && line_count + 1 < 1024 {
+                line_count += 1;
+                line_positions[line_count] = idx + 1;
+            }
+        }
+        line_count += 1; // Total number of lines
+        
+        // Calculate start line
+        let start_line = if line_count > num_lines {
+            line_count - num_lines
+        } else {
+            0
+        };
+        
+        // Print lines from start_line onwards
+        for line_idx in start_line..line_count {
+            let line_start = line_positions[line_idx];
+            let line_end = if line_idx + 1 < line_count {
+                line_positions[line_idx + 1] - 1 // Exclude newline
+            } else {
+                content.len()
+            };
+            
+            if line_start < content.len() {
+                let end = line_end.min(content.len());
+                // Content usually ends in a newline; don't print it twice
+                let end = if end == content.len() && content[end - 1] == b'\n' { end - 1 } else { end };
+                unsafe { print(content[line_start..end].as_ptr(), end - line_start) };
+                log("\n");
+            }
+        }
+    }
+
     fn resolve_path(arg: &[u8], out: &mut [u8]) -> usize {
         let mut cwd = [0u8; 256];
         let cwd_len = unsafe { cwd_get(cwd.as_mut_ptr(), cwd.len() as i32) };
@@ -76,11 +135,6 @@ mod wasm {
     pub extern "C" fn _start() {
         let argc = unsafe { arg_count() };
         
-        if argc < 1 {
-            log("Usage: tail [-n NUM] <file...>\n");
-            return;
-        }
-        
         let mut num_lines = 10usize;
         let mut files: [(usize, usize); 16] = [(0, 0); 16];
         let mut file_count = 0usize;
@@ -135,8 +189,17 @@ mod wasm {
             i += 1;
         }
         
+        // Without files, show the end of piped input
         if file_count == 0 {
-            log("Usage: tail [-n NUM] <file...>\n");
+            let mut content = [0u8; 65536];
+            let read_len = read_stdin(&mut content);
+            if read_len < 0 {
+                log("Usage: tail [-n NUM] <file...>\n");
+                return;
+            }
+            if read_len > 0 {
+                tail_content(&content[..read_len as usize], num_lines);
+            }
             return;
         }
         
@@ -173,43 +236,7 @@ mod wasm {
                 log(" <==\x1b[0m\n");
             }
             
-            let content = &content[..read_len as usize];
-            
-            // Count lines and find positions
-            let mut line_positions: [usize; 1024] = [0; 1024];
-            let mut line_count = 0usize;
-            line_positions[0] = 0;
-            
-            for (idx, &c) in content.iter().enumerate() {
-                if c == b'\n' && idx + 1 < content.len() && line_count + 1 < 1024 {
-                    line_count += 1;
-                    line_positions[line_count] = idx + 1;
-                }
-            }
-            line_count += 1; // Total number of lines
-            
-            // Calculate start line
-            let start_line = if line_count > num_lines {
-                line_count - num_lines
-            } else {
-                0
-            };
-            
-            // Print lines from start_line onwards
-            for line_idx in start_line..line_count {
-                let line_start = line_positions[line_idx];
-                let line_end = if line_idx + 1 < line_count {
-                    line_positions[line_idx + 1] - 1 // Exclude newline
-                } else {
-                    content.len()
-                };
-                
-                if line_start < content.len() {
-                    let end = line_end.min(content.len());
-                    unsafe { print(content[line_start..end].as_ptr(), end - line_start) };
-                    log("\n");
-                }
-            }
+            tail_content(&content[..read_len as usize], num_lines);
         }
     }
 }
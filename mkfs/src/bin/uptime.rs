@@ -1,7 +1,7 @@
 // uptime - Show system uptime
 //
 // Usage:
-//   uptime        Show how long the system has been running
+//   uptime        Show the time of day and how long the system has been running
 
 #![cfg_attr(target_arch = "wasm32", no_std)]
 #![cfg_attr(target_arch = "wasm32", no_main)]
@@ -14,6 +14,7 @@ mod wasm {
     extern "C" {
         fn print(ptr: *const u8, len: usize);
         fn time() -> i64;
+        fn time_unix() -> i64;
     }
 
     fn log(s: &str) {
@@ -39,15 +40,31 @@ mod wasm {
         unsafe { print(buf[i..].as_ptr(), buf.len() - i) };
     }
 
+    fn print_2digits(n: i64) {
+        if n < 10 {
+            log("0");
+        }
+        print_num(n);
+    }
+
     #[no_mangle]
     pub extern "C" fn _start() {
+        // Time of day (UTC) from the wall clock, as the RTC or NTP set it
+        let day_sec = unsafe { time_unix() }.rem_euclid(86_400);
+        print_2digits(day_sec / 3600);
+        log(":");
+        print_2digits(day_sec / 60 % 60);
+        log(":");
+        print_2digits(day_sec % 60);
+        log(" ");
+
         let ms = unsafe { time() };
         let total_sec = ms / 1000;
         let hours = total_sec / 3600;
         let minutes = (total_sec % 3600) / 60;
         let seconds = total_sec % 60;
         
-        log("Uptime: ");
+        log("up ");
         
         if hours > 0 {
             print_num(hours);
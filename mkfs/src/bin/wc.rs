@@ -0,0 +1,232 @@
+// wc - Count lines, words and bytes
+//
+// Usage:
+//   wc <file...>          Show line, word and byte counts
+//   wc -l <file...>       Count lines only (-w words, -c bytes)
+//   cmd | wc [-lwc]       Count piped input
+
+#![cfg_attr(target_arch = "wasm32", no_std)]
+#![cfg_attr(target_arch = "wasm32", no_main)]
+
+#[cfg(target_arch = "wasm32")]
+extern crate mkfs;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    extern "C" {
+        fn print(ptr: *const u8, len: usize);
+        fn arg_count() -> i32;
+        fn arg_get(index: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        fn stdin_read(buf_ptr: *mut u8, buf_len: i32) -> i32;
+        fn cwd_get(buf_ptr: *mut u8, buf_len: i32) -> i32;
+        fn fs_read(path_ptr: *const u8, path_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+    }
+
+    fn log(s: &str) {
+        unsafe { print(s.as_ptr(), s.len()) };
+    }
+
+    /// Print `n` right-aligned in `width` columns
+    fn print_num(mut n: usize, width: usize) {
+        let mut buf = [b' '; 20];
+        let mut i = buf.len();
+        loop {
+            i -= 1;
+            buf[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 || i == 0 {
+                break;
+            }
+        }
+        let start = i.min(buf.len().saturating_sub(width));
+        unsafe { print(buf[start..].as_ptr(), buf.len() - start) };
+    }
+
+    /// Read all piped input into `buf`, or -1 if there is none
+    fn read_stdin(buf: &mut [u8]) -> i32 {
+        let mut total = 0usize;
+        while total < buf.len() {
+            let len = unsafe { stdin_read(buf[total..].as_mut_ptr(), (buf.len() - total) as i32) };
+            if len < 0 {
+                return -1;
+            }
+            if len == 0 {
+                break;
+            }
+            total += len as usize;
+        }
+        total as i32
+    }
+
+    #[derive(Clone, Copy, Default)]
+    struct Counts {
+        lines: usize,
+        words: usize,
+        bytes: usize,
+    }
+
+    fn count(content: &[u8]) -> Counts {
+        let mut counts = Counts { bytes: content.len(), ..Counts::default() };
+        let mut in_word = false;
+        for &c in content {
+            if c == b'\n' {
+                counts.lines += 1;
+            }
+            let space = c == b' ' || c == b'\t' || c == b'\n' || c == b'\r';
+            if !space && !in_word {
+                counts.words += 1;
+            }
+            in_word = !space;
+        }
+        counts
+    }
+
+    struct Columns {
+        lines: bool,
+        words: bool,
+        bytes: bool,
+    }
+
+    fn print_counts(counts: Counts, columns: &Columns, name: Option<&[u8]>) {
+        if columns.lines {
+            print_num(counts.lines, 7);
+        }
+        if columns.words {
+            log(" ");
+            print_num(counts.words, 7);
+        }
+        if columns.bytes {
+            log(" ");
+            print_num(counts.bytes, 7);
+        }
+        if let Some(name) = name {
+            log(" ");
+            unsafe { print(name.as_ptr(), name.len()) };
+        }
+        log("\n");
+    }
+
+    fn resolve_path(arg: &[u8], out: &mut [u8]) -> usize {
+        let mut cwd = [0u8; 256];
+        let cwd_len = unsafe { cwd_get(cwd.as_mut_ptr(), cwd.len() as i32) };
+
+        if arg.starts_with(b"/") {
+            let len = arg.len().min(out.len());
+            out[..len].copy_from_slice(&arg[..len]);
+            len
+        } else if cwd_len > 0 {
+            let cwd_len = cwd_len as usize;
+            let copy_len = cwd_len.min(out.len());
+            out[..copy_len].copy_from_slice(&cwd[..copy_len]);
+            let mut pos = copy_len;
+
+            if pos < out.len() && pos > 0 && out[pos - 1] != b'/' {
+                out[pos] = b'/';
+                pos += 1;
+            }
+
+            let remaining = out.len() - pos;
+            let copy_len = arg.len().min(remaining);
+            out[pos..pos + copy_len].copy_from_slice(&arg[..copy_len]);
+            pos + copy_len
+        } else {
+            if out.len() > 0 {
+                out[0] = b'/';
+            }
+            let copy_len = arg.len().min(out.len() - 1);
+            out[1..1 + copy_len].copy_from_slice(&arg[..copy_len]);
+            1 + copy_len
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn _start() {
+        let argc = unsafe { arg_count() };
+
+        let mut columns = Columns { lines: false, words: false, bytes: false };
+        let mut files: [(usize, usize); 16] = [(0, 0); 16];
+        let mut file_count = 0usize;
+        let mut args_storage = [0u8; 4096];
+        let mut storage_pos = 0usize;
+
+        // Parse arguments
+        for i in 0..argc {
+            let mut arg_buf = [0u8; 256];
+            let arg_len = unsafe { arg_get(i, arg_buf.as_mut_ptr(), 256) };
+            if arg_len <= 0 {
+                continue;
+            }
+            let arg = &arg_buf[..arg_len as usize];
+
+            if arg.starts_with(b"-") && arg.len() > 1 {
+                for &c in &arg[1..] {
+                    match c {
+                        b'l' => columns.lines = true,
+                        b'w' => columns.words = true,
+                        b'c' => columns.bytes = true,
+                        _ => {}
+                    }
+                }
+            } else if file_count < 16 {
+                let remaining = args_storage.len() - storage_pos;
+                let copy_len = arg.len().min(remaining);
+                if copy_len > 0 {
+                    args_storage[storage_pos..storage_pos + copy_len].copy_from_slice(&arg[..copy_len]);
+                    files[file_count] = (storage_pos, copy_len);
+                    storage_pos += copy_len;
+                    file_count += 1;
+                }
+            }
+        }
+
+        // No flags means all three columns
+        if !columns.lines && !columns.words && !columns.bytes {
+            columns = Columns { lines: true, words: true, bytes: true };
+        }
+
+        let mut content = [0u8; 65536];
+
+        // Without files, count piped input
+        if file_count == 0 {
+            let read_len = read_stdin(&mut content);
+            if read_len < 0 {
+                log("Usage: wc [-lwc] <file...>\n");
+                return;
+            }
+            print_counts(count(&content[..read_len as usize]), &columns, None);
+            return;
+        }
+
+        let mut total = Counts::default();
+        for f in 0..file_count {
+            let (start, len) = files[f];
+            let file_arg = &args_storage[start..start + len];
+
+            let mut path_buf = [0u8; 512];
+            let path_len = resolve_path(file_arg, &mut path_buf);
+
+            let read_len = unsafe {
+                fs_read(path_buf.as_ptr(), path_len as i32, content.as_mut_ptr(), content.len() as i32)
+            };
+            if read_len < 0 {
+                log("\x1b[1;31mwc:\x1b[0m ");
+                unsafe { print(file_arg.as_ptr(), file_arg.len()) };
+                log(": No such file\n");
+                continue;
+            }
+
+            let counts = count(&content[..read_len as usize]);
+            total.lines += counts.lines;
+            total.words += counts.words;
+            total.bytes += counts.bytes;
+            print_counts(counts, &columns, Some(file_arg));
+        }
+
+        if file_count > 1 {
+            print_counts(total, &columns, Some(b"total"));
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {}
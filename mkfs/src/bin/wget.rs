@@ -1,4 +1,9 @@
 // wget - Download files from the web
+//
+// Usage:
+//   wget <url>              Save to the file named in the URL (index.html for /)
+//   wget <url> -O <file>    Save to <file>
+//   wget <url> -O -         Print to stdout
 
 #![cfg_attr(target_arch = "wasm32", no_std)]
 #![cfg_attr(target_arch = "wasm32", no_main)]
@@ -28,7 +33,7 @@ mod wasm {
         let argc = unsafe { arg_count() };
         
         if argc < 1 {
-            log("Usage: wget <url> [-O file]\n");
+            log("Usage: wget <url> [-O file|-]\n");
             return;
         }
 
@@ -58,13 +63,21 @@ mod wasm {
             i += 1;
         }
 
+        // Without -O, name the file after the last part of the URL path
+        if rel_path_len <= 0 {
+            rel_path_len = url_file_name(&url_buf[..url_len as usize], &mut rel_path_buf) as i32;
+        }
+        let to_stdout = rel_path_len == 1 && rel_path_buf[0] == b'-';
+
         log("Fetching: ");
         unsafe { print(url_buf.as_ptr(), url_len as usize) };
         log("\n");
 
         // Make HTTP request
-        let mut resp_buf = [0u8; 16384];
-        let resp_len = unsafe { http_get(url_buf.as_ptr(), url_len, resp_buf.as_mut_ptr(), 16384) };
+        let mut resp_buf = [0u8; 65536];
+        let resp_len = unsafe {
+            http_get(url_buf.as_ptr(), url_len, resp_buf.as_mut_ptr(), resp_buf.len() as i32)
+        };
         
         if resp_len < 0 {
             log("Error: Request failed\n");
@@ -75,7 +88,7 @@ mod wasm {
         print_num(resp_len as i64);
         log(" bytes\n");
 
-        if rel_path_len > 0 {
+        if !to_stdout {
             // Build absolute path
             let mut abs_path_buf = [0u8; 256];
             let abs_path_len: i32;
@@ -141,6 +154,30 @@ mod wasm {
         }
     }
 
+    /// Last segment of the URL path (without query), or index.html
+    fn url_file_name(url: &[u8], out: &mut [u8]) -> usize {
+        let after_scheme = match url.windows(3).position(|w| w == b"://") {
+            Some(pos) => &url[pos + 3..],
+            None => url,
+        };
+        let path = match after_scheme.iter().position(|&c| c == b'/') {
+            Some(pos) => &after_scheme[pos..],
+            None => &[],
+        };
+        let path = match path.iter().position(|&c| c == b'?' || c == b'#') {
+            Some(pos) => &path[..pos],
+            None => path,
+        };
+        let name = match path.iter().rposition(|&c| c == b'/') {
+            Some(pos) => &path[pos + 1..],
+            None => path,
+        };
+        let name: &[u8] = if name.is_empty() { b"index.html" } else { name };
+        let len = name.len().min(out.len());
+        out[..len].copy_from_slice(&name[..len]);
+        len
+    }
+
     fn print_num(mut n: i64) {
         if n < 0 {
             log("-");
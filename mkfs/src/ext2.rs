@@ -0,0 +1,820 @@
+//! ext2 images, as mounted by the kernel's ext2 driver
+//!
+//! Images are written with 1 KiB blocks, 128-byte inodes and directory file
+//! types, the layout `mke2fs -t ext2 -b 1024 -O ^sparse_super` produces, so
+//! `e2fsck` and `debugfs` can check and edit them too. Every group keeps a
+//! copy of the superblock and group descriptors, and `lost+found` is created
+//! for `e2fsck`. Reading accepts any block size and indirect depth the
+//! kernel driver accepts.
+
+use std::collections::BTreeMap;
+
+use crate::image::{parent, BuildError, Built, Entry, Tree};
+
+const BLOCK_SIZE: usize = 1024;
+const BLOCKS_PER_GROUP: u32 = 8192;
+const INODE_SIZE: usize = 128;
+/// One inode for every this many bytes of disk, as `mke2fs` does
+const BYTES_PER_INODE: u64 = 4096;
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+const ROOT_INO: u32 = 2;
+const FIRST_INO: u32 = 11;
+const DIRECT_BLOCKS: usize = 12;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+const LOST_AND_FOUND: &str = "/lost+found";
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn put16(buf: &mut [u8], offset: usize, val: u16) {
+    buf[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// Space a directory entry with a name of `name_len` bytes takes
+fn entry_len(name_len: usize) -> usize {
+    (8 + name_len).next_multiple_of(4)
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// WRITING
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Where the metadata of each block group lives
+#[derive(Clone, Copy)]
+struct Layout {
+    blocks_count: u32,
+    group_count: u32,
+    inodes_per_group: u32,
+    gdt_blocks: u32,
+    inode_table_blocks: u32,
+}
+
+impl Layout {
+    fn new(size: u64, entries: usize) -> Result<Self, BuildError> {
+        let mut blocks_count = (size / BLOCK_SIZE as u64).min(u32::MAX as u64) as u32;
+        if blocks_count < 2 {
+            return Err(BuildError::Full);
+        }
+        let mut group_count = (blocks_count - 1).div_ceil(BLOCKS_PER_GROUP);
+
+        let wanted_inodes = (size / BYTES_PER_INODE).max((entries as u64) + FIRST_INO as u64 + 16);
+        let inodes_per_group = (wanted_inodes.div_ceil(group_count as u64) as u32)
+            .next_multiple_of((BLOCK_SIZE / INODE_SIZE) as u32)
+            .min(BLOCK_SIZE as u32 * 8);
+        let inode_table_blocks = inodes_per_group * INODE_SIZE as u32 / BLOCK_SIZE as u32;
+        let gdt_blocks = (group_count as usize * 32).div_ceil(BLOCK_SIZE) as u32;
+
+        // A last group too small for its own metadata is left out
+        let overhead = 1 + gdt_blocks + 2 + inode_table_blocks;
+        let last_len = blocks_count - 1 - (group_count - 1) * BLOCKS_PER_GROUP;
+        if last_len <= overhead {
+            blocks_count -= last_len;
+            group_count -= 1;
+        }
+        if group_count == 0 {
+            return Err(BuildError::Full);
+        }
+
+        Ok(Self {
+            blocks_count,
+            group_count,
+            inodes_per_group,
+            gdt_blocks,
+            inode_table_blocks,
+        })
+    }
+
+    fn group_start(&self, group: u32) -> u32 {
+        1 + group * BLOCKS_PER_GROUP
+    }
+
+    fn group_len(&self, group: u32) -> u32 {
+        (self.blocks_count - self.group_start(group)).min(BLOCKS_PER_GROUP)
+    }
+
+    fn block_bitmap(&self, group: u32) -> u32 {
+        self.group_start(group) + 1 + self.gdt_blocks
+    }
+
+    fn inode_bitmap(&self, group: u32) -> u32 {
+        self.block_bitmap(group) + 1
+    }
+
+    fn inode_table(&self, group: u32) -> u32 {
+        self.inode_bitmap(group) + 1
+    }
+
+    fn inodes_count(&self) -> u32 {
+        self.inodes_per_group * self.group_count
+    }
+}
+
+struct Writer {
+    layout: Layout,
+    image: Vec<u8>,
+    /// Whether each block is in use
+    used: Vec<bool>,
+    /// Where the next block search starts
+    cursor: u32,
+    inodes: Vec<[u8; INODE_SIZE]>,
+}
+
+impl Writer {
+    fn block_mut(&mut self, block: u32) -> &mut [u8] {
+        let offset = block as usize * BLOCK_SIZE;
+        &mut self.image[offset..offset + BLOCK_SIZE]
+    }
+
+    fn alloc(&mut self) -> Result<u32, BuildError> {
+        let blocks_count = self.layout.blocks_count;
+        let block = (self.cursor..blocks_count)
+            .find(|&b| !self.used[b as usize])
+            .ok_or(BuildError::Full)?;
+        self.used[block as usize] = true;
+        self.cursor = block + 1;
+        Ok(block)
+    }
+
+    /// Store `data` in new blocks and point `inode` at them
+    fn store(&mut self, ino: u32, data: &[u8]) -> Result<(), BuildError> {
+        let count = data.len().div_ceil(BLOCK_SIZE);
+        let mut blocks = Vec::with_capacity(count);
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let block = self.alloc()?;
+            self.block_mut(block)[..chunk.len()].copy_from_slice(chunk);
+            blocks.push(block);
+        }
+
+        let mut pointers = [0u32; 15];
+        let direct = blocks.len().min(DIRECT_BLOCKS);
+        pointers[..direct].copy_from_slice(&blocks[..direct]);
+        let mut rest = &blocks[direct..];
+        let mut meta = 0;
+        let per_block = BLOCK_SIZE / 4;
+        for depth in 1..=3u32 {
+            if rest.is_empty() {
+                break;
+            }
+            let (now, later) = rest.split_at(rest.len().min(per_block.pow(depth)));
+            pointers[DIRECT_BLOCKS + depth as usize - 1] =
+                self.pointer_block(now, depth, &mut meta)?;
+            rest = later;
+        }
+        if !rest.is_empty() {
+            return Err(BuildError::Invalid(String::from("file too large for ext2")));
+        }
+
+        let inode = &mut self.inodes[ino as usize - 1];
+        for (i, &pointer) in pointers.iter().enumerate() {
+            put32(inode, 40 + i * 4, pointer);
+        }
+        put32(inode, 4, data.len() as u32);
+        put32(inode, 28, ((blocks.len() + meta) * BLOCK_SIZE / 512) as u32);
+        Ok(())
+    }
+
+    /// Write a block of pointers to `blocks`, through `depth - 1` further
+    /// levels of pointer blocks
+    fn pointer_block(
+        &mut self,
+        blocks: &[u32],
+        depth: u32,
+        meta: &mut usize,
+    ) -> Result<u32, BuildError> {
+        let block = self.alloc()?;
+        *meta += 1;
+        let pointers = if depth == 1 {
+            blocks.to_vec()
+        } else {
+            let span = (BLOCK_SIZE / 4).pow(depth - 1);
+            blocks
+                .chunks(span)
+                .map(|chunk| self.pointer_block(chunk, depth - 1, meta))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let buf = self.block_mut(block);
+        for (i, &pointer) in pointers.iter().enumerate() {
+            put32(buf, i * 4, pointer);
+        }
+        Ok(block)
+    }
+}
+
+/// Directory contents as raw blocks
+fn dir_blocks(entries: &[(u32, u8, &str)]) -> Vec<u8> {
+    let mut data = vec![0u8; BLOCK_SIZE];
+    let mut block_start = 0;
+    let mut pos = 0;
+    let mut last = 0;
+    for &(ino, file_type, name) in entries {
+        let len = entry_len(name.len());
+        if pos + len > block_start + BLOCK_SIZE {
+            // Stretch the last entry of the full block to its end
+            put16(
+                &mut data,
+                last + 4,
+                (block_start + BLOCK_SIZE - last) as u16,
+            );
+            block_start += BLOCK_SIZE;
+            pos = block_start;
+            data.resize(block_start + BLOCK_SIZE, 0);
+        }
+        put32(&mut data, pos, ino);
+        put16(&mut data, pos + 4, len as u16);
+        data[pos + 6] = name.len() as u8;
+        data[pos + 7] = file_type;
+        data[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+        last = pos;
+        pos += len;
+    }
+    put16(
+        &mut data,
+        last + 4,
+        (block_start + BLOCK_SIZE - last) as u16,
+    );
+    data
+}
+
+/// Build an ext2 image of `size` bytes holding `tree`, with `now` as its
+/// last write time
+pub fn build(tree: &Tree, size: u64, now: u32) -> Result<Built, BuildError> {
+    if let Some(entry) = tree.entries().find(|e| e.name().len() > 255) {
+        return Err(BuildError::Invalid(format!(
+            "{}: name too long",
+            entry.path
+        )));
+    }
+
+    // `lost+found` goes in inode 11, like `mke2fs` puts it
+    let mut entries: Vec<&Entry> = tree.entries().collect();
+    let lost_and_found = Entry::dir(LOST_AND_FOUND, 0o700, now);
+    if !entries.iter().any(|e| e.path == LOST_AND_FOUND) {
+        entries.push(&lost_and_found);
+    }
+    let layout = Layout::new(size, entries.len())?;
+
+    let mut inos = BTreeMap::new();
+    let mut next_ino = FIRST_INO + 1;
+    for entry in &entries {
+        let ino = match entry.path.as_str() {
+            "/" => ROOT_INO,
+            LOST_AND_FOUND => FIRST_INO,
+            _ => {
+                next_ino += 1;
+                next_ino - 1
+            }
+        };
+        inos.insert(entry.path.as_str(), ino);
+    }
+    if next_ino - 1 > layout.inodes_count() {
+        return Err(BuildError::Full);
+    }
+
+    let mut writer = Writer {
+        image: vec![0u8; layout.blocks_count as usize * BLOCK_SIZE],
+        used: vec![false; layout.blocks_count as usize],
+        cursor: 0,
+        inodes: vec![[0u8; INODE_SIZE]; layout.inodes_count() as usize],
+        layout,
+    };
+
+    // Boot block and group metadata
+    writer.used[0] = true;
+    for group in 0..writer.layout.group_count {
+        let start = writer.layout.group_start(group);
+        let end = writer.layout.inode_table(group) + writer.layout.inode_table_blocks;
+        for block in start..end {
+            writer.used[block as usize] = true;
+        }
+    }
+
+    // Children of each directory
+    let mut children: BTreeMap<&str, Vec<(u32, u8, &str)>> = BTreeMap::new();
+    for entry in entries.iter().filter(|e| e.path != "/") {
+        let file_type = if entry.is_dir() { FT_DIR } else { FT_REG_FILE };
+        children.entry(parent(&entry.path)).or_default().push((
+            inos[entry.path.as_str()],
+            file_type,
+            entry.name(),
+        ));
+    }
+
+    for entry in &entries {
+        let ino = inos[entry.path.as_str()];
+        let inode = &mut writer.inodes[ino as usize - 1];
+        let kind = if entry.is_dir() { S_IFDIR } else { S_IFREG };
+        put16(inode, 0, kind | (entry.mode & 0o7777));
+        for offset in [8, 12, 16] {
+            put32(inode, offset, entry.mtime);
+        }
+
+        match &entry.data {
+            Some(data) => {
+                put16(inode, 26, 1);
+                writer.store(ino, data)?;
+            }
+            None => {
+                let own = children
+                    .get(entry.path.as_str())
+                    .map_or(&[][..], |c| c.as_slice());
+                let subdirs = own.iter().filter(|c| c.1 == FT_DIR).count();
+                put16(&mut writer.inodes[ino as usize - 1], 26, 2 + subdirs as u16);
+                let parent_ino = inos[parent(&entry.path)];
+                let mut list = vec![(ino, FT_DIR, "."), (parent_ino, FT_DIR, "..")];
+                list.extend_from_slice(own);
+                writer.store(ino, &dir_blocks(&list))?;
+            }
+        }
+    }
+
+    let mut used_inodes: Vec<u32> = (1..FIRST_INO).chain(inos.values().copied()).collect();
+    used_inodes.sort_unstable();
+    used_inodes.dedup();
+    let dirs: Vec<u32> = entries
+        .iter()
+        .filter(|e| e.is_dir())
+        .map(|e| inos[e.path.as_str()])
+        .collect();
+    Ok(finish(writer, &used_inodes, &dirs, now))
+}
+
+/// Write the inode tables, bitmaps, group descriptors and superblocks
+fn finish(mut writer: Writer, used_inodes: &[u32], dirs: &[u32], now: u32) -> Built {
+    let layout = writer.layout;
+    let ipg = layout.inodes_per_group;
+    let mut groups = vec![0u8; layout.gdt_blocks as usize * BLOCK_SIZE];
+    let (mut free_blocks, mut free_inodes) = (0u32, 0u32);
+
+    let mut bitmaps = Vec::new();
+    for group in 0..layout.group_count {
+        let start = layout.group_start(group);
+        let len = layout.group_len(group);
+
+        // Bits past the end of the group are set, as e2fsck expects
+        let mut block_bitmap = vec![0xFFu8; BLOCK_SIZE];
+        let mut group_free_blocks = 0;
+        for i in 0..BLOCK_SIZE as u32 * 8 {
+            let free = i < len && !writer.used[(start + i) as usize];
+            if free {
+                block_bitmap[i as usize / 8] &= !(1 << (i % 8));
+                group_free_blocks += 1;
+            }
+        }
+
+        let mut inode_bitmap = vec![0xFFu8; BLOCK_SIZE];
+        for i in 0..ipg {
+            inode_bitmap[i as usize / 8] &= !(1 << (i % 8));
+        }
+        let first = group * ipg + 1;
+        let mut group_used_inodes = 0;
+        for &ino in used_inodes
+            .iter()
+            .filter(|&&ino| ino >= first && ino < first + ipg)
+        {
+            let i = ino - first;
+            inode_bitmap[i as usize / 8] |= 1 << (i % 8);
+            group_used_inodes += 1;
+        }
+        let group_dirs = dirs
+            .iter()
+            .filter(|&&ino| ino >= first && ino < first + ipg)
+            .count();
+
+        let desc = &mut groups[group as usize * 32..group as usize * 32 + 32];
+        put32(desc, 0, layout.block_bitmap(group));
+        put32(desc, 4, layout.inode_bitmap(group));
+        put32(desc, 8, layout.inode_table(group));
+        put16(desc, 12, group_free_blocks as u16);
+        put16(desc, 14, (ipg - group_used_inodes) as u16);
+        put16(desc, 16, group_dirs as u16);
+
+        free_blocks += group_free_blocks;
+        free_inodes += ipg - group_used_inodes;
+        bitmaps.push((block_bitmap, inode_bitmap));
+    }
+
+    let mut superblock = vec![0u8; 1024];
+    put32(&mut superblock, 0, layout.inodes_count());
+    put32(&mut superblock, 4, layout.blocks_count);
+    put32(&mut superblock, 12, free_blocks);
+    put32(&mut superblock, 16, free_inodes);
+    put32(&mut superblock, 20, 1); // First data block
+    put32(&mut superblock, 32, BLOCKS_PER_GROUP);
+    put32(&mut superblock, 36, BLOCKS_PER_GROUP); // Fragments per group
+    put32(&mut superblock, 40, ipg);
+    put32(&mut superblock, 48, now); // Last write
+    put16(&mut superblock, 54, u16::MAX); // No forced checks by mount count
+    put16(&mut superblock, 56, MAGIC);
+    put16(&mut superblock, 58, 1); // Clean
+    put16(&mut superblock, 60, 1); // Continue on errors
+    put32(&mut superblock, 64, now); // Last check
+    put32(&mut superblock, 76, 1); // Dynamic revision
+    put32(&mut superblock, 84, FIRST_INO);
+    put16(&mut superblock, 88, INODE_SIZE as u16);
+    put32(&mut superblock, 96, INCOMPAT_FILETYPE);
+    // A UUID derived from the contents keeps images reproducible
+    let seed = crate::image::crc32(&writer.image);
+    for (i, chunk) in superblock[104..120].chunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&(seed ^ now).rotate_left(i as u32 * 8).to_le_bytes());
+    }
+    superblock[120..124].copy_from_slice(b"bavy");
+
+    for group in 0..layout.group_count {
+        let start = layout.group_start(group);
+        let offset = start as usize * BLOCK_SIZE;
+        let mut copy = superblock.clone();
+        put16(&mut copy, 90, group as u16);
+        writer.image[offset..offset + BLOCK_SIZE].copy_from_slice(&copy);
+        let offset = (start as usize + 1) * BLOCK_SIZE;
+        writer.image[offset..offset + groups.len()].copy_from_slice(&groups);
+
+        let (block_bitmap, inode_bitmap) = &bitmaps[group as usize];
+        writer
+            .block_mut(layout.block_bitmap(group))
+            .copy_from_slice(block_bitmap);
+        writer
+            .block_mut(layout.inode_bitmap(group))
+            .copy_from_slice(inode_bitmap);
+
+        let table = layout.inode_table(group) as usize * BLOCK_SIZE;
+        for i in 0..ipg as usize {
+            let inode = writer.inodes[group as usize * ipg as usize + i];
+            writer.image[table + i * INODE_SIZE..table + (i + 1) * INODE_SIZE]
+                .copy_from_slice(&inode);
+        }
+    }
+
+    Built {
+        image: writer.image,
+        free_bytes: free_blocks as u64 * BLOCK_SIZE as u64,
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// READING
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Whether `image` holds an ext2 filesystem
+pub fn detect(image: &[u8]) -> bool {
+    image.len() >= SUPERBLOCK_OFFSET + 1024 && le16(image, SUPERBLOCK_OFFSET + 56) == MAGIC
+}
+
+struct Reader<'a> {
+    image: &'a [u8],
+    block_size: usize,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    groups: Vec<u8>,
+    /// Blocks used by the inodes read so far
+    referenced: Vec<u32>,
+    /// Inodes reached from the root
+    inodes: Vec<u32>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(image: &'a [u8]) -> Result<Self, String> {
+        if !detect(image) {
+            return Err(String::from("not an ext2 image"));
+        }
+        let sb = &image[SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 1024];
+        let log_block_size = le32(sb, 24);
+        if log_block_size > 2 {
+            return Err(format!(
+                "unsupported block size {}",
+                1024u64 << log_block_size
+            ));
+        }
+        let rev = le32(sb, 76);
+        let inode_size = if rev == 0 {
+            INODE_SIZE
+        } else {
+            le16(sb, 88) as usize
+        };
+        let incompat = if rev == 0 { 0 } else { le32(sb, 96) };
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(format!("unsupported incompatible features {:#x}", incompat));
+        }
+
+        let mut reader = Self {
+            image,
+            block_size: 1024 << log_block_size,
+            blocks_count: le32(sb, 4),
+            first_data_block: le32(sb, 20),
+            blocks_per_group: le32(sb, 32),
+            inodes_per_group: le32(sb, 40),
+            inode_size,
+            groups: Vec::new(),
+            referenced: Vec::new(),
+            inodes: Vec::new(),
+        };
+        if reader.blocks_per_group == 0
+            || reader.inodes_per_group == 0
+            || reader.blocks_count <= reader.first_data_block
+        {
+            return Err(String::from("corrupt superblock"));
+        }
+        let table_len = (reader.group_count() as usize * 32).div_ceil(reader.block_size);
+        for i in 0..table_len {
+            let block = reader.block(reader.first_data_block + 1 + i as u32)?;
+            reader.groups.extend_from_slice(block);
+        }
+        Ok(reader)
+    }
+
+    fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    fn block(&self, block: u32) -> Result<&'a [u8], String> {
+        let offset = block as usize * self.block_size;
+        self.image
+            .get(offset..offset + self.block_size)
+            .ok_or_else(|| format!("block {} is past the end of the image", block))
+    }
+
+    fn inode(&self, ino: u32) -> Result<&'a [u8], String> {
+        let group = (ino - 1) / self.inodes_per_group;
+        let index = ((ino - 1) % self.inodes_per_group) as usize;
+        if group >= self.group_count() {
+            return Err(format!("inode {} out of range", ino));
+        }
+        let table = le32(&self.groups, group as usize * 32 + 8) as usize;
+        let offset = table * self.block_size + index * self.inode_size;
+        self.image
+            .get(offset..offset + INODE_SIZE)
+            .ok_or_else(|| format!("inode {} is past the end of the image", ino))
+    }
+
+    /// Data blocks of an inode in order, noting every block it uses
+    fn data_blocks(&mut self, inode: &[u8]) -> Result<Vec<u32>, String> {
+        let mut blocks = Vec::new();
+        for i in 0..15 {
+            let pointer = le32(inode, 40 + i * 4);
+            if pointer == 0 {
+                continue;
+            }
+            let depth = i.saturating_sub(DIRECT_BLOCKS - 1);
+            self.collect(pointer, depth, &mut blocks)?;
+        }
+        Ok(blocks)
+    }
+
+    fn collect(&mut self, block: u32, depth: usize, out: &mut Vec<u32>) -> Result<(), String> {
+        if block >= self.blocks_count {
+            return Err(format!("block {} is past the end of the filesystem", block));
+        }
+        self.referenced.push(block);
+        if depth == 0 {
+            out.push(block);
+            return Ok(());
+        }
+        let pointers = self.block(block)?;
+        for i in 0..self.block_size / 4 {
+            let pointer = le32(pointers, i * 4);
+            if pointer != 0 {
+                self.collect(pointer, depth - 1, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, inode: &[u8]) -> Result<Vec<u8>, String> {
+        // The high half of the size is only meaningful for regular files
+        let mut size = le32(inode, 4) as u64;
+        if le16(inode, 0) & S_IFMT == S_IFREG {
+            size |= (le32(inode, 108) as u64) << 32;
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        for block in self.data_blocks(inode)? {
+            data.extend_from_slice(self.block(block)?);
+        }
+        // Holes read as zeros
+        data.resize(size as usize, 0);
+        Ok(data)
+    }
+
+    fn walk(&mut self, ino: u32, path: &str, tree: &mut Tree) -> Result<(), String> {
+        self.inodes.push(ino);
+        let inode = self.inode(ino)?;
+        let dir = self.data(inode)?;
+        let mut pos = 0;
+        while pos + 8 <= dir.len() {
+            let child = le32(&dir, pos);
+            let rec_len = le16(&dir, pos + 4) as usize;
+            let name_len = dir[pos + 6] as usize;
+            if rec_len < 8 || pos + 8 + name_len > dir.len() {
+                return Err(format!("{}: corrupt directory", path));
+            }
+            let name = String::from_utf8_lossy(&dir[pos + 8..pos + 8 + name_len]).into_owned();
+            pos += rec_len;
+            if child == 0 || name == "." || name == ".." {
+                continue;
+            }
+
+            let child_path = if path == "/" {
+                format!("/{}", name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+            let inode = self.inode(child)?;
+            let mode = le16(inode, 0);
+            let (perm, mtime) = (mode & 0o7777, le32(inode, 16));
+            match mode & S_IFMT {
+                S_IFDIR => {
+                    tree.insert(Entry::dir(&child_path, perm, mtime));
+                    self.walk(child, &child_path, tree)?;
+                }
+                S_IFREG => {
+                    self.inodes.push(child);
+                    let data = self
+                        .data(inode)
+                        .map_err(|e| format!("{}: {}", child_path, e))?;
+                    tree.insert(Entry::file(&child_path, data, perm, mtime));
+                }
+                // Symlinks, devices and the like have no SFS equivalent
+                _ => self.inodes.push(child),
+            }
+        }
+        Ok(())
+    }
+
+    fn bit_set(&self, bitmap_offset: usize, index: u32) -> Result<bool, String> {
+        let bitmap = self.block(le32(&self.groups, bitmap_offset))?;
+        Ok(bitmap[index as usize / 8] & (1 << (index % 8)) != 0)
+    }
+}
+
+/// Read every directory and regular file of an ext2 image
+pub fn read(image: &[u8]) -> Result<Tree, String> {
+    let mut reader = Reader::new(image)?;
+    let root = reader.inode(ROOT_INO)?;
+    let mut tree = Tree::new(le32(root, 16));
+    reader.walk(ROOT_INO, "/", &mut tree)?;
+    Ok(tree)
+}
+
+/// Check the allocation bitmaps against the blocks and inodes reachable
+/// from the root, returning the problems found
+pub fn check(image: &[u8]) -> Result<Vec<String>, String> {
+    let mut reader = Reader::new(image)?;
+    let mut tree = Tree::new(0);
+    reader.walk(ROOT_INO, "/", &mut tree)?;
+
+    let mut problems = Vec::new();
+    let mut referenced = std::mem::take(&mut reader.referenced);
+    referenced.sort_unstable();
+    let before = referenced.len();
+    referenced.dedup();
+    if referenced.len() != before {
+        problems.push(format!(
+            "{} blocks are used by more than one file",
+            before - referenced.len()
+        ));
+    }
+
+    let unmarked = referenced
+        .iter()
+        .map(|&block| {
+            let group = (block - reader.first_data_block) / reader.blocks_per_group;
+            let index = (block - reader.first_data_block) % reader.blocks_per_group;
+            reader.bit_set(group as usize * 32, index)
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|set| !set)
+        .count();
+    if unmarked > 0 {
+        problems.push(format!("{} blocks in use are marked free", unmarked));
+    }
+
+    let mut inodes = std::mem::take(&mut reader.inodes);
+    inodes.sort_unstable();
+    inodes.dedup();
+    let unmarked = inodes
+        .iter()
+        .map(|&ino| {
+            let group = (ino - 1) / reader.inodes_per_group;
+            reader.bit_set(group as usize * 32 + 4, (ino - 1) % reader.inodes_per_group)
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|set| !set)
+        .count();
+    if unmarked > 0 {
+        problems.push(format!("{} inodes in use are marked free", unmarked));
+    }
+
+    let free_in_groups: u32 = (0..reader.group_count())
+        .map(|g| le16(&reader.groups, g as usize * 32 + 12) as u32)
+        .sum();
+    let free = le32(image, SUPERBLOCK_OFFSET + 12);
+    if free != free_in_groups {
+        problems.push(format!(
+            "superblock counts {} free blocks, groups count {}",
+            free, free_in_groups
+        ));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Tree {
+        let mut tree = Tree::new(1_700_000_000);
+        tree.insert(Entry::file(
+            "/usr/bin/hello",
+            vec![0x5a; 300_000],
+            0o755,
+            1_700_000_001,
+        ));
+        tree.insert(Entry::file(
+            "/home/README.md",
+            b"hi\n".to_vec(),
+            0o644,
+            1_700_000_002,
+        ));
+        tree.insert(Entry::file("/empty", Vec::new(), 0o600, 1_700_000_003));
+        tree.insert(Entry::dir("/tmp", 0o1777, 1_700_000_004));
+        for i in 0..100 {
+            tree.insert(Entry::file(
+                &format!("/many/a-rather-long-file-name-{:03}", i),
+                vec![i as u8],
+                0o644,
+                0,
+            ));
+        }
+        tree
+    }
+
+    #[test]
+    fn build_and_read_round_trip() {
+        let tree = sample();
+        let built = build(&tree, 4 * 1024 * 1024, 1_700_000_100).unwrap();
+        assert!(detect(&built.image));
+        assert!(built.free_bytes > 3 * 1024 * 1024);
+
+        let read_back = read(&built.image).unwrap();
+        let mut expected: Vec<Entry> = tree.entries().cloned().collect();
+        expected.push(Entry::dir(LOST_AND_FOUND, 0o700, 1_700_000_100));
+        expected.sort_by(|a, b| a.path.cmp(&b.path));
+        let actual: Vec<Entry> = read_back.entries().cloned().collect();
+        assert_eq!(actual, expected);
+        assert!(check(&built.image).unwrap().is_empty());
+    }
+
+    #[test]
+    fn last_group_too_small_is_dropped() {
+        let size = (1 + BLOCKS_PER_GROUP as u64 + 20) * BLOCK_SIZE as u64;
+        let layout = Layout::new(size, 10).unwrap();
+        assert_eq!(layout.group_count, 1);
+        assert_eq!(layout.blocks_count, 1 + BLOCKS_PER_GROUP);
+    }
+
+    #[test]
+    fn full_image_is_reported() {
+        assert!(matches!(
+            build(&sample(), 256 * 1024, 0),
+            Err(BuildError::Full)
+        ));
+    }
+
+    #[test]
+    fn check_finds_blocks_marked_free() {
+        let mut built = build(&sample(), 1024 * 1024, 0).unwrap();
+        let layout = Layout::new(1024 * 1024, sample().entries().count() + 1).unwrap();
+        let bitmap = layout.block_bitmap(0) as usize * BLOCK_SIZE;
+        built.image[bitmap..bitmap + 64].fill(0);
+        let problems = check(&built.image).unwrap();
+        assert!(problems.iter().any(|p| p.contains("marked free")));
+    }
+}
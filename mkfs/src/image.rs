@@ -0,0 +1,163 @@
+//! Format-independent view of an image's contents
+//!
+//! The builders turn a list of [`Entry`] values into an image, and the readers
+//! turn an image back into one, so `ls`, `extract` and `verify` work the same
+//! on every format.
+
+use std::collections::BTreeMap;
+
+/// Mode bits given to entries that don't come from a host file
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+
+/// A file or directory in an image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// Absolute path without a trailing slash, `/` for the root
+    pub path: String,
+    /// File contents, `None` for a directory
+    pub data: Option<Vec<u8>>,
+    /// Permission bits
+    pub mode: u16,
+    /// Modification time, in seconds since the epoch
+    pub mtime: u32,
+}
+
+impl Entry {
+    pub fn file(path: &str, data: Vec<u8>, mode: u16, mtime: u32) -> Self {
+        Self {
+            path: String::from(path),
+            data: Some(data),
+            mode,
+            mtime,
+        }
+    }
+
+    pub fn dir(path: &str, mode: u16, mtime: u32) -> Self {
+        Self {
+            path: String::from(path),
+            data: None,
+            mode,
+            mtime,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.data.is_none()
+    }
+
+    /// Name of the entry within its directory
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or("")
+    }
+}
+
+/// Directory holding `path`, `/` for top-level entries
+pub fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+/// The contents of an image being built, keyed by path so later entries
+/// replace earlier ones and parents are always present
+#[derive(Default)]
+pub struct Tree {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Tree {
+    pub fn new(mtime: u32) -> Self {
+        let mut tree = Self::default();
+        tree.entries
+            .insert(String::from("/"), Entry::dir("/", DEFAULT_DIR_MODE, mtime));
+        tree
+    }
+
+    /// Add an entry, creating any missing parent directories
+    pub fn insert(&mut self, entry: Entry) {
+        let mut dir = parent(&entry.path);
+        let mtime = entry.mtime;
+        while !self.entries.contains_key(dir) {
+            self.entries
+                .insert(String::from(dir), Entry::dir(dir, DEFAULT_DIR_MODE, mtime));
+            dir = parent(dir);
+        }
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    /// Keep only the entries `keep` returns true for
+    pub fn retain(&mut self, mut keep: impl FnMut(&Entry) -> bool) {
+        self.entries.retain(|_, entry| keep(entry));
+    }
+
+    /// Entries sorted by path, so parents come before their children
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    pub fn files(&self) -> usize {
+        self.entries.values().filter(|e| !e.is_dir()).count()
+    }
+}
+
+/// An image produced by one of the builders
+pub struct Built {
+    pub image: Vec<u8>,
+    /// Space left for new files
+    pub free_bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// The contents don't fit in the requested size
+    Full,
+    /// The contents can't be stored in this format at any size
+    Invalid(String),
+}
+
+/// Render permission bits like `ls -l`
+pub fn mode_string(entry: &Entry) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(if entry.is_dir() { 'd' } else { '-' });
+    for shift in [6, 3, 0] {
+        let bits = entry.mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// CRC-32 (IEEE), as used by the checksum manifest
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn insert_creates_parents() {
+        let mut tree = Tree::new(0);
+        tree.insert(Entry::file("/usr/bin/hello", vec![1], 0o755, 0));
+        let paths: Vec<_> = tree.entries().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["/", "/usr", "/usr/bin", "/usr/bin/hello"]);
+        assert_eq!(parent("/usr"), "/");
+    }
+}
@@ -13,15 +13,24 @@ pub mod syscalls {
     use core::panic::PanicInfo;
 
     // --- System Calls provided by Kernel ---
+    // Keep in sync with kernel/src/wasm.rs (HOST_FUNCTIONS); `wasm check`
+    // in the kernel shell shows any a program imports that it lacks.
     extern "C" {
         /// Print a string to the console
         pub fn print(ptr: *const u8, len: usize);
         /// Get current time in milliseconds
         pub fn time() -> i64;
+        /// Get the wall clock in seconds since the Unix epoch (UTC)
+        pub fn time_unix() -> i64;
+        /// Get the date as `date` prints it into buffer, returns length or -1
+        pub fn date_get(buf_ptr: *mut u8, buf_len: i32) -> i32;
         /// Get number of command-line arguments
         pub fn arg_count() -> i32;
         /// Get argument at index into buffer, returns actual length or -1 on error
         pub fn arg_get(index: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        /// Read piped input into buffer, returns bytes read (0 at end of input)
+        /// or -1 if the program isn't reading from a pipe
+        pub fn stdin_read(buf_ptr: *mut u8, buf_len: i32) -> i32;
         /// Get current working directory into buffer, returns length or -1
         pub fn cwd_get(buf_ptr: *mut u8, buf_len: i32) -> i32;
         /// Check if file exists (1 = yes, 0 = no)
@@ -44,6 +53,41 @@ pub mod syscalls {
             resp_ptr: *mut u8,
             resp_len: i32,
         ) -> i32;
+        /// Bind a named IPC endpoint, returns 0 on success or -1 on error
+        pub fn ipc_bind(name_ptr: *const u8, name_len: i32) -> i32;
+        /// Send one message to a named IPC endpoint, returns 0 on success or -1 on error
+        pub fn ipc_send(
+            name_ptr: *const u8,
+            name_len: i32,
+            data_ptr: *const u8,
+            data_len: i32,
+        ) -> i32;
+        /// Receive one message into buffer (truncated to fit), returns its length,
+        /// -1 if none is queued, or -2 if the endpoint doesn't exist
+        pub fn ipc_recv(name_ptr: *const u8, name_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        /// Send one UDP datagram to a host (IPv4 address or name), returns 0 on
+        /// success or -1 on error
+        pub fn udp_send(
+            host_ptr: *const u8,
+            host_len: i32,
+            port: i32,
+            data_ptr: *const u8,
+            data_len: i32,
+        ) -> i32;
+        /// Receive one UDP datagram into buffer (truncated to fit), returns its
+        /// length or -1 if none is queued. Unless from_ptr is null, the sender's
+        /// address and big-endian port are written there as 6 bytes.
+        pub fn udp_recv(buf_ptr: *mut u8, buf_len: i32, from_ptr: *mut u8) -> i32;
+        /// Open a TCP connection and wait for it, returns a handle, -1 if the
+        /// host can't be resolved or no socket is free, or -2 if it failed
+        pub fn tcp_connect(host_ptr: *const u8, host_len: i32, port: i32) -> i32;
+        /// Send all of data on a connection, returns data_len or -1 on error
+        pub fn tcp_send(handle: i32, data_ptr: *const u8, data_len: i32) -> i32;
+        /// Read received data into buffer, returns bytes read, 0 if nothing has
+        /// arrived yet, or -1 once the peer has closed the connection
+        pub fn tcp_recv(handle: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        /// Close a connection, returns 0 on success or -1 for a bad handle
+        pub fn tcp_close(handle: i32) -> i32;
     }
 
     // --- Helper Wrappers ---
@@ -58,6 +102,21 @@ pub mod syscalls {
         unsafe { time() }
     }
 
+    /// Get the wall clock in seconds since the Unix epoch
+    pub fn unix_time() -> i64 {
+        unsafe { time_unix() }
+    }
+
+    /// Get the date as `date` prints it, e.g. `Thu Jan  1 00:00:00 UTC 1970`
+    pub fn date(buf: &mut [u8]) -> Option<&str> {
+        let len = unsafe { date_get(buf.as_mut_ptr(), buf.len() as i32) };
+        if len >= 0 {
+            core::str::from_utf8(&buf[..len as usize]).ok()
+        } else {
+            None
+        }
+    }
+
     /// Get number of arguments
     pub fn argc() -> usize {
         unsafe { arg_count() as usize }
@@ -73,6 +132,25 @@ pub mod syscalls {
         }
     }
 
+    /// Read all piped input into buffer (truncated to fit), returns bytes
+    /// read or None if the program isn't reading from a pipe
+    pub fn read_stdin(buf: &mut [u8]) -> Option<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            let len = unsafe {
+                stdin_read(buf[total..].as_mut_ptr(), (buf.len() - total) as i32)
+            };
+            if len < 0 {
+                return None;
+            }
+            if len == 0 {
+                break;
+            }
+            total += len as usize;
+        }
+        Some(total)
+    }
+
     /// Get current working directory
     pub fn get_cwd(buf: &mut [u8]) -> Option<usize> {
         let len = unsafe { cwd_get(buf.as_mut_ptr(), buf.len() as i32) };
@@ -154,6 +232,102 @@ pub mod syscalls {
         }
     }
 
+    /// Bind a named IPC endpoint for this program to receive on
+    pub fn ipc_listen(name: &str) -> bool {
+        unsafe { ipc_bind(name.as_ptr(), name.len() as i32) == 0 }
+    }
+
+    /// Send one message to a named IPC endpoint
+    pub fn ipc_send_to(name: &str, data: &[u8]) -> bool {
+        unsafe {
+            ipc_send(
+                name.as_ptr(),
+                name.len() as i32,
+                data.as_ptr(),
+                data.len() as i32,
+            ) == 0
+        }
+    }
+
+    /// Receive the oldest message queued on a named IPC endpoint
+    pub fn ipc_recv_from(name: &str, buf: &mut [u8]) -> Option<usize> {
+        let len = unsafe {
+            ipc_recv(
+                name.as_ptr(),
+                name.len() as i32,
+                buf.as_mut_ptr(),
+                buf.len() as i32,
+            )
+        };
+        if len >= 0 {
+            Some(len as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Send one UDP datagram to `host:port`
+    pub fn udp_send_to(host: &str, port: u16, data: &[u8]) -> bool {
+        unsafe {
+            udp_send(
+                host.as_ptr(),
+                host.len() as i32,
+                port as i32,
+                data.as_ptr(),
+                data.len() as i32,
+            ) == 0
+        }
+    }
+
+    /// Receive one queued UDP datagram, with the sender's address and port
+    pub fn udp_recv_from(buf: &mut [u8]) -> Option<(usize, [u8; 4], u16)> {
+        let mut from = [0u8; 6];
+        let len = unsafe { udp_recv(buf.as_mut_ptr(), buf.len() as i32, from.as_mut_ptr()) };
+        if len >= 0 {
+            let ip = [from[0], from[1], from[2], from[3]];
+            Some((len as usize, ip, u16::from_be_bytes([from[4], from[5]])))
+        } else {
+            None
+        }
+    }
+
+    /// A TCP connection, closed when dropped
+    pub struct TcpStream(i32);
+
+    impl TcpStream {
+        /// Connect to `host:port`, blocking until the handshake completes
+        pub fn connect(host: &str, port: u16) -> Option<TcpStream> {
+            let handle = unsafe { tcp_connect(host.as_ptr(), host.len() as i32, port as i32) };
+            if handle >= 0 {
+                Some(TcpStream(handle))
+            } else {
+                None
+            }
+        }
+
+        /// Send all of `data`
+        pub fn send(&self, data: &[u8]) -> bool {
+            unsafe { tcp_send(self.0, data.as_ptr(), data.len() as i32) >= 0 }
+        }
+
+        /// Read what has arrived: `Some(0)` if nothing yet, `None` once the
+        /// peer has closed the connection
+        pub fn recv(&self, buf: &mut [u8]) -> Option<usize> {
+            let len = unsafe { tcp_recv(self.0, buf.as_mut_ptr(), buf.len() as i32) };
+            if len >= 0 {
+                Some(len as usize)
+            } else {
+                None
+            }
+        }
+    }
+
+    impl Drop for TcpStream {
+        fn drop(&mut self) {
+            unsafe { tcp_close(self.0) };
+        }
+    }
+
     /// Print an integer
     pub fn print_int(n: i64) {
         let mut buf = [0u8; 20];
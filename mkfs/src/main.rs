@@ -1,121 +1,156 @@
-use clap::Parser;
-use std::fs::{self, File};
-use std::io::{Seek, SeekFrom, Write};
-use std::path::PathBuf;
-
-const SECTOR_SIZE: u64 = 512;
-const MAGIC: u32 = 0x53465331; // "SFS1"
-
-// Layout
-const SEC_SUPER: u64 = 0;
-const SEC_MAP_START: u64 = 1;
-const SEC_MAP_COUNT: u64 = 64; // Covers ~128MB
-const SEC_DIR_START: u64 = 65;
-const SEC_DIR_COUNT: u64 = 64; // 1024 files max
-const SEC_DATA_START: u64 = 129;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+mod ext2;
+mod image;
+mod sfs;
+
+use image::{BuildError, Built, Entry, Tree};
+
+// Long help pages, shared with the kernel's command registry
+#[allow(dead_code)]
+#[path = "../../kernel/src/cmd/manual.rs"]
+mod manual;
+
+/// Directory the shell command help pages are written to
+const HELP_DIR: &str = "/usr/share/help";
+
+/// Directory the WASM programs are installed to
+const BIN_DIR: &str = "/usr/bin";
+
+/// CRC-32 and path of every file, one per line, as written at build time
+const SUMS_FILE: &str = "/etc/mkfs.sums";
+
+/// Largest image picked when sizing automatically
+const MAX_AUTO_SIZE_MB: u64 = 4096;
+
+const MB: u64 = 1024 * 1024;
+
+/// Build and inspect filesystem images for the kernel
+///
+/// Options without a command build an image, as `mkfs build` does.
 #[derive(Parser)]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build an image from a host directory, help pages and WASM programs
+    Build(BuildArgs),
+    /// List the files in an image
+    Ls {
+        /// Disk image path
+        image: PathBuf,
+    },
+    /// Copy files out of an image, keeping modes and modification times
+    Extract {
+        /// Disk image path
+        image: PathBuf,
+        /// Host directory to extract into
+        dest: PathBuf,
+        /// Only extract this path and what's under it
+        #[arg(short, long, default_value = "/")]
+        path: String,
+    },
+    /// Check an image's structure and the checksums recorded when it was built
+    Verify {
+        /// Disk image path
+        image: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
     /// Output disk image path
     #[arg(short, long)]
     output: PathBuf,
 
-    /// Directory to import files from
+    /// Directory tree to import, subdirectories and modes included
     #[arg(short, long)]
     dir: Option<PathBuf>,
 
-    /// Disk size in MB
-    #[arg(short, long, default_value_t = 128)]
-    size: u64,
-}
+    /// Disk size in MB [default: just enough for the contents plus --reserve]
+    #[arg(short, long)]
+    size: Option<u64>,
 
-#[repr(C, packed)]
-struct DirEntry {
-    name: [u8; 24],
-    size: u32,
-    head: u32,
-}
+    /// Free space to leave for the guest, in MB [default: 1 when sizing
+    /// automatically, 0 with --size]
+    #[arg(short, long)]
+    reserve: Option<u64>,
 
-fn main() -> std::io::Result<()> {
-    let args = Args::parse();
+    /// Filesystem layout
+    #[arg(short, long, value_enum, default_value_t = Format::Ext2)]
+    format: Format,
+}
 
-    let total_sectors = (args.size * 1024 * 1024) / SECTOR_SIZE;
-    println!(
-        "Creating SFS image: {:?} ({} MB, {} sectors)",
-        args.output, args.size, total_sectors
-    );
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Real directories, modes and timestamps (the kernel's ext2 driver)
+    Ext2,
+    /// The kernel's original flat layout: 23-byte paths, no modes
+    Sfs,
+}
 
-    let mut file = File::create(&args.output)?;
-    file.set_len(args.size * 1024 * 1024)?;
-
-    // 1. Write Superblock
-    file.seek(SeekFrom::Start(SEC_SUPER * SECTOR_SIZE))?;
-    file.write_all(&MAGIC.to_le_bytes())?;
-    file.write_all(&(total_sectors as u32).to_le_bytes())?;
-
-    // 2. Initialize Bitmap (Mark system sectors as used)
-    let mut bitmap = vec![0u8; (SEC_MAP_COUNT * SECTOR_SIZE) as usize];
-    let reserved_sectors = SEC_DATA_START;
-    for i in 0..reserved_sectors {
-        let byte_idx = (i / 8) as usize;
-        let bit_idx = i % 8;
-        if byte_idx < bitmap.len() {
-            bitmap[byte_idx] |= 1 << bit_idx;
-        }
+fn main() {
+    // `mkfs --output ...` predates the commands and still builds
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    let legacy = argv.get(1).and_then(|a| a.to_str()).is_some_and(|a| {
+        a.starts_with('-') && !matches!(a, "-h" | "--help" | "-V" | "--version")
+    });
+    if legacy {
+        argv.insert(1, OsString::from("build"));
     }
 
-    let mut dir_idx = 0u64;
-
-    // 3. Import Files from root directory (non-recursive, just files in root)
-    if let Some(ref src_dir) = args.dir {
-        if src_dir.exists() {
-            dir_idx = import_directory(&mut file, &mut bitmap, src_dir, dir_idx, "")?;
-        }
+    let result = match Cli::parse_from(argv).command {
+        Command::Build(args) => build(&args),
+        Command::Ls { image } => list(&image),
+        Command::Extract { image, dest, path } => extract(&image, &dest, &path),
+        Command::Verify { image } => verify(&image),
+    };
+    if let Err(e) = result {
+        eprintln!("mkfs: {}", e);
+        std::process::exit(1);
     }
+}
 
-    // 4. Import files from usr/bin/ subdirectory (scripts with /usr/bin/ prefix)
-    if let Some(ref src_dir) = args.dir {
-        let usr_bin_dir = src_dir.join("usr").join("bin");
-        if usr_bin_dir.exists() {
-            println!("\n📜 Importing scripts from usr/bin/...");
-            dir_idx = import_directory(&mut file, &mut bitmap, &usr_bin_dir, dir_idx, "/usr/bin/")?;
-        }
-    }
+/// Build time, from `SOURCE_DATE_EPOCH` when set so images are reproducible
+fn build_time() -> u32 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32)
+        })
+}
 
-    // 5. Import files from home/ subdirectory (with /home/ prefix)
-    if let Some(ref src_dir) = args.dir {
-        let home_dir = src_dir.join("home");
-        if home_dir.exists() {
-            println!("\n🏠 Importing files from home/...");
-            dir_idx = import_directory(&mut file, &mut bitmap, &home_dir, dir_idx, "/home/")?;
-        }
-    }
+// ═══════════════════════════════════════════════════════════════════════════════
+// BUILD
+// ═══════════════════════════════════════════════════════════════════════════════
 
-    // 6. Import files from var/log/ subdirectory (with /var/log/ prefix)
-    if let Some(ref src_dir) = args.dir {
-        let var_log_dir = src_dir.join("var").join("log");
-        if var_log_dir.exists() {
-            println!("\n📋 Importing files from var/log/...");
-            dir_idx = import_directory(&mut file, &mut bitmap, &var_log_dir, dir_idx, "/var/log/")?;
-        }
-    }
+fn build(args: &BuildArgs) -> Result<(), String> {
+    let now = build_time();
+    let mut tree = Tree::new(now);
 
-    // 7. Import files from etc/init.d/ subdirectory (with /etc/init.d/ prefix)
+    // 1. Import the host directory tree
     if let Some(ref src_dir) = args.dir {
-        let etc_init_dir = src_dir.join("etc").join("init.d");
-        if etc_init_dir.exists() {
-            println!("\n⚙️  Importing files from etc/init.d/...");
-            dir_idx = import_directory(
-                &mut file,
-                &mut bitmap,
-                &etc_init_dir,
-                dir_idx,
-                "/etc/init.d/",
-            )?;
+        if src_dir.exists() {
+            println!("📂 Importing {:?}...", src_dir);
+            import_tree(&mut tree, src_dir, "").map_err(|e| format!("{:?}: {}", src_dir, e))?;
         }
     }
 
-    // 8. Import WASM binaries from target/wasm32-unknown-unknown/release/
+    // 2. Generate /usr/share/help/ pages from the kernel's command manual
+    println!("\n📖 Generating help pages in {}/...", HELP_DIR);
+    write_help_pages(&mut tree, now);
+
+    // 3. Import WASM binaries from target/wasm32-unknown-unknown/release/
     // These are compiled from mkfs/src/bin/*.rs files
     {
         // Try multiple possible locations for the wasm target directory
@@ -138,30 +173,181 @@ fn main() -> std::io::Result<()> {
         for wasm_path in possible_paths.iter() {
             if wasm_path.exists() && wasm_path.is_dir() {
                 println!("\n🔷 Importing WASM binaries from {:?}...", wasm_path);
-                dir_idx = import_wasm_binaries(&mut file, &mut bitmap, wasm_path, dir_idx)?;
+                import_wasm_binaries(&mut tree, wasm_path)
+                    .map_err(|e| format!("{:?}: {}", wasm_path, e))?;
                 break;
             }
         }
     }
 
-    // 9. Write Bitmap back to disk
-    file.seek(SeekFrom::Start(SEC_MAP_START * SECTOR_SIZE))?;
-    file.write_all(&bitmap)?;
+    if args.format == Format::Sfs {
+        sfs::prune(&mut tree);
+    }
+
+    // 4. Record checksums for `mkfs verify`
+    let sums = checksums(&tree);
+    tree.insert(Entry::file(SUMS_FILE, sums.into_bytes(), image::DEFAULT_FILE_MODE, now));
+
+    // 5. Lay out the image
+    let built = match args.size {
+        Some(size) => {
+            let reserve = args.reserve.unwrap_or(0) * MB;
+            let built = build_image(&tree, args.format, size * MB, now).map_err(|e| match e {
+                BuildError::Full => format!("contents don't fit in {} MB", size),
+                BuildError::Invalid(msg) => msg,
+            })?;
+            if built.free_bytes < reserve {
+                return Err(format!(
+                    "only {} KB left free in {} MB, {} MB reserve requested",
+                    built.free_bytes / 1024,
+                    size,
+                    reserve / MB
+                ));
+            }
+            built
+        }
+        None => auto_size(&tree, args.format, args.reserve.unwrap_or(1) * MB, now)?,
+    };
+
+    let kind = match args.format {
+        Format::Ext2 => "ext2",
+        Format::Sfs => "SFS",
+    };
+    println!(
+        "\n💾 Writing {} image: {:?} ({} KB, {} KB free)",
+        kind,
+        args.output,
+        built.image.len() / 1024,
+        built.free_bytes / 1024
+    );
+    fs::write(&args.output, &built.image).map_err(|e| format!("{:?}: {}", args.output, e))?;
+
+    println!("\n✅ Done. {} files imported.", tree.files());
+    Ok(())
+}
+
+fn build_image(tree: &Tree, format: Format, size: u64, now: u32) -> Result<Built, BuildError> {
+    match format {
+        Format::Ext2 => ext2::build(tree, size, now),
+        Format::Sfs => sfs::build(tree, size),
+    }
+}
+
+/// Build the smallest whole-MB image that holds `tree` with `reserve`
+/// bytes to spare
+fn auto_size(tree: &Tree, format: Format, reserve: u64, now: u32) -> Result<Built, String> {
+    let content: u64 = tree
+        .entries()
+        .filter_map(|e| e.data.as_ref())
+        .map(|d| d.len() as u64)
+        .sum();
+    let mut size_mb = (content + reserve).div_ceil(MB).max(1);
+    while size_mb <= MAX_AUTO_SIZE_MB {
+        match build_image(tree, format, size_mb * MB, now) {
+            Ok(built) if built.free_bytes >= reserve => return Ok(built),
+            Ok(_) | Err(BuildError::Full) => size_mb += 1,
+            Err(BuildError::Invalid(msg)) => return Err(msg),
+        }
+    }
+    Err(format!("contents don't fit in {} MB", MAX_AUTO_SIZE_MB))
+}
+
+/// Mode bits and modification time of a host file
+fn metadata_of(meta: &fs::Metadata, default_mode: u16) -> (u16, u32) {
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        (meta.permissions().mode() & 0o7777) as u16
+    };
+    #[cfg(not(unix))]
+    let mode = default_mode;
+    let _ = default_mode;
+
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as u32);
+    (mode, mtime)
+}
+
+/// Import a host directory and everything under it at `prefix`
+fn import_tree(tree: &mut Tree, dir: &Path, prefix: &str) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let Some(base_name) = path.file_name().and_then(|n| n.to_str()) else {
+            println!("  ⚠️  Skipping {:?}: Name isn't UTF-8", path);
+            continue;
+        };
+        let fs_path = format!("{}/{}", prefix, base_name);
+        let meta = fs::metadata(&path)?;
+
+        if meta.is_dir() {
+            let (mode, mtime) = metadata_of(&meta, image::DEFAULT_DIR_MODE);
+            tree.insert(Entry::dir(&fs_path, mode, mtime));
+            import_tree(tree, &path, &fs_path)?;
+        } else if meta.is_file() {
+            // Show different icon for different file types
+            let icon = if fs_path.ends_with(".rhai") {
+                "📜"
+            } else if fs_path.ends_with(".wasm") {
+                "🔷"
+            } else {
+                "📄"
+            };
+            println!("  {} Importing {}", icon, fs_path);
 
-    println!("\n✅ Done. {} files imported.", dir_idx);
+            let (mode, mtime) = metadata_of(&meta, image::DEFAULT_FILE_MODE);
+            tree.insert(Entry::file(&fs_path, fs::read(&path)?, mode, mtime));
+        }
+    }
     Ok(())
 }
 
+/// Render a help page as plain text (no ANSI escapes, so `cat` works
+/// on any terminal)
+fn render_help_page(name: &str, page: &manual::Manual) -> String {
+    let mut text = format!("{}\n\nDESCRIPTION\n", name);
+    for line in page.description.lines() {
+        if !line.is_empty() {
+            text.push_str("  ");
+            text.push_str(line);
+        }
+        text.push('\n');
+    }
+    if !page.examples.is_empty() {
+        text.push_str("\nEXAMPLES\n");
+        for example in page.examples {
+            text.push_str(&format!(
+                "  $ {}\n      {}\n",
+                example.command, example.explanation
+            ));
+        }
+    }
+    text
+}
+
+/// Write one help page per registry command into /usr/share/help/
+fn write_help_pages(tree: &mut Tree, now: u32) {
+    for (name, page) in manual::ALL {
+        let fs_path = format!("{}/{}", HELP_DIR, name);
+        println!("  📖 Writing {}", fs_path);
+
+        let data = render_help_page(name, page).into_bytes();
+        tree.insert(Entry::file(&fs_path, data, image::DEFAULT_FILE_MODE, now));
+    }
+}
+
 /// Import WASM binaries from target directory into /usr/bin/
 /// Only imports .wasm files that correspond to binaries in mkfs/src/bin/
-fn import_wasm_binaries(
-    file: &mut File,
-    bitmap: &mut Vec<u8>,
-    wasm_dir: &PathBuf,
-    mut dir_idx: u64,
-) -> std::io::Result<u64> {
-    for entry in fs::read_dir(wasm_dir)? {
-        let entry = entry?;
+fn import_wasm_binaries(tree: &mut Tree, wasm_dir: &Path) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(wasm_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
         let path = entry.path();
 
         // Only process .wasm files
@@ -191,136 +377,163 @@ fn import_wasm_binaries(
         }
 
         // Create the filesystem path: /usr/bin/<name>
-        let fs_path = format!("/usr/bin/{}", bin_name);
-
-        if fs_path.len() > 23 {
-            println!("  ⚠️  Skipping {}: Path too long (max 23 chars)", fs_path);
-            continue;
-        }
-
+        let fs_path = format!("{}/{}", BIN_DIR, bin_name);
         println!("  🔷 Importing {} -> {}", bin_name, fs_path);
 
-        let data = fs::read(&path)?;
-        let head_sector = write_data(file, bitmap, &data)?;
-        write_dir_entry(file, dir_idx, &fs_path, data.len() as u32, head_sector)?;
-        dir_idx += 1;
+        let (_, mtime) = metadata_of(&fs::metadata(&path)?, 0o755);
+        tree.insert(Entry::file(&fs_path, fs::read(&path)?, 0o755, mtime));
     }
 
-    Ok(dir_idx)
+    Ok(())
 }
 
-/// Import all files from a directory into the filesystem image
-fn import_directory(
-    file: &mut File,
-    bitmap: &mut Vec<u8>,
-    dir: &PathBuf,
-    mut dir_idx: u64,
-    prefix: &str,
-) -> std::io::Result<u64> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        // Skip subdirectories (except bin/ which is handled separately)
-        if path.is_dir() {
-            continue;
-        }
-
-        if path.is_file() {
-            let base_name = path.file_name().unwrap().to_str().unwrap();
-            let filename = if prefix.is_empty() {
-                base_name.to_string()
-            } else {
-                format!("{}{}", prefix, base_name)
-            };
-
-            if filename.len() > 23 {
-                println!("⚠️  Skipping {}: Name too long (max 23 chars)", filename);
-                continue;
-            }
-
-            // Show different icon for different file types
-            let icon = if filename.ends_with(".rhai") {
-                "📜"
-            } else if filename.ends_with(".wasm") {
-                "🔷"
-            } else {
-                "📄"
-            };
-            println!("  {} Importing {}", icon, filename);
-
-            let data = fs::read(&path)?;
-            let head_sector = write_data(file, bitmap, &data)?;
-            write_dir_entry(file, dir_idx, &filename, data.len() as u32, head_sector)?;
-            dir_idx += 1;
+/// The checksum manifest of every file in `tree`
+fn checksums(tree: &Tree) -> String {
+    let mut sums = String::new();
+    for entry in tree.entries() {
+        if let Some(data) = &entry.data {
+            sums.push_str(&format!("{:08x}  {}\n", image::crc32(data), entry.path));
         }
     }
-    Ok(dir_idx)
+    sums
 }
 
-fn find_free_sector(bitmap: &mut [u8]) -> Option<u32> {
-    for (byte_idx, &byte) in bitmap.iter().enumerate() {
-        if byte != 0xFF {
-            for bit_idx in 0..8 {
-                if (byte & (1 << bit_idx)) == 0 {
-                    bitmap[byte_idx] |= 1 << bit_idx;
-                    return Some((byte_idx * 8 + bit_idx) as u32);
-                }
-            }
-        }
+// ═══════════════════════════════════════════════════════════════════════════════
+// INSPECT
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Read an image file, detecting its format
+fn open(path: &Path) -> Result<(Format, Vec<u8>), String> {
+    let data = fs::read(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    if ext2::detect(&data) {
+        Ok((Format::Ext2, data))
+    } else if sfs::detect(&data) {
+        Ok((Format::Sfs, data))
+    } else {
+        Err(format!("{:?}: not an ext2 or SFS image", path))
     }
-    None
 }
 
-fn write_data(file: &mut File, bitmap: &mut [u8], data: &[u8]) -> std::io::Result<u32> {
-    if data.is_empty() {
-        return Ok(0);
+fn read_tree(path: &Path) -> Result<(Format, Tree, Vec<u8>), String> {
+    let (format, data) = open(path)?;
+    let tree = match format {
+        Format::Ext2 => ext2::read(&data),
+        Format::Sfs => sfs::read(&data),
     }
+    .map_err(|e| format!("{:?}: {}", path, e))?;
+    Ok((format, tree, data))
+}
 
-    let mut remaining = data;
-    let head = find_free_sector(bitmap).expect("Disk full");
-    let mut current = head;
+fn list(path: &Path) -> Result<(), String> {
+    let (_, tree, _) = read_tree(path)?;
+    for entry in tree.entries() {
+        let size = entry
+            .data
+            .as_ref()
+            .map_or(String::from("-"), |d| d.len().to_string());
+        println!("{}  {:>9}  {}", image::mode_string(entry), size, entry.path);
+    }
+    Ok(())
+}
 
-    while !remaining.is_empty() {
-        let chunk_len = std::cmp::min(remaining.len(), 508);
-        let chunk = &remaining[..chunk_len];
-        remaining = &remaining[chunk_len..];
+fn extract(path: &Path, dest: &Path, under: &str) -> Result<(), String> {
+    let (_, tree, _) = read_tree(path)?;
+    let under = under.trim_end_matches('/');
+    let selected: Vec<&Entry> = tree
+        .entries()
+        .filter(|e| {
+            under.is_empty() || e.path == under || e.path.starts_with(&format!("{}/", under))
+        })
+        .collect();
+    if selected.is_empty() {
+        return Err(format!("{}: not in the image", under));
+    }
 
-        let next = if remaining.is_empty() {
-            0
-        } else {
-            find_free_sector(bitmap).expect("Disk full")
+    let mut files = 0;
+    for entry in &selected {
+        let target = dest.join(entry.path.trim_start_matches('/'));
+        let result = match &entry.data {
+            None => fs::create_dir_all(&target),
+            Some(data) => {
+                files += 1;
+                println!("  📄 Extracting {}", entry.path);
+                write_host_file(&target, data, entry.mtime)
+            }
         };
+        result.map_err(|e| format!("{:?}: {}", target, e))?;
+    }
 
-        file.seek(SeekFrom::Start(current as u64 * SECTOR_SIZE))?;
-        file.write_all(&next.to_le_bytes())?;
-        file.write_all(chunk)?;
-        // Pad with zeros if partial sector
-        if chunk_len < 508 {
-            file.write_all(&vec![0u8; 508 - chunk_len])?;
-        }
+    // Directory modes last, so read-only directories can still be filled
+    for entry in selected.iter().rev() {
+        let target = dest.join(entry.path.trim_start_matches('/'));
+        set_mode(&target, entry.mode).map_err(|e| format!("{:?}: {}", target, e))?;
+    }
+    println!("\n✅ Done. {} files extracted to {:?}.", files, dest);
+    Ok(())
+}
 
-        current = next;
+fn write_host_file(target: &Path, data: &[u8], mtime: u32) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(target)?;
+    std::io::Write::write_all(&mut &file, data)?;
+    if mtime != 0 {
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(mtime as u64))?;
     }
-    Ok(head)
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(target: &Path, mode: u16) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(target, fs::Permissions::from_mode(mode as u32))
 }
 
-fn write_dir_entry(
-    file: &mut File,
-    idx: u64,
-    name: &str,
-    size: u32,
-    head: u32,
-) -> std::io::Result<()> {
-    let offset = (SEC_DIR_START * SECTOR_SIZE) + (idx * 32);
-    file.seek(SeekFrom::Start(offset))?;
-
-    let mut name_bytes = [0u8; 24];
-    let nb = name.as_bytes();
-    name_bytes[..nb.len()].copy_from_slice(nb);
-
-    file.write_all(&name_bytes)?;
-    file.write_all(&size.to_le_bytes())?;
-    file.write_all(&head.to_le_bytes())?;
+#[cfg(not(unix))]
+fn set_mode(_target: &Path, _mode: u16) -> std::io::Result<()> {
     Ok(())
 }
+
+fn verify(path: &Path) -> Result<(), String> {
+    let (format, tree, data) = read_tree(path)?;
+    let mut problems = match format {
+        Format::Ext2 => ext2::check(&data).map_err(|e| format!("{:?}: {}", path, e))?,
+        Format::Sfs => Vec::new(),
+    };
+
+    let files: Vec<&Entry> = tree.entries().filter(|e| !e.is_dir()).collect();
+    match files.iter().find(|e| e.path == SUMS_FILE) {
+        None => println!("⚠️  No {} in the image, checking structure only", SUMS_FILE),
+        Some(sums) => {
+            let sums = String::from_utf8_lossy(sums.data.as_deref().unwrap_or_default()).into_owned();
+            let mut checked = 0;
+            for line in sums.lines() {
+                let Some((crc, file)) = line.split_once("  ") else {
+                    continue;
+                };
+                checked += 1;
+                match files.iter().find(|e| e.path == file) {
+                    None => problems.push(format!("{}: missing", file)),
+                    Some(entry) => {
+                        let actual = image::crc32(entry.data.as_deref().unwrap_or_default());
+                        if format!("{:08x}", actual) != crc {
+                            problems.push(format!("{}: checksum mismatch", file));
+                        }
+                    }
+                }
+            }
+            println!("🔎 Checked {} of {} files against {}", checked, files.len(), SUMS_FILE);
+        }
+    }
+
+    if problems.is_empty() {
+        println!("✅ {:?} is consistent", path);
+        Ok(())
+    } else {
+        for problem in &problems {
+            println!("  ❌ {}", problem);
+        }
+        Err(format!("{} problems found", problems.len()))
+    }
+}
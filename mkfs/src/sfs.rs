@@ -0,0 +1,266 @@
+//! SFS, the kernel's original flat layout
+//!
+//! Sector 0 holds the superblock, sectors 1-64 the allocation bitmap and
+//! sectors 65-128 a table of 1024 directory entries naming files by full
+//! path. File data is a chain of sectors, each starting with the number of
+//! the next. Directories are entries whose name ends in `/`, and there are
+//! no modes or timestamps.
+
+use crate::image::{self, BuildError, Built, Entry, Tree};
+
+pub const SECTOR_SIZE: u64 = 512;
+pub const MAGIC: u32 = 0x53465331; // "SFS1"
+
+// Layout
+const SEC_SUPER: u64 = 0;
+const SEC_MAP_START: u64 = 1;
+const SEC_MAP_COUNT: u64 = 64; // Covers ~128MB
+const SEC_DIR_START: u64 = 65;
+const SEC_DIR_COUNT: u64 = 64; // 1024 files max
+const SEC_DATA_START: u64 = 129;
+
+/// Longest name a directory entry can hold
+const MAX_NAME_LEN: usize = 23;
+
+/// Payload of a data sector, after the next-sector link
+const CHUNK_LEN: usize = 508;
+
+/// What a directory placeholder contains, as written by the kernel's `mkdir`
+const DIR_MARKER: &[u8] = b"DIR";
+
+struct Writer {
+    image: Vec<u8>,
+    bitmap: Vec<u8>,
+    total_sectors: u64,
+    dir_idx: u64,
+}
+
+impl Writer {
+    fn find_free_sector(&mut self) -> Result<u32, BuildError> {
+        for sector in SEC_DATA_START..self.total_sectors {
+            let (byte_idx, bit_idx) = ((sector / 8) as usize, sector % 8);
+            if byte_idx >= self.bitmap.len() {
+                break;
+            }
+            if self.bitmap[byte_idx] & (1 << bit_idx) == 0 {
+                self.bitmap[byte_idx] |= 1 << bit_idx;
+                return Ok(sector as u32);
+            }
+        }
+        Err(BuildError::Full)
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<u32, BuildError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let head = self.find_free_sector()?;
+        let mut current = head;
+        let mut chunks = data.chunks(CHUNK_LEN).peekable();
+        while let Some(chunk) = chunks.next() {
+            let next = if chunks.peek().is_some() {
+                self.find_free_sector()?
+            } else {
+                0
+            };
+            let offset = (current as u64 * SECTOR_SIZE) as usize;
+            self.image[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+            self.image[offset + 4..offset + 4 + chunk.len()].copy_from_slice(chunk);
+            current = next;
+        }
+        Ok(head)
+    }
+
+    fn write_dir_entry(&mut self, name: &str, size: u32, head: u32) -> Result<(), BuildError> {
+        if self.dir_idx >= SEC_DIR_COUNT * SECTOR_SIZE / 32 {
+            return Err(BuildError::Invalid(String::from(
+                "SFS directory table is full (1024 entries max)",
+            )));
+        }
+        let offset = (SEC_DIR_START * SECTOR_SIZE + self.dir_idx * 32) as usize;
+        let entry = &mut self.image[offset..offset + 32];
+        entry[..name.len()].copy_from_slice(name.as_bytes());
+        entry[24..28].copy_from_slice(&size.to_le_bytes());
+        entry[28..32].copy_from_slice(&head.to_le_bytes());
+        self.dir_idx += 1;
+        Ok(())
+    }
+}
+
+/// Drop the entries whose names are longer than the 23 bytes an SFS
+/// directory entry can hold, with a warning
+pub fn prune(tree: &mut Tree) {
+    tree.retain(|entry| {
+        let len = entry.path.len() + entry.is_dir() as usize;
+        if len > MAX_NAME_LEN {
+            println!(
+                "  ⚠️  Skipping {}: Path too long (max {} chars)",
+                entry.path, MAX_NAME_LEN
+            );
+        }
+        len <= MAX_NAME_LEN
+    });
+}
+
+/// Build an SFS image of `size` bytes holding `tree`, after [`prune`]
+pub fn build(tree: &Tree, size: u64) -> Result<Built, BuildError> {
+    let total_sectors = size / SECTOR_SIZE;
+    if total_sectors <= SEC_DATA_START {
+        return Err(BuildError::Full);
+    }
+
+    let mut writer = Writer {
+        image: vec![0u8; size as usize],
+        bitmap: vec![0u8; (SEC_MAP_COUNT * SECTOR_SIZE) as usize],
+        total_sectors: total_sectors.min(SEC_MAP_COUNT * SECTOR_SIZE * 8),
+        dir_idx: 0,
+    };
+
+    // Superblock
+    let offset = (SEC_SUPER * SECTOR_SIZE) as usize;
+    writer.image[offset..offset + 4].copy_from_slice(&MAGIC.to_le_bytes());
+    writer.image[offset + 4..offset + 8].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+
+    // Mark system sectors as used
+    for sector in 0..SEC_DATA_START {
+        writer.bitmap[(sector / 8) as usize] |= 1 << (sector % 8);
+    }
+
+    for entry in tree.entries().filter(|e| e.path != "/") {
+        let (name, data) = match &entry.data {
+            Some(data) => (entry.path.clone(), data.as_slice()),
+            None => (format!("{}/", entry.path), DIR_MARKER),
+        };
+        if name.len() > MAX_NAME_LEN {
+            return Err(BuildError::Invalid(format!("{}: name too long", name)));
+        }
+        let head = writer.write_data(data)?;
+        writer.write_dir_entry(&name, data.len() as u32, head)?;
+    }
+
+    let offset = (SEC_MAP_START * SECTOR_SIZE) as usize;
+    writer.image[offset..offset + writer.bitmap.len()].copy_from_slice(&writer.bitmap);
+
+    let used = (SEC_DATA_START..writer.total_sectors)
+        .filter(|&s| writer.bitmap[(s / 8) as usize] & (1 << (s % 8)) != 0)
+        .count() as u64;
+    let free_sectors = writer.total_sectors - SEC_DATA_START - used;
+    Ok(Built {
+        image: writer.image,
+        free_bytes: free_sectors * CHUNK_LEN as u64,
+    })
+}
+
+/// Whether `image` holds an SFS filesystem
+pub fn detect(image: &[u8]) -> bool {
+    image.len() >= 4 && image[..4] == MAGIC.to_le_bytes()
+}
+
+fn sector(image: &[u8], sector: u64) -> Result<&[u8], String> {
+    let offset = (sector * SECTOR_SIZE) as usize;
+    image
+        .get(offset..offset + SECTOR_SIZE as usize)
+        .ok_or_else(|| format!("sector {} is past the end of the image", sector))
+}
+
+/// Read every entry of an SFS image. Directories only implied by file
+/// paths are included too.
+pub fn read(image: &[u8]) -> Result<Tree, String> {
+    let mut tree = Tree::new(0);
+    for dir_sector in SEC_DIR_START..SEC_DIR_START + SEC_DIR_COUNT {
+        let entries = sector(image, dir_sector)?;
+        for raw in entries.chunks(32) {
+            if raw[0] == 0 {
+                continue;
+            }
+            let name_len = raw[..24].iter().position(|&b| b == 0).unwrap_or(24);
+            let name = String::from_utf8_lossy(&raw[..name_len]).into_owned();
+            let size = u32::from_le_bytes([raw[24], raw[25], raw[26], raw[27]]) as usize;
+            let head = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
+
+            let path = if name.starts_with('/') {
+                name
+            } else {
+                format!("/{}", name)
+            };
+            if let Some(dir) = path.strip_suffix('/') {
+                if !dir.is_empty() {
+                    tree.insert(Entry::dir(dir, image::DEFAULT_DIR_MODE, 0));
+                }
+                continue;
+            }
+
+            let data = read_chain(image, head, size).map_err(|e| format!("{}: {}", path, e))?;
+            tree.insert(Entry::file(&path, data, image::DEFAULT_FILE_MODE, 0));
+        }
+    }
+    Ok(tree)
+}
+
+fn read_chain(image: &[u8], head: u32, size: usize) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(size);
+    let mut current = head;
+    while data.len() < size {
+        if (current as u64) < SEC_DATA_START {
+            return Err(String::from("block chain ends before the file does"));
+        }
+        let raw = sector(image, current as u64)?;
+        let take = (size - data.len()).min(CHUNK_LEN);
+        data.extend_from_slice(&raw[4..4 + take]);
+        current = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_and_read_round_trip() {
+        let mut tree = Tree::new(0);
+        tree.insert(Entry::file("/home/notes.txt", vec![7; 1500], 0o644, 0));
+        tree.insert(Entry::file("/empty", Vec::new(), 0o644, 0));
+        tree.insert(Entry::dir("/tmp", 0o755, 0));
+        tree.insert(Entry::file(
+            "/a/name/that/is/far/too/long",
+            vec![1],
+            0o644,
+            0,
+        ));
+        prune(&mut tree);
+
+        let built = build(&tree, 1024 * 1024).ok().unwrap();
+        assert!(detect(&built.image));
+        let read_back = read(&built.image).unwrap();
+        let paths: Vec<_> = read_back.entries().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/",
+                "/a",
+                "/a/name",
+                "/a/name/that",
+                "/a/name/that/is",
+                "/a/name/that/is/far",
+                "/empty",
+                "/home",
+                "/home/notes.txt",
+                "/tmp"
+            ]
+        );
+        let notes = read_back
+            .entries()
+            .find(|e| e.path == "/home/notes.txt")
+            .unwrap();
+        assert_eq!(notes.data.as_deref(), Some(&[7u8; 1500][..]));
+    }
+
+    #[test]
+    fn full_image_is_reported() {
+        let mut tree = Tree::new(0);
+        tree.insert(Entry::file("/big", vec![0; 200_000], 0o644, 0));
+        assert!(matches!(build(&tree, 128 * 1024), Err(BuildError::Full)));
+    }
+}
@@ -0,0 +1,125 @@
+//! Access control for public deployments.
+//!
+//! A relay without any policy accepts every peer. With `--auth-token`, a
+//! session must carry one of the configured tokens as a `token` query
+//! parameter in its URL (`https://relay:4433/?token=...`), which browsers
+//! and native clients can both set; other sessions are refused with 403
+//! before they are accepted. With `--allow-mac`, only peers registering
+//! one of the listed MAC addresses get an IP.
+
+use std::collections::HashSet;
+
+use crate::protocol::format_mac;
+
+/// Who may use the relay.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    /// Accepted tokens; empty means no token is needed.
+    tokens: Vec<String>,
+    /// Allowed peer MACs; `None` allows any.
+    macs: Option<HashSet<[u8; 6]>>,
+}
+
+impl AccessPolicy {
+    pub fn new(tokens: Vec<String>, macs: Vec<[u8; 6]>) -> Self {
+        Self {
+            tokens,
+            macs: (!macs.is_empty()).then(|| macs.into_iter().collect()),
+        }
+    }
+
+    pub fn requires_token(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Whether a session requested for `path` may connect.
+    pub fn allows_session(&self, path: &str) -> bool {
+        if !self.requires_token() {
+            return true;
+        }
+        let Some(token) = token_from_path(path) else {
+            return false;
+        };
+        // Compare against every token so timing doesn't tell which matched
+        self.tokens.iter().fold(false, |found, t| {
+            constant_time_eq(t.as_bytes(), token.as_bytes()) | found
+        })
+    }
+
+    /// Whether a peer may register with `mac`.
+    pub fn allows_mac(&self, mac: &[u8; 6]) -> bool {
+        self.macs.as_ref().is_none_or(|macs| macs.contains(mac))
+    }
+
+    /// One-line summary for the startup log.
+    pub fn describe(&self) -> String {
+        let tokens = match self.tokens.len() {
+            0 => "open (no token)".to_string(),
+            n => format!("{} token(s)", n),
+        };
+        match &self.macs {
+            None => tokens,
+            Some(macs) => {
+                let mut list: Vec<String> = macs.iter().map(format_mac).collect();
+                list.sort();
+                format!("{}, MACs {}", tokens, list.join(" "))
+            }
+        }
+    }
+}
+
+/// Value of the `token` query parameter of a request path.
+fn token_from_path(path: &str) -> Option<&str> {
+    let (_, query) = path.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Parse a MAC address written as six colon-separated hex bytes.
+pub fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let mut mac = [0u8; 6];
+    let mut bytes = s.split(':');
+    for byte in mac.iter_mut() {
+        *byte = bytes
+            .next()
+            .and_then(|b| u8::from_str_radix(b, 16).ok())
+            .ok_or_else(|| format!("invalid MAC address '{}'", s))?;
+    }
+    if bytes.next().is_some() {
+        return Err(format!("invalid MAC address '{}'", s));
+    }
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_policy_allows_everyone() {
+        let policy = AccessPolicy::default();
+        assert!(policy.allows_session("/"));
+        assert!(policy.allows_mac(&[0x52, 0x54, 0, 0, 0, 1]));
+    }
+
+    #[test]
+    fn test_token_and_mac_checks() {
+        let mac = parse_mac("52:54:00:12:34:56").unwrap();
+        let policy = AccessPolicy::new(vec!["s3cret".into(), "other".into()], vec![mac]);
+        assert!(policy.allows_session("/?token=s3cret"));
+        assert!(policy.allows_session("/relay?v=2&token=other"));
+        assert!(!policy.allows_session("/?token=s3cre"));
+        assert!(!policy.allows_session("/"));
+        assert!(policy.allows_mac(&mac));
+        assert!(!policy.allows_mac(&[0x52, 0x54, 0, 0x12, 0x34, 0x57]));
+        assert!(parse_mac("52:54:00:12:34").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! DHCP responder for the virtual gateway.
+//!
+//! Peers already learn their address from the `Assigned` control message,
+//! but guests that run a DHCP client get the same configuration this way.
+//! The lease is the address the hub assigned to the peer's connection, so it
+//! is unique on the relay and lasts as long as the connection does.
+
+use crate::hub::build_udp_frame;
+use crate::protocol::{DNS_SERVER, GATEWAY_IP, NETWORK_MASK};
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Lease time offered to clients, in seconds
+const LEASE_SECS: u32 = 86400;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed BOOTP header before the magic cookie
+const BOOTP_LEN: usize = 236;
+
+// Message types (option 53)
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const INFORM: u8 = 8;
+
+// Options
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_END: u8 = 255;
+const OPT_PAD: u8 = 0;
+
+/// Whether an Ethernet frame is a UDP datagram for the DHCP server port
+pub fn is_dhcp_request(frame: &[u8]) -> bool {
+    if frame.len() < 42 || frame[12..14] != [0x08, 0x00] || frame[23] != 17 {
+        return false;
+    }
+    let ihl = (frame[14] & 0x0f) as usize * 4;
+    let udp = 14 + ihl;
+    frame.len() >= udp + 8
+        && u16::from_be_bytes([frame[udp + 2], frame[udp + 3]]) == DHCP_SERVER_PORT
+}
+
+/// Answer a DHCP request frame from a peer whose lease is `lease_ip`.
+/// Returns the reply frame, or `None` for messages that need no answer
+/// (RELEASE, DECLINE, malformed packets).
+pub fn handle_request(frame: &[u8], lease_ip: [u8; 4]) -> Option<Vec<u8>> {
+    let ihl = (frame[14] & 0x0f) as usize * 4;
+    let bootp = frame.get(14 + ihl + 8..)?;
+    if bootp.len() < BOOTP_LEN + 4 || bootp[0] != BOOTREQUEST || bootp[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let options = parse_options(&bootp[240..]);
+    let msg_type = *find_option(&options, OPT_MESSAGE_TYPE)?.first()?;
+    let ciaddr: [u8; 4] = bootp[12..16].try_into().unwrap();
+
+    let (reply_type, yiaddr) = match msg_type {
+        DISCOVER => (OFFER, lease_ip),
+        REQUEST => {
+            // Requested address comes from option 50 (SELECTING/INIT-REBOOT)
+            // or ciaddr (RENEWING/REBINDING)
+            let requested = find_option(&options, OPT_REQUESTED_IP)
+                .and_then(|ip| <[u8; 4]>::try_from(ip).ok())
+                .unwrap_or(ciaddr);
+            let for_us = find_option(&options, OPT_SERVER_ID).is_none_or(|id| id == GATEWAY_IP);
+            if !for_us {
+                return None; // Client chose another server
+            }
+            if requested == lease_ip {
+                (ACK, lease_ip)
+            } else {
+                (NAK, [0; 4])
+            }
+        }
+        INFORM => (ACK, [0; 4]),
+        _ => return None,
+    };
+
+    let mut reply = vec![0u8; BOOTP_LEN];
+    reply[0] = BOOTREPLY;
+    reply[1..3].copy_from_slice(&bootp[1..3]); // htype, hlen
+    reply[4..8].copy_from_slice(&bootp[4..8]); // xid
+    reply[10..12].copy_from_slice(&bootp[10..12]); // flags
+    if msg_type == INFORM {
+        reply[12..16].copy_from_slice(&ciaddr);
+    }
+    reply[16..20].copy_from_slice(&yiaddr);
+    reply[20..24].copy_from_slice(&GATEWAY_IP); // siaddr
+    reply[24..28].copy_from_slice(&bootp[24..28]); // giaddr
+    reply[28..44].copy_from_slice(&bootp[28..44]); // chaddr
+    reply.extend_from_slice(&MAGIC_COOKIE);
+    push_option(&mut reply, OPT_MESSAGE_TYPE, &[reply_type]);
+    push_option(&mut reply, OPT_SERVER_ID, &GATEWAY_IP);
+    if reply_type != NAK {
+        if msg_type != INFORM {
+            push_option(&mut reply, OPT_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+        }
+        push_option(&mut reply, OPT_SUBNET_MASK, &NETWORK_MASK);
+        push_option(&mut reply, OPT_ROUTER, &GATEWAY_IP);
+        push_option(&mut reply, OPT_DNS, &DNS_SERVER);
+    }
+    reply.push(OPT_END);
+    // Pad to the minimum BOOTP message size
+    reply.resize(reply.len().max(300), OPT_PAD);
+
+    // Unicast to the client unless it asked for broadcast or has no address
+    // yet that it would accept packets on
+    let broadcast = bootp[10] & 0x80 != 0 || reply_type == NAK;
+    let (dst_mac, dst_ip) = match (broadcast, msg_type) {
+        (false, INFORM) => (frame[6..12].try_into().unwrap(), ciaddr),
+        (false, _) => (frame[6..12].try_into().unwrap(), yiaddr),
+        (true, _) => ([0xff; 6], [255; 4]),
+    };
+    Some(build_udp_frame(
+        dst_mac,
+        (GATEWAY_IP, DHCP_SERVER_PORT),
+        (dst_ip, DHCP_CLIENT_PORT),
+        &reply,
+    ))
+}
+
+fn parse_options(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut options = Vec::new();
+    while let Some((&code, rest)) = data.split_first() {
+        match code {
+            OPT_PAD => data = rest,
+            OPT_END => break,
+            _ => {
+                let Some((&len, rest)) = rest.split_first() else {
+                    break;
+                };
+                let Some(value) = rest.get(..len as usize) else {
+                    break;
+                };
+                options.push((code, value));
+                data = &rest[len as usize..];
+            }
+        }
+    }
+    options
+}
+
+fn find_option<'a>(options: &[(u8, &'a [u8])], code: u8) -> Option<&'a [u8]> {
+    options
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, value)| *value)
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u8, value: &[u8]) {
+    buf.push(code);
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::compute_checksum;
+
+    const CLIENT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef];
+
+    /// Broadcast DHCP request from CLIENT_MAC carrying `options`
+    fn request(options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bootp = vec![0u8; BOOTP_LEN];
+        bootp[0] = BOOTREQUEST;
+        bootp[1] = 1;
+        bootp[2] = 6;
+        bootp[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        bootp[28..34].copy_from_slice(&CLIENT_MAC);
+        bootp.extend_from_slice(&MAGIC_COOKIE);
+        for (code, value) in options {
+            push_option(&mut bootp, *code, value);
+        }
+        bootp.push(OPT_END);
+
+        let mut frame = build_udp_frame(
+            [0xff; 6],
+            ([0; 4], DHCP_CLIENT_PORT),
+            ([255; 4], DHCP_SERVER_PORT),
+            &bootp,
+        );
+        frame[6..12].copy_from_slice(&CLIENT_MAC);
+        frame
+    }
+
+    /// Message type and yiaddr of a reply frame
+    fn reply_summary(reply: &[u8]) -> (u8, [u8; 4]) {
+        let bootp = &reply[42..];
+        let options = parse_options(&bootp[240..]);
+        let msg_type = find_option(&options, OPT_MESSAGE_TYPE).unwrap()[0];
+        (msg_type, bootp[16..20].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_discover_request_ack() {
+        let lease = [10, 0, 2, 42];
+        let discover = request(&[(OPT_MESSAGE_TYPE, &[DISCOVER])]);
+        assert!(is_dhcp_request(&discover));
+
+        let offer = handle_request(&discover, lease).unwrap();
+        assert_eq!(reply_summary(&offer), (OFFER, lease));
+        assert_eq!(offer[0..6], CLIENT_MAC);
+        assert_eq!(offer[4 + 42..8 + 42], [1, 2, 3, 4]); // xid
+        assert_eq!(compute_checksum(&offer[14..34]), 0);
+        let options = parse_options(&offer[42 + 240..]);
+        assert_eq!(find_option(&options, OPT_ROUTER), Some(&GATEWAY_IP[..]));
+
+        let req = request(&[
+            (OPT_MESSAGE_TYPE, &[REQUEST]),
+            (OPT_REQUESTED_IP, &lease),
+            (OPT_SERVER_ID, &GATEWAY_IP),
+        ]);
+        assert_eq!(
+            reply_summary(&handle_request(&req, lease).unwrap()),
+            (ACK, lease)
+        );
+    }
+
+    #[test]
+    fn test_request_for_other_address_is_refused() {
+        let req = request(&[
+            (OPT_MESSAGE_TYPE, &[REQUEST]),
+            (OPT_REQUESTED_IP, &[10, 0, 2, 15]),
+        ]);
+        let nak = handle_request(&req, [10, 0, 2, 42]).unwrap();
+        assert_eq!(reply_summary(&nak), (NAK, [0; 4]));
+        assert_eq!(nak[0..6], [0xff; 6]);
+
+        // Releases and requests for other servers get no answer
+        let release = request(&[(OPT_MESSAGE_TYPE, &[7])]);
+        assert!(handle_request(&release, [10, 0, 2, 42]).is_none());
+        let other = request(&[
+            (OPT_MESSAGE_TYPE, &[REQUEST]),
+            (OPT_SERVER_ID, &[10, 0, 2, 3]),
+        ]);
+        assert!(handle_request(&other, [10, 0, 2, 42]).is_none());
+    }
+}
@@ -0,0 +1,675 @@
+//! Caching DNS proxy for the virtual network.
+//!
+//! The hub hands every guest UDP datagram for port 53 to [`DnsProxy`]
+//! instead of the NAT proxy. Names are answered, in order, from:
+//! 1. peer names: `peer<ID>.local` is the address of peer `ID` on the
+//!    asker's network (see the relay's `/status` for IDs)
+//! 2. `--dns-override NAME=IP` entries (`*.example` matches subdomains)
+//! 3. the response cache, honouring record TTLs; cached answers are handed
+//!    out with their TTLs lowered by the time they spent in the cache
+//! 4. the `--dns-upstream` servers in order, or the server the guest asked
+//!    when none are configured. Upstreams are plain DNS servers over UDP or
+//!    DNS-over-HTTPS endpoints (RFC 8484, `https://` URLs).
+//!
+//! Answers from servers a guest picked are cached for that server only, so
+//! a guest pointing its queries at a server it runs cannot answer for the
+//! other guests. Responses to another question than the query's are
+//! dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+/// How long to wait for each upstream server.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// TTL of answers synthesized by the relay.
+const LOCAL_TTL: u32 = 60;
+
+/// Cache lifetime of NXDOMAIN and empty answers.
+const NEGATIVE_TTL: u32 = 30;
+
+/// Longest time a response stays cached, whatever its TTL.
+const MAX_CACHE_TTL: u32 = 3600;
+
+/// Entries kept in the cache.
+const MAX_CACHE_ENTRIES: usize = 1024;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_OPT: u16 = 41;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Suffix of peer names.
+pub const PEER_DOMAIN: &str = ".local";
+
+/// Media type of DNS messages over HTTPS.
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// A server lookups are forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Upstream {
+    /// Plain DNS over UDP
+    Udp(SocketAddr),
+    /// DNS-over-HTTPS endpoint URL
+    Https(String),
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(addr) => write!(f, "{}", addr),
+            Self::Https(url) => f.write_str(url),
+        }
+    }
+}
+
+/// The question of a DNS query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Question {
+    /// Lowercased name without the trailing dot
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// Cache key: the server a guest picked, `None` for the configured
+/// upstreams, and the question.
+type CacheKey = (Option<SocketAddr>, Question);
+
+struct CacheEntry {
+    response: Vec<u8>,
+    stored: Instant,
+    expires: Instant,
+}
+
+/// Resolver shared by all peers.
+pub struct DnsProxy {
+    upstreams: Vec<Upstream>,
+    http: reqwest::Client,
+    overrides: Vec<(String, Ipv4Addr)>,
+    cache: Mutex<HashMap<CacheKey, CacheEntry>>,
+}
+
+impl DnsProxy {
+    /// Proxy forwarding misses to `upstreams` (empty: the guest's choice)
+    /// and answering `overrides` (name or `*.suffix`, address) locally.
+    pub fn new(upstreams: Vec<Upstream>, overrides: Vec<(String, Ipv4Addr)>) -> Self {
+        let overrides = overrides
+            .into_iter()
+            .map(|(name, ip)| (name.trim_end_matches('.').to_ascii_lowercase(), ip))
+            .collect();
+        Self {
+            upstreams,
+            http: reqwest::Client::new(),
+            overrides,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Address configured for `name` with `--dns-override`.
+    fn lookup_override(&self, name: &str) -> Option<Ipv4Addr> {
+        self.overrides.iter().find_map(|(pattern, ip)| {
+            let matches = match pattern.strip_prefix("*.") {
+                Some(suffix) => name
+                    .strip_suffix(suffix)
+                    .is_some_and(|head| head.ends_with('.')),
+                None => name == pattern,
+            };
+            matches.then_some(*ip)
+        })
+    }
+
+    /// Answer `query` without leaving the relay, if its name is a peer
+    /// (`peer_ip`, as looked up by the hub) or an override.
+    pub fn answer_local(&self, query: &[u8], peer_ip: Option<[u8; 4]>) -> Option<Vec<u8>> {
+        let (question, end) = parse_question(query)?;
+        let ip = peer_ip
+            .map(Ipv4Addr::from)
+            .or_else(|| self.lookup_override(&question.name))?;
+        Some(build_answer(query, end, &question, ip))
+    }
+
+    /// Resolve `query` from the cache or upstream. `guest_server` is where
+    /// the guest sent it, used when no upstream is configured.
+    pub async fn resolve(&self, query: &[u8], guest_server: SocketAddr) -> Option<Vec<u8>> {
+        let (question, question_end) = parse_question(query)?;
+        let key = (
+            self.upstreams.is_empty().then_some(guest_server),
+            question.clone(),
+        );
+
+        {
+            let mut cache = self.cache.lock().await;
+            match cache.get(&key) {
+                Some(entry) if entry.expires > Instant::now() => {
+                    tracing::debug!("DNS cache hit: {} type {}", question.name, question.qtype);
+                    // Echo the query's ID and name spelling, and count down
+                    // the TTLs so the guest doesn't keep it past its expiry
+                    let mut response = entry.response.clone();
+                    response[0..2].copy_from_slice(&query[0..2]);
+                    response[HEADER_LEN..question_end]
+                        .copy_from_slice(&query[HEADER_LEN..question_end]);
+                    age_response(&mut response, entry.stored.elapsed().as_secs() as u32);
+                    return Some(response);
+                }
+                Some(_) => {
+                    cache.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        let guest = [Upstream::Udp(guest_server)];
+        let servers = if self.upstreams.is_empty() {
+            &guest[..]
+        } else {
+            &self.upstreams[..]
+        };
+        for server in servers {
+            let result = match server {
+                Upstream::Udp(addr) => query_upstream(query, *addr).await,
+                Upstream::Https(url) => self.query_https(query, url).await,
+            };
+            match result {
+                Ok(response) if parse_question(&response).is_some_and(|(q, _)| q == question) => {
+                    if let Some(ttl) = cache_ttl(&response) {
+                        self.store(key, &response, ttl).await;
+                    }
+                    return Some(response);
+                }
+                Ok(_) => tracing::debug!(
+                    "DNS upstream {} answered another question than {}",
+                    server,
+                    question.name
+                ),
+                Err(e) => tracing::debug!("DNS upstream {} failed: {}", server, e),
+            }
+        }
+        tracing::warn!("DNS: no upstream answered for {}", question.name);
+        None
+    }
+
+    /// POST `query` to the DNS-over-HTTPS endpoint `url`.
+    async fn query_https(&self, query: &[u8], url: &str) -> std::io::Result<Vec<u8>> {
+        let response = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, DNS_MESSAGE)
+            .header(reqwest::header::ACCEPT, DNS_MESSAGE)
+            .timeout(UPSTREAM_TIMEOUT)
+            .body(query.to_vec())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(std::io::Error::other)?;
+        let mut body = response
+            .bytes()
+            .await
+            .map_err(std::io::Error::other)?
+            .to_vec();
+        if body.len() < HEADER_LEN || body[2] & 0x80 == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a DNS response",
+            ));
+        }
+        // Servers may answer with ID 0 (RFC 8484 section 4.1)
+        body[0..2].copy_from_slice(&query[0..2]);
+        Ok(body)
+    }
+
+    async fn store(&self, key: CacheKey, response: &[u8], ttl: u32) {
+        let mut cache = self.cache.lock().await;
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            let now = Instant::now();
+            cache.retain(|_, entry| entry.expires > now);
+            if cache.len() >= MAX_CACHE_ENTRIES {
+                return;
+            }
+        }
+        let now = Instant::now();
+        cache.insert(
+            key,
+            CacheEntry {
+                response: response.to_vec(),
+                stored: now,
+                expires: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+}
+
+/// Send `query` to `server` and wait for the matching response.
+async fn query_upstream(query: &[u8], server: SocketAddr) -> std::io::Result<Vec<u8>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0u8; 4096];
+    tokio::time::timeout(UPSTREAM_TIMEOUT, async {
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            // Ignore stray datagrams that don't answer our query ID
+            if n >= HEADER_LEN && buf[0..2] == query[0..2] && buf[2] & 0x80 != 0 {
+                buf.truncate(n);
+                return Ok(buf);
+            }
+        }
+    })
+    .await
+    .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))?
+}
+
+/// Parse the single question of a query. Returns it and the offset just
+/// past it.
+pub fn parse_question(msg: &[u8]) -> Option<(Question, usize)> {
+    if msg.len() < HEADER_LEN || u16::from_be_bytes([msg[4], msg[5]]) != 1 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Questions are never compressed
+        if len > 63 {
+            return None;
+        }
+        let label = msg.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len;
+    }
+    let fixed = msg.get(pos..pos + 4)?;
+    let question = Question {
+        name: labels.join("."),
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+    };
+    Some((question, pos + 4))
+}
+
+/// Authoritative response to `query` mapping its name to `ip`. Questions
+/// for other record types get an empty answer.
+fn build_answer(query: &[u8], question_end: usize, question: &Question, ip: Ipv4Addr) -> Vec<u8> {
+    let has_answer = matches!(question.qtype, TYPE_A | TYPE_ANY) && question.qclass == CLASS_IN;
+    let mut response = query[..question_end].to_vec();
+    // QR, opcode and RD from the query, AA, RA
+    response[2] = 0x80 | (query[2] & 0x79) | 0x04;
+    response[3] = 0x80;
+    response[6..8].copy_from_slice(&(has_answer as u16).to_be_bytes());
+    response[8..12].fill(0); // NSCOUNT, ARCOUNT
+    if has_answer {
+        response.extend_from_slice(&[0xc0, HEADER_LEN as u8]); // pointer to the question name
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&LOCAL_TTL.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&ip.octets());
+    }
+    response
+}
+
+/// How long `response` may be cached: the smallest TTL of its answer and
+/// authority records, or [`NEGATIVE_TTL`] for NXDOMAIN and empty answers.
+/// `None` for failures and truncated responses, which are not cached.
+fn cache_ttl(response: &[u8]) -> Option<u32> {
+    if response.len() < HEADER_LEN || response[2] & 0x02 != 0 {
+        return None; // Truncated
+    }
+    match response[3] & 0x0f {
+        0 => {}
+        RCODE_NXDOMAIN => return Some(NEGATIVE_TTL),
+        _ => return None,
+    }
+    let (_, mut pos) = parse_question(response)?;
+    let records = u16::from_be_bytes([response[6], response[7]]) as usize
+        + u16::from_be_bytes([response[8], response[9]]) as usize;
+    if records == 0 {
+        return Some(NEGATIVE_TTL);
+    }
+    let mut ttl = MAX_CACHE_TTL;
+    for _ in 0..records {
+        pos = skip_name(response, pos)?;
+        let fixed = response.get(pos..pos + 10)?;
+        ttl = ttl.min(u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]));
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        pos += 10 + rdlen;
+    }
+    (ttl > 0).then_some(ttl)
+}
+
+/// Lower the TTL of every record in `response` by `age` seconds. OPT
+/// records keep flags in that field and are left alone.
+fn age_response(response: &mut [u8], age: u32) -> Option<()> {
+    let (_, mut pos) = parse_question(response)?;
+    let records = (6..12)
+        .step_by(2)
+        .map(|i| u16::from_be_bytes([response[i], response[i + 1]]) as usize)
+        .sum::<usize>();
+    for _ in 0..records {
+        pos = skip_name(response, pos)?;
+        let fixed = response.get_mut(pos..pos + 10)?;
+        if u16::from_be_bytes([fixed[0], fixed[1]]) != TYPE_OPT {
+            let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+            fixed[4..8].copy_from_slice(&ttl.saturating_sub(age).to_be_bytes());
+        }
+        pos += 10 + u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+    }
+    Some(())
+}
+
+/// Offset just past the (possibly compressed) name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+/// Parse a `--dns-override` argument: `NAME=IP`.
+pub fn parse_override(s: &str) -> Result<(String, Ipv4Addr), String> {
+    let (name, ip) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=IP, got '{}'", s))?;
+    let ip = ip
+        .parse()
+        .map_err(|_| format!("invalid IPv4 address '{}'", ip))?;
+    Ok((name.to_string(), ip))
+}
+
+/// Parse a `--dns-upstream` argument: an address with optional port, or
+/// an `https://` URL for DNS over HTTPS.
+pub fn parse_upstream(s: &str) -> Result<Upstream, String> {
+    if s.starts_with("https://") {
+        return reqwest::Url::parse(s)
+            .map(|_| Upstream::Https(s.to_string()))
+            .map_err(|e| format!("invalid DNS-over-HTTPS URL '{}': {}", s, e));
+    }
+    s.parse()
+        .or_else(|_| {
+            s.parse::<std::net::IpAddr>()
+                .map(|ip| SocketAddr::new(ip, 53))
+        })
+        .map(Upstream::Udp)
+        .map_err(|_| format!("invalid DNS server address '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut msg = id.to_be_bytes().to_vec();
+        msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]); // RD, one question
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+        msg
+    }
+
+    /// `query` answered with one A record of TTL `ttl`
+    fn upstream_answer(query: &[u8], ttl: u32) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response[3] = 0x80;
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&[0, 4, 93, 184, 216, 34]);
+        response
+    }
+
+    #[test]
+    fn test_local_answers() {
+        let proxy = DnsProxy::new(
+            vec![],
+            vec![
+                ("*.lab.".into(), Ipv4Addr::new(10, 0, 2, 50)),
+                ("Build".into(), Ipv4Addr::new(10, 0, 2, 51)),
+            ],
+        );
+
+        let q = query(7, "web.LAB", TYPE_A);
+        let response = proxy.answer_local(&q, None).unwrap();
+        assert_eq!(response[0..2], [0, 7]);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        assert_eq!(response[response.len() - 4..], [10, 0, 2, 50]);
+
+        assert!(
+            proxy
+                .answer_local(&query(1, "build", TYPE_A), None)
+                .is_some()
+        );
+        assert!(proxy.answer_local(&query(1, "lab", TYPE_A), None).is_none());
+        assert!(
+            proxy
+                .answer_local(&query(1, "example.com", TYPE_A), None)
+                .is_none()
+        );
+
+        // Peer names resolved by the hub; AAAA gets an empty answer
+        let response = proxy
+            .answer_local(&query(2, "peer3.local", TYPE_A), Some([10, 0, 2, 12]))
+            .unwrap();
+        assert_eq!(response[response.len() - 4..], [10, 0, 2, 12]);
+        let response = proxy
+            .answer_local(&query(2, "peer3.local", 28), Some([10, 0, 2, 12]))
+            .unwrap();
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 0);
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let q = query(1, "example.com", TYPE_A);
+        assert_eq!(cache_ttl(&upstream_answer(&q, 300)), Some(300));
+        assert_eq!(
+            cache_ttl(&upstream_answer(&q, 1 << 20)),
+            Some(MAX_CACHE_TTL)
+        );
+        assert_eq!(cache_ttl(&upstream_answer(&q, 0)), None);
+
+        let mut nxdomain = q.clone();
+        nxdomain[2] |= 0x80;
+        nxdomain[3] = 0x80 | RCODE_NXDOMAIN;
+        assert_eq!(cache_ttl(&nxdomain), Some(NEGATIVE_TTL));
+        nxdomain[3] = 0x82; // SERVFAIL
+        assert_eq!(cache_ttl(&nxdomain), None);
+    }
+
+    /// TTL of the single answer record of an [`upstream_answer`]
+    fn answer_ttl(response: &[u8]) -> u32 {
+        let at = response.len() - 10;
+        u32::from_be_bytes(response[at..at + 4].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_cache_hits_count_down_ttls() {
+        let upstream = server("example.com", [93, 184, 216, 34]).await;
+        let proxy = DnsProxy::new(vec![Upstream::Udp(upstream)], vec![]);
+        let q = query(1, "example.com", TYPE_A);
+        assert_eq!(answer_ttl(&proxy.resolve(&q, upstream).await.unwrap()), 300);
+
+        // Pretend the answer has been cached for 100 s
+        for entry in proxy.cache.lock().await.values_mut() {
+            entry.stored -= Duration::from_secs(100);
+        }
+        let response = proxy
+            .resolve(&query(2, "example.com", TYPE_A), upstream)
+            .await
+            .unwrap();
+        assert_eq!(response[0..2], [0, 2]);
+        assert_eq!(answer_ttl(&response), 200);
+
+        // OPT records are left alone
+        let mut response = upstream_answer(&q, 300);
+        response[11] = 1; // ARCOUNT
+        response.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+        age_response(&mut response, 100).unwrap();
+        let ttl_at = q.len() + 6;
+        assert_eq!(response[ttl_at..ttl_at + 4], 200u32.to_be_bytes());
+        assert_eq!(
+            response[response.len() - 6..response.len() - 2],
+            [0, 0, 0x80, 0]
+        );
+    }
+
+    /// A server answering every query with `answer` for `name`
+    async fn server(name: &'static str, answer: [u8; 4]) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (_, from) = socket.recv_from(&mut buf).await.unwrap();
+                let mut response = upstream_answer(&query(0, name, TYPE_A), 300);
+                response[0..2].copy_from_slice(&buf[0..2]);
+                let len = response.len();
+                response[len - 4..].copy_from_slice(&answer);
+                socket.send_to(&response, from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_rogue_server_cannot_poison_other_guests() {
+        let rogue = server("bank.com", [6, 6, 6, 6]).await;
+        let honest = server("bank.com", [93, 184, 216, 34]).await;
+        let proxy = DnsProxy::new(vec![], vec![]);
+
+        // One guest asks a server it runs...
+        let poisoned = proxy
+            .resolve(&query(1, "bank.com", TYPE_A), rogue)
+            .await
+            .unwrap();
+        assert_eq!(poisoned[poisoned.len() - 4..], [6, 6, 6, 6]);
+
+        // ...which another guest asking its own server never sees
+        let answer = proxy
+            .resolve(&query(2, "bank.com", TYPE_A), honest)
+            .await
+            .unwrap();
+        assert_eq!(answer[answer.len() - 4..], [93, 184, 216, 34]);
+
+        // Answers to another question are dropped, not cached
+        assert!(
+            proxy
+                .resolve(&query(3, "other.com", TYPE_A), rogue)
+                .await
+                .is_none()
+        );
+        assert_eq!(proxy.cache.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_caches_upstream_answers() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            // Answer only once; the second lookup must come from the cache
+            let (n, from) = upstream.recv_from(&mut buf).await.unwrap();
+            let response = upstream_answer(&buf[..n], 300);
+            upstream.send_to(&response, from).await.unwrap();
+        });
+
+        let proxy = DnsProxy::new(vec![Upstream::Udp(server)], vec![]);
+        let unused: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let first = proxy
+            .resolve(&query(1, "example.com", TYPE_A), unused)
+            .await
+            .unwrap();
+        assert_eq!(first[first.len() - 4..], [93, 184, 216, 34]);
+        let second = proxy
+            .resolve(&query(2, "Example.com", TYPE_A), unused)
+            .await
+            .unwrap();
+        assert_eq!(second[0..2], [0, 2]);
+        assert_eq!(second[13..20], *b"Example");
+        assert_eq!(second[20..], first[20..]);
+
+        assert_eq!(
+            parse_upstream("1.1.1.1").unwrap(),
+            Upstream::Udp("1.1.1.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_upstream("https://dns.example/dns-query").unwrap(),
+            Upstream::Https("https://dns.example/dns-query".to_string())
+        );
+        assert!(parse_upstream("https://").is_err());
+        assert_eq!(
+            parse_override("nas=10.0.2.9").unwrap().1,
+            Ipv4Addr::new(10, 0, 2, 9)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_over_https() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            // The query is the whole body, after the headers
+            let body_at = loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                if let Some(end) = text.find("\r\n\r\n")
+                    && text.starts_with("post /dns-query ")
+                    && text.contains("content-type: application/dns-message")
+                    && request.len() > end + 4 + HEADER_LEN
+                    && parse_question(&request[end + 4..]).is_some()
+                {
+                    break end + 4;
+                }
+            };
+            // Answer with ID 0, as DoH servers may
+            let mut answer = upstream_answer(&request[body_at..], 300);
+            answer[0..2].fill(0);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\n\r\n",
+                DNS_MESSAGE,
+                answer.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(&answer).await.unwrap();
+        });
+
+        let proxy = DnsProxy::new(vec![Upstream::Https(url)], vec![]);
+        let unused: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let response = proxy
+            .resolve(&query(7, "example.com", TYPE_A), unused)
+            .await
+            .unwrap();
+        assert_eq!(response[0..2], [0, 7]);
+        assert_eq!(response[response.len() - 4..], [93, 184, 216, 34]);
+        assert_eq!(proxy.cache.lock().await.len(), 1);
+    }
+}
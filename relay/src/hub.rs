@@ -2,18 +2,28 @@
 //!
 //! The hub manages:
 //! - Peer connections and registration
-//! - Ethernet frame routing between peers
+//! - Ethernet frame routing between peers on the same virtual network
 //! - ARP handling for the virtual gateway
+//! - DHCP for guests that ask for their address
+//! - DNS queries, answered by the caching [`DnsProxy`] when enabled
 //! - Forwarding external traffic to the proxy
+//! - Live migration sessions between two hosts of a VM
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{RwLock, broadcast, mpsc};
 
+use crate::auth::constant_time_eq;
+use crate::batch::TransportMetrics;
+use crate::dhcp;
+use crate::dns::{self, DnsProxy};
+use crate::metrics::{PeerStatus, RouteCounters};
+use crate::migration::{MIGRATION_TIMEOUT, MigrationSide, Migrations, Reservation};
 use crate::peer::{PeerId, PeerManager};
 use crate::protocol::{
     ControlMessage, DNS_SERVER, GATEWAY_IP, GATEWAY_MAC, MSG_TYPE_CONTROL, MSG_TYPE_DATA,
-    NETWORK_MASK, encode_data_frame, format_ip, format_mac,
+    NETWORK_MASK, Session, encode_data_frame, format_ip, format_mac,
 };
 use crate::proxy::ExternalProxy;
 
@@ -34,8 +44,18 @@ pub struct Hub {
     peer_senders: Arc<RwLock<HashMap<PeerId, mpsc::Sender<PeerMessage>>>>,
     /// External traffic proxy
     proxy: Arc<ExternalProxy>,
-    /// Broadcast channel for frames (used for broadcasting)
-    broadcast_tx: broadcast::Sender<(PeerId, Vec<u8>)>,
+    /// Broadcast channel for frames flooded to a network: (sender, network, frame)
+    broadcast_tx: broadcast::Sender<(PeerId, Arc<str>, Vec<u8>)>,
+    /// Latest transport counters reported by each connection
+    transport: Arc<RwLock<HashMap<PeerId, TransportMetrics>>>,
+    /// Resolver for guest DNS queries; `None` forwards them like any UDP
+    dns: Option<Arc<DnsProxy>>,
+    /// Routing outcome counters
+    routes: RouteCounters,
+    /// Open live migration sessions
+    migrations: Arc<RwLock<Migrations>>,
+    /// When the hub was created
+    started: Instant,
 }
 
 impl Hub {
@@ -46,9 +66,58 @@ impl Hub {
             peer_senders: Arc::new(RwLock::new(HashMap::new())),
             proxy: Arc::new(ExternalProxy::new()),
             broadcast_tx,
+            transport: Arc::new(RwLock::new(HashMap::new())),
+            dns: None,
+            routes: RouteCounters::default(),
+            migrations: Arc::new(RwLock::new(Migrations::new())),
+            started: Instant::now(),
         }
     }
 
+    /// Answer guest DNS queries with `dns` instead of forwarding them
+    pub fn with_dns(mut self, dns: DnsProxy) -> Self {
+        self.dns = Some(Arc::new(dns));
+        self
+    }
+
+    /// Time since the hub started
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Routing outcome counters
+    pub fn route_counters(&self) -> &RouteCounters {
+        &self.routes
+    }
+
+    /// Status of every connected peer, and the number of networks in use
+    pub async fn peer_statuses(&self) -> (Vec<PeerStatus>, usize) {
+        let peers = self.peers.read().await;
+        let transport = self.transport.read().await;
+        let mut statuses: Vec<PeerStatus> = peers
+            .all_peers()
+            .into_iter()
+            .map(|peer| {
+                let metrics = transport.get(&peer.id).cloned().unwrap_or_default();
+                PeerStatus {
+                    id: peer.id,
+                    mac: format_mac(&peer.mac),
+                    ip: format_ip(&peer.ip),
+                    network: peer.network.clone(),
+                    idle_secs: peer.last_seen.elapsed().as_secs(),
+                    frames_sent: metrics.frames_sent,
+                    frames_received: metrics.frames_received,
+                    datagrams_sent: metrics.datagrams_sent,
+                    datagrams_received: metrics.datagrams_received,
+                    bytes_sent: metrics.bytes_wire,
+                    bytes_received: metrics.bytes_received,
+                }
+            })
+            .collect();
+        statuses.sort_by_key(|status| status.id);
+        (statuses, peers.network_count())
+    }
+
     /// Get a clone of the peers manager
     pub fn peers(&self) -> Arc<RwLock<PeerManager>> {
         self.peers.clone()
@@ -60,19 +129,59 @@ impl Hub {
     }
 
     /// Subscribe to the broadcast channel
-    pub fn subscribe(&self) -> broadcast::Receiver<(PeerId, Vec<u8>)> {
+    pub fn subscribe(&self) -> broadcast::Receiver<(PeerId, Arc<str>, Vec<u8>)> {
         self.broadcast_tx.subscribe()
     }
 
-    /// Register a new peer connection
+    /// Register a new peer connection on `network`. With the ticket of the
+    /// `migration` that brought its VM here, the peer takes over the
+    /// address and network the VM had on its old host. Otherwise a MAC
+    /// whose connection is still open cannot be registered again, so that
+    /// nobody else gets its address or migration key.
     pub async fn register_peer(
         &self,
         mac: [u8; 6],
+        network: &str,
         sender: mpsc::Sender<PeerMessage>,
-    ) -> Option<(PeerId, [u8; 4])> {
+        session: Session,
+        migration: Option<&str>,
+    ) -> Result<(PeerId, [u8; 4]), String> {
+        let reservation = match migration {
+            Some(ticket) => self.migrations.write().await.claim(ticket, &mac),
+            None => None,
+        };
         let mut peers = self.peers.write().await;
-        let result = peers.register(mac)?;
+        let result = match &reservation {
+            Some(reservation) => {
+                // The old host's registration gives way
+                if let Some(old) = peers.find_by_mac(&mac).map(|peer| peer.id) {
+                    peers.unregister(old);
+                    if let Some(sender) = self.peer_senders.write().await.remove(&old) {
+                        let _ = sender.try_send(PeerMessage::Disconnect);
+                    }
+                    self.transport.write().await.remove(&old);
+                }
+                peers.register_at(mac, &reservation.network, Some(reservation.ip))
+            }
+            None => {
+                if let Some(existing) = peers.find_by_mac(&mac).map(|peer| peer.id) {
+                    let live = self
+                        .peer_senders
+                        .read()
+                        .await
+                        .get(&existing)
+                        .is_some_and(|sender| !sender.is_closed());
+                    if live {
+                        return Err(format!("{} is already registered", format_mac(&mac)));
+                    }
+                }
+                peers.register(mac, network)
+            }
+        }
+        .ok_or_else(|| "IP pool exhausted".to_string())?;
         let (peer_id, ip) = result;
+        let migration_key = peers.get(peer_id).map(|peer| peer.migration_key.clone());
+        drop(peers);
 
         let mut senders = self.peer_senders.write().await;
         senders.insert(peer_id, sender);
@@ -83,13 +192,95 @@ impl Hub {
             gateway: GATEWAY_IP,
             netmask: NETWORK_MASK,
             dns: DNS_SERVER,
+            version: session.version,
+            compression: session.compression,
+            migration_key,
         };
 
         if let Some(sender) = senders.get(&peer_id) {
             let _ = sender.send(PeerMessage::Send(msg.encode())).await;
         }
 
-        Some((peer_id, ip))
+        Ok((peer_id, ip))
+    }
+
+    /// Open a migration session for a VM leaving through the connection
+    /// `sender`, reserving the address of its NIC `mac` if it is
+    /// registered, and return the ticket. A registered NIC is only moved
+    /// with the migration `key` its registration was assigned.
+    pub async fn open_migration(
+        &self,
+        mac: Option<[u8; 6]>,
+        key: Option<&str>,
+        sender: mpsc::Sender<PeerMessage>,
+    ) -> Result<String, String> {
+        let reservation = match mac {
+            Some(mac) => match self.peers.read().await.find_by_mac(&mac) {
+                Some(peer) => {
+                    let owner = key.is_some_and(|key| {
+                        constant_time_eq(key.as_bytes(), peer.migration_key.as_bytes())
+                    });
+                    if !owner {
+                        return Err(format!("wrong migration key for {}", format_mac(&mac)));
+                    }
+                    Some(Reservation {
+                        mac,
+                        ip: peer.ip,
+                        network: peer.network.clone(),
+                    })
+                }
+                None => None,
+            },
+            None => None,
+        };
+        let ticket = self
+            .migrations
+            .write()
+            .await
+            .open(reservation.clone(), sender)
+            .ok_or_else(|| "too many open migrations".to_string())?;
+        match reservation {
+            Some(reservation) => tracing::info!(
+                "Migration {} opened for {} (IP {}, network '{}')",
+                ticket,
+                format_mac(&reservation.mac),
+                format_ip(&reservation.ip),
+                reservation.network
+            ),
+            None => tracing::info!("Migration {} opened", ticket),
+        }
+        Ok(ticket)
+    }
+
+    /// Join the migration `ticket` as the host the VM moves to, through the
+    /// connection `sender`. Both ends are told once they are connected.
+    pub async fn join_migration(
+        &self,
+        ticket: &str,
+        sender: mpsc::Sender<PeerMessage>,
+    ) -> Result<(), String> {
+        let source = self.migrations.write().await.join(ticket, sender.clone())?;
+        let paired = ControlMessage::MigrationPaired.encode();
+        if let Some(source) = source {
+            let _ = source.send(PeerMessage::Send(paired.clone())).await;
+        }
+        let _ = sender.send(PeerMessage::Send(paired)).await;
+        tracing::info!("Migration {} joined", ticket);
+        Ok(())
+    }
+
+    /// Pass a migration datagram from the `from` end of `ticket`'s session
+    /// to the other one, if it is connected
+    pub async fn forward_migration(&self, ticket: &str, from: MigrationSide, data: Vec<u8>) {
+        let partner = self.migrations.read().await.partner(ticket, from);
+        if let Some(partner) = partner {
+            let _ = partner.send(PeerMessage::Send(data)).await;
+        }
+    }
+
+    /// Forget the `side` connection of `ticket`'s session
+    pub async fn leave_migration(&self, ticket: &str, side: MigrationSide) {
+        self.migrations.write().await.leave(ticket, side);
     }
 
     /// Unregister a peer
@@ -99,6 +290,13 @@ impl Hub {
 
         let mut senders = self.peer_senders.write().await;
         senders.remove(&peer_id);
+
+        self.transport.write().await.remove(&peer_id);
+    }
+
+    /// Store the latest transport counters of a peer connection
+    pub async fn report_transport(&self, peer_id: PeerId, metrics: TransportMetrics) {
+        self.transport.write().await.insert(peer_id, metrics);
     }
 
     /// Update last-seen timestamp for a peer
@@ -159,26 +357,53 @@ impl Hub {
         }
 
         let dst_mac: [u8; 6] = ethernet_frame[0..6].try_into().unwrap();
-        let _src_mac: [u8; 6] = ethernet_frame[6..12].try_into().unwrap();
+        let src_mac: [u8; 6] = ethernet_frame[6..12].try_into().unwrap();
         let ethertype = u16::from_be_bytes([ethernet_frame[12], ethernet_frame[13]]);
 
-        // Check for broadcast MAC
-        let is_broadcast = dst_mac == [0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        // Broadcast and multicast frames are flooded to the network
+        let is_group = dst_mac[0] & 0x01 != 0;
+
+        let Some(network) = self.learn_source(from_peer, src_mac).await else {
+            return; // Peer left while its frame was in flight
+        };
 
         // Handle ARP for gateway
         if ethertype == 0x0806 && self.is_arp_request_for_gateway(ethernet_frame) {
+            RouteCounters::count(&self.routes.gateway);
             let reply = self.generate_arp_reply(ethernet_frame);
             self.send_to_peer(from_peer, encode_data_frame(&reply))
                 .await;
             return;
         }
 
+        // DHCP hands out the address assigned to the peer's connection
+        if dhcp::is_dhcp_request(ethernet_frame) {
+            RouteCounters::count(&self.routes.gateway);
+            let lease = self.peers.read().await.get(from_peer).map(|peer| peer.ip);
+            if let Some(reply) = lease.and_then(|ip| dhcp::handle_request(ethernet_frame, ip)) {
+                self.send_to_peer(from_peer, encode_data_frame(&reply))
+                    .await;
+            }
+            return;
+        }
+
+        if let Some(dns) = &self.dns
+            && let Some(datagram) = UdpDatagram::parse(ethernet_frame)
+            && datagram.dst.1 == 53
+        {
+            RouteCounters::count(&self.routes.gateway);
+            self.handle_dns_query(dns.clone(), from_peer, &network, datagram)
+                .await;
+            return;
+        }
+
         // Handle IPv4
         if ethertype == 0x0800 && ethernet_frame.len() >= 34 {
             let dst_ip: [u8; 4] = ethernet_frame[30..34].try_into().unwrap();
 
             // Check if destination is gateway (ping to gateway)
             if dst_ip == GATEWAY_IP {
+                RouteCounters::count(&self.routes.gateway);
                 if let Some(reply) = self.handle_gateway_packet(ethernet_frame).await {
                     self.send_to_peer(from_peer, encode_data_frame(&reply))
                         .await;
@@ -190,6 +415,7 @@ impl Hub {
             let peers = self.peers.read().await;
             if !peers.is_internal_ip(&dst_ip) {
                 drop(peers);
+                RouteCounters::count(&self.routes.external);
                 // Route to external proxy
                 if let Some(reply) = self.proxy.handle_external_packet(ethernet_frame).await {
                     self.send_to_peer(from_peer, encode_data_frame(&reply))
@@ -198,35 +424,105 @@ impl Hub {
                 return;
             }
 
-            // Route to internal peer
+            // Route to internal peer, if it shares the sender's network
             if let Some(target_peer) = peers.peer_id_by_ip(&dst_ip) {
+                let same_network = peers.network_of(target_peer) == Some(&*network);
                 drop(peers);
-                if target_peer != from_peer {
+                if target_peer != from_peer && same_network {
+                    RouteCounters::count(&self.routes.unicast);
                     self.send_to_peer(target_peer, encode_data_frame(ethernet_frame))
                         .await;
+                } else {
+                    RouteCounters::count(&self.routes.dropped);
                 }
                 return;
             }
         }
 
-        // Broadcast handling
-        if is_broadcast {
+        if is_group {
+            RouteCounters::count(&self.routes.flooded);
             let _ = self
                 .broadcast_tx
-                .send((from_peer, encode_data_frame(ethernet_frame)));
+                .send((from_peer, network, encode_data_frame(ethernet_frame)));
         } else if dst_mac == GATEWAY_MAC {
             // Addressed to gateway but not handled above - drop
+            RouteCounters::count(&self.routes.dropped);
             tracing::trace!("Dropping frame addressed to gateway MAC");
         } else {
-            // Try to find peer by MAC
+            // Unicast goes to the peer that owns the MAC; unknown ones are
+            // flooded to the network like a switch would
+            let target = self.peers.read().await.lookup_mac(&network, &dst_mac);
+            match target {
+                Some(target_id) if target_id != from_peer => {
+                    RouteCounters::count(&self.routes.unicast);
+                    self.send_to_peer(target_id, encode_data_frame(ethernet_frame))
+                        .await;
+                }
+                Some(_) => RouteCounters::count(&self.routes.dropped),
+                None => {
+                    RouteCounters::count(&self.routes.flooded);
+                    let _ = self.broadcast_tx.send((
+                        from_peer,
+                        network,
+                        encode_data_frame(ethernet_frame),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Answer a guest DNS query, from the relay itself if it names a peer
+    /// or override, else from the proxy's cache or upstream in the background
+    async fn handle_dns_query(
+        &self,
+        dns: Arc<DnsProxy>,
+        from_peer: PeerId,
+        network: &str,
+        query: UdpDatagram<'_>,
+    ) {
+        let Some((question, _)) = dns::parse_question(query.payload) else {
+            return;
+        };
+        let peer_ip = self
+            .peers
+            .read()
+            .await
+            .resolve_peer_name(&question.name, network);
+        let reply_frame =
+            move |response: &[u8]| build_udp_frame(query.src_mac, query.dst, query.src, response);
+
+        if let Some(response) = dns.answer_local(query.payload, peer_ip) {
+            self.send_to_peer(from_peer, encode_data_frame(&reply_frame(&response)))
+                .await;
+            return;
+        }
+
+        let payload = query.payload.to_vec();
+        let guest_server = (std::net::Ipv4Addr::from(query.dst.0), query.dst.1).into();
+        let senders = self.peer_senders.clone();
+        tokio::spawn(async move {
+            let Some(response) = dns.resolve(&payload, guest_server).await else {
+                return;
+            };
+            let frame = encode_data_frame(&reply_frame(&response));
+            if let Some(sender) = senders.read().await.get(&from_peer) {
+                let _ = sender.send(PeerMessage::Send(frame)).await;
+            }
+        });
+    }
+
+    /// Learn `src_mac` behind `from_peer` and return the peer's network
+    async fn learn_source(&self, from_peer: PeerId, src_mac: [u8; 6]) -> Option<Arc<str>> {
+        {
             let peers = self.peers.read().await;
-            if let Some(peer) = peers.find_by_mac(&dst_mac) {
-                let target_id = peer.id;
-                drop(peers);
-                self.send_to_peer(target_id, encode_data_frame(ethernet_frame))
-                    .await;
+            let network = peers.network_of(from_peer)?;
+            if peers.lookup_mac(network, &src_mac) == Some(from_peer) || src_mac[0] & 0x01 != 0 {
+                return Some(network.into());
             }
         }
+        let mut peers = self.peers.write().await;
+        peers.learn(from_peer, src_mac);
+        peers.network_of(from_peer).map(Arc::from)
     }
 
     /// Send a message to a specific peer
@@ -325,13 +621,17 @@ impl Hub {
 
     /// Cleanup expired peers
     pub async fn cleanup_expired_peers(&self) {
+        self.migrations.write().await.expire(MIGRATION_TIMEOUT);
+
         let mut peers = self.peers.write().await;
         let expired = peers.cleanup_expired();
         drop(peers);
 
         let mut senders = self.peer_senders.write().await;
+        let mut transport = self.transport.write().await;
         for id in expired {
             senders.remove(&id);
+            transport.remove(&id);
         }
     }
 
@@ -340,15 +640,33 @@ impl Hub {
         let peers = self.peers.read().await;
         let count = peers.peer_count();
         if count > 0 {
-            tracing::info!("Hub stats: {} connected peers", count);
+            tracing::info!(
+                "Hub stats: {} connected peers on {} network(s)",
+                count,
+                peers.network_count()
+            );
+            let transport = self.transport.read().await;
             for peer in peers.all_peers() {
                 tracing::debug!(
-                    "  Peer {}: MAC={}, IP={}",
+                    "  Peer {}: MAC={}, IP={}, network={}",
                     peer.id,
                     format_mac(&peer.mac),
-                    format_ip(&peer.ip)
+                    format_ip(&peer.ip),
+                    peer.network
                 );
+                if let Some(metrics) = transport.get(&peer.id) {
+                    tracing::debug!("    {}", metrics);
+                }
+            }
+            let mut total = TransportMetrics::default();
+            for metrics in transport.values() {
+                total.merge(metrics);
             }
+            tracing::info!("Transport: {}", total);
+        }
+        let migrations = self.migrations.read().await.count();
+        if migrations > 0 {
+            tracing::info!("Hub stats: {} migration(s) open", migrations);
         }
     }
 }
@@ -359,8 +677,77 @@ impl Default for Hub {
     }
 }
 
+/// A guest UDP datagram inside an Ethernet frame
+#[derive(Clone, Copy)]
+struct UdpDatagram<'a> {
+    src_mac: [u8; 6],
+    src: ([u8; 4], u16),
+    dst: ([u8; 4], u16),
+    payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < 42 || frame[12..14] != [0x08, 0x00] || frame[23] != 17 {
+            return None;
+        }
+        let ihl = (frame[14] & 0x0f) as usize * 4;
+        let ip_len = u16::from_be_bytes([frame[16], frame[17]]) as usize;
+        let udp = frame.get(14 + ihl..(14 + ip_len).min(frame.len()))?;
+        let udp_len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+        Some(Self {
+            src_mac: frame[6..12].try_into().unwrap(),
+            src: (
+                frame[26..30].try_into().unwrap(),
+                u16::from_be_bytes([udp[0], udp[1]]),
+            ),
+            dst: (
+                frame[30..34].try_into().unwrap(),
+                u16::from_be_bytes([udp[2], udp[3]]),
+            ),
+            payload: udp.get(8..udp_len.min(udp.len()))?,
+        })
+    }
+}
+
+/// Build an Ethernet frame from the gateway carrying a UDP datagram from
+/// `src` to `dst` (address, port)
+pub fn build_udp_frame(
+    dst_mac: [u8; 6],
+    src: ([u8; 4], u16),
+    dst: ([u8; 4], u16),
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = 8 + payload.len();
+    let ip_len = 20 + udp_len;
+    let mut frame = vec![0u8; 14 + ip_len];
+
+    frame[0..6].copy_from_slice(&dst_mac);
+    frame[6..12].copy_from_slice(&GATEWAY_MAC);
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+
+    let ip = &mut frame[14..34];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(ip_len as u16).to_be_bytes());
+    ip[8] = 64; // TTL
+    ip[9] = 17; // UDP
+    ip[12..16].copy_from_slice(&src.0);
+    ip[16..20].copy_from_slice(&dst.0);
+    let checksum = compute_checksum(ip);
+    ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    // UDP checksum is optional over IPv4 and left at zero
+    let udp = &mut frame[34..42];
+    udp[0..2].copy_from_slice(&src.1.to_be_bytes());
+    udp[2..4].copy_from_slice(&dst.1.to_be_bytes());
+    udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+
+    frame[42..].copy_from_slice(payload);
+    frame
+}
+
 /// Compute Internet checksum
-fn compute_checksum(data: &[u8]) -> u16 {
+pub fn compute_checksum(data: &[u8]) -> u16 {
     let mut sum: u32 = 0;
     let mut i = 0;
     while i + 1 < data.len() {
@@ -375,3 +762,230 @@ fn compute_checksum(data: &[u8]) -> u16 {
     }
     !(sum as u16)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(dst: [u8; 6], src: [u8; 6]) -> Vec<u8> {
+        let mut frame = vec![0u8; 60];
+        frame[0..6].copy_from_slice(&dst);
+        frame[6..12].copy_from_slice(&src);
+        frame[12..14].copy_from_slice(&[0x88, 0xb5]); // local experimental ethertype
+        frame
+    }
+
+    async fn register(
+        hub: &Hub,
+        mac: [u8; 6],
+        network: &str,
+    ) -> (PeerId, mpsc::Receiver<PeerMessage>) {
+        let (tx, mut rx) = mpsc::channel(16);
+        let session = Session::negotiate(2, false, false);
+        let (id, _) = hub
+            .register_peer(mac, network, tx, session, None)
+            .await
+            .unwrap();
+        rx.recv().await.unwrap(); // Assigned
+        (id, rx)
+    }
+
+    #[tokio::test]
+    async fn test_frames_stay_on_their_network() {
+        let hub = Hub::new();
+        let mut flooded = hub.subscribe();
+        let (mac_a, mac_b, mac_c) = ([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2], [2, 0, 0, 0, 0, 3]);
+        let (a, _rx_a) = register(&hub, mac_a, "lab").await;
+        let (_b, mut rx_b) = register(&hub, mac_b, "lab").await;
+        let (_c, mut rx_c) = register(&hub, mac_c, "other").await;
+
+        // Unicast goes to the owner on the same network only
+        hub.route_data_frame(a, &frame(mac_b, mac_a)).await;
+        assert!(matches!(rx_b.try_recv(), Ok(PeerMessage::Send(_))));
+        hub.route_data_frame(a, &frame(mac_c, mac_a)).await;
+        assert!(rx_c.try_recv().is_err());
+
+        // ...which is flooded on the sender's network, as is a broadcast
+        let (from, network, _) = flooded.try_recv().unwrap();
+        assert_eq!((from, &*network), (a, "lab"));
+        hub.route_data_frame(a, &frame([0xff; 6], mac_a)).await;
+        let (_, network, _) = flooded.try_recv().unwrap();
+        assert_eq!(&*network, "lab");
+    }
+
+    #[tokio::test]
+    async fn test_dns_queries_answered_locally() {
+        let overrides = vec![("nas.lab".to_string(), std::net::Ipv4Addr::new(10, 0, 2, 20))];
+        let hub = Hub::new().with_dns(DnsProxy::new(vec![], overrides));
+        let mac = [2, 0, 0, 0, 0, 1];
+        let (a, mut rx) = register(&hub, mac, "lab").await;
+        let (b, _rx_b) = register(&hub, [2, 0, 0, 0, 0, 2], "lab").await;
+        let ip_b = hub.peers.read().await.get(b).unwrap().ip;
+
+        for (name, expected) in [
+            ("nas.lab", [10, 0, 2, 20]),
+            (&*format!("peer{}.local", b), ip_b),
+        ] {
+            let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+            for label in name.split('.') {
+                query.push(label.len() as u8);
+                query.extend_from_slice(label.as_bytes());
+            }
+            query.extend_from_slice(&[0, 0, 1, 0, 1]);
+            let mut frame = build_udp_frame(
+                GATEWAY_MAC,
+                ([10, 0, 2, 15], 4000),
+                (DNS_SERVER, 53),
+                &query,
+            );
+            frame[6..12].copy_from_slice(&mac);
+            hub.route_data_frame(a, &frame).await;
+
+            let Ok(PeerMessage::Send(reply)) = rx.try_recv() else {
+                panic!("no DNS reply for {}", name);
+            };
+            let reply = UdpDatagram::parse(&reply[1..]).unwrap();
+            assert_eq!(
+                (reply.src, reply.dst),
+                ((DNS_SERVER, 53), ([10, 0, 2, 15], 4000))
+            );
+            assert_eq!(reply.payload[0..2], [0x12, 0x34]);
+            assert_eq!(reply.payload[reply.payload.len() - 4..], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migration_keeps_address() {
+        let hub = Hub::new();
+        let mac = [2, 0, 0, 0, 0, 1];
+        let (old, mut rx_old) = register(&hub, mac, "lab").await;
+        let (old_ip, key) = {
+            let peers = hub.peers.read().await;
+            let peer = peers.get(old).unwrap();
+            (peer.ip, peer.migration_key.clone())
+        };
+
+        let (source, mut rx_source) = mpsc::channel(16);
+        let (destination, mut rx_destination) = mpsc::channel(16);
+        let ticket = hub
+            .open_migration(Some(mac), Some(&key), source)
+            .await
+            .unwrap();
+        assert!(
+            hub.join_migration("bogus", destination.clone())
+                .await
+                .is_err()
+        );
+        hub.join_migration(&ticket, destination).await.unwrap();
+        for rx in [&mut rx_source, &mut rx_destination] {
+            let Some(PeerMessage::Send(data)) = rx.recv().await else {
+                panic!("no pairing message");
+            };
+            assert!(matches!(
+                ControlMessage::decode(&data),
+                Ok(ControlMessage::MigrationPaired)
+            ));
+        }
+
+        hub.forward_migration(&ticket, MigrationSide::Source, vec![3, 1])
+            .await;
+        assert!(
+            matches!(rx_destination.recv().await, Some(PeerMessage::Send(data)) if data == [3, 1])
+        );
+        hub.leave_migration(&ticket, MigrationSide::Destination)
+            .await;
+        hub.forward_migration(&ticket, MigrationSide::Source, vec![3, 2])
+            .await;
+        assert!(rx_destination.try_recv().is_err());
+
+        // The new host asks for the default network but keeps the old
+        // address, and the old registration is dropped
+        let (tx, _rx) = mpsc::channel(16);
+        let session = Session::negotiate(2, false, false);
+        let (new, ip) = hub
+            .register_peer(mac, "default", tx, session, Some(&ticket))
+            .await
+            .unwrap();
+        assert_ne!(new, old);
+        assert_eq!(ip, old_ip);
+        assert!(matches!(rx_old.recv().await, Some(PeerMessage::Disconnect)));
+        let peers = hub.peers.read().await;
+        assert!(peers.get(old).is_none());
+        assert_eq!(peers.network_of(new), Some("lab"));
+    }
+
+    #[tokio::test]
+    async fn test_stranger_cannot_migrate_a_peer() {
+        let hub = Hub::new();
+        let mac = [2, 0, 0, 0, 0, 1];
+        let (tx, mut rx) = mpsc::channel(16);
+        let session = Session::negotiate(2, false, false);
+        let (victim, _) = hub
+            .register_peer(mac, "lab", tx, session, None)
+            .await
+            .unwrap();
+        let Some(PeerMessage::Send(assigned)) = rx.recv().await else {
+            panic!("no assignment");
+        };
+        let Ok(ControlMessage::Assigned {
+            migration_key: Some(key),
+            ..
+        }) = ControlMessage::decode(&assigned)
+        else {
+            panic!("assignment without a migration key");
+        };
+
+        // Without the key, or with a wrong one, no ticket is issued
+        for wrong in [None, Some("0123456789abcdef0123456789abcdef")] {
+            let (stranger, _) = mpsc::channel(16);
+            assert!(
+                hub.open_migration(Some(mac), wrong, stranger)
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(hub.migrations.read().await.count(), 0);
+
+        assert_eq!(hub.peers.read().await.network_of(victim), Some("lab"));
+
+        // Nor can a second connection register the live MAC to get a key
+        let (tx, mut rx_stranger) = mpsc::channel(16);
+        assert!(
+            hub.register_peer(mac, "lab", tx, session, None)
+                .await
+                .is_err()
+        );
+        assert!(rx_stranger.try_recv().is_err());
+        let (source, _) = mpsc::channel(16);
+        assert!(hub.open_migration(Some(mac), None, source).await.is_err());
+        assert_eq!(hub.migrations.read().await.count(), 0);
+
+        // Once the victim's connection is gone the MAC can be registered
+        // again, with a new key
+        drop(rx);
+        let (tx, _rx) = mpsc::channel(16);
+        hub.register_peer(mac, "lab", tx, session, None)
+            .await
+            .unwrap();
+        let (source, _) = mpsc::channel(16);
+        assert!(
+            hub.open_migration(Some(mac), Some(&key), source)
+                .await
+                .is_err()
+        );
+
+        // A MAC nobody registered has no address to take over
+        let (source, _) = mpsc::channel(16);
+        let ticket = hub
+            .open_migration(Some([2, 0, 0, 0, 0, 9]), None, source)
+            .await
+            .unwrap();
+        assert!(
+            hub.migrations
+                .write()
+                .await
+                .claim(&ticket, &[2, 0, 0, 0, 0, 9])
+                .is_none()
+        );
+    }
+}
@@ -4,16 +4,31 @@
 //! - Browser <-> Browser connectivity via WebTransport
 //! - Browser <-> Server connectivity
 //! - Server <-> Server connectivity
-//! - Virtual network with DHCP-like IP assignment (10.0.2.x)
-//! - External traffic proxy (DNS, ICMP) for VMs
-
+//! - Virtual network with IP assignment (10.0.2.x), also served over DHCP
+//! - Isolated virtual LANs selected by a network ID at registration
+//! - External traffic proxy (TCP, UDP, ICMP) for VMs
+//! - Caching DNS proxy with local name overrides
+//! - Optional token and MAC allow-list access control
+//! - Optional Prometheus/JSON monitoring endpoint
+//! - Live migration of VMs between hosts, keeping their address
+
+mod auth;
+// The VM's batching code, compiled here too so the format cannot drift
+#[path = "../../riscv-vm/src/net/batch.rs"]
+mod batch;
+mod dhcp;
+mod dns;
 mod hub;
+mod metrics;
+mod migration;
 mod peer;
 mod protocol;
 mod proxy;
 
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use clap::Parser;
@@ -31,9 +46,23 @@ const QUIC_KEEP_ALIVE_SECS: u64 = 15;
 /// Connection is closed if no activity for this duration.
 const QUIC_MAX_IDLE_TIMEOUT_SECS: u64 = 180;
 
+/// How often each connection reports its transport counters to the hub.
+const TRANSPORT_REPORT_SECS: u64 = 10;
+
+/// Age at which a stored self-signed identity is replaced. Browsers only pin
+/// certificates valid for at most 14 days, so it is renewed a day early.
+const IDENTITY_MAX_AGE: Duration = Duration::from_secs(13 * 24 * 60 * 60);
+
+use crate::auth::AccessPolicy;
+use crate::batch::{BatchConfig, Batcher, unpack_batch};
+use crate::dns::DnsProxy;
 use crate::hub::{Hub, PeerMessage};
+use crate::migration::MigrationSide;
 use crate::peer::PeerId;
-use crate::protocol::{ControlMessage, MSG_TYPE_CONTROL, encode_data_frame};
+use crate::protocol::{
+    ControlMessage, DEFAULT_NETWORK, MSG_TYPE_BATCH, MSG_TYPE_CONTROL, MSG_TYPE_MIGRATION, Session,
+    encode_data_frame,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -60,6 +89,22 @@ struct Args {
     #[arg(long, env = "RELAY_KEY_PEM")]
     key_pem: Option<String>,
 
+    /// File holding the self-signed identity (certificate and key). It is
+    /// created on first start and reused, so the certificate hash stays the
+    /// same across restarts until the certificate is renewed.
+    #[arg(long, env = "RELAY_IDENTITY_FILE", conflicts_with_all = ["cert_pem", "key_pem"])]
+    identity_file: Option<String>,
+
+    /// Require clients to connect with `?token=<TOKEN>` in the URL.
+    /// May be repeated to accept several tokens.
+    #[arg(long = "auth-token", env = "RELAY_AUTH_TOKENS", value_delimiter = ',')]
+    auth_tokens: Vec<String>,
+
+    /// Only let peers with this MAC address register (e.g. 52:54:00:12:34:56).
+    /// May be repeated.
+    #[arg(long = "allow-mac", value_parser = auth::parse_mac)]
+    allow_macs: Vec<[u8; 6]>,
+
     /// Heartbeat interval in seconds
     #[arg(long, default_value_t = 30)]
     heartbeat_interval: u64,
@@ -67,6 +112,39 @@ struct Args {
     /// Peer timeout in seconds (increased for browser backgrounding tolerance)
     #[arg(long, default_value_t = 150)]
     peer_timeout: u64,
+
+    /// Coalesce frames to a v2 peer for up to this many milliseconds
+    /// (0 disables batching)
+    #[arg(long, default_value_t = 2)]
+    batch_window_ms: u64,
+
+    /// Largest batch datagram in bytes
+    #[arg(long, default_value_t = 1200)]
+    batch_max_bytes: usize,
+
+    /// Never LZ4-compress batches
+    #[arg(long)]
+    no_compression: bool,
+
+    /// Forward guest DNS queries to this server (`IP`, `IP:PORT` or a
+    /// DNS-over-HTTPS `https://` URL) instead of the one the guest asked.
+    /// May be repeated; tried in order.
+    #[arg(long = "dns-upstream", value_parser = dns::parse_upstream)]
+    dns_upstreams: Vec<dns::Upstream>,
+
+    /// Answer DNS queries for NAME with IP (e.g. `nas.lab=10.0.2.20`;
+    /// `*.lab=...` matches all subdomains). May be repeated.
+    #[arg(long = "dns-override", value_parser = dns::parse_override)]
+    dns_overrides: Vec<(String, Ipv4Addr)>,
+
+    /// Forward guest DNS queries untouched, without caching or local names
+    #[arg(long, conflicts_with_all = ["dns_upstreams", "dns_overrides"])]
+    no_dns_proxy: bool,
+
+    /// Serve Prometheus metrics on /metrics and a JSON status on /status
+    /// at this address (e.g. 127.0.0.1:9090)
+    #[arg(long, env = "RELAY_METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
 }
 
 /// Build the TLS identity either from provided PEM files (certificate + key) or
@@ -84,12 +162,56 @@ async fn build_identity(args: &Args) -> Result<Identity> {
             "Both --cert-pem/RELAY_CERT_PEM and --key-pem/RELAY_KEY_PEM must be set \
              to use a custom certificate"
         );
+    } else if let Some(path) = &args.identity_file {
+        load_or_create_identity(Path::new(path)).await
     } else {
         info!("No certificate/key provided; generating ephemeral self-signed identity");
         Ok(Identity::self_signed(["localhost", "127.0.0.1", "::1"])?)
     }
 }
 
+/// Load the self-signed identity stored at `path`, or generate one and store
+/// it there if the file is missing or due for renewal.
+async fn load_or_create_identity(path: &Path) -> Result<Identity> {
+    let age = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+    if let Some(age) = age {
+        if age < IDENTITY_MAX_AGE {
+            info!("Loading TLS identity from '{}'", path.display());
+            return Ok(Identity::load_pemfiles(path, path).await?);
+        }
+        info!("Stored identity in '{}' is about to expire; renewing", path.display());
+    } else {
+        info!("Generating self-signed identity into '{}'", path.display());
+    }
+
+    let identity = Identity::self_signed(["localhost", "127.0.0.1", "::1"])?;
+    let pem = format!(
+        "{}{}",
+        identity.certificate_chain().as_slice()[0].to_pem(),
+        identity.private_key().to_secret_pem()
+    );
+    write_private_file(path, pem.as_bytes())?;
+    Ok(identity)
+}
+
+/// Write a file only the current user can read.
+fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize logging
@@ -117,8 +239,33 @@ async fn main() -> Result<()> {
     info!("Certificate Hash: {}", cert_hash_hex);
     info!("Use this hash with --net-cert-hash when connecting");
 
+    let policy = Arc::new(AccessPolicy::new(args.auth_tokens.clone(), args.allow_macs.clone()));
+    info!("Access: {}", policy.describe());
+
     // Create the central hub
-    let hub = Arc::new(Hub::new());
+    let mut hub = Hub::new();
+    if args.no_dns_proxy {
+        info!("DNS proxy: off (queries forwarded as-is)");
+    } else {
+        info!(
+            "DNS proxy: upstream {}, {} override(s)",
+            if args.dns_upstreams.is_empty() {
+                "as asked by the guest".to_string()
+            } else {
+                args.dns_upstreams
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            args.dns_overrides.len()
+        );
+        hub = hub.with_dns(DnsProxy::new(
+            args.dns_upstreams.clone(),
+            args.dns_overrides.clone(),
+        ));
+    }
+    let hub = Arc::new(hub);
 
     // Initialize the external proxy
     if let Err(e) = hub.proxy().init().await {
@@ -137,6 +284,15 @@ async fn main() -> Result<()> {
         run_tcp_proxy_receiver(hub_clone).await;
     });
 
+    if let Some(addr) = args.metrics_addr {
+        let hub_clone = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, hub_clone).await {
+                warn!("Metrics endpoint failed: {}", e);
+            }
+        });
+    }
+
     // Spawn the peer cleanup task
     let hub_clone = hub.clone();
     let timeout = args.peer_timeout;
@@ -173,13 +329,27 @@ async fn main() -> Result<()> {
 
     info!("Listening on https://{}:{}", args.bind, args.port);
 
+    let batching = BatchConfig {
+        window: Duration::from_millis(args.batch_window_ms),
+        max_datagram: args.batch_max_bytes,
+        compress: !args.no_compression,
+        ..Default::default()
+    };
+    info!(
+        "Frame batching: window {}ms, max {} bytes, compression {}",
+        args.batch_window_ms,
+        args.batch_max_bytes,
+        if batching.compress { "on" } else { "off" }
+    );
+
     // Accept incoming sessions
     loop {
         let incoming_session = endpoint.accept().await;
         let hub = hub.clone();
+        let policy = policy.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(incoming_session, hub).await {
+            if let Err(e) = handle_connection(incoming_session, hub, batching, &policy).await {
                 warn!("Connection error: {}", e);
             }
         });
@@ -190,10 +360,18 @@ async fn main() -> Result<()> {
 async fn handle_connection(
     incoming: wtransport::endpoint::IncomingSession,
     hub: Arc<Hub>,
+    batching: BatchConfig,
+    policy: &AccessPolicy,
 ) -> Result<()> {
     let request = incoming.await?;
     info!("New connection from {:?}", request.remote_address());
 
+    if !policy.allows_session(request.path()) {
+        warn!("Rejected {:?}: missing or invalid token", request.remote_address());
+        request.forbidden().await;
+        return Ok(());
+    }
+
     let connection = request.accept().await?;
     info!("Session established with {:?}", connection.remote_address());
 
@@ -203,6 +381,8 @@ async fn handle_connection(
     // Wait for registration message
     let peer_id: PeerId;
     let assigned_ip: [u8; 4];
+    let session: Session;
+    let network: String;
 
     loop {
         tokio::select! {
@@ -211,29 +391,82 @@ async fn handle_connection(
                     Ok(datagram) => {
                         let data = datagram.to_vec();
                         if !data.is_empty() && data[0] == MSG_TYPE_CONTROL {
-                            if let Ok(ControlMessage::Register { mac }) = ControlMessage::decode(&data) {
+                            match ControlMessage::decode(&data) {
+                            Ok(ControlMessage::MigrateOut { mac, key }) => {
+                                if let Some(mac) = mac.filter(|mac| !policy.allows_mac(mac)) {
+                                    warn!("Rejected migration of {}: MAC not allowed", protocol::format_mac(&mac));
+                                    let err = ControlMessage::Error {
+                                        message: "MAC address not allowed".to_string(),
+                                    };
+                                    let _ = connection.send_datagram(err.encode());
+                                    return Ok(());
+                                }
+                                let ticket = match hub.open_migration(mac, key.as_deref(), tx.clone()).await {
+                                    Ok(ticket) => ticket,
+                                    Err(message) => {
+                                        warn!("Rejected migration: {}", message);
+                                        let _ = connection.send_datagram(ControlMessage::Error { message }.encode());
+                                        return Ok(());
+                                    }
+                                };
+                                let reply = ControlMessage::MigrationTicket { ticket: ticket.clone() };
+                                let _ = connection.send_datagram(reply.encode());
+                                return serve_migration(&connection, &hub, &ticket, MigrationSide::Source, rx).await;
+                            }
+                            Ok(ControlMessage::MigrateIn { ticket }) => {
+                                if let Err(message) = hub.join_migration(&ticket, tx.clone()).await {
+                                    warn!("Rejected migration {}: {}", ticket, message);
+                                    let _ = connection.send_datagram(ControlMessage::Error { message }.encode());
+                                    return Ok(());
+                                }
+                                return serve_migration(&connection, &hub, &ticket, MigrationSide::Destination, rx).await;
+                            }
+                            Ok(ControlMessage::Register { mac, version, compression, network: requested, migration }) => {
+                                if !policy.allows_mac(&mac) {
+                                    warn!("Rejected registration of {}: MAC not allowed", protocol::format_mac(&mac));
+                                    let err = ControlMessage::Error {
+                                        message: "MAC address not allowed".to_string(),
+                                    };
+                                    let _ = connection.send_datagram(err.encode());
+                                    return Ok(());
+                                }
+                                let requested = requested.unwrap_or_else(|| DEFAULT_NETWORK.to_string());
+                                if !protocol::is_valid_network_id(&requested) {
+                                    let err = ControlMessage::Error {
+                                        message: format!("invalid network ID '{}'", requested),
+                                    };
+                                    let _ = connection.send_datagram(err.encode());
+                                    return Ok(());
+                                }
                                 // Register the peer
-                                match hub.register_peer(mac, tx.clone()).await {
-                                    Some((id, ip)) => {
+                                let negotiated = Session::negotiate(version, compression, batching.compress);
+                                match hub.register_peer(mac, &requested, tx.clone(), negotiated, migration.as_deref()).await {
+                                    Ok((id, ip)) => {
                                         peer_id = id;
                                         assigned_ip = ip;
+                                        session = negotiated;
+                                        network = requested;
                                         info!(
-                                            "Peer {} registered: MAC={}, IP={}",
+                                            "Peer {} registered: MAC={}, IP={}, network '{}', protocol v{}{}",
                                             peer_id,
                                             protocol::format_mac(&mac),
-                                            protocol::format_ip(&ip)
+                                            protocol::format_ip(&ip),
+                                            network,
+                                            session.version,
+                                            if session.compression { " +lz4" } else { "" }
                                         );
                                         break;
                                     }
-                                    None => {
-                                        let err = ControlMessage::Error {
-                                            message: "IP pool exhausted".to_string(),
-                                        };
+                                    Err(message) => {
+                                        warn!("Rejected registration of {}: {}", protocol::format_mac(&mac), message);
+                                        let err = ControlMessage::Error { message };
                                         let _ = connection.send_datagram(err.encode());
                                         return Ok(());
                                     }
                                 }
                             }
+                            _ => {}
+                            }
                         }
                     }
                     Err(e) => {
@@ -252,8 +485,21 @@ async fn handle_connection(
     // Subscribe to broadcast channel
     let mut broadcast_rx = hub.subscribe();
 
+    // Frames to this peer are batched once it has agreed to protocol v2
+    let mut batcher = if session.batching() {
+        let mut config = batching;
+        if let Some(max) = connection.max_datagram_size() {
+            config.max_datagram = config.max_datagram.min(max);
+        }
+        Batcher::new(config, session.compression)
+    } else {
+        Batcher::new(BatchConfig::disabled(), false)
+    };
+    let mut report_interval = tokio::time::interval(Duration::from_secs(TRANSPORT_REPORT_SECS));
+
     // Main message loop
     loop {
+        let flush_at = batcher.deadline();
         tokio::select! {
             // Receive from client
             result = connection.receive_datagram() => {
@@ -261,7 +507,20 @@ async fn handle_connection(
                     Ok(datagram) => {
                         let data = datagram.to_vec();
                         hub.touch_peer(peer_id).await;
-                        hub.route_frame(peer_id, data).await;
+                        if data.first() == Some(&MSG_TYPE_BATCH) {
+                            match unpack_batch(&data) {
+                                Ok(messages) => {
+                                    batcher.metrics.record_received(data.len(), messages.len());
+                                    for msg in messages {
+                                        hub.route_frame(peer_id, msg).await;
+                                    }
+                                }
+                                Err(e) => warn!("Dropping batch from peer {}: {}", peer_id, e),
+                            }
+                        } else {
+                            batcher.metrics.record_received(data.len(), 1);
+                            hub.route_frame(peer_id, data).await;
+                        }
                     }
                     Err(e) => {
                         info!("Peer {} disconnected: {}", peer_id, e);
@@ -274,7 +533,9 @@ async fn handle_connection(
             Some(msg) = rx.recv() => {
                 match msg {
                     PeerMessage::Send(data) => {
-                        if let Err(e) = connection.send_datagram(data) {
+                        if let Some(datagram) = batcher.push(data, Instant::now())
+                            && let Err(e) = connection.send_datagram(datagram)
+                        {
                             warn!("Failed to send to peer {}: {}", peer_id, e);
                             break;
                         }
@@ -287,17 +548,35 @@ async fn handle_connection(
             }
 
             // Broadcast messages (from other peers)
-            Ok((from_peer, data)) = broadcast_rx.recv() => {
-                if from_peer != peer_id {
-                    if let Err(e) = connection.send_datagram(data) {
-                        warn!("Failed to broadcast to peer {}: {}", peer_id, e);
-                        break;
-                    }
+            Ok((from_peer, frame_network, data)) = broadcast_rx.recv() => {
+                if from_peer != peer_id
+                    && *frame_network == *network
+                    && let Some(datagram) = batcher.push(data, Instant::now())
+                    && let Err(e) = connection.send_datagram(datagram)
+                {
+                    warn!("Failed to broadcast to peer {}: {}", peer_id, e);
+                    break;
                 }
             }
+
+            // Batch window elapsed
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now).into()), if flush_at.is_some() => {
+                if let Some(datagram) = batcher.poll(Instant::now())
+                    && let Err(e) = connection.send_datagram(datagram)
+                {
+                    warn!("Failed to send to peer {}: {}", peer_id, e);
+                    break;
+                }
+            }
+
+            _ = report_interval.tick() => {
+                hub.report_transport(peer_id, batcher.metrics.clone()).await;
+            }
         }
     }
 
+    info!("Peer {} transport: {}", peer_id, batcher.metrics);
+
     // Cleanup
     hub.unregister_peer(peer_id).await;
     info!(
@@ -309,6 +588,53 @@ async fn handle_connection(
     Ok(())
 }
 
+/// Carry one end of the migration session `ticket` until its connection
+/// closes: migration datagrams go to the other end, and whatever the hub
+/// sends back comes out here
+async fn serve_migration(
+    connection: &wtransport::Connection,
+    hub: &Hub,
+    ticket: &str,
+    side: MigrationSide,
+    mut rx: mpsc::Receiver<PeerMessage>,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            result = connection.receive_datagram() => {
+                let data = match result {
+                    Ok(datagram) => datagram.to_vec(),
+                    Err(e) => {
+                        info!("Migration {} {:?} disconnected: {}", ticket, side, e);
+                        break;
+                    }
+                };
+                match data.first() {
+                    Some(&MSG_TYPE_MIGRATION) => hub.forward_migration(ticket, side, data).await,
+                    Some(&MSG_TYPE_CONTROL) => {
+                        if let Ok(ControlMessage::Heartbeat) = ControlMessage::decode(&data) {
+                            let _ = connection.send_datagram(ControlMessage::HeartbeatAck.encode());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some(msg) = rx.recv() => {
+                match msg {
+                    PeerMessage::Send(data) => {
+                        if let Err(e) = connection.send_datagram(data) {
+                            warn!("Failed to send to migration {} {:?}: {}", ticket, side, e);
+                            break;
+                        }
+                    }
+                    PeerMessage::Disconnect => break,
+                }
+            }
+        }
+    }
+    hub.leave_migration(ticket, side).await;
+    Ok(())
+}
+
 /// Run the external proxy UDP receiver loop
 async fn run_udp_proxy_receiver(hub: Arc<Hub>) {
     loop {
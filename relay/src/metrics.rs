@@ -0,0 +1,314 @@
+//! Monitoring endpoint for the relay.
+//!
+//! With `--metrics-addr`, the relay serves plain HTTP on that address:
+//! - `GET /metrics`: Prometheus text format
+//! - `GET /status`: the same data as JSON, with one entry per peer
+//!
+//! Counters are cumulative since startup; rates are left to the scraper.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::hub::Hub;
+
+/// Largest request head read before giving up on a client.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// How the hub disposed of the data frames it routed.
+#[derive(Debug, Default)]
+pub struct RouteCounters {
+    /// Delivered to the single peer owning the destination
+    pub unicast: AtomicU64,
+    /// Flooded to a network (broadcast, multicast, unknown unicast)
+    pub flooded: AtomicU64,
+    /// Handed to the external NAT proxy
+    pub external: AtomicU64,
+    /// Answered by the gateway itself (ARP, ping, DHCP)
+    pub gateway: AtomicU64,
+    /// Dropped (cross-network or undeliverable)
+    pub dropped: AtomicU64,
+}
+
+impl RouteCounters {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> RouteStats {
+        RouteStats {
+            unicast: self.unicast.load(Ordering::Relaxed),
+            flooded: self.flooded.load(Ordering::Relaxed),
+            external: self.external.load(Ordering::Relaxed),
+            gateway: self.gateway.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time view of the relay, as served on `/status`.
+#[derive(Debug, Serialize)]
+pub struct RelayStatus {
+    pub uptime_secs: u64,
+    pub peers: Vec<PeerStatus>,
+    pub networks: usize,
+    pub frames: RouteStats,
+    pub nat: NatStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeerStatus {
+    pub id: u64,
+    pub mac: String,
+    pub ip: String,
+    pub network: String,
+    pub idle_secs: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteStats {
+    pub unicast: u64,
+    pub flooded: u64,
+    pub external: u64,
+    pub gateway: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NatStats {
+    pub tcp_sessions: usize,
+    pub udp_sessions: usize,
+}
+
+impl RelayStatus {
+    pub async fn collect(hub: &Hub) -> Self {
+        let (tcp_sessions, udp_sessions) = hub.proxy().session_counts().await;
+        let (peers, networks) = hub.peer_statuses().await;
+        Self {
+            uptime_secs: hub.uptime().as_secs(),
+            peers,
+            networks,
+            frames: hub.route_counters().snapshot(),
+            nat: NatStats {
+                tcp_sessions,
+                udp_sessions,
+            },
+        }
+    }
+
+    /// Render in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, help: &str, kind: &str, samples: &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP relay_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE relay_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "relay_{}{} {}", name, labels, value);
+            }
+        };
+
+        metric(
+            "uptime_seconds",
+            "Seconds since the relay started.",
+            "counter",
+            &[(String::new(), self.uptime_secs)],
+        );
+        metric(
+            "peers",
+            "Connected peers.",
+            "gauge",
+            &[(String::new(), self.peers.len() as u64)],
+        );
+        metric(
+            "networks",
+            "Virtual networks with at least one peer.",
+            "gauge",
+            &[(String::new(), self.networks as u64)],
+        );
+        let routes = &self.frames;
+        metric(
+            "frames_routed_total",
+            "Data frames routed by the hub, by outcome.",
+            "counter",
+            &[
+                (r#"{route="unicast"}"#.into(), routes.unicast),
+                (r#"{route="flooded"}"#.into(), routes.flooded),
+                (r#"{route="external"}"#.into(), routes.external),
+                (r#"{route="gateway"}"#.into(), routes.gateway),
+                (r#"{route="dropped"}"#.into(), routes.dropped),
+            ],
+        );
+        metric(
+            "nat_sessions",
+            "Open sessions of the external NAT proxy.",
+            "gauge",
+            &[
+                (r#"{protocol="tcp"}"#.into(), self.nat.tcp_sessions as u64),
+                (r#"{protocol="udp"}"#.into(), self.nat.udp_sessions as u64),
+            ],
+        );
+
+        let per_peer = |value: fn(&PeerStatus) -> u64| -> Vec<(String, u64)> {
+            self.peers
+                .iter()
+                .map(|peer| {
+                    let labels = format!(
+                        r#"{{peer="{}",mac="{}",ip="{}",network="{}"}}"#,
+                        peer.id, peer.mac, peer.ip, peer.network
+                    );
+                    (labels, value(peer))
+                })
+                .collect()
+        };
+        metric(
+            "peer_bytes_sent_total",
+            "Bytes sent to a peer on the wire.",
+            "counter",
+            &per_peer(|p| p.bytes_sent),
+        );
+        metric(
+            "peer_bytes_received_total",
+            "Bytes received from a peer on the wire.",
+            "counter",
+            &per_peer(|p| p.bytes_received),
+        );
+        metric(
+            "peer_frames_sent_total",
+            "Relay messages sent to a peer.",
+            "counter",
+            &per_peer(|p| p.frames_sent),
+        );
+        metric(
+            "peer_frames_received_total",
+            "Relay messages received from a peer.",
+            "counter",
+            &per_peer(|p| p.frames_received),
+        );
+        metric(
+            "peer_idle_seconds",
+            "Seconds since a peer was last heard from.",
+            "gauge",
+            &per_peer(|p| p.idle_secs),
+        );
+        out
+    }
+}
+
+/// Serve `/metrics` and `/status` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, hub: Arc<Hub>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Metrics on http://{}/metrics and /status", addr);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &hub).await {
+                tracing::debug!("Metrics request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, hub: &Hub) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut parts = head.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or(path);
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            RelayStatus::collect(hub).await.to_prometheus(),
+        ),
+        ("GET", "/status") => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string_pretty(&RelayStatus::collect(hub).await)
+                .unwrap_or_else(|e| format!(r#"{{"error":"{}"}}"#, e)),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_status_endpoints() {
+        let hub = Arc::new(Hub::new());
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let session = crate::protocol::Session::negotiate(2, false, false);
+        hub.register_peer([2, 0, 0, 0, 0, 1], "lab", tx, session, None)
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_hub = hub.clone();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                handle_request(stream, &server_hub).await.unwrap();
+            }
+        });
+
+        async fn get(addr: SocketAddr, path: &str) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: relay\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        let metrics = get(addr, "/metrics").await;
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        assert!(metrics.contains("relay_peers 1\n"));
+        assert!(
+            metrics.contains(r#"relay_peer_bytes_sent_total{peer="1",mac="02:00:00:00:00:01""#)
+        );
+
+        let status = get(addr, "/status").await;
+        let body = status.split("\r\n\r\n").nth(1).unwrap();
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["peers"][0]["network"], "lab");
+        assert_eq!(json["nat"]["tcp_sessions"], 0);
+    }
+}
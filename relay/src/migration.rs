@@ -0,0 +1,250 @@
+//! Live migration sessions.
+//!
+//! A VM moving to another host (browser to native or the other way round)
+//! sends its state through the relay, since either end may be a browser
+//! that cannot accept connections. The old host opens a session with
+//! `MigrateOut` and receives a ticket, which it hands to the new host out
+//! of band. The new host joins with `MigrateIn`; from then on the hub
+//! passes migration datagrams (type 0x03) from one end to the other
+//! without looking into them.
+//!
+//! Opening a session also reserves the address of the VM's NIC: when the
+//! new host registers that MAC with the ticket, it gets the same IP on the
+//! same network and the old host's registration, if still there, is
+//! dropped. Tickets expire after [`MIGRATION_TIMEOUT`]. At most
+//! [`MAX_MIGRATIONS`] sessions are open at once, and one whose source
+//! leaves before a destination joined is dropped straight away.
+//!
+//! Only the VM's own host may open a session for a registered MAC: each
+//! registration is given a migration key in `Assigned`, and `MigrateOut`
+//! must present it. Anyone else would otherwise take over the VM's address
+//! and network with the ticket.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use crate::hub::PeerMessage;
+
+/// How long a ticket stays valid
+pub const MIGRATION_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Most sessions open at once
+pub const MAX_MIGRATIONS: usize = 64;
+
+/// End of a migration session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationSide {
+    /// The host the VM leaves
+    Source,
+    /// The host the VM moves to
+    Destination,
+}
+
+impl MigrationSide {
+    fn other(self) -> Self {
+        match self {
+            Self::Source => Self::Destination,
+            Self::Destination => Self::Source,
+        }
+    }
+}
+
+/// Address kept for the migrating VM's NIC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub mac: [u8; 6],
+    pub ip: [u8; 4],
+    pub network: String,
+}
+
+#[derive(Debug)]
+struct Session {
+    reservation: Option<Reservation>,
+    source: Option<mpsc::Sender<PeerMessage>>,
+    destination: Option<mpsc::Sender<PeerMessage>>,
+    created: Instant,
+}
+
+impl Session {
+    fn end(&mut self, side: MigrationSide) -> &mut Option<mpsc::Sender<PeerMessage>> {
+        match side {
+            MigrationSide::Source => &mut self.source,
+            MigrationSide::Destination => &mut self.destination,
+        }
+    }
+}
+
+/// Open migration sessions by ticket
+#[derive(Debug, Default)]
+pub struct Migrations {
+    sessions: HashMap<String, Session>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a session for the source connection `source`, keeping
+    /// `reservation` for the VM, and return its ticket, or `None` if
+    /// [`MAX_MIGRATIONS`] sessions are already open
+    pub fn open(
+        &mut self,
+        reservation: Option<Reservation>,
+        source: mpsc::Sender<PeerMessage>,
+    ) -> Option<String> {
+        if self.sessions.len() >= MAX_MIGRATIONS {
+            return None;
+        }
+        let ticket = new_secret();
+        self.sessions.insert(
+            ticket.clone(),
+            Session {
+                reservation,
+                source: Some(source),
+                destination: None,
+                created: Instant::now(),
+            },
+        );
+        Some(ticket)
+    }
+
+    /// Attach the destination connection to `ticket`'s session and return
+    /// the source's, if it is still connected
+    pub fn join(
+        &mut self,
+        ticket: &str,
+        destination: mpsc::Sender<PeerMessage>,
+    ) -> Result<Option<mpsc::Sender<PeerMessage>>, String> {
+        let session = self
+            .sessions
+            .get_mut(ticket)
+            .ok_or_else(|| "unknown migration ticket".to_string())?;
+        if session.destination.is_some() {
+            return Err("migration already has a destination".to_string());
+        }
+        session.destination = Some(destination);
+        Ok(session.source.clone())
+    }
+
+    /// Connection at the other end of `ticket`'s session from `side`
+    pub fn partner(&self, ticket: &str, side: MigrationSide) -> Option<mpsc::Sender<PeerMessage>> {
+        let session = self.sessions.get(ticket)?;
+        match side.other() {
+            MigrationSide::Source => session.source.clone(),
+            MigrationSide::Destination => session.destination.clone(),
+        }
+    }
+
+    /// Forget the `side` connection of `ticket`'s session. The reservation
+    /// is kept for the destination to claim, unless the source leaves
+    /// before any destination joined: then the session is dropped.
+    pub fn leave(&mut self, ticket: &str, side: MigrationSide) {
+        let Some(session) = self.sessions.get_mut(ticket) else {
+            return;
+        };
+        if side == MigrationSide::Source && session.destination.is_none() {
+            self.sessions.remove(ticket);
+        } else {
+            *session.end(side) = None;
+        }
+    }
+
+    /// Take the reservation of `ticket` for a registration of `mac`. The
+    /// ticket is used up either way.
+    pub fn claim(&mut self, ticket: &str, mac: &[u8; 6]) -> Option<Reservation> {
+        let session = self.sessions.remove(ticket)?;
+        session
+            .reservation
+            .filter(|reservation| reservation.mac == *mac)
+    }
+
+    /// Drop sessions older than `timeout`
+    pub fn expire(&mut self, timeout: Duration) {
+        self.sessions
+            .retain(|_, session| session.created.elapsed() < timeout);
+    }
+
+    /// Number of open sessions
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+}
+
+/// A fresh 128-bit ticket or migration key from the OS random number
+/// generator, hex-encoded, so secrets cannot be guessed from earlier ones.
+pub fn new_secret() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("OS random number generator unavailable");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_lifecycle() {
+        let mut migrations = Migrations::new();
+        let (source, mut source_rx) = mpsc::channel(4);
+        let (destination, _) = mpsc::channel(4);
+        let reservation = Reservation {
+            mac: [2, 0, 0, 0, 0, 1],
+            ip: [10, 0, 2, 50],
+            network: "lab".to_string(),
+        };
+        let ticket = migrations.open(Some(reservation.clone()), source).unwrap();
+        assert_eq!(ticket.len(), 32);
+        assert_ne!(Some(ticket.clone()), migrations.open(None, mpsc::channel(1).0));
+
+        assert!(migrations.join("nope", destination.clone()).is_err());
+        assert!(
+            migrations
+                .join(&ticket, destination.clone())
+                .unwrap()
+                .is_some()
+        );
+        assert!(migrations.join(&ticket, destination).is_err());
+
+        let to_source = migrations
+            .partner(&ticket, MigrationSide::Destination)
+            .unwrap();
+        to_source.try_send(PeerMessage::Send(vec![3])).unwrap();
+        assert!(matches!(source_rx.try_recv(), Ok(PeerMessage::Send(_))));
+
+        migrations.leave(&ticket, MigrationSide::Source);
+        assert!(
+            migrations
+                .partner(&ticket, MigrationSide::Destination)
+                .is_none()
+        );
+        assert!(migrations.partner(&ticket, MigrationSide::Source).is_some());
+
+        // Only the migrated MAC gets the address, and only once
+        assert_eq!(
+            migrations.claim(&ticket, &[2, 0, 0, 0, 0, 1]),
+            Some(reservation)
+        );
+        assert_eq!(migrations.claim(&ticket, &[2, 0, 0, 0, 0, 1]), None);
+        assert_eq!(migrations.count(), 1);
+        migrations.expire(Duration::ZERO);
+        assert_eq!(migrations.count(), 0);
+    }
+
+    #[test]
+    fn test_abandoned_sessions_are_dropped() {
+        let mut migrations = Migrations::new();
+        let (source, _) = mpsc::channel(1);
+        let tickets: Vec<String> = (0..MAX_MIGRATIONS)
+            .map(|_| migrations.open(None, source.clone()).unwrap())
+            .collect();
+        assert_eq!(migrations.open(None, source.clone()), None);
+
+        // A source leaving before anyone joined takes its session along
+        migrations.leave(&tickets[0], MigrationSide::Source);
+        assert_eq!(migrations.count(), MAX_MIGRATIONS - 1);
+        assert!(migrations.open(None, source).is_some());
+    }
+}
@@ -1,9 +1,15 @@
 //! Peer state management and IP pool allocation for the relay hub.
+//!
+//! Every peer belongs to one virtual network, an isolated L2 segment with
+//! its own MAC learning table. Addresses come from a single pool, so IPs
+//! stay unique across networks.
 
 use std::collections::HashMap;
 use std::time::Instant;
 
-use crate::protocol::{IP_POOL_END, IP_POOL_START, format_ip, format_mac};
+use crate::dns::PEER_DOMAIN;
+use crate::migration::new_secret;
+use crate::protocol::{GATEWAY_MAC, IP_POOL_END, IP_POOL_START, format_ip, format_mac};
 
 /// Unique identifier for a connected peer
 pub type PeerId = u64;
@@ -17,16 +23,22 @@ pub struct Peer {
     pub mac: [u8; 6],
     /// Assigned IP address
     pub ip: [u8; 4],
+    /// Virtual network the peer joined
+    pub network: String,
+    /// Secret that lets the peer's host migrate its VM away
+    pub migration_key: String,
     /// Last activity timestamp (for heartbeat timeout)
     pub last_seen: Instant,
 }
 
 impl Peer {
-    pub fn new(id: PeerId, mac: [u8; 6], ip: [u8; 4]) -> Self {
+    pub fn new(id: PeerId, mac: [u8; 6], ip: [u8; 4], network: &str) -> Self {
         Self {
             id,
             mac,
             ip,
+            network: network.to_string(),
+            migration_key: new_secret(),
             last_seen: Instant::now(),
         }
     }
@@ -74,6 +86,18 @@ impl IpPool {
         ])
     }
 
+    /// Allocate `ip` for a peer if it is free, e.g. to give a migrated VM
+    /// its old address
+    pub fn allocate_at(&mut self, ip: [u8; 4], peer_id: PeerId) -> Option<[u8; 4]> {
+        if !self.is_internal(&ip) {
+            return None;
+        }
+        let pos = self.available.iter().position(|&host| host == ip[3])?;
+        self.available.swap_remove(pos);
+        self.allocated.insert(ip[3], peer_id);
+        Some(ip)
+    }
+
     /// Release an IP address back to the pool
     pub fn release(&mut self, ip: &[u8; 4]) {
         if ip[0] == self.network_prefix[0]
@@ -110,6 +134,8 @@ pub struct PeerManager {
     mac_to_peer: HashMap<[u8; 6], PeerId>,
     /// IP to peer ID mapping for routing
     ip_to_peer: HashMap<[u8; 4], PeerId>,
+    /// Per-network MAC learning tables (source MAC -> peer behind it)
+    mac_tables: HashMap<String, HashMap<[u8; 6], PeerId>>,
     /// IP address pool
     ip_pool: IpPool,
     /// Next peer ID
@@ -124,6 +150,7 @@ impl PeerManager {
             peers: HashMap::new(),
             mac_to_peer: HashMap::new(),
             ip_to_peer: HashMap::new(),
+            mac_tables: HashMap::new(),
             ip_pool: IpPool::new(),
             next_id: 1,
             // Increased timeout to tolerate browser tabs going to background
@@ -132,33 +159,58 @@ impl PeerManager {
         }
     }
 
-    /// Register a new peer with the given MAC address
+    /// Register a new peer with the given MAC address on `network`
     /// Returns the peer ID and assigned IP, or None if pool exhausted
-    pub fn register(&mut self, mac: [u8; 6]) -> Option<(PeerId, [u8; 4])> {
+    pub fn register(&mut self, mac: [u8; 6], network: &str) -> Option<(PeerId, [u8; 4])> {
+        self.register_at(mac, network, None)
+    }
+
+    /// Register like [`register`](Self::register), preferring `ip` for a new
+    /// peer; another address is assigned if it is taken
+    pub fn register_at(
+        &mut self,
+        mac: [u8; 6],
+        network: &str,
+        ip: Option<[u8; 4]>,
+    ) -> Option<(PeerId, [u8; 4])> {
         // Check if MAC already registered
-        if let Some(&existing_id) = self.mac_to_peer.get(&mac) {
-            // Return existing registration
-            if let Some(peer) = self.peers.get(&existing_id) {
-                return Some((existing_id, peer.ip));
+        if let Some(&existing_id) = self.mac_to_peer.get(&mac)
+            && let Some(peer) = self.peers.get_mut(&existing_id)
+        {
+            // Return existing registration, moving it if the network changed.
+            // The hub only does this once the old connection is gone, and
+            // the new registrant takes over with a fresh migration key.
+            let ip = peer.ip;
+            peer.migration_key = new_secret();
+            if peer.network != network {
+                peer.network = network.to_string();
+                self.forget_macs(existing_id);
+                self.learn(existing_id, mac);
             }
+            return Some((existing_id, ip));
         }
 
         // Allocate new peer
         let id = self.next_id;
         self.next_id += 1;
 
-        let ip = self.ip_pool.allocate(id)?;
+        let ip = match ip.and_then(|ip| self.ip_pool.allocate_at(ip, id)) {
+            Some(ip) => ip,
+            None => self.ip_pool.allocate(id)?,
+        };
 
-        let peer = Peer::new(id, mac, ip);
+        let peer = Peer::new(id, mac, ip, network);
         self.peers.insert(id, peer);
         self.mac_to_peer.insert(mac, id);
         self.ip_to_peer.insert(ip, id);
+        self.learn(id, mac);
 
         tracing::info!(
-            "Registered peer {} with MAC {} -> IP {}",
+            "Registered peer {} with MAC {} -> IP {} on network '{}'",
             id,
             format_mac(&mac),
-            format_ip(&ip)
+            format_ip(&ip),
+            network
         );
 
         Some((id, ip))
@@ -170,6 +222,7 @@ impl PeerManager {
             self.mac_to_peer.remove(&peer.mac);
             self.ip_to_peer.remove(&peer.ip);
             self.ip_pool.release(&peer.ip);
+            self.forget_macs(peer_id);
 
             tracing::info!(
                 "Unregistered peer {} (MAC {} / IP {})",
@@ -187,6 +240,65 @@ impl PeerManager {
         }
     }
 
+    /// Record that frames from `mac` come from `peer_id`, in its network.
+    /// Group and gateway addresses are never learned.
+    pub fn learn(&mut self, peer_id: PeerId, mac: [u8; 6]) {
+        if mac[0] & 0x01 != 0 || mac == GATEWAY_MAC {
+            return;
+        }
+        let Some(peer) = self.peers.get(&peer_id) else {
+            return;
+        };
+        let table = self.mac_tables.entry(peer.network.clone()).or_default();
+        if table.insert(mac, peer_id) != Some(peer_id) {
+            tracing::debug!(
+                "Learned MAC {} on peer {} (network '{}')",
+                format_mac(&mac),
+                peer_id,
+                peer.network
+            );
+        }
+    }
+
+    /// Drop every learned MAC pointing at `peer_id`
+    fn forget_macs(&mut self, peer_id: PeerId) {
+        self.mac_tables.retain(|_, table| {
+            table.retain(|_, id| *id != peer_id);
+            !table.is_empty()
+        });
+    }
+
+    /// Peer owning `mac` on `network`, as learned from its frames
+    pub fn lookup_mac(&self, network: &str, mac: &[u8; 6]) -> Option<PeerId> {
+        self.mac_tables.get(network)?.get(mac).copied()
+    }
+
+    /// Address of the peer named `name` (`peer<ID>.local`) on `network`
+    pub fn resolve_peer_name(&self, name: &str, network: &str) -> Option<[u8; 4]> {
+        let id: PeerId = name
+            .strip_suffix(PEER_DOMAIN)?
+            .strip_prefix("peer")?
+            .parse()
+            .ok()?;
+        let peer = self.peers.get(&id)?;
+        (peer.network == network).then_some(peer.ip)
+    }
+
+    /// Network a peer belongs to
+    pub fn network_of(&self, peer_id: PeerId) -> Option<&str> {
+        self.peers.get(&peer_id).map(|peer| peer.network.as_str())
+    }
+
+    /// Number of networks with at least one peer
+    pub fn network_count(&self) -> usize {
+        self.mac_tables.len()
+    }
+
+    /// Get a peer by ID
+    pub fn get(&self, peer_id: PeerId) -> Option<&Peer> {
+        self.peers.get(&peer_id)
+    }
+
     /// Find peer by MAC address
     pub fn find_by_mac(&self, mac: &[u8; 6]) -> Option<&Peer> {
         self.mac_to_peer.get(mac).and_then(|id| self.peers.get(id))
@@ -255,14 +367,70 @@ mod tests {
         assert_eq!(ip1, ip3); // Should get the same IP back
     }
 
+    #[test]
+    fn test_register_at_reserved_ip() {
+        let mut manager = PeerManager::new();
+        let (_, ip) = manager
+            .register_at([2, 0, 0, 0, 0, 1], "default", Some([10, 0, 2, 42]))
+            .unwrap();
+        assert_eq!(ip, [10, 0, 2, 42]);
+
+        // Taken or outside the pool: any free address will do
+        let (_, ip) = manager
+            .register_at([2, 0, 0, 0, 0, 2], "default", Some([10, 0, 2, 42]))
+            .unwrap();
+        assert_ne!(ip, [10, 0, 2, 42]);
+        let (_, ip) = manager
+            .register_at([2, 0, 0, 0, 0, 3], "default", Some([192, 168, 0, 1]))
+            .unwrap();
+        assert!(manager.is_internal_ip(&ip));
+    }
+
     #[test]
     fn test_peer_lookup() {
         let mut manager = PeerManager::new();
 
         let mac = [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef];
-        let (id, ip) = manager.register(mac).unwrap();
+        let (id, ip) = manager.register(mac, "default").unwrap();
 
         assert!(manager.find_by_mac(&mac).is_some());
         assert_eq!(manager.peer_id_by_ip(&ip), Some(id));
     }
+
+    #[test]
+    fn test_mac_learning_per_network() {
+        let mut manager = PeerManager::new();
+        let mac_a = [0x52, 0x54, 0x00, 0, 0, 1];
+        let mac_b = [0x52, 0x54, 0x00, 0, 0, 2];
+        let (a, _) = manager.register(mac_a, "lab").unwrap();
+        let (b, _) = manager.register(mac_b, "other").unwrap();
+
+        // A guest bridged behind peer A shows up under A's network only
+        let bridged = [0x02, 0, 0, 0, 0, 9];
+        manager.learn(a, bridged);
+        manager.learn(a, [0xff; 6]);
+        assert_eq!(manager.lookup_mac("lab", &bridged), Some(a));
+        assert_eq!(manager.lookup_mac("other", &bridged), None);
+        assert_eq!(manager.lookup_mac("lab", &mac_b), None);
+        assert_eq!(manager.lookup_mac("other", &mac_b), Some(b));
+        assert_eq!(manager.lookup_mac("lab", &[0xff; 6]), None);
+        assert_eq!(manager.network_count(), 2);
+
+        // Re-registering on another network moves the peer
+        manager.register(mac_a, "other").unwrap();
+        assert_eq!(manager.network_of(a), Some("other"));
+        assert_eq!(manager.lookup_mac("lab", &bridged), None);
+        assert_eq!(manager.lookup_mac("other", &mac_a), Some(a));
+
+        let name = format!("peer{}.local", a);
+        assert_eq!(
+            manager.resolve_peer_name(&name, "other"),
+            manager.get(a).map(|p| p.ip)
+        );
+        assert_eq!(manager.resolve_peer_name(&name, "lab"), None);
+
+        manager.unregister(a);
+        assert_eq!(manager.lookup_mac("other", &mac_a), None);
+        assert_eq!(manager.network_count(), 1);
+    }
 }
@@ -3,14 +3,31 @@
 //! Frames are prefixed with a message type byte:
 //! - 0x00 = Control message (JSON-encoded)
 //! - 0x01 = Ethernet data frame
+//! - 0x02 = Batch of the above (protocol v2, see `riscv-vm/src/net/batch.rs`)
 //!
 //! Control messages handle peer registration, IP assignment, and heartbeat.
+//! Peers announce their protocol version in `Register`; the hub answers with
+//! the negotiated version in `Assigned`. Peers that omit it speak v1.
+//!
+//! `Register` may also name a virtual network. Peers only exchange frames
+//! with peers on the same network; those that name none share
+//! [`DEFAULT_NETWORK`].
+//!
+//! A VM moving to another host opens a migration session with
+//! `MigrateOut` and gets a ticket; the new host joins it with `MigrateIn`
+//! and the hub passes 0x03 datagrams between the two (see `migration.rs`).
+//! A `Register` carrying the ticket then takes over the old peer's address.
+//! `MigrateOut` for a registered MAC must carry the `migration_key` the
+//! hub gave that registration in `Assigned`.
 
 use serde::{Deserialize, Serialize};
 
 /// Message type prefix bytes
 pub const MSG_TYPE_CONTROL: u8 = 0x00;
 pub const MSG_TYPE_DATA: u8 = 0x01;
+pub const MSG_TYPE_MIGRATION: u8 = 0x03;
+
+pub use crate::batch::{MSG_TYPE_BATCH, PROTOCOL_VERSION};
 
 /// Network configuration constants
 pub const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
@@ -18,6 +35,12 @@ pub const GATEWAY_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
 pub const NETWORK_MASK: [u8; 4] = [255, 255, 255, 0];
 pub const DNS_SERVER: [u8; 4] = [8, 8, 8, 8];
 
+/// Network joined by peers that don't name one
+pub const DEFAULT_NETWORK: &str = "default";
+
+/// Longest accepted network ID
+pub const MAX_NETWORK_ID_LEN: usize = 64;
+
 /// IP pool range for peer assignment
 pub const IP_POOL_START: u8 = 10; // 10.0.2.10
 pub const IP_POOL_END: u8 = 254; // 10.0.2.254
@@ -27,7 +50,22 @@ pub const IP_POOL_END: u8 = 254; // 10.0.2.254
 #[serde(tag = "type")]
 pub enum ControlMessage {
     /// Peer requests registration with its MAC address
-    Register { mac: [u8; 6] },
+    Register {
+        mac: [u8; 6],
+        /// Highest protocol version the peer speaks
+        #[serde(default = "protocol_v1")]
+        version: u8,
+        /// Peer can decode LZ4-compressed batches
+        #[serde(default)]
+        compression: bool,
+        /// Virtual network to join ([`DEFAULT_NETWORK`] if absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        network: Option<String>,
+        /// Ticket of the migration that brought the VM here, to keep the
+        /// address it had on the old host
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        migration: Option<String>,
+    },
 
     /// Hub assigns IP configuration to peer
     Assigned {
@@ -35,6 +73,15 @@ pub enum ControlMessage {
         gateway: [u8; 4],
         netmask: [u8; 4],
         dns: [u8; 4],
+        /// Negotiated protocol version
+        #[serde(default = "protocol_v1")]
+        version: u8,
+        /// Batches may be LZ4-compressed in both directions
+        #[serde(default)]
+        compression: bool,
+        /// Secret to present in `MigrateOut` to migrate this peer's VM
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        migration_key: Option<String>,
     },
 
     /// Heartbeat to keep connection alive
@@ -51,6 +98,52 @@ pub enum ControlMessage {
 
     /// List of connected peers (optional, for discovery)
     PeerList { peers: Vec<PeerInfo> },
+
+    /// Host sending a VM away opens a migration session for the VM whose
+    /// NIC has `mac`, if it has one. A registered NIC's address is only
+    /// kept with the `key` its registration was assigned.
+    MigrateOut {
+        #[serde(default)]
+        mac: Option<[u8; 6]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+
+    /// Hub names the migration session opened by `MigrateOut`
+    MigrationTicket { ticket: String },
+
+    /// Host receiving a VM joins the migration session `ticket`
+    MigrateIn { ticket: String },
+
+    /// Both ends of a migration session are connected
+    MigrationPaired,
+}
+
+fn protocol_v1() -> u8 {
+    1
+}
+
+/// Protocol features agreed with a peer during registration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub version: u8,
+    pub compression: bool,
+}
+
+impl Session {
+    /// Settle on what both sides support
+    pub fn negotiate(peer_version: u8, peer_compression: bool, allow_compression: bool) -> Self {
+        let version = peer_version.clamp(1, PROTOCOL_VERSION);
+        Self {
+            version,
+            compression: version >= 2 && peer_compression && allow_compression,
+        }
+    }
+
+    /// Whether batches may be exchanged
+    pub fn batching(&self) -> bool {
+        self.version >= 2
+    }
 }
 
 /// Information about a connected peer
@@ -91,6 +184,16 @@ pub fn encode_data_frame(ethernet_frame: &[u8]) -> Vec<u8> {
     frame
 }
 
+/// Whether `id` may name a virtual network: 1 to [`MAX_NETWORK_ID_LEN`]
+/// ASCII letters, digits, `-`, `_` or `.`
+pub fn is_valid_network_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_NETWORK_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
 /// Helper to format MAC address for display
 pub fn format_mac(mac: &[u8; 6]) -> String {
     format!(
@@ -112,15 +215,47 @@ mod tests {
     fn test_control_message_roundtrip() {
         let msg = ControlMessage::Register {
             mac: [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef],
+            version: PROTOCOL_VERSION,
+            compression: true,
+            network: Some("lab-1".to_string()),
+            migration: None,
         };
         let encoded = msg.encode();
         let decoded = ControlMessage::decode(&encoded).unwrap();
 
         match decoded {
-            ControlMessage::Register { mac } => {
+            ControlMessage::Register { mac, network, .. } => {
                 assert_eq!(mac, [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef]);
+                assert_eq!(network.as_deref(), Some("lab-1"));
             }
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[test]
+    fn test_v1_register_negotiates_v1() {
+        let mut data = vec![MSG_TYPE_CONTROL];
+        data.extend(br#"{"type":"Register","mac":[82,84,0,1,2,3]}"#);
+        let Ok(ControlMessage::Register {
+            version,
+            compression,
+            network,
+            ..
+        }) = ControlMessage::decode(&data)
+        else {
+            panic!("Wrong message type");
+        };
+        assert_eq!((version, compression), (1, false));
+        assert_eq!(network, None);
+
+        let session = Session::negotiate(version, compression, true);
+        assert!(!session.batching());
+        assert_eq!(
+            Session::negotiate(3, true, false),
+            Session {
+                version: PROTOCOL_VERSION,
+                compression: false
+            }
+        );
+    }
 }
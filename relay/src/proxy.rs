@@ -84,8 +84,8 @@ pub struct ExternalProxy {
     udp_socket: Mutex<Option<Arc<UdpSocket>>>,
     /// Active UDP sessions (keyed by local port or dst:port combo)
     udp_sessions: Mutex<HashMap<(Ipv4Addr, u16, u16), UdpSession>>,
-    /// Active TCP sessions
-    tcp_sessions: Mutex<HashMap<TcpKey, TcpSession>>,
+    /// Active TCP sessions, shared with the connection tasks
+    tcp_sessions: Arc<Mutex<HashMap<TcpKey, TcpSession>>>,
     /// Channel to receive responses from TCP connections
     tcp_response_tx: mpsc::Sender<Vec<u8>>,
     tcp_response_rx: Mutex<mpsc::Receiver<Vec<u8>>>,
@@ -99,13 +99,20 @@ impl ExternalProxy {
         Self {
             udp_socket: Mutex::new(None),
             udp_sessions: Mutex::new(HashMap::new()),
-            tcp_sessions: Mutex::new(HashMap::new()),
+            tcp_sessions: Arc::new(Mutex::new(HashMap::new())),
             tcp_response_tx: tx,
             tcp_response_rx: Mutex::new(rx),
             session_timeout: Duration::from_secs(120),
         }
     }
 
+    /// Number of open TCP and UDP sessions
+    pub async fn session_counts(&self) -> (usize, usize) {
+        let tcp = self.tcp_sessions.lock().await.len();
+        let udp = self.udp_sessions.lock().await.len();
+        (tcp, udp)
+    }
+
     /// Initialize the proxy (bind UDP socket)
     pub async fn init(&self) -> anyhow::Result<()> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
@@ -300,14 +307,13 @@ impl ExternalProxy {
 
         let dst_addr = Ipv4Addr::from(dst_ip);
 
-        // Calculate payload size
+        // Calculate payload size from the IP length, so Ethernet padding
+        // on short frames is not taken for data
         let tcp_header_len = ((frame[tcp_start + 12] >> 4) * 4) as usize;
         let payload_start = tcp_start + tcp_header_len;
-        let payload_len = if frame.len() > payload_start {
-            frame.len() - payload_start
-        } else {
-            0
-        };
+        let ip_len = u16::from_be_bytes([frame[16], frame[17]]) as usize;
+        let payload_end = (14 + ip_len).min(frame.len());
+        let payload_len = payload_end.saturating_sub(payload_start);
 
         tracing::info!(
             "TCP: {}:{} -> {}:{} flags=[{}{}{}{}] seq={} ack={} payload={}",
@@ -329,6 +335,10 @@ impl ExternalProxy {
             // Check if we already have a session for this connection
             let sessions = self.tcp_sessions.lock().await;
             if let Some(session) = sessions.get(&key) {
+                if session.state == TcpState::SynSent {
+                    // Still connecting; the SYN-ACK follows once connected
+                    return None;
+                }
                 // SYN retransmission - resend SYN-ACK
                 tracing::debug!("TCP proxy: SYN retransmission, resending SYN-ACK");
                 let synack = Self::build_tcp_packet(
@@ -434,6 +444,29 @@ impl ExternalProxy {
                     return Some(ack_packet);
                 }
 
+                // Data past a gap can't be forwarded yet; the duplicate ACK
+                // makes the VM resend from the missing byte
+                if diff_start < 0 {
+                    tracing::debug!(
+                        "TCP proxy: out-of-order segment (seq={}, expected={}), sending duplicate ACK",
+                        seq_num,
+                        session.server_ack
+                    );
+                    let ack_packet = Self::build_tcp_packet(
+                        &session.src_mac,
+                        &session.src_ip,
+                        session.src_port,
+                        &session.dst_ip,
+                        session.dst_port,
+                        session.server_seq.wrapping_add(1),
+                        session.server_ack,
+                        0x10, // ACK
+                        &[],
+                    );
+                    drop(sessions);
+                    return Some(ack_packet);
+                }
+
                 // Extract only the new data (skip bytes we've already ACKed)
                 let payload = if diff_start > 0 && diff_start < (1 << 30) {
                     // Partial retransmission - extract only new portion
@@ -443,10 +476,10 @@ impl ExternalProxy {
                         skip_bytes,
                         payload_len - skip_bytes
                     );
-                    frame[payload_start + skip_bytes..].to_vec()
+                    frame[payload_start + skip_bytes..payload_end].to_vec()
                 } else {
                     // All new data
-                    frame[payload_start..].to_vec()
+                    frame[payload_start..payload_end].to_vec()
                 };
 
                 // Calculate the expected ACK based on actual packet end
@@ -500,6 +533,10 @@ impl ExternalProxy {
     }
 
     /// Handle TCP SYN - establish new connection
+    ///
+    /// The outbound connection is made in the background, so a slow or
+    /// unreachable server doesn't hold up the hub. The SYN-ACK (or a RST if
+    /// the connection fails) reaches the VM through the response channel.
     async fn handle_tcp_syn(
         &self,
         key: TcpKey,
@@ -514,30 +551,6 @@ impl ExternalProxy {
 
         tracing::info!("TCP proxy: new connection to {}:{}", dst_addr, dst_port);
 
-        // Try to connect to the external server
-        let server_addr = SocketAddrV4::new(dst_addr, dst_port);
-
-        let stream =
-            match tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(server_addr))
-                .await
-            {
-                Ok(Ok(stream)) => stream,
-                Ok(Err(e)) => {
-                    tracing::warn!("TCP proxy: connection failed: {}", e);
-                    return Some(self.generate_tcp_rst(
-                        &src_mac, &src_ip, src_port, &dst_ip, dst_port, seq_num,
-                    ));
-                }
-                Err(_) => {
-                    tracing::warn!("TCP proxy: connection timeout");
-                    return Some(self.generate_tcp_rst(
-                        &src_mac, &src_ip, src_port, &dst_ip, dst_port, seq_num,
-                    ));
-                }
-            };
-
-        tracing::info!("TCP proxy: connected to {}:{}", dst_addr, dst_port);
-
         // Create channel for sending data to the forwarding task
         let (tx, rx) = mpsc::channel(64);
 
@@ -565,9 +578,56 @@ impl ExternalProxy {
 
         self.tcp_sessions.lock().await.insert(key, session);
 
-        // Spawn task to handle this connection
+        // Spawn task to connect to the external server and handle the connection
+        let sessions = Arc::clone(&self.tcp_sessions);
         let response_tx = self.tcp_response_tx.clone();
         tokio::spawn(async move {
+            let server_addr = SocketAddrV4::new(dst_addr, dst_port);
+            let stream = match tokio::time::timeout(
+                Duration::from_secs(10),
+                TcpStream::connect(server_addr),
+            )
+            .await
+            {
+                Ok(Ok(stream)) => Some(stream),
+                Ok(Err(e)) => {
+                    tracing::warn!("TCP proxy: connection failed: {}", e);
+                    None
+                }
+                Err(_) => {
+                    tracing::warn!("TCP proxy: connection timeout");
+                    None
+                }
+            };
+            let Some(stream) = stream else {
+                sessions.lock().await.remove(&key);
+                let rst =
+                    Self::generate_tcp_rst(&src_mac, &src_ip, src_port, &dst_ip, dst_port, seq_num);
+                let _ = response_tx.send(rst).await;
+                return;
+            };
+
+            tracing::info!("TCP proxy: connected to {}:{}", dst_addr, dst_port);
+            match sessions.lock().await.get_mut(&key) {
+                Some(session) => session.state = TcpState::Established,
+                // The VM gave up while we were connecting
+                None => return,
+            }
+
+            // Send SYN-ACK back to VM
+            let synack = Self::generate_tcp_synack(
+                &src_mac,
+                &src_ip,
+                src_port,
+                &dst_ip,
+                dst_port,
+                server_seq,
+                seq_num.wrapping_add(1),
+            );
+            if response_tx.send(synack).await.is_err() {
+                return;
+            }
+
             Self::tcp_connection_task(
                 stream,
                 rx,
@@ -583,16 +643,7 @@ impl ExternalProxy {
             .await;
         });
 
-        // Send SYN-ACK back to VM
-        Some(self.generate_tcp_synack(
-            &src_mac,
-            &src_ip,
-            src_port,
-            &dst_ip,
-            dst_port,
-            server_seq,
-            seq_num.wrapping_add(1),
-        ))
+        None
     }
 
     /// Task that handles a single TCP connection
@@ -761,7 +812,6 @@ impl ExternalProxy {
 
     /// Generate TCP SYN-ACK packet
     fn generate_tcp_synack(
-        &self,
         dst_mac: &[u8; 6],
         dst_ip: &[u8; 4],
         dst_port: u16,
@@ -785,7 +835,6 @@ impl ExternalProxy {
 
     /// Generate TCP RST packet
     fn generate_tcp_rst(
-        &self,
         dst_mac: &[u8; 6],
         dst_ip: &[u8; 4],
         dst_port: u16,
@@ -1042,4 +1091,69 @@ mod tests {
         let proxy = ExternalProxy::new();
         assert!(proxy.udp_socket().await.is_none());
     }
+
+    async fn next_response(proxy: &ExternalProxy) -> Vec<u8> {
+        for _ in 0..500 {
+            if let Some(frame) = proxy.poll_tcp_response().await {
+                return frame;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no response from the TCP proxy");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connects_in_background_and_forwards_data() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = ExternalProxy::new();
+        let vm_ip = [10, 0, 2, 15];
+        let server_ip = [127, 0, 0, 1];
+        // Frames from the VM, built as if the server were the receiver
+        let vm_frame = |seq: u32, flags: u8, payload: &[u8]| {
+            ExternalProxy::build_tcp_packet(
+                &GATEWAY_MAC,
+                &server_ip,
+                port,
+                &vm_ip,
+                40000,
+                seq,
+                0,
+                flags,
+                payload,
+            )
+        };
+
+        // The SYN is answered through the response channel once connected
+        assert!(
+            proxy
+                .handle_external_packet(&vm_frame(100, 0x02, &[]))
+                .await
+                .is_none()
+        );
+        let (mut server, _) = listener.accept().await.unwrap();
+        let synack = next_response(&proxy).await;
+        assert_eq!(synack[47], 0x12);
+        assert_eq!(synack[42..46], 101u32.to_be_bytes());
+
+        // Ethernet padding after the payload is not forwarded
+        let mut data = vm_frame(101, 0x18, b"hi");
+        data.resize(60, 0);
+        let ack = proxy.handle_external_packet(&data).await.unwrap();
+        assert_eq!(ack[42..46], 103u32.to_be_bytes());
+
+        // Data past a gap is held back with a duplicate ACK
+        let ack = proxy
+            .handle_external_packet(&vm_frame(110, 0x18, b"late"))
+            .await
+            .unwrap();
+        assert_eq!(ack[42..46], 103u32.to_be_bytes());
+
+        let mut buf = [0u8; 8];
+        let n = tokio::time::timeout(Duration::from_secs(5), server.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"hi");
+    }
 }
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Rebuild the bundled benchmark images. The ELFs are checked in next to
+# this script so the emulator builds without a RISC-V target; rerun this
+# after changing the sources and commit the results.
+#
+# Needs the riscv64gc-unknown-none-elf target:
+#   rustup target add riscv64gc-unknown-none-elf
+
+set -e
+cd "$(dirname "$0")"
+
+cargo build --release
+for name in dhrystone coremark; do
+    cp "target/riscv64gc-unknown-none-elf/release/$name" "$name.elf"
+done
@@ -0,0 +1,25 @@
+/* Bare-metal layout for the benchmark images: everything in DRAM from
+ * 0x8000_0000, entered at _start. The boot ROM sets up the stack. */
+OUTPUT_ARCH(riscv)
+ENTRY(_start)
+
+MEMORY
+{
+  RAM : ORIGIN = 0x80000000, LENGTH = 16M
+}
+
+SECTIONS
+{
+  .text : { KEEP(*(.text.start)) *(.text .text.*) } > RAM
+  .rodata : ALIGN(8) { *(.srodata .srodata.* .rodata .rodata.*) } > RAM
+  .data : ALIGN(8) { *(.sdata .sdata.* .data .data.*) } > RAM
+  .bss (NOLOAD) : ALIGN(8)
+  {
+    _sbss = .;
+    *(.sbss .sbss.* .bss .bss.*)
+    . = ALIGN(8);
+    _ebss = .;
+  } > RAM
+
+  /DISCARD/ : { *(.eh_frame .eh_frame_hdr .comment) }
+}
@@ -0,0 +1,907 @@
+//! CoreMark 1.0 (EEMBC), ported from the C reference version to bare-metal
+//! Rust.
+//!
+//! The port keeps the reference algorithms, in the same order and with the
+//! same 16- and 32-bit arithmetic: linked-list find, reverse and merge sort
+//! (`core_list_join.c`), small matrix arithmetic (`core_matrix.c`), a
+//! number-parsing state machine (`core_state.c`) and the CRC that ties them
+//! together (`core_util.c`). It runs the performance configuration: seeds 0,
+//! 0 and 0x66 over 2000 bytes of data, a single thread. The list, matrix and
+//! state CRCs are checked against the reference's known values for that
+//! configuration, so a wrong result fails the run.
+//!
+//! CoreMark is copyright EEMBC and distributed under the Apache License
+//! 2.0; this port follows the reference source at github.com/eembc/coremark.
+//! It is not that source built under the CoreMark run rules, so its timings
+//! are emulator benchmarks, not CoreMark scores.
+
+#![no_std]
+#![no_main]
+
+use bench_images::println;
+use core::ptr::{self, addr_of_mut};
+
+/// Iterations when the harness passes 0.
+const DEFAULT_ITERATIONS: u64 = 100;
+
+const TOTAL_DATA_SIZE: u32 = 2000;
+const NUM_ALGORITHMS: u32 = 3;
+/// Bytes of `TOTAL_DATA_SIZE` each algorithm gets.
+const BLOCK_SIZE: u32 = TOTAL_DATA_SIZE / NUM_ALGORITHMS;
+
+// Performance run seeds
+const SEED1: i16 = 0;
+const SEED2: i16 = 0;
+const SEED3: i16 = 0x66;
+
+// Known CRCs of the performance run
+const SEED_CRC: u16 = 0xe9f5;
+const LIST_KNOWN_CRC: u16 = 0xe714;
+const MATRIX_KNOWN_CRC: u16 = 0x1fd7;
+const STATE_KNOWN_CRC: u16 = 0x8e3a;
+
+// ---------------------------------------------------------------------
+// core_util.c
+// ---------------------------------------------------------------------
+
+fn crcu8(mut data: u8, mut crc: u16) -> u16 {
+    for _ in 0..8 {
+        let x16 = (data & 1) ^ (crc as u8 & 1);
+        data >>= 1;
+        let carry = if x16 == 1 {
+            crc ^= 0x4002;
+            true
+        } else {
+            false
+        };
+        crc >>= 1;
+        if carry {
+            crc |= 0x8000;
+        } else {
+            crc &= 0x7fff;
+        }
+    }
+    crc
+}
+
+fn crcu16(newval: u16, crc: u16) -> u16 {
+    let crc = crcu8(newval as u8, crc);
+    crcu8((newval >> 8) as u8, crc)
+}
+
+fn crcu32(newval: u32, crc: u16) -> u16 {
+    let crc = crc16(newval as i16, crc);
+    crc16((newval >> 16) as i16, crc)
+}
+
+fn crc16(newval: i16, crc: u16) -> u16 {
+    crcu16(newval as u16, crc)
+}
+
+// ---------------------------------------------------------------------
+// Benchmark state (core_results)
+// ---------------------------------------------------------------------
+
+struct MatParams {
+    n: usize,
+    a: *mut i16,
+    b: *mut i16,
+    c: *mut i32,
+}
+
+struct Results {
+    seed1: i16,
+    seed2: i16,
+    seed3: i16,
+    size: u32,
+    list: *mut ListHead,
+    mat: MatParams,
+    state: *mut u8,
+    crc: u16,
+    crclist: u16,
+    crcmatrix: u16,
+    crcstate: u16,
+}
+
+// ---------------------------------------------------------------------
+// core_list_join.c
+// ---------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ListData {
+    data16: i16,
+    idx: i16,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ListHead {
+    next: *mut ListHead,
+    info: *mut ListData,
+}
+
+type ListCmp = unsafe fn(*mut ListData, *mut ListData, *mut Results) -> i32;
+
+/// Items the reference fits in one block: `blksize / (16 + sizeof(list_data)) - 2`.
+const LIST_ITEMS: usize = BLOCK_SIZE as usize / 20 - 2;
+
+const EMPTY_HEAD: ListHead = ListHead {
+    next: ptr::null_mut(),
+    info: ptr::null_mut(),
+};
+
+static mut LIST_HEADS: [ListHead; LIST_ITEMS] = [EMPTY_HEAD; LIST_ITEMS];
+static mut LIST_DATA: [ListData; LIST_ITEMS] = [ListData { data16: 0, idx: 0 }; LIST_ITEMS];
+
+unsafe fn calc_func(pdata: *mut i16, res: *mut Results) -> i16 {
+    let res = &mut *res;
+    let data = *pdata;
+    let optype = (data >> 7) & 1;
+    if optype != 0 {
+        return data & 0x007f;
+    }
+    let flag = data & 0x7;
+    let mut dtype = (data >> 3) & 0xf;
+    dtype |= dtype << 4;
+    let mut retval = match flag {
+        0 => {
+            if dtype < 0x22 {
+                dtype = 0x22;
+            }
+            let retval =
+                core_bench_state(res.size, res.state, res.seed1, res.seed2, dtype, res.crc) as i16;
+            if res.crcstate == 0 {
+                res.crcstate = retval as u16;
+            }
+            retval
+        }
+        1 => {
+            let retval = core_bench_matrix(&res.mat, dtype, res.crc) as i16;
+            if res.crcmatrix == 0 {
+                res.crcmatrix = retval as u16;
+            }
+            retval
+        }
+        _ => data,
+    };
+    res.crc = crcu16(retval as u16, res.crc);
+    retval &= 0x007f;
+    *pdata = (data & 0xff00u16 as i16) | 0x0080 | retval;
+    retval
+}
+
+unsafe fn cmp_complex(a: *mut ListData, b: *mut ListData, res: *mut Results) -> i32 {
+    let val1 = calc_func(&mut (*a).data16, res);
+    let val2 = calc_func(&mut (*b).data16, res);
+    val1 as i32 - val2 as i32
+}
+
+unsafe fn cmp_idx(a: *mut ListData, b: *mut ListData, res: *mut Results) -> i32 {
+    if res.is_null() {
+        (*a).data16 = ((*a).data16 & 0xff00u16 as i16) | (0x00ff & ((*a).data16 >> 8));
+        (*b).data16 = ((*b).data16 & 0xff00u16 as i16) | (0x00ff & ((*b).data16 >> 8));
+    }
+    (*a).idx as i32 - (*b).idx as i32
+}
+
+#[inline(never)]
+unsafe fn core_bench_list(res: *mut Results, finder_idx: i16) -> u16 {
+    let mut retval: u16 = 0;
+    let mut found: u16 = 0;
+    let mut missed: u16 = 0;
+    let mut list = (*res).list;
+    let find_num = (*res).seed3;
+    let mut info = ListData {
+        data16: 0,
+        idx: finder_idx,
+    };
+
+    // Find find_num values in the list, changing the list each time
+    // (reverse, and cache the value after each one found)
+    let mut i: i16 = 0;
+    while i < find_num {
+        info.data16 = i & 0xff;
+        let this_find = core_list_find(list, &info);
+        list = core_list_reverse(list);
+        if this_find.is_null() {
+            missed = missed.wrapping_add(1);
+            retval = retval.wrapping_add((((*(*(*list).next).info).data16 >> 8) & 1) as u16);
+        } else {
+            found = found.wrapping_add(1);
+            if (*(*this_find).info).data16 & 0x1 != 0 {
+                retval = retval.wrapping_add((((*(*this_find).info).data16 >> 9) & 1) as u16);
+            }
+            if !(*this_find).next.is_null() {
+                let finder = (*this_find).next;
+                (*this_find).next = (*finder).next;
+                (*finder).next = (*list).next;
+                (*list).next = finder;
+            }
+        }
+        if info.idx >= 0 {
+            info.idx += 1;
+        }
+        i += 1;
+    }
+    retval = retval.wrapping_add(found.wrapping_mul(4).wrapping_sub(missed));
+
+    // Sort the list by data content and remove one item
+    if finder_idx > 0 {
+        list = core_list_mergesort(list, cmp_complex, res);
+    }
+    let remover = core_list_remove((*list).next);
+    // CRC the data from the item with index N on, then undo the removal.
+    // The reference CRCs the head's data on each step, kept as is.
+    let mut finder = core_list_find(list, &info);
+    if finder.is_null() {
+        finder = (*list).next;
+    }
+    while !finder.is_null() {
+        retval = crc16((*(*list).info).data16, retval);
+        finder = (*finder).next;
+    }
+    core_list_undo_remove(remover, (*list).next);
+    // Sort by index, which puts the list back in its original order
+    list = core_list_mergesort(list, cmp_idx, ptr::null_mut());
+    finder = (*list).next;
+    while !finder.is_null() {
+        retval = crc16((*(*list).info).data16, retval);
+        finder = (*finder).next;
+    }
+    retval
+}
+
+unsafe fn core_list_init(seed: i16) -> *mut ListHead {
+    let heads = addr_of_mut!(LIST_HEADS) as *mut ListHead;
+    let datas = addr_of_mut!(LIST_DATA) as *mut ListData;
+    let size = LIST_ITEMS as u32;
+    let memblock_end = heads.add(LIST_ITEMS);
+    let datablock_end = datas.add(LIST_ITEMS);
+    let mut memblock = heads;
+    let mut datablock = datas;
+
+    // Fake items for the list head and tail
+    let list = memblock;
+    (*list).next = ptr::null_mut();
+    (*list).info = datablock;
+    (*(*list).info).idx = 0x0000;
+    (*(*list).info).data16 = 0x8080u16 as i16;
+    memblock = memblock.add(1);
+    datablock = datablock.add(1);
+    let mut info = ListData {
+        data16: 0xffffu16 as i16,
+        idx: 0x7fff,
+    };
+    core_list_insert_new(
+        list,
+        &info,
+        &mut memblock,
+        &mut datablock,
+        memblock_end,
+        datablock_end,
+    );
+
+    // Then size items, alternating between the algorithms
+    for i in 0..size {
+        let datpat = (seed as u16 ^ i as u16) & 0xf;
+        let dat = (datpat << 3) | (i as u16 & 0x7);
+        info.data16 = ((dat << 8) | dat) as i16;
+        core_list_insert_new(
+            list,
+            &info,
+            &mut memblock,
+            &mut datablock,
+            memblock_end,
+            datablock_end,
+        );
+    }
+
+    // Index the list to record its initial order: the first 20% in
+    // sequence, the rest pseudo-random after them
+    let mut finder = (*list).next;
+    let mut i: u32 = 1;
+    while !(*finder).next.is_null() {
+        if i < size / 5 {
+            (*(*finder).info).idx = i as i16;
+            i += 1;
+        } else {
+            let pat = (i as u16) ^ seed as u16;
+            i += 1;
+            (*(*finder).info).idx = (0x3fff & ((((i & 0x07) << 8) as u16) | pat)) as i16;
+        }
+        finder = (*finder).next;
+    }
+    core_list_mergesort(list, cmp_idx, ptr::null_mut())
+}
+
+unsafe fn core_list_insert_new(
+    insert_point: *mut ListHead,
+    info: *const ListData,
+    memblock: &mut *mut ListHead,
+    datablock: &mut *mut ListData,
+    memblock_end: *mut ListHead,
+    datablock_end: *mut ListData,
+) -> *mut ListHead {
+    if memblock.add(1) >= memblock_end {
+        return ptr::null_mut();
+    }
+    if datablock.add(1) >= datablock_end {
+        return ptr::null_mut();
+    }
+    let newitem = *memblock;
+    *memblock = memblock.add(1);
+    (*newitem).next = (*insert_point).next;
+    (*insert_point).next = newitem;
+    (*newitem).info = *datablock;
+    *datablock = datablock.add(1);
+    *(*newitem).info = *info;
+    newitem
+}
+
+#[inline(never)]
+unsafe fn core_list_remove(item: *mut ListHead) -> *mut ListHead {
+    let ret = (*item).next;
+    // Swap data pointers, and drop the item after
+    ptr::swap(&mut (*item).info, &mut (*ret).info);
+    (*item).next = (*(*item).next).next;
+    (*ret).next = ptr::null_mut();
+    ret
+}
+
+#[inline(never)]
+unsafe fn core_list_undo_remove(
+    item_removed: *mut ListHead,
+    item_modified: *mut ListHead,
+) -> *mut ListHead {
+    ptr::swap(&mut (*item_removed).info, &mut (*item_modified).info);
+    (*item_removed).next = (*item_modified).next;
+    (*item_modified).next = item_removed;
+    item_removed
+}
+
+#[inline(never)]
+unsafe fn core_list_find(mut list: *mut ListHead, info: *const ListData) -> *mut ListHead {
+    if (*info).idx >= 0 {
+        while !list.is_null() && (*(*list).info).idx != (*info).idx {
+            list = (*list).next;
+        }
+    } else {
+        while !list.is_null() && ((*(*list).info).data16 & 0xff) != (*info).data16 {
+            list = (*list).next;
+        }
+    }
+    list
+}
+
+#[inline(never)]
+unsafe fn core_list_reverse(mut list: *mut ListHead) -> *mut ListHead {
+    let mut next = ptr::null_mut();
+    while !list.is_null() {
+        let tmp = (*list).next;
+        (*list).next = next;
+        next = list;
+        list = tmp;
+    }
+    next
+}
+
+/// Simon Tatham's linked-list merge sort, as in the reference.
+#[allow(clippy::if_same_then_else)]
+#[inline(never)]
+unsafe fn core_list_mergesort(
+    mut list: *mut ListHead,
+    cmp: ListCmp,
+    res: *mut Results,
+) -> *mut ListHead {
+    let mut insize: i32 = 1;
+    loop {
+        let mut p = list;
+        list = ptr::null_mut();
+        let mut tail: *mut ListHead = ptr::null_mut();
+        let mut nmerges = 0;
+
+        while !p.is_null() {
+            nmerges += 1;
+            // Step insize places along from p
+            let mut q = p;
+            let mut psize = 0;
+            for _ in 0..insize {
+                psize += 1;
+                q = (*q).next;
+                if q.is_null() {
+                    break;
+                }
+            }
+            let mut qsize = insize;
+
+            // Merge the two lists
+            while psize > 0 || (qsize > 0 && !q.is_null()) {
+                let e;
+                if psize == 0 {
+                    e = q;
+                    q = (*q).next;
+                    qsize -= 1;
+                } else if qsize == 0 || q.is_null() {
+                    e = p;
+                    p = (*p).next;
+                    psize -= 1;
+                } else if cmp((*p).info, (*q).info, res) <= 0 {
+                    e = p;
+                    p = (*p).next;
+                    psize -= 1;
+                } else {
+                    e = q;
+                    q = (*q).next;
+                    qsize -= 1;
+                }
+                if tail.is_null() {
+                    list = e;
+                } else {
+                    (*tail).next = e;
+                }
+                tail = e;
+            }
+            p = q;
+        }
+        (*tail).next = ptr::null_mut();
+
+        if nmerges <= 1 {
+            return list;
+        }
+        insize *= 2;
+    }
+}
+
+// ---------------------------------------------------------------------
+// core_matrix.c
+// ---------------------------------------------------------------------
+
+/// Largest matrix side that fits: the reference picks the largest N with
+/// three N*N matrices in one block.
+const MATRIX_MAX: usize = 9;
+
+static mut MATRIX_A: [i16; MATRIX_MAX * MATRIX_MAX] = [0; MATRIX_MAX * MATRIX_MAX];
+static mut MATRIX_B: [i16; MATRIX_MAX * MATRIX_MAX] = [0; MATRIX_MAX * MATRIX_MAX];
+static mut MATRIX_C: [i32; MATRIX_MAX * MATRIX_MAX] = [0; MATRIX_MAX * MATRIX_MAX];
+
+fn bit_extract(x: i32, from: u32, to: u32) -> u32 {
+    ((x >> from) as u32) & !(0xffff_ffffu32 << to)
+}
+
+#[inline(never)]
+unsafe fn core_bench_matrix(p: &MatParams, seed: i16, crc: u16) -> u16 {
+    crc16(matrix_test(p.n, p.c, p.a, p.b, seed), crc)
+}
+
+unsafe fn matrix_test(n: usize, c: *mut i32, a: *mut i16, b: *mut i16, val: i16) -> i16 {
+    let mut crc: u16 = 0;
+    let clipval = (0xf000 | val as i32) as i16;
+
+    matrix_add_const(n, a, val);
+
+    matrix_mul_const(n, c, a, val);
+    crc = crc16(matrix_sum(n, c, clipval), crc);
+
+    matrix_mul_vect(n, c, a, b);
+    crc = crc16(matrix_sum(n, c, clipval), crc);
+
+    matrix_mul_matrix(n, c, a, b);
+    crc = crc16(matrix_sum(n, c, clipval), crc);
+
+    matrix_mul_matrix_bitextract(n, c, a, b);
+    crc = crc16(matrix_sum(n, c, clipval), crc);
+
+    matrix_add_const(n, a, val.wrapping_neg());
+    crc as i16
+}
+
+unsafe fn core_init_matrix(blksize: u32, mut seed: i32) -> MatParams {
+    if seed == 0 {
+        seed = 1;
+    }
+    let mut i: u32 = 0;
+    let mut j: u32 = 0;
+    while j < blksize {
+        i += 1;
+        j = i * i * 2 * 4;
+    }
+    let n = (i - 1) as usize;
+    assert!(n <= MATRIX_MAX);
+    let a = addr_of_mut!(MATRIX_A) as *mut i16;
+    let b = addr_of_mut!(MATRIX_B) as *mut i16;
+
+    let mut order: i32 = 1;
+    for i in 0..n {
+        for j in 0..n {
+            seed = (order * seed) % 65536;
+            let mut val = (seed + order) as i16;
+            val = (val as i32 & 0x0ffff) as i16;
+            *b.add(i * n + j) = val;
+            val = (val as i32 + order) as i16;
+            val = (val as i32 & 0x0ff) as i16;
+            *a.add(i * n + j) = val;
+            order += 1;
+        }
+    }
+
+    MatParams {
+        n,
+        a,
+        b,
+        c: addr_of_mut!(MATRIX_C) as *mut i32,
+    }
+}
+
+#[inline(never)]
+unsafe fn matrix_sum(n: usize, c: *mut i32, clipval: i16) -> i16 {
+    let mut tmp: i32 = 0;
+    let mut prev: i32 = 0;
+    let mut ret: i16 = 0;
+    for i in 0..n {
+        for j in 0..n {
+            let cur = *c.add(i * n + j);
+            tmp = tmp.wrapping_add(cur);
+            if tmp > clipval as i32 {
+                ret = ret.wrapping_add(10);
+                tmp = 0;
+            } else {
+                ret = ret.wrapping_add((cur > prev) as i16);
+            }
+            prev = cur;
+        }
+    }
+    ret
+}
+
+#[inline(never)]
+unsafe fn matrix_mul_const(n: usize, c: *mut i32, a: *mut i16, val: i16) {
+    for i in 0..n {
+        for j in 0..n {
+            *c.add(i * n + j) = (*a.add(i * n + j) as i32).wrapping_mul(val as i32);
+        }
+    }
+}
+
+#[inline(never)]
+unsafe fn matrix_add_const(n: usize, a: *mut i16, val: i16) {
+    for i in 0..n {
+        for j in 0..n {
+            let p = a.add(i * n + j);
+            *p = (*p).wrapping_add(val);
+        }
+    }
+}
+
+#[inline(never)]
+unsafe fn matrix_mul_vect(n: usize, c: *mut i32, a: *mut i16, b: *mut i16) {
+    for i in 0..n {
+        *c.add(i) = 0;
+        for j in 0..n {
+            let product = (*a.add(i * n + j) as i32).wrapping_mul(*b.add(j) as i32);
+            *c.add(i) = (*c.add(i)).wrapping_add(product);
+        }
+    }
+}
+
+#[inline(never)]
+unsafe fn matrix_mul_matrix(n: usize, c: *mut i32, a: *mut i16, b: *mut i16) {
+    for i in 0..n {
+        for j in 0..n {
+            let out = c.add(i * n + j);
+            *out = 0;
+            for k in 0..n {
+                let product = (*a.add(i * n + k) as i32).wrapping_mul(*b.add(k * n + j) as i32);
+                *out = (*out).wrapping_add(product);
+            }
+        }
+    }
+}
+
+#[inline(never)]
+unsafe fn matrix_mul_matrix_bitextract(n: usize, c: *mut i32, a: *mut i16, b: *mut i16) {
+    for i in 0..n {
+        for j in 0..n {
+            let out = c.add(i * n + j);
+            *out = 0;
+            for k in 0..n {
+                let tmp = (*a.add(i * n + k) as i32).wrapping_mul(*b.add(k * n + j) as i32);
+                let bits = bit_extract(tmp, 2, 4).wrapping_mul(bit_extract(tmp, 5, 7));
+                *out = (*out).wrapping_add(bits as i32);
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// core_state.c
+// ---------------------------------------------------------------------
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CoreState {
+    Start,
+    Invalid,
+    S1,
+    S2,
+    Int,
+    Float,
+    Exponent,
+    Scientific,
+}
+
+const NUM_CORE_STATES: usize = 8;
+
+static mut STATE_INPUT: [u8; BLOCK_SIZE as usize] = [0; BLOCK_SIZE as usize];
+
+const INTPAT: [&[u8]; 4] = [b"5012", b"1234", b"-874", b"+122"];
+const FLOATPAT: [&[u8]; 4] = [b"35.54400", b".1234500", b"-110.700", b"+0.64400"];
+const SCIPAT: [&[u8]; 4] = [b"5.500e+3", b"-.123e-2", b"-87e+832", b"+0.6e-12"];
+const ERRPAT: [&[u8]; 4] = [b"T0.3e-1F", b"-T.T++Tq", b"1T3.4e4z", b"34.0e-T^"];
+
+#[inline(never)]
+unsafe fn core_bench_state(
+    blksize: u32,
+    memblock: *mut u8,
+    seed1: i16,
+    seed2: i16,
+    step: i16,
+    mut crc: u16,
+) -> u16 {
+    let mut final_counts = [0u32; NUM_CORE_STATES];
+    let mut track_counts = [0u32; NUM_CORE_STATES];
+    let end = memblock.add(blksize as usize);
+
+    // Run the state machine over the input
+    let mut p = memblock;
+    while *p != 0 {
+        let fstate = core_state_transition(&mut p, &mut track_counts);
+        final_counts[fstate as usize] += 1;
+    }
+    // Insert some corruption
+    p = memblock;
+    while p < end {
+        if *p != b',' {
+            *p ^= seed1 as u8;
+        }
+        p = p.wrapping_add(step as usize);
+    }
+    // And run it again
+    p = memblock;
+    while *p != 0 {
+        let fstate = core_state_transition(&mut p, &mut track_counts);
+        final_counts[fstate as usize] += 1;
+    }
+    // Undo the corruption if seed1 and seed2 are equal
+    p = memblock;
+    while p < end {
+        if *p != b',' {
+            *p ^= seed2 as u8;
+        }
+        p = p.wrapping_add(step as usize);
+    }
+
+    for i in 0..NUM_CORE_STATES {
+        crc = crcu32(final_counts[i], crc);
+        crc = crcu32(track_counts[i], crc);
+    }
+    crc
+}
+
+/// Fill `size` bytes at `p` with comma-separated numbers, some malformed.
+unsafe fn core_init_state(size: u32, mut seed: i16, p: *mut u8) {
+    let mut total: u32 = 0;
+    let mut next: u32 = 0;
+    let mut buf: &[u8] = &[];
+
+    let size = size - 1;
+    while total + next + 1 < size {
+        if next > 0 {
+            for i in 0..next {
+                *p.add((total + i) as usize) = buf[i as usize];
+            }
+            *p.add((total + next) as usize) = b',';
+            total += next + 1;
+        }
+        seed = seed.wrapping_add(1);
+        let pattern = ((seed >> 3) & 0x3) as usize;
+        match seed & 0x7 {
+            0..=2 => {
+                buf = INTPAT[pattern];
+                next = 4;
+            }
+            3 | 4 => {
+                buf = FLOATPAT[pattern];
+                next = 8;
+            }
+            5 | 6 => {
+                buf = SCIPAT[pattern];
+                next = 8;
+            }
+            _ => {
+                buf = ERRPAT[pattern];
+                next = 8;
+            }
+        }
+    }
+    let size = size + 1;
+    while total < size {
+        *p.add(total as usize) = 0;
+        total += 1;
+    }
+}
+
+fn is_digit(c: u8) -> bool {
+    c.is_ascii_digit()
+}
+
+/// Run the state machine over one comma-terminated input at `*instr`,
+/// leaving `*instr` after it.
+#[inline(never)]
+unsafe fn core_state_transition(
+    instr: &mut *mut u8,
+    transition_count: &mut [u32; NUM_CORE_STATES],
+) -> CoreState {
+    use CoreState::*;
+
+    let mut s = *instr;
+    let mut state = Start;
+    while *s != 0 && state != Invalid {
+        let next_symbol = *s;
+        if next_symbol == b',' {
+            s = s.add(1);
+            break;
+        }
+        match state {
+            Start => {
+                if is_digit(next_symbol) {
+                    state = Int;
+                } else if next_symbol == b'+' || next_symbol == b'-' {
+                    state = S1;
+                } else if next_symbol == b'.' {
+                    state = Float;
+                } else {
+                    state = Invalid;
+                    transition_count[Invalid as usize] += 1;
+                }
+                transition_count[Start as usize] += 1;
+            }
+            S1 => {
+                if is_digit(next_symbol) || next_symbol == b'.' {
+                    state = if next_symbol == b'.' { Float } else { Int };
+                } else {
+                    state = Invalid;
+                }
+                transition_count[S1 as usize] += 1;
+            }
+            Int => {
+                if next_symbol == b'.' {
+                    state = Float;
+                    transition_count[Int as usize] += 1;
+                } else if !is_digit(next_symbol) {
+                    state = Invalid;
+                    transition_count[Int as usize] += 1;
+                }
+            }
+            Float => {
+                if next_symbol == b'E' || next_symbol == b'e' {
+                    state = S2;
+                    transition_count[Float as usize] += 1;
+                } else if !is_digit(next_symbol) {
+                    state = Invalid;
+                    transition_count[Float as usize] += 1;
+                }
+            }
+            S2 => {
+                state = if next_symbol == b'+' || next_symbol == b'-' {
+                    Exponent
+                } else {
+                    Invalid
+                };
+                transition_count[S2 as usize] += 1;
+            }
+            Exponent => {
+                state = if is_digit(next_symbol) {
+                    Scientific
+                } else {
+                    Invalid
+                };
+                transition_count[Exponent as usize] += 1;
+            }
+            Scientific => {
+                if !is_digit(next_symbol) {
+                    state = Invalid;
+                    transition_count[Invalid as usize] += 1;
+                }
+            }
+            Invalid => {}
+        }
+        s = s.add(1);
+    }
+    *instr = s;
+    state
+}
+
+// ---------------------------------------------------------------------
+// core_main.c
+// ---------------------------------------------------------------------
+
+unsafe fn iterate(res: *mut Results, iterations: u64) {
+    (*res).crc = 0;
+    (*res).crclist = 0;
+    (*res).crcmatrix = 0;
+    (*res).crcstate = 0;
+    for i in 0..iterations {
+        let crc = core_bench_list(res, 1);
+        (*res).crc = crcu16(crc, (*res).crc);
+        let crc = core_bench_list(res, -1);
+        (*res).crc = crcu16(crc, (*res).crc);
+        if i == 0 {
+            (*res).crclist = (*res).crc;
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn bench_main(iterations: u64) -> bool {
+    let iterations = if iterations == 0 {
+        DEFAULT_ITERATIONS
+    } else {
+        iterations
+    };
+
+    // SAFETY: single hart; the benchmark owns its statics and, like the
+    // reference, works on them through raw pointers
+    let mut results = unsafe {
+        let state = addr_of_mut!(STATE_INPUT) as *mut u8;
+        let results = Results {
+            seed1: SEED1,
+            seed2: SEED2,
+            seed3: SEED3,
+            size: BLOCK_SIZE,
+            list: core_list_init(SEED1),
+            mat: core_init_matrix(BLOCK_SIZE, SEED1 as i32 | ((SEED2 as i32) << 16)),
+            state,
+            crc: 0,
+            crclist: 0,
+            crcmatrix: 0,
+            crcstate: 0,
+        };
+        core_init_state(BLOCK_SIZE, SEED1, state);
+        results
+    };
+    unsafe { iterate(&mut results, iterations) };
+
+    let mut seedcrc = crc16(results.seed1, 0);
+    seedcrc = crc16(results.seed2, seedcrc);
+    seedcrc = crc16(results.seed3, seedcrc);
+    seedcrc = crc16(results.size as i16, seedcrc);
+
+    println!("CoreMark Size    : {}", results.size);
+    println!("Iterations       : {}", iterations);
+    println!("seedcrc          : 0x{:04x}", seedcrc);
+    println!("[0]crclist       : 0x{:04x}", results.crclist);
+    println!("[0]crcmatrix     : 0x{:04x}", results.crcmatrix);
+    println!("[0]crcstate      : 0x{:04x}", results.crcstate);
+    println!("[0]crcfinal      : 0x{:04x}", results.crc);
+
+    let checks = [
+        ("seedcrc", seedcrc, SEED_CRC),
+        ("list crc", results.crclist, LIST_KNOWN_CRC),
+        ("matrix crc", results.crcmatrix, MATRIX_KNOWN_CRC),
+        ("state crc", results.crcstate, STATE_KNOWN_CRC),
+    ];
+    let mut pass = true;
+    for (name, got, expected) in checks {
+        if got != expected {
+            println!(
+                "ERROR! {} 0x{:04x} - should be 0x{:04x}",
+                name, got, expected
+            );
+            pass = false;
+        }
+    }
+    if pass {
+        println!("Correct operation validated.");
+    }
+    pass
+}
@@ -0,0 +1,354 @@
+//! Dhrystone 2.1 (Reinhold P. Weicker, 1988; distributed freely by its
+//! author), ported from the C reference version to bare-metal Rust.
+//!
+//! The procedures keep the original's structure, names and statement
+//! order, including the globals, the two records linked by pointers and the
+//! C string copies and comparisons. Each procedure is kept out of line, as
+//! the original splits them over two translation units to stop the
+//! compiler folding the benchmark away. Results are comparable between
+//! emulator builds, not with Dhrystone figures from C compilers.
+//!
+//! After the runs the image checks the globals against the values the
+//! reference version prints as "should be".
+
+#![no_std]
+#![no_main]
+
+use bench_images::println;
+use core::ptr::{self, addr_of_mut};
+
+/// Runs when the harness passes 0.
+const DEFAULT_RUNS: u64 = 100_000;
+
+// Ident5 only shows up in Proc_6's switch, as in the original
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Enumeration {
+    Ident1,
+    Ident2,
+    Ident3,
+    Ident4,
+    Ident5,
+}
+use Enumeration::*;
+
+type Str30 = [u8; 31];
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Record {
+    ptr_comp: *mut Record,
+    discr: Enumeration,
+    enum_comp: Enumeration,
+    int_comp: i32,
+    str_comp: Str30,
+}
+
+const EMPTY_RECORD: Record = Record {
+    ptr_comp: ptr::null_mut(),
+    discr: Ident1,
+    enum_comp: Ident1,
+    int_comp: 0,
+    str_comp: [0; 31],
+};
+
+struct Globals {
+    ptr_glob: *mut Record,
+    next_ptr_glob: *mut Record,
+    int_glob: i32,
+    bool_glob: bool,
+    ch_1_glob: u8,
+    ch_2_glob: u8,
+}
+
+static mut GLOBALS: Globals = Globals {
+    ptr_glob: ptr::null_mut(),
+    next_ptr_glob: ptr::null_mut(),
+    int_glob: 0,
+    bool_glob: false,
+    ch_1_glob: 0,
+    ch_2_glob: 0,
+};
+static mut ARR_1_GLOB: [i32; 50] = [0; 50];
+static mut ARR_2_GLOB: [[i32; 50]; 50] = [[0; 50]; 50];
+// The reference version mallocs these
+static mut RECORD_GLOB: Record = EMPTY_RECORD;
+static mut NEXT_RECORD_GLOB: Record = EMPTY_RECORD;
+
+/// `strcpy` of a NUL-terminated string.
+#[inline(never)]
+fn str_copy(dst: &mut Str30, src: &[u8]) {
+    let mut i = 0;
+    loop {
+        let c = src[i];
+        dst[i] = c;
+        if c == 0 {
+            break;
+        }
+        i += 1;
+    }
+}
+
+/// `strcmp` of two NUL-terminated strings.
+#[inline(never)]
+fn str_compare(a: &Str30, b: &Str30) -> i32 {
+    let mut i = 0;
+    while a[i] != 0 && a[i] == b[i] {
+        i += 1;
+    }
+    a[i] as i32 - b[i] as i32
+}
+
+#[no_mangle]
+extern "C" fn bench_main(runs: u64) -> bool {
+    let runs = if runs == 0 { DEFAULT_RUNS } else { runs };
+    // SAFETY: single hart, and nothing else touches these statics
+    let (g, arr_1_glob, arr_2_glob) = unsafe {
+        (
+            &mut *addr_of_mut!(GLOBALS),
+            &mut *addr_of_mut!(ARR_1_GLOB),
+            &mut *addr_of_mut!(ARR_2_GLOB),
+        )
+    };
+
+    let mut int_1_loc: i32 = 0;
+    let mut int_2_loc: i32 = 0;
+    let mut int_3_loc: i32 = 0;
+    let mut enum_loc = Ident1;
+    let mut str_1_loc: Str30 = [0; 31];
+    let mut str_2_loc: Str30 = [0; 31];
+
+    g.next_ptr_glob = addr_of_mut!(NEXT_RECORD_GLOB);
+    g.ptr_glob = addr_of_mut!(RECORD_GLOB);
+    unsafe {
+        (*g.ptr_glob).ptr_comp = g.next_ptr_glob;
+        (*g.ptr_glob).discr = Ident1;
+        (*g.ptr_glob).enum_comp = Ident3;
+        (*g.ptr_glob).int_comp = 40;
+        str_copy(
+            &mut (*g.ptr_glob).str_comp,
+            b"DHRYSTONE PROGRAM, SOME STRING\0",
+        );
+    }
+    str_copy(&mut str_1_loc, b"DHRYSTONE PROGRAM, 1'ST STRING\0");
+    arr_2_glob[8][7] = 10;
+
+    println!("Dhrystone 2.1: {} runs", runs);
+
+    for run_index in 1..=runs {
+        proc_5(g);
+        proc_4(g);
+        int_1_loc = 2;
+        int_2_loc = 3;
+        str_copy(&mut str_2_loc, b"DHRYSTONE PROGRAM, 2'ND STRING\0");
+        enum_loc = Ident2;
+        g.bool_glob = !func_2(g, &str_1_loc, &str_2_loc);
+        while int_1_loc < int_2_loc {
+            int_3_loc = 5 * int_1_loc - int_2_loc;
+            proc_7(int_1_loc, int_2_loc, &mut int_3_loc);
+            int_1_loc += 1;
+        }
+        proc_8(g, arr_1_glob, arr_2_glob, int_1_loc, int_3_loc);
+        unsafe { proc_1(g, g.ptr_glob) };
+        let mut ch_index = b'A';
+        while ch_index <= g.ch_2_glob {
+            if enum_loc == func_1(g, ch_index, b'C') {
+                proc_6(g, Ident1, &mut enum_loc);
+                str_copy(&mut str_2_loc, b"DHRYSTONE PROGRAM, 3'RD STRING\0");
+                int_2_loc = run_index as i32;
+                g.int_glob = run_index as i32;
+            }
+            ch_index += 1;
+        }
+        int_2_loc *= int_1_loc;
+        int_1_loc = int_2_loc / int_3_loc;
+        int_2_loc = 7 * (int_2_loc - int_3_loc) - int_1_loc;
+        proc_2(g, &mut int_1_loc);
+    }
+
+    let (glob, next) = unsafe { (*g.ptr_glob, *g.next_ptr_glob) };
+    let some_string = b"DHRYSTONE PROGRAM, SOME STRING\0";
+    let checks = [
+        ("Int_Glob", g.int_glob == 5),
+        ("Bool_Glob", g.bool_glob),
+        ("Ch_1_Glob", g.ch_1_glob == b'A'),
+        ("Ch_2_Glob", g.ch_2_glob == b'B'),
+        ("Arr_1_Glob[8]", arr_1_glob[8] == 7),
+        (
+            "Arr_2_Glob[8][7]",
+            arr_2_glob[8][7] == (runs as i32).wrapping_add(10),
+        ),
+        ("Ptr_Glob->Discr", glob.discr == Ident1),
+        ("Ptr_Glob->Enum_Comp", glob.enum_comp == Ident3),
+        ("Ptr_Glob->Int_Comp", glob.int_comp == 17),
+        ("Ptr_Glob->Str_Comp", glob.str_comp == *some_string),
+        ("Next_Ptr_Glob->Discr", next.discr == Ident1),
+        ("Next_Ptr_Glob->Enum_Comp", next.enum_comp == Ident2),
+        ("Next_Ptr_Glob->Int_Comp", next.int_comp == 18),
+        ("Next_Ptr_Glob->Str_Comp", next.str_comp == *some_string),
+        ("Int_1_Loc", int_1_loc == 5),
+        ("Int_2_Loc", int_2_loc == 13),
+        ("Int_3_Loc", int_3_loc == 7),
+        ("Enum_Loc", enum_loc == Ident2),
+        (
+            "Str_1_Loc",
+            str_1_loc == *b"DHRYSTONE PROGRAM, 1'ST STRING\0",
+        ),
+        (
+            "Str_2_Loc",
+            str_2_loc == *b"DHRYSTONE PROGRAM, 2'ND STRING\0",
+        ),
+    ];
+    let mut pass = true;
+    for (name, ok) in checks {
+        if !ok {
+            println!("wrong final value: {}", name);
+            pass = false;
+        }
+    }
+    if pass {
+        println!("Final values ok");
+    }
+    pass
+}
+
+#[inline(never)]
+unsafe fn proc_1(g: &mut Globals, ptr_val_par: *mut Record) {
+    let next_record = (*ptr_val_par).ptr_comp;
+    *(*ptr_val_par).ptr_comp = *g.ptr_glob;
+    (*ptr_val_par).int_comp = 5;
+    (*next_record).int_comp = (*ptr_val_par).int_comp;
+    (*next_record).ptr_comp = (*ptr_val_par).ptr_comp;
+    proc_3(g, &mut (*next_record).ptr_comp);
+    if (*next_record).discr == Ident1 {
+        (*next_record).int_comp = 6;
+        proc_6(g, (*ptr_val_par).enum_comp, &mut (*next_record).enum_comp);
+        (*next_record).ptr_comp = (*g.ptr_glob).ptr_comp;
+        proc_7((*next_record).int_comp, 10, &mut (*next_record).int_comp);
+    } else {
+        *ptr_val_par = *(*ptr_val_par).ptr_comp;
+    }
+}
+
+#[inline(never)]
+fn proc_2(g: &mut Globals, int_par_ref: &mut i32) {
+    let mut int_loc = *int_par_ref + 10;
+    let mut enum_loc = Ident4;
+    loop {
+        if g.ch_1_glob == b'A' {
+            int_loc -= 1;
+            *int_par_ref = int_loc - g.int_glob;
+            enum_loc = Ident1;
+        }
+        if enum_loc == Ident1 {
+            break;
+        }
+    }
+}
+
+#[inline(never)]
+unsafe fn proc_3(g: &mut Globals, ptr_ref_par: &mut *mut Record) {
+    if !g.ptr_glob.is_null() {
+        *ptr_ref_par = (*g.ptr_glob).ptr_comp;
+    }
+    proc_7(10, g.int_glob, &mut (*g.ptr_glob).int_comp);
+}
+
+#[inline(never)]
+fn proc_4(g: &mut Globals) {
+    let bool_loc = g.ch_1_glob == b'A';
+    g.bool_glob |= bool_loc;
+    g.ch_2_glob = b'B';
+}
+
+#[inline(never)]
+fn proc_5(g: &mut Globals) {
+    g.ch_1_glob = b'A';
+    g.bool_glob = false;
+}
+
+#[inline(never)]
+fn proc_6(g: &mut Globals, enum_val_par: Enumeration, enum_ref_par: &mut Enumeration) {
+    *enum_ref_par = enum_val_par;
+    if !func_3(enum_val_par) {
+        *enum_ref_par = Ident4;
+    }
+    match enum_val_par {
+        Ident1 => *enum_ref_par = Ident1,
+        Ident2 => {
+            *enum_ref_par = if g.int_glob > 100 { Ident1 } else { Ident4 };
+        }
+        Ident3 => *enum_ref_par = Ident2,
+        Ident4 => {}
+        Ident5 => *enum_ref_par = Ident3,
+    }
+}
+
+#[inline(never)]
+fn proc_7(int_1_par_val: i32, int_2_par_val: i32, int_par_ref: &mut i32) {
+    let int_loc = int_1_par_val + 2;
+    *int_par_ref = int_2_par_val + int_loc;
+}
+
+#[allow(clippy::needless_range_loop)]
+#[inline(never)]
+fn proc_8(
+    g: &mut Globals,
+    arr_1_par_ref: &mut [i32; 50],
+    arr_2_par_ref: &mut [[i32; 50]; 50],
+    int_1_par_val: i32,
+    int_2_par_val: i32,
+) {
+    let int_loc = (int_1_par_val + 5) as usize;
+    arr_1_par_ref[int_loc] = int_2_par_val;
+    arr_1_par_ref[int_loc + 1] = arr_1_par_ref[int_loc];
+    arr_1_par_ref[int_loc + 30] = int_loc as i32;
+    for int_index in int_loc..=int_loc + 1 {
+        arr_2_par_ref[int_loc][int_index] = int_loc as i32;
+    }
+    arr_2_par_ref[int_loc][int_loc - 1] += 1;
+    arr_2_par_ref[int_loc + 20][int_loc] = arr_1_par_ref[int_loc];
+    g.int_glob = 5;
+}
+
+#[inline(never)]
+fn func_1(g: &mut Globals, ch_1_par_val: u8, ch_2_par_val: u8) -> Enumeration {
+    let ch_1_loc = ch_1_par_val;
+    let ch_2_loc = ch_1_loc;
+    if ch_2_loc != ch_2_par_val {
+        Ident1
+    } else {
+        g.ch_1_glob = ch_1_loc;
+        Ident2
+    }
+}
+
+#[inline(never)]
+fn func_2(g: &mut Globals, str_1_par_ref: &Str30, str_2_par_ref: &Str30) -> bool {
+    let mut int_loc = 2;
+    let mut ch_loc = 0;
+    while int_loc <= 2 {
+        if func_1(g, str_1_par_ref[int_loc], str_2_par_ref[int_loc + 1]) == Ident1 {
+            ch_loc = b'A';
+            int_loc += 1;
+        }
+    }
+    if (b'W'..b'Z').contains(&ch_loc) {
+        int_loc = 7;
+    }
+    if ch_loc == b'R' {
+        true
+    } else if str_compare(str_1_par_ref, str_2_par_ref) > 0 {
+        int_loc += 7;
+        g.int_glob = int_loc as i32;
+        true
+    } else {
+        false
+    }
+}
+
+#[inline(never)]
+fn func_3(enum_par_val: Enumeration) -> bool {
+    let enum_loc = enum_par_val;
+    enum_loc == Ident3
+}
@@ -0,0 +1,70 @@
+//! Bare-metal runtime shared by the benchmark images.
+//!
+//! The emulator's boot ROM enters `_start` in machine mode with the stack
+//! set up and the run's iteration count in `a1` (see `riscv_vm::bench`).
+//! Images print through the UART and stop by writing to the test finisher:
+//! `0x5555` when their self-check passed, `0x3333` otherwise.
+
+#![no_std]
+
+use core::fmt;
+use core::panic::PanicInfo;
+use core::ptr;
+
+const UART_THR: *mut u8 = 0x1000_0000 as *mut u8;
+const TEST_FINISHER: *mut u32 = 0x0010_0000 as *mut u32;
+
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_FAIL: u32 = 0x3333;
+
+core::arch::global_asm!(
+    ".section .text.start, \"ax\"",
+    ".globl _start",
+    "_start:",
+    "    la t0, _sbss",
+    "    la t1, _ebss",
+    "1:  bgeu t0, t1, 2f",
+    "    sd zero, 0(t0)",
+    "    addi t0, t0, 8",
+    "    j 1b",
+    "2:  mv a0, a1",
+    "    call bench_main",
+    "    call {exit}",
+    exit = sym exit,
+);
+
+/// Stop the run, reporting whether the benchmark's results checked out.
+pub fn exit(pass: bool) -> ! {
+    let code = if pass { FINISHER_PASS } else { FINISHER_FAIL };
+    unsafe { ptr::write_volatile(TEST_FINISHER, code) };
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// The emulator's 16550 UART. Its transmitter never fills up, so bytes go
+/// straight to the holding register.
+pub struct Uart;
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            unsafe { ptr::write_volatile(UART_THR, byte) };
+        }
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        let _ = core::fmt::Write::write_fmt(&mut $crate::Uart, format_args!($($arg)*));
+        let _ = core::fmt::Write::write_str(&mut $crate::Uart, "\n");
+    }};
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("panic: {}", info.message());
+    exit(false)
+}
@@ -1,3 +1,5 @@
+use std::process::Command;
+
 fn main() {
     // napi-build setup (only when napi feature is enabled)
     #[cfg(feature = "napi")]
@@ -5,4 +7,21 @@ fn main() {
         extern crate napi_build;
         napi_build::setup();
     }
+
+    // Commit hash for the build information device
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+    let hash = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=RISCV_VM_GIT_HASH={}", hash);
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        // logs/HEAD changes on every commit and checkout
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/logs/HEAD", git_dir);
+    }
 }
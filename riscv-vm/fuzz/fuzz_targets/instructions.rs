@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use riscv_vm::fuzz::{self, Program};
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(finding) = fuzz::check(data) {
+        panic!("{}", fuzz::report(&Program::from_bytes(data), &finding));
+    }
+});
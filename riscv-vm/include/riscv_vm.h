@@ -0,0 +1,89 @@
+/*
+ * riscv_vm.h - C interface to the RISC-V virtual machine.
+ *
+ * Build the shared library with:
+ *
+ *     cargo build --release -p riscv-vm --features ffi
+ *
+ * and link against target/release/libriscv_vm.so (.dylib on macOS,
+ * riscv_vm.dll on Windows).
+ *
+ * A RiscvVm is a single hart with DRAM, the boot ROM, CLINT, PLIC, UART and
+ * any VirtIO block devices attached before running. The host drives it with
+ * vm_step/vm_run. Functions returning int return 0 on success and -1 on
+ * failure, with a description from vm_last_error() on the same thread.
+ * A RiscvVm must not be used from two threads at once.
+ */
+
+#ifndef RISCV_VM_H
+#define RISCV_VM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RiscvVm RiscvVm;
+
+/* Message describing the last failure on this thread, or NULL. Valid until
+ * the next failing call on the same thread. */
+const char *vm_last_error(void);
+
+/* Create a VM and boot `kernel` (ELF or raw binary) with `memory_mib` MiB of
+ * DRAM (0 for the default 512 MiB). Returns NULL on failure. */
+RiscvVm *vm_create(const uint8_t *kernel, size_t len, uint32_t memory_mib);
+
+/* Destroy a VM. NULL is ignored. */
+void vm_destroy(RiscvVm *vm);
+
+/* Attach a copy of `disk` as a VirtIO block device. Call before running. */
+void vm_load_disk(RiscvVm *vm, const uint8_t *disk, size_t len);
+
+/* Execute one instruction. Returns 1 while running and 0 once halted. */
+int vm_step(RiscvVm *vm);
+
+/* Execute up to `max_steps` instructions (0 for no limit), stopping early if
+ * the VM halts. Returns the number executed. */
+uint64_t vm_run(RiscvVm *vm, uint64_t max_steps);
+
+/* Whether the guest has requested shutdown (or hit a fatal error). */
+int vm_is_halted(const RiscvVm *vm);
+
+/* Halt code once halted; 0x5555 is a clean shutdown. */
+uint64_t vm_halt_code(const RiscvVm *vm);
+
+/* Program counter of the hart. */
+uint64_t vm_get_pc(const RiscvVm *vm);
+
+/* Read or write integer register x<index> (writes to x0 are ignored). */
+int vm_read_reg(const RiscvVm *vm, uint32_t index, uint64_t *value);
+int vm_write_reg(RiscvVm *vm, uint32_t index, uint64_t value);
+
+/* Copy physical memory to or from `buf`. Only DRAM is accessible. */
+int vm_read_mem(const RiscvVm *vm, uint64_t addr, uint8_t *buf, size_t len);
+int vm_write_mem(RiscvVm *vm, uint64_t addr, const uint8_t *buf, size_t len);
+
+/* Queue `len` bytes of UART input. */
+void vm_input(RiscvVm *vm, const uint8_t *data, size_t len);
+
+/* Move up to `cap` bytes of pending UART output into `buf`. Returns the
+ * number of bytes written. */
+size_t vm_read_output(RiscvVm *vm, uint8_t *buf, size_t cap);
+
+/* Serialize the VM state (hart, CLINT, PLIC, UART and DRAM). On success
+ * `*data` and `*len` describe a buffer to release with vm_free_snapshot. */
+int vm_snapshot(const RiscvVm *vm, uint8_t **data, size_t *len);
+
+/* Release a buffer returned by vm_snapshot. NULL is ignored. */
+void vm_free_snapshot(uint8_t *data, size_t len);
+
+/* Restore state saved with vm_snapshot. The DRAM size must match. */
+int vm_restore(RiscvVm *vm, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RISCV_VM_H */
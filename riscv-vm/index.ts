@@ -12,7 +12,7 @@ export async function WasmInternal() {
   return loaded;
 }
 
-export { NetworkStatus, WasmVm } from "./pkg/riscv_vm";
+export { NetworkStatus, VmPool, WasmVm } from "./pkg/riscv_vm";
 
 // Re-export worker message types for consumers (from side-effect-free module)
 export type {
@@ -21,6 +21,8 @@ export type {
   WorkerHaltedMessage,
   WorkerErrorMessage,
   WorkerOutboundMessage,
+  PoolInitMessage,
+  PoolExitedMessage,
 } from "./worker-utils";
 
 // Re-export worker utilities (from side-effect-free module)
@@ -0,0 +1,248 @@
+//! Symbolized guest backtraces.
+//!
+//! [`unwind`] walks the guest stack of a stopped hart by following the
+//! frame-pointer chain (`s0`), as laid out by GCC and Clang with
+//! `-fno-omit-frame-pointer`: the return address sits at `fp - 8` and the
+//! caller's frame pointer at `fp - 16`. A leaf function that saves only
+//! `s0` keeps the caller's frame pointer at `fp - 8` instead; that slot is
+//! recognised because it points further up the stack rather than at code,
+//! and the return address is then taken from `ra`. The walk stops at the
+//! first frame pointer that is zero, misaligned, not above the previous
+//! frame, or unreadable, so code built without frame pointers yields a
+//! short (but never wrong-looking) trace.
+//!
+//! Return addresses are resolved against a [`SymbolMap`] read from an ELF
+//! symbol table or a `System.map`/`nm` listing.
+
+use std::fmt;
+
+use goblin::elf::Elf;
+use goblin::elf::sym::{STT_FUNC, STT_NOTYPE};
+
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::vm::guest_mem::{self, Translation};
+
+/// Deepest backtrace produced.
+pub const MAX_FRAMES: usize = 64;
+
+/// Largest distance between two frame pointers that is still taken to be
+/// a single stack frame.
+const MAX_FRAME_SIZE: u64 = 1 << 20;
+
+/// Function symbols sorted by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolMap {
+    /// `(start, size, name)`; a size of 0 extends to the next symbol.
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl SymbolMap {
+    /// Read the symbols of an ELF image, or of a `System.map`-style text
+    /// listing (`<hex address> <type> <name>` per line).
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.starts_with(b"\x7fELF") {
+            return Self::from_elf(data);
+        }
+        let text = std::str::from_utf8(data).map_err(|_| "symbol map is not text or ELF")?;
+        Ok(Self::from_listing(text))
+    }
+
+    /// Function and untyped (assembly label) symbols of an ELF image.
+    pub fn from_elf(image: &[u8]) -> Result<Self, String> {
+        let elf = Elf::parse(image).map_err(|e| format!("ELF parse error: {}", e))?;
+        let mut map = Self::default();
+        for sym in elf.syms.iter() {
+            if !matches!(sym.st_type(), STT_FUNC | STT_NOTYPE)
+                || sym.st_shndx == 0
+                || sym.st_value == 0
+            {
+                continue;
+            }
+            let Some(name) = elf.strtab.get_at(sym.st_name) else {
+                continue;
+            };
+            // Skip mapping symbols ($x, $d) and compiler-local labels
+            if name.is_empty() || name.starts_with('$') || name.starts_with(".L") {
+                continue;
+            }
+            map.symbols
+                .push((sym.st_value, sym.st_size, name.to_string()));
+        }
+        map.sort();
+        Ok(map)
+    }
+
+    /// Text symbols (`T`, `t`, `W`, `w`) of an `nm`/`System.map` listing.
+    /// Other lines are ignored.
+    pub fn from_listing(text: &str) -> Self {
+        let mut map = Self::default();
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(addr), Some(kind), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if !matches!(kind, "T" | "t" | "W" | "w") {
+                continue;
+            }
+            if let Ok(addr) = u64::from_str_radix(addr, 16) {
+                map.symbols.push((addr, 0, name.to_string()));
+            }
+        }
+        map.sort();
+        map
+    }
+
+    fn sort(&mut self) {
+        self.symbols.sort_by_key(|&(start, _, _)| start);
+        self.symbols.dedup_by_key(|&mut (start, _, _)| start);
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Function containing `addr` and the offset into it.
+    pub fn lookup(&self, addr: u64) -> Option<(&str, u64)> {
+        let index = self.symbols.partition_point(|&(start, _, _)| start <= addr);
+        let (start, size, name) = self.symbols.get(index.checked_sub(1)?)?;
+        let offset = addr - start;
+        if *size != 0 && offset >= *size {
+            return None;
+        }
+        Some((name, offset))
+    }
+}
+
+/// One frame of a [`Backtrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// PC of the trapping instruction (frame 0) or the return address.
+    pub pc: u64,
+    /// Frame pointer of the frame, if known.
+    pub fp: u64,
+    /// Containing function and offset into it.
+    pub function: Option<(String, u64)>,
+}
+
+/// Guest call stack, innermost frame first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backtrace {
+    pub frames: Vec<Frame>,
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            write!(f, "  #{:<2} 0x{:016x}", i, frame.pc)?;
+            if let Some((name, offset)) = &frame.function {
+                write!(f, " in {}+0x{:x}", name, offset)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk the stack of `cpu` in its current address space; see the
+/// [module docs](self).
+pub fn unwind(cpu: &Cpu, bus: &SystemBus, symbols: &SymbolMap) -> Backtrace {
+    let read = |addr: u64| {
+        let mut word = [0; 8];
+        guest_mem::read_virt(cpu, bus, addr, &mut word, Translation::Current).ok()?;
+        Some(u64::from_le_bytes(word))
+    };
+    let is_frame =
+        |fp: u64, below: u64| fp > below && fp.is_multiple_of(8) && fp - below <= MAX_FRAME_SIZE;
+    let frame = |pc: u64, fp: u64, lookup: u64| Frame {
+        pc,
+        fp,
+        function: symbols
+            .lookup(lookup)
+            .map(|(name, offset)| (name.to_string(), offset + (pc - lookup))),
+    };
+
+    let mut fp = cpu.regs[8];
+    let mut frames = vec![frame(cpu.pc, fp, cpu.pc)];
+    let mut below = cpu.regs[2].wrapping_sub(1);
+    while frames.len() < MAX_FRAMES && is_frame(fp, below) {
+        let Some(slot) = read(fp - 8) else {
+            break;
+        };
+        let (pc, caller_fp) = if frames.len() == 1 && is_frame(slot, fp) {
+            // Leaf function: only s0 was saved
+            (cpu.regs[1], slot)
+        } else {
+            let Some(caller_fp) = read(fp - 16) else {
+                break;
+            };
+            (slot, caller_fp)
+        };
+        if pc == 0 {
+            break;
+        }
+        // Look up the call instruction, not the one after it
+        frames.push(frame(pc, caller_fp, pc - 1));
+        below = fp;
+        fp = caller_fp;
+    }
+    Backtrace { frames }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE};
+
+    const MAP: &str = "\
+0000000080000000 T _start
+0000000080000100 t helper
+0000000080000200 T main
+0000000080001000 D some_data
+";
+
+    #[test]
+    fn test_listing_lookup() {
+        let map = SymbolMap::from_listing(MAP);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.lookup(0x8000_0104), Some(("helper", 4)));
+        assert_eq!(map.lookup(0x8000_0200), Some(("main", 0)));
+        assert_eq!(map.lookup(0x7fff_fffc), None);
+    }
+
+    #[test]
+    fn test_unwind_frame_pointer_chain() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let stack = DRAM_BASE + 0x8000;
+        // main's frame: ra into _start, caller fp 0 ends the chain
+        let main_fp = stack + 0x40;
+        bus.write64(main_fp - 8, 0x8000_0010).unwrap();
+        bus.write64(main_fp - 16, 0).unwrap();
+        // Leaf helper saved only s0 (main's fp) at fp - 8
+        let helper_fp = stack + 0x10;
+        bus.write64(helper_fp - 8, main_fp).unwrap();
+
+        let mut cpu = Cpu::new(0x8000_0108, 0);
+        cpu.regs[1] = 0x8000_0224; // ra: back into main
+        cpu.regs[2] = stack;
+        cpu.regs[8] = helper_fp;
+
+        let trace = unwind(&cpu, &bus, &SymbolMap::from_listing(MAP));
+        let pcs: Vec<u64> = trace.frames.iter().map(|f| f.pc).collect();
+        assert_eq!(pcs, [0x8000_0108, 0x8000_0224, 0x8000_0010]);
+        assert_eq!(trace.frames[0].function, Some(("helper".to_string(), 8)));
+        assert_eq!(trace.frames[1].function, Some(("main".to_string(), 0x24)));
+        assert!(
+            trace
+                .to_string()
+                .contains("#2  0x0000000080000010 in _start+0x10")
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Emulator benchmarks.
+//!
+//! Runs bare-metal workloads to completion on a single hart under each
+//! execution engine and reports how fast the emulator got through them, so
+//! performance changes show up between commits. The `riscv-bench` binary
+//! runs the suite and writes the report as JSON (to keep as a baseline) and
+//! as a markdown table comparing the engines and, given a baseline, the
+//! change since then.
+//!
+//! Five workloads are built in. Three are small hand-written loops: `alu`
+//! (integer arithmetic and multiplies), `memcpy` (64 KiB copies with
+//! doubleword loads and stores) and `sieve` (a byte-array prime sieve,
+//! branch and byte-access heavy). The other two are bare-metal Rust ports
+//! of Dhrystone 2.1 and CoreMark, checked in as ELF images under
+//! `bench-images/` together with their sources and `build.sh`, which
+//! rebuilds them for `riscv64gc-unknown-none-elf`. They take their iteration
+//! count in `a1` and check their own results before stopping. Other
+//! programs are added with [`Workload::from_image`] from a bare-metal build
+//! (ELF or raw image entered at the DRAM base) that prints through the UART
+//! and stops by writing `0x5555` to the test finisher.
+
+use crate::Trap;
+use crate::bus::{DRAM_BASE, SystemBus};
+use crate::cpu::Cpu;
+use crate::devices::bootrom::{BootConfig, RESET_VECTOR};
+use crate::loader::load_elf_into_dram;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// DRAM given to each run.
+const BENCH_DRAM_SIZE: usize = 64 * 1024 * 1024;
+
+/// Halt code of a workload that ran to completion.
+const PASS: u64 = 0x5555;
+
+/// Steps between checks of the instruction budget and the UART.
+const BATCH_STEPS: u64 = 4096;
+
+/// Iterations of the built-in workloads at scale 1, each about 10-20
+/// million instructions.
+const ALU_ITERATIONS: u64 = 2_000_000;
+const MEMCPY_ITERATIONS: u64 = 200;
+const SIEVE_ITERATIONS: u64 = 100;
+const DHRYSTONE_RUNS: u64 = 30_000;
+const COREMARK_ITERATIONS: u64 = 40;
+
+/// Dhrystone 2.1 and CoreMark, built by `bench-images/build.sh`.
+static DHRYSTONE_ELF: &[u8] = include_bytes!("../bench-images/dhrystone.elf");
+static COREMARK_ELF: &[u8] = include_bytes!("../bench-images/coremark.elf");
+
+/// `for (n = count; n; n--) { a0 += a1; a1 ^= a0; a0 -= (a0 << 3) * (a1 >> 5); a1 += 7; }`
+const ALU_CODE: [u32; 18] = [
+    0x0000_0317, // auipc t1, %pcrel_hi(count)
+    0x0483_3283, // ld t0, %pcrel_lo(count)(t1)
+    0x0000_0513, // li a0, 0
+    0x0010_0593, // li a1, 1
+    0x00b5_0533, // loop: add a0, a0, a1
+    0x00a5_c5b3, // xor a1, a1, a0
+    0x0035_1613, // slli a2, a0, 3
+    0x0055_d693, // srli a3, a1, 5
+    0x02d6_0733, // mul a4, a2, a3
+    0x40e5_0533, // sub a0, a0, a4
+    0x0075_8593, // addi a1, a1, 7
+    0xfff2_8293, // addi t0, t0, -1
+    0xfe02_90e3, // bnez t0, loop
+    0x0010_03b7, // lui t2, 0x100 (test finisher)
+    0x0000_5e37, // lui t3, 5
+    0x555e_0e13, // addi t3, t3, 0x555
+    0x01c3_a023, // sw t3, 0(t2)
+    0x0000_006f, // j .
+];
+
+/// Copy 64 KiB from DRAM + 1 MiB to DRAM + 2 MiB, `count` times.
+const MEMCPY_CODE: [u32; 21] = [
+    0x0000_0317, // auipc t1, %pcrel_hi(count)
+    0x0583_3403, // ld s0, %pcrel_lo(count)(t1)
+    0x0010_03b7, // lui t2, 0x100
+    0x0073_04b3, // add s1, t1, t2
+    0x0074_8933, // add s2, s1, t2
+    0x0004_8513, // outer: mv a0, s1
+    0x0009_0593, // mv a1, s2
+    0x0000_2637, // lui a2, 2 (8192 doublewords)
+    0x0005_3683, // inner: ld a3, 0(a0)
+    0x00d5_b023, // sd a3, 0(a1)
+    0x0085_0513, // addi a0, a0, 8
+    0x0085_8593, // addi a1, a1, 8
+    0xfff6_0613, // addi a2, a2, -1
+    0xfe06_16e3, // bnez a2, inner
+    0xfff4_0413, // addi s0, s0, -1
+    0xfc04_1ce3, // bnez s0, outer
+    0x0010_03b7, // lui t2, 0x100 (test finisher)
+    0x0000_5e37, // lui t3, 5
+    0x555e_0e13, // addi t3, t3, 0x555
+    0x01c3_a023, // sw t3, 0(t2)
+    0x0000_006f, // j .
+];
+
+/// Count the primes below 8192 with a byte sieve at DRAM + 1 MiB, `count`
+/// times.
+const SIEVE_CODE: [u32; 34] = [
+    0x0000_0317, // auipc t1, %pcrel_hi(count)
+    0x0883_3403, // ld s0, %pcrel_lo(count)(t1)
+    0x0010_03b7, // lui t2, 0x100
+    0x0073_04b3, // add s1, t1, t2
+    0x0000_28b7, // lui a7, 2 (8192)
+    0x0004_8513, // outer: mv a0, s1
+    0x0008_8593, // mv a1, a7
+    0x0005_0023, // clear: sb zero, 0(a0)
+    0x0015_0513, // addi a0, a0, 1
+    0xfff5_8593, // addi a1, a1, -1
+    0xfe05_9ae3, // bnez a1, clear
+    0x0020_0613, // li a2, 2
+    0x0000_0813, // li a6, 0
+    0x0316_7c63, // next: bgeu a2, a7, done
+    0x00c4_86b3, // add a3, s1, a2
+    0x0006_c703, // lbu a4, 0(a3)
+    0x0207_1263, // bnez a4, skip
+    0x0018_0813, // addi a6, a6, 1
+    0x00c6_07b3, // add a5, a2, a2
+    0x0117_fc63, // mark: bgeu a5, a7, skip
+    0x00f4_86b3, // add a3, s1, a5
+    0x0010_0713, // li a4, 1
+    0x00e6_8023, // sb a4, 0(a3)
+    0x00c7_87b3, // add a5, a5, a2
+    0xfedf_f06f, // j mark
+    0x0016_0613, // skip: addi a2, a2, 1
+    0xfcdf_f06f, // j next
+    0xfff4_0413, // done: addi s0, s0, -1
+    0xfa04_12e3, // bnez s0, outer
+    0x0010_03b7, // lui t2, 0x100 (test finisher)
+    0x0000_5e37, // lui t3, 5
+    0x555e_0e13, // addi t3, t3, 0x555
+    0x01c3_a023, // sw t3, 0(t2)
+    0x0000_006f, // j .
+];
+
+/// Raw image of `code` followed by its iteration count, which the code
+/// loads from the next 8-byte boundary.
+fn counted_image(code: &[u32], iterations: u64) -> Vec<u8> {
+    let mut image: Vec<u8> = code.iter().flat_map(|insn| insn.to_le_bytes()).collect();
+    if !image.len().is_multiple_of(8) {
+        image.extend_from_slice(&0x0000_0013u32.to_le_bytes()); // nop
+    }
+    image.extend_from_slice(&iterations.to_le_bytes());
+    image
+}
+
+/// How the hart executes guest code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// One instruction at a time.
+    Interpreter,
+    /// Cached superblocks (see [`crate::engine::block`]).
+    Blocks,
+    /// Superblocks with hot blocks compiled to host code (`jit-native`).
+    Jit,
+}
+
+impl Engine {
+    /// Engines this build can run.
+    pub fn available() -> Vec<Engine> {
+        let mut engines = vec![Engine::Interpreter, Engine::Blocks];
+        if cfg!(feature = "jit-native") {
+            engines.push(Engine::Jit);
+        }
+        engines
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Interpreter => "interpreter",
+            Engine::Blocks => "blocks",
+            Engine::Jit => "jit",
+        }
+    }
+
+    /// Set `cpu` up to run with this engine.
+    fn configure(self, cpu: &mut Cpu) -> Result<(), String> {
+        match self {
+            Engine::Interpreter => cpu.use_blocks = false,
+            Engine::Blocks => cpu.use_blocks = true,
+            #[cfg(feature = "jit-native")]
+            Engine::Jit => cpu.enable_jit(Default::default())?,
+            #[cfg(not(feature = "jit-native"))]
+            Engine::Jit => return Err("built without the jit-native feature".to_string()),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" | "interp" => Ok(Engine::Interpreter),
+            "blocks" => Ok(Engine::Blocks),
+            "jit" => Ok(Engine::Jit),
+            _ => Err(format!(
+                "unknown engine '{}' (expected interpreter, blocks or jit)",
+                s
+            )),
+        }
+    }
+}
+
+/// A bare-metal program to time.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    pub name: String,
+    /// ELF, or a raw image entered at the DRAM base.
+    pub image: Vec<u8>,
+    /// Passed to the workload in `a1`.
+    pub boot_arg: u64,
+}
+
+impl Workload {
+    pub fn from_image(name: &str, image: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            image,
+            boot_arg: 0,
+        }
+    }
+
+    /// Integer arithmetic loop, 9 instructions per iteration.
+    pub fn alu(iterations: u64) -> Self {
+        Self::from_image("alu", counted_image(&ALU_CODE, iterations))
+    }
+
+    /// 64 KiB doubleword copy, about 49k instructions per iteration.
+    pub fn memcpy(iterations: u64) -> Self {
+        Self::from_image("memcpy", counted_image(&MEMCPY_CODE, iterations))
+    }
+
+    /// Prime sieve up to 8192, about 110k instructions per iteration.
+    pub fn sieve(iterations: u64) -> Self {
+        Self::from_image("sieve", counted_image(&SIEVE_CODE, iterations))
+    }
+
+    /// Dhrystone 2.1, about 480 instructions per run.
+    pub fn dhrystone(runs: u64) -> Self {
+        Self {
+            boot_arg: runs,
+            ..Self::from_image("dhrystone", DHRYSTONE_ELF.to_vec())
+        }
+    }
+
+    /// CoreMark's performance run, about 370k instructions per iteration.
+    pub fn coremark(iterations: u64) -> Self {
+        Self {
+            boot_arg: iterations,
+            ..Self::from_image("coremark", COREMARK_ELF.to_vec())
+        }
+    }
+}
+
+/// The built-in workloads, with their iteration counts multiplied by
+/// `scale`.
+pub fn builtin(scale: u64) -> Vec<Workload> {
+    vec![
+        Workload::alu(ALU_ITERATIONS * scale),
+        Workload::memcpy(MEMCPY_ITERATIONS * scale),
+        Workload::sieve(SIEVE_ITERATIONS * scale),
+        Workload::dhrystone(DHRYSTONE_RUNS * scale),
+        Workload::coremark(COREMARK_ITERATIONS * scale),
+    ]
+}
+
+/// Timing of one workload under one engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub workload: String,
+    pub engine: Engine,
+    /// Instructions retired.
+    pub instructions: u64,
+    /// Guest cycles, see [`crate::engine::timing`].
+    pub cycles: u64,
+    /// Host time of the fastest run.
+    pub seconds: f64,
+    /// Million instructions per host second.
+    pub mips: f64,
+    /// Whether the workload stopped with the pass code before the budget
+    /// ran out.
+    pub completed: bool,
+    /// What the workload printed.
+    pub output: String,
+}
+
+/// Results of a benchmark run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub results: Vec<BenchResult>,
+}
+
+/// Run options.
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub engines: Vec<Engine>,
+    /// Runs per workload and engine; the fastest is reported.
+    pub repeat: u32,
+    /// Instruction budget per run.
+    pub max_instructions: u64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            engines: Engine::available(),
+            repeat: 3,
+            max_instructions: 10_000_000_000,
+        }
+    }
+}
+
+/// Run one workload once under `engine`.
+pub fn run_once(
+    workload: &Workload,
+    engine: Engine,
+    max_instructions: u64,
+) -> Result<BenchResult, String> {
+    let bus = SystemBus::new(DRAM_BASE, BENCH_DRAM_SIZE);
+    let entry = if workload.image.starts_with(b"\x7FELF") {
+        load_elf_into_dram(&workload.image, &bus)?
+    } else {
+        bus.dram
+            .load(&workload.image, 0)
+            .map_err(|e| format!("failed to load {}: {:?}", workload.name, e))?;
+        DRAM_BASE
+    };
+    let mut boot = BootConfig::new(entry, DRAM_BASE + BENCH_DRAM_SIZE as u64);
+    boot.boot_arg = workload.boot_arg;
+    bus.boot_rom.configure(&boot);
+    let mut cpu = Cpu::new(RESET_VECTOR, 0);
+    engine.configure(&mut cpu)?;
+
+    let mut output = Vec::new();
+    let mut halt = None;
+    let start = Instant::now();
+    while halt.is_none() && cpu.perf_counters().instret < max_instructions {
+        for _ in 0..BATCH_STEPS {
+            match cpu.step(&bus) {
+                Ok(()) => {}
+                Err(Trap::RequestedTrap(code)) => {
+                    halt = Some(code);
+                    break;
+                }
+                Err(Trap::Fatal(msg)) => {
+                    return Err(format!("{} under {}: {}", workload.name, engine, msg));
+                }
+                // Architectural traps are handled by the workload
+                Err(_) => {}
+            }
+        }
+        output.extend(bus.uart.drain_output());
+    }
+    let elapsed = start.elapsed();
+
+    let counters = cpu.perf_counters();
+    Ok(BenchResult {
+        workload: workload.name.clone(),
+        engine,
+        instructions: counters.instret,
+        cycles: counters.cycles,
+        seconds: elapsed.as_secs_f64(),
+        mips: mips(counters.instret, elapsed),
+        completed: halt == Some(PASS),
+        output: String::from_utf8_lossy(&output).into_owned(),
+    })
+}
+
+fn mips(instructions: u64, elapsed: Duration) -> f64 {
+    instructions as f64 / elapsed.as_secs_f64().max(1e-9) / 1e6
+}
+
+/// Run every workload under every configured engine.
+pub fn run(workloads: &[Workload], config: &BenchConfig) -> Result<BenchReport, String> {
+    let mut report = BenchReport::default();
+    for workload in workloads {
+        for &engine in &config.engines {
+            let mut best: Option<BenchResult> = None;
+            for _ in 0..config.repeat.max(1) {
+                let result = run_once(workload, engine, config.max_instructions)?;
+                if best.as_ref().is_none_or(|b| result.seconds < b.seconds) {
+                    best = Some(result);
+                }
+            }
+            report.results.extend(best);
+        }
+    }
+    Ok(report)
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("failed to encode report: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("invalid report: {}", e))
+    }
+
+    /// Result for `workload` under `engine`.
+    pub fn get(&self, workload: &str, engine: Engine) -> Option<&BenchResult> {
+        self.results
+            .iter()
+            .find(|r| r.workload == workload && r.engine == engine)
+    }
+
+    /// Relative MIPS change of each result against the same workload and
+    /// engine in `baseline` (0.1 is 10% faster).
+    pub fn compare<'a>(&'a self, baseline: &BenchReport) -> Vec<(&'a BenchResult, f64)> {
+        self.results
+            .iter()
+            .filter_map(|r| {
+                let base = baseline.get(&r.workload, r.engine)?;
+                Some((r, r.mips / base.mips - 1.0))
+            })
+            .collect()
+    }
+
+    /// Results more than `threshold` slower than `baseline` (0.05 for 5%),
+    /// with their change.
+    pub fn regressions<'a>(
+        &'a self,
+        baseline: &BenchReport,
+        threshold: f64,
+    ) -> Vec<(&'a BenchResult, f64)> {
+        self.compare(baseline)
+            .into_iter()
+            .filter(|(_, change)| *change < -threshold)
+            .collect()
+    }
+
+    /// Markdown table of the results, with each engine's speedup over the
+    /// interpreter and, given a `baseline`, the change since then.
+    pub fn to_markdown(&self, baseline: Option<&BenchReport>) -> String {
+        let mut out = String::new();
+        out.push_str("| Workload | Engine | Instructions | Time (s) | MIPS | Speedup |");
+        if baseline.is_some() {
+            out.push_str(" vs baseline |");
+        }
+        out.push_str("\n|---|---|---:|---:|---:|---:|");
+        if baseline.is_some() {
+            out.push_str("---:|");
+        }
+        out.push('\n');
+
+        for r in &self.results {
+            let speedup = match self.get(&r.workload, Engine::Interpreter) {
+                Some(interp) => format!("{:.2}x", r.mips / interp.mips),
+                None => "-".to_string(),
+            };
+            let _ = write!(
+                out,
+                "| {}{} | {} | {} | {:.3} | {:.1} | {} |",
+                r.workload,
+                if r.completed { "" } else { " (incomplete)" },
+                r.engine,
+                r.instructions,
+                r.seconds,
+                r.mips,
+                speedup
+            );
+            if let Some(baseline) = baseline {
+                match baseline.get(&r.workload, r.engine) {
+                    Some(base) => {
+                        let _ = write!(out, " {:+.1}% |", (r.mips / base.mips - 1.0) * 100.0);
+                    }
+                    None => out.push_str(" - |"),
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_workloads_complete() {
+        let workloads = [Workload::alu(100), Workload::memcpy(2), Workload::sieve(1)];
+        for workload in &workloads {
+            let result = run_once(workload, Engine::Interpreter, 10_000_000).unwrap();
+            assert!(result.completed, "{} did not complete", workload.name);
+        }
+    }
+
+    #[test]
+    fn test_bundled_benchmarks_pass_their_checks() {
+        for workload in [Workload::dhrystone(100), Workload::coremark(1)] {
+            let result = run_once(&workload, Engine::Blocks, 10_000_000).unwrap();
+            assert!(result.completed, "{}: {}", workload.name, result.output);
+        }
+    }
+
+    #[test]
+    fn test_engines_retire_the_same_instructions() {
+        let workload = Workload::alu(1000);
+        let interp = run_once(&workload, Engine::Interpreter, 10_000_000).unwrap();
+        let blocks = run_once(&workload, Engine::Blocks, 10_000_000).unwrap();
+        assert!(interp.completed && blocks.completed);
+        // Only the interpreter counts the finisher store that stops the run
+        assert!(interp.instructions.abs_diff(blocks.instructions) <= 1);
+        // Boot ROM, setup, 9 per iteration and the finisher store
+        assert!(interp.instructions >= 9 * 1000);
+        assert!(interp.instructions < 9 * 1000 + 64);
+    }
+
+    #[test]
+    fn test_budget_stops_endless_workload() {
+        let workload = Workload::alu(u64::MAX);
+        let result = run_once(&workload, Engine::Blocks, 100_000).unwrap();
+        assert!(!result.completed);
+        assert!(result.instructions >= 100_000);
+    }
+
+    #[test]
+    fn test_report_round_trips_and_compares() {
+        let config = BenchConfig {
+            engines: vec![Engine::Interpreter, Engine::Blocks],
+            repeat: 1,
+            max_instructions: 1_000_000,
+        };
+        let report = run(&[Workload::alu(100)], &config).unwrap();
+        assert_eq!(report.results.len(), 2);
+
+        let baseline = BenchReport::from_json(&report.to_json().unwrap()).unwrap();
+        assert_eq!(baseline, report);
+        let mut slower = baseline.clone();
+        for r in &mut slower.results {
+            r.mips /= 2.0;
+        }
+        assert!(slower.regressions(&baseline, 0.1).len() == 2);
+        assert!(report.regressions(&slower, 0.1).is_empty());
+
+        let table = report.to_markdown(Some(&baseline));
+        assert!(table.contains("| alu | blocks |"));
+        assert!(table.contains("+0.0% |"));
+    }
+}
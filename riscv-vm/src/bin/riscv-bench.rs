@@ -0,0 +1,134 @@
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
+
+use riscv_vm::bench::{self, BenchConfig, BenchReport, Engine, Workload};
+
+#[derive(Parser, Debug)]
+#[command(name = "riscv-bench")]
+#[command(about = "Time bare-metal workloads under each execution engine")]
+#[command(version)]
+struct Args {
+    /// Engine to run (repeatable; defaults to every engine this build has)
+    #[arg(short, long)]
+    engine: Vec<Engine>,
+
+    /// Also run a bare-metal image, as NAME=PATH (repeatable; e.g.
+    /// mybench=mybench.elf)
+    #[arg(short, long, value_parser = parse_image)]
+    image: Vec<(String, PathBuf)>,
+
+    /// Skip the built-in workloads
+    #[arg(long)]
+    no_builtin: bool,
+
+    /// Multiply the built-in workloads' iteration counts
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    scale: u64,
+
+    /// Runs per workload and engine; the fastest is reported
+    #[arg(long, default_value_t = 3)]
+    repeat: u32,
+
+    /// Instruction budget per run, in millions
+    #[arg(long, default_value_t = 10_000)]
+    max_minstret: u64,
+
+    /// Write the report as JSON, for use as a later --baseline
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Write the markdown table here instead of stdout
+    #[arg(long)]
+    markdown: Option<PathBuf>,
+
+    /// Compare against a JSON report from an earlier run
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Exit with an error if any result is this many percent slower than
+    /// the baseline
+    #[arg(long, requires = "baseline")]
+    max_regression: Option<f64>,
+}
+
+/// Parse a `NAME=PATH` image
+fn parse_image(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected NAME=PATH, got '{}'", s)),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut workloads = if args.no_builtin {
+        Vec::new()
+    } else {
+        bench::builtin(args.scale)
+    };
+    for (name, path) in &args.image {
+        let image = fs::read(path)
+            .map_err(|e| format!("Failed to read image '{}': {}", path.display(), e))?;
+        workloads.push(Workload::from_image(name, image));
+    }
+    if workloads.is_empty() {
+        return Err("nothing to run: --no-builtin without --image".into());
+    }
+
+    let baseline = match &args.baseline {
+        Some(path) => {
+            let json = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read baseline '{}': {}", path.display(), e))?;
+            Some(BenchReport::from_json(&json)?)
+        }
+        None => None,
+    };
+
+    let mut config = BenchConfig {
+        repeat: args.repeat,
+        max_instructions: args.max_minstret.saturating_mul(1_000_000),
+        ..Default::default()
+    };
+    if !args.engine.is_empty() {
+        config.engines = args.engine;
+    }
+    let report = bench::run(&workloads, &config)?;
+
+    if let Some(path) = &args.json {
+        fs::write(path, report.to_json()?)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    }
+    let table = report.to_markdown(baseline.as_ref());
+    match &args.markdown {
+        Some(path) => fs::write(path, &table)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?,
+        None => print!("{}", table),
+    }
+
+    for r in report.results.iter().filter(|r| !r.completed) {
+        eprintln!(
+            "warning: {} under {} ran out of instruction budget or failed its own checks",
+            r.workload, r.engine
+        );
+    }
+    if let (Some(baseline), Some(percent)) = (&baseline, args.max_regression) {
+        let regressions = report.regressions(baseline, percent / 100.0);
+        for (r, change) in &regressions {
+            eprintln!(
+                "regression: {} under {} is {:.1}% slower",
+                r.workload,
+                r.engine,
+                -change * 100.0
+            );
+        }
+        if !regressions.is_empty() {
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
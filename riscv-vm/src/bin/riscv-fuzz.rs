@@ -0,0 +1,90 @@
+use clap::Parser;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use riscv_vm::fuzz::{self, Program};
+
+#[derive(Parser, Debug)]
+#[command(name = "riscv-fuzz")]
+#[command(about = "Cross-check the execution engines on random or saved instruction sequences")]
+#[command(version)]
+struct Args {
+    /// Fuzzer inputs or corpus directories to check; failing ones are
+    /// minimized and their encodings printed. Without any, random inputs
+    /// are generated.
+    inputs: Vec<PathBuf>,
+
+    /// Random inputs to generate
+    #[arg(long, default_value_t = 10_000)]
+    runs: u64,
+
+    /// Seed of the first random input
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Write each minimized failing input here, for reproducing
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+/// Files of `path`, or `path` itself if it is not a directory
+fn expand(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let entries =
+        fs::read_dir(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        if entry.path().is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("error")).init();
+
+    let mut inputs: Vec<(String, Vec<u8>)> = Vec::new();
+    if args.inputs.is_empty() {
+        for seed in args.seed..args.seed.saturating_add(args.runs) {
+            inputs.push((format!("seed {}", seed), fuzz::generate(seed)));
+        }
+    } else {
+        for path in &args.inputs {
+            for file in expand(path)? {
+                let data = fs::read(&file)
+                    .map_err(|e| format!("Failed to read '{}': {}", file.display(), e))?;
+                inputs.push((file.display().to_string(), data));
+            }
+        }
+    }
+    if let Some(out) = &args.out {
+        fs::create_dir_all(out)
+            .map_err(|e| format!("Failed to create '{}': {}", out.display(), e))?;
+    }
+
+    let mut failures = 0;
+    for (name, data) in &inputs {
+        let Some((program, finding)) = fuzz::minimize(&Program::from_bytes(data)) else {
+            continue;
+        };
+        failures += 1;
+        println!("{}: {}", name, fuzz::report(&program, &finding));
+        if let Some(out) = &args.out {
+            let path = out.join(format!("minimized-{}", failures));
+            fs::write(&path, program.to_bytes())
+                .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        }
+    }
+
+    eprintln!("{} of {} inputs failed", failures, inputs.len());
+    if failures > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -1,10 +1,32 @@
 use crate::Trap;
-use crate::devices::clint::{CLINT_BASE, CLINT_SIZE, Clint};
-use crate::devices::plic::{PLIC_BASE, PLIC_SIZE, Plic, UART_IRQ, VIRTIO0_IRQ};
+use crate::devices::bootrom::{BOOTROM_BASE, BOOTROM_SIZE, BootRom};
+use crate::devices::buildinfo::{BUILDINFO_BASE, BUILDINFO_SIZE, BuildInfo};
+use crate::devices::clint::{
+    CLINT_BASE, CLINT_SIZE, Clint, DEFAULT_CPU_FREQUENCY, MSIP_OFFSET, MTIME_OFFSET,
+    MTIMECMP_OFFSET,
+};
+use crate::devices::framebuffer::{FRAMEBUFFER_BASE, FRAMEBUFFER_SIZE, Framebuffer};
+use crate::devices::input::{INPUT_BASE, INPUT_SIZE, InputQueue};
+use crate::devices::ivshmem::{IVSHMEM_BASE, IVSHMEM_SIZE, IvShmem};
+use crate::devices::mailbox::{MAILBOX_BASE, MAILBOX_SIZE, Mailbox};
+use crate::devices::mmio::MmioDevice;
+use crate::devices::plic::{
+    INPUT_IRQ, IVSHMEM_IRQ, MAILBOX_IRQ, NUM_SOURCES, PLIC_BASE, PLIC_SIZE, Plic, RTC_IRQ,
+    UART_IRQ, VIRTIO0_IRQ,
+};
+use crate::devices::rtc::{MtimeSource, RTC_BASE, RTC_SIZE, Rtc, RtcDevice};
+use crate::devices::scheduler::EventScheduler;
+use crate::devices::semihost::{SEMIHOST_BASE, SEMIHOST_SIZE, Semihost};
 use crate::devices::sysinfo::{SYSINFO_BASE, SYSINFO_SIZE, SysInfo};
+use crate::devices::test_finisher::{TEST_FINISHER_BASE, TEST_FINISHER_SIZE, TestFinisher};
 use crate::devices::uart::{UART_BASE, UART_SIZE, Uart};
-use crate::devices::virtio::VirtioDevice;
+use crate::devices::virtio::device::STATUS_OFFSET;
+use crate::devices::virtio::{HotplugSlot, VirtioDevice, VirtioState};
 use crate::dram::Dram;
+use crate::replay::{Channel, GuestInput, InputLog};
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[cfg(target_arch = "wasm32")]
 use js_sys::SharedArrayBuffer;
@@ -25,14 +47,119 @@ static AMO_LOCK: Mutex<()> = Mutex::new(());
 /// Default DRAM base for the virt platform.
 pub const DRAM_BASE: u64 = 0x8000_0000;
 
-/// Base address of the RISC-V test finisher MMIO region.
-pub const TEST_FINISHER_BASE: u64 = 0x0010_0000;
-pub const TEST_FINISHER_SIZE: u64 = 0x1000;
-
 /// VirtIO MMIO base address (for the first device).
 pub const VIRTIO_BASE: u64 = 0x1000_1000;
 /// Size of each VirtIO MMIO region.
 pub const VIRTIO_STRIDE: u64 = 0x1000;
+/// Number of VirtIO MMIO slots decoded by the bus.
+pub const VIRTIO_SLOTS: u64 = 8;
+
+/// Default DRAM size for full-system VMs.
+pub const DEFAULT_DRAM_SIZE: usize = 512 * 1024 * 1024;
+
+/// Physical memory map of a [`SystemBus`].
+///
+/// The default matches the fixed layout the bundled kernel is linked for.
+/// Other layouts are described to the guest through the device tree the
+/// boot ROM exposes (see [`crate::devices::fdt`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusConfig {
+    pub dram_base: u64,
+    pub dram_size: usize,
+    pub clint_base: u64,
+    pub plic_base: u64,
+    pub uart_base: u64,
+    /// Base of the first VirtIO slot; the others follow every `VIRTIO_STRIDE`.
+    pub virtio_base: u64,
+    pub sysinfo_base: u64,
+    pub test_finisher_base: u64,
+    pub framebuffer_base: u64,
+    pub input_base: u64,
+    pub buildinfo_base: u64,
+    pub semihost_base: u64,
+    pub ivshmem_base: u64,
+    pub mailbox_base: u64,
+    pub rtc_base: u64,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            dram_base: DRAM_BASE,
+            dram_size: DEFAULT_DRAM_SIZE,
+            clint_base: CLINT_BASE,
+            plic_base: PLIC_BASE,
+            uart_base: UART_BASE,
+            virtio_base: VIRTIO_BASE,
+            sysinfo_base: SYSINFO_BASE,
+            test_finisher_base: TEST_FINISHER_BASE,
+            framebuffer_base: FRAMEBUFFER_BASE,
+            input_base: INPUT_BASE,
+            buildinfo_base: BUILDINFO_BASE,
+            semihost_base: SEMIHOST_BASE,
+            ivshmem_base: IVSHMEM_BASE,
+            mailbox_base: MAILBOX_BASE,
+            rtc_base: RTC_BASE,
+        }
+    }
+}
+
+impl BusConfig {
+    /// Default memory map with the given DRAM placement.
+    pub fn with_dram(dram_base: u64, dram_size: usize) -> Self {
+        Self {
+            dram_base,
+            dram_size,
+            ..Self::default()
+        }
+    }
+
+    /// Every decoded region as `(name, base, size)`, boot ROM included.
+    pub fn regions(&self) -> [(&'static str, u64, u64); 15] {
+        [
+            ("bootrom", BOOTROM_BASE, BOOTROM_SIZE),
+            ("test-finisher", self.test_finisher_base, TEST_FINISHER_SIZE),
+            ("rtc", self.rtc_base, RTC_SIZE),
+            ("sysinfo", self.sysinfo_base, SYSINFO_SIZE),
+            ("clint", self.clint_base, CLINT_SIZE),
+            ("plic", self.plic_base, PLIC_SIZE),
+            ("uart", self.uart_base, UART_SIZE),
+            ("virtio", self.virtio_base, VIRTIO_STRIDE * VIRTIO_SLOTS),
+            ("input", self.input_base, INPUT_SIZE),
+            ("buildinfo", self.buildinfo_base, BUILDINFO_SIZE),
+            ("semihost", self.semihost_base, SEMIHOST_SIZE),
+            ("ivshmem", self.ivshmem_base, IVSHMEM_SIZE),
+            ("mailbox", self.mailbox_base, MAILBOX_SIZE),
+            ("framebuffer", self.framebuffer_base, FRAMEBUFFER_SIZE),
+            ("dram", self.dram_base, self.dram_size as u64),
+        ]
+    }
+
+    /// Check that DRAM is non-empty and no two regions overlap.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dram_size == 0 {
+            return Err("DRAM size must be non-zero".to_string());
+        }
+        let regions = self.regions();
+        for (i, &(name, base, size)) in regions.iter().enumerate() {
+            let Some(end) = base.checked_add(size) else {
+                return Err(format!(
+                    "{} region at 0x{:x} wraps the address space",
+                    name, base
+                ));
+            };
+            for &(other, other_base, other_size) in &regions[i + 1..] {
+                if base < other_base + other_size && other_base < end {
+                    return Err(format!(
+                        "{} region at 0x{:x} overlaps {} region at 0x{:x}",
+                        name, base, other, other_base
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 /// System bus trait for memory and MMIO access.
 ///
@@ -52,6 +179,21 @@ pub trait Bus: Send + Sync {
     fn write32(&self, addr: u64, val: u32) -> Result<(), Trap>;
     fn write64(&self, addr: u64, val: u64) -> Result<(), Trap>;
 
+    /// Current CLINT `mtime`, read for the `time` CSR and Sstc.
+    fn read_mtime(&self) -> Result<u64, Trap> {
+        self.read64(CLINT_BASE + MTIME_OFFSET)
+    }
+
+    /// CLINT `mtimecmp` of hart `hart_id`, read for WFI idling.
+    fn read_mtimecmp(&self, hart_id: usize) -> Result<u64, Trap> {
+        self.read64(CLINT_BASE + MTIMECMP_OFFSET + 8 * hart_id as u64)
+    }
+
+    /// Modelled CPU clock in Hz, which converts `mtime` ticks to cycles.
+    fn cpu_frequency(&self) -> u64 {
+        DEFAULT_CPU_FREQUENCY
+    }
+
     /// Generic load helper used by the MMU for page-table walks.
     fn load(&self, addr: u64, size: u64) -> Result<u64, Trap> {
         match size {
@@ -97,6 +239,11 @@ pub trait Bus: Send + Sync {
         0
     }
 
+    /// Account for `cycles` CPU cycles executed by `hart_id` since the last
+    /// call, advancing guest time. Called right before each interrupt poll.
+    /// Default implementation does nothing (time stands still).
+    fn advance_cycles(&self, _hart_id: usize, _cycles: u64) {}
+
     // ========== Atomic Operations for SMP ==========
     //
     // These are used by AMO instructions. Default implementations use
@@ -281,14 +428,60 @@ pub trait Bus: Send + Sync {
     }
 }
 
+/// A device added with [`SystemBus::register_device`].
+struct MmioMapping {
+    base: u64,
+    size: u64,
+    irq: Option<u32>,
+    device: Box<dyn MmioDevice>,
+    /// Scheduler cycle the device was last ticked up to
+    last_tick: AtomicU64,
+}
+
+impl MmioMapping {
+    /// Tick the device up to `now`.
+    fn catch_up(&self, now: u64) {
+        let last = self.last_tick.swap(now, Ordering::Relaxed);
+        if now > last {
+            self.device.tick(now - last);
+        }
+    }
+}
+
 // A simple system bus that just wraps DRAM for now (Phase 1)
 pub struct SystemBus {
+    /// Memory map the bus decodes
+    config: BusConfig,
     pub dram: Dram,
-    pub clint: Clint,
+    pub clint: Arc<Clint>,
     pub plic: Plic,
     pub uart: Uart,
     pub sysinfo: SysInfo,
+    /// Powers off or resets the machine for the guest
+    pub test_finisher: TestFinisher,
+    /// Linear framebuffer the host blits to a display
+    pub framebuffer: Framebuffer,
+    /// Keyboard/mouse events queued by the host
+    pub input: InputQueue,
+    /// Read-only emulator identification page
+    pub buildinfo: BuildInfo,
+    /// Host file/console/exit calls for bare-metal guests (off by default)
+    pub semihost: Semihost,
+    /// Memory and doorbells shared with other VMs (detached by default)
+    pub ivshmem: IvShmem,
+    /// Byte messages to and from the embedding application
+    pub mailbox: Arc<Mailbox>,
+    /// Goldfish real-time clock with the host's wall-clock time
+    pub rtc: Arc<Rtc>,
+    /// Reset-vector ROM holding the first-stage loader and boot mailbox
+    pub boot_rom: Arc<BootRom>,
     pub virtio_devices: Vec<Box<dyn VirtioDevice>>,
+    /// Built-in devices behind [`MmioDevice`], then embedder peripherals
+    mmio_devices: Vec<MmioMapping>,
+    /// Decides when interrupt polls refresh the device lines
+    pub events: EventScheduler,
+    /// Input log of a recorded or replayed run
+    pub replay: Option<Arc<InputLog>>,
     /// Shared CLINT for WASM workers (routes CLINT accesses to SharedArrayBuffer)
     #[cfg(target_arch = "wasm32")]
     shared_clint: Option<crate::shared_mem::wasm::SharedClint>,
@@ -302,20 +495,41 @@ pub struct SystemBus {
 
 impl SystemBus {
     pub fn new(dram_base: u64, dram_size: usize) -> Self {
-        Self {
-            dram: Dram::new(dram_base, dram_size),
-            clint: Clint::new(),
+        Self::with_config(BusConfig::with_dram(dram_base, dram_size))
+    }
+
+    /// Create a bus with a custom memory map.
+    pub fn with_config(config: BusConfig) -> Self {
+        let mut bus = Self {
+            config,
+            dram: Dram::new(config.dram_base, config.dram_size),
+            clint: Arc::new(Clint::new()),
             plic: Plic::new(),
             uart: Uart::new(),
             sysinfo: SysInfo::new(),
+            test_finisher: TestFinisher::new(),
+            framebuffer: Framebuffer::new(),
+            input: InputQueue::new(),
+            buildinfo: BuildInfo::new(),
+            semihost: Semihost::new(),
+            ivshmem: IvShmem::new(),
+            mailbox: Arc::new(Mailbox::new()),
+            rtc: Arc::new(Rtc::new()),
+            boot_rom: Arc::new(BootRom::new()),
             virtio_devices: Vec::new(),
+            mmio_devices: Vec::new(),
+            events: EventScheduler::new(),
+            replay: None,
             #[cfg(target_arch = "wasm32")]
             shared_clint: None,
             #[cfg(target_arch = "wasm32")]
             shared_uart_output: None,
             #[cfg(target_arch = "wasm32")]
             shared_uart_input: None,
-        }
+        };
+        let clint = bus.clint.clone();
+        bus.map_builtin_devices(Box::new(move || clint.mtime()));
+        bus
     }
 
     /// Create a SystemBus from an existing SharedArrayBuffer for SMP mode.
@@ -343,7 +557,7 @@ impl SystemBus {
         // Use the shared CLINT's hart count for local CLINT initialization
         // The local CLINT is a fallback; shared_clint is used for actual MMIO
         let num_harts = shared_clint.num_harts();
-        let clint = Clint::with_harts(num_harts);
+        let clint = Arc::new(Clint::with_harts(num_harts));
 
         // Create shared UART output for workers to send output to main thread
         let shared_uart_output = crate::shared_mem::wasm::SharedUartOutput::new(&buffer);
@@ -355,23 +569,47 @@ impl SystemBus {
             None
         };
 
-        Self {
-            dram: Dram::from_shared(DRAM_BASE, buffer, dram_offset),
+        // The RTC reads the time from the shared CLINT too
+        let mtime = crate::shared_mem::wasm::SharedClint::new(&buffer);
+
+        let dram = Dram::from_shared(DRAM_BASE, buffer, dram_offset);
+        let mut bus = Self {
+            config: BusConfig::with_dram(DRAM_BASE, dram.size()),
+            dram,
             clint,
             plic: Plic::new(),
             uart: Uart::new(),
             sysinfo: SysInfo::new(),
+            test_finisher: TestFinisher::new(),
+            framebuffer: Framebuffer::new(),
+            input: InputQueue::new(),
+            buildinfo: BuildInfo::new(),
+            semihost: Semihost::new(),
+            ivshmem: IvShmem::new(),
+            mailbox: Arc::new(Mailbox::new()),
+            rtc: Arc::new(Rtc::new()),
+            boot_rom: Arc::new(BootRom::new()),
             virtio_devices: Vec::new(),
+            mmio_devices: Vec::new(),
+            events: EventScheduler::new(),
+            replay: None,
             shared_clint: Some(shared_clint),
             shared_uart_output: Some(shared_uart_output),
             shared_uart_input,
-        }
+        };
+        bus.map_builtin_devices(Box::new(move || mtime.mtime()));
+        bus
     }
 
     pub fn dram_base(&self) -> u64 {
         self.dram.base
     }
 
+    /// The memory map this bus decodes.
+    pub fn config(&self) -> &BusConfig {
+        &self.config
+    }
+
     pub fn dram_size(&self) -> usize {
         self.dram.size()
     }
@@ -380,6 +618,7 @@ impl SystemBus {
     /// This writes the hart count to a CLINT register so the kernel can read it.
     pub fn set_num_harts(&self, num_harts: usize) {
         self.clint.set_num_harts(num_harts);
+        self.buildinfo.set_harts(num_harts as u32);
     }
 
     /// Check interrupts for hart 0 (backward compatibility).
@@ -387,25 +626,43 @@ impl SystemBus {
         self.check_interrupts_for_hart(0)
     }
 
-    /// Check interrupts for a specific hart.
+    /// Check interrupts for a specific hart, refreshing every device's
+    /// interrupt line first.
     ///
     /// Each hart has its own:
     /// - MSIP (software interrupt from CLINT)
     /// - MTIP (timer interrupt from CLINT)
     /// - SEIP/MEIP (external interrupt from PLIC)
     ///
-    /// Thread-safe: each device has internal locking.
-    /// Optimized to minimize lock acquisitions.
-    #[cfg(not(target_arch = "wasm32"))]
+    /// The CPU polls through [`Bus::poll_interrupts_for_hart`] instead,
+    /// which leaves the device lines alone until something may have
+    /// changed them (see [`crate::devices::scheduler`]).
     pub fn check_interrupts_for_hart(&self, hart_id: usize) -> u64 {
-        // Advance CLINT timer - only from hart 0 to avoid Nx speedup with N harts
-        if hart_id == 0 {
-            self.clint.tick();
+        self.service_devices(hart_id);
+        self.pending_interrupts(hart_id)
+    }
+
+    /// Refresh the PLIC lines of the devices that drive one, catching
+    /// registered devices up to the current cycle and collecting their next
+    /// deadlines.
+    ///
+    /// Thread-safe: each device has internal locking.
+    fn service_devices(&self, hart_id: usize) {
+        // Hart 0 handles devices; workers don't have virtio_devices
+        #[cfg(target_arch = "wasm32")]
+        if hart_id != 0 {
+            return;
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = hart_id;
 
-        // Update PLIC with UART interrupt status
+        // Update PLIC with UART and input queue interrupt status
         let uart_irq = self.uart.is_interrupting();
         self.plic.set_source_level(UART_IRQ, uart_irq);
+        self.plic
+            .set_source_level(INPUT_IRQ, self.input.is_interrupting());
+        self.plic
+            .set_source_level(IVSHMEM_IRQ, self.ivshmem.is_interrupting());
 
         // Update PLIC with VirtIO interrupts
         // Device 0 -> IRQ 1 (VIRTIO0_IRQ)
@@ -418,6 +675,22 @@ impl SystemBus {
             }
         }
 
+        let now = self.events.now();
+        for mapping in &self.mmio_devices {
+            mapping.catch_up(now);
+            if let Some(cycle) = mapping.device.next_event_cycle(now) {
+                self.events.schedule(cycle);
+            }
+            if let Some(irq) = mapping.irq {
+                self.plic
+                    .set_source_level(irq, mapping.device.is_interrupting());
+            }
+        }
+    }
+
+    /// MIP bits for a hart from the CLINT and the PLIC, as they stand.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn pending_interrupts(&self, hart_id: usize) -> u64 {
         // Calculate MIP bits for this hart
         let mut mip: u64 = 0;
 
@@ -455,12 +728,12 @@ impl SystemBus {
         mip
     }
 
-    /// Check interrupts for a specific hart (WASM version).
+    /// MIP bits for a hart (WASM version).
     ///
     /// For WASM with shared memory, uses the shared CLINT to correctly
     /// receive IPIs between harts.
     #[cfg(target_arch = "wasm32")]
-    pub fn check_interrupts_for_hart(&self, hart_id: usize) -> u64 {
+    fn pending_interrupts(&self, hart_id: usize) -> u64 {
         // Calculate MIP bits for this hart
         let mut mip: u64 = 0;
 
@@ -483,26 +756,6 @@ impl SystemBus {
             mip |= 1 << 7;
         }
 
-        // Hart 0 handles devices - advance timer and update PLIC
-        // Workers (hart 1+) don't have virtio_devices, so PLIC checks are safe but no-op
-        if hart_id == 0 {
-            // Advance local CLINT timer from hart 0 only
-            // Note: Shared CLINT timer is ticked separately in WasmVm::step()
-            self.clint.tick();
-
-            // Update PLIC with UART interrupt status
-            let uart_irq = self.uart.is_interrupting();
-            self.plic.set_source_level(UART_IRQ, uart_irq);
-
-            // Update PLIC with VirtIO interrupts
-            for (i, dev) in self.virtio_devices.iter().enumerate() {
-                let irq = VIRTIO0_IRQ + i as u32;
-                if irq < 32 {
-                    self.plic.set_source_level(irq, dev.is_interrupting());
-                }
-            }
-        }
-
         // SEIP (Supervisor External Interrupt) - Bit 9
         if self
             .plic
@@ -522,9 +775,185 @@ impl SystemBus {
         mip
     }
 
+    /// Make the next interrupt poll service devices after an MMIO access
+    /// that may have changed an interrupt line. Boot ROM fetches and
+    /// framebuffer pixels are too frequent to count, and never do.
+    #[inline]
+    fn note_mmio(&self, addr: u64) {
+        let passive = (BOOTROM_BASE..BOOTROM_BASE + BOOTROM_SIZE).contains(&addr)
+            || (self.config.framebuffer_base..self.config.framebuffer_base + FRAMEBUFFER_SIZE)
+                .contains(&addr);
+        if !passive {
+            self.events.notify();
+        }
+    }
+
+    /// Whether PLIC source `irq` is driven by a built-in device: the UART,
+    /// the input queue, the shared memory device, the mailbox, the RTC or
+    /// one of the VirtIO slots. The bus refreshes these lines whenever it services
+    /// devices, so they can't be injected.
+    pub fn is_device_irq(irq: u32) -> bool {
+        irq == UART_IRQ
+            || irq == INPUT_IRQ
+            || irq == IVSHMEM_IRQ
+            || irq == MAILBOX_IRQ
+            || irq == RTC_IRQ
+            || (VIRTIO0_IRQ..VIRTIO0_IRQ + VIRTIO_SLOTS as u32).contains(&irq)
+    }
+
+    /// Raise external interrupt line `irq` on behalf of the embedder.
+    ///
+    /// The line is level-triggered: it stays pending until [`Self::clear_irq`],
+    /// and reaches a hart through the PLIC's usual priority, enable,
+    /// threshold and claim/complete handling.
+    pub fn raise_irq(&self, irq: u32) -> Result<(), String> {
+        self.set_external_irq(irq, true)
+    }
+
+    /// Lower an external interrupt line raised with [`Self::raise_irq`].
+    pub fn clear_irq(&self, irq: u32) -> Result<(), String> {
+        self.set_external_irq(irq, false)
+    }
+
+    fn set_external_irq(&self, irq: u32, level: bool) -> Result<(), String> {
+        if irq == 0 || irq as usize >= NUM_SOURCES {
+            return Err(format!(
+                "IRQ {} is out of range (1-{})",
+                irq,
+                NUM_SOURCES - 1
+            ));
+        }
+        if Self::is_device_irq(irq) {
+            return Err(format!("IRQ {} is used by a built-in device", irq));
+        }
+        if self.mmio_devices.iter().any(|m| m.irq == Some(irq)) {
+            return Err(format!("IRQ {} is used by a registered device", irq));
+        }
+        // Recorded runs take the change at hart 0's next poll point
+        if let Some(log) = &self.replay {
+            return log.defer(GuestInput::Irq { irq, raised: level });
+        }
+        self.plic.set_source_level(irq, level);
+        Ok(())
+    }
+
+    /// Move the input log to hart 0's poll point at `cycle` and deliver the
+    /// interrupt changes due there.
+    pub fn sync_replay(&self, cycle: u64) {
+        let Some(log) = &self.replay else {
+            return;
+        };
+        log.set_clock(cycle);
+        for input in log.take_deferred() {
+            if let GuestInput::Irq { irq, raised } = input {
+                self.plic.set_source_level(irq, raised);
+            }
+            log.record(input);
+        }
+        while let Some(GuestInput::Irq { irq, raised }) = log.next_due(Channel::Irq) {
+            self.plic.set_source_level(irq, raised);
+        }
+    }
+
+    /// Map `device` at `range`, see [`crate::devices::mmio`].
+    ///
+    /// The range may not overlap a built-in region or another registered
+    /// device, and the device's IRQ, if it has one, must be a PLIC source no
+    /// other device drives. Embedders that inject the IRQ themselves with
+    /// [`Self::raise_irq`] can't use it afterwards.
+    pub fn register_device(
+        &mut self,
+        range: Range<u64>,
+        device: Box<dyn MmioDevice>,
+    ) -> Result<(), String> {
+        if range.is_empty() {
+            return Err(format!(
+                "device range 0x{:x}..0x{:x} is empty",
+                range.start, range.end
+            ));
+        }
+        let overlaps =
+            |base: u64, size: u64| range.start < base.saturating_add(size) && base < range.end;
+        if let Some((name, base, _)) = self
+            .config
+            .regions()
+            .into_iter()
+            .find(|&(_, base, size)| overlaps(base, size))
+        {
+            return Err(format!(
+                "device range 0x{:x}..0x{:x} overlaps {} region at 0x{:x}",
+                range.start, range.end, name, base
+            ));
+        }
+        if let Some(other) = self.mmio_devices.iter().find(|m| overlaps(m.base, m.size)) {
+            return Err(format!(
+                "device range 0x{:x}..0x{:x} overlaps the device at 0x{:x}",
+                range.start, range.end, other.base
+            ));
+        }
+        let irq = device.irq();
+        if let Some(irq) = irq {
+            if irq == 0 || irq as usize >= NUM_SOURCES {
+                return Err(format!(
+                    "IRQ {} is out of range (1-{})",
+                    irq,
+                    NUM_SOURCES - 1
+                ));
+            }
+            if Self::is_device_irq(irq) {
+                return Err(format!("IRQ {} is used by a built-in device", irq));
+            }
+            if self.mmio_devices.iter().any(|m| m.irq == Some(irq)) {
+                return Err(format!("IRQ {} is used by a registered device", irq));
+            }
+        }
+        self.mmio_devices.push(MmioMapping {
+            base: range.start,
+            size: range.end - range.start,
+            irq,
+            device,
+            last_tick: AtomicU64::new(self.events.now()),
+        });
+        Ok(())
+    }
+
+    /// Map the built-in devices that implement [`MmioDevice`] at their
+    /// regions of the memory map. The RTC reads the CLINT's time from
+    /// `mtime`.
+    fn map_builtin_devices(&mut self, mtime: MtimeSource) {
+        self.map_device(BOOTROM_BASE, BOOTROM_SIZE, Box::new(self.boot_rom.clone()));
+        let mailbox = Box::new(self.mailbox.clone());
+        self.map_device(self.config.mailbox_base, MAILBOX_SIZE, mailbox);
+        let rtc = Box::new(RtcDevice::new(self.rtc.clone(), mtime));
+        self.map_device(self.config.rtc_base, RTC_SIZE, rtc);
+    }
+
+    /// Map `device` at `base` without the checks of
+    /// [`Self::register_device`], for the bus's own devices.
+    fn map_device(&mut self, base: u64, size: u64, device: Box<dyn MmioDevice>) {
+        self.mmio_devices.push(MmioMapping {
+            base,
+            size,
+            irq: device.irq(),
+            device,
+            last_tick: AtomicU64::new(self.events.now()),
+        });
+    }
+
+    /// The registered device mapped at `addr`, ticked up to now, and the
+    /// offset into it.
+    fn get_mmio_device(&self, addr: u64) -> Option<(&dyn MmioDevice, u64)> {
+        let mapping = self
+            .mmio_devices
+            .iter()
+            .find(|m| addr >= m.base && addr - m.base < m.size)?;
+        mapping.catch_up(self.events.now());
+        Some((mapping.device.as_ref(), addr - mapping.base))
+    }
+
     fn get_virtio_device(&self, addr: u64) -> Option<(usize, u64)> {
-        if addr >= VIRTIO_BASE {
-            let offset = addr - VIRTIO_BASE;
+        if addr >= self.config.virtio_base {
+            let offset = addr - self.config.virtio_base;
             let idx = (offset / VIRTIO_STRIDE) as usize;
             if idx < self.virtio_devices.len() {
                 return Some((idx, offset % VIRTIO_STRIDE));
@@ -536,13 +965,23 @@ impl SystemBus {
     /// Check if an address is in the VirtIO MMIO region (even if no device present).
     /// Returns the offset within the device region if in range.
     fn is_virtio_region(&self, addr: u64) -> Option<u64> {
-        if addr >= VIRTIO_BASE && addr < VIRTIO_BASE + VIRTIO_STRIDE * 8 {
-            Some((addr - VIRTIO_BASE) % VIRTIO_STRIDE)
+        if addr >= self.config.virtio_base
+            && addr < self.config.virtio_base + VIRTIO_STRIDE * VIRTIO_SLOTS
+        {
+            Some((addr - self.config.virtio_base) % VIRTIO_STRIDE)
         } else {
             None
         }
     }
 
+    /// Make devices that complete requests off the CPU loop finish them by
+    /// the next poll, so completions don't depend on host timing.
+    pub fn set_synchronous_io(&self, synchronous: bool) {
+        for device in &self.virtio_devices {
+            device.set_synchronous(synchronous);
+        }
+    }
+
     /// Poll all VirtIO devices for pending work (e.g., incoming network packets).
     /// Should be called periodically from the main emulation loop.
     pub fn poll_virtio(&self) {
@@ -551,6 +990,105 @@ impl SystemBus {
                 log::warn!("[Bus] VirtIO poll error: {:?}", e);
             }
         }
+        // Completions and host input arrive off the CPU loop
+        self.events.notify();
+    }
+
+    /// Plug `device` into VirtIO slot `slot` while the machine runs. The
+    /// slot must hold a [`HotplugSlot`] with nothing in it; see
+    /// [`crate::devices::virtio::hotplug`].
+    pub fn plug_virtio(&self, slot: usize, device: Box<dyn VirtioDevice>) -> Result<(), String> {
+        self.hotplug_slot(slot)?
+            .plug(device)
+            .map_err(|_| format!("VirtIO slot {} is occupied", slot))?;
+        self.events.notify();
+        Ok(())
+    }
+
+    /// Take the device out of hot-pluggable VirtIO slot `slot` while the
+    /// machine runs. It is reset first, so it no longer touches guest
+    /// memory.
+    pub fn unplug_virtio(&self, slot: usize) -> Result<Box<dyn VirtioDevice>, String> {
+        let device = self
+            .hotplug_slot(slot)?
+            .unplug()
+            .ok_or_else(|| format!("VirtIO slot {} is empty", slot))?;
+        if let Err(e) = device.write(STATUS_OFFSET, 0, &self.dram) {
+            log::warn!("[Bus] VirtIO reset error: {:?}", e);
+        }
+        self.events.notify();
+        Ok(device)
+    }
+
+    /// State of every VirtIO slot, in order, for migrating the machine.
+    /// Outstanding disk requests are completed first, and devices finish
+    /// their requests synchronously from then on. Fails if a device cannot
+    /// be migrated.
+    pub fn virtio_states(&self) -> Result<Vec<VirtioState>, String> {
+        self.set_synchronous_io(true);
+        self.poll_virtio();
+        self.virtio_devices
+            .iter()
+            .enumerate()
+            .map(|(slot, device)| {
+                device.save_state().ok_or_else(|| {
+                    format!(
+                        "VirtIO slot {} (device {}) cannot be migrated",
+                        slot,
+                        device.device_id()
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Take over VirtIO state saved by [`virtio_states`](Self::virtio_states)
+    /// on a machine with the same devices in the same slots.
+    pub fn load_virtio_states(&self, states: &[VirtioState]) -> Result<(), String> {
+        if states.len() != self.virtio_devices.len() {
+            return Err(format!(
+                "state for {} VirtIO slots, but this machine has {}",
+                states.len(),
+                self.virtio_devices.len()
+            ));
+        }
+        for (slot, (device, state)) in self.virtio_devices.iter().zip(states).enumerate() {
+            device
+                .load_state(state)
+                .map_err(|e| format!("VirtIO slot {}: {}", slot, e))?;
+        }
+        self.events.notify();
+        Ok(())
+    }
+
+    fn hotplug_slot(&self, slot: usize) -> Result<&HotplugSlot, String> {
+        self.virtio_devices
+            .get(slot)
+            .and_then(|device| device.as_hotplug())
+            .ok_or_else(|| format!("VirtIO slot {} is not hot-pluggable", slot))
+    }
+
+    /// Return the devices to their power-on state for a machine reset, with
+    /// every hart stopped. DRAM, `mtime` and the host side of each device
+    /// (disk images, shares, network links, console buffers) are kept.
+    /// VirtIO devices are reset as their driver would, by writing 0 to the
+    /// status register, so they stop using guest memory.
+    pub fn reset_devices(&self) {
+        for hart in 0..self.clint.num_harts() as u64 {
+            self.clint_store(MSIP_OFFSET + 4 * hart, 4, 0);
+            self.clint_store(MTIMECMP_OFFSET + 8 * hart, 8, u64::MAX);
+        }
+        self.plic.reset();
+        self.uart.reset();
+        for device in &self.virtio_devices {
+            if let Err(e) = device.write(STATUS_OFFSET, 0, &self.dram) {
+                log::warn!("[Bus] VirtIO reset error: {:?}", e);
+            }
+        }
+        for mapping in &self.mmio_devices {
+            mapping.device.reset();
+        }
+        self.events.notify();
     }
 
     /// Load from CLINT, routing through shared CLINT when available (WASM workers).
@@ -593,26 +1131,64 @@ impl SystemBus {
 
     #[cold]
     fn read8_slow(&self, addr: u64) -> Result<u8, Trap> {
-        // Test finisher region: reads are harmless and return zero.
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Ok(0);
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return Ok(self.test_finisher.load(offset, 1) as u8);
         }
 
         // SysInfo device
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             let val = self.sysinfo.load(offset, 1);
             return Ok(val as u8);
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            let val = self
+                .framebuffer
+                .load(offset, 1)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u8);
+        }
+
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            let offset = addr - self.config.input_base;
+            let val = self.input.load(offset, 1);
+            return Ok(val as u8);
+        }
+
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            let offset = addr - self.config.buildinfo_base;
+            let val = self.buildinfo.load(offset, 1);
+            return Ok(val as u8);
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            let val = self.ivshmem.load(offset, 1);
+            return Ok(val as u8);
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            let val = self.semihost.load(offset, 1);
+            return Ok(val as u8);
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             let val = self.clint_load(offset, 1);
             return Ok(val as u8);
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             let val = self
                 .plic
                 .load(offset, 1)
@@ -620,8 +1196,8 @@ impl SystemBus {
             return Ok(val as u8);
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             // For workers with shared UART input, route reads to shared buffer
             #[cfg(target_arch = "wasm32")]
             if let Some(ref shared_uart) = self.shared_uart_input {
@@ -667,29 +1243,75 @@ impl SystemBus {
             return Ok(0);
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            let val = device
+                .load(offset, 1)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u8);
+        }
+
         Err(Trap::LoadAccessFault(addr))
     }
 
     #[cold]
     fn read16_slow(&self, addr: u64) -> Result<u16, Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Ok(0);
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return Ok(self.test_finisher.load(offset, 2) as u16);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             let val = self.sysinfo.load(offset, 2);
             return Ok(val as u16);
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            let val = self
+                .framebuffer
+                .load(offset, 2)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u16);
+        }
+
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            let offset = addr - self.config.input_base;
+            let val = self.input.load(offset, 2);
+            return Ok(val as u16);
+        }
+
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            let offset = addr - self.config.buildinfo_base;
+            let val = self.buildinfo.load(offset, 2);
+            return Ok(val as u16);
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            let val = self.ivshmem.load(offset, 2);
+            return Ok(val as u16);
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            let val = self.semihost.load(offset, 2);
+            return Ok(val as u16);
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             let val = self.clint_load(offset, 2);
             return Ok(val as u16);
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             let val = self
                 .plic
                 .load(offset, 2)
@@ -697,8 +1319,8 @@ impl SystemBus {
             return Ok(val as u16);
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             let val = self
                 .uart
                 .load(offset, 2)
@@ -720,29 +1342,75 @@ impl SystemBus {
             return Ok(0);
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            let val = device
+                .load(offset, 2)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u16);
+        }
+
         Err(Trap::LoadAccessFault(addr))
     }
 
     #[cold]
     fn read32_slow(&self, addr: u64) -> Result<u32, Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Ok(0);
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return Ok(self.test_finisher.load(offset, 4) as u32);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             let val = self.sysinfo.load(offset, 4);
             return Ok(val as u32);
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            let val = self
+                .framebuffer
+                .load(offset, 4)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u32);
+        }
+
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            let offset = addr - self.config.input_base;
+            let val = self.input.load(offset, 4);
+            return Ok(val as u32);
+        }
+
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            let offset = addr - self.config.buildinfo_base;
+            let val = self.buildinfo.load(offset, 4);
+            return Ok(val as u32);
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            let val = self.ivshmem.load(offset, 4);
+            return Ok(val as u32);
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            let val = self.semihost.load(offset, 4);
+            return Ok(val as u32);
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             let val = self.clint_load(offset, 4);
             return Ok(val as u32);
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             let val = self
                 .plic
                 .load(offset, 4)
@@ -750,8 +1418,8 @@ impl SystemBus {
             return Ok(val as u32);
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             let val = self
                 .uart
                 .load(offset, 4)
@@ -771,29 +1439,75 @@ impl SystemBus {
             return Ok(0);
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            let val = device
+                .load(offset, 4)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val as u32);
+        }
+
         Err(Trap::LoadAccessFault(addr))
     }
 
     #[cold]
     fn read64_slow(&self, addr: u64) -> Result<u64, Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Ok(0);
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return Ok(self.test_finisher.load(offset, 8));
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             let val = self.sysinfo.load(offset, 8);
             return Ok(val);
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            let val = self
+                .framebuffer
+                .load(offset, 8)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val);
+        }
+
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            let offset = addr - self.config.input_base;
+            let val = self.input.load(offset, 8);
+            return Ok(val);
+        }
+
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            let offset = addr - self.config.buildinfo_base;
+            let val = self.buildinfo.load(offset, 8);
+            return Ok(val);
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            let val = self.ivshmem.load(offset, 8);
+            return Ok(val);
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            let val = self.semihost.load(offset, 8);
+            return Ok(val);
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             let val = self.clint_load(offset, 8);
             return Ok(val);
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             let val = self
                 .plic
                 .load(offset, 8)
@@ -801,8 +1515,8 @@ impl SystemBus {
             return Ok(val);
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             let val = self
                 .uart
                 .load(offset, 8)
@@ -825,38 +1539,85 @@ impl SystemBus {
             return Ok(0);
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            let val = device
+                .load(offset, 8)
+                .map_err(|_| Trap::LoadAccessFault(addr))?;
+            return Ok(val);
+        }
+
         Err(Trap::LoadAccessFault(addr))
     }
 
     #[cold]
     fn write8_slow(&self, addr: u64, val: u8) -> Result<(), Trap> {
-        // Any write in the test finisher region signals a requested trap to the host.
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Err(Trap::RequestedTrap(val as u64));
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return self.test_finisher.store(offset, 1, val as u64);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             self.sysinfo.store(offset, 1, val as u64);
             return Ok(());
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            self.framebuffer
+                .store(offset, 1, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
+        // Input queue registers are read-only
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            return Ok(());
+        }
+
+        // Build information is read-only
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            return Ok(());
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            self.ivshmem.store(offset, 1, val as u64);
+            return Ok(());
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            return match self
+                .semihost
+                .store(offset, 1, val as u64, &self.dram, &self.uart)
+            {
+                Some(code) => Err(Trap::RequestedTrap(code)),
+                None => Ok(()),
+            };
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             self.clint_store(offset, 1, val as u64);
             return Ok(());
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             self.plic
                 .store(offset, 1, val as u64)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
             return Ok(());
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             // For workers with shared UART output, route THR writes to shared buffer
             #[cfg(target_arch = "wasm32")]
             if offset == 0 {
@@ -880,37 +1641,85 @@ impl SystemBus {
             return Ok(());
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            device
+                .store(offset, 1, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
         Err(Trap::StoreAccessFault(addr))
     }
 
     #[cold]
     fn write16_slow(&self, addr: u64, val: u16) -> Result<(), Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Err(Trap::RequestedTrap(val as u64));
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return self.test_finisher.store(offset, 2, val as u64);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             self.sysinfo.store(offset, 2, val as u64);
             return Ok(());
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            self.framebuffer
+                .store(offset, 2, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
+        // Input queue registers are read-only
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            return Ok(());
+        }
+
+        // Build information is read-only
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            return Ok(());
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            self.ivshmem.store(offset, 2, val as u64);
+            return Ok(());
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            return match self
+                .semihost
+                .store(offset, 2, val as u64, &self.dram, &self.uart)
+            {
+                Some(code) => Err(Trap::RequestedTrap(code)),
+                None => Ok(()),
+            };
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             self.clint_store(offset, 2, val as u64);
             return Ok(());
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             self.plic
                 .store(offset, 2, val as u64)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
             return Ok(());
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             self.uart
                 .store(offset, 2, val as u64)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
@@ -921,37 +1730,85 @@ impl SystemBus {
             return Ok(());
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            device
+                .store(offset, 2, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
         Err(Trap::StoreAccessFault(addr))
     }
 
     #[cold]
     fn write32_slow(&self, addr: u64, val: u32) -> Result<(), Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Err(Trap::RequestedTrap(val as u64));
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return self.test_finisher.store(offset, 4, val as u64);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             self.sysinfo.store(offset, 4, val as u64);
             return Ok(());
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            self.framebuffer
+                .store(offset, 4, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
+        // Input queue registers are read-only
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            return Ok(());
+        }
+
+        // Build information is read-only
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            return Ok(());
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            self.ivshmem.store(offset, 4, val as u64);
+            return Ok(());
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            return match self
+                .semihost
+                .store(offset, 4, val as u64, &self.dram, &self.uart)
+            {
+                Some(code) => Err(Trap::RequestedTrap(code)),
+                None => Ok(()),
+            };
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             self.clint_store(offset, 4, val as u64);
             return Ok(());
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             self.plic
                 .store(offset, 4, val as u64)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
             return Ok(());
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             self.uart
                 .store(offset, 4, val as u64)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
@@ -970,37 +1827,82 @@ impl SystemBus {
             return Ok(());
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            device
+                .store(offset, 4, val as u64)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
         Err(Trap::StoreAccessFault(addr))
     }
 
     #[cold]
     fn write64_slow(&self, addr: u64, val: u64) -> Result<(), Trap> {
-        if addr >= TEST_FINISHER_BASE && addr < TEST_FINISHER_BASE + TEST_FINISHER_SIZE {
-            return Err(Trap::RequestedTrap(val));
+        if addr >= self.config.test_finisher_base
+            && addr < self.config.test_finisher_base + TEST_FINISHER_SIZE
+        {
+            let offset = addr - self.config.test_finisher_base;
+            return self.test_finisher.store(offset, 8, val);
         }
 
-        if addr >= SYSINFO_BASE && addr < SYSINFO_BASE + SYSINFO_SIZE {
-            let offset = addr - SYSINFO_BASE;
+        if addr >= self.config.sysinfo_base && addr < self.config.sysinfo_base + SYSINFO_SIZE {
+            let offset = addr - self.config.sysinfo_base;
             self.sysinfo.store(offset, 8, val);
             return Ok(());
         }
 
-        if addr >= CLINT_BASE && addr < CLINT_BASE + CLINT_SIZE {
-            let offset = addr - CLINT_BASE;
+        if addr >= self.config.framebuffer_base
+            && addr < self.config.framebuffer_base + FRAMEBUFFER_SIZE
+        {
+            let offset = addr - self.config.framebuffer_base;
+            self.framebuffer
+                .store(offset, 8, val)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
+        // Input queue registers are read-only
+        if addr >= self.config.input_base && addr < self.config.input_base + INPUT_SIZE {
+            return Ok(());
+        }
+
+        // Build information is read-only
+        if addr >= self.config.buildinfo_base && addr < self.config.buildinfo_base + BUILDINFO_SIZE
+        {
+            return Ok(());
+        }
+
+        if addr >= self.config.ivshmem_base && addr < self.config.ivshmem_base + IVSHMEM_SIZE {
+            let offset = addr - self.config.ivshmem_base;
+            self.ivshmem.store(offset, 8, val);
+            return Ok(());
+        }
+
+        if addr >= self.config.semihost_base && addr < self.config.semihost_base + SEMIHOST_SIZE {
+            let offset = addr - self.config.semihost_base;
+            return match self.semihost.store(offset, 8, val, &self.dram, &self.uart) {
+                Some(code) => Err(Trap::RequestedTrap(code)),
+                None => Ok(()),
+            };
+        }
+
+        if addr >= self.config.clint_base && addr < self.config.clint_base + CLINT_SIZE {
+            let offset = addr - self.config.clint_base;
             self.clint_store(offset, 8, val);
             return Ok(());
         }
 
-        if addr >= PLIC_BASE && addr < PLIC_BASE + PLIC_SIZE {
-            let offset = addr - PLIC_BASE;
+        if addr >= self.config.plic_base && addr < self.config.plic_base + PLIC_SIZE {
+            let offset = addr - self.config.plic_base;
             self.plic
                 .store(offset, 8, val)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
             return Ok(());
         }
 
-        if addr >= UART_BASE && addr < UART_BASE + UART_SIZE {
-            let offset = addr - UART_BASE;
+        if addr >= self.config.uart_base && addr < self.config.uart_base + UART_SIZE {
+            let offset = addr - self.config.uart_base;
             self.uart
                 .store(offset, 8, val)
                 .map_err(|_| Trap::StoreAccessFault(addr))?;
@@ -1018,6 +1920,13 @@ impl SystemBus {
             return Ok(());
         }
 
+        if let Some((device, offset)) = self.get_mmio_device(addr) {
+            device
+                .store(offset, 8, val)
+                .map_err(|_| Trap::StoreAccessFault(addr))?;
+            return Ok(());
+        }
+
         Err(Trap::StoreAccessFault(addr))
     }
 }
@@ -1025,12 +1934,54 @@ impl SystemBus {
 impl Bus for SystemBus {
     #[inline]
     fn poll_interrupts(&self) -> u64 {
-        self.check_interrupts()
+        self.poll_interrupts_for_hart(0)
     }
 
+    /// Devices are only serviced when the scheduler says something may
+    /// have changed; see [`crate::devices::scheduler`].
     #[inline]
     fn poll_interrupts_for_hart(&self, hart_id: usize) -> u64 {
-        self.check_interrupts_for_hart(hart_id)
+        if self.events.take_due() {
+            self.service_devices(hart_id);
+        }
+        self.pending_interrupts(hart_id)
+    }
+
+    /// Guest time follows hart 0 only, so extra harts don't speed it up.
+    #[inline]
+    fn advance_cycles(&self, hart_id: usize, cycles: u64) {
+        if hart_id != 0 {
+            return;
+        }
+        self.events.advance(cycles);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.clint.advance_cycles(cycles);
+        // SMP harts in workers read mtime from shared memory
+        #[cfg(target_arch = "wasm32")]
+        {
+            let ticks = self.clint.advance_cycles(cycles);
+            if let Some(ref shared) = self.shared_clint
+                && ticks > 0
+            {
+                shared.tick(ticks);
+            }
+        }
+    }
+
+    #[inline]
+    fn read_mtime(&self) -> Result<u64, Trap> {
+        Ok(self.clint_load(MTIME_OFFSET, 8))
+    }
+
+    /// Goes to the CLINT wherever the memory map puts it.
+    #[inline]
+    fn read_mtimecmp(&self, hart_id: usize) -> Result<u64, Trap> {
+        Ok(self.clint_load(MTIMECMP_OFFSET + 8 * hart_id as u64, 8))
+    }
+
+    #[inline]
+    fn cpu_frequency(&self) -> u64 {
+        self.clint.cpu_frequency()
     }
 
     // ========== WASM Atomic Operations ==========
@@ -1661,7 +2612,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::LoadAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.read8_slow(addr)
+        let result = self.read8_slow(addr);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1677,7 +2630,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::LoadAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.read16_slow(addr)
+        let result = self.read16_slow(addr);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1693,7 +2648,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::LoadAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.read32_slow(addr)
+        let result = self.read32_slow(addr);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1709,7 +2666,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::LoadAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.read64_slow(addr)
+        let result = self.read64_slow(addr);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1722,7 +2681,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::StoreAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.write8_slow(addr, val)
+        let result = self.write8_slow(addr, val);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1738,7 +2699,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::StoreAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.write16_slow(addr, val)
+        let result = self.write16_slow(addr, val);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1754,7 +2717,9 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::StoreAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.write32_slow(addr, val)
+        let result = self.write32_slow(addr, val);
+        self.note_mmio(addr);
+        result
     }
 
     #[inline(always)]
@@ -1770,6 +2735,401 @@ impl Bus for SystemBus {
                 .map_err(|_| Trap::StoreAccessFault(addr));
         }
         // Slow path: MMIO devices
-        self.write64_slow(addr, val)
+        let result = self.write64_slow(addr, val);
+        self.note_mmio(addr);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_memory_map() {
+        let config = BusConfig {
+            dram_base: 0x4000_0000,
+            dram_size: 64 * 1024,
+            uart_base: 0x0900_0000,
+            virtio_base: 0x0a00_0000,
+            ..BusConfig::default()
+        };
+        config.validate().unwrap();
+        let bus = SystemBus::with_config(config);
+
+        bus.write64(0x4000_fff8, 0x1234).unwrap();
+        assert_eq!(bus.read64(0x4000_fff8).unwrap(), 0x1234);
+        assert!(bus.read8(DRAM_BASE).is_err());
+
+        // UART LSR (offset 5) reports an empty transmitter at the new address only
+        assert_ne!(bus.read8(0x0900_0005).unwrap() & 0x20, 0);
+        assert!(bus.read8(UART_BASE + 5).is_err());
+        // Empty VirtIO slots probe as zero
+        assert_eq!(bus.read32(0x0a00_0000).unwrap(), 0);
+        assert!(bus.read32(VIRTIO_BASE).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_map_is_rejected() {
+        assert!(BusConfig::default().validate().is_ok());
+        let config = BusConfig {
+            uart_base: DRAM_BASE + 0x1000,
+            ..BusConfig::default()
+        };
+        assert!(config.validate().unwrap_err().contains("uart"));
+        assert!(BusConfig::with_dram(DRAM_BASE, 0).validate().is_err());
+    }
+
+    #[test]
+    fn test_framebuffer_and_input_are_mapped() {
+        use crate::devices::input::InputEvent;
+
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.write32(FRAMEBUFFER_BASE + 4, 0x00ff_0000).unwrap();
+        assert_eq!(bus.read32(FRAMEBUFFER_BASE + 4).unwrap(), 0x00ff_0000);
+        assert_eq!(bus.framebuffer.take_dirty_rects().len(), 1);
+        assert!(bus.read8(FRAMEBUFFER_BASE + FRAMEBUFFER_SIZE).is_err());
+
+        bus.input.push(InputEvent::Key {
+            code: 13,
+            pressed: true,
+        });
+        bus.check_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << INPUT_IRQ), 0);
+        assert_eq!(bus.read32(INPUT_BASE).unwrap(), 1);
+        assert_eq!(bus.read64(INPUT_BASE + 8).unwrap(), 0x000d_0101);
+        bus.check_interrupts();
+        assert_eq!(bus.plic.get_pending() & (1 << INPUT_IRQ), 0);
+    }
+
+    #[test]
+    fn test_mailbox_messages_over_mmio() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let port = bus.mailbox.port();
+        bus.write32(MAILBOX_BASE + 0x18, 1).unwrap();
+
+        port.post(b"hi").unwrap();
+        bus.check_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << MAILBOX_IRQ), 0);
+        assert_eq!(bus.read32(MAILBOX_BASE + 0x0c).unwrap(), 2);
+        assert_eq!(bus.read16(MAILBOX_BASE + 0x1000).unwrap(), 0x6968);
+        bus.write32(MAILBOX_BASE + 0x10, 1).unwrap();
+        bus.check_interrupts();
+        assert_eq!(bus.plic.get_pending() & (1 << MAILBOX_IRQ), 0);
+
+        bus.write64(MAILBOX_BASE + 0x2000, 0x2179_6568).unwrap();
+        bus.write32(MAILBOX_BASE + 0x14, 4).unwrap();
+        assert_eq!(port.take().as_deref(), Some(&b"hey!"[..]));
+    }
+
+    #[test]
+    fn test_rtc_follows_mtime_over_mmio() {
+        use crate::devices::rtc::DETERMINISTIC_EPOCH_NS;
+
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.rtc.set_deterministic(Some(DETERMINISTIC_EPOCH_NS));
+        // 1.5 s of guest time
+        bus.clint.set_mtime(15_000_000);
+        assert_eq!(
+            bus.read64(RTC_BASE).unwrap(),
+            DETERMINISTIC_EPOCH_NS + 1_500_000_000
+        );
+
+        // An alarm already due raises the RTC line until acknowledged
+        bus.write32(RTC_BASE + 0x10, 1).unwrap();
+        bus.write64(RTC_BASE + 0x08, DETERMINISTIC_EPOCH_NS).unwrap();
+        bus.check_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << RTC_IRQ), 0);
+        bus.write32(RTC_BASE + 0x1c, 1).unwrap();
+        bus.check_interrupts();
+        assert_eq!(bus.plic.get_pending() & (1 << RTC_IRQ), 0);
+    }
+
+    #[test]
+    fn test_buildinfo_is_read_only() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert_eq!(
+            bus.read32(BUILDINFO_BASE).unwrap(),
+            u32::from_le_bytes(*b"RVBI")
+        );
+        bus.write32(BUILDINFO_BASE, 0).unwrap();
+        assert_eq!(bus.read8(BUILDINFO_BASE).unwrap(), b'R');
+    }
+
+    #[test]
+    fn test_injected_irq_reaches_the_hart() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let s_ctx = Plic::s_context(0) as u64;
+        bus.write32(PLIC_BASE + 4 * 20, 2).unwrap();
+        bus.write32(PLIC_BASE + 0x2000 + 0x80 * s_ctx, 1 << 20)
+            .unwrap();
+
+        bus.raise_irq(20).unwrap();
+        assert_ne!(bus.check_interrupts() & (1 << 9), 0);
+
+        // Masked by the context threshold
+        bus.write32(PLIC_BASE + 0x20_0000 + 0x1000 * s_ctx, 2)
+            .unwrap();
+        assert_eq!(bus.check_interrupts() & (1 << 9), 0);
+        bus.write32(PLIC_BASE + 0x20_0000 + 0x1000 * s_ctx, 0)
+            .unwrap();
+
+        let claim = PLIC_BASE + 0x20_0004 + 0x1000 * s_ctx;
+        assert_eq!(bus.read32(claim).unwrap(), 20);
+        bus.write32(claim, 20).unwrap();
+        bus.clear_irq(20).unwrap();
+        assert_eq!(bus.check_interrupts() & (1 << 9), 0);
+        assert_eq!(bus.read32(claim).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_shared_memory_between_buses() {
+        let window = crate::shared_mem::SharedWindow::new(0x2000);
+        let a = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let b = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        a.ivshmem.attach(window.clone(), 0).unwrap();
+        b.ivshmem.attach(window, 1).unwrap();
+
+        let s_ctx = Plic::s_context(0) as u64;
+        b.write32(PLIC_BASE + 4 * IVSHMEM_IRQ as u64, 1).unwrap();
+        b.write32(PLIC_BASE + 0x2000 + 0x80 * s_ctx, 1 << IVSHMEM_IRQ)
+            .unwrap();
+        b.write32(IVSHMEM_BASE + 0x1c, 0xff).unwrap();
+
+        let window = IVSHMEM_BASE + 0x1000;
+        a.write64(window + 0x100, 0xfeed_f00d).unwrap();
+        a.write32(IVSHMEM_BASE + 0x10, 1).unwrap();
+        assert_ne!(b.check_interrupts() & (1 << 9), 0);
+        assert_eq!(b.read64(window + 0x100).unwrap(), 0xfeed_f00d);
+        assert_eq!(b.read32(IVSHMEM_BASE + 0x14).unwrap(), 0b1);
+
+        b.write32(IVSHMEM_BASE + 0x18, 0b1).unwrap();
+        assert_eq!(b.check_interrupts() & (1 << 9), 0);
+    }
+
+    #[test]
+    fn test_device_irqs_cannot_be_injected() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert!(bus.raise_irq(0).is_err());
+        assert!(bus.raise_irq(NUM_SOURCES as u32).is_err());
+        assert!(bus.raise_irq(UART_IRQ).is_err());
+        assert!(bus.raise_irq(INPUT_IRQ).is_err());
+        assert!(bus.raise_irq(VIRTIO0_IRQ).is_err());
+        assert!(bus.raise_irq(IVSHMEM_IRQ).is_err());
+        assert!(
+            bus.clear_irq(VIRTIO0_IRQ + VIRTIO_SLOTS as u32 - 1)
+                .is_err()
+        );
+        assert!(bus.raise_irq(31).is_ok());
+    }
+
+    /// A timer that raises its IRQ once it has been ticked past a deadline
+    /// written at offset 0.
+    struct TestTimer {
+        now: std::sync::atomic::AtomicU64,
+        deadline: std::sync::atomic::AtomicU64,
+    }
+
+    impl MmioDevice for TestTimer {
+        fn load(&self, offset: u64, _size: u64) -> Result<u64, crate::dram::MemoryError> {
+            match offset {
+                0 => Ok(self.deadline.load(std::sync::atomic::Ordering::Relaxed)),
+                8 => Ok(self.now.load(std::sync::atomic::Ordering::Relaxed)),
+                _ => Err(crate::dram::MemoryError::OutOfBounds(offset)),
+            }
+        }
+
+        fn store(
+            &self,
+            offset: u64,
+            _size: u64,
+            value: u64,
+        ) -> Result<(), crate::dram::MemoryError> {
+            match offset {
+                0 => self
+                    .deadline
+                    .store(value, std::sync::atomic::Ordering::Relaxed),
+                _ => return Err(crate::dram::MemoryError::OutOfBounds(offset)),
+            }
+            Ok(())
+        }
+
+        fn tick(&self, cycles: u64) {
+            self.now
+                .fetch_add(cycles, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        fn next_event_cycle(&self, now: u64) -> Option<u64> {
+            let ticks = self.now.load(std::sync::atomic::Ordering::Relaxed);
+            let deadline = self.deadline.load(std::sync::atomic::Ordering::Relaxed);
+            (deadline > ticks).then(|| now + (deadline - ticks))
+        }
+
+        fn irq(&self) -> Option<u32> {
+            Some(24)
+        }
+
+        fn is_interrupting(&self) -> bool {
+            let now = self.now.load(std::sync::atomic::Ordering::Relaxed);
+            let deadline = self.deadline.load(std::sync::atomic::Ordering::Relaxed);
+            deadline != 0 && now >= deadline
+        }
+    }
+
+    fn test_timer() -> Box<dyn MmioDevice> {
+        Box::new(TestTimer {
+            now: Default::default(),
+            deadline: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_registered_device() {
+        const BASE: u64 = 0x0400_0000;
+        let mut bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.register_device(BASE..BASE + 0x10, test_timer())
+            .unwrap();
+
+        let s_ctx = Plic::s_context(0) as u64;
+        bus.write32(PLIC_BASE + 4 * 24, 1).unwrap();
+        bus.write32(PLIC_BASE + 0x2000 + 0x80 * s_ctx, 1 << 24)
+            .unwrap();
+
+        bus.write64(BASE, 100).unwrap();
+        assert_eq!(bus.read32(BASE).unwrap(), 100);
+        assert!(bus.read8(BASE + 4).is_err());
+        assert!(bus.write8(BASE + 0x10, 0).is_err());
+
+        bus.advance_cycles(0, 60);
+        // Only hart 0 moves guest time
+        bus.advance_cycles(1, 60);
+        assert_eq!(bus.read64(BASE + 8).unwrap(), 60);
+        assert_eq!(bus.check_interrupts() & (1 << 9), 0);
+        bus.advance_cycles(0, 40);
+        assert_ne!(bus.check_interrupts() & (1 << 9), 0);
+
+        // The device owns the line
+        assert!(bus.clear_irq(24).is_err());
+        bus.write64(BASE, 0).unwrap();
+        assert_eq!(bus.check_interrupts() & (1 << 9), 0);
+    }
+
+    #[test]
+    fn test_devices_are_serviced_on_events() {
+        const BASE: u64 = 0x0400_0000;
+        let mut bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.register_device(BASE..BASE + 0x10, test_timer())
+            .unwrap();
+        let s_ctx = Plic::s_context(0) as u64;
+        bus.write32(PLIC_BASE + 4 * 24, 1).unwrap();
+        bus.write32(PLIC_BASE + 0x2000 + 0x80 * s_ctx, 1 << 24)
+            .unwrap();
+        bus.write64(BASE, 1_000).unwrap();
+        bus.poll_interrupts();
+        let services = bus.events.services();
+
+        // Nothing is due before the timer's deadline
+        for _ in 0..9 {
+            bus.advance_cycles(0, 100);
+            assert_eq!(bus.poll_interrupts() & (1 << 9), 0);
+        }
+        assert_eq!(bus.events.services(), services);
+        bus.advance_cycles(0, 100);
+        assert_ne!(bus.poll_interrupts() & (1 << 9), 0);
+        assert_eq!(bus.events.services(), services + 1);
+
+        // Touching a device gets the next poll to look at it
+        bus.uart.push_input(b'x');
+        bus.write8(UART_BASE + 1, 0x01).unwrap();
+        bus.poll_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << UART_IRQ), 0);
+    }
+
+    #[test]
+    fn test_register_device_is_validated() {
+        let mut bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert!(
+            bus.register_device(0x0400_0000..0x0400_0000, test_timer())
+                .is_err()
+        );
+        let err = bus
+            .register_device(UART_BASE..UART_BASE + 0x10, test_timer())
+            .unwrap_err();
+        assert!(err.contains("uart"));
+        assert!(
+            bus.register_device(DRAM_BASE - 8..DRAM_BASE + 8, test_timer())
+                .is_err()
+        );
+
+        bus.register_device(0x0400_0000..0x0400_1000, test_timer())
+            .unwrap();
+        assert!(
+            bus.register_device(0x0400_0800..0x0400_2000, test_timer())
+                .is_err()
+        );
+        // Free range, but IRQ 24 is taken
+        let err = bus
+            .register_device(0x0600_0000..0x0600_1000, test_timer())
+            .unwrap_err();
+        assert!(err.contains("IRQ 24"));
+    }
+    #[test]
+    fn test_reset_devices() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let finisher = TEST_FINISHER_BASE;
+        assert!(matches!(
+            bus.write32(finisher, 0x7777),
+            Err(Trap::RequestedTrap(0x7777))
+        ));
+        assert!(bus.write32(finisher + 4, 0x5555).is_ok());
+
+        let s_ctx = Plic::s_context(0) as u64;
+        bus.write32(PLIC_BASE + 4 * UART_IRQ as u64, 1).unwrap();
+        bus.write32(PLIC_BASE + 0x2000 + 0x80 * s_ctx, 1 << UART_IRQ)
+            .unwrap();
+        bus.write64(CLINT_BASE + MTIMECMP_OFFSET, 100).unwrap();
+        bus.write32(CLINT_BASE + MSIP_OFFSET, 1).unwrap();
+        bus.write8(UART_BASE + 1, 0x01).unwrap();
+        bus.uart.push_input(b'x');
+        bus.poll_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << UART_IRQ), 0);
+
+        bus.reset_devices();
+        assert_eq!(bus.read8(UART_BASE + 1).unwrap(), 0);
+        assert_eq!(bus.read32(PLIC_BASE + 4 * UART_IRQ as u64).unwrap(), 0);
+        assert_eq!(bus.clint.get_mtimecmp(0), u64::MAX);
+        assert_eq!(bus.clint.get_msip(0), 0);
+        assert_eq!(bus.poll_interrupts() & ((1 << 9) | (1 << 3)), 0);
+        // Host input survives for the rebooted guest
+        assert_eq!(bus.read8(UART_BASE).unwrap(), b'x');
+    }
+
+    #[test]
+    fn test_virtio_hotplug() {
+        use crate::devices::virtio::VirtioRng;
+        use crate::devices::virtio::device::{DEVICE_ID_OFFSET, VIRTIO_RNG_DEVICE_ID};
+
+        let mut bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.virtio_devices.push(Box::new(HotplugSlot::new()));
+        bus.virtio_devices.push(Box::new(VirtioRng::new()));
+        assert_eq!(bus.read32(VIRTIO_BASE + DEVICE_ID_OFFSET).unwrap(), 0);
+
+        bus.plug_virtio(0, Box::new(VirtioRng::new())).unwrap();
+        assert!(bus.plug_virtio(0, Box::new(VirtioRng::new())).is_err());
+        assert!(bus.plug_virtio(1, Box::new(VirtioRng::new())).is_err());
+        assert!(bus.plug_virtio(2, Box::new(VirtioRng::new())).is_err());
+        assert_eq!(
+            bus.read32(VIRTIO_BASE + DEVICE_ID_OFFSET).unwrap(),
+            VIRTIO_RNG_DEVICE_ID
+        );
+        // The slot signals the change
+        bus.poll_interrupts();
+        assert_ne!(bus.plic.get_pending() & (1 << VIRTIO0_IRQ), 0);
+
+        let device = bus.unplug_virtio(0).unwrap();
+        assert_eq!(device.device_id(), VIRTIO_RNG_DEVICE_ID);
+        assert!(bus.unplug_virtio(0).is_err());
+        assert!(bus.unplug_virtio(1).is_err());
+        assert_eq!(bus.read32(VIRTIO_BASE + DEVICE_ID_OFFSET).unwrap(), 0);
     }
 }
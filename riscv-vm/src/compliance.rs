@@ -0,0 +1,258 @@
+//! riscv-tests compliance runner.
+//!
+//! Runs the official `riscv-tests` ISA binaries (the `-p-` physical-memory
+//! variants of rv64ui/rv64um/rv64ua/rv64uf/rv64ud/rv64uc) and reports
+//! pass/fail per test.
+//!
+//! The binaries signal completion through the HTIF `tohost` convention:
+//! the test writes `1` to the `tohost` symbol on success, or
+//! `(test_num << 1) | 1` when sub-test `test_num` failed. The runner polls
+//! that location while stepping the CPU and stops as soon as it changes.
+//!
+//! Build the binaries from <https://github.com/riscv-software-src/riscv-tests>
+//! and point [`run_suite`] (or `NativeVm::run_compliance_tests`) at the
+//! `isa/` output directory.
+
+use crate::Trap;
+use crate::bus::{Bus, DRAM_BASE, SystemBus};
+use crate::cpu::Cpu;
+use crate::devices::bootrom::{BootConfig, RESET_VECTOR};
+use crate::loader::load_elf_into_dram;
+use goblin::elf::Elf;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Test suites run by default.
+pub const DEFAULT_SUITES: &[&str] = &["rv64ui", "rv64um", "rv64ua", "rv64uf", "rv64ud", "rv64uc"];
+
+/// Step budget per test before it is reported as a timeout.
+pub const DEFAULT_MAX_STEPS: u64 = 5_000_000;
+
+/// DRAM given to each test. The `-p-` tests fit comfortably in a few pages.
+const TEST_DRAM_SIZE: usize = 16 * 1024 * 1024;
+
+/// How a single test ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Pass,
+    /// The test reported failure of the given sub-test number.
+    Fail(u64),
+    /// The step budget ran out before `tohost` was written.
+    Timeout,
+    /// The binary could not be loaded or the VM hit a fatal error.
+    Error(String),
+}
+
+/// Result of one test binary.
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    pub steps: u64,
+}
+
+/// Results of a full compliance run.
+#[derive(Debug, Clone, Default)]
+pub struct ComplianceReport {
+    pub results: Vec<TestResult>,
+}
+
+impl ComplianceReport {
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.outcome == TestOutcome::Pass)
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+impl fmt::Display for ComplianceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            match &r.outcome {
+                TestOutcome::Pass => writeln!(f, "PASS  {}", r.name)?,
+                TestOutcome::Fail(n) => writeln!(f, "FAIL  {} (test #{})", r.name, n)?,
+                TestOutcome::Timeout => {
+                    writeln!(f, "FAIL  {} (timeout after {} steps)", r.name, r.steps)?
+                }
+                TestOutcome::Error(e) => writeln!(f, "ERROR {} ({})", r.name, e)?,
+            }
+        }
+        write!(
+            f,
+            "{} passed, {} failed, {} total",
+            self.passed(),
+            self.failed(),
+            self.results.len()
+        )
+    }
+}
+
+/// Locate the `tohost` word, by symbol first and `.tohost` section second.
+fn find_tohost(elf: &Elf) -> Option<u64> {
+    for sym in elf.syms.iter() {
+        if elf.strtab.get_at(sym.st_name) == Some("tohost") {
+            return Some(sym.st_value);
+        }
+    }
+    elf.section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".tohost"))
+        .map(|sh| sh.sh_addr)
+}
+
+/// Step `cpu` until `tohost` is written, the budget runs out, or the VM halts.
+fn run_until_tohost(
+    cpu: &mut Cpu,
+    bus: &SystemBus,
+    tohost: u64,
+    max_steps: u64,
+) -> (TestOutcome, u64) {
+    for steps in 0..max_steps {
+        match cpu.step(bus) {
+            Ok(()) => {}
+            Err(Trap::RequestedTrap(code)) => {
+                let outcome = if code == 0x5555 {
+                    TestOutcome::Pass
+                } else {
+                    TestOutcome::Fail(code)
+                };
+                return (outcome, steps + 1);
+            }
+            Err(Trap::Fatal(msg)) => return (TestOutcome::Error(msg), steps + 1),
+            // Architectural traps are handled by the test's own trap vector
+            Err(_) => {}
+        }
+
+        let value = bus.read64(tohost).unwrap_or(0);
+        if value != 0 {
+            let outcome = if value == 1 {
+                TestOutcome::Pass
+            } else if value & 1 == 1 {
+                TestOutcome::Fail(value >> 1)
+            } else {
+                TestOutcome::Error(format!("unsupported HTIF request 0x{:x}", value))
+            };
+            return (outcome, steps + 1);
+        }
+    }
+    (TestOutcome::Timeout, max_steps)
+}
+
+/// Run a single riscv-tests ELF image.
+pub fn run_elf(name: &str, image: &[u8], max_steps: u64) -> TestResult {
+    let error = |msg: String| TestResult {
+        name: name.to_string(),
+        outcome: TestOutcome::Error(msg),
+        steps: 0,
+    };
+
+    let elf = match Elf::parse(image) {
+        Ok(elf) => elf,
+        Err(e) => return error(format!("ELF parse error: {}", e)),
+    };
+    let Some(tohost) = find_tohost(&elf) else {
+        return error("no tohost symbol".to_string());
+    };
+
+    let bus = SystemBus::new(DRAM_BASE, TEST_DRAM_SIZE);
+    let entry = match load_elf_into_dram(image, &bus) {
+        Ok(entry) => entry,
+        Err(e) => return error(e),
+    };
+    bus.boot_rom
+        .configure(&BootConfig::new(entry, DRAM_BASE + TEST_DRAM_SIZE as u64));
+
+    let mut cpu = Cpu::new(RESET_VECTOR, 0);
+    let (outcome, steps) = run_until_tohost(&mut cpu, &bus, tohost, max_steps);
+    TestResult {
+        name: name.to_string(),
+        outcome,
+        steps,
+    }
+}
+
+/// Run every `<suite>-p-*` binary found in `dir` for the given suites.
+///
+/// Disassembly dumps (`*.dump`) are skipped. Results are sorted by name.
+pub fn run_suite<P: AsRef<Path>>(
+    dir: P,
+    suites: &[&str],
+    max_steps: u64,
+) -> io::Result<ComplianceReport> {
+    let mut names: Vec<String> = Vec::new();
+    for entry in std::fs::read_dir(dir.as_ref())? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".dump") {
+            continue;
+        }
+        if suites
+            .iter()
+            .any(|suite| name.starts_with(&format!("{}-p-", suite)))
+        {
+            names.push(name);
+        }
+    }
+    names.sort();
+
+    let mut report = ComplianceReport::default();
+    for name in names {
+        let image = std::fs::read(dir.as_ref().join(&name))?;
+        report.results.push(run_elf(&name, &image, max_steps));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOHOST: u64 = DRAM_BASE + 0x1000;
+
+    fn run_raw(program: &[u32], max_steps: u64) -> TestOutcome {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        for (i, insn) in program.iter().enumerate() {
+            bus.write32(DRAM_BASE + i as u64 * 4, *insn).unwrap();
+        }
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        run_until_tohost(&mut cpu, &bus, TOHOST, max_steps).0
+    }
+
+    #[test]
+    fn test_tohost_pass() {
+        // auipc t0, 1; addi t1, x0, 1; sd t1, 0(t0); j .
+        let program = [0x0000_1297, 0x0010_0313, 0x0062_b023, 0x0000_006f];
+        assert_eq!(run_raw(&program, 100), TestOutcome::Pass);
+    }
+
+    #[test]
+    fn test_tohost_fail_reports_test_number() {
+        // auipc t0, 1; addi t1, x0, 7; sd t1, 0(t0); j .
+        let program = [0x0000_1297, 0x0070_0313, 0x0062_b023, 0x0000_006f];
+        assert_eq!(run_raw(&program, 100), TestOutcome::Fail(3));
+    }
+
+    #[test]
+    fn test_timeout() {
+        assert_eq!(run_raw(&[0x0000_006f], 100), TestOutcome::Timeout);
+    }
+
+    #[test]
+    fn test_run_elf_rejects_garbage() {
+        let result = run_elf("bogus", b"not an elf", 10);
+        assert!(matches!(result.outcome, TestOutcome::Error(_)));
+    }
+}
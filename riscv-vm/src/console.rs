@@ -2,9 +2,18 @@
 
 #![cfg(not(target_arch = "wasm32"))]
 
+use std::fs::OpenOptions;
 use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
 use std::sync::mpsc::{self, Receiver, TryRecvError};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::devices::virtio::ConsolePort;
+
+/// How often a port bridge moves bytes
+const BRIDGE_INTERVAL: Duration = Duration::from_millis(5);
 
 /// Non-blocking console input handler.
 ///
@@ -59,6 +68,14 @@ impl Console {
         }
     }
 
+    /// A console that never has input, for running a VM without a
+    /// terminal.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        let (_, rx) = mpsc::channel();
+        Self { rx, _handle: None }
+    }
+
     /// Try to read a byte from stdin (non-blocking).
     ///
     /// Returns `Some(byte)` if input is available, `None` otherwise.
@@ -155,3 +172,67 @@ impl RawModeGuard {
         Self
     }
 }
+
+/// Where the host end of a VirtIO console port is connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortTarget {
+    /// Accept TCP clients on this address, one at a time. Guest output
+    /// written while nobody is connected waits for the next client.
+    Listen(SocketAddr),
+    /// Append guest output to this file; the guest gets no input.
+    File(PathBuf),
+}
+
+/// Move bytes between `port` and `target` on a background thread.
+///
+/// The listening socket or file is opened before returning, so a bad
+/// address or path is reported here.
+pub fn bridge_port(port: ConsolePort, target: PortTarget) -> io::Result<()> {
+    match target {
+        PortTarget::Listen(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            thread::Builder::new()
+                .name("console-port".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        let Ok(mut stream) = stream else { continue };
+                        if stream.set_read_timeout(Some(BRIDGE_INTERVAL)).is_err() {
+                            continue;
+                        }
+                        let mut buf = [0u8; 4096];
+                        loop {
+                            let output = port.drain_output();
+                            if !output.is_empty() && stream.write_all(&output).is_err() {
+                                break;
+                            }
+                            match stream.read(&mut buf) {
+                                Ok(0) => break,
+                                Ok(n) => port.push_input(&buf[..n]),
+                                Err(e)
+                                    if matches!(
+                                        e.kind(),
+                                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                                    ) => {}
+                                Err(_) => break,
+                            }
+                        }
+                    }
+                })?;
+        }
+        PortTarget::File(path) => {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            thread::Builder::new()
+                .name("console-port".to_string())
+                .spawn(move || {
+                    loop {
+                        let output = port.drain_output();
+                        if !output.is_empty() && file.write_all(&output).is_err() {
+                            break;
+                        }
+                        thread::sleep(BRIDGE_INTERVAL);
+                    }
+                })?;
+        }
+    }
+    Ok(())
+}
@@ -2,16 +2,28 @@ use crate::bus::Bus;
 use crate::engine::block::Block;
 use crate::engine::cache::BlockCache;
 use crate::engine::decoder::{self, Op, Register};
-use crate::engine::microop::MicroOp;
+#[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+use crate::engine::jit::{JitCache, JitConfig};
+use crate::engine::microop::{MicroOp, csr_write_has_side_effects};
+use crate::engine::trace::TraceBuffer;
 use crate::mmu::{self, AccessType as MmuAccessType, Tlb};
 use std::collections::HashMap;
 
+use super::counters::{self, Counters};
 use super::csr::{
     CSR_MCAUSE, CSR_MEDELEG, CSR_MEPC, CSR_MHARTID, CSR_MIDELEG, CSR_MIE, CSR_MIP, CSR_MISA,
     CSR_MSTATUS, CSR_MTVAL, CSR_MTVEC, CSR_SATP, CSR_SCAUSE, CSR_SEPC, CSR_STVAL, CSR_STVEC,
-    CsrFile,
+    CSR_TIME, CsrFile, CsrPolicy,
 };
+use super::debug::{TrapBreak, TrapBreakHit};
+use super::fpu::NAN_BOX;
+use super::latency::InterruptClock;
+use super::pmp::{self, Pmp};
+use super::sbi::SbiState;
+use super::tracer::Tracer;
 use super::types::{Mode, Trap};
+use super::vector::{self, CSR_VTYPE, VTYPE_VILL, VectorState};
+use super::watch::{WatchHit, WatchId, Watchpoint};
 
 /// Cached decode result.
 /// Stores (pc, raw_instruction, decoded_op) for cache hit checking.
@@ -39,6 +51,8 @@ pub(super) enum BlockExecResult {
 #[repr(align(128))]
 pub struct Cpu {
     pub regs: [u64; 32],
+    /// Floating-point registers (F/D). Single-precision values are NaN-boxed.
+    pub fregs: [u64; 32],
     pub pc: u64,
     /// Reservation set address for LR/SC (granule-aligned), or None if no reservation.
     pub(super) reservation: Option<u64>,
@@ -51,14 +65,49 @@ pub struct Cpu {
     /// Poll counter for batching interrupt checks (rolls over every 256 instructions).
     /// Exposed for testing to force immediate interrupt polling.
     pub poll_counter: u8,
+    /// Cycles executed by this hart, see [`crate::engine::timing`].
+    pub cycles: u64,
+    /// Cycles not yet handed to the bus to advance guest time.
+    pub(super) unsynced_cycles: u64,
     /// Instruction decode cache.
     /// Key: pc & DECODE_CACHE_MASK
     /// Value: Some((full_pc, raw_insn, decoded_op)) or None
     decode_cache: [Option<DecodeCacheEntry>; DECODE_CACHE_SIZE],
     /// Block cache for superblock execution.
     pub block_cache: BlockCache,
+    /// Block transition profile and hot traces (tier 2).
+    pub traces: TraceBuffer,
     /// Enable/disable superblock optimization.
     pub use_blocks: bool,
+    /// Native code for hot blocks (tier 3), see [`Cpu::enable_jit`].
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    pub jit: Option<Box<JitCache>>,
+    /// Exception causes handed back to the caller instead of being taken
+    /// (bit N = exception cause N). See [`TrapHook`](super::hook::TrapHook).
+    pub intercept_exceptions: u64,
+    /// Armed trap breakpoints (see [`Cpu::break_on`]).
+    pub(super) trap_breaks: Vec<TrapBreak>,
+    /// Latched trap breakpoint; the hart is paused while set.
+    pub(super) break_hit: Option<TrapBreakHit>,
+    /// Armed watchpoints (see [`Cpu::add_watchpoint`]).
+    pub(super) watchpoints: Vec<(WatchId, Watchpoint)>,
+    pub(super) next_watch_id: WatchId,
+    /// Latched watchpoint hit; the hart is paused while set.
+    pub(super) watch_hit: Option<WatchHit>,
+    /// Set by WFI until a run loop takes it (see [`Cpu::take_idle`]).
+    pub(super) wfi: bool,
+    /// Execution tracer (see [`Cpu::set_tracer`]).
+    pub(super) tracer: Option<Box<Tracer>>,
+    /// Built-in SBI firmware state (see [`Cpu::enable_sbi`]).
+    pub(super) sbi: Option<SbiState>,
+    /// Decoded PMP entries (see [`pmp`](super::pmp)).
+    pub(super) pmp: Pmp,
+    /// Performance counter state (see [`counters`](super::counters)).
+    pub(super) counters: Counters,
+    /// Vector registers (see [`vector`](super::vector)).
+    pub(super) vector: VectorState,
+    /// Interrupt latency bookkeeping (see [`latency`](super::latency)).
+    pub(super) irq_clock: InterruptClock,
 }
 
 impl Cpu {
@@ -69,25 +118,45 @@ impl Cpu {
     /// * `hart_id` - Hardware thread ID (0 for primary, 1+ for secondary)
     pub fn new(pc: u64, hart_id: u64) -> Self {
         let mut csrs = CsrFile::new();
-        // misa: rv64imac_zicsr_zifencei (value from phase-0.md)
-        const MISA_RV64IMAC_ZICSR_ZIFENCEI: u64 = 0x4000_0000_0018_1125;
-        csrs[CSR_MISA as usize] = MISA_RV64IMAC_ZICSR_ZIFENCEI;
+        // misa: rv64imafdcbv_zicsr_zifencei (B = Zba + Zbb + Zbs)
+        const MISA_RV64IMAFDCBV_ZICSR_ZIFENCEI: u64 = 0x4000_0000_0038_112F;
+        csrs[CSR_MISA as usize] = MISA_RV64IMAFDCBV_ZICSR_ZIFENCEI;
         csrs[CSR_MHARTID as usize] = hart_id; // Initialize hart ID
+        csrs[CSR_VTYPE as usize] = VTYPE_VILL;
 
         // mstatus initial value: all zeros except UXL/SXL can be left as 0 (WARL).
         csrs[CSR_MSTATUS as usize] = 0;
 
         Self {
             regs: [0; 32],
+            fregs: [0; 32],
             pc,
             reservation: None,
             csrs,
             mode: Mode::Machine,
             tlb: Tlb::new(),
             poll_counter: 0,
+            cycles: 0,
+            unsynced_cycles: 0,
             decode_cache: [None; DECODE_CACHE_SIZE],
             block_cache: BlockCache::new(),
+            traces: TraceBuffer::new(),
             use_blocks: false, // Disabled by default; enable for production workloads
+            #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+            jit: None,
+            intercept_exceptions: 0,
+            trap_breaks: Vec::new(),
+            break_hit: None,
+            watchpoints: Vec::new(),
+            next_watch_id: 0,
+            watch_hit: None,
+            wfi: false,
+            tracer: None,
+            sbi: None,
+            pmp: Pmp::default(),
+            counters: Counters::default(),
+            vector: VectorState::default(),
+            irq_clock: InterruptClock::default(),
         }
     }
 
@@ -104,6 +173,7 @@ impl Cpu {
     /// snapshot/restore.
     pub fn import_csrs(&mut self, map: &HashMap<u16, u64>) {
         self.csrs.import(map);
+        self.sync_pmp();
     }
 
     /// Look up instruction in decode cache
@@ -130,12 +200,57 @@ impl Cpu {
         self.decode_cache = [None; DECODE_CACHE_SIZE];
     }
 
-    /// Invalidate block cache on SATP write or SFENCE.VMA
+    /// Enable block execution with hot blocks compiled to host code.
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    pub fn enable_jit(&mut self, config: JitConfig) -> Result<(), String> {
+        self.jit = Some(Box::new(JitCache::new(config)?));
+        self.use_blocks = true;
+        Ok(())
+    }
+
+    /// Invalidate block cache on SATP write, SFENCE.VMA or FENCE.I
     pub fn invalidate_blocks(&mut self) {
         self.block_cache.flush();
+        self.traces.flush();
+        #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+        if let Some(jit) = self.jit.as_mut() {
+            jit.flush();
+        }
         self.invalidate_decode_cache();
     }
 
+    /// Discard state derived from memory and CSRs (TLB, decode/block/JIT
+    /// caches, LR reservation), e.g. after the architectural state has been
+    /// replaced by a snapshot restore.
+    ///
+    /// The interrupt poll phase is reset too, so a restored hart polls on
+    /// the same schedule whatever it ran before.
+    pub fn flush_cached_state(&mut self) {
+        self.tlb.flush();
+        self.invalidate_blocks();
+        self.reservation = None;
+        self.poll_counter = 0;
+        self.unsynced_cycles = 0;
+        self.reset_interrupt_clock();
+    }
+
+    /// Drop compiled code on the pages touched by a store to `[pa, pa + len)`.
+    ///
+    /// The block that issued the store may finish with the old code, which
+    /// the ISA allows without an intervening FENCE.I.
+    #[inline]
+    pub(super) fn note_code_write(&mut self, pa: u64, len: u64) {
+        if !self.block_cache.is_code(pa, len) {
+            return;
+        }
+        let (start, end) = self.block_cache.invalidate_code(pa, len);
+        self.traces.invalidate_range(start, end);
+        #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+        if let Some(jit) = self.jit.as_mut() {
+            jit.invalidate_range(start, end);
+        }
+    }
+
     pub fn read_reg(&self, reg: Register) -> u64 {
         if reg == Register::X0 {
             0
@@ -163,16 +278,37 @@ impl Cpu {
         }
     }
 
+    /// Choose what accesses to CSRs this hart does not implement do.
+    pub fn set_csr_policy(&mut self, policy: CsrPolicy) {
+        self.csrs.set_policy(policy);
+    }
+
     pub fn read_csr(&self, addr: u16) -> Result<u64, Trap> {
+        if let Some(index) = counters::counter_index(addr) {
+            return self.read_counter(addr, index);
+        }
+        if vector::is_vector_csr(addr) {
+            return self.read_vector_csr(addr);
+        }
         self.csrs.read(addr, self.mode)
     }
 
     pub fn write_csr(&mut self, addr: u16, val: u64) -> Result<(), Trap> {
-        self.csrs.write(addr, val, self.mode)
+        if counters::is_counter_csr(addr) {
+            return self.write_counter_csr(addr, val);
+        }
+        if vector::is_vector_csr(addr) {
+            return self.write_vector_csr(addr, val);
+        }
+        self.csrs.write(addr, val, self.mode)?;
+        if pmp::is_pmp_csr(addr) {
+            self.sync_pmp();
+        }
+        Ok(())
     }
 
     /// Map a `Trap` into (is_interrupt, cause, tval) per privileged spec, or `None` if it's a host-only error.
-    fn trap_to_cause_tval(trap: &Trap) -> Option<(bool, u64, u64)> {
+    pub(crate) fn trap_to_cause_tval(trap: &Trap) -> Option<(bool, u64, u64)> {
         match *trap {
             Trap::InstructionAddressMisaligned(addr) => Some((false, 0, addr)),
             Trap::InstructionAccessFault(addr) => Some((false, 1, addr)),
@@ -200,14 +336,31 @@ impl Cpu {
         }
     }
 
+    /// Whether `trap` is an exception the host has asked to intercept.
+    pub fn intercepts(&self, trap: &Trap) -> bool {
+        matches!(
+            Self::trap_to_cause_tval(trap),
+            Some((false, cause, _)) if self.intercept_exceptions & (1 << cause) != 0
+        )
+    }
+
     pub(super) fn handle_trap<T>(
         &mut self,
         trap: Trap,
         pc: u64,
         _insn_raw: Option<u32>,
     ) -> Result<T, Trap> {
+        // Intercepted exceptions leave the hart at the faulting instruction
+        if self.intercepts(&trap) {
+            self.pc = pc;
+            return Err(trap);
+        }
+
         // Fatal/host-only traps bypass architectural trap entry.
         if let Some((is_interrupt, cause, tval)) = Self::trap_to_cause_tval(&trap) {
+            let from_mode = self.mode;
+            self.counters.events.traps += 1;
+
             // Determine delegation target per medeleg/mideleg
             let medeleg = self.csrs[CSR_MEDELEG as usize];
             let mideleg = self.csrs[CSR_MIDELEG as usize];
@@ -302,6 +455,10 @@ impl Cpu {
                 };
                 self.pc = target_pc;
             }
+
+            if !self.trap_breaks.is_empty() {
+                self.check_trap_break(&trap, is_interrupt, cause, pc, from_mode);
+            }
         }
 
         Err(trap)
@@ -321,7 +478,9 @@ impl Cpu {
     ) -> Result<u64, Trap> {
         let satp = self.csrs[CSR_SATP as usize];
         let mstatus = self.csrs[CSR_MSTATUS as usize];
-        match mmu::translate(bus, &mut self.tlb, self.mode, satp, mstatus, vaddr, access) {
+        match mmu::translate(bus, &mut self.tlb, self.mode, satp, mstatus, vaddr, access)
+            .and_then(|pa| self.pmp_check(vaddr, pa, 1, access).map(|()| pa))
+        {
             Ok(pa) => Ok(pa),
             Err(trap) => self.handle_trap(trap, pc, insn_raw),
         }
@@ -337,13 +496,11 @@ impl Cpu {
     /// Exit: Block needs to exit to interpreter
     pub(super) fn execute_block_inner(&mut self, block: &Block, bus: &dyn Bus) -> BlockExecResult {
         let base_pc = block.start_pc;
-        let len = block.len as usize;
-        // Copy the ops array to avoid borrow issues during execution
-        let ops = block.ops;
+        let ops = block.ops();
 
         let mut idx = 0usize;
 
-        while idx < len {
+        while idx < ops.len() {
             let op = ops[idx];
             idx += 1;
 
@@ -755,6 +912,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 8, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read64(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -777,6 +937,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 4, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read32(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -799,6 +962,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 4, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read32(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -821,6 +987,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 2, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read16(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -843,6 +1012,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 2, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read16(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -865,6 +1037,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 1, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read8(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -887,6 +1062,9 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 1, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     match bus.read8(pa) {
                         Ok(val) => {
                             if rd != 0 {
@@ -913,9 +1091,13 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 8, true) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     if let Err(trap) = bus.write64(pa, val) {
                         return BlockExecResult::Trap { trap, fault_pc: pc };
                     }
+                    self.note_code_write(pa, 8);
                     self.clear_reservation_if_conflict(addr);
                 }
 
@@ -932,9 +1114,13 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 4, true) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     if let Err(trap) = bus.write32(pa, val) {
                         return BlockExecResult::Trap { trap, fault_pc: pc };
                     }
+                    self.note_code_write(pa, 4);
                     self.clear_reservation_if_conflict(addr);
                 }
 
@@ -951,9 +1137,13 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 2, true) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     if let Err(trap) = bus.write16(pa, val) {
                         return BlockExecResult::Trap { trap, fault_pc: pc };
                     }
+                    self.note_code_write(pa, 2);
                     self.clear_reservation_if_conflict(addr);
                 }
 
@@ -970,12 +1160,113 @@ impl Cpu {
                         Ok(pa) => pa,
                         Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
                     };
+                    if self.watched(addr, pa, 1, true) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
                     if let Err(trap) = bus.write8(pa, val) {
                         return BlockExecResult::Trap { trap, fault_pc: pc };
                     }
+                    self.note_code_write(pa, 1);
                     self.clear_reservation_if_conflict(addr);
                 }
 
+                // ═══════════════════════════════════════════════════════════
+                // Floating point (may trap)
+                // ═══════════════════════════════════════════════════════════
+                MicroOp::Flw {
+                    rd,
+                    rs1,
+                    imm,
+                    pc_offset,
+                }
+                | MicroOp::Fld {
+                    rd,
+                    rs1,
+                    imm,
+                    pc_offset,
+                } => {
+                    let pc = base_pc.wrapping_add(pc_offset as u64);
+                    if !self.fp_enabled() {
+                        // Let the interpreter raise the illegal instruction trap
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                    let addr = self.regs[rs1 as usize].wrapping_add(imm as u64);
+                    let pa = match self.translate_addr_for_block(bus, addr, MmuAccessType::Load) {
+                        Ok(pa) => pa,
+                        Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
+                    };
+                    let is_word = matches!(op, MicroOp::Flw { .. });
+                    if self.watched(addr, pa, if is_word { 4 } else { 8 }, false) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                    let val = if is_word {
+                        bus.read32(pa).map(|v| v as u64 | NAN_BOX)
+                    } else {
+                        bus.read64(pa)
+                    };
+                    match val {
+                        Ok(val) => self.write_freg(rd as usize, val),
+                        Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
+                    }
+                }
+
+                MicroOp::Fsw {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                }
+                | MicroOp::Fsd {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                } => {
+                    let pc = base_pc.wrapping_add(pc_offset as u64);
+                    if !self.fp_enabled() {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                    let addr = self.regs[rs1 as usize].wrapping_add(imm as u64);
+                    let val = self.fregs[rs2 as usize];
+                    let pa = match self.translate_addr_for_block(bus, addr, MmuAccessType::Store) {
+                        Ok(pa) => pa,
+                        Err(trap) => return BlockExecResult::Trap { trap, fault_pc: pc },
+                    };
+                    let is_word = matches!(op, MicroOp::Fsw { .. });
+                    if self.watched(addr, pa, if is_word { 4 } else { 8 }, true) {
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                    let res = if is_word {
+                        bus.write32(pa, val as u32)
+                    } else {
+                        bus.write64(pa, val)
+                    };
+                    if let Err(trap) = res {
+                        return BlockExecResult::Trap { trap, fault_pc: pc };
+                    }
+                    self.note_code_write(pa, if is_word { 4 } else { 8 });
+                    self.clear_reservation_if_conflict(addr);
+                }
+
+                MicroOp::FpOp { insn, pc_offset } => {
+                    if let Err(trap) = self.execute_fp(insn) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Trap { trap, fault_pc: pc };
+                    }
+                }
+
+                MicroOp::Vector { insn, pc_offset } => {
+                    if !self.watchpoints.is_empty() {
+                        // The interpreter reports watched element accesses
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                    if let Err(trap) = self.execute_vector(bus, insn) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Trap { trap, fault_pc: pc };
+                    }
+                }
+
                 // ═══════════════════════════════════════════════════════════
                 // Control flow (block terminators)
                 // ═══════════════════════════════════════════════════════════
@@ -1111,6 +1402,86 @@ impl Cpu {
                     return BlockExecResult::Continue(next);
                 }
 
+                // ═══════════════════════════════════════════════════════════
+                // CSR access (inline unless the write has side effects)
+                // ═══════════════════════════════════════════════════════════
+                MicroOp::Csrrw {
+                    rd,
+                    rs1,
+                    csr,
+                    pc_offset,
+                } => {
+                    let src = self.regs[rs1 as usize];
+                    if !self.block_csr(bus, csr, rd, |_| Some(src)) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
+                MicroOp::Csrrs {
+                    rd,
+                    rs1,
+                    csr,
+                    pc_offset,
+                } => {
+                    let mask = self.regs[rs1 as usize];
+                    if !self.block_csr(bus, csr, rd, |old| (rs1 != 0).then_some(old | mask)) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
+                MicroOp::Csrrc {
+                    rd,
+                    rs1,
+                    csr,
+                    pc_offset,
+                } => {
+                    let mask = self.regs[rs1 as usize];
+                    if !self.block_csr(bus, csr, rd, |old| (rs1 != 0).then_some(old & !mask)) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
+                MicroOp::Csrrwi {
+                    rd,
+                    zimm,
+                    csr,
+                    pc_offset,
+                } => {
+                    if !self.block_csr(bus, csr, rd, |_| Some(zimm as u64)) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
+                MicroOp::Csrrsi {
+                    rd,
+                    zimm,
+                    csr,
+                    pc_offset,
+                } => {
+                    let update = |old| (zimm != 0).then_some(old | zimm as u64);
+                    if !self.block_csr(bus, csr, rd, update) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
+                MicroOp::Csrrci {
+                    rd,
+                    zimm,
+                    csr,
+                    pc_offset,
+                } => {
+                    let update = |old| (zimm != 0).then_some(old & !(zimm as u64));
+                    if !self.block_csr(bus, csr, rd, update) {
+                        let pc = base_pc.wrapping_add(pc_offset as u64);
+                        return BlockExecResult::Exit { next_pc: pc };
+                    }
+                }
+
                 // ═══════════════════════════════════════════════════════════
                 // System operations (exit to interpreter)
                 // ═══════════════════════════════════════════════════════════
@@ -1119,12 +1490,7 @@ impl Cpu {
                 | MicroOp::Mret { pc_offset }
                 | MicroOp::Sret { pc_offset }
                 | MicroOp::SfenceVma { pc_offset }
-                | MicroOp::Csrrw { pc_offset, .. }
-                | MicroOp::Csrrs { pc_offset, .. }
-                | MicroOp::Csrrc { pc_offset, .. }
-                | MicroOp::Csrrwi { pc_offset, .. }
-                | MicroOp::Csrrsi { pc_offset, .. }
-                | MicroOp::Csrrci { pc_offset, .. }
+                | MicroOp::FenceI { pc_offset }
                 | MicroOp::LrW { pc_offset, .. }
                 | MicroOp::LrD { pc_offset, .. }
                 | MicroOp::ScW { pc_offset, .. }
@@ -1142,12 +1508,24 @@ impl Cpu {
                     return BlockExecResult::Exit { next_pc: pc };
                 }
 
-                MicroOp::Wfi { pc_offset: _ } => {
-                    // WFI: spin briefly and continue
-                    for _ in 0..10 {
-                        std::hint::spin_loop();
+                MicroOp::Wfi { pc_offset } => {
+                    // The interpreter flags the hart idle
+                    let pc = base_pc.wrapping_add(pc_offset as u64);
+                    return BlockExecResult::Exit { next_pc: pc };
+                }
+
+                MicroOp::Bit { op, rd, rs1, rs2 } => {
+                    let val = op.eval(self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                    if rd != 0 {
+                        self.regs[rd as usize] = val;
+                    }
+                }
+
+                MicroOp::BitImm { op, rd, rs1, shamt } => {
+                    let val = op.eval(self.regs[rs1 as usize], shamt as u64);
+                    if rd != 0 {
+                        self.regs[rd as usize] = val;
                     }
-                    // Continue to next instruction in block
                 }
 
                 MicroOp::Fence => {
@@ -1160,8 +1538,45 @@ impl Cpu {
         BlockExecResult::Continue(base_pc.wrapping_add(block.byte_len as u64))
     }
 
+    /// Execute a CSR access inside a block.
+    ///
+    /// `update` maps the old value to the value to write, or `None` when the
+    /// instruction only reads. Returns false, leaving all state untouched,
+    /// if the access would trap or writes a CSR with side effects; the
+    /// caller then exits so the interpreter re-runs the instruction.
+    #[inline]
+    fn block_csr(
+        &mut self,
+        bus: &dyn Bus,
+        csr: u16,
+        rd: u8,
+        update: impl FnOnce(u64) -> Option<u64>,
+    ) -> bool {
+        // Dynamic read for time CSR to reflect CLINT MTIME, as in the interpreter
+        let old = if csr == CSR_TIME {
+            if self.check_counter_enabled(csr).is_err() {
+                return false;
+            }
+            bus.read_mtime().unwrap_or(0)
+        } else {
+            match self.read_csr(csr) {
+                Ok(v) => v,
+                Err(_) => return false,
+            }
+        };
+        if let Some(new) = update(old)
+            && (csr_write_has_side_effects(csr) || self.write_csr(csr, new).is_err())
+        {
+            return false;
+        }
+        if rd != 0 {
+            self.regs[rd as usize] = old;
+        }
+        true
+    }
+
     /// Translate address without entering trap handler (for block execution)
-    fn translate_addr_for_block(
+    pub(super) fn translate_addr_for_block(
         &mut self,
         bus: &dyn Bus,
         vaddr: u64,
@@ -1169,7 +1584,9 @@ impl Cpu {
     ) -> Result<u64, Trap> {
         let satp = self.csrs[CSR_SATP as usize];
         let mstatus = self.csrs[CSR_MSTATUS as usize];
-        mmu::translate(bus, &mut self.tlb, self.mode, satp, mstatus, vaddr, access)
+        let pa = mmu::translate(bus, &mut self.tlb, self.mode, satp, mstatus, vaddr, access)?;
+        self.pmp_check(vaddr, pa, 1, access)?;
+        Ok(pa)
     }
 
     /// Handle block execution result and return to normal step() flow
@@ -1332,6 +1749,7 @@ impl Cpu {
 mod tests {
     use super::*;
     use crate::bus::SystemBus;
+    use crate::cpu::{load_test_program, test_hart};
 
     // --- Memory layout tests (Task 10.1) ---------------------------------
 
@@ -2041,4 +2459,271 @@ mod tests {
             _ => panic!("Expected MachineExternalInterrupt, got {:?}", res),
         }
     }
+
+    #[test]
+    fn test_block_executes_csr_ops_inline() {
+        let program = [
+            encode_i(0x340, 5, 5, 0, 0x73), // csrrwi x0, mscratch, 5
+            encode_i(0x340, 0, 2, 1, 0x73), // csrr x1, mscratch
+            encode_i(0x340, 2, 6, 0, 0x73), // csrrsi x0, mscratch, 2
+            encode_i(0x340, 0, 2, 3, 0x73), // csrr x3, mscratch
+            encode_i(0x300, 0, 2, 4, 0x73), // csrr x4, mstatus (read-only)
+            encode_i(1, 0, 0, 2, 0x13),     // addi x2, x0, 1
+            0x0000_006f,                    // j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.use_blocks = true;
+
+        // One step runs the whole block up to the jump
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.read_reg(Register::X1), 5);
+        assert_eq!(cpu.read_reg(Register::X3), 7);
+        assert_eq!(cpu.read_reg(Register::X2), 1);
+        assert_eq!(cpu.pc, 0x8000_0018);
+    }
+
+    #[test]
+    fn test_hot_loop_forms_trace() {
+        let program = [
+            encode_i(1, 1, 0, 1, 0x13),   // 0x00: addi x1, x1, 1
+            0x0080_006f,                  // 0x04: j 0x0c
+            0x0000_0013,                  // 0x08: nop
+            encode_i(1, 2, 0, 2, 0x13),   // 0x0c: addi x2, x2, 1
+            encode_b(-16, 3, 2, 4, 0x63), // 0x10: blt x2, x3, 0x00
+            0x0000_006f,                  // 0x14: j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.use_blocks = true;
+        cpu.regs[3] = 200;
+
+        let mut dispatches = 0;
+        while cpu.pc != 0x8000_0014 {
+            cpu.step(&bus).unwrap();
+            dispatches += 1;
+            assert!(dispatches < 400, "loop did not terminate");
+        }
+
+        assert_eq!(cpu.read_reg(Register::X1), 200);
+        assert_eq!(cpu.read_reg(Register::X2), 200);
+        assert!(cpu.traces.formed > 0);
+        assert!(cpu.traces.executions > 0);
+        // Trace dispatch runs many blocks per step
+        assert!(dispatches < 200, "{} dispatches", dispatches);
+    }
+
+    #[test]
+    fn test_block_successors_are_prefetched() {
+        let program = [
+            encode_i(1, 0, 0, 1, 0x13), // 0x00: addi x1, x0, 1
+            encode_b(8, 0, 1, 1, 0x63), // 0x04: bne x1, x0, 0x0c
+            encode_i(2, 0, 0, 2, 0x13), // 0x08: addi x2, x0, 2
+            encode_i(3, 0, 0, 3, 0x13), // 0x0c: addi x3, x0, 3
+            0x0000_006f,                // 0x10: j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.use_blocks = true;
+
+        // Compiling the first block also compiles both sides of the branch
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.pc, 0x8000_000c);
+        assert_eq!(cpu.block_cache.prefetched, 2);
+        assert!(cpu.block_cache.peek(0x8000_0008).is_some());
+
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.read_reg(Register::X3), 3);
+        assert_eq!(cpu.read_reg(Register::X2), 0);
+        let (prefetched, hits, _) = cpu.block_cache.prefetch_stats();
+        assert_eq!((prefetched, hits), (2, 1));
+    }
+
+    #[test]
+    fn test_blocks_and_interpreter_charge_the_same_cycles() {
+        use crate::engine::timing::{ALU_CYCLES, BRANCH_CYCLES};
+
+        let program = [
+            encode_i(10, 0, 0, 3, 0x13), // 0x00: addi x3, x0, 10
+            encode_i(1, 1, 0, 1, 0x13),  // 0x04: addi x1, x1, 1
+            encode_b(-4, 3, 1, 1, 0x63), // 0x08: bne x1, x3, 0x04
+            0x0000_006f,                 // 0x0c: j .
+        ];
+        let run = |use_blocks: bool| {
+            let (mut cpu, bus) = test_hart(&program);
+            cpu.use_blocks = use_blocks;
+            while cpu.pc != 0x8000_000c {
+                cpu.step(&bus).unwrap();
+            }
+            assert_eq!(cpu.read_reg(Register::X1), 10);
+            cpu.cycles
+        };
+
+        let expected = ALU_CYCLES + 10 * (ALU_CYCLES + BRANCH_CYCLES);
+        assert_eq!(run(false), expected as u64);
+        assert_eq!(run(true), expected as u64);
+    }
+
+    #[test]
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    fn test_jit_runs_hot_loop() {
+        let program = [
+            encode_i(1, 1, 0, 1, 0x13),   // 0x00: addi x1, x1, 1
+            0x0080_006f,                  // 0x04: j 0x0c
+            0x0000_0013,                  // 0x08: nop
+            encode_i(1, 2, 0, 2, 0x13),   // 0x0c: addi x2, x2, 1
+            encode_b(-16, 3, 2, 4, 0x63), // 0x10: blt x2, x3, 0x00
+            0x0000_006f,                  // 0x14: j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.enable_jit(JitConfig {
+            hot_threshold: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        cpu.regs[3] = 200;
+
+        let mut dispatches = 0;
+        while cpu.pc != 0x8000_0014 {
+            cpu.step(&bus).unwrap();
+            dispatches += 1;
+            assert!(dispatches < 400, "loop did not terminate");
+        }
+
+        assert_eq!(cpu.read_reg(Register::X1), 200);
+        assert_eq!(cpu.read_reg(Register::X2), 200);
+        let jit = cpu.jit.as_ref().unwrap();
+        assert_eq!(jit.compiled, 2);
+        assert!(jit.executions > 300);
+    }
+
+    #[test]
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    fn test_jit_verify_agrees_with_interpreter() {
+        let program = [
+            encode_i(5, 1, 0, 1, 0x13),   // 0x00: addi x1, x1, 5
+            encode_i(3, 1, 1, 4, 0x13),   // 0x04: slli x4, x1, 3
+            encode_i(1, 2, 0, 2, 0x13),   // 0x08: addi x2, x2, 1
+            encode_b(-12, 3, 2, 4, 0x63), // 0x0c: blt x2, x3, 0x00
+            0x0000_006f,                  // 0x10: j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.enable_jit(JitConfig {
+            hot_threshold: 2,
+            verify: true,
+            ..Default::default()
+        })
+        .unwrap();
+        cpu.regs[3] = 100;
+
+        let mut dispatches = 0;
+        while cpu.pc != 0x8000_0010 {
+            cpu.step(&bus).unwrap();
+            dispatches += 1;
+            assert!(dispatches < 200, "loop did not terminate");
+        }
+
+        assert_eq!(cpu.read_reg(Register::X1), 500);
+        assert_eq!(cpu.read_reg(Register::X4), 4000);
+        let diag = cpu.jit.as_ref().unwrap().diagnostics();
+        assert!(diag.verified > 90);
+        assert_eq!(diag.divergences, 0);
+    }
+
+    #[test]
+    fn test_block_exits_for_side_effect_csr_writes() {
+        let bus = make_bus();
+        let mut cpu = Cpu::new(0x8000_0000, 0);
+        cpu.use_blocks = true;
+
+        bus.write32(0x8000_0000, encode_i(1, 0, 0, 1, 0x13))
+            .unwrap(); // addi x1, x0, 1
+        bus.write32(0x8000_0004, encode_i(0x180, 0, 1, 0, 0x73))
+            .unwrap(); // csrw satp, x0
+        bus.write32(0x8000_0008, encode_i(2, 0, 0, 2, 0x13))
+            .unwrap(); // addi x2, x0, 2
+
+        // The block stops at the satp write and the interpreter executes it
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.read_reg(Register::X1), 1);
+        assert_eq!(cpu.pc, 0x8000_0008);
+        assert_eq!(cpu.read_reg(Register::X2), 0);
+    }
+
+    /// Loop on `addi x1, x1, 1` until x1 reaches 100, then patch that
+    /// instruction to `addi x1, x1, 100` with a guest store and rerun it.
+    fn run_self_modifying_loop(cpu: &mut Cpu, bus: &SystemBus) {
+        cpu.regs[3] = 100;
+        cpu.regs[4] = 0x8000_0000;
+        cpu.regs[5] = encode_i(100, 1, 0, 1, 0x13) as u64;
+        let program = [
+            encode_i(1, 1, 0, 1, 0x13),  // 0x00: addi x1, x1, 1
+            encode_b(-4, 3, 1, 4, 0x63), // 0x04: blt x1, x3, 0x00
+            encode_s(0, 5, 4, 2, 0x23),  // 0x08: sw x5, 0(x4)
+            0x0000_006f,                 // 0x0c: j .
+        ];
+        load_test_program(bus, &program);
+
+        let run = |cpu: &mut Cpu| {
+            let mut dispatches = 0;
+            while cpu.pc != 0x8000_000c {
+                cpu.step(bus).unwrap();
+                dispatches += 1;
+                assert!(dispatches < 1000, "loop did not terminate");
+            }
+        };
+        run(cpu);
+        assert_eq!(cpu.read_reg(Register::X1), 100);
+
+        // No FENCE.I: the store alone must retire the compiled loop body
+        cpu.pc = 0x8000_0000;
+        run(cpu);
+        assert_eq!(cpu.read_reg(Register::X1), 200);
+    }
+
+    #[test]
+    fn test_block_store_invalidates_patched_code() {
+        let bus = make_bus();
+        let mut cpu = Cpu::new(0x8000_0000, 0);
+        cpu.use_blocks = true;
+        run_self_modifying_loop(&mut cpu, &bus);
+        assert!(cpu.traces.formed > 0);
+    }
+
+    #[test]
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    fn test_jit_store_invalidates_patched_code() {
+        let bus = make_bus();
+        let mut cpu = Cpu::new(0x8000_0000, 0);
+        cpu.enable_jit(JitConfig {
+            hot_threshold: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        run_self_modifying_loop(&mut cpu, &bus);
+        assert!(cpu.jit.as_ref().unwrap().compiled > 0);
+    }
+
+    #[test]
+    fn test_fence_i_picks_up_host_patched_code() {
+        let program = [
+            encode_i(1, 0, 0, 1, 0x13), // 0x00: addi x1, x0, 1
+            0x0000_006f | (4 << 21),    // 0x04: j 0x08
+            0x0000_100f,                // 0x08: fence.i
+            0x0000_006f,                // 0x0c: j .
+        ];
+        let (mut cpu, bus) = test_hart(&program);
+        cpu.use_blocks = true;
+        while cpu.pc != 0x8000_000c {
+            cpu.step(&bus).unwrap();
+        }
+        assert_eq!(cpu.read_reg(Register::X1), 1);
+
+        // A DMA-style write bypasses the store path; the guest then runs FENCE.I
+        bus.write32(0x8000_0000, encode_i(7, 0, 0, 1, 0x13))
+            .unwrap();
+        cpu.pc = 0x8000_0008;
+        cpu.step(&bus).unwrap();
+
+        cpu.pc = 0x8000_0000;
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.read_reg(Register::X1), 7);
+    }
 }
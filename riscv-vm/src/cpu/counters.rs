@@ -0,0 +1,283 @@
+//! Hardware performance counters (Zicntr/Zihpm).
+//!
+//! `mcycle` counts the cycles of the timing model ([`Cpu::cycles`]) and
+//! `minstret` the instructions executed by any engine. Each of the 29
+//! `mhpmcounter`s counts the event selected by its `mhpmevent`, which lets
+//! a guest tell apart the instructions run by the interpreter, the block
+//! engine and native JIT code:
+//!
+//! | `mhpmevent`                  | Counts                                  |
+//! |------------------------------|-----------------------------------------|
+//! | [`EVENT_INTERP_INSTRET`] (1) | instructions run by the interpreter     |
+//! | [`EVENT_BLOCK_INSTRET`] (2)  | instructions run by interpreted blocks  |
+//! | [`EVENT_JIT_INSTRET`] (3)    | instructions run by native JIT code     |
+//! | [`EVENT_TRAPS`] (4)          | exceptions and interrupts taken         |
+//!
+//! Counters are kept as an offset from running totals, so counting costs
+//! nothing until a counter CSR is accessed, and they stop while their bit
+//! in `mcountinhibit` is set. The unprivileged `cycle`, `instret`, `time`
+//! and `hpmcounterN` views can be read from S-mode when enabled in
+//! `mcounteren`, and from U-mode when also enabled in `scounteren`.
+//! Blocks add their counts when they finish, so a read inside a block sees
+//! the value from the block's start.
+
+use super::core::Cpu;
+use super::csr::{CSR_MCOUNTEREN, CSR_TIME};
+use super::types::{Mode, Trap};
+
+pub const CSR_SCOUNTEREN: u16 = 0x106;
+pub const CSR_MCOUNTINHIBIT: u16 = 0x320;
+pub const CSR_MHPMEVENT3: u16 = 0x323;
+pub const CSR_MHPMEVENT31: u16 = 0x33F;
+pub const CSR_MCYCLE: u16 = 0xB00;
+pub const CSR_MINSTRET: u16 = 0xB02;
+pub const CSR_MHPMCOUNTER3: u16 = 0xB03;
+pub const CSR_MHPMCOUNTER31: u16 = 0xB1F;
+pub const CSR_CYCLE: u16 = 0xC00;
+pub const CSR_INSTRET: u16 = 0xC02;
+pub const CSR_HPMCOUNTER3: u16 = 0xC03;
+pub const CSR_HPMCOUNTER31: u16 = 0xC1F;
+
+pub const EVENT_INTERP_INSTRET: u64 = 1;
+pub const EVENT_BLOCK_INSTRET: u64 = 2;
+pub const EVENT_JIT_INSTRET: u64 = 3;
+pub const EVENT_TRAPS: u64 = 4;
+
+/// Running totals of the countable events.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Events {
+    pub interp_instret: u64,
+    pub block_instret: u64,
+    pub jit_instret: u64,
+    pub traps: u64,
+}
+
+impl Events {
+    fn instret(&self) -> u64 {
+        self.interp_instret + self.block_instret + self.jit_instret
+    }
+
+    fn get(&self, event: u64) -> u64 {
+        match event {
+            EVENT_INTERP_INSTRET => self.interp_instret,
+            EVENT_BLOCK_INSTRET => self.block_instret,
+            EVENT_JIT_INSTRET => self.jit_instret,
+            EVENT_TRAPS => self.traps,
+            _ => 0,
+        }
+    }
+}
+
+/// Counter state of a hart; see the [module docs](self).
+#[derive(Clone, Debug, Default)]
+pub(super) struct Counters {
+    pub events: Events,
+    /// Per counter: value = running total - offset, while not inhibited.
+    offset: [u64; 32],
+    /// Per counter: value while inhibited.
+    frozen: [u64; 32],
+    /// Totals at the last [`Cpu::reset_perf_counters`].
+    host_base: Events,
+}
+
+/// Counter values reported to the host by [`Cpu::perf_counters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerfCounters {
+    /// Guest-visible `mcycle`.
+    pub cycles: u64,
+    /// Guest-visible `minstret`.
+    pub instret: u64,
+    /// Instructions run by the interpreter since the last reset.
+    pub interp_instret: u64,
+    /// Instructions run by interpreted blocks since the last reset.
+    pub block_instret: u64,
+    /// Instructions run by native JIT code since the last reset.
+    pub jit_instret: u64,
+    /// Exceptions and interrupts taken since the last reset.
+    pub traps: u64,
+}
+
+/// Index of the counter behind `addr` (0 = cycle, 2 = instret, 3-31 =
+/// hpmcounters), or `None` if `addr` is not a counter CSR. `time` is read
+/// from the CLINT instead.
+#[inline]
+pub fn counter_index(addr: u16) -> Option<usize> {
+    let counter = matches!(addr, CSR_MCYCLE..=CSR_MHPMCOUNTER31 | CSR_CYCLE..=CSR_HPMCOUNTER31);
+    let index = (addr & 0x1F) as usize;
+    (counter && index != 1).then_some(index)
+}
+
+/// Whether writes to `addr` go through [`Cpu::write_counter_csr`].
+#[inline]
+pub fn is_counter_csr(addr: u16) -> bool {
+    counter_index(addr).is_some()
+        || matches!(addr, CSR_MCOUNTINHIBIT | CSR_MHPMEVENT3..=CSR_MHPMEVENT31)
+}
+
+impl Cpu {
+    fn counter_total(&self, index: usize) -> u64 {
+        let events = &self.counters.events;
+        match index {
+            0 => self.cycles,
+            2 => events.instret(),
+            3.. => events.get(self.csrs[CSR_MHPMEVENT3 as usize + index - 3]),
+            _ => 0,
+        }
+    }
+
+    fn counter_value(&self, index: usize) -> u64 {
+        if (self.csrs[CSR_MCOUNTINHIBIT as usize] >> index) & 1 != 0 {
+            self.counters.frozen[index]
+        } else {
+            self.counter_total(index)
+                .wrapping_sub(self.counters.offset[index])
+        }
+    }
+
+    fn set_counter(&mut self, index: usize, value: u64) {
+        if (self.csrs[CSR_MCOUNTINHIBIT as usize] >> index) & 1 != 0 {
+            self.counters.frozen[index] = value;
+        } else {
+            self.counters.offset[index] = self.counter_total(index).wrapping_sub(value);
+        }
+    }
+
+    /// Check that the current mode may read the unprivileged counter
+    /// `cycle`/`time`/`instret`/`hpmcounterN` at `addr`.
+    pub(super) fn check_counter_enabled(&self, addr: u16) -> Result<(), Trap> {
+        let bit = 1u64 << (addr & 0x1F);
+        let enabled = match self.mode {
+            Mode::Machine => true,
+            Mode::Supervisor => self.csrs[CSR_MCOUNTEREN as usize] & bit != 0,
+            Mode::User => {
+                self.csrs[CSR_MCOUNTEREN as usize] & self.csrs[CSR_SCOUNTEREN as usize] & bit != 0
+            }
+        };
+        if enabled {
+            Ok(())
+        } else {
+            Err(Trap::IllegalInstruction(addr as u64))
+        }
+    }
+
+    /// Read counter CSR `addr` (see [`counter_index`]).
+    pub(super) fn read_counter(&self, addr: u16, index: usize) -> Result<u64, Trap> {
+        if addr >= CSR_CYCLE {
+            self.check_counter_enabled(addr)?;
+        } else if self.mode != Mode::Machine {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        Ok(self.counter_value(index))
+    }
+
+    /// Write CSR `addr`, keeping counter values intact across changes to
+    /// `mcountinhibit` and `mhpmevent`.
+    pub(super) fn write_counter_csr(&mut self, addr: u16, val: u64) -> Result<(), Trap> {
+        if let Some(index) = counter_index(addr) {
+            // The unprivileged views are read-only
+            if addr >= CSR_CYCLE {
+                return self.csrs.write(addr, val, self.mode);
+            }
+            if self.mode != Mode::Machine {
+                return Err(Trap::IllegalInstruction(addr as u64));
+            }
+            self.set_counter(index, val);
+            return Ok(());
+        }
+
+        let values: [u64; 32] = std::array::from_fn(|i| self.counter_value(i));
+        self.csrs.write(addr, val, self.mode)?;
+        // `time` cannot be inhibited
+        self.csrs[CSR_MCOUNTINHIBIT as usize] &= !(1 << (CSR_TIME & 0x1F));
+        for (index, value) in values.into_iter().enumerate() {
+            if index != 1 {
+                self.set_counter(index, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Current counter values; see [`PerfCounters`].
+    pub fn perf_counters(&self) -> PerfCounters {
+        let events = &self.counters.events;
+        let base = &self.counters.host_base;
+        PerfCounters {
+            cycles: self.counter_value(0),
+            instret: self.counter_value(2),
+            interp_instret: events.interp_instret - base.interp_instret,
+            block_instret: events.block_instret - base.block_instret,
+            jit_instret: events.jit_instret - base.jit_instret,
+            traps: events.traps - base.traps,
+        }
+    }
+
+    /// Zero every counter, guest-visible ones included, e.g. before
+    /// running a benchmark.
+    pub fn reset_perf_counters(&mut self) {
+        for index in (0..32).filter(|&i| i != 1) {
+            self.set_counter(index, 0);
+        }
+        self.counters.host_base = self.counters.events;
+        self.tlb.reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE, SystemBus};
+
+    const NOP: u32 = 0x0000_0013;
+
+    fn run(cpu: &mut Cpu, bus: &SystemBus, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(bus).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_counters_count_and_inhibit() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        for i in 0..16 {
+            bus.write32(DRAM_BASE + i * 4, NOP).unwrap();
+        }
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.write_csr(CSR_MHPMEVENT3, EVENT_INTERP_INSTRET).unwrap();
+        run(&mut cpu, &bus, 4);
+        assert_eq!(cpu.read_csr(CSR_MINSTRET).unwrap(), 4);
+        assert_eq!(cpu.read_csr(CSR_MHPMCOUNTER3).unwrap(), 4);
+        assert_eq!(cpu.read_csr(CSR_CYCLE).unwrap(), cpu.cycles);
+
+        // Stop instret, then move it; the value holds until re-enabled
+        cpu.write_csr(CSR_MCOUNTINHIBIT, 1 << 2).unwrap();
+        cpu.write_csr(CSR_MINSTRET, 100).unwrap();
+        run(&mut cpu, &bus, 2);
+        assert_eq!(cpu.read_csr(CSR_MINSTRET).unwrap(), 100);
+        cpu.write_csr(CSR_MCOUNTINHIBIT, 0).unwrap();
+        run(&mut cpu, &bus, 3);
+        assert_eq!(cpu.read_csr(CSR_MINSTRET).unwrap(), 103);
+        assert_eq!(cpu.read_csr(CSR_MHPMCOUNTER3).unwrap(), 9);
+
+        cpu.reset_perf_counters();
+        run(&mut cpu, &bus, 1);
+        let counters = cpu.perf_counters();
+        assert_eq!(counters.instret, 1);
+        assert_eq!(counters.interp_instret, 1);
+        assert_eq!(counters.jit_instret, 0);
+    }
+
+    #[test]
+    fn test_unprivileged_counters_need_enable_bits() {
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.mode = Mode::Supervisor;
+        assert!(cpu.read_csr(CSR_CYCLE).is_err());
+        assert!(cpu.read_csr(CSR_MCYCLE).is_err());
+        cpu.csrs[CSR_MCOUNTEREN as usize] = 1 << 2;
+        assert!(cpu.read_csr(CSR_INSTRET).is_ok());
+        cpu.mode = Mode::User;
+        assert!(cpu.read_csr(CSR_INSTRET).is_err());
+        cpu.csrs[CSR_SCOUNTEREN as usize] = 1 << 2;
+        assert!(cpu.read_csr(CSR_INSTRET).is_ok());
+        assert!(cpu.check_counter_enabled(CSR_TIME).is_err());
+    }
+}
@@ -1,18 +1,129 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::ops::{Index, IndexMut};
+use std::str::FromStr;
 
+use super::counters::{
+    CSR_CYCLE, CSR_HPMCOUNTER31, CSR_MCOUNTINHIBIT, CSR_MCYCLE, CSR_MHPMCOUNTER31, CSR_MHPMEVENT3,
+    CSR_MHPMEVENT31, CSR_SCOUNTEREN,
+};
+use super::fpu::{MSTATUS_FS, MSTATUS_SD};
+use super::pmp::{self, CSR_PMPADDR63, CSR_PMPCFG0};
 use super::types::Trap;
+use super::vector;
 
 pub use super::types::Mode;
 
+/// What an access to a CSR this hart does not implement does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CsrPolicy {
+    /// Raise an illegal-instruction exception, as the privileged spec
+    /// requires, to check a guest only uses the CSRs listed in
+    /// [`is_implemented`].
+    Strict,
+    /// Read as zero and ignore writes, logging each access at trace level,
+    /// so guests that probe CSRs without expecting the trap keep running.
+    /// Accesses from too low a privilege level and to the Debug-mode CSRs
+    /// still trap.
+    #[default]
+    Permissive,
+}
+
+impl fmt::Display for CsrPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CsrPolicy::Strict => "strict",
+            CsrPolicy::Permissive => "permissive",
+        })
+    }
+}
+
+impl FromStr for CsrPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(CsrPolicy::Strict),
+            "permissive" => Ok(CsrPolicy::Permissive),
+            _ => Err(format!(
+                "unknown CSR policy '{}' (expected strict or permissive)",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether this hart implements the CSR at `addr`.
+pub fn is_implemented(addr: u16) -> bool {
+    vector::is_vector_csr(addr)
+        || matches!(
+            addr,
+            CSR_FFLAGS..=CSR_FCSR
+                | CSR_SSTATUS
+                | CSR_SIE
+                | CSR_STVEC
+                | CSR_SCOUNTEREN
+                | CSR_SENVCFG
+                | CSR_SSCRATCH..=CSR_SIP
+                | CSR_STIMECMP
+                | CSR_SATP
+                | CSR_MSTATUS..=CSR_MCOUNTEREN
+                | CSR_MENVCFG
+                | CSR_MCOUNTINHIBIT
+                | CSR_MHPMEVENT3..=CSR_MHPMEVENT31
+                | CSR_MSCRATCH..=CSR_MIP
+                | CSR_PMPCFG0..=CSR_PMPADDR63
+                | CSR_CYCLE..=CSR_HPMCOUNTER31
+                | CSR_MVENDORID..=CSR_MCONFIGPTR
+        )
+        // There is no machine-mode copy of time
+        || (matches!(addr, CSR_MCYCLE..=CSR_MHPMCOUNTER31) && addr != CSR_MCYCLE + 1)
+}
+
+/// The Debug-mode CSRs (dcsr, dpc, dscratch0/1), which trap outside Debug
+/// mode on every hart whatever the policy.
+fn is_debug_csr(addr: u16) -> bool {
+    (0x7B0..=0x7BF).contains(&addr)
+}
+
 /// Compact CSR storage with privilege-aware access helpers.
 pub struct CsrFile {
     storage: [u64; 4096],
+    policy: CsrPolicy,
 }
 
 impl CsrFile {
     pub const fn new() -> Self {
-        Self { storage: [0; 4096] }
+        Self {
+            storage: [0; 4096],
+            policy: CsrPolicy::Permissive,
+        }
+    }
+
+    pub fn policy(&self) -> CsrPolicy {
+        self.policy
+    }
+
+    /// Choose what accesses to unimplemented CSRs do.
+    pub fn set_policy(&mut self, policy: CsrPolicy) {
+        self.policy = policy;
+    }
+
+    /// `Ok(true)` if `addr` is implemented, `Ok(false)` if the access is to
+    /// be ignored under the permissive policy, otherwise the trap to raise.
+    fn check_implemented(&self, addr: u16, access: &str) -> Result<bool, Trap> {
+        if is_implemented(addr) {
+            return Ok(true);
+        }
+        if self.policy == CsrPolicy::Strict || is_debug_csr(addr) {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        log::trace!(
+            "{} of unimplemented CSR {:#05x} ignored (permissive CSR policy)",
+            access,
+            addr
+        );
+        Ok(false)
     }
 
     pub fn export(&self) -> HashMap<u16, u64> {
@@ -35,17 +146,41 @@ impl CsrFile {
         }
     }
 
+    /// Floating-point CSRs are only accessible while `mstatus.FS` is not Off.
+    fn fp_csr_check(&self, addr: u16) -> Result<(), Trap> {
+        let is_fp = matches!(addr, CSR_FFLAGS | CSR_FRM | CSR_FCSR);
+        if is_fp && self.storage[CSR_MSTATUS as usize] & MSTATUS_FS == 0 {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        Ok(())
+    }
+
     pub fn read(&self, addr: u16, mode: Mode) -> Result<u64, Trap> {
         let required_priv = (addr >> 8) & 0x3;
         let current_priv = mode.privilege_level() as u16;
         if current_priv < required_priv {
             return Err(Trap::IllegalInstruction(addr as u64));
         }
+        self.fp_csr_check(addr)?;
+        if !self.check_implemented(addr, "read")? {
+            return Ok(0);
+        }
 
         match addr {
+            // fflags and frm are views of fcsr
+            CSR_FFLAGS => Ok(self.storage[CSR_FCSR as usize] & 0x1F),
+            CSR_FRM => Ok((self.storage[CSR_FCSR as usize] >> 5) & 0x7),
+            CSR_FCSR => Ok(self.storage[CSR_FCSR as usize] & 0xFF),
             CSR_SSTATUS => {
                 let mstatus = self.storage[CSR_MSTATUS as usize];
-                let mask = (1 << 1) | (1 << 5) | (1 << 8) | (3 << 13) | (1 << 18) | (1 << 19);
+                let mask = (1 << 1)
+                    | (1 << 5)
+                    | (1 << 8)
+                    | (3 << 9)
+                    | (3 << 13)
+                    | (1 << 18)
+                    | (1 << 19)
+                    | MSTATUS_SD;
                 Ok(mstatus & mask)
             }
             CSR_SIE => {
@@ -73,11 +208,25 @@ impl CsrFile {
         if current_priv < required_priv {
             return Err(Trap::IllegalInstruction(addr as u64));
         }
+        self.fp_csr_check(addr)?;
+        if !self.check_implemented(addr, "write")? {
+            return Ok(());
+        }
 
         match addr {
+            CSR_FFLAGS | CSR_FRM | CSR_FCSR => {
+                let fcsr = self.storage[CSR_FCSR as usize];
+                self.storage[CSR_FCSR as usize] = match addr {
+                    CSR_FFLAGS => (fcsr & !0x1F) | (val & 0x1F),
+                    CSR_FRM => (fcsr & !0xE0) | ((val & 0x7) << 5),
+                    _ => val & 0xFF,
+                };
+                self.storage[CSR_MSTATUS as usize] |= MSTATUS_FS | MSTATUS_SD;
+            }
             CSR_SSTATUS => {
                 let mut mstatus = self.storage[CSR_MSTATUS as usize];
-                let mask = (1 << 1) | (1 << 5) | (1 << 8) | (3 << 13) | (1 << 18) | (1 << 19);
+                let mask =
+                    (1 << 1) | (1 << 5) | (1 << 8) | (3 << 9) | (3 << 13) | (1 << 18) | (1 << 19);
                 mstatus = (mstatus & !mask) | (val & mask);
                 self.storage[CSR_MSTATUS as usize] = mstatus;
             }
@@ -93,6 +242,7 @@ impl CsrFile {
                 mip = (mip & !mask) | (val & mask);
                 self.storage[CSR_MIP as usize] = mip;
             }
+            CSR_PMPCFG0..=CSR_PMPADDR63 => pmp::write_csr(self, addr, val)?,
             _ => {
                 self.storage[addr as usize] = val;
             }
@@ -122,6 +272,11 @@ impl IndexMut<usize> for CsrFile {
     }
 }
 
+// Floating-point CSRs (F/D extensions)
+pub const CSR_FFLAGS: u16 = 0x001;
+pub const CSR_FRM: u16 = 0x002;
+pub const CSR_FCSR: u16 = 0x003;
+
 // Common CSR addresses used by the privileged architecture.
 pub const CSR_SATP: u16 = 0x180;
 
@@ -132,6 +287,7 @@ pub const CSR_MIDELEG: u16 = 0x303;
 pub const CSR_MIE: u16 = 0x304;
 pub const CSR_MTVEC: u16 = 0x305;
 
+pub const CSR_MSCRATCH: u16 = 0x340;
 pub const CSR_MEPC: u16 = 0x341;
 pub const CSR_MCAUSE: u16 = 0x342;
 pub const CSR_MTVAL: u16 = 0x343;
@@ -141,6 +297,7 @@ pub const CSR_MIP: u16 = 0x344;
 pub const CSR_SSTATUS: u16 = 0x100;
 pub const CSR_SIE: u16 = 0x104;
 pub const CSR_STVEC: u16 = 0x105;
+pub const CSR_SENVCFG: u16 = 0x10A;
 pub const CSR_SSCRATCH: u16 = 0x140;
 pub const CSR_SEPC: u16 = 0x141;
 pub const CSR_SCAUSE: u16 = 0x142;
@@ -158,3 +315,79 @@ pub const CSR_MVENDORID: u16 = 0xF11; // Vendor ID
 pub const CSR_MARCHID: u16 = 0xF12; // Architecture ID
 pub const CSR_MIMPID: u16 = 0xF13; // Implementation ID
 pub const CSR_MHARTID: u16 = 0xF14; // Hardware thread ID
+pub const CSR_MCONFIGPTR: u16 = 0xF15; // Configuration structure (none)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+    use crate::cpu::{Cpu, test_hart};
+
+    /// A vendor CSR this hart does not implement
+    const CSR_CUSTOM: u16 = 0x7C0;
+
+    // csrrs x5, 0x7c0, x0; csrrw x0, 0x7c0, x5
+    const PROBE: [u32; 2] = [0x7C00_22F3, 0x7C02_9073];
+
+    fn probe_rig(policy: CsrPolicy) -> (Cpu, SystemBus) {
+        let (mut cpu, bus) = test_hart(&PROBE);
+        cpu.set_csr_policy(policy);
+        cpu.regs[5] = 0x55;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_strict_policy_traps_on_unimplemented_csr() {
+        let (mut cpu, bus) = probe_rig(CsrPolicy::Strict);
+        let _ = cpu.step(&bus);
+        assert_eq!(cpu.read_csr(CSR_MCAUSE).unwrap(), 2);
+        assert_eq!(cpu.read_csr(CSR_MEPC).unwrap(), DRAM_BASE);
+        assert_eq!(cpu.regs[5], 0x55);
+    }
+
+    #[test]
+    fn test_permissive_policy_reads_zero_and_ignores_writes() {
+        let (mut cpu, bus) = probe_rig(CsrPolicy::Permissive);
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.regs[5], 0);
+        cpu.regs[5] = 0x55;
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.pc, DRAM_BASE + 8);
+        assert_eq!(cpu.read_csr(CSR_CUSTOM).unwrap(), 0);
+        assert_eq!(cpu.read_csr(CSR_MCAUSE).unwrap(), 0);
+
+        // Accesses no hart allows still trap
+        assert!(cpu.read_csr(0x7B0).is_err());
+        cpu.mode = Mode::Supervisor;
+        assert!(cpu.read_csr(CSR_CUSTOM).is_err());
+        assert!(cpu.read_csr(0x5C0).is_ok());
+    }
+
+    #[test]
+    fn test_implemented_csrs_are_accessible_under_strict_policy() {
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        assert_eq!(cpu.csrs.policy(), CsrPolicy::Permissive);
+        cpu.set_csr_policy(CsrPolicy::Strict);
+        cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_FS;
+        for addr in [
+            CSR_FCSR,
+            CSR_SSTATUS,
+            CSR_SCOUNTEREN,
+            CSR_SENVCFG,
+            CSR_STIMECMP,
+            CSR_SATP,
+            CSR_MSCRATCH,
+            CSR_MENVCFG,
+            CSR_MCOUNTINHIBIT,
+            CSR_PMPADDR63,
+            CSR_MHPMCOUNTER31,
+            CSR_TIME,
+            CSR_MCONFIGPTR,
+        ] {
+            assert!(cpu.read_csr(addr).is_ok(), "{:#x}", addr);
+        }
+        assert!(cpu.read_csr(CSR_MCYCLE + 1).is_err());
+        assert!(cpu.write_csr(0x5C0, 1).is_err());
+        assert_eq!("Permissive".parse::<CsrPolicy>(), Ok(CsrPolicy::Permissive));
+    }
+}
@@ -0,0 +1,180 @@
+//! Trap breakpoints for debuggers.
+//!
+//! A debugger arms one-shot conditions with [`Cpu::break_on`]: the next trap,
+//! the next exception with a given cause, or the next `ecall` from a given
+//! privilege mode. They are checked in the trap path, so a break fires on
+//! the exact trap without single-stepping the guest.
+//!
+//! When a condition fires it is disarmed and the trap is latched as a
+//! [`TrapBreakHit`] after trap entry (`pc` is at the handler). The latch works
+//! like an SR latch: it stays set, and the hart stays paused with `step()`
+//! returning without executing, until [`Cpu::resume`] resets it. A later trap
+//! can't overwrite it and a poll can't miss it.
+
+use super::core::Cpu;
+use super::types::{Mode, Trap};
+
+/// Condition that pauses the hart when a trap is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapBreak {
+    /// Any exception or interrupt.
+    NextTrap,
+    /// An exception with this `mcause` code.
+    Exception(u64),
+    /// An `ecall` executed in this privilege mode.
+    Ecall(Mode),
+}
+
+impl TrapBreak {
+    fn matches(&self, trap: &Trap, is_interrupt: bool, cause: u64) -> bool {
+        match *self {
+            TrapBreak::NextTrap => true,
+            TrapBreak::Exception(code) => !is_interrupt && cause == code,
+            TrapBreak::Ecall(mode) => matches!(
+                (mode, trap),
+                (Mode::User, Trap::EnvironmentCallFromU)
+                    | (Mode::Supervisor, Trap::EnvironmentCallFromS)
+                    | (Mode::Machine, Trap::EnvironmentCallFromM)
+            ),
+        }
+    }
+}
+
+/// A trap that fired a [`TrapBreak`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrapBreakHit {
+    /// The condition that fired.
+    pub condition: TrapBreak,
+    pub trap: Trap,
+    pub is_interrupt: bool,
+    /// Exception or interrupt code, as written to `mcause`/`scause`.
+    pub cause: u64,
+    /// PC of the trapping instruction (the value written to `xepc`).
+    pub epc: u64,
+    /// Privilege mode the trap was taken from.
+    pub from_mode: Mode,
+    /// PC of the trap handler.
+    pub handler_pc: u64,
+}
+
+impl Cpu {
+    /// Arm a one-shot trap breakpoint.
+    pub fn break_on(&mut self, condition: TrapBreak) {
+        if !self.trap_breaks.contains(&condition) {
+            self.trap_breaks.push(condition);
+        }
+    }
+
+    /// Disarm all trap breakpoints (a latched hit is kept).
+    pub fn clear_trap_breaks(&mut self) {
+        self.trap_breaks.clear();
+    }
+
+    /// Armed trap breakpoints.
+    pub fn trap_breaks(&self) -> &[TrapBreak] {
+        &self.trap_breaks
+    }
+
+    /// The latched break, if the hart is paused.
+    pub fn break_hit(&self) -> Option<&TrapBreakHit> {
+        self.break_hit.as_ref()
+    }
+
+    /// Reset the latch and let the hart run again.
+    pub fn resume(&mut self) -> Option<TrapBreakHit> {
+        self.break_hit.take()
+    }
+
+    /// Called from the trap path once trap entry has set `pc`.
+    pub(super) fn check_trap_break(
+        &mut self,
+        trap: &Trap,
+        is_interrupt: bool,
+        cause: u64,
+        epc: u64,
+        from_mode: Mode,
+    ) {
+        if self.break_hit.is_some() {
+            return;
+        }
+        let Some(idx) = self
+            .trap_breaks
+            .iter()
+            .position(|b| b.matches(trap, is_interrupt, cause))
+        else {
+            return;
+        };
+        let condition = self.trap_breaks.remove(idx);
+        self.break_hit = Some(TrapBreakHit {
+            condition,
+            trap: trap.clone(),
+            is_interrupt,
+            cause,
+            epc,
+            from_mode,
+            handler_pc: self.pc,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE, SystemBus};
+    use crate::cpu::test_hart;
+    use crate::csr::CSR_MTVEC;
+
+    const HANDLER: u64 = DRAM_BASE + 0x100;
+
+    fn setup(program: &[u32]) -> (Cpu, SystemBus) {
+        let (mut cpu, bus) = test_hart(program);
+        bus.write32(HANDLER, 0x3420_2f73).unwrap(); // csrr t5, mcause
+        cpu.write_csr(CSR_MTVEC, HANDLER).unwrap();
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_break_on_ecall_latches_until_resume() {
+        let (mut cpu, bus) = setup(&[0x0010_0093, 0x0000_0073]); // addi x1, x0, 1; ecall
+        cpu.break_on(TrapBreak::Ecall(Mode::Supervisor));
+        cpu.break_on(TrapBreak::Ecall(Mode::Machine));
+
+        cpu.step(&bus).unwrap();
+        assert!(cpu.break_hit().is_none());
+        assert!(cpu.step(&bus).is_err());
+
+        let hit = cpu.break_hit().unwrap().clone();
+        assert_eq!(hit.condition, TrapBreak::Ecall(Mode::Machine));
+        assert_eq!(hit.trap, Trap::EnvironmentCallFromM);
+        assert_eq!(hit.cause, 11);
+        assert_eq!(hit.epc, DRAM_BASE + 4);
+        assert_eq!(hit.from_mode, Mode::Machine);
+        assert_eq!(hit.handler_pc, HANDLER);
+
+        // Paused: stepping does not execute the handler
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.pc, HANDLER);
+
+        // Only the condition that fired is disarmed
+        assert_eq!(cpu.trap_breaks(), &[TrapBreak::Ecall(Mode::Supervisor)]);
+        assert_eq!(cpu.resume(), Some(hit));
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.pc, HANDLER + 4);
+    }
+
+    #[test]
+    fn test_break_on_exception_cause() {
+        // ecall, then an illegal instruction once the handler returns
+        let (mut cpu, bus) = setup(&[0x0000_0073, 0xffff_ffff]);
+        cpu.break_on(TrapBreak::Exception(2));
+
+        assert!(cpu.step(&bus).is_err());
+        assert!(cpu.break_hit().is_none());
+
+        cpu.pc = DRAM_BASE + 4;
+        assert!(cpu.step(&bus).is_err());
+        let hit = cpu.break_hit().unwrap();
+        assert!(matches!(hit.trap, Trap::IllegalInstruction(_)));
+        assert!(cpu.trap_breaks().is_empty());
+    }
+}
@@ -1,25 +1,32 @@
-use super::core::Cpu;
+use super::core::{BlockExecResult, Cpu};
 use super::csr::{
     CSR_MENVCFG, CSR_MEPC, CSR_MHARTID, CSR_MIP, CSR_MSTATUS, CSR_SATP, CSR_SEPC, CSR_STIMECMP,
     CSR_TIME,
 };
+use super::fpu::NAN_BOX;
+use super::pmp::Pmp;
 use crate::Mode;
 use crate::Trap;
 use crate::bus::Bus;
-use crate::devices::clint::{CLINT_BASE, MTIME_OFFSET};
-use crate::engine::block::{Block, BlockCompiler, CompileResult, MAX_BLOCK_SIZE};
+use crate::engine::block::{Block, BlockCompiler, CompileResult};
 use crate::engine::decoder::{self, Op, Register};
-use crate::engine::microop::MicroOp;
+use crate::engine::trace::{TRACE_LOOP_LIMIT, Trace};
 use crate::mmu::AccessType as MmuAccessType;
 
 impl Cpu {
     pub fn step(&mut self, bus: &dyn Bus) -> Result<(), Trap> {
+        // Paused on a trap breakpoint or watchpoint until the debugger resumes
+        if self.break_hit.is_some() || self.watch_hit.is_some() {
+            return Ok(());
+        }
+
         // Batch interrupt polling: only check every 256 instructions for performance.
         self.poll_counter = self.poll_counter.wrapping_add(1);
 
         if self.poll_counter == 0 {
             // Poll device-driven interrupts into MIP mask.
             let hart_id = self.csrs[CSR_MHARTID as usize] as usize;
+            bus.advance_cycles(hart_id, std::mem::take(&mut self.unsynced_cycles));
             let mut hw_mip = bus.poll_interrupts_for_hart(hart_id);
 
             // Sstc support: raise STIP (bit 5) when time >= stimecmp and Sstc enabled.
@@ -27,29 +34,41 @@ impl Cpu {
             let sstc_enabled = ((menvcfg >> 63) & 1) == 1;
             let stimecmp = self.csrs[CSR_STIMECMP as usize];
             if sstc_enabled && stimecmp != 0 {
-                if let Ok(now) = bus.read64(CLINT_BASE + MTIME_OFFSET) {
+                if let Ok(now) = bus.read_mtime() {
                     if now >= stimecmp {
                         hw_mip |= 1 << 5; // STIP
                     }
                 }
             }
 
+            // The built-in SBI forwards machine timer/software interrupts to S-mode
+            if self.sbi.is_some() {
+                hw_mip = self.sbi_route_interrupts(bus, hw_mip);
+            }
+
             // Update MIP
             let hw_bits: u64 = (1 << 3) | (1 << 7) | (1 << 9) | (1 << 11);
             let hw_bits_with_stip: u64 = hw_bits | (1 << 5);
-            let mask = if sstc_enabled {
+            let mask = if sstc_enabled || self.sbi.is_some() {
                 hw_bits_with_stip
             } else {
                 hw_bits
             };
             let old_mip = self.csrs[CSR_MIP as usize];
             self.csrs[CSR_MIP as usize] = (old_mip & !mask) | (hw_mip & mask);
+            self.note_interrupt_poll(bus);
 
             if let Some(trap) = self.check_pending_interrupt() {
+                self.note_interrupt_taken(&trap);
                 return self.handle_trap(trap, self.pc, None);
             }
         }
 
+        // Tracing observes every instruction, so it bypasses blocks and the JIT
+        if self.tracing() {
+            return self.step_traced(bus);
+        }
+
         // Try superblock execution if enabled
         if self.use_blocks {
             if let Some(result) = self.try_execute_block(bus) {
@@ -65,34 +84,29 @@ impl Cpu {
     /// Returns Some(result) if block was executed, None if should fall back to interpreter.
     fn try_execute_block(&mut self, bus: &dyn Bus) -> Option<Result<(), Trap>> {
         let pc = self.pc;
+        // With PMP in force, every block entry is checked for fetch permission
+        let pmp = self.pmp.applies(self.mode);
 
-        // Check block cache for existing block
-        if let Some(block) = self.block_cache.get(pc) {
-            // Clone needed values to avoid borrow issues
-            let block_start_pc = block.start_pc;
-            let block_len = block.len;
-            let block_byte_len = block.byte_len;
-            let block_ops: [MicroOp; MAX_BLOCK_SIZE] = block.ops;
-
-            // Create a temporary block for execution
-            let exec_block = Block {
-                start_pc: block_start_pc,
-                start_pa: block.start_pa,
-                len: block_len,
-                byte_len: block_byte_len,
-                ops: block_ops,
-                exec_count: 0,
-                generation: block.generation,
-            };
-
-            // Execute the block
-            let result = self.execute_block_inner(&exec_block, bus);
+        // Hot traces run several blocks per dispatch
+        if !pmp && let Some(trace) = self.traces.take(pc) {
+            let result = self.execute_trace(&trace, bus);
+            self.traces.put_back(trace);
+            return Some(self.handle_block_result(result, bus));
+        }
 
-            // Update execution count
-            if let Some(cached_block) = self.block_cache.get_mut(pc) {
-                cached_block.exec_count = cached_block.exec_count.saturating_add(1);
+        // Run a cached block in place
+        if let Some(mut block) = self.block_cache.take(pc) {
+            if pmp && !fetch_allowed(&self.pmp, self.mode, &block) {
+                // The interpreter raises the access fault
+                self.block_cache.put_back(block);
+                return None;
+            }
+            let result = self.run_block(&block, bus);
+            block.exec_count = block.exec_count.saturating_add(1);
+            self.block_cache.put_back(block);
+            if let BlockExecResult::Continue(next_pc) = result {
+                self.record_transition(pc, next_pc);
             }
-
             return Some(self.handle_block_result(result, bus));
         }
 
@@ -114,22 +128,19 @@ impl Cpu {
 
         match compile_result {
             CompileResult::Ok(block) => {
-                // Clone needed values before inserting
-                let exec_block = Block {
-                    start_pc: block.start_pc,
-                    start_pa: block.start_pa,
-                    len: block.len,
-                    byte_len: block.byte_len,
-                    ops: block.ops,
-                    exec_count: 0,
-                    generation: block.generation,
-                };
-
-                // Insert into cache
-                self.block_cache.insert(block);
+                let block = self.block_cache.insert_taken(block);
+                if pmp && !fetch_allowed(&self.pmp, self.mode, &block) {
+                    self.block_cache.put_back(block);
+                    return None;
+                }
+                if self.block_cache.prefetch {
+                    self.prefetch_successors(&block, bus);
+                }
 
                 // Execute the block
-                let result = self.execute_block_inner(&exec_block, bus);
+                let result = self.execute_block_inner(&block, bus);
+                self.charge_block(&block, &result);
+                self.block_cache.put_back(block);
                 Some(self.handle_block_result(result, bus))
             }
             CompileResult::Trap(trap) => Some(self.handle_trap(trap, pc, None)),
@@ -140,6 +151,157 @@ impl Cpu {
         }
     }
 
+    /// Compile the static successors of a freshly compiled block, so running
+    /// them for the first time doesn't stall on decode.
+    ///
+    /// Only successors on the block's own page are compiled: their fetches
+    /// read the page the block came from (never MMIO) and need no page-table
+    /// walk beyond the one just done.
+    fn prefetch_successors(&mut self, block: &Block, bus: &dyn Bus) {
+        let page = block.start_pc & !0xFFF;
+        for pc in block.successors().into_iter().flatten() {
+            // The block itself is out of the cache while it runs
+            if pc & !0xFFF != page || pc == block.start_pc || self.block_cache.peek(pc).is_some() {
+                continue;
+            }
+            let mut compiler = BlockCompiler {
+                bus,
+                satp: self.csrs[CSR_SATP as usize],
+                mstatus: self.csrs[CSR_MSTATUS as usize],
+                mode: self.mode,
+                tlb: &mut self.tlb,
+            };
+            // Faults are left for the real fetch to report
+            if let CompileResult::Ok(successor) = compiler.compile(pc, self.block_cache.generation)
+            {
+                self.block_cache.insert_prefetched(successor);
+            }
+        }
+    }
+
+    /// Execute a block, natively if the JIT has compiled it.
+    #[inline]
+    fn run_block(&mut self, block: &Block, bus: &dyn Bus) -> BlockExecResult {
+        #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+        if let Some(jit) = self.jit.as_mut() {
+            if jit.config.verify {
+                return self.run_block_verified(block, bus);
+            }
+            if let Some(next_pc) = jit.execute(block, &mut self.regs) {
+                self.charge_cycles(block.cost);
+                self.counters.events.jit_instret += block.len as u64;
+                return BlockExecResult::Continue(next_pc);
+            }
+        }
+        let result = self.execute_block_inner(block, bus);
+        self.charge_block(block, &result);
+        result
+    }
+
+    /// Run a block natively (if compiled) and again in the interpreter from
+    /// the same registers, reporting differences to the JIT. The guest
+    /// continues from the interpreter's results.
+    #[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+    fn run_block_verified(&mut self, block: &Block, bus: &dyn Bus) -> BlockExecResult {
+        let Some(mut jit) = self.jit.take() else {
+            return self.execute_block_inner(block, bus);
+        };
+        let regs = self.regs;
+        let native_pc = jit.execute(block, &mut self.regs);
+        let native_regs = self.regs;
+        self.regs = regs;
+        let result = self.execute_block_inner(block, bus);
+        self.charge_block(block, &result);
+
+        // Compiled blocks can't trap or exit early
+        if let (Some(native_pc), BlockExecResult::Continue(next_pc)) = (native_pc, &result) {
+            let interpreted_regs = self.regs;
+            jit.verify(
+                block,
+                &regs,
+                (&native_regs, native_pc),
+                (&interpreted_regs, *next_pc),
+                |prefix, regs| {
+                    self.regs = *regs;
+                    let next_pc = match self.execute_block_inner(prefix, bus) {
+                        BlockExecResult::Continue(pc) | BlockExecResult::Exit { next_pc: pc } => pc,
+                        BlockExecResult::Trap { fault_pc, .. } => fault_pc,
+                    };
+                    *regs = self.regs;
+                    next_pc
+                },
+            );
+            self.regs = interpreted_regs;
+        }
+        self.jit = Some(jit);
+        result
+    }
+
+    /// Charge the cycles of the part of `block` that ran.
+    #[inline]
+    fn charge_block(&mut self, block: &Block, result: &BlockExecResult) {
+        let (cost, insns) = match *result {
+            BlockExecResult::Continue(_) => (block.cost, block.len as u32),
+            BlockExecResult::Exit { next_pc: pc } | BlockExecResult::Trap { fault_pc: pc, .. } => {
+                (block.cost_before(pc), block.insns_before(pc))
+            }
+        };
+        self.charge_cycles(cost);
+        self.counters.events.block_instret += insns as u64;
+    }
+
+    #[inline]
+    fn charge_cycles(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        self.unsynced_cycles += cycles as u64;
+    }
+
+    /// Profile a block-to-block transition and form a trace once it is hot.
+    fn record_transition(&mut self, from: u64, to: u64) {
+        if let Some(head) = self.traces.record(from, to) {
+            self.traces.form(head, &self.block_cache);
+        }
+    }
+
+    /// Execute a trace, following its internal branches while each block
+    /// continues to the expected successor.
+    fn execute_trace(&mut self, trace: &Trace, bus: &dyn Bus) -> BlockExecResult {
+        self.traces.executions += 1;
+        let mut iterations = 0;
+        let mut idx = 0;
+
+        loop {
+            let block = &trace.blocks[idx];
+            let result = self.run_block(block, bus);
+            let BlockExecResult::Continue(next_pc) = result else {
+                self.traces.side_exits += 1;
+                return result;
+            };
+
+            idx += 1;
+            if idx < trace.blocks.len() {
+                if next_pc == trace.blocks[idx].start_pc {
+                    continue;
+                }
+            } else if !trace.is_loop {
+                self.record_transition(block.start_pc, next_pc);
+                return result;
+            } else if next_pc == trace.head() {
+                iterations += 1;
+                if iterations < TRACE_LOOP_LIMIT {
+                    idx = 0;
+                    continue;
+                }
+                return result;
+            }
+
+            // Side exit: the block left the recorded path
+            self.traces.side_exits += 1;
+            self.record_transition(block.start_pc, next_pc);
+            return result;
+        }
+    }
+
     /// Execute a single instruction (interpreter mode).
     /// This is the original step() implementation without the interrupt check.
     pub(super) fn step_single(&mut self, bus: &dyn Bus) -> Result<(), Trap> {
@@ -147,30 +309,37 @@ impl Cpu {
         self.poll_counter = self.poll_counter.wrapping_add(1);
         if self.poll_counter == 0 {
             let hart_id = self.csrs[CSR_MHARTID as usize] as usize;
+            bus.advance_cycles(hart_id, std::mem::take(&mut self.unsynced_cycles));
             let mut hw_mip = bus.poll_interrupts_for_hart(hart_id);
 
             let menvcfg = self.csrs[CSR_MENVCFG as usize];
             let sstc_enabled = ((menvcfg >> 63) & 1) == 1;
             let stimecmp = self.csrs[CSR_STIMECMP as usize];
             if sstc_enabled && stimecmp != 0 {
-                if let Ok(now) = bus.read64(CLINT_BASE + MTIME_OFFSET) {
+                if let Ok(now) = bus.read_mtime() {
                     if now >= stimecmp {
                         hw_mip |= 1 << 5;
                     }
                 }
             }
 
+            if self.sbi.is_some() {
+                hw_mip = self.sbi_route_interrupts(bus, hw_mip);
+            }
+
             let hw_bits: u64 = (1 << 3) | (1 << 7) | (1 << 9) | (1 << 11);
             let hw_bits_with_stip: u64 = hw_bits | (1 << 5);
-            let mask = if sstc_enabled {
+            let mask = if sstc_enabled || self.sbi.is_some() {
                 hw_bits_with_stip
             } else {
                 hw_bits
             };
             let old_mip = self.csrs[CSR_MIP as usize];
             self.csrs[CSR_MIP as usize] = (old_mip & !mask) | (hw_mip & mask);
+            self.note_interrupt_poll(bus);
 
             if let Some(trap) = self.check_pending_interrupt() {
+                self.note_interrupt_taken(&trap);
                 return self.handle_trap(trap, self.pc, None);
             }
         }
@@ -179,7 +348,7 @@ impl Cpu {
     }
 
     /// Inner implementation of single-step execution (no interrupt check).
-    fn step_single_inner(&mut self, bus: &dyn Bus) -> Result<(), Trap> {
+    pub(super) fn step_single_inner(&mut self, bus: &dyn Bus) -> Result<(), Trap> {
         let pc = self.pc;
         // Fetch (supports compressed 16-bit and regular 32-bit instructions)
         let (insn_raw, insn_len) = self.fetch_and_expand(bus)?;
@@ -196,6 +365,11 @@ impl Cpu {
             self.decode_cache_insert(pc, insn_raw, op);
             op
         };
+        self.charge_cycles(op.cost());
+        self.counters.events.interp_instret += 1;
+        if let Some(tracer) = self.tracer.as_deref_mut() {
+            tracer.pending = Some((pc, insn_raw, insn_len));
+        }
 
         let mut next_pc = pc.wrapping_add(insn_len as u64);
 
@@ -269,108 +443,30 @@ impl Cpu {
                 imm,
                 funct3,
             } => {
+                if funct3 == 7 {
+                    return self.handle_trap(
+                        Trap::IllegalInstruction(insn_raw as u64),
+                        pc,
+                        Some(insn_raw),
+                    );
+                }
                 let addr = self.read_reg(rs1).wrapping_add(imm as u64);
-                let val = match funct3 {
-                    0 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read8(pa) {
-                            Ok(v) => (v as i8) as i64 as u64, // LB
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    1 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read16(pa) {
-                            Ok(v) => (v as i16) as i64 as u64, // LH
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    2 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read32(pa) {
-                            Ok(v) => (v as i32) as i64 as u64, // LW
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    3 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read64(pa) {
-                            Ok(v) => v, // LD
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    4 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read8(pa) {
-                            Ok(v) => v as u64, // LBU
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    5 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read16(pa) {
-                            Ok(v) => v as u64, // LHU
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    6 => {
-                        let pa = self.translate_addr(
-                            bus,
-                            addr,
-                            MmuAccessType::Load,
-                            pc,
-                            Some(insn_raw),
-                        )?;
-                        match bus.read32(pa) {
-                            Ok(v) => v as u64, // LWU
-                            Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
-                        }
-                    }
-                    _ => {
-                        return self.handle_trap(
-                            Trap::IllegalInstruction(insn_raw as u64),
-                            pc,
-                            Some(insn_raw),
-                        );
-                    }
+                let pa = self.translate_addr(bus, addr, MmuAccessType::Load, pc, Some(insn_raw))?;
+                let res = match funct3 {
+                    0 => bus.read8(pa).map(|v| v as i8 as i64 as u64), // LB
+                    1 => bus.read16(pa).map(|v| v as i16 as i64 as u64), // LH
+                    2 => bus.read32(pa).map(|v| v as i32 as i64 as u64), // LW
+                    3 => bus.read64(pa),                               // LD
+                    4 => bus.read8(pa).map(u64::from),                 // LBU
+                    5 => bus.read16(pa).map(u64::from),                // LHU
+                    _ => bus.read32(pa).map(u64::from),                // LWU
+                };
+                let val = match res {
+                    Ok(v) => v,
+                    Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
                 };
                 self.write_reg(rd, val);
+                self.watch_access(pc, addr, pa, 1 << (funct3 & 3), val, false);
             }
             Op::Store {
                 rs1,
@@ -400,6 +496,8 @@ impl Cpu {
                 if let Err(e) = res {
                     return self.handle_trap(e, pc, Some(insn_raw));
                 }
+                self.note_code_write(pa, 1 << funct3);
+                self.watch_access(pc, addr, pa, 1 << funct3, val, true);
             }
             Op::OpImm {
                 rd,
@@ -707,6 +805,7 @@ impl Cpu {
                         };
                         self.write_reg(rd, loaded);
                         self.reservation = Some(Self::reservation_granule(addr));
+                        self.watch_access(pc, addr, pa, if is_word { 4 } else { 8 }, loaded, false);
                     }
                     0b00011 => {
                         // SC.W / SC.D
@@ -739,6 +838,7 @@ impl Cpu {
                             }
                             self.write_reg(rd, 0);
                             self.reservation = None;
+                            self.watch_access(pc, addr, pa, if is_word { 4 } else { 8 }, val, true);
                         } else {
                             // Failed store, no memory access
                             self.write_reg(rd, 1);
@@ -836,6 +936,19 @@ impl Cpu {
                         );
                     }
                 }
+                if funct5 != 0b00010 {
+                    self.note_code_write(pa, if is_word { 4 } else { 8 });
+                }
+                if funct5 & 0b11110 != 0b00010 && !self.watchpoints.is_empty() {
+                    // AMOs report the value they leave in memory
+                    let stored = if is_word {
+                        bus.read32(pa).map(|v| v as i32 as i64 as u64)
+                    } else {
+                        bus.read64(pa)
+                    };
+                    let size = if is_word { 4 } else { 8 };
+                    self.watch_access(pc, addr, pa, size, stored.unwrap_or(0), true);
+                }
             }
             Op::System {
                 rd,
@@ -861,10 +974,16 @@ impl Cpu {
                                     Some(insn_raw),
                                 );
                             }
-                            // Simplest implementation: flush entire TLB.
-                            self.tlb.flush();
-                            // Also invalidate decode cache (PC->PA mappings may have changed)
-                            self.invalidate_decode_cache();
+                            let rs2 = ((insn_raw >> 20) & 0x1F) as usize;
+                            let asid = self.regs[rs2] & 0xFFFF;
+                            match (rs1 != Register::X0, rs2 != 0) {
+                                (false, false) => self.tlb.flush(),
+                                (false, true) => self.tlb.flush_asid(asid),
+                                (true, false) => self.tlb.flush_va(self.read_reg(rs1)),
+                                (true, true) => self.tlb.flush_page(self.read_reg(rs1) >> 12, asid),
+                            }
+                            // Blocks and the decode cache are keyed by virtual PC
+                            self.invalidate_blocks();
                         } else {
                             match insn_raw {
                                 0x0010_0073 => {
@@ -872,16 +991,15 @@ impl Cpu {
                                     return self.handle_trap(Trap::Breakpoint, pc, Some(insn_raw));
                                 }
                                 0x1050_0073 => {
-                                    // WFI - Wait For Interrupt
-                                    // Instead of busy-spinning, hint to the CPU to reduce power usage.
-                                    // This uses the PAUSE instruction on x86 or equivalent on other archs.
-                                    // Multiple iterations give the scheduler a chance to run other threads.
-                                    for _ in 0..10 {
-                                        std::hint::spin_loop();
-                                    }
+                                    // WFI - Wait For Interrupt. Retires at once; the
+                                    // run loop may idle the host (see `cpu::idle`).
+                                    self.wfi = true;
                                 }
                                 0x0000_0073 => {
                                     // ECALL - route based on current privilege mode
+                                    if self.mode == Mode::Supervisor && self.sbi.is_some() {
+                                        return self.sbi_call(bus, pc);
+                                    }
                                     let trap = match self.mode {
                                         Mode::User => Trap::EnvironmentCallFromU,
                                         Mode::Supervisor => Trap::EnvironmentCallFromS,
@@ -964,7 +1082,10 @@ impl Cpu {
                         let csr_addr = (imm & 0xFFF) as u16;
                         // Dynamic read for time CSR to reflect CLINT MTIME.
                         let old = if csr_addr == CSR_TIME {
-                            bus.read64(CLINT_BASE + MTIME_OFFSET).unwrap_or(0)
+                            if let Err(e) = self.check_counter_enabled(csr_addr) {
+                                return self.handle_trap(e, pc, Some(insn_raw));
+                            }
+                            bus.read_mtime().unwrap_or(0)
                         } else {
                             match self.read_csr(csr_addr) {
                                 Ok(v) => v,
@@ -1019,10 +1140,10 @@ impl Cpu {
                             if let Err(e) = self.write_csr(csr_addr, new_val) {
                                 return self.handle_trap(e, pc, Some(insn_raw));
                             }
-                            // Invalidate decode cache if SATP changed (address space switch)
+                            // Invalidate cached code if SATP changed (address space switch)
                             if csr_addr == CSR_SATP {
                                 self.tlb.flush();
-                                self.invalidate_decode_cache();
+                                self.invalidate_blocks();
                             }
                         }
 
@@ -1039,12 +1160,97 @@ impl Cpu {
                     }
                 }
             }
+            Op::LoadFp {
+                rd,
+                rs1,
+                imm,
+                funct3,
+            } => {
+                if !self.fp_enabled() || !(funct3 == 2 || funct3 == 3) {
+                    return self.handle_trap(
+                        Trap::IllegalInstruction(insn_raw as u64),
+                        pc,
+                        Some(insn_raw),
+                    );
+                }
+                let addr = self.read_reg(rs1).wrapping_add(imm as u64);
+                let pa = self.translate_addr(bus, addr, MmuAccessType::Load, pc, Some(insn_raw))?;
+                let val = if funct3 == 2 {
+                    bus.read32(pa).map(|v| v as u64 | NAN_BOX) // FLW
+                } else {
+                    bus.read64(pa) // FLD
+                };
+                match val {
+                    Ok(v) => {
+                        self.write_freg(rd.to_usize(), v);
+                        self.watch_access(pc, addr, pa, 1 << funct3, v, false);
+                    }
+                    Err(e) => return self.handle_trap(e, pc, Some(insn_raw)),
+                }
+            }
+            Op::StoreFp {
+                rs1,
+                rs2,
+                imm,
+                funct3,
+            } => {
+                if !self.fp_enabled() || !(funct3 == 2 || funct3 == 3) {
+                    return self.handle_trap(
+                        Trap::IllegalInstruction(insn_raw as u64),
+                        pc,
+                        Some(insn_raw),
+                    );
+                }
+                let addr = self.read_reg(rs1).wrapping_add(imm as u64);
+                let pa =
+                    self.translate_addr(bus, addr, MmuAccessType::Store, pc, Some(insn_raw))?;
+                self.clear_reservation_if_conflict(addr);
+                let val = self.fregs[rs2.to_usize()];
+                let res = if funct3 == 2 {
+                    bus.write32(pa, val as u32) // FSW
+                } else {
+                    bus.write64(pa, val) // FSD
+                };
+                if let Err(e) = res {
+                    return self.handle_trap(e, pc, Some(insn_raw));
+                }
+                self.note_code_write(pa, 1 << funct3);
+                self.watch_access(pc, addr, pa, 1 << funct3, val, true);
+            }
+            Op::OpFp { .. } | Op::FusedMulAdd { .. } => {
+                if let Err(e) = self.execute_fp(insn_raw) {
+                    return self.handle_trap(e, pc, Some(insn_raw));
+                }
+            }
+            Op::Vector { insn } => {
+                if let Err(e) = self.execute_vector(bus, insn) {
+                    return self.handle_trap(e, pc, Some(insn_raw));
+                }
+            }
+            Op::Bit { op, rd, rs1, rs2 } => {
+                let res = op.eval(self.read_reg(rs1), self.read_reg(rs2));
+                self.write_reg(rd, res);
+            }
+            Op::BitImm { op, rd, rs1, shamt } => {
+                let res = op.eval(self.read_reg(rs1), shamt as u64);
+                self.write_reg(rd, res);
+            }
             Op::Fence => {
                 // NOP
             }
+            Op::FenceI => {
+                // Later fetches must see earlier stores, so drop all compiled code
+                self.invalidate_blocks();
+            }
         }
 
         self.pc = next_pc;
         Ok(())
     }
 }
+
+/// Whether PMP lets `mode` fetch all of `block`.
+fn fetch_allowed(pmp: &Pmp, mode: Mode, block: &Block) -> bool {
+    let len = block.byte_len as u64;
+    pmp.allows(block.start_pa, len, mode, MmuAccessType::Instruction)
+}
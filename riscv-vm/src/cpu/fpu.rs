@@ -0,0 +1,1040 @@
+//! RV64F/D floating-point unit.
+//!
+//! Arithmetic is done on the raw IEEE-754 encodings with integer
+//! significands rather than host floats. That keeps all five RISC-V rounding
+//! modes, the accrued exception flags and subnormal handling exact, and
+//! makes results identical on every host (native and wasm).
+//!
+//! Single-precision values live NaN-boxed in the 64-bit `f` registers; an
+//! improperly boxed value reads as the canonical NaN. Every NaN produced by
+//! an operation is the canonical NaN.
+
+use std::cmp::Ordering;
+
+use super::core::Cpu;
+use super::csr::{CSR_FCSR, CSR_MSTATUS};
+use crate::Trap;
+
+// fflags bits
+pub const FFLAG_NX: u64 = 1 << 0;
+pub const FFLAG_UF: u64 = 1 << 1;
+pub const FFLAG_OF: u64 = 1 << 2;
+pub const FFLAG_DZ: u64 = 1 << 3;
+pub const FFLAG_NV: u64 = 1 << 4;
+
+pub const MSTATUS_FS: u64 = 3 << 13;
+pub const MSTATUS_SD: u64 = 1 << 63;
+
+/// Upper half of a NaN-boxed single-precision register value.
+pub const NAN_BOX: u64 = 0xFFFF_FFFF_0000_0000;
+
+/// Rounding modes encoded in the `rm` field and `frm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    Rne,
+    /// Round towards zero
+    Rtz,
+    /// Round down (towards -inf)
+    Rdn,
+    /// Round up (towards +inf)
+    Rup,
+    /// Round to nearest, ties to max magnitude
+    Rmm,
+}
+
+impl RoundingMode {
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(RoundingMode::Rne),
+            1 => Some(RoundingMode::Rtz),
+            2 => Some(RoundingMode::Rdn),
+            3 => Some(RoundingMode::Rup),
+            4 => Some(RoundingMode::Rmm),
+            _ => None,
+        }
+    }
+}
+
+/// Binary floating-point format parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Format {
+    exp_bits: u32,
+    man_bits: u32,
+}
+
+const F32: Format = Format {
+    exp_bits: 8,
+    man_bits: 23,
+};
+const F64: Format = Format {
+    exp_bits: 11,
+    man_bits: 52,
+};
+
+/// A finite value `(-1)^sign * sig * 2^exp`. Zero has `sig == 0`.
+#[derive(Debug, Clone, Copy)]
+struct Parts {
+    sign: bool,
+    sig: u128,
+    exp: i32,
+}
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn exp_max(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn man_mask(self) -> u64 {
+        (1 << self.man_bits) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.man_bits)
+    }
+
+    fn exp_field(self, bits: u64) -> u64 {
+        (bits >> self.man_bits) & self.exp_max()
+    }
+
+    fn sign(self, bits: u64) -> bool {
+        bits & self.sign_bit() != 0
+    }
+
+    fn is_nan(self, bits: u64) -> bool {
+        self.exp_field(bits) == self.exp_max() && bits & self.man_mask() != 0
+    }
+
+    fn is_snan(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & (1 << (self.man_bits - 1)) == 0
+    }
+
+    fn is_inf(self, bits: u64) -> bool {
+        self.exp_field(bits) == self.exp_max() && bits & self.man_mask() == 0
+    }
+
+    fn is_zero(self, bits: u64) -> bool {
+        bits & !self.sign_bit() == 0
+    }
+
+    fn canonical_nan(self) -> u64 {
+        (self.exp_max() << self.man_bits) | (1 << (self.man_bits - 1))
+    }
+
+    fn with_sign(self, bits: u64, sign: bool) -> u64 {
+        if sign { bits | self.sign_bit() } else { bits }
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        self.with_sign(0, sign)
+    }
+
+    fn inf(self, sign: bool) -> u64 {
+        self.with_sign(self.exp_max() << self.man_bits, sign)
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.with_sign(
+            ((self.exp_max() - 1) << self.man_bits) | self.man_mask(),
+            sign,
+        )
+    }
+
+    /// Decompose a finite encoding.
+    fn unpack(self, bits: u64) -> Parts {
+        let field = self.exp_field(bits) as i32;
+        let man = bits & self.man_mask();
+        let (sig, exp) = if field == 0 {
+            (man, 1 - self.bias() - self.man_bits as i32)
+        } else {
+            (
+                man | (1 << self.man_bits),
+                field - self.bias() - self.man_bits as i32,
+            )
+        };
+        Parts {
+            sign: self.sign(bits),
+            sig: sig as u128,
+            exp,
+        }
+    }
+
+    /// Decompose a finite non-zero encoding with the significand's top bit
+    /// moved to bit `man_bits`, so subnormals look like normals.
+    fn unpack_normalized(self, bits: u64) -> Parts {
+        let mut p = self.unpack(bits);
+        let shift = p.sig.leading_zeros() as i32 - (127 - self.man_bits as i32);
+        p.sig <<= shift;
+        p.exp -= shift;
+        p
+    }
+
+    /// Round `(-1)^sign * (sig + sticky) * 2^exp` into this format, where
+    /// `sticky` stands for a non-zero fraction below the last bit of `sig`.
+    fn round_pack(
+        self,
+        sign: bool,
+        sig: u128,
+        exp: i32,
+        sticky: bool,
+        rm: RoundingMode,
+    ) -> (u64, u64) {
+        if sig == 0 {
+            return (self.zero(sign), 0);
+        }
+        let precision = self.man_bits as i32 + 1;
+        let emin = 1 - self.bias();
+        let msb = 127 - sig.leading_zeros() as i32;
+        let lead = exp + msb;
+        let mut lsb = (lead - (precision - 1)).max(emin - self.man_bits as i32);
+
+        let (mut kept, inexact) = if lsb >= exp {
+            shift_round(sig, (lsb - exp) as u32, sticky, sign, rm)
+        } else {
+            shift_round(sig << (exp - lsb), 0, sticky, sign, rm)
+        };
+        if kept >> precision != 0 {
+            kept >>= 1;
+            lsb += 1;
+        }
+
+        let normal = kept >> (precision - 1) != 0;
+        if normal && lsb + precision - 1 > self.bias() {
+            let to_inf = match rm {
+                RoundingMode::Rne | RoundingMode::Rmm => true,
+                RoundingMode::Rtz => false,
+                RoundingMode::Rdn => sign,
+                RoundingMode::Rup => !sign,
+            };
+            let bits = if to_inf {
+                self.inf(sign)
+            } else {
+                self.max_finite(sign)
+            };
+            return (bits, FFLAG_OF | FFLAG_NX);
+        }
+
+        let bits = if normal {
+            let field = (lsb + self.man_bits as i32 + self.bias()) as u64;
+            (field << self.man_bits) | (kept as u64 & self.man_mask())
+        } else {
+            kept as u64
+        };
+
+        let mut flags = 0;
+        if inexact {
+            flags |= FFLAG_NX;
+            // Tininess is detected after rounding: the result is tiny unless
+            // rounding with an unbounded exponent reaches 2^emin.
+            let tiny = lead < emin
+                && !(lead == emin - 1
+                    && msb >= precision - 1
+                    && shift_round(sig, (msb - (precision - 1)) as u32, sticky, sign, rm).0
+                        >> precision
+                        != 0);
+            if tiny {
+                flags |= FFLAG_UF;
+            }
+        }
+        (self.with_sign(bits, sign), flags)
+    }
+}
+
+/// Drop the low `shift` bits of `sig`, rounding per `rm`. Returns the kept
+/// bits and whether anything non-zero was discarded.
+fn shift_round(sig: u128, shift: u32, sticky: bool, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    let (kept, half, inexact) = if shift == 0 {
+        (sig, Ordering::Less, sticky)
+    } else if shift > 128 {
+        (0, Ordering::Less, sig != 0 || sticky)
+    } else {
+        let (kept, rem) = if shift == 128 {
+            (0, sig)
+        } else {
+            (sig >> shift, sig & ((1u128 << shift) - 1))
+        };
+        let half = rem.cmp(&(1u128 << (shift - 1))).then(if sticky {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        });
+        (kept, half, rem != 0 || sticky)
+    };
+    if !inexact {
+        return (kept, false);
+    }
+    let up = match rm {
+        RoundingMode::Rne => {
+            half == Ordering::Greater || (half == Ordering::Equal && kept & 1 == 1)
+        }
+        RoundingMode::Rmm => half != Ordering::Less,
+        RoundingMode::Rtz => false,
+        RoundingMode::Rdn => sign,
+        RoundingMode::Rup => !sign,
+    };
+    (kept + up as u128, true)
+}
+
+/// Exact sum of two finite values, rounded into `fmt`.
+fn add_parts(fmt: Format, x: Parts, y: Parts, rm: RoundingMode) -> (u64, u64) {
+    if x.sig == 0 && y.sig == 0 {
+        let sign = if x.sign == y.sign {
+            x.sign
+        } else {
+            rm == RoundingMode::Rdn
+        };
+        return (fmt.zero(sign), 0);
+    }
+    if x.sig == 0 {
+        return fmt.round_pack(y.sign, y.sig, y.exp, false, rm);
+    }
+    if y.sig == 0 {
+        return fmt.round_pack(x.sign, x.sig, x.exp, false, rm);
+    }
+
+    let top = |p: &Parts| p.exp + (128 - p.sig.leading_zeros() as i32);
+    let (big, small) = if top(&x) >= top(&y) { (x, y) } else { (y, x) };
+    // Keep one spare bit for the carry of an effective addition.
+    let room = big.sig.leading_zeros() as i32 - 1;
+    let d = big.exp - small.exp;
+    let (hi, lo, exp, sticky) = if d < 0 {
+        (big.sig, small.sig << -d, big.exp, false)
+    } else if d <= room {
+        (big.sig << d, small.sig, small.exp, false)
+    } else {
+        // The smaller operand lies entirely below the guard bits.
+        let shift = (d - room) as u32;
+        let (lo, sticky) = if shift >= 128 {
+            (0, true)
+        } else {
+            (small.sig >> shift, small.sig & ((1u128 << shift) - 1) != 0)
+        };
+        (big.sig << room, lo, big.exp - room, sticky)
+    };
+
+    if big.sign == small.sign {
+        return fmt.round_pack(big.sign, hi + lo, exp, sticky, rm);
+    }
+    match hi.cmp(&lo) {
+        Ordering::Equal if !sticky => (fmt.zero(rm == RoundingMode::Rdn), 0),
+        Ordering::Less => fmt.round_pack(small.sign, lo - hi, exp, false, rm),
+        // hi - (lo + sticky) == (hi - lo - 1) + (1 - sticky)
+        _ => fmt.round_pack(big.sign, hi - lo - sticky as u128, exp, sticky, rm),
+    }
+}
+
+fn mul_parts(x: Parts, y: Parts) -> Parts {
+    Parts {
+        sign: x.sign != y.sign,
+        sig: x.sig * y.sig,
+        exp: x.exp + y.exp,
+    }
+}
+
+/// NaN result of an operation, raising NV for signaling inputs.
+fn nan_result(fmt: Format, operands: &[u64]) -> (u64, u64) {
+    let flags = if operands.iter().any(|&v| fmt.is_snan(v)) {
+        FFLAG_NV
+    } else {
+        0
+    };
+    (fmt.canonical_nan(), flags)
+}
+
+fn invalid(fmt: Format) -> (u64, u64) {
+    (fmt.canonical_nan(), FFLAG_NV)
+}
+
+fn fadd(fmt: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if fmt.is_nan(a) || fmt.is_nan(b) {
+        return nan_result(fmt, &[a, b]);
+    }
+    match (fmt.is_inf(a), fmt.is_inf(b)) {
+        (true, true) if fmt.sign(a) != fmt.sign(b) => invalid(fmt),
+        (true, _) => (a, 0),
+        (_, true) => (b, 0),
+        _ => add_parts(fmt, fmt.unpack(a), fmt.unpack(b), rm),
+    }
+}
+
+fn fmul(fmt: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if fmt.is_nan(a) || fmt.is_nan(b) {
+        return nan_result(fmt, &[a, b]);
+    }
+    let sign = fmt.sign(a) != fmt.sign(b);
+    if fmt.is_inf(a) || fmt.is_inf(b) {
+        if fmt.is_zero(a) || fmt.is_zero(b) {
+            return invalid(fmt);
+        }
+        return (fmt.inf(sign), 0);
+    }
+    let p = mul_parts(fmt.unpack(a), fmt.unpack(b));
+    fmt.round_pack(p.sign, p.sig, p.exp, false, rm)
+}
+
+fn fdiv(fmt: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if fmt.is_nan(a) || fmt.is_nan(b) {
+        return nan_result(fmt, &[a, b]);
+    }
+    let sign = fmt.sign(a) != fmt.sign(b);
+    match (fmt.is_inf(a), fmt.is_inf(b)) {
+        (true, true) => return invalid(fmt),
+        (true, false) => return (fmt.inf(sign), 0),
+        (false, true) => return (fmt.zero(sign), 0),
+        _ => {}
+    }
+    match (fmt.is_zero(a), fmt.is_zero(b)) {
+        (true, true) => return invalid(fmt),
+        (false, true) => return (fmt.inf(sign), FFLAG_DZ),
+        (true, false) => return (fmt.zero(sign), 0),
+        _ => {}
+    }
+    // With both significands normalized the quotient keeps > 64 bits.
+    let x = fmt.unpack_normalized(a);
+    let y = fmt.unpack_normalized(b);
+    let num = x.sig << 66;
+    let q = num / y.sig;
+    let sticky = !num.is_multiple_of(y.sig);
+    fmt.round_pack(sign, q, x.exp - 66 - y.exp, sticky, rm)
+}
+
+fn fsqrt(fmt: Format, a: u64, rm: RoundingMode) -> (u64, u64) {
+    if fmt.is_nan(a) {
+        return nan_result(fmt, &[a]);
+    }
+    if fmt.is_zero(a) {
+        return (a, 0);
+    }
+    if fmt.sign(a) {
+        return invalid(fmt);
+    }
+    if fmt.is_inf(a) {
+        return (a, 0);
+    }
+    let mut x = fmt.unpack_normalized(a);
+    if x.exp & 1 != 0 {
+        x.sig <<= 1;
+        x.exp -= 1;
+    }
+    let radicand = x.sig << 70;
+    let root = radicand.isqrt();
+    let sticky = root * root != radicand;
+    fmt.round_pack(false, root, (x.exp - 70) / 2, sticky, rm)
+}
+
+/// `(a * b) + c` with a single rounding.
+fn fmadd(fmt: Format, a: u64, b: u64, c: u64, rm: RoundingMode) -> (u64, u64) {
+    let invalid_product = (fmt.is_inf(a) && fmt.is_zero(b)) || (fmt.is_zero(a) && fmt.is_inf(b));
+    if fmt.is_nan(a) || fmt.is_nan(b) || fmt.is_nan(c) {
+        // inf * 0 is invalid even when the addend is a quiet NaN.
+        let (nan, flags) = nan_result(fmt, &[a, b, c]);
+        let nv = if invalid_product { FFLAG_NV } else { 0 };
+        return (nan, flags | nv);
+    }
+    if invalid_product {
+        return invalid(fmt);
+    }
+    let product_sign = fmt.sign(a) != fmt.sign(b);
+    if fmt.is_inf(a) || fmt.is_inf(b) {
+        if fmt.is_inf(c) && fmt.sign(c) != product_sign {
+            return invalid(fmt);
+        }
+        return (fmt.inf(product_sign), 0);
+    }
+    if fmt.is_inf(c) {
+        return (c, 0);
+    }
+    let p = mul_parts(fmt.unpack(a), fmt.unpack(b));
+    add_parts(fmt, p, fmt.unpack(c), rm)
+}
+
+/// Convert between formats. Widening is always exact.
+fn fcvt(from: Format, to: Format, a: u64, rm: RoundingMode) -> (u64, u64) {
+    if from.is_nan(a) {
+        let flags = if from.is_snan(a) { FFLAG_NV } else { 0 };
+        return (to.canonical_nan(), flags);
+    }
+    if from.is_inf(a) {
+        return (to.inf(from.sign(a)), 0);
+    }
+    let p = from.unpack(a);
+    to.round_pack(p.sign, p.sig, p.exp, false, rm)
+}
+
+fn from_int(fmt: Format, value: i128, rm: RoundingMode) -> (u64, u64) {
+    fmt.round_pack(value < 0, value.unsigned_abs(), 0, false, rm)
+}
+
+/// Convert to an integer in `[min, max]`, saturating with NV when the
+/// rounded value is out of range or the input is NaN.
+fn to_int(fmt: Format, a: u64, min: i128, max: i128, rm: RoundingMode) -> (i128, u64) {
+    if fmt.is_nan(a) {
+        return (max, FFLAG_NV);
+    }
+    let sign = fmt.sign(a);
+    let saturated = if sign { min } else { max };
+    if fmt.is_inf(a) {
+        return (saturated, FFLAG_NV);
+    }
+    let p = fmt.unpack(a);
+    let (magnitude, inexact) = if p.exp >= 0 {
+        if p.exp > 64 {
+            return (saturated, FFLAG_NV);
+        }
+        (p.sig << p.exp, false)
+    } else {
+        shift_round(p.sig, (-p.exp) as u32, false, sign, rm)
+    };
+    let value = if sign {
+        -(magnitude as i128)
+    } else {
+        magnitude as i128
+    };
+    if value < min || value > max {
+        return (saturated, FFLAG_NV);
+    }
+    (value, if inexact { FFLAG_NX } else { 0 })
+}
+
+/// Value of a non-NaN encoding as a host double, for ordering only.
+fn to_host(fmt: Format, bits: u64) -> f64 {
+    if fmt == F32 {
+        f32::from_bits(bits as u32) as f64
+    } else {
+        f64::from_bits(bits)
+    }
+}
+
+fn fminmax(fmt: Format, a: u64, b: u64, max: bool) -> (u64, u64) {
+    let flags = if fmt.is_snan(a) || fmt.is_snan(b) {
+        FFLAG_NV
+    } else {
+        0
+    };
+    let result = match (fmt.is_nan(a), fmt.is_nan(b)) {
+        (true, true) => fmt.canonical_nan(),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            let (x, y) = (to_host(fmt, a), to_host(fmt, b));
+            // -0.0 is ordered below +0.0 here.
+            let a_first = if x == y {
+                fmt.sign(a) != max
+            } else {
+                (x < y) != max
+            };
+            if a_first { a } else { b }
+        }
+    };
+    (result, flags)
+}
+
+/// FEQ (quiet) when `signaling` is false, FLT/FLE otherwise.
+fn fcompare(fmt: Format, a: u64, b: u64, ord: fn(f64, f64) -> bool, signaling: bool) -> (u64, u64) {
+    if fmt.is_nan(a) || fmt.is_nan(b) {
+        let nv = signaling || fmt.is_snan(a) || fmt.is_snan(b);
+        return (0, if nv { FFLAG_NV } else { 0 });
+    }
+    (ord(to_host(fmt, a), to_host(fmt, b)) as u64, 0)
+}
+
+fn fclass(fmt: Format, a: u64) -> u64 {
+    let sign = fmt.sign(a);
+    let bit = if fmt.is_inf(a) {
+        if sign { 0 } else { 7 }
+    } else if fmt.is_nan(a) {
+        if fmt.is_snan(a) { 8 } else { 9 }
+    } else if fmt.is_zero(a) {
+        if sign { 3 } else { 4 }
+    } else if fmt.exp_field(a) == 0 {
+        if sign { 2 } else { 5 }
+    } else if sign {
+        1
+    } else {
+        6
+    };
+    1 << bit
+}
+
+impl Cpu {
+    /// True when `mstatus.FS` is not Off.
+    #[inline]
+    pub(super) fn fp_enabled(&self) -> bool {
+        self.csrs[CSR_MSTATUS as usize] & MSTATUS_FS != 0
+    }
+
+    /// Mark the floating-point state Dirty.
+    #[inline]
+    fn fp_mark_dirty(&mut self) {
+        self.csrs[CSR_MSTATUS as usize] |= MSTATUS_FS | MSTATUS_SD;
+    }
+
+    /// Write a raw 64-bit value into `f[rd]`.
+    #[inline]
+    pub(super) fn write_freg(&mut self, rd: usize, val: u64) {
+        self.fregs[rd] = val;
+        self.fp_mark_dirty();
+    }
+
+    fn read_fp(&self, fmt: Format, r: usize) -> u64 {
+        let raw = self.fregs[r];
+        if fmt == F64 {
+            raw
+        } else if raw & NAN_BOX == NAN_BOX {
+            raw & 0xFFFF_FFFF
+        } else {
+            F32.canonical_nan()
+        }
+    }
+
+    fn write_fp(&mut self, fmt: Format, rd: usize, bits: u64) {
+        let raw = if fmt == F64 { bits } else { bits | NAN_BOX };
+        self.write_freg(rd, raw);
+    }
+
+    fn accrue_fflags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csrs[CSR_FCSR as usize] |= flags;
+            self.fp_mark_dirty();
+        }
+    }
+
+    fn rounding_mode(&self, rm: u32) -> Option<RoundingMode> {
+        if rm == 7 {
+            RoundingMode::from_bits((self.csrs[CSR_FCSR as usize] >> 5) & 0x7)
+        } else {
+            RoundingMode::from_bits(rm as u64)
+        }
+    }
+
+    fn write_int(&mut self, rd: usize, val: u64) {
+        if rd != 0 {
+            self.regs[rd] = val;
+        }
+    }
+
+    /// Execute an OP-FP or fused multiply-add instruction (opcodes 0x53,
+    /// 0x43, 0x47, 0x4B, 0x4F). Loads and stores are handled with the other
+    /// memory operations.
+    pub(super) fn execute_fp(&mut self, insn: u32) -> Result<(), Trap> {
+        let illegal = Trap::IllegalInstruction(insn as u64);
+        if !self.fp_enabled() {
+            return Err(illegal);
+        }
+
+        let opcode = insn & 0x7F;
+        let rd = ((insn >> 7) & 0x1F) as usize;
+        let rm_bits = (insn >> 12) & 0x7;
+        let rs1 = ((insn >> 15) & 0x1F) as usize;
+        let rs2 = ((insn >> 20) & 0x1F) as usize;
+        let funct7 = insn >> 25;
+        let fmt = match funct7 & 0x3 {
+            0 => F32,
+            1 => F64,
+            _ => return Err(illegal),
+        };
+        let rm = self.rounding_mode(rm_bits);
+
+        if opcode != 0x53 {
+            let rm = rm.ok_or(illegal.clone())?;
+            let rs3 = (insn >> 27) as usize;
+            let a = self.read_fp(fmt, rs1);
+            let b = self.read_fp(fmt, rs2);
+            let c = self.read_fp(fmt, rs3);
+            let neg = fmt.sign_bit();
+            // Negation only flips sign bits, so NaN handling is unaffected.
+            let (a, c) = match opcode {
+                0x43 => (a, c),             // FMADD
+                0x47 => (a, c ^ neg),       // FMSUB
+                0x4B => (a ^ neg, c),       // FNMSUB
+                0x4F => (a ^ neg, c ^ neg), // FNMADD
+                _ => return Err(illegal),
+            };
+            let (res, flags) = fmadd(fmt, a, b, c, rm);
+            self.write_fp(fmt, rd, res);
+            self.accrue_fflags(flags);
+            return Ok(());
+        }
+
+        let a = self.read_fp(fmt, rs1);
+        let b = self.read_fp(fmt, rs2);
+        match funct7 >> 2 {
+            // FADD / FSUB / FMUL / FDIV
+            0x00..=0x03 => {
+                let rm = rm.ok_or(illegal)?;
+                let (res, flags) = match funct7 >> 2 {
+                    0x00 => fadd(fmt, a, b, rm),
+                    0x01 => fadd(fmt, a, b ^ fmt.sign_bit(), rm),
+                    0x02 => fmul(fmt, a, b, rm),
+                    _ => fdiv(fmt, a, b, rm),
+                };
+                self.write_fp(fmt, rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FSQRT
+            0x0B if rs2 == 0 => {
+                let rm = rm.ok_or(illegal)?;
+                let (res, flags) = fsqrt(fmt, a, rm);
+                self.write_fp(fmt, rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FSGNJ / FSGNJN / FSGNJX
+            0x04 => {
+                let sign = fmt.sign_bit();
+                let res = match rm_bits {
+                    0 => (a & !sign) | (b & sign),
+                    1 => (a & !sign) | (!b & sign),
+                    2 => a ^ (b & sign),
+                    _ => return Err(illegal),
+                };
+                self.write_fp(fmt, rd, res);
+            }
+            // FMIN / FMAX
+            0x05 if rm_bits <= 1 => {
+                let (res, flags) = fminmax(fmt, a, b, rm_bits == 1);
+                self.write_fp(fmt, rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FCVT.S.D / FCVT.D.S
+            0x08 => {
+                let rm = rm.ok_or(illegal.clone())?;
+                let from = match (fmt == F32, rs2) {
+                    (true, 1) => F64,
+                    (false, 0) => F32,
+                    _ => return Err(illegal),
+                };
+                let (res, flags) = fcvt(from, fmt, self.read_fp(from, rs1), rm);
+                self.write_fp(fmt, rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FLE / FLT / FEQ
+            0x14 => {
+                let (res, flags) = match rm_bits {
+                    0 => fcompare(fmt, a, b, |x, y| x <= y, true),
+                    1 => fcompare(fmt, a, b, |x, y| x < y, true),
+                    2 => fcompare(fmt, a, b, |x, y| x == y, false),
+                    _ => return Err(illegal),
+                };
+                self.write_int(rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FCVT.{W,WU,L,LU}.{S,D}
+            0x18 => {
+                let rm = rm.ok_or(illegal.clone())?;
+                let (min, max) = match rs2 {
+                    0 => (i32::MIN as i128, i32::MAX as i128),
+                    1 => (0, u32::MAX as i128),
+                    2 => (i64::MIN as i128, i64::MAX as i128),
+                    3 => (0, u64::MAX as i128),
+                    _ => return Err(illegal),
+                };
+                let (value, flags) = to_int(fmt, a, min, max, rm);
+                // 32-bit results are sign-extended, including WU.
+                let res = if rs2 < 2 {
+                    value as i32 as i64 as u64
+                } else {
+                    value as u64
+                };
+                self.write_int(rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FCVT.{S,D}.{W,WU,L,LU}
+            0x1A => {
+                let rm = rm.ok_or(illegal.clone())?;
+                let x = self.regs[rs1];
+                let value = match rs2 {
+                    0 => x as i32 as i128,
+                    1 => x as u32 as i128,
+                    2 => x as i64 as i128,
+                    3 => x as i128,
+                    _ => return Err(illegal),
+                };
+                let (res, flags) = from_int(fmt, value, rm);
+                self.write_fp(fmt, rd, res);
+                self.accrue_fflags(flags);
+            }
+            // FMV.X.{W,D} / FCLASS
+            0x1C if rs2 == 0 => {
+                let res = match rm_bits {
+                    // FMV.X.W moves the raw low word, sign-extended.
+                    0 if fmt == F32 => self.fregs[rs1] as u32 as i32 as i64 as u64,
+                    0 => self.fregs[rs1],
+                    1 => fclass(fmt, a),
+                    _ => return Err(illegal),
+                };
+                self.write_int(rd, res);
+            }
+            // FMV.{W,D}.X
+            0x1E if rs2 == 0 && rm_bits == 0 => {
+                let x = self.regs[rs1];
+                let bits = if fmt == F32 { x & 0xFFFF_FFFF } else { x };
+                self.write_fp(fmt, rd, bits);
+            }
+            _ => return Err(illegal),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_F64: u64 = 0x3FF0_0000_0000_0000;
+
+    fn f64_bits(x: f64) -> u64 {
+        x.to_bits()
+    }
+
+    fn f32_bits(x: f32) -> u64 {
+        x.to_bits() as u64
+    }
+
+    fn fp_cpu() -> Cpu {
+        let mut cpu = Cpu::new(0x8000_0000, 0);
+        cpu.csrs[CSR_MSTATUS as usize] |= 1 << 13; // FS = Initial
+        cpu
+    }
+
+    fn r_type(funct7: u32, rs2: u32, rs1: u32, rm: u32, rd: u32) -> u32 {
+        (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (rm << 12) | (rd << 7) | 0x53
+    }
+
+    #[test]
+    fn test_basic_arithmetic_matches_host() {
+        let rm = RoundingMode::Rne;
+        let cases = [(1.5, 2.25), (0.1, 0.2), (-3.0, 7.0), (1e30, 1e-30)];
+        for (x, y) in cases {
+            assert_eq!(fadd(F64, f64_bits(x), f64_bits(y), rm).0, f64_bits(x + y));
+            assert_eq!(fmul(F64, f64_bits(x), f64_bits(y), rm).0, f64_bits(x * y));
+            assert_eq!(fdiv(F64, f64_bits(x), f64_bits(y), rm).0, f64_bits(x / y));
+            let (xs, ys) = (x as f32, y as f32);
+            assert_eq!(
+                fdiv(F32, f32_bits(xs), f32_bits(ys), rm).0,
+                f32_bits(xs / ys)
+            );
+            assert_eq!(
+                fadd(F32, f32_bits(xs), f32_bits(ys), rm).0,
+                f32_bits(xs + ys)
+            );
+            assert_eq!(
+                fmul(F32, f32_bits(xs), f32_bits(ys), rm).0,
+                f32_bits(xs * ys)
+            );
+        }
+        assert_eq!(fsqrt(F64, f64_bits(2.0), rm).0, f64_bits(2f64.sqrt()));
+        assert_eq!(fsqrt(F32, f32_bits(2.0), rm).0, f32_bits(2f32.sqrt()));
+        assert_eq!(
+            fmadd(F64, f64_bits(0.1), f64_bits(10.0), f64_bits(-1.0), rm).0,
+            f64_bits(0.1f64.mul_add(10.0, -1.0))
+        );
+    }
+
+    #[test]
+    fn test_round_to_nearest_matches_host_on_random_inputs() {
+        // The host FPU rounds to nearest-even, so it is a reference for RNE.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let rm = RoundingMode::Rne;
+        for _ in 0..20_000 {
+            let (a, b, c) = (next(), next(), next());
+            let (x, y, z) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            let check = |got: u64, want: f64| {
+                if !want.is_nan() {
+                    assert_eq!(got, want.to_bits(), "{:e} {:e} {:e}", x, y, z);
+                }
+            };
+            check(fadd(F64, a, b, rm).0, x + y);
+            check(fmul(F64, a, b, rm).0, x * y);
+            check(fdiv(F64, a, b, rm).0, x / y);
+            check(fsqrt(F64, a & !(1 << 63), rm).0, x.abs().sqrt());
+            check(fmadd(F64, a, b, c, rm).0, x.mul_add(y, z));
+
+            let (a, b) = (a & 0xFFFF_FFFF, b & 0xFFFF_FFFF);
+            let (x, y) = (f32::from_bits(a as u32), f32::from_bits(b as u32));
+            let check = |got: u64, want: f32| {
+                if !want.is_nan() {
+                    assert_eq!(got, want.to_bits() as u64, "{:e} {:e}", x, y);
+                }
+            };
+            check(fadd(F32, a, b, rm).0, x + y);
+            check(fmul(F32, a, b, rm).0, x * y);
+            check(fdiv(F32, a, b, rm).0, x / y);
+            check(
+                fcvt(F64, F32, f64_bits(x as f64 * 1.1), rm).0,
+                (x as f64 * 1.1) as f32,
+            );
+        }
+    }
+
+    #[test]
+    fn test_rounding_modes_and_flags() {
+        let third = |rm| fdiv(F64, ONE_F64, f64_bits(3.0), rm);
+        let (down, flags) = third(RoundingMode::Rdn);
+        let (up, _) = third(RoundingMode::Rup);
+        assert_eq!(flags, FFLAG_NX);
+        assert_eq!(up, down + 1);
+        assert_eq!(third(RoundingMode::Rtz).0, down);
+
+        // 2.5 -> 2 under RNE, 3 under RMM
+        let half = f64_bits(2.5);
+        let rne = to_int(
+            F64,
+            half,
+            i64::MIN as i128,
+            i64::MAX as i128,
+            RoundingMode::Rne,
+        );
+        let rmm = to_int(
+            F64,
+            half,
+            i64::MIN as i128,
+            i64::MAX as i128,
+            RoundingMode::Rmm,
+        );
+        assert_eq!(rne, (2, FFLAG_NX));
+        assert_eq!(rmm, (3, FFLAG_NX));
+
+        // Exact cancellation is -0 only when rounding down
+        assert_eq!(
+            fadd(F64, ONE_F64, ONE_F64 | (1 << 63), RoundingMode::Rne).0,
+            0
+        );
+        assert_eq!(
+            fadd(F64, ONE_F64, ONE_F64 | (1 << 63), RoundingMode::Rdn).0,
+            1 << 63
+        );
+
+        // Overflow saturates to MAX when rounding towards zero
+        let max = f64_bits(f64::MAX);
+        assert_eq!(
+            fmul(F64, max, f64_bits(2.0), RoundingMode::Rtz),
+            (max, FFLAG_OF | FFLAG_NX)
+        );
+        assert_eq!(
+            fmul(F64, max, f64_bits(2.0), RoundingMode::Rne),
+            (F64.inf(false), FFLAG_OF | FFLAG_NX)
+        );
+
+        // Inexact subnormal result underflows
+        let tiny = f64_bits(f64::MIN_POSITIVE);
+        let (res, flags) = fmul(F64, tiny, f64_bits(0.3), RoundingMode::Rne);
+        assert_eq!(res, f64_bits(f64::MIN_POSITIVE * 0.3));
+        assert_eq!(flags, FFLAG_UF | FFLAG_NX);
+    }
+
+    #[test]
+    fn test_invalid_and_divide_by_zero() {
+        let rm = RoundingMode::Rne;
+        assert_eq!(fdiv(F64, ONE_F64, 0, rm), (F64.inf(false), FFLAG_DZ));
+        assert_eq!(fdiv(F64, 0, 0, rm), (F64.canonical_nan(), FFLAG_NV));
+        assert_eq!(
+            fsqrt(F32, f32_bits(-1.0), rm),
+            (F32.canonical_nan(), FFLAG_NV)
+        );
+        let snan = 0x7FF0_0000_0000_0001;
+        assert_eq!(
+            fadd(F64, snan, ONE_F64, rm),
+            (F64.canonical_nan(), FFLAG_NV)
+        );
+        assert_eq!(fminmax(F64, snan, ONE_F64, false), (ONE_F64, FFLAG_NV));
+        assert_eq!(
+            fcompare(F64, F64.canonical_nan(), ONE_F64, |x, y| x == y, false),
+            (0, 0)
+        );
+        assert_eq!(
+            fcompare(F64, F64.canonical_nan(), ONE_F64, |x, y| x < y, true),
+            (0, FFLAG_NV)
+        );
+        assert_eq!(
+            to_int(F32, F32.canonical_nan(), 0, u32::MAX as i128, rm),
+            (u32::MAX as i128, FFLAG_NV)
+        );
+        assert_eq!(
+            to_int(F64, f64_bits(-1.0), 0, u64::MAX as i128, rm),
+            (0, FFLAG_NV)
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        let rm = RoundingMode::Rne;
+        assert_eq!(from_int(F64, -7, rm), (f64_bits(-7.0), 0));
+        assert_eq!(
+            from_int(F32, 16_777_217, rm),
+            (f32_bits(16_777_216.0), FFLAG_NX)
+        );
+        assert_eq!(
+            fcvt(F32, F64, f32_bits(0.1), rm),
+            (f64_bits(0.1f32 as f64), 0)
+        );
+        assert_eq!(fcvt(F64, F32, f64_bits(0.1), rm), (f32_bits(0.1), FFLAG_NX));
+        assert_eq!(fclass(F64, f64_bits(f64::NEG_INFINITY)), 1 << 0);
+        assert_eq!(fclass(F32, f32_bits(-0.0)), 1 << 3);
+        assert_eq!(fclass(F64, 1), 1 << 5);
+    }
+
+    #[test]
+    fn test_nan_boxing() {
+        let mut cpu = fp_cpu();
+        // fmv.w.x f1, x5
+        cpu.regs[5] = 0xdead_beef_3f80_0000;
+        cpu.execute_fp(r_type(0x78, 0, 5, 0, 1)).unwrap();
+        assert_eq!(cpu.fregs[1], NAN_BOX | 0x3f80_0000);
+
+        // fadd.s f2, f1, f3 with f3 not boxed: reads as canonical NaN
+        cpu.fregs[3] = f64_bits(1.0);
+        cpu.execute_fp(r_type(0x00, 3, 1, 0, 2)).unwrap();
+        assert_eq!(cpu.fregs[2], NAN_BOX | F32.canonical_nan());
+
+        // fmv.x.w x6, f1 sign-extends the raw low word
+        cpu.fregs[1] = NAN_BOX | 0xbf80_0000;
+        cpu.execute_fp(r_type(0x70, 0, 1, 0, 6)).unwrap();
+        assert_eq!(cpu.regs[6], 0xffff_ffff_bf80_0000);
+    }
+
+    #[test]
+    fn test_execute_accrues_flags_and_dirties_state() {
+        let mut cpu = fp_cpu();
+        cpu.fregs[1] = ONE_F64;
+        cpu.fregs[2] = f64_bits(3.0);
+        // fdiv.d f3, f1, f2 (dynamic rounding, frm = RNE)
+        cpu.execute_fp(r_type(0x0D, 2, 1, 7, 3)).unwrap();
+        assert_eq!(f64::from_bits(cpu.fregs[3]), 1.0 / 3.0);
+        assert_eq!(cpu.csrs[CSR_FCSR as usize] & 0x1F, FFLAG_NX);
+        let mstatus = cpu.csrs[CSR_MSTATUS as usize];
+        assert_eq!(mstatus & MSTATUS_FS, MSTATUS_FS);
+        assert_ne!(mstatus & MSTATUS_SD, 0);
+
+        // fcvt.l.d x5, f3, rtz
+        cpu.fregs[3] = f64_bits(-2.75);
+        cpu.execute_fp(r_type(0x61, 2, 3, 1, 5)).unwrap();
+        assert_eq!(cpu.regs[5] as i64, -2);
+    }
+
+    #[test]
+    fn test_illegal_when_disabled_or_bad_rm() {
+        let mut cpu = Cpu::new(0x8000_0000, 0);
+        let fadd_d = r_type(0x01, 2, 1, 0, 3);
+        assert_eq!(
+            cpu.execute_fp(fadd_d),
+            Err(Trap::IllegalInstruction(fadd_d as u64))
+        );
+
+        let mut cpu = fp_cpu();
+        // Reserved static rounding mode
+        assert!(cpu.execute_fp(r_type(0x01, 2, 1, 5, 3)).is_err());
+        // Invalid dynamic rounding mode in frm
+        cpu.csrs[CSR_FCSR as usize] = 5 << 5;
+        assert!(cpu.execute_fp(r_type(0x01, 2, 1, 7, 3)).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Host-side trap hooks.
+//!
+//! Exceptions whose cause bit is set in `Cpu::intercept_exceptions` are not
+//! taken architecturally: `step()` leaves `pc` at the faulting instruction and
+//! returns the trap, and [`Cpu::step_with_hook`] hands it to a [`TrapHook`].
+//! This is how syscall-level (user-mode) emulation services `ecall` on the
+//! host without a guest kernel.
+
+use super::core::Cpu;
+use super::types::Trap;
+use crate::bus::Bus;
+
+/// Handler for intercepted exceptions.
+pub trait TrapHook {
+    /// Handle `trap`, raised by the instruction at `cpu.pc`.
+    ///
+    /// Return `Ok(())` to resume execution; the hook is responsible for
+    /// advancing `pc` past the instruction. Return `Err` to stop and report
+    /// the trap to the caller of [`Cpu::step_with_hook`].
+    fn on_trap(&mut self, cpu: &mut Cpu, bus: &dyn Bus, trap: Trap) -> Result<(), Trap>;
+}
+
+impl Cpu {
+    /// Step once, routing intercepted exceptions through `hook`.
+    pub fn step_with_hook(&mut self, bus: &dyn Bus, hook: &mut dyn TrapHook) -> Result<(), Trap> {
+        match self.step(bus) {
+            Err(trap) if self.intercepts(&trap) => hook.on_trap(self, bus, trap),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+
+    /// Counts ecalls and skips over them.
+    struct CountEcalls(u32);
+
+    impl TrapHook for CountEcalls {
+        fn on_trap(&mut self, cpu: &mut Cpu, _bus: &dyn Bus, trap: Trap) -> Result<(), Trap> {
+            match trap {
+                Trap::EnvironmentCallFromM => {
+                    self.0 += 1;
+                    cpu.pc += 4;
+                    Ok(())
+                }
+                other => Err(other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_intercepted_ecall_reaches_hook() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.write32(DRAM_BASE, 0x0000_0073).unwrap(); // ecall
+        bus.write32(DRAM_BASE + 4, 0x0000_0073).unwrap(); // ecall
+        bus.write32(DRAM_BASE + 8, 0xffff_ffff).unwrap(); // illegal
+
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.intercept_exceptions = (1 << 11) | (1 << 2);
+        let mut hook = CountEcalls(0);
+
+        cpu.step_with_hook(&bus, &mut hook).unwrap();
+        cpu.step_with_hook(&bus, &mut hook).unwrap();
+        assert_eq!(hook.0, 2);
+
+        // Unhandled exceptions come back with pc still at the instruction
+        let err = cpu.step_with_hook(&bus, &mut hook).unwrap_err();
+        assert!(matches!(err, Trap::IllegalInstruction(_)));
+        assert_eq!(cpu.pc, DRAM_BASE + 8);
+        assert_eq!(cpu.read_csr(crate::csr::CSR_MCAUSE).unwrap(), 0);
+    }
+}
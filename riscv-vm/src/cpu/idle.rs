@@ -0,0 +1,117 @@
+//! Idle detection for WFI.
+//!
+//! WFI retires at once, as the spec allows, but leaves a flag behind so a
+//! run loop can tell the hart has nothing to do. [`Cpu::take_idle`] then
+//! reports how long the hart may sleep: until its next timer deadline
+//! (`mtimecmp`, or `stimecmp` under Sstc), or not at all if an interrupt
+//! is already pending. Guest time follows executed cycles, so after
+//! sleeping on the host the loop lets that time pass with
+//! [`Cpu::skip_idle`], and the timer fires on the next step just as if the
+//! hart had been spinning in a WFI loop.
+
+use super::core::Cpu;
+use super::csr::{CSR_MENVCFG, CSR_MHARTID, CSR_MIE, CSR_MIP, CSR_STIMECMP};
+use crate::bus::{Bus, SystemBus};
+use crate::devices::clint::TIMEBASE_FREQUENCY;
+
+/// Longest single idle period in `mtime` ticks (10 ms), so a run loop
+/// still polls the console and network while the guest sleeps.
+pub const MAX_IDLE_TICKS: u64 = TIMEBASE_FREQUENCY / 100;
+
+impl Cpu {
+    /// Whether the hart executed a WFI that no run loop has taken yet.
+    #[inline]
+    pub fn in_wfi(&self) -> bool {
+        self.wfi
+    }
+
+    /// Take the WFI flag. If it was set and no interrupt is pending,
+    /// returns the `mtime` ticks until the hart's next timer deadline
+    /// (`u64::MAX` if no timer is armed).
+    pub fn take_idle(&mut self, bus: &dyn Bus) -> Option<u64> {
+        if !std::mem::take(&mut self.wfi) {
+            return None;
+        }
+        let hart_id = self.csrs[CSR_MHARTID as usize] as usize;
+        // Any raised device line counts, even one the guest masked
+        let pending = self.csrs[CSR_MIP as usize] & self.csrs[CSR_MIE as usize];
+        if pending != 0 || bus.poll_interrupts_for_hart(hart_id) != 0 {
+            return None;
+        }
+
+        let now = bus.read_mtime().ok()?;
+        let mut deadline = bus.read_mtimecmp(hart_id).unwrap_or(u64::MAX);
+        let sstc_enabled = (self.csrs[CSR_MENVCFG as usize] >> 63) & 1 == 1;
+        let stimecmp = self.csrs[CSR_STIMECMP as usize];
+        if sstc_enabled && stimecmp != 0 {
+            deadline = deadline.min(stimecmp);
+        }
+        match deadline {
+            u64::MAX => Some(u64::MAX),
+            _ if deadline <= now => None,
+            _ => Some(deadline - now),
+        }
+    }
+
+    /// Let `ticks` of guest time pass without executing anything, as
+    /// idle cycles of this hart. Interrupts are polled on the next step.
+    pub fn skip_idle(&mut self, bus: &SystemBus, ticks: u64) {
+        let hz = bus.clint.cpu_frequency() as u128;
+        let tb = TIMEBASE_FREQUENCY as u128;
+        let cycles = (ticks as u128 * hz).div_ceil(tb).min(u64::MAX as u128) as u64;
+        self.cycles = self.cycles.wrapping_add(cycles);
+        self.unsynced_cycles = self.unsynced_cycles.wrapping_add(cycles);
+        self.poll_counter = u8::MAX;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::devices::clint::MTIMECMP_OFFSET;
+
+    #[test]
+    fn test_wfi_idles_until_timer_deadline() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        // wfi; wfi
+        bus.write32(DRAM_BASE, 0x1050_0073).unwrap();
+        bus.write32(DRAM_BASE + 4, 0x1050_0073).unwrap();
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.csrs[CSR_MIE as usize] = 1 << 7;
+        bus.clint.set_mtimecmp(0, 5_000);
+
+        assert_eq!(cpu.take_idle(&bus), None);
+        cpu.step(&bus).unwrap();
+        assert!(cpu.in_wfi());
+        let ticks = cpu.take_idle(&bus).unwrap();
+        assert_eq!(ticks, 5_000 - bus.clint.mtime());
+        assert!(!cpu.in_wfi());
+
+        cpu.skip_idle(&bus, ticks);
+        cpu.step(&bus).unwrap();
+        assert!(bus.clint.mtime() >= 5_000);
+        assert_ne!(cpu.csrs[CSR_MIP as usize] & (1 << 7), 0);
+        // The timer is pending now, so the second WFI does not idle
+        assert!(cpu.in_wfi());
+        assert_eq!(cpu.take_idle(&bus), None);
+    }
+
+    #[test]
+    fn test_wfi_follows_a_moved_clint() {
+        use crate::bus::BusConfig;
+
+        let config = BusConfig {
+            clint_base: 0x0300_0000,
+            ..BusConfig::default()
+        };
+        let bus = SystemBus::with_config(config);
+        bus.write32(DRAM_BASE, 0x1050_0073).unwrap();
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.csrs[CSR_MIE as usize] = 1 << 7;
+        bus.write64(0x0300_0000 + MTIMECMP_OFFSET, 5_000).unwrap();
+
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.take_idle(&bus), Some(5_000 - bus.clint.mtime()));
+    }
+}
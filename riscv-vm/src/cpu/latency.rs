@@ -0,0 +1,356 @@
+//! Interrupt latency.
+//!
+//! A hart only looks at its interrupt lines when it polls them, once every
+//! 256 steps (an instruction, a block or a JIT trace), and takes a pending
+//! interrupt only while the guest has it enabled. [`InterruptLatency`]
+//! records how many cycles of the hart each taken interrupt waited, from
+//! the moment it became pending to the trap, along with how often the hart
+//! polled, so the cost of checking less often can be weighed against the
+//! throughput it buys.
+//!
+//! Timer interrupts are timed from their deadline (`mtimecmp`, or
+//! `stimecmp` under Sstc) converted to cycles at the modelled clock, so
+//! they include the wait for the next poll. The PLIC raises device lines
+//! when devices are serviced, which happens at a poll, and IPIs and bits
+//! set by software carry no timestamp, so those interrupts are timed from
+//! the poll that first saw them. An interrupt still pending after it was
+//! taken counts again from that trap.
+
+use std::fmt;
+
+use super::core::Cpu;
+use super::csr::{CSR_MENVCFG, CSR_MHARTID, CSR_MIP, CSR_STIMECMP};
+use super::types::Trap;
+use crate::bus::Bus;
+use crate::devices::clint::TIMEBASE_FREQUENCY;
+
+/// Buckets of a [`LatencyHistogram`].
+pub const LATENCY_BUCKETS: usize = 32;
+
+const MIP_STIP: usize = 5;
+const MIP_MTIP: usize = 7;
+
+/// Distribution of interrupt latencies, in cycles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Interrupts taken.
+    pub count: u64,
+    /// Sum of their latencies.
+    pub total: u64,
+    /// Longest latency.
+    pub max: u64,
+    /// Bucket 0 counts latencies of 0 cycles and bucket `i` those of
+    /// `2^(i-1)` to `2^i - 1` cycles; the last one also counts all longer
+    /// ones.
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, cycles: u64) {
+        let bucket = (u64::BITS - cycles.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total = self.total.saturating_add(cycles);
+        self.max = self.max.max(cycles);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, n) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += n;
+        }
+        self.count += other.count;
+        self.total = self.total.saturating_add(other.total);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Average latency.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+
+    /// Upper bound of the latency below which `fraction` (0 to 1) of the
+    /// interrupts fall, as precise as the buckets allow.
+    pub fn percentile(&self, fraction: f64) -> u64 {
+        let target = (self.count as f64 * fraction).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                let upper = if i == 0 { 0 } else { (1u64 << i) - 1 };
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} taken, mean {:.0}, p99 {}, max {} cycles",
+            self.count,
+            self.mean(),
+            self.percentile(0.99),
+            self.max
+        )
+    }
+}
+
+/// Interrupt figures of a hart, or of several summed; see the
+/// [module docs](self).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterruptLatency {
+    /// Interrupt polls.
+    pub polls: u64,
+    /// Software interrupts (IPIs).
+    pub software: LatencyHistogram,
+    /// Timer interrupts.
+    pub timer: LatencyHistogram,
+    /// External interrupts from the PLIC.
+    pub external: LatencyHistogram,
+}
+
+impl InterruptLatency {
+    pub fn merge(&mut self, other: &InterruptLatency) {
+        self.polls += other.polls;
+        self.software.merge(&other.software);
+        self.timer.merge(&other.timer);
+        self.external.merge(&other.external);
+    }
+
+    /// All interrupts taken, whatever their kind.
+    pub fn all(&self) -> LatencyHistogram {
+        let mut all = self.software;
+        all.merge(&self.timer);
+        all.merge(&self.external);
+        all
+    }
+
+    fn kind(&mut self, bit: usize) -> &mut LatencyHistogram {
+        match bit {
+            1 | 3 => &mut self.software,
+            5 | 7 => &mut self.timer,
+            _ => &mut self.external,
+        }
+    }
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} polls", self.polls)?;
+        for (name, kind) in [
+            ("timer", &self.timer),
+            ("external", &self.external),
+            ("software", &self.software),
+        ] {
+            if kind.count > 0 {
+                write!(f, "; {}: {}", name, kind)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Per-hart bookkeeping behind [`InterruptLatency`].
+#[derive(Clone, Debug, Default)]
+pub(super) struct InterruptClock {
+    /// `mip` as of the last poll.
+    seen: u64,
+    /// Per `mip` bit set in `seen`: cycle it became pending, or was last
+    /// taken.
+    since: [u64; 12],
+    /// `cycles` at the last poll.
+    last_poll: u64,
+    stats: InterruptLatency,
+}
+
+impl Cpu {
+    /// Time the interrupts that became pending since the last poll. Called
+    /// by every poll, right after it updated `mip`.
+    pub(super) fn note_interrupt_poll(&mut self, bus: &dyn Bus) {
+        let mip = self.csrs[CSR_MIP as usize];
+        let rising = mip & !self.irq_clock.seen;
+        // A line that rose since the last poll became pending after it
+        let since_poll = self.cycles.wrapping_sub(self.irq_clock.last_poll);
+        for bit in [1, 3, 5, 7, 9, 11] {
+            if rising & (1 << bit) != 0 {
+                let late = self.timer_lateness(bus, bit).min(since_poll);
+                self.irq_clock.since[bit] = self.cycles.wrapping_sub(late);
+            }
+        }
+        let clock = &mut self.irq_clock;
+        clock.seen = mip;
+        clock.last_poll = self.cycles;
+        clock.stats.polls += 1;
+    }
+
+    /// Record the latency of the interrupt `trap` the hart is taking.
+    pub(super) fn note_interrupt_taken(&mut self, trap: &Trap) {
+        let Some((true, cause, _)) = Self::trap_to_cause_tval(trap) else {
+            return;
+        };
+        let bit = cause as usize;
+        if bit >= self.irq_clock.since.len() {
+            return;
+        }
+        let clock = &mut self.irq_clock;
+        let latency = self.cycles.wrapping_sub(clock.since[bit]);
+        clock.stats.kind(bit).record(latency);
+        clock.since[bit] = self.cycles;
+    }
+
+    /// Cycles since the timer deadline behind `mip` bit `bit` passed, or 0
+    /// if the bit is not a timer or has no deadline.
+    fn timer_lateness(&self, bus: &dyn Bus, bit: usize) -> u64 {
+        if bit != MIP_MTIP && bit != MIP_STIP {
+            return 0;
+        }
+        let Ok(now) = bus.read_mtime() else {
+            return 0;
+        };
+        let hart_id = self.csrs[CSR_MHARTID as usize] as usize;
+        let sstc_enabled = (self.csrs[CSR_MENVCFG as usize] >> 63) & 1 == 1;
+        let stimecmp = self.csrs[CSR_STIMECMP as usize];
+        let deadline = match bit {
+            MIP_STIP if sstc_enabled && stimecmp != 0 && stimecmp <= now => Some(stimecmp),
+            // The built-in SBI turns the machine timer into STIP
+            MIP_STIP if self.sbi.is_none() => None,
+            _ => bus.read_mtimecmp(hart_id).ok(),
+        };
+        match deadline {
+            Some(deadline) if deadline <= now => {
+                let cycles = (now - deadline) as u128 * bus.cpu_frequency() as u128
+                    / TIMEBASE_FREQUENCY as u128;
+                cycles.min(u64::MAX as u128) as u64
+            }
+            _ => 0,
+        }
+    }
+
+    /// Interrupt figures gathered since the hart was created or they were
+    /// last taken.
+    pub fn interrupt_latency(&self) -> InterruptLatency {
+        self.irq_clock.stats
+    }
+
+    /// Return the interrupt figures and start gathering anew.
+    pub fn take_interrupt_latency(&mut self) -> InterruptLatency {
+        std::mem::take(&mut self.irq_clock.stats)
+    }
+
+    /// Forget since when interrupts are pending, e.g. after the hart's
+    /// state was replaced by a snapshot restore. The figures are kept.
+    pub(super) fn reset_interrupt_clock(&mut self) {
+        self.irq_clock = InterruptClock {
+            last_poll: self.cycles,
+            stats: self.irq_clock.stats,
+            ..InterruptClock::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+    use crate::cpu::csr::{CSR_MIE, CSR_MSTATUS, CSR_MTVEC};
+    use crate::cpu::test_hart;
+
+    const NOP: u32 = 0x0000_0013;
+    const MSTATUS_MIE: u64 = 1 << 3;
+
+    /// A hart running NOPs in M-mode, with its trap vector on NOPs too.
+    fn rig() -> (SystemBus, Cpu) {
+        let (mut cpu, bus) = test_hart(&[NOP; 0x4000]);
+        cpu.csrs[CSR_MTVEC as usize] = DRAM_BASE + 0x8000;
+        (bus, cpu)
+    }
+
+    /// Run `steps` steps; taking an interrupt ends one with an error.
+    fn run(cpu: &mut Cpu, bus: &SystemBus, steps: usize) {
+        for _ in 0..steps {
+            let _ = cpu.step(bus);
+        }
+    }
+
+    #[test]
+    fn test_histogram_buckets_and_percentiles() {
+        let mut h = LatencyHistogram::default();
+        for cycles in [0, 1, 5, 6, 7, 100] {
+            h.record(cycles);
+        }
+        assert_eq!(h.buckets[0], 1);
+        assert_eq!(h.buckets[1], 1);
+        assert_eq!(h.buckets[3], 3);
+        assert_eq!(h.buckets[7], 1);
+        assert_eq!(h.percentile(0.5), 7);
+        assert_eq!(h.percentile(1.0), 100);
+        assert_eq!(h.total, 119);
+
+        let mut merged = h;
+        merged.merge(&h);
+        assert_eq!(merged.count, 12);
+        assert_eq!(merged.buckets[3], 6);
+        assert_eq!(merged.max, 100);
+        h.record(u64::MAX);
+        assert_eq!(h.buckets[LATENCY_BUCKETS - 1], 1);
+    }
+
+    #[test]
+    fn test_timer_latency_counts_from_the_deadline() {
+        let (bus, mut cpu) = rig();
+        cpu.csrs[CSR_MIE as usize] = 1 << MIP_MTIP;
+        cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_MIE;
+        // The deadline passes between the first and the second poll
+        let ticks_per_poll = 256 * TIMEBASE_FREQUENCY / bus.cpu_frequency();
+        let deadline = ticks_per_poll * 3 / 2;
+        bus.clint.set_mtimecmp(0, deadline);
+
+        run(&mut cpu, &bus, 511);
+        assert_eq!(cpu.interrupt_latency().timer.count, 0);
+        let polled_at = cpu.cycles;
+        run(&mut cpu, &bus, 1);
+        assert_eq!(cpu.pc, DRAM_BASE + 0x8000);
+
+        let stats = cpu.interrupt_latency();
+        assert_eq!(stats.polls, 2);
+        assert_eq!(stats.timer.count, 1);
+        // Exact to an mtime tick
+        let cycles_per_tick = bus.cpu_frequency() / TIMEBASE_FREQUENCY;
+        let late = polled_at - deadline * cycles_per_tick;
+        assert!(stats.timer.max <= late && stats.timer.max + cycles_per_tick > late);
+        assert_eq!(stats.all().count, 1);
+    }
+
+    #[test]
+    fn test_masked_interrupt_counts_from_the_poll_that_saw_it() {
+        let (bus, mut cpu) = rig();
+        cpu.csrs[CSR_MIE as usize] = 1 << 3;
+        bus.clint.set_msip(0, 1);
+
+        run(&mut cpu, &bus, 255);
+        let seen_at = cpu.cycles;
+        run(&mut cpu, &bus, 256);
+        assert_eq!(cpu.interrupt_latency().software.count, 0);
+        cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_MIE;
+        let taken_at = cpu.cycles;
+        run(&mut cpu, &bus, 1);
+
+        let stats = cpu.take_interrupt_latency();
+        assert_eq!(stats.software.count, 1);
+        assert_eq!(stats.software.max, taken_at - seen_at);
+        assert_eq!(cpu.interrupt_latency(), InterruptLatency::default());
+
+        // Still raised, so it is taken again once re-enabled, counting
+        // from the last trap
+        cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_MIE;
+        run(&mut cpu, &bus, 256);
+        assert_eq!(cpu.interrupt_latency().software.count, 1);
+        assert_eq!(cpu.interrupt_latency().software.max, cpu.cycles - taken_at);
+    }
+}
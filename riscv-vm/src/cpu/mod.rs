@@ -1,7 +1,44 @@
 pub mod core;
+pub mod counters;
 pub mod csr;
+pub mod debug;
 pub mod execution;
+pub mod fpu;
+pub mod hook;
+pub mod idle;
+pub mod latency;
+pub mod pmp;
+pub mod sbi;
+pub mod tracer;
 pub mod types;
+pub mod vector;
+pub mod watch;
 
 pub use core::Cpu;
+pub use counters::PerfCounters;
+pub use csr::CsrPolicy;
+pub use debug::{TrapBreak, TrapBreakHit};
+pub use hook::TrapHook;
+pub use latency::{InterruptLatency, LatencyHistogram};
+pub use sbi::SbiConfig;
+pub use tracer::{RegWrite, TraceFilter, TraceRecord, TraceSink, Tracer};
 pub use types::{Mode, Trap};
+pub use watch::{WatchHit, WatchId, WatchSpace, Watchpoint};
+
+/// Write `program` to memory from `DRAM_BASE` on.
+#[cfg(test)]
+pub(crate) fn load_test_program(bus: &crate::bus::SystemBus, program: &[u32]) {
+    use crate::bus::{Bus, DRAM_BASE};
+    for (i, &insn) in program.iter().enumerate() {
+        bus.write32(DRAM_BASE + i as u64 * 4, insn).unwrap();
+    }
+}
+
+/// A hart at `DRAM_BASE` of a 1 MiB machine, with `program` loaded there.
+#[cfg(test)]
+pub(crate) fn test_hart(program: &[u32]) -> (Cpu, crate::bus::SystemBus) {
+    use crate::bus::{DRAM_BASE, SystemBus};
+    let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+    load_test_program(&bus, program);
+    (Cpu::new(DRAM_BASE, 0), bus)
+}
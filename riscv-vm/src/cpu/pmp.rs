@@ -0,0 +1,289 @@
+//! Physical Memory Protection (PMP).
+//!
+//! Each hart implements [`PMP_ENTRIES`] entries, configured through
+//! `pmpcfg0`/`pmpcfg2` (eight 8-bit configs each, the odd `pmpcfg` CSRs do
+//! not exist on RV64) and `pmpaddr0-15`. An entry matches a naturally
+//! aligned power-of-two region (NAPOT), a single 4-byte word (NA4) or the
+//! range from the previous entry's address up to its own (TOR). Locked
+//! entries cannot be rewritten until reset and also restrict M-mode.
+//!
+//! Checks are made on the physical address after translation, for data
+//! accesses and instruction fetches. The lowest-numbered matching entry
+//! decides, and an access that only partly falls inside it fails. When no
+//! entry matches, M-mode is allowed and S/U-mode are denied, except that a
+//! hart with every entry OFF behaves as if PMP were not implemented, so
+//! guests that never program it (including kernels booted through the
+//! built-in SBI) keep full access. Page-table walks are not checked.
+//!
+//! The decoded entries are cached in [`Pmp`], rebuilt on every PMP CSR
+//! write, so the common case of no active entries costs a single branch.
+
+use super::core::Cpu;
+use super::csr::CsrFile;
+use super::types::{Mode, Trap};
+use crate::mmu::AccessType;
+
+/// Number of PMP entries implemented per hart.
+pub const PMP_ENTRIES: usize = 16;
+
+pub const CSR_PMPCFG0: u16 = 0x3A0;
+pub const CSR_PMPCFG15: u16 = 0x3AF;
+pub const CSR_PMPADDR0: u16 = 0x3B0;
+pub const CSR_PMPADDR63: u16 = 0x3EF;
+
+pub const PMP_R: u8 = 1 << 0;
+pub const PMP_W: u8 = 1 << 1;
+pub const PMP_X: u8 = 1 << 2;
+pub const PMP_A_SHIFT: u8 = 3;
+pub const PMP_L: u8 = 1 << 7;
+
+pub const PMP_A_OFF: u8 = 0;
+pub const PMP_A_TOR: u8 = 1;
+pub const PMP_A_NA4: u8 = 2;
+pub const PMP_A_NAPOT: u8 = 3;
+
+/// `pmpaddr` holds bits 55:2 of a 56-bit physical address.
+const PMPADDR_MASK: u64 = (1 << 54) - 1;
+
+/// Whether `addr` is one of the `pmpcfg`/`pmpaddr` CSRs.
+#[inline]
+pub fn is_pmp_csr(addr: u16) -> bool {
+    (CSR_PMPCFG0..=CSR_PMPADDR63).contains(&addr)
+}
+
+/// 8-bit configuration of entry `index`.
+fn cfg(csrs: &CsrFile, index: usize) -> u8 {
+    let reg = CSR_PMPCFG0 as usize + (index / 8) * 2;
+    (csrs[reg] >> ((index % 8) * 8)) as u8
+}
+
+fn address_mode(cfg: u8) -> u8 {
+    (cfg >> PMP_A_SHIFT) & 3
+}
+
+/// Apply a write of `val` to a PMP CSR with the WARL and locking rules:
+/// locked configs and addresses keep their value, as does the address
+/// below a locked TOR entry; `W` without `R` is reserved and drops `W`.
+pub(super) fn write_csr(csrs: &mut CsrFile, addr: u16, val: u64) -> Result<(), Trap> {
+    if addr <= CSR_PMPCFG15 {
+        if addr & 1 != 0 {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        let first = (addr - CSR_PMPCFG0) as usize / 2 * 8;
+        if first >= PMP_ENTRIES {
+            return Ok(());
+        }
+        let old = csrs[addr as usize];
+        let mut new = 0;
+        for byte in 0..8 {
+            let shift = byte * 8;
+            let old_cfg = (old >> shift) as u8;
+            let mut cfg = (val >> shift) as u8;
+            if old_cfg & PMP_L != 0 {
+                cfg = old_cfg;
+            } else {
+                cfg &= PMP_R | PMP_W | PMP_X | (3 << PMP_A_SHIFT) | PMP_L;
+                if cfg & (PMP_R | PMP_W) == PMP_W {
+                    cfg &= !PMP_W;
+                }
+            }
+            new |= (cfg as u64) << shift;
+        }
+        csrs[addr as usize] = new;
+    } else {
+        let index = (addr - CSR_PMPADDR0) as usize;
+        if index >= PMP_ENTRIES {
+            return Ok(());
+        }
+        let locked = cfg(csrs, index) & PMP_L != 0
+            || (index + 1 < PMP_ENTRIES && {
+                let next = cfg(csrs, index + 1);
+                next & PMP_L != 0 && address_mode(next) == PMP_A_TOR
+            });
+        if !locked {
+            csrs[addr as usize] = val & PMPADDR_MASK;
+        }
+    }
+    Ok(())
+}
+
+/// One active entry: `[start, end)` with its config bits.
+#[derive(Clone, Copy, Debug, Default)]
+struct Region {
+    start: u64,
+    end: u64,
+    cfg: u8,
+}
+
+/// Decoded PMP entries of a hart, in priority order.
+#[derive(Clone, Debug, Default)]
+pub struct Pmp {
+    regions: [Region; PMP_ENTRIES],
+    len: usize,
+    /// Some active entry is locked and so also applies to M-mode.
+    locked: bool,
+}
+
+impl Pmp {
+    /// Decode the entries currently held in `csrs`.
+    pub fn from_csrs(csrs: &CsrFile) -> Self {
+        let mut pmp = Self::default();
+        let mut prev_top = 0;
+        for index in 0..PMP_ENTRIES {
+            let cfg = cfg(csrs, index);
+            let pmpaddr = csrs[CSR_PMPADDR0 as usize + index] & PMPADDR_MASK;
+            let range = match address_mode(cfg) {
+                PMP_A_TOR => Some((prev_top << 2, pmpaddr << 2)),
+                PMP_A_NA4 => Some((pmpaddr << 2, (pmpaddr << 2) + 4)),
+                PMP_A_NAPOT => {
+                    let ones = pmpaddr.trailing_ones().min(54);
+                    let size = 1u64 << (ones + 3);
+                    let start = (pmpaddr & !((1u64 << ones) - 1)) << 2;
+                    Some((start, start.saturating_add(size)))
+                }
+                _ => None,
+            };
+            prev_top = pmpaddr;
+            if let Some((start, end)) = range {
+                pmp.regions[pmp.len] = Region { start, end, cfg };
+                pmp.len += 1;
+                pmp.locked |= cfg & PMP_L != 0;
+            }
+        }
+        pmp
+    }
+
+    /// Whether accesses in `mode` have to be checked at all.
+    #[inline]
+    pub fn applies(&self, mode: Mode) -> bool {
+        self.len != 0 && (mode != Mode::Machine || self.locked)
+    }
+
+    /// Whether `mode` may access the `len` bytes at physical address `pa`.
+    pub fn allows(&self, pa: u64, len: u64, mode: Mode, access: AccessType) -> bool {
+        let last = pa.saturating_add(len.max(1) - 1);
+        for region in &self.regions[..self.len] {
+            if last < region.start || pa >= region.end {
+                continue;
+            }
+            if pa < region.start || last >= region.end {
+                // Straddles the entry boundary
+                return false;
+            }
+            if mode == Mode::Machine && region.cfg & PMP_L == 0 {
+                return true;
+            }
+            let needed = match access {
+                AccessType::Load => PMP_R,
+                AccessType::Store => PMP_W,
+                AccessType::Instruction => PMP_X,
+            };
+            return region.cfg & needed != 0;
+        }
+        mode == Mode::Machine
+    }
+}
+
+impl Cpu {
+    /// Re-decode the PMP entries after their CSRs changed.
+    pub(super) fn sync_pmp(&mut self) {
+        self.pmp = Pmp::from_csrs(&self.csrs);
+    }
+
+    /// Check a translated access of `len` bytes at `pa` (for virtual address
+    /// `vaddr`) against PMP, returning the access fault on denial.
+    #[inline]
+    pub(super) fn pmp_check(
+        &self,
+        vaddr: u64,
+        pa: u64,
+        len: u64,
+        access: AccessType,
+    ) -> Result<(), Trap> {
+        if !self.pmp.applies(self.mode) || self.pmp.allows(pa, len, self.mode, access) {
+            return Ok(());
+        }
+        Err(match access {
+            AccessType::Instruction => Trap::InstructionAccessFault(vaddr),
+            AccessType::Load => Trap::LoadAccessFault(vaddr),
+            AccessType::Store => Trap::StoreAccessFault(vaddr),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE, SystemBus};
+    use crate::cpu::csr::CSR_MCAUSE;
+
+    fn napot(base: u64, size: u64) -> u64 {
+        (base | (size / 2 - 1)) >> 2
+    }
+
+    fn set_cfg(cpu: &mut Cpu, index: usize, cfg: u8) {
+        let reg = CSR_PMPCFG0 + (index / 8) as u16 * 2;
+        let old = cpu.read_csr(reg).unwrap();
+        let shift = (index % 8) * 8;
+        let val = (old & !(0xff << shift)) | ((cfg as u64) << shift);
+        cpu.write_csr(reg, val).unwrap();
+    }
+
+    #[test]
+    fn test_napot_and_tor_matching() {
+        let mut cpu = Cpu::new(0, 0);
+        cpu.write_csr(CSR_PMPADDR0, napot(0x8000_0000, 0x1000))
+            .unwrap();
+        set_cfg(&mut cpu, 0, PMP_R | (PMP_A_NAPOT << PMP_A_SHIFT));
+        cpu.write_csr(CSR_PMPADDR0 + 1, 0x8000_2000 >> 2).unwrap();
+        set_cfg(&mut cpu, 1, PMP_R | PMP_W | (PMP_A_TOR << PMP_A_SHIFT));
+
+        let pmp = &cpu.pmp;
+        let s = Mode::Supervisor;
+        assert!(pmp.allows(0x8000_0ff8, 8, s, AccessType::Load));
+        assert!(!pmp.allows(0x8000_0ff8, 8, s, AccessType::Store));
+        // Entry 1 covers [pmpaddr0 << 2, 0x8000_2000), entry 0 wins below 0x8000_1000
+        assert!(pmp.allows(0x8000_1000, 8, s, AccessType::Store));
+        assert!(!pmp.allows(0x8000_0ffc, 8, s, AccessType::Load));
+        assert!(!pmp.allows(0x8000_2000, 4, s, AccessType::Load));
+        // M-mode is unrestricted by unlocked entries
+        assert!(pmp.allows(0x8000_0000, 4, Mode::Machine, AccessType::Store));
+        assert!(!pmp.applies(Mode::Machine));
+    }
+
+    #[test]
+    fn test_locked_entry_binds_machine_mode_and_ignores_writes() {
+        let mut cpu = Cpu::new(0, 0);
+        cpu.write_csr(CSR_PMPADDR0, 0x1000 >> 2).unwrap();
+        set_cfg(&mut cpu, 0, PMP_R | PMP_L | (PMP_A_NA4 << PMP_A_SHIFT));
+        assert!(cpu.pmp.applies(Mode::Machine));
+        assert!(cpu.pmp.allows(0x1000, 4, Mode::Machine, AccessType::Load));
+        assert!(!cpu.pmp.allows(0x1000, 4, Mode::Machine, AccessType::Store));
+
+        cpu.write_csr(CSR_PMPADDR0, 0x2000 >> 2).unwrap();
+        set_cfg(&mut cpu, 0, PMP_R | PMP_W | (PMP_A_NA4 << PMP_A_SHIFT));
+        assert_eq!(cpu.read_csr(CSR_PMPADDR0).unwrap(), 0x1000 >> 2);
+        assert!(!cpu.pmp.allows(0x1000, 4, Mode::Machine, AccessType::Store));
+        // Odd pmpcfg registers do not exist on RV64
+        assert!(cpu.write_csr(CSR_PMPCFG0 + 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_user_access_faults_without_matching_entry() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        // lw x1, 0(x2)
+        bus.write32(DRAM_BASE, 0x0001_2083).unwrap();
+        // Let U-mode execute the code page but not read the data page
+        cpu.write_csr(CSR_PMPADDR0, napot(DRAM_BASE, 0x1000))
+            .unwrap();
+        set_cfg(&mut cpu, 0, PMP_X | (PMP_A_NAPOT << PMP_A_SHIFT));
+        cpu.mode = Mode::User;
+        cpu.regs[2] = DRAM_BASE + 0x1000;
+
+        let _ = cpu.step(&bus);
+        assert_eq!(cpu.mode, Mode::Machine);
+        assert_eq!(cpu.read_csr(CSR_MCAUSE).unwrap(), 5);
+        assert_eq!(cpu.pc, 0);
+    }
+}
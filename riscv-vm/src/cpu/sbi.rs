@@ -0,0 +1,463 @@
+//! Built-in SBI firmware.
+//!
+//! Lets a supervisor-mode kernel (Linux, or an S-mode xv6 port) boot without
+//! bundling OpenSBI. [`Cpu::enable_sbi`] sets the hart up the way firmware
+//! would leave it, delegating exceptions and supervisor interrupts and
+//! entering the kernel in S-mode with the hart ID in `a0` and the device
+//! tree in `a1`. From then on `ecall` from S-mode is serviced on the host
+//! instead of trapping to M-mode:
+//!
+//! | Extension        | EID          | Functions                                  |
+//! |------------------|--------------|--------------------------------------------|
+//! | Base             | `0x10`       | spec/impl version, probe, machine IDs      |
+//! | Legacy           | `0x00-0x08`  | timer, console, clear IPI, fences, shutdown|
+//! | Timer            | `TIME`       | `set_timer`                                |
+//! | IPI              | `sPI`        | `send_ipi`                                 |
+//! | Remote fence     | `RFNC`       | `remote_fence_i`, `remote_sfence_vma(_asid)` |
+//! | System reset     | `SRST`       | shutdown and reboot (both halt the VM)     |
+//! | Debug console    | `DBCN`       | write, read, write byte                    |
+//!
+//! The machine timer and software interrupts are forwarded to S-mode as
+//! STIP and SSIP when the hart polls for interrupts. Remote fences flush
+//! every hart's TLB and block caches by their next poll. There is no hart
+//! state management, so all harts enter the kernel together (Linux's
+//! spin-wait boot).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::core::Cpu;
+use super::csr::{
+    CSR_MARCHID, CSR_MCOUNTEREN, CSR_MEDELEG, CSR_MHARTID, CSR_MIDELEG, CSR_MIMPID, CSR_MIP,
+    CSR_MVENDORID, CSR_SATP,
+};
+use super::types::{Mode, Trap};
+use crate::bus::Bus;
+use crate::devices::clint::{HART_COUNT_OFFSET, MSIP_OFFSET, MTIMECMP_OFFSET};
+
+/// Implemented version of the SBI specification (2.0).
+pub const SBI_SPEC_VERSION: u64 = 2 << 24;
+/// Implementation ID reported by `sbi_get_impl_id`.
+pub const SBI_IMPL_ID: u64 = 0x5256_4d00;
+
+pub const EID_BASE: u64 = 0x10;
+pub const EID_TIME: u64 = 0x5449_4d45;
+pub const EID_IPI: u64 = 0x0073_5049;
+pub const EID_RFENCE: u64 = 0x5246_4e43;
+pub const EID_SRST: u64 = 0x5352_5354;
+pub const EID_DBCN: u64 = 0x4442_434e;
+
+const LEGACY_SET_TIMER: u64 = 0x00;
+const LEGACY_CONSOLE_PUTCHAR: u64 = 0x01;
+const LEGACY_CONSOLE_GETCHAR: u64 = 0x02;
+const LEGACY_CLEAR_IPI: u64 = 0x03;
+const LEGACY_REMOTE_FENCE_I: u64 = 0x05;
+const LEGACY_REMOTE_SFENCE_VMA_ASID: u64 = 0x07;
+const LEGACY_SHUTDOWN: u64 = 0x08;
+
+const SBI_SUCCESS: i64 = 0;
+const SBI_ERR_FAILED: i64 = -1;
+const SBI_ERR_NOT_SUPPORTED: i64 = -2;
+const SBI_ERR_INVALID_PARAM: i64 = -3;
+
+/// Halt codes of `sbi_system_reset`, as written to the test finisher.
+const HALT_PASS: u64 = 0x5555;
+const HALT_FAIL: u64 = 0x3333;
+const HALT_RESET: u64 = 0x7777;
+
+/// Exceptions handed straight to S-mode: everything but `ecall` from S/M.
+const DELEGATED_EXCEPTIONS: u64 = 0xb1ff;
+/// SSIP, STIP and SEIP.
+const DELEGATED_INTERRUPTS: u64 = (1 << 1) | (1 << 5) | (1 << 9);
+
+const MIP_SSIP: u64 = 1 << 1;
+const MIP_MSIP: u64 = 1 << 3;
+const MIP_STIP: u64 = 1 << 5;
+const MIP_MTIP: u64 = 1 << 7;
+
+const UART_LSR: u64 = 5;
+const UART_LSR_DR: u8 = 1;
+
+/// Bumped by every remote fence; harts flush when they see a new value.
+static FENCE_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Where the built-in SBI enters the kernel and finds its devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SbiConfig {
+    /// Supervisor entry point.
+    pub entry: u64,
+    /// Device tree address handed over in `a1`.
+    pub fdt: u64,
+    pub clint_base: u64,
+    pub uart_base: u64,
+}
+
+/// Per-hart state of the built-in SBI.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SbiState {
+    clint_base: u64,
+    uart_base: u64,
+    /// Last `FENCE_EPOCH` this hart flushed for.
+    fence_epoch: u64,
+}
+
+/// SBI call result: `a0` (error) and `a1` (value).
+type SbiRet = (i64, u64);
+
+impl Cpu {
+    /// Boot this hart as a supervisor-mode kernel under the built-in SBI:
+    /// delegate exceptions and supervisor interrupts, enter `config.entry`
+    /// in S-mode and service S-mode `ecall`s on the host from now on.
+    pub fn enable_sbi(&mut self, config: SbiConfig) {
+        self.csrs[CSR_MEDELEG as usize] = DELEGATED_EXCEPTIONS;
+        self.csrs[CSR_MIDELEG as usize] = DELEGATED_INTERRUPTS;
+        self.csrs[CSR_MCOUNTEREN as usize] = 0x7;
+        self.csrs[CSR_SATP as usize] = 0;
+        self.sbi = Some(SbiState {
+            clint_base: config.clint_base,
+            uart_base: config.uart_base,
+            fence_epoch: FENCE_EPOCH.load(Ordering::Acquire),
+        });
+        self.mode = Mode::Supervisor;
+        self.pc = config.entry;
+        self.regs[10] = self.csrs[CSR_MHARTID as usize];
+        self.regs[11] = config.fdt;
+        self.tlb.flush();
+        self.invalidate_blocks();
+    }
+
+    /// Whether S-mode `ecall`s are serviced by the built-in SBI.
+    pub fn sbi_enabled(&self) -> bool {
+        self.sbi.is_some()
+    }
+
+    /// Turn the machine timer and software interrupts in `hw_mip` into
+    /// their supervisor counterparts, and apply pending remote fences.
+    pub(super) fn sbi_route_interrupts(&mut self, bus: &dyn Bus, mut hw_mip: u64) -> u64 {
+        let Some(state) = self.sbi else {
+            return hw_mip;
+        };
+        let hart = self.csrs[CSR_MHARTID as usize];
+        if hw_mip & MIP_MSIP != 0 {
+            let _ = bus.write32(state.clint_base + MSIP_OFFSET + hart * 4, 0);
+            self.csrs[CSR_MIP as usize] |= MIP_SSIP;
+        }
+        if hw_mip & MIP_MTIP != 0 {
+            hw_mip |= MIP_STIP;
+        }
+        hw_mip &= !(MIP_MSIP | MIP_MTIP);
+
+        let epoch = FENCE_EPOCH.load(Ordering::Acquire);
+        if epoch != state.fence_epoch {
+            self.sbi = Some(SbiState {
+                fence_epoch: epoch,
+                ..state
+            });
+            self.tlb.flush();
+            self.invalidate_blocks();
+        }
+        hw_mip
+    }
+
+    /// Service the SBI call of the `ecall` at `pc`.
+    pub(super) fn sbi_call(&mut self, bus: &dyn Bus, pc: u64) -> Result<(), Trap> {
+        let Some(state) = self.sbi else {
+            return Err(Trap::Fatal("SBI call without SBI".to_string()));
+        };
+        let eid = self.regs[17];
+        let fid = self.regs[16];
+        let args = [self.regs[10], self.regs[11], self.regs[12]];
+
+        let (error, value) = match eid {
+            0x00..=0x08 => {
+                self.regs[10] = self.sbi_legacy(bus, &state, eid, args[0])? as u64;
+                self.pc = pc.wrapping_add(4);
+                return Ok(());
+            }
+            EID_BASE => self.sbi_base(fid, args[0]),
+            EID_TIME if fid == 0 => self.sbi_set_timer(bus, &state, args[0]),
+            EID_IPI if fid == 0 => Self::sbi_send_ipi(bus, &state, args[0], args[1]),
+            EID_RFENCE if fid <= 2 => self.sbi_remote_fence(),
+            EID_SRST if fid == 0 => return Err(Self::sbi_system_reset(args[0], args[1])),
+            EID_DBCN => Self::sbi_debug_console(bus, &state, fid, args),
+            _ => (SBI_ERR_NOT_SUPPORTED, 0),
+        };
+        self.regs[10] = error as u64;
+        self.regs[11] = value;
+        self.pc = pc.wrapping_add(4);
+        Ok(())
+    }
+
+    fn sbi_legacy(
+        &mut self,
+        bus: &dyn Bus,
+        state: &SbiState,
+        eid: u64,
+        arg: u64,
+    ) -> Result<i64, Trap> {
+        Ok(match eid {
+            LEGACY_SET_TIMER => self.sbi_set_timer(bus, state, arg).0,
+            LEGACY_CONSOLE_PUTCHAR => {
+                let _ = bus.write8(state.uart_base, arg as u8);
+                SBI_SUCCESS
+            }
+            LEGACY_CONSOLE_GETCHAR => match Self::sbi_getchar(bus, state) {
+                Some(byte) => byte as i64,
+                None => -1,
+            },
+            LEGACY_CLEAR_IPI => {
+                self.csrs[CSR_MIP as usize] &= !MIP_SSIP;
+                SBI_SUCCESS
+            }
+            LEGACY_REMOTE_FENCE_I..=LEGACY_REMOTE_SFENCE_VMA_ASID => self.sbi_remote_fence().0,
+            LEGACY_SHUTDOWN => return Err(Trap::RequestedTrap(HALT_PASS)),
+            // send_ipi takes a hart mask by virtual address
+            _ => SBI_ERR_NOT_SUPPORTED,
+        })
+    }
+
+    fn sbi_base(&self, fid: u64, arg: u64) -> SbiRet {
+        match fid {
+            0 => (SBI_SUCCESS, SBI_SPEC_VERSION),
+            1 => (SBI_SUCCESS, SBI_IMPL_ID),
+            2 => (SBI_SUCCESS, impl_version()),
+            3 => {
+                let supported = matches!(
+                    arg,
+                    0x00..=0x08 | EID_BASE | EID_TIME | EID_IPI | EID_RFENCE | EID_SRST | EID_DBCN
+                );
+                (SBI_SUCCESS, supported as u64)
+            }
+            4 => (SBI_SUCCESS, self.csrs[CSR_MVENDORID as usize]),
+            5 => (SBI_SUCCESS, self.csrs[CSR_MARCHID as usize]),
+            6 => (SBI_SUCCESS, self.csrs[CSR_MIMPID as usize]),
+            _ => (SBI_ERR_NOT_SUPPORTED, 0),
+        }
+    }
+
+    fn sbi_set_timer(&mut self, bus: &dyn Bus, state: &SbiState, deadline: u64) -> SbiRet {
+        let hart = self.csrs[CSR_MHARTID as usize];
+        let addr = state.clint_base + MTIMECMP_OFFSET + hart * 8;
+        if bus.write64(addr, deadline).is_err() {
+            return (SBI_ERR_FAILED, 0);
+        }
+        // The next poll raises STIP again if the deadline has passed
+        self.csrs[CSR_MIP as usize] &= !MIP_STIP;
+        (SBI_SUCCESS, 0)
+    }
+
+    fn sbi_send_ipi(bus: &dyn Bus, state: &SbiState, mask: u64, base: u64) -> SbiRet {
+        let harts = bus
+            .read32(state.clint_base + HART_COUNT_OFFSET)
+            .unwrap_or(1)
+            .max(1) as u64;
+        let targets: Vec<u64> = if base == u64::MAX {
+            (0..harts).collect()
+        } else {
+            (0..64)
+                .filter(|bit| mask & (1 << bit) != 0)
+                .map(|bit| base.wrapping_add(bit))
+                .collect()
+        };
+        if targets.iter().any(|&hart| hart >= harts) {
+            return (SBI_ERR_INVALID_PARAM, 0);
+        }
+        for hart in targets {
+            let _ = bus.write32(state.clint_base + MSIP_OFFSET + hart * 4, 1);
+        }
+        (SBI_SUCCESS, 0)
+    }
+
+    /// Flush this hart now and every other hart at its next poll. Remote
+    /// fences are rare enough that flushing everything is cheaper than
+    /// tracking the requested range.
+    fn sbi_remote_fence(&mut self) -> SbiRet {
+        let epoch = FENCE_EPOCH.fetch_add(1, Ordering::AcqRel) + 1;
+        if let Some(state) = self.sbi.as_mut() {
+            state.fence_epoch = epoch;
+        }
+        self.tlb.flush();
+        self.invalidate_blocks();
+        (SBI_SUCCESS, 0)
+    }
+
+    fn sbi_system_reset(reset_type: u64, reason: u64) -> Trap {
+        let code = match (reset_type, reason) {
+            (0, 0) => HALT_PASS,
+            (0, _) => (1 << 16) | HALT_FAIL,
+            _ => HALT_RESET,
+        };
+        Trap::RequestedTrap(code)
+    }
+
+    fn sbi_getchar(bus: &dyn Bus, state: &SbiState) -> Option<u8> {
+        let lsr = bus.read8(state.uart_base + UART_LSR).ok()?;
+        if lsr & UART_LSR_DR == 0 {
+            return None;
+        }
+        bus.read8(state.uart_base).ok()
+    }
+
+    fn sbi_debug_console(bus: &dyn Bus, state: &SbiState, fid: u64, args: [u64; 3]) -> SbiRet {
+        let [len, addr, addr_hi] = args;
+        // The high half only matters for RV32
+        if fid < 2 && addr_hi != 0 {
+            return (SBI_ERR_INVALID_PARAM, 0);
+        }
+        match fid {
+            0 => {
+                for i in 0..len {
+                    let Ok(byte) = bus.read8(addr.wrapping_add(i)) else {
+                        return (SBI_ERR_INVALID_PARAM, 0);
+                    };
+                    let _ = bus.write8(state.uart_base, byte);
+                }
+                (SBI_SUCCESS, len)
+            }
+            1 => {
+                let mut read = 0;
+                while read < len {
+                    let Some(byte) = Self::sbi_getchar(bus, state) else {
+                        break;
+                    };
+                    if bus.write8(addr.wrapping_add(read), byte).is_err() {
+                        return (SBI_ERR_INVALID_PARAM, 0);
+                    }
+                    read += 1;
+                }
+                (SBI_SUCCESS, read)
+            }
+            2 => {
+                let _ = bus.write8(state.uart_base, len as u8);
+                (SBI_SUCCESS, 0)
+            }
+            _ => (SBI_ERR_NOT_SUPPORTED, 0),
+        }
+    }
+}
+
+/// Crate version as `major << 16 | minor << 8 | patch`.
+fn impl_version() -> u64 {
+    let part = |s: &str| s.parse::<u64>().unwrap_or(0) & 0xff;
+    part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+    use crate::devices::clint::CLINT_BASE;
+    use crate::devices::uart::UART_BASE;
+
+    const ECALL: u32 = 0x0000_0073;
+    const NOP: u32 = 0x0000_0013;
+
+    fn boot(hart: u64) -> Cpu {
+        let mut cpu = Cpu::new(0x1000, hart);
+        cpu.enable_sbi(SbiConfig {
+            entry: DRAM_BASE,
+            fdt: 0x2000,
+            clint_base: CLINT_BASE,
+            uart_base: UART_BASE,
+        });
+        cpu
+    }
+
+    /// Run one `ecall` with `a7 = eid`, `a6 = fid` and `a0.. = args`.
+    fn call(cpu: &mut Cpu, bus: &SystemBus, eid: u64, fid: u64, args: &[u64]) -> Result<(), Trap> {
+        bus.write32(DRAM_BASE, ECALL).unwrap();
+        cpu.pc = DRAM_BASE;
+        cpu.regs[17] = eid;
+        cpu.regs[16] = fid;
+        cpu.regs[10..10 + args.len()].copy_from_slice(args);
+        cpu.step(bus)
+    }
+
+    #[test]
+    fn test_enters_kernel_in_supervisor_mode() {
+        let cpu = boot(2);
+        assert_eq!(cpu.mode, Mode::Supervisor);
+        assert_eq!(cpu.pc, DRAM_BASE);
+        assert_eq!(cpu.regs[10], 2);
+        assert_eq!(cpu.regs[11], 0x2000);
+        assert_eq!(cpu.csrs[CSR_MEDELEG as usize] & (1 << 9), 0);
+        assert!(cpu.sbi_enabled());
+    }
+
+    #[test]
+    fn test_base_and_console_calls() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let mut cpu = boot(0);
+
+        call(&mut cpu, &bus, EID_BASE, 0, &[]).unwrap();
+        assert_eq!((cpu.regs[10], cpu.regs[11]), (0, SBI_SPEC_VERSION));
+        assert_eq!(cpu.pc, DRAM_BASE + 4);
+        assert_eq!(cpu.mode, Mode::Supervisor);
+
+        call(&mut cpu, &bus, EID_BASE, 3, &[EID_TIME]).unwrap();
+        assert_eq!(cpu.regs[11], 1);
+        // Hart state management is not implemented
+        call(&mut cpu, &bus, EID_BASE, 3, &[0x0048_534d]).unwrap();
+        assert_eq!(cpu.regs[11], 0);
+
+        call(&mut cpu, &bus, LEGACY_CONSOLE_PUTCHAR, 0, &[b'A' as u64]).unwrap();
+        bus.dram.write_bytes(0x100, b"hi").unwrap();
+        call(&mut cpu, &bus, EID_DBCN, 0, &[2, DRAM_BASE + 0x100, 0]).unwrap();
+        assert_eq!((cpu.regs[10], cpu.regs[11]), (0, 2));
+        assert_eq!(bus.uart.drain_output(), b"Ahi");
+
+        call(&mut cpu, &bus, 0x0123_4567, 0, &[]).unwrap();
+        assert_eq!(cpu.regs[10] as i64, SBI_ERR_NOT_SUPPORTED);
+    }
+
+    #[test]
+    fn test_timer_is_forwarded_as_stip() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let mut cpu = boot(0);
+        call(&mut cpu, &bus, EID_TIME, 0, &[50]).unwrap();
+        assert_eq!(bus.clint.get_mtimecmp(0), 50);
+
+        bus.clint.set_mtime(100);
+        bus.write32(DRAM_BASE + 4, NOP).unwrap();
+        cpu.poll_counter = 255;
+        cpu.step(&bus).unwrap();
+        let mip = cpu.csrs[CSR_MIP as usize];
+        assert_ne!(mip & MIP_STIP, 0);
+        assert_eq!(mip & MIP_MTIP, 0);
+    }
+
+    #[test]
+    fn test_ipi_is_forwarded_as_ssip() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.set_num_harts(2);
+        let mut sender = boot(0);
+        let mut target = boot(1);
+
+        call(&mut sender, &bus, EID_IPI, 0, &[0b10, 0]).unwrap();
+        assert_eq!(sender.regs[10], 0);
+        assert_eq!(bus.clint.get_msip(1), 1);
+        call(&mut sender, &bus, EID_IPI, 0, &[0b1, 5]).unwrap();
+        assert_eq!(sender.regs[10] as i64, SBI_ERR_INVALID_PARAM);
+
+        bus.write32(DRAM_BASE, NOP).unwrap();
+        target.poll_counter = 255;
+        target.step(&bus).unwrap();
+        assert_ne!(target.csrs[CSR_MIP as usize] & MIP_SSIP, 0);
+        assert_eq!(bus.clint.get_msip(1), 0);
+    }
+
+    #[test]
+    fn test_system_reset_halts() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let mut cpu = boot(0);
+        assert_eq!(
+            call(&mut cpu, &bus, EID_SRST, 0, &[0, 0]),
+            Err(Trap::RequestedTrap(HALT_PASS))
+        );
+        assert_eq!(
+            call(&mut cpu, &bus, LEGACY_SHUTDOWN, 0, &[]),
+            Err(Trap::RequestedTrap(HALT_PASS))
+        );
+    }
+}
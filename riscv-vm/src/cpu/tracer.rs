@@ -0,0 +1,394 @@
+//! Instruction-level execution tracing.
+//!
+//! A [`Tracer`] installed with [`Cpu::set_tracer`] records every retired
+//! instruction that passes its [`TraceFilter`] as a [`TraceRecord`] (pc,
+//! encoding, privilege mode, disassembly and the registers it changed) and
+//! hands it to a [`TraceSink`]: a callback, a bounded ring buffer drained by
+//! the host, or (natively) a text file.
+//!
+//! While a tracer is enabled the hart runs in the interpreter, one
+//! instruction per `step()`, so blocks and native JIT code are bypassed and
+//! every instruction is observed. Disabling it with [`Tracer::set_enabled`]
+//! returns to full speed without losing the filter or buffered records.
+
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use super::core::Cpu;
+use super::csr::CSR_MHARTID;
+use super::types::{Mode, Trap};
+use crate::bus::Bus;
+use crate::engine::disasm::{disassemble, freg_name, xreg_name};
+
+/// A register changed by a traced instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegWrite {
+    /// Integer register index and new value.
+    X(u8, u64),
+    /// Floating-point register index and new (NaN-boxed) bits.
+    F(u8, u64),
+}
+
+impl fmt::Display for RegWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RegWrite::X(reg, value) => write!(f, "{}={:#x}", xreg_name(reg as usize), value),
+            RegWrite::F(reg, value) => write!(f, "{}={:#x}", freg_name(reg as usize), value),
+        }
+    }
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub hart: u64,
+    pub pc: u64,
+    /// Encoding; compressed instructions are given in expanded form.
+    pub insn: u32,
+    /// Length of the instruction in memory (2 or 4 bytes).
+    pub len: u8,
+    /// Privilege mode the instruction executed in.
+    pub mode: Mode,
+    pub disasm: String,
+    /// Registers whose value changed.
+    pub writes: Vec<RegWrite>,
+}
+
+impl fmt::Display for TraceRecord {
+    /// `0 M 0000000080000000: 00000297  auipc t0, 0x0  t0=0x80000000`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:016x}: {:08x}{} {:<32}",
+            self.hart,
+            self.mode,
+            self.pc,
+            self.insn,
+            if self.len == 2 { "c" } else { " " },
+            self.disasm
+        )?;
+        for write in &self.writes {
+            write!(f, " {}", write)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which instructions are recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only instructions whose pc is in this range.
+    pub pc_range: Option<Range<u64>>,
+    /// Only instructions executed in this privilege mode.
+    pub mode: Option<Mode>,
+}
+
+impl TraceFilter {
+    /// Whether an instruction at `pc` executed in `mode` is recorded.
+    pub fn accepts(&self, pc: u64, mode: Mode) -> bool {
+        self.pc_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&pc))
+            && self.mode.is_none_or(|m| m == mode)
+    }
+}
+
+/// Where trace records go.
+pub enum TraceSink {
+    /// Called for each record.
+    Callback(Box<dyn FnMut(&TraceRecord) + Send>),
+    /// The most recent records, oldest first; older ones are dropped.
+    Ring {
+        records: VecDeque<TraceRecord>,
+        capacity: usize,
+    },
+    /// One [`TraceRecord`] line per instruction.
+    #[cfg(not(target_arch = "wasm32"))]
+    File(BufWriter<File>),
+}
+
+impl TraceSink {
+    /// A ring buffer keeping the last `capacity` records.
+    pub fn ring(capacity: usize) -> Self {
+        TraceSink::Ring {
+            records: VecDeque::with_capacity(capacity.min(4096)),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// A text file at `path`, created or truncated.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn file(path: &Path) -> io::Result<Self> {
+        Ok(TraceSink::File(BufWriter::new(File::create(path)?)))
+    }
+}
+
+/// Execution tracer attached to a hart.
+pub struct Tracer {
+    enabled: bool,
+    filter: TraceFilter,
+    sink: TraceSink,
+    recorded: u64,
+    /// Instruction fetched by the current step: (pc, insn, len).
+    pub(super) pending: Option<(u64, u32, u8)>,
+}
+
+impl Tracer {
+    /// An enabled tracer recording everything into `sink`.
+    pub fn new(sink: TraceSink) -> Self {
+        Self {
+            enabled: true,
+            filter: TraceFilter::default(),
+            sink,
+            recorded: 0,
+            pending: None,
+        }
+    }
+
+    /// Replace the filter.
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Pause or resume recording; blocks and the JIT run while paused.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    /// Number of records produced so far (including any a ring dropped).
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Take the records buffered in a ring sink (empty for other sinks).
+    pub fn drain(&mut self) -> Vec<TraceRecord> {
+        match &mut self.sink {
+            TraceSink::Ring { records, .. } => records.drain(..).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The records buffered in a ring sink, oldest first, left in place
+    /// (empty for other sinks).
+    pub fn buffered(&self) -> impl Iterator<Item = &TraceRecord> {
+        match &self.sink {
+            TraceSink::Ring { records, .. } => Some(records.iter()),
+            _ => None,
+        }
+        .into_iter()
+        .flatten()
+    }
+
+    /// Flush a file sink. A file sink that fails to write disables the
+    /// tracer.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            TraceSink::File(file) => file.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn emit(&mut self, record: TraceRecord) {
+        self.recorded += 1;
+        match &mut self.sink {
+            TraceSink::Callback(callback) => callback(&record),
+            TraceSink::Ring { records, capacity } => {
+                if records.len() == *capacity {
+                    records.pop_front();
+                }
+                records.push_back(record);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            TraceSink::File(file) => {
+                if writeln!(file, "{}", record).is_err() {
+                    self.enabled = false;
+                }
+            }
+        }
+    }
+}
+
+impl Cpu {
+    /// Install or remove the execution tracer.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer.map(Box::new);
+    }
+
+    /// Remove the execution tracer and hand it back.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take().map(|tracer| *tracer)
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_deref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_deref_mut()
+    }
+
+    /// Whether `step()` must go through [`Cpu::step_traced`].
+    #[inline]
+    pub(super) fn tracing(&self) -> bool {
+        self.tracer.as_ref().is_some_and(|t| t.enabled)
+    }
+
+    /// Interpret one instruction and record it.
+    pub(super) fn step_traced(&mut self, bus: &dyn Bus) -> Result<(), Trap> {
+        let regs = self.regs;
+        let fregs = self.fregs;
+        let mode = self.mode;
+
+        let result = self.step_single_inner(bus);
+
+        let tracer = self
+            .tracer
+            .as_deref_mut()
+            .expect("tracing without a tracer");
+        // Nothing was executed if the fetch or decode faulted
+        let Some((pc, insn, len)) = tracer.pending.take() else {
+            return result;
+        };
+        if !tracer.filter.accepts(pc, mode) {
+            return result;
+        }
+
+        let mut writes: Vec<RegWrite> = changed(&regs, &self.regs)
+            .map(|(i, v)| RegWrite::X(i, v))
+            .collect();
+        writes.extend(changed(&fregs, &self.fregs).map(|(i, v)| RegWrite::F(i, v)));
+
+        let tracer = self
+            .tracer
+            .as_deref_mut()
+            .expect("tracing without a tracer");
+        tracer.emit(TraceRecord {
+            hart: self.csrs[CSR_MHARTID as usize],
+            pc,
+            insn,
+            len,
+            mode,
+            disasm: disassemble(pc, insn),
+            writes,
+        });
+        result
+    }
+}
+
+/// Registers that differ between two register files, with their new values.
+fn changed<'a>(
+    before: &'a [u64; 32],
+    after: &'a [u64; 32],
+) -> impl Iterator<Item = (u8, u64)> + 'a {
+    (0..32u8)
+        .filter(|&i| before[i as usize] != after[i as usize])
+        .map(|i| (i, after[i as usize]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::cpu::test_hart;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_trace_records_register_writes() {
+        let (mut cpu, bus) = test_hart(&[
+            0x0050_0513, // li a0, 5
+            0x0000_0013, // nop
+            0x00a5_05b3, // add a1, a0, a0
+        ]);
+        cpu.use_blocks = true;
+        cpu.set_tracer(Some(Tracer::new(TraceSink::ring(16))));
+        for _ in 0..3 {
+            cpu.step(&bus).unwrap();
+        }
+
+        let records = cpu.tracer_mut().unwrap().drain();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].pc, DRAM_BASE);
+        assert_eq!(records[0].disasm, "li a0, 5");
+        assert_eq!(records[0].writes, vec![RegWrite::X(10, 5)]);
+        assert!(records[1].writes.is_empty());
+        assert_eq!(records[2].writes, vec![RegWrite::X(11, 10)]);
+        assert_eq!(records[2].mode, Mode::Machine);
+        assert_eq!(
+            records[2].to_string().trim_end(),
+            format!(
+                "0 M {:016x}: 00a505b3  {:<32} a1=0xa",
+                DRAM_BASE + 8,
+                "add a1, a0, a0"
+            )
+        );
+    }
+
+    #[test]
+    fn test_trace_filter_and_callback() {
+        let (mut cpu, bus) = test_hart(&[0x0000_0013; 4]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let seen = seen.clone();
+            TraceSink::Callback(Box::new(move |r: &TraceRecord| {
+                seen.lock().unwrap().push(r.pc)
+            }))
+        };
+        let filter = TraceFilter {
+            pc_range: Some(DRAM_BASE + 4..DRAM_BASE + 12),
+            mode: Some(Mode::Machine),
+        };
+        cpu.set_tracer(Some(Tracer::new(sink).with_filter(filter)));
+        for _ in 0..4 {
+            cpu.step(&bus).unwrap();
+        }
+        assert_eq!(*seen.lock().unwrap(), vec![DRAM_BASE + 4, DRAM_BASE + 8]);
+        assert_eq!(cpu.tracer().unwrap().recorded(), 2);
+
+        // Nothing matches user mode; a paused tracer records nothing
+        let tracer = cpu.tracer_mut().unwrap();
+        tracer.set_filter(TraceFilter {
+            pc_range: None,
+            mode: Some(Mode::User),
+        });
+        cpu.pc = DRAM_BASE;
+        cpu.step(&bus).unwrap();
+        cpu.tracer_mut().unwrap().set_enabled(false);
+        cpu.tracer_mut().unwrap().set_filter(TraceFilter::default());
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.tracer().unwrap().recorded(), 2);
+    }
+
+    #[test]
+    fn test_ring_keeps_latest_records() {
+        let (mut cpu, bus) = test_hart(&[0x0000_0013; 5]);
+        cpu.set_tracer(Some(Tracer::new(TraceSink::ring(2))));
+        for _ in 0..5 {
+            cpu.step(&bus).unwrap();
+        }
+        let tracer = cpu.tracer_mut().unwrap();
+        assert_eq!(tracer.recorded(), 5);
+        let pcs: Vec<u64> = tracer.drain().iter().map(|r| r.pc).collect();
+        assert_eq!(pcs, vec![DRAM_BASE + 12, DRAM_BASE + 16]);
+    }
+}
@@ -37,6 +37,34 @@ impl Mode {
     }
 }
 
+impl std::fmt::Display for Mode {
+    /// The one-letter name: `M`, `S` or `U`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Mode::Machine => "M",
+            Mode::Supervisor => "S",
+            Mode::User => "U",
+        })
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    /// Parse `m`/`s`/`u` or the full mode name, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "m" | "machine" => Ok(Mode::Machine),
+            "s" | "supervisor" => Ok(Mode::Supervisor),
+            "u" | "user" => Ok(Mode::User),
+            _ => Err(format!(
+                "unknown privilege mode '{}' (expected m, s or u)",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Trap {
     InstructionAddressMisaligned(u64),
@@ -0,0 +1,1127 @@
+//! Vector extension (RVV 1.0), minimal subset.
+//!
+//! Enough of V for the vectorized `memcpy`, `memset` and `strlen` of newer
+//! libc builds: `vsetvli`/`vsetivli`/`vsetvl`; unit-stride, strided,
+//! fault-only-first, mask and whole-register loads and stores; integer
+//! arithmetic, compares and merges; and `vmv.x.s`, `vmv.s.x`, `vcpop.m`
+//! and `vfirst.m`. Every other encoding (indexed and segment accesses,
+//! widening, fixed-point, floating-point and permutation ops) raises an
+//! illegal instruction exception, so a guest can fall back to scalar code.
+//!
+//! VLEN is [`DEFAULT_VLEN`] bits unless set with [`Cpu::set_vlen`], and
+//! ELEN is 64. Tail and inactive elements are left undisturbed, which
+//! satisfies both the agnostic and the undisturbed policy. As with the FPU,
+//! the unit is off until the guest sets `mstatus.VS`. The device tree does
+//! not list `v`, since only a subset is implemented.
+
+use super::core::Cpu;
+use super::csr::CSR_MSTATUS;
+use super::fpu::MSTATUS_SD;
+use super::types::Trap;
+use crate::bus::Bus;
+use crate::engine::disasm::xreg_name;
+use crate::mmu::AccessType as MmuAccessType;
+
+pub const CSR_VSTART: u16 = 0x008;
+pub const CSR_VXSAT: u16 = 0x009;
+pub const CSR_VXRM: u16 = 0x00A;
+pub const CSR_VCSR: u16 = 0x00F;
+pub const CSR_VL: u16 = 0xC20;
+pub const CSR_VTYPE: u16 = 0xC21;
+pub const CSR_VLENB: u16 = 0xC22;
+
+/// `mstatus.VS`: vector unit state (Off, Initial, Clean, Dirty).
+pub const MSTATUS_VS: u64 = 3 << 9;
+/// `vtype.vill`: the last `vset{i}vl{i}` asked for an unsupported type.
+pub const VTYPE_VILL: u64 = 1 << 63;
+
+/// VLEN of a new hart, in bits.
+pub const DEFAULT_VLEN: usize = 128;
+/// Largest VLEN accepted by [`Cpu::set_vlen`], in bits.
+pub const MAX_VLEN: usize = 4096;
+/// Widest element, in bits.
+const ELEN: usize = 64;
+
+const OPCODE_LOAD_FP: u32 = 0x07;
+const OPCODE_STORE_FP: u32 = 0x27;
+const OPCODE_OP_V: u32 = 0x57;
+
+/// Whether reads and writes of `addr` go through the vector unit.
+#[inline]
+pub fn is_vector_csr(addr: u16) -> bool {
+    matches!(
+        addr,
+        CSR_VSTART | CSR_VXSAT | CSR_VXRM | CSR_VCSR | CSR_VL | CSR_VTYPE | CSR_VLENB
+    )
+}
+
+/// Vector register file of a hart.
+#[derive(Clone, Debug)]
+pub(super) struct VectorState {
+    /// VLEN in bytes.
+    vlenb: usize,
+    /// `v0`-`v31`, `vlenb` bytes each, with little-endian elements.
+    regs: Vec<u8>,
+}
+
+impl VectorState {
+    fn new(vlen: usize) -> Self {
+        Self {
+            vlenb: vlen / 8,
+            regs: vec![0; 32 * vlen / 8],
+        }
+    }
+
+    /// Element `index` of the group starting at `reg`, `sew` bytes wide.
+    fn elem(&self, reg: usize, index: usize, sew: usize) -> u64 {
+        let start = reg * self.vlenb + index * sew;
+        let mut bytes = [0; 8];
+        bytes[..sew].copy_from_slice(&self.regs[start..start + sew]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn set_elem(&mut self, reg: usize, index: usize, sew: usize, value: u64) {
+        let start = reg * self.vlenb + index * sew;
+        self.regs[start..start + sew].copy_from_slice(&value.to_le_bytes()[..sew]);
+    }
+
+    fn mask_bit(&self, reg: usize, index: usize) -> bool {
+        (self.regs[reg * self.vlenb + index / 8] >> (index % 8)) & 1 != 0
+    }
+
+    fn set_mask_bit(&mut self, reg: usize, index: usize, bit: bool) {
+        let byte = &mut self.regs[reg * self.vlenb + index / 8];
+        *byte = (*byte & !(1 << (index % 8))) | ((bit as u8) << (index % 8));
+    }
+}
+
+impl Default for VectorState {
+    fn default() -> Self {
+        Self::new(DEFAULT_VLEN)
+    }
+}
+
+/// A supported `vtype`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Vtype {
+    /// SEW in bytes.
+    sew: usize,
+    /// LMUL in eighths of a register.
+    lmul8: usize,
+}
+
+impl Vtype {
+    fn parse(vtype: u64) -> Option<Self> {
+        // Bits above vma are reserved
+        if vtype >> 8 != 0 || (vtype >> 3) & 7 > 3 {
+            return None;
+        }
+        let sew = 1 << ((vtype >> 3) & 7);
+        let lmul8 = match vtype & 7 {
+            lmul @ 0..=3 => 8 << lmul,
+            5 => 1,
+            6 => 2,
+            7 => 4,
+            _ => return None,
+        };
+        // Fractional LMUL needs SEW <= LMUL * ELEN
+        if sew * 64 > lmul8 * ELEN {
+            return None;
+        }
+        Some(Self { sew, lmul8 })
+    }
+
+    fn vlmax(self, vlenb: usize) -> usize {
+        vlenb * self.lmul8 / 8 / self.sew
+    }
+
+    /// Registers per group of `eew`-byte elements, or `None` if their EMUL
+    /// is out of range.
+    fn group(self, eew: usize) -> Option<usize> {
+        let emul8 = eew * self.lmul8 / self.sew;
+        (1..=64).contains(&emul8).then(|| emul8.div_ceil(8))
+    }
+}
+
+/// Source of the AVL of `vset{i}vl{i}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Avl {
+    Reg(usize),
+    Imm(u64),
+}
+
+/// Source of the new `vtype` of `vset{i}vl{i}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VtypeSrc {
+    Reg(usize),
+    Imm(u64),
+}
+
+/// Addressing of a vector load or store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MemMode {
+    Unit,
+    /// Byte stride in `x[rs2]`.
+    Strided(usize),
+    /// Unit-stride load that only traps on element 0.
+    FaultFirst,
+    /// `vlm.v`/`vsm.v`: `ceil(vl / 8)` bytes.
+    Mask,
+    /// `vl<n>re<eew>.v`/`vs<n>r.v`: whole registers, ignoring `vl` and `vtype`.
+    Whole(usize),
+}
+
+/// Second operand of an arithmetic op.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Src {
+    /// `.vv`: `vs1`.
+    V(usize),
+    /// `.vx`: `x[rs1]`.
+    X(usize),
+    /// `.vi`: sign-extended 5-bit immediate.
+    I(i64),
+}
+
+/// Integer ops of the OPIVV/OPIVX/OPIVI and OPMVV/OPMVX formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IntOp {
+    Add,
+    Sub,
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    /// `vmerge`, or `vmv.v.*` when unmasked.
+    Merge,
+    Sll,
+    Srl,
+    Sra,
+    Mul,
+    Mulh,
+    Mulhu,
+    Mulhsu,
+    Divu,
+    Div,
+    Remu,
+    Rem,
+    Macc,
+    Mseq,
+    Msne,
+    Msltu,
+    Mslt,
+    Msleu,
+    Msle,
+    Msgtu,
+    Msgt,
+}
+
+/// A decoded vector instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VInsn {
+    SetVl {
+        rd: usize,
+        avl: Avl,
+        vtype: VtypeSrc,
+    },
+    Mem {
+        store: bool,
+        vd: usize,
+        rs1: usize,
+        /// Element width in bytes.
+        eew: usize,
+        mode: MemMode,
+        vm: bool,
+    },
+    Arith {
+        op: IntOp,
+        vd: usize,
+        vs2: usize,
+        src: Src,
+        vm: bool,
+    },
+    /// `vmv.x.s`
+    MvXS { rd: usize, vs2: usize },
+    /// `vmv.s.x`
+    MvSX { vd: usize, rs1: usize },
+    /// `vcpop.m`
+    Cpop { rd: usize, vs2: usize, vm: bool },
+    /// `vfirst.m`
+    First { rd: usize, vs2: usize, vm: bool },
+}
+
+/// `value` as a signed `bits`-wide integer.
+fn sext(value: u64, bits: usize) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+impl IntOp {
+    fn is_compare(self) -> bool {
+        matches!(
+            self,
+            IntOp::Mseq
+                | IntOp::Msne
+                | IntOp::Msltu
+                | IntOp::Mslt
+                | IntOp::Msleu
+                | IntOp::Msle
+                | IntOp::Msgtu
+                | IntOp::Msgt
+        )
+    }
+
+    /// Mask bit for `a` (`vs2`) compared with `b`.
+    fn compare(self, a: u64, b: u64, bits: usize) -> bool {
+        let (sa, sb) = (sext(a, bits), sext(b, bits));
+        match self {
+            IntOp::Mseq => a == b,
+            IntOp::Msne => a != b,
+            IntOp::Msltu => a < b,
+            IntOp::Mslt => sa < sb,
+            IntOp::Msleu => a <= b,
+            IntOp::Msle => sa <= sb,
+            IntOp::Msgtu => a > b,
+            IntOp::Msgt => sa > sb,
+            _ => unreachable!("{:?} is not a compare", self),
+        }
+    }
+
+    /// Result for `a` = `vs2`, `b` = the second operand and `d` = `vd`,
+    /// all `bits` wide. The caller truncates the result to SEW.
+    fn eval(self, a: u64, b: u64, d: u64, bits: usize) -> u64 {
+        let (sa, sb) = (sext(a, bits), sext(b, bits));
+        let shamt = b & (bits as u64 - 1);
+        match self {
+            IntOp::Add => a.wrapping_add(b),
+            IntOp::Sub => a.wrapping_sub(b),
+            IntOp::Rsub => b.wrapping_sub(a),
+            IntOp::Minu => a.min(b),
+            IntOp::Min => sa.min(sb) as u64,
+            IntOp::Maxu => a.max(b),
+            IntOp::Max => sa.max(sb) as u64,
+            IntOp::And => a & b,
+            IntOp::Or => a | b,
+            IntOp::Xor => a ^ b,
+            IntOp::Merge => b,
+            IntOp::Sll => a << shamt,
+            IntOp::Srl => a >> shamt,
+            IntOp::Sra => (sa >> shamt) as u64,
+            IntOp::Mul => a.wrapping_mul(b),
+            IntOp::Mulh => ((sa as i128 * sb as i128) >> bits) as u64,
+            IntOp::Mulhu => ((a as u128 * b as u128) >> bits) as u64,
+            IntOp::Mulhsu => ((sa as i128 * b as i128) >> bits) as u64,
+            IntOp::Divu => a.checked_div(b).unwrap_or(u64::MAX),
+            IntOp::Div if b == 0 => u64::MAX,
+            IntOp::Div => sa.wrapping_div(sb) as u64,
+            IntOp::Remu if b == 0 => a,
+            IntOp::Remu => a % b,
+            IntOp::Rem if b == 0 => a,
+            IntOp::Rem => sa.wrapping_rem(sb) as u64,
+            IntOp::Macc => a.wrapping_mul(b).wrapping_add(d),
+            _ => unreachable!("{:?} is a compare", self),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            IntOp::Add => "vadd",
+            IntOp::Sub => "vsub",
+            IntOp::Rsub => "vrsub",
+            IntOp::Minu => "vminu",
+            IntOp::Min => "vmin",
+            IntOp::Maxu => "vmaxu",
+            IntOp::Max => "vmax",
+            IntOp::And => "vand",
+            IntOp::Or => "vor",
+            IntOp::Xor => "vxor",
+            IntOp::Merge => "vmerge",
+            IntOp::Sll => "vsll",
+            IntOp::Srl => "vsrl",
+            IntOp::Sra => "vsra",
+            IntOp::Mul => "vmul",
+            IntOp::Mulh => "vmulh",
+            IntOp::Mulhu => "vmulhu",
+            IntOp::Mulhsu => "vmulhsu",
+            IntOp::Divu => "vdivu",
+            IntOp::Div => "vdiv",
+            IntOp::Remu => "vremu",
+            IntOp::Rem => "vrem",
+            IntOp::Macc => "vmacc",
+            IntOp::Mseq => "vmseq",
+            IntOp::Msne => "vmsne",
+            IntOp::Msltu => "vmsltu",
+            IntOp::Mslt => "vmslt",
+            IntOp::Msleu => "vmsleu",
+            IntOp::Msle => "vmsle",
+            IntOp::Msgtu => "vmsgtu",
+            IntOp::Msgt => "vmsgt",
+        }
+    }
+}
+
+/// Decode `insn` if it is in the implemented subset.
+fn decode(insn: u32) -> Option<VInsn> {
+    let field = |shift: u32| ((insn >> shift) & 0x1F) as usize;
+    let vd = field(7);
+    let funct3 = (insn >> 12) & 0x7;
+    let rs1 = field(15);
+    let vs2 = field(20);
+    let vm = insn & (1 << 25) != 0;
+
+    match insn & 0x7F {
+        OPCODE_LOAD_FP | OPCODE_STORE_FP => {
+            let store = insn & 0x7F == OPCODE_STORE_FP;
+            let eew = match funct3 {
+                0 => 1,
+                5 => 2,
+                6 => 4,
+                7 => 8,
+                _ => return None,
+            };
+            let nf = (insn >> 29) as usize;
+            let mew = insn & (1 << 28) != 0;
+            let mop = (insn >> 26) & 0x3;
+            if mew {
+                return None;
+            }
+            let mode = match (mop, vs2) {
+                // Whole-register stores only exist with EEW 8
+                (0, 0b01000) if vm && (nf + 1).is_power_of_two() && (!store || eew == 1) => {
+                    MemMode::Whole(nf + 1)
+                }
+                // Segment accesses
+                _ if nf != 0 => return None,
+                (0, 0b00000) => MemMode::Unit,
+                (0, 0b01011) if vm && eew == 1 => MemMode::Mask,
+                (0, 0b10000) if !store => MemMode::FaultFirst,
+                (2, rs2) => MemMode::Strided(rs2),
+                _ => return None,
+            };
+            Some(VInsn::Mem {
+                store,
+                vd,
+                rs1,
+                eew,
+                mode,
+                vm,
+            })
+        }
+        OPCODE_OP_V if funct3 == 7 => decode_setvl(insn, vd, rs1, vs2),
+        OPCODE_OP_V => decode_arith(insn, vd, rs1, vs2, vm),
+        _ => None,
+    }
+}
+
+fn decode_setvl(insn: u32, rd: usize, rs1: usize, rs2: usize) -> Option<VInsn> {
+    let (avl, vtype) = if insn >> 31 == 0 {
+        // vsetvli
+        (Avl::Reg(rs1), VtypeSrc::Imm(((insn >> 20) & 0x7FF) as u64))
+    } else if insn >> 30 == 0b11 {
+        // vsetivli
+        (
+            Avl::Imm(rs1 as u64),
+            VtypeSrc::Imm(((insn >> 20) & 0x3FF) as u64),
+        )
+    } else if (insn >> 25) & 0x3F == 0 {
+        // vsetvl
+        (Avl::Reg(rs1), VtypeSrc::Reg(rs2))
+    } else {
+        return None;
+    };
+    Some(VInsn::SetVl { rd, avl, vtype })
+}
+
+fn decode_arith(insn: u32, vd: usize, rs1: usize, vs2: usize, vm: bool) -> Option<VInsn> {
+    let funct3 = (insn >> 12) & 0x7;
+    let funct6 = insn >> 26;
+    let src = match funct3 {
+        0 | 2 => Src::V(rs1),
+        3 => Src::I(((rs1 as i64) << 59) >> 59),
+        4 | 6 => Src::X(rs1),
+        // OPFVV/OPFVF
+        _ => return None,
+    };
+
+    let op = if funct3 == 2 || funct3 == 6 {
+        // OPMVV/OPMVX
+        match funct6 {
+            0b010000 => {
+                return match (funct3, rs1) {
+                    (2, 0b00000) if vm => Some(VInsn::MvXS { rd: vd, vs2 }),
+                    (2, 0b10000) => Some(VInsn::Cpop { rd: vd, vs2, vm }),
+                    (2, 0b10001) => Some(VInsn::First { rd: vd, vs2, vm }),
+                    (6, _) if vm && vs2 == 0 => Some(VInsn::MvSX { vd, rs1 }),
+                    _ => None,
+                };
+            }
+            0b100000 => IntOp::Divu,
+            0b100001 => IntOp::Div,
+            0b100010 => IntOp::Remu,
+            0b100011 => IntOp::Rem,
+            0b100100 => IntOp::Mulhu,
+            0b100101 => IntOp::Mul,
+            0b100110 => IntOp::Mulhsu,
+            0b100111 => IntOp::Mulh,
+            0b101101 => IntOp::Macc,
+            _ => return None,
+        }
+    } else {
+        // OPIVV/OPIVI/OPIVX; `vv`, `vi` and `vx` say which forms exist
+        let (vv, vi) = (funct3 == 0, funct3 == 3);
+        match funct6 {
+            0b000000 => IntOp::Add,
+            0b000010 if !vi => IntOp::Sub,
+            0b000011 if !vv => IntOp::Rsub,
+            0b000100 if !vi => IntOp::Minu,
+            0b000101 if !vi => IntOp::Min,
+            0b000110 if !vi => IntOp::Maxu,
+            0b000111 if !vi => IntOp::Max,
+            0b001001 => IntOp::And,
+            0b001010 => IntOp::Or,
+            0b001011 => IntOp::Xor,
+            // vmv.v.* has no vs2
+            0b010111 if !vm || vs2 == 0 => IntOp::Merge,
+            0b011000 => IntOp::Mseq,
+            0b011001 => IntOp::Msne,
+            0b011010 if !vi => IntOp::Msltu,
+            0b011011 if !vi => IntOp::Mslt,
+            0b011100 => IntOp::Msleu,
+            0b011101 => IntOp::Msle,
+            0b011110 if !vv => IntOp::Msgtu,
+            0b011111 if !vv => IntOp::Msgt,
+            0b100101 => IntOp::Sll,
+            0b101000 => IntOp::Srl,
+            0b101001 => IntOp::Sra,
+            _ => return None,
+        }
+    };
+    Some(VInsn::Arith {
+        op,
+        vd,
+        vs2,
+        src,
+        vm,
+    })
+}
+
+impl VInsn {
+    /// Check register group alignment and `v0` overlap under `vtype`.
+    fn is_legal(self, vtype: Vtype) -> bool {
+        let aligned = |reg: usize, group: usize| reg.is_multiple_of(group);
+        match self {
+            VInsn::Mem {
+                vd,
+                eew,
+                mode: MemMode::Unit | MemMode::Strided(_) | MemMode::FaultFirst,
+                vm,
+                ..
+            } => vtype
+                .group(eew)
+                .is_some_and(|group| aligned(vd, group) && (vm || vd != 0)),
+            VInsn::Arith {
+                op,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => {
+                let group = vtype.lmul8.div_ceil(8);
+                let vs1_ok = match src {
+                    Src::V(vs1) => aligned(vs1, group),
+                    _ => true,
+                };
+                // Compares write a single mask register
+                let vd_ok = op.is_compare() || (aligned(vd, group) && (vm || vd != 0));
+                aligned(vs2, group) && vs1_ok && vd_ok
+            }
+            _ => true,
+        }
+    }
+}
+
+fn check_vlen(vlen: usize) -> Result<(), String> {
+    if !vlen.is_power_of_two() || !(ELEN..=MAX_VLEN).contains(&vlen) {
+        return Err(format!(
+            "VLEN must be a power of two from {} to {} bits, got {}",
+            ELEN, MAX_VLEN, vlen
+        ));
+    }
+    Ok(())
+}
+
+impl Cpu {
+    /// Set VLEN, a power of two from 64 to [`MAX_VLEN`] bits. Clears the
+    /// vector registers and `vtype`.
+    pub fn set_vlen(&mut self, vlen: usize) -> Result<(), String> {
+        check_vlen(vlen)?;
+        self.vector = VectorState::new(vlen);
+        self.csrs[CSR_VTYPE as usize] = VTYPE_VILL;
+        self.csrs[CSR_VL as usize] = 0;
+        self.csrs[CSR_VSTART as usize] = 0;
+        Ok(())
+    }
+
+    /// VLEN in bits.
+    pub fn vlen(&self) -> usize {
+        self.vector.vlenb * 8
+    }
+
+    /// `v0`-`v31`, VLEN/8 bytes each, with little-endian elements.
+    pub fn vector_regs(&self) -> &[u8] {
+        &self.vector.regs
+    }
+
+    /// Set VLEN and the vector registers, laid out as by
+    /// [`vector_regs`](Self::vector_regs), e.g. from a snapshot. Unlike
+    /// [`set_vlen`](Self::set_vlen) this leaves `vtype`, `vl` and `vstart`
+    /// alone.
+    pub fn restore_vector_regs(&mut self, vlen: usize, regs: &[u8]) -> Result<(), String> {
+        check_vlen(vlen)?;
+        if regs.len() != 32 * vlen / 8 {
+            return Err(format!(
+                "expected {} bytes of vector registers for VLEN {}, got {}",
+                32 * vlen / 8,
+                vlen,
+                regs.len()
+            ));
+        }
+        self.vector = VectorState {
+            vlenb: vlen / 8,
+            regs: regs.to_vec(),
+        };
+        Ok(())
+    }
+
+    #[inline]
+    fn vector_enabled(&self) -> bool {
+        self.csrs[CSR_MSTATUS as usize] & MSTATUS_VS != 0
+    }
+
+    /// Mark the vector state Dirty.
+    #[inline]
+    fn vector_mark_dirty(&mut self) {
+        self.csrs[CSR_MSTATUS as usize] |= MSTATUS_VS | MSTATUS_SD;
+    }
+
+    /// Read vector CSR `addr` (see [`is_vector_csr`]).
+    pub(super) fn read_vector_csr(&self, addr: u16) -> Result<u64, Trap> {
+        if !self.vector_enabled() {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        match addr {
+            CSR_VLENB => Ok(self.vector.vlenb as u64),
+            CSR_VCSR => Ok((self.csrs[CSR_VXRM as usize] << 1) | self.csrs[CSR_VXSAT as usize]),
+            _ => self.csrs.read(addr, self.mode),
+        }
+    }
+
+    /// Write vector CSR `addr` (see [`is_vector_csr`]).
+    pub(super) fn write_vector_csr(&mut self, addr: u16, val: u64) -> Result<(), Trap> {
+        if !self.vector_enabled() {
+            return Err(Trap::IllegalInstruction(addr as u64));
+        }
+        match addr {
+            // Large enough for any element index
+            CSR_VSTART => self.csrs[addr as usize] = val & (self.vlen() as u64 - 1),
+            CSR_VXSAT => self.csrs[addr as usize] = val & 1,
+            CSR_VXRM => self.csrs[addr as usize] = val & 3,
+            CSR_VCSR => {
+                self.csrs[CSR_VXSAT as usize] = val & 1;
+                self.csrs[CSR_VXRM as usize] = (val >> 1) & 3;
+            }
+            // vl, vtype and vlenb are read-only
+            _ => return self.csrs.write(addr, val, self.mode),
+        }
+        self.vector_mark_dirty();
+        Ok(())
+    }
+
+    /// Execute the vector instruction `insn` (OP-V, or a vector width of
+    /// LOAD-FP/STORE-FP).
+    pub(super) fn execute_vector(&mut self, bus: &dyn Bus, insn: u32) -> Result<(), Trap> {
+        let illegal = || Trap::IllegalInstruction(insn as u64);
+        if !self.vector_enabled() {
+            return Err(illegal());
+        }
+        let decoded = decode(insn).ok_or_else(illegal)?;
+
+        let result = match decoded {
+            VInsn::SetVl { rd, avl, vtype } => {
+                self.set_vl(rd, avl, vtype);
+                Ok(())
+            }
+            VInsn::Mem {
+                store,
+                vd,
+                rs1,
+                eew,
+                mode: MemMode::Whole(regs),
+                ..
+            } => {
+                if !vd.is_multiple_of(regs) {
+                    return Err(illegal());
+                }
+                let count = regs * self.vector.vlenb / eew;
+                self.vector_access(bus, store, vd, rs1, eew, MemMode::Whole(regs), true, count)
+            }
+            _ => {
+                let vtype = Vtype::parse(self.csrs[CSR_VTYPE as usize])
+                    .filter(|&vtype| decoded.is_legal(vtype))
+                    .ok_or_else(illegal)?;
+                let vl = self.csrs[CSR_VL as usize] as usize;
+                match decoded {
+                    VInsn::Mem {
+                        store,
+                        vd,
+                        rs1,
+                        eew,
+                        mode,
+                        vm,
+                    } => {
+                        let count = if mode == MemMode::Mask {
+                            vl.div_ceil(8)
+                        } else {
+                            vl
+                        };
+                        self.vector_access(bus, store, vd, rs1, eew, mode, vm, count)
+                    }
+                    VInsn::Arith {
+                        op,
+                        vd,
+                        vs2,
+                        src,
+                        vm,
+                    } => {
+                        self.vector_arith(vtype, vl, op, vd, vs2, src, vm);
+                        Ok(())
+                    }
+                    VInsn::MvXS { rd, vs2 } => {
+                        let value = self.vector.elem(vs2, 0, vtype.sew);
+                        self.set_xreg(rd, sext(value, vtype.sew * 8) as u64);
+                        Ok(())
+                    }
+                    VInsn::MvSX { vd, rs1 } => {
+                        if (self.csrs[CSR_VSTART as usize] as usize) < vl {
+                            self.vector.set_elem(vd, 0, vtype.sew, self.regs[rs1]);
+                        }
+                        Ok(())
+                    }
+                    VInsn::Cpop { rd, vs2, vm } | VInsn::First { rd, vs2, vm } => {
+                        if self.csrs[CSR_VSTART as usize] != 0 {
+                            return Err(illegal());
+                        }
+                        let mut set = (0..vl).filter(|&i| {
+                            self.vector.mask_bit(vs2, i) && (vm || self.vector.mask_bit(0, i))
+                        });
+                        let value = match decoded {
+                            VInsn::Cpop { .. } => set.count() as u64,
+                            _ => set.next().map_or(u64::MAX, |i| i as u64),
+                        };
+                        self.set_xreg(rd, value);
+                        Ok(())
+                    }
+                    VInsn::SetVl { .. } => unreachable!(),
+                }
+            }
+        };
+        if result.is_ok() {
+            self.csrs[CSR_VSTART as usize] = 0;
+        }
+        self.vector_mark_dirty();
+        result
+    }
+
+    fn set_xreg(&mut self, rd: usize, value: u64) {
+        if rd != 0 {
+            self.regs[rd] = value;
+        }
+    }
+
+    fn set_vl(&mut self, rd: usize, avl: Avl, vtype: VtypeSrc) {
+        let raw = match vtype {
+            VtypeSrc::Reg(rs2) => self.regs[rs2],
+            VtypeSrc::Imm(vtype) => vtype,
+        };
+        let vl = match Vtype::parse(raw) {
+            Some(parsed) => {
+                let avl = match avl {
+                    Avl::Imm(avl) => avl,
+                    Avl::Reg(0) if rd != 0 => u64::MAX,
+                    // Keep vl
+                    Avl::Reg(0) => self.csrs[CSR_VL as usize],
+                    Avl::Reg(rs1) => self.regs[rs1],
+                };
+                self.csrs[CSR_VTYPE as usize] = raw;
+                avl.min(parsed.vlmax(self.vector.vlenb) as u64)
+            }
+            None => {
+                self.csrs[CSR_VTYPE as usize] = VTYPE_VILL;
+                0
+            }
+        };
+        self.csrs[CSR_VL as usize] = vl;
+        self.set_xreg(rd, vl);
+    }
+
+    /// Load or store elements `vstart..count`. A fault sets `vstart` to
+    /// the faulting element so the instruction can be restarted, except
+    /// past element 0 of a fault-only-first load, which shrinks `vl`
+    /// instead.
+    #[allow(clippy::too_many_arguments)]
+    fn vector_access(
+        &mut self,
+        bus: &dyn Bus,
+        store: bool,
+        vd: usize,
+        rs1: usize,
+        eew: usize,
+        mode: MemMode,
+        vm: bool,
+        count: usize,
+    ) -> Result<(), Trap> {
+        let base = self.regs[rs1];
+        let stride = match mode {
+            MemMode::Strided(rs2) => self.regs[rs2],
+            _ => eew as u64,
+        };
+        let start = self.csrs[CSR_VSTART as usize] as usize;
+        for i in start..count {
+            if !vm && !self.vector.mask_bit(0, i) {
+                continue;
+            }
+            let addr = base.wrapping_add((i as u64).wrapping_mul(stride));
+            if let Err(trap) = self.vector_element(bus, store, vd, i, eew, addr) {
+                if mode == MemMode::FaultFirst && i > 0 {
+                    self.csrs[CSR_VL as usize] = i as u64;
+                    break;
+                }
+                self.csrs[CSR_VSTART as usize] = i as u64;
+                return Err(trap);
+            }
+        }
+        Ok(())
+    }
+
+    fn vector_element(
+        &mut self,
+        bus: &dyn Bus,
+        store: bool,
+        reg: usize,
+        index: usize,
+        eew: usize,
+        addr: u64,
+    ) -> Result<(), Trap> {
+        if store {
+            let pa = self.translate_addr_for_block(bus, addr, MmuAccessType::Store)?;
+            let value = self.vector.elem(reg, index, eew);
+            match eew {
+                1 => bus.write8(pa, value as u8),
+                2 => bus.write16(pa, value as u16),
+                4 => bus.write32(pa, value as u32),
+                _ => bus.write64(pa, value),
+            }?;
+            self.clear_reservation_if_conflict(addr);
+            self.note_code_write(pa, eew as u64);
+            self.watch_access(self.pc, addr, pa, eew as u64, value, true);
+        } else {
+            let pa = self.translate_addr_for_block(bus, addr, MmuAccessType::Load)?;
+            let value = match eew {
+                1 => bus.read8(pa).map(u64::from),
+                2 => bus.read16(pa).map(u64::from),
+                4 => bus.read32(pa).map(u64::from),
+                _ => bus.read64(pa),
+            }?;
+            self.vector.set_elem(reg, index, eew, value);
+            self.watch_access(self.pc, addr, pa, eew as u64, value, false);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn vector_arith(
+        &mut self,
+        vtype: Vtype,
+        vl: usize,
+        op: IntOp,
+        vd: usize,
+        vs2: usize,
+        src: Src,
+        vm: bool,
+    ) {
+        let sew = vtype.sew;
+        let bits = sew * 8;
+        let truncate = u64::MAX >> (64 - bits);
+        let scalar = match src {
+            Src::X(rs1) => self.regs[rs1] & truncate,
+            Src::I(imm) => imm as u64 & truncate,
+            Src::V(_) => 0,
+        };
+        let start = self.csrs[CSR_VSTART as usize] as usize;
+        for i in start..vl {
+            let active = vm || self.vector.mask_bit(0, i);
+            let b = match src {
+                Src::V(vs1) => self.vector.elem(vs1, i, sew),
+                _ => scalar,
+            };
+            if op == IntOp::Merge {
+                // v0 selects between the operand and vs2 instead of masking
+                let value = if active {
+                    b
+                } else {
+                    self.vector.elem(vs2, i, sew)
+                };
+                self.vector.set_elem(vd, i, sew, value);
+                continue;
+            }
+            if !active {
+                continue;
+            }
+            let a = self.vector.elem(vs2, i, sew);
+            if op.is_compare() {
+                self.vector.set_mask_bit(vd, i, op.compare(a, b, bits));
+            } else {
+                let d = self.vector.elem(vd, i, sew);
+                self.vector.set_elem(vd, i, sew, op.eval(a, b, d, bits));
+            }
+        }
+    }
+}
+
+/// Format a `vtype` value as assembler does, e.g. `e32, m2, ta, ma`.
+fn vtype_name(vtype: u64) -> String {
+    let Some(parsed) = Vtype::parse(vtype) else {
+        return format!("{:#x}", vtype);
+    };
+    let lmul = match parsed.lmul8 {
+        8.. => format!("m{}", parsed.lmul8 / 8),
+        lmul8 => format!("mf{}", 8 / lmul8),
+    };
+    let ta = if vtype & (1 << 6) != 0 { "ta" } else { "tu" };
+    let ma = if vtype & (1 << 7) != 0 { "ma" } else { "mu" };
+    format!("e{}, {}, {}, {}", parsed.sew * 8, lmul, ta, ma)
+}
+
+/// Disassemble `insn` if it is in the implemented subset.
+pub fn disassemble(insn: u32) -> Option<String> {
+    let x = xreg_name;
+    let mask = |vm: bool| if vm { "" } else { ", v0.t" };
+    let text = match decode(insn)? {
+        VInsn::SetVl { rd, avl, vtype } => match (avl, vtype) {
+            (Avl::Reg(rs1), VtypeSrc::Imm(vtype)) => {
+                format!("vsetvli {}, {}, {}", x(rd), x(rs1), vtype_name(vtype))
+            }
+            (Avl::Imm(avl), VtypeSrc::Imm(vtype)) => {
+                format!("vsetivli {}, {}, {}", x(rd), avl, vtype_name(vtype))
+            }
+            (Avl::Reg(rs1), VtypeSrc::Reg(rs2)) => {
+                format!("vsetvl {}, {}, {}", x(rd), x(rs1), x(rs2))
+            }
+            (Avl::Imm(_), VtypeSrc::Reg(_)) => unreachable!(),
+        },
+        VInsn::Mem {
+            store,
+            vd,
+            rs1,
+            eew,
+            mode,
+            vm,
+        } => {
+            let (l, bits) = (if store { "s" } else { "l" }, eew * 8);
+            let name = match mode {
+                MemMode::Unit => format!("v{}e{}.v", l, bits),
+                MemMode::Strided(_) => format!("v{}se{}.v", l, bits),
+                MemMode::FaultFirst => format!("vle{}ff.v", bits),
+                MemMode::Mask => format!("v{}m.v", l),
+                MemMode::Whole(regs) if store => format!("vs{}r.v", regs),
+                MemMode::Whole(regs) => format!("vl{}re{}.v", regs, bits),
+            };
+            match mode {
+                MemMode::Strided(rs2) => {
+                    format!("{} v{}, ({}), {}{}", name, vd, x(rs1), x(rs2), mask(vm))
+                }
+                _ => format!("{} v{}, ({}){}", name, vd, x(rs1), mask(vm)),
+            }
+        }
+        VInsn::Arith {
+            op,
+            vd,
+            vs2,
+            src,
+            vm,
+        } => {
+            let (form, operand) = match src {
+                Src::V(vs1) => ("v", format!("v{}", vs1)),
+                Src::X(rs1) => ("x", x(rs1).to_string()),
+                Src::I(imm) => ("i", imm.to_string()),
+            };
+            match op {
+                IntOp::Merge if vm => format!("vmv.v.{} v{}, {}", form, vd, operand),
+                IntOp::Merge => format!("vmerge.v{}m v{}, v{}, {}, v0", form, vd, vs2, operand),
+                IntOp::Macc => {
+                    format!("vmacc.v{} v{}, {}, v{}{}", form, vd, operand, vs2, mask(vm))
+                }
+                _ => format!(
+                    "{}.v{} v{}, v{}, {}{}",
+                    op.name(),
+                    form,
+                    vd,
+                    vs2,
+                    operand,
+                    mask(vm)
+                ),
+            }
+        }
+        VInsn::MvXS { rd, vs2 } => format!("vmv.x.s {}, v{}", x(rd), vs2),
+        VInsn::MvSX { vd, rs1 } => format!("vmv.s.x v{}, {}", vd, x(rs1)),
+        VInsn::Cpop { rd, vs2, vm } => format!("vcpop.m {}, v{}{}", x(rd), vs2, mask(vm)),
+        VInsn::First { rd, vs2, vm } => format!("vfirst.m {}, v{}{}", x(rd), vs2, mask(vm)),
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+    use crate::cpu::csr::{CSR_MCAUSE, CSR_MEPC};
+    use crate::cpu::test_hart;
+
+    const A0: u32 = 10;
+    const A1: u32 = 11;
+    const A2: u32 = 12;
+    const T0: u32 = 5;
+    /// e8, m1, ta, ma
+    const E8M1: u32 = 0xC0;
+    /// e32, m1, ta, ma
+    const E32M1: u32 = 0xD0;
+
+    fn vsetvli(rd: u32, rs1: u32, vtype: u32) -> u32 {
+        (vtype << 20) | (rs1 << 15) | (7 << 12) | (rd << 7) | OPCODE_OP_V
+    }
+
+    /// Unit-stride load or store of `vd` at `(rs1)`.
+    fn vmem(opcode: u32, width: u32, vd: u32, rs1: u32) -> u32 {
+        (1 << 25) | (rs1 << 15) | (width << 12) | (vd << 7) | opcode
+    }
+
+    fn varith(funct6: u32, funct3: u32, vd: u32, vs2: u32, rs1: u32, vm: bool) -> u32 {
+        (funct6 << 26)
+            | ((vm as u32) << 25)
+            | (vs2 << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | (vd << 7)
+            | OPCODE_OP_V
+    }
+
+    /// A hart with the vector unit on, running `program` from DRAM_BASE.
+    fn setup(program: &[u32]) -> (Cpu, SystemBus) {
+        let (mut cpu, bus) = test_hart(program);
+        cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_VS;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_strip_mined_copy() {
+        let program = [
+            vsetvli(T0, A2, E8M1),
+            vmem(OPCODE_LOAD_FP, 0, 1, A1),
+            vmem(OPCODE_STORE_FP, 0, 1, A0),
+        ];
+        let (mut cpu, bus) = setup(&program);
+        let src: Vec<u8> = (1..=20).collect();
+        bus.dram.write_bytes(0x1000, &src).unwrap();
+        cpu.regs[A0 as usize] = DRAM_BASE + 0x2000;
+        cpu.regs[A1 as usize] = DRAM_BASE + 0x1000;
+        cpu.regs[A2 as usize] = 20;
+        for _ in 0..program.len() {
+            cpu.step(&bus).unwrap();
+        }
+
+        // VLEN 128 holds 16 bytes
+        assert_eq!(cpu.regs[T0 as usize], 16);
+        assert_eq!(cpu.read_csr(CSR_VL).unwrap(), 16);
+        assert_eq!(bus.dram.read_range(0x2000, 20).unwrap()[..16], src[..16]);
+        assert_eq!(bus.dram.read_range(0x2000 + 16, 4).unwrap(), [0; 4]);
+        assert_ne!(cpu.csrs[CSR_MSTATUS as usize] & MSTATUS_SD, 0);
+
+        cpu.set_vlen(256).unwrap();
+        cpu.pc = DRAM_BASE;
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.regs[T0 as usize], 20);
+        assert_eq!(cpu.read_csr(CSR_VLENB).unwrap(), 32);
+    }
+
+    #[test]
+    fn test_masked_arithmetic() {
+        let program = [
+            vsetvli(T0, A2, E32M1),
+            vmem(OPCODE_LOAD_FP, 6, 1, A1),
+            // vmseq.vi v0, v1, 2
+            varith(0b011000, 3, 0, 1, 2, true),
+            // vadd.vx v1, v1, a0, v0.t
+            varith(0b000000, 4, 1, 1, A0, false),
+            // vcpop.m a1, v0
+            varith(0b010000, 2, A1, 0, 0b10000, true),
+            // vmv.x.s a2, v1
+            varith(0b010000, 2, A2, 1, 0, true),
+        ];
+        let (mut cpu, bus) = setup(&program);
+        for (i, value) in [1u32, 2, 3, 4].into_iter().enumerate() {
+            bus.write32(DRAM_BASE + 0x1000 + i as u64 * 4, value)
+                .unwrap();
+        }
+        cpu.regs[A0 as usize] = 100;
+        cpu.regs[A1 as usize] = DRAM_BASE + 0x1000;
+        cpu.regs[A2 as usize] = 4;
+        for _ in 0..program.len() {
+            cpu.step(&bus).unwrap();
+        }
+
+        let elems: Vec<u64> = (0..4).map(|i| cpu.vector.elem(1, i, 4)).collect();
+        assert_eq!(elems, [1, 102, 3, 4]);
+        assert_eq!(cpu.regs[A1 as usize], 1);
+        assert_eq!(cpu.regs[A2 as usize], 1);
+    }
+
+    #[test]
+    fn test_unsupported_encodings_trap() {
+        // vluxei8.v (indexed), then vadd.vv under an illegal vtype
+        let indexed = (1 << 26) | vmem(OPCODE_LOAD_FP, 0, 1, A1);
+        let program = [indexed, vsetvli(T0, A2, 0x3F), varith(0, 0, 1, 2, 3, true)];
+        let (mut cpu, bus) = setup(&program);
+        let _ = cpu.step(&bus);
+        assert_eq!(cpu.read_csr(CSR_MCAUSE).unwrap(), 2);
+        assert_eq!(cpu.pc, 0);
+
+        cpu.pc = DRAM_BASE + 4;
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.read_csr(CSR_VTYPE).unwrap(), VTYPE_VILL);
+        assert_eq!(cpu.regs[T0 as usize], 0);
+        let _ = cpu.step(&bus);
+        assert_eq!(cpu.read_csr(CSR_MEPC).unwrap(), DRAM_BASE + 8);
+
+        // The unit is off until mstatus.VS is set
+        cpu.csrs[CSR_MSTATUS as usize] &= !MSTATUS_VS;
+        assert!(cpu.read_csr(CSR_VL).is_err());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            disassemble(vsetvli(T0, A2, E8M1)).unwrap(),
+            "vsetvli t0, a2, e8, m1, ta, ma"
+        );
+        assert_eq!(
+            disassemble(vmem(OPCODE_STORE_FP, 6, 3, A0)).unwrap(),
+            "vse32.v v3, (a0)"
+        );
+        assert_eq!(
+            disassemble(varith(0b000000, 4, 1, 2, A0, false)).unwrap(),
+            "vadd.vx v1, v2, a0, v0.t"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Watchpoints: breakpoints on guest memory accesses.
+//!
+//! A debugger arms a [`Watchpoint`] on a range of guest physical or virtual
+//! addresses with [`Cpu::add_watchpoint`]. When a load or store overlaps
+//! the range, the access completes and the hart pauses after the accessing
+//! instruction with a [`WatchHit`] latched, reporting its PC and the value
+//! read or written. Like a trap break, the latch holds the hart (`step()`
+//! returns without executing) until [`Cpu::resume_watch`] resets it.
+//! Watchpoints stay armed after they fire.
+//!
+//! The interpreter checks every load, store, AMO and vector element access.
+//! Interpreted blocks check the translated address and, on a match, exit to
+//! the interpreter just before the access so it is the interpreter that
+//! performs and reports it. Native JIT code only ever holds ALU and branch
+//! ops, so it never touches guest memory and needs no check.
+
+use super::core::Cpu;
+
+/// Identifies an armed watchpoint.
+pub type WatchId = u32;
+
+/// Address space a [`Watchpoint`] range lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchSpace {
+    /// Guest physical addresses, as seen on the bus.
+    Physical,
+    /// Guest virtual addresses, before translation.
+    Virtual,
+}
+
+/// A watched address range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u64,
+    /// Length in bytes.
+    pub len: u64,
+    pub space: WatchSpace,
+    /// Fire on loads.
+    pub read: bool,
+    /// Fire on stores (AMOs count as stores).
+    pub write: bool,
+}
+
+impl Watchpoint {
+    /// Watch `len` bytes at physical address `start` for loads and stores.
+    pub fn physical(start: u64, len: u64) -> Self {
+        Self {
+            start,
+            len,
+            space: WatchSpace::Physical,
+            read: true,
+            write: true,
+        }
+    }
+
+    /// Watch `len` bytes at virtual address `start` for loads and stores.
+    pub fn virtual_range(start: u64, len: u64) -> Self {
+        Self {
+            space: WatchSpace::Virtual,
+            ..Self::physical(start, len)
+        }
+    }
+
+    /// Only fire on stores.
+    pub fn writes_only(self) -> Self {
+        Self {
+            read: false,
+            write: true,
+            ..self
+        }
+    }
+
+    /// Only fire on loads.
+    pub fn reads_only(self) -> Self {
+        Self {
+            read: true,
+            write: false,
+            ..self
+        }
+    }
+
+    fn matches(&self, vaddr: u64, paddr: u64, size: u64, write: bool) -> bool {
+        if !(if write { self.write } else { self.read }) {
+            return false;
+        }
+        let addr = match self.space {
+            WatchSpace::Physical => paddr,
+            WatchSpace::Virtual => vaddr,
+        };
+        addr < self.start.saturating_add(self.len) && self.start < addr.saturating_add(size)
+    }
+}
+
+/// An access that fired a [`Watchpoint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The watchpoint that fired.
+    pub id: WatchId,
+    /// PC of the accessing instruction.
+    pub pc: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    /// Access size in bytes.
+    pub size: u64,
+    pub write: bool,
+    /// Value loaded or stored. For AMOs, the value left in memory.
+    pub value: u64,
+}
+
+impl Cpu {
+    /// Arm a watchpoint and return its ID.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchId {
+        let id = self.next_watch_id;
+        self.next_watch_id = self.next_watch_id.wrapping_add(1);
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Disarm a watchpoint. Returns false if `id` is not armed.
+    pub fn remove_watchpoint(&mut self, id: WatchId) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|(armed, _)| *armed != id);
+        self.watchpoints.len() != len
+    }
+
+    /// Disarm all watchpoints (a latched hit is kept).
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Armed watchpoints with their IDs.
+    pub fn watchpoints(&self) -> &[(WatchId, Watchpoint)] {
+        &self.watchpoints
+    }
+
+    /// The latched watchpoint hit, if the hart is paused on one.
+    pub fn watch_hit(&self) -> Option<&WatchHit> {
+        self.watch_hit.as_ref()
+    }
+
+    /// Reset the latch and let the hart run again.
+    pub fn resume_watch(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Whether an access would fire a watchpoint. Blocks use this to hand
+    /// the access to the interpreter.
+    #[inline]
+    pub(super) fn watched(&self, vaddr: u64, paddr: u64, size: u64, write: bool) -> bool {
+        !self.watchpoints.is_empty()
+            && self
+                .watchpoints
+                .iter()
+                .any(|(_, w)| w.matches(vaddr, paddr, size, write))
+    }
+
+    /// Called by the interpreter after a completed access.
+    #[inline]
+    pub(super) fn watch_access(
+        &mut self,
+        pc: u64,
+        vaddr: u64,
+        paddr: u64,
+        size: u64,
+        value: u64,
+        write: bool,
+    ) {
+        if self.watchpoints.is_empty() || self.watch_hit.is_some() {
+            return;
+        }
+        let Some(&(id, _)) = self
+            .watchpoints
+            .iter()
+            .find(|(_, w)| w.matches(vaddr, paddr, size, write))
+        else {
+            return;
+        };
+        self.watch_hit = Some(WatchHit {
+            id,
+            pc,
+            vaddr,
+            paddr,
+            size,
+            write,
+            value,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE, SystemBus};
+    use crate::cpu::test_hart;
+
+    const DATA: u64 = DRAM_BASE + 0x1000;
+
+    fn setup(program: &[u32]) -> (Cpu, SystemBus) {
+        let (mut cpu, bus) = test_hart(program);
+        cpu.regs[10] = DATA;
+        cpu.regs[11] = 0x1234;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_store_watchpoint_latches_until_resume() {
+        // lw a2, 0(a0); sw a1, 4(a0); addi a3, x0, 1
+        let (mut cpu, bus) = setup(&[0x0005_2603, 0x00b5_2223, 0x0010_0693]);
+        let id = cpu.add_watchpoint(Watchpoint::physical(DATA + 6, 1).writes_only());
+
+        cpu.step(&bus).unwrap();
+        assert!(cpu.watch_hit().is_none());
+        cpu.step(&bus).unwrap();
+        let hit = cpu.watch_hit().unwrap().clone();
+        assert_eq!(hit.id, id);
+        assert_eq!(hit.pc, DRAM_BASE + 4);
+        assert_eq!((hit.vaddr, hit.size, hit.write), (DATA + 4, 4, true));
+        assert_eq!(hit.value, 0x1234);
+        assert_eq!(bus.read32(DATA + 4).unwrap(), 0x1234);
+
+        // Paused after the store
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.pc, DRAM_BASE + 8);
+        assert_eq!(cpu.resume_watch(), Some(hit));
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.regs[13], 1);
+        assert!(cpu.remove_watchpoint(id));
+        assert!(!cpu.remove_watchpoint(id));
+    }
+
+    #[test]
+    fn test_block_exits_to_interpreter_on_watched_load() {
+        // addi a3, x0, 1; ld a2, 0(a0); addi a3, a3, 1; ecall
+        let (mut cpu, bus) = setup(&[0x0010_0693, 0x0005_3603, 0x0016_8693, 0x0000_0073]);
+        bus.write64(DATA, 0xdead_beef).unwrap();
+        cpu.use_blocks = true;
+        cpu.add_watchpoint(Watchpoint::virtual_range(DATA, 8).reads_only());
+
+        for _ in 0..4 {
+            if cpu.watch_hit().is_some() {
+                break;
+            }
+            cpu.step(&bus).unwrap();
+        }
+        let hit = cpu.watch_hit().unwrap();
+        assert_eq!(hit.pc, DRAM_BASE + 4);
+        assert_eq!((hit.value, hit.write), (0xdead_beef, false));
+        assert_eq!(cpu.pc, DRAM_BASE + 8);
+        assert_eq!(cpu.regs[12], 0xdead_beef);
+        assert_eq!(cpu.regs[13], 1);
+    }
+}
@@ -0,0 +1,259 @@
+//! Crash dumps for post-mortem debugging.
+//!
+//! When a hart halts the VM on a fatal error, [`CrashDump::capture`] records
+//! what is needed to work out why after the fact:
+//!
+//! - the trap, as described by [`TrapInfo`];
+//! - the hart's registers and CSRs;
+//! - the last instructions it executed, taken from its tracer's ring
+//!   buffer ([`TraceSink::ring`](crate::cpu::TraceSink::ring)) if it has
+//!   one; nothing else keeps a history of the PC, so without one the list
+//!   is empty;
+//! - the memory around the PC and above the stack pointer, read in the
+//!   address space the hart trapped in;
+//! - the state of the CLINT, PLIC and UART, as in a [`Snapshot`].
+//!
+//! A dump is plain JSON so it can be read without this crate.
+//! `NativeVm::set_crash_dump` writes it to a file when the VM halts, and
+//! `WasmVm::crash_dump` hands it to the page to offer as a download.
+//!
+//! [`Snapshot`]: crate::snapshot::Snapshot
+
+use serde::{Deserialize, Serialize};
+
+use crate::Mode;
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::csr::{CSR_MHARTID, CSR_SATP};
+use crate::snapshot::{CpuSnapshot, DeviceSnapshot};
+use crate::vm::guest_mem::{self, Translation};
+use crate::vm::trap_info::TrapInfo;
+
+/// Version identifier of the dump layout.
+pub const CRASH_DUMP_VERSION: &str = "2";
+
+/// Bytes dumped on each side of the PC.
+pub const PC_WINDOW: u64 = 128;
+
+/// Bytes dumped from the stack pointer up.
+pub const STACK_WINDOW: u64 = 512;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// An instruction the hart executed before it crashed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutedInsn {
+    pub pc: u64,
+    /// Encoding; compressed instructions are given in expanded form.
+    pub insn: u32,
+    pub mode: Mode,
+    pub disasm: String,
+}
+
+/// A range of guest virtual memory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryWindow {
+    /// What the window surrounds: `"pc"` or `"sp"`.
+    pub name: String,
+    /// Virtual address of the first byte.
+    pub base: u64,
+    /// Contents, hex-encoded.
+    pub data: String,
+}
+
+impl MemoryWindow {
+    /// Contents as bytes.
+    pub fn bytes(&self) -> Vec<u8> {
+        hex::decode(&self.data).unwrap_or_default()
+    }
+}
+
+/// State of a hart and the machine at the moment it halted on a fatal
+/// error; see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrashDump {
+    pub version: String,
+    pub hart: u64,
+    /// The trap, as printed by the VM.
+    pub reason: String,
+    /// `mcause`-style code, or `None` for host-level stops.
+    pub cause: Option<u64>,
+    pub tval: u64,
+    /// Privilege mode the hart trapped in.
+    pub mode: Mode,
+    /// PC of the trapping instruction.
+    pub pc: u64,
+    /// Encoding of the instruction at `pc`, empty if it was not readable.
+    pub insn: Vec<u8>,
+    /// Cycles the hart had executed.
+    pub cycles: u64,
+    pub cpu: CpuSnapshot,
+    /// Last instructions executed, oldest first.
+    pub recent: Vec<ExecutedInsn>,
+    /// Memory around the PC and the stack pointer; a window is cut short
+    /// at the page its address is in when the next page is not mapped, and
+    /// left out when that page is not mapped either.
+    pub memory: Vec<MemoryWindow>,
+    pub devices: DeviceSnapshot,
+}
+
+impl CrashDump {
+    /// Capture the state of `cpu`, which just stopped on `info`, and of the
+    /// devices on `bus`.
+    pub fn capture(info: &TrapInfo, cpu: &Cpu, bus: &SystemBus) -> Self {
+        let recent = cpu
+            .tracer()
+            .map(|tracer| {
+                tracer
+                    .buffered()
+                    .map(|record| ExecutedInsn {
+                        pc: record.pc,
+                        insn: record.insn,
+                        mode: record.mode,
+                        disasm: record.disasm.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let translation = Translation::Satp(match info.mode {
+            Mode::Machine => 0,
+            _ => cpu.csrs[CSR_SATP as usize],
+        });
+        let sp = cpu.regs[2];
+        let memory = [
+            (
+                "pc",
+                info.pc.saturating_sub(PC_WINDOW),
+                2 * PC_WINDOW,
+                info.pc,
+            ),
+            ("sp", sp, STACK_WINDOW, sp),
+        ]
+        .into_iter()
+        .filter_map(|(name, base, len, addr)| {
+            let (base, bytes) = read_window(cpu, bus, base, len, addr, translation)?;
+            Some(MemoryWindow {
+                name: name.to_string(),
+                base,
+                data: hex::encode(bytes),
+            })
+        })
+        .collect();
+
+        Self {
+            version: CRASH_DUMP_VERSION.to_string(),
+            hart: cpu.csrs[CSR_MHARTID as usize],
+            reason: info.to_string(),
+            cause: info.cause,
+            tval: info.tval,
+            mode: info.mode,
+            pc: info.pc,
+            insn: info.insn.clone(),
+            cycles: cpu.cycles,
+            cpu: CpuSnapshot::capture(cpu),
+            recent,
+            memory,
+            devices: DeviceSnapshot::capture(bus),
+        }
+    }
+
+    /// The window named `name`, if it was readable.
+    pub fn window(&self, name: &str) -> Option<&MemoryWindow> {
+        self.memory.iter().find(|window| window.name == name)
+    }
+
+    /// Encode as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("failed to encode crash dump: {}", e))
+    }
+
+    /// Decode a dump written by [`to_json`](Self::to_json).
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let dump: Self =
+            serde_json::from_str(json).map_err(|e| format!("invalid crash dump: {}", e))?;
+        if dump.version != CRASH_DUMP_VERSION {
+            return Err(format!("unsupported crash dump version {}", dump.version));
+        }
+        Ok(dump)
+    }
+}
+
+/// Read `[base, base + len)`, or failing that the part of it in the page
+/// holding `addr`.
+fn read_window(
+    cpu: &Cpu,
+    bus: &SystemBus,
+    base: u64,
+    len: u64,
+    addr: u64,
+    translation: Translation,
+) -> Option<(u64, Vec<u8>)> {
+    let mut buf = vec![0; len as usize];
+    if guest_mem::read_virt(cpu, bus, base, &mut buf, translation).is_ok() {
+        return Some((base, buf));
+    }
+    let page = addr & !(PAGE_SIZE - 1);
+    let start = base.max(page);
+    let end = base.saturating_add(len).min(page.saturating_add(PAGE_SIZE));
+    let mut buf = vec![0; end.checked_sub(start)? as usize];
+    guest_mem::read_virt(cpu, bus, start, &mut buf, translation).ok()?;
+    Some((start, buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trap;
+    use crate::bus::{Bus, DRAM_BASE};
+    use crate::cpu::{TraceSink, Tracer};
+
+    #[test]
+    fn test_capture_and_round_trip() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        for i in 0..3 {
+            bus.write32(DRAM_BASE + 4 * i, 0x0000_0013).unwrap(); // nop
+        }
+        let stack = DRAM_BASE + 0x8000;
+        bus.write64(stack, 0x1234_5678).unwrap();
+
+        let mut cpu = Cpu::new(DRAM_BASE, 0);
+        cpu.regs[2] = stack;
+        cpu.set_tracer(Some(Tracer::new(TraceSink::ring(2))));
+        for _ in 0..3 {
+            cpu.step(&bus).unwrap();
+        }
+        let trap = Trap::Fatal("test".to_string());
+        let dump = CrashDump::capture(&TrapInfo::capture(&trap, &cpu, &bus), &cpu, &bus);
+
+        assert_eq!(dump.pc, DRAM_BASE + 12);
+        assert_eq!(dump.cpu.regs[2], stack);
+        let pcs: Vec<u64> = dump.recent.iter().map(|insn| insn.pc).collect();
+        assert_eq!(pcs, [DRAM_BASE + 4, DRAM_BASE + 8]);
+        assert_eq!(dump.recent[0].disasm, "nop");
+
+        // The PC window starts below DRAM, so only its page is dumped
+        let pc = dump.window("pc").unwrap();
+        assert_eq!(pc.base, DRAM_BASE);
+        assert_eq!(&pc.bytes()[..4], &0x0000_0013u32.to_le_bytes());
+        let sp = dump.window("sp").unwrap();
+        assert_eq!(sp.base, stack);
+        assert_eq!(sp.bytes().len(), STACK_WINDOW as usize);
+        assert_eq!(&sp.bytes()[..8], &0x1234_5678u64.to_le_bytes());
+
+        let json = dump.to_json().unwrap();
+        assert_eq!(CrashDump::from_json(&json).unwrap(), dump);
+    }
+
+    #[test]
+    fn test_unmapped_windows_are_left_out() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        let cpu = Cpu::new(0x10, 0);
+        let trap = Trap::Fatal("test".to_string());
+        let dump = CrashDump::capture(&TrapInfo::capture(&trap, &cpu, &bus), &cpu, &bus);
+        assert!(dump.memory.is_empty());
+        assert!(dump.recent.is_empty());
+        assert!(dump.insn.is_empty());
+    }
+}
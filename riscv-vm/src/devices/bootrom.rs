@@ -0,0 +1,458 @@
+//! Boot ROM with an embedded first-stage loader.
+//!
+//! Every hart comes out of reset at [`RESET_VECTOR`] and runs a tiny
+//! first-stage loader that lives in this read-only region. The loader
+//! mimics what a real board's mask ROM does before handing control to the
+//! kernel:
+//!
+//! 1. `a0` ← `mhartid`
+//! 2. Reads the boot configuration from the mailbox below
+//! 3. `sp` ← `STACK_TOP - mhartid * STACK_STRIDE`, `a1` ← `BOOT_ARG`
+//! 4. Clears every other general-purpose register
+//! 5. Jumps to `ENTRY`
+//!
+//! The loader is assembled at compile time from the encoders below, so no
+//! external toolchain is needed to build the emulator.
+//!
+//! ## Mailbox Layout (read-only to the guest, filled by the host)
+//!
+//! | Offset | Name         | Description                                  |
+//! |--------|--------------|----------------------------------------------|
+//! | 0x100  | MAGIC        | `BOOT_MAGIC` once the host has configured it |
+//! | 0x108  | ENTRY        | Kernel entry point                           |
+//! | 0x110  | STACK_TOP    | Initial stack pointer for hart 0             |
+//! | 0x118  | STACK_STRIDE | Stack bytes reserved per hart                |
+//! | 0x120  | BOOT_ARG     | Value passed to the kernel in `a1`           |
+//! | 0x128  | FDT          | Address of the device tree blob, or 0        |
+//!
+//! The device tree blob (see [`crate::devices::fdt`]) is served from
+//! `FDT_OFFSET` up to the end of the ROM.
+//!
+//! ## Custom ROM and Reset Vector
+//!
+//! The host may replace the loader page (everything below `FDT_OFFSET`,
+//! mailbox included) with its own code through [`BootRom::set_image`], and
+//! move the reset vector with [`BootRom::set_reset_vector`], e.g. straight
+//! to firmware such as OpenSBI. To boot firmware through the built-in
+//! loader instead, point `ENTRY` at it and pass the device tree address in
+//! `BOOT_ARG`, which the loader hands over in `a1`.
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::devices::mmio::MmioDevice;
+use crate::dram::MemoryError;
+
+/// Base address of the boot ROM.
+pub const BOOTROM_BASE: u64 = 0x0000_1000;
+/// Size of the boot ROM region (loader page plus device tree).
+pub const BOOTROM_SIZE: u64 = 0x10000;
+/// Address every hart starts executing from after reset.
+pub const RESET_VECTOR: u64 = BOOTROM_BASE;
+
+/// Value stored in the MAGIC slot once the mailbox is configured ("BOOTROM\0").
+pub const BOOT_MAGIC: u64 = 0x004d_4f52_544f_4f42;
+
+// Mailbox offsets (relative to BOOTROM_BASE)
+const MAILBOX: u64 = 0x100;
+const MAGIC: u64 = MAILBOX;
+const ENTRY: u64 = MAILBOX + 0x08;
+const STACK_TOP: u64 = MAILBOX + 0x10;
+const STACK_STRIDE: u64 = MAILBOX + 0x18;
+const BOOT_ARG: u64 = MAILBOX + 0x20;
+const FDT: u64 = MAILBOX + 0x28;
+const MAILBOX_END: u64 = MAILBOX + 0x30;
+
+/// Offset of the device tree blob within the ROM.
+pub const FDT_OFFSET: u64 = 0x1000;
+/// Largest device tree blob the ROM can hold.
+pub const FDT_MAX_SIZE: usize = (BOOTROM_SIZE - FDT_OFFSET) as usize;
+
+/// Default per-hart stack reservation used by [`BootConfig::new`].
+pub const DEFAULT_STACK_STRIDE: u64 = 64 * 1024;
+
+// ---------------------------------------------------------------------------
+// First-stage loader
+// ---------------------------------------------------------------------------
+
+const RA: u32 = 1;
+const SP: u32 = 2;
+const T0: u32 = 5;
+const T1: u32 = 6;
+const T2: u32 = 7;
+const A0: u32 = 10;
+const A1: u32 = 11;
+
+const CSR_MHARTID: u32 = 0xF14;
+
+const fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: u32) -> u32 {
+    ((imm & 0xFFF) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+const fn r_type(funct7: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0x33
+}
+
+const fn auipc(rd: u32, imm20: u32) -> u32 {
+    (imm20 << 12) | (rd << 7) | 0x17
+}
+
+const fn csrr(rd: u32, csr: u32) -> u32 {
+    i_type(0x73, 0b010, rd, 0, csr)
+}
+
+const fn ld(rd: u32, rs1: u32, offset: u64) -> u32 {
+    i_type(0x03, 0b011, rd, rs1, offset as u32)
+}
+
+const fn addi(rd: u32, rs1: u32, imm: u32) -> u32 {
+    i_type(0x13, 0b000, rd, rs1, imm)
+}
+
+const fn jalr(rd: u32, rs1: u32, imm: u32) -> u32 {
+    i_type(0x67, 0b000, rd, rs1, imm)
+}
+
+/// Registers zeroed before the jump. `t0` goes last since it holds the ROM
+/// base until then; `t1` keeps the entry address for the final `jr`.
+const CLEARED: [u32; 27] = [
+    RA, 3, 4, T2, 8, 9, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30,
+    31, T0,
+];
+
+const PROLOGUE_WORDS: usize = 8;
+const CODE_WORDS: usize = PROLOGUE_WORDS + CLEARED.len() + 1;
+
+const fn assemble() -> [u32; CODE_WORDS] {
+    let mut code = [0u32; CODE_WORDS];
+    code[0] = auipc(T0, 0); // t0 = BOOTROM_BASE
+    code[1] = csrr(A0, CSR_MHARTID);
+    code[2] = ld(T1, T0, ENTRY);
+    code[3] = ld(SP, T0, STACK_TOP);
+    code[4] = ld(T2, T0, STACK_STRIDE);
+    code[5] = r_type(0x01, 0b000, T2, T2, A0); // mul t2, t2, a0
+    code[6] = r_type(0x20, 0b000, SP, SP, T2); // sub sp, sp, t2
+    code[7] = ld(A1, T0, BOOT_ARG);
+    let mut i = 0;
+    while i < CLEARED.len() {
+        code[PROLOGUE_WORDS + i] = addi(CLEARED[i], 0, 0);
+        i += 1;
+    }
+    code[CODE_WORDS - 1] = jalr(0, T1, 0);
+    code
+}
+
+/// Machine code of the first-stage loader, placed at [`RESET_VECTOR`].
+pub const BOOT_CODE: [u32; CODE_WORDS] = assemble();
+
+const _: () = assert!((CODE_WORDS * 4) as u64 <= MAILBOX);
+
+/// Boot parameters handed from the host to the first-stage loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig {
+    pub entry: u64,
+    pub stack_top: u64,
+    pub stack_stride: u64,
+    pub boot_arg: u64,
+}
+
+impl BootConfig {
+    /// Configuration with the default per-hart stack stride and no boot argument.
+    pub fn new(entry: u64, stack_top: u64) -> Self {
+        Self {
+            entry,
+            stack_top,
+            stack_stride: DEFAULT_STACK_STRIDE,
+            boot_arg: 0,
+        }
+    }
+}
+
+/// Read-only boot ROM device.
+pub struct BootRom {
+    magic: AtomicU64,
+    entry: AtomicU64,
+    stack_top: AtomicU64,
+    stack_stride: AtomicU64,
+    boot_arg: AtomicU64,
+    fdt: RwLock<Vec<u8>>,
+    /// Host-supplied loader page replacing `BOOT_CODE` and the mailbox
+    image: RwLock<Option<Vec<u8>>>,
+    reset_vector: AtomicU64,
+}
+
+impl BootRom {
+    pub fn new() -> Self {
+        Self {
+            magic: AtomicU64::new(0),
+            entry: AtomicU64::new(0),
+            stack_top: AtomicU64::new(0),
+            stack_stride: AtomicU64::new(0),
+            boot_arg: AtomicU64::new(0),
+            fdt: RwLock::new(Vec::new()),
+            image: RwLock::new(None),
+            reset_vector: AtomicU64::new(RESET_VECTOR),
+        }
+    }
+
+    /// Replace the first-stage loader and mailbox with `image`, which runs
+    /// from [`BOOTROM_BASE`] and may be up to `FDT_OFFSET` bytes long. The
+    /// device tree is still served after it. Must happen before any hart
+    /// leaves reset.
+    pub fn set_image(&self, image: Vec<u8>) -> Result<(), String> {
+        if image.len() as u64 > FDT_OFFSET {
+            return Err(format!(
+                "boot ROM image is {} bytes, the loader page holds {}",
+                image.len(),
+                FDT_OFFSET
+            ));
+        }
+        *self.image.write().unwrap() = Some(image);
+        Ok(())
+    }
+
+    /// Whether the built-in loader has been replaced with [`Self::set_image`].
+    pub fn has_custom_image(&self) -> bool {
+        self.image.read().unwrap().is_some()
+    }
+
+    /// Address harts start executing from after reset.
+    pub fn reset_vector(&self) -> u64 {
+        self.reset_vector.load(Ordering::Relaxed)
+    }
+
+    /// Start harts at `pc` instead of [`RESET_VECTOR`]. Must happen before
+    /// any hart is created.
+    pub fn set_reset_vector(&self, pc: u64) {
+        self.reset_vector.store(pc, Ordering::Relaxed);
+    }
+
+    /// Install the device tree blob and publish its address in the mailbox.
+    /// Must happen before any hart leaves reset.
+    pub fn set_fdt(&self, blob: Vec<u8>) -> Result<(), String> {
+        if blob.len() > FDT_MAX_SIZE {
+            return Err(format!(
+                "device tree is {} bytes, boot ROM holds {}",
+                blob.len(),
+                FDT_MAX_SIZE
+            ));
+        }
+        *self.fdt.write().unwrap() = blob;
+        Ok(())
+    }
+
+    /// Guest physical address of the device tree, if one is installed.
+    pub fn fdt_addr(&self) -> Option<u64> {
+        if self.fdt.read().unwrap().is_empty() {
+            None
+        } else {
+            Some(BOOTROM_BASE + FDT_OFFSET)
+        }
+    }
+
+    /// Fill the mailbox. Must happen before any hart leaves reset.
+    pub fn configure(&self, config: &BootConfig) {
+        self.entry.store(config.entry, Ordering::Relaxed);
+        self.stack_top.store(config.stack_top, Ordering::Relaxed);
+        self.stack_stride
+            .store(config.stack_stride, Ordering::Relaxed);
+        self.boot_arg.store(config.boot_arg, Ordering::Relaxed);
+        self.magic.store(BOOT_MAGIC, Ordering::Release);
+    }
+
+    /// Current mailbox contents, or `None` if the host never configured it.
+    pub fn config(&self) -> Option<BootConfig> {
+        if self.magic.load(Ordering::Acquire) != BOOT_MAGIC {
+            return None;
+        }
+        Some(BootConfig {
+            entry: self.entry.load(Ordering::Relaxed),
+            stack_top: self.stack_top.load(Ordering::Relaxed),
+            stack_stride: self.stack_stride.load(Ordering::Relaxed),
+            boot_arg: self.boot_arg.load(Ordering::Relaxed),
+        })
+    }
+
+    fn byte_at(&self, offset: u64) -> u8 {
+        if offset < MAILBOX {
+            let word = (offset / 4) as usize;
+            return match BOOT_CODE.get(word) {
+                Some(insn) => (insn >> ((offset % 4) * 8)) as u8,
+                None => 0,
+            };
+        }
+        if offset >= MAILBOX_END {
+            return 0;
+        }
+        if offset & !7 == FDT {
+            let addr = self.fdt_addr().unwrap_or(0);
+            return (addr >> ((offset % 8) * 8)) as u8;
+        }
+        let slot = match offset & !7 {
+            MAGIC => &self.magic,
+            ENTRY => &self.entry,
+            STACK_TOP => &self.stack_top,
+            STACK_STRIDE => &self.stack_stride,
+            BOOT_ARG => &self.boot_arg,
+            _ => return 0,
+        };
+        (slot.load(Ordering::Relaxed) >> ((offset % 8) * 8)) as u8
+    }
+
+    /// Read `size` bytes (little-endian) at `offset` within the ROM.
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        let mut value = 0u64;
+        if offset >= FDT_OFFSET {
+            let fdt = self.fdt.read().unwrap();
+            for i in 0..size {
+                let byte = fdt.get((offset - FDT_OFFSET + i) as usize).copied();
+                value |= (byte.unwrap_or(0) as u64) << (i * 8);
+            }
+            return value;
+        }
+        if let Some(image) = self.image.read().unwrap().as_ref() {
+            for i in 0..size {
+                let byte = image.get((offset + i) as usize).copied();
+                value |= (byte.unwrap_or(0) as u64) << (i * 8);
+            }
+            return value;
+        }
+        for i in 0..size {
+            value |= (self.byte_at(offset + i) as u64) << (i * 8);
+        }
+        value
+    }
+}
+
+impl MmioDevice for BootRom {
+    fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        Ok(BootRom::load(self, offset, size))
+    }
+
+    /// The ROM is read-only: stores fault.
+    fn store(&self, offset: u64, _size: u64, _value: u64) -> Result<(), MemoryError> {
+        Err(MemoryError::OutOfBounds(offset))
+    }
+}
+
+impl Default for BootRom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trap;
+    use crate::bus::{Bus, DRAM_BASE, SystemBus};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_code_fits_before_mailbox() {
+        let rom = BootRom::new();
+        assert_eq!(rom.load(0, 4) as u32, BOOT_CODE[0]);
+        assert_eq!(rom.load(MAILBOX - 4, 4), 0);
+        assert!(rom.config().is_none());
+    }
+
+    #[test]
+    fn test_mailbox_readback() {
+        let rom = BootRom::new();
+        let cfg = BootConfig {
+            entry: 0x8020_0000,
+            stack_top: 0x8800_0000,
+            stack_stride: 0x4000,
+            boot_arg: 0xdead_beef,
+        };
+        rom.configure(&cfg);
+        assert_eq!(rom.config(), Some(cfg));
+        assert_eq!(rom.load(MAGIC, 8), BOOT_MAGIC);
+        assert_eq!(rom.load(ENTRY, 8), 0x8020_0000);
+        assert_eq!(rom.load(ENTRY + 4, 4), 0);
+        assert_eq!(rom.load(BOOT_ARG, 2), 0xbeef);
+    }
+
+    #[test]
+    fn test_fdt_is_served_from_rom() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert_eq!(bus.read64(BOOTROM_BASE + FDT).unwrap(), 0);
+
+        let blob = crate::devices::fdt::generate(bus.config(), 4, "");
+        bus.boot_rom.set_fdt(blob.clone()).unwrap();
+        let addr = bus.read64(BOOTROM_BASE + FDT).unwrap();
+        assert_eq!(addr, BOOTROM_BASE + FDT_OFFSET);
+        // The blob is big-endian: the magic reads back byte-swapped
+        assert_eq!(bus.read32(addr).unwrap().swap_bytes(), 0xd00d_feed);
+        let last = addr + blob.len() as u64 - 1;
+        assert_eq!(bus.read8(last).unwrap(), blob[blob.len() - 1]);
+        assert_eq!(bus.read8(last + 1).unwrap(), 0);
+
+        assert!(bus.boot_rom.set_fdt(vec![0; FDT_MAX_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_rom_is_read_only() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert!(matches!(
+            bus.write32(BOOTROM_BASE, 0),
+            Err(Trap::StoreAccessFault(_))
+        ));
+        assert_eq!(bus.read32(BOOTROM_BASE).unwrap(), BOOT_CODE[0]);
+    }
+
+    #[test]
+    fn test_custom_image_replaces_loader() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        bus.boot_rom.set_fdt(vec![0xd0, 0x0d]).unwrap();
+        // addi a0, zero, 7; j .
+        let code = [0x0070_0513u32, 0x0000_006f];
+        let image: Vec<u8> = code.iter().flat_map(|w| w.to_le_bytes()).collect();
+        bus.boot_rom.set_image(image).unwrap();
+
+        assert_eq!(bus.read32(BOOTROM_BASE).unwrap(), code[0]);
+        assert_eq!(bus.read64(BOOTROM_BASE + MAGIC).unwrap(), 0);
+        assert_eq!(bus.read8(BOOTROM_BASE + FDT_OFFSET).unwrap(), 0xd0);
+        assert!(
+            bus.boot_rom
+                .set_image(vec![0; FDT_OFFSET as usize + 1])
+                .is_err()
+        );
+
+        let mut cpu = Cpu::new(bus.boot_rom.reset_vector(), 0);
+        cpu.step(&bus).unwrap();
+        cpu.step(&bus).unwrap();
+        assert_eq!(cpu.regs[10], 7);
+        assert_eq!(cpu.pc, BOOTROM_BASE + 4);
+    }
+
+    #[test]
+    fn test_loader_hands_off_to_kernel() {
+        let bus = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        // `j .` at the kernel entry so execution parks there
+        bus.write32(DRAM_BASE, 0x0000_006f).unwrap();
+        bus.boot_rom.configure(&BootConfig {
+            entry: DRAM_BASE,
+            stack_top: DRAM_BASE + 0x10_0000,
+            stack_stride: 0x1000,
+            boot_arg: 0x1234,
+        });
+
+        let mut cpu = Cpu::new(RESET_VECTOR, 3);
+        for reg in cpu.regs.iter_mut().skip(1) {
+            *reg = 0x5a5a;
+        }
+        for _ in 0..CODE_WORDS {
+            cpu.step(&bus).unwrap();
+        }
+
+        assert_eq!(cpu.pc, DRAM_BASE);
+        assert_eq!(cpu.regs[10], 3);
+        assert_eq!(cpu.regs[11], 0x1234);
+        assert_eq!(cpu.regs[2], DRAM_BASE + 0x10_0000 - 3 * 0x1000);
+        assert_eq!(cpu.regs[6], DRAM_BASE);
+        for i in CLEARED {
+            assert_eq!(cpu.regs[i as usize], 0, "x{} not cleared", i);
+        }
+    }
+}
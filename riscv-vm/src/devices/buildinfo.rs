@@ -0,0 +1,192 @@
+//! Emulator Build Information Device
+//!
+//! A read-only page identifying the emulator the guest is running on, so
+//! that guest-side bug reports (e.g. the output of `sysinfo`) carry the
+//! exact emulator version, commit and configuration.
+//!
+//! ## Register Layout
+//!
+//! | Offset | Name        | Description                                     |
+//! |--------|-------------|-------------------------------------------------|
+//! | 0x00   | MAGIC       | `"RVBI"` (32 bits)                              |
+//! | 0x04   | LAYOUT      | Layout revision of this page, currently 1       |
+//! | 0x08   | VERSION     | Major, minor, patch in bits 23-16, 15-8, 7-0    |
+//! | 0x0c   | HARTS       | Number of harts the VM was started with         |
+//! | 0x10   | FEATURES    | Feature bits (64 bits, see `FEATURE_*`)         |
+//! | 0x40   | VERSION_STR | Crate version string                            |
+//! | 0x60   | GIT_HASH    | Commit the emulator was built from, or `unknown` |
+//! | 0x80   | HOST        | Host platform as `<arch>-<os>`                  |
+//! | 0xa0   | NETWORK     | Network backend name, or `none`                 |
+//!
+//! Strings are ASCII, NUL-padded to 32 bytes. Any access size works; bytes
+//! outside the fields read as zero and stores are ignored.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Base address for the build information device
+pub const BUILDINFO_BASE: u64 = 0x0013_0000;
+/// Size of the build information MMIO region
+pub const BUILDINFO_SIZE: u64 = 0x1000;
+
+/// The emulator was compiled with the native JIT
+pub const FEATURE_JIT_BUILTIN: u64 = 1 << 0;
+/// The JIT is enabled for this VM
+pub const FEATURE_JIT_ENABLED: u64 = 1 << 1;
+/// A network backend is attached
+pub const FEATURE_NETWORK: u64 = 1 << 2;
+
+const MAGIC: u64 = 0x00;
+const LAYOUT: u64 = 0x04;
+const VERSION: u64 = 0x08;
+const HARTS: u64 = 0x0c;
+const FEATURES: u64 = 0x10;
+const FEATURES_END: u64 = FEATURES + 8;
+const VERSION_STR: u64 = 0x40;
+const GIT_HASH: u64 = 0x60;
+const HOST: u64 = 0x80;
+const NETWORK: u64 = 0xa0;
+const STR_LEN: u64 = 32;
+const NETWORK_END: u64 = NETWORK + STR_LEN;
+
+const LAYOUT_REVISION: u32 = 1;
+
+/// Commit hash captured by the build script
+pub const GIT_HASH_STR: &str = env!("RISCV_VM_GIT_HASH");
+
+/// Host platform string, e.g. `x86_64-linux` or `wasm32-unknown`
+pub fn host_platform() -> String {
+    format!("{}-{}", std::env::consts::ARCH, host_os())
+}
+
+fn host_os() -> &'static str {
+    match std::env::consts::OS {
+        "" => "unknown",
+        os => os,
+    }
+}
+
+fn packed_version() -> u32 {
+    let part = |s: &str| s.parse::<u32>().unwrap_or(0) & 0xff;
+    part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+pub struct BuildInfo {
+    harts: AtomicU32,
+    jit_enabled: AtomicBool,
+    network: Mutex<&'static str>,
+    host: String,
+}
+
+impl BuildInfo {
+    pub fn new() -> Self {
+        Self {
+            harts: AtomicU32::new(1),
+            jit_enabled: AtomicBool::new(false),
+            network: Mutex::new("none"),
+            host: host_platform(),
+        }
+    }
+
+    /// Record the hart count the VM was started with.
+    pub fn set_harts(&self, harts: u32) {
+        self.harts.store(harts, Ordering::Relaxed);
+    }
+
+    /// Record whether the JIT is enabled.
+    pub fn set_jit_enabled(&self, enabled: bool) {
+        self.jit_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Record the attached network backend, `None` once it is detached.
+    pub fn set_network(&self, backend: Option<&'static str>) {
+        *self.network.lock().unwrap() = backend.unwrap_or("none");
+    }
+
+    pub fn features(&self) -> u64 {
+        let mut features = 0;
+        if cfg!(all(feature = "jit-native", not(target_arch = "wasm32"))) {
+            features |= FEATURE_JIT_BUILTIN;
+        }
+        if self.jit_enabled.load(Ordering::Relaxed) {
+            features |= FEATURE_JIT_ENABLED;
+        }
+        if *self.network.lock().unwrap() != "none" {
+            features |= FEATURE_NETWORK;
+        }
+        features
+    }
+
+    /// Load from register
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        let mut val = 0;
+        for i in (0..size).rev() {
+            val = val << 8 | self.byte(offset + i) as u64;
+        }
+        val
+    }
+
+    fn byte(&self, offset: u64) -> u8 {
+        let word = |value: u64, base: u64| (value >> ((offset - base) * 8)) as u8;
+        let text = |s: &str, base: u64| s.as_bytes().get((offset - base) as usize).copied();
+        match offset {
+            MAGIC..LAYOUT => word(u32::from_le_bytes(*b"RVBI") as u64, MAGIC),
+            LAYOUT..VERSION => word(LAYOUT_REVISION as u64, LAYOUT),
+            VERSION..HARTS => word(packed_version() as u64, VERSION),
+            HARTS..FEATURES => word(self.harts.load(Ordering::Relaxed) as u64, HARTS),
+            FEATURES..FEATURES_END => word(self.features(), FEATURES),
+            VERSION_STR..GIT_HASH => text(env!("CARGO_PKG_VERSION"), VERSION_STR).unwrap_or(0),
+            GIT_HASH..HOST => text(GIT_HASH_STR, GIT_HASH).unwrap_or(0),
+            HOST..NETWORK => text(&self.host, HOST).unwrap_or(0),
+            NETWORK..NETWORK_END => text(&self.network.lock().unwrap(), NETWORK).unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read a NUL-padded string field byte by byte, as a guest would.
+    fn read_str(info: &BuildInfo, base: u64) -> String {
+        let bytes: Vec<u8> = (base..base + STR_LEN)
+            .map(|off| info.load(off, 1) as u8)
+            .take_while(|&b| b != 0)
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_identification_page() {
+        let info = BuildInfo::new();
+        assert_eq!(info.load(MAGIC, 4), 0x4942_5652);
+        assert_eq!(info.load(LAYOUT, 4), 1);
+        assert_eq!(info.load(HARTS, 4), 1);
+
+        info.set_harts(4);
+        info.set_jit_enabled(true);
+        info.set_network(Some("webtransport"));
+        // A 64-bit read at VERSION returns HARTS in its upper half
+        assert_eq!(info.load(VERSION, 8) >> 32, 4);
+        assert_ne!(info.load(FEATURES, 8) & FEATURE_JIT_ENABLED, 0);
+        assert_ne!(info.load(FEATURES, 8) & FEATURE_NETWORK, 0);
+
+        assert_eq!(read_str(&info, VERSION_STR), env!("CARGO_PKG_VERSION"));
+        assert_eq!(read_str(&info, GIT_HASH), GIT_HASH_STR);
+        assert_eq!(read_str(&info, HOST), host_platform());
+        assert_eq!(read_str(&info, NETWORK), "webtransport");
+
+        info.set_network(None);
+        assert_eq!(read_str(&info, NETWORK), "none");
+        assert_eq!(info.load(FEATURES, 8) & FEATURE_NETWORK, 0);
+    }
+}
@@ -1,4 +1,8 @@
 use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Condvar, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
 pub const CLINT_BASE: u64 = 0x0200_0000;
 pub const CLINT_SIZE: u64 = 0x10000;
@@ -13,11 +17,12 @@ pub const HART_COUNT_OFFSET: u64 = 0x0F00;
 /// Set high enough to support modern multi-core systems.
 pub const MAX_HARTS: usize = 128;
 
-/// Time increment per tick (in timer ticks).
-/// This is called every 256 CPU steps (when CPU poll_counter wraps), so we
-/// increment by 256 to maintain the same effective timer rate.
-/// At 10MHz and ~1 instruction per cycle at ~10MHz CPU, this gives roughly real-time.
-const MTIME_INCREMENT: u64 = 256;
+/// `mtime` tick rate, reported to the guest as `timebase-frequency`.
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// Default modelled CPU clock. With the cycle costs of
+/// [`crate::engine::timing`] this is roughly 60-70 MIPS of guest time.
+pub const DEFAULT_CPU_FREQUENCY: u64 = 100_000_000;
 
 /// Core Local Interruptor (CLINT) - Timer and Software Interrupts
 ///
@@ -26,10 +31,20 @@ const MTIME_INCREMENT: u64 = 256;
 /// - Each hart primarily accesses its own msip/mtimecmp slots
 /// - mtime is shared but only incremented by hart 0
 /// - The weak memory ordering matches RISC-V's memory model
+///
+/// The one lock is taken when an IPI is sent or `mtimecmp` is written, to
+/// wake harts the host put to sleep in WFI (see [`Clint::wait_for_interrupt`]).
 pub struct Clint {
-    /// Machine timer counter - incremented by tick() every 256 CPU steps.
+    /// Machine timer counter, advanced from the cycles hart 0 executes.
     mtime: AtomicU64,
 
+    /// Modelled CPU clock in Hz.
+    cpu_frequency: AtomicU64,
+
+    /// Cycle remainder not yet worth a whole `mtime` tick, scaled by
+    /// `TIMEBASE_FREQUENCY`. Only touched by hart 0.
+    cycle_remainder: AtomicU64,
+
     /// Per-hart Machine Software Interrupt Pending bits.
     /// Only bit 0 is meaningful for each entry.
     msip: [AtomicU32; MAX_HARTS],
@@ -40,6 +55,18 @@ pub struct Clint {
 
     /// Number of harts in the system (set at initialization).
     num_harts: AtomicUsize,
+
+    /// Harts sleeping in WFI wait here for an IPI or a new timer compare.
+    #[cfg(not(target_arch = "wasm32"))]
+    wakeup: Wakeup,
+}
+
+/// Generation counter the CLINT bumps, under its lock, to wake sleepers.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Wakeup {
+    generation: Mutex<u64>,
+    cond: Condvar,
 }
 
 impl Clint {
@@ -58,9 +85,13 @@ impl Clint {
 
         Self {
             mtime: AtomicU64::new(0),
+            cpu_frequency: AtomicU64::new(DEFAULT_CPU_FREQUENCY),
+            cycle_remainder: AtomicU64::new(0),
             msip: [ZERO_U32; MAX_HARTS],
             mtimecmp: [MAX_U64; MAX_HARTS],
             num_harts: AtomicUsize::new(num_harts.min(MAX_HARTS)),
+            #[cfg(not(target_arch = "wasm32"))]
+            wakeup: Wakeup::default(),
         }
     }
 
@@ -88,20 +119,35 @@ impl Clint {
         self.mtime.store(val, Ordering::Relaxed);
     }
 
-    /// Advance mtime by one tick. Called once per CPU step.
-    /// Lock-free using atomic fetch_add.
-    #[inline]
-    pub fn tick(&self) {
-        self.mtime.fetch_add(MTIME_INCREMENT, Ordering::Relaxed);
+    /// Modelled CPU clock in Hz.
+    pub fn cpu_frequency(&self) -> u64 {
+        self.cpu_frequency.load(Ordering::Relaxed)
     }
 
-    /// Backward compatibility: increment is now tick()
-    pub fn increment(&self) {
-        self.tick();
+    /// Set the modelled CPU clock. Guest time then advances by one second
+    /// for every `hz` cycles executed. Zero is ignored.
+    pub fn set_cpu_frequency(&self, hz: u64) {
+        if hz > 0 {
+            self.cpu_frequency.store(hz, Ordering::Relaxed);
+            self.cycle_remainder.store(0, Ordering::Relaxed);
+        }
     }
 
-    pub fn sync_time_micros(&self, _micros: u64) {
-        // No-op for deterministic timer
+    /// Advance mtime by the time `cycles` CPU cycles take at the configured
+    /// frequency, carrying fractions of a tick over to the next call.
+    /// Called by hart 0 only. Returns the number of ticks added.
+    #[inline]
+    pub fn advance_cycles(&self, cycles: u64) -> u64 {
+        let hz = self.cpu_frequency.load(Ordering::Relaxed) as u128;
+        let scaled = self.cycle_remainder.load(Ordering::Relaxed) as u128
+            + cycles as u128 * TIMEBASE_FREQUENCY as u128;
+        let ticks = (scaled / hz) as u64;
+        self.cycle_remainder
+            .store((scaled % hz) as u64, Ordering::Relaxed);
+        if ticks > 0 {
+            self.mtime.fetch_add(ticks, Ordering::Relaxed);
+        }
+        ticks
     }
 
     /// Get msip value for a hart (lock-free using atomics)
@@ -120,9 +166,41 @@ impl Clint {
         if hart < MAX_HARTS {
             // Only bit 0 matters for MSIP
             self.msip[hart].store(value & 1, Ordering::Release);
+            if value & 1 != 0 {
+                self.wake_harts();
+            }
+        }
+    }
+
+    /// Wake every hart sleeping in [`Self::wait_for_interrupt`] so it
+    /// checks its interrupts again.
+    #[inline]
+    fn wake_harts(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            *self.wakeup.generation.lock().unwrap() += 1;
+            self.wakeup.cond.notify_all();
         }
     }
 
+    /// Sleep hart `hart_id`, idling in WFI, for up to `timeout` or until
+    /// its software interrupt is raised or any hart's `mtimecmp` is
+    /// written. Returns how long it slept if woken before `timeout`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_for_interrupt(&self, hart_id: usize, timeout: Duration) -> Option<Duration> {
+        let start = Instant::now();
+        let generation = self.wakeup.generation.lock().unwrap();
+        let seen = *generation;
+        let (_generation, result) = self
+            .wakeup
+            .cond
+            .wait_timeout_while(generation, timeout, |g| {
+                *g == seen && !self.is_msip_pending(hart_id)
+            })
+            .unwrap();
+        (!result.timed_out()).then(|| start.elapsed())
+    }
+
     /// Get mtimecmp value for a hart (lock-free using atomics)
     pub fn get_mtimecmp(&self, hart: usize) -> u64 {
         if hart < MAX_HARTS {
@@ -136,6 +214,7 @@ impl Clint {
     pub fn set_mtimecmp(&self, hart: usize, value: u64) {
         if hart < MAX_HARTS {
             self.mtimecmp[hart].store(value, Ordering::Release);
+            self.wake_harts();
         }
     }
 
@@ -175,7 +254,10 @@ impl Clint {
                 Ordering::Release,
                 Ordering::Relaxed,
             ) {
-                Ok(_) => break,
+                Ok(_) => {
+                    self.wake_harts();
+                    break;
+                }
                 Err(_) => continue, // Retry on contention
             }
         }
@@ -198,7 +280,10 @@ impl Clint {
                 Ordering::Release,
                 Ordering::Relaxed,
             ) {
-                Ok(_) => break,
+                Ok(_) => {
+                    self.wake_harts();
+                    break;
+                }
                 Err(_) => continue,
             }
         }
@@ -381,13 +466,13 @@ impl Clint {
                 let hart_idx = ((o - MSIP_OFFSET) / 4) as usize;
                 if hart_idx < MAX_HARTS {
                     // Only bit 0 matters for MSIP (Machine Software Interrupt Pending)
-                    self.msip[hart_idx].store((value & 1) as u32, Ordering::Release);
+                    self.set_msip(hart_idx, value as u32);
                 }
             }
 
             // ============================================================
             // MTIME: Read-only in this implementation
-            // (Timer is driven by tick() calls from the emulator)
+            // (Timer is driven by advance_cycles() from hart 0)
             // ============================================================
             (MTIME_OFFSET, _) => {
                 // Ignore writes to MTIME
@@ -403,7 +488,7 @@ impl Clint {
                 // Full 64-bit write
                 let hart_idx = ((o - MTIMECMP_OFFSET) / 8) as usize;
                 if hart_idx < MAX_HARTS {
-                    self.mtimecmp[hart_idx].store(value, Ordering::Release);
+                    self.set_mtimecmp(hart_idx, value);
                 }
             }
             (o, 4) if o >= MTIMECMP_OFFSET && o < MTIMECMP_OFFSET + (MAX_HARTS as u64 * 8) => {
@@ -694,6 +779,25 @@ mod tests {
         assert_eq!(clint2.get_mtimecmp(3), 3000);
     }
 
+    #[test]
+    fn test_advance_cycles_follows_cpu_frequency() {
+        let clint = Clint::new();
+        assert_eq!(clint.cpu_frequency(), DEFAULT_CPU_FREQUENCY);
+
+        // 100 MHz CPU, 10 MHz timebase: one tick per 10 cycles, remainder kept
+        assert_eq!(clint.advance_cycles(25), 2);
+        assert_eq!(clint.advance_cycles(5), 1);
+        assert_eq!(clint.mtime(), 3);
+
+        // One second of cycles is one second of mtime at any frequency
+        clint.set_cpu_frequency(3_000_000);
+        clint.advance_cycles(3_000_000);
+        assert_eq!(clint.mtime(), 3 + TIMEBASE_FREQUENCY);
+
+        clint.set_cpu_frequency(0);
+        assert_eq!(clint.cpu_frequency(), 3_000_000);
+    }
+
     #[test]
     fn test_is_timer_pending() {
         let clint = Clint::with_harts(2);
@@ -737,4 +841,29 @@ mod tests {
         // Out of bounds
         assert!(!clint.is_msip_pending(MAX_HARTS));
     }
+
+    #[test]
+    fn test_ipi_wakes_a_sleeping_hart() {
+        use std::sync::Arc;
+
+        let clint = Arc::new(Clint::with_harts(2));
+        assert_eq!(clint.wait_for_interrupt(1, Duration::from_millis(1)), None);
+
+        let sleeper = Arc::clone(&clint);
+        let handle =
+            std::thread::spawn(move || sleeper.wait_for_interrupt(1, Duration::from_secs(30)));
+        std::thread::sleep(Duration::from_millis(10));
+        // An IPI for another hart leaves it asleep
+        clint.store(MSIP_OFFSET, 4, 1);
+        clint.store(MSIP_OFFSET + 4, 4, 1);
+        let slept = handle.join().unwrap().unwrap();
+        assert!(slept < Duration::from_secs(30));
+
+        // Already pending: no sleep at all
+        assert!(
+            clint
+                .wait_for_interrupt(1, Duration::from_secs(30))
+                .is_some()
+        );
+    }
 }
@@ -0,0 +1,478 @@
+//! Flattened device tree (DTB) generator.
+//!
+//! Describes a [`BusConfig`] memory map to the guest: DRAM, harts, CLINT,
+//! PLIC, UART, the VirtIO MMIO slots, the test finisher, the sysinfo and
+//! build information pages, the semihosting device, the framebuffer, the
+//! input queue and the inter-VM shared memory device. Node names and `compatible` strings follow QEMU's `virt`
+//! board, so a guest that already knows that board finds its devices
+//! unchanged.
+//!
+//! The blob is served from the boot ROM (see [`crate::devices::bootrom`]),
+//! and its address is published in the boot mailbox. The kernel command
+//! line, if any, is in `/chosen/bootargs`.
+
+use std::collections::HashMap;
+
+use crate::bus::{BusConfig, VIRTIO_SLOTS, VIRTIO_STRIDE};
+use crate::devices::buildinfo::BUILDINFO_SIZE;
+use crate::devices::clint::{CLINT_SIZE, TIMEBASE_FREQUENCY};
+use crate::devices::framebuffer::{FB_HEIGHT, FB_STRIDE, FB_WIDTH, FRAMEBUFFER_SIZE};
+use crate::devices::input::INPUT_SIZE;
+use crate::devices::ivshmem::{IVSHMEM_WINDOW_MAX, IVSHMEM_WINDOW_OFFSET};
+use crate::devices::mailbox::MAILBOX_SIZE;
+use crate::devices::plic::{
+    INPUT_IRQ, IVSHMEM_IRQ, MAILBOX_IRQ, NUM_SOURCES, PLIC_SIZE, RTC_IRQ, UART_IRQ, VIRTIO0_IRQ,
+};
+use crate::devices::rtc::RTC_SIZE;
+use crate::devices::semihost::SEMIHOST_SIZE;
+use crate::devices::sysinfo::SYSINFO_SIZE;
+use crate::devices::test_finisher::{FINISHER_PASS, FINISHER_RESET, TEST_FINISHER_SIZE};
+use crate::devices::uart::UART_SIZE;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+/// One empty memory reservation entry terminates the list.
+const FDT_RSVMAP_SIZE: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// Supervisor and machine external interrupt causes.
+const IRQ_S_EXT: u32 = 9;
+const IRQ_M_EXT: u32 = 11;
+/// Machine software and timer interrupt causes.
+const IRQ_M_SOFT: u32 = 3;
+const IRQ_M_TIMER: u32 = 7;
+
+/// Low-level builder for the structure and strings blocks of a DTB.
+pub struct FdtWriter {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<&'static str, u32>,
+    depth: usize,
+}
+
+impl FdtWriter {
+    pub fn new() -> Self {
+        Self {
+            structure: Vec::new(),
+            strings: Vec::new(),
+            string_offsets: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    fn string_offset(&mut self, name: &'static str) -> u32 {
+        if let Some(&offset) = self.string_offsets.get(name) {
+            return offset;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(name, offset);
+        offset
+    }
+
+    /// Open a node; the root node has an empty name.
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "end_node without begin_node");
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    /// Property with a raw byte value.
+    pub fn prop(&mut self, name: &'static str, value: &[u8]) {
+        let nameoff = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(nameoff);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    /// Empty (boolean) property.
+    pub fn prop_null(&mut self, name: &'static str) {
+        self.prop(name, &[]);
+    }
+
+    pub fn prop_u32(&mut self, name: &'static str, value: u32) {
+        self.prop(name, &value.to_be_bytes());
+    }
+
+    /// Property made of 32-bit cells.
+    pub fn prop_cells(&mut self, name: &'static str, cells: &[u32]) {
+        let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.prop(name, &bytes);
+    }
+
+    /// `reg`-style property of 64-bit `(address, size)` pairs (2 cells each).
+    pub fn prop_reg(&mut self, name: &'static str, pairs: &[(u64, u64)]) {
+        let cells: Vec<u32> = pairs
+            .iter()
+            .flat_map(|&(addr, size)| {
+                [
+                    (addr >> 32) as u32,
+                    addr as u32,
+                    (size >> 32) as u32,
+                    size as u32,
+                ]
+            })
+            .collect();
+        self.prop_cells(name, &cells);
+    }
+
+    pub fn prop_str(&mut self, name: &'static str, value: &str) {
+        self.prop_strs(name, &[value]);
+    }
+
+    /// String-list property (e.g. `compatible`).
+    pub fn prop_strs(&mut self, name: &'static str, values: &[&str]) {
+        let mut bytes = Vec::new();
+        for value in values {
+            bytes.extend_from_slice(value.as_bytes());
+            bytes.push(0);
+        }
+        self.prop(name, &bytes);
+    }
+
+    /// Close the tree and assemble the blob.
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "unclosed device tree node");
+        self.push_u32(FDT_END);
+
+        let off_rsvmap = FDT_HEADER_SIZE;
+        let off_struct = off_rsvmap + FDT_RSVMAP_SIZE;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+
+        let mut blob = Vec::with_capacity(total);
+        for field in [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            off_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0, // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            blob.extend_from_slice(&field.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0; FDT_RSVMAP_SIZE]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+impl Default for FdtWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build the device tree for `config` with `num_harts` harts and the kernel
+/// command line `bootargs`, left out when empty.
+pub fn generate(config: &BusConfig, num_harts: usize, bootargs: &str) -> Vec<u8> {
+    // phandles: one interrupt controller per hart, then the PLIC and the
+    // test finisher
+    let cpu_intc = |hart: usize| hart as u32 + 1;
+    let plic_phandle = num_harts as u32 + 1;
+    let finisher_phandle = plic_phandle + 1;
+
+    let mut fdt = FdtWriter::new();
+    fdt.begin_node("");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "riscv-virtio");
+    fdt.prop_str("model", "riscv-vm,virt");
+
+    fdt.begin_node("chosen");
+    fdt.prop_str(
+        "stdout-path",
+        &format!("/soc/serial@{:x}", config.uart_base),
+    );
+    if !bootargs.is_empty() {
+        fdt.prop_str("bootargs", bootargs);
+    }
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", config.dram_base));
+    fdt.prop_str("device_type", "memory");
+    fdt.prop_reg("reg", &[(config.dram_base, config.dram_size as u64)]);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.prop_u32("#address-cells", 1);
+    fdt.prop_u32("#size-cells", 0);
+    fdt.prop_u32("timebase-frequency", TIMEBASE_FREQUENCY as u32);
+    for hart in 0..num_harts {
+        fdt.begin_node(&format!("cpu@{}", hart));
+        fdt.prop_str("device_type", "cpu");
+        fdt.prop_u32("reg", hart as u32);
+        fdt.prop_str("status", "okay");
+        fdt.prop_str("compatible", "riscv");
+        fdt.prop_str("riscv,isa", "rv64imafdc_zicsr_zifencei_zba_zbb_zbs");
+        fdt.prop_str("mmu-type", "riscv,sv39");
+        fdt.begin_node("interrupt-controller");
+        fdt.prop_u32("#interrupt-cells", 1);
+        fdt.prop_null("interrupt-controller");
+        fdt.prop_str("compatible", "riscv,cpu-intc");
+        fdt.prop_u32("phandle", cpu_intc(hart));
+        fdt.end_node();
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "simple-bus");
+    fdt.prop_null("ranges");
+
+    fdt.begin_node(&format!("test@{:x}", config.test_finisher_base));
+    fdt.prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+    fdt.prop_reg("reg", &[(config.test_finisher_base, TEST_FINISHER_SIZE)]);
+    fdt.prop_u32("phandle", finisher_phandle);
+    fdt.end_node();
+
+    // Linux powers off and reboots through the finisher's register
+    for (name, value) in [("poweroff", FINISHER_PASS), ("reboot", FINISHER_RESET)] {
+        fdt.begin_node(name);
+        fdt.prop_str("compatible", &format!("syscon-{}", name));
+        fdt.prop_u32("regmap", finisher_phandle);
+        fdt.prop_u32("offset", 0);
+        fdt.prop_u32("value", value as u32);
+        fdt.end_node();
+    }
+
+    fdt.begin_node(&format!("sysinfo@{:x}", config.sysinfo_base));
+    fdt.prop_str("compatible", "riscv-vm,sysinfo");
+    fdt.prop_reg("reg", &[(config.sysinfo_base, SYSINFO_SIZE)]);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("buildinfo@{:x}", config.buildinfo_base));
+    fdt.prop_str("compatible", "riscv-vm,buildinfo");
+    fdt.prop_reg("reg", &[(config.buildinfo_base, BUILDINFO_SIZE)]);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("semihost@{:x}", config.semihost_base));
+    fdt.prop_str("compatible", "riscv-vm,semihost");
+    fdt.prop_reg("reg", &[(config.semihost_base, SEMIHOST_SIZE)]);
+    fdt.end_node();
+
+    let clint_irqs: Vec<u32> = (0..num_harts)
+        .flat_map(|h| [cpu_intc(h), IRQ_M_SOFT, cpu_intc(h), IRQ_M_TIMER])
+        .collect();
+    fdt.begin_node(&format!("clint@{:x}", config.clint_base));
+    fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.prop_reg("reg", &[(config.clint_base, CLINT_SIZE)]);
+    fdt.prop_cells("interrupts-extended", &clint_irqs);
+    fdt.end_node();
+
+    let plic_irqs: Vec<u32> = (0..num_harts)
+        .flat_map(|h| [cpu_intc(h), IRQ_M_EXT, cpu_intc(h), IRQ_S_EXT])
+        .collect();
+    fdt.begin_node(&format!("plic@{:x}", config.plic_base));
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop_u32("#address-cells", 0);
+    fdt.prop_null("interrupt-controller");
+    fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    fdt.prop_reg("reg", &[(config.plic_base, PLIC_SIZE)]);
+    fdt.prop_cells("interrupts-extended", &plic_irqs);
+    // Every source, so lines injected with `SystemBus::raise_irq` are usable
+    fdt.prop_u32("riscv,ndev", NUM_SOURCES as u32 - 1);
+    fdt.prop_u32("phandle", plic_phandle);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("serial@{:x}", config.uart_base));
+    fdt.prop_str("compatible", "ns16550a");
+    fdt.prop_reg("reg", &[(config.uart_base, UART_SIZE)]);
+    fdt.prop_u32("clock-frequency", 3_686_400);
+    fdt.prop_u32("interrupt-parent", plic_phandle);
+    fdt.prop_u32("interrupts", UART_IRQ);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("input@{:x}", config.input_base));
+    fdt.prop_str("compatible", "riscv-vm,input");
+    fdt.prop_reg("reg", &[(config.input_base, INPUT_SIZE)]);
+    fdt.prop_u32("interrupt-parent", plic_phandle);
+    fdt.prop_u32("interrupts", INPUT_IRQ);
+    fdt.end_node();
+
+    // Registers, then the window at its largest; SIZE gives the mapped part
+    fdt.begin_node(&format!("shmem@{:x}", config.ivshmem_base));
+    fdt.prop_str("compatible", "riscv-vm,ivshmem");
+    fdt.prop_reg(
+        "reg",
+        &[
+            (config.ivshmem_base, IVSHMEM_WINDOW_OFFSET),
+            (
+                config.ivshmem_base + IVSHMEM_WINDOW_OFFSET,
+                IVSHMEM_WINDOW_MAX as u64,
+            ),
+        ],
+    );
+    fdt.prop_strs("reg-names", &["registers", "window"]);
+    fdt.prop_u32("interrupt-parent", plic_phandle);
+    fdt.prop_u32("interrupts", IVSHMEM_IRQ);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("mailbox@{:x}", config.mailbox_base));
+    fdt.prop_str("compatible", "riscv-vm,mailbox");
+    fdt.prop_reg("reg", &[(config.mailbox_base, MAILBOX_SIZE)]);
+    fdt.prop_u32("interrupt-parent", plic_phandle);
+    fdt.prop_u32("interrupts", MAILBOX_IRQ);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("rtc@{:x}", config.rtc_base));
+    fdt.prop_str("compatible", "google,goldfish-rtc");
+    fdt.prop_reg("reg", &[(config.rtc_base, RTC_SIZE)]);
+    fdt.prop_u32("interrupt-parent", plic_phandle);
+    fdt.prop_u32("interrupts", RTC_IRQ);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("framebuffer@{:x}", config.framebuffer_base));
+    fdt.prop_str("compatible", "simple-framebuffer");
+    fdt.prop_reg("reg", &[(config.framebuffer_base, FRAMEBUFFER_SIZE)]);
+    fdt.prop_u32("width", FB_WIDTH);
+    fdt.prop_u32("height", FB_HEIGHT);
+    fdt.prop_u32("stride", FB_STRIDE);
+    fdt.prop_str("format", "x8r8g8b8");
+    fdt.end_node();
+
+    for slot in 0..VIRTIO_SLOTS {
+        let base = config.virtio_base + slot * VIRTIO_STRIDE;
+        fdt.begin_node(&format!("virtio_mmio@{:x}", base));
+        fdt.prop_str("compatible", "virtio,mmio");
+        fdt.prop_reg("reg", &[(base, VIRTIO_STRIDE)]);
+        fdt.prop_u32("interrupt-parent", plic_phandle);
+        fdt.prop_u32("interrupts", VIRTIO0_IRQ + slot as u32);
+        fdt.end_node();
+    }
+
+    fdt.end_node(); // soc
+    fdt.end_node(); // root
+    fdt.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(blob: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Find the first `reg` property of the node whose name starts with `node`.
+    fn find_reg(blob: &[u8], node: &str) -> Option<Vec<u32>> {
+        let value = find_prop(blob, node, "reg")?;
+        Some(value.chunks(4).map(|c| be32(c, 0)).collect())
+    }
+
+    /// Find property `prop` of the first node whose name starts with `node`.
+    fn find_prop<'a>(blob: &'a [u8], node: &str, prop: &str) -> Option<&'a [u8]> {
+        let off_struct = be32(blob, 8) as usize;
+        let off_strings = be32(blob, 12) as usize;
+        let mut pos = off_struct;
+        let mut in_node = false;
+        loop {
+            let token = be32(blob, pos);
+            pos += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let end = pos + blob[pos..].iter().position(|&b| b == 0).unwrap();
+                    in_node = std::str::from_utf8(&blob[pos..end])
+                        .unwrap()
+                        .starts_with(node);
+                    pos = (end + 4) & !3;
+                }
+                FDT_PROP => {
+                    let len = be32(blob, pos) as usize;
+                    let nameoff = be32(blob, pos + 4) as usize;
+                    let value = &blob[pos + 8..pos + 8 + len];
+                    pos = (pos + 8 + len + 3) & !3;
+                    let name_start = off_strings + nameoff;
+                    let name_end = name_start + blob[name_start..].iter().position(|&b| b == 0)?;
+                    if in_node && &blob[name_start..name_end] == prop.as_bytes() {
+                        return Some(value);
+                    }
+                }
+                FDT_END_NODE => in_node = false,
+                _ => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_header_layout() {
+        let blob = generate(&BusConfig::default(), 2, "");
+        assert_eq!(be32(&blob, 0), FDT_MAGIC);
+        assert_eq!(be32(&blob, 4) as usize, blob.len());
+        assert_eq!(be32(&blob, 20), FDT_VERSION);
+        let off_struct = be32(&blob, 8) as usize;
+        let size_struct = be32(&blob, 36) as usize;
+        assert_eq!(be32(&blob, off_struct), FDT_BEGIN_NODE);
+        assert_eq!(be32(&blob, off_struct + size_struct - 4), FDT_END);
+    }
+
+    #[test]
+    fn test_memory_map_is_reflected() {
+        let config = BusConfig {
+            dram_base: 0x4000_0000,
+            dram_size: 3 << 30,
+            uart_base: 0x0900_0000,
+            ..BusConfig::default()
+        };
+        let blob = generate(&config, 1, "");
+        assert_eq!(
+            find_reg(&blob, "memory@40000000"),
+            Some(vec![0, 0x4000_0000, 0, 0xc000_0000])
+        );
+        assert_eq!(
+            find_reg(&blob, "serial@9000000"),
+            Some(vec![0, 0x0900_0000, 0, UART_SIZE as u32])
+        );
+        assert_eq!(
+            find_reg(&blob, "shmem@150000"),
+            Some(vec![0, 0x15_0000, 0, 0x1000, 0, 0x15_1000, 0, 0x10_0000])
+        );
+        assert!(find_reg(&blob, "cpu@1").is_none());
+    }
+
+    #[test]
+    fn test_bootargs_are_in_chosen() {
+        let blob = generate(&BusConfig::default(), 1, "plain quiet");
+        assert_eq!(
+            find_prop(&blob, "chosen", "bootargs"),
+            Some(&b"plain quiet\0"[..])
+        );
+        let blob = generate(&BusConfig::default(), 1, "");
+        assert!(find_prop(&blob, "chosen", "bootargs").is_none());
+        assert!(find_prop(&blob, "chosen", "stdout-path").is_some());
+    }
+}
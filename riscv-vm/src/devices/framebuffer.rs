@@ -0,0 +1,200 @@
+//! Linear Framebuffer Device
+//!
+//! A fixed-geometry, memory-mapped pixel buffer. The guest draws by storing
+//! to it like ordinary memory; the host reads the pixels back (e.g. to blit
+//! them onto a browser canvas) and asks which rows changed since last time.
+//!
+//! ## Layout
+//!
+//! `FB_WIDTH` x `FB_HEIGHT` pixels, row-major, `FB_STRIDE` bytes per row.
+//! Each pixel is a little-endian `u32` in x8r8g8b8 format (`0x00RRGGBB`), so
+//! bytes in memory are B, G, R, X. The guest learns the geometry from the
+//! `simple-framebuffer` node in the device tree.
+//!
+//! ## Dirty tracking
+//!
+//! Every store marks the rows it touches. [`Framebuffer::take_dirty_rects`]
+//! returns the changed rows merged into full-width rectangles and clears the
+//! marks, so a frontend only has to upload what changed.
+
+use crate::dram::MemoryError;
+use std::sync::Mutex;
+
+/// Base address of the framebuffer
+pub const FRAMEBUFFER_BASE: u64 = 0x5000_0000;
+/// Width in pixels
+pub const FB_WIDTH: u32 = 640;
+/// Height in pixels
+pub const FB_HEIGHT: u32 = 480;
+/// Bytes per pixel
+pub const FB_BPP: u32 = 4;
+/// Bytes per row
+pub const FB_STRIDE: u32 = FB_WIDTH * FB_BPP;
+/// Size of the framebuffer MMIO region
+pub const FRAMEBUFFER_SIZE: u64 = FB_STRIDE as u64 * FB_HEIGHT as u64;
+
+/// A changed region of the framebuffer, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+struct FbState {
+    pixels: Vec<u8>,
+    /// One flag per row, set by stores and cleared by `take_dirty_rects`
+    dirty: Vec<bool>,
+}
+
+pub struct Framebuffer {
+    state: Mutex<FbState>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(FbState {
+                pixels: vec![0; FRAMEBUFFER_SIZE as usize],
+                dirty: vec![false; FB_HEIGHT as usize],
+            }),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        FB_WIDTH
+    }
+
+    pub fn height(&self) -> u32 {
+        FB_HEIGHT
+    }
+
+    /// Load `size` bytes (little-endian) from `offset`.
+    pub fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        let state = self.state.lock().unwrap();
+        let bytes = Self::range(&state.pixels, offset, size)?;
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Store the low `size` bytes of `value` (little-endian) at `offset`.
+    pub fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        Self::range(&state.pixels, offset, size)?;
+        let start = offset as usize;
+        let end = start + size as usize;
+        state.pixels[start..end].copy_from_slice(&value.to_le_bytes()[..size as usize]);
+
+        let first_row = start / FB_STRIDE as usize;
+        let last_row = (end - 1) / FB_STRIDE as usize;
+        state.dirty[first_row..=last_row].fill(true);
+        Ok(())
+    }
+
+    fn range(pixels: &[u8], offset: u64, size: u64) -> Result<&[u8], MemoryError> {
+        if size > 8 {
+            return Err(MemoryError::InvalidAlignment(offset));
+        }
+        let start = offset as usize;
+        pixels
+            .get(start..start + size as usize)
+            .ok_or(MemoryError::OutOfBounds(offset))
+    }
+
+    /// Raw pixel bytes in the guest's x8r8g8b8 layout.
+    pub fn pixels(&self) -> Vec<u8> {
+        self.state.lock().unwrap().pixels.clone()
+    }
+
+    /// Pixels converted to RGBA with opaque alpha, the layout of a canvas
+    /// `ImageData`.
+    pub fn rgba(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        let mut out = Vec::with_capacity(state.pixels.len());
+        for px in state.pixels.chunks_exact(FB_BPP as usize) {
+            out.extend_from_slice(&[px[2], px[1], px[0], 0xff]);
+        }
+        out
+    }
+
+    /// Rows changed since the last call, merged into full-width rectangles.
+    pub fn take_dirty_rects(&self) -> Vec<DirtyRect> {
+        let mut state = self.state.lock().unwrap();
+        let mut rects: Vec<DirtyRect> = Vec::new();
+        for (y, dirty) in state.dirty.iter_mut().enumerate() {
+            if !std::mem::take(dirty) {
+                continue;
+            }
+            let y = y as u32;
+            match rects.last_mut() {
+                Some(last) if last.y + last.height == y => last.height += 1,
+                _ => rects.push(DirtyRect {
+                    x: 0,
+                    y,
+                    width: FB_WIDTH,
+                    height: 1,
+                }),
+            }
+        }
+        rects
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stores_mark_rows_dirty() {
+        let fb = Framebuffer::new();
+        assert!(fb.take_dirty_rects().is_empty());
+
+        // Pixel (3, 10), row 11, a store straddling rows 11-12, then row 40
+        fb.store(10 * FB_STRIDE as u64 + 12, 4, 0x00ff_8000)
+            .unwrap();
+        fb.store(11 * FB_STRIDE as u64, 8, u64::MAX).unwrap();
+        fb.store(12 * FB_STRIDE as u64 - 4, 8, u64::MAX).unwrap();
+        fb.store(40 * FB_STRIDE as u64, 1, 1).unwrap();
+
+        let rects = fb.take_dirty_rects();
+        assert_eq!(
+            rects,
+            vec![
+                DirtyRect {
+                    x: 0,
+                    y: 10,
+                    width: FB_WIDTH,
+                    height: 3
+                },
+                DirtyRect {
+                    x: 0,
+                    y: 40,
+                    width: FB_WIDTH,
+                    height: 1
+                },
+            ]
+        );
+        assert!(fb.take_dirty_rects().is_empty());
+
+        assert_eq!(fb.load(10 * FB_STRIDE as u64 + 12, 4).unwrap(), 0x00ff_8000);
+        let rgba = fb.rgba();
+        let px = (10 * FB_WIDTH as usize + 3) * 4;
+        assert_eq!(&rgba[px..px + 4], &[0xff, 0x80, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_access_past_end_is_rejected() {
+        let fb = Framebuffer::new();
+        assert!(fb.store(FRAMEBUFFER_SIZE - 4, 8, 0).is_err());
+        assert!(fb.load(FRAMEBUFFER_SIZE, 1).is_err());
+        assert!(fb.load(FRAMEBUFFER_SIZE - 8, 8).is_ok());
+    }
+}
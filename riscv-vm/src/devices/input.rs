@@ -0,0 +1,155 @@
+//! Keyboard/Mouse Input Queue Device
+//!
+//! The host pushes keyboard and mouse events (e.g. from browser DOM events);
+//! the guest drains them through two registers. The PLIC line `INPUT_IRQ`
+//! is held high while the queue is non-empty.
+//!
+//! ## Register Layout
+//!
+//! | Offset | Name  | Access | Description                                   |
+//! |--------|-------|--------|-----------------------------------------------|
+//! | 0x00   | COUNT | R      | Number of queued events (32 bits)             |
+//! | 0x08   | EVENT | R      | Pop the oldest event (64-bit load), 0 if none |
+//!
+//! ## Event Encoding
+//!
+//! | Bits  | Field                                               |
+//! |-------|-----------------------------------------------------|
+//! | 0-7   | Kind: 1 = key, 2 = mouse move, 3 = mouse button     |
+//! | 8     | Pressed (key and mouse button)                      |
+//! | 16-31 | Key code or button number                           |
+//! | 32-47 | Mouse X (mouse move)                                |
+//! | 48-63 | Mouse Y (mouse move)                                |
+//!
+//! Key codes are passed through from the host untouched; the browser
+//! frontend uses `KeyboardEvent.keyCode`.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Base address for the input queue device
+pub const INPUT_BASE: u64 = 0x0012_0000;
+/// Size of the input queue MMIO region
+pub const INPUT_SIZE: u64 = 0x1000;
+
+/// Events beyond this are dropped until the guest catches up
+pub const INPUT_QUEUE_CAPACITY: usize = 256;
+
+const COUNT: u64 = 0x00;
+const EVENT: u64 = 0x08;
+
+/// A host input event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key { code: u16, pressed: bool },
+    MouseMove { x: u16, y: u16 },
+    MouseButton { button: u16, pressed: bool },
+}
+
+impl InputEvent {
+    /// Encode as the value returned by the EVENT register.
+    pub fn encode(self) -> u64 {
+        match self {
+            InputEvent::Key { code, pressed } => 1 | (pressed as u64) << 8 | (code as u64) << 16,
+            InputEvent::MouseMove { x, y } => 2 | (x as u64) << 32 | (y as u64) << 48,
+            InputEvent::MouseButton { button, pressed } => {
+                3 | (pressed as u64) << 8 | (button as u64) << 16
+            }
+        }
+    }
+}
+
+pub struct InputQueue {
+    events: Mutex<VecDeque<u64>>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queue an event. Returns false if the queue is full and it was dropped.
+    pub fn push(&self, event: InputEvent) -> bool {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= INPUT_QUEUE_CAPACITY {
+            return false;
+        }
+        events.push_back(event.encode());
+        true
+    }
+
+    /// Number of events waiting for the guest
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the IRQ line is asserted
+    pub fn is_interrupting(&self) -> bool {
+        !self.is_empty()
+    }
+
+    /// Load from register
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        match (offset, size) {
+            (COUNT, 4) | (COUNT, 8) => self.len() as u64,
+            (EVENT, 8) => self.events.lock().unwrap().pop_front().unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_pop_in_order() {
+        let input = InputQueue::new();
+        assert_eq!(input.load(EVENT, 8), 0);
+
+        input.push(InputEvent::Key {
+            code: 65,
+            pressed: true,
+        });
+        input.push(InputEvent::MouseMove { x: 320, y: 240 });
+        input.push(InputEvent::MouseButton {
+            button: 0,
+            pressed: false,
+        });
+        assert!(input.is_interrupting());
+        assert_eq!(input.load(COUNT, 4), 3);
+
+        // Narrow reads don't consume an event
+        assert_eq!(input.load(EVENT, 4), 0);
+        assert_eq!(input.load(EVENT, 8), 0x0041_0101);
+        assert_eq!(input.load(EVENT, 8), 0x00f0_0140_0000_0002);
+        assert_eq!(input.load(EVENT, 8), 3);
+        assert!(!input.is_interrupting());
+    }
+
+    #[test]
+    fn test_full_queue_drops_events() {
+        let input = InputQueue::new();
+        let key = InputEvent::Key {
+            code: 1,
+            pressed: true,
+        };
+        for _ in 0..INPUT_QUEUE_CAPACITY {
+            assert!(input.push(key));
+        }
+        assert!(!input.push(key));
+        assert_eq!(input.len(), INPUT_QUEUE_CAPACITY);
+    }
+}
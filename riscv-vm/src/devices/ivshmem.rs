@@ -0,0 +1,214 @@
+//! Inter-VM Shared Memory Device
+//!
+//! An ivshmem-like device: VMs attached to the same
+//! [`SharedWindow`] see the same memory through the window region of this
+//! device, and can interrupt each other through doorbells. Two VMs in one
+//! process share a window by cloning it; VMs on one browser page share a
+//! SharedArrayBuffer (see [`crate::shared_mem`]).
+//!
+//! ## Register Layout
+//!
+//! | Offset | Name     | Access | Description                                    |
+//! |--------|----------|--------|------------------------------------------------|
+//! | 0x00   | MAGIC    | R      | `"RVSM"` when a window is attached, 0 otherwise |
+//! | 0x04   | PEER_ID  | R      | This VM's peer number                          |
+//! | 0x08   | SIZE     | R      | Window size in bytes                           |
+//! | 0x0c   | PEERS    | R      | Mask of attached peers (bit per peer number)   |
+//! | 0x10   | DOORBELL | W      | Writing peer number `n` rings peer `n`         |
+//! | 0x14   | PENDING  | R      | Mask of peers that rang this VM                |
+//! | 0x18   | ACK      | W      | Write 1s to clear bits in PENDING              |
+//! | 0x1c   | IRQ_MASK | RW     | PENDING bits that raise `IVSHMEM_IRQ` (reset 0) |
+//!
+//! The window itself is mapped at `IVSHMEM_WINDOW_OFFSET`. What goes in it
+//! is up to the guests; the usual layout is one single-producer ring per
+//! direction, each with its head and tail in an aligned 32-bit word (those
+//! accesses are atomic between peers), and a doorbell after every batch of
+//! updates. The PLIC line stays high while an unmasked doorbell is pending.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::shared_mem::SharedWindow;
+
+/// Base address for the shared memory device
+pub const IVSHMEM_BASE: u64 = 0x0015_0000;
+/// Offset of the shared window from the device base
+pub const IVSHMEM_WINDOW_OFFSET: u64 = 0x1000;
+/// Largest window the device maps
+pub const IVSHMEM_WINDOW_MAX: usize = 0x10_0000;
+/// Size of the MMIO region: registers, then the largest window
+pub const IVSHMEM_SIZE: u64 = IVSHMEM_WINDOW_OFFSET + IVSHMEM_WINDOW_MAX as u64;
+
+const MAGIC: u64 = 0x00;
+const PEER_ID: u64 = 0x04;
+const SIZE: u64 = 0x08;
+const PEERS: u64 = 0x0c;
+const DOORBELL: u64 = 0x10;
+const PENDING: u64 = 0x14;
+const ACK: u64 = 0x18;
+const IRQ_MASK: u64 = 0x1c;
+
+const MAGIC_VALUE: u32 = u32::from_le_bytes(*b"RVSM");
+
+struct Attachment {
+    window: SharedWindow,
+    peer: u32,
+}
+
+pub struct IvShmem {
+    attachment: Mutex<Option<Attachment>>,
+    irq_mask: AtomicU32,
+}
+
+impl IvShmem {
+    pub fn new() -> Self {
+        Self {
+            attachment: Mutex::new(None),
+            irq_mask: AtomicU32::new(0),
+        }
+    }
+
+    /// Attach to `window` as peer number `peer`, replacing any earlier window.
+    pub fn attach(&self, window: SharedWindow, peer: u32) -> Result<(), String> {
+        if window.size() > IVSHMEM_WINDOW_MAX {
+            return Err(format!(
+                "shared window of {} bytes exceeds the {} byte maximum",
+                window.size(),
+                IVSHMEM_WINDOW_MAX
+            ));
+        }
+        window.attach(peer)?;
+        self.detach();
+        *self.attachment.lock().unwrap() = Some(Attachment { window, peer });
+        Ok(())
+    }
+
+    /// Leave the current window, if any.
+    pub fn detach(&self) {
+        if let Some(old) = self.attachment.lock().unwrap().take() {
+            old.window.detach(old.peer);
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attachment.lock().unwrap().is_some()
+    }
+
+    /// Whether the IRQ line is asserted
+    pub fn is_interrupting(&self) -> bool {
+        let mask = self.irq_mask.load(Ordering::Relaxed);
+        if mask == 0 {
+            return false;
+        }
+        match &*self.attachment.lock().unwrap() {
+            Some(a) => a.window.pending(a.peer) & mask != 0,
+            None => false,
+        }
+    }
+
+    /// Load from a register or the window
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        let guard = self.attachment.lock().unwrap();
+        let Some(a) = guard.as_ref() else {
+            return 0;
+        };
+        if offset >= IVSHMEM_WINDOW_OFFSET {
+            return a
+                .window
+                .read((offset - IVSHMEM_WINDOW_OFFSET) as usize, size as usize);
+        }
+        let val = match offset & !3 {
+            MAGIC => MAGIC_VALUE,
+            PEER_ID => a.peer,
+            SIZE => a.window.size() as u32,
+            PEERS => a.window.peers(),
+            PENDING => a.window.pending(a.peer),
+            IRQ_MASK => self.irq_mask.load(Ordering::Relaxed),
+            _ => 0,
+        };
+        (val >> (8 * (offset & 3))) as u64
+    }
+
+    /// Store to a register or the window
+    pub fn store(&self, offset: u64, size: u64, val: u64) {
+        if offset == IRQ_MASK {
+            self.irq_mask.store(val as u32, Ordering::Relaxed);
+            return;
+        }
+        let guard = self.attachment.lock().unwrap();
+        let Some(a) = guard.as_ref() else {
+            return;
+        };
+        if offset >= IVSHMEM_WINDOW_OFFSET {
+            a.window.write(
+                (offset - IVSHMEM_WINDOW_OFFSET) as usize,
+                size as usize,
+                val,
+            );
+            return;
+        }
+        match offset {
+            DOORBELL => a.window.ring(val as u32, a.peer),
+            ACK => a.window.ack(a.peer, val as u32),
+            _ => {}
+        }
+    }
+}
+
+impl Default for IvShmem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IvShmem {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unattached_device_reads_zero() {
+        let dev = IvShmem::new();
+        dev.store(IVSHMEM_WINDOW_OFFSET, 4, 1);
+        assert_eq!(dev.load(MAGIC, 4), 0);
+        assert_eq!(dev.load(IVSHMEM_WINDOW_OFFSET, 4), 0);
+        assert!(!dev.is_interrupting());
+    }
+
+    #[test]
+    fn test_two_peers_exchange_data() {
+        let window = SharedWindow::new(4096);
+        let a = IvShmem::new();
+        let b = IvShmem::new();
+        a.attach(window.clone(), 0).unwrap();
+        b.attach(window.clone(), 1).unwrap();
+        assert!(IvShmem::new().attach(window.clone(), 1).is_err());
+
+        assert_eq!(a.load(MAGIC, 4), MAGIC_VALUE as u64);
+        assert_eq!(b.load(PEER_ID, 4), 1);
+        assert_eq!(b.load(SIZE, 4), 4096);
+        assert_eq!(a.load(PEERS, 4), 0b11);
+
+        // A writes a message and rings B
+        a.store(IVSHMEM_WINDOW_OFFSET + 8, 8, 0x1122_3344_5566_7788);
+        b.store(IRQ_MASK, 4, 0b1);
+        assert!(!b.is_interrupting());
+        a.store(DOORBELL, 4, 1);
+        assert!(b.is_interrupting());
+        assert!(!a.is_interrupting());
+        assert_eq!(b.load(PENDING, 4), 0b1);
+        assert_eq!(b.load(IVSHMEM_WINDOW_OFFSET + 8, 8), 0x1122_3344_5566_7788);
+
+        b.store(ACK, 4, 0b1);
+        assert!(!b.is_interrupting());
+
+        // Dropping a peer frees its slot
+        drop(b);
+        assert_eq!(a.load(PEERS, 4), 0b1);
+    }
+}
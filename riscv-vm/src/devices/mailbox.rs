@@ -0,0 +1,304 @@
+//! Guest/Host Message Mailbox
+//!
+//! Carries whole byte messages between the guest and the embedding
+//! application, e.g. a page that wants structured data rather than scraping
+//! the serial console. The host end is a [`MailboxPort`]; `WasmVm` exposes
+//! it as `post_message` / `on_guest_message` and `NativeVm` hands it out
+//! with `mailbox()`.
+//!
+//! ## Register Layout
+//!
+//! | Offset | Name     | Access | Description                                       |
+//! |--------|----------|--------|---------------------------------------------------|
+//! | 0x00   | MAGIC    | R      | `"RVMB"`                                          |
+//! | 0x04   | MAX_LEN  | R      | Largest message in bytes                          |
+//! | 0x08   | STATUS   | R      | Bit 0: a message is in RX; bit 1: host queue full |
+//! | 0x0c   | RX_LEN   | R      | Length of the message in RX, 0 if none            |
+//! | 0x10   | RX_POP   | W      | Drop the message in RX; the next one moves in     |
+//! | 0x14   | TX_SEND  | W      | Send the first `n` bytes of TX to the host        |
+//! | 0x18   | IRQ_MASK | RW     | Bit 0: interrupt while a message is in RX         |
+//! | 0x1c   | DROPPED  | R      | Messages sent while the host queue was full       |
+//!
+//! RX, at `MAILBOX_RX_OFFSET`, holds the oldest message from the host,
+//! read-only. TX, at `MAILBOX_TX_OFFSET`, is where the guest builds its next
+//! message before writing its length to TX_SEND. Both are `MAX_LEN` bytes.
+//!
+//! Each direction queues up to [`MAILBOX_QUEUE_CAPACITY`] messages: the
+//! host's [`MailboxPort::post`] fails when the guest is that far behind,
+//! and a guest send is dropped (and counted in DROPPED) when the host is.
+//! Queued messages survive a reboot.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::devices::mmio::MmioDevice;
+use crate::devices::plic::MAILBOX_IRQ;
+use crate::dram::MemoryError;
+
+/// Base address for the mailbox device
+pub const MAILBOX_BASE: u64 = 0x0030_0000;
+/// Offset of the RX buffer from the device base
+pub const MAILBOX_RX_OFFSET: u64 = 0x1000;
+/// Offset of the TX buffer from the device base
+pub const MAILBOX_TX_OFFSET: u64 = 0x2000;
+/// Largest message in either direction
+pub const MAILBOX_MAX_LEN: usize = 0x1000;
+/// Size of the MMIO region: registers, RX, TX
+pub const MAILBOX_SIZE: u64 = MAILBOX_TX_OFFSET + MAILBOX_MAX_LEN as u64;
+
+/// Messages each direction holds before more are refused
+pub const MAILBOX_QUEUE_CAPACITY: usize = 64;
+
+const MAGIC: u64 = 0x00;
+const MAX_LEN: u64 = 0x04;
+const STATUS: u64 = 0x08;
+const RX_LEN: u64 = 0x0c;
+const RX_POP: u64 = 0x10;
+const TX_SEND: u64 = 0x14;
+const IRQ_MASK: u64 = 0x18;
+const DROPPED: u64 = 0x1c;
+
+const MAGIC_VALUE: u32 = u32::from_le_bytes(*b"RVMB");
+
+const STATUS_RX_READY: u64 = 1 << 0;
+const STATUS_TX_FULL: u64 = 1 << 1;
+
+#[derive(Default)]
+struct Queues {
+    /// Host messages; the front one is in RX
+    to_guest: VecDeque<Vec<u8>>,
+    /// Guest messages not yet taken by the host
+    to_host: VecDeque<Vec<u8>>,
+    /// Guest sends refused because `to_host` was full
+    dropped: u32,
+}
+
+/// Host end of the mailbox. Clones share the same queues.
+#[derive(Clone, Default)]
+pub struct MailboxPort {
+    queues: Arc<Mutex<Queues>>,
+}
+
+impl MailboxPort {
+    /// Queue `message` for the guest.
+    pub fn post(&self, message: &[u8]) -> Result<(), String> {
+        if message.len() > MAILBOX_MAX_LEN {
+            return Err(format!(
+                "message of {} bytes exceeds the {} byte maximum",
+                message.len(),
+                MAILBOX_MAX_LEN
+            ));
+        }
+        let mut queues = self.queues.lock().unwrap();
+        if queues.to_guest.len() >= MAILBOX_QUEUE_CAPACITY {
+            return Err("the guest is not reading its messages".to_string());
+        }
+        queues.to_guest.push_back(message.to_vec());
+        Ok(())
+    }
+
+    /// Take the oldest message the guest sent, if any.
+    pub fn take(&self) -> Option<Vec<u8>> {
+        self.queues.lock().unwrap().to_host.pop_front()
+    }
+
+    /// Number of host messages the guest has not consumed yet.
+    pub fn pending(&self) -> usize {
+        self.queues.lock().unwrap().to_guest.len()
+    }
+}
+
+pub struct Mailbox {
+    port: MailboxPort,
+    /// The message the guest is building
+    tx: Mutex<Vec<u8>>,
+    irq_mask: AtomicU32,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self {
+            port: MailboxPort::default(),
+            tx: Mutex::new(vec![0; MAILBOX_MAX_LEN]),
+            irq_mask: AtomicU32::new(0),
+        }
+    }
+
+    /// The host end of the mailbox.
+    pub fn port(&self) -> MailboxPort {
+        self.port.clone()
+    }
+
+    /// Whether the IRQ line is asserted
+    pub fn is_interrupting(&self) -> bool {
+        self.irq_mask.load(Ordering::Relaxed) & 1 != 0 && self.port.pending() > 0
+    }
+
+    /// Back to the power-on state for a machine reset. Queued messages are
+    /// kept.
+    pub fn reset(&self) {
+        self.irq_mask.store(0, Ordering::Relaxed);
+        self.tx.lock().unwrap().fill(0);
+    }
+
+    /// Load from a register or a buffer
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        if offset >= MAILBOX_TX_OFFSET {
+            let tx = self.tx.lock().unwrap();
+            return read_le(&tx, (offset - MAILBOX_TX_OFFSET) as usize, size as usize);
+        }
+        let queues = self.port.queues.lock().unwrap();
+        if offset >= MAILBOX_RX_OFFSET {
+            let Some(message) = queues.to_guest.front() else {
+                return 0;
+            };
+            return read_le(
+                message,
+                (offset - MAILBOX_RX_OFFSET) as usize,
+                size as usize,
+            );
+        }
+        let val = match offset & !3 {
+            MAGIC => MAGIC_VALUE as u64,
+            MAX_LEN => MAILBOX_MAX_LEN as u64,
+            STATUS => {
+                let mut status = 0;
+                if !queues.to_guest.is_empty() {
+                    status |= STATUS_RX_READY;
+                }
+                if queues.to_host.len() >= MAILBOX_QUEUE_CAPACITY {
+                    status |= STATUS_TX_FULL;
+                }
+                status
+            }
+            RX_LEN => queues.to_guest.front().map_or(0, |m| m.len() as u64),
+            IRQ_MASK => self.irq_mask.load(Ordering::Relaxed) as u64,
+            DROPPED => queues.dropped as u64,
+            _ => 0,
+        };
+        val >> ((offset & 3) * 8)
+    }
+
+    /// Store to a register or the TX buffer
+    pub fn store(&self, offset: u64, size: u64, value: u64) {
+        if offset >= MAILBOX_TX_OFFSET {
+            let mut tx = self.tx.lock().unwrap();
+            let start = (offset - MAILBOX_TX_OFFSET) as usize;
+            let end = (start + size as usize).min(tx.len());
+            tx[start..end].copy_from_slice(&value.to_le_bytes()[..end - start]);
+            return;
+        }
+        match offset {
+            RX_POP => {
+                self.port.queues.lock().unwrap().to_guest.pop_front();
+            }
+            TX_SEND => {
+                let len = (value as u32 as usize).min(MAILBOX_MAX_LEN);
+                let message = self.tx.lock().unwrap()[..len].to_vec();
+                let mut queues = self.port.queues.lock().unwrap();
+                if queues.to_host.len() >= MAILBOX_QUEUE_CAPACITY {
+                    queues.dropped = queues.dropped.wrapping_add(1);
+                } else {
+                    queues.to_host.push_back(message);
+                }
+            }
+            IRQ_MASK => self.irq_mask.store(value as u32, Ordering::Relaxed),
+            _ => {}
+        }
+    }
+}
+
+impl MmioDevice for Mailbox {
+    fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        Ok(Mailbox::load(self, offset, size))
+    }
+
+    fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        Mailbox::store(self, offset, size, value);
+        Ok(())
+    }
+
+    fn reset(&self) {
+        Mailbox::reset(self)
+    }
+
+    fn irq(&self) -> Option<u32> {
+        Some(MAILBOX_IRQ)
+    }
+
+    fn is_interrupting(&self) -> bool {
+        Mailbox::is_interrupting(self)
+    }
+}
+
+impl Default for Mailbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Little-endian value of `size` bytes at `offset` in `buf`, zero past
+/// its end.
+fn read_le(buf: &[u8], offset: usize, size: usize) -> u64 {
+    let mut bytes = [0; 8];
+    let end = (offset + size).min(buf.len());
+    if offset < end {
+        bytes[..end - offset].copy_from_slice(&buf[offset..end]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_both_ways() {
+        let mailbox = Mailbox::new();
+        let port = mailbox.port();
+        assert_eq!(mailbox.load(MAGIC, 4), MAGIC_VALUE as u64);
+        assert_eq!(mailbox.load(STATUS, 4), 0);
+
+        port.post(b"hello").unwrap();
+        port.post(b"").unwrap();
+        // Masked until the guest opts in
+        assert!(!mailbox.is_interrupting());
+        mailbox.store(IRQ_MASK, 4, 1);
+        assert!(mailbox.is_interrupting());
+
+        assert_eq!(mailbox.load(STATUS, 4), STATUS_RX_READY);
+        assert_eq!(mailbox.load(RX_LEN, 4), 5);
+        assert_eq!(mailbox.load(MAILBOX_RX_OFFSET, 4), 0x6c6c_6568);
+        assert_eq!(mailbox.load(MAILBOX_RX_OFFSET + 4, 8), b'o' as u64);
+        mailbox.store(RX_POP, 4, 1);
+        // An empty message is still a message
+        assert_eq!(mailbox.load(STATUS, 4), STATUS_RX_READY);
+        assert_eq!(mailbox.load(RX_LEN, 4), 0);
+        mailbox.store(RX_POP, 4, 1);
+        assert!(!mailbox.is_interrupting());
+
+        mailbox.store(MAILBOX_TX_OFFSET, 4, u32::from_le_bytes(*b"ping") as u64);
+        mailbox.store(MAILBOX_TX_OFFSET + 4, 1, b'!' as u64);
+        mailbox.store(TX_SEND, 4, 5);
+        assert_eq!(port.take().as_deref(), Some(&b"ping!"[..]));
+        assert_eq!(port.take(), None);
+    }
+
+    #[test]
+    fn test_full_queues() {
+        let mailbox = Mailbox::new();
+        let port = mailbox.port();
+        assert!(port.post(&[0; MAILBOX_MAX_LEN + 1]).is_err());
+        for _ in 0..MAILBOX_QUEUE_CAPACITY {
+            port.post(b"x").unwrap();
+            mailbox.store(TX_SEND, 4, 1);
+        }
+        assert!(port.post(b"x").is_err());
+        assert_eq!(mailbox.load(STATUS, 4), STATUS_RX_READY | STATUS_TX_FULL);
+        mailbox.store(TX_SEND, 4, 1);
+        assert_eq!(mailbox.load(DROPPED, 4), 1);
+        assert!(port.take().is_some());
+        assert_eq!(mailbox.load(STATUS, 4), STATUS_RX_READY);
+    }
+}
@@ -0,0 +1,96 @@
+//! Custom MMIO Peripherals
+//!
+//! Embedders can map their own devices into the physical address space with
+//! [`SystemBus::register_device`](crate::bus::SystemBus::register_device)
+//! instead of patching the bus. A registered device gets:
+//!
+//! - every guest load and store that falls in its range, with the offset
+//!   from the range start and the access size (1, 2, 4 or 8 bytes);
+//! - a [`tick`](MmioDevice::tick) with the cycles hart 0 retired since the
+//!   last one, whenever the bus services devices and before every access;
+//! - an optional PLIC source, refreshed from
+//!   [`is_interrupting`](MmioDevice::is_interrupting) whenever the bus
+//!   services devices like the built-in devices' lines;
+//! - a [`reset`](MmioDevice::reset) when the guest reboots the machine.
+//!
+//! The bus services devices after an MMIO access to any of them, and
+//! otherwise only now and then (see [`crate::devices::scheduler`]). A device
+//! whose line changes with time alone, like a timer, reports when that
+//! happens through [`next_event_cycle`](MmioDevice::next_event_cycle).
+//!
+//! Registered devices are not described in the device tree; the guest has
+//! to know where they are.
+//!
+//! The bus maps some of its own devices (the boot ROM, the mailbox and the
+//! RTC) the same way, keeping an `Arc` to each for the host side.
+
+use std::sync::Arc;
+
+use crate::dram::MemoryError;
+
+/// A memory-mapped peripheral supplied by the embedder.
+///
+/// Like the built-in devices, methods take `&self` and may be called from
+/// any hart's thread, so state lives behind atomics or a lock.
+pub trait MmioDevice: Send + Sync {
+    /// Read `size` bytes at `offset` from the start of the device's range.
+    /// An error becomes a load access fault.
+    fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError>;
+
+    /// Write the low `size` bytes of `value` at `offset`. An error becomes a
+    /// store access fault.
+    fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError>;
+
+    /// Advance the device by `cycles` of guest time.
+    fn tick(&self, _cycles: u64) {}
+
+    /// Cycle of guest time, on the clock that has reached `now`, at which
+    /// the device next needs a tick without being accessed, if any.
+    fn next_event_cycle(&self, _now: u64) -> Option<u64> {
+        None
+    }
+
+    /// Return to the power-on state when the guest resets the machine.
+    fn reset(&self) {}
+
+    /// PLIC source the device drives, if any.
+    fn irq(&self) -> Option<u32> {
+        None
+    }
+
+    /// Level of the device's interrupt line.
+    fn is_interrupting(&self) -> bool {
+        false
+    }
+}
+
+/// A shared device, so the embedder can keep a handle to what it registered.
+impl<T: MmioDevice + ?Sized> MmioDevice for Arc<T> {
+    fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        (**self).load(offset, size)
+    }
+
+    fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        (**self).store(offset, size, value)
+    }
+
+    fn tick(&self, cycles: u64) {
+        (**self).tick(cycles)
+    }
+
+    fn next_event_cycle(&self, now: u64) -> Option<u64> {
+        (**self).next_event_cycle(now)
+    }
+
+    fn reset(&self) {
+        (**self).reset()
+    }
+
+    fn irq(&self) -> Option<u32> {
+        (**self).irq()
+    }
+
+    fn is_interrupting(&self) -> bool {
+        (**self).is_interrupting()
+    }
+}
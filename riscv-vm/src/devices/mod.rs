@@ -1,5 +1,19 @@
+pub mod bootrom;
+pub mod buildinfo;
 pub mod clint;
+pub mod fdt;
+pub mod framebuffer;
+pub mod input;
+pub mod ivshmem;
+pub mod mailbox;
+pub mod mmio;
 pub mod plic;
+pub mod rtc;
+pub mod scheduler;
+pub mod semihost;
 pub mod sysinfo;
+pub mod test_finisher;
 pub mod uart;
 pub mod virtio;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod worker;
@@ -7,9 +7,14 @@ pub const PLIC_BASE: u64 = 0x0C00_0000;
 pub const PLIC_SIZE: u64 = 0x400_0000;
 
 pub const UART_IRQ: u32 = 10;
+pub const INPUT_IRQ: u32 = 11;
+pub const IVSHMEM_IRQ: u32 = 12;
+pub const MAILBOX_IRQ: u32 = 13;
+pub const RTC_IRQ: u32 = 14;
 pub const VIRTIO0_IRQ: u32 = 1;
 
-const NUM_SOURCES: usize = 32;
+/// Interrupt sources, including the reserved source 0.
+pub const NUM_SOURCES: usize = 32;
 /// Number of interrupt contexts.
 /// Each hart has 2 contexts: M-mode (2*N) and S-mode (2*N+1).
 const NUM_CONTEXTS: usize = 2 * MAX_HARTS; // 2 contexts per hart (M-mode and S-mode)
@@ -147,6 +152,17 @@ impl Plic {
         self.sync_caches_from(&state);
     }
 
+    /// Clear priorities, enables, thresholds and claims, as on a machine
+    /// reset. Pending bits mirror the device lines and are kept.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.priority = [0; NUM_SOURCES];
+        state.enable = [0; NUM_CONTEXTS];
+        state.threshold = [0; NUM_CONTEXTS];
+        state.active = [0; NUM_CONTEXTS];
+        self.sync_caches_from(&state);
+    }
+
     // ============================================================
     // Cache Accessor Methods (lock-free)
     // ============================================================
@@ -449,7 +465,8 @@ impl Plic {
         // ============================================================
         if offset < 0x001000 {
             let idx = (offset >> 2) as usize;
-            if idx < NUM_SOURCES {
+            // Source 0 does not exist; its priority is hardwired to zero
+            if idx > 0 && idx < NUM_SOURCES {
                 state.priority[idx] = val;
                 // Sync priority cache for this source
                 self.sync_priority_cache(&state, idx);
@@ -466,13 +483,16 @@ impl Plic {
         }
 
         // ============================================================
-        // Enable per context: 0x002000 + 0x80 * context
+        // Enable per context: 0x002000 + 0x80 * context, one bit per
+        // source. Only word 0 (sources 0-31) is implemented; the other
+        // words read as zero and ignore writes.
         // ============================================================
         if offset >= 0x002000 && offset < 0x002000 + 0x80 * (NUM_CONTEXTS as u64) {
             let ctx = ((offset - 0x002000) / 0x80) as usize;
             let inner = (offset - 0x002000) % 0x80;
             if ctx < NUM_CONTEXTS && inner == 0 {
-                state.enable[ctx] = val;
+                // Source 0 can't be enabled
+                state.enable[ctx] = val & !1;
                 // Sync enable cache for this context
                 self.sync_enable_cache(&state, ctx);
             }
@@ -512,11 +532,18 @@ impl Plic {
         Ok(())
     }
 
+    /// Sources claimed by any context and not yet completed. A source has a
+    /// single gateway, so once one hart claims it no other context can
+    /// claim it until that hart completes it.
+    fn in_flight(state: &PlicState) -> u32 {
+        state.active.iter().fold(0, |acc, &active| acc | active)
+    }
+
     fn eligible_for_context(state: &PlicState, source: usize, ctx: usize) -> bool {
         let pending = ((state.pending >> source) & 1) == 1;
         let enabled = ((state.enable[ctx] >> source) & 1) == 1;
         let over_threshold = state.priority[source] > state.threshold[ctx];
-        let not_active = ((state.active[ctx] >> source) & 1) == 0;
+        let not_active = ((Self::in_flight(state) >> source) & 1) == 0;
         pending && enabled && over_threshold && not_active
     }
 
@@ -576,7 +603,7 @@ impl Plic {
                 let pending = ((state.pending >> i) & 1) == 1;
                 let enabled = ((state.enable[ctx] >> i) & 1) == 1;
                 let over_threshold = state.priority[i] > state.threshold[ctx];
-                let not_active = ((state.active[ctx] >> i) & 1) == 0;
+                let not_active = ((Self::in_flight(&state) >> i) & 1) == 0;
                 if pending {
                     eprintln!(
                         "[PLIC] Source {} pending but not eligible for ctx={}: enabled={} over_threshold={} (prio={} > thresh={}) not_active={}",
@@ -806,4 +833,34 @@ mod tests {
         plic.set_source_level(3, false);
         assert!(!plic.has_pending_candidate(0));
     }
+
+    #[test]
+    fn test_claim_is_exclusive_across_contexts() {
+        let plic = Plic::new();
+        plic.store(4 * 12, 4, 1).unwrap();
+        // Source 12 enabled for both harts' S-mode contexts
+        plic.store(0x002000 + 0x80 * 1, 4, 1 << 12).unwrap();
+        plic.store(0x002000 + 0x80 * 3, 4, 1 << 12).unwrap();
+        plic.set_source_level(12, true);
+
+        assert_eq!(plic.claim_interrupt_for(Plic::s_context(0)), 12);
+        // Hart 1 can't claim it while hart 0 is handling it
+        assert!(!plic.is_interrupt_pending_for(Plic::s_context(1)));
+        assert_eq!(plic.claim_interrupt_for(Plic::s_context(1)), 0);
+
+        plic.store(0x200004 + 0x1000 * 1, 4, 12).unwrap();
+        assert_eq!(plic.claim_interrupt_for(Plic::s_context(1)), 12);
+    }
+
+    #[test]
+    fn test_source_zero_is_reserved() {
+        let plic = Plic::new();
+        plic.store(0, 4, 7).unwrap();
+        plic.store(0x002000, 4, 0xffff_ffff).unwrap();
+        assert_eq!(plic.load(0, 4).unwrap(), 0);
+        assert_eq!(plic.load(0x002000, 4).unwrap(), 0xffff_fffe);
+        // Enable words past the implemented sources read as zero
+        plic.store(0x002004, 4, 0xffff_ffff).unwrap();
+        assert_eq!(plic.load(0x002004, 4).unwrap(), 0);
+    }
 }
@@ -0,0 +1,330 @@
+//! Goldfish Real-Time Clock
+//!
+//! Gives the guest the wall-clock date without a network round trip. The
+//! register set is Android's `google,goldfish-rtc`, which Linux drives with
+//! `rtc-goldfish` and which QEMU's virt board maps at the same address, so
+//! one kernel finds it on both:
+//!
+//! | Offset | Name            | Access | Description                            |
+//! |--------|-----------------|--------|----------------------------------------|
+//! | 0x00   | TIME_LOW        | RW     | Nanoseconds since the Unix epoch, low  |
+//! | 0x04   | TIME_HIGH       | RW     | High half, latched by TIME_LOW reads   |
+//! | 0x08   | ALARM_LOW       | RW     | Alarm time, low; writing arms it       |
+//! | 0x0c   | ALARM_HIGH      | RW     | Alarm time, high; write before the low |
+//! | 0x10   | IRQ_ENABLED     | RW     | Bit 0: raise `RTC_IRQ` on the alarm    |
+//! | 0x14   | CLEAR_ALARM     | W      | Disarm the alarm                       |
+//! | 0x18   | ALARM_STATUS    | R      | 1 while the alarm is armed             |
+//! | 0x1c   | CLEAR_INTERRUPT | W      | Acknowledge a fired alarm              |
+//!
+//! Reading TIME_LOW latches the high half, so a 32-bit guest reads the
+//! low word then the high word; a 64-bit load of TIME_LOW returns both.
+//! Setting the clock works the other way round: TIME_HIGH, then TIME_LOW.
+//!
+//! The time is the host's (`SystemTime` natively, `Date.now()` in the
+//! browser) plus whatever offset the guest set, which survives a reboot
+//! like a battery-backed clock would. In deterministic mode (see
+//! [`Rtc::set_deterministic`]) the host clock is left out: the RTC counts
+//! `mtime` from a fixed date instead, so it only moves as the guest
+//! executes.
+
+use crate::devices::clint::TIMEBASE_FREQUENCY;
+use crate::devices::mmio::MmioDevice;
+use crate::devices::plic::RTC_IRQ;
+use crate::dram::MemoryError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+/// Base address of the RTC, as on QEMU's virt board
+pub const RTC_BASE: u64 = 0x0010_1000;
+/// Size of the RTC MMIO region
+pub const RTC_SIZE: u64 = 0x1000;
+
+/// Date a deterministic RTC reads at `mtime` 0: 2000-01-01 00:00:00 UTC
+pub const DETERMINISTIC_EPOCH_NS: u64 = 946_684_800 * 1_000_000_000;
+
+const NS_PER_TICK: u64 = 1_000_000_000 / TIMEBASE_FREQUENCY;
+
+const TIME_LOW: u64 = 0x00;
+const TIME_HIGH: u64 = 0x04;
+const ALARM_LOW: u64 = 0x08;
+const ALARM_HIGH: u64 = 0x0c;
+const IRQ_ENABLED: u64 = 0x10;
+const CLEAR_ALARM: u64 = 0x14;
+const ALARM_STATUS: u64 = 0x18;
+const CLEAR_INTERRUPT: u64 = 0x1c;
+
+pub struct Rtc {
+    /// Count from `epoch_ns` by `mtime` instead of reading the host clock
+    deterministic: AtomicBool,
+    epoch_ns: AtomicU64,
+    /// Guest time minus the source's, in nanoseconds
+    offset_ns: AtomicI64,
+    /// High half latched by the last TIME_LOW read
+    time_high: AtomicU32,
+    /// High halves written ahead of TIME_LOW / ALARM_LOW
+    set_time_high: AtomicU32,
+    set_alarm_high: AtomicU32,
+    alarm_ns: AtomicU64,
+    alarm_armed: AtomicBool,
+    irq_enabled: AtomicBool,
+    irq_pending: AtomicBool,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            deterministic: AtomicBool::new(false),
+            epoch_ns: AtomicU64::new(0),
+            offset_ns: AtomicI64::new(0),
+            time_high: AtomicU32::new(0),
+            set_time_high: AtomicU32::new(0),
+            set_alarm_high: AtomicU32::new(0),
+            alarm_ns: AtomicU64::new(0),
+            alarm_armed: AtomicBool::new(false),
+            irq_enabled: AtomicBool::new(false),
+            irq_pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Count from `epoch_ns` (since the Unix epoch) by `mtime` rather
+    /// than follow the host clock, or follow it again with `None`. The
+    /// offset the guest set is dropped.
+    pub fn set_deterministic(&self, epoch_ns: Option<u64>) {
+        self.epoch_ns
+            .store(epoch_ns.unwrap_or(0), Ordering::Relaxed);
+        self.deterministic
+            .store(epoch_ns.is_some(), Ordering::Relaxed);
+        self.offset_ns.store(0, Ordering::Relaxed);
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic.load(Ordering::Relaxed)
+    }
+
+    /// The clock before the guest's offset: the host's, or the epoch plus
+    /// `mtime` in deterministic mode.
+    fn source_ns(&self, mtime: u64) -> u64 {
+        if self.is_deterministic() {
+            self.epoch_ns
+                .load(Ordering::Relaxed)
+                .wrapping_add(mtime.wrapping_mul(NS_PER_TICK))
+        } else {
+            host_time_ns()
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch, as the guest sees them at `mtime`
+    pub fn now_ns(&self, mtime: u64) -> u64 {
+        self.source_ns(mtime)
+            .wrapping_add_signed(self.offset_ns.load(Ordering::Relaxed))
+    }
+
+    /// Set the clock the guest sees to `ns` since the Unix epoch, as of
+    /// `mtime`.
+    pub fn set_now_ns(&self, ns: u64, mtime: u64) {
+        self.offset_ns.store(
+            ns.wrapping_sub(self.source_ns(mtime)) as i64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Whether the IRQ line is asserted at `mtime`. Fires the alarm once it
+    /// is due.
+    pub fn is_interrupting(&self, mtime: u64) -> bool {
+        if self.alarm_armed.load(Ordering::Relaxed)
+            && self.now_ns(mtime) >= self.alarm_ns.load(Ordering::Relaxed)
+        {
+            self.alarm_armed.store(false, Ordering::Relaxed);
+            self.irq_pending.store(true, Ordering::Relaxed);
+        }
+        self.irq_enabled.load(Ordering::Relaxed) && self.irq_pending.load(Ordering::Relaxed)
+    }
+
+    /// Disarm the alarm and mask the interrupt for a machine reset. The
+    /// time is kept.
+    pub fn reset(&self) {
+        self.alarm_armed.store(false, Ordering::Relaxed);
+        self.irq_enabled.store(false, Ordering::Relaxed);
+        self.irq_pending.store(false, Ordering::Relaxed);
+    }
+
+    /// Load from a register; `mtime` is the CLINT's at the time of access
+    pub fn load(&self, offset: u64, size: u64, mtime: u64) -> u64 {
+        let val = match offset & !3 {
+            TIME_LOW => {
+                let now = self.now_ns(mtime);
+                self.time_high.store((now >> 32) as u32, Ordering::Relaxed);
+                if size == 8 {
+                    return now;
+                }
+                now & 0xffff_ffff
+            }
+            TIME_HIGH => self.time_high.load(Ordering::Relaxed) as u64,
+            ALARM_LOW => {
+                let alarm = self.alarm_ns.load(Ordering::Relaxed);
+                if size == 8 {
+                    return alarm;
+                }
+                alarm & 0xffff_ffff
+            }
+            ALARM_HIGH => self.alarm_ns.load(Ordering::Relaxed) >> 32,
+            IRQ_ENABLED => self.irq_enabled.load(Ordering::Relaxed) as u64,
+            ALARM_STATUS => self.alarm_armed.load(Ordering::Relaxed) as u64,
+            _ => 0,
+        };
+        val >> ((offset & 3) * 8)
+    }
+
+    /// Store to a register; `mtime` is the CLINT's at the time of access
+    pub fn store(&self, offset: u64, size: u64, value: u64, mtime: u64) {
+        match offset {
+            TIME_LOW => {
+                let high = match size {
+                    8 => value >> 32,
+                    _ => self.set_time_high.load(Ordering::Relaxed) as u64,
+                };
+                self.set_now_ns(high << 32 | value & 0xffff_ffff, mtime);
+            }
+            TIME_HIGH => self.set_time_high.store(value as u32, Ordering::Relaxed),
+            ALARM_LOW => {
+                let high = match size {
+                    8 => value >> 32,
+                    _ => self.set_alarm_high.load(Ordering::Relaxed) as u64,
+                };
+                self.alarm_ns
+                    .store(high << 32 | value & 0xffff_ffff, Ordering::Relaxed);
+                self.alarm_armed.store(true, Ordering::Relaxed);
+            }
+            ALARM_HIGH => self.set_alarm_high.store(value as u32, Ordering::Relaxed),
+            IRQ_ENABLED => self.irq_enabled.store(value & 1 != 0, Ordering::Relaxed),
+            CLEAR_ALARM => self.alarm_armed.store(false, Ordering::Relaxed),
+            CLEAR_INTERRUPT => self.irq_pending.store(false, Ordering::Relaxed),
+            _ => {}
+        }
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the CLINT's `mtime` for a mapped RTC
+pub type MtimeSource = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// The RTC as the bus maps it, reading `mtime` at every access
+pub struct RtcDevice {
+    rtc: Arc<Rtc>,
+    mtime: MtimeSource,
+}
+
+impl RtcDevice {
+    pub fn new(rtc: Arc<Rtc>, mtime: MtimeSource) -> Self {
+        Self { rtc, mtime }
+    }
+}
+
+impl MmioDevice for RtcDevice {
+    fn load(&self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        Ok(self.rtc.load(offset, size, (self.mtime)()))
+    }
+
+    fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        self.rtc.store(offset, size, value, (self.mtime)());
+        Ok(())
+    }
+
+    fn reset(&self) {
+        self.rtc.reset()
+    }
+
+    fn irq(&self) -> Option<u32> {
+        Some(RTC_IRQ)
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.rtc.is_interrupting((self.mtime)())
+    }
+}
+
+/// Host wall-clock time in nanoseconds since the Unix epoch
+#[cfg(not(target_arch = "wasm32"))]
+fn host_time_ns() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Host wall-clock time in nanoseconds since the Unix epoch
+#[cfg(target_arch = "wasm32")]
+fn host_time_ns() -> u64 {
+    (js_sys::Date::now() * 1_000_000.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_reads_host_time() {
+        let rtc = Rtc::new();
+        let host = host_time_ns();
+        let low = rtc.load(TIME_LOW, 4, 0);
+        let high = rtc.load(TIME_HIGH, 4, 0);
+        let now = high << 32 | low;
+        assert!(now >= host && now - host < SECOND);
+        assert!(rtc.load(TIME_LOW, 8, 0) >= now);
+    }
+
+    #[test]
+    fn test_set_time_and_alarm() {
+        let rtc = Rtc::new();
+        // 2001-09-09 01:46:40 UTC
+        let set = 1_000_000_000 * SECOND;
+        rtc.store(TIME_HIGH, 4, set >> 32, 0);
+        rtc.store(TIME_LOW, 4, set & 0xffff_ffff, 0);
+        let now = rtc.load(TIME_LOW, 8, 0);
+        assert!(now >= set && now - set < SECOND);
+
+        // An alarm in the future waits; one in the past fires at once
+        rtc.store(IRQ_ENABLED, 4, 1, 0);
+        rtc.store(ALARM_LOW, 8, set + 3600 * SECOND, 0);
+        assert!(!rtc.is_interrupting(0));
+        assert_eq!(rtc.load(ALARM_STATUS, 4, 0), 1);
+        rtc.store(ALARM_HIGH, 4, set >> 32, 0);
+        rtc.store(ALARM_LOW, 4, set & 0xffff_ffff, 0);
+        assert!(rtc.is_interrupting(0));
+        assert_eq!(rtc.load(ALARM_STATUS, 4, 0), 0);
+        rtc.store(CLEAR_INTERRUPT, 4, 1, 0);
+        assert!(!rtc.is_interrupting(0));
+
+        // The guest's time survives a reset
+        rtc.reset();
+        assert!(rtc.now_ns(0) - set < SECOND);
+    }
+
+    #[test]
+    fn test_deterministic_time_follows_mtime() {
+        let rtc = Rtc::new();
+        rtc.set_deterministic(Some(DETERMINISTIC_EPOCH_NS));
+        assert_eq!(rtc.load(TIME_LOW, 8, 0), DETERMINISTIC_EPOCH_NS);
+        // 1.5 s of guest time
+        let mtime = 15_000_000;
+        assert_eq!(
+            rtc.load(TIME_LOW, 8, mtime),
+            DETERMINISTIC_EPOCH_NS + 1_500_000_000
+        );
+
+        rtc.store(IRQ_ENABLED, 4, 1, mtime);
+        rtc.store(ALARM_LOW, 8, DETERMINISTIC_EPOCH_NS + 2 * SECOND, mtime);
+        assert!(!rtc.is_interrupting(mtime));
+        assert!(rtc.is_interrupting(2 * mtime));
+
+        // Setting the clock moves it from there on
+        rtc.store(TIME_LOW, 8, 0, mtime);
+        assert_eq!(rtc.now_ns(mtime + 10), 1000);
+    }
+}
@@ -0,0 +1,139 @@
+//! Device Event Scheduler
+//!
+//! Refreshing every device's interrupt line takes a lock per device, which
+//! adds up when done at every interrupt poll of every hart. Most polls find
+//! nothing changed, so the bus only services devices when one of these
+//! happened since the last time:
+//!
+//! - the guest touched a device with an interrupt line through MMIO, or the
+//!   host signalled new work with [`EventScheduler::notify`] (the bus does
+//!   this when it polls the VirtIO devices);
+//! - guest time reached a deadline a device asked for through
+//!   [`MmioDevice::next_event_cycle`](super::mmio::MmioDevice::next_event_cycle);
+//! - [`MAX_SERVICE_INTERVAL`] cycles passed, which bounds how late the bus
+//!   notices state a host thread changed without notifying.
+//!
+//! The CLINT and the PLIC are not scheduled: their interrupt state is kept
+//! in atomics and checked on every poll.
+//!
+//! Time is counted in the cycles hart 0 has executed, the clock `mtime`
+//! follows, so deadlines mean the same thing under every execution engine.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest stretch of guest cycles between two device services.
+pub const MAX_SERVICE_INTERVAL: u64 = 1 << 16;
+
+pub struct EventScheduler {
+    /// Cycles hart 0 has executed since the bus was created.
+    now: AtomicU64,
+    /// Earliest cycle at which devices must be serviced again.
+    deadline: AtomicU64,
+    /// Bumped by every [`Self::notify`].
+    epoch: AtomicU64,
+    /// `epoch` as of the last service.
+    serviced_epoch: AtomicU64,
+    /// Number of services so far.
+    services: AtomicU64,
+}
+
+impl EventScheduler {
+    pub fn new() -> Self {
+        Self {
+            now: AtomicU64::new(0),
+            deadline: AtomicU64::new(0),
+            epoch: AtomicU64::new(0),
+            serviced_epoch: AtomicU64::new(0),
+            services: AtomicU64::new(0),
+        }
+    }
+
+    /// Cycles hart 0 has executed.
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+
+    /// Let `cycles` of guest time pass. Called for hart 0 only.
+    #[inline]
+    pub fn advance(&self, cycles: u64) {
+        self.now.fetch_add(cycles, Ordering::Relaxed);
+    }
+
+    /// Have devices serviced at the next poll.
+    #[inline]
+    pub fn notify(&self) {
+        self.epoch.fetch_add(1, Ordering::Release);
+    }
+
+    /// Have devices serviced once guest time reaches `cycle`, unless
+    /// something earlier is already due.
+    #[inline]
+    pub fn schedule(&self, cycle: u64) {
+        self.deadline.fetch_min(cycle, Ordering::Relaxed);
+    }
+
+    /// Whether devices are due for a service. If so, the caller must
+    /// service them: the pending notifications are consumed and the next
+    /// deadline is reset to [`MAX_SERVICE_INTERVAL`] from now.
+    #[inline]
+    pub fn take_due(&self) -> bool {
+        let epoch = self.epoch.load(Ordering::Acquire);
+        let now = self.now();
+        if epoch == self.serviced_epoch.load(Ordering::Relaxed)
+            && now < self.deadline.load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        self.serviced_epoch.store(epoch, Ordering::Relaxed);
+        self.deadline
+            .store(now.saturating_add(MAX_SERVICE_INTERVAL), Ordering::Relaxed);
+        self.services.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Number of device services so far.
+    pub fn services(&self) -> u64 {
+        self.services.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for EventScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_services_on_notify_and_deadline() {
+        let events = EventScheduler::new();
+        // The first poll services, then nothing is due
+        assert!(events.take_due());
+        assert!(!events.take_due());
+
+        events.notify();
+        assert!(events.take_due());
+        assert!(!events.take_due());
+
+        events.schedule(100);
+        events.advance(99);
+        assert!(!events.take_due());
+        events.advance(1);
+        assert!(events.take_due());
+        assert!(!events.take_due());
+
+        // Later deadlines don't delay earlier ones
+        events.schedule(200);
+        events.schedule(1_000);
+        events.advance(100);
+        assert!(events.take_due());
+
+        events.advance(MAX_SERVICE_INTERVAL);
+        assert!(events.take_due());
+        assert_eq!(events.services(), 5);
+    }
+}
@@ -0,0 +1,728 @@
+//! Semihosting Device
+//!
+//! Lets a bare-metal guest use the host for console output, files, its
+//! command line and its exit status without a VirtIO driver, so test
+//! binaries and early boot tools can run against a host directory
+//! directly. Calls follow the Arm/RISC-V semihosting numbering
+//! (`SYS_OPEN` = 0x01, ...), but are issued through MMIO instead of the
+//! `slli`/`ebreak`/`srai` sequence.
+//!
+//! ## Register Layout
+//!
+//! | Offset | Name   | Description                                          |
+//! |--------|--------|------------------------------------------------------|
+//! | 0x00   | MAGIC  | `"RVSH"` (32 bits) when enabled, 0 otherwise         |
+//! | 0x08   | ARG    | Guest physical address of the parameter block (64 bits) |
+//! | 0x10   | OP     | Writing an operation number performs the call        |
+//! | 0x18   | RESULT | Return value of the last call (64 bits)              |
+//!
+//! A parameter block is an array of 64-bit little-endian fields, laid out
+//! as in the semihosting specification for a 64-bit target. Buffers and
+//! strings it points to must lie in DRAM. Errors return `-1` in RESULT and
+//! leave an errno value for `SYS_ERRNO`.
+//!
+//! ## Host Policy
+//!
+//! Nothing is exposed until the host installs a [`SemihostPolicy`]. File
+//! calls go through a [`ShareBackend`], so the same sandboxing as the 9p
+//! shares applies: paths are relative to the backend root, may not contain
+//! `.` or `..` components, and a read-only backend refuses writes. The
+//! special name `:tt` opens the console; console output goes to the UART
+//! output queue and console reads report end of file.
+
+use std::io;
+use std::sync::Mutex;
+
+use crate::devices::uart::Uart;
+use crate::dram::Dram;
+use crate::share::{FileKind, ShareBackend};
+
+/// Base address for the semihosting device
+pub const SEMIHOST_BASE: u64 = 0x0014_0000;
+/// Size of the semihosting MMIO region
+pub const SEMIHOST_SIZE: u64 = 0x1000;
+
+const MAGIC: u64 = 0x00;
+const ARG: u64 = 0x08;
+const OP: u64 = 0x10;
+const RESULT: u64 = 0x18;
+
+const MAGIC_VALUE: u32 = u32::from_le_bytes(*b"RVSH");
+
+pub const SYS_OPEN: u64 = 0x01;
+pub const SYS_CLOSE: u64 = 0x02;
+pub const SYS_WRITEC: u64 = 0x03;
+pub const SYS_WRITE0: u64 = 0x04;
+pub const SYS_WRITE: u64 = 0x05;
+pub const SYS_READ: u64 = 0x06;
+pub const SYS_ISTTY: u64 = 0x09;
+pub const SYS_SEEK: u64 = 0x0a;
+pub const SYS_FLEN: u64 = 0x0c;
+pub const SYS_REMOVE: u64 = 0x0e;
+pub const SYS_ERRNO: u64 = 0x13;
+pub const SYS_GET_CMDLINE: u64 = 0x15;
+pub const SYS_EXIT: u64 = 0x18;
+pub const SYS_EXIT_EXTENDED: u64 = 0x20;
+
+/// `SYS_EXIT` reason for a normal exit; the subcode is the exit status
+pub const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Halt code of a successful exit, as written to the test finisher
+pub const EXIT_PASS: u64 = 0x5555;
+/// Low half of the halt code of a failed exit; the status is in bits 16+
+pub const EXIT_FAIL: u64 = 0x3333;
+
+const ENOENT: u64 = 2;
+const EIO: u64 = 5;
+const EBADF: u64 = 9;
+const EACCES: u64 = 13;
+const EFAULT: u64 = 14;
+const EEXIST: u64 = 17;
+const EISDIR: u64 = 21;
+const EINVAL: u64 = 22;
+const ENOSYS: u64 = 38;
+
+const ERROR: u64 = u64::MAX;
+
+/// Longest path name a guest may pass
+const MAX_PATH: u64 = 4096;
+
+/// What the host lets a semihosting guest do.
+pub struct SemihostPolicy {
+    /// Files the guest may open; `None` denies every file call.
+    pub files: Option<Box<dyn ShareBackend>>,
+    /// Command line returned by `SYS_GET_CMDLINE`.
+    pub cmdline: String,
+    /// Whether `SYS_EXIT` stops the VM; otherwise it fails with `EACCES`.
+    pub allow_exit: bool,
+}
+
+impl Default for SemihostPolicy {
+    /// Console output and exit only.
+    fn default() -> Self {
+        Self {
+            files: None,
+            cmdline: String::new(),
+            allow_exit: true,
+        }
+    }
+}
+
+impl SemihostPolicy {
+    /// Expose `files` to the guest as well.
+    pub fn with_files(files: Box<dyn ShareBackend>) -> Self {
+        Self {
+            files: Some(files),
+            ..Self::default()
+        }
+    }
+}
+
+/// Halt code for a guest exit status: [`EXIT_PASS`] for 0, otherwise the
+/// test finisher's failure encoding.
+pub fn exit_halt_code(status: u64) -> u64 {
+    if status == 0 {
+        EXIT_PASS
+    } else {
+        (status << 16) | EXIT_FAIL
+    }
+}
+
+enum Handle {
+    Console,
+    File {
+        path: String,
+        offset: u64,
+        read: bool,
+        write: bool,
+        append: bool,
+    },
+}
+
+struct State {
+    policy: Option<SemihostPolicy>,
+    arg: u64,
+    result: u64,
+    errno: u64,
+    handles: Vec<Option<Handle>>,
+}
+
+pub struct Semihost {
+    state: Mutex<State>,
+}
+
+impl Default for Semihost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Guest memory and console a call operates on.
+struct Guest<'a> {
+    dram: &'a Dram,
+    uart: &'a Uart,
+}
+
+impl Guest<'_> {
+    fn offset(&self, addr: u64, len: u64) -> Result<u64, u64> {
+        let start = self.dram.offset(addr).ok_or(EFAULT)? as u64;
+        let end = start.checked_add(len).ok_or(EFAULT)?;
+        if end > self.dram.size() as u64 {
+            return Err(EFAULT);
+        }
+        Ok(start)
+    }
+
+    fn read(&self, addr: u64, len: u64) -> Result<Vec<u8>, u64> {
+        let offset = self.offset(addr, len)?;
+        self.dram
+            .read_range(offset as usize, len as usize)
+            .map_err(|_| EFAULT)
+    }
+
+    fn write(&self, addr: u64, data: &[u8]) -> Result<(), u64> {
+        let offset = self.offset(addr, data.len() as u64)?;
+        self.dram.write_bytes(offset, data).map_err(|_| EFAULT)
+    }
+
+    /// Field `index` of the parameter block at `block`.
+    fn field(&self, block: u64, index: u64) -> Result<u64, u64> {
+        let bytes = self.read(block.wrapping_add(index * 8), 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn set_field(&self, block: u64, index: u64, value: u64) -> Result<(), u64> {
+        self.write(block.wrapping_add(index * 8), &value.to_le_bytes())
+    }
+
+    fn print(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.uart.push_output(byte);
+        }
+    }
+}
+
+fn errno_of(err: &io::Error) -> u64 {
+    match err.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::IsADirectory => EISDIR,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+/// Check a guest path name and return it as a backend path.
+fn share_path(name: &[u8]) -> Result<String, u64> {
+    let name = std::str::from_utf8(name).map_err(|_| EINVAL)?;
+    let valid = !name.is_empty()
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..");
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(EACCES)
+    }
+}
+
+impl Semihost {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                policy: None,
+                arg: 0,
+                result: 0,
+                errno: 0,
+                handles: Vec::new(),
+            }),
+        }
+    }
+
+    /// Install `policy`, or disable the device with `None`. Open handles
+    /// are closed either way.
+    pub fn set_policy(&self, policy: Option<SemihostPolicy>) {
+        let mut state = self.state.lock().unwrap();
+        state.policy = policy;
+        state.handles.clear();
+        state.errno = 0;
+    }
+
+    /// Whether a policy is installed.
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().policy.is_some()
+    }
+
+    pub fn load(&self, offset: u64, size: u64) -> u64 {
+        let state = self.state.lock().unwrap();
+        let (base, reg) = match offset & !7 {
+            MAGIC if state.policy.is_some() => (MAGIC, MAGIC_VALUE as u64),
+            ARG => (ARG, state.arg),
+            RESULT => (RESULT, state.result),
+            _ => return 0,
+        };
+        let shift = (offset - base) * 8;
+        let mask = if size >= 8 {
+            u64::MAX
+        } else {
+            (1 << (size * 8)) - 1
+        };
+        (reg >> shift) & mask
+    }
+
+    /// Handle a register write. Returns the halt code when the guest exits.
+    pub fn store(
+        &self,
+        offset: u64,
+        size: u64,
+        value: u64,
+        dram: &Dram,
+        uart: &Uart,
+    ) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        match offset & !7 {
+            ARG => {
+                let shift = (offset - ARG) * 8;
+                let mask = if size >= 8 {
+                    u64::MAX
+                } else {
+                    ((1u64 << (size * 8)) - 1) << shift
+                };
+                state.arg = (state.arg & !mask) | ((value << shift) & mask);
+                None
+            }
+            OP if offset == OP && state.policy.is_some() => {
+                let arg = state.arg;
+                let guest = Guest { dram, uart };
+                match state.call(value, arg, &guest) {
+                    Ok(Call::Return(result)) => state.result = result,
+                    Ok(Call::Exit(status)) => return Some(exit_halt_code(status)),
+                    Err(errno) => {
+                        state.errno = errno;
+                        state.result = ERROR;
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+enum Call {
+    Return(u64),
+    Exit(u64),
+}
+
+impl State {
+    fn policy(&mut self) -> &mut SemihostPolicy {
+        self.policy
+            .as_mut()
+            .expect("semihosting call without a policy")
+    }
+
+    fn files(&mut self) -> Result<&mut dyn ShareBackend, u64> {
+        match self.policy().files.as_deref_mut() {
+            Some(files) => Ok(files),
+            None => Err(EACCES),
+        }
+    }
+
+    fn handle(&mut self, handle: u64) -> Result<&mut Handle, u64> {
+        let index = handle.checked_sub(1).ok_or(EBADF)?;
+        self.handles
+            .get_mut(index as usize)
+            .and_then(Option::as_mut)
+            .ok_or(EBADF)
+    }
+
+    fn call(&mut self, op: u64, arg: u64, guest: &Guest) -> Result<Call, u64> {
+        let result = match op {
+            SYS_OPEN => {
+                let name_len = guest.field(arg, 2)?;
+                if name_len > MAX_PATH {
+                    return Err(EINVAL);
+                }
+                let name = guest.read(guest.field(arg, 0)?, name_len)?;
+                self.open(&name, guest.field(arg, 1)?)?
+            }
+            SYS_CLOSE => {
+                let handle = guest.field(arg, 0)?;
+                self.handle(handle)?;
+                self.handles[handle as usize - 1] = None;
+                0
+            }
+            SYS_WRITEC => {
+                guest.print(&guest.read(arg, 1)?);
+                0
+            }
+            SYS_WRITE0 => {
+                let mut addr = arg;
+                loop {
+                    let byte = guest.read(addr, 1)?[0];
+                    if byte == 0 {
+                        break;
+                    }
+                    guest.print(&[byte]);
+                    addr += 1;
+                }
+                0
+            }
+            SYS_WRITE => {
+                let (handle, buf, len) = (
+                    guest.field(arg, 0)?,
+                    guest.field(arg, 1)?,
+                    guest.field(arg, 2)?,
+                );
+                let data = guest.read(buf, len)?;
+                let written = self.write(handle, &data, guest)?;
+                len - written as u64
+            }
+            SYS_READ => {
+                let (handle, buf, len) = (
+                    guest.field(arg, 0)?,
+                    guest.field(arg, 1)?,
+                    guest.field(arg, 2)?,
+                );
+                guest.offset(buf, len)?;
+                let data = self.read(handle, len)?;
+                guest.write(buf, &data)?;
+                len - data.len() as u64
+            }
+            SYS_ISTTY => match self.handle(guest.field(arg, 0)?)? {
+                Handle::Console => 1,
+                Handle::File { .. } => 0,
+            },
+            SYS_SEEK => {
+                let pos = guest.field(arg, 1)?;
+                match self.handle(guest.field(arg, 0)?)? {
+                    Handle::Console => return Err(EINVAL),
+                    Handle::File { offset, .. } => *offset = pos,
+                }
+                0
+            }
+            SYS_FLEN => {
+                let path = match self.handle(guest.field(arg, 0)?)? {
+                    Handle::Console => return Err(EINVAL),
+                    Handle::File { path, .. } => path.clone(),
+                };
+                self.files()?.stat(&path).map_err(|e| errno_of(&e))?.size
+            }
+            SYS_REMOVE => {
+                let name_len = guest.field(arg, 1)?;
+                if name_len > MAX_PATH {
+                    return Err(EINVAL);
+                }
+                let path = share_path(&guest.read(guest.field(arg, 0)?, name_len)?)?;
+                let files = self.files()?;
+                if files.is_read_only() {
+                    return Err(EACCES);
+                }
+                files.remove(&path).map_err(|e| errno_of(&e))?;
+                0
+            }
+            SYS_ERRNO => self.errno,
+            SYS_GET_CMDLINE => {
+                let cmdline = self.policy().cmdline.clone();
+                let (buf, len) = (guest.field(arg, 0)?, guest.field(arg, 1)?);
+                let mut bytes = cmdline.into_bytes();
+                bytes.push(0);
+                if bytes.len() as u64 > len {
+                    return Err(EINVAL);
+                }
+                guest.write(buf, &bytes)?;
+                guest.set_field(arg, 1, bytes.len() as u64 - 1)?;
+                0
+            }
+            SYS_EXIT | SYS_EXIT_EXTENDED => {
+                if !self.policy().allow_exit {
+                    return Err(EACCES);
+                }
+                let (reason, subcode) = (guest.field(arg, 0)?, guest.field(arg, 1)?);
+                let status = if reason == ADP_STOPPED_APPLICATION_EXIT {
+                    subcode
+                } else {
+                    1
+                };
+                return Ok(Call::Exit(status));
+            }
+            _ => return Err(ENOSYS),
+        };
+        Ok(Call::Return(result))
+    }
+
+    /// Open `name` with an `fopen`-style mode (0-11, as in the spec).
+    fn open(&mut self, name: &[u8], mode: u64) -> Result<u64, u64> {
+        if mode > 11 {
+            return Err(EINVAL);
+        }
+        let handle = if name == b":tt" {
+            Handle::Console
+        } else {
+            let path = share_path(name)?;
+            // r, r+, w, w+, a, a+, each with and without `b`
+            let (read, write, truncate, append) = match mode / 2 {
+                0 => (true, false, false, false),
+                1 => (true, true, false, false),
+                2 => (false, true, true, false),
+                3 => (true, true, true, false),
+                4 => (false, true, false, true),
+                _ => (true, true, false, true),
+            };
+            let files = self.files()?;
+            if write && files.is_read_only() {
+                return Err(EACCES);
+            }
+            match files.stat(&path) {
+                Ok(attr) if attr.kind == FileKind::Dir => return Err(EISDIR),
+                Ok(_) if truncate => files.set_len(&path, 0).map_err(|e| errno_of(&e))?,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound && (truncate || append) => {
+                    files.create(&path).map_err(|e| errno_of(&e))?
+                }
+                Err(e) => return Err(errno_of(&e)),
+            }
+            Handle::File {
+                path,
+                offset: 0,
+                read,
+                write,
+                append,
+            }
+        };
+        let slot = match self.handles.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+        };
+        self.handles[slot] = Some(handle);
+        Ok(slot as u64 + 1)
+    }
+
+    /// Write `data` to `handle`, returning the bytes written.
+    fn write(&mut self, handle: u64, data: &[u8], guest: &Guest) -> Result<usize, u64> {
+        let (path, offset, append) = match self.handle(handle)? {
+            Handle::Console => {
+                guest.print(data);
+                return Ok(data.len());
+            }
+            Handle::File { write: false, .. } => return Err(EBADF),
+            Handle::File {
+                path,
+                offset,
+                append,
+                ..
+            } => (path.clone(), *offset, *append),
+        };
+        let files = self.files()?;
+        let offset = if append {
+            files.stat(&path).map_err(|e| errno_of(&e))?.size
+        } else {
+            offset
+        };
+        let written = files.write(&path, offset, data).map_err(|e| errno_of(&e))?;
+        if let Handle::File { offset: pos, .. } = self.handle(handle)? {
+            *pos = offset + written as u64;
+        }
+        Ok(written)
+    }
+
+    /// Read up to `len` bytes from `handle`.
+    fn read(&mut self, handle: u64, len: u64) -> Result<Vec<u8>, u64> {
+        let (path, offset) = match self.handle(handle)? {
+            Handle::Console => return Ok(Vec::new()),
+            Handle::File { read: false, .. } => return Err(EBADF),
+            Handle::File { path, offset, .. } => (path.clone(), *offset),
+        };
+        let mut data = vec![0u8; len as usize];
+        let mut filled = 0;
+        let files = self.files()?;
+        while filled < data.len() {
+            let n = files
+                .read(&path, offset + filled as u64, &mut data[filled..])
+                .map_err(|e| errno_of(&e))?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        data.truncate(filled);
+        if let Handle::File { offset: pos, .. } = self.handle(handle)? {
+            *pos = offset + filled as u64;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::share::HostDir;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const BLOCK: u64 = DRAM_BASE;
+    const NAME: u64 = DRAM_BASE + 0x100;
+    const BUF: u64 = DRAM_BASE + 0x1000;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("riscv-vm-semihost-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    struct Rig {
+        sh: Semihost,
+        dram: Dram,
+        uart: Uart,
+    }
+
+    impl Rig {
+        fn new(policy: SemihostPolicy) -> Self {
+            let sh = Semihost::new();
+            sh.set_policy(Some(policy));
+            Self {
+                sh,
+                dram: Dram::new(DRAM_BASE, 1 << 20),
+                uart: Uart::new(),
+            }
+        }
+
+        fn poke(&self, addr: u64, data: &[u8]) {
+            self.dram.write_bytes(addr - DRAM_BASE, data).unwrap();
+        }
+
+        fn peek(&self, addr: u64, len: usize) -> Vec<u8> {
+            self.dram
+                .read_range((addr - DRAM_BASE) as usize, len)
+                .unwrap()
+        }
+
+        /// Issue `op` with a parameter block of `fields`.
+        fn call(&self, op: u64, fields: &[u64]) -> Result<u64, u64> {
+            for (i, field) in fields.iter().enumerate() {
+                self.poke(BLOCK + i as u64 * 8, &field.to_le_bytes());
+            }
+            self.sh.store(ARG, 8, BLOCK, &self.dram, &self.uart);
+            if let Some(code) = self.sh.store(OP, 8, op, &self.dram, &self.uart) {
+                return Err(code);
+            }
+            Ok(self.sh.load(RESULT, 8))
+        }
+
+        fn open(&self, name: &str, mode: u64) -> u64 {
+            self.poke(NAME, name.as_bytes());
+            self.call(SYS_OPEN, &[NAME, mode, name.len() as u64])
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn test_disabled_until_policy_installed() {
+        let sh = Semihost::new();
+        let dram = Dram::new(DRAM_BASE, 0x1000);
+        let uart = Uart::new();
+        assert_eq!(sh.load(MAGIC, 4), 0);
+        assert_eq!(sh.store(OP, 8, SYS_EXIT, &dram, &uart), None);
+
+        sh.set_policy(Some(SemihostPolicy::default()));
+        assert_eq!(sh.load(MAGIC, 4), MAGIC_VALUE as u64);
+        assert_eq!(sh.load(MAGIC, 1), b'R' as u64);
+    }
+
+    #[test]
+    fn test_console_output_and_exit() {
+        let rig = Rig::new(SemihostPolicy::default());
+        rig.poke(NAME, b"hi\0");
+        rig.sh.store(ARG, 8, NAME, &rig.dram, &rig.uart);
+        rig.sh.store(OP, 8, SYS_WRITE0, &rig.dram, &rig.uart);
+        assert_eq!(rig.uart.drain_output(), b"hi");
+
+        let tt = rig.open(":tt", 4);
+        rig.poke(BUF, b"out");
+        assert_eq!(rig.call(SYS_WRITE, &[tt, BUF, 3]), Ok(0));
+        assert_eq!(rig.call(SYS_ISTTY, &[tt]), Ok(1));
+        assert_eq!(rig.uart.drain_output(), b"out");
+
+        assert_eq!(
+            rig.call(SYS_EXIT, &[ADP_STOPPED_APPLICATION_EXIT, 0]),
+            Err(EXIT_PASS)
+        );
+        assert_eq!(
+            rig.call(SYS_EXIT_EXTENDED, &[ADP_STOPPED_APPLICATION_EXIT, 3]),
+            Err(3 << 16 | EXIT_FAIL)
+        );
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let root = temp_root("files");
+        let rig = Rig::new(SemihostPolicy::with_files(Box::new(
+            HostDir::new(&root, false).unwrap(),
+        )));
+
+        let fd = rig.open("out.txt", 4);
+        assert_ne!(fd, ERROR);
+        rig.poke(BUF, b"hello world");
+        assert_eq!(rig.call(SYS_WRITE, &[fd, BUF, 11]), Ok(0));
+        assert_eq!(rig.call(SYS_CLOSE, &[fd]), Ok(0));
+        assert_eq!(fs::read(root.join("out.txt")).unwrap(), b"hello world");
+
+        let fd = rig.open("out.txt", 0);
+        assert_eq!(rig.call(SYS_FLEN, &[fd]), Ok(11));
+        assert_eq!(rig.call(SYS_SEEK, &[fd, 6]), Ok(0));
+        // Five bytes left, so three of the eight requested are not read
+        assert_eq!(rig.call(SYS_READ, &[fd, BUF + 0x100, 8]), Ok(3));
+        assert_eq!(rig.peek(BUF + 0x100, 5), b"world");
+        assert_eq!(rig.call(SYS_WRITE, &[fd, BUF, 1]), Ok(ERROR));
+        assert_eq!(rig.call(SYS_ERRNO, &[]), Ok(EBADF));
+
+        rig.poke(NAME, b"out.txt");
+        assert_eq!(rig.call(SYS_REMOVE, &[NAME, 7]), Ok(0));
+        assert!(!root.join("out.txt").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_policy_limits_files() {
+        let root = temp_root("policy");
+        fs::write(root.join("data"), b"x").unwrap();
+
+        let rig = Rig::new(SemihostPolicy::default());
+        assert_eq!(rig.open("data", 0), ERROR);
+        assert_eq!(rig.call(SYS_ERRNO, &[]), Ok(EACCES));
+
+        let rig = Rig::new(SemihostPolicy {
+            allow_exit: false,
+            ..SemihostPolicy::with_files(Box::new(HostDir::new(&root, true).unwrap()))
+        });
+        assert_ne!(rig.open("data", 0), ERROR);
+        assert_eq!(rig.open("data", 4), ERROR);
+        assert_eq!(rig.open("../data", 0), ERROR);
+        assert_eq!(rig.open("/etc/passwd", 0), ERROR);
+        assert_eq!(rig.call(SYS_ERRNO, &[]), Ok(EACCES));
+        assert_eq!(
+            rig.call(SYS_EXIT, &[ADP_STOPPED_APPLICATION_EXIT, 0]),
+            Ok(ERROR)
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_get_cmdline() {
+        let rig = Rig::new(SemihostPolicy {
+            cmdline: "test --fast".to_string(),
+            ..SemihostPolicy::default()
+        });
+        assert_eq!(rig.call(SYS_GET_CMDLINE, &[BUF, 4]), Ok(ERROR));
+        assert_eq!(rig.call(SYS_GET_CMDLINE, &[BUF, 64]), Ok(0));
+        assert_eq!(rig.peek(BUF, 12), b"test --fast\0");
+        assert_eq!(rig.peek(BLOCK + 8, 8), 11u64.to_le_bytes());
+    }
+}
@@ -13,10 +13,11 @@
 //! | 0x18   | DISK_TOTAL       | R/W    | Disk total bytes (64 bits)               |
 //! | 0x20   | CPU_COUNT        | R/W    | Number of CPUs/harts (32 bits, padded)   |
 //! | 0x28   | UPTIME           | R/W    | Uptime in ms (64 bits)                   |
+//! | 0x40   | KERNEL_ID        | R/W    | `uname -a` line, NUL-padded (128 bytes)  |
 //!
 //! The kernel writes to these registers, and the emulator reads them.
 
-use std::sync::atomic::{AtomicU64, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicU64, AtomicU32, Ordering};
 
 /// Base address for the system info device
 pub const SYSINFO_BASE: u64 = 0x0011_0000;
@@ -31,6 +32,10 @@ const DISK_TOTAL: u64 = 0x18;
 const CPU_COUNT: u64 = 0x20;
 // 0x24 is padding for alignment
 const UPTIME: u64 = 0x28;
+/// Start of the kernel identification string
+const KERNEL_ID: u64 = 0x40;
+/// Length of the kernel identification string in bytes
+pub const KERNEL_ID_LEN: usize = 128;
 
 /// System information device for kernel-to-host communication
 pub struct SysInfo {
@@ -46,6 +51,8 @@ pub struct SysInfo {
     cpu_count: AtomicU32,
     /// System uptime in milliseconds
     uptime_ms: AtomicU64,
+    /// Kernel identification, NUL-padded ASCII
+    kernel_id: [AtomicU8; KERNEL_ID_LEN],
 }
 
 impl SysInfo {
@@ -57,6 +64,7 @@ impl SysInfo {
             disk_total: AtomicU64::new(0),
             cpu_count: AtomicU32::new(1),
             uptime_ms: AtomicU64::new(0),
+            kernel_id: [const { AtomicU8::new(0) }; KERNEL_ID_LEN],
         }
     }
 
@@ -86,8 +94,30 @@ impl SysInfo {
         self.uptime_ms.load(Ordering::Relaxed)
     }
 
+    /// Get the kernel identification line (empty until the kernel reports it)
+    pub fn kernel_id(&self) -> String {
+        self.kernel_id
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .take_while(|&b| b != 0)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Offset into the kernel identification string, if `offset..offset+size` lies in it
+    fn kernel_id_index(offset: u64, size: u64) -> Option<usize> {
+        let end = KERNEL_ID + KERNEL_ID_LEN as u64;
+        (offset >= KERNEL_ID && offset + size <= end).then(|| (offset - KERNEL_ID) as usize)
+    }
+
     /// Load from register
     pub fn load(&self, offset: u64, size: u64) -> u64 {
+        if let Some(index) = Self::kernel_id_index(offset, size) {
+            // Little-endian, like guest memory
+            return (0..size as usize).rev().fold(0, |acc, i| {
+                (acc << 8) | self.kernel_id[index + i].load(Ordering::Relaxed) as u64
+            });
+        }
         match (offset, size) {
             // Heap used (64-bit at offset 0x00)
             (HEAP_USED, 4) => self.heap_used.load(Ordering::Relaxed) as u32 as u64,
@@ -123,6 +153,12 @@ impl SysInfo {
 
     /// Store to register
     pub fn store(&self, offset: u64, size: u64, value: u64) {
+        if let Some(index) = Self::kernel_id_index(offset, size) {
+            for i in 0..size as usize {
+                self.kernel_id[index + i].store((value >> (8 * i)) as u8, Ordering::Relaxed);
+            }
+            return;
+        }
         match (offset, size) {
             // Heap used (64-bit at offset 0x00)
             (HEAP_USED, 4) => {
@@ -252,5 +288,24 @@ mod tests {
         sysinfo.store(CPU_COUNT, 4, 4);
         assert_eq!(sysinfo.cpu_count(), 4);
     }
+
+    #[test]
+    fn test_kernel_id() {
+        let sysinfo = SysInfo::new();
+        assert_eq!(sysinfo.kernel_id(), "");
+
+        // Mixed byte and doubleword writes, as a guest might copy it
+        let id = b"BAVY bavy 0.1.0 riscv64";
+        for (i, &b) in id.iter().enumerate().skip(8) {
+            sysinfo.store(KERNEL_ID + i as u64, 1, b as u64);
+        }
+        sysinfo.store(KERNEL_ID, 8, u64::from_le_bytes(id[..8].try_into().unwrap()));
+        assert_eq!(sysinfo.kernel_id(), "BAVY bavy 0.1.0 riscv64");
+        assert_eq!(sysinfo.load(KERNEL_ID, 4), u32::from_le_bytes(*b"BAVY") as u64);
+
+        // Accesses straddling the end of the string are ignored
+        sysinfo.store(KERNEL_ID + KERNEL_ID_LEN as u64 - 4, 8, u64::MAX);
+        assert_eq!(sysinfo.load(KERNEL_ID + KERNEL_ID_LEN as u64 - 4, 4), 0);
+    }
 }
 
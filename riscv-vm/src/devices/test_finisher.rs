@@ -0,0 +1,156 @@
+//! SiFive Test Finisher
+//!
+//! The `sifive,test0` device the virt board powers off and resets through.
+//! A 32- or 64-bit store to its first register ends the run, with the low
+//! 16 bits selecting how:
+//!
+//! | Value                        | Exit                     |
+//! |------------------------------|--------------------------|
+//! | `0x5555`                     | [`VmExit::PowerOff`]     |
+//! | `(status << 16) \| 0x3333`   | [`VmExit::Fail(status)`] |
+//! | `0x7777`                     | [`VmExit::Reboot`]       |
+//!
+//! Linux finds it through the `syscon-poweroff` and `syscon-reboot` nodes
+//! of the device tree. The built-in SBI's system reset and semihosting's
+//! exit call end the run with the same values, so every front-end decodes
+//! one set of codes. Any other value written to the register stops the VM
+//! as a failure with the whole value as its status.
+//!
+//! The store stops the hart with [`Trap::RequestedTrap`] carrying the
+//! value, and the front-end running it turns that into a [`VmExit`]:
+//! `NativeVm::run` returns it, `WasmVm` reports it to the page, and the
+//! `vm` binary exits with [`VmExit::exit_status`].
+
+use crate::Trap;
+
+/// Base address of the test finisher MMIO region.
+pub const TEST_FINISHER_BASE: u64 = 0x0010_0000;
+/// Size of the test finisher MMIO region.
+pub const TEST_FINISHER_SIZE: u64 = 0x1000;
+
+/// Power off the machine.
+pub const FINISHER_PASS: u64 = 0x5555;
+/// Power off the machine reporting a failure; the bits above 15 hold the
+/// status.
+pub const FINISHER_FAIL: u64 = 0x3333;
+/// Reset the machine.
+pub const FINISHER_RESET: u64 = 0x7777;
+
+/// How the guest ended the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmExit {
+    /// The guest powered the machine off.
+    PowerOff,
+    /// The guest asked for a reset.
+    Reboot,
+    /// The guest powered off reporting a failure with this status.
+    Fail(u32),
+}
+
+impl VmExit {
+    /// Decode a value written to the test finisher.
+    pub fn from_code(code: u64) -> Self {
+        match code & 0xffff {
+            FINISHER_PASS => VmExit::PowerOff,
+            FINISHER_RESET => VmExit::Reboot,
+            FINISHER_FAIL => VmExit::Fail((code >> 16) as u32),
+            _ => VmExit::Fail(code as u32),
+        }
+    }
+
+    /// The value that requests this exit from the test finisher.
+    pub fn code(self) -> u64 {
+        match self {
+            VmExit::PowerOff => FINISHER_PASS,
+            VmExit::Reboot => FINISHER_RESET,
+            VmExit::Fail(status) => ((status as u64) << 16) | FINISHER_FAIL,
+        }
+    }
+
+    /// Process exit status for a host running the guest: 0 for a power off
+    /// or reset, otherwise the failure status, or 1 if that is 0.
+    pub fn exit_status(self) -> i32 {
+        match self {
+            VmExit::PowerOff | VmExit::Reboot => 0,
+            VmExit::Fail(0) => 1,
+            VmExit::Fail(status) => status as i32,
+        }
+    }
+}
+
+impl std::fmt::Display for VmExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmExit::PowerOff => write!(f, "power off"),
+            VmExit::Reboot => write!(f, "reboot"),
+            VmExit::Fail(status) => write!(f, "failure (status {})", status),
+        }
+    }
+}
+
+/// The test finisher. It holds no state: a store either ends the run or is
+/// ignored.
+pub struct TestFinisher;
+
+impl TestFinisher {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Reads are harmless and return zero.
+    pub fn load(&self, _offset: u64, _size: u64) -> u64 {
+        0
+    }
+
+    /// End the run if `value` was written to the finisher register. Stores
+    /// elsewhere in the region, or narrower than 32 bits, are ignored.
+    pub fn store(&self, offset: u64, size: u64, value: u64) -> Result<(), Trap> {
+        if offset != 0 || size < 4 {
+            return Ok(());
+        }
+        Err(Trap::RequestedTrap(value & 0xffff_ffff))
+    }
+}
+
+impl Default for TestFinisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_round_trip() {
+        assert_eq!(VmExit::from_code(0x5555), VmExit::PowerOff);
+        assert_eq!(VmExit::from_code(0x7777), VmExit::Reboot);
+        assert_eq!(VmExit::from_code(0x3333), VmExit::Fail(0));
+        assert_eq!(VmExit::from_code((3 << 16) | 0x3333), VmExit::Fail(3));
+        assert_eq!(VmExit::from_code(0xdead), VmExit::Fail(0xdead));
+        for exit in [VmExit::PowerOff, VmExit::Reboot, VmExit::Fail(42)] {
+            assert_eq!(VmExit::from_code(exit.code()), exit);
+        }
+
+        assert_eq!(VmExit::PowerOff.exit_status(), 0);
+        assert_eq!(VmExit::Fail(0).exit_status(), 1);
+        assert_eq!(VmExit::Fail(42).exit_status(), 42);
+    }
+
+    #[test]
+    fn test_only_the_register_ends_the_run() {
+        let finisher = TestFinisher::new();
+        assert!(matches!(
+            finisher.store(0, 4, 0x5555),
+            Err(Trap::RequestedTrap(0x5555))
+        ));
+        assert!(matches!(
+            finisher.store(0, 8, 0xffff_ffff_0000_7777),
+            Err(Trap::RequestedTrap(0x7777))
+        ));
+        assert!(finisher.store(4, 4, 0x5555).is_ok());
+        assert!(finisher.store(0, 1, 0x55).is_ok());
+        assert_eq!(finisher.load(0, 4), 0);
+    }
+}
@@ -1,3 +1,22 @@
+//! NS16550A UART
+//!
+//! The console UART, with the 16550's register set:
+//!
+//! - RX and TX FIFOs of [`FIFO_DEPTH`] bytes while FCR bit 0 is set, one
+//!   byte otherwise, with the receive trigger level from FCR bits 7:6;
+//! - the prioritised interrupt sources of IIR (receiver line status,
+//!   received data available, character timeout, THR empty and modem
+//!   status), each enabled through IER and raised on the PLIC's `UART_IRQ`;
+//! - loopback mode (MCR bit 4), with the modem control outputs reflected
+//!   in MSR, as drivers use to probe for the chip.
+//!
+//! There is no line timing. Transmitted bytes go to the host at once, so
+//! THR is always empty again by the time the guest looks. Host input waits
+//! in a backlog and moves into the RX FIFO as the guest makes room, like a
+//! line with hardware flow control, so it is never overrun; only loopback
+//! bytes can be. A FIFO holding less than the trigger level reports a
+//! character timeout straight away, since no more bytes are on the way.
+
 use crate::dram::MemoryError;
 use std::collections::VecDeque;
 use std::sync::Mutex;
@@ -5,6 +24,9 @@ use std::sync::Mutex;
 pub const UART_BASE: u64 = 0x1000_0000;
 pub const UART_SIZE: u64 = 0x100;
 
+/// Depth of each FIFO while FIFOs are enabled.
+pub const FIFO_DEPTH: usize = 16;
+
 // Registers (offset)
 const RBR: u64 = 0x00; // Receiver Buffer (Read)
 const THR: u64 = 0x00; // Transmitter Holding (Write)
@@ -17,10 +39,54 @@ const LSR: u64 = 0x05; // Line Status
 const MSR: u64 = 0x06; // Modem Status
 const SCR: u64 = 0x07; // Scratch
 
+// IER bits
+const IER_RDI: u8 = 0x01; // Received data available (and character timeout)
+const IER_THRI: u8 = 0x02; // THR empty
+const IER_RLSI: u8 = 0x04; // Receiver line status
+const IER_MSI: u8 = 0x08; // Modem status
+
+// IIR interrupt identities, highest priority first
+const IIR_RLSI: u8 = 0x06;
+const IIR_RDI: u8 = 0x04;
+const IIR_CTI: u8 = 0x0c;
+const IIR_THRI: u8 = 0x02;
+const IIR_MSI: u8 = 0x00;
+const IIR_NO_INT: u8 = 0x01;
+const IIR_ID_MASK: u8 = 0x0f;
+const IIR_FIFOS: u8 = 0xc0; // FIFOs enabled
+
+// FCR bits
+const FCR_ENABLE: u8 = 0x01;
+const FCR_CLEAR_RX: u8 = 0x02;
+const FCR_CLEAR_TX: u8 = 0x04;
+const FCR_TRIGGER: u8 = 0xc0;
+
+// LCR bits
+const LCR_DLAB: u8 = 0x80;
+
+// MCR bits
+const MCR_LOOP: u8 = 0x10;
+
+// LSR bits
+const LSR_DR: u8 = 0x01; // Data ready
+const LSR_OE: u8 = 0x02; // Overrun error
+const LSR_THRE: u8 = 0x20; // THR empty
+const LSR_TEMT: u8 = 0x40; // Transmitter empty
+
+// MSR bits: deltas in the low nibble, line states in the high one
+const MSR_DELTAS: u8 = 0x0f;
+const MSR_TERI: u8 = 0x04; // RI went low
+const MSR_CTS: u8 = 0x10;
+const MSR_DSR: u8 = 0x20;
+const MSR_RI: u8 = 0x40;
+const MSR_DCD: u8 = 0x80;
+
 /// RX path state (host → guest)
 struct RxState {
-    /// Input FIFO (keyboard/serial input from host)
+    /// Receive FIFO the guest reads through RBR
     fifo: VecDeque<u8>,
+    /// Host input (keyboard/serial) not yet received
+    backlog: VecDeque<u8>,
 }
 
 /// TX path state (guest → host)
@@ -50,6 +116,7 @@ impl RxState {
     fn new() -> Self {
         Self {
             fifo: VecDeque::new(),
+            backlog: VecDeque::new(),
         }
     }
 }
@@ -67,18 +134,66 @@ impl UartRegs {
     fn new() -> Self {
         Self {
             ier: 0x00,
-            iir: 0x01, // No interrupt pending
+            iir: IIR_NO_INT,
             fcr: 0x00,
             lcr: 0x00,
             mcr: 0x00,
-            lsr: 0x60, // TX empty
-            msr: 0x00,
+            lsr: LSR_THRE | LSR_TEMT,
+            msr: Self::modem_lines(0x00),
             scr: 0x00,
             dll: 0x00,
             dlm: 0x00,
             interrupting: false,
         }
     }
+
+    fn fifos_enabled(&self) -> bool {
+        self.fcr & FCR_ENABLE != 0
+    }
+
+    fn loopback(&self) -> bool {
+        self.mcr & MCR_LOOP != 0
+    }
+
+    /// Bytes the RX FIFO holds.
+    fn rx_depth(&self) -> usize {
+        if self.fifos_enabled() { FIFO_DEPTH } else { 1 }
+    }
+
+    /// Bytes in the RX FIFO that raise a received data interrupt.
+    fn rx_trigger(&self) -> usize {
+        if !self.fifos_enabled() {
+            return 1;
+        }
+        match self.fcr >> 6 {
+            0 => 1,
+            1 => 4,
+            2 => 8,
+            _ => 14,
+        }
+    }
+
+    /// MSR line states for modem control `mcr`: the MCR outputs in
+    /// loopback, otherwise a peer that is always there and ready.
+    fn modem_lines(mcr: u8) -> u8 {
+        if mcr & MCR_LOOP == 0 {
+            return MSR_CTS | MSR_DSR | MSR_DCD;
+        }
+        let mut lines = 0;
+        if mcr & 0x01 != 0 {
+            lines |= MSR_DSR; // DTR
+        }
+        if mcr & 0x02 != 0 {
+            lines |= MSR_CTS; // RTS
+        }
+        if mcr & 0x04 != 0 {
+            lines |= MSR_RI; // OUT1
+        }
+        if mcr & 0x08 != 0 {
+            lines |= MSR_DCD; // OUT2
+        }
+        lines
+    }
 }
 
 pub struct Uart {
@@ -101,26 +216,81 @@ impl Uart {
         }
     }
 
-    /// Internal helper to update interrupt state
+    /// Move host input into the RX FIFO while it has room, or back out of
+    /// it if the FIFO shrank. The line is disconnected in loopback.
+    fn refill_rx(regs: &UartRegs, rx: &mut RxState) {
+        let depth = regs.rx_depth();
+        while rx.fifo.len() > depth {
+            let byte = rx.fifo.pop_back().unwrap();
+            rx.backlog.push_front(byte);
+        }
+        if regs.loopback() {
+            return;
+        }
+        while rx.fifo.len() < depth {
+            match rx.backlog.pop_front() {
+                Some(byte) => rx.fifo.push_back(byte),
+                None => break,
+            }
+        }
+    }
+
+    /// Internal helper to update LSR and the interrupt state
     /// Lock order convention: regs must be locked first, then rx, then tx
-    fn update_interrupts_internal(regs: &mut UartRegs, _rx: &RxState, tx: &TxState) {
-        regs.interrupting = false;
-        regs.iir = 0x01; // No interrupt pending
+    fn update_interrupts_internal(regs: &mut UartRegs, rx: &RxState, tx: &TxState) {
+        regs.lsr = (regs.lsr & LSR_OE) | LSR_THRE | LSR_TEMT;
+        if !rx.fifo.is_empty() {
+            regs.lsr |= LSR_DR;
+        }
 
-        // Priority 1: Receiver Line Status (not implemented extensively)
+        let id = if regs.ier & IER_RLSI != 0 && regs.lsr & LSR_OE != 0 {
+            IIR_RLSI
+        } else if regs.ier & IER_RDI != 0 && rx.fifo.len() >= regs.rx_trigger() {
+            IIR_RDI
+        } else if regs.ier & IER_RDI != 0 && !rx.fifo.is_empty() {
+            IIR_CTI
+        } else if regs.ier & IER_THRI != 0 && tx.thre_ip {
+            IIR_THRI
+        } else if regs.ier & IER_MSI != 0 && regs.msr & MSR_DELTAS != 0 {
+            IIR_MSI
+        } else {
+            IIR_NO_INT
+        };
 
-        // Priority 2: Received Data Available
-        if (regs.lsr & 0x01) != 0 && (regs.ier & 0x01) != 0 {
-            regs.interrupting = true;
-            regs.iir = 0x04;
-            return;
+        regs.interrupting = id != IIR_NO_INT;
+        regs.iir = id;
+        if regs.fifos_enabled() {
+            regs.iir |= IIR_FIFOS;
         }
+    }
+
+    /// Drive the modem status lines from modem control `mcr`, latching the
+    /// changes in the MSR delta bits.
+    fn set_modem_lines(regs: &mut UartRegs, mcr: u8) {
+        let old = regs.msr & !MSR_DELTAS;
+        let new = UartRegs::modem_lines(mcr);
+        let changed = old ^ new;
+        let mut deltas = (changed >> 4) & (MSR_DELTAS & !MSR_TERI);
+        if old & MSR_RI != 0 && new & MSR_RI == 0 {
+            deltas |= MSR_TERI;
+        }
+        regs.msr = new | (regs.msr & MSR_DELTAS) | deltas;
+    }
 
-        // Priority 3: THRE
-        if tx.thre_ip && (regs.ier & 0x02) != 0 {
-            regs.interrupting = true;
-            regs.iir = 0x02;
+    /// Put the registers back to their power-on values, as on a machine
+    /// reset. Host input not yet read and output not yet drained are kept.
+    pub fn reset(&self) {
+        let mut regs = self.regs.lock().unwrap();
+        let mut rx = self.rx.lock().unwrap();
+        let mut tx = self.tx.lock().unwrap();
+
+        *regs = UartRegs::new();
+        while let Some(byte) = rx.fifo.pop_back() {
+            rx.backlog.push_front(byte);
         }
+        tx.thre_ip = true;
+        Self::refill_rx(&regs, &mut rx);
+        Self::update_interrupts_internal(&mut regs, &rx, &tx);
     }
 
     /// Check if the UART is currently signaling an interrupt (only locks regs)
@@ -130,9 +300,11 @@ impl Uart {
 
     // Snapshot support methods
 
-    /// Get input FIFO contents for snapshot
+    /// Get input contents for snapshot: the RX FIFO, then host input not
+    /// yet received
     pub fn get_input(&self) -> Vec<u8> {
-        self.rx.lock().unwrap().fifo.iter().copied().collect()
+        let rx = self.rx.lock().unwrap();
+        rx.fifo.iter().chain(rx.backlog.iter()).copied().collect()
     }
 
     /// Get output FIFO contents for snapshot
@@ -154,21 +326,18 @@ impl Uart {
         )
     }
 
-    /// Restore input FIFO from snapshot
+    /// Restore input from snapshot
     pub fn set_input(&self, values: &[u8]) {
         let mut regs = self.regs.lock().unwrap();
         let mut rx = self.rx.lock().unwrap();
 
         rx.fifo.clear();
-        for &v in values {
-            rx.fifo.push_back(v);
-        }
-        // Update LSR data ready bit
-        if !rx.fifo.is_empty() {
-            regs.lsr |= 0x01;
-        } else {
-            regs.lsr &= !0x01;
-        }
+        rx.backlog.clear();
+        rx.backlog.extend(values);
+        Self::refill_rx(&regs, &mut rx);
+
+        let tx = self.tx.lock().unwrap();
+        Self::update_interrupts_internal(&mut regs, &rx, &tx);
     }
 
     /// Restore output FIFO from snapshot
@@ -195,7 +364,7 @@ impl Uart {
         dlm: u8,
     ) {
         let mut regs = self.regs.lock().unwrap();
-        let rx = self.rx.lock().unwrap();
+        let mut rx = self.rx.lock().unwrap();
         let tx = self.tx.lock().unwrap();
 
         regs.ier = ier;
@@ -204,10 +373,12 @@ impl Uart {
         regs.lcr = lcr;
         regs.mcr = mcr;
         regs.lsr = lsr;
-        regs.msr = msr;
+        // Line states follow MCR; older snapshots kept none
+        regs.msr = (msr & MSR_DELTAS) | UartRegs::modem_lines(mcr);
         regs.scr = scr;
         regs.dll = dll;
         regs.dlm = dlm;
+        Self::refill_rx(&regs, &mut rx);
         Self::update_interrupts_internal(&mut regs, &rx, &tx);
     }
 
@@ -219,18 +390,14 @@ impl Uart {
         match offset {
             RBR => {
                 let mut regs = self.regs.lock().unwrap();
-                if (regs.lcr & 0x80) != 0 {
+                if (regs.lcr & LCR_DLAB) != 0 {
                     // DLAB mode: return DLL
                     Ok(regs.dll as u64)
                 } else {
-                    // Normal mode: read from RX FIFO
+                    // Normal mode: read from RX FIFO and let more input in
                     let mut rx = self.rx.lock().unwrap();
                     let byte = rx.fifo.pop_front().unwrap_or(0);
-
-                    // Update LSR based on FIFO state
-                    if rx.fifo.is_empty() {
-                        regs.lsr &= !0x01; // Clear Data Ready
-                    }
+                    Self::refill_rx(&regs, &mut rx);
 
                     let tx = self.tx.lock().unwrap();
                     Self::update_interrupts_internal(&mut regs, &rx, &tx);
@@ -239,7 +406,7 @@ impl Uart {
             }
             IER => {
                 let regs = self.regs.lock().unwrap();
-                if (regs.lcr & 0x80) != 0 {
+                if (regs.lcr & LCR_DLAB) != 0 {
                     Ok(regs.dlm as u64)
                 } else {
                     Ok(regs.ier as u64)
@@ -248,8 +415,8 @@ impl Uart {
             IIR => {
                 let mut regs = self.regs.lock().unwrap();
                 let val = regs.iir;
-                // Clear THRE interrupt if it was the pending one
-                if (val & 0x0F) == 0x02 {
+                // Reading IIR acknowledges a THR empty interrupt
+                if (val & IIR_ID_MASK) == IIR_THRI {
                     let rx = self.rx.lock().unwrap();
                     let mut tx = self.tx.lock().unwrap();
                     tx.thre_ip = false;
@@ -262,8 +429,30 @@ impl Uart {
             }
             LCR => Ok(self.regs.lock().unwrap().lcr as u64),
             MCR => Ok(self.regs.lock().unwrap().mcr as u64),
-            LSR => Ok(self.regs.lock().unwrap().lsr as u64),
-            MSR => Ok(self.regs.lock().unwrap().msr as u64),
+            LSR => {
+                // Reading LSR clears the overrun error
+                let mut regs = self.regs.lock().unwrap();
+                let val = regs.lsr;
+                if val & LSR_OE != 0 {
+                    regs.lsr &= !LSR_OE;
+                    let rx = self.rx.lock().unwrap();
+                    let tx = self.tx.lock().unwrap();
+                    Self::update_interrupts_internal(&mut regs, &rx, &tx);
+                }
+                Ok(val as u64)
+            }
+            MSR => {
+                // Reading MSR clears the deltas
+                let mut regs = self.regs.lock().unwrap();
+                let val = regs.msr;
+                if val & MSR_DELTAS != 0 {
+                    regs.msr &= !MSR_DELTAS;
+                    let rx = self.rx.lock().unwrap();
+                    let tx = self.tx.lock().unwrap();
+                    Self::update_interrupts_internal(&mut regs, &rx, &tx);
+                }
+                Ok(val as u64)
+            }
             SCR => Ok(self.regs.lock().unwrap().scr as u64),
             _ => Ok(0),
         }
@@ -278,10 +467,9 @@ impl Uart {
         match offset {
             THR => {
                 let mut regs = self.regs.lock().unwrap();
-                if (regs.lcr & 0x80) != 0 {
+                if (regs.lcr & LCR_DLAB) != 0 {
                     regs.dll = val;
                 } else {
-                    // Write to TX FIFO
                     log::trace!(
                         "[UART] TX '{}' (0x{:02x})",
                         if val.is_ascii_graphic() {
@@ -291,12 +479,20 @@ impl Uart {
                         },
                         val
                     );
-                    let rx = self.rx.lock().unwrap();
+                    let mut rx = self.rx.lock().unwrap();
                     let mut tx = self.tx.lock().unwrap();
-                    tx.fifo.push_back(val);
+                    if regs.loopback() {
+                        // Looped back into a full FIFO, the byte is lost
+                        if rx.fifo.len() < regs.rx_depth() {
+                            rx.fifo.push_back(val);
+                        } else {
+                            regs.lsr |= LSR_OE;
+                        }
+                    } else {
+                        tx.fifo.push_back(val);
+                    }
 
                     // THR is instantly "transmitted", so THRE stays set
-                    regs.lsr |= 0x20;
                     tx.thre_ip = true; // Re-assert THRE interrupt
 
                     Self::update_interrupts_internal(&mut regs, &rx, &tx);
@@ -304,34 +500,48 @@ impl Uart {
             }
             IER => {
                 let mut regs = self.regs.lock().unwrap();
-                if (regs.lcr & 0x80) != 0 {
+                if (regs.lcr & LCR_DLAB) != 0 {
                     regs.dlm = val;
                 } else {
-                    regs.ier = val;
                     let rx = self.rx.lock().unwrap();
-                    let tx = self.tx.lock().unwrap();
+                    let mut tx = self.tx.lock().unwrap();
+                    // Enabling the THR empty interrupt while THR is empty
+                    // raises it, which drivers use to kick off transmission
+                    if val & IER_THRI != 0 && regs.ier & IER_THRI == 0 {
+                        tx.thre_ip = true;
+                    }
+                    regs.ier = val & 0x0f;
                     Self::update_interrupts_internal(&mut regs, &rx, &tx);
                 }
             }
             FCR => {
                 let mut regs = self.regs.lock().unwrap();
-                regs.fcr = val;
+                let mut rx = self.rx.lock().unwrap();
+                let mut tx = self.tx.lock().unwrap();
 
-                if (val & 0x02) != 0 {
-                    // Clear RX FIFO
-                    let mut rx = self.rx.lock().unwrap();
+                if (val & FCR_CLEAR_RX) != 0 {
                     rx.fifo.clear();
-                    regs.lsr &= !0x01;
+                    rx.backlog.clear();
                 }
-                if (val & 0x04) != 0 {
-                    // Clear TX FIFO
-                    let mut tx = self.tx.lock().unwrap();
+                if (val & FCR_CLEAR_TX) != 0 {
                     tx.fifo.clear();
-                    regs.lsr |= 0x60;
+                    tx.thre_ip = true;
                 }
+                // The clear bits are self-clearing
+                regs.fcr = val & (FCR_ENABLE | FCR_TRIGGER);
+                Self::refill_rx(&regs, &mut rx);
+                Self::update_interrupts_internal(&mut regs, &rx, &tx);
             }
             LCR => self.regs.lock().unwrap().lcr = val,
-            MCR => self.regs.lock().unwrap().mcr = val,
+            MCR => {
+                let mut regs = self.regs.lock().unwrap();
+                let mut rx = self.rx.lock().unwrap();
+                let tx = self.tx.lock().unwrap();
+                regs.mcr = val & 0x1f;
+                Self::set_modem_lines(&mut regs, val);
+                Self::refill_rx(&regs, &mut rx);
+                Self::update_interrupts_internal(&mut regs, &rx, &tx);
+            }
             LSR => {
                 // Usually read-only, but factory test mode might write. Ignore.
             }
@@ -348,11 +558,19 @@ impl Uart {
 
     /// Push input byte from host (lock-free for TX path)
     pub fn push_input(&self, byte: u8) {
+        self.push_input_bytes(&[byte]);
+    }
+
+    /// Push a run of input bytes from host, taking the locks once
+    pub fn push_input_bytes(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
         let mut regs = self.regs.lock().unwrap();
         let mut rx = self.rx.lock().unwrap();
 
-        rx.fifo.push_back(byte);
-        regs.lsr |= 0x01; // Data Ready
+        rx.backlog.extend(bytes);
+        Self::refill_rx(&regs, &mut rx);
 
         let tx = self.tx.lock().unwrap();
         Self::update_interrupts_internal(&mut regs, &rx, &tx);
@@ -413,6 +631,18 @@ mod tests {
         assert_eq!(uart.pop_output(), None);
     }
 
+    #[test]
+    fn test_push_input_bytes() {
+        let uart = Uart::new();
+        uart.push_input_bytes(b"");
+        assert_eq!(uart.load(LSR, 1).unwrap() & 0x01, 0);
+
+        uart.push_input_bytes(b"ls\n");
+        assert_eq!(uart.load(LSR, 1).unwrap() & 0x01, 1);
+        assert_eq!(uart.get_input(), b"ls\n");
+        assert_eq!(uart.load(RBR, 1).unwrap(), b'l' as u64);
+    }
+
     #[test]
     fn test_concurrent_input_output() {
         let uart = Arc::new(Uart::new());
@@ -589,4 +819,111 @@ mod tests {
         assert_eq!(uart2.get_output(), vec![b'B']);
         assert_eq!(uart2.load(SCR, 1).unwrap(), 0x55);
     }
+
+    #[test]
+    fn test_rx_fifo_trigger_and_timeout() {
+        let uart = Uart::new();
+        // FIFOs on, trigger at 4 bytes, received data interrupts enabled
+        uart.store(FCR, 1, 0x41).unwrap();
+        uart.store(IER, 1, IER_RDI as u64).unwrap();
+        assert_eq!(uart.load(IIR, 1).unwrap(), 0xc1);
+
+        uart.push_input_bytes(b"abc");
+        // Below the trigger level: character timeout
+        assert_eq!(uart.load(IIR, 1).unwrap(), 0xcc);
+        uart.push_input(b'd');
+        assert_eq!(uart.load(IIR, 1).unwrap(), 0xc4);
+        assert!(uart.is_interrupting());
+
+        for &b in b"abcd" {
+            assert_eq!(uart.load(RBR, 1).unwrap(), b as u64);
+        }
+        assert_eq!(uart.load(LSR, 1).unwrap() & LSR_DR as u64, 0);
+        assert!(!uart.is_interrupting());
+    }
+
+    #[test]
+    fn test_host_input_waits_for_fifo_room() {
+        let uart = Uart::new();
+        uart.store(FCR, 1, 0xc1).unwrap();
+        let input: Vec<u8> = (0..40).collect();
+        uart.push_input_bytes(&input);
+        assert_eq!(uart.rx.lock().unwrap().fifo.len(), FIFO_DEPTH);
+
+        // Nothing is lost or overrun
+        for &b in &input {
+            assert_eq!(uart.load(RBR, 1).unwrap(), b as u64);
+        }
+        assert_eq!(uart.load(LSR, 1).unwrap(), 0x60);
+
+        // Without FIFOs the holding register takes one byte at a time
+        uart.store(FCR, 1, 0).unwrap();
+        uart.push_input_bytes(b"xy");
+        assert_eq!(uart.rx.lock().unwrap().fifo.len(), 1);
+        assert_eq!(uart.get_input(), b"xy");
+    }
+
+    #[test]
+    fn test_thre_interrupt() {
+        let uart = Uart::new();
+        // Enabling the interrupt with THR empty raises it
+        uart.store(IER, 1, IER_THRI as u64).unwrap();
+        assert!(uart.is_interrupting());
+        // Reading IIR acknowledges it
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_THRI as u64);
+        assert!(!uart.is_interrupting());
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_NO_INT as u64);
+
+        // Each transmitted byte leaves THR empty again
+        uart.store(THR, 1, b'!' as u64).unwrap();
+        assert!(uart.is_interrupting());
+        assert_eq!(uart.pop_output(), Some(b'!'));
+
+        // Received data takes priority
+        uart.store(IER, 1, (IER_RDI | IER_THRI) as u64).unwrap();
+        uart.push_input(b'a');
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_RDI as u64);
+        uart.load(RBR, 1).unwrap();
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_THRI as u64);
+    }
+
+    #[test]
+    fn test_loopback() {
+        let uart = Uart::new();
+        uart.push_input(b'h');
+        // Loopback with DTR and OUT2, as 8250 drivers probe for
+        uart.store(MCR, 1, 0x19).unwrap();
+        assert_eq!(uart.load(MSR, 1).unwrap() & 0xf0, 0xa0);
+
+        // The host byte already received is still there; THR loops back
+        assert_eq!(uart.load(RBR, 1).unwrap(), b'h' as u64);
+        uart.store(THR, 1, b'x' as u64).unwrap();
+        assert!(!uart.has_output());
+        // A second byte overruns the one-byte holding register
+        uart.store(IER, 1, IER_RLSI as u64).unwrap();
+        uart.store(THR, 1, b'y' as u64).unwrap();
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_RLSI as u64);
+        assert_ne!(uart.load(LSR, 1).unwrap() & LSR_OE as u64, 0);
+        assert_eq!(uart.load(LSR, 1).unwrap() & LSR_OE as u64, 0);
+        assert_eq!(uart.load(RBR, 1).unwrap(), b'x' as u64);
+
+        // Back to normal: the peer is always ready
+        uart.store(MCR, 1, 0x03).unwrap();
+        assert_eq!(uart.load(MSR, 1).unwrap() & 0xf0, 0xb0);
+    }
+
+    #[test]
+    fn test_modem_status_interrupt() {
+        let uart = Uart::new();
+        uart.store(MCR, 1, MCR_LOOP as u64).unwrap();
+        uart.load(MSR, 1).unwrap();
+        uart.store(IER, 1, IER_MSI as u64).unwrap();
+        assert!(!uart.is_interrupting());
+
+        // RTS drives CTS
+        uart.store(MCR, 1, (MCR_LOOP | 0x02) as u64).unwrap();
+        assert_eq!(uart.load(IIR, 1).unwrap(), IIR_MSI as u64);
+        assert_eq!(uart.load(MSR, 1).unwrap(), (MSR_CTS | 0x01) as u64);
+        assert!(!uart.is_interrupting());
+    }
 }
@@ -1,8 +1,21 @@
+//! VirtIO block device.
+//!
+//! Queue notifications only parse the descriptor chains; the backend I/O
+//! runs off the CPU loop (on a worker thread natively, and in budgeted
+//! slices from `poll` under WASM, which has no threads to spare). Finished
+//! requests are written back to the used ring from `poll`, which raises the
+//! device's PLIC line, so a large read does not stall the hart that issued
+//! it.
+
 use crate::bus::DRAM_BASE;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::devices::worker::{DeviceLatencyHandle, DeviceWorker};
+use crate::disk::{BlockBackend, MemoryDisk, SECTOR_SIZE};
 use crate::dram::{Dram, MemoryError};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use super::device::{self, VirtioDevice};
+use super::device::{self, QueueState, VirtioDevice, VirtioState};
 
 /// Internal mutable state for VirtioBlock, protected by Mutex
 struct VirtioBlockState {
@@ -18,17 +31,186 @@ struct VirtioBlockState {
     queue_ready: bool,
     interrupt_status: u32,
     status: u32,
-    disk: Vec<u8>,
+    /// Disk size in bytes, cached so config reads don't wait on the worker
+    capacity: u64,
+    read_only: bool,
+    io: IoWorker,
+    /// Finish every request by the next poll (see `set_synchronous`)
+    synchronous: bool,
+    /// Bumped on device reset so completions of older requests are dropped
+    generation: u32,
     last_avail_idx: u16,
     debug: bool,
 }
 
+// Request types and completion status codes (VirtIO spec 5.2.6)
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+enum BlockOp {
+    Read {
+        offset: u64,
+        len: u32,
+    },
+    Write {
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Flush,
+    /// Completed with the given status without touching the backend
+    Fail(u8),
+}
+
+/// A parsed descriptor chain waiting for the backend.
+struct BlockRequest {
+    generation: u32,
+    head: u16,
+    op: BlockOp,
+    data_addr: u64,
+    status_addr: u64,
+}
+
+struct BlockCompletion {
+    generation: u32,
+    head: u16,
+    status: u8,
+    /// Bytes read, to be copied to `data_addr`
+    data: Vec<u8>,
+    data_addr: u64,
+    status_addr: u64,
+}
+
+impl BlockRequest {
+    fn execute(self, disk: &mut dyn BlockBackend) -> BlockCompletion {
+        let status_of = |result: std::io::Result<()>| match result {
+            Ok(()) => VIRTIO_BLK_S_OK,
+            Err(_) => VIRTIO_BLK_S_IOERR,
+        };
+        let (status, data) = match self.op {
+            BlockOp::Read { offset, len } => {
+                let mut data = vec![0u8; len as usize];
+                match disk.read_at(offset, &mut data) {
+                    Ok(()) => (VIRTIO_BLK_S_OK, data),
+                    Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            }
+            BlockOp::Write { offset, data } => {
+                (status_of(disk.write_at(offset, &data)), Vec::new())
+            }
+            BlockOp::Flush => (status_of(disk.flush()), Vec::new()),
+            BlockOp::Fail(status) => (status, Vec::new()),
+        };
+        BlockCompletion {
+            generation: self.generation,
+            head: self.head,
+            status,
+            data,
+            data_addr: self.data_addr,
+            status_addr: self.status_addr,
+        }
+    }
+}
+
+/// Runs backend I/O on a dedicated device worker thread.
+#[cfg(not(target_arch = "wasm32"))]
+struct IoWorker {
+    worker: DeviceWorker<BlockRequest, BlockCompletion>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl IoWorker {
+    fn new(mut disk: Box<dyn BlockBackend>) -> Self {
+        // Requests run in submission order, so a FLUSH covers every write
+        // queued before it
+        let worker = DeviceWorker::spawn("virtio-blk-io", move |request: BlockRequest| {
+            request.execute(disk.as_mut())
+        })
+        .expect("Failed to spawn block I/O thread");
+        Self { worker }
+    }
+
+    fn submit(&mut self, request: BlockRequest) {
+        // The worker only stops once we are gone
+        self.worker.submit(request);
+    }
+
+    fn completed(&mut self) -> Vec<BlockCompletion> {
+        self.worker.completed()
+    }
+
+    fn wait_all(&mut self, in_flight: u32) -> Vec<BlockCompletion> {
+        self.worker.wait(in_flight as usize)
+    }
+}
+
+/// Runs backend I/O from `poll`, a bounded amount per call.
+#[cfg(target_arch = "wasm32")]
+struct IoWorker {
+    disk: Box<dyn BlockBackend>,
+    pending: std::collections::VecDeque<BlockRequest>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl IoWorker {
+    /// Bytes transferred per poll before yielding back to the CPU
+    const POLL_BUDGET: usize = 64 * 1024;
+
+    fn new(disk: Box<dyn BlockBackend>) -> Self {
+        Self {
+            disk,
+            pending: std::collections::VecDeque::new(),
+        }
+    }
+
+    fn submit(&mut self, request: BlockRequest) {
+        self.pending.push_back(request);
+    }
+
+    fn completed(&mut self) -> Vec<BlockCompletion> {
+        let mut done = Vec::new();
+        let mut budget = Self::POLL_BUDGET;
+        while budget > 0 {
+            let Some(request) = self.pending.pop_front() else {
+                break;
+            };
+            budget = budget.saturating_sub(match &request.op {
+                BlockOp::Read { len, .. } => *len as usize,
+                BlockOp::Write { data, .. } => data.len(),
+                _ => 0,
+            });
+            done.push(request.execute(self.disk.as_mut()));
+        }
+        done
+    }
+
+    fn wait_all(&mut self, _in_flight: u32) -> Vec<BlockCompletion> {
+        let disk = self.disk.as_mut();
+        self.pending
+            .drain(..)
+            .map(|request| request.execute(disk))
+            .collect()
+    }
+}
+
 pub struct VirtioBlock {
     state: Mutex<VirtioBlockState>,
+    /// Requests handed to the I/O worker and not yet completed, so `poll`
+    /// can skip the state lock while the disk is idle
+    in_flight: AtomicU32,
 }
 
 impl VirtioBlock {
+    /// Serve an in-memory image; guest writes are lost when the VM exits.
     pub fn new(disk_image: Vec<u8>) -> Self {
+        Self::with_backend(Box::new(MemoryDisk::new(disk_image)))
+    }
+
+    /// Serve any block backend, e.g. a host file from [`crate::disk::open`].
+    pub fn with_backend(disk: Box<dyn BlockBackend>) -> Self {
         Self {
             state: Mutex::new(VirtioBlockState {
                 driver_features: 0,
@@ -43,13 +225,24 @@ impl VirtioBlock {
                 queue_ready: false,
                 interrupt_status: 0,
                 status: 0,
-                disk: disk_image,
+                capacity: disk.len(),
+                read_only: disk.is_read_only(),
+                io: IoWorker::new(disk),
+                synchronous: false,
+                generation: 0,
                 last_avail_idx: 0,
                 debug: false,
             }),
+            in_flight: AtomicU32::new(0),
         }
     }
 
+    /// Guest-visible latency of this disk's requests.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn latency_handle(&self) -> DeviceLatencyHandle {
+        self.state.lock().unwrap().io.worker.latency_handle()
+    }
+
     fn phys_to_offset(addr: u64) -> Result<u64, MemoryError> {
         if addr < DRAM_BASE {
             return Err(MemoryError::OutOfBounds(addr));
@@ -57,37 +250,44 @@ impl VirtioBlock {
         Ok(addr - DRAM_BASE)
     }
 
-    fn process_queue(state: &mut VirtioBlockState, dram: &Dram) -> Result<(), MemoryError> {
+    fn queue_size(state: &VirtioBlockState) -> u32 {
+        if state.queue_num > 0 {
+            state.queue_num
+        } else {
+            device::QUEUE_SIZE
+        }
+    }
+
+    /// Hand every newly available request to the I/O worker.
+    fn process_queue(
+        state: &mut VirtioBlockState,
+        dram: &Dram,
+        in_flight: &AtomicU32,
+    ) -> Result<(), MemoryError> {
         let avail_idx_addr = state.queue_avail.wrapping_add(2);
         let avail_idx = dram.load_16(Self::phys_to_offset(avail_idx_addr)?)? as u16;
 
-        let mut processed_any = false;
         while state.last_avail_idx != avail_idx {
-            let qsz = if state.queue_num > 0 {
-                state.queue_num
-            } else {
-                device::QUEUE_SIZE
-            };
+            let qsz = Self::queue_size(state);
             let ring_slot = (state.last_avail_idx as u32 % qsz) as u64;
             let head_idx_addr = state
                 .queue_avail
                 .wrapping_add(4)
                 .wrapping_add(ring_slot * 2);
             let head_desc_idx = dram.load_16(Self::phys_to_offset(head_idx_addr)?)? as u16;
+            state.last_avail_idx = state.last_avail_idx.wrapping_add(1);
 
-            let desc_idx = head_desc_idx;
-
-            let desc_addr0 = state.queue_desc.wrapping_add((desc_idx as u64) * 16);
+            let desc_addr0 = state.queue_desc.wrapping_add((head_desc_idx as u64) * 16);
             let off_desc_addr0 = Self::phys_to_offset(desc_addr0)?;
             let header_addr = dram.load_64(off_desc_addr0)?;
             let header_len = dram.load_32(off_desc_addr0 + 8)?;
             let header_flags = dram.load_16(off_desc_addr0 + 12)? as u64;
-            let mut next_desc_idx = dram.load_16(off_desc_addr0 + 14)?;
+            let next_desc_idx = dram.load_16(off_desc_addr0 + 14)?;
 
-            if header_len < 16 {
-                // Consume malformed descriptor to avoid loop
-                state.last_avail_idx = state.last_avail_idx.wrapping_add(1);
-                processed_any = true;
+            if header_len < 16 || (header_flags & device::VRING_DESC_F_NEXT) == 0 {
+                // Malformed chain with nowhere to put a status; return it
+                // as used straight away so the driver doesn't hang
+                Self::push_used(state, dram, head_desc_idx, 0)?;
                 continue;
             }
 
@@ -96,68 +296,109 @@ impl VirtioBlock {
             let _blk_reserved = dram.load_32(off_header_addr + 4)?;
             let blk_sector = dram.load_64(off_header_addr + 8)?;
 
-            let mut data_len_done: u32 = 0;
-
-            if (header_flags & device::VRING_DESC_F_NEXT) != 0 {
-                let desc2_addr = state.queue_desc.wrapping_add((next_desc_idx as u64) * 16);
-                let off_desc2_addr = Self::phys_to_offset(desc2_addr)?;
-                let data_addr = dram.load_64(off_desc2_addr)?;
-                let data_len = dram.load_32(off_desc2_addr + 8)?;
-                let flags2 = dram.load_16(off_desc2_addr + 12)? as u64;
-                next_desc_idx = dram.load_16(off_desc2_addr + 14)?;
-
-                if blk_type == 0 {
-                    // IN (Read)
-                    let offset = blk_sector * 512;
-                    if offset + (data_len as u64) <= state.disk.len() as u64 {
-                        let slice =
-                            &state.disk[offset as usize..(offset as usize + data_len as usize)];
-                        let dram_off = Self::phys_to_offset(data_addr)?;
-                        dram.write_bytes(dram_off, slice)?;
-                        data_len_done = data_len as u32;
-                    }
-                } else if blk_type == 1 {
-                    // OUT (Write) - use bulk read from DRAM for performance
-                    let offset = blk_sector * 512;
-                    if offset + (data_len as u64) <= state.disk.len() as u64 {
-                        let dram_off = Self::phys_to_offset(data_addr)?;
-                        let src = dram.read_range(dram_off as usize, data_len as usize)?;
-                        state.disk[offset as usize..offset as usize + data_len as usize]
-                            .copy_from_slice(&src);
-                        data_len_done = data_len as u32;
-                    }
-                }
+            let desc2_addr = state.queue_desc.wrapping_add((next_desc_idx as u64) * 16);
+            let off_desc2_addr = Self::phys_to_offset(desc2_addr)?;
+            let desc2_buf = dram.load_64(off_desc2_addr)?;
+            let desc2_len = dram.load_32(off_desc2_addr + 8)?;
+            let flags2 = dram.load_16(off_desc2_addr + 12)? as u64;
+            let desc3_idx = dram.load_16(off_desc2_addr + 14)?;
 
-                if (flags2 & device::VRING_DESC_F_NEXT) != 0 {
-                    let desc3_addr = state.queue_desc.wrapping_add((next_desc_idx as u64) * 16);
-                    let off_desc3_addr = Self::phys_to_offset(desc3_addr)?;
-                    let status_addr = dram.load_64(off_desc3_addr)?;
-                    dram.store_8(Self::phys_to_offset(status_addr)?, 0)?; // Status: OK
+            // header -> data -> status, or header -> status for requests
+            // without data (FLUSH)
+            let (data_addr, data_len, status_addr) = if (flags2 & device::VRING_DESC_F_NEXT) != 0 {
+                let desc3_addr = state.queue_desc.wrapping_add((desc3_idx as u64) * 16);
+                let status_addr = dram.load_64(Self::phys_to_offset(desc3_addr)?)?;
+                (desc2_buf, desc2_len, status_addr)
+            } else {
+                (0, 0, desc2_buf)
+            };
+
+            let offset = blk_sector * SECTOR_SIZE;
+            let op = match blk_type {
+                VIRTIO_BLK_T_IN if data_len > 0 => BlockOp::Read {
+                    offset,
+                    len: data_len,
+                },
+                VIRTIO_BLK_T_OUT if data_len > 0 => {
+                    // Bulk read from DRAM for performance
+                    let dram_off = Self::phys_to_offset(data_addr)?;
+                    let data = dram.read_range(dram_off as usize, data_len as usize)?;
+                    BlockOp::Write { offset, data }
                 }
-            }
+                VIRTIO_BLK_T_FLUSH => BlockOp::Flush,
+                VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => BlockOp::Fail(VIRTIO_BLK_S_IOERR),
+                _ => BlockOp::Fail(VIRTIO_BLK_S_UNSUPP),
+            };
 
-            let used_idx_addr = state.queue_used.wrapping_add(2);
-            let mut used_idx = dram.load_16(Self::phys_to_offset(used_idx_addr)?)? as u16;
-            let elem_addr = state
-                .queue_used
-                .wrapping_add(4)
-                .wrapping_add((used_idx as u64 % qsz as u64) * 8);
-            let off_elem_addr = Self::phys_to_offset(elem_addr)?;
-            dram.store_32(off_elem_addr, head_desc_idx as u64)?;
-            dram.store_32(off_elem_addr + 4, data_len_done as u64)?;
-            used_idx = used_idx.wrapping_add(1);
-            dram.store_16(Self::phys_to_offset(used_idx_addr)?, used_idx as u64)?;
+            let request = BlockRequest {
+                generation: state.generation,
+                head: head_desc_idx,
+                op,
+                data_addr,
+                status_addr,
+            };
+            state.io.submit(request);
+            in_flight.fetch_add(1, Ordering::AcqRel);
+        }
 
-            state.last_avail_idx = state.last_avail_idx.wrapping_add(1);
-            processed_any = true;
+        Ok(())
+    }
+
+    /// Write finished requests back to the guest and raise the interrupt.
+    fn complete_requests(
+        state: &mut VirtioBlockState,
+        dram: &Dram,
+        in_flight: &AtomicU32,
+    ) -> Result<(), MemoryError> {
+        let done = if state.synchronous {
+            state.io.wait_all(in_flight.load(Ordering::Acquire))
+        } else {
+            state.io.completed()
+        };
+        in_flight.fetch_sub(done.len() as u32, Ordering::AcqRel);
+        let mut completed_any = false;
+        for done in done {
+            if done.generation != state.generation {
+                // Issued before a device reset; the guest has moved on
+                continue;
+            }
+            if !done.data.is_empty() {
+                dram.write_bytes(Self::phys_to_offset(done.data_addr)?, &done.data)?;
+            }
+            dram.store_8(Self::phys_to_offset(done.status_addr)?, done.status as u64)?;
+            Self::push_used(state, dram, done.head, done.data.len() as u32)?;
+            completed_any = true;
         }
 
-        if processed_any {
+        if completed_any {
             state.interrupt_status |= 1;
         }
 
         Ok(())
     }
+
+    fn push_used(
+        state: &VirtioBlockState,
+        dram: &Dram,
+        head: u16,
+        len: u32,
+    ) -> Result<(), MemoryError> {
+        let qsz = Self::queue_size(state);
+        let used_idx_addr = state.queue_used.wrapping_add(2);
+        let used_idx = dram.load_16(Self::phys_to_offset(used_idx_addr)?)? as u16;
+        let elem_addr = state
+            .queue_used
+            .wrapping_add(4)
+            .wrapping_add((used_idx as u64 % qsz as u64) * 8);
+        let off_elem_addr = Self::phys_to_offset(elem_addr)?;
+        dram.store_32(off_elem_addr, head as u64)?;
+        dram.store_32(off_elem_addr + 4, len as u64)?;
+        dram.store_16(
+            Self::phys_to_offset(used_idx_addr)?,
+            used_idx.wrapping_add(1) as u64,
+        )?;
+        Ok(())
+    }
 }
 
 impl VirtioDevice for VirtioBlock {
@@ -179,7 +420,11 @@ impl VirtioDevice for VirtioBlock {
             device::VENDOR_ID_OFFSET => device::VENDOR_ID,
             device::DEVICE_FEATURES_OFFSET => {
                 if state.device_features_sel == 0 {
-                    1u64 << device::VIRTIO_BLK_F_FLUSH
+                    let mut features = 1u64 << device::VIRTIO_BLK_F_FLUSH;
+                    if state.read_only {
+                        features |= 1u64 << device::VIRTIO_BLK_F_RO;
+                    }
+                    features
                 } else {
                     0
                 }
@@ -203,10 +448,10 @@ impl VirtioDevice for VirtioBlock {
             device::CONFIG_GENERATION_OFFSET => 0,
             _ if offset >= 0x100 => {
                 if offset == 0x100 {
-                    let cap = state.disk.len() as u64 / 512;
+                    let cap = state.capacity / SECTOR_SIZE;
                     cap & 0xffff_ffff
                 } else if offset == 0x104 {
-                    let cap = state.disk.len() as u64 / 512;
+                    let cap = state.capacity / SECTOR_SIZE;
                     cap >> 32
                 } else {
                     0
@@ -217,6 +462,64 @@ impl VirtioDevice for VirtioBlock {
         Ok(val)
     }
 
+    fn poll(&self, dram: &Dram) -> Result<(), MemoryError> {
+        if self.in_flight.load(Ordering::Acquire) == 0 {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        Self::complete_requests(&mut state, dram, &self.in_flight)
+    }
+
+    fn set_synchronous(&self, synchronous: bool) {
+        self.state.lock().unwrap().synchronous = synchronous;
+    }
+
+    fn save_state(&self) -> Option<VirtioState> {
+        // Completions in flight would be lost; the caller drains them first
+        if self.in_flight.load(Ordering::Acquire) != 0 {
+            return None;
+        }
+        let state = self.state.lock().unwrap();
+        Some(VirtioState {
+            device_id: device::VIRTIO_BLK_DEVICE_ID,
+            driver_features: state.driver_features,
+            driver_features_sel: state.driver_features_sel,
+            device_features_sel: state.device_features_sel,
+            page_size: state.page_size,
+            queue_sel: state.queue_sel,
+            interrupt_status: state.interrupt_status,
+            status: state.status,
+            queues: vec![QueueState {
+                num: state.queue_num,
+                desc: state.queue_desc,
+                avail: state.queue_avail,
+                used: state.queue_used,
+                ready: state.queue_ready,
+                last_avail_idx: state.last_avail_idx,
+            }],
+        })
+    }
+
+    fn load_state(&self, saved: &VirtioState) -> Result<(), String> {
+        saved.check(device::VIRTIO_BLK_DEVICE_ID, 1)?;
+        let mut state = self.state.lock().unwrap();
+        state.driver_features = saved.driver_features;
+        state.driver_features_sel = saved.driver_features_sel;
+        state.device_features_sel = saved.device_features_sel;
+        state.page_size = saved.page_size;
+        state.queue_sel = saved.queue_sel;
+        state.interrupt_status = saved.interrupt_status;
+        state.status = saved.status;
+        let queue = &saved.queues[0];
+        state.queue_num = queue.num;
+        state.queue_desc = queue.desc;
+        state.queue_avail = queue.avail;
+        state.queue_used = queue.used;
+        state.queue_ready = queue.ready;
+        state.last_avail_idx = queue.last_avail_idx;
+        Ok(())
+    }
+
     fn write(&self, offset: u64, val: u64, dram: &Dram) -> Result<(), MemoryError> {
         let mut state = self.state.lock().unwrap();
         let val32 = val as u32;
@@ -265,7 +568,7 @@ impl VirtioDevice for VirtioBlock {
             }
             device::QUEUE_NOTIFY_OFFSET => {
                 if val32 == 0 {
-                    Self::process_queue(&mut state, dram)?;
+                    Self::process_queue(&mut state, dram, &self.in_flight)?;
                 }
             }
             device::INTERRUPT_ACK_OFFSET => {
@@ -278,6 +581,7 @@ impl VirtioDevice for VirtioBlock {
                     state.queue_ready = false;
                     state.interrupt_status = 0;
                     state.last_avail_idx = 0;
+                    state.generation = state.generation.wrapping_add(1);
                 } else {
                     state.status = val32;
                 }
@@ -308,3 +612,87 @@ impl VirtioDevice for VirtioBlock {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Guest memory layout for the tests
+    const DESC: u64 = DRAM_BASE;
+    const AVAIL: u64 = DRAM_BASE + 0x1000;
+    const USED: u64 = DRAM_BASE + 0x2000;
+    const HEADER: u64 = DRAM_BASE + 0x3000;
+    const STATUS: u64 = DRAM_BASE + 0x3100;
+    const DATA: u64 = DRAM_BASE + 0x4000;
+
+    fn off(addr: u64) -> u64 {
+        addr - DRAM_BASE
+    }
+
+    fn set_desc(dram: &Dram, idx: u64, addr: u64, len: u64, flags: u64, next: u64) {
+        let d = off(DESC) + idx * 16;
+        dram.store_64(d, addr).unwrap();
+        dram.store_32(d + 8, len).unwrap();
+        dram.store_16(d + 12, flags).unwrap();
+        dram.store_16(d + 14, next).unwrap();
+    }
+
+    /// Queue a 1024-byte read of sector 3 on a fresh device.
+    fn submit_read() -> (VirtioBlock, Dram) {
+        let image: Vec<u8> = (0..8192).map(|i| (i / 512) as u8).collect();
+        let blk = VirtioBlock::new(image);
+        let dram = Dram::new(DRAM_BASE, 1 << 20);
+        blk.write(device::QUEUE_NUM_OFFSET, 16, &dram).unwrap();
+        blk.write(device::QUEUE_DESC_LOW_OFFSET, DESC, &dram)
+            .unwrap();
+        blk.write(device::QUEUE_DRIVER_LOW_OFFSET, AVAIL, &dram)
+            .unwrap();
+        blk.write(device::QUEUE_DEVICE_LOW_OFFSET, USED, &dram)
+            .unwrap();
+        blk.write(device::QUEUE_READY_OFFSET, 1, &dram).unwrap();
+
+        // IN, sector 3, 1024 bytes
+        dram.store_32(off(HEADER), VIRTIO_BLK_T_IN as u64).unwrap();
+        dram.store_64(off(HEADER) + 8, 3).unwrap();
+        dram.store_8(off(STATUS), 0xff).unwrap();
+        let write = device::VRING_DESC_F_WRITE;
+        set_desc(&dram, 0, HEADER, 16, device::VRING_DESC_F_NEXT, 1);
+        set_desc(&dram, 1, DATA, 1024, device::VRING_DESC_F_NEXT | write, 2);
+        set_desc(&dram, 2, STATUS, 1, write, 0);
+        dram.store_16(off(AVAIL) + 4, 0).unwrap();
+        dram.store_16(off(AVAIL) + 2, 1).unwrap();
+        blk.write(device::QUEUE_NOTIFY_OFFSET, 0, &dram).unwrap();
+        (blk, dram)
+    }
+
+    #[test]
+    fn test_read_completes_from_poll() {
+        let (blk, dram) = submit_read();
+
+        // Nothing lands in guest memory until the device is polled
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while dram.load_16(off(USED) + 2).unwrap() == 0 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "request never completed"
+            );
+            blk.poll(&dram).unwrap();
+        }
+
+        assert!(blk.is_interrupting());
+        assert_eq!(dram.load_8(off(STATUS)).unwrap(), VIRTIO_BLK_S_OK);
+        assert_eq!(dram.load_32(off(USED) + 8).unwrap(), 1024);
+        let data = dram.read_range(off(DATA) as usize, 1024).unwrap();
+        assert!(data[..512].iter().all(|&b| b == 3));
+        assert!(data[512..].iter().all(|&b| b == 4));
+    }
+
+    #[test]
+    fn test_synchronous_read_completes_on_first_poll() {
+        let (blk, dram) = submit_read();
+        blk.set_synchronous(true);
+        blk.poll(&dram).unwrap();
+        assert_eq!(dram.load_16(off(USED) + 2).unwrap(), 1);
+        assert_eq!(dram.load_8(off(STATUS)).unwrap(), VIRTIO_BLK_S_OK);
+    }
+}
@@ -0,0 +1,497 @@
+//! VirtIO console: a second byte stream between the guest and the host.
+//!
+//! The UART carries the interactive console; this device gives the guest
+//! another channel, e.g. for raw log output or a machine-readable control
+//! protocol that a test harness drives. It offers a single port and no
+//! features: queue 0 (receiveq) holds guest buffers the device fills with
+//! host input, queue 1 (transmitq) holds guest output.
+//!
+//! The host side is a [`ConsolePort`], a pair of byte queues shared with
+//! whatever bridges the stream (see [`crate::console::bridge_port`]).
+
+use crate::bus::DRAM_BASE;
+use crate::dram::{Dram, MemoryError};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::device::{self, QueueState, VirtioDevice, VirtioState};
+
+/// Host input held for the guest before more is dropped
+const MAX_INPUT: usize = 64 * 1024;
+
+/// Guest output held for the host before the oldest is dropped
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+
+/// Host end of a VirtIO console: bytes for the guest and bytes from it.
+///
+/// Clones share the same queues.
+#[derive(Clone, Default)]
+pub struct ConsolePort {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl ConsolePort {
+    /// Queue bytes for the guest to read. Bytes beyond what the port holds
+    /// are dropped.
+    pub fn push_input(&self, bytes: &[u8]) {
+        let mut input = self.input.lock().unwrap();
+        let room = MAX_INPUT.saturating_sub(input.len());
+        input.extend(&bytes[..bytes.len().min(room)]);
+    }
+
+    /// Take everything the guest has written so far.
+    pub fn drain_output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().drain(..).collect()
+    }
+
+    fn push_output(&self, bytes: &[u8]) {
+        let mut output = self.output.lock().unwrap();
+        output.extend(bytes);
+        let excess = output.len().saturating_sub(MAX_OUTPUT);
+        output.drain(..excess);
+    }
+}
+
+/// One virtqueue as configured by the driver
+#[derive(Default)]
+struct Queue {
+    num: u32,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    ready: bool,
+    last_avail_idx: u16,
+}
+
+impl Queue {
+    fn size(&self) -> u32 {
+        if self.num > 0 {
+            self.num
+        } else {
+            device::QUEUE_SIZE
+        }
+    }
+
+    /// Head of the next buffer the driver made available, if any
+    fn next_avail(&self, dram: &Dram) -> Result<Option<u16>, MemoryError> {
+        if !self.ready || self.desc == 0 {
+            return Ok(None);
+        }
+        let avail_idx = dram.load_16(phys_to_offset(self.avail.wrapping_add(2))?)?;
+        if avail_idx == self.last_avail_idx {
+            return Ok(None);
+        }
+        let slot = (self.last_avail_idx as u32 % self.size()) as u64;
+        let head = dram.load_16(phys_to_offset(self.avail.wrapping_add(4 + slot * 2))?)?;
+        Ok(Some(head))
+    }
+
+    /// Descriptor `idx` as (address, length, flags, next)
+    fn descriptor(&self, dram: &Dram, idx: u16) -> Result<(u64, u32, u64, u16), MemoryError> {
+        let off = phys_to_offset(self.desc.wrapping_add(idx as u64 * 16))?;
+        Ok((
+            dram.load_64(off)?,
+            dram.load_32(off + 8)?,
+            dram.load_16(off + 12)? as u64,
+            dram.load_16(off + 14)?,
+        ))
+    }
+
+    fn save(&self) -> QueueState {
+        QueueState {
+            num: self.num,
+            desc: self.desc,
+            avail: self.avail,
+            used: self.used,
+            ready: self.ready,
+            last_avail_idx: self.last_avail_idx,
+        }
+    }
+
+    fn load(saved: &QueueState) -> Self {
+        Self {
+            num: saved.num,
+            desc: saved.desc,
+            avail: saved.avail,
+            used: saved.used,
+            ready: saved.ready,
+            last_avail_idx: saved.last_avail_idx,
+        }
+    }
+
+    /// Return buffer `head` to the driver with `len` bytes written
+    fn complete(&mut self, dram: &Dram, head: u16, len: u32) -> Result<(), MemoryError> {
+        let used_idx_off = phys_to_offset(self.used.wrapping_add(2))?;
+        let used_idx = dram.load_16(used_idx_off)?;
+        let slot = (used_idx as u32 % self.size()) as u64;
+        let elem = phys_to_offset(self.used.wrapping_add(4 + slot * 8))?;
+        dram.store_32(elem, head as u64)?;
+        dram.store_32(elem + 4, len as u64)?;
+        dram.store_16(used_idx_off, used_idx.wrapping_add(1) as u64)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+        Ok(())
+    }
+}
+
+fn phys_to_offset(addr: u64) -> Result<u64, MemoryError> {
+    if addr < DRAM_BASE {
+        return Err(MemoryError::OutOfBounds(addr));
+    }
+    Ok(addr - DRAM_BASE)
+}
+
+struct VirtioConsoleState {
+    driver_features: u32,
+    driver_features_sel: u32,
+    device_features_sel: u32,
+    page_size: u32,
+    queue_sel: u32,
+    interrupt_status: u32,
+    status: u32,
+    rx: Queue,
+    tx: Queue,
+}
+
+impl VirtioConsoleState {
+    fn queue(&mut self) -> &mut Queue {
+        if self.queue_sel == TX_QUEUE {
+            &mut self.tx
+        } else {
+            &mut self.rx
+        }
+    }
+}
+
+pub struct VirtioConsole {
+    state: Mutex<VirtioConsoleState>,
+    port: ConsolePort,
+}
+
+impl VirtioConsole {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(VirtioConsoleState {
+                driver_features: 0,
+                driver_features_sel: 0,
+                device_features_sel: 0,
+                page_size: 4096,
+                queue_sel: 0,
+                interrupt_status: 0,
+                status: 0,
+                rx: Queue::default(),
+                tx: Queue::default(),
+            }),
+            port: ConsolePort::default(),
+        }
+    }
+
+    /// Host end of the console
+    pub fn port(&self) -> ConsolePort {
+        self.port.clone()
+    }
+
+    /// Copy queued host input into the guest's receive buffers
+    fn deliver_input(
+        &self,
+        state: &mut VirtioConsoleState,
+        dram: &Dram,
+    ) -> Result<(), MemoryError> {
+        let mut input = self.port.input.lock().unwrap();
+        let mut delivered = false;
+        while !input.is_empty() {
+            let Some(head) = state.rx.next_avail(dram)? else {
+                break;
+            };
+            let mut written = 0u32;
+            let mut idx = head;
+            loop {
+                let (addr, len, flags, next) = state.rx.descriptor(dram, idx)?;
+                if flags & device::VRING_DESC_F_WRITE != 0 {
+                    let n = (len as usize).min(input.len());
+                    let chunk: Vec<u8> = input.drain(..n).collect();
+                    dram.write_bytes(phys_to_offset(addr)?, &chunk)?;
+                    written += n as u32;
+                }
+                if flags & device::VRING_DESC_F_NEXT == 0 || input.is_empty() {
+                    break;
+                }
+                idx = next;
+            }
+            state.rx.complete(dram, head, written)?;
+            delivered = true;
+        }
+        if delivered {
+            state.interrupt_status |= 1;
+        }
+        Ok(())
+    }
+
+    /// Pass everything in the transmit queue to the host
+    fn take_output(&self, state: &mut VirtioConsoleState, dram: &Dram) -> Result<(), MemoryError> {
+        let mut processed = false;
+        while let Some(head) = state.tx.next_avail(dram)? {
+            let mut idx = head;
+            for _ in 0..state.tx.size() {
+                let (addr, len, flags, next) = state.tx.descriptor(dram, idx)?;
+                if flags & device::VRING_DESC_F_WRITE == 0 {
+                    let bytes = dram.read_range(phys_to_offset(addr)? as usize, len as usize)?;
+                    self.port.push_output(&bytes);
+                }
+                if flags & device::VRING_DESC_F_NEXT == 0 {
+                    break;
+                }
+                idx = next;
+            }
+            state.tx.complete(dram, head, 0)?;
+            processed = true;
+        }
+        if processed {
+            state.interrupt_status |= 1;
+        }
+        Ok(())
+    }
+}
+
+impl Default for VirtioConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn device_id(&self) -> u32 {
+        device::VIRTIO_CONSOLE_DEVICE_ID
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.state.lock().unwrap().interrupt_status != 0
+    }
+
+    fn read(&self, offset: u64) -> Result<u64, MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        let val = match offset {
+            device::MAGIC_VALUE_OFFSET => device::MAGIC_VALUE,
+            device::VERSION_OFFSET => device::VERSION,
+            device::DEVICE_ID_OFFSET => device::VIRTIO_CONSOLE_DEVICE_ID as u64,
+            device::VENDOR_ID_OFFSET => device::VENDOR_ID,
+            device::DEVICE_FEATURES_OFFSET => 0,
+            device::DEVICE_FEATURES_SEL_OFFSET => state.device_features_sel as u64,
+            device::DRIVER_FEATURES_OFFSET => state.driver_features as u64,
+            device::DRIVER_FEATURES_SEL_OFFSET => state.driver_features_sel as u64,
+            device::GUEST_PAGE_SIZE_OFFSET => state.page_size as u64,
+            device::QUEUE_NUM_MAX_OFFSET => device::QUEUE_SIZE as u64,
+            device::QUEUE_SEL_OFFSET => state.queue_sel as u64,
+            device::QUEUE_NUM_OFFSET => state.queue().num as u64,
+            device::QUEUE_READY_OFFSET => state.queue().ready as u64,
+            device::INTERRUPT_STATUS_OFFSET => state.interrupt_status as u64,
+            device::STATUS_OFFSET => state.status as u64,
+            // No features, so the config space (cols, rows, ports) is unused
+            _ => 0,
+        };
+        Ok(val)
+    }
+
+    fn write(&self, offset: u64, val: u64, dram: &Dram) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        let val32 = val as u32;
+        match offset {
+            device::DEVICE_FEATURES_SEL_OFFSET => state.device_features_sel = val32,
+            device::DRIVER_FEATURES_OFFSET => state.driver_features = val32,
+            device::DRIVER_FEATURES_SEL_OFFSET => state.driver_features_sel = val32,
+            device::GUEST_PAGE_SIZE_OFFSET => state.page_size = val32,
+            device::QUEUE_SEL_OFFSET => state.queue_sel = val32,
+            device::QUEUE_NUM_OFFSET => state.queue().num = val32,
+            device::QUEUE_PFN_OFFSET => {
+                let page_size = state.page_size as u64;
+                let queue = state.queue();
+                let pfn = val32 as u64;
+                if pfn != 0 {
+                    queue.desc = pfn * page_size;
+                    queue.avail = queue.desc + 16 * queue.num as u64;
+                    let avail_size = 6 + 2 * queue.num as u64;
+                    queue.used = (queue.avail + avail_size + page_size - 1) & !(page_size - 1);
+                    queue.ready = true;
+                }
+            }
+            device::QUEUE_READY_OFFSET => state.queue().ready = val32 != 0,
+            device::QUEUE_NOTIFY_OFFSET => match val32 {
+                RX_QUEUE => self.deliver_input(&mut state, dram)?,
+                TX_QUEUE => self.take_output(&mut state, dram)?,
+                _ => {}
+            },
+            device::INTERRUPT_ACK_OFFSET => state.interrupt_status &= !val32,
+            device::STATUS_OFFSET => {
+                if val32 == 0 {
+                    state.status = 0;
+                    state.interrupt_status = 0;
+                    state.rx = Queue::default();
+                    state.tx = Queue::default();
+                } else {
+                    state.status = val32;
+                }
+            }
+            device::QUEUE_DESC_LOW_OFFSET => {
+                let queue = state.queue();
+                queue.desc = (queue.desc & !0xffff_ffff) | val32 as u64;
+            }
+            device::QUEUE_DESC_HIGH_OFFSET => {
+                let queue = state.queue();
+                queue.desc = (queue.desc & 0xffff_ffff) | (val32 as u64) << 32;
+            }
+            device::QUEUE_DRIVER_LOW_OFFSET => {
+                let queue = state.queue();
+                queue.avail = (queue.avail & !0xffff_ffff) | val32 as u64;
+            }
+            device::QUEUE_DRIVER_HIGH_OFFSET => {
+                let queue = state.queue();
+                queue.avail = (queue.avail & 0xffff_ffff) | (val32 as u64) << 32;
+            }
+            device::QUEUE_DEVICE_LOW_OFFSET => {
+                let queue = state.queue();
+                queue.used = (queue.used & !0xffff_ffff) | val32 as u64;
+            }
+            device::QUEUE_DEVICE_HIGH_OFFSET => {
+                let queue = state.queue();
+                queue.used = (queue.used & 0xffff_ffff) | (val32 as u64) << 32;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn poll(&self, dram: &Dram) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        self.deliver_input(&mut state, dram)
+    }
+    fn save_state(&self) -> Option<VirtioState> {
+        let state = self.state.lock().unwrap();
+        Some(VirtioState {
+            device_id: device::VIRTIO_CONSOLE_DEVICE_ID,
+            driver_features: state.driver_features,
+            driver_features_sel: state.driver_features_sel,
+            device_features_sel: state.device_features_sel,
+            page_size: state.page_size,
+            queue_sel: state.queue_sel,
+            interrupt_status: state.interrupt_status,
+            status: state.status,
+            queues: vec![state.rx.save(), state.tx.save()],
+        })
+    }
+
+    fn load_state(&self, saved: &VirtioState) -> Result<(), String> {
+        saved.check(device::VIRTIO_CONSOLE_DEVICE_ID, 2)?;
+        let mut state = self.state.lock().unwrap();
+        state.driver_features = saved.driver_features;
+        state.driver_features_sel = saved.driver_features_sel;
+        state.device_features_sel = saved.device_features_sel;
+        state.page_size = saved.page_size;
+        state.queue_sel = saved.queue_sel;
+        state.interrupt_status = saved.interrupt_status;
+        state.status = saved.status;
+        state.rx = Queue::load(&saved.queues[0]);
+        state.tx = Queue::load(&saved.queues[1]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Guest memory layout for the tests, one ring per queue
+    const RING: [(u64, u64, u64); 2] = [
+        (DRAM_BASE, DRAM_BASE + 0x1000, DRAM_BASE + 0x2000),
+        (DRAM_BASE + 0x3000, DRAM_BASE + 0x4000, DRAM_BASE + 0x5000),
+    ];
+    const BUF: u64 = DRAM_BASE + 0x8000;
+
+    fn off(addr: u64) -> u64 {
+        addr - DRAM_BASE
+    }
+
+    fn setup() -> (VirtioConsole, Dram) {
+        let console = VirtioConsole::new();
+        let dram = Dram::new(DRAM_BASE, 1 << 20);
+        for (queue, &(desc, avail, used)) in RING.iter().enumerate() {
+            console
+                .write(device::QUEUE_SEL_OFFSET, queue as u64, &dram)
+                .unwrap();
+            console.write(device::QUEUE_NUM_OFFSET, 16, &dram).unwrap();
+            console
+                .write(device::QUEUE_DESC_LOW_OFFSET, desc, &dram)
+                .unwrap();
+            console
+                .write(device::QUEUE_DRIVER_LOW_OFFSET, avail, &dram)
+                .unwrap();
+            console
+                .write(device::QUEUE_DEVICE_LOW_OFFSET, used, &dram)
+                .unwrap();
+            console.write(device::QUEUE_READY_OFFSET, 1, &dram).unwrap();
+        }
+        (console, dram)
+    }
+
+    /// Make a one-descriptor buffer of `len` bytes at `addr` available on
+    /// `queue`, returning its ring index
+    fn offer(dram: &Dram, queue: usize, addr: u64, len: u32, flags: u64) -> u16 {
+        let (desc, avail, _) = RING[queue];
+        let idx = dram.load_16(off(avail) + 2).unwrap();
+        let d = off(desc) + (idx as u64 % 16) * 16;
+        dram.store_64(d, addr).unwrap();
+        dram.store_32(d + 8, len as u64).unwrap();
+        dram.store_16(d + 12, flags).unwrap();
+        dram.store_16(off(avail) + 4 + (idx as u64 % 16) * 2, idx as u64 % 16)
+            .unwrap();
+        dram.store_16(off(avail) + 2, idx.wrapping_add(1) as u64)
+            .unwrap();
+        idx
+    }
+
+    fn used_len(dram: &Dram, queue: usize, idx: u16) -> u32 {
+        let (_, _, used) = RING[queue];
+        dram.load_32(off(used) + 4 + (idx as u64 % 16) * 8 + 4)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_guest_output_reaches_port() {
+        let (console, dram) = setup();
+        let port = console.port();
+        dram.write_bytes(off(BUF), b"hello").unwrap();
+        offer(&dram, 1, BUF, 5, 0);
+        console
+            .write(device::QUEUE_NOTIFY_OFFSET, 1, &dram)
+            .unwrap();
+
+        assert_eq!(port.drain_output(), b"hello");
+        assert!(console.is_interrupting());
+        assert!(port.drain_output().is_empty());
+    }
+
+    #[test]
+    fn test_host_input_waits_for_buffers() {
+        let (console, dram) = setup();
+        let port = console.port();
+        port.push_input(b"status\n");
+        console.poll(&dram).unwrap();
+        assert!(!console.is_interrupting());
+
+        // A 4-byte buffer takes the start, the next one the rest
+        let first = offer(&dram, 0, BUF, 4, device::VRING_DESC_F_WRITE);
+        let second = offer(&dram, 0, BUF + 0x100, 64, device::VRING_DESC_F_WRITE);
+        console.poll(&dram).unwrap();
+
+        assert_eq!(used_len(&dram, 0, first), 4);
+        assert_eq!(used_len(&dram, 0, second), 3);
+        assert_eq!(dram.read_range(off(BUF) as usize, 4).unwrap(), b"stat");
+        assert_eq!(
+            dram.read_range(off(BUF + 0x100) as usize, 3).unwrap(),
+            b"us\n"
+        );
+        assert!(console.is_interrupting());
+    }
+}
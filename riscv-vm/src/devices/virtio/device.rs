@@ -1,4 +1,7 @@
 use crate::dram::{Dram, MemoryError};
+use serde::{Deserialize, Serialize};
+
+use super::hotplug::HotplugSlot;
 
 // MMIO register *values* expected by the xv6 VirtIO driver.
 pub const MAGIC_VALUE: u64 = 0x7472_6976;
@@ -36,9 +39,10 @@ pub const CONFIG_SPACE_OFFSET: u64 = 0x100;
 // Device IDs
 pub const VIRTIO_NET_DEVICE_ID: u32 = 1;
 pub const VIRTIO_BLK_DEVICE_ID: u32 = 2;
-#[allow(dead_code)]
 pub const VIRTIO_CONSOLE_DEVICE_ID: u32 = 3;
 pub const VIRTIO_RNG_DEVICE_ID: u32 = 4;
+pub const VIRTIO_9P_DEVICE_ID: u32 = 9;
+pub const VIRTIO_GPU_DEVICE_ID: u32 = 16;
 
 // VirtIO Block Features
 #[allow(dead_code)]
@@ -53,6 +57,9 @@ pub const VIRTIO_BLK_F_RO: u64 = 5;
 pub const VIRTIO_BLK_F_BLK_SIZE: u64 = 6;
 pub const VIRTIO_BLK_F_FLUSH: u64 = 9;
 
+// VirtIO 9P Features
+pub const VIRTIO_9P_F_MOUNT_TAG: u64 = 0; // Config space holds the mount tag
+
 // VirtIO Net Features
 pub const VIRTIO_NET_F_MAC: u64 = 5; // Device has given MAC address
 pub const VIRTIO_NET_F_STATUS: u64 = 16; // Configuration status field available
@@ -71,6 +78,50 @@ pub const QUEUE_SIZE: u32 = 16;
 pub const VRING_DESC_F_NEXT: u64 = 1;
 pub const VRING_DESC_F_WRITE: u64 = 2;
 
+/// A virtqueue as the driver configured it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueState {
+    pub num: u32,
+    pub desc: u64,
+    pub avail: u64,
+    pub used: u64,
+    pub ready: bool,
+    pub last_avail_idx: u16,
+}
+
+/// Transport registers and virtqueues of a device, enough for a device of
+/// the same type on another machine to carry on where this one stopped.
+/// Backend state (the disk, the network connection) is not part of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VirtioState {
+    pub device_id: u32,
+    pub driver_features: u32,
+    pub driver_features_sel: u32,
+    pub device_features_sel: u32,
+    pub page_size: u32,
+    pub queue_sel: u32,
+    pub interrupt_status: u32,
+    pub status: u32,
+    pub queues: Vec<QueueState>,
+}
+
+impl VirtioState {
+    /// Check that this state was saved from a device with ID `device_id`
+    /// and `queues` queues.
+    pub fn check(&self, device_id: u32, queues: usize) -> Result<(), String> {
+        if self.device_id != device_id || self.queues.len() != queues {
+            return Err(format!(
+                "state of device {} with {} queues does not fit device {} with {}",
+                self.device_id,
+                self.queues.len(),
+                device_id,
+                queues
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Trait for all VirtIO devices to implement.
 ///
 /// Note: Methods take `&self` to allow concurrent access from multiple harts.
@@ -93,4 +144,33 @@ pub trait VirtioDevice: Send + Sync {
     fn poll(&self, _dram: &Dram) -> Result<(), MemoryError> {
         Ok(())
     }
+
+    /// Finish every submitted request by the next `poll` instead of
+    /// whenever the host gets to it, e.g. for deterministic replay.
+    /// Devices that never defer work ignore this.
+    fn set_synchronous(&self, _synchronous: bool) {}
+
+    /// Transport and queue state for migrating the device, or `None` if it
+    /// cannot be migrated, e.g. because it keeps guest-visible state on
+    /// the host or still has requests in flight.
+    fn save_state(&self) -> Option<VirtioState> {
+        None
+    }
+
+    /// Take over the state saved by [`save_state`](Self::save_state) on a
+    /// device of the same type.
+    fn load_state(&self, _state: &VirtioState) -> Result<(), String> {
+        Err(format!("device {} cannot be migrated", self.device_id()))
+    }
+
+    /// The slot itself, if this is a [`HotplugSlot`] rather than a device.
+    fn as_hotplug(&self) -> Option<&HotplugSlot> {
+        None
+    }
+
+    /// Key the relay gave a NIC for migrating the VM away, if this is one
+    /// connected to a relay.
+    fn migration_key(&self) -> Option<String> {
+        None
+    }
 }
@@ -0,0 +1,1086 @@
+//! VirtIO GPU Device (2D)
+//!
+//! Implements the 2D subset of virtio-gpu. The guest creates host-side
+//! resources, attaches guest pages as their backing store, copies pixels
+//! across with TRANSFER_TO_HOST_2D and shows them on a scanout with
+//! SET_SCANOUT and RESOURCE_FLUSH. There is no 3D (virgl) or EDID support,
+//! so no feature bits are offered.
+//!
+//! Every scanout is a display the host can show. Flushed pixels are copied
+//! into a [`GpuDisplay`] shared with the host, which reads them back as RGBA
+//! (the layout of a canvas `ImageData`) along with the rectangles that
+//! changed since it last looked.
+//!
+//! ## Queues
+//!
+//! | Index | Name     | Handling                                        |
+//! |-------|----------|-------------------------------------------------|
+//! | 0     | controlq | Display info, resources, transfers and flushes  |
+//! | 1     | cursorq  | UPDATE_CURSOR / MOVE_CURSOR, acknowledged only  |
+//!
+//! ## Config Space
+//!
+//! | Offset | Name         | Description                             |
+//! |--------|--------------|-----------------------------------------|
+//! | 0x00   | events_read  | Always 0; scanouts are never hotplugged |
+//! | 0x04   | events_clear | Ignored                                 |
+//! | 0x08   | num_scanouts | Number of scanouts                      |
+//! | 0x0c   | num_capsets  | Always 0                                |
+
+use crate::devices::framebuffer::DirtyRect;
+use crate::dram::{Dram, MemoryError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::device::{self, VirtioDevice};
+
+/// Most scanouts a device can have
+pub const GPU_MAX_SCANOUTS: usize = 16;
+/// Largest scanout or resource dimension, in pixels
+pub const GPU_MAX_DIMENSION: u32 = 16384;
+/// Host memory the guest may hold in resources at once
+pub const GPU_MAX_RESOURCE_BYTES: usize = 256 << 20;
+
+const BPP: u32 = 4;
+
+// Control queue commands
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_RESOURCE_UNREF: u32 = 0x0102;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+
+// Cursor queue commands
+const CMD_UPDATE_CURSOR: u32 = 0x0300;
+const CMD_MOVE_CURSOR: u32 = 0x0301;
+
+// Response types
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+const RESP_ERR_UNSPEC: u32 = 0x1200;
+const RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+const RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+const RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+const RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+/// Header flag asking for `fence_id` to be echoed once the command is done
+const FLAG_FENCE: u32 = 1;
+
+/// Size of `virtio_gpu_ctrl_hdr`
+const HDR_SIZE: usize = 24;
+
+/// Damage rectangles kept per scanout before they collapse into one
+const MAX_DAMAGE: usize = 64;
+
+/// Byte offsets of red, green and blue within a pixel of a virtio-gpu
+/// format. Format names list bytes in memory order; alpha is ignored since
+/// scanouts are opaque.
+fn rgb_offsets(format: u32) -> Option<[usize; 3]> {
+    match format {
+        1 | 2 => Some([2, 1, 0]),    // B8G8R8A8, B8G8R8X8
+        3 | 4 => Some([1, 2, 3]),    // A8R8G8B8, X8R8G8B8
+        67 | 134 => Some([0, 1, 2]), // R8G8B8A8, R8G8B8X8
+        68 | 121 => Some([3, 2, 1]), // X8B8G8R8, A8B8G8R8
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn parse(buf: &[u8], off: usize) -> Option<Self> {
+        Some(Self {
+            x: u32_at(buf, off)?,
+            y: u32_at(buf, off + 4)?,
+            width: u32_at(buf, off + 8)?,
+            height: u32_at(buf, off + 12)?,
+        })
+    }
+
+    /// Whether the rectangle lies inside a `width` x `height` area.
+    fn fits(&self, width: u32, height: u32) -> bool {
+        self.x as u64 + self.width as u64 <= width as u64
+            && self.y as u64 + self.height as u64 <= height as u64
+    }
+
+    fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+        (x0 < x1 && y0 < y1).then(|| Rect {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+}
+
+fn u32_at(buf: &[u8], off: usize) -> Option<u32> {
+    let bytes = buf.get(off..off + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn u64_at(buf: &[u8], off: usize) -> Option<u64> {
+    let bytes = buf.get(off..off + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Host-side image created by RESOURCE_CREATE_2D.
+struct Resource {
+    width: u32,
+    height: u32,
+    rgb: [usize; 3],
+    /// Guest memory (address, length) pairs from RESOURCE_ATTACH_BACKING
+    backing: Vec<(u64, u32)>,
+    /// Contents in the guest's pixel format, `width * BPP` bytes per row
+    pixels: Vec<u8>,
+}
+
+impl Resource {
+    fn stride(&self) -> usize {
+        (self.width * BPP) as usize
+    }
+
+    /// Copy `buf.len()` bytes starting `offset` bytes into the backing store.
+    fn read_backing(&self, dram: &Dram, mut offset: u64, buf: &mut [u8]) -> Result<(), u32> {
+        let mut filled = 0;
+        for &(addr, len) in &self.backing {
+            if filled == buf.len() {
+                break;
+            }
+            if offset >= len as u64 {
+                offset -= len as u64;
+                continue;
+            }
+            let take = ((len as u64 - offset) as usize).min(buf.len() - filled);
+            let start = dram
+                .offset(addr + offset)
+                .ok_or(RESP_ERR_INVALID_PARAMETER)?;
+            let bytes = dram
+                .read_range(start, take)
+                .map_err(|_| RESP_ERR_INVALID_PARAMETER)?;
+            buf[filled..filled + take].copy_from_slice(&bytes);
+            filled += take;
+            offset = 0;
+        }
+        if filled == buf.len() {
+            Ok(())
+        } else {
+            Err(RESP_ERR_INVALID_PARAMETER)
+        }
+    }
+}
+
+/// What a scanout shows: a region of one resource.
+#[derive(Clone, Copy)]
+struct Binding {
+    resource_id: u32,
+    rect: Rect,
+}
+
+struct ScanoutImage {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    damage: Vec<DirtyRect>,
+}
+
+impl ScanoutImage {
+    fn blank(width: u32, height: u32) -> Self {
+        let mut image = Self {
+            width,
+            height,
+            rgba: Vec::new(),
+            damage: Vec::new(),
+        };
+        image.resize(width, height);
+        image
+    }
+
+    /// Resize to `width` x `height`, black, with everything damaged.
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.rgba.clear();
+        self.rgba.resize((width * height * BPP) as usize, 0);
+        for px in self.rgba.chunks_exact_mut(BPP as usize) {
+            px[3] = 0xff;
+        }
+        self.damage.clear();
+        self.damage.push(DirtyRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+    }
+
+    /// Copy `src` (resource coordinates) of `res` to (`dst_x`, `dst_y`).
+    fn blit(&mut self, res: &Resource, src: Rect, dst_x: u32, dst_y: u32) {
+        let stride = res.stride();
+        let [r, g, b] = res.rgb;
+        for row in 0..src.height {
+            let from = (src.y + row) as usize * stride + (src.x * BPP) as usize;
+            let to = ((dst_y + row) * self.width + dst_x) as usize * BPP as usize;
+            let len = (src.width * BPP) as usize;
+            let pixels = res.pixels[from..from + len].chunks_exact(BPP as usize);
+            let out = self.rgba[to..to + len].chunks_exact_mut(BPP as usize);
+            for (px, out) in pixels.zip(out) {
+                out.copy_from_slice(&[px[r], px[g], px[b], 0xff]);
+            }
+        }
+
+        if self.damage.len() >= MAX_DAMAGE {
+            self.damage.clear();
+            self.damage.push(DirtyRect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            });
+        } else {
+            self.damage.push(DirtyRect {
+                x: dst_x,
+                y: dst_y,
+                width: src.width,
+                height: src.height,
+            });
+        }
+    }
+}
+
+/// Scanout contents as the host sees them.
+///
+/// Shared between the device and the VM frontend; get one from
+/// [`VirtioGpu::display`] before the device is moved onto the bus.
+pub struct GpuDisplay {
+    scanouts: Vec<Mutex<ScanoutImage>>,
+}
+
+impl GpuDisplay {
+    pub fn scanout_count(&self) -> usize {
+        self.scanouts.len()
+    }
+
+    /// Current size of a scanout in pixels. Follows the rectangle the guest
+    /// last passed to SET_SCANOUT.
+    pub fn size(&self, scanout: usize) -> Option<(u32, u32)> {
+        let image = self.scanouts.get(scanout)?.lock().unwrap();
+        Some((image.width, image.height))
+    }
+
+    /// Pixels of a scanout as RGBA with opaque alpha. A disabled scanout is
+    /// black.
+    pub fn rgba(&self, scanout: usize) -> Option<Vec<u8>> {
+        Some(self.scanouts.get(scanout)?.lock().unwrap().rgba.clone())
+    }
+
+    /// Regions of a scanout flushed since the last call.
+    pub fn take_damage(&self, scanout: usize) -> Vec<DirtyRect> {
+        match self.scanouts.get(scanout) {
+            Some(image) => std::mem::take(&mut image.lock().unwrap().damage),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// VirtIO queue state
+struct GpuQueue {
+    num: u32,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    ready: bool,
+    last_avail_idx: u16,
+}
+
+impl GpuQueue {
+    fn new() -> Self {
+        Self {
+            num: 0,
+            desc: 0,
+            avail: 0,
+            used: 0,
+            ready: false,
+            last_avail_idx: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Internal mutable state for VirtioGpu, protected by Mutex
+struct VirtioGpuState {
+    driver_features: u32,
+    driver_features_sel: u32,
+    device_features_sel: u32,
+    page_size: u32,
+    queue_sel: u32,
+    interrupt_status: u32,
+    status: u32,
+
+    // Queues: 0 = control, 1 = cursor
+    controlq: GpuQueue,
+    cursorq: GpuQueue,
+
+    /// Preferred size of each scanout, reported by GET_DISPLAY_INFO
+    modes: Vec<(u32, u32)>,
+    resources: HashMap<u32, Resource>,
+    /// Bytes held by `resources`
+    resource_bytes: usize,
+    bindings: Vec<Option<Binding>>,
+}
+
+/// VirtIO GPU Device
+pub struct VirtioGpu {
+    state: Mutex<VirtioGpuState>,
+    display: Arc<GpuDisplay>,
+}
+
+impl VirtioGpu {
+    /// Create a GPU with one scanout per entry of `modes`, each starting at
+    /// the given (width, height).
+    pub fn new(modes: &[(u32, u32)]) -> Result<Self, String> {
+        if modes.is_empty() || modes.len() > GPU_MAX_SCANOUTS {
+            return Err(format!(
+                "virtio-gpu needs 1 to {} scanouts, got {}",
+                GPU_MAX_SCANOUTS,
+                modes.len()
+            ));
+        }
+        let valid = 1..=GPU_MAX_DIMENSION;
+        if let Some(&(w, h)) = modes
+            .iter()
+            .find(|(w, h)| !valid.contains(w) || !valid.contains(h))
+        {
+            return Err(format!("invalid scanout size {}x{}", w, h));
+        }
+
+        let display = GpuDisplay {
+            scanouts: modes
+                .iter()
+                .map(|&(w, h)| Mutex::new(ScanoutImage::blank(w, h)))
+                .collect(),
+        };
+        Ok(Self {
+            state: Mutex::new(VirtioGpuState {
+                driver_features: 0,
+                driver_features_sel: 0,
+                device_features_sel: 0,
+                page_size: 4096,
+                queue_sel: 0,
+                interrupt_status: 0,
+                status: 0,
+                controlq: GpuQueue::new(),
+                cursorq: GpuQueue::new(),
+                modes: modes.to_vec(),
+                resources: HashMap::new(),
+                resource_bytes: 0,
+                bindings: vec![None; modes.len()],
+            }),
+            display: Arc::new(display),
+        })
+    }
+
+    /// Handle for reading scanout contents from the host.
+    pub fn display(&self) -> Arc<GpuDisplay> {
+        self.display.clone()
+    }
+
+    fn guest_offset(dram: &Dram, addr: u64) -> Result<u64, MemoryError> {
+        dram.offset(addr)
+            .map(|off| off as u64)
+            .ok_or(MemoryError::OutOfBounds(addr))
+    }
+
+    fn current_queue_mut(state: &mut VirtioGpuState) -> &mut GpuQueue {
+        match state.queue_sel {
+            1 => &mut state.cursorq,
+            _ => &mut state.controlq,
+        }
+    }
+
+    fn current_queue(state: &VirtioGpuState) -> &GpuQueue {
+        match state.queue_sel {
+            1 => &state.cursorq,
+            _ => &state.controlq,
+        }
+    }
+
+    /// Drain the available ring of queue `index`, answering each request.
+    fn process_queue(
+        state: &mut VirtioGpuState,
+        index: u32,
+        dram: &Dram,
+        display: &GpuDisplay,
+    ) -> Result<(), MemoryError> {
+        let queue = match index {
+            0 => &state.controlq,
+            1 => &state.cursorq,
+            _ => return Ok(()),
+        };
+        if !queue.ready || queue.desc == 0 {
+            return Ok(());
+        }
+        let (desc, avail, used) = (queue.desc, queue.avail, queue.used);
+        let qsz = if queue.num > 0 {
+            queue.num
+        } else {
+            device::QUEUE_SIZE
+        };
+        let mut last_avail_idx = queue.last_avail_idx;
+
+        let avail_idx = dram.load_16(Self::guest_offset(dram, avail.wrapping_add(2))?)?;
+        let mut processed_any = false;
+        while last_avail_idx != avail_idx {
+            let ring_slot = (last_avail_idx as u32 % qsz) as u64;
+            let head_addr = avail.wrapping_add(4).wrapping_add(ring_slot * 2);
+            let head = dram.load_16(Self::guest_offset(dram, head_addr)?)?;
+
+            // Gather the device-readable request and the writable buffers
+            let mut request = Vec::new();
+            let mut writable = Vec::new();
+            let mut idx = head;
+            for _ in 0..qsz {
+                let off = Self::guest_offset(dram, desc.wrapping_add(idx as u64 * 16))?;
+                let addr = dram.load_64(off)?;
+                let len = dram.load_32(off + 8)?;
+                let flags = dram.load_16(off + 12)? as u64;
+                let next = dram.load_16(off + 14)?;
+                if flags & device::VRING_DESC_F_WRITE != 0 {
+                    writable.push((addr, len));
+                } else {
+                    let start = Self::guest_offset(dram, addr)? as usize;
+                    request.extend_from_slice(&dram.read_range(start, len as usize)?);
+                }
+                if flags & device::VRING_DESC_F_NEXT == 0 {
+                    break;
+                }
+                idx = next;
+            }
+
+            let response = Self::handle_request(state, &request, dram, display);
+            let mut written = 0;
+            for (addr, len) in writable {
+                if written == response.len() {
+                    break;
+                }
+                let take = (len as usize).min(response.len() - written);
+                dram.write_bytes(
+                    Self::guest_offset(dram, addr)?,
+                    &response[written..written + take],
+                )?;
+                written += take;
+            }
+
+            let used_idx_off = Self::guest_offset(dram, used.wrapping_add(2))?;
+            let used_idx = dram.load_16(used_idx_off)?;
+            let elem_addr = used
+                .wrapping_add(4)
+                .wrapping_add((used_idx as u64 % qsz as u64) * 8);
+            let elem_off = Self::guest_offset(dram, elem_addr)?;
+            dram.store_32(elem_off, head as u64)?;
+            dram.store_32(elem_off + 4, written as u64)?;
+            dram.store_16(used_idx_off, used_idx.wrapping_add(1) as u64)?;
+
+            last_avail_idx = last_avail_idx.wrapping_add(1);
+            processed_any = true;
+        }
+
+        match index {
+            0 => state.controlq.last_avail_idx = last_avail_idx,
+            _ => state.cursorq.last_avail_idx = last_avail_idx,
+        }
+        if processed_any {
+            state.interrupt_status |= 1;
+        }
+        Ok(())
+    }
+
+    /// Run one command and build its response, header included.
+    fn handle_request(
+        state: &mut VirtioGpuState,
+        request: &[u8],
+        dram: &Dram,
+        display: &GpuDisplay,
+    ) -> Vec<u8> {
+        let (Some(cmd), Some(flags), Some(fence_id), Some(ctx_id)) = (
+            u32_at(request, 0),
+            u32_at(request, 4),
+            u64_at(request, 8),
+            u32_at(request, 16),
+        ) else {
+            return Self::response(RESP_ERR_UNSPEC, 0, 0, 0, &[]);
+        };
+
+        let (ty, body) = match Self::execute(state, cmd, request, dram, display) {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::debug!("[VirtioGpu] Command 0x{:x} failed: 0x{:x}", cmd, err);
+                (err, Vec::new())
+            }
+        };
+        // Commands complete synchronously, so a fence is already signalled
+        Self::response(ty, flags & FLAG_FENCE, fence_id, ctx_id, &body)
+    }
+
+    fn response(ty: u32, flags: u32, fence_id: u64, ctx_id: u32, body: &[u8]) -> Vec<u8> {
+        let mut resp = Vec::with_capacity(HDR_SIZE + body.len());
+        resp.extend_from_slice(&ty.to_le_bytes());
+        resp.extend_from_slice(&flags.to_le_bytes());
+        resp.extend_from_slice(&fence_id.to_le_bytes());
+        resp.extend_from_slice(&ctx_id.to_le_bytes());
+        resp.extend_from_slice(&[0; 4]); // ring_idx + padding
+        resp.extend_from_slice(body);
+        resp
+    }
+
+    fn execute(
+        state: &mut VirtioGpuState,
+        cmd: u32,
+        req: &[u8],
+        dram: &Dram,
+        display: &GpuDisplay,
+    ) -> Result<(u32, Vec<u8>), u32> {
+        let arg = |off: usize| u32_at(req, HDR_SIZE + off).ok_or(RESP_ERR_INVALID_PARAMETER);
+        let rect = |off: usize| Rect::parse(req, HDR_SIZE + off).ok_or(RESP_ERR_INVALID_PARAMETER);
+
+        match cmd {
+            CMD_GET_DISPLAY_INFO => {
+                let mut body = Vec::with_capacity(GPU_MAX_SCANOUTS * 24);
+                for i in 0..GPU_MAX_SCANOUTS {
+                    let (w, h) = state.modes.get(i).copied().unwrap_or((0, 0));
+                    let enabled = (i < state.modes.len()) as u32;
+                    for field in [0, 0, w, h, enabled, 0] {
+                        body.extend_from_slice(&u32::to_le_bytes(field));
+                    }
+                }
+                Ok((RESP_OK_DISPLAY_INFO, body))
+            }
+            CMD_RESOURCE_CREATE_2D => {
+                let id = arg(0)?;
+                let format = arg(4)?;
+                let width = arg(8)?;
+                let height = arg(12)?;
+                let rgb = rgb_offsets(format).ok_or(RESP_ERR_INVALID_PARAMETER)?;
+                if id == 0 || state.resources.contains_key(&id) {
+                    return Err(RESP_ERR_INVALID_RESOURCE_ID);
+                }
+                let valid = 1..=GPU_MAX_DIMENSION;
+                if !valid.contains(&width) || !valid.contains(&height) {
+                    return Err(RESP_ERR_INVALID_PARAMETER);
+                }
+                let bytes = (width * height * BPP) as usize;
+                if state.resource_bytes + bytes > GPU_MAX_RESOURCE_BYTES {
+                    return Err(RESP_ERR_OUT_OF_MEMORY);
+                }
+                state.resource_bytes += bytes;
+                state.resources.insert(
+                    id,
+                    Resource {
+                        width,
+                        height,
+                        rgb,
+                        backing: Vec::new(),
+                        pixels: vec![0; bytes],
+                    },
+                );
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_RESOURCE_UNREF => {
+                let id = arg(0)?;
+                let res = state
+                    .resources
+                    .remove(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                state.resource_bytes -= res.pixels.len();
+                for (scanout, binding) in state.bindings.iter_mut().enumerate() {
+                    if binding.is_some_and(|b| b.resource_id == id) {
+                        *binding = None;
+                        let mut image = display.scanouts[scanout].lock().unwrap();
+                        let (w, h) = (image.width, image.height);
+                        image.resize(w, h);
+                    }
+                }
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_SET_SCANOUT => {
+                let r = rect(0)?;
+                let scanout = arg(16)? as usize;
+                let id = arg(20)?;
+                if scanout >= state.bindings.len() {
+                    return Err(RESP_ERR_INVALID_SCANOUT_ID);
+                }
+                let mut image = display.scanouts[scanout].lock().unwrap();
+                if id == 0 {
+                    // Disable the scanout
+                    state.bindings[scanout] = None;
+                    let (w, h) = (image.width, image.height);
+                    image.resize(w, h);
+                    return Ok((RESP_OK_NODATA, Vec::new()));
+                }
+                let res = state
+                    .resources
+                    .get(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                if r.width == 0 || r.height == 0 || !r.fits(res.width, res.height) {
+                    return Err(RESP_ERR_INVALID_PARAMETER);
+                }
+                state.bindings[scanout] = Some(Binding {
+                    resource_id: id,
+                    rect: r,
+                });
+                image.resize(r.width, r.height);
+                image.blit(res, r, 0, 0);
+                image.damage.truncate(1);
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_RESOURCE_FLUSH => {
+                let r = rect(0)?;
+                let id = arg(16)?;
+                let res = state
+                    .resources
+                    .get(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                if !r.fits(res.width, res.height) {
+                    return Err(RESP_ERR_INVALID_PARAMETER);
+                }
+                for (scanout, binding) in state.bindings.iter().enumerate() {
+                    let Some(binding) = binding.filter(|b| b.resource_id == id) else {
+                        continue;
+                    };
+                    if let Some(area) = r.intersect(&binding.rect) {
+                        let mut image = display.scanouts[scanout].lock().unwrap();
+                        image.blit(res, area, area.x - binding.rect.x, area.y - binding.rect.y);
+                    }
+                }
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_TRANSFER_TO_HOST_2D => {
+                let r = rect(0)?;
+                let offset = u64_at(req, HDR_SIZE + 16).ok_or(RESP_ERR_INVALID_PARAMETER)?;
+                let id = arg(24)?;
+                let res = state
+                    .resources
+                    .get_mut(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                if !r.fits(res.width, res.height) {
+                    return Err(RESP_ERR_INVALID_PARAMETER);
+                }
+                if res.backing.is_empty() {
+                    return Err(RESP_ERR_UNSPEC);
+                }
+                let stride = res.stride();
+                let len = (r.width * BPP) as usize;
+                let mut row_buf = vec![0; len];
+                for row in 0..r.height as usize {
+                    res.read_backing(dram, offset + (row * stride) as u64, &mut row_buf)?;
+                    let to = (r.y as usize + row) * stride + (r.x * BPP) as usize;
+                    res.pixels[to..to + len].copy_from_slice(&row_buf);
+                }
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_RESOURCE_ATTACH_BACKING => {
+                let id = arg(0)?;
+                let nr_entries = arg(4)? as usize;
+                let res = state
+                    .resources
+                    .get_mut(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                let mut backing = Vec::with_capacity(nr_entries.min(1024));
+                for i in 0..nr_entries {
+                    let off = HDR_SIZE + 8 + i * 16;
+                    let addr = u64_at(req, off).ok_or(RESP_ERR_INVALID_PARAMETER)?;
+                    let len = u32_at(req, off + 8).ok_or(RESP_ERR_INVALID_PARAMETER)?;
+                    let in_dram = len > 0
+                        && dram.offset(addr).is_some()
+                        && dram.offset(addr + len as u64 - 1).is_some();
+                    if !in_dram {
+                        return Err(RESP_ERR_INVALID_PARAMETER);
+                    }
+                    backing.push((addr, len));
+                }
+                res.backing = backing;
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_RESOURCE_DETACH_BACKING => {
+                let id = arg(0)?;
+                let res = state
+                    .resources
+                    .get_mut(&id)
+                    .ok_or(RESP_ERR_INVALID_RESOURCE_ID)?;
+                res.backing.clear();
+                Ok((RESP_OK_NODATA, Vec::new()))
+            }
+            CMD_UPDATE_CURSOR | CMD_MOVE_CURSOR => Ok((RESP_OK_NODATA, Vec::new())),
+            _ => Err(RESP_ERR_UNSPEC),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioGpu {
+    fn device_id(&self) -> u32 {
+        device::VIRTIO_GPU_DEVICE_ID
+    }
+
+    fn is_interrupting(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.interrupt_status != 0
+    }
+
+    fn read(&self, offset: u64) -> Result<u64, MemoryError> {
+        let state = self.state.lock().unwrap();
+        let val = match offset {
+            device::MAGIC_VALUE_OFFSET => device::MAGIC_VALUE,
+            device::VERSION_OFFSET => device::VERSION,
+            device::DEVICE_ID_OFFSET => device::VIRTIO_GPU_DEVICE_ID as u64,
+            device::VENDOR_ID_OFFSET => device::VENDOR_ID,
+            device::DEVICE_FEATURES_OFFSET => 0,
+            device::DEVICE_FEATURES_SEL_OFFSET => state.device_features_sel as u64,
+            device::DRIVER_FEATURES_OFFSET => state.driver_features as u64,
+            device::DRIVER_FEATURES_SEL_OFFSET => state.driver_features_sel as u64,
+            device::GUEST_PAGE_SIZE_OFFSET => state.page_size as u64,
+            device::QUEUE_NUM_MAX_OFFSET => device::QUEUE_SIZE as u64,
+            device::QUEUE_SEL_OFFSET => state.queue_sel as u64,
+            device::QUEUE_NUM_OFFSET => Self::current_queue(&state).num as u64,
+            device::QUEUE_READY_OFFSET => Self::current_queue(&state).ready as u64,
+            device::INTERRUPT_STATUS_OFFSET => state.interrupt_status as u64,
+            device::STATUS_OFFSET => state.status as u64,
+            device::CONFIG_GENERATION_OFFSET => 0,
+            // Config space: events_read, events_clear, num_scanouts, num_capsets
+            _ if offset >= device::CONFIG_SPACE_OFFSET => {
+                match offset - device::CONFIG_SPACE_OFFSET {
+                    0x08 => state.modes.len() as u64,
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        };
+        Ok(val)
+    }
+
+    fn write(&self, offset: u64, val: u64, dram: &Dram) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        let val32 = val as u32;
+
+        match offset {
+            device::DEVICE_FEATURES_SEL_OFFSET => {
+                state.device_features_sel = val32;
+            }
+            device::DRIVER_FEATURES_OFFSET => {
+                state.driver_features = val32;
+            }
+            device::DRIVER_FEATURES_SEL_OFFSET => {
+                state.driver_features_sel = val32;
+            }
+            device::QUEUE_SEL_OFFSET => {
+                state.queue_sel = val32;
+            }
+            device::QUEUE_NUM_OFFSET => {
+                Self::current_queue_mut(&mut state).num = val32;
+            }
+            device::GUEST_PAGE_SIZE_OFFSET => {
+                state.page_size = val32;
+            }
+            device::QUEUE_PFN_OFFSET => {
+                let pfn = val32 as u64;
+                if pfn != 0 {
+                    let page_size = state.page_size as u64;
+                    let queue = Self::current_queue_mut(&mut state);
+                    let desc = pfn * page_size;
+                    queue.desc = desc;
+                    queue.avail = desc + 16 * (queue.num as u64);
+                    // Avail ring size: flags(2) + idx(2) + ring(2*n) + used_event(2) = 6 + 2*n
+                    let avail_size = 6 + 2 * (queue.num as u64);
+                    queue.used = (queue.avail + avail_size + page_size - 1) & !(page_size - 1);
+                    queue.ready = true;
+                }
+            }
+            device::QUEUE_READY_OFFSET => {
+                Self::current_queue_mut(&mut state).ready = val32 != 0;
+            }
+            device::QUEUE_NOTIFY_OFFSET => {
+                Self::process_queue(&mut state, val32, dram, &self.display)?;
+            }
+            device::INTERRUPT_ACK_OFFSET => {
+                state.interrupt_status &= !val32;
+            }
+            device::STATUS_OFFSET => {
+                if val32 == 0 {
+                    // Reset: drop every resource and blank the scanouts
+                    state.status = 0;
+                    state.controlq.reset();
+                    state.cursorq.reset();
+                    state.interrupt_status = 0;
+                    state.resources.clear();
+                    state.resource_bytes = 0;
+                    state.bindings.fill(None);
+                    for (image, &(w, h)) in self.display.scanouts.iter().zip(&state.modes) {
+                        image.lock().unwrap().resize(w, h);
+                    }
+                } else {
+                    state.status = val32;
+                }
+            }
+            device::QUEUE_DESC_LOW_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.desc = (queue.desc & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DESC_HIGH_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.desc = (queue.desc & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            device::QUEUE_DRIVER_LOW_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.avail = (queue.avail & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DRIVER_HIGH_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.avail = (queue.avail & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            device::QUEUE_DEVICE_LOW_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.used = (queue.used & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DEVICE_HIGH_OFFSET => {
+                let queue = Self::current_queue_mut(&mut state);
+                queue.used = (queue.used & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+
+    // Guest memory layout for the tests
+    const DESC: u64 = DRAM_BASE;
+    const AVAIL: u64 = DRAM_BASE + 0x1000;
+    const USED: u64 = DRAM_BASE + 0x2000;
+    const REQ: u64 = DRAM_BASE + 0x3000;
+    const RESP: u64 = DRAM_BASE + 0x4000;
+    const PIXELS: u64 = DRAM_BASE + 0x10000;
+
+    fn setup(modes: &[(u32, u32)]) -> (VirtioGpu, Dram) {
+        let gpu = VirtioGpu::new(modes).unwrap();
+        let dram = Dram::new(DRAM_BASE, 1 << 20);
+        gpu.write(device::QUEUE_SEL_OFFSET, 0, &dram).unwrap();
+        gpu.write(device::QUEUE_NUM_OFFSET, 16, &dram).unwrap();
+        gpu.write(device::QUEUE_DESC_LOW_OFFSET, DESC, &dram)
+            .unwrap();
+        gpu.write(device::QUEUE_DRIVER_LOW_OFFSET, AVAIL, &dram)
+            .unwrap();
+        gpu.write(device::QUEUE_DEVICE_LOW_OFFSET, USED, &dram)
+            .unwrap();
+        gpu.write(device::QUEUE_READY_OFFSET, 1, &dram).unwrap();
+        (gpu, dram)
+    }
+
+    /// Submit `args` after a header for `cmd` and return the response.
+    fn submit(gpu: &VirtioGpu, dram: &Dram, cmd: u32, args: &[u8]) -> Vec<u8> {
+        let off = |addr: u64| addr - DRAM_BASE;
+        let mut req = VirtioGpu::response(cmd, FLAG_FENCE, 7, 0, &[]);
+        req.extend_from_slice(args);
+        dram.write_bytes(off(REQ), &req).unwrap();
+
+        // Two descriptors: request, then a 1 KiB response buffer
+        dram.store_64(off(DESC), REQ).unwrap();
+        dram.store_32(off(DESC) + 8, req.len() as u64).unwrap();
+        dram.store_16(off(DESC) + 12, device::VRING_DESC_F_NEXT)
+            .unwrap();
+        dram.store_16(off(DESC) + 14, 1).unwrap();
+        dram.store_64(off(DESC) + 16, RESP).unwrap();
+        dram.store_32(off(DESC) + 24, 1024).unwrap();
+        dram.store_16(off(DESC) + 28, device::VRING_DESC_F_WRITE)
+            .unwrap();
+
+        let idx = dram.load_16(off(AVAIL) + 2).unwrap();
+        dram.store_16(off(AVAIL) + 4 + (idx as u64 % 16) * 2, 0)
+            .unwrap();
+        dram.store_16(off(AVAIL) + 2, idx.wrapping_add(1) as u64)
+            .unwrap();
+        gpu.write(device::QUEUE_NOTIFY_OFFSET, 0, dram).unwrap();
+
+        let used_len = dram
+            .load_32(off(USED) + 4 + (idx as u64 % 16) * 8 + 4)
+            .unwrap();
+        let resp = dram
+            .read_range(off(RESP) as usize, used_len as usize)
+            .unwrap();
+        // Fenced commands echo the fence
+        assert_eq!(u32_at(&resp, 4), Some(FLAG_FENCE));
+        assert_eq!(u64_at(&resp, 8), Some(7));
+        resp
+    }
+
+    fn args(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn status(resp: &[u8]) -> u32 {
+        u32_at(resp, 0).unwrap()
+    }
+
+    #[test]
+    fn test_display_info_lists_scanouts() {
+        let (gpu, dram) = setup(&[(640, 480), (320, 200)]);
+        assert_eq!(gpu.read(device::CONFIG_SPACE_OFFSET + 8).unwrap(), 2);
+
+        let resp = submit(&gpu, &dram, CMD_GET_DISPLAY_INFO, &[]);
+        assert_eq!(status(&resp), RESP_OK_DISPLAY_INFO);
+        assert_eq!(resp.len(), HDR_SIZE + GPU_MAX_SCANOUTS * 24);
+        let pmode = |i: usize| Rect::parse(&resp, HDR_SIZE + i * 24).unwrap();
+        let enabled = |i: usize| u32_at(&resp, HDR_SIZE + i * 24 + 16).unwrap();
+        assert_eq!((pmode(0).width, pmode(0).height, enabled(0)), (640, 480, 1));
+        assert_eq!((pmode(1).width, pmode(1).height, enabled(1)), (320, 200, 1));
+        assert_eq!(enabled(2), 0);
+        assert!(gpu.is_interrupting());
+    }
+
+    #[test]
+    fn test_transfer_and_flush_reach_scanouts() {
+        let (gpu, dram) = setup(&[(8, 4), (8, 4)]);
+        let display = gpu.display();
+
+        // 8x4 B8G8R8X8 resource; pixel (x, y) has blue = x, green = y
+        let ok = |resp: Vec<u8>| assert_eq!(status(&resp), RESP_OK_NODATA);
+        ok(submit(
+            &gpu,
+            &dram,
+            CMD_RESOURCE_CREATE_2D,
+            &args(&[1, 2, 8, 4]),
+        ));
+        for y in 0..4u64 {
+            for x in 0..8u64 {
+                let px = 0x0080_0000 | y << 8 | x;
+                dram.store_32(PIXELS - DRAM_BASE + (y * 8 + x) * 4, px)
+                    .unwrap();
+            }
+        }
+        // Backing split over two entries, in the middle of row 1
+        let mut attach = args(&[1, 2]);
+        attach.extend_from_slice(&PIXELS.to_le_bytes());
+        attach.extend_from_slice(&args(&[40, 0]));
+        attach.extend_from_slice(&(PIXELS + 40).to_le_bytes());
+        attach.extend_from_slice(&args(&[88, 0]));
+        ok(submit(&gpu, &dram, CMD_RESOURCE_ATTACH_BACKING, &attach));
+
+        // Scanout 0 shows the whole resource, scanout 1 its bottom-right 4x2
+        ok(submit(
+            &gpu,
+            &dram,
+            CMD_SET_SCANOUT,
+            &args(&[0, 0, 8, 4, 0, 1]),
+        ));
+        ok(submit(
+            &gpu,
+            &dram,
+            CMD_SET_SCANOUT,
+            &args(&[4, 2, 4, 2, 1, 1]),
+        ));
+        assert_eq!(display.size(1), Some((4, 2)));
+        display.take_damage(0);
+        display.take_damage(1);
+
+        // Transfer everything, then flush rows 1-2
+        let mut transfer = args(&[0, 0, 8, 4]);
+        transfer.extend_from_slice(&0u64.to_le_bytes());
+        transfer.extend_from_slice(&args(&[1, 0]));
+        ok(submit(&gpu, &dram, CMD_TRANSFER_TO_HOST_2D, &transfer));
+        ok(submit(
+            &gpu,
+            &dram,
+            CMD_RESOURCE_FLUSH,
+            &args(&[0, 1, 8, 2, 1, 0]),
+        ));
+
+        let rgba = display.rgba(0).unwrap();
+        let px = |x: usize, y: usize| &rgba[(y * 8 + x) * 4..][..4];
+        assert_eq!(px(5, 1), &[0x80, 1, 5, 0xff]);
+        assert_eq!(px(5, 3), &[0, 0, 0, 0xff], "row 3 was not flushed");
+        assert_eq!(
+            display.take_damage(0),
+            vec![DirtyRect {
+                x: 0,
+                y: 1,
+                width: 8,
+                height: 2
+            }]
+        );
+
+        // Only row 2 of the flush overlaps scanout 1, at its row 0
+        let rgba = display.rgba(1).unwrap();
+        assert_eq!(&rgba[..4], &[0x80, 2, 4, 0xff]);
+        assert_eq!(
+            display.take_damage(1),
+            vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 1
+            }]
+        );
+
+        // Unref blanks the scanouts showing the resource
+        ok(submit(&gpu, &dram, CMD_RESOURCE_UNREF, &args(&[1, 0])));
+        assert_eq!(&display.rgba(0).unwrap()[..4], &[0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_invalid_commands_are_rejected() {
+        let (gpu, dram) = setup(&[(64, 64)]);
+        let status_of = |cmd, words: &[u32]| status(&submit(&gpu, &dram, cmd, &args(words)));
+
+        assert_eq!(
+            status_of(CMD_RESOURCE_CREATE_2D, &[1, 999, 8, 8]),
+            RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            status_of(CMD_RESOURCE_CREATE_2D, &[1, 1, 16384, 16384]),
+            RESP_ERR_OUT_OF_MEMORY
+        );
+        assert_eq!(
+            status_of(CMD_RESOURCE_CREATE_2D, &[1, 1, 8, 8]),
+            RESP_OK_NODATA
+        );
+        assert_eq!(
+            status_of(CMD_RESOURCE_CREATE_2D, &[1, 1, 8, 8]),
+            RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            status_of(CMD_SET_SCANOUT, &[0, 0, 8, 8, 1, 1]),
+            RESP_ERR_INVALID_SCANOUT_ID
+        );
+        assert_eq!(
+            status_of(CMD_SET_SCANOUT, &[4, 0, 8, 8, 0, 1]),
+            RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            status_of(CMD_RESOURCE_FLUSH, &[0, 0, 8, 8, 2, 0]),
+            RESP_ERR_INVALID_RESOURCE_ID
+        );
+        // No backing attached yet
+        assert_eq!(
+            status_of(CMD_TRANSFER_TO_HOST_2D, &[0, 0, 8, 8, 0, 0, 1, 0]),
+            RESP_ERR_UNSPEC
+        );
+        assert_eq!(status_of(0x0999, &[]), RESP_ERR_UNSPEC);
+    }
+}
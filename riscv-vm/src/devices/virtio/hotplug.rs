@@ -0,0 +1,229 @@
+//! Hot-pluggable VirtIO MMIO slots.
+//!
+//! A [`HotplugSlot`] takes the place of a device on the bus and forwards
+//! every access to the device plugged into it, if any. Devices can be
+//! plugged and unplugged while the guest runs, through
+//! [`SystemBus::plug_virtio`](crate::bus::SystemBus::plug_virtio) and
+//! [`SystemBus::unplug_virtio`](crate::bus::SystemBus::unplug_virtio).
+//!
+//! An empty slot looks like a VirtIO MMIO transport with device ID 0, which
+//! drivers skip as "no device". Every plug and unplug raises the slot's
+//! interrupt with the configuration-change bit set until the driver
+//! acknowledges it: a driver still bound to an unplugged NIC then reads a
+//! configuration space of zeroes, i.e. the link is down. VirtIO MMIO has no
+//! way to announce a new device, so the guest rescans the slot (on Linux,
+//! by writing the device name to `/sys/bus/platform/drivers_probe`) to
+//! bind a driver to it.
+
+use crate::dram::{Dram, MemoryError};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::device::{self, VirtioDevice, VirtioState};
+
+/// Interrupt status bit of a configuration change.
+const CONFIG_CHANGE: u64 = 2;
+
+/// A VirtIO slot whose device can change while the machine runs.
+pub struct HotplugSlot {
+    device: RwLock<Option<Box<dyn VirtioDevice>>>,
+    /// A device came or went and the driver has not acknowledged it yet
+    changed: AtomicBool,
+    /// Passed on to devices plugged in later
+    synchronous: AtomicBool,
+}
+
+impl HotplugSlot {
+    /// An empty slot.
+    pub fn new() -> Self {
+        Self {
+            device: RwLock::new(None),
+            changed: AtomicBool::new(false),
+            synchronous: AtomicBool::new(false),
+        }
+    }
+
+    /// A slot holding `device` from power-on.
+    pub fn with_device(device: Box<dyn VirtioDevice>) -> Self {
+        let slot = Self::new();
+        *slot.device.write().unwrap() = Some(device);
+        slot
+    }
+
+    /// Whether a device is plugged in.
+    pub fn is_occupied(&self) -> bool {
+        self.device.read().unwrap().is_some()
+    }
+
+    /// Plug in `device`, or hand it back if the slot is taken.
+    pub fn plug(&self, device: Box<dyn VirtioDevice>) -> Result<(), Box<dyn VirtioDevice>> {
+        let mut slot = self.device.write().unwrap();
+        if slot.is_some() {
+            return Err(device);
+        }
+        device.set_synchronous(self.synchronous.load(Ordering::Relaxed));
+        *slot = Some(device);
+        self.changed.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take out the device, if there is one. It is left as the guest had
+    /// it; the bus resets it.
+    pub fn unplug(&self) -> Option<Box<dyn VirtioDevice>> {
+        let device = self.device.write().unwrap().take()?;
+        self.changed.store(true, Ordering::Release);
+        Some(device)
+    }
+}
+
+impl Default for HotplugSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtioDevice for HotplugSlot {
+    fn read(&self, offset: u64) -> Result<u64, MemoryError> {
+        let changed = if self.changed.load(Ordering::Acquire) {
+            CONFIG_CHANGE
+        } else {
+            0
+        };
+        if let Some(device) = self.device.read().unwrap().as_ref() {
+            let val = device.read(offset)?;
+            return Ok(match offset {
+                device::INTERRUPT_STATUS_OFFSET => val | changed,
+                _ => val,
+            });
+        }
+        Ok(match offset {
+            device::MAGIC_VALUE_OFFSET => device::MAGIC_VALUE,
+            device::VERSION_OFFSET => device::VERSION,
+            device::VENDOR_ID_OFFSET => device::VENDOR_ID,
+            device::INTERRUPT_STATUS_OFFSET => changed,
+            _ => 0,
+        })
+    }
+
+    fn write(&self, offset: u64, val: u64, dram: &Dram) -> Result<(), MemoryError> {
+        if offset == device::INTERRUPT_ACK_OFFSET && val & CONFIG_CHANGE != 0 {
+            self.changed.store(false, Ordering::Release);
+        }
+        match self.device.read().unwrap().as_ref() {
+            Some(device) => device.write(offset, val, dram),
+            None => Ok(()),
+        }
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.changed.load(Ordering::Acquire)
+            || self
+                .device
+                .read()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|device| device.is_interrupting())
+    }
+
+    /// The plugged device's ID, or 0 for an empty slot.
+    fn device_id(&self) -> u32 {
+        self.device
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |device| device.device_id())
+    }
+
+    fn reg_read_size(&self, offset: u64) -> u64 {
+        self.device
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(4, |device| device.reg_read_size(offset))
+    }
+
+    fn poll(&self, dram: &Dram) -> Result<(), MemoryError> {
+        match self.device.read().unwrap().as_ref() {
+            Some(device) => device.poll(dram),
+            None => Ok(()),
+        }
+    }
+
+    fn set_synchronous(&self, synchronous: bool) {
+        self.synchronous.store(synchronous, Ordering::Relaxed);
+        if let Some(device) = self.device.read().unwrap().as_ref() {
+            device.set_synchronous(synchronous);
+        }
+    }
+
+    /// The plugged device's state, or that of an empty slot (device ID 0).
+    /// A pending configuration change is not carried over.
+    fn save_state(&self) -> Option<VirtioState> {
+        match self.device.read().unwrap().as_ref() {
+            Some(device) => device.save_state(),
+            None => Some(VirtioState::default()),
+        }
+    }
+
+    fn load_state(&self, state: &VirtioState) -> Result<(), String> {
+        match self.device.read().unwrap().as_ref() {
+            Some(device) => device.load_state(state),
+            None => state.check(0, 0),
+        }
+    }
+
+    fn as_hotplug(&self) -> Option<&HotplugSlot> {
+        Some(self)
+    }
+
+    fn migration_key(&self) -> Option<String> {
+        self.device
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|device| device.migration_key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::devices::virtio::VirtioRng;
+
+    #[test]
+    fn test_plug_and_unplug() {
+        let dram = Dram::new(DRAM_BASE, 1 << 16);
+        let slot = HotplugSlot::new();
+        assert_eq!(
+            slot.read(device::MAGIC_VALUE_OFFSET).unwrap(),
+            device::MAGIC_VALUE
+        );
+        assert_eq!(slot.read(device::DEVICE_ID_OFFSET).unwrap(), 0);
+        assert!(!slot.is_interrupting());
+
+        assert!(slot.plug(Box::new(VirtioRng::new())).is_ok());
+        assert!(slot.plug(Box::new(VirtioRng::new())).is_err());
+        assert_eq!(slot.device_id(), device::VIRTIO_RNG_DEVICE_ID);
+        assert_eq!(
+            slot.read(device::DEVICE_ID_OFFSET).unwrap(),
+            device::VIRTIO_RNG_DEVICE_ID as u64
+        );
+
+        // The change is reported until the driver acknowledges it
+        assert!(slot.is_interrupting());
+        assert_eq!(
+            slot.read(device::INTERRUPT_STATUS_OFFSET).unwrap(),
+            CONFIG_CHANGE
+        );
+        slot.write(device::INTERRUPT_ACK_OFFSET, CONFIG_CHANGE, &dram)
+            .unwrap();
+        assert!(!slot.is_interrupting());
+
+        assert!(slot.unplug().is_some());
+        assert!(slot.unplug().is_none());
+        assert!(!slot.is_occupied());
+        assert_eq!(slot.read(device::DEVICE_ID_OFFSET).unwrap(), 0);
+        assert!(slot.is_interrupting());
+    }
+}
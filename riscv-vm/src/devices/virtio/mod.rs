@@ -1,10 +1,18 @@
 pub mod block;
+pub mod console;
 pub mod device;
+pub mod gpu;
+pub mod hotplug;
 pub mod net;
+pub mod p9;
 pub mod rng;
 
 // Re-export common types for convenience
 pub use block::VirtioBlock;
-pub use device::VirtioDevice;
+pub use console::{ConsolePort, VirtioConsole};
+pub use device::{VirtioDevice, VirtioState};
+pub use gpu::{GpuDisplay, VirtioGpu};
+pub use hotplug::HotplugSlot;
 pub use net::VirtioNet;
+pub use p9::Virtio9p;
 pub use rng::VirtioRng;
@@ -3,7 +3,7 @@ use crate::dram::{Dram, MemoryError};
 use crate::net::NetworkBackend;
 use std::sync::Mutex;
 
-use super::device::{self, VirtioDevice};
+use super::device::{self, QueueState, VirtioDevice, VirtioState};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen;
@@ -38,6 +38,26 @@ impl NetQueue {
         self.ready = false;
         self.last_avail_idx = 0;
     }
+
+    fn save(&self) -> QueueState {
+        QueueState {
+            num: self.num,
+            desc: self.desc,
+            avail: self.avail,
+            used: self.used,
+            ready: self.ready,
+            last_avail_idx: self.last_avail_idx,
+        }
+    }
+
+    fn load(&mut self, saved: &QueueState) {
+        self.num = saved.num;
+        self.desc = saved.desc;
+        self.avail = saved.avail;
+        self.used = saved.used;
+        self.ready = saved.ready;
+        self.last_avail_idx = saved.last_avail_idx;
+    }
 }
 
 /// Network statistics for monitoring and debugging (Phase 5)
@@ -94,9 +114,15 @@ pub struct VirtioNet {
 
 impl VirtioNet {
     /// Create a new VirtIO network device with the given backend.
-    pub fn new(mut backend: Box<dyn NetworkBackend>) -> Self {
+    pub fn new(backend: Box<dyn NetworkBackend>) -> Self {
         let mac = backend.mac_address();
+        Self::with_mac(backend, mac)
+    }
 
+    /// Create a device on `backend` that reports `mac` to the guest rather
+    /// than the backend's own address, e.g. to keep the NICs of a guest
+    /// with several on one backend type apart.
+    pub fn with_mac(mut backend: Box<dyn NetworkBackend>, mac: [u8; 6]) -> Self {
         // Initialize the backend
         if let Err(e) = backend.init() {
             log::error!("[VirtioNet] Failed to initialize backend: {}", e);
@@ -601,4 +627,38 @@ impl VirtioDevice for VirtioNet {
         let mut state = self.state.lock().unwrap();
         Self::process_rx_queue(&mut state, dram)
     }
+
+    fn save_state(&self) -> Option<VirtioState> {
+        let state = self.state.lock().unwrap();
+        Some(VirtioState {
+            device_id: device::VIRTIO_NET_DEVICE_ID,
+            driver_features: state.driver_features,
+            driver_features_sel: state.driver_features_sel,
+            device_features_sel: state.device_features_sel,
+            page_size: state.page_size,
+            queue_sel: state.queue_sel,
+            interrupt_status: state.interrupt_status,
+            status: state.status,
+            queues: vec![state.rx_queue.save(), state.tx_queue.save()],
+        })
+    }
+
+    fn load_state(&self, saved: &VirtioState) -> Result<(), String> {
+        saved.check(device::VIRTIO_NET_DEVICE_ID, 2)?;
+        let mut state = self.state.lock().unwrap();
+        state.driver_features = saved.driver_features;
+        state.driver_features_sel = saved.driver_features_sel;
+        state.device_features_sel = saved.device_features_sel;
+        state.page_size = saved.page_size;
+        state.queue_sel = saved.queue_sel;
+        state.interrupt_status = saved.interrupt_status;
+        state.status = saved.status;
+        state.rx_queue.load(&saved.queues[0]);
+        state.tx_queue.load(&saved.queues[1]);
+        Ok(())
+    }
+
+    fn migration_key(&self) -> Option<String> {
+        self.state.lock().unwrap().backend.migration_key()
+    }
 }
@@ -0,0 +1,1134 @@
+//! VirtIO 9P Device (shared directory)
+//!
+//! Exports a [`ShareBackend`] to the guest as a 9P2000.L file server, the
+//! protocol Linux mounts with `mount -t 9p -o trans=virtio <tag> <dir>`.
+//! Each request chain carries one T-message in its device-readable
+//! descriptors and receives the R-message in its writable ones. Requests
+//! complete synchronously, so Tflush has nothing to cancel.
+//!
+//! Supported messages: version, attach, walk, lopen, lcreate, read, write,
+//! clunk, remove, getattr, setattr (size only), readdir, statfs, mkdir,
+//! unlinkat, renameat, fsync and flush. Anything else is answered with
+//! Rlerror(EOPNOTSUPP). There are no symlinks, device nodes, xattrs or
+//! locks, and ownership is always root.
+//!
+//! Fids refer to paths relative to the share root. Walking `..` from the
+//! root stays at the root, so the guest cannot leave the share.
+//!
+//! ## Queues
+//!
+//! | Index | Name     | Handling                      |
+//! |-------|----------|-------------------------------|
+//! | 0     | requestq | T-message in, R-message out   |
+//!
+//! ## Config Space
+//!
+//! | Offset | Name    | Description                     |
+//! |--------|---------|---------------------------------|
+//! | 0x00   | tag_len | Length of the mount tag (u16)   |
+//! | 0x02   | tag     | Mount tag, not NUL-terminated   |
+
+use crate::dram::{Dram, MemoryError};
+use crate::share::{FileAttr, FileKind, ShareBackend};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use super::device::{self, VirtioDevice};
+
+/// Longest mount tag a device accepts
+pub const P9_MAX_TAG_LEN: usize = 64;
+
+/// Largest message size negotiated with Tversion
+const MAX_MSIZE: u32 = 128 * 1024;
+/// size[4] type[1] tag[2]
+const HDR_SIZE: usize = 7;
+/// Most path elements in one Twalk
+const MAX_WELEM: usize = 16;
+const PROTOCOL: &str = "9P2000.L";
+
+// Message types (the R-message is always T + 1)
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+// Linux errno values carried by Rlerror
+const EPERM: u32 = 1;
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EEXIST: u32 = 17;
+const ENOTDIR: u32 = 20;
+const EISDIR: u32 = 21;
+const EINVAL: u32 = 22;
+const EROFS: u32 = 30;
+const ENAMETOOLONG: u32 = 36;
+const ENOTEMPTY: u32 = 39;
+const EOPNOTSUPP: u32 = 95;
+
+const QTDIR: u8 = 0x80;
+const QTFILE: u8 = 0x00;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+// Tlopen / Tlcreate flags
+const O_ACCMODE: u32 = 0o3;
+const O_TRUNC: u32 = 0o1000;
+/// Tunlinkat flag for removing a directory
+const AT_REMOVEDIR: u32 = 0x200;
+/// Tsetattr valid bit for the size field
+const SETATTR_SIZE: u32 = 0x8;
+/// Rgetattr valid mask: mode through blocks
+const GETATTR_BASIC: u64 = 0x7ff;
+/// Rstatfs filesystem type (V9FS_MAGIC)
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+/// Cursor over a T-message body.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], u32> {
+        let bytes = self.buf.get(self.pos..self.pos + n).ok_or(EINVAL)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, u32> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, u32> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, u32> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| EINVAL)
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Write a qid. The path is a hash of the share path, so it stays stable
+/// for as long as the file keeps its name.
+fn put_qid(out: &mut Vec<u8>, path: &str, kind: FileKind) {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in path.as_bytes() {
+        hash = (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    out.push(match kind {
+        FileKind::Dir => QTDIR,
+        FileKind::File => QTFILE,
+    });
+    out.extend_from_slice(&0u32.to_le_bytes()); // version
+    out.extend_from_slice(&hash.to_le_bytes());
+}
+
+fn errno(err: io::Error) -> u32 {
+    match err.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::NotADirectory => ENOTDIR,
+        io::ErrorKind::IsADirectory => EISDIR,
+        io::ErrorKind::DirectoryNotEmpty => ENOTEMPTY,
+        io::ErrorKind::InvalidInput => EINVAL,
+        io::ErrorKind::ReadOnlyFilesystem => EROFS,
+        io::ErrorKind::Unsupported => EOPNOTSUPP,
+        _ => EIO,
+    }
+}
+
+/// Reject names that are not a single path component.
+fn check_name(name: &str) -> Result<(), u32> {
+    if name.len() > 255 {
+        return Err(ENAMETOOLONG);
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(EINVAL);
+    }
+    Ok(())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// A fid: a path in the share, possibly opened.
+struct Fid {
+    path: String,
+    open: bool,
+}
+
+struct P9Queue {
+    num: u32,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    ready: bool,
+    last_avail_idx: u16,
+}
+
+impl P9Queue {
+    fn new() -> Self {
+        Self {
+            num: 0,
+            desc: 0,
+            avail: 0,
+            used: 0,
+            ready: false,
+            last_avail_idx: 0,
+        }
+    }
+}
+
+/// Internal mutable state for Virtio9p, protected by Mutex
+struct Virtio9pState {
+    driver_features: u32,
+    driver_features_sel: u32,
+    device_features_sel: u32,
+    page_size: u32,
+    queue_sel: u32,
+    interrupt_status: u32,
+    status: u32,
+    queue: P9Queue,
+
+    backend: Box<dyn ShareBackend>,
+    fids: HashMap<u32, Fid>,
+    msize: u32,
+}
+
+/// VirtIO 9P Device
+pub struct Virtio9p {
+    state: Mutex<Virtio9pState>,
+    /// tag_len followed by the tag, as laid out in config space
+    config: Vec<u8>,
+}
+
+impl Virtio9p {
+    /// Export `backend` under the mount tag `tag`.
+    pub fn new(tag: &str, backend: Box<dyn ShareBackend>) -> Result<Self, String> {
+        if tag.is_empty() || tag.len() > P9_MAX_TAG_LEN {
+            return Err(format!(
+                "9p mount tag must be 1 to {} bytes, got {:?}",
+                P9_MAX_TAG_LEN, tag
+            ));
+        }
+        let mut config = Vec::with_capacity(2 + tag.len());
+        config.extend_from_slice(&(tag.len() as u16).to_le_bytes());
+        config.extend_from_slice(tag.as_bytes());
+        Ok(Self {
+            state: Mutex::new(Virtio9pState {
+                driver_features: 0,
+                driver_features_sel: 0,
+                device_features_sel: 0,
+                page_size: 4096,
+                queue_sel: 0,
+                interrupt_status: 0,
+                status: 0,
+                queue: P9Queue::new(),
+                backend,
+                fids: HashMap::new(),
+                msize: MAX_MSIZE,
+            }),
+            config,
+        })
+    }
+
+    /// The mount tag the guest sees.
+    pub fn tag(&self) -> &str {
+        std::str::from_utf8(&self.config[2..]).unwrap_or_default()
+    }
+
+    fn guest_offset(dram: &Dram, addr: u64) -> Result<u64, MemoryError> {
+        dram.offset(addr)
+            .map(|off| off as u64)
+            .ok_or(MemoryError::OutOfBounds(addr))
+    }
+
+    /// Drain the request queue, answering each T-message.
+    fn process_queue(state: &mut Virtio9pState, dram: &Dram) -> Result<(), MemoryError> {
+        let queue = &state.queue;
+        if !queue.ready || queue.desc == 0 {
+            return Ok(());
+        }
+        let (desc, avail, used) = (queue.desc, queue.avail, queue.used);
+        let qsz = if queue.num > 0 {
+            queue.num
+        } else {
+            device::QUEUE_SIZE
+        };
+        let mut last_avail_idx = queue.last_avail_idx;
+
+        let avail_idx = dram.load_16(Self::guest_offset(dram, avail.wrapping_add(2))?)?;
+        let mut processed_any = false;
+        while last_avail_idx != avail_idx {
+            let ring_slot = (last_avail_idx as u32 % qsz) as u64;
+            let head_addr = avail.wrapping_add(4).wrapping_add(ring_slot * 2);
+            let head = dram.load_16(Self::guest_offset(dram, head_addr)?)?;
+
+            // Gather the T-message and the buffers for the reply
+            let mut request = Vec::new();
+            let mut writable = Vec::new();
+            let mut idx = head;
+            for _ in 0..qsz {
+                let off = Self::guest_offset(dram, desc.wrapping_add(idx as u64 * 16))?;
+                let addr = dram.load_64(off)?;
+                let len = dram.load_32(off + 8)?;
+                let flags = dram.load_16(off + 12)? as u64;
+                let next = dram.load_16(off + 14)?;
+                if flags & device::VRING_DESC_F_WRITE != 0 {
+                    writable.push((addr, len));
+                } else {
+                    let start = Self::guest_offset(dram, addr)? as usize;
+                    request.extend_from_slice(&dram.read_range(start, len as usize)?);
+                }
+                if flags & device::VRING_DESC_F_NEXT == 0 {
+                    break;
+                }
+                idx = next;
+            }
+
+            let response = Self::handle_request(state, &request);
+            let mut written = 0;
+            for (addr, len) in writable {
+                if written == response.len() {
+                    break;
+                }
+                let take = (len as usize).min(response.len() - written);
+                dram.write_bytes(
+                    Self::guest_offset(dram, addr)?,
+                    &response[written..written + take],
+                )?;
+                written += take;
+            }
+
+            let used_idx_off = Self::guest_offset(dram, used.wrapping_add(2))?;
+            let used_idx = dram.load_16(used_idx_off)?;
+            let elem_addr = used
+                .wrapping_add(4)
+                .wrapping_add((used_idx as u64 % qsz as u64) * 8);
+            let elem_off = Self::guest_offset(dram, elem_addr)?;
+            dram.store_32(elem_off, head as u64)?;
+            dram.store_32(elem_off + 4, written as u64)?;
+            dram.store_16(used_idx_off, used_idx.wrapping_add(1) as u64)?;
+
+            last_avail_idx = last_avail_idx.wrapping_add(1);
+            processed_any = true;
+        }
+
+        state.queue.last_avail_idx = last_avail_idx;
+        if processed_any {
+            state.interrupt_status |= 1;
+        }
+        Ok(())
+    }
+
+    /// Run one T-message and build the R-message, header included.
+    fn handle_request(state: &mut Virtio9pState, request: &[u8]) -> Vec<u8> {
+        let mut msg = Reader {
+            buf: request,
+            pos: 0,
+        };
+        let (Ok(size), Ok(&[ty]), Ok(tag)) = (msg.u32(), msg.take(1), msg.u16()) else {
+            return Self::reply(RLERROR, 0xffff, &EINVAL.to_le_bytes());
+        };
+        // Ignore anything past the declared size
+        msg.buf = &request[..(size as usize).clamp(HDR_SIZE, request.len())];
+
+        match Self::execute(state, ty, &mut msg) {
+            Ok(body) => Self::reply(ty.wrapping_add(1), tag, &body),
+            Err(err) => {
+                log::debug!("[Virtio9p] Message {} failed: errno {}", ty, err);
+                Self::reply(RLERROR, tag, &err.to_le_bytes())
+            }
+        }
+    }
+
+    fn reply(ty: u8, tag: u16, body: &[u8]) -> Vec<u8> {
+        let mut resp = Vec::with_capacity(HDR_SIZE + body.len());
+        resp.extend_from_slice(&((HDR_SIZE + body.len()) as u32).to_le_bytes());
+        resp.push(ty);
+        resp.extend_from_slice(&tag.to_le_bytes());
+        resp.extend_from_slice(body);
+        resp
+    }
+
+    fn fid(state: &Virtio9pState, fid: u32) -> Result<&Fid, u32> {
+        state.fids.get(&fid).ok_or(EBADF)
+    }
+
+    fn writable(state: &Virtio9pState) -> Result<(), u32> {
+        if state.backend.is_read_only() {
+            Err(EROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn stat(state: &mut Virtio9pState, path: &str) -> Result<FileAttr, u32> {
+        state.backend.stat(path).map_err(errno)
+    }
+
+    /// Path of the directory `fid`, checked to be one.
+    fn dir_path(state: &mut Virtio9pState, fid: u32) -> Result<String, u32> {
+        let path = Self::fid(state, fid)?.path.clone();
+        match Self::stat(state, &path)?.kind {
+            FileKind::Dir => Ok(path),
+            FileKind::File => Err(ENOTDIR),
+        }
+    }
+
+    /// Largest payload of an Rread/Rreaddir: msize less size, type, tag, count.
+    fn io_limit(state: &Virtio9pState) -> u32 {
+        state.msize - HDR_SIZE as u32 - 4
+    }
+
+    fn execute(state: &mut Virtio9pState, ty: u8, msg: &mut Reader) -> Result<Vec<u8>, u32> {
+        let mut out = Vec::new();
+        match ty {
+            TVERSION => {
+                let msize = msg.u32()?;
+                let version = msg.string()?;
+                if msize < 4096 {
+                    return Err(EINVAL);
+                }
+                state.msize = msize.min(MAX_MSIZE);
+                // A new session: every fid from the old one is gone
+                state.fids.clear();
+                out.extend_from_slice(&state.msize.to_le_bytes());
+                put_str(
+                    &mut out,
+                    if version.starts_with(PROTOCOL) {
+                        PROTOCOL
+                    } else {
+                        "unknown"
+                    },
+                );
+            }
+            TATTACH => {
+                let fid = msg.u32()?;
+                let _afid = msg.u32()?;
+                let _uname = msg.string()?;
+                let _aname = msg.string()?;
+                if state.fids.contains_key(&fid) {
+                    return Err(EINVAL);
+                }
+                let attr = Self::stat(state, "")?;
+                state.fids.insert(
+                    fid,
+                    Fid {
+                        path: String::new(),
+                        open: false,
+                    },
+                );
+                put_qid(&mut out, "", attr.kind);
+            }
+            TWALK => {
+                let fid = msg.u32()?;
+                let newfid = msg.u32()?;
+                let nwname = msg.u16()? as usize;
+                if nwname > MAX_WELEM {
+                    return Err(EINVAL);
+                }
+                let names = (0..nwname)
+                    .map(|_| msg.string())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut path = Self::fid(state, fid)?.path.clone();
+                if newfid != fid && state.fids.contains_key(&newfid) {
+                    return Err(EINVAL);
+                }
+
+                let mut qids = Vec::new();
+                for (i, name) in names.iter().enumerate() {
+                    let next = if name == ".." {
+                        parent(&path).to_string()
+                    } else {
+                        check_name(name)?;
+                        join(&path, name)
+                    };
+                    let step = Self::stat(state, &path).and_then(|dir| match dir.kind {
+                        FileKind::Dir => Self::stat(state, &next),
+                        FileKind::File => Err(ENOTDIR),
+                    });
+                    match step {
+                        Ok(attr) => {
+                            put_qid(&mut qids, &next, attr.kind);
+                            path = next;
+                        }
+                        // Only a failing first element is an error
+                        Err(err) if i == 0 => return Err(err),
+                        Err(_) => break,
+                    }
+                }
+                let walked = qids.len() / 13;
+                if walked == nwname {
+                    state.fids.insert(newfid, Fid { path, open: false });
+                }
+                out.extend_from_slice(&(walked as u16).to_le_bytes());
+                out.extend_from_slice(&qids);
+            }
+            TLOPEN => {
+                let fid = msg.u32()?;
+                let flags = msg.u32()?;
+                let path = Self::fid(state, fid)?.path.clone();
+                let attr = Self::stat(state, &path)?;
+                let writes = flags & O_ACCMODE != 0 || flags & O_TRUNC != 0;
+                if writes {
+                    Self::writable(state)?;
+                    if attr.kind == FileKind::Dir {
+                        return Err(EISDIR);
+                    }
+                }
+                if flags & O_TRUNC != 0 {
+                    state.backend.set_len(&path, 0).map_err(errno)?;
+                }
+                state.fids.get_mut(&fid).unwrap().open = true;
+                put_qid(&mut out, &path, attr.kind);
+                out.extend_from_slice(&0u32.to_le_bytes()); // iounit: use msize
+            }
+            TLCREATE => {
+                let fid = msg.u32()?;
+                let name = msg.string()?;
+                let _flags = msg.u32()?;
+                let _mode = msg.u32()?;
+                let _gid = msg.u32()?;
+                Self::writable(state)?;
+                check_name(&name)?;
+                let path = join(&Self::dir_path(state, fid)?, &name);
+                state.backend.create(&path).map_err(errno)?;
+                put_qid(&mut out, &path, FileKind::File);
+                out.extend_from_slice(&0u32.to_le_bytes());
+                // The fid now stands for the new, opened file
+                state.fids.insert(fid, Fid { path, open: true });
+            }
+            TREAD => {
+                let fid = msg.u32()?;
+                let offset = msg.u64()?;
+                let count = msg.u32()?.min(Self::io_limit(state));
+                let f = Self::fid(state, fid)?;
+                if !f.open {
+                    return Err(EBADF);
+                }
+                let path = f.path.clone();
+                let mut data = vec![0u8; count as usize];
+                let n = state
+                    .backend
+                    .read(&path, offset, &mut data)
+                    .map_err(errno)?;
+                out.extend_from_slice(&(n as u32).to_le_bytes());
+                out.extend_from_slice(&data[..n]);
+            }
+            TWRITE => {
+                let fid = msg.u32()?;
+                let offset = msg.u64()?;
+                let count = msg.u32()? as usize;
+                let data = msg.take(count)?;
+                Self::writable(state)?;
+                let f = Self::fid(state, fid)?;
+                if !f.open {
+                    return Err(EBADF);
+                }
+                let path = f.path.clone();
+                let n = state.backend.write(&path, offset, data).map_err(errno)?;
+                out.extend_from_slice(&(n as u32).to_le_bytes());
+            }
+            TCLUNK => {
+                let fid = msg.u32()?;
+                state.fids.remove(&fid).ok_or(EBADF)?;
+            }
+            TREMOVE => {
+                // The fid is clunked even if the remove fails
+                let fid = msg.u32()?;
+                let f = state.fids.remove(&fid).ok_or(EBADF)?;
+                Self::writable(state)?;
+                if f.path.is_empty() {
+                    return Err(EPERM);
+                }
+                state.backend.remove(&f.path).map_err(errno)?;
+            }
+            TGETATTR => {
+                let fid = msg.u32()?;
+                let _mask = msg.u64()?;
+                let path = Self::fid(state, fid)?.path.clone();
+                let attr = Self::stat(state, &path)?;
+                let (mode, nlink) = match attr.kind {
+                    FileKind::Dir => (S_IFDIR | attr.mode, 2u64),
+                    FileKind::File => (S_IFREG | attr.mode, 1u64),
+                };
+                out.extend_from_slice(&GETATTR_BASIC.to_le_bytes());
+                put_qid(&mut out, &path, attr.kind);
+                out.extend_from_slice(&mode.to_le_bytes());
+                out.extend_from_slice(&0u32.to_le_bytes()); // uid
+                out.extend_from_slice(&0u32.to_le_bytes()); // gid
+                for word in [
+                    nlink,
+                    0, // rdev
+                    attr.size,
+                    4096, // blksize
+                    attr.size.div_ceil(512),
+                    attr.mtime, // atime
+                    0,
+                    attr.mtime,
+                    0,
+                    attr.mtime, // ctime
+                    0,
+                    0, // btime
+                    0,
+                    0, // gen
+                    0, // data_version
+                ] {
+                    out.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            TSETATTR => {
+                let fid = msg.u32()?;
+                let valid = msg.u32()?;
+                let _mode = msg.u32()?;
+                let _uid = msg.u32()?;
+                let _gid = msg.u32()?;
+                let size = msg.u64()?;
+                let path = Self::fid(state, fid)?.path.clone();
+                // Ownership, mode and times aren't stored; only size changes stick
+                if valid & SETATTR_SIZE != 0 {
+                    Self::writable(state)?;
+                    state.backend.set_len(&path, size).map_err(errno)?;
+                }
+            }
+            TREADDIR => {
+                let fid = msg.u32()?;
+                let offset = msg.u64()?;
+                let count = msg.u32()?.min(Self::io_limit(state)) as usize;
+                let f = Self::fid(state, fid)?;
+                if !f.open {
+                    return Err(EBADF);
+                }
+                let path = f.path.clone();
+                let mut entries = state.backend.list(&path).map_err(errno)?;
+                entries.sort_by(|a, b| a.name.cmp(&b.name));
+                let dots = [(".", path.as_str()), ("..", parent(&path))];
+
+                // Offsets are entry indexes, so a listing resumes where it stopped
+                let mut data = Vec::new();
+                let all = dots
+                    .iter()
+                    .map(|&(name, target)| (name, target.to_string(), FileKind::Dir))
+                    .chain(
+                        entries
+                            .iter()
+                            .map(|e| (e.name.as_str(), join(&path, &e.name), e.kind)),
+                    );
+                for (i, (name, target, kind)) in all.enumerate().skip(offset as usize) {
+                    if data.len() + 24 + name.len() > count {
+                        break;
+                    }
+                    put_qid(&mut data, &target, kind);
+                    data.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+                    data.push(match kind {
+                        FileKind::Dir => DT_DIR,
+                        FileKind::File => DT_REG,
+                    });
+                    put_str(&mut data, name);
+                }
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out.extend_from_slice(&data);
+            }
+            TSTATFS => {
+                let fid = msg.u32()?;
+                Self::fid(state, fid)?;
+                // The backend has no notion of capacity; report an empty one
+                out.extend_from_slice(&V9FS_MAGIC.to_le_bytes());
+                out.extend_from_slice(&4096u32.to_le_bytes()); // bsize
+                out.extend_from_slice(&[0; 48]); // blocks .. fsid
+                out.extend_from_slice(&255u32.to_le_bytes()); // namelen
+            }
+            TMKDIR => {
+                let dfid = msg.u32()?;
+                let name = msg.string()?;
+                let _mode = msg.u32()?;
+                let _gid = msg.u32()?;
+                Self::writable(state)?;
+                check_name(&name)?;
+                let path = join(&Self::dir_path(state, dfid)?, &name);
+                state.backend.mkdir(&path).map_err(errno)?;
+                put_qid(&mut out, &path, FileKind::Dir);
+            }
+            TUNLINKAT => {
+                let dfid = msg.u32()?;
+                let name = msg.string()?;
+                let flags = msg.u32()?;
+                Self::writable(state)?;
+                check_name(&name)?;
+                let path = join(&Self::dir_path(state, dfid)?, &name);
+                let attr = Self::stat(state, &path)?;
+                match (attr.kind, flags & AT_REMOVEDIR != 0) {
+                    (FileKind::Dir, false) => return Err(EISDIR),
+                    (FileKind::File, true) => return Err(ENOTDIR),
+                    _ => {}
+                }
+                state.backend.remove(&path).map_err(errno)?;
+            }
+            TRENAMEAT => {
+                let old_dfid = msg.u32()?;
+                let old_name = msg.string()?;
+                let new_dfid = msg.u32()?;
+                let new_name = msg.string()?;
+                Self::writable(state)?;
+                check_name(&old_name)?;
+                check_name(&new_name)?;
+                let from = join(&Self::dir_path(state, old_dfid)?, &old_name);
+                let to = join(&Self::dir_path(state, new_dfid)?, &new_name);
+                state.backend.rename(&from, &to).map_err(errno)?;
+                // Fids follow the file to its new name
+                let prefix = format!("{}/", from);
+                for f in state.fids.values_mut() {
+                    if f.path == from {
+                        f.path = to.clone();
+                    } else if let Some(rest) = f.path.strip_prefix(&prefix) {
+                        f.path = join(&to, rest);
+                    }
+                }
+            }
+            TFSYNC => {
+                let fid = msg.u32()?;
+                let _datasync = msg.u32()?;
+                let path = Self::fid(state, fid)?.path.clone();
+                state.backend.sync(&path).map_err(errno)?;
+            }
+            TFLUSH => {
+                // Requests finish before the next is read; nothing to cancel
+                let _oldtag = msg.u16()?;
+            }
+            _ => return Err(EOPNOTSUPP),
+        }
+        Ok(out)
+    }
+}
+
+impl VirtioDevice for Virtio9p {
+    fn device_id(&self) -> u32 {
+        device::VIRTIO_9P_DEVICE_ID
+    }
+
+    fn is_interrupting(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.interrupt_status != 0
+    }
+
+    fn read(&self, offset: u64) -> Result<u64, MemoryError> {
+        let state = self.state.lock().unwrap();
+        let val = match offset {
+            device::MAGIC_VALUE_OFFSET => device::MAGIC_VALUE,
+            device::VERSION_OFFSET => device::VERSION,
+            device::DEVICE_ID_OFFSET => device::VIRTIO_9P_DEVICE_ID as u64,
+            device::VENDOR_ID_OFFSET => device::VENDOR_ID,
+            device::DEVICE_FEATURES_OFFSET if state.device_features_sel == 0 => {
+                1u64 << device::VIRTIO_9P_F_MOUNT_TAG
+            }
+            device::DEVICE_FEATURES_SEL_OFFSET => state.device_features_sel as u64,
+            device::DRIVER_FEATURES_OFFSET => state.driver_features as u64,
+            device::DRIVER_FEATURES_SEL_OFFSET => state.driver_features_sel as u64,
+            device::GUEST_PAGE_SIZE_OFFSET => state.page_size as u64,
+            device::QUEUE_NUM_MAX_OFFSET if state.queue_sel == 0 => device::QUEUE_SIZE as u64,
+            device::QUEUE_SEL_OFFSET => state.queue_sel as u64,
+            device::QUEUE_NUM_OFFSET => state.queue.num as u64,
+            device::QUEUE_READY_OFFSET => state.queue.ready as u64,
+            device::INTERRUPT_STATUS_OFFSET => state.interrupt_status as u64,
+            device::STATUS_OFFSET => state.status as u64,
+            device::CONFIG_GENERATION_OFFSET => 0,
+            // Config space: tag_len and tag, packed into 32-bit words
+            _ if offset >= device::CONFIG_SPACE_OFFSET => {
+                let start = ((offset - device::CONFIG_SPACE_OFFSET) & !3) as usize;
+                self.config
+                    .iter()
+                    .skip(start)
+                    .take(4)
+                    .enumerate()
+                    .fold(0u64, |word, (i, &b)| word | (b as u64) << (i * 8))
+            }
+            _ => 0,
+        };
+        Ok(val)
+    }
+
+    fn write(&self, offset: u64, val: u64, dram: &Dram) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().unwrap();
+        let val32 = val as u32;
+
+        // Only queue 0 exists; writes for other queues are dropped
+        let queue_selected = state.queue_sel == 0;
+        match offset {
+            device::DEVICE_FEATURES_SEL_OFFSET => {
+                state.device_features_sel = val32;
+            }
+            device::DRIVER_FEATURES_OFFSET => {
+                state.driver_features = val32;
+            }
+            device::DRIVER_FEATURES_SEL_OFFSET => {
+                state.driver_features_sel = val32;
+            }
+            device::QUEUE_SEL_OFFSET => {
+                state.queue_sel = val32;
+            }
+            device::QUEUE_NUM_OFFSET if queue_selected => {
+                state.queue.num = val32;
+            }
+            device::GUEST_PAGE_SIZE_OFFSET => {
+                state.page_size = val32;
+            }
+            device::QUEUE_PFN_OFFSET if queue_selected => {
+                let pfn = val32 as u64;
+                if pfn != 0 {
+                    let page_size = state.page_size as u64;
+                    let queue = &mut state.queue;
+                    let desc = pfn * page_size;
+                    queue.desc = desc;
+                    queue.avail = desc + 16 * (queue.num as u64);
+                    // Avail ring size: flags(2) + idx(2) + ring(2*n) + used_event(2) = 6 + 2*n
+                    let avail_size = 6 + 2 * (queue.num as u64);
+                    queue.used = (queue.avail + avail_size + page_size - 1) & !(page_size - 1);
+                    queue.ready = true;
+                }
+            }
+            device::QUEUE_READY_OFFSET if queue_selected => {
+                state.queue.ready = val32 != 0;
+            }
+            device::QUEUE_NOTIFY_OFFSET if val32 == 0 => {
+                Self::process_queue(&mut state, dram)?;
+            }
+            device::INTERRUPT_ACK_OFFSET => {
+                state.interrupt_status &= !val32;
+            }
+            device::STATUS_OFFSET => {
+                if val32 == 0 {
+                    // Reset: forget the session and its fids
+                    state.status = 0;
+                    state.queue = P9Queue::new();
+                    state.interrupt_status = 0;
+                    state.fids.clear();
+                    state.msize = MAX_MSIZE;
+                } else {
+                    state.status = val32;
+                }
+            }
+            device::QUEUE_DESC_LOW_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.desc = (queue.desc & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DESC_HIGH_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.desc = (queue.desc & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            device::QUEUE_DRIVER_LOW_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.avail = (queue.avail & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DRIVER_HIGH_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.avail = (queue.avail & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            device::QUEUE_DEVICE_LOW_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.used = (queue.used & 0xffff_ffff_0000_0000) | (val32 as u64);
+            }
+            device::QUEUE_DEVICE_HIGH_OFFSET if queue_selected => {
+                let queue = &mut state.queue;
+                queue.used = (queue.used & 0x0000_0000_ffff_ffff) | ((val32 as u64) << 32);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::share::HostDir;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Guest memory layout for the tests
+    const DESC: u64 = DRAM_BASE;
+    const AVAIL: u64 = DRAM_BASE + 0x1000;
+    const USED: u64 = DRAM_BASE + 0x2000;
+    const REQ: u64 = DRAM_BASE + 0x3000;
+    const RESP: u64 = DRAM_BASE + 0x10000;
+
+    fn temp_share(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("riscv-vm-p9-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn setup(root: &PathBuf, read_only: bool) -> (Virtio9p, Dram) {
+        let backend = HostDir::new(root, read_only).unwrap();
+        let p9 = Virtio9p::new("host", Box::new(backend)).unwrap();
+        let dram = Dram::new(DRAM_BASE, 1 << 20);
+        p9.write(device::QUEUE_SEL_OFFSET, 0, &dram).unwrap();
+        p9.write(device::QUEUE_NUM_OFFSET, 16, &dram).unwrap();
+        p9.write(device::QUEUE_DESC_LOW_OFFSET, DESC, &dram)
+            .unwrap();
+        p9.write(device::QUEUE_DRIVER_LOW_OFFSET, AVAIL, &dram)
+            .unwrap();
+        p9.write(device::QUEUE_DEVICE_LOW_OFFSET, USED, &dram)
+            .unwrap();
+        p9.write(device::QUEUE_READY_OFFSET, 1, &dram).unwrap();
+        (p9, dram)
+    }
+
+    /// Send a T-message and return (R-message type, body).
+    fn rpc(p9: &Virtio9p, dram: &Dram, ty: u8, body: &[u8]) -> (u8, Vec<u8>) {
+        let off = |addr: u64| addr - DRAM_BASE;
+        let req = Virtio9p::reply(ty, 1, body);
+        dram.write_bytes(off(REQ), &req).unwrap();
+
+        // Two descriptors: T-message, then an 8 KiB reply buffer
+        dram.store_64(off(DESC), REQ).unwrap();
+        dram.store_32(off(DESC) + 8, req.len() as u64).unwrap();
+        dram.store_16(off(DESC) + 12, device::VRING_DESC_F_NEXT)
+            .unwrap();
+        dram.store_16(off(DESC) + 14, 1).unwrap();
+        dram.store_64(off(DESC) + 16, RESP).unwrap();
+        dram.store_32(off(DESC) + 24, 8192).unwrap();
+        dram.store_16(off(DESC) + 28, device::VRING_DESC_F_WRITE)
+            .unwrap();
+
+        let idx = dram.load_16(off(AVAIL) + 2).unwrap();
+        dram.store_16(off(AVAIL) + 4 + (idx as u64 % 16) * 2, 0)
+            .unwrap();
+        dram.store_16(off(AVAIL) + 2, idx.wrapping_add(1) as u64)
+            .unwrap();
+        p9.write(device::QUEUE_NOTIFY_OFFSET, 0, dram).unwrap();
+
+        let used_len = dram
+            .load_32(off(USED) + 4 + (idx as u64 % 16) * 8 + 4)
+            .unwrap();
+        let resp = dram
+            .read_range(off(RESP) as usize, used_len as usize)
+            .unwrap();
+        assert_eq!(u32::from_le_bytes(resp[0..4].try_into().unwrap()), used_len);
+        assert_eq!(&resp[5..7], &1u16.to_le_bytes(), "tag not echoed");
+        (resp[4], resp[HDR_SIZE..].to_vec())
+    }
+
+    /// Build a message body from words and strings.
+    #[derive(Default)]
+    struct Body(Vec<u8>);
+
+    impl Body {
+        fn u16(mut self, v: u16) -> Self {
+            self.0.extend_from_slice(&v.to_le_bytes());
+            self
+        }
+        fn u32(mut self, v: u32) -> Self {
+            self.0.extend_from_slice(&v.to_le_bytes());
+            self
+        }
+        fn u64(mut self, v: u64) -> Self {
+            self.0.extend_from_slice(&v.to_le_bytes());
+            self
+        }
+        fn str(mut self, s: &str) -> Self {
+            put_str(&mut self.0, s);
+            self
+        }
+    }
+
+    fn ok(p9: &Virtio9p, dram: &Dram, ty: u8, body: Body) -> Vec<u8> {
+        let (rty, resp) = rpc(p9, dram, ty, &body.0);
+        assert_eq!(rty, ty + 1, "message {} failed: {:?}", ty, resp);
+        resp
+    }
+
+    fn error(p9: &Virtio9p, dram: &Dram, ty: u8, body: Body) -> u32 {
+        let (rty, resp) = rpc(p9, dram, ty, &body.0);
+        assert_eq!(rty, RLERROR);
+        u32::from_le_bytes(resp[0..4].try_into().unwrap())
+    }
+
+    /// Negotiate the protocol and attach fid 0 to the share root.
+    fn attach(p9: &Virtio9p, dram: &Dram) {
+        let resp = ok(p9, dram, TVERSION, Body::default().u32(8192).str(PROTOCOL));
+        assert_eq!(&resp[6..], PROTOCOL.as_bytes());
+        let qid = ok(
+            p9,
+            dram,
+            TATTACH,
+            Body::default().u32(0).u32(!0).str("root").str("").u32(0),
+        );
+        assert_eq!(qid[0], QTDIR);
+    }
+
+    fn walk(p9: &Virtio9p, dram: &Dram, fid: u32, newfid: u32, names: &[&str]) -> usize {
+        let mut body = Body::default().u32(fid).u32(newfid).u16(names.len() as u16);
+        for name in names {
+            body = body.str(name);
+        }
+        let resp = ok(p9, dram, TWALK, body);
+        u16::from_le_bytes(resp[0..2].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn test_mount_tag_in_config_space() {
+        let root = temp_share("tag");
+        let (p9, _dram) = setup(&root, false);
+        assert_eq!(p9.read(device::DEVICE_ID_OFFSET).unwrap(), 9);
+        assert_eq!(p9.read(device::DEVICE_FEATURES_OFFSET).unwrap(), 1);
+        // tag_len = 4, then "ho" / "st"
+        assert_eq!(p9.read(device::CONFIG_SPACE_OFFSET).unwrap(), 0x6f68_0004);
+        assert_eq!(p9.read(device::CONFIG_SPACE_OFFSET + 4).unwrap(), 0x7473);
+        assert!(Virtio9p::new("", Box::new(HostDir::new(&root, false).unwrap())).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_read_write_and_list_host_files() {
+        let root = temp_share("rw");
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/hello.txt"), b"hello from the host").unwrap();
+        let (p9, dram) = setup(&root, false);
+        attach(&p9, &dram);
+
+        // Read an existing file
+        assert_eq!(walk(&p9, &dram, 0, 1, &["docs", "hello.txt"]), 2);
+        ok(&p9, &dram, TLOPEN, Body::default().u32(1).u32(0));
+        let resp = ok(&p9, &dram, TREAD, Body::default().u32(1).u64(11).u32(100));
+        assert_eq!(&resp[4..], b"the host");
+        let attr = ok(&p9, &dram, TGETATTR, Body::default().u32(1).u64(!0));
+        assert_eq!(
+            u64::from_le_bytes(attr[8 + 13 + 12 + 16..][..8].try_into().unwrap()),
+            19
+        );
+        ok(&p9, &dram, TCLUNK, Body::default().u32(1));
+
+        // Create and write a new one from the guest
+        assert_eq!(walk(&p9, &dram, 0, 2, &["docs"]), 1);
+        ok(
+            &p9,
+            &dram,
+            TLCREATE,
+            Body::default()
+                .u32(2)
+                .str("new.txt")
+                .u32(2)
+                .u32(0o644)
+                .u32(0),
+        );
+        let mut write = Body::default().u32(2).u64(0).u32(5);
+        write.0.extend_from_slice(b"guest");
+        ok(&p9, &dram, TWRITE, write);
+        assert_eq!(fs::read(root.join("docs/new.txt")).unwrap(), b"guest");
+
+        // The listing has the dot entries, then the names in order
+        assert_eq!(walk(&p9, &dram, 0, 3, &["docs"]), 1);
+        ok(&p9, &dram, TLOPEN, Body::default().u32(3).u32(0));
+        let resp = ok(
+            &p9,
+            &dram,
+            TREADDIR,
+            Body::default().u32(3).u64(0).u32(4096),
+        );
+        let mut names = Vec::new();
+        let mut msg = Reader {
+            buf: &resp[4..],
+            pos: 0,
+        };
+        while msg.pos < msg.buf.len() {
+            msg.take(13 + 8 + 1).unwrap();
+            names.push(msg.string().unwrap());
+        }
+        assert_eq!(names, [".", "..", "hello.txt", "new.txt"]);
+
+        // Rename and remove through the directory fid
+        ok(
+            &p9,
+            &dram,
+            TRENAMEAT,
+            Body::default()
+                .u32(3)
+                .str("new.txt")
+                .u32(3)
+                .str("renamed.txt"),
+        );
+        assert!(root.join("docs/renamed.txt").exists());
+        ok(
+            &p9,
+            &dram,
+            TUNLINKAT,
+            Body::default().u32(3).str("renamed.txt").u32(0),
+        );
+        assert!(!root.join("docs/renamed.txt").exists());
+        assert!(p9.is_interrupting());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_guest_stays_inside_share() {
+        let root = temp_share("confine");
+        fs::write(root.join("file"), b"data").unwrap();
+        let (p9, dram) = setup(&root, true);
+        attach(&p9, &dram);
+
+        // `..` at the root is the root itself
+        assert_eq!(walk(&p9, &dram, 0, 1, &["..", ".."]), 2);
+        let attr = ok(&p9, &dram, TGETATTR, Body::default().u32(1).u64(!0));
+        let root_attr = ok(&p9, &dram, TGETATTR, Body::default().u32(0).u64(!0));
+        assert_eq!(attr, root_attr);
+
+        // Names can't smuggle in separators, and missing files fail the walk
+        let bad = Body::default().u32(0).u32(2).u16(1).str("a/../..");
+        assert_eq!(error(&p9, &dram, TWALK, bad), EINVAL);
+        let missing = Body::default().u32(0).u32(2).u16(1).str("nope");
+        assert_eq!(error(&p9, &dram, TWALK, missing), ENOENT);
+
+        // A read-only share refuses writes
+        assert_eq!(walk(&p9, &dram, 0, 2, &["file"]), 1);
+        assert_eq!(
+            error(&p9, &dram, TLOPEN, Body::default().u32(2).u32(2)),
+            EROFS
+        );
+        let mkdir = Body::default().u32(0).str("dir").u32(0o755).u32(0);
+        assert_eq!(error(&p9, &dram, TMKDIR, mkdir), EROFS);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
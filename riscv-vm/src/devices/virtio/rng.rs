@@ -2,7 +2,7 @@ use crate::bus::DRAM_BASE;
 use crate::dram::{Dram, MemoryError};
 use std::sync::Mutex;
 
-use super::device::{self, VirtioDevice};
+use super::device::{self, QueueState, VirtioDevice, VirtioState};
 
 /// Internal mutable state for VirtioRng, protected by Mutex
 struct VirtioRngState {
@@ -230,4 +230,45 @@ impl VirtioDevice for VirtioRng {
         }
         Ok(())
     }
+    fn save_state(&self) -> Option<VirtioState> {
+        let state = self.state.lock().unwrap();
+        Some(VirtioState {
+            device_id: device::VIRTIO_RNG_DEVICE_ID,
+            driver_features: state.driver_features,
+            driver_features_sel: state.driver_features_sel,
+            device_features_sel: state.device_features_sel,
+            page_size: state.page_size,
+            queue_sel: state.queue_sel,
+            interrupt_status: state.interrupt_status,
+            status: state.status,
+            queues: vec![QueueState {
+                num: state.queue_num,
+                desc: state.queue_desc,
+                avail: state.queue_avail,
+                used: state.queue_used,
+                ready: state.queue_ready,
+                last_avail_idx: state.last_avail_idx,
+            }],
+        })
+    }
+
+    fn load_state(&self, saved: &VirtioState) -> Result<(), String> {
+        saved.check(device::VIRTIO_RNG_DEVICE_ID, 1)?;
+        let mut state = self.state.lock().unwrap();
+        state.driver_features = saved.driver_features;
+        state.driver_features_sel = saved.driver_features_sel;
+        state.device_features_sel = saved.device_features_sel;
+        state.page_size = saved.page_size;
+        state.queue_sel = saved.queue_sel;
+        state.interrupt_status = saved.interrupt_status;
+        state.status = saved.status;
+        let queue = &saved.queues[0];
+        state.queue_num = queue.num;
+        state.queue_desc = queue.desc;
+        state.queue_avail = queue.avail;
+        state.queue_used = queue.used;
+        state.queue_ready = queue.ready;
+        state.last_avail_idx = queue.last_avail_idx;
+        Ok(())
+    }
 }
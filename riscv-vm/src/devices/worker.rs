@@ -0,0 +1,154 @@
+//! Per-device worker threads.
+//!
+//! Devices whose backends block (host files, sockets) hand each command to
+//! a dedicated thread over a channel and pick the results up later from
+//! their `poll` hook, so the hart that issued the command never waits on
+//! host I/O. Commands run one at a time in submission order.
+//!
+//! Each worker also records how long commands take from submission until
+//! the device collects the completion, i.e. the latency the guest sees
+//! (including the delay until the next device poll).
+
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Guest-visible latency of a device's commands.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLatency {
+    /// Completions delivered to the device.
+    pub completions: u64,
+    /// Sum of submit-to-delivery times.
+    pub total: Duration,
+    /// Longest submit-to-delivery time.
+    pub max: Duration,
+}
+
+impl DeviceLatency {
+    /// Average submit-to-delivery time.
+    pub fn avg(&self) -> Duration {
+        if self.completions == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.completions as u32
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        self.completions += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+}
+
+impl fmt::Display for DeviceLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests, avg {:.1?}, max {:.1?}",
+            self.completions,
+            self.avg(),
+            self.max
+        )
+    }
+}
+
+/// Shared handle to a worker's latency figures.
+pub type DeviceLatencyHandle = Arc<Mutex<DeviceLatency>>;
+
+/// A thread running one device's commands.
+///
+/// `C` is the command type and `E` the completion event it produces. The
+/// thread exits once the worker is dropped.
+pub struct DeviceWorker<C, E> {
+    commands: Sender<(Instant, C)>,
+    completions: Receiver<(Instant, E)>,
+    latency: DeviceLatencyHandle,
+}
+
+impl<C: Send + 'static, E: Send + 'static> DeviceWorker<C, E> {
+    /// Start a thread named `name` that runs `handler` on every command.
+    pub fn spawn<F>(name: &str, mut handler: F) -> io::Result<Self>
+    where
+        F: FnMut(C) -> E + Send + 'static,
+    {
+        let (commands, rx) = channel::<(Instant, C)>();
+        let (tx, completions) = channel();
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                for (submitted, command) in rx {
+                    if tx.send((submitted, handler(command))).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self {
+            commands,
+            completions,
+            latency: Arc::new(Mutex::new(DeviceLatency::default())),
+        })
+    }
+
+    /// Queue a command. Returns false if the worker thread has died.
+    pub fn submit(&self, command: C) -> bool {
+        self.commands.send((Instant::now(), command)).is_ok()
+    }
+
+    /// Collect every finished command without blocking.
+    pub fn completed(&self) -> Vec<E> {
+        let done: Vec<(Instant, E)> = self.completions.try_iter().collect();
+        self.collect(done)
+    }
+
+    /// Wait for the next `count` commands to finish, or for the thread to
+    /// die.
+    pub fn wait(&self, count: usize) -> Vec<E> {
+        let done: Vec<(Instant, E)> = self.completions.iter().take(count).collect();
+        self.collect(done)
+    }
+
+    fn collect(&self, done: Vec<(Instant, E)>) -> Vec<E> {
+        let now = Instant::now();
+        if !done.is_empty() {
+            let mut latency = self.latency.lock().unwrap();
+            for (submitted, _) in &done {
+                latency.record(now.duration_since(*submitted));
+            }
+        }
+        done.into_iter().map(|(_, event)| event).collect()
+    }
+
+    /// Handle to this worker's latency figures.
+    pub fn latency_handle(&self) -> DeviceLatencyHandle {
+        Arc::clone(&self.latency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_complete_in_order() {
+        let worker = DeviceWorker::spawn("test-worker", |n: u32| n * 2).unwrap();
+        for n in 0..4 {
+            assert!(worker.submit(n));
+        }
+
+        let mut done = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while done.len() < 4 {
+            assert!(Instant::now() < deadline, "worker never finished");
+            done.extend(worker.completed());
+        }
+        assert_eq!(done, vec![0, 2, 4, 6]);
+
+        let latency = worker.latency_handle().lock().unwrap().clone();
+        assert_eq!(latency.completions, 4);
+        assert!(latency.max >= latency.avg());
+    }
+}
@@ -0,0 +1,372 @@
+//! "qcow2-lite": a sparse, copy-on-write disk image format.
+//!
+//! A much smaller cousin of QEMU's qcow2: one flat cluster table, no
+//! compression, snapshots or refcounts. An image either stands alone
+//! (unallocated clusters read as zeros) or overlays a backing image, in
+//! which case unallocated clusters read through to the backing file and the
+//! first write to a cluster copies it up. The backing file is never written,
+//! so a pristine base image can be shared by any number of overlays.
+//!
+//! ## Layout
+//!
+//! | Offset       | Contents                                               |
+//! |--------------|--------------------------------------------------------|
+//! | 0x00         | Magic `QCOWLITE`                                       |
+//! | 0x08         | Version (u32, 1)                                       |
+//! | 0x0c         | Cluster size as a power of two (u32)                   |
+//! | 0x10         | Virtual disk size in bytes (u64)                       |
+//! | 0x18         | Offset of the cluster table (u64)                      |
+//! | 0x20         | Length of the backing file path (u32), 0 for none      |
+//! | 0x24         | Backing file path, UTF-8                               |
+//! | table offset | One u64 per cluster: file offset of its data, 0 if unallocated |
+//!
+//! All integers are little-endian. Data clusters are appended after the
+//! table, cluster-aligned. A cluster's data is written before its table
+//! entry, so an interrupted write leaves at worst an unreferenced cluster.
+
+use super::{BlockBackend, DiskMode, SECTOR_SIZE};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"QCOWLITE";
+const VERSION: u32 = 1;
+/// Bytes reserved for the header, backing path included
+const HEADER_SIZE: u64 = 4096;
+/// 64 KiB clusters
+pub const DEFAULT_CLUSTER_BITS: u32 = 16;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+pub struct CowDisk {
+    file: File,
+    size: u64,
+    cluster_size: u64,
+    table_offset: u64,
+    /// In-memory copy of the cluster table, written through on change
+    table: Vec<u64>,
+    backing: Option<Box<dyn BlockBackend>>,
+    /// Where the next allocated cluster goes
+    next_free: u64,
+    read_only: bool,
+}
+
+impl CowDisk {
+    /// Whether `path` starts with the qcow2-lite magic.
+    pub fn probe(path: &Path) -> io::Result<bool> {
+        let mut magic = [0u8; 8];
+        let mut file = File::open(path)?;
+        match file.read_exact(&mut magic) {
+            Ok(()) => Ok(&magic == MAGIC),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create an overlay on top of `backing`, which is opened read-only
+    /// and must not change while the overlay is in use.
+    pub fn create_overlay(path: &Path, backing: &Path) -> io::Result<Self> {
+        let backing_path = fs::canonicalize(backing)?;
+        let base = super::open(&backing_path, DiskMode::ReadOnly)?;
+        Self::create_with(path, base.len(), Some((&backing_path, base)))
+    }
+
+    /// Create a standalone sparse image of `size` bytes.
+    pub fn create(path: &Path, size: u64) -> io::Result<Self> {
+        if !size.is_multiple_of(SECTOR_SIZE) {
+            return Err(invalid("disk size must be a whole number of sectors"));
+        }
+        Self::create_with(path, size, None)
+    }
+
+    fn create_with(
+        path: &Path,
+        size: u64,
+        backing: Option<(&Path, Box<dyn BlockBackend>)>,
+    ) -> io::Result<Self> {
+        let backing_path = backing
+            .as_ref()
+            .map(|(p, _)| {
+                p.to_str()
+                    .ok_or_else(|| invalid("backing path is not UTF-8"))
+            })
+            .transpose()?
+            .unwrap_or("");
+        if 0x24 + backing_path.len() as u64 > HEADER_SIZE {
+            return Err(invalid("backing path too long"));
+        }
+
+        let cluster_size = 1u64 << DEFAULT_CLUSTER_BITS;
+        let clusters = size.div_ceil(cluster_size);
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&DEFAULT_CLUSTER_BITS.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(&(backing_path.len() as u32).to_le_bytes());
+        header.extend_from_slice(backing_path.as_bytes());
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.write_all(&header)?;
+        // An all-zero table: every cluster unallocated
+        file.set_len(HEADER_SIZE + clusters * 8)?;
+        file.sync_all()?;
+
+        Ok(Self {
+            file,
+            size,
+            cluster_size,
+            table_offset: HEADER_SIZE,
+            table: vec![0; clusters as usize],
+            backing: backing.map(|(_, b)| b),
+            next_free: Self::data_start(HEADER_SIZE, clusters, cluster_size),
+            read_only: false,
+        })
+    }
+
+    /// Open an existing image. The backing file, if any, is opened
+    /// read-only.
+    pub fn open(path: &Path, mode: DiskMode) -> io::Result<Self> {
+        let read_only = mode == DiskMode::ReadOnly;
+        let mut file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        let u32_at = |off: usize| u32::from_le_bytes(header[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(header[off..off + 8].try_into().unwrap());
+
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a qcow2-lite image"));
+        }
+        if u32_at(0x08) != VERSION {
+            return Err(invalid("unsupported qcow2-lite version"));
+        }
+        let cluster_bits = u32_at(0x0c);
+        if !(9..=24).contains(&cluster_bits) {
+            return Err(invalid("bad cluster size"));
+        }
+        let cluster_size = 1u64 << cluster_bits;
+        let size = u64_at(0x10);
+        let table_offset = u64_at(0x18);
+        let backing_len = u32_at(0x20) as usize;
+        if table_offset < HEADER_SIZE || 0x24 + backing_len > HEADER_SIZE as usize {
+            return Err(invalid("corrupt qcow2-lite header"));
+        }
+
+        let clusters = size.div_ceil(cluster_size);
+        let mut raw = vec![0u8; clusters as usize * 8];
+        file.seek(SeekFrom::Start(table_offset))?;
+        file.read_exact(&mut raw)?;
+        let table: Vec<u64> = raw
+            .chunks_exact(8)
+            .map(|e| u64::from_le_bytes(e.try_into().unwrap()))
+            .collect();
+
+        let backing = if backing_len > 0 {
+            let path = std::str::from_utf8(&header[0x24..0x24 + backing_len])
+                .map_err(|_| invalid("backing path is not UTF-8"))?;
+            let base = super::open(&PathBuf::from(path), DiskMode::ReadOnly)?;
+            if base.len() < size {
+                return Err(invalid("backing file is smaller than the image"));
+            }
+            Some(base)
+        } else {
+            None
+        };
+
+        let end = file.metadata()?.len().next_multiple_of(cluster_size);
+        Ok(Self {
+            file,
+            size,
+            cluster_size,
+            table_offset,
+            table,
+            backing,
+            next_free: end.max(Self::data_start(table_offset, clusters, cluster_size)),
+            read_only,
+        })
+    }
+
+    fn data_start(table_offset: u64, clusters: u64, cluster_size: u64) -> u64 {
+        (table_offset + clusters * 8).next_multiple_of(cluster_size)
+    }
+
+    /// Number of clusters holding data of their own.
+    pub fn allocated_clusters(&self) -> usize {
+        self.table.iter().filter(|&&e| e != 0).count()
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "access past end of disk",
+            )),
+        }
+    }
+
+    /// Visit the per-cluster pieces of `offset..offset + len` as
+    /// (cluster index, offset within cluster, position in buffer, length).
+    fn pieces(&self, offset: u64, len: usize) -> Vec<(usize, u64, usize, usize)> {
+        let mut pieces = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let within = pos % self.cluster_size;
+            let n = ((self.cluster_size - within) as usize).min(len - done);
+            pieces.push(((pos / self.cluster_size) as usize, within, done, n));
+            done += n;
+        }
+        pieces
+    }
+
+    /// Read unallocated data: from the backing image, or zeros.
+    fn read_below(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match &mut self.backing {
+            Some(base) => base.read_at(offset, buf),
+            None => {
+                buf.fill(0);
+                Ok(())
+            }
+        }
+    }
+
+    /// Copy cluster `index` up into this image and point the table at it.
+    fn allocate(&mut self, index: usize) -> io::Result<u64> {
+        let start = index as u64 * self.cluster_size;
+        let len = self.cluster_size.min(self.size - start) as usize;
+        let mut data = vec![0u8; self.cluster_size as usize];
+        self.read_below(start, &mut data[..len])?;
+
+        let at = self.next_free;
+        self.file.seek(SeekFrom::Start(at))?;
+        self.file.write_all(&data)?;
+        self.file
+            .seek(SeekFrom::Start(self.table_offset + index as u64 * 8))?;
+        self.file.write_all(&at.to_le_bytes())?;
+        self.table[index] = at;
+        self.next_free += self.cluster_size;
+        Ok(at)
+    }
+}
+
+impl BlockBackend for CowDisk {
+    fn len(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.check_range(offset, buf.len())?;
+        for (index, within, pos, n) in self.pieces(offset, buf.len()) {
+            let out = &mut buf[pos..pos + n];
+            match self.table[index] {
+                0 => self.read_below(offset + pos as u64, out)?,
+                at => {
+                    self.file.seek(SeekFrom::Start(at + within))?;
+                    self.file.read_exact(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "disk is read-only",
+            ));
+        }
+        self.check_range(offset, data.len())?;
+        for (index, within, pos, n) in self.pieces(offset, data.len()) {
+            let at = match self.table[index] {
+                0 => self.allocate(index)?,
+                at => at,
+            };
+            self.file.seek(SeekFrom::Start(at + within))?;
+            self.file.write_all(&data[pos..pos + n])?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.file.sync_data()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("riscv-vm-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_overlay_keeps_base_intact_and_persists() {
+        let base_path = temp_path("base.img");
+        let overlay_path = temp_path("overlay.qcl");
+        // 3 clusters minus a sector, so the last cluster is partial
+        let size = 3 * 65536 - 512;
+        let base: Vec<u8> = (0..size).map(|i| (i / 512) as u8).collect();
+        fs::write(&base_path, &base).unwrap();
+
+        let mut disk = CowDisk::create_overlay(&overlay_path, &base_path).unwrap();
+        assert_eq!(disk.len(), size as u64);
+
+        // A write straddling clusters 0 and 1 copies both up
+        disk.write_at(65536 - 512, &[0xaa; 1024]).unwrap();
+        disk.write_at(size as u64 - 512, &[0xbb; 512]).unwrap();
+        assert_eq!(disk.allocated_clusters(), 3);
+        drop(disk);
+
+        let mut disk = CowDisk::open(&overlay_path, DiskMode::ReadWrite).unwrap();
+        let mut buf = vec![0u8; size];
+        disk.read_at(0, &mut buf).unwrap();
+        let mut expected = base.clone();
+        expected[65536 - 512..65536 + 512].fill(0xaa);
+        expected[size - 512..].fill(0xbb);
+        assert!(buf == expected);
+        assert_eq!(fs::read(&base_path).unwrap(), base);
+
+        assert!(CowDisk::probe(&overlay_path).unwrap());
+        assert!(!CowDisk::probe(&base_path).unwrap());
+        assert!(disk.read_at(size as u64, &mut [0; 1]).is_err());
+
+        let _ = fs::remove_file(&base_path);
+        let _ = fs::remove_file(&overlay_path);
+    }
+
+    #[test]
+    fn test_standalone_image_reads_zeros() {
+        let path = temp_path("sparse.qcl");
+        let mut disk = CowDisk::create(&path, 1 << 20).unwrap();
+        disk.write_at(4096, b"hello").unwrap();
+        drop(disk);
+
+        let mut disk = super::super::open(&path, DiskMode::ReadOnly).unwrap();
+        assert!(disk.is_read_only());
+        let mut buf = [0xffu8; 8];
+        disk.read_at(4094, &mut buf).unwrap();
+        assert_eq!(&buf, b"\0\0hello\0");
+        assert!(disk.write_at(0, &[1]).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,67 @@
+//! Raw disk image file with read/write pass-through.
+
+use super::{BlockBackend, DiskMode};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A raw image file. Guest writes land in the file, so they persist across
+/// runs.
+pub struct FileDisk {
+    file: File,
+    len: u64,
+    read_only: bool,
+}
+
+impl FileDisk {
+    pub fn open(path: &Path, mode: DiskMode) -> io::Result<Self> {
+        let read_only = mode == DiskMode::ReadOnly;
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            len,
+            read_only,
+        })
+    }
+}
+
+impl BlockBackend for FileDisk {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "disk is read-only",
+            ));
+        }
+        if offset + data.len() as u64 > self.len {
+            // Don't let the guest grow the file
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "write past end of disk",
+            ));
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.file.sync_data()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
@@ -0,0 +1,129 @@
+//! Block storage backends for the VirtIO block device.
+//!
+//! [`BlockBackend`] abstracts where disk sectors live, so the same
+//! `VirtioBlock` can serve an in-memory image (the only option in WASM), a
+//! host file with read/write pass-through, or a copy-on-write overlay that
+//! keeps guest changes in a separate file and leaves the base image intact.
+//!
+//! Host files are either raw images or the "qcow2-lite" format written by
+//! [`CowDisk`]; [`open`] tells them apart by their magic.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cow;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod file;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use cow::CowDisk;
+#[cfg(not(target_arch = "wasm32"))]
+pub use file::FileDisk;
+
+use std::io;
+
+/// Sector size used by the VirtIO block protocol.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// Storage behind a VirtIO block device.
+///
+/// Offsets are in bytes; the device only issues whole-sector requests that
+/// lie within [`len`](Self::len).
+pub trait BlockBackend: Send {
+    /// Capacity in bytes.
+    fn len(&self) -> u64;
+
+    /// Fill `buf` from `offset`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Write `data` at `offset`.
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Make completed writes durable.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether writes are refused; offered to the guest as VIRTIO_BLK_F_RO.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A disk image held in memory. Changes are lost when the VM exits.
+pub struct MemoryDisk {
+    data: Vec<u8>,
+}
+
+impl MemoryDisk {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    fn range(&self, offset: u64, len: usize) -> io::Result<std::ops::Range<usize>> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok(start..end),
+            _ => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "access past end of disk",
+            )),
+        }
+    }
+}
+
+impl BlockBackend for MemoryDisk {
+    fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let range = self.range(offset, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let range = self.range(offset, data.len())?;
+        self.data[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// How [`open`] treats a disk image file.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskMode {
+    /// Guest writes go straight to the file.
+    ReadWrite,
+    /// The guest may not write; the device is offered read-only.
+    ReadOnly,
+}
+
+/// Open a raw or qcow2-lite image file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open(path: &std::path::Path, mode: DiskMode) -> io::Result<Box<dyn BlockBackend>> {
+    if CowDisk::probe(path)? {
+        Ok(Box::new(CowDisk::open(path, mode)?))
+    } else {
+        Ok(Box::new(FileDisk::open(path, mode)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_disk_bounds() {
+        let mut disk = MemoryDisk::new(vec![0; 1024]);
+        disk.write_at(512, &[7; 512]).unwrap();
+        let mut buf = [0; 4];
+        disk.read_at(1020, &mut buf).unwrap();
+        assert_eq!(buf, [7; 4]);
+        assert!(disk.read_at(1021, &mut buf).is_err());
+        assert!(disk.write_at(u64::MAX, &buf).is_err());
+    }
+}
@@ -2,9 +2,13 @@
 #[cfg(target_arch = "wasm32")]
 use js_sys::{Atomics, DataView, Int32Array, SharedArrayBuffer, Uint8Array};
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::integrity::{IntegrityMap, PAGE_SIZE};
 #[cfg(not(target_arch = "wasm32"))]
 use std::cell::UnsafeCell;
 #[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
 use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use thiserror::Error;
 
@@ -57,6 +61,9 @@ pub struct Dram {
     size: usize, // Cached size (immutable after creation)
     #[cfg(not(target_arch = "wasm32"))]
     data: UnsafeCell<Vec<u8>>, // Lock-free memory access
+    /// Per-page checksums, present once integrity checking is enabled.
+    #[cfg(not(target_arch = "wasm32"))]
+    integrity: OnceLock<IntegrityMap>,
 
     #[cfg(target_arch = "wasm32")]
     buffer: SharedArrayBuffer,
@@ -94,6 +101,7 @@ impl Dram {
             base,
             size,
             data: UnsafeCell::new(vec![0; size]),
+            integrity: OnceLock::new(),
         }
     }
 
@@ -131,6 +139,16 @@ impl Dram {
         if offset + len > self.size {
             return Err(MemoryError::OutOfBounds(offset as u64));
         }
+        if self.integrity.get().is_some() {
+            const ZEROS: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+            let mut pos = 0;
+            while pos < len {
+                let chunk = (len - pos).min(PAGE_SIZE);
+                self.write_bytes((offset + pos) as u64, &ZEROS[..chunk])?;
+                pos += chunk;
+            }
+            return Ok(());
+        }
         // SAFETY: Bounds checked above, and this is used during initialization
         unsafe {
             let ptr = self.mem_ptr().add(offset);
@@ -209,6 +227,10 @@ impl Dram {
         if off >= self.size {
             return Err(MemoryError::OutOfBounds(offset));
         }
+        if let Some(map) = self.integrity.get() {
+            self.store_tracked(map, off, 1, value);
+            return Ok(());
+        }
         // SAFETY: Bounds checked, lock-free write is safe for RISC-V memory model
         unsafe {
             *self.mem_ptr().add(off) = (value & 0xff) as u8;
@@ -225,6 +247,10 @@ impl Dram {
         if off + 2 > self.size {
             return Err(MemoryError::OutOfBounds(offset));
         }
+        if let Some(map) = self.integrity.get() {
+            self.store_tracked(map, off, 2, value);
+            return Ok(());
+        }
         // SAFETY: Alignment and bounds checked
         unsafe {
             let ptr = self.mem_ptr().add(off) as *mut u16;
@@ -242,6 +268,10 @@ impl Dram {
         if off + 4 > self.size {
             return Err(MemoryError::OutOfBounds(offset));
         }
+        if let Some(map) = self.integrity.get() {
+            self.store_tracked(map, off, 4, value);
+            return Ok(());
+        }
         // Use atomic store with SeqCst to ensure visibility across threads.
         // This is crucial for spinlock synchronization in SMP mode.
         unsafe {
@@ -260,6 +290,10 @@ impl Dram {
         if off + 8 > self.size {
             return Err(MemoryError::OutOfBounds(offset));
         }
+        if let Some(map) = self.integrity.get() {
+            self.store_tracked(map, off, 8, value);
+            return Ok(());
+        }
         // Use atomic store with SeqCst to ensure visibility across threads.
         // This is crucial for spinlock synchronization in SMP mode.
         unsafe {
@@ -275,6 +309,18 @@ impl Dram {
         if off + data.len() > self.size {
             return Err(MemoryError::OutOfBounds(offset));
         }
+        if let Some(map) = self.integrity.get() {
+            let mut pos = 0;
+            while pos < data.len() {
+                let addr = off + pos;
+                let len = (8 - addr % 8).min(data.len() - pos);
+                let mut bytes = [0u8; 8];
+                bytes[..len].copy_from_slice(&data[pos..pos + len]);
+                self.store_tracked(map, addr, len, u64::from_le_bytes(bytes));
+                pos += len;
+            }
+            return Ok(());
+        }
         // SAFETY: Bounds checked
         unsafe {
             let dst = self.mem_ptr().add(off);
@@ -312,8 +358,73 @@ impl Dram {
         unsafe {
             (*self.data.get()).clone_from_slice(data);
         }
+        if let Some(map) = self.integrity.get() {
+            map.rebuild(self.words());
+        }
         Ok(())
     }
+
+    // ========== INTEGRITY CHECKING ==========
+
+    /// DRAM viewed as little-endian 64-bit words.
+    fn words(&self) -> &[AtomicU64] {
+        // SAFETY: the backing Vec is 8-byte aligned (checked when integrity
+        // is enabled) and lives as long as `self`
+        unsafe { std::slice::from_raw_parts(self.mem_ptr() as *const AtomicU64, self.size / 8) }
+    }
+
+    /// Store the low `len` bytes of `value` at `off` through the checksum map.
+    /// The bytes must not cross a 64-bit word boundary.
+    #[inline]
+    fn store_tracked(&self, map: &IntegrityMap, off: usize, len: usize, value: u64) {
+        let shift = (off % 8) * 8;
+        let mask = if len == 8 {
+            u64::MAX
+        } else {
+            ((1u64 << (len * 8)) - 1) << shift
+        };
+        map.store(self.words(), off / 8, mask, value << shift);
+    }
+
+    /// Start maintaining per-page checksums for the current contents.
+    ///
+    /// Returns false if checking is already enabled or the backing store
+    /// cannot be viewed as 64-bit words. Call before the harts start; it
+    /// hashes all of DRAM once.
+    pub fn enable_integrity(&self) -> bool {
+        // SAFETY: only the address is inspected
+        let aligned = (unsafe { self.mem_ptr() } as usize).is_multiple_of(8);
+        if !aligned || !self.size.is_multiple_of(8) || self.integrity.get().is_some() {
+            return false;
+        }
+        self.integrity
+            .set(IntegrityMap::new(self.base, self.words()))
+            .is_ok()
+    }
+
+    /// Checksum map, if integrity checking is enabled.
+    pub fn integrity(&self) -> Option<&IntegrityMap> {
+        self.integrity.get()
+    }
+
+    /// Verify up to `max_pages` cold pages. Returns the number of corrupted
+    /// pages found (0 when checking is disabled).
+    pub fn verify_integrity(&self, max_pages: usize) -> usize {
+        match self.integrity.get() {
+            Some(map) => map.verify(self.words(), max_pages),
+            None => 0,
+        }
+    }
+
+    /// Flip bits behind the checksum map's back, simulating a host fault.
+    #[cfg(test)]
+    pub(crate) fn poke_untracked(&self, offset: usize, xor: u8) {
+        assert!(offset < self.size);
+        // SAFETY: bounds checked above
+        unsafe {
+            *self.mem_ptr().add(offset) ^= xor;
+        }
+    }
 }
 
 // ============================================================================
@@ -1,7 +0,0 @@
-// This file is deprecated and its contents have been moved:
-// - Snapshot types -> src/snapshot.rs
-// - ELF loading -> src/loader.rs
-// - NativeVm, SharedState -> src/vm/native.rs
-// - Emulator -> src/vm/emulator.rs
-//
-// This file can be safely deleted.
@@ -0,0 +1,338 @@
+//! Bit-manipulation extensions Zba, Zbb and Zbs (together, `B`).
+//!
+//! Their encodings share the OP, OP-32, OP-IMM and OP-IMM-32 major opcodes
+//! with the base ISA. [`decode`] picks them out before the base decoder
+//! sees them, producing [`Op::Bit`] (register and unary forms) or
+//! [`Op::BitImm`] (shift-amount forms such as `rori` and `bseti`). Every
+//! engine computes them with [`BitOp::eval`], and the JIT lowers each to
+//! a few Cranelift instructions.
+
+use super::decoder::{Op, Register};
+
+/// A Zba/Zbb/Zbs operation. Immediate forms reuse the register form's
+/// variant (`rori` is [`BitOp::Ror`] with the shift amount as operand),
+/// except `slli.uw`, which only exists as an immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    // Zba
+    AddUw,
+    Sh1Add,
+    Sh2Add,
+    Sh3Add,
+    Sh1AddUw,
+    Sh2AddUw,
+    Sh3AddUw,
+    SlliUw,
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Clzw,
+    Ctz,
+    Ctzw,
+    Cpop,
+    Cpopw,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Rolw,
+    Ror,
+    Rorw,
+    OrcB,
+    Rev8,
+    // Zbs
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+}
+
+/// Decode `insn` if it is a Zba/Zbb/Zbs instruction.
+pub fn decode(insn: u32) -> Option<Op> {
+    let opcode = insn & 0x7F;
+    let rd = Register::from_u32((insn >> 7) & 0x1F);
+    let funct3 = (insn >> 12) & 0x7;
+    let rs1 = Register::from_u32((insn >> 15) & 0x1F);
+    let rs2 = Register::from_u32((insn >> 20) & 0x1F);
+    let funct7 = insn >> 25;
+    let funct6 = insn >> 26;
+    let imm12 = insn >> 20;
+
+    let unary = |op| {
+        Some(Op::Bit {
+            op,
+            rd,
+            rs1,
+            rs2: Register::X0,
+        })
+    };
+    let shift = |op, bits: u32| {
+        Some(Op::BitImm {
+            op,
+            rd,
+            rs1,
+            shamt: imm12 & ((1 << bits) - 1),
+        })
+    };
+
+    let op = match (opcode, funct3) {
+        // OP
+        (0x33, _) => match (funct7, funct3) {
+            (0x10, 2) => BitOp::Sh1Add,
+            (0x10, 4) => BitOp::Sh2Add,
+            (0x10, 6) => BitOp::Sh3Add,
+            (0x20, 7) => BitOp::Andn,
+            (0x20, 6) => BitOp::Orn,
+            (0x20, 4) => BitOp::Xnor,
+            (0x05, 4) => BitOp::Min,
+            (0x05, 5) => BitOp::Minu,
+            (0x05, 6) => BitOp::Max,
+            (0x05, 7) => BitOp::Maxu,
+            (0x30, 1) => BitOp::Rol,
+            (0x30, 5) => BitOp::Ror,
+            (0x24, 1) => BitOp::Bclr,
+            (0x24, 5) => BitOp::Bext,
+            (0x34, 1) => BitOp::Binv,
+            (0x14, 1) => BitOp::Bset,
+            _ => return None,
+        },
+        // OP-32
+        (0x3B, _) => match (funct7, funct3) {
+            (0x04, 0) => BitOp::AddUw,
+            (0x04, 4) if rs2 == Register::X0 => return unary(BitOp::ZextH),
+            (0x10, 2) => BitOp::Sh1AddUw,
+            (0x10, 4) => BitOp::Sh2AddUw,
+            (0x10, 6) => BitOp::Sh3AddUw,
+            (0x30, 1) => BitOp::Rolw,
+            (0x30, 5) => BitOp::Rorw,
+            _ => return None,
+        },
+        // OP-IMM
+        (0x13, 1) => {
+            return match (imm12, funct6) {
+                (0x600, _) => unary(BitOp::Clz),
+                (0x601, _) => unary(BitOp::Ctz),
+                (0x602, _) => unary(BitOp::Cpop),
+                (0x604, _) => unary(BitOp::SextB),
+                (0x605, _) => unary(BitOp::SextH),
+                (_, 0x12) => shift(BitOp::Bclr, 6),
+                (_, 0x1A) => shift(BitOp::Binv, 6),
+                (_, 0x0A) => shift(BitOp::Bset, 6),
+                _ => None,
+            };
+        }
+        (0x13, 5) => {
+            return match (imm12, funct6) {
+                (0x287, _) => unary(BitOp::OrcB),
+                (0x6B8, _) => unary(BitOp::Rev8),
+                (_, 0x18) => shift(BitOp::Ror, 6),
+                (_, 0x12) => shift(BitOp::Bext, 6),
+                _ => None,
+            };
+        }
+        // OP-IMM-32
+        (0x1B, 1) => {
+            return match (imm12, funct6) {
+                (0x600, _) => unary(BitOp::Clzw),
+                (0x601, _) => unary(BitOp::Ctzw),
+                (0x602, _) => unary(BitOp::Cpopw),
+                (_, 0x02) => shift(BitOp::SlliUw, 6),
+                _ => None,
+            };
+        }
+        (0x1B, 5) if funct7 == 0x30 => return shift(BitOp::Rorw, 5),
+        _ => return None,
+    };
+    Some(Op::Bit { op, rd, rs1, rs2 })
+}
+
+impl BitOp {
+    /// Result for `a` = rs1 and `b` = rs2 or the shift amount (ignored by
+    /// unary ops).
+    #[inline]
+    pub fn eval(self, a: u64, b: u64) -> u64 {
+        let uw = a as u32 as u64;
+        let bit = 1u64 << (b & 0x3F);
+        let sext_w = |w: u32| w as i32 as i64 as u64;
+        match self {
+            BitOp::AddUw => b.wrapping_add(uw),
+            BitOp::Sh1Add => b.wrapping_add(a << 1),
+            BitOp::Sh2Add => b.wrapping_add(a << 2),
+            BitOp::Sh3Add => b.wrapping_add(a << 3),
+            BitOp::Sh1AddUw => b.wrapping_add(uw << 1),
+            BitOp::Sh2AddUw => b.wrapping_add(uw << 2),
+            BitOp::Sh3AddUw => b.wrapping_add(uw << 3),
+            BitOp::SlliUw => uw << (b & 0x3F),
+            BitOp::Andn => a & !b,
+            BitOp::Orn => a | !b,
+            BitOp::Xnor => !(a ^ b),
+            BitOp::Clz => a.leading_zeros() as u64,
+            BitOp::Clzw => (a as u32).leading_zeros() as u64,
+            BitOp::Ctz => a.trailing_zeros() as u64,
+            BitOp::Ctzw => (a as u32).trailing_zeros() as u64,
+            BitOp::Cpop => a.count_ones() as u64,
+            BitOp::Cpopw => (a as u32).count_ones() as u64,
+            BitOp::Max => (a as i64).max(b as i64) as u64,
+            BitOp::Maxu => a.max(b),
+            BitOp::Min => (a as i64).min(b as i64) as u64,
+            BitOp::Minu => a.min(b),
+            BitOp::SextB => a as i8 as i64 as u64,
+            BitOp::SextH => a as i16 as i64 as u64,
+            BitOp::ZextH => a as u16 as u64,
+            BitOp::Rol => a.rotate_left((b & 0x3F) as u32),
+            BitOp::Rolw => sext_w((a as u32).rotate_left((b & 0x1F) as u32)),
+            BitOp::Ror => a.rotate_right((b & 0x3F) as u32),
+            BitOp::Rorw => sext_w((a as u32).rotate_right((b & 0x1F) as u32)),
+            BitOp::OrcB => {
+                let bytes = a.to_le_bytes().map(|byte| if byte == 0 { 0 } else { 0xFF });
+                u64::from_le_bytes(bytes)
+            }
+            BitOp::Rev8 => a.swap_bytes(),
+            BitOp::Bclr => a & !bit,
+            BitOp::Bext => (a >> (b & 0x3F)) & 1,
+            BitOp::Binv => a ^ bit,
+            BitOp::Bset => a | bit,
+        }
+    }
+
+    /// Whether the op only reads rs1.
+    pub fn is_unary(self) -> bool {
+        matches!(
+            self,
+            BitOp::Clz
+                | BitOp::Clzw
+                | BitOp::Ctz
+                | BitOp::Ctzw
+                | BitOp::Cpop
+                | BitOp::Cpopw
+                | BitOp::SextB
+                | BitOp::SextH
+                | BitOp::ZextH
+                | BitOp::OrcB
+                | BitOp::Rev8
+        )
+    }
+
+    /// Assembler mnemonic of the register or unary form.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            BitOp::AddUw => "add.uw",
+            BitOp::Sh1Add => "sh1add",
+            BitOp::Sh2Add => "sh2add",
+            BitOp::Sh3Add => "sh3add",
+            BitOp::Sh1AddUw => "sh1add.uw",
+            BitOp::Sh2AddUw => "sh2add.uw",
+            BitOp::Sh3AddUw => "sh3add.uw",
+            BitOp::SlliUw => "slli.uw",
+            BitOp::Andn => "andn",
+            BitOp::Orn => "orn",
+            BitOp::Xnor => "xnor",
+            BitOp::Clz => "clz",
+            BitOp::Clzw => "clzw",
+            BitOp::Ctz => "ctz",
+            BitOp::Ctzw => "ctzw",
+            BitOp::Cpop => "cpop",
+            BitOp::Cpopw => "cpopw",
+            BitOp::Max => "max",
+            BitOp::Maxu => "maxu",
+            BitOp::Min => "min",
+            BitOp::Minu => "minu",
+            BitOp::SextB => "sext.b",
+            BitOp::SextH => "sext.h",
+            BitOp::ZextH => "zext.h",
+            BitOp::Rol => "rol",
+            BitOp::Rolw => "rolw",
+            BitOp::Ror => "ror",
+            BitOp::Rorw => "rorw",
+            BitOp::OrcB => "orc.b",
+            BitOp::Rev8 => "rev8",
+            BitOp::Bclr => "bclr",
+            BitOp::Bext => "bext",
+            BitOp::Binv => "binv",
+            BitOp::Bset => "bset",
+        }
+    }
+
+    /// Assembler mnemonic of the immediate form.
+    pub fn imm_mnemonic(self) -> &'static str {
+        match self {
+            BitOp::Ror => "rori",
+            BitOp::Rorw => "roriw",
+            BitOp::Bclr => "bclri",
+            BitOp::Bext => "bexti",
+            BitOp::Binv => "binvi",
+            BitOp::Bset => "bseti",
+            other => other.mnemonic(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op_of(insn: u32) -> (BitOp, bool) {
+        match decode(insn) {
+            Some(Op::Bit { op, .. }) => (op, false),
+            Some(Op::BitImm { op, .. }) => (op, true),
+            other => panic!("{:#010x} decoded as {:?}", insn, other),
+        }
+    }
+
+    #[test]
+    fn test_decode_leaves_base_instructions_alone() {
+        // add, slli a0, a0, 3, srai a0, a0, 3, addw, srliw
+        for insn in [
+            0x00c5_8533,
+            0x0035_1513,
+            0x4035_5513,
+            0x00c5_853b,
+            0x0035_551b,
+        ] {
+            assert!(decode(insn).is_none(), "{:#010x}", insn);
+        }
+    }
+
+    #[test]
+    fn test_decode_and_eval() {
+        // sh2add a0, a1, a2
+        assert_eq!(op_of(0x20c5_c533), (BitOp::Sh2Add, false));
+        // clz a0, a1
+        assert_eq!(op_of(0x6005_9513), (BitOp::Clz, false));
+        // rev8 a0, a1
+        assert_eq!(op_of(0x6b85_d513), (BitOp::Rev8, false));
+        // rori a0, a1, 8
+        assert_eq!(op_of(0x6085_d513), (BitOp::Ror, true));
+        // bseti a0, a1, 63
+        assert_eq!(op_of(0x2bf5_9513), (BitOp::Bset, true));
+        // zext.h a0, a1
+        assert_eq!(op_of(0x0805_c53b), (BitOp::ZextH, false));
+        // slli.uw a0, a1, 2
+        assert_eq!(op_of(0x0825_951b), (BitOp::SlliUw, true));
+
+        assert_eq!(BitOp::Sh2Add.eval(3, 100), 112);
+        assert_eq!(BitOp::AddUw.eval(u64::MAX, 1), 1 << 32);
+        assert_eq!(BitOp::Clzw.eval(1, 0), 31);
+        assert_eq!(BitOp::Cpop.eval(0xF0F0, 0), 8);
+        assert_eq!(
+            BitOp::OrcB.eval(0x0100_0000_0020_0000, 0),
+            0xFF00_0000_00FF_0000
+        );
+        assert_eq!(
+            BitOp::Rev8.eval(0x0102_0304_0506_0708, 0),
+            0x0807_0605_0403_0201
+        );
+        assert_eq!(BitOp::Rorw.eval(1, 1), 0xFFFF_FFFF_8000_0000);
+        assert_eq!(BitOp::Min.eval(u64::MAX, 1), u64::MAX);
+        assert_eq!(BitOp::Minu.eval(u64::MAX, 1), 1);
+        assert_eq!(BitOp::SextB.eval(0x80, 0), 0xFFFF_FFFF_FFFF_FF80);
+        assert_eq!(BitOp::Bext.eval(0b100, 2), 1);
+        assert_eq!(BitOp::Binv.eval(0, 65), 2);
+    }
+}
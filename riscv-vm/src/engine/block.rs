@@ -26,12 +26,16 @@ pub struct Block {
     pub len: u8,
     /// Total bytes consumed by RISC-V instructions in this block.
     pub byte_len: u16,
+    /// Cycles charged for running the whole block (sum of op costs).
+    pub cost: u32,
     /// Pre-decoded micro-operations.
     pub ops: [MicroOp; MAX_BLOCK_SIZE],
     /// Execution count for profiling/optimization.
     pub exec_count: u32,
     /// Generation counter (for cache invalidation).
     pub generation: u32,
+    /// Compiled ahead of execution and not run yet.
+    pub prefetched: bool,
 }
 
 impl Block {
@@ -42,9 +46,11 @@ impl Block {
             start_pa,
             len: 0,
             byte_len: 0,
+            cost: 0,
             ops: [MicroOp::Fence; MAX_BLOCK_SIZE], // Dummy init
             exec_count: 0,
             generation,
+            prefetched: false,
         }
     }
 
@@ -58,6 +64,7 @@ impl Block {
         self.ops[self.len as usize] = op;
         self.len += 1;
         self.byte_len += insn_len as u16;
+        self.cost += op.cost();
         true
     }
 
@@ -72,6 +79,87 @@ impl Block {
     pub fn ops(&self) -> &[MicroOp] {
         &self.ops[..self.len as usize]
     }
+
+    /// Cycles charged when the block stops at `pc` (a trap or an exit to the
+    /// interpreter): the cost of the ops before the one at `pc`, which is
+    /// either abandoned or re-executed by the interpreter.
+    pub fn cost_before(&self, pc: u64) -> u32 {
+        let offset = pc.wrapping_sub(self.start_pc);
+        self.ops()
+            .iter()
+            .take_while(|op| op.pc_offset().map(u64::from) != Some(offset))
+            .map(MicroOp::cost)
+            .sum()
+    }
+
+    /// Instructions completed when the block stops at `pc`, counted like
+    /// [`Block::cost_before`].
+    pub fn insns_before(&self, pc: u64) -> u32 {
+        let offset = pc.wrapping_sub(self.start_pc);
+        self.ops()
+            .iter()
+            .take_while(|op| op.pc_offset().map(u64::from) != Some(offset))
+            .count() as u32
+    }
+
+    /// PCs control can reach from this block other than through an indirect
+    /// jump or a trap: the target of a direct jump or branch, and the
+    /// fall-through of a branch or of a block cut off by its size or page.
+    pub fn successors(&self) -> [Option<u64>; 2] {
+        let at = |pc_offset: u16, delta: i64| {
+            self.start_pc
+                .wrapping_add(pc_offset as u64)
+                .wrapping_add(delta as u64)
+        };
+        match self.ops().last() {
+            Some(&MicroOp::Jal { imm, pc_offset, .. }) => [Some(at(pc_offset, imm)), None],
+            Some(
+                &MicroOp::Beq {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                }
+                | &MicroOp::Bne {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                }
+                | &MicroOp::Blt {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                }
+                | &MicroOp::Bge {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                }
+                | &MicroOp::Bltu {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                }
+                | &MicroOp::Bgeu {
+                    imm,
+                    pc_offset,
+                    insn_len,
+                    ..
+                },
+            ) => [
+                Some(at(pc_offset, imm)),
+                Some(at(pc_offset, insn_len as i64)),
+            ],
+            Some(op) if !op.is_terminator() => {
+                [Some(self.start_pc.wrapping_add(self.byte_len as u64)), None]
+            }
+            _ => [None, None],
+        }
+    }
 }
 
 /// Result of block compilation.
@@ -139,7 +227,12 @@ impl<'a> BlockCompiler<'a> {
             };
 
             // Convert to MicroOp
-            let micro_op = self.transcode(op, pc_offset, insn_len);
+            let Some(micro_op) = self.transcode(op, raw, pc_offset, insn_len) else {
+                if block.len > 0 {
+                    return CompileResult::Ok(block);
+                }
+                return CompileResult::Unsuitable;
+            };
             let is_term = micro_op.is_terminator();
 
             // Add to block
@@ -229,9 +322,13 @@ impl<'a> BlockCompiler<'a> {
         }
     }
 
-    /// Transcode a decoded Op into a MicroOp.
-    fn transcode(&self, op: Op, pc_offset: u16, insn_len: u8) -> MicroOp {
-        match op {
+    /// Transcode a decoded Op into a MicroOp, or `None` for encodings
+    /// left to the interpreter (which raises illegal instruction for them).
+    ///
+    /// `raw` is the (expanded) 32-bit encoding, kept by ops that are executed
+    /// from their encoding.
+    fn transcode(&self, op: Op, raw: u32, pc_offset: u16, insn_len: u8) -> Option<MicroOp> {
+        Some(match op {
             Op::Lui { rd, imm } => MicroOp::Lui {
                 rd: rd.to_usize() as u8,
                 imm,
@@ -309,7 +406,7 @@ impl<'a> BlockCompiler<'a> {
                         pc_offset,
                         insn_len,
                     },
-                    _ => MicroOp::Fence, // Should not happen
+                    _ => return None,
                 }
             }
 
@@ -364,7 +461,7 @@ impl<'a> BlockCompiler<'a> {
                         imm,
                         pc_offset,
                     },
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -401,7 +498,7 @@ impl<'a> BlockCompiler<'a> {
                         imm,
                         pc_offset,
                     },
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -434,7 +531,7 @@ impl<'a> BlockCompiler<'a> {
                             MicroOp::Srli { rd, rs1, shamt }
                         }
                     }
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -467,7 +564,7 @@ impl<'a> BlockCompiler<'a> {
                     (6, 0x01) => MicroOp::Rem { rd, rs1, rs2 },
                     (7, 0x00) => MicroOp::And { rd, rs1, rs2 },
                     (7, 0x01) => MicroOp::Remu { rd, rs1, rs2 },
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -499,7 +596,7 @@ impl<'a> BlockCompiler<'a> {
                             MicroOp::Srliw { rd, rs1, shamt }
                         }
                     }
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -524,7 +621,7 @@ impl<'a> BlockCompiler<'a> {
                     (5, 0x01) => MicroOp::Divuw { rd, rs1, rs2 },
                     (6, 0x01) => MicroOp::Remw { rd, rs1, rs2 },
                     (7, 0x01) => MicroOp::Remuw { rd, rs1, rs2 },
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -552,7 +649,7 @@ impl<'a> BlockCompiler<'a> {
                                 if funct7 == 0x09 {
                                     MicroOp::SfenceVma { pc_offset }
                                 } else {
-                                    MicroOp::Fence // Unknown, treat as fence
+                                    return None;
                                 }
                             }
                         }
@@ -593,7 +690,7 @@ impl<'a> BlockCompiler<'a> {
                         csr: (imm & 0xFFF) as u16,
                         pc_offset,
                     },
-                    _ => MicroOp::Fence,
+                    _ => return None,
                 }
             }
 
@@ -700,12 +797,90 @@ impl<'a> BlockCompiler<'a> {
                         is_word,
                         pc_offset,
                     },
-                    _ => MicroOp::Fence,
+                    _ => return None,
+                }
+            }
+
+            Op::LoadFp {
+                rd,
+                rs1,
+                imm,
+                funct3,
+            } => {
+                let rd = rd.to_usize() as u8;
+                let rs1 = rs1.to_usize() as u8;
+                match funct3 {
+                    2 => MicroOp::Flw {
+                        rd,
+                        rs1,
+                        imm,
+                        pc_offset,
+                    },
+                    3 => MicroOp::Fld {
+                        rd,
+                        rs1,
+                        imm,
+                        pc_offset,
+                    },
+                    // Unsupported width: raises illegal instruction when run
+                    _ => MicroOp::FpOp {
+                        insn: raw,
+                        pc_offset,
+                    },
+                }
+            }
+
+            Op::StoreFp {
+                rs1,
+                rs2,
+                imm,
+                funct3,
+            } => {
+                let rs1 = rs1.to_usize() as u8;
+                let rs2 = rs2.to_usize() as u8;
+                match funct3 {
+                    2 => MicroOp::Fsw {
+                        rs1,
+                        rs2,
+                        imm,
+                        pc_offset,
+                    },
+                    3 => MicroOp::Fsd {
+                        rs1,
+                        rs2,
+                        imm,
+                        pc_offset,
+                    },
+                    _ => MicroOp::FpOp {
+                        insn: raw,
+                        pc_offset,
+                    },
                 }
             }
 
+            Op::OpFp { .. } | Op::FusedMulAdd { .. } => MicroOp::FpOp {
+                insn: raw,
+                pc_offset,
+            },
+
+            Op::Vector { insn } => MicroOp::Vector { insn, pc_offset },
+
+            Op::Bit { op, rd, rs1, rs2 } => MicroOp::Bit {
+                op,
+                rd: rd.to_usize() as u8,
+                rs1: rs1.to_usize() as u8,
+                rs2: rs2.to_usize() as u8,
+            },
+            Op::BitImm { op, rd, rs1, shamt } => MicroOp::BitImm {
+                op,
+                rd: rd.to_usize() as u8,
+                rs1: rs1.to_usize() as u8,
+                shamt: shamt as u8,
+            },
+
             Op::Fence => MicroOp::Fence,
-        }
+            Op::FenceI => MicroOp::FenceI { pc_offset },
+        })
     }
 }
 
@@ -742,6 +917,46 @@ mod tests {
         assert_eq!(block.byte_len, 8);
     }
 
+    #[test]
+    fn test_block_successors() {
+        let mut block = Block::new(0x8000_0000, 0x8000_0000, 0);
+        block.push(
+            MicroOp::Addi {
+                rd: 1,
+                rs1: 0,
+                imm: 1,
+            },
+            4,
+        );
+        // A block cut off before its terminator falls through
+        assert_eq!(block.successors(), [Some(0x8000_0004), None]);
+
+        block.push(
+            MicroOp::Bne {
+                rs1: 1,
+                rs2: 0,
+                imm: -4,
+                pc_offset: 4,
+                insn_len: 2,
+            },
+            2,
+        );
+        assert_eq!(block.successors(), [Some(0x8000_0000), Some(0x8000_0006)]);
+
+        let mut block = Block::new(0x8000_0000, 0x8000_0000, 0);
+        block.push(
+            MicroOp::Jalr {
+                rd: 0,
+                rs1: 1,
+                imm: 0,
+                pc_offset: 0,
+                insn_len: 4,
+            },
+            4,
+        );
+        assert_eq!(block.successors(), [None, None]);
+    }
+
     #[test]
     fn test_block_max_size() {
         let mut block = Block::new(0x8000_0000, 0x8000_0000, 0);
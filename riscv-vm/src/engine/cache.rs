@@ -2,19 +2,69 @@
 //!
 //! Manages a cache of compiled basic blocks keyed by PC. Uses generation-based
 //! invalidation for efficient TLB flush handling.
+//!
+//! The cache also records which physical pages hold compiled code, so stores
+//! can cheaply detect self-modifying code (see [`BlockCache::is_code`]).
+//!
+//! Blocks can also be compiled speculatively, before control reaches them
+//! (see [`BlockCache::insert_prefetched`]); the cache counts how many of
+//! those end up being run.
+//!
+//! Blocks run out of the cache: [`BlockCache::take`] hands the boxed block to
+//! the dispatch loop and [`BlockCache::put_back`] returns it, so executing a
+//! block never copies its micro-ops.
 
 use super::block::Block;
 #[cfg(test)]
 use super::microop::MicroOp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+
+/// Hasher for guest addresses, which are already well spread in their low
+/// bits: a multiply instead of SipHash keeps block dispatch cheap.
+#[derive(Default, Clone, Copy)]
+pub struct PcHasher(u64);
+
+impl Hasher for PcHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0.rotate_left(8) ^ byte as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        }
+    }
+
+    #[inline]
+    fn write_u64(&mut self, pc: u64) {
+        self.0 = (self.0 ^ pc).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+/// Map keyed by guest address.
+pub type PcMap<V> = HashMap<u64, V, BuildHasherDefault<PcHasher>>;
+
+/// Set of guest addresses or page numbers.
+pub type PcSet = HashSet<u64, BuildHasherDefault<PcHasher>>;
 
 /// Block cache configuration.
 pub const BLOCK_CACHE_SIZE: usize = 4096;
 
+/// Granularity of self-modifying code tracking.
+pub const CODE_PAGE_SHIFT: u32 = 12;
+
 /// Block cache using PC as key.
 pub struct BlockCache {
     /// PC → Block mapping.
-    blocks: HashMap<u64, Box<Block>>,
+    blocks: PcMap<Box<Block>>,
+    /// Physical page numbers that compiled blocks were read from.
+    ///
+    /// Conservative: evicted blocks leave their pages behind until the page
+    /// is written or the cache is flushed.
+    code_pages: PcSet,
     /// Current generation (incremented on flush).
     pub generation: u32,
     /// Statistics: cache hits.
@@ -23,17 +73,33 @@ pub struct BlockCache {
     pub misses: u64,
     /// Statistics: invalidations.
     pub invalidations: u64,
+    /// Statistics: blocks compiled ahead of execution.
+    pub prefetched: u64,
+    /// Statistics: prefetched blocks that were later run.
+    pub prefetch_hits: u64,
+    /// Compile the static successors of newly compiled blocks ahead of time.
+    pub prefetch: bool,
+    /// A block is out for execution.
+    taken: bool,
+    /// Ranges invalidated while a block was out.
+    dirty: Vec<(u64, u64)>,
 }
 
 impl BlockCache {
     /// Create a new empty block cache.
     pub fn new() -> Self {
         Self {
-            blocks: HashMap::with_capacity(BLOCK_CACHE_SIZE),
+            blocks: PcMap::with_capacity_and_hasher(BLOCK_CACHE_SIZE, Default::default()),
+            code_pages: PcSet::default(),
             generation: 0,
             hits: 0,
             misses: 0,
             invalidations: 0,
+            prefetched: 0,
+            prefetch_hits: 0,
+            prefetch: true,
+            taken: false,
+            dirty: Vec::new(),
         }
     }
 
@@ -41,9 +107,13 @@ impl BlockCache {
     /// Returns a reference to the block if found and valid.
     #[inline]
     pub fn get(&mut self, pc: u64) -> Option<&Block> {
-        if let Some(block) = self.blocks.get(&pc) {
+        if let Some(block) = self.blocks.get_mut(&pc) {
             if block.generation == self.generation {
                 self.hits += 1;
+                if block.prefetched {
+                    block.prefetched = false;
+                    self.prefetch_hits += 1;
+                }
                 return Some(block);
             }
         }
@@ -51,6 +121,67 @@ impl BlockCache {
         None
     }
 
+    /// Remove the valid block at `pc` for execution, counting a hit or miss
+    /// like [`get`](Self::get).
+    ///
+    /// The block must be handed back with [`put_back`](Self::put_back).
+    #[inline]
+    pub fn take(&mut self, pc: u64) -> Option<Box<Block>> {
+        match self.blocks.remove(&pc) {
+            Some(mut block) if block.generation == self.generation => {
+                self.hits += 1;
+                if block.prefetched {
+                    block.prefetched = false;
+                    self.prefetch_hits += 1;
+                }
+                self.taken = true;
+                Some(block)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Register a freshly compiled block and hand it out for execution as
+    /// if by [`take`](Self::take).
+    pub fn insert_taken(&mut self, block: Block) -> Box<Block> {
+        self.track_code(&block);
+        self.taken = true;
+        Box::new(block)
+    }
+
+    /// Return a block taken with [`take`](Self::take) or
+    /// [`insert_taken`](Self::insert_taken).
+    ///
+    /// Blocks invalidated while they were out are dropped.
+    #[inline]
+    pub fn put_back(&mut self, block: Box<Block>) {
+        self.taken = false;
+        let block_end = block.start_pa + block.byte_len as u64;
+        let dirty = self
+            .dirty
+            .iter()
+            .any(|&(start, end)| block.start_pa < end && block_end > start);
+        self.dirty.clear();
+        if block.generation == self.generation && !dirty {
+            if self.blocks.len() >= BLOCK_CACHE_SIZE {
+                self.evict_cold();
+            }
+            self.blocks.insert(block.start_pc, block);
+        }
+    }
+
+    /// Look up a valid block without touching the hit/miss statistics.
+    #[inline]
+    pub fn peek(&self, pc: u64) -> Option<&Block> {
+        self.blocks
+            .get(&pc)
+            .filter(|block| block.generation == self.generation)
+            .map(|block| &**block)
+    }
+
     /// Insert a compiled block into the cache.
     pub fn insert(&mut self, block: Block) {
         // Evict if cache is full
@@ -58,17 +189,57 @@ impl BlockCache {
             self.evict_cold();
         }
 
-        let pc = block.start_pc;
-        self.blocks.insert(pc, Box::new(block));
+        self.track_code(&block);
+        self.blocks.insert(block.start_pc, Box::new(block));
+    }
+
+    /// Record the physical pages `block` was read from.
+    fn track_code(&mut self, block: &Block) {
+        // The last instruction may straddle into the next page
+        let first = block.start_pa >> CODE_PAGE_SHIFT;
+        let last = (block.start_pa + block.byte_len.max(1) as u64 - 1) >> CODE_PAGE_SHIFT;
+        self.code_pages.extend(first..=last);
     }
 
-    /// Invalidate all blocks (called on SATP change, SFENCE.VMA).
+    /// Insert a block compiled before control reached it.
+    pub fn insert_prefetched(&mut self, mut block: Block) {
+        block.prefetched = true;
+        self.prefetched += 1;
+        self.insert(block);
+    }
+
+    /// Invalidate all blocks (called on SATP change, SFENCE.VMA, FENCE.I).
     pub fn flush(&mut self) {
         self.generation = self.generation.wrapping_add(1);
         self.invalidations += 1;
+        self.code_pages.clear();
         // Don't clear the map; stale entries will be rejected by generation check
     }
 
+    /// Whether a write to `[pa, pa + len)` may modify compiled code.
+    #[inline]
+    pub fn is_code(&self, pa: u64, len: u64) -> bool {
+        !self.code_pages.is_empty()
+            && (self.code_pages.contains(&(pa >> CODE_PAGE_SHIFT))
+                || self
+                    .code_pages
+                    .contains(&((pa + len - 1) >> CODE_PAGE_SHIFT)))
+    }
+
+    /// Drop every block overlapping the code pages touched by `[pa, pa + len)`.
+    ///
+    /// Returns the invalidated physical range so other tiers can follow.
+    pub fn invalidate_code(&mut self, pa: u64, len: u64) -> (u64, u64) {
+        let first = pa >> CODE_PAGE_SHIFT;
+        let last = (pa + len - 1) >> CODE_PAGE_SHIFT;
+        for page in first..=last {
+            self.code_pages.remove(&page);
+        }
+        let range = (first << CODE_PAGE_SHIFT, (last + 1) << CODE_PAGE_SHIFT);
+        self.invalidate_range(range.0, range.1);
+        range
+    }
+
     /// Invalidate blocks in a specific physical address range.
     /// Called when code is modified.
     pub fn invalidate_range(&mut self, start_pa: u64, end_pa: u64) {
@@ -76,6 +247,9 @@ impl BlockCache {
             let block_end = block.start_pa + block.byte_len as u64;
             !(block.start_pa < end_pa && block_end > start_pa)
         });
+        if self.taken {
+            self.dirty.push((start_pa, end_pa));
+        }
         self.invalidations += 1;
     }
 
@@ -115,10 +289,14 @@ impl BlockCache {
     /// Clear the entire cache.
     pub fn clear(&mut self) {
         self.blocks.clear();
+        self.dirty.clear();
+        self.code_pages.clear();
         self.generation = 0;
         self.hits = 0;
         self.misses = 0;
         self.invalidations = 0;
+        self.prefetched = 0;
+        self.prefetch_hits = 0;
     }
 
     /// Get cache statistics as a tuple: (hits, misses, size, hit_rate).
@@ -131,6 +309,16 @@ impl BlockCache {
         };
         (self.hits, self.misses, self.blocks.len(), hit_rate)
     }
+
+    /// Get prefetch statistics as a tuple: (prefetched, hits, hit_rate).
+    pub fn prefetch_stats(&self) -> (u64, u64, f64) {
+        let hit_rate = if self.prefetched > 0 {
+            self.prefetch_hits as f64 / self.prefetched as f64
+        } else {
+            0.0
+        };
+        (self.prefetched, self.prefetch_hits, hit_rate)
+    }
 }
 
 impl Default for BlockCache {
@@ -206,6 +394,31 @@ mod tests {
         assert!(cache.get(0x8000_0000).is_none());
     }
 
+    #[test]
+    fn test_take_and_put_back() {
+        let mut cache = BlockCache::new();
+        cache.insert(make_test_block(0x8000_0000, cache.generation));
+
+        let block = cache.take(0x8000_0000).unwrap();
+        assert!(cache.peek(0x8000_0000).is_none());
+        assert_eq!(cache.hits, 1);
+        cache.put_back(block);
+        assert!(cache.peek(0x8000_0000).is_some());
+
+        // A store to the block's page while it runs drops it
+        let block = cache.take(0x8000_0000).unwrap();
+        cache.invalidate_code(0x8000_0000, 4);
+        cache.put_back(block);
+        assert!(cache.peek(0x8000_0000).is_none());
+
+        // So does a flush
+        let block = cache.insert_taken(make_test_block(0x8000_1000, cache.generation));
+        assert!(cache.is_code(0x8000_1000, 4));
+        cache.flush();
+        cache.put_back(block);
+        assert!(cache.peek(0x8000_1000).is_none());
+    }
+
     #[test]
     fn test_cache_stats() {
         let mut cache = BlockCache::new();
@@ -224,4 +437,42 @@ mod tests {
         assert_eq!(size, 1);
         assert!((hit_rate - 0.333).abs() < 0.01);
     }
+
+    #[test]
+    fn test_prefetch_stats() {
+        let mut cache = BlockCache::new();
+        cache.insert_prefetched(make_test_block(0x8000_0000, cache.generation));
+        cache.insert_prefetched(make_test_block(0x8000_0010, cache.generation));
+
+        // Only the first run of a prefetched block counts
+        cache.get(0x8000_0000);
+        cache.get(0x8000_0000);
+        let (prefetched, hits, hit_rate) = cache.prefetch_stats();
+        assert_eq!((prefetched, hits), (2, 1));
+        assert!((hit_rate - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_code_page_tracking() {
+        let mut cache = BlockCache::new();
+        cache.insert(make_test_block(0x8000_0ffe, cache.generation));
+        cache.insert(make_test_block(0x8000_3000, cache.generation));
+
+        // The first block spans two pages
+        assert!(cache.is_code(0x8000_0800, 8));
+        assert!(cache.is_code(0x8000_1000, 1));
+        assert!(!cache.is_code(0x8000_2000, 8));
+        assert!(cache.is_code(0x8000_2ffc, 8));
+
+        assert_eq!(
+            cache.invalidate_code(0x8000_1004, 4),
+            (0x8000_1000, 0x8000_2000)
+        );
+        assert!(cache.peek(0x8000_0ffe).is_none());
+        assert!(!cache.is_code(0x8000_1000, 1));
+        assert!(cache.peek(0x8000_3000).is_some());
+
+        cache.flush();
+        assert!(!cache.is_code(0x8000_3000, 4));
+    }
 }
@@ -1,3 +1,4 @@
+use super::bitmanip::{self, BitOp};
 use crate::Trap;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -130,7 +131,51 @@ pub enum Op {
         aq: bool,
         rl: bool,
     }, // RV64A atomics (LR/SC/AMO*)
-    Fence, // FENCE / FENCE.I
+    LoadFp {
+        rd: Register,
+        rs1: Register,
+        imm: i64,
+        funct3: u32,
+    }, // FLW / FLD
+    StoreFp {
+        rs1: Register,
+        rs2: Register,
+        imm: i64,
+        funct3: u32,
+    }, // FSW / FSD
+    OpFp {
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+        rm: u32,
+        funct7: u32,
+    }, // F/D arithmetic, compares, conversions and moves
+    FusedMulAdd {
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+        rs3: Register,
+        rm: u32,
+        fmt: u32,
+        opcode: u32,
+    }, // FMADD / FMSUB / FNMSUB / FNMADD
+    Bit {
+        op: BitOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    }, // Zba/Zbb/Zbs register and unary forms
+    BitImm {
+        op: BitOp,
+        rd: Register,
+        rs1: Register,
+        shamt: u32,
+    }, // Zba/Zbb/Zbs shift-amount forms (RORI, BSETI etc)
+    Vector {
+        insn: u32,
+    }, // V extension, decoded by cpu::vector when run
+    Fence,  // FENCE
+    FenceI, // FENCE.I
 }
 
 #[inline]
@@ -167,6 +212,13 @@ pub fn decode(insn: u32) -> Result<Op, Trap> {
         ((val as i32) << 11 >> 11) as i64
     };
 
+    // Bitmanip shares the integer ALU opcodes
+    if matches!(opcode, 0x13 | 0x1B | 0x33 | 0x3B)
+        && let Some(op) = bitmanip::decode(insn)
+    {
+        return Ok(op);
+    }
+
     match opcode {
         0x37 => Ok(Op::Lui { rd, imm: imm_u }),
         0x17 => Ok(Op::Auipc { rd, imm: imm_u }),
@@ -246,7 +298,39 @@ pub fn decode(insn: u32) -> Result<Op, Trap> {
                 imm: i_imm,
             })
         }
+        0x0F if funct3 == 1 => Ok(Op::FenceI),
         0x0F => Ok(Op::Fence),
+        // Vector loads/stores share LOAD-FP/STORE-FP, told apart by width
+        0x07 | 0x27 if matches!(funct3, 0 | 5 | 6 | 7) => Ok(Op::Vector { insn }),
+        0x57 => Ok(Op::Vector { insn }),
+        0x07 => Ok(Op::LoadFp {
+            rd,
+            rs1,
+            imm: imm_i,
+            funct3,
+        }),
+        0x27 => Ok(Op::StoreFp {
+            rs1,
+            rs2,
+            imm: imm_s,
+            funct3,
+        }),
+        0x53 => Ok(Op::OpFp {
+            rd,
+            rs1,
+            rs2,
+            rm: funct3,
+            funct7,
+        }),
+        0x43 | 0x47 | 0x4B | 0x4F => Ok(Op::FusedMulAdd {
+            rd,
+            rs1,
+            rs2,
+            rs3: Register::from_u32(insn >> 27),
+            rm: funct3,
+            fmt: funct7 & 0x3,
+            opcode,
+        }),
 
         _ => Err(Trap::IllegalInstruction(insn as u64)),
     }
@@ -346,6 +430,13 @@ fn expand_q0(insn: u16, funct3: u16) -> Result<u32, Trap> {
             let rd_prime = 8 + ((insn_u >> 2) & 0x7);
             Ok(encode_i(nzuimm as i32, 2, 0x0, rd_prime, 0x13))
         }
+        // C.FLD -> FLD rd', uimm(rs1')
+        0b001 => {
+            let uimm = (((insn_u >> 10) & 0x7) << 3) | (((insn_u >> 5) & 0x3) << 6);
+            let rd_prime = 8 + ((insn_u >> 2) & 0x7);
+            let rs1_prime = 8 + ((insn_u >> 7) & 0x7);
+            Ok(encode_i(uimm as i32, rs1_prime, 0x3, rd_prime, 0x07))
+        }
         // C.LW -> LW rd', uimm(rs1')
         0b010 => {
             let uimm = (((insn_u >> 6) & 0x1) << 2)
@@ -371,6 +462,13 @@ fn expand_q0(insn: u16, funct3: u16) -> Result<u32, Trap> {
             let rs1_prime = 8 + ((insn_u >> 7) & 0x7);
             Ok(encode_s(uimm as i32, rs2_prime, rs1_prime, 0x2, 0x23))
         }
+        // C.FSD -> FSD rs2', uimm(rs1')
+        0b101 => {
+            let uimm = (((insn_u >> 10) & 0x7) << 3) | (((insn_u >> 5) & 0x3) << 6);
+            let rs2_prime = 8 + ((insn_u >> 2) & 0x7);
+            let rs1_prime = 8 + ((insn_u >> 7) & 0x7);
+            Ok(encode_s(uimm as i32, rs2_prime, rs1_prime, 0x3, 0x27))
+        }
         // C.SD -> SD rs2', uimm(rs1')
         0b111 => {
             let uimm = (((insn_u >> 10) & 0x7) << 3) | (((insn_u >> 5) & 0x3) << 6);
@@ -463,9 +561,9 @@ fn expand_q1(insn: u16, funct3: u16) -> Result<u32, Trap> {
                 0b01 => {
                     let shamt_bits = ((insn_u >> 2) & 0x1F) | (((insn_u >> 12) & 0x1) << 5);
                     let shamt = shamt_bits & 0x3F;
-                    // SRAI encoding: funct7=0b0100000, funct3=101
+                    // SRAI encoding: funct6=0b010000, funct3=101
                     Ok(encode_i(
-                        (0x20 << 6) | (shamt as i32),
+                        (0x10 << 6) | (shamt as i32),
                         rs1_prime,
                         0x5,
                         rs1_prime,
@@ -579,6 +677,14 @@ fn expand_q2(insn: u16, funct3: u16) -> Result<u32, Trap> {
             }
             Ok(encode_i(imm as i32, rd, 0x1, rd, 0x13))
         }
+        // C.FLDSP: FLD rd, uimm(sp) - same immediate layout as C.LDSP, f0 allowed
+        0b001 => {
+            let rd = (insn_u >> 7) & 0x1F;
+            let uimm = (((insn_u >> 12) & 0x1) << 5)
+                | (((insn_u >> 5) & 0x3) << 3)
+                | (((insn_u >> 2) & 0x7) << 6);
+            Ok(encode_i(uimm as i32, 2, 0x3, rd, 0x07))
+        }
         // C.LWSP
         0b010 => {
             let rd = (insn_u >> 7) & 0x1F;
@@ -629,6 +735,12 @@ fn expand_q2(insn: u16, funct3: u16) -> Result<u32, Trap> {
                 _ => Err(Trap::IllegalInstruction(insn as u64)),
             }
         }
+        // C.FSDSP: FSD rs2, uimm(sp) - same immediate layout as C.SDSP
+        0b101 => {
+            let rs2 = (insn_u >> 2) & 0x1F;
+            let uimm = (((insn_u >> 10) & 0x7) << 3) | (((insn_u >> 7) & 0x7) << 6);
+            Ok(encode_s(uimm as i32, rs2, 2, 0x3, 0x27))
+        }
         // C.SWSP: SW rs2, uimm(sp) - uimm[5:2|7:6] scaled by 4
         0b110 => {
             let rs2 = (insn_u >> 2) & 0x1F;
@@ -687,6 +799,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn decode_fence_and_fence_i() {
+        // fence iorw, iorw
+        assert!(matches!(decode(0x0ff0_000f).unwrap(), Op::Fence));
+        // fence.i
+        assert!(matches!(decode(0x0000_100f).unwrap(), Op::FenceI));
+    }
+
     #[test]
     fn expand_compressed_basic_integer_ops() {
         // These 16-bit encodings come from assembling with rv64imac:
@@ -775,4 +895,84 @@ mod tests {
             _ => panic!("Expected Lui from C.LUI"),
         }
     }
+
+    #[test]
+    fn expand_compressed_shifts() {
+        // srli a3, a3, 63 / srai a2, a2, 63 / slli a2, a2, 48
+        assert_eq!(expand_compressed(0x92fd).unwrap(), 0x03f6_d693);
+        assert_eq!(expand_compressed(0x967d).unwrap(), 0x43f6_5613);
+        assert_eq!(expand_compressed(0x1642).unwrap(), 0x0306_1613);
+        match decode(expand_compressed(0x967d).unwrap()).unwrap() {
+            Op::OpImm {
+                rd, funct3, funct7, ..
+            } => {
+                assert_eq!(rd, Register::X12);
+                assert_eq!(funct3, 5);
+                assert_eq!(funct7 & 0x20, 0x20);
+            }
+            _ => panic!("Expected OpImm from C.SRAI"),
+        }
+    }
+
+    #[test]
+    fn decode_fp_loads_stores_and_arith() {
+        // fld f1, 8(x2)
+        match decode(0x0081_3087).unwrap() {
+            Op::LoadFp {
+                rd,
+                rs1,
+                imm,
+                funct3,
+            } => {
+                assert_eq!((rd, rs1, imm, funct3), (Register::X1, Register::X2, 8, 3));
+            }
+            op => panic!("Expected LoadFp, got {:?}", op),
+        }
+
+        // fsw f3, -4(x10)
+        match decode(0xFE35_2E27).unwrap() {
+            Op::StoreFp {
+                rs1,
+                rs2,
+                imm,
+                funct3,
+            } => {
+                assert_eq!(
+                    (rs1, rs2, imm, funct3),
+                    (Register::X10, Register::X3, -4, 2)
+                );
+            }
+            op => panic!("Expected StoreFp, got {:?}", op),
+        }
+
+        // fadd.d f1, f2, f3, dyn
+        match decode(0x0231_70D3).unwrap() {
+            Op::OpFp { rd, rm, funct7, .. } => {
+                assert_eq!((rd, rm, funct7), (Register::X1, 7, 0x01));
+            }
+            op => panic!("Expected OpFp, got {:?}", op),
+        }
+
+        // fmadd.s f1, f2, f3, f4, rne
+        match decode(0x2031_00C3).unwrap() {
+            Op::FusedMulAdd {
+                rs3, fmt, opcode, ..
+            } => {
+                assert_eq!((rs3, fmt, opcode), (Register::X4, 0, 0x43));
+            }
+            op => panic!("Expected FusedMulAdd, got {:?}", op),
+        }
+    }
+
+    #[test]
+    fn expand_compressed_fp_loads_stores() {
+        // c.fld f8, 8(x9)
+        assert_eq!(expand_compressed(0x2480).unwrap(), 0x0084_B407);
+        // c.fsd f8, 8(x9)
+        assert_eq!(expand_compressed(0xA480).unwrap(), 0x0084_B427);
+        // c.fldsp f0, 16(sp)
+        assert_eq!(expand_compressed(0x2042).unwrap(), 0x0101_3007);
+        // c.fsdsp f1, 16(sp)
+        assert_eq!(expand_compressed(0xA806).unwrap(), 0x0011_3827);
+    }
 }
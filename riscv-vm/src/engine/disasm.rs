@@ -0,0 +1,508 @@
+//! Disassembler for execution traces.
+//!
+//! Formats 32-bit encodings (compressed instructions are shown in their
+//! expanded form) in the usual assembler syntax with ABI register names,
+//! using the common pseudo-instructions (`li`, `mv`, `j`, `ret`, ...).
+//! Branch and jump targets are printed as absolute addresses.
+
+use super::decoder::{Op, Register, decode};
+use crate::cpu::vector;
+
+/// ABI names of the integer registers.
+const XREG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// ABI names of the floating-point registers.
+const FREG_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// ABI name of integer register `index`.
+pub fn xreg_name(index: usize) -> &'static str {
+    XREG_NAMES[index & 31]
+}
+
+/// ABI name of floating-point register `index`.
+pub fn freg_name(index: usize) -> &'static str {
+    FREG_NAMES[index & 31]
+}
+
+fn x(reg: Register) -> &'static str {
+    xreg_name(reg.to_usize())
+}
+
+fn f(reg: Register) -> &'static str {
+    freg_name(reg.to_usize())
+}
+
+/// Name of a CSR, or its number in hex.
+fn csr_name(csr: u32) -> String {
+    let name = match csr {
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0x008 => "vstart",
+        0x009 => "vxsat",
+        0x00a => "vxrm",
+        0x00f => "vcsr",
+        0x100 => "sstatus",
+        0x104 => "sie",
+        0x105 => "stvec",
+        0x106 => "scounteren",
+        0x140 => "sscratch",
+        0x141 => "sepc",
+        0x142 => "scause",
+        0x143 => "stval",
+        0x144 => "sip",
+        0x14d => "stimecmp",
+        0x180 => "satp",
+        0x300 => "mstatus",
+        0x301 => "misa",
+        0x302 => "medeleg",
+        0x303 => "mideleg",
+        0x304 => "mie",
+        0x305 => "mtvec",
+        0x306 => "mcounteren",
+        0x30a => "menvcfg",
+        0x340 => "mscratch",
+        0x341 => "mepc",
+        0x342 => "mcause",
+        0x343 => "mtval",
+        0x344 => "mip",
+        0xc00 => "cycle",
+        0xc01 => "time",
+        0xc02 => "instret",
+        0xc20 => "vl",
+        0xc21 => "vtype",
+        0xc22 => "vlenb",
+        0xf11 => "mvendorid",
+        0xf12 => "marchid",
+        0xf13 => "mimpid",
+        0xf14 => "mhartid",
+        _ => return format!("{:#x}", csr),
+    };
+    name.to_string()
+}
+
+/// Disassemble the instruction `insn` located at `pc`.
+///
+/// Encodings that don't decode are shown as `.word 0x...`.
+pub fn disassemble(pc: u64, insn: u32) -> String {
+    let unknown = || format!(".word {:#010x}", insn);
+    let target = |imm: i64| format!("{:#x}", pc.wrapping_add(imm as u64));
+    let Ok(op) = decode(insn) else {
+        return unknown();
+    };
+
+    match op {
+        Op::Lui { rd, imm } => format!("lui {}, {:#x}", x(rd), (imm >> 12) & 0xfffff),
+        Op::Auipc { rd, imm } => format!("auipc {}, {:#x}", x(rd), (imm >> 12) & 0xfffff),
+        Op::Jal { rd, imm } => match rd {
+            Register::X0 => format!("j {}", target(imm)),
+            Register::X1 => format!("jal {}", target(imm)),
+            _ => format!("jal {}, {}", x(rd), target(imm)),
+        },
+        Op::Jalr { rd, rs1, imm } => match (rd, rs1, imm) {
+            (Register::X0, Register::X1, 0) => "ret".to_string(),
+            (Register::X0, _, 0) => format!("jr {}", x(rs1)),
+            (Register::X1, _, 0) => format!("jalr {}", x(rs1)),
+            _ => format!("jalr {}, {}({})", x(rd), imm, x(rs1)),
+        },
+        Op::Branch {
+            rs1,
+            rs2,
+            imm,
+            funct3,
+        } => {
+            let mnemonic = match funct3 {
+                0 => "beq",
+                1 => "bne",
+                4 => "blt",
+                5 => "bge",
+                6 => "bltu",
+                7 => "bgeu",
+                _ => return unknown(),
+            };
+            if rs2 == Register::X0 && matches!(funct3, 0 | 1) {
+                format!("{}z {}, {}", mnemonic, x(rs1), target(imm))
+            } else {
+                format!("{} {}, {}, {}", mnemonic, x(rs1), x(rs2), target(imm))
+            }
+        }
+        Op::Load {
+            rd,
+            rs1,
+            imm,
+            funct3,
+        } => {
+            let mnemonic = match funct3 {
+                0 => "lb",
+                1 => "lh",
+                2 => "lw",
+                3 => "ld",
+                4 => "lbu",
+                5 => "lhu",
+                6 => "lwu",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}({})", mnemonic, x(rd), imm, x(rs1))
+        }
+        Op::Store {
+            rs1,
+            rs2,
+            imm,
+            funct3,
+        } => {
+            let mnemonic = match funct3 {
+                0 => "sb",
+                1 => "sh",
+                2 => "sw",
+                3 => "sd",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}({})", mnemonic, x(rs2), imm, x(rs1))
+        }
+        Op::OpImm {
+            rd,
+            rs1,
+            imm,
+            funct3,
+            funct7,
+        } => {
+            let shamt = imm & 0x3f;
+            match funct3 {
+                0 if rd == Register::X0 && rs1 == Register::X0 && imm == 0 => "nop".to_string(),
+                0 if rs1 == Register::X0 => format!("li {}, {}", x(rd), imm),
+                0 if imm == 0 => format!("mv {}, {}", x(rd), x(rs1)),
+                0 => format!("addi {}, {}, {}", x(rd), x(rs1), imm),
+                1 => format!("slli {}, {}, {}", x(rd), x(rs1), shamt),
+                2 => format!("slti {}, {}, {}", x(rd), x(rs1), imm),
+                3 => format!("sltiu {}, {}, {}", x(rd), x(rs1), imm),
+                4 if imm == -1 => format!("not {}, {}", x(rd), x(rs1)),
+                4 => format!("xori {}, {}, {}", x(rd), x(rs1), imm),
+                // funct7 bit 5 (insn bit 30) selects the arithmetic shift
+                5 if funct7 & 0x20 != 0 => format!("srai {}, {}, {}", x(rd), x(rs1), shamt),
+                5 => format!("srli {}, {}, {}", x(rd), x(rs1), shamt),
+                6 => format!("ori {}, {}, {}", x(rd), x(rs1), imm),
+                _ => format!("andi {}, {}, {}", x(rd), x(rs1), imm),
+            }
+        }
+        Op::Op {
+            rd,
+            rs1,
+            rs2,
+            funct3,
+            funct7,
+        } => {
+            let mnemonic = match (funct7, funct3) {
+                (0x00, 0) => "add",
+                (0x20, 0) => "sub",
+                (0x00, 1) => "sll",
+                (0x00, 2) => "slt",
+                (0x00, 3) => "sltu",
+                (0x00, 4) => "xor",
+                (0x00, 5) => "srl",
+                (0x20, 5) => "sra",
+                (0x00, 6) => "or",
+                (0x00, 7) => "and",
+                (0x01, 0) => "mul",
+                (0x01, 1) => "mulh",
+                (0x01, 2) => "mulhsu",
+                (0x01, 3) => "mulhu",
+                (0x01, 4) => "div",
+                (0x01, 5) => "divu",
+                (0x01, 6) => "rem",
+                (0x01, 7) => "remu",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}, {}", mnemonic, x(rd), x(rs1), x(rs2))
+        }
+        Op::OpImm32 {
+            rd,
+            rs1,
+            imm,
+            funct3,
+            funct7,
+        } => {
+            let shamt = imm & 0x1f;
+            match funct3 {
+                0 if imm == 0 => format!("sext.w {}, {}", x(rd), x(rs1)),
+                0 => format!("addiw {}, {}, {}", x(rd), x(rs1), imm),
+                1 => format!("slliw {}, {}, {}", x(rd), x(rs1), shamt),
+                5 if funct7 & 0x20 != 0 => format!("sraiw {}, {}, {}", x(rd), x(rs1), shamt),
+                5 => format!("srliw {}, {}, {}", x(rd), x(rs1), shamt),
+                _ => unknown(),
+            }
+        }
+        Op::Op32 {
+            rd,
+            rs1,
+            rs2,
+            funct3,
+            funct7,
+        } => {
+            let mnemonic = match (funct7, funct3) {
+                (0x00, 0) => "addw",
+                (0x20, 0) => "subw",
+                (0x00, 1) => "sllw",
+                (0x00, 5) => "srlw",
+                (0x20, 5) => "sraw",
+                (0x01, 0) => "mulw",
+                (0x01, 4) => "divw",
+                (0x01, 5) => "divuw",
+                (0x01, 6) => "remw",
+                (0x01, 7) => "remuw",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}, {}", mnemonic, x(rd), x(rs1), x(rs2))
+        }
+        Op::System {
+            rd,
+            rs1,
+            funct3,
+            imm,
+        } => match funct3 {
+            0 => match imm {
+                0x000 => "ecall".to_string(),
+                0x001 => "ebreak".to_string(),
+                0x102 => "sret".to_string(),
+                0x302 => "mret".to_string(),
+                0x105 => "wfi".to_string(),
+                _ if imm >> 5 == 0x09 => {
+                    let rs2 = Register::from_u32(imm & 0x1f);
+                    format!("sfence.vma {}, {}", x(rs1), x(rs2))
+                }
+                _ => unknown(),
+            },
+            4 => unknown(),
+            _ => {
+                let mnemonic = [
+                    "", "csrrw", "csrrs", "csrrc", "", "csrrwi", "csrrsi", "csrrci",
+                ][funct3 as usize];
+                let csr = csr_name(imm);
+                let source = if funct3 >= 5 {
+                    rs1.to_usize().to_string()
+                } else {
+                    x(rs1).to_string()
+                };
+                match (funct3, rd, rs1) {
+                    (2, _, Register::X0) => format!("csrr {}, {}", x(rd), csr),
+                    (1, Register::X0, _) => format!("csrw {}, {}", csr, source),
+                    _ => format!("{} {}, {}, {}", mnemonic, x(rd), csr, source),
+                }
+            }
+        },
+        Op::Amo {
+            rd,
+            rs1,
+            rs2,
+            funct3,
+            funct5,
+            aq,
+            rl,
+        } => {
+            let width = match funct3 {
+                2 => "w",
+                3 => "d",
+                _ => return unknown(),
+            };
+            let name = match funct5 {
+                0x02 => "lr",
+                0x03 => "sc",
+                0x01 => "amoswap",
+                0x00 => "amoadd",
+                0x04 => "amoxor",
+                0x0c => "amoand",
+                0x08 => "amoor",
+                0x10 => "amomin",
+                0x14 => "amomax",
+                0x18 => "amominu",
+                0x1c => "amomaxu",
+                _ => return unknown(),
+            };
+            let ordering = match (aq, rl) {
+                (true, true) => ".aqrl",
+                (true, false) => ".aq",
+                (false, true) => ".rl",
+                (false, false) => "",
+            };
+            if funct5 == 0x02 {
+                format!("{}.{}{} {}, ({})", name, width, ordering, x(rd), x(rs1))
+            } else {
+                format!(
+                    "{}.{}{} {}, {}, ({})",
+                    name,
+                    width,
+                    ordering,
+                    x(rd),
+                    x(rs2),
+                    x(rs1)
+                )
+            }
+        }
+        Op::LoadFp {
+            rd,
+            rs1,
+            imm,
+            funct3,
+        } => match funct3 {
+            2 => format!("flw {}, {}({})", f(rd), imm, x(rs1)),
+            3 => format!("fld {}, {}({})", f(rd), imm, x(rs1)),
+            _ => unknown(),
+        },
+        Op::StoreFp {
+            rs1,
+            rs2,
+            imm,
+            funct3,
+        } => match funct3 {
+            2 => format!("fsw {}, {}({})", f(rs2), imm, x(rs1)),
+            3 => format!("fsd {}, {}({})", f(rs2), imm, x(rs1)),
+            _ => unknown(),
+        },
+        Op::OpFp {
+            rd,
+            rs1,
+            rs2,
+            rm,
+            funct7,
+        } => {
+            let fmt = match funct7 & 3 {
+                0 => "s",
+                1 => "d",
+                _ => return unknown(),
+            };
+            let int_fmt = ["w", "wu", "l", "lu"];
+            match funct7 >> 2 {
+                0x00 => format!("fadd.{} {}, {}, {}", fmt, f(rd), f(rs1), f(rs2)),
+                0x01 => format!("fsub.{} {}, {}, {}", fmt, f(rd), f(rs1), f(rs2)),
+                0x02 => format!("fmul.{} {}, {}, {}", fmt, f(rd), f(rs1), f(rs2)),
+                0x03 => format!("fdiv.{} {}, {}, {}", fmt, f(rd), f(rs1), f(rs2)),
+                0x0b => format!("fsqrt.{} {}, {}", fmt, f(rd), f(rs1)),
+                0x04 if rm == 0 && rs1 == rs2 => format!("fmv.{} {}, {}", fmt, f(rd), f(rs1)),
+                0x04 if rm < 3 => {
+                    let name = ["fsgnj", "fsgnjn", "fsgnjx"][rm as usize];
+                    format!("{}.{} {}, {}, {}", name, fmt, f(rd), f(rs1), f(rs2))
+                }
+                0x05 if rm < 2 => {
+                    let name = ["fmin", "fmax"][rm as usize];
+                    format!("{}.{} {}, {}, {}", name, fmt, f(rd), f(rs1), f(rs2))
+                }
+                0x14 if rm < 3 => {
+                    let name = ["fle", "flt", "feq"][rm as usize];
+                    format!("{}.{} {}, {}, {}", name, fmt, x(rd), f(rs1), f(rs2))
+                }
+                // fcvt.s.d / fcvt.d.s
+                0x08 => {
+                    let from = if fmt == "s" { "d" } else { "s" };
+                    format!("fcvt.{}.{} {}, {}", fmt, from, f(rd), f(rs1))
+                }
+                0x18 if rs2.to_usize() < 4 => format!(
+                    "fcvt.{}.{} {}, {}",
+                    int_fmt[rs2.to_usize()],
+                    fmt,
+                    x(rd),
+                    f(rs1)
+                ),
+                0x1a if rs2.to_usize() < 4 => format!(
+                    "fcvt.{}.{} {}, {}",
+                    fmt,
+                    int_fmt[rs2.to_usize()],
+                    f(rd),
+                    x(rs1)
+                ),
+                0x1c if rm == 0 => {
+                    let width = if fmt == "s" { "w" } else { "d" };
+                    format!("fmv.x.{} {}, {}", width, x(rd), f(rs1))
+                }
+                0x1c if rm == 1 => format!("fclass.{} {}, {}", fmt, x(rd), f(rs1)),
+                0x1e => {
+                    let width = if fmt == "s" { "w" } else { "d" };
+                    format!("fmv.{}.x {}, {}", width, f(rd), x(rs1))
+                }
+                _ => unknown(),
+            }
+        }
+        Op::FusedMulAdd {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            fmt,
+            opcode,
+            ..
+        } => {
+            let name = match opcode {
+                0x43 => "fmadd",
+                0x47 => "fmsub",
+                0x4b => "fnmsub",
+                _ => "fnmadd",
+            };
+            let fmt = match fmt {
+                0 => "s",
+                1 => "d",
+                _ => return unknown(),
+            };
+            format!(
+                "{}.{} {}, {}, {}, {}",
+                name,
+                fmt,
+                f(rd),
+                f(rs1),
+                f(rs2),
+                f(rs3)
+            )
+        }
+        Op::Vector { insn } => vector::disassemble(insn).unwrap_or_else(unknown),
+        Op::Bit { op, rd, rs1, .. } if op.is_unary() => {
+            format!("{} {}, {}", op.mnemonic(), x(rd), x(rs1))
+        }
+        Op::Bit { op, rd, rs1, rs2 } => {
+            format!("{} {}, {}, {}", op.mnemonic(), x(rd), x(rs1), x(rs2))
+        }
+        Op::BitImm { op, rd, rs1, shamt } => {
+            format!("{} {}, {}, {}", op.imm_mnemonic(), x(rd), x(rs1), shamt)
+        }
+        Op::Fence => "fence".to_string(),
+        Op::FenceI => "fence.i".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let pc = 0x8000_0000;
+        let cases = [
+            (0x0000_0297, "auipc t0, 0x0"),
+            (0x0010_8093, "addi ra, ra, 1"),
+            (0x0050_0513, "li a0, 5"),
+            (0x0000_0013, "nop"),
+            (0x0000_8067, "ret"),
+            (0x0080_006f, "j 0x80000008"),
+            (0xfe05_1ee3, "bnez a0, 0x7ffffffc"),
+            (0x0081_3503, "ld a0, 8(sp)"),
+            (0x00a1_3423, "sd a0, 8(sp)"),
+            (0x0231_00b3, "mul ra, sp, gp"),
+            (0x4030_d093, "srai ra, ra, 3"),
+            (0x3400_2573, "csrr a0, mscratch"),
+            (0x3052_9073, "csrw mtvec, t0"),
+            (0x0000_0073, "ecall"),
+            (0x3020_0073, "mret"),
+            (0x1005_a52f, "lr.w a0, (a1)"),
+            (0x1a31_70d3, "fdiv.d ft1, ft2, ft3"),
+            (0xe205_0553, "fmv.x.d a0, fa0"),
+            (0xffff_ffff, ".word 0xffffffff"),
+        ];
+        for (insn, text) in cases {
+            assert_eq!(disassemble(pc, insn), text, "{:#010x}", insn);
+        }
+    }
+}
@@ -0,0 +1,1881 @@
+//! Native JIT Backend (Tier 3) for the Superblock Engine.
+//!
+//! Blocks that keep being dispatched are translated from their MicroOps to
+//! host machine code with Cranelift. A compiled block is a function
+//! `fn(regs: *mut [u64; 32]) -> u64` that updates the integer registers and
+//! returns the next PC, so it can stand in for `execute_block_inner` on any
+//! block whose ops cannot trap or touch the bus: integer ALU (including the
+//! RV64 word ops and non-dividing M ops), LUI/AUIPC, FENCE, and the
+//! JAL/JALR/branch terminators. Blocks with other ops stay on the
+//! interpreter.
+//!
+//! [`JitConfig`] and [`JitCache`] are the front-end (hotness profiling,
+//! lookup, invalidation); [`NativeBackend`] owns the Cranelift module and
+//! the generated code. [`JitCache::diagnostics`] reports per-block profiles
+//! and fallback reasons for tuning the config.
+//!
+//! Blocks that become hot wait in a compile queue. Each window of
+//! [`COMPILE_WINDOW`] dispatches compiles at most
+//! [`JitConfig::compile_budget`] of them, hottest first (a queued block keeps
+//! gaining heat every time it is interpreted), so a burst of new code does not
+//! stall the hart in Cranelift. Requests for code that was invalidated or
+//! flushed before their turn are dropped, and a full queue turns away blocks
+//! colder than everything already in it.
+//!
+//! A [`JitProfile`] lists the blocks compiled in a session, identified by
+//! their PC and a hash of their code. Preloaded into the next session of the
+//! same kernel image (see [`JitCache::preload`]), it lets those blocks be
+//! compiled on their first run instead of after the hot threshold, which
+//! cuts most of the warm-up of a second boot. The machine code itself is not
+//! saved: regenerating it is cheap next to the interpreted warm-up, and it
+//! would tie the profile to one host and Cranelift version.
+//!
+//! With [`JitConfig::verify`] set, every native execution is repeated by the
+//! interpreter from the same registers (see [`JitCache::verify`]). A block
+//! whose results differ is narrowed down to the first diverging MicroOp by
+//! compiling and interpreting successively longer prefixes of it, reported
+//! as a [`Divergence`], and left to the interpreter from then on.
+//!
+//! Only available with the `jit-native` feature on non-WASM targets.
+
+use super::bitmanip::BitOp;
+use super::block::Block;
+use super::microop::MicroOp;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{AbiParam, InstBuilder, MemFlags, Value, types};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Signature of a compiled block: updates `regs` and returns the next PC.
+pub type BlockFn = unsafe extern "C" fn(regs: *mut u64) -> u64;
+
+/// Number of hottest blocks listed in [`JitDiagnostics::hot_blocks`].
+pub const DIAGNOSTICS_HOT_BLOCKS: usize = 16;
+
+/// Block dispatches between refreshes of an attached diagnostics handle.
+const PUBLISH_INTERVAL: u64 = 1 << 20;
+
+/// Block dispatches per compile budget window, see
+/// [`JitConfig::compile_budget`].
+pub const COMPILE_WINDOW: u64 = 1 << 12;
+
+/// Version identifier for JIT profile compatibility checks.
+pub const JIT_PROFILE_VERSION: &str = "1.0";
+
+/// Fallback reason for blocks the code generator itself failed on.
+const CODEGEN_FAILED: &str = "codegen";
+
+/// Fallback reason for blocks whose native code disagreed with the
+/// interpreter.
+const DIVERGED: &str = "diverged";
+
+/// Tuning knobs for the JIT front-end.
+#[derive(Debug, Clone, Copy)]
+pub struct JitConfig {
+    /// Interpreted executions of a block before it is compiled.
+    pub hot_threshold: u32,
+    /// Maximum number of compiled blocks before all code is discarded.
+    pub max_blocks: usize,
+    /// Blocks compiled per [`COMPILE_WINDOW`] dispatches; hot blocks beyond
+    /// that wait in the compile queue.
+    pub compile_budget: u32,
+    /// Hot blocks that may wait in the compile queue.
+    pub max_queued: usize,
+    /// Check every native block execution against the interpreter. Several
+    /// times slower than interpreting alone; meant for debugging the code
+    /// generator.
+    pub verify: bool,
+}
+
+impl Default for JitConfig {
+    fn default() -> Self {
+        Self {
+            hot_threshold: 64,
+            max_blocks: 4096,
+            compile_budget: 64,
+            max_queued: 256,
+            verify: false,
+        }
+    }
+}
+
+/// Where a native block execution and the interpreter disagreed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    Reg {
+        reg: u8,
+        native: u64,
+        interpreter: u64,
+    },
+    NextPc {
+        native: u64,
+        interpreter: u64,
+    },
+}
+
+/// A compiled block whose results differed from the interpreter's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Start PC of the block.
+    pub block_pc: u64,
+    /// First op whose effects differ, with its index in the block, if
+    /// compiling the block's prefixes isolated it.
+    pub op: Option<(usize, MicroOp)>,
+    /// Registers on entry to the block.
+    pub regs: [u64; 32],
+    /// Differences after the diverging op, or after the whole block if it
+    /// was not isolated.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block 0x{:x}", self.block_pc)?;
+        if let Some((index, op)) = &self.op {
+            write!(f, " op {} ({:?})", index, op)?;
+        }
+        write!(f, ":")?;
+        for mismatch in &self.mismatches {
+            match *mismatch {
+                Mismatch::Reg {
+                    reg,
+                    native,
+                    interpreter,
+                } => write!(
+                    f,
+                    " x{} = 0x{:x} native, 0x{:x} interpreted;",
+                    reg, native, interpreter
+                )?,
+                Mismatch::NextPc {
+                    native,
+                    interpreter,
+                } => write!(
+                    f,
+                    " next pc 0x{:x} native, 0x{:x} interpreted;",
+                    native, interpreter
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Differences between a native and an interpreted run.
+fn mismatches(native: (&[u64; 32], u64), interpreted: (&[u64; 32], u64)) -> Vec<Mismatch> {
+    let mut found: Vec<Mismatch> = (0..32u8)
+        .filter(|&reg| native.0[reg as usize] != interpreted.0[reg as usize])
+        .map(|reg| Mismatch::Reg {
+            reg,
+            native: native.0[reg as usize],
+            interpreter: interpreted.0[reg as usize],
+        })
+        .collect();
+    if native.1 != interpreted.1 {
+        found.push(Mismatch::NextPc {
+            native: native.1,
+            interpreter: interpreted.1,
+        });
+    }
+    found
+}
+
+/// A compiled block and the code it was compiled from.
+struct JitEntry {
+    func: BlockFn,
+    start_pa: u64,
+    byte_len: u16,
+    /// [`block_hash`] of the block.
+    hash: u64,
+    generation: u32,
+    /// Ops in the block.
+    ops: u8,
+    /// Native executions.
+    executions: u64,
+    compile_time: Duration,
+}
+
+/// A hot block waiting to be compiled.
+struct CompileRequest {
+    block: Block,
+    /// Interpreted executions, including those while queued.
+    hotness: u32,
+}
+
+impl CompileRequest {
+    fn overlaps(&self, start_pa: u64, end_pa: u64) -> bool {
+        let end = self.block.start_pa + self.block.byte_len as u64;
+        self.block.start_pa < end_pa && end > start_pa
+    }
+}
+
+/// Profile of one compiled block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotBlock {
+    pub pc: u64,
+    pub executions: u64,
+    pub ops: u8,
+    pub compile_time: Duration,
+}
+
+/// A compiled block, as recorded in a [`JitProfile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProfiledBlock {
+    pub pc: u64,
+    /// Hash of the block's physical address and decoded ops.
+    pub hash: u64,
+}
+
+/// Blocks compiled during a session, for compiling them on first use in a
+/// later session of the same kernel image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JitProfile {
+    pub version: String,
+    /// SHA-256 of the kernel image the blocks were compiled from.
+    pub image: [u8; 32],
+    pub blocks: Vec<ProfiledBlock>,
+}
+
+impl JitProfile {
+    pub fn new(image: [u8; 32], blocks: Vec<ProfiledBlock>) -> Self {
+        Self {
+            version: JIT_PROFILE_VERSION.to_string(),
+            image,
+            blocks,
+        }
+    }
+
+    /// Encode with bincode, the on-disk profile format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| format!("failed to encode JIT profile: {}", e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let version: String = bincode::deserialize(bytes)
+            .map_err(|e| format!("invalid JIT profile header: {}", e))?;
+        if version != JIT_PROFILE_VERSION {
+            return Err(format!("unsupported JIT profile version {}", version));
+        }
+        bincode::deserialize(bytes).map_err(|e| format!("invalid JIT profile: {}", e))
+    }
+}
+
+/// Snapshot of JIT activity, see [`JitCache::diagnostics`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JitDiagnostics {
+    /// Blocks compiled.
+    pub compiled: u64,
+    /// Blocks that stayed on the interpreter after becoming hot.
+    pub rejects: u64,
+    /// Times all compiled code was discarded.
+    pub flushes: u64,
+    /// Block dispatches that ran native code.
+    pub hits: u64,
+    /// Block dispatches that fell back to the interpreter.
+    pub misses: u64,
+    /// Ops executed by native code.
+    pub jit_ops: u64,
+    /// Block ops executed by the interpreter.
+    pub interpreted_ops: u64,
+    /// Total time spent in Cranelift.
+    pub compile_time: Duration,
+    /// Hot blocks that entered the compile queue.
+    pub queued: u64,
+    /// Queued blocks dropped because their code was invalidated or flushed
+    /// before they were compiled.
+    pub stale: u64,
+    /// Hot blocks turned away or evicted because the queue was full.
+    pub dropped: u64,
+    /// Dispatches that found blocks queued but the compile budget spent.
+    pub throttled: u64,
+    /// Blocks waiting in the compile queue.
+    pub queue_depth: u64,
+    /// Blocks compiled on their first run thanks to a preloaded profile.
+    pub preloaded: u64,
+    /// Native block executions checked against the interpreter.
+    pub verified: u64,
+    /// Checked executions that disagreed with the interpreter.
+    pub divergences: u64,
+    /// The first of them.
+    pub first_divergence: Option<Divergence>,
+    /// Hottest compiled blocks, most executed first.
+    pub hot_blocks: Vec<HotBlock>,
+    /// Rejected blocks by the MicroOp kind that prevented compilation,
+    /// most frequent first.
+    pub fallbacks: Vec<(String, u64)>,
+    /// Every compiled block, by PC; see [`JitProfile`].
+    pub profile: Vec<ProfiledBlock>,
+}
+
+impl JitDiagnostics {
+    /// Fraction of block dispatches that ran native code.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+
+    /// Fraction of block ops executed natively.
+    pub fn jit_ratio(&self) -> f64 {
+        let total = self.jit_ops + self.interpreted_ops;
+        if total == 0 {
+            0.0
+        } else {
+            self.jit_ops as f64 / total as f64
+        }
+    }
+
+    /// Combine diagnostics from several harts.
+    pub fn merge(&mut self, other: &JitDiagnostics) {
+        self.compiled += other.compiled;
+        self.rejects += other.rejects;
+        self.flushes += other.flushes;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.jit_ops += other.jit_ops;
+        self.interpreted_ops += other.interpreted_ops;
+        self.compile_time += other.compile_time;
+        self.queued += other.queued;
+        self.stale += other.stale;
+        self.dropped += other.dropped;
+        self.throttled += other.throttled;
+        self.queue_depth += other.queue_depth;
+        self.preloaded += other.preloaded;
+        self.verified += other.verified;
+        self.divergences += other.divergences;
+        if self.first_divergence.is_none() {
+            self.first_divergence = other.first_divergence.clone();
+        }
+
+        self.hot_blocks.extend(other.hot_blocks.iter().cloned());
+        self.hot_blocks.sort_by_key(|b| Reverse(b.executions));
+        self.hot_blocks.truncate(DIAGNOSTICS_HOT_BLOCKS);
+
+        for (kind, count) in &other.fallbacks {
+            match self.fallbacks.iter_mut().find(|(k, _)| k == kind) {
+                Some((_, total)) => *total += count,
+                None => self.fallbacks.push((kind.clone(), *count)),
+            }
+        }
+        self.fallbacks.sort_by_key(|f| Reverse(f.1));
+
+        self.profile.extend_from_slice(&other.profile);
+        self.profile.sort_unstable();
+        self.profile.dedup();
+    }
+}
+
+impl fmt::Display for JitDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks compiled in {:.1}ms, {} rejected, {} flushes; \
+             {:.1}% hits, {:.1}% of block ops native",
+            self.compiled,
+            self.compile_time.as_secs_f64() * 1000.0,
+            self.rejects,
+            self.flushes,
+            self.hit_rate() * 100.0,
+            self.jit_ratio() * 100.0
+        )?;
+        if self.stale + self.dropped + self.throttled + self.queue_depth > 0 {
+            write!(
+                f,
+                "; compile queue: {} waiting, {} stale, {} dropped, {} throttled",
+                self.queue_depth, self.stale, self.dropped, self.throttled
+            )?;
+        }
+        if self.preloaded > 0 {
+            write!(f, "; {} compiled from a saved profile", self.preloaded)?;
+        }
+        if self.verified > 0 {
+            write!(
+                f,
+                "; {} of {} verified runs diverged",
+                self.divergences, self.verified
+            )?;
+        }
+        if !self.fallbacks.is_empty() {
+            write!(f, "; fallbacks:")?;
+            for (kind, count) in self.fallbacks.iter().take(5) {
+                write!(f, " {}={}", kind, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared, periodically refreshed copy of a hart's diagnostics.
+pub type JitDiagnosticsHandle = Arc<Mutex<JitDiagnostics>>;
+
+/// Compiled blocks, keyed by start PC.
+pub struct JitCache {
+    pub config: JitConfig,
+    backend: NativeBackend,
+    entries: HashMap<u64, JitEntry>,
+    /// Interpreted executions per block start PC (until compiled).
+    counts: HashMap<u64, u32>,
+    /// Blocks containing ops the backend does not support.
+    rejected: HashSet<u64>,
+    /// Hot blocks waiting to be compiled, by start PC.
+    queue: HashMap<u64, CompileRequest>,
+    /// Block hashes from a profile, by start PC, not run yet.
+    preload: HashMap<u64, u64>,
+    /// Dispatch count at the start of the current budget window.
+    window_start: u64,
+    /// Blocks compiled in the current budget window.
+    window_compiles: u32,
+    /// Current generation (incremented on flush).
+    pub generation: u32,
+    /// Statistics: blocks compiled.
+    pub compiled: u64,
+    /// Statistics: blocks the backend could not compile.
+    pub rejects: u64,
+    /// Statistics: compiled block executions.
+    pub executions: u64,
+    /// Statistics: dispatches that fell back to the interpreter.
+    pub misses: u64,
+    /// Statistics: flushes.
+    pub flushes: u64,
+    jit_ops: u64,
+    interpreted_ops: u64,
+    compile_time: Duration,
+    queued: u64,
+    stale: u64,
+    dropped: u64,
+    throttled: u64,
+    preloaded: u64,
+    verified: u64,
+    divergences: u64,
+    first_divergence: Option<Divergence>,
+    /// Rejections by MicroOp kind.
+    fallbacks: HashMap<String, u64>,
+    /// Where [`publish`](Self::publish) copies diagnostics to.
+    sink: Option<JitDiagnosticsHandle>,
+}
+
+// SAFETY: the generated code and the module that owns it are only reached
+// through `&mut JitCache`, which belongs to a single hart.
+unsafe impl Send for JitCache {}
+
+impl JitCache {
+    /// Create a JIT for the host ISA.
+    pub fn new(config: JitConfig) -> Result<Self, String> {
+        Ok(Self {
+            config,
+            backend: NativeBackend::new()?,
+            entries: HashMap::new(),
+            counts: HashMap::new(),
+            rejected: HashSet::new(),
+            queue: HashMap::new(),
+            preload: HashMap::new(),
+            window_start: 0,
+            window_compiles: 0,
+            generation: 0,
+            compiled: 0,
+            rejects: 0,
+            executions: 0,
+            misses: 0,
+            flushes: 0,
+            jit_ops: 0,
+            interpreted_ops: 0,
+            compile_time: Duration::ZERO,
+            queued: 0,
+            stale: 0,
+            dropped: 0,
+            throttled: 0,
+            preloaded: 0,
+            verified: 0,
+            divergences: 0,
+            first_divergence: None,
+            fallbacks: HashMap::new(),
+            sink: None,
+        })
+    }
+
+    /// Run `block` natively if it has been compiled, compiling it first
+    /// once it becomes hot.
+    ///
+    /// Returns the next PC, or `None` if the caller must interpret the block.
+    #[inline]
+    pub fn execute(&mut self, block: &Block, regs: &mut [u64; 32]) -> Option<u64> {
+        let pc = block.start_pc;
+        let func = match self.entries.get_mut(&pc) {
+            Some(entry)
+                if entry.generation == self.generation
+                    && entry.start_pa == block.start_pa
+                    && entry.byte_len == block.byte_len =>
+            {
+                entry.executions += 1;
+                entry.func
+            }
+            _ => match self.profile(block) {
+                Some(func) => func,
+                None => {
+                    self.misses += 1;
+                    self.interpreted_ops += block.len as u64;
+                    self.maybe_publish();
+                    return None;
+                }
+            },
+        };
+
+        self.executions += 1;
+        self.jit_ops += block.len as u64;
+        self.maybe_publish();
+        // SAFETY: compiled code only accesses regs[0..32] and never writes x0.
+        Some(unsafe { func(regs.as_mut_ptr()) })
+    }
+
+    /// Count an interpreted execution, queue the block once it is hot and
+    /// compile what the budget allows. Returns the block's code if it is
+    /// compiled now.
+    fn profile(&mut self, block: &Block) -> Option<BlockFn> {
+        let pc = block.start_pc;
+        if self.rejected.contains(&pc) {
+            return None;
+        }
+        match self.queue.get_mut(&pc) {
+            Some(request)
+                if request.block.start_pa == block.start_pa
+                    && request.block.byte_len == block.byte_len =>
+            {
+                request.hotness += 1;
+            }
+            Some(request) => {
+                // Different code at the same PC: start over with the new block
+                self.stale += 1;
+                *request = CompileRequest {
+                    block: block.clone(),
+                    hotness: 1,
+                };
+            }
+            None => {
+                let warm = self
+                    .preload
+                    .remove(&pc)
+                    .is_some_and(|hash| hash == block_hash(block));
+                let count = self.counts.entry(pc).or_insert(0);
+                *count += 1;
+                if *count < self.config.hot_threshold && !warm {
+                    return None;
+                }
+                let hotness = (*count).max(self.config.hot_threshold);
+                self.counts.remove(&pc);
+
+                if let Some(op) = block.ops().iter().find(|op| !is_supported(op)) {
+                    self.reject(pc, &op_kind(op));
+                    return None;
+                }
+                if warm {
+                    self.preloaded += 1;
+                }
+                self.enqueue(block, hotness);
+            }
+        }
+
+        self.compile_queued();
+        let entry = self.entries.get_mut(&pc).filter(|entry| {
+            entry.generation == self.generation
+                && entry.start_pa == block.start_pa
+                && entry.byte_len == block.byte_len
+        })?;
+        entry.executions += 1;
+        Some(entry.func)
+    }
+
+    /// Queue a hot block, turning away the coldest request if the queue is
+    /// full.
+    fn enqueue(&mut self, block: &Block, hotness: u32) {
+        if self.queue.len() >= self.config.max_queued {
+            self.dropped += 1;
+            let coldest = self
+                .queue
+                .iter()
+                .min_by_key(|(pc, request)| (request.hotness, Reverse(**pc)))
+                .map(|(&pc, request)| (pc, request.hotness));
+            match coldest {
+                Some((pc, coldest)) if coldest < hotness => {
+                    self.queue.remove(&pc);
+                }
+                // Profile the block again from scratch
+                _ => return,
+            }
+        }
+        self.queued += 1;
+        self.queue.insert(
+            block.start_pc,
+            CompileRequest {
+                block: block.clone(),
+                hotness,
+            },
+        );
+    }
+
+    /// Compile queued blocks, hottest first, until the budget of the current
+    /// window is spent.
+    fn compile_queued(&mut self) {
+        let dispatches = self.executions + self.misses;
+        if dispatches.wrapping_sub(self.window_start) >= COMPILE_WINDOW {
+            self.window_start = dispatches;
+            self.window_compiles = 0;
+        }
+        while !self.queue.is_empty() {
+            if self.window_compiles >= self.config.compile_budget {
+                self.throttled += 1;
+                return;
+            }
+            let pc = self
+                .queue
+                .iter()
+                .max_by_key(|(pc, request)| (request.hotness, Reverse(**pc)))
+                .map(|(&pc, _)| pc)
+                .unwrap();
+            let request = self.queue.remove(&pc).unwrap();
+            self.window_compiles += 1;
+            self.compile(&request.block);
+        }
+    }
+
+    fn compile(&mut self, block: &Block) {
+        if self.entries.len() >= self.config.max_blocks {
+            self.flush();
+        }
+
+        let start = Instant::now();
+        let compiled = self.backend.compile(block);
+        let compile_time = start.elapsed();
+        self.compile_time += compile_time;
+
+        let Some(func) = compiled else {
+            self.reject(block.start_pc, CODEGEN_FAILED);
+            return;
+        };
+        self.entries.insert(
+            block.start_pc,
+            JitEntry {
+                func,
+                start_pa: block.start_pa,
+                byte_len: block.byte_len,
+                hash: block_hash(block),
+                generation: self.generation,
+                ops: block.len,
+                executions: 0,
+                compile_time,
+            },
+        );
+        self.compiled += 1;
+    }
+
+    /// Compare a native run of `block` from `regs` with the interpreter's
+    /// run from the same registers; `interpret` runs a block (or a prefix of
+    /// one) in the interpreter and returns the next PC.
+    ///
+    /// On a mismatch the divergence is isolated, logged and counted, and the
+    /// block is left to the interpreter from then on. Returns whether the
+    /// runs agreed.
+    pub fn verify(
+        &mut self,
+        block: &Block,
+        regs: &[u64; 32],
+        native: (&[u64; 32], u64),
+        interpreted: (&[u64; 32], u64),
+        mut interpret: impl FnMut(&Block, &mut [u64; 32]) -> u64,
+    ) -> bool {
+        self.verified += 1;
+        if native == interpreted {
+            return true;
+        }
+
+        let mut divergence = Divergence {
+            block_pc: block.start_pc,
+            op: None,
+            regs: *regs,
+            mismatches: mismatches(native, interpreted),
+        };
+        let mut prefix = block.clone();
+        for len in 1..=block.len {
+            prefix.len = len;
+            let Some(func) = self.backend.compile(&prefix) else {
+                break;
+            };
+            let mut native_regs = *regs;
+            // SAFETY: compiled code only accesses regs[0..32] and never writes x0.
+            let native_pc = unsafe { func(native_regs.as_mut_ptr()) };
+            let mut interpreted_regs = *regs;
+            let interpreted_pc = interpret(&prefix, &mut interpreted_regs);
+            let found = mismatches(
+                (&native_regs, native_pc),
+                (&interpreted_regs, interpreted_pc),
+            );
+            if !found.is_empty() {
+                let index = len as usize - 1;
+                divergence.op = Some((index, block.ops[index]));
+                divergence.mismatches = found;
+                break;
+            }
+        }
+
+        log::error!(
+            "[JIT] Native code diverged from the interpreter: {}",
+            divergence
+        );
+        self.divergences += 1;
+        self.first_divergence.get_or_insert(divergence);
+        self.entries.remove(&block.start_pc);
+        self.reject(block.start_pc, DIVERGED);
+        false
+    }
+
+    fn reject(&mut self, pc: u64, reason: &str) {
+        self.rejected.insert(pc);
+        self.rejects += 1;
+        *self.fallbacks.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    /// Compile the blocks of an earlier session's profile on their first
+    /// run, if their code is unchanged, instead of once they are hot.
+    ///
+    /// The caller checks that the profile was recorded with the same
+    /// kernel image.
+    pub fn preload(&mut self, blocks: &[ProfiledBlock]) {
+        self.preload
+            .extend(blocks.iter().map(|block| (block.pc, block.hash)));
+    }
+
+    /// Discard all compiled code, profiles and queued blocks.
+    pub fn flush(&mut self) {
+        // Keep the per-block profiles of the discarded code visible
+        self.publish();
+        self.generation = self.generation.wrapping_add(1);
+        self.entries.clear();
+        self.counts.clear();
+        self.stale += self.queue.len() as u64;
+        self.queue.clear();
+        self.rejected.clear();
+        self.backend.reset();
+        self.flushes += 1;
+    }
+
+    /// Drop compiled and queued blocks containing code in a physical
+    /// address range.
+    ///
+    /// Their machine code stays allocated until the next [`flush`](Self::flush).
+    pub fn invalidate_range(&mut self, start_pa: u64, end_pa: u64) {
+        self.entries.retain(|_, entry| {
+            let entry_end = entry.start_pa + entry.byte_len as u64;
+            !(entry.start_pa < end_pa && entry_end > start_pa)
+        });
+        let queued = self.queue.len();
+        self.queue
+            .retain(|_, request| !request.overlaps(start_pa, end_pa));
+        self.stale += (queued - self.queue.len()) as u64;
+    }
+
+    /// Number of compiled blocks.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no blocks are compiled.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Current profile: counters, hottest blocks and fallback reasons.
+    pub fn diagnostics(&self) -> JitDiagnostics {
+        let mut hot_blocks: Vec<HotBlock> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.generation == self.generation)
+            .map(|(&pc, entry)| HotBlock {
+                pc,
+                executions: entry.executions,
+                ops: entry.ops,
+                compile_time: entry.compile_time,
+            })
+            .collect();
+        hot_blocks.sort_by_key(|b| Reverse(b.executions));
+        hot_blocks.truncate(DIAGNOSTICS_HOT_BLOCKS);
+
+        let mut fallbacks: Vec<(String, u64)> = self
+            .fallbacks
+            .iter()
+            .map(|(kind, &count)| (kind.clone(), count))
+            .collect();
+        fallbacks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut profile: Vec<ProfiledBlock> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.generation == self.generation)
+            .map(|(&pc, entry)| ProfiledBlock {
+                pc,
+                hash: entry.hash,
+            })
+            .collect();
+        profile.sort_unstable();
+
+        JitDiagnostics {
+            compiled: self.compiled,
+            rejects: self.rejects,
+            flushes: self.flushes,
+            hits: self.executions,
+            misses: self.misses,
+            jit_ops: self.jit_ops,
+            interpreted_ops: self.interpreted_ops,
+            compile_time: self.compile_time,
+            queued: self.queued,
+            stale: self.stale,
+            dropped: self.dropped,
+            throttled: self.throttled,
+            queue_depth: self.queue.len() as u64,
+            preloaded: self.preloaded,
+            verified: self.verified,
+            divergences: self.divergences,
+            first_divergence: self.first_divergence.clone(),
+            hot_blocks,
+            fallbacks,
+            profile,
+        }
+    }
+
+    /// Copy diagnostics to `handle` now, periodically, and when dropped.
+    ///
+    /// This lets another thread read the profile of a hart it can't reach.
+    pub fn attach(&mut self, handle: JitDiagnosticsHandle) {
+        self.sink = Some(handle);
+        self.publish();
+    }
+
+    /// Refresh the attached diagnostics handle.
+    pub fn publish(&self) {
+        if let Some(sink) = &self.sink
+            && let Ok(mut shared) = sink.lock()
+        {
+            *shared = self.diagnostics();
+        }
+    }
+
+    #[inline]
+    fn maybe_publish(&self) {
+        if self.sink.is_some() && (self.executions + self.misses).is_multiple_of(PUBLISH_INTERVAL) {
+            self.publish();
+        }
+    }
+}
+
+impl Drop for JitCache {
+    fn drop(&mut self) {
+        self.publish();
+    }
+}
+
+/// Hash identifying a block's code across sessions.
+fn block_hash(block: &Block) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(block.start_pa.to_le_bytes());
+    hasher.update(block.byte_len.to_le_bytes());
+    hasher.update(format!("{:?}", block.ops()));
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Name of an op's kind, e.g. "Ld" or "Csrrw".
+fn op_kind(op: &MicroOp) -> String {
+    let debug = format!("{:?}", op);
+    debug
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Whether the backend can compile `op`.
+fn is_supported(op: &MicroOp) -> bool {
+    matches!(
+        op,
+        MicroOp::Addi { .. }
+            | MicroOp::Xori { .. }
+            | MicroOp::Ori { .. }
+            | MicroOp::Andi { .. }
+            | MicroOp::Slti { .. }
+            | MicroOp::Sltiu { .. }
+            | MicroOp::Slli { .. }
+            | MicroOp::Srli { .. }
+            | MicroOp::Srai { .. }
+            | MicroOp::Add { .. }
+            | MicroOp::Sub { .. }
+            | MicroOp::Xor { .. }
+            | MicroOp::Or { .. }
+            | MicroOp::And { .. }
+            | MicroOp::Sll { .. }
+            | MicroOp::Srl { .. }
+            | MicroOp::Sra { .. }
+            | MicroOp::Slt { .. }
+            | MicroOp::Sltu { .. }
+            | MicroOp::Addiw { .. }
+            | MicroOp::Slliw { .. }
+            | MicroOp::Srliw { .. }
+            | MicroOp::Sraiw { .. }
+            | MicroOp::Addw { .. }
+            | MicroOp::Subw { .. }
+            | MicroOp::Sllw { .. }
+            | MicroOp::Srlw { .. }
+            | MicroOp::Sraw { .. }
+            | MicroOp::Mul { .. }
+            | MicroOp::Mulh { .. }
+            | MicroOp::Mulhu { .. }
+            | MicroOp::Mulw { .. }
+            | MicroOp::Bit { .. }
+            | MicroOp::BitImm { .. }
+            | MicroOp::Lui { .. }
+            | MicroOp::Auipc { .. }
+            | MicroOp::Fence
+            | MicroOp::Jal { .. }
+            | MicroOp::Jalr { .. }
+            | MicroOp::Beq { .. }
+            | MicroOp::Bne { .. }
+            | MicroOp::Blt { .. }
+            | MicroOp::Bge { .. }
+            | MicroOp::Bltu { .. }
+            | MicroOp::Bgeu { .. }
+    )
+}
+
+/// Cranelift code generator for the host machine.
+pub struct NativeBackend {
+    module: JITModule,
+    builder_ctx: FunctionBuilderContext,
+}
+
+impl NativeBackend {
+    /// Create a backend targeting the host ISA.
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            module: new_module()?,
+            builder_ctx: FunctionBuilderContext::new(),
+        })
+    }
+
+    /// Compile `block` to a host function, or `None` if it contains
+    /// unsupported ops.
+    pub fn compile(&mut self, block: &Block) -> Option<BlockFn> {
+        if !block.ops().iter().all(is_supported) {
+            return None;
+        }
+
+        let ptr = self.module.target_config().pointer_type();
+        let mut ctx = self.module.make_context();
+        ctx.func.signature.params.push(AbiParam::new(ptr));
+        ctx.func.signature.returns.push(AbiParam::new(types::I64));
+
+        {
+            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_ctx);
+            let entry = builder.create_block();
+            builder.append_block_params_for_function_params(entry);
+            builder.switch_to_block(entry);
+            builder.seal_block(entry);
+            let regs = builder.block_params(entry)[0];
+
+            let mut emitter = Emitter {
+                builder,
+                regs,
+                values: [None; 32],
+                dirty: 0,
+            };
+            let next_pc = emitter.emit_block(block);
+            emitter.write_back();
+            emitter.builder.ins().return_(&[next_pc]);
+            emitter.builder.finalize();
+        }
+
+        let id = self
+            .module
+            .declare_anonymous_function(&ctx.func.signature)
+            .ok()?;
+        let defined = self.module.define_function(id, &mut ctx);
+        self.module.clear_context(&mut ctx);
+        defined.ok()?;
+        self.module.finalize_definitions().ok()?;
+
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: the function was built with the `BlockFn` signature.
+        Some(unsafe { std::mem::transmute::<*const u8, BlockFn>(code) })
+    }
+
+    /// Free all generated code. Previously returned functions become invalid.
+    pub fn reset(&mut self) {
+        let Ok(fresh) = new_module() else {
+            return;
+        };
+        let old = std::mem::replace(&mut self.module, fresh);
+        // SAFETY: callers drop every `BlockFn` from this module before reset.
+        unsafe { old.free_memory() };
+    }
+}
+
+fn new_module() -> Result<JITModule, String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+    Ok(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+/// Translates one block, caching guest registers in SSA values.
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    /// Pointer to the guest register file.
+    regs: Value,
+    /// Current value of each register, if loaded or written.
+    values: [Option<Value>; 32],
+    /// Registers written by the block (bit N = xN).
+    dirty: u32,
+}
+
+impl Emitter<'_> {
+    fn get(&mut self, reg: u8) -> Value {
+        if reg == 0 {
+            return self.builder.ins().iconst(types::I64, 0);
+        }
+        if let Some(value) = self.values[reg as usize] {
+            return value;
+        }
+        let value =
+            self.builder
+                .ins()
+                .load(types::I64, MemFlags::trusted(), self.regs, reg as i32 * 8);
+        self.values[reg as usize] = Some(value);
+        value
+    }
+
+    fn set(&mut self, reg: u8, value: Value) {
+        if reg != 0 {
+            self.values[reg as usize] = Some(value);
+            self.dirty |= 1 << reg;
+        }
+    }
+
+    fn constant(&mut self, value: u64) -> Value {
+        self.builder.ins().iconst(types::I64, value as i64)
+    }
+
+    /// Sign-extend the low 32 bits of `value` (RV64 word result).
+    fn sext_w(&mut self, value: Value) -> Value {
+        let low = self.builder.ins().ireduce(types::I32, value);
+        self.builder.ins().sextend(types::I64, low)
+    }
+
+    /// Compare to 0/1, as produced by SLT and friends.
+    fn set_if(&mut self, cc: IntCC, a: Value, b: Value) -> Value {
+        let flag = self.builder.ins().icmp(cc, a, b);
+        self.builder.ins().uextend(types::I64, flag)
+    }
+
+    fn binary(
+        &mut self,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        f: impl FnOnce(&mut Self, Value, Value) -> Value,
+    ) {
+        let a = self.get(rs1);
+        let b = self.get(rs2);
+        let value = f(self, a, b);
+        self.set(rd, value);
+    }
+
+    fn unary(&mut self, rd: u8, rs1: u8, f: impl FnOnce(&mut Self, Value) -> Value) {
+        let a = self.get(rs1);
+        let value = f(self, a);
+        self.set(rd, value);
+    }
+
+    /// Zba/Zbb/Zbs `op` on `a` (rs1) and `b` (rs2 or shift amount), as
+    /// [`BitOp::eval`].
+    fn bitop(&mut self, op: BitOp, a: Value, b: Value) -> Value {
+        let uw = |e: &mut Self, v: Value| {
+            let low = e.builder.ins().ireduce(types::I32, v);
+            e.builder.ins().uextend(types::I64, low)
+        };
+        let word = |e: &mut Self, v: Value| e.builder.ins().ireduce(types::I32, v);
+        match op {
+            BitOp::AddUw => {
+                let a = uw(self, a);
+                self.builder.ins().iadd(b, a)
+            }
+            BitOp::Sh1Add | BitOp::Sh2Add | BitOp::Sh3Add => {
+                let shift = match op {
+                    BitOp::Sh1Add => 1,
+                    BitOp::Sh2Add => 2,
+                    _ => 3,
+                };
+                let scaled = self.builder.ins().ishl_imm(a, shift);
+                self.builder.ins().iadd(b, scaled)
+            }
+            BitOp::Sh1AddUw | BitOp::Sh2AddUw | BitOp::Sh3AddUw => {
+                let shift = match op {
+                    BitOp::Sh1AddUw => 1,
+                    BitOp::Sh2AddUw => 2,
+                    _ => 3,
+                };
+                let a = uw(self, a);
+                let scaled = self.builder.ins().ishl_imm(a, shift);
+                self.builder.ins().iadd(b, scaled)
+            }
+            BitOp::SlliUw => {
+                let a = uw(self, a);
+                self.builder.ins().ishl(a, b)
+            }
+            BitOp::Andn => self.builder.ins().band_not(a, b),
+            BitOp::Orn => self.builder.ins().bor_not(a, b),
+            BitOp::Xnor => self.builder.ins().bxor_not(a, b),
+            BitOp::Clz => self.builder.ins().clz(a),
+            BitOp::Ctz => self.builder.ins().ctz(a),
+            BitOp::Cpop => self.builder.ins().popcnt(a),
+            BitOp::Clzw | BitOp::Ctzw | BitOp::Cpopw => {
+                let w = word(self, a);
+                let count = match op {
+                    BitOp::Clzw => self.builder.ins().clz(w),
+                    BitOp::Ctzw => self.builder.ins().ctz(w),
+                    _ => self.builder.ins().popcnt(w),
+                };
+                self.builder.ins().uextend(types::I64, count)
+            }
+            BitOp::Max => self.builder.ins().smax(a, b),
+            BitOp::Maxu => self.builder.ins().umax(a, b),
+            BitOp::Min => self.builder.ins().smin(a, b),
+            BitOp::Minu => self.builder.ins().umin(a, b),
+            BitOp::SextB | BitOp::SextH | BitOp::ZextH => {
+                let ty = if op == BitOp::SextB {
+                    types::I8
+                } else {
+                    types::I16
+                };
+                let low = self.builder.ins().ireduce(ty, a);
+                if op == BitOp::ZextH {
+                    self.builder.ins().uextend(types::I64, low)
+                } else {
+                    self.builder.ins().sextend(types::I64, low)
+                }
+            }
+            BitOp::Rol => self.builder.ins().rotl(a, b),
+            BitOp::Ror => self.builder.ins().rotr(a, b),
+            BitOp::Rolw | BitOp::Rorw => {
+                let w = word(self, a);
+                let rotated = if op == BitOp::Rolw {
+                    self.builder.ins().rotl(w, b)
+                } else {
+                    self.builder.ins().rotr(w, b)
+                };
+                self.builder.ins().sextend(types::I64, rotated)
+            }
+            BitOp::OrcB => {
+                // Set the top bit of each non-zero byte, then widen it to 0xFF
+                const LOW7: i64 = 0x7F7F_7F7F_7F7F_7F7F;
+                let low = self.builder.ins().band_imm(a, LOW7);
+                let carried = self.builder.ins().iadd_imm(low, LOW7);
+                let any = self.builder.ins().bor(carried, a);
+                let top = self.builder.ins().band_imm(any, !LOW7);
+                let ones = self.builder.ins().ushr_imm(top, 7);
+                self.builder.ins().imul_imm(ones, 0xFF)
+            }
+            BitOp::Rev8 => self.builder.ins().bswap(a),
+            BitOp::Bclr | BitOp::Binv | BitOp::Bset => {
+                let one = self.constant(1);
+                let bit = self.builder.ins().ishl(one, b);
+                match op {
+                    BitOp::Bclr => self.builder.ins().band_not(a, bit),
+                    BitOp::Binv => self.builder.ins().bxor(a, bit),
+                    _ => self.builder.ins().bor(a, bit),
+                }
+            }
+            BitOp::Bext => {
+                let shifted = self.builder.ins().ushr(a, b);
+                self.builder.ins().band_imm(shifted, 1)
+            }
+        }
+    }
+
+    /// Conditional branch: next PC is the target if `cc(rs1, rs2)` holds.
+    fn branch(&mut self, cc: IntCC, rs1: u8, rs2: u8, pc: u64, imm: i64, insn_len: u8) -> Value {
+        let a = self.get(rs1);
+        let b = self.get(rs2);
+        let taken = self.builder.ins().icmp(cc, a, b);
+        let target = self.constant(pc.wrapping_add(imm as u64));
+        let fallthrough = self.constant(pc.wrapping_add(insn_len as u64));
+        self.builder.ins().select(taken, target, fallthrough)
+    }
+
+    /// Emit every op of `block` and return the next PC.
+    fn emit_block(&mut self, block: &Block) -> Value {
+        let base_pc = block.start_pc;
+        let at = |pc_offset: u16| base_pc.wrapping_add(pc_offset as u64);
+
+        for op in block.ops() {
+            match *op {
+                MicroOp::Addi { rd, rs1, imm } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().iadd_imm(a, imm))
+                }
+                MicroOp::Xori { rd, rs1, imm } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().bxor_imm(a, imm))
+                }
+                MicroOp::Ori { rd, rs1, imm } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().bor_imm(a, imm))
+                }
+                MicroOp::Andi { rd, rs1, imm } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().band_imm(a, imm))
+                }
+                MicroOp::Slti { rd, rs1, imm } => self.unary(rd, rs1, |e, a| {
+                    let b = e.constant(imm as u64);
+                    e.set_if(IntCC::SignedLessThan, a, b)
+                }),
+                MicroOp::Sltiu { rd, rs1, imm } => self.unary(rd, rs1, |e, a| {
+                    let b = e.constant(imm as u64);
+                    e.set_if(IntCC::UnsignedLessThan, a, b)
+                }),
+                MicroOp::Slli { rd, rs1, shamt } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().ishl_imm(a, shamt as i64))
+                }
+                MicroOp::Srli { rd, rs1, shamt } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().ushr_imm(a, shamt as i64))
+                }
+                MicroOp::Srai { rd, rs1, shamt } => {
+                    self.unary(rd, rs1, |e, a| e.builder.ins().sshr_imm(a, shamt as i64))
+                }
+
+                // Cranelift masks shift amounts to the operand width, as
+                // RISC-V does.
+                MicroOp::Add { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().iadd(a, b))
+                }
+                MicroOp::Sub { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().isub(a, b))
+                }
+                MicroOp::Xor { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().bxor(a, b))
+                }
+                MicroOp::Or { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().bor(a, b))
+                }
+                MicroOp::And { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().band(a, b))
+                }
+                MicroOp::Sll { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().ishl(a, b))
+                }
+                MicroOp::Srl { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().ushr(a, b))
+                }
+                MicroOp::Sra { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().sshr(a, b))
+                }
+                MicroOp::Slt { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    e.set_if(IntCC::SignedLessThan, a, b)
+                }),
+                MicroOp::Sltu { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    e.set_if(IntCC::UnsignedLessThan, a, b)
+                }),
+
+                MicroOp::Addiw { rd, rs1, imm } => self.unary(rd, rs1, |e, a| {
+                    let sum = e.builder.ins().iadd_imm(a, imm as i64);
+                    e.sext_w(sum)
+                }),
+                MicroOp::Slliw { rd, rs1, shamt } => self.unary(rd, rs1, |e, a| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().ishl_imm(word, shamt as i64);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+                MicroOp::Srliw { rd, rs1, shamt } => self.unary(rd, rs1, |e, a| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().ushr_imm(word, shamt as i64);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+                MicroOp::Sraiw { rd, rs1, shamt } => self.unary(rd, rs1, |e, a| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().sshr_imm(word, shamt as i64);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+                MicroOp::Addw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let sum = e.builder.ins().iadd(a, b);
+                    e.sext_w(sum)
+                }),
+                MicroOp::Subw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let diff = e.builder.ins().isub(a, b);
+                    e.sext_w(diff)
+                }),
+                MicroOp::Sllw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().ishl(word, b);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+                MicroOp::Srlw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().ushr(word, b);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+                MicroOp::Sraw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let word = e.builder.ins().ireduce(types::I32, a);
+                    let shifted = e.builder.ins().sshr(word, b);
+                    e.builder.ins().sextend(types::I64, shifted)
+                }),
+
+                MicroOp::Mul { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().imul(a, b))
+                }
+                MicroOp::Mulh { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().smulhi(a, b))
+                }
+                MicroOp::Mulhu { rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.builder.ins().umulhi(a, b))
+                }
+                MicroOp::Mulw { rd, rs1, rs2 } => self.binary(rd, rs1, rs2, |e, a, b| {
+                    let product = e.builder.ins().imul(a, b);
+                    e.sext_w(product)
+                }),
+
+                MicroOp::Bit { op, rd, rs1, rs2 } => {
+                    self.binary(rd, rs1, rs2, |e, a, b| e.bitop(op, a, b))
+                }
+                MicroOp::BitImm { op, rd, rs1, shamt } => self.unary(rd, rs1, |e, a| {
+                    let b = e.constant(shamt as u64);
+                    e.bitop(op, a, b)
+                }),
+
+                MicroOp::Lui { rd, imm } => {
+                    let value = self.constant(imm as u64);
+                    self.set(rd, value);
+                }
+                MicroOp::Auipc { rd, imm, pc_offset } => {
+                    let value = self.constant(at(pc_offset).wrapping_add(imm as u64));
+                    self.set(rd, value);
+                }
+                MicroOp::Fence => {}
+
+                MicroOp::Jal {
+                    rd,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    let pc = at(pc_offset);
+                    let link = self.constant(pc.wrapping_add(insn_len as u64));
+                    self.set(rd, link);
+                    return self.constant(pc.wrapping_add(imm as u64));
+                }
+                MicroOp::Jalr {
+                    rd,
+                    rs1,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    // The target is read before rd is written (rd may equal rs1)
+                    let base = self.get(rs1);
+                    let sum = self.builder.ins().iadd_imm(base, imm);
+                    let target = self.builder.ins().band_imm(sum, !1);
+                    let link = self.constant(at(pc_offset).wrapping_add(insn_len as u64));
+                    self.set(rd, link);
+                    return target;
+                }
+                MicroOp::Beq {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => return self.branch(IntCC::Equal, rs1, rs2, at(pc_offset), imm, insn_len),
+                MicroOp::Bne {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => return self.branch(IntCC::NotEqual, rs1, rs2, at(pc_offset), imm, insn_len),
+                MicroOp::Blt {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    return self.branch(
+                        IntCC::SignedLessThan,
+                        rs1,
+                        rs2,
+                        at(pc_offset),
+                        imm,
+                        insn_len,
+                    );
+                }
+                MicroOp::Bge {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    return self.branch(
+                        IntCC::SignedGreaterThanOrEqual,
+                        rs1,
+                        rs2,
+                        at(pc_offset),
+                        imm,
+                        insn_len,
+                    );
+                }
+                MicroOp::Bltu {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    return self.branch(
+                        IntCC::UnsignedLessThan,
+                        rs1,
+                        rs2,
+                        at(pc_offset),
+                        imm,
+                        insn_len,
+                    );
+                }
+                MicroOp::Bgeu {
+                    rs1,
+                    rs2,
+                    imm,
+                    pc_offset,
+                    insn_len,
+                } => {
+                    return self.branch(
+                        IntCC::UnsignedGreaterThanOrEqual,
+                        rs1,
+                        rs2,
+                        at(pc_offset),
+                        imm,
+                        insn_len,
+                    );
+                }
+
+                // Rejected by `is_supported` before emission
+                _ => unreachable!("unsupported op in JIT block: {:?}", op),
+            }
+        }
+
+        // Block ended without a terminator (full block)
+        self.constant(base_pc.wrapping_add(block.byte_len as u64))
+    }
+
+    /// Store every written register back to the register file.
+    fn write_back(&mut self) {
+        for reg in 1..32 {
+            if self.dirty & (1 << reg) != 0
+                && let Some(value) = self.values[reg]
+            {
+                self.builder
+                    .ins()
+                    .store(MemFlags::trusted(), value, self.regs, reg as i32 * 8);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_of(ops: &[MicroOp]) -> Block {
+        let mut block = Block::new(0x8000_0000, 0x8000_0000, 0);
+        for op in ops {
+            block.push(*op, 4);
+        }
+        block
+    }
+
+    #[test]
+    fn test_compile_alu_block() {
+        let mut backend = NativeBackend::new().unwrap();
+        let block = block_of(&[
+            MicroOp::Addi {
+                rd: 1,
+                rs1: 0,
+                imm: -5,
+            },
+            MicroOp::Slli {
+                rd: 2,
+                rs1: 1,
+                shamt: 4,
+            },
+            MicroOp::Addw {
+                rd: 3,
+                rs1: 2,
+                rs2: 4,
+            },
+            MicroOp::Sltu {
+                rd: 5,
+                rs1: 0,
+                rs2: 1,
+            },
+            MicroOp::Addi {
+                rd: 0,
+                rs1: 1,
+                imm: 1,
+            },
+        ]);
+        let func = backend.compile(&block).unwrap();
+
+        let mut regs = [0u64; 32];
+        regs[4] = 0x7FFF_FFFF;
+        let next_pc = unsafe { func(regs.as_mut_ptr()) };
+
+        assert_eq!(next_pc, 0x8000_0014);
+        assert_eq!(regs[0], 0);
+        assert_eq!(regs[1], (-5i64) as u64);
+        assert_eq!(regs[2], (-80i64) as u64);
+        assert_eq!(
+            regs[3],
+            (0x7FFF_FFFFu64.wrapping_sub(80) as i32) as i64 as u64
+        );
+        assert_eq!(regs[5], 1);
+    }
+
+    #[test]
+    fn test_bitmanip_matches_interpreter() {
+        use BitOp::*;
+        let ops = [
+            AddUw, Sh1Add, Sh2Add, Sh3Add, Sh1AddUw, Sh2AddUw, Sh3AddUw, SlliUw, Andn, Orn, Xnor,
+            Clz, Clzw, Ctz, Ctzw, Cpop, Cpopw, Max, Maxu, Min, Minu, SextB, SextH, ZextH, Rol,
+            Rolw, Ror, Rorw, OrcB, Rev8, Bclr, Bext, Binv, Bset,
+        ];
+        let inputs = [
+            (0, 0),
+            (0x8000_0000_0000_0001, 63),
+            (0x0000_00F0_8000_FF00, 37),
+            (u64::MAX, 0xFFFF_FFFF_0000_0004),
+        ];
+        let mut backend = NativeBackend::new().unwrap();
+        for op in ops {
+            let block = block_of(&[
+                MicroOp::Bit {
+                    op,
+                    rd: 3,
+                    rs1: 1,
+                    rs2: 2,
+                },
+                MicroOp::BitImm {
+                    op,
+                    rd: 4,
+                    rs1: 1,
+                    shamt: 5,
+                },
+            ]);
+            let func = backend.compile(&block).unwrap();
+            for (a, b) in inputs {
+                let mut regs = [0u64; 32];
+                regs[1] = a;
+                regs[2] = b;
+                unsafe { func(regs.as_mut_ptr()) };
+                assert_eq!(regs[3], op.eval(a, b), "{:?}({:#x}, {:#x})", op, a, b);
+                assert_eq!(regs[4], op.eval(a, 5), "{:?}({:#x}, 5)", op, a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_branch_and_jalr() {
+        let mut backend = NativeBackend::new().unwrap();
+        let branch = block_of(&[MicroOp::Blt {
+            rs1: 1,
+            rs2: 2,
+            imm: -16,
+            pc_offset: 0,
+            insn_len: 4,
+        }]);
+        let func = backend.compile(&branch).unwrap();
+
+        let mut regs = [0u64; 32];
+        regs[1] = (-1i64) as u64;
+        assert_eq!(unsafe { func(regs.as_mut_ptr()) }, 0x7FFF_FFF0);
+        regs[1] = 3;
+        assert_eq!(unsafe { func(regs.as_mut_ptr()) }, 0x8000_0004);
+
+        // rd == rs1: the target uses the old value
+        let jalr = block_of(&[MicroOp::Jalr {
+            rd: 1,
+            rs1: 1,
+            imm: 3,
+            pc_offset: 0,
+            insn_len: 4,
+        }]);
+        let func = backend.compile(&jalr).unwrap();
+        regs[1] = 0x1000;
+        assert_eq!(unsafe { func(regs.as_mut_ptr()) }, 0x1002);
+        assert_eq!(regs[1], 0x8000_0004);
+    }
+
+    #[test]
+    fn test_rejects_memory_ops_and_compiles_when_hot() {
+        let mut jit = JitCache::new(JitConfig {
+            hot_threshold: 2,
+            max_blocks: 16,
+            ..Default::default()
+        })
+        .unwrap();
+        let load = block_of(&[MicroOp::Ld {
+            rd: 1,
+            rs1: 2,
+            imm: 0,
+            pc_offset: 0,
+        }]);
+        let mut regs = [0u64; 32];
+        assert_eq!(jit.execute(&load, &mut regs), None);
+        assert_eq!(jit.execute(&load, &mut regs), None);
+        assert_eq!(jit.rejects, 1);
+        assert_eq!(jit.diagnostics().fallbacks, vec![("Ld".to_string(), 1)]);
+
+        let mut add = Block::new(0x8000_1000, 0x8000_1000, 0);
+        add.push(
+            MicroOp::Addi {
+                rd: 1,
+                rs1: 1,
+                imm: 1,
+            },
+            4,
+        );
+        assert_eq!(jit.execute(&add, &mut regs), None);
+        assert_eq!(jit.execute(&add, &mut regs), Some(0x8000_1004));
+        assert_eq!(regs[1], 1);
+        assert_eq!(jit.len(), 1);
+
+        let diag = jit.diagnostics();
+        assert_eq!((diag.hits, diag.misses), (1, 3));
+        assert_eq!((diag.jit_ops, diag.interpreted_ops), (1, 3));
+        assert_eq!(diag.hot_blocks.len(), 1);
+        assert_eq!(diag.hot_blocks[0].pc, 0x8000_1000);
+
+        jit.flush();
+        assert!(jit.is_empty());
+    }
+
+    #[test]
+    fn test_compile_queue_prioritizes_and_drops() {
+        let mut jit = JitCache::new(JitConfig {
+            hot_threshold: 1,
+            compile_budget: 1,
+            max_queued: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        let block_at = |pc: u64| {
+            let mut block = Block::new(pc, pc, 0);
+            block.push(
+                MicroOp::Addi {
+                    rd: 1,
+                    rs1: 1,
+                    imm: 1,
+                },
+                4,
+            );
+            block
+        };
+        let (a, b, c, d, e) = (
+            block_at(0x1000),
+            block_at(0x2000),
+            block_at(0x3000),
+            block_at(0x4000),
+            block_at(0x5000),
+        );
+        let mut regs = [0u64; 32];
+
+        // The first hot block uses up the window's budget
+        assert!(jit.execute(&a, &mut regs).is_some());
+        for block in [&b, &b, &c, &e] {
+            assert_eq!(jit.execute(block, &mut regs), None);
+        }
+        // The queue is full of blocks at least as hot
+        assert_eq!(jit.execute(&d, &mut regs), None);
+        jit.invalidate_range(0x3000, 0x3004);
+        assert_eq!(jit.diagnostics().queue_depth, 2);
+
+        // The next window compiles the hottest block first
+        while jit.executions + jit.misses < COMPILE_WINDOW {
+            jit.execute(&a, &mut regs);
+        }
+        assert_eq!(jit.execute(&e, &mut regs), None);
+        assert!(jit.execute(&b, &mut regs).is_some());
+
+        let diag = jit.diagnostics();
+        assert_eq!(diag.compiled, 2);
+        assert_eq!((diag.queued, diag.stale, diag.dropped), (4, 1, 1));
+        assert_eq!((diag.throttled, diag.queue_depth), (6, 1));
+        jit.flush();
+        assert_eq!(jit.diagnostics().stale, 2);
+    }
+
+    #[test]
+    fn test_profile_preloads_unchanged_blocks() {
+        let block = block_of(&[MicroOp::Addi {
+            rd: 1,
+            rs1: 1,
+            imm: 1,
+        }]);
+        let mut regs = [0u64; 32];
+        let mut first = JitCache::new(JitConfig {
+            hot_threshold: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        first.execute(&block, &mut regs).unwrap();
+        let profile = JitProfile::new([7; 32], first.diagnostics().profile);
+        let profile = JitProfile::from_bytes(&profile.to_bytes().unwrap()).unwrap();
+        assert_eq!(profile.blocks.len(), 1);
+
+        let mut second = JitCache::new(JitConfig {
+            hot_threshold: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        second.preload(&profile.blocks);
+        assert!(second.execute(&block, &mut regs).is_some());
+        assert_eq!(second.diagnostics().preloaded, 1);
+
+        // Different code at the same PC waits for the hot threshold
+        let mut third = JitCache::new(JitConfig {
+            hot_threshold: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        third.preload(&profile.blocks);
+        let changed = block_of(&[MicroOp::Addi {
+            rd: 1,
+            rs1: 1,
+            imm: 2,
+        }]);
+        assert_eq!(third.execute(&changed, &mut regs), None);
+        assert_eq!(third.diagnostics().preloaded, 0);
+    }
+
+    #[test]
+    fn test_verify_isolates_diverging_op() {
+        let mut jit = JitCache::new(JitConfig {
+            hot_threshold: 1,
+            verify: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let block = block_of(&[
+            MicroOp::Addi {
+                rd: 1,
+                rs1: 0,
+                imm: 7,
+            },
+            MicroOp::Slli {
+                rd: 2,
+                rs1: 1,
+                shamt: 2,
+            },
+            MicroOp::Add {
+                rd: 3,
+                rs1: 2,
+                rs2: 1,
+            },
+        ]);
+        let mut regs = [0u64; 32];
+        let next_pc = jit.execute(&block, &mut regs).unwrap();
+
+        // A reference "interpreter" that gets the shift wrong
+        let mut reference = NativeBackend::new().unwrap();
+        let mut interpret = |prefix: &Block, regs: &mut [u64; 32]| {
+            let func = reference.compile(prefix).unwrap();
+            let next_pc = unsafe { func(regs.as_mut_ptr()) };
+            if prefix.len >= 2 {
+                regs[2] += 1;
+            }
+            next_pc
+        };
+        let mut interpreted = [0u64; 32];
+        interpret(&block, &mut interpreted);
+
+        let before = [0u64; 32];
+        assert!(jit.verify(
+            &block,
+            &before,
+            (&regs, next_pc),
+            (&regs, next_pc),
+            &mut interpret
+        ));
+        assert!(!jit.verify(
+            &block,
+            &before,
+            (&regs, next_pc),
+            (&interpreted, next_pc),
+            &mut interpret
+        ));
+
+        let diag = jit.diagnostics();
+        assert_eq!((diag.verified, diag.divergences), (2, 1));
+        let divergence = diag.first_divergence.unwrap();
+        assert_eq!(divergence.op, Some((1, block.ops[1])));
+        assert_eq!(
+            divergence.mismatches,
+            vec![Mismatch::Reg {
+                reg: 2,
+                native: 28,
+                interpreter: 29
+            }]
+        );
+        // The block is interpreted from now on
+        assert_eq!(jit.execute(&block, &mut regs), None);
+        assert_eq!(diag.fallbacks, vec![(DIVERGED.to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_diagnostics_publish_and_merge() {
+        let handle = JitDiagnosticsHandle::default();
+        let mut jit = JitCache::new(JitConfig {
+            hot_threshold: 1,
+            max_blocks: 16,
+            ..Default::default()
+        })
+        .unwrap();
+        jit.attach(handle.clone());
+
+        let block = block_of(&[MicroOp::Addi {
+            rd: 1,
+            rs1: 1,
+            imm: 1,
+        }]);
+        let mut regs = [0u64; 32];
+        for _ in 0..3 {
+            jit.execute(&block, &mut regs);
+        }
+        drop(jit);
+
+        let published = handle.lock().unwrap().clone();
+        assert_eq!(published.compiled, 1);
+        assert_eq!(published.hits, 3);
+        assert_eq!(published.hot_blocks[0].executions, 3);
+
+        let mut total = published.clone();
+        total.merge(&published);
+        assert_eq!(total.hits, 6);
+        assert_eq!(total.hot_blocks.len(), 2);
+        assert_eq!(total.jit_ratio(), 1.0);
+    }
+}
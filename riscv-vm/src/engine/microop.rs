@@ -4,10 +4,17 @@
 //! instructions optimized for execution speed. Each variant contains all
 //! information needed for execution without re-decoding.
 
+use super::bitmanip::BitOp;
+use crate::cpu::pmp::is_pmp_csr;
+use crate::csr::{
+    CSR_MENVCFG, CSR_MIE, CSR_MIP, CSR_MSTATUS, CSR_SATP, CSR_SIE, CSR_SIP, CSR_SSTATUS,
+    CSR_STIMECMP,
+};
+
 /// Compact micro-operation for superblock execution.
 /// Each variant is designed to be cache-efficient with pre-computed
 /// register indices and immediates.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum MicroOp {
     // ═══════════════════════════════════════════════════════════════════════
@@ -145,6 +152,20 @@ pub enum MicroOp {
     /// rd = sext32(rs1 % rs2) (unsigned)
     Remuw { rd: u8, rs1: u8, rs2: u8 },
 
+    // ═══════════════════════════════════════════════════════════════════════
+    // Bit Manipulation (Zba/Zbb/Zbs)
+    // ═══════════════════════════════════════════════════════════════════════
+    /// rd = op(rs1, rs2), see [`BitOp::eval`]
+    Bit { op: BitOp, rd: u8, rs1: u8, rs2: u8 },
+
+    /// rd = op(rs1, shamt)
+    BitImm {
+        op: BitOp,
+        rd: u8,
+        rs1: u8,
+        shamt: u8,
+    },
+
     // ═══════════════════════════════════════════════════════════════════════
     // Upper Immediate Operations
     // ═══════════════════════════════════════════════════════════════════════
@@ -251,6 +272,49 @@ pub enum MicroOp {
         pc_offset: u16,
     },
 
+    // ═══════════════════════════════════════════════════════════════════════
+    // Floating Point (F/D)
+    // ═══════════════════════════════════════════════════════════════════════
+    /// f[rd] = NaN-box(mem[rs1 + imm][31:0])
+    Flw {
+        rd: u8,
+        rs1: u8,
+        imm: i64,
+        pc_offset: u16,
+    },
+
+    /// f[rd] = mem[rs1 + imm][63:0]
+    Fld {
+        rd: u8,
+        rs1: u8,
+        imm: i64,
+        pc_offset: u16,
+    },
+
+    /// mem[rs1 + imm][31:0] = f[rs2][31:0]
+    Fsw {
+        rs1: u8,
+        rs2: u8,
+        imm: i64,
+        pc_offset: u16,
+    },
+
+    /// mem[rs1 + imm][63:0] = f[rs2]
+    Fsd {
+        rs1: u8,
+        rs2: u8,
+        imm: i64,
+        pc_offset: u16,
+    },
+
+    /// Register-only F/D instruction (OP-FP or fused multiply-add),
+    /// executed from its raw encoding.
+    FpOp { insn: u32, pc_offset: u16 },
+
+    /// Vector instruction (see [`crate::cpu::vector`]), executed from its
+    /// raw encoding.
+    Vector { insn: u32, pc_offset: u16 },
+
     // ═══════════════════════════════════════════════════════════════════════
     // Control Flow (Block Terminators)
     // These end the basic block
@@ -398,6 +462,9 @@ pub enum MicroOp {
     /// FENCE - Memory barrier (no-op in our model)
     Fence,
 
+    /// FENCE.I - Instruction fetch barrier (flushes compiled code)
+    FenceI { pc_offset: u16 },
+
     // ═══════════════════════════════════════════════════════════════════════
     // Atomic Operations (A-Extension)
     // All atomics terminate block due to potential side effects
@@ -506,6 +573,25 @@ pub enum MicroOp {
     },
 }
 
+/// CSRs whose writes flush translation state or can make an interrupt
+/// pending. Blocks exit to the interpreter before writing them; every other
+/// CSR access executes inline.
+#[inline]
+pub fn csr_write_has_side_effects(csr: u16) -> bool {
+    matches!(
+        csr,
+        CSR_SATP
+            | CSR_MSTATUS
+            | CSR_SSTATUS
+            | CSR_MIE
+            | CSR_SIE
+            | CSR_MIP
+            | CSR_SIP
+            | CSR_STIMECMP
+            | CSR_MENVCFG
+    ) || is_pmp_csr(csr)
+}
+
 impl MicroOp {
     /// Returns true if this op terminates the basic block.
     #[inline]
@@ -524,7 +610,9 @@ impl MicroOp {
                 | MicroOp::Ebreak { .. }
                 | MicroOp::Mret { .. }
                 | MicroOp::Sret { .. }
+                | MicroOp::Wfi { .. }
                 | MicroOp::SfenceVma { .. }
+                | MicroOp::FenceI { .. }
                 | MicroOp::LrW { .. }
                 | MicroOp::LrD { .. }
                 | MicroOp::ScW { .. }
@@ -538,13 +626,28 @@ impl MicroOp {
                 | MicroOp::AmoMax { .. }
                 | MicroOp::AmoMinu { .. }
                 | MicroOp::AmoMaxu { .. }
-                | MicroOp::Csrrw { .. }
-                | MicroOp::Csrrs { .. }
-                | MicroOp::Csrrc { .. }
-                | MicroOp::Csrrwi { .. }
-                | MicroOp::Csrrsi { .. }
-                | MicroOp::Csrrci { .. }
-        )
+        ) || self.writes_side_effect_csr()
+    }
+
+    /// Returns true for CSR ops that write a CSR whose update must be
+    /// handled by the interpreter (see [`csr_write_has_side_effects`]).
+    ///
+    /// Whether a CSR op writes is fixed by its encoding, so this is known
+    /// when the block is compiled.
+    #[inline]
+    pub fn writes_side_effect_csr(&self) -> bool {
+        match *self {
+            MicroOp::Csrrw { csr, .. } | MicroOp::Csrrwi { csr, .. } => {
+                csr_write_has_side_effects(csr)
+            }
+            MicroOp::Csrrs { rs1, csr, .. } | MicroOp::Csrrc { rs1, csr, .. } => {
+                rs1 != 0 && csr_write_has_side_effects(csr)
+            }
+            MicroOp::Csrrsi { zimm, csr, .. } | MicroOp::Csrrci { zimm, csr, .. } => {
+                zimm != 0 && csr_write_has_side_effects(csr)
+            }
+            _ => false,
+        }
     }
 
     /// Returns true if this op may cause a trap.
@@ -563,6 +666,12 @@ impl MicroOp {
                 | MicroOp::Sh { .. }
                 | MicroOp::Sw { .. }
                 | MicroOp::Sd { .. }
+                | MicroOp::Flw { .. }
+                | MicroOp::Fld { .. }
+                | MicroOp::Fsw { .. }
+                | MicroOp::Fsd { .. }
+                | MicroOp::FpOp { .. }
+                | MicroOp::Vector { .. }
                 | MicroOp::Ecall { .. }
                 | MicroOp::Ebreak { .. }
                 | MicroOp::Csrrw { .. }
@@ -590,6 +699,12 @@ impl MicroOp {
             | MicroOp::Sh { pc_offset, .. }
             | MicroOp::Sw { pc_offset, .. }
             | MicroOp::Sd { pc_offset, .. }
+            | MicroOp::Flw { pc_offset, .. }
+            | MicroOp::Fld { pc_offset, .. }
+            | MicroOp::Fsw { pc_offset, .. }
+            | MicroOp::Fsd { pc_offset, .. }
+            | MicroOp::FpOp { pc_offset, .. }
+            | MicroOp::Vector { pc_offset, .. }
             | MicroOp::Jal { pc_offset, .. }
             | MicroOp::Jalr { pc_offset, .. }
             | MicroOp::Beq { pc_offset, .. }
@@ -610,6 +725,7 @@ impl MicroOp {
             | MicroOp::Sret { pc_offset }
             | MicroOp::Wfi { pc_offset }
             | MicroOp::SfenceVma { pc_offset }
+            | MicroOp::FenceI { pc_offset }
             | MicroOp::LrW { pc_offset, .. }
             | MicroOp::LrD { pc_offset, .. }
             | MicroOp::ScW { pc_offset, .. }
@@ -663,6 +779,7 @@ mod tests {
             .is_terminator()
         );
         assert!(MicroOp::Ecall { pc_offset: 0 }.is_terminator());
+        assert!(MicroOp::FenceI { pc_offset: 0 }.is_terminator());
         assert!(
             !MicroOp::Addi {
                 rd: 1,
@@ -681,6 +798,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_csr_ops_terminate_only_for_side_effect_writes() {
+        let read_satp = MicroOp::Csrrs {
+            rd: 1,
+            rs1: 0,
+            csr: CSR_SATP,
+            pc_offset: 0,
+        };
+        let write_satp = MicroOp::Csrrw {
+            rd: 0,
+            rs1: 1,
+            csr: CSR_SATP,
+            pc_offset: 0,
+        };
+        let write_mscratch = MicroOp::Csrrwi {
+            rd: 0,
+            zimm: 3,
+            csr: 0x340,
+            pc_offset: 0,
+        };
+        let clear_mie_noop = MicroOp::Csrrci {
+            rd: 1,
+            zimm: 0,
+            csr: CSR_MIE,
+            pc_offset: 0,
+        };
+        assert!(!read_satp.is_terminator());
+        assert!(write_satp.is_terminator());
+        assert!(!write_mscratch.is_terminator());
+        assert!(!clear_mie_noop.is_terminator());
+        assert!(write_mscratch.may_trap());
+    }
+
     #[test]
     fn test_may_trap() {
         assert!(
@@ -1,4 +1,10 @@
+pub mod bitmanip;
 pub mod block;
 pub mod cache;
 pub mod decoder;
+pub mod disasm;
+#[cfg(all(feature = "jit-native", not(target_arch = "wasm32")))]
+pub mod jit;
 pub mod microop;
+pub mod timing;
+pub mod trace;
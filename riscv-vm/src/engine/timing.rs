@@ -0,0 +1,245 @@
+//! Instruction timing model.
+//!
+//! Every executed instruction costs a number of CPU cycles, loosely modelled
+//! on a simple in-order core: single-cycle ALU ops, a few cycles for memory
+//! and multiplies, and long divides. The interpreter and the block engine
+//! (including native JIT blocks) charge the same costs, and the CLINT turns
+//! cycles into `mtime` ticks at the configured CPU frequency (see
+//! [`crate::devices::clint::Clint::advance_cycles`]), so guest time does not
+//! depend on which engine ran the code.
+
+use super::decoder::Op;
+use super::microop::MicroOp;
+
+/// Integer ALU, `lui`/`auipc` and not-taken control flow.
+pub const ALU_CYCLES: u32 = 1;
+/// Integer multiply.
+pub const MUL_CYCLES: u32 = 3;
+/// Integer divide and remainder.
+pub const DIV_CYCLES: u32 = 20;
+/// Load from memory (a cache hit).
+pub const LOAD_CYCLES: u32 = 2;
+/// Store to memory.
+pub const STORE_CYCLES: u32 = 1;
+/// Branch or jump.
+pub const BRANCH_CYCLES: u32 = 2;
+/// Floating-point arithmetic, conversions and moves.
+pub const FP_CYCLES: u32 = 4;
+/// Floating-point divide and square root.
+pub const FP_DIV_CYCLES: u32 = 20;
+/// CSR access.
+pub const CSR_CYCLES: u32 = 4;
+/// LR/SC and AMOs.
+pub const ATOMIC_CYCLES: u32 = 8;
+/// Traps and returns, fences and TLB flushes.
+pub const SYSTEM_CYCLES: u32 = 10;
+
+/// Cost of an OP-FP instruction from its `funct7` field.
+fn op_fp_cost(funct7: u32) -> u32 {
+    // fdiv.* and fsqrt.*
+    match funct7 >> 2 {
+        0x03 | 0x0b => FP_DIV_CYCLES,
+        _ => FP_CYCLES,
+    }
+}
+
+/// Cost of a register-only F/D encoding (OP-FP or fused multiply-add).
+fn fp_cost(insn: u32) -> u32 {
+    const OP_FP: u32 = 0b1010011;
+    if insn & 0x7f == OP_FP {
+        op_fp_cost(insn >> 25)
+    } else {
+        FP_CYCLES
+    }
+}
+
+/// Cost of a vector encoding: one memory access or ALU op, whatever `vl`.
+fn vector_cost(insn: u32) -> u32 {
+    match insn & 0x7f {
+        0b0000111 => LOAD_CYCLES,
+        0b0100111 => STORE_CYCLES,
+        _ => ALU_CYCLES,
+    }
+}
+
+impl MicroOp {
+    /// Cycles charged for executing this op.
+    #[inline]
+    pub fn cost(&self) -> u32 {
+        match *self {
+            MicroOp::Mul { .. }
+            | MicroOp::Mulh { .. }
+            | MicroOp::Mulhsu { .. }
+            | MicroOp::Mulhu { .. }
+            | MicroOp::Mulw { .. } => MUL_CYCLES,
+            MicroOp::Div { .. }
+            | MicroOp::Divu { .. }
+            | MicroOp::Rem { .. }
+            | MicroOp::Remu { .. }
+            | MicroOp::Divw { .. }
+            | MicroOp::Divuw { .. }
+            | MicroOp::Remw { .. }
+            | MicroOp::Remuw { .. } => DIV_CYCLES,
+            MicroOp::Lb { .. }
+            | MicroOp::Lbu { .. }
+            | MicroOp::Lh { .. }
+            | MicroOp::Lhu { .. }
+            | MicroOp::Lw { .. }
+            | MicroOp::Lwu { .. }
+            | MicroOp::Ld { .. }
+            | MicroOp::Flw { .. }
+            | MicroOp::Fld { .. } => LOAD_CYCLES,
+            MicroOp::Sb { .. }
+            | MicroOp::Sh { .. }
+            | MicroOp::Sw { .. }
+            | MicroOp::Sd { .. }
+            | MicroOp::Fsw { .. }
+            | MicroOp::Fsd { .. } => STORE_CYCLES,
+            MicroOp::FpOp { insn, .. } => fp_cost(insn),
+            MicroOp::Vector { insn, .. } => vector_cost(insn),
+            MicroOp::Jal { .. }
+            | MicroOp::Jalr { .. }
+            | MicroOp::Beq { .. }
+            | MicroOp::Bne { .. }
+            | MicroOp::Blt { .. }
+            | MicroOp::Bge { .. }
+            | MicroOp::Bltu { .. }
+            | MicroOp::Bgeu { .. } => BRANCH_CYCLES,
+            MicroOp::Csrrw { .. }
+            | MicroOp::Csrrs { .. }
+            | MicroOp::Csrrc { .. }
+            | MicroOp::Csrrwi { .. }
+            | MicroOp::Csrrsi { .. }
+            | MicroOp::Csrrci { .. } => CSR_CYCLES,
+            MicroOp::LrW { .. }
+            | MicroOp::LrD { .. }
+            | MicroOp::ScW { .. }
+            | MicroOp::ScD { .. }
+            | MicroOp::AmoSwap { .. }
+            | MicroOp::AmoAdd { .. }
+            | MicroOp::AmoXor { .. }
+            | MicroOp::AmoAnd { .. }
+            | MicroOp::AmoOr { .. }
+            | MicroOp::AmoMin { .. }
+            | MicroOp::AmoMax { .. }
+            | MicroOp::AmoMinu { .. }
+            | MicroOp::AmoMaxu { .. } => ATOMIC_CYCLES,
+            MicroOp::Ecall { .. }
+            | MicroOp::Ebreak { .. }
+            | MicroOp::Mret { .. }
+            | MicroOp::Sret { .. }
+            | MicroOp::Wfi { .. }
+            | MicroOp::SfenceVma { .. }
+            | MicroOp::Fence
+            | MicroOp::FenceI { .. } => SYSTEM_CYCLES,
+            _ => ALU_CYCLES,
+        }
+    }
+}
+
+impl Op {
+    /// Cycles charged for executing this instruction in the interpreter,
+    /// matching [`MicroOp::cost`] for the op it compiles to.
+    #[inline]
+    pub fn cost(&self) -> u32 {
+        match *self {
+            // funct7 1 is the M extension: mul* below funct3 4, div/rem above
+            Op::Op {
+                funct3, funct7: 1, ..
+            }
+            | Op::Op32 {
+                funct3, funct7: 1, ..
+            } => {
+                if funct3 < 4 {
+                    MUL_CYCLES
+                } else {
+                    DIV_CYCLES
+                }
+            }
+            Op::Load { .. } | Op::LoadFp { .. } => LOAD_CYCLES,
+            Op::Store { .. } | Op::StoreFp { .. } => STORE_CYCLES,
+            Op::OpFp { funct7, .. } => op_fp_cost(funct7),
+            Op::FusedMulAdd { .. } => FP_CYCLES,
+            Op::Vector { insn } => vector_cost(insn),
+            Op::Jal { .. } | Op::Jalr { .. } | Op::Branch { .. } => BRANCH_CYCLES,
+            // CSR accesses; funct3 0 is ecall/ebreak/xret/wfi/sfence.vma
+            Op::System { funct3: 0, .. } => SYSTEM_CYCLES,
+            Op::System { .. } => CSR_CYCLES,
+            Op::Amo { .. } => ATOMIC_CYCLES,
+            Op::Fence | Op::FenceI => SYSTEM_CYCLES,
+            _ => ALU_CYCLES,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::decoder::decode;
+
+    #[test]
+    fn test_interpreter_and_block_costs_agree() {
+        // (encoding, micro-op it compiles to)
+        let cases = [
+            // addi x1, x1, 1
+            (
+                0x0010_8093,
+                MicroOp::Addi {
+                    rd: 1,
+                    rs1: 1,
+                    imm: 1,
+                },
+            ),
+            // mul x1, x2, x3
+            (
+                0x0231_00b3,
+                MicroOp::Mul {
+                    rd: 1,
+                    rs1: 2,
+                    rs2: 3,
+                },
+            ),
+            // divu x1, x2, x3
+            (
+                0x0231_50b3,
+                MicroOp::Divu {
+                    rd: 1,
+                    rs1: 2,
+                    rs2: 3,
+                },
+            ),
+            // ld x1, 0(x2)
+            (
+                0x0001_3083,
+                MicroOp::Ld {
+                    rd: 1,
+                    rs1: 2,
+                    imm: 0,
+                    pc_offset: 0,
+                },
+            ),
+            // fdiv.d f1, f2, f3
+            (
+                0x1a31_70d3,
+                MicroOp::FpOp {
+                    insn: 0x1a31_70d3,
+                    pc_offset: 0,
+                },
+            ),
+        ];
+        for (insn, micro) in cases {
+            let op = decode(insn).unwrap();
+            assert_eq!(op.cost(), micro.cost(), "{:?}", op);
+        }
+        assert_eq!(
+            MicroOp::Divu {
+                rd: 1,
+                rs1: 2,
+                rs2: 3
+            }
+            .cost(),
+            DIV_CYCLES
+        );
+        assert_eq!(fp_cost(0x0231_70d3), FP_CYCLES); // fadd.d
+    }
+}
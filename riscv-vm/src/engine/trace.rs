@@ -0,0 +1,348 @@
+//! Trace Formation (Tier 2) for the JIT-less Superblock Engine.
+//!
+//! Tier 1 dispatches one basic block per `step()`, paying a cache lookup and
+//! result handling on every block boundary. Tier 2 records block-to-block
+//! transitions and, once a chain of blocks keeps following the same
+//! successors, stitches those blocks into a [`Trace`]. A trace runs as a
+//! single unit: each block's exit is checked against the expected successor
+//! (an internal branch) and only leaves the trace on a side exit. Traces that
+//! lead back to their head run as loops, bounded by [`TRACE_LOOP_LIMIT`] so
+//! interrupts are still polled regularly.
+
+use super::block::Block;
+use super::cache::{BlockCache, PcMap};
+
+/// Consecutive identical transitions before an edge counts as hot.
+pub const HOT_EDGE_THRESHOLD: u32 = 32;
+
+/// Maximum number of blocks stitched into one trace.
+pub const MAX_TRACE_BLOCKS: usize = 8;
+
+/// Maximum loop iterations of a trace per dispatch.
+pub const TRACE_LOOP_LIMIT: u32 = 16;
+
+/// Maximum number of traces kept before the buffer is reset.
+pub const TRACE_CACHE_SIZE: usize = 1024;
+
+/// Last observed successor of a block.
+#[derive(Clone, Copy)]
+struct Edge {
+    target: u64,
+    count: u32,
+}
+
+/// A chain of hot blocks executed as one unit.
+pub struct Trace {
+    /// Blocks in execution order; `blocks[0]` is the trace head.
+    pub blocks: Vec<Block>,
+    /// The last block's expected successor is the head.
+    pub is_loop: bool,
+    /// Generation counter (for cache invalidation).
+    pub generation: u32,
+}
+
+impl Trace {
+    /// Start PC of the trace head.
+    #[inline]
+    pub fn head(&self) -> u64 {
+        self.blocks[0].start_pc
+    }
+
+    /// Whether any block of the trace overlaps `[start_pa, end_pa)`.
+    fn overlaps(&self, start_pa: u64, end_pa: u64) -> bool {
+        self.blocks.iter().any(|block| {
+            let block_end = block.start_pa + block.byte_len as u64;
+            block.start_pa < end_pa && block_end > start_pa
+        })
+    }
+}
+
+/// Records block transitions and holds the traces formed from them.
+pub struct TraceBuffer {
+    /// Block start PC → last observed successor.
+    edges: PcMap<Edge>,
+    /// Trace head PC → trace.
+    traces: PcMap<Box<Trace>>,
+    /// Current generation (incremented on flush).
+    pub generation: u32,
+    /// A trace is out for execution (between `take` and `put_back`).
+    taken: bool,
+    /// Ranges invalidated while a trace was out.
+    dirty: Vec<(u64, u64)>,
+    /// Statistics: traces formed.
+    pub formed: u64,
+    /// Statistics: trace dispatches.
+    pub executions: u64,
+    /// Statistics: dispatches that left through a side exit.
+    pub side_exits: u64,
+}
+
+impl TraceBuffer {
+    /// Create an empty trace buffer.
+    pub fn new() -> Self {
+        Self {
+            edges: PcMap::default(),
+            traces: PcMap::default(),
+            generation: 0,
+            taken: false,
+            dirty: Vec::new(),
+            formed: 0,
+            executions: 0,
+            side_exits: 0,
+        }
+    }
+
+    /// Record that the block at `from` continued to `to`.
+    ///
+    /// When the edge has just become hot, returns the PC the caller should
+    /// try to [`form`](Self::form) a trace at: the target of a backward edge
+    /// (a loop header), otherwise the source block.
+    pub fn record(&mut self, from: u64, to: u64) -> Option<u64> {
+        let edge = self.edges.entry(from).or_insert(Edge {
+            target: to,
+            count: 0,
+        });
+        if edge.target != to {
+            *edge = Edge {
+                target: to,
+                count: 0,
+            };
+        }
+        edge.count = edge.count.saturating_add(1);
+        if edge.count != HOT_EDGE_THRESHOLD {
+            return None;
+        }
+        Some(if to <= from { to } else { from })
+    }
+
+    /// The hot successor of `pc`, if any.
+    fn hot_successor(&self, pc: u64) -> Option<u64> {
+        self.edges
+            .get(&pc)
+            .filter(|edge| edge.count >= HOT_EDGE_THRESHOLD)
+            .map(|edge| edge.target)
+    }
+
+    /// Follow hot edges from `head` and install the resulting trace.
+    ///
+    /// Blocks are copied out of `cache`, so the chain stops at the first
+    /// block that is not compiled. Single blocks only form a trace when they
+    /// loop onto themselves. Returns true if a trace was installed.
+    pub fn form(&mut self, head: u64, cache: &BlockCache) -> bool {
+        let Some(first) = cache.peek(head) else {
+            return false;
+        };
+        let mut blocks = vec![first.clone()];
+        let mut is_loop = false;
+        let mut pc = head;
+
+        while let Some(next) = self.hot_successor(pc) {
+            if next == head {
+                is_loop = true;
+                break;
+            }
+            if blocks.len() >= MAX_TRACE_BLOCKS || blocks.iter().any(|b| b.start_pc == next) {
+                break;
+            }
+            let Some(block) = cache.peek(next) else {
+                break;
+            };
+            blocks.push(block.clone());
+            pc = next;
+        }
+
+        if blocks.len() < 2 && !is_loop {
+            return false;
+        }
+
+        if self.traces.len() >= TRACE_CACHE_SIZE {
+            self.traces.clear();
+        }
+        self.traces.insert(
+            head,
+            Box::new(Trace {
+                blocks,
+                is_loop,
+                generation: self.generation,
+            }),
+        );
+        self.formed += 1;
+        true
+    }
+
+    /// Remove the valid trace headed at `pc` for execution.
+    ///
+    /// The trace must be handed back with [`put_back`](Self::put_back).
+    #[inline]
+    pub fn take(&mut self, pc: u64) -> Option<Box<Trace>> {
+        if self.traces.is_empty() {
+            return None;
+        }
+        let trace = self.traces.remove(&pc)?;
+        if trace.generation != self.generation {
+            return None;
+        }
+        self.taken = true;
+        Some(trace)
+    }
+
+    /// Return a trace taken with [`take`](Self::take).
+    ///
+    /// Traces invalidated while they were out are dropped.
+    #[inline]
+    pub fn put_back(&mut self, trace: Box<Trace>) {
+        self.taken = false;
+        let dirty = self
+            .dirty
+            .iter()
+            .any(|&(start, end)| trace.overlaps(start, end));
+        self.dirty.clear();
+        if trace.generation == self.generation && !dirty {
+            self.traces.insert(trace.head(), trace);
+        }
+    }
+
+    /// Invalidate all traces and edge profiles.
+    pub fn flush(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.traces.clear();
+        self.edges.clear();
+    }
+
+    /// Drop traces containing code in a specific physical address range.
+    pub fn invalidate_range(&mut self, start_pa: u64, end_pa: u64) {
+        self.traces
+            .retain(|_, trace| !trace.overlaps(start_pa, end_pa));
+        if self.taken {
+            self.dirty.push((start_pa, end_pa));
+        }
+    }
+
+    /// Number of installed traces.
+    pub fn len(&self) -> usize {
+        self.traces.len()
+    }
+
+    /// Whether no traces are installed.
+    pub fn is_empty(&self) -> bool {
+        self.traces.is_empty()
+    }
+}
+
+impl Default for TraceBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::microop::MicroOp;
+
+    fn cache_with(pcs: &[u64]) -> BlockCache {
+        let mut cache = BlockCache::new();
+        for &pc in pcs {
+            let mut block = Block::new(pc, pc, cache.generation);
+            block.push(
+                MicroOp::Addi {
+                    rd: 1,
+                    rs1: 1,
+                    imm: 1,
+                },
+                4,
+            );
+            cache.insert(block);
+        }
+        cache
+    }
+
+    fn heat(buffer: &mut TraceBuffer, from: u64, to: u64) -> Option<u64> {
+        let mut head = None;
+        for _ in 0..HOT_EDGE_THRESHOLD {
+            head = buffer.record(from, to);
+        }
+        head
+    }
+
+    #[test]
+    fn test_edge_becomes_hot_once() {
+        let mut buffer = TraceBuffer::new();
+        assert_eq!(heat(&mut buffer, 0x100, 0x200), Some(0x100));
+        assert_eq!(buffer.record(0x100, 0x200), None);
+
+        // Backward edges nominate the loop header
+        assert_eq!(heat(&mut buffer, 0x300, 0x100), Some(0x100));
+
+        // A different successor resets the count
+        assert_eq!(buffer.record(0x100, 0x300), None);
+        assert_eq!(buffer.hot_successor(0x100), None);
+    }
+
+    #[test]
+    fn test_form_loop_trace() {
+        let cache = cache_with(&[0x100, 0x200, 0x300]);
+        let mut buffer = TraceBuffer::new();
+        heat(&mut buffer, 0x100, 0x200);
+        heat(&mut buffer, 0x200, 0x300);
+        heat(&mut buffer, 0x300, 0x100);
+
+        assert!(buffer.form(0x100, &cache));
+        let trace = buffer.take(0x100).unwrap();
+        let pcs: Vec<u64> = trace.blocks.iter().map(|b| b.start_pc).collect();
+        assert_eq!(pcs, vec![0x100, 0x200, 0x300]);
+        assert!(trace.is_loop);
+        buffer.put_back(trace);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn test_form_requires_compiled_chain() {
+        let cache = cache_with(&[0x100]);
+        let mut buffer = TraceBuffer::new();
+        heat(&mut buffer, 0x100, 0x200);
+
+        // 0x200 is not compiled, so only a lone non-looping block remains
+        assert!(!buffer.form(0x100, &cache));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_flush_and_range_invalidation() {
+        let cache = cache_with(&[0x100, 0x200]);
+        let mut buffer = TraceBuffer::new();
+        heat(&mut buffer, 0x100, 0x200);
+        assert!(buffer.form(0x100, &cache));
+
+        buffer.invalidate_range(0x200, 0x204);
+        assert!(buffer.is_empty());
+
+        assert!(buffer.form(0x100, &cache));
+        let trace = buffer.take(0x100).unwrap();
+        buffer.flush();
+        buffer.put_back(trace);
+        assert!(buffer.is_empty());
+        assert!(buffer.take(0x100).is_none());
+    }
+
+    #[test]
+    fn test_range_invalidation_while_taken() {
+        let cache = cache_with(&[0x100, 0x200]);
+        let mut buffer = TraceBuffer::new();
+        heat(&mut buffer, 0x100, 0x200);
+        assert!(buffer.form(0x100, &cache));
+
+        // A store from inside the running trace patches its second block
+        let trace = buffer.take(0x100).unwrap();
+        buffer.invalidate_range(0x200, 0x204);
+        buffer.put_back(trace);
+        assert!(buffer.is_empty());
+
+        // Unrelated ranges leave the trace alone
+        assert!(buffer.form(0x100, &cache));
+        let trace = buffer.take(0x100).unwrap();
+        buffer.invalidate_range(0x1000, 0x2000);
+        buffer.put_back(trace);
+        assert_eq!(buffer.len(), 1);
+    }
+}
@@ -0,0 +1,408 @@
+//! C ABI for embedding the VM in non-Rust hosts.
+//!
+//! Built into the crate's cdylib with the `ffi` feature and declared in
+//! `include/riscv_vm.h`, so C, C++, Python (ctypes) or Go (cgo) programs can
+//! create a single-hart VM, step it, and inspect or snapshot its state.
+//!
+//! Functions returning `int` return 0 on success and -1 on failure; the
+//! reason is then available from `vm_last_error` on the same thread. A
+//! `RiscvVm` must not be used from two threads at once.
+
+use crate::Trap;
+use crate::bus::{BusConfig, DRAM_BASE};
+use crate::devices::virtio::VirtioBlock;
+use crate::engine::decoder::Register;
+use crate::snapshot::Snapshot;
+use crate::vm::emulator::Emulator;
+use std::cell::RefCell;
+use std::ffi::{CString, c_char, c_int};
+use std::ptr;
+use std::slice;
+
+/// Instructions between device polls, as in the other embeddings.
+const POLL_INTERVAL: u32 = 100;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Record `message` for `vm_last_error` and return the failure status.
+fn fail(message: impl Into<String>) -> c_int {
+    let message = CString::new(message.into().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    -1
+}
+
+fn status(result: Result<(), String>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(message) => fail(message),
+    }
+}
+
+/// Borrow `len` bytes at `data`; a null pointer is only valid for zero bytes.
+///
+/// # Safety
+/// A non-null `data` must point to `len` readable bytes.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// A VM created by `vm_create`.
+pub struct RiscvVm {
+    emu: Emulator,
+    halted: bool,
+    halt_code: u64,
+    poll_counter: u32,
+}
+
+impl RiscvVm {
+    fn new(kernel: &[u8], memory_mib: u32) -> Result<Self, String> {
+        let config = match memory_mib {
+            0 => BusConfig::default(),
+            mib => BusConfig::with_dram(DRAM_BASE, (mib as usize) << 20),
+        };
+        config.validate()?;
+        let mut emu = Emulator::with_config(config);
+        emu.bus
+            .boot_rom
+            .set_fdt(crate::devices::fdt::generate(&config, 1, ""))?;
+        emu.load_kernel(kernel)?;
+        Ok(Self {
+            emu,
+            halted: false,
+            halt_code: 0,
+            poll_counter: 0,
+        })
+    }
+
+    /// Execute one instruction; false once the VM has halted.
+    fn step(&mut self) -> bool {
+        if self.halted {
+            return false;
+        }
+
+        self.poll_counter = self.poll_counter.wrapping_add(1);
+        if self.poll_counter.is_multiple_of(POLL_INTERVAL) {
+            self.emu.bus.poll_virtio();
+        }
+
+        match self.emu.cpu.step(&self.emu.bus) {
+            Ok(()) => {}
+            Err(Trap::RequestedTrap(code)) => {
+                self.halted = true;
+                self.halt_code = code;
+            }
+            Err(Trap::Fatal(msg)) => {
+                log::error!("[VM] Fatal error: {} at PC=0x{:x}", msg, self.emu.cpu.pc);
+                self.halted = true;
+                self.halt_code = 0xDEAD;
+            }
+            Err(_trap) => {
+                // Architectural traps handled by CPU
+            }
+        }
+        !self.halted
+    }
+}
+
+/// Message describing the last failure on this thread, or NULL. Valid
+/// until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn vm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Create a VM and boot `kernel` (ELF or raw binary) with `memory_mib` MiB
+/// of DRAM (0 for the default 512 MiB). Returns NULL on failure.
+///
+/// # Safety
+/// `kernel` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_create(kernel: *const u8, len: usize, memory_mib: u32) -> *mut RiscvVm {
+    let kernel = unsafe { bytes(kernel, len) };
+    match RiscvVm::new(kernel, memory_mib) {
+        Ok(vm) => Box::into_raw(Box::new(vm)),
+        Err(message) => {
+            fail(message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Destroy a VM. NULL is ignored.
+///
+/// # Safety
+/// `vm` must come from `vm_create` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_destroy(vm: *mut RiscvVm) {
+    if !vm.is_null() {
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
+
+/// Attach a copy of `disk` as a VirtIO block device. Call before running.
+///
+/// # Safety
+/// `vm` must be a live VM and `disk` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_load_disk(vm: *mut RiscvVm, disk: *const u8, len: usize) {
+    let vm = unsafe { &mut *vm };
+    let disk = unsafe { bytes(disk, len) };
+    vm.emu
+        .bus
+        .virtio_devices
+        .push(Box::new(VirtioBlock::new(disk.to_vec())));
+}
+
+/// Execute one instruction. Returns 1 while running and 0 once halted.
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_step(vm: *mut RiscvVm) -> c_int {
+    let vm = unsafe { &mut *vm };
+    vm.step() as c_int
+}
+
+/// Execute up to `max_steps` instructions (0 for no limit), stopping early
+/// if the VM halts. Returns the number executed.
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_run(vm: *mut RiscvVm, max_steps: u64) -> u64 {
+    let vm = unsafe { &mut *vm };
+    let limit = if max_steps == 0 { u64::MAX } else { max_steps };
+    let mut steps = 0;
+    while steps < limit && vm.step() {
+        steps += 1;
+    }
+    steps
+}
+
+/// Whether the guest has requested shutdown (or hit a fatal error).
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_is_halted(vm: *const RiscvVm) -> c_int {
+    unsafe { &*vm }.halted as c_int
+}
+
+/// Halt code once halted; 0x5555 is a clean shutdown.
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_halt_code(vm: *const RiscvVm) -> u64 {
+    unsafe { &*vm }.halt_code
+}
+
+/// Program counter of the hart.
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_get_pc(vm: *const RiscvVm) -> u64 {
+    unsafe { &*vm }.emu.cpu.pc
+}
+
+/// Read integer register `x<index>` into `*value`.
+///
+/// # Safety
+/// `vm` must be a live VM and `value` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_read_reg(vm: *const RiscvVm, index: u32, value: *mut u64) -> c_int {
+    if index >= 32 {
+        return fail(format!("no register x{}", index));
+    }
+    let vm = unsafe { &*vm };
+    unsafe { *value = vm.emu.cpu.read_reg(Register::from_u32(index)) };
+    0
+}
+
+/// Write integer register `x<index>` (writes to x0 are ignored).
+///
+/// # Safety
+/// `vm` must be a live VM.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_write_reg(vm: *mut RiscvVm, index: u32, value: u64) -> c_int {
+    if index >= 32 {
+        return fail(format!("no register x{}", index));
+    }
+    let vm = unsafe { &mut *vm };
+    vm.emu.cpu.write_reg(Register::from_u32(index), value);
+    0
+}
+
+/// Copy `len` bytes of physical memory at `addr` into `buf`. Only DRAM can
+/// be read.
+///
+/// # Safety
+/// `vm` must be a live VM and `buf` must point to `len` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_read_mem(
+    vm: *const RiscvVm,
+    addr: u64,
+    buf: *mut u8,
+    len: usize,
+) -> c_int {
+    let vm = unsafe { &*vm };
+    status(vm.emu.read_memory(addr, len).map(|data| {
+        if len > 0 {
+            unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buf, len) };
+        }
+    }))
+}
+
+/// Write `len` bytes from `buf` to physical memory at `addr`. Only DRAM can
+/// be written.
+///
+/// # Safety
+/// `vm` must be a live VM and `buf` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_write_mem(
+    vm: *mut RiscvVm,
+    addr: u64,
+    buf: *const u8,
+    len: usize,
+) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let data = unsafe { bytes(buf, len) };
+    status(vm.emu.write_memory(addr, data))
+}
+
+/// Queue `len` bytes of UART input.
+///
+/// # Safety
+/// `vm` must be a live VM and `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_input(vm: *mut RiscvVm, data: *const u8, len: usize) {
+    let vm = unsafe { &*vm };
+    vm.emu
+        .bus
+        .uart
+        .push_input_bytes(unsafe { bytes(data, len) });
+}
+
+/// Move up to `cap` bytes of pending UART output into `buf`. Returns the
+/// number of bytes written.
+///
+/// # Safety
+/// `vm` must be a live VM and `buf` must point to `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_read_output(vm: *mut RiscvVm, buf: *mut u8, cap: usize) -> usize {
+    let vm = unsafe { &*vm };
+    let mut count = 0;
+    while count < cap {
+        let Some(byte) = vm.emu.bus.uart.pop_output() else {
+            break;
+        };
+        unsafe { *buf.add(count) = byte };
+        count += 1;
+    }
+    count
+}
+
+/// Serialize the VM state (hart, CLINT, PLIC, UART and DRAM). On success
+/// `*data` and `*len` describe a buffer to release with `vm_free_snapshot`.
+///
+/// # Safety
+/// `vm` must be a live VM; `data` and `len` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_snapshot(
+    vm: *const RiscvVm,
+    data: *mut *mut u8,
+    len: *mut usize,
+) -> c_int {
+    let vm = unsafe { &*vm };
+    match vm.emu.snapshot().to_bytes() {
+        Ok(bytes) => {
+            let bytes = Box::into_raw(bytes.into_boxed_slice());
+            unsafe {
+                *len = bytes.len();
+                *data = bytes.cast();
+            }
+            0
+        }
+        Err(message) => fail(message),
+    }
+}
+
+/// Release a buffer returned by `vm_snapshot`. NULL is ignored.
+///
+/// # Safety
+/// `data` and `len` must be exactly as returned by `vm_snapshot`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_free_snapshot(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)) });
+    }
+}
+
+/// Restore state saved with `vm_snapshot`. The DRAM size must match.
+///
+/// # Safety
+/// `vm` must be a live VM and `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vm_restore(vm: *mut RiscvVm, data: *const u8, len: usize) -> c_int {
+    let vm = unsafe { &mut *vm };
+    let result = Snapshot::from_bytes(unsafe { bytes(data, len) })
+        .and_then(|snapshot| vm.emu.apply_snapshot(&snapshot));
+    if result.is_ok() {
+        vm.halted = false;
+        vm.halt_code = 0;
+    }
+    status(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_c_api_round_trip() {
+        // addi x1, x0, 42; then a loop (jal x0, 0)
+        let mut kernel = 0x02a0_0093u32.to_le_bytes().to_vec();
+        kernel.extend(0x0000_006fu32.to_le_bytes());
+
+        unsafe {
+            let vm = vm_create(kernel.as_ptr(), kernel.len(), 16);
+            assert!(!vm.is_null());
+            // Through the boot ROM to the kernel and past its first instruction
+            let steps = vm_run(vm, 1000);
+            assert_eq!(steps, 1000);
+            let mut value = 0;
+            assert_eq!(vm_read_reg(vm, 1, &mut value), 0);
+            assert_eq!(value, 42);
+
+            let mut snapshot = ptr::null_mut();
+            let mut len = 0;
+            assert_eq!(vm_snapshot(vm, &mut snapshot, &mut len), 0);
+            assert_eq!(vm_write_reg(vm, 1, 7), 0);
+            assert_eq!(vm_write_mem(vm, DRAM_BASE + 0x100, [9u8; 4].as_ptr(), 4), 0);
+            assert_eq!(vm_restore(vm, snapshot, len), 0);
+            vm_free_snapshot(snapshot, len);
+
+            assert_eq!(vm_read_reg(vm, 1, &mut value), 0);
+            assert_eq!(value, 42);
+            let mut buf = [0xffu8; 4];
+            assert_eq!(vm_read_mem(vm, DRAM_BASE + 0x100, buf.as_mut_ptr(), 4), 0);
+            assert_eq!(buf, [0; 4]);
+
+            assert_eq!(vm_read_mem(vm, 0, buf.as_mut_ptr(), 4), -1);
+            let error = CStr::from_ptr(vm_last_error()).to_str().unwrap();
+            assert!(error.contains("not in DRAM"), "{}", error);
+            vm_destroy(vm);
+        }
+    }
+}
@@ -0,0 +1,841 @@
+//! Instruction fuzzing.
+//!
+//! Runs short instruction sequences on a fresh hart under every execution
+//! engine this build has (see [`Engine`]) and checks that none of them
+//! panics and that the block engine and the JIT end in the same
+//! architectural state as the interpreter. The JIT also runs with
+//! verification on, so a compiled block that disagrees with the interpreter
+//! is caught even when later code hides the difference.
+//!
+//! [`check`] takes raw fuzzer bytes, which makes the cargo-fuzz target in
+//! `fuzz/` a one-liner around it. The first byte picks how the rest is read:
+//! with its low bit set the instruction words are shaped into mostly valid
+//! encodings (loads and stores aimed at a data page, short branches, a few
+//! harmless CSRs), otherwise they run as they are. The next 8 bytes seed the
+//! registers and the data page, and the rest are little-endian instruction
+//! words, at most [`MAX_WORDS`] of them. The program is followed by `j .`,
+//! which is also where traps go, and runs in M-mode with the FPU and vector
+//! unit on.
+//!
+//! [`minimize`] cuts a failing input down to the instructions that matter;
+//! the `riscv-fuzz` binary does that for a corpus of crashes and prints the
+//! failing encodings.
+//!
+//! Runs whose outcome depends on more than the instructions are checked for
+//! panics and JIT divergences only: ones the interpreter does not finish,
+//! that write over their own code (blocks may finish with the old code,
+//! which the ISA allows without FENCE.I), that read the counters, or that
+//! enable interrupts, which are only polled every so many steps.
+
+use crate::bench::Engine;
+use crate::bus::{Bus, DRAM_BASE, SystemBus};
+use crate::cpu::csr::{
+    CSR_FCSR, CSR_FFLAGS, CSR_FRM, CSR_MCAUSE, CSR_MEPC, CSR_MIE, CSR_MSTATUS, CSR_MTVAL,
+    CSR_MTVEC, CSR_SSCRATCH,
+};
+use crate::cpu::{Cpu, Mode};
+use crate::engine::decoder::expand_compressed;
+use crate::engine::disasm::disassemble;
+#[cfg(feature = "jit-native")]
+use crate::engine::jit::JitConfig;
+use std::fmt::{self, Write as _};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Most instruction words a program runs.
+pub const MAX_WORDS: usize = 64;
+
+/// DRAM given to each run.
+const FUZZ_DRAM_SIZE: usize = 128 * 1024;
+
+/// Data page the pointer registers aim into, from the DRAM base.
+const DATA_OFFSET: u64 = 0x10000;
+const DATA_SIZE: usize = 0x1000;
+
+/// Registers holding pointers into the data page.
+const POINTER_REGS: std::ops::RangeInclusive<usize> = 28..=31;
+
+/// Steps before a run is given up on.
+const MAX_STEPS: u64 = 10_000;
+
+/// `addi x0, x0, 0`
+const NOP: u32 = 0x0000_0013;
+/// `j .`
+const SPIN: u32 = 0x0000_006f;
+
+/// mstatus.FS and mstatus.VS set to Initial.
+const MSTATUS_FS_VS_INITIAL: u64 = 1 << 13 | 1 << 9;
+
+/// CSRs compared after a run, besides the registers and memory.
+const COMPARED_CSRS: [(u16, &str); 7] = [
+    (CSR_MSTATUS, "mstatus"),
+    (CSR_MCAUSE, "mcause"),
+    (CSR_MEPC, "mepc"),
+    (CSR_MTVAL, "mtval"),
+    (CSR_FCSR, "fcsr"),
+    (CSR_SSCRATCH, "sscratch"),
+    (CSR_MIE, "mie"),
+];
+
+/// CSRs structured programs access.
+const STRUCTURED_CSRS: [u16; 4] = [CSR_FFLAGS, CSR_FRM, CSR_FCSR, CSR_SSCRATCH];
+
+/// Register values more likely to hit edge cases than random ones.
+const SPECIAL_VALUES: [u64; 6] = [0, 1, u64::MAX, 1 << 63, 0xffff_ffff_8000_0000, 0x7fff_ffff];
+
+/// SplitMix64, enough to spread a seed over the registers.
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// A program to run and the seed of its starting state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// Seeds the registers and the data page.
+    pub seed: u64,
+    /// Instruction words, run from the DRAM base.
+    pub words: Vec<u32>,
+}
+
+impl Program {
+    /// Read fuzzer input, see the [module docs](self).
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let structured = data.first().is_some_and(|flags| flags & 1 != 0);
+        let mut seed = [0u8; 8];
+        let seed_bytes = data.get(1..).unwrap_or_default();
+        let len = seed_bytes.len().min(8);
+        seed[..len].copy_from_slice(&seed_bytes[..len]);
+        let words = data
+            .get(9..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .take(MAX_WORDS)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .map(|word| if structured { shape(word) } else { word })
+            .collect();
+        Self {
+            seed: u64::from_le_bytes(seed),
+            words,
+        }
+    }
+
+    /// Input that reads back as this program, with the words taken as they
+    /// are.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0];
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        for word in &self.words {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data
+    }
+
+    /// Address of the `j .` that ends the program and takes traps.
+    fn end(&self) -> u64 {
+        DRAM_BASE + 4 * self.words.len() as u64
+    }
+
+    /// Whether any word accesses a counter CSR, whose values differ
+    /// between engines.
+    fn reads_counters(&self) -> bool {
+        self.words.iter().any(|&word| {
+            let csr = word >> 20;
+            word & 0x7f == 0x73
+                && (word >> 12) & 3 != 0
+                && matches!(csr, 0xb00..=0xb1f | 0xc00..=0xc1f)
+        })
+    }
+}
+
+/// Random fuzzer input from `seed`, mostly structured.
+pub fn generate(seed: u64) -> Vec<u8> {
+    let mut rng = SplitMix(seed);
+    let words = 1 + rng.next() as usize % MAX_WORDS;
+    let mut data = vec![!rng.next().is_multiple_of(4) as u8];
+    data.extend_from_slice(&rng.next().to_le_bytes());
+    for _ in 0..words {
+        data.extend_from_slice(&(rng.next() as u32).to_le_bytes());
+    }
+    data
+}
+
+/// Turn a random word into a likely valid encoding, keeping whatever
+/// fields it can.
+fn shape(word: u32) -> u32 {
+    // Leave the pointer registers alone
+    let rd = ((word >> 7) & 31) % 28;
+    let rs1 = (word >> 15) & 31;
+    let rs2 = (word >> 20) & 31;
+    let funct3 = (word >> 12) & 7;
+    let shamt = (word >> 20) & 0x3f;
+    let base = 28 + (word >> 15 & 3);
+    // Within 256 bytes either side of the pointer, aligned for doublewords
+    let offset = (((word >> 20) & 0x1f8) as i32 - 0x100) as u32 & 0xfff;
+    let pick = |table: &[u32], bits: u32| table[bits as usize % table.len()];
+    let rm = pick(&[0, 1, 2, 3, 4, 7], funct3);
+    let r_type = |funct7: u32, rs2: u32, funct3: u32, opcode: u32| {
+        funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+    };
+    let i_type = |imm: u32, funct3: u32, opcode: u32| {
+        imm << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+    };
+    let store = |width: u32, opcode: u32| {
+        (offset >> 5) << 25 | rs2 << 20 | base << 15 | width << 12 | (offset & 0x1f) << 7 | opcode
+    };
+
+    match (word & 0x7f) % 18 {
+        0 | 1 => match funct3 {
+            // Shifts, and the Zbb and Zbs ops sharing their encodings
+            1 => {
+                const IMM: [u32; 8] = [0, 0x280, 0x480, 0x680, 0x600, 0x601, 0x602, 0x604];
+                let imm = IMM[(word >> 29) as usize];
+                i_type(if imm < 0x600 { imm | shamt } else { imm }, 1, 0x13)
+            }
+            5 => {
+                const IMM: [u32; 8] = [0, 0, 0x400, 0x400, 0x600, 0x480, 0x287, 0x6b8];
+                let imm = IMM[(word >> 29) as usize];
+                i_type(if imm < 0x600 { imm | shamt } else { imm }, 5, 0x13)
+            }
+            _ => i_type(word >> 20, funct3, 0x13),
+        },
+        2 | 3 => {
+            // Base, M, Zba, Zbb and Zbs ops, as (funct7, funct3)
+            const OPS: [(u32, u32); 33] = [
+                (0x00, 0),
+                (0x00, 1),
+                (0x00, 2),
+                (0x00, 3),
+                (0x00, 4),
+                (0x00, 5),
+                (0x00, 6),
+                (0x00, 7),
+                (0x20, 0),
+                (0x20, 5),
+                (0x01, 0),
+                (0x01, 1),
+                (0x01, 2),
+                (0x01, 3),
+                (0x01, 4),
+                (0x01, 5),
+                (0x01, 6),
+                (0x01, 7),
+                (0x10, 2),
+                (0x10, 4),
+                (0x10, 6),
+                (0x20, 4),
+                (0x20, 6),
+                (0x20, 7),
+                (0x05, 4),
+                (0x05, 5),
+                (0x05, 6),
+                (0x05, 7),
+                (0x30, 1),
+                (0x30, 5),
+                (0x24, 1),
+                (0x24, 5),
+                (0x34, 1),
+            ];
+            let (funct7, funct3) = OPS[(word >> 25) as usize % OPS.len()];
+            r_type(funct7, rs2, funct3, 0x33)
+        }
+        4 => match funct3 % 3 {
+            0 => i_type(word >> 20, 0, 0x1b),
+            1 => {
+                const IMM: [u32; 5] = [0, 0x080, 0x600, 0x601, 0x602];
+                let imm = pick(&IMM, word >> 27);
+                let imm = match imm {
+                    0 => shamt & 0x1f,
+                    0x080 => imm | shamt,
+                    _ => imm,
+                };
+                i_type(imm, 1, 0x1b)
+            }
+            _ => i_type(pick(&[0, 0x400, 0x600], word >> 27) | shamt & 0x1f, 5, 0x1b),
+        },
+        5 => {
+            const OPS: [(u32, u32); 16] = [
+                (0x00, 0),
+                (0x20, 0),
+                (0x00, 1),
+                (0x00, 5),
+                (0x20, 5),
+                (0x01, 0),
+                (0x01, 4),
+                (0x01, 5),
+                (0x01, 6),
+                (0x01, 7),
+                (0x04, 0),
+                (0x10, 2),
+                (0x10, 4),
+                (0x10, 6),
+                (0x30, 1),
+                (0x30, 5),
+            ];
+            let (funct7, funct3) = OPS[(word >> 25) as usize % OPS.len()];
+            r_type(funct7, rs2, funct3, 0x3b)
+        }
+        6 => (word & 0xffff_f000) | rd << 7 | 0x37,
+        7 => (word & 0xffff_f000) | rd << 7 | 0x17,
+        8 => i_type(offset, funct3 % 7, 0x03) & !(31 << 15) | base << 15,
+        9 => store(funct3 & 3, 0x23),
+        10 => {
+            // Up to 8 instructions either way, never onto itself
+            let step = (word >> 25) & 7;
+            let imm = if word >> 28 & 1 != 0 {
+                4 * (step + 1)
+            } else {
+                (-4 * (step as i32 + 1)) as u32
+            };
+            (imm >> 12 & 1) << 31
+                | (imm >> 5 & 0x3f) << 25
+                | rs2 << 20
+                | rs1 << 15
+                | pick(&[0, 1, 4, 5, 6, 7], funct3) << 12
+                | (imm >> 1 & 0xf) << 8
+                | (imm >> 11 & 1) << 7
+                | 0x63
+        }
+        11 => {
+            let imm = 4 * ((word >> 25) & 7) + 4;
+            (imm >> 1 & 0x3ff) << 21 | rd << 7 | 0x6f
+        }
+        12 => {
+            // AMOs and LR/SC on an aligned pointer
+            const FUNCT5: [u32; 11] = [
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x08, 0x0c, 0x10, 0x14, 0x18, 0x1c,
+            ];
+            let funct5 = FUNCT5[(word >> 27) as usize % FUNCT5.len()];
+            let rs2 = if funct5 == 0x02 { 0 } else { rs2 };
+            funct5 << 27
+                | (word & 0x0600_0000)
+                | rs2 << 20
+                | base << 15
+                | (2 + (funct3 & 1)) << 12
+                | rd << 7
+                | 0x2f
+        }
+        13 => {
+            if word >> 31 != 0 {
+                offset << 20 | base << 15 | (2 + (funct3 & 1)) << 12 | rd << 7 | 0x07
+            } else {
+                store(2 + (funct3 & 1), 0x27)
+            }
+        }
+        14 => {
+            // Single and double precision OP-FP
+            let fmt = (word >> 25) & 1;
+            let (funct5, rs2, rm) = match (word >> 27) % 13 {
+                op @ 0..=3 => (op, rs2, rm),
+                4 => (0x0b, 0, rm),
+                5 => (0x04, rs2, funct3 % 3),
+                6 => (0x05, rs2, funct3 & 1),
+                7 => (0x08, 1 - fmt, rm),
+                8 => (0x14, rs2, funct3 % 3),
+                9 => (0x18, rs2 & 3, rm),
+                10 => (0x1a, rs2 & 3, rm),
+                11 => (0x1c, 0, funct3 & 1),
+                _ => (0x1e, 0, 0),
+            };
+            r_type(funct5 << 2 | fmt, rs2, rm, 0x53)
+        }
+        15 => {
+            const FMA: [u32; 4] = [0x43, 0x47, 0x4b, 0x4f];
+            (word & 0xf9f0_0000) | rs1 << 15 | rm << 12 | rd << 7 | FMA[(word >> 30) as usize]
+        }
+        16 => {
+            let csr = STRUCTURED_CSRS[(word >> 20) as usize & 3] as u32;
+            i_type(csr, pick(&[1, 2, 3, 5, 6, 7], funct3), 0x73)
+        }
+        _ => {
+            // A pair of compressed instructions from quadrants 0-2
+            (word & !0x0003_0003) | ((word >> 2) % 3) | ((word >> 18) % 3) << 16
+        }
+    }
+}
+
+/// How a run ended.
+#[derive(Debug, Clone)]
+struct Outcome {
+    /// Reached the `j .` at the end of the program.
+    finished: bool,
+    /// Host-only trap that stopped the run.
+    error: Option<String>,
+    pc: u64,
+    mode: Mode,
+    regs: [u64; 32],
+    fregs: [u64; 32],
+    csrs: [u64; COMPARED_CSRS.len()],
+    dram: Vec<u8>,
+    /// First JIT verification failure, as text.
+    divergence: Option<String>,
+}
+
+impl Outcome {
+    /// Final value of `addr`, one of [`COMPARED_CSRS`].
+    fn csr(&self, addr: u16) -> u64 {
+        let index = COMPARED_CSRS.iter().position(|&(a, _)| a == addr);
+        index.map_or(0, |i| self.csrs[i])
+    }
+
+    /// First difference from `other`, as (what, ours, theirs).
+    fn diff(&self, other: &Outcome) -> Option<(String, String, String)> {
+        let hex = |value: u64| format!("{:#x}", value);
+        if self.finished != other.finished {
+            return Some((
+                "finished".to_string(),
+                self.finished.to_string(),
+                other.finished.to_string(),
+            ));
+        }
+        if self.error != other.error {
+            return Some((
+                "error".to_string(),
+                format!("{:?}", self.error),
+                format!("{:?}", other.error),
+            ));
+        }
+        if self.pc != other.pc {
+            return Some(("pc".to_string(), hex(self.pc), hex(other.pc)));
+        }
+        if self.mode != other.mode {
+            return Some((
+                "mode".to_string(),
+                format!("{:?}", self.mode),
+                format!("{:?}", other.mode),
+            ));
+        }
+        for reg in 1..32 {
+            if self.regs[reg] != other.regs[reg] {
+                return Some((
+                    format!("x{}", reg),
+                    hex(self.regs[reg]),
+                    hex(other.regs[reg]),
+                ));
+            }
+        }
+        for reg in 0..32 {
+            if self.fregs[reg] != other.fregs[reg] {
+                return Some((
+                    format!("f{}", reg),
+                    hex(self.fregs[reg]),
+                    hex(other.fregs[reg]),
+                ));
+            }
+        }
+        for (i, (_, name)) in COMPARED_CSRS.iter().enumerate() {
+            if self.csrs[i] != other.csrs[i] {
+                return Some((name.to_string(), hex(self.csrs[i]), hex(other.csrs[i])));
+            }
+        }
+        let offset = self
+            .dram
+            .iter()
+            .zip(&other.dram)
+            .position(|(a, b)| a != b)?;
+        Some((
+            format!("memory at {:#x}", DRAM_BASE + offset as u64),
+            hex(self.dram[offset] as u64),
+            hex(other.dram[offset] as u64),
+        ))
+    }
+}
+
+/// Run `program` under `engine`.
+fn run(engine: Engine, program: &Program) -> Result<Outcome, String> {
+    let bus = SystemBus::new(DRAM_BASE, FUZZ_DRAM_SIZE);
+    let mut rng = SplitMix(program.seed);
+    let data: Vec<u8> = (0..DATA_SIZE / 8)
+        .flat_map(|_| rng.next().to_le_bytes())
+        .collect();
+    bus.dram
+        .load(&data, DATA_OFFSET)
+        .map_err(|e| format!("failed to load the data page: {:?}", e))?;
+    for (i, &word) in program.words.iter().chain(&[SPIN]).enumerate() {
+        bus.write32(DRAM_BASE + 4 * i as u64, word)
+            .map_err(|trap| format!("failed to load the program: {:?}", trap))?;
+    }
+
+    let mut cpu = Cpu::new(DRAM_BASE, 0);
+    for reg in 1..32 {
+        let value = rng.next();
+        cpu.regs[reg] = match value % 4 {
+            0 => SPECIAL_VALUES[(value >> 8) as usize % SPECIAL_VALUES.len()],
+            _ => rng.next(),
+        };
+        cpu.fregs[reg] = rng.next();
+    }
+    cpu.fregs[0] = rng.next();
+    for (i, reg) in POINTER_REGS.enumerate() {
+        cpu.regs[reg] = DRAM_BASE + DATA_OFFSET + 0x200 + 0x400 * i as u64;
+    }
+    cpu.csrs[CSR_MTVEC as usize] = program.end();
+    cpu.csrs[CSR_MSTATUS as usize] |= MSTATUS_FS_VS_INITIAL;
+
+    match engine {
+        Engine::Interpreter => cpu.use_blocks = false,
+        Engine::Blocks => cpu.use_blocks = true,
+        #[cfg(feature = "jit-native")]
+        Engine::Jit => cpu.enable_jit(JitConfig {
+            hot_threshold: 1,
+            verify: true,
+            ..Default::default()
+        })?,
+        #[cfg(not(feature = "jit-native"))]
+        Engine::Jit => return Err("built without the jit-native feature".to_string()),
+    }
+
+    let mut finished = false;
+    let mut error = None;
+    for _ in 0..MAX_STEPS {
+        if cpu.pc == program.end() {
+            finished = true;
+            break;
+        }
+        // Architectural traps have been taken by the time they are returned
+        if let Err(trap) = cpu.step(&bus)
+            && Cpu::trap_to_cause_tval(&trap).is_none()
+        {
+            error = Some(format!("{:?}", trap));
+            break;
+        }
+    }
+    finished |= error.is_none() && cpu.pc == program.end();
+
+    #[cfg(feature = "jit-native")]
+    let divergence = cpu
+        .jit
+        .as_ref()
+        .and_then(|jit| jit.diagnostics().first_divergence)
+        .map(|divergence| divergence.to_string());
+    #[cfg(not(feature = "jit-native"))]
+    let divergence = None;
+
+    let mut csrs = [0; COMPARED_CSRS.len()];
+    for (value, (addr, _)) in csrs.iter_mut().zip(COMPARED_CSRS) {
+        *value = cpu.read_csr(addr).unwrap_or_default();
+    }
+    Ok(Outcome {
+        finished,
+        error,
+        pc: cpu.pc,
+        mode: cpu.mode,
+        regs: cpu.regs,
+        fregs: cpu.fregs,
+        csrs,
+        dram: bus
+            .dram
+            .read_range(0, FUZZ_DRAM_SIZE)
+            .map_err(|e| format!("failed to read DRAM: {:?}", e))?,
+        divergence,
+    })
+}
+
+/// Run `program` under `engine`, turning a panic into an error.
+fn run_guarded(engine: Engine, program: &Program) -> Result<Outcome, Finding> {
+    let panic = |message: String| Finding {
+        engine,
+        kind: FindingKind::Panic(message),
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| run(engine, program))) {
+        Ok(outcome) => outcome.map_err(panic),
+        Err(payload) => Err(panic(
+            payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string()),
+        )),
+    }
+}
+
+/// What went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FindingKind {
+    /// The engine panicked, or could not set the run up.
+    Panic(String),
+    /// The engine ended in a different state from the interpreter.
+    Mismatch {
+        what: String,
+        interpreter: String,
+        actual: String,
+    },
+    /// A compiled block disagreed with the interpreter during JIT
+    /// verification.
+    Divergence(String),
+}
+
+/// A program on which an engine misbehaved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub engine: Engine,
+    pub kind: FindingKind,
+}
+
+impl Finding {
+    /// Whether `other` is the same kind of failure on the same engine, even
+    /// if the details moved.
+    pub fn same_failure(&self, other: &Finding) -> bool {
+        self.engine == other.engine
+            && std::mem::discriminant(&self.kind) == std::mem::discriminant(&other.kind)
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            FindingKind::Panic(message) => write!(f, "{} panicked: {}", self.engine, message),
+            FindingKind::Mismatch {
+                what,
+                interpreter,
+                actual,
+            } => write!(
+                f,
+                "{} disagrees with the interpreter on {}: {} instead of {}",
+                self.engine, what, actual, interpreter
+            ),
+            FindingKind::Divergence(divergence) => {
+                write!(f, "{} verification failed: {}", self.engine, divergence)
+            }
+        }
+    }
+}
+
+/// Check `program` under every engine this build has.
+pub fn check_program(program: &Program) -> Result<(), Finding> {
+    let reference = run_guarded(Engine::Interpreter, program)?;
+    let code_len = 4 * (program.words.len() + 1);
+    let code: Vec<u8> = program
+        .words
+        .iter()
+        .chain(&[SPIN])
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    let conclusive = reference.finished
+        && reference.dram[..code_len] == code[..]
+        && !program.reads_counters()
+        && reference.csr(CSR_MIE) == 0;
+
+    for engine in Engine::available() {
+        if engine == Engine::Interpreter {
+            continue;
+        }
+        let outcome = run_guarded(engine, program)?;
+        if let Some(divergence) = outcome.divergence.clone() {
+            return Err(Finding {
+                engine,
+                kind: FindingKind::Divergence(divergence),
+            });
+        }
+        if conclusive && let Some((what, interpreter, actual)) = reference.diff(&outcome) {
+            return Err(Finding {
+                engine,
+                kind: FindingKind::Mismatch {
+                    what,
+                    interpreter,
+                    actual,
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check fuzzer input, see the [module docs](self).
+pub fn check(data: &[u8]) -> Result<(), Finding> {
+    check_program(&Program::from_bytes(data))
+}
+
+/// Cut `program` down while `fails` holds: drop words, then blank the ones
+/// that cannot go because later branches count on their place, then zero
+/// the seed.
+fn shrink(program: &Program, mut fails: impl FnMut(&Program) -> bool) -> Program {
+    let mut best = program.clone();
+    loop {
+        let mut changed = false;
+        for i in (0..best.words.len()).rev() {
+            let mut candidate = best.clone();
+            candidate.words.remove(i);
+            if fails(&candidate) {
+                best = candidate;
+                changed = true;
+                continue;
+            }
+            if best.words[i] != NOP {
+                candidate = best.clone();
+                candidate.words[i] = NOP;
+                if fails(&candidate) {
+                    best = candidate;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    if best.seed != 0 {
+        let candidate = Program {
+            seed: 0,
+            ..best.clone()
+        };
+        if fails(&candidate) {
+            best = candidate;
+        }
+    }
+    best
+}
+
+/// The smallest program found that fails like `program`, and how it fails,
+/// or `None` if `program` passes.
+pub fn minimize(program: &Program) -> Option<(Program, Finding)> {
+    let finding = check_program(program).err()?;
+    let mut last = finding.clone();
+    let smallest = shrink(program, |candidate| match check_program(candidate) {
+        Err(found) if found.same_failure(&finding) => {
+            last = found;
+            true
+        }
+        _ => false,
+    });
+    // The last failure seen may belong to a candidate that was later cut
+    // further, so check the result again for its exact finding
+    let finding = check_program(&smallest).err().unwrap_or(last);
+    Some((smallest, finding))
+}
+
+/// `finding` and the disassembled program it was found on.
+pub fn report(program: &Program, finding: &Finding) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", finding);
+    let _ = writeln!(out, "seed {:#018x}", program.seed);
+    for (i, &word) in program.words.iter().enumerate() {
+        let pc = DRAM_BASE + 4 * i as u64;
+        let text = if word & 3 == 3 {
+            disassemble(pc, word)
+        } else {
+            // Two compressed instructions, unless the second half starts a
+            // 32-bit one
+            let half = |pc: u64, bits: u16| match expand_compressed(bits) {
+                Ok(insn) => disassemble(pc, insn),
+                Err(_) => format!(".half {:#06x}", bits),
+            };
+            format!(
+                "{}; {}",
+                half(pc, word as u16),
+                half(pc + 2, (word >> 16) as u16)
+            )
+        };
+        let _ = writeln!(out, "  {:#x}: {:08x}  {}", pc, word, text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(words: &[u32]) -> Program {
+        Program {
+            seed: 0x1234_5678,
+            words: words.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_input_roundtrip() {
+        let p = program(&[0x00b5_0533, 0x0000_0073]);
+        assert_eq!(Program::from_bytes(&p.to_bytes()), p);
+        // Short input is padded; a trailing partial word is dropped
+        assert_eq!(
+            Program::from_bytes(&[0, 1, 2]),
+            Program {
+                seed: 0x0201,
+                words: Vec::new(),
+            }
+        );
+        let mut data = vec![1; 9 + 4 * (MAX_WORDS + 1) + 3];
+        data[0] = 1;
+        let shaped = Program::from_bytes(&data);
+        assert_eq!(shaped.words.len(), MAX_WORDS);
+        assert!(shaped.words.iter().all(|&word| word != 0x0101_0101));
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        // The ALU loop from the benchmarks, then an FP add, an AMO and a trap
+        let p = program(&[
+            0x00b5_0533, // add a0, a0, a1
+            0x00a5_c5b3, // xor a1, a1, a0
+            0x0035_1613, // slli a2, a0, 3
+            0x02d6_0733, // mul a4, a2, a3
+            0x02c5_4533, // div a0, a0, a2
+            0x0211_f0d3, // fadd.d f1, f3, f1
+            0x00be_b62f, // amoadd.d a2, a1, (t4)
+            0x001f_3023, // sd ra, 0(t5)
+            0x0000_0073, // ecall
+        ]);
+        assert_eq!(check_program(&p), Ok(()));
+        let outcome = run(Engine::Interpreter, &p).unwrap();
+        assert!(outcome.finished);
+        assert_eq!(outcome.csr(CSR_MCAUSE), 11); // ecall from M-mode
+
+        // Undefined OP encodings are illegal under blocks too, whether or
+        // not they start the block
+        for words in [&[0x61be_fdb3][..], &[0x0015_0513, 0x61be_fdb3]] {
+            let p = program(words);
+            assert_eq!(check_program(&p), Ok(()));
+            let outcome = run(Engine::Blocks, &p).unwrap();
+            assert_eq!(outcome.csr(CSR_MCAUSE), 2);
+            assert_eq!(outcome.csr(CSR_MTVAL), 0x61be_fdb3);
+        }
+    }
+
+    #[test]
+    fn test_random_programs() {
+        for seed in 0..200 {
+            let data = generate(seed);
+            if let Some((program, finding)) = minimize(&Program::from_bytes(&data)) {
+                panic!("{}", report(&program, &finding));
+            }
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        // Pretend `sub` fails whenever it follows an `addi`
+        let bad = 0x40b5_0533; // sub a0, a0, a1
+        let fails = |p: &Program| {
+            p.words
+                .windows(2)
+                .any(|w| w[0] & 0x7f == 0x13 && w[0] != NOP && w[1] == bad)
+        };
+        let p = program(&[
+            0x00b5_0533,
+            0x0015_0513,
+            0x0010_0593,
+            bad,
+            0x0000_0073,
+            0x0020_0613,
+        ]);
+        let smallest = shrink(&p, fails);
+        assert_eq!(smallest.words, vec![0x0015_0513, bad]);
+        assert_eq!(smallest.seed, 0);
+
+        // Nothing fails, nothing to report
+        assert_eq!(minimize(&p), None);
+        let finding = Finding {
+            engine: Engine::Blocks,
+            kind: FindingKind::Panic("boom".to_string()),
+        };
+        let text = report(&smallest, &finding);
+        assert!(text.starts_with("blocks panicked: boom"));
+        assert!(text.contains("40b50533  sub a0, a0, a1"));
+    }
+}
@@ -0,0 +1,533 @@
+//! GDB remote serial protocol stub.
+//!
+//! `--gdb ADDR` makes the native CLI wait for a debugger before booting and
+//! then run hart 0 under its control (`target remote ADDR` in gdb). The stub
+//! speaks enough of the protocol for source-level kernel debugging:
+//! registers (x0-x31 and pc, described by a target XML), memory in the
+//! hart's current address space, single-step, continue with Ctrl+C,
+//! software and hardware breakpoints, and read, write and access
+//! watchpoints on top of [`Cpu::add_watchpoint`].
+//!
+//! [`GdbStub`] turns packets into replies and run requests; the run loop
+//! itself lives with the VM (see `NativeVm::run_gdb`), which steps the hart
+//! and keeps devices going while the debugger waits for a stop.
+
+use crate::bus::SystemBus;
+use crate::cpu::{Cpu, WatchId, Watchpoint};
+use crate::vm::guest_mem::{self, Translation};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// Largest packet the stub accepts, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x4000;
+
+/// Signal reported when the debugger interrupts a running hart.
+pub const SIGINT: u8 = 2;
+
+/// Signal reported for breakpoints, steps and watchpoints.
+pub const SIGTRAP: u8 = 5;
+
+/// Signal reported when the hart stops on a fatal error.
+pub const SIGSEGV: u8 = 11;
+
+/// Number of registers in the `g` packet: x0-x31 and pc.
+const NUM_REGS: usize = 33;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// What the debugger asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Send this reply and wait for the next packet.
+    Reply(String),
+    /// Execute one instruction, then report a stop.
+    Step,
+    /// Run until a breakpoint, watchpoint, interrupt or halt.
+    Continue,
+    /// Let the guest run on without the debugger.
+    Detach,
+    /// Stop the VM.
+    Kill,
+}
+
+/// Kind of a `Z2`-`Z4` watchpoint, in packet order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn from_type(kind: u8) -> Option<Self> {
+        match kind {
+            2 => Some(WatchKind::Write),
+            3 => Some(WatchKind::Read),
+            4 => Some(WatchKind::Access),
+            _ => None,
+        }
+    }
+
+    /// Stop reason reported when it fires.
+    fn reason(self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+/// Protocol state: breakpoints and watchpoints the debugger has set.
+#[derive(Default)]
+pub struct GdbStub {
+    breakpoints: BTreeSet<u64>,
+    watches: Vec<(WatchKind, u64, u64, WatchId)>,
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a breakpoint is set at `pc`.
+    pub fn has_breakpoint(&self, pc: u64) -> bool {
+        self.breakpoints.contains(&pc)
+    }
+
+    /// Stop reply for a hart that stopped with `signal`, naming the
+    /// watchpoint if one fired.
+    pub fn stop_reply(&self, cpu: &Cpu, signal: u8) -> String {
+        if let Some(hit) = cpu.watch_hit()
+            && let Some((kind, ..)) = self.watches.iter().find(|w| w.3 == hit.id)
+        {
+            return format!("T{:02x}{}:{:x};", SIGTRAP, kind.reason(), hit.vaddr);
+        }
+        format!("S{:02x}", signal)
+    }
+
+    /// Handle one packet (without framing) for `cpu`.
+    pub fn handle(&mut self, packet: &str, cpu: &mut Cpu, bus: &SystemBus) -> Request {
+        let reply = |s: &str| Request::Reply(s.to_string());
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => Request::Reply(self.stop_reply(cpu, SIGTRAP)),
+            "g" => {
+                let mut out = String::with_capacity(NUM_REGS * 16);
+                for n in 0..NUM_REGS {
+                    push_hex(&mut out, &read_reg(cpu, n).to_le_bytes());
+                }
+                Request::Reply(out)
+            }
+            "G" => match parse_hex(args) {
+                Some(bytes) if bytes.len() >= NUM_REGS * 8 => {
+                    for (n, value) in bytes.chunks_exact(8).take(NUM_REGS).enumerate() {
+                        write_reg(cpu, n, u64::from_le_bytes(value.try_into().unwrap()));
+                    }
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < NUM_REGS => {
+                    let mut out = String::new();
+                    push_hex(&mut out, &read_reg(cpu, n).to_le_bytes());
+                    Request::Reply(out)
+                }
+                _ => reply("E01"),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16)
+                        .ok()
+                        .filter(|&n| n < NUM_REGS)?;
+                    let bytes: [u8; 8] = parse_hex(value)?.try_into().ok()?;
+                    Some((n, u64::from_le_bytes(bytes)))
+                });
+                match parsed {
+                    Some((n, value)) => {
+                        write_reg(cpu, n, value);
+                        reply("OK")
+                    }
+                    None => reply("E01"),
+                }
+            }
+            "m" => {
+                let Some((addr, len)) = parse_addr_len(args) else {
+                    return reply("E01");
+                };
+                let mut buf = vec![0u8; len.min(PACKET_SIZE / 2)];
+                match guest_mem::read_virt(cpu, bus, addr, &mut buf, Translation::Current) {
+                    Ok(()) => {
+                        let mut out = String::with_capacity(buf.len() * 2);
+                        push_hex(&mut out, &buf);
+                        Request::Reply(out)
+                    }
+                    Err(_) => reply("E14"),
+                }
+            }
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(target, data)| {
+                    let (addr, len) = parse_addr_len(target)?;
+                    let data = parse_hex(data).filter(|data| data.len() == len)?;
+                    Some((addr, data))
+                });
+                let Some((addr, data)) = parsed else {
+                    return reply("E01");
+                };
+                match guest_mem::write_virt(cpu, bus, addr, &data, Translation::Current) {
+                    Ok(()) => reply("OK"),
+                    Err(_) => reply("E14"),
+                }
+            }
+            "Z" | "z" => self.set_point(command == "Z", args, cpu),
+            "s" => Request::Step,
+            "c" => Request::Continue,
+            "D" => Request::Detach,
+            "k" => Request::Kill,
+            // One hart, so thread selection always succeeds
+            "H" | "T" => reply("OK"),
+            _ => self.handle_query(packet),
+        }
+    }
+
+    fn handle_query(&self, packet: &str) -> Request {
+        let reply = |s: &str| Request::Reply(s.to_string());
+        if packet.starts_with("qSupported") {
+            return Request::Reply(format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+",
+                PACKET_SIZE
+            ));
+        }
+        if let Some(annex) = packet.strip_prefix("qXfer:features:read:") {
+            let Some(("target.xml", range)) = annex.split_once(':') else {
+                return reply("E00");
+            };
+            let Some((offset, len)) = parse_addr_len(range) else {
+                return reply("E01");
+            };
+            let xml = target_xml();
+            let start = (offset as usize).min(xml.len());
+            let end = start.saturating_add(len).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            return Request::Reply(format!("{}{}", marker, &xml[start..end]));
+        }
+        match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "QStartNoAckMode" => reply("OK"),
+            "vCont?" => reply("vCont;c;C;s;S"),
+            "vMustReplyEmpty" => reply(""),
+            _ => match packet.strip_prefix("vCont;") {
+                // The one hart runs however the action is addressed
+                Some(action) if action.starts_with(['s', 'S']) => Request::Step,
+                Some(action) if action.starts_with(['c', 'C']) => Request::Continue,
+                _ => reply(""),
+            },
+        }
+    }
+
+    /// `Z`/`z` packets: `type,addr,kind`.
+    fn set_point(&mut self, insert: bool, args: &str, cpu: &mut Cpu) -> Request {
+        let reply = |s: &str| Request::Reply(s.to_string());
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return reply("E01");
+        };
+        let (Ok(kind), Ok(addr), Ok(len)) = (
+            kind.parse::<u8>(),
+            u64::from_str_radix(addr, 16),
+            u64::from_str_radix(len, 16),
+        ) else {
+            return reply("E01");
+        };
+
+        // Software and hardware breakpoints both compare the PC
+        if kind <= 1 {
+            if insert {
+                self.breakpoints.insert(addr);
+            } else {
+                self.breakpoints.remove(&addr);
+            }
+            return reply("OK");
+        }
+        let Some(kind) = WatchKind::from_type(kind) else {
+            return reply("");
+        };
+        if insert {
+            let watch = Watchpoint::virtual_range(addr, len.max(1));
+            let watch = match kind {
+                WatchKind::Write => watch.writes_only(),
+                WatchKind::Read => watch.reads_only(),
+                WatchKind::Access => watch,
+            };
+            let id = cpu.add_watchpoint(watch);
+            self.watches.push((kind, addr, len, id));
+        } else if let Some(i) = self
+            .watches
+            .iter()
+            .position(|w| (w.0, w.1, w.2) == (kind, addr, len))
+        {
+            cpu.remove_watchpoint(self.watches.remove(i).3);
+        }
+        reply("OK")
+    }
+}
+
+fn read_reg(cpu: &Cpu, n: usize) -> u64 {
+    if n < 32 { cpu.regs[n] } else { cpu.pc }
+}
+
+fn write_reg(cpu: &mut Cpu, n: usize, value: u64) {
+    match n {
+        0 => {}
+        1..32 => cpu.regs[n] = value,
+        _ => cpu.pc = value,
+    }
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `addr,len` in hex.
+fn parse_addr_len(s: &str) -> Option<(u64, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Register layout given to gdb.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>riscv:rv64</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for (n, name) in REG_NAMES.iter().enumerate() {
+        let kind = match n {
+            1 => "code_ptr",
+            2..=4 | 8 => "data_ptr",
+            _ => "int",
+        };
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"64\" type=\"{}\" regnum=\"{}\"/>",
+            name, kind, n
+        );
+    }
+    xml.push_str("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"32\"/>");
+    xml.push_str("</feature></target>");
+    xml
+}
+
+/// Frame `data` as a packet: `$data#checksum`.
+fn frame(data: &str) -> Vec<u8> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, checksum).into_bytes()
+}
+
+/// A debugger connection.
+pub struct Connection {
+    stream: TcpStream,
+    /// Whether packets are still acknowledged with `+`.
+    ack: bool,
+}
+
+impl Connection {
+    /// Wait for a debugger to connect on `addr`.
+    pub fn accept(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream, ack: true })
+    }
+
+    /// The next packet, or `None` once the debugger hangs up. Interrupts
+    /// while the hart is stopped are ignored.
+    pub fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0u8; 1];
+        loop {
+            // Skip acks and stray bytes up to the start of a packet
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                if data.len() < PACKET_SIZE {
+                    data.push(byte[0]);
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if self.ack {
+                let valid = expected == Some(sum);
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                if !valid {
+                    continue;
+                }
+            }
+            let packet = String::from_utf8_lossy(&data).into_owned();
+            if packet == "QStartNoAckMode" {
+                self.send("OK")?;
+                self.ack = false;
+                continue;
+            }
+            return Ok(Some(packet));
+        }
+    }
+
+    /// Send one packet.
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        self.stream.write_all(&frame(data))
+    }
+
+    /// Whether the debugger sent Ctrl+C, without waiting.
+    pub fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8; 1];
+        let result = match self.stream.read(&mut byte) {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::cpu::test_hart;
+
+    fn reply(request: Request) -> String {
+        match request {
+            Request::Reply(s) => s,
+            other => panic!("expected a reply, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn frames_packets_with_checksum() {
+        assert_eq!(frame("OK"), b"$OK#9a");
+        assert_eq!(frame(""), b"$#00");
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let (mut cpu, bus) = test_hart(&[]);
+        let mut stub = GdbStub::new();
+        cpu.regs[10] = 0x1122_3344_5566_7788;
+
+        let regs = reply(stub.handle("g", &mut cpu, &bus));
+        assert_eq!(regs.len(), NUM_REGS * 16);
+        assert_eq!(&regs[10 * 16..11 * 16], "8877665544332211");
+        assert_eq!(&regs[32 * 16..], "0000008000000000");
+
+        assert_eq!(
+            reply(stub.handle("P20=0010008000000000", &mut cpu, &bus)),
+            "OK"
+        );
+        assert_eq!(cpu.pc, DRAM_BASE + 0x1000);
+        // x0 stays zero
+        stub.handle("P0=0100000000000000", &mut cpu, &bus);
+        assert_eq!(cpu.regs[0], 0);
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let (mut cpu, bus) = test_hart(&[]);
+        let mut stub = GdbStub::new();
+
+        let write = format!("M{:x},4:deadbeef", DRAM_BASE + 0x10);
+        assert_eq!(reply(stub.handle(&write, &mut cpu, &bus)), "OK");
+        let read = format!("m{:x},4", DRAM_BASE + 0x10);
+        assert_eq!(reply(stub.handle(&read, &mut cpu, &bus)), "deadbeef");
+        assert_eq!(reply(stub.handle("m0,4", &mut cpu, &bus)), "E14");
+    }
+
+    #[test]
+    fn tracks_breakpoints_and_watchpoints() {
+        let (mut cpu, bus) = test_hart(&[]);
+        let mut stub = GdbStub::new();
+
+        let addr = DRAM_BASE + 0x40;
+        stub.handle(&format!("Z0,{:x},4", addr), &mut cpu, &bus);
+        assert!(stub.has_breakpoint(addr));
+        stub.handle(&format!("z0,{:x},4", addr), &mut cpu, &bus);
+        assert!(!stub.has_breakpoint(addr));
+
+        stub.handle(&format!("Z2,{:x},8", addr), &mut cpu, &bus);
+        assert_eq!(cpu.watchpoints().len(), 1);
+        assert!(!cpu.watchpoints()[0].1.read);
+        stub.handle(&format!("z2,{:x},8", addr), &mut cpu, &bus);
+        assert!(cpu.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn serves_target_description_in_chunks() {
+        let (mut cpu, bus) = test_hart(&[]);
+        let mut stub = GdbStub::new();
+        let xml = target_xml();
+
+        let first = reply(stub.handle("qXfer:features:read:target.xml:0,10", &mut cpu, &bus));
+        assert_eq!(first, format!("m{}", &xml[..16]));
+        let rest = format!("qXfer:features:read:target.xml:10,{:x}", xml.len());
+        let last = reply(stub.handle(&rest, &mut cpu, &bus));
+        assert_eq!(last, format!("l{}", &xml[16..]));
+        assert!(xml.contains("riscv:rv64"));
+    }
+
+    #[test]
+    fn maps_run_control_packets() {
+        let (mut cpu, bus) = test_hart(&[]);
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle("s", &mut cpu, &bus), Request::Step);
+        assert_eq!(stub.handle("vCont;c", &mut cpu, &bus), Request::Continue);
+        assert_eq!(stub.handle("D", &mut cpu, &bus), Request::Detach);
+        assert_eq!(reply(stub.handle("?", &mut cpu, &bus)), "S05");
+    }
+}
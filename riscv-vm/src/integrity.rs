@@ -0,0 +1,360 @@
+//! DRAM integrity checking.
+//!
+//! Long-running hosted VMs can opt into a background checker that detects
+//! silent corruption of guest memory: bad host RAM, a stray host-side write,
+//! or an emulator bug that bypasses the normal store path.
+//!
+//! Every 4 KiB page carries a checksum that is the XOR of a mixed hash of
+//! each 64-bit word in the page. Because XOR is commutative, a store only has
+//! to fold `hash(old) ^ hash(new)` into the page checksum, so concurrent
+//! harts can update the same page without a lock. The checker periodically
+//! rehashes a sample of *cold* pages (pages not written since its previous
+//! visit) and compares against the running checksum.
+//!
+//! Each page also has a state word (low 32 bits: stores in flight, high 32
+//! bits: write generation). A verification is only trusted if no store was
+//! in flight and the generation did not move while the page was hashed, so
+//! races with the guest never produce false positives.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Granularity of the per-page checksums.
+pub const PAGE_SIZE: usize = 4096;
+const WORDS_PER_PAGE: usize = PAGE_SIZE / 8;
+
+/// Maximum number of undrained corruption events kept.
+const MAX_PENDING_EVENTS: usize = 64;
+
+const IN_FLIGHT_MASK: u64 = 0xFFFF_FFFF;
+const GENERATION_ONE: u64 = 1 << 32;
+
+/// How often and how much the background checker verifies.
+#[derive(Debug, Clone, Copy)]
+pub struct IntegrityConfig {
+    /// Delay between verification passes.
+    pub interval: Duration,
+    /// Pages examined per pass.
+    pub pages_per_pass: usize,
+}
+
+impl Default for IntegrityConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            pages_per_pass: 256,
+        }
+    }
+}
+
+/// A page whose contents no longer match its running checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionEvent {
+    /// Guest physical address of the start of the page.
+    pub page_addr: u64,
+    /// Checksum maintained by the store path.
+    pub expected: u64,
+    /// Checksum of the page contents as found by the checker.
+    pub actual: u64,
+}
+
+impl fmt::Display for CorruptionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DRAM corruption in page {:#x} (checksum {:#018x}, expected {:#018x})",
+            self.page_addr, self.actual, self.expected
+        )
+    }
+}
+
+/// Counters reported by [`IntegrityMap::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntegrityStats {
+    /// Pages covered by checksums.
+    pub tracked_pages: usize,
+    /// Verification passes run so far.
+    pub passes: u64,
+    /// Pages rehashed and compared.
+    pub pages_verified: u64,
+    /// Pages skipped because they were written since the last visit or
+    /// while being hashed.
+    pub pages_skipped: u64,
+    /// Corrupted pages detected.
+    pub corruptions: u64,
+}
+
+/// Per-page checksums over a DRAM image viewed as 64-bit words.
+pub struct IntegrityMap {
+    base: u64,
+    checksums: Box<[AtomicU64]>,
+    state: Box<[AtomicU64]>,
+    /// Generation seen by the checker on its previous visit to each page.
+    seen: Box<[AtomicU32]>,
+    cursor: AtomicUsize,
+    passes: AtomicU64,
+    pages_verified: AtomicU64,
+    pages_skipped: AtomicU64,
+    corruptions: AtomicU64,
+    events: Mutex<VecDeque<CorruptionEvent>>,
+}
+
+/// Position-dependent hash of one word (SplitMix64 finaliser).
+#[inline(always)]
+fn word_hash(index: usize, value: u64) -> u64 {
+    let mut x = value ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn page_hash(words: &[AtomicU64], page: usize) -> u64 {
+    let start = page * WORDS_PER_PAGE;
+    let end = (start + WORDS_PER_PAGE).min(words.len());
+    (start..end).fold(0, |acc, i| {
+        acc ^ word_hash(i, u64::from_le(words[i].load(Ordering::SeqCst)))
+    })
+}
+
+impl IntegrityMap {
+    /// Build checksums for the current contents of `words`, which back
+    /// guest memory starting at physical address `base`.
+    pub fn new(base: u64, words: &[AtomicU64]) -> Self {
+        let pages = words.len().div_ceil(WORDS_PER_PAGE);
+        let map = Self {
+            base,
+            checksums: (0..pages).map(|_| AtomicU64::new(0)).collect(),
+            state: (0..pages).map(|_| AtomicU64::new(0)).collect(),
+            seen: (0..pages).map(|_| AtomicU32::new(0)).collect(),
+            cursor: AtomicUsize::new(0),
+            passes: AtomicU64::new(0),
+            pages_verified: AtomicU64::new(0),
+            pages_skipped: AtomicU64::new(0),
+            corruptions: AtomicU64::new(0),
+            events: Mutex::new(VecDeque::new()),
+        };
+        map.rebuild(words);
+        map
+    }
+
+    /// Recompute every checksum from scratch (after a bulk restore).
+    ///
+    /// Must only be called while no other thread writes guest memory.
+    pub fn rebuild(&self, words: &[AtomicU64]) {
+        for page in 0..self.checksums.len() {
+            self.checksums[page].store(page_hash(words, page), Ordering::SeqCst);
+            self.state[page].fetch_add(GENERATION_ONE, Ordering::SeqCst);
+        }
+    }
+
+    /// Replace the bits selected by `mask` in word `index` with `value`,
+    /// keeping the page checksum up to date.
+    #[inline]
+    pub fn store(&self, words: &[AtomicU64], index: usize, mask: u64, value: u64) {
+        let page = index / WORDS_PER_PAGE;
+        self.state[page].fetch_add(1, Ordering::SeqCst);
+        let merge = |old: u64| (old & !mask) | (value & mask);
+        let old = match words[index].fetch_update(Ordering::SeqCst, Ordering::SeqCst, |raw| {
+            Some(merge(u64::from_le(raw)).to_le())
+        }) {
+            Ok(raw) | Err(raw) => u64::from_le(raw),
+        };
+        let delta = word_hash(index, old) ^ word_hash(index, merge(old));
+        self.checksums[page].fetch_xor(delta, Ordering::SeqCst);
+        self.state[page].fetch_add(GENERATION_ONE - 1, Ordering::SeqCst);
+    }
+
+    /// Verify up to `max_pages` cold pages, continuing where the previous
+    /// pass stopped. Returns the number of corrupted pages found.
+    pub fn verify(&self, words: &[AtomicU64], max_pages: usize) -> usize {
+        let pages = self.checksums.len();
+        let mut found = 0;
+        for _ in 0..max_pages.min(pages) {
+            let page = self.cursor.fetch_add(1, Ordering::Relaxed) % pages;
+            if let Some(event) = self.verify_page(words, page) {
+                log::error!("[VM] {}", event);
+                let mut events = self.events.lock().unwrap();
+                if events.len() == MAX_PENDING_EVENTS {
+                    events.pop_front();
+                }
+                events.push_back(event);
+                found += 1;
+            }
+        }
+        self.passes.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    fn verify_page(&self, words: &[AtomicU64], page: usize) -> Option<CorruptionEvent> {
+        let before = self.state[page].load(Ordering::SeqCst);
+        let generation = (before >> 32) as u32;
+        let last_seen = self.seen[page].swap(generation, Ordering::Relaxed);
+        if before & IN_FLIGHT_MASK != 0 || generation != last_seen {
+            self.pages_skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let actual = page_hash(words, page);
+        let expected = self.checksums[page].load(Ordering::SeqCst);
+        if self.state[page].load(Ordering::SeqCst) != before {
+            self.pages_skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.pages_verified.fetch_add(1, Ordering::Relaxed);
+        if actual == expected {
+            return None;
+        }
+
+        // Adopt the corrupted contents so the same fault is reported once
+        let _ = self.checksums[page].compare_exchange(
+            expected,
+            actual,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        self.corruptions.fetch_add(1, Ordering::Relaxed);
+        Some(CorruptionEvent {
+            page_addr: self.base + (page * PAGE_SIZE) as u64,
+            expected,
+            actual,
+        })
+    }
+
+    /// Take all corruption events reported since the last call.
+    pub fn drain_events(&self) -> Vec<CorruptionEvent> {
+        self.events.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn stats(&self) -> IntegrityStats {
+        IntegrityStats {
+            tracked_pages: self.checksums.len(),
+            passes: self.passes.load(Ordering::Relaxed),
+            pages_verified: self.pages_verified.load(Ordering::Relaxed),
+            pages_skipped: self.pages_skipped.load(Ordering::Relaxed),
+            corruptions: self.corruptions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dram::{DRAM_BASE, Dram};
+    use std::sync::Arc;
+    use std::thread;
+
+    const SIZE: usize = 16 * PAGE_SIZE;
+
+    fn tracked_dram() -> Dram {
+        let dram = Dram::new(DRAM_BASE, SIZE);
+        dram.write_bytes(0x100, b"pre-existing contents").unwrap();
+        assert!(dram.enable_integrity());
+        dram
+    }
+
+    fn verify_all(dram: &Dram) -> usize {
+        // Two passes: the first records each page's generation, the second
+        // checks every page that stayed cold
+        dram.verify_integrity(SIZE / PAGE_SIZE);
+        dram.verify_integrity(SIZE / PAGE_SIZE)
+    }
+
+    #[test]
+    fn test_tracked_stores_stay_consistent() {
+        let dram = tracked_dram();
+        dram.store_8(3, 0xAB).unwrap();
+        dram.store_16(0x1002, 0xBEEF).unwrap();
+        dram.store_32(0x2004, 0xDEAD_BEEF).unwrap();
+        dram.store_64(0x3008, 0x0123_4567_89AB_CDEF).unwrap();
+        dram.write_bytes(8 * PAGE_SIZE as u64 - 5, b"straddles a page")
+            .unwrap();
+        dram.zero_range(0x4001, 0x30).unwrap();
+
+        assert_eq!(dram.load_8(3).unwrap(), 0xAB);
+        assert_eq!(dram.load_16(0x1002).unwrap(), 0xBEEF);
+        assert_eq!(dram.load_32(0x2004).unwrap(), 0xDEAD_BEEF);
+        assert_eq!(dram.load_64(0x3008).unwrap(), 0x0123_4567_89AB_CDEF);
+        assert_eq!(
+            dram.read_range(8 * PAGE_SIZE - 5, 16).unwrap(),
+            b"straddles a page"
+        );
+
+        assert_eq!(verify_all(&dram), 0);
+        let stats = dram.integrity().unwrap().stats();
+        assert_eq!(stats.tracked_pages, SIZE / PAGE_SIZE);
+        assert_eq!(stats.corruptions, 0);
+        assert!(stats.pages_verified > 0);
+    }
+
+    #[test]
+    fn test_detects_untracked_bit_flip() {
+        let dram = tracked_dram();
+        dram.poke_untracked(0x5123, 0x10);
+
+        assert_eq!(verify_all(&dram), 1);
+        let events = dram.integrity().unwrap().drain_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].page_addr, DRAM_BASE + 0x5000);
+        assert_ne!(events[0].expected, events[0].actual);
+
+        // Reported once, then the new contents become the baseline
+        assert_eq!(verify_all(&dram), 0);
+        assert_eq!(dram.integrity().unwrap().stats().corruptions, 1);
+    }
+
+    #[test]
+    fn test_hot_pages_are_skipped() {
+        let dram = tracked_dram();
+        let map = dram.integrity().unwrap();
+        verify_all(&dram);
+        let skipped = map.stats().pages_skipped;
+
+        dram.store_64(0x6000, 1).unwrap();
+        dram.poke_untracked(0x6008, 0xFF);
+        dram.verify_integrity(SIZE / PAGE_SIZE);
+        assert_eq!(map.stats().pages_skipped, skipped + 1);
+        assert_eq!(map.stats().corruptions, 0);
+
+        // Once the page goes cold again the flip is caught
+        assert_eq!(dram.verify_integrity(SIZE / PAGE_SIZE), 1);
+    }
+
+    #[test]
+    fn test_set_data_rebuilds_checksums() {
+        let dram = tracked_dram();
+        let mut image = dram.get_data();
+        image[0x7777] = 0x42;
+        dram.set_data(&image).unwrap();
+        assert_eq!(verify_all(&dram), 0);
+    }
+
+    #[test]
+    fn test_concurrent_writers_never_report_false_positives() {
+        let dram = Arc::new(tracked_dram());
+        let writers: Vec<_> = (0..4u64)
+            .map(|t| {
+                let dram = Arc::clone(&dram);
+                thread::spawn(move || {
+                    for i in 0..20_000u64 {
+                        let off = (i * 40 + t * 8) % SIZE as u64;
+                        match i % 3 {
+                            0 => dram.store_64(off & !7, i ^ t).unwrap(),
+                            1 => dram.store_32(off & !3, i as u64).unwrap(),
+                            _ => dram.store_8(off + 1, t).unwrap(),
+                        }
+                    }
+                })
+            })
+            .collect();
+        while !writers.iter().all(|w| w.is_finished()) {
+            assert_eq!(dram.verify_integrity(4), 0);
+        }
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(verify_all(&dram), 0);
+    }
+}
@@ -1,12 +1,20 @@
+pub mod backtrace;
 pub mod bus;
 pub mod cpu;
+pub mod crash_dump;
 pub mod devices;
+pub mod disk;
 pub mod dram;
 pub mod engine;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fuzz;
 pub mod mmu;
 pub use devices::{clint, plic, uart};
 pub mod loader;
+pub mod migration;
 pub mod net;
+pub mod replay;
+pub mod share;
 pub mod shared_mem;
 pub mod snapshot;
 pub mod vm;
@@ -16,17 +24,40 @@ pub use cpu::{Mode, Trap, csr};
 #[cfg(all(feature = "napi", not(target_arch = "wasm32")))]
 pub mod napi_bindings;
 
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
+pub mod ffi;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod compliance;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod console;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gdb;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod integrity;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod usermode;
+
 #[cfg(target_arch = "wasm32")]
 pub mod worker;
 
 // Re-export specific VM types for consumers
+pub use devices::test_finisher::VmExit;
 pub use vm::emulator::Emulator;
+pub use vm::trap_info::TrapInfo;
 
 #[cfg(target_arch = "wasm32")]
-pub use vm::wasm::{NetworkStatus, WasmVm};
+pub use vm::wasm::{NetworkStatus, RunToken, WasmVm};
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use vm::native::NativeVm;
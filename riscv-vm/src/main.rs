@@ -1,8 +1,30 @@
 use clap::Parser;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use riscv_vm::backtrace::SymbolMap;
+use riscv_vm::bus::BusConfig;
+use riscv_vm::console::{self, PortTarget};
+use riscv_vm::cpu::vector::DEFAULT_VLEN;
+use riscv_vm::cpu::{CsrPolicy, Mode, TraceFilter, TraceSink, Tracer};
+use riscv_vm::devices::clint::DEFAULT_CPU_FREQUENCY;
+use riscv_vm::devices::semihost::SemihostPolicy;
+use riscv_vm::devices::test_finisher::VmExit;
+use riscv_vm::disk::{self, BlockBackend, CowDisk, DiskMode};
+#[cfg(feature = "jit-native")]
+use riscv_vm::engine::jit::{JitConfig, JitProfile};
+use riscv_vm::gdb;
+use riscv_vm::migration::MigrationHeader;
+use riscv_vm::net::{self, NetBackend};
+use riscv_vm::net::batch::BatchConfig;
+use riscv_vm::replay::Recording;
+use riscv_vm::share::HostDir;
+use riscv_vm::snapshot::Compression;
+use riscv_vm::usermode::{UserExit, UserProcess};
 use riscv_vm::vm::native::NativeVm;
 
 #[derive(Parser, Debug)]
@@ -14,27 +36,406 @@ struct Args {
     #[arg(short, long)]
     kernel: PathBuf,
 
-    /// Path to disk image (optional)
-    #[arg(short, long)]
+    /// Path to disk image (optional); raw or qcow2-lite, guest writes go to the file
+    #[arg(short, long, visible_alias = "drive")]
     disk: Option<PathBuf>,
 
+    /// Keep guest disk writes in this copy-on-write overlay, created on
+    /// first use, and leave the --disk image untouched
+    #[arg(long, requires = "disk", conflicts_with = "disk_volatile")]
+    disk_overlay: Option<PathBuf>,
+
+    /// Load the disk image into memory and discard guest writes on exit
+    #[arg(long, requires = "disk")]
+    disk_volatile: bool,
+
+    /// Share a host directory with the guest over virtio-9p, as TAG=DIR
+    /// (repeatable; mount with `mount -t 9p -o trans=virtio TAG /mnt`)
+    #[arg(long, value_parser = parse_share)]
+    share: Vec<(String, PathBuf)>,
+
+    /// Like --share, but the guest can only read the directory
+    #[arg(long, value_parser = parse_share)]
+    share_ro: Vec<(String, PathBuf)>,
+
+    /// Firmware to boot before the kernel, e.g. OpenSBI (ELF, or raw at the
+    /// DRAM base); it gets the hart ID in a0 and the device tree in a1
+    #[arg(long)]
+    bios: Option<PathBuf>,
+
+    /// Boot the kernel in S-mode under the built-in SBI firmware (for Linux
+    /// images that expect OpenSBI)
+    #[arg(long, conflicts_with_all = ["bios", "reset_vector"])]
+    sbi: bool,
+
+    /// Kernel command line, passed in the device tree's /chosen/bootargs
+    /// (e.g. `plain` for plain-text output)
+    #[arg(long, default_value = "")]
+    bootargs: String,
+
+    /// Replace the boot ROM's first-stage loader with this raw image
+    #[arg(long)]
+    boot_rom: Option<PathBuf>,
+
+    /// Address harts start at after reset (defaults to the boot ROM)
+    #[arg(long, value_parser = parse_address)]
+    reset_vector: Option<u64>,
+
     /// Number of harts (CPUs), 0 for auto-detect
     #[arg(short = 'n', long, default_value = "0")]
     harts: usize,
 
-    /// WebTransport relay URL for networking (e.g., https://127.0.0.1:4433)
+    /// Guest DRAM size in MiB, or with an M or G suffix (e.g. 2G)
+    #[arg(short, long, visible_alias = "dram-size", default_value = "512", value_parser = parse_size)]
+    memory: usize,
+
+    /// Modelled CPU clock in MHz; guest time advances by the cycles executed
+    #[arg(long, default_value_t = DEFAULT_CPU_FREQUENCY / 1_000_000, value_parser = clap::value_parser!(u64).range(1..))]
+    cpu_mhz: u64,
+
+    /// Spin through the guest's idle loop instead of sleeping in WFI
+    #[arg(long)]
+    no_idle: bool,
+
+    /// Exit when the guest reboots instead of resetting the machine
     #[arg(long)]
+    no_reboot: bool,
+
+    /// Cap each hart at this many million instructions per second
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_mips: Option<u32>,
+
+    /// Keep the host clock out of guest time, so a single-hart run behaves
+    /// the same at any speed: the RTC counts guest time from 2000-01-01
+    #[arg(long)]
+    deterministic_time: bool,
+
+    /// Vector register width (VLEN) in bits, a power of two from 64 to 4096
+    #[arg(long, default_value_t = DEFAULT_VLEN)]
+    vlen: usize,
+
+    /// What the guest's accesses to unimplemented CSRs do: permissive reads
+    /// zero and ignores writes (logged at trace level), strict raises an
+    /// illegal-instruction exception
+    #[arg(long, default_value = "permissive", value_parser = str::parse::<CsrPolicy>)]
+    csr_policy: CsrPolicy,
+
+    /// Guest DRAM base address (hex with 0x prefix, or decimal)
+    #[arg(long, value_parser = parse_address, default_value = "0x80000000")]
+    dram_base: u64,
+
+    /// Network the guest NIC is attached to; the --net-* options below
+    /// configure it (and pick it on their own when --net is left out)
+    #[arg(long, value_enum)]
+    net: Option<NetMode>,
+
+    /// WebTransport relay URL for networking (e.g., https://127.0.0.1:4433)
+    #[arg(long, visible_alias = "relay", required_if_eq("net", "relay"))]
     net_webtransport: Option<String>,
 
+    /// Give the guest a user-mode NAT network on host sockets, no relay needed
+    #[arg(long, conflicts_with = "net_webtransport")]
+    net_slirp: bool,
+
+    /// Bridge the guest NIC to this host TAP interface ("" picks the next free tapN)
+    #[arg(long, conflicts_with_all = ["net_webtransport", "net_slirp"])]
+    net_tap: Option<String>,
+
+    /// MAC address of the guest NIC on a TAP interface (e.g. 52:54:00:12:34:57)
+    #[arg(long, requires = "net_tap", value_parser = parse_mac)]
+    net_mac: Option<[u8; 6]>,
+
+    /// IPv4 address the guest is given on a TAP interface
+    #[arg(long, requires = "net_tap", default_value = "10.0.2.15")]
+    net_ip: Ipv4Addr,
+
     /// Certificate hash for WebTransport (for self-signed certs)
     #[arg(long)]
     cert_hash: Option<String>,
 
+    /// Coalesce outgoing frames for up to this many milliseconds (0 disables)
+    #[arg(long, default_value_t = 2)]
+    net_batch_ms: u64,
+
+    /// Don't LZ4-compress frame batches
+    #[arg(long)]
+    net_no_compress: bool,
+
+    /// Give the guest a second console (virtio-console), served on
+    /// tcp:HOST:PORT or appended to a file
+    #[arg(long, value_parser = parse_port_target)]
+    console2: Option<PortTarget>,
+
+    /// Verify DRAM contents against per-page checksums in the background
+    #[arg(long)]
+    dram_check: bool,
+
+    /// Run predecoded blocks instead of one instruction at a time
+    #[arg(long, conflicts_with = "gdb")]
+    blocks: bool,
+
+    /// Compile hot blocks to native code
+    #[cfg(feature = "jit-native")]
+    #[arg(long, conflicts_with = "gdb")]
+    jit: bool,
+
+    /// Check every native block against the interpreter and report divergences
+    #[cfg(feature = "jit-native")]
+    #[arg(long, requires = "jit")]
+    jit_verify: bool,
+
+    /// Compile the blocks listed in this file on first use, if it exists,
+    /// and save the blocks compiled in this run to it on exit
+    #[cfg(feature = "jit-native")]
+    #[arg(long, requires = "jit")]
+    jit_profile: Option<PathBuf>,
+
+    /// Write an instruction trace of hart 0 to this file (runs it interpreted)
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions in START:END (hex with 0x prefix, or decimal)
+    #[arg(long, requires = "trace", value_parser = parse_range)]
+    trace_range: Option<Range<u64>>,
+
+    /// Only trace instructions executed in this privilege mode (m, s or u)
+    #[arg(long, requires = "trace", value_parser = str::parse::<Mode>)]
+    trace_mode: Option<Mode>,
+
+    /// Record console, network and interrupt input to this file (single hart)
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Re-run a session saved with --record, feeding it the recorded input
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Save a snapshot of the machine to this file when it stops (single hart)
+    #[arg(long)]
+    snapshot_on_exit: Option<PathBuf>,
+
+    /// Compress the --snapshot-on-exit snapshot: none, lz4 or zstd
+    #[arg(long, requires = "snapshot_on_exit", default_value = "none", value_parser = str::parse::<Compression>)]
+    snapshot_compression: Compression,
+
+    /// Hand the VM over to another host through the relay when it stops
+    /// (Ctrl-A x), printing the ticket to start it there with (single hart)
+    #[arg(long)]
+    migrate_on_exit: bool,
+
+    /// Carry on with the VM of this migration ticket instead of booting; the
+    /// kernel, memory size and devices must match the old host's (single hart)
+    #[arg(long, conflicts_with_all = ["record", "replay", "migrate_on_exit"])]
+    migrate_in: Option<String>,
+
+    /// Relay to migrate through, if not the --net-webtransport one
+    #[arg(long)]
+    migrate_relay: Option<String>,
+
+    /// Write a JSON crash dump (registers, CSRs, memory around the PC and
+    /// stack, device state) to this file if a hart halts on a fatal error
+    #[arg(long)]
+    crash_dump: Option<PathBuf>,
+
+    /// Wait for a GDB connection on [HOST]:PORT before booting and run
+    /// hart 0 under the debugger (single hart; `target remote :PORT`)
+    #[arg(long, value_parser = parse_gdb_addr, conflicts_with_all = ["record", "replay"])]
+    gdb: Option<SocketAddr>,
+
+    /// Print a symbolized guest backtrace when execution stops on a fault
+    #[arg(long)]
+    backtrace: bool,
+
+    /// Symbols for --backtrace: an ELF file or a System.map listing
+    /// (default: the kernel's own symbols)
+    #[arg(long, requires = "backtrace")]
+    symbols: Option<PathBuf>,
+
+    /// Run the kernel file as a static Linux user binary (no guest kernel)
+    #[arg(long)]
+    user: bool,
+
+    /// Let a bare-metal guest print, read its arguments and exit through
+    /// the semihosting device
+    #[arg(long)]
+    semihost: bool,
+
+    /// Directory semihosting guests may open files in
+    #[arg(long, requires = "semihost")]
+    semihost_dir: Option<PathBuf>,
+
+    /// Only let semihosting guests read files in --semihost-dir
+    #[arg(long, requires = "semihost_dir")]
+    semihost_read_only: bool,
+
+    /// Arguments passed to the user binary or semihosting guest (after `--`)
+    #[arg(last = true)]
+    user_args: Vec<String>,
+
     /// Enable debug output
     #[arg(long)]
     debug: bool,
 }
 
+/// Network backends selectable with --net
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum NetMode {
+    /// No network
+    None,
+    /// User-mode NAT on host sockets
+    Slirp,
+    /// Host TAP interface
+    Tap,
+    /// WebTransport relay
+    Relay,
+}
+
+impl NetMode {
+    fn name(self) -> &'static str {
+        match self {
+            NetMode::None => "none",
+            NetMode::Slirp => "slirp",
+            NetMode::Tap => "tap",
+            NetMode::Relay => "relay",
+        }
+    }
+}
+
+impl Args {
+    /// Network picked with --net, or implied by the --net-* option given
+    fn net_mode(&self) -> Result<NetMode, String> {
+        let implied = if self.net_webtransport.is_some() {
+            Some(NetMode::Relay)
+        } else if self.net_slirp {
+            Some(NetMode::Slirp)
+        } else if self.net_tap.is_some() {
+            Some(NetMode::Tap)
+        } else {
+            None
+        };
+        match (self.net, implied) {
+            (Some(mode), Some(other)) if mode != other => Err(format!(
+                "--net {} conflicts with the options for {}",
+                mode.name(),
+                other.name()
+            )),
+            (Some(mode), _) => Ok(mode),
+            (None, implied) => Ok(implied.unwrap_or(NetMode::None)),
+        }
+    }
+}
+
+/// Parse an address given in hex (`0x...`) or decimal
+fn parse_address(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+    parsed.map_err(|e| format!("invalid address '{}': {}", s, e))
+}
+
+/// Parse a DRAM size in MiB, or with an `M`/`MiB` or `G`/`GiB` suffix
+fn parse_size(s: &str) -> Result<usize, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let (digits, scale) = if let Some(n) = lower
+        .strip_suffix("gib")
+        .or_else(|| lower.strip_suffix('g'))
+    {
+        (n, 1024)
+    } else {
+        let n = lower
+            .strip_suffix("mib")
+            .or_else(|| lower.strip_suffix('m'))
+            .unwrap_or(&lower);
+        (n, 1)
+    };
+    let size = digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid size '{}'", s))?;
+    Ok(size)
+}
+
+/// Parse a GDB listen address; `:PORT` listens on localhost
+fn parse_gdb_addr(s: &str) -> Result<SocketAddr, String> {
+    let full = match s.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => s.to_string(),
+    };
+    full.parse()
+        .map_err(|e| format!("invalid address '{}': {}", s, e))
+}
+
+/// Parse a `START:END` address range
+fn parse_range(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected START:END, got '{}'", s))?;
+    let range = parse_address(start)?..parse_address(end)?;
+    if range.is_empty() {
+        return Err(format!("empty range '{}'", s));
+    }
+    Ok(range)
+}
+
+/// Parse a `TAG=DIR` share
+fn parse_share(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((tag, dir)) if !tag.is_empty() && !dir.is_empty() => {
+            Ok((tag.to_string(), PathBuf::from(dir)))
+        }
+        _ => Err(format!("expected TAG=DIR, got '{}'", s)),
+    }
+}
+
+/// Parse a `tcp:HOST:PORT` address or a file path
+fn parse_port_target(s: &str) -> Result<PortTarget, String> {
+    match s.strip_prefix("tcp:") {
+        Some(addr) => addr
+            .parse()
+            .map(PortTarget::Listen)
+            .map_err(|e| format!("invalid address '{}': {}", addr, e)),
+        None if !s.is_empty() => Ok(PortTarget::File(PathBuf::from(s))),
+        None => Err("expected tcp:HOST:PORT or a file path".to_string()),
+    }
+}
+
+/// Parse a MAC address written as six colon-separated hex bytes
+fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let mut mac = [0u8; 6];
+    let mut bytes = s.split(':');
+    for byte in mac.iter_mut() {
+        *byte = bytes
+            .next()
+            .and_then(|b| u8::from_str_radix(b, 16).ok())
+            .ok_or_else(|| format!("invalid MAC address '{}'", s))?;
+    }
+    if bytes.next().is_some() {
+        return Err(format!("invalid MAC address '{}'", s));
+    }
+    Ok(mac)
+}
+
+/// Open the --disk image, through a copy-on-write overlay if one is given
+fn open_disk(disk: &Path, overlay: Option<&Path>) -> Result<Box<dyn BlockBackend>, String> {
+    let result = match overlay {
+        Some(overlay) if overlay.exists() => disk::open(overlay, DiskMode::ReadWrite),
+        Some(overlay) => {
+            CowDisk::create_overlay(overlay, disk).map(|d| Box::new(d) as Box<dyn BlockBackend>)
+        }
+        None => disk::open(disk, DiskMode::ReadWrite),
+    };
+    result.map_err(|e| {
+        format!(
+            "Failed to open disk '{}': {}",
+            overlay.unwrap_or(disk).display(),
+            e
+        )
+    })
+}
+
 /// Write to stdout with \r\n line endings (for raw terminal mode)
 fn uart_print(s: &str) {
     let stdout = std::io::stdout();
@@ -72,16 +473,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let kernel_data = fs::read(&args.kernel)
         .map_err(|e| format!("Failed to read kernel '{}': {}", args.kernel.display(), e))?;
 
+    if args.user {
+        let mut argv = vec![args.kernel.display().to_string()];
+        argv.extend(args.user_args);
+        let mut process = UserProcess::load(&kernel_data, &argv)?;
+        match process.run(u64::MAX) {
+            UserExit::Exited(code) => std::process::exit(code),
+            fault @ UserExit::Fault { .. } if args.backtrace => {
+                return Err(format!("user program {}\n{}", fault, process.backtrace()).into());
+            }
+            other => return Err(format!("user program {}", other).into()),
+        }
+    }
+
+    let net_mode = args.net_mode()?;
+
     // Determine hart count - use half available cores or user-specified count
-    let num_harts = if args.harts == 0 {
-        let cpus = std::thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or(2);
-        (cpus / 2).max(1) // Use half the CPUs, ensure at least 1
-    } else {
-        args.harts
+    // (record/replay is only deterministic on a single hart, and the
+    // debugger and snapshots drive and save hart 0 alone)
+    let single_hart = args.gdb.is_some()
+        || args.snapshot_on_exit.is_some()
+        || args.migrate_on_exit
+        || args.migrate_in.is_some();
+    if single_hart && args.harts > 1 {
+        return Err("--gdb, --snapshot-on-exit and migration need a single hart".into());
     }
-    .max(1); // Ensure at least 1
+    let migrate_relay = args
+        .migrate_relay
+        .clone()
+        .or_else(|| args.net_webtransport.clone());
+    if (args.migrate_on_exit || args.migrate_in.is_some()) && migrate_relay.is_none() {
+        return Err("migration needs --migrate-relay or --net-webtransport".into());
+    }
+    let num_harts =
+        if args.harts == 0 && (single_hart || args.record.is_some() || args.replay.is_some()) {
+            1
+        } else if args.harts == 0 {
+            let cpus = std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(2);
+            (cpus / 2).max(1) // Use half the CPUs, ensure at least 1
+        } else {
+            args.harts
+        }
+        .max(1); // Ensure at least 1
 
     // Print banner
     uart_println!();
@@ -96,40 +531,313 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .to_string_lossy()
     );
     uart_println!("║  Harts:  {:50} ║", num_harts);
-    if let Some(relay) = &args.net_webtransport {
-        uart_println!("║  Network: {:49} ║", relay);
+    uart_println!("║  Clock:  {:50} ║", format!("{} MHz", args.cpu_mhz));
+    uart_println!(
+        "║  Memory: {:50} ║",
+        format!("{} MiB @ 0x{:x}", args.memory, args.dram_base)
+    );
+    match net_mode {
+        NetMode::Relay => {
+            uart_println!(
+                "║  Network: {:49} ║",
+                args.net_webtransport.as_deref().unwrap_or_default()
+            )
+        }
+        NetMode::Slirp => uart_println!("║  Network: {:49} ║", "user-mode NAT (slirp)"),
+        NetMode::Tap => uart_println!(
+            "║  Network: {:49} ║",
+            format!("TAP {}", args.net_tap.as_deref().unwrap_or_default())
+        ),
+        NetMode::None => {}
     }
     uart_println!("╚══════════════════════════════════════════════════════════════╝");
     uart_println!();
 
     // Create VM
-    let mut vm = NativeVm::new(&kernel_data, num_harts)?;
+    let memory_map = BusConfig::with_dram(args.dram_base, args.memory << 20);
+    let mut vm = NativeVm::with_config(&kernel_data, num_harts, memory_map)?;
+    vm.set_cpu_frequency(args.cpu_mhz * 1_000_000);
+    vm.set_vlen(args.vlen)?;
+    vm.set_csr_policy(args.csr_policy);
+    vm.set_bootargs(&args.bootargs)?;
+    vm.set_idle(!args.no_idle);
+    vm.set_reboot(!args.no_reboot);
+    vm.set_max_mips(args.max_mips);
+    vm.set_deterministic_time(args.deterministic_time);
+
+    if let Some(path) = &args.bios {
+        let firmware = fs::read(path)
+            .map_err(|e| format!("Failed to read firmware '{}': {}", path.display(), e))?;
+        let entry = vm.load_firmware(&firmware)?;
+        uart_println!("[VM] Firmware: {} (entry 0x{:x})", path.display(), entry);
+    }
+    if let Some(path) = &args.boot_rom {
+        let image = fs::read(path)
+            .map_err(|e| format!("Failed to read boot ROM '{}': {}", path.display(), e))?;
+        vm.set_boot_rom(image)?;
+        uart_println!("[VM] Boot ROM: {}", path.display());
+    }
+    if args.sbi {
+        vm.enable_sbi()?;
+        uart_println!("[VM] Built-in SBI enabled");
+    }
+    if let Some(pc) = args.reset_vector {
+        vm.set_reset_vector(pc);
+        uart_println!("[VM] Reset vector: 0x{:x}", pc);
+    }
 
     // Load disk if specified
     if let Some(disk_path) = &args.disk {
-        let disk_data = fs::read(disk_path)
-            .map_err(|e| format!("Failed to read disk '{}': {}", disk_path.display(), e))?;
-        vm.load_disk(disk_data);
+        if args.disk_volatile {
+            let disk_data = fs::read(disk_path)
+                .map_err(|e| format!("Failed to read disk '{}': {}", disk_path.display(), e))?;
+            vm.load_disk(disk_data);
+        } else {
+            vm.attach_disk(open_disk(disk_path, args.disk_overlay.as_deref())?);
+        }
         uart_println!("[VM] Loaded disk: {}", disk_path.display());
+        if let Some(overlay) = &args.disk_overlay {
+            uart_println!("[VM] Disk overlay: {}", overlay.display());
+        }
     }
 
-    // Connect to WebTransport relay if specified
-    if let Some(relay_url) = &args.net_webtransport {
-        vm.connect_webtransport(relay_url, args.cert_hash.clone());
+    // Share host directories
+    let shares = args.share.iter().map(|share| (share, false));
+    let shares = shares.chain(args.share_ro.iter().map(|share| (share, true)));
+    for ((tag, dir), read_only) in shares {
+        let backend = HostDir::new(dir, read_only)
+            .map_err(|e| format!("Failed to share '{}': {}", dir.display(), e))?;
+        vm.attach_share(tag, Box::new(backend))?;
+        uart_println!(
+            "[VM] Shared {} as '{}'{}",
+            dir.display(),
+            tag,
+            if read_only { " (read-only)" } else { "" }
+        );
     }
 
-    // Run VM
-    vm.run();
+    if let Some(target) = &args.console2 {
+        let port = vm.attach_console()?;
+        console::bridge_port(port, target.clone())
+            .map_err(|e| format!("Failed to open second console {:?}: {}", target, e))?;
+        match target {
+            PortTarget::Listen(addr) => uart_println!("[VM] Second console on tcp:{}", addr),
+            PortTarget::File(path) => uart_println!("[VM] Second console to {}", path.display()),
+        }
+    }
 
-    // Report exit status
-    let halt_code = vm.shared.halt_code();
-    if halt_code == 0x5555 {
-        uart_println!();
-        uart_println!("[VM] Clean shutdown (PASS)");
-        Ok(())
+    if args.semihost {
+        let mut policy = SemihostPolicy::default();
+        if let Some(dir) = &args.semihost_dir {
+            let backend = HostDir::new(dir, args.semihost_read_only)
+                .map_err(|e| format!("Failed to open '{}': {}", dir.display(), e))?;
+            policy.files = Some(Box::new(backend));
+        }
+        let mut cmdline = vec![args.kernel.display().to_string()];
+        cmdline.extend(args.user_args.iter().cloned());
+        policy.cmdline = cmdline.join(" ");
+        vm.enable_semihosting(policy);
+        uart_println!("[VM] Semihosting enabled");
+    }
+
+    if args.backtrace {
+        let symbols = match &args.symbols {
+            Some(path) => {
+                let data = fs::read(path)
+                    .map_err(|e| format!("Failed to read symbols '{}': {}", path.display(), e))?;
+                SymbolMap::parse(&data)?
+            }
+            // A raw kernel image has no symbols; frames stay unnamed
+            None => SymbolMap::from_elf(&kernel_data).unwrap_or_default(),
+        };
+        uart_println!("[VM] Backtraces enabled ({} symbols)", symbols.len());
+        vm.enable_backtrace(symbols);
+    }
+
+    if let Some(path) = &args.crash_dump {
+        vm.set_crash_dump(path);
+    }
+
+    if args.dram_check && !vm.enable_integrity_checker(Default::default()) {
+        uart_println!("[VM] DRAM integrity checking unavailable");
+    }
+
+    if args.blocks {
+        vm.enable_blocks();
+    }
+
+    #[cfg(feature = "jit-native")]
+    if args.jit {
+        let config = JitConfig {
+            verify: args.jit_verify,
+            ..Default::default()
+        };
+        match vm.enable_jit(config) {
+            Ok(()) => uart_println!("[VM] Native JIT enabled"),
+            Err(e) => uart_println!("[VM] Native JIT unavailable: {}", e),
+        }
+        if let Some(path) = args.jit_profile.as_ref().filter(|path| path.exists()) {
+            let loaded = fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| JitProfile::from_bytes(&bytes))
+                .and_then(|profile| vm.load_jit_profile(&profile));
+            match loaded {
+                Ok(blocks) => uart_println!(
+                    "[VM] JIT profile: {} blocks from {}",
+                    blocks,
+                    path.display()
+                ),
+                Err(e) => uart_println!("[VM] Ignoring JIT profile '{}': {}", path.display(), e),
+            }
+        }
+    }
+
+    if let Some(path) = &args.trace {
+        let sink = TraceSink::file(path)
+            .map_err(|e| format!("Failed to create trace '{}': {}", path.display(), e))?;
+        let filter = TraceFilter {
+            pc_range: args.trace_range.clone(),
+            mode: args.trace_mode,
+        };
+        vm.set_tracer(Tracer::new(sink).with_filter(filter))?;
+        uart_println!("[VM] Tracing hart 0 to {}", path.display());
+    }
+
+    // The input log has to be in place before networking is connected
+    if args.record.is_some() {
+        vm.start_recording()?;
+    } else if let Some(path) = &args.replay {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("Failed to read recording '{}': {}", path.display(), e))?;
+        vm.replay(Recording::from_bytes(&bytes)?)?;
+        uart_println!("[VM] Replaying {}", path.display());
+    }
+
+    // Fetch a migrating VM, keeping its NIC's address on the relay
+    let migration = match (&args.migrate_in, &migrate_relay) {
+        (Some(ticket), Some(relay_url)) => {
+            uart_println!("[VM] Fetching migration {} from {}", ticket, relay_url);
+            let image = net::migration::receive(relay_url, args.cert_hash.as_deref(), ticket)
+                .map_err(|e| format!("Migration failed: {}", e))?;
+            let (header, _) = MigrationHeader::read(&image)?;
+            Some((image, header.mac, ticket.as_str()))
+        }
+        _ => None,
+    };
+
+    // Connect to a WebTransport relay or the user-mode NAT if specified
+    match net_mode {
+        NetMode::Relay => {
+            let batching = BatchConfig {
+                window: Duration::from_millis(args.net_batch_ms),
+                compress: !args.net_no_compress,
+                ..Default::default()
+            };
+            let relay_url = args.net_webtransport.as_deref().unwrap_or_default();
+            match &migration {
+                Some((_, Some(mac), ticket)) => vm.resume_webtransport(
+                    relay_url,
+                    args.cert_hash.clone(),
+                    batching,
+                    *mac,
+                    ticket,
+                )?,
+                _ => vm.connect_webtransport_with(relay_url, args.cert_hash.clone(), batching),
+            }
+        }
+        NetMode::Slirp => vm.attach_network(NetBackend::Slirp)?,
+        NetMode::Tap => vm.attach_network(NetBackend::Tap {
+            name: args.net_tap.clone().unwrap_or_default(),
+            mac: args.net_mac,
+            ip: Some(args.net_ip.octets()),
+        })?,
+        NetMode::None => {}
+    }
+
+    if let Some((image, _, _)) = &migration {
+        vm.restore_migration(image)
+            .map_err(|e| format!("Cannot take over the migrated VM: {}", e))?;
+        uart_println!("[VM] Migrated VM restored ({} bytes)", image.len());
+    }
+
+    // Run VM, under the debugger if one is expected
+    if let Some(addr) = args.gdb {
+        uart_println!("[VM] Waiting for GDB on {}", addr);
+        let conn = gdb::Connection::accept(addr)
+            .map_err(|e| format!("Failed to accept GDB on {}: {}", addr, e))?;
+        uart_println!("[VM] GDB attached");
+        vm.run_gdb(conn)?;
     } else {
-        uart_println!();
-        uart_println!("[VM] Shutdown with code: {:#x}", halt_code);
-        Ok(())
+        // Reported below, once the run's artifacts are saved
+        let _ = vm.run();
+    }
+
+    if let Some(path) = &args.snapshot_on_exit {
+        vm.save_snapshot(path, args.snapshot_compression)
+            .map_err(|e| format!("Failed to save snapshot '{}': {}", path.display(), e))?;
+        uart_println!("[VM] Saved snapshot to {}", path.display());
+    }
+
+    if let (true, Some(relay_url)) = (args.migrate_on_exit, &migrate_relay) {
+        let image = vm.migration_image(Compression::Lz4)?;
+        let (header, _) = MigrationHeader::read(&image)?;
+        uart_println!("[VM] Migrating {} bytes through {}", image.len(), relay_url);
+        net::migration::send(
+            relay_url,
+            args.cert_hash.as_deref(),
+            image,
+            header.mac,
+            vm.migration_key().as_deref(),
+            |ticket| uart_println!("[VM] Migration ticket: {} (run with --migrate-in {})", ticket, ticket),
+        )
+        .map_err(|e| format!("Migration failed: {}", e))?;
+        uart_println!("[VM] Migration complete");
+    }
+
+    if let (Some(path), Some(recording)) = (&args.record, vm.take_recording()) {
+        fs::write(path, recording.to_bytes()?)
+            .map_err(|e| format!("Failed to write recording '{}': {}", path.display(), e))?;
+        uart_println!(
+            "[VM] Recorded {} inputs over {} cycles to {}",
+            recording.events.len(),
+            recording.end_cycle,
+            path.display()
+        );
+    }
+
+    #[cfg(feature = "jit-native")]
+    if let (Some(path), Some(profile)) = (&args.jit_profile, vm.jit_profile()) {
+        fs::write(path, profile.to_bytes()?)
+            .map_err(|e| format!("Failed to write JIT profile '{}': {}", path.display(), e))?;
+        uart_println!(
+            "[VM] Saved {} JIT blocks to {}",
+            profile.blocks.len(),
+            path.display()
+        );
+    }
+
+    // Report exit status
+    uart_println!();
+    match vm.exit() {
+        Ok(Some(VmExit::PowerOff)) => {
+            uart_println!("[VM] Clean shutdown (PASS)");
+            Ok(())
+        }
+        Ok(Some(VmExit::Reboot)) => {
+            uart_println!("[VM] Guest rebooted (--no-reboot)");
+            Ok(())
+        }
+        Ok(Some(exit @ VmExit::Fail(status))) => {
+            uart_println!("[VM] Guest exited with status {}", status);
+            std::process::exit(exit.exit_status());
+        }
+        Ok(None) => {
+            uart_println!("[VM] Stopped");
+            Ok(())
+        }
+        Err(error) => {
+            uart_println!("[VM] Halted on fatal error: {}", error);
+            std::process::exit(1);
+        }
     }
 }
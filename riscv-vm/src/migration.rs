@@ -0,0 +1,553 @@
+//! Live migration of a VM to another host.
+//!
+//! A migrated VM carries on where it stopped on a host of another kind,
+//! e.g. from a browser tab to a native process or back. Its state travels
+//! as a migration image:
+//!
+//! - a [`MigrationHeader`]: the transport and queue state of every VirtIO
+//!   slot, and the MAC and IP address of the VM's first NIC;
+//! - a [`Snapshot`] stream of hart 0, the CLINT, PLIC and UART and DRAM.
+//!
+//! The new host must be set up like the old one: same kernel, same DRAM
+//! size, same VirtIO devices in the same order, and a disk with the same
+//! contents (disk images are not sent). The single-hart limit of snapshots
+//! applies. GPU and 9P devices cannot be migrated, and other devices (the
+//! mailbox, RTC, framebuffer) start afresh.
+//!
+//! ## Transfer
+//!
+//! Images go through the relay, as both hosts may be browsers. The old
+//! host opens a migration session there and gets a ticket, which the user
+//! hands to the new host; the relay then passes datagrams of type
+//! [`MSG_TYPE_MIGRATION`] between the two. Datagrams may be lost, so the
+//! new host asks for the image [`PIECE_SIZE`] bytes at a time, keeping up
+//! to [`FETCH_WINDOW`] pieces in flight and asking again for those that do
+//! not arrive within [`RETRY_MS`]:
+//!
+//! | Kind  | From | Body                                          |
+//! |-------|------|-----------------------------------------------|
+//! | HELLO | new  | -                                             |
+//! | OFFER | old  | `len: u64`, SHA-256 of the image              |
+//! | FETCH | new  | piece indices, `u32` each                     |
+//! | PIECE | old  | `index: u32`, the piece                       |
+//! | DONE  | new  | - (the image arrived and its hash matches)    |
+//!
+//! Each datagram is `0x03 | kind | body`, little-endian. [`MigrationSender`]
+//! and [`MigrationReceiver`] implement the two ends without doing any I/O;
+//! [`crate::net::migration`] runs them over WebTransport.
+//!
+//! When the new host's NIC registers with the relay using the VM's MAC
+//! address and the ticket, the relay gives it the VM's old IP address.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::devices::virtio::VirtioState;
+use crate::devices::virtio::device::{CONFIG_SPACE_OFFSET, VIRTIO_NET_DEVICE_ID};
+use crate::snapshot::{Compression, Snapshot, restore_stream};
+
+/// Relay message type of migration datagrams.
+pub const MSG_TYPE_MIGRATION: u8 = 0x03;
+
+/// Bytes of the image per PIECE datagram.
+pub const PIECE_SIZE: usize = 1024;
+
+/// Pieces the new host asks for before earlier ones have arrived.
+pub const FETCH_WINDOW: usize = 256;
+
+/// Milliseconds after which an unanswered request is sent again.
+pub const RETRY_MS: u64 = 500;
+
+/// Largest image a new host accepts.
+pub const MAX_IMAGE_LEN: u64 = 1 << 34;
+
+const IMAGE_MAGIC: &[u8; 4] = b"RVMG";
+const IMAGE_FORMAT: u8 = 1;
+
+const KIND_HELLO: u8 = 0;
+const KIND_OFFER: u8 = 1;
+const KIND_FETCH: u8 = 2;
+const KIND_PIECE: u8 = 3;
+const KIND_DONE: u8 = 4;
+
+/// Indices per FETCH datagram.
+const FETCH_BATCH: usize = 128;
+
+/// Times DONE is sent, as nothing acknowledges it.
+const DONE_COPIES: usize = 3;
+
+/// The part of a migration image that is not a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationHeader {
+    /// MAC address of the first NIC, which the new host gives its NIC so
+    /// the relay keeps the VM's address.
+    pub mac: Option<[u8; 6]>,
+    /// IP address the first NIC had been assigned, if any.
+    pub ip: Option<[u8; 4]>,
+    /// State of every VirtIO slot, in order.
+    pub virtio: Vec<VirtioState>,
+}
+
+impl MigrationHeader {
+    /// Split `image` into its header and snapshot stream.
+    pub fn read(image: &[u8]) -> Result<(Self, &[u8]), String> {
+        if image.len() < 9 || &image[..4] != IMAGE_MAGIC {
+            return Err("not a migration image".to_string());
+        }
+        if image[4] != IMAGE_FORMAT {
+            return Err(format!("unsupported migration image format {}", image[4]));
+        }
+        let end = usize::try_from(u32::from_le_bytes(image[5..9].try_into().unwrap()))
+            .ok()
+            .and_then(|len| len.checked_add(9))
+            .ok_or_else(|| "truncated migration image".to_string())?;
+        let header = image
+            .get(9..end)
+            .ok_or_else(|| "truncated migration image".to_string())?;
+        let header: Self =
+            bincode::deserialize(header).map_err(|e| format!("invalid migration header: {}", e))?;
+        Ok((header, &image[end..]))
+    }
+}
+
+/// Capture `cpu` and the devices and DRAM of `bus` as a migration image,
+/// compressing DRAM with `compression`. Outstanding disk requests are
+/// completed first. The machine should not run again afterwards, or the
+/// guest would exist twice.
+pub fn capture(cpu: &Cpu, bus: &SystemBus, compression: Compression) -> Result<Vec<u8>, String> {
+    let virtio = bus.virtio_states()?;
+    let (mac, ip) = match first_nic(bus) {
+        Some((mac, ip)) => (Some(mac), ip),
+        None => (None, None),
+    };
+    let header = bincode::serialize(&MigrationHeader { mac, ip, virtio })
+        .map_err(|e| format!("failed to encode migration header: {}", e))?;
+
+    let mut image = Vec::with_capacity(9 + header.len());
+    image.extend_from_slice(IMAGE_MAGIC);
+    image.push(IMAGE_FORMAT);
+    image.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    image.extend_from_slice(&header);
+    Snapshot::write_stream(cpu, bus, &mut image, compression)?;
+    Ok(image)
+}
+
+/// Load `image` into `cpu` and `bus`, which must be set up like the
+/// machine it was captured on, and return its header. On error the
+/// machine holds part of the image and must not run.
+pub fn restore(image: &[u8], cpu: &mut Cpu, bus: &SystemBus) -> Result<MigrationHeader, String> {
+    let (header, mut stream) = MigrationHeader::read(image)?;
+    if header.virtio.len() != bus.virtio_devices.len() {
+        return Err(format!(
+            "the VM had {} VirtIO devices, this machine has {}",
+            header.virtio.len(),
+            bus.virtio_devices.len()
+        ));
+    }
+    restore_stream(&mut stream, cpu, bus)?;
+    bus.load_virtio_states(&header.virtio)?;
+    Ok(header)
+}
+
+/// Key the relay gave the first NIC on `bus`, which the old host presents
+/// to keep the NIC's address for the new one.
+pub fn relay_key(bus: &SystemBus) -> Option<String> {
+    bus.virtio_devices
+        .iter()
+        .find(|device| device.device_id() == VIRTIO_NET_DEVICE_ID)?
+        .migration_key()
+}
+
+/// MAC address and assigned IP of the first NIC on `bus`, as its driver
+/// reads them.
+fn first_nic(bus: &SystemBus) -> Option<([u8; 6], Option<[u8; 4]>)> {
+    let nic = bus
+        .virtio_devices
+        .iter()
+        .find(|device| device.device_id() == VIRTIO_NET_DEVICE_ID)?;
+    let low = nic.read(CONFIG_SPACE_OFFSET).ok()? as u32;
+    let high = nic.read(CONFIG_SPACE_OFFSET + 4).ok()? as u16;
+    let ip = nic.read(CONFIG_SPACE_OFFSET + 8).ok()? as u32;
+    let mut mac = [0; 6];
+    mac[..4].copy_from_slice(&low.to_le_bytes());
+    mac[4..].copy_from_slice(&high.to_le_bytes());
+    Some((mac, (ip != 0).then(|| ip.to_le_bytes())))
+}
+
+fn message(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(2 + body.len());
+    msg.push(MSG_TYPE_MIGRATION);
+    msg.push(kind);
+    msg.extend_from_slice(body);
+    msg
+}
+
+/// Kind and body of a migration datagram.
+fn parse(msg: &[u8]) -> Option<(u8, &[u8])> {
+    match msg {
+        [MSG_TYPE_MIGRATION, kind, body @ ..] => Some((*kind, body)),
+        _ => None,
+    }
+}
+
+fn piece_count(len: usize) -> usize {
+    len.div_ceil(PIECE_SIZE)
+}
+
+/// The old host's end of a transfer: answers the new host's requests for
+/// pieces of the image.
+pub struct MigrationSender {
+    image: Vec<u8>,
+    offer: Vec<u8>,
+    done: bool,
+}
+
+impl MigrationSender {
+    pub fn new(image: Vec<u8>) -> Self {
+        let mut body = (image.len() as u64).to_le_bytes().to_vec();
+        body.extend_from_slice(&Sha256::digest(&image));
+        Self {
+            offer: message(KIND_OFFER, &body),
+            image,
+            done: false,
+        }
+    }
+
+    /// Datagrams answering the datagram `msg` from the new host.
+    pub fn handle(&mut self, msg: &[u8]) -> Vec<Vec<u8>> {
+        let Some((kind, body)) = parse(msg) else {
+            return Vec::new();
+        };
+        match kind {
+            KIND_HELLO => vec![self.offer.clone()],
+            KIND_FETCH => body
+                .chunks_exact(4)
+                .filter_map(|index| {
+                    let index = u32::from_le_bytes(index.try_into().unwrap());
+                    let start = index as usize * PIECE_SIZE;
+                    let piece = self.image.get(start..)?;
+                    let mut body = index.to_le_bytes().to_vec();
+                    body.extend_from_slice(&piece[..piece.len().min(PIECE_SIZE)]);
+                    (start < self.image.len()).then(|| message(KIND_PIECE, &body))
+                })
+                .collect(),
+            KIND_DONE => {
+                self.done = true;
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the new host has the whole image.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    Missing,
+    Requested,
+    Received,
+}
+
+/// The new host's end of a transfer: asks for the image piece by piece
+/// and puts it back together. Time is passed in, in milliseconds from
+/// any fixed point.
+#[derive(Default)]
+pub struct MigrationReceiver {
+    /// Image length and hash, once offered
+    offer: Option<(usize, [u8; 32])>,
+    image: Vec<u8>,
+    pieces: Vec<Piece>,
+    /// When each requested piece was last asked for
+    requested_at: Vec<u64>,
+    /// Requests in the order they were made, with the time they were made
+    outstanding: VecDeque<(u32, u64)>,
+    in_flight: usize,
+    /// Pieces whose request timed out
+    retry: Vec<u32>,
+    /// First piece never asked for
+    next_new: u32,
+    received: usize,
+    last_hello: Option<u64>,
+    complete: bool,
+}
+
+impl MigrationReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Datagrams to send at time `now`: greetings until the old host
+    /// offers the image, then requests for pieces.
+    pub fn poll(&mut self, now: u64) -> Vec<Vec<u8>> {
+        if self.complete {
+            return Vec::new();
+        }
+        if self.offer.is_none() {
+            if self.last_hello.is_some_and(|at| now < at + RETRY_MS) {
+                return Vec::new();
+            }
+            self.last_hello = Some(now);
+            return vec![message(KIND_HELLO, &[])];
+        }
+
+        while let Some(&(index, at)) = self.outstanding.front() {
+            let i = index as usize;
+            if self.pieces[i] == Piece::Requested && self.requested_at[i] == at {
+                if now < at + RETRY_MS {
+                    break;
+                }
+                self.pieces[i] = Piece::Missing;
+                self.in_flight -= 1;
+                self.retry.push(index);
+            }
+            self.outstanding.pop_front();
+        }
+
+        let mut wanted = Vec::new();
+        while self.in_flight < FETCH_WINDOW {
+            let index = match self.retry.pop() {
+                Some(index) if self.pieces[index as usize] != Piece::Missing => continue,
+                Some(index) => index,
+                None if (self.next_new as usize) < self.pieces.len() => {
+                    self.next_new += 1;
+                    self.next_new - 1
+                }
+                None => break,
+            };
+            self.pieces[index as usize] = Piece::Requested;
+            self.requested_at[index as usize] = now;
+            self.outstanding.push_back((index, now));
+            self.in_flight += 1;
+            wanted.push(index);
+        }
+        wanted
+            .chunks(FETCH_BATCH)
+            .map(|batch| {
+                let body: Vec<u8> = batch.iter().flat_map(|i| i.to_le_bytes()).collect();
+                message(KIND_FETCH, &body)
+            })
+            .collect()
+    }
+
+    /// Take in the datagram `msg` from the old host, received at time
+    /// `now`, and return the datagrams to send in reply. Fails if the
+    /// image is complete but does not match its hash.
+    pub fn handle(&mut self, msg: &[u8], now: u64) -> Result<Vec<Vec<u8>>, String> {
+        let Some((kind, body)) = parse(msg) else {
+            return Ok(Vec::new());
+        };
+        match kind {
+            KIND_OFFER if self.offer.is_none() && body.len() == 40 => {
+                let len = u64::from_le_bytes(body[..8].try_into().unwrap());
+                // A 32-bit host cannot hold more than its address space
+                let len = usize::try_from(len)
+                    .ok()
+                    .filter(|_| len <= MAX_IMAGE_LEN)
+                    .ok_or_else(|| format!("offered image of {} bytes is too large", len))?;
+                let count = piece_count(len);
+                self.offer = Some((len, body[8..].try_into().unwrap()));
+                self.image = vec![0; len];
+                self.pieces = vec![Piece::Missing; count];
+                self.requested_at = vec![0; count];
+                if count == 0 {
+                    return self.finish();
+                }
+                Ok(self.poll(now))
+            }
+            KIND_PIECE if body.len() >= 4 => {
+                let Some((len, _)) = self.offer else {
+                    return Ok(Vec::new());
+                };
+                let index = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+                let data = &body[4..];
+                // The offset is only worked out for indices below the piece
+                // count, so it cannot overflow
+                if index >= self.pieces.len()
+                    || data.len() != PIECE_SIZE.min(len - index * PIECE_SIZE)
+                {
+                    log::warn!("[Migration] Ignoring malformed piece {}", index);
+                    return Ok(Vec::new());
+                }
+                let start = index * PIECE_SIZE;
+                if self.pieces[index] == Piece::Received {
+                    return Ok(Vec::new());
+                }
+                if self.pieces[index] == Piece::Requested {
+                    self.in_flight -= 1;
+                }
+                self.pieces[index] = Piece::Received;
+                self.image[start..start + data.len()].copy_from_slice(data);
+                self.received += 1;
+                if self.received == self.pieces.len() {
+                    return self.finish();
+                }
+                // Ask for more in batches rather than one per piece
+                if self.in_flight <= FETCH_WINDOW / 2 {
+                    return Ok(self.poll(now));
+                }
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        let (_, digest) = self.offer.expect("finished without an offer");
+        if Sha256::digest(&self.image)[..] != digest {
+            return Err("migration image does not match its hash".to_string());
+        }
+        self.complete = true;
+        Ok(vec![message(KIND_DONE, &[]); DONE_COPIES])
+    }
+
+    /// Pieces received and offered so far.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.received, self.pieces.len())
+    }
+
+    /// Whether the whole image arrived intact.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// The image, once complete.
+    pub fn into_image(self) -> Option<Vec<u8>> {
+        self.complete.then_some(self.image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE};
+    use crate::cpu::test_hart;
+    use crate::devices::virtio::device::{QUEUE_NUM_OFFSET, QUEUE_PFN_OFFSET, STATUS_OFFSET};
+    use crate::devices::virtio::{HotplugSlot, VirtioNet, VirtioRng};
+    use crate::net::DummyBackend;
+
+    fn machine() -> (Cpu, SystemBus) {
+        let (cpu, mut bus) = test_hart(&[]);
+        let nic = VirtioNet::with_mac(Box::new(DummyBackend::new()), [2, 0, 0, 0, 0, 7]);
+        bus.virtio_devices
+            .push(Box::new(HotplugSlot::with_device(Box::new(nic))));
+        bus.virtio_devices.push(Box::new(VirtioRng::new()));
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_capture_and_restore() {
+        let (mut cpu, bus) = machine();
+        cpu.regs[10] = 0x1234;
+        cpu.pc = DRAM_BASE + 0x40;
+        bus.write64(DRAM_BASE + 0x800, 0xfeed).unwrap();
+        let rng = &bus.virtio_devices[1];
+        rng.write(QUEUE_NUM_OFFSET, 8, &bus.dram).unwrap();
+        rng.write(QUEUE_PFN_OFFSET, 0x80010, &bus.dram).unwrap();
+        rng.write(STATUS_OFFSET, 0xf, &bus.dram).unwrap();
+        let image = capture(&cpu, &bus, Compression::Lz4).unwrap();
+
+        let (mut cpu2, bus2) = machine();
+        let header = restore(&image, &mut cpu2, &bus2).unwrap();
+        assert_eq!(header.mac, Some([2, 0, 0, 0, 0, 7]));
+        assert_eq!(header.ip, None);
+        assert_eq!((cpu2.pc, cpu2.regs[10]), (cpu.pc, 0x1234));
+        assert_eq!(bus2.read64(DRAM_BASE + 0x800).unwrap(), 0xfeed);
+        assert_eq!(bus2.virtio_states().unwrap(), bus.virtio_states().unwrap());
+
+        // A machine with other devices is refused before anything changes
+        let other = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        assert!(restore(&image, &mut Cpu::new(DRAM_BASE, 0), &other).is_err());
+        let mut swapped = SystemBus::new(DRAM_BASE, 1024 * 1024);
+        swapped.virtio_devices.push(Box::new(VirtioRng::new()));
+        swapped.virtio_devices.push(Box::new(VirtioRng::new()));
+        assert!(restore(&image, &mut Cpu::new(DRAM_BASE, 0), &swapped).is_err());
+    }
+
+    /// Move `image` from a sender to a receiver, dropping every datagram
+    /// for which `lose` is true, and return the received image and the
+    /// simulated time it took.
+    fn transfer(image: Vec<u8>, mut lose: impl FnMut(usize) -> bool) -> (Vec<u8>, u64) {
+        let mut sender = MigrationSender::new(image);
+        let mut receiver = MigrationReceiver::new();
+        let mut sent = 0;
+        let mut now = 0;
+        while !sender.is_done() {
+            assert!(now < 100 * RETRY_MS, "transfer stalled");
+            let mut to_sender = receiver.poll(now);
+            while !to_sender.is_empty() {
+                let mut to_receiver = Vec::new();
+                for msg in to_sender.drain(..) {
+                    sent += 1;
+                    if !lose(sent) {
+                        to_receiver.extend(sender.handle(&msg));
+                    }
+                }
+                for msg in to_receiver {
+                    sent += 1;
+                    if !lose(sent) {
+                        to_sender.extend(receiver.handle(&msg, now).unwrap());
+                    }
+                }
+            }
+            now += 10;
+        }
+        assert!(receiver.is_complete());
+        (receiver.into_image().unwrap(), now)
+    }
+
+    #[test]
+    fn test_transfer_survives_loss() {
+        let image: Vec<u8> = (0..FETCH_WINDOW * PIECE_SIZE * 3 + 100)
+            .map(|i| (i * 7 + i / 977) as u8)
+            .collect();
+        let (received, clean) = transfer(image.clone(), |_| false);
+        assert_eq!(received, image);
+        assert_eq!(clean, 10);
+
+        // A fifth of the datagrams, including greetings and the last
+        // DONEs, go missing
+        let (received, lossy) = transfer(image.clone(), |n| n % 5 == 0);
+        assert_eq!(received, image);
+        assert!(lossy > clean);
+    }
+
+    #[test]
+    fn test_corrupt_image_is_rejected() {
+        let mut sender = MigrationSender::new(vec![1; PIECE_SIZE + 1]);
+        let mut receiver = MigrationReceiver::new();
+        let mut requests = receiver.poll(0);
+        let offer = sender.handle(&requests.remove(0)).remove(0);
+        let fetch = receiver.handle(&offer, 0).unwrap();
+        let mut pieces = sender.handle(&fetch[0]);
+        assert_eq!(pieces.len(), 2);
+        // Wrong length for its index: ignored
+        assert!(receiver.handle(&pieces[0][..10], 0).unwrap().is_empty());
+        *pieces[1].last_mut().unwrap() = 2;
+        receiver.handle(&pieces[0], 0).unwrap();
+        assert!(receiver.handle(&pieces[1], 0).is_err());
+        assert!(!receiver.is_complete());
+    }
+
+    #[test]
+    fn test_bad_lengths_are_rejected() {
+        // An offer beyond the limit is refused rather than truncated
+        let mut body = (MAX_IMAGE_LEN + 1).to_le_bytes().to_vec();
+        body.extend([0; 32]);
+        let mut receiver = MigrationReceiver::new();
+        assert!(receiver.handle(&message(KIND_OFFER, &body), 0).is_err());
+
+        // So is a header running past the end of the image
+        let mut image = IMAGE_MAGIC.to_vec();
+        image.push(IMAGE_FORMAT);
+        image.extend(u32::MAX.to_le_bytes());
+        assert!(MigrationHeader::read(&image).is_err());
+    }
+}
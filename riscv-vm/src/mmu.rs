@@ -13,9 +13,11 @@ const PAGE_SIZE: u64 = 4096;
 const PTE_SIZE: u64 = 8;
 const MAX_LEVELS: usize = 4;
 
-/// TLB size (power of 2 for fast modulo)
-const TLB_SIZE: usize = 64;
-const TLB_MASK: usize = TLB_SIZE - 1;
+/// Sets in each TLB (power of 2 for fast modulo)
+const TLB_SETS: usize = 16;
+const TLB_SET_MASK: usize = TLB_SETS - 1;
+/// Ways in each set
+const TLB_WAYS: usize = 4;
 
 /// Permission bit masks for packed perm field
 pub const PERM_R: u8 = 1 << 0;
@@ -28,7 +30,10 @@ pub const PERM_G: u8 = 1 << 6; // Global mapping bit
 
 /// Compact, cache-friendly TLB entry structure.
 /// Memory layout: 8 + 8 + 2 + 1 + 1 + 1 + padding = 24 bytes
-/// Total TLB: 64 × 24 = 1.5KB (fits in L1 cache)
+/// Each TLB: 16 sets × 4 ways × 24 = 1.5KB (fits in L1 cache)
+///
+/// Entries cache one 4 KiB page; a superpage fills one entry per page used,
+/// each remembering its `level` so SFENCE.VMA can drop them together.
 #[derive(Clone, Copy, Debug)]
 pub struct TlbEntry {
     /// Virtual page number (upper bits of VA)
@@ -109,6 +114,20 @@ impl TlbEntry {
     pub fn set_d(&mut self) {
         self.perm |= PERM_D;
     }
+
+    /// Whether this entry translates `vpn` through the same leaf PTE, i.e.
+    /// `vpn` lies in the same (super)page.
+    #[inline(always)]
+    fn covers(&self, vpn: u64) -> bool {
+        let shift = 9 * self.level as u64;
+        self.valid && (self.vpn >> shift) == (vpn >> shift)
+    }
+
+    /// Whether the entry is usable in address space `asid`.
+    #[inline(always)]
+    fn in_asid(&self, asid: u64) -> bool {
+        self.global() || self.asid == asid as u16
+    }
 }
 
 impl Default for TlbEntry {
@@ -117,138 +136,187 @@ impl Default for TlbEntry {
     }
 }
 
-/// Direct-mapped TLB for fast virtual-to-physical address translation
+/// Hit and miss counters of one TLB.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    /// Lookups answered from the TLB.
+    pub hits: u64,
+    /// Lookups that needed a page-table walk.
+    pub misses: u64,
+    /// Full or partial flushes (SATP writes, SFENCE.VMA).
+    pub flushes: u64,
+}
+
+impl TlbStats {
+    /// Fraction of lookups that hit, or 0 before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total > 0 {
+            self.hits as f64 / total as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// One set-associative TLB with round-robin replacement.
+struct TlbArray {
+    sets: [[TlbEntry; TLB_WAYS]; TLB_SETS],
+    /// Next way to replace in each set when none is free.
+    victim: [u8; TLB_SETS],
+    stats: TlbStats,
+}
+
+impl TlbArray {
+    const fn new() -> Self {
+        Self {
+            sets: [[TlbEntry::EMPTY; TLB_WAYS]; TLB_SETS],
+            victim: [0; TLB_SETS],
+            stats: TlbStats {
+                hits: 0,
+                misses: 0,
+                flushes: 0,
+            },
+        }
+    }
+
+    #[inline(always)]
+    fn set_index(vpn: u64) -> usize {
+        (vpn as usize) & TLB_SET_MASK
+    }
+
+    #[inline(always)]
+    fn lookup(&mut self, vpn: u64, asid: u64) -> Option<TlbEntry> {
+        let set = &self.sets[Self::set_index(vpn)];
+        match set
+            .iter()
+            .find(|e| e.valid && e.vpn == vpn && e.in_asid(asid))
+        {
+            Some(&entry) => {
+                self.stats.hits += 1;
+                Some(entry)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Insert `entry`, replacing an entry for the same page, else a free
+    /// way, else the set's round-robin victim.
+    fn insert(&mut self, entry: TlbEntry) {
+        let index = Self::set_index(entry.vpn);
+        let set = &mut self.sets[index];
+        let way = set
+            .iter()
+            .position(|e| e.valid && e.vpn == entry.vpn && e.asid == entry.asid)
+            .or_else(|| set.iter().position(|e| !e.valid))
+            .unwrap_or_else(|| {
+                let way = self.victim[index] as usize;
+                self.victim[index] = ((way + 1) % TLB_WAYS) as u8;
+                way
+            });
+        set[way] = entry;
+    }
+
+    /// Invalidate every entry matching `pred`.
+    fn flush_where(&mut self, pred: impl Fn(&TlbEntry) -> bool) {
+        self.stats.flushes += 1;
+        for entry in self.sets.iter_mut().flatten() {
+            if entry.valid && pred(entry) {
+                entry.valid = false;
+            }
+        }
+    }
+}
+
+/// Software TLBs for fast virtual-to-physical address translation: an
+/// I-TLB for instruction fetches and a D-TLB for loads and stores, each
+/// 4-way set-associative and tagged with the ASID of the translation.
 pub struct Tlb {
-    entries: [TlbEntry; TLB_SIZE],
+    itlb: TlbArray,
+    dtlb: TlbArray,
 }
 
 impl Tlb {
     pub fn new() -> Self {
         Self {
-            entries: [TlbEntry::EMPTY; TLB_SIZE],
+            itlb: TlbArray::new(),
+            dtlb: TlbArray::new(),
         }
     }
 
-    /// Flush entire TLB (SFENCE.VMA with rs1=x0, rs2=x0)
+    #[inline(always)]
+    fn array(&mut self, access: AccessType) -> &mut TlbArray {
+        match access {
+            AccessType::Instruction => &mut self.itlb,
+            AccessType::Load | AccessType::Store => &mut self.dtlb,
+        }
+    }
+
+    /// Flush entire TLB (SFENCE.VMA with rs1=x0, rs2=x0, or a SATP write)
     #[inline]
     pub fn flush(&mut self) {
-        for entry in &mut self.entries {
-            entry.valid = false;
-        }
+        self.itlb.flush_where(|_| true);
+        self.dtlb.flush_where(|_| true);
     }
 
-    /// Flush by ASID (SFENCE.VMA with rs2!=x0)
+    /// Flush by ASID (SFENCE.VMA with rs1=x0, rs2!=x0)
     /// Global mappings are not flushed.
     #[inline]
     pub fn flush_asid(&mut self, asid: u64) {
-        let asid16 = asid as u16;
-        for entry in &mut self.entries {
-            if !entry.global() && entry.asid == asid16 {
-                entry.valid = false;
-            }
-        }
+        let pred = |e: &TlbEntry| !e.global() && e.asid == asid as u16;
+        self.itlb.flush_where(pred);
+        self.dtlb.flush_where(pred);
     }
 
-    /// Flush specific virtual address (SFENCE.VMA with rs1!=x0)
+    /// Flush the (super)page holding `va` in every address space
+    /// (SFENCE.VMA with rs1!=x0, rs2=x0)
     #[inline]
     pub fn flush_va(&mut self, va: u64) {
         let vpn = va >> 12;
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        let entry = unsafe { self.entries.get_unchecked_mut(idx) };
-
-        if entry.vpn == vpn {
-            entry.valid = false;
-        }
+        self.itlb.flush_where(|e| e.covers(vpn));
+        self.dtlb.flush_where(|e| e.covers(vpn));
     }
 
-    /// Flush specific page with ASID check (SFENCE.VMA with rs1!=x0, rs2!=x0)
+    /// Flush the (super)page holding virtual page `vpn` in address space
+    /// `asid`; global mappings are kept (SFENCE.VMA with rs1!=x0, rs2!=x0)
     #[inline]
     pub fn flush_page(&mut self, vpn: u64, asid: u64) {
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        let entry = unsafe { self.entries.get_unchecked_mut(idx) };
-
-        if entry.valid && entry.vpn == vpn {
-            // For page-specific flush, invalidate matching ASID mappings.
-            // Global mappings ignore ASID and are treated as matching.
-            let match_asid = entry.global() || entry.asid == asid as u16;
-            if match_asid {
-                entry.valid = false;
-            }
-        }
-    }
-
-    /// Look up a virtual page number in the TLB.
-    /// Returns reference to TlbEntry if found, None if miss.
-    #[inline(always)]
-    pub fn lookup(&self, vpn: u64, asid: u64) -> Option<&TlbEntry> {
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        let entry = unsafe { self.entries.get_unchecked(idx) };
-
-        // Hit if: valid AND VPN matches AND (entry is global OR ASID matches).
-        if entry.valid && entry.vpn == vpn && (entry.global() || entry.asid == asid as u16) {
-            Some(entry)
-        } else {
-            None
-        }
+        let pred = |e: &TlbEntry| e.covers(vpn) && !e.global() && e.asid == asid as u16;
+        self.itlb.flush_where(pred);
+        self.dtlb.flush_where(pred);
     }
 
-    /// Fast lookup returning just (ppn, perm) for common case
+    /// Look up a virtual page number in the TLB serving `access`, counting a
+    /// hit or a miss. Returns a copy of the entry if found.
     #[inline(always)]
-    pub fn lookup_fast(&self, vpn: u64, asid: u64) -> Option<(u64, u8)> {
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        let entry = unsafe { self.entries.get_unchecked(idx) };
-
-        if entry.valid && entry.vpn == vpn && (entry.global() || entry.asid == asid as u16) {
-            Some((entry.ppn, entry.perm))
-        } else {
-            None
-        }
+    pub fn lookup(&mut self, access: AccessType, vpn: u64, asid: u64) -> Option<TlbEntry> {
+        self.array(access).lookup(vpn, asid)
     }
 
-    /// Look up with level awareness (for superpages)
+    /// Insert a translation into the TLB serving `access`.
     #[inline]
-    pub fn lookup_with_level(&self, vpn: u64, asid: u64) -> Option<(u64, u8, u8)> {
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        let entry = unsafe { self.entries.get_unchecked(idx) };
+    pub fn insert(&mut self, access: AccessType, entry: TlbEntry) {
+        self.array(access).insert(entry);
+    }
 
-        if entry.valid && entry.vpn == vpn && (entry.global() || entry.asid == asid as u16) {
-            Some((entry.ppn, entry.perm, entry.level))
-        } else {
-            None
-        }
+    /// Counters of the I-TLB and the D-TLB.
+    pub fn stats(&self) -> (TlbStats, TlbStats) {
+        (self.itlb.stats, self.dtlb.stats)
     }
 
-    /// Insert a TLB entry. Overwrites any existing entry at the same index.
-    #[inline(always)]
-    pub fn insert(&mut self, entry: TlbEntry) {
-        let idx = (entry.vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        unsafe {
-            *self.entries.get_unchecked_mut(idx) = entry;
-        }
+    /// Zero the hit, miss and flush counters.
+    pub fn reset_stats(&mut self) {
+        self.itlb.stats = TlbStats::default();
+        self.dtlb.stats = TlbStats::default();
     }
+}
 
-    /// Insert a translation with explicit parameters.
-    /// Overwrites any existing entry at the same index (direct-mapped).
-    #[inline]
-    pub fn insert_translation(&mut self, vpn: u64, ppn: u64, perm: u8, level: u8, asid: u16) {
-        let idx = (vpn as usize) & TLB_MASK;
-        // SAFETY: idx is always < TLB_SIZE due to the bitmask
-        unsafe {
-            *self.entries.get_unchecked_mut(idx) = TlbEntry {
-                vpn,
-                ppn,
-                asid,
-                perm,
-                level,
-                valid: true,
-            };
-        }
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -309,16 +377,16 @@ pub fn translate(
 
     let vpn_full = (addr >> 12) & vpn_full_mask;
 
-    // TLB hit path.
-    if let Some(entry) = tlb.lookup(vpn_full, current_asid) {
-        if check_permission_tlb(mode, mstatus, entry, access_type) {
-            // For now we do not lazily update A/D on TLB hits – page table
-            // entries are already marked by the walk that inserted this entry.
+    // TLB hit path. A/D were set in memory by the walk that inserted the
+    // entry; the first store through a clean entry walks again to set D.
+    if let Some(entry) = tlb.lookup(access_type, vpn_full, current_asid) {
+        if !check_permission_tlb(mode, mstatus, &entry, access_type) {
+            return Err(page_fault(access_type, addr));
+        }
+        if access_type != AccessType::Store || entry.d() {
             let offset = addr & 0xFFF;
             let pa = (entry.ppn << 12) | offset;
             return Ok(pa);
-        } else {
-            return Err(page_fault(access_type, addr));
         }
     }
 
@@ -434,7 +502,7 @@ pub fn translate(
         let result_ppn = (ppn & !vpn_mask) | ((addr >> 12) & vpn_mask);
 
         entry.ppn = result_ppn;
-        tlb.insert(entry);
+        tlb.insert(access_type, entry);
 
         let pa = (result_ppn << 12) | offset_in_page;
         return Ok(pa);
@@ -443,6 +511,45 @@ pub fn translate(
     Err(page_fault(access_type, addr))
 }
 
+/// Translate `addr` under `satp` for a debugger.
+///
+/// Unlike [`translate`], this ignores permissions and leaves the TLB and
+/// the A/D bits alone, so inspecting guest memory does not change it.
+/// Returns `None` if `addr` is not mapped.
+pub fn translate_debug(bus: &dyn Bus, satp: u64, addr: u64) -> Option<u64> {
+    let (levels, va_bits) = match (satp >> 60) & 0xF {
+        8 => (3, 39),
+        9 => (4, 48),
+        _ => return Some(addr),
+    };
+    let upper = addr >> (va_bits - 1);
+    if upper != 0 && upper != u64::MAX >> (va_bits - 1) {
+        return None;
+    }
+
+    let mut a = (satp & ((1u64 << 44) - 1)) * PAGE_SIZE;
+    for i in (0..levels).rev() {
+        let vpn = (addr >> (12 + 9 * i as u64)) & 0x1FF;
+        let pte = bus.load(a + vpn * PTE_SIZE, 8).ok()?;
+        let (v, r, w, x) = (pte & 1, (pte >> 1) & 1, (pte >> 2) & 1, (pte >> 3) & 1);
+        if v == 0 || (r == 0 && w == 1) {
+            return None;
+        }
+        let ppn = (pte >> 10) & 0xFFF_FFFF_FFFF;
+        if r == 0 && x == 0 {
+            a = ppn * PAGE_SIZE;
+            continue;
+        }
+        let vpn_mask = (1 << (9 * i)) - 1;
+        if ppn & vpn_mask != 0 {
+            return None;
+        }
+        let ppn = ppn | ((addr >> 12) & vpn_mask);
+        return Some((ppn << 12) | (addr & 0xFFF));
+    }
+    None
+}
+
 #[inline(always)]
 fn check_permission_tlb(
     mode: Mode,
@@ -502,3 +609,179 @@ fn access_fault(access_type: AccessType, addr: u64) -> Trap {
         AccessType::Store => Trap::StoreAccessFault(addr),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DRAM_BASE, SystemBus};
+
+    const ROOT: u64 = DRAM_BASE + 0x1_0000;
+    const L1: u64 = DRAM_BASE + 0x1_1000;
+    const L0: u64 = DRAM_BASE + 0x1_2000;
+    const V: u64 = 1;
+    const RW: u64 = 0b110;
+    const A: u64 = 1 << 6;
+    const D: u64 = 1 << 7;
+    const G: u64 = 1 << 5;
+
+    fn pte(pa: u64, flags: u64) -> u64 {
+        ((pa >> 12) << 10) | flags
+    }
+
+    /// Sv39 tables for the low 2 MiB of the address space (4 KiB pages)
+    /// and a 2 MiB superpage at 0x20_0000.
+    fn setup() -> SystemBus {
+        let bus = SystemBus::new(DRAM_BASE, 16 * 1024 * 1024);
+        bus.write64(ROOT, pte(L1, V)).unwrap();
+        bus.write64(L1, pte(L0, V)).unwrap();
+        bus.write64(L1 + 8, pte(DRAM_BASE + 0x20_0000, V | RW | A | D))
+            .unwrap();
+        bus
+    }
+
+    fn map(bus: &SystemBus, va: u64, pa: u64, flags: u64) {
+        bus.write64(L0 + (va >> 12) * 8, pte(pa, V | flags))
+            .unwrap();
+    }
+
+    fn satp(asid: u64) -> u64 {
+        (8 << 60) | (asid << 44) | (ROOT >> 12)
+    }
+
+    fn load(bus: &SystemBus, tlb: &mut Tlb, asid: u64, va: u64) -> Result<u64, Trap> {
+        translate(
+            bus,
+            tlb,
+            Mode::Supervisor,
+            satp(asid),
+            0,
+            va,
+            AccessType::Load,
+        )
+    }
+
+    #[test]
+    fn test_hits_misses_and_flush_va() {
+        let bus = setup();
+        let mut tlb = Tlb::new();
+        map(&bus, 0x1000, DRAM_BASE + 0x4_0000, RW | A | D);
+        map(&bus, 0x2000, DRAM_BASE + 0x5_0000, RW | A | D);
+
+        assert_eq!(load(&bus, &mut tlb, 0, 0x1008), Ok(DRAM_BASE + 0x4_0008));
+        assert_eq!(load(&bus, &mut tlb, 0, 0x1010), Ok(DRAM_BASE + 0x4_0010));
+        assert_eq!(load(&bus, &mut tlb, 0, 0x2000), Ok(DRAM_BASE + 0x5_0000));
+        let (itlb, dtlb) = tlb.stats();
+        assert_eq!((dtlb.hits, dtlb.misses), (1, 2));
+        assert_eq!(itlb, TlbStats::default());
+
+        // A remapped page keeps its stale translation until SFENCE.VMA
+        map(&bus, 0x1000, DRAM_BASE + 0x6_0000, RW | A | D);
+        assert_eq!(load(&bus, &mut tlb, 0, 0x1000), Ok(DRAM_BASE + 0x4_0000));
+        tlb.flush_va(0x1000);
+        assert_eq!(load(&bus, &mut tlb, 0, 0x1000), Ok(DRAM_BASE + 0x6_0000));
+        // Other pages stay cached
+        load(&bus, &mut tlb, 0, 0x2000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 3);
+    }
+
+    #[test]
+    fn test_asid_tagging() {
+        let bus = setup();
+        let mut tlb = Tlb::new();
+        map(&bus, 0x1000, DRAM_BASE + 0x4_0000, RW | A | D);
+        map(&bus, 0x2000, DRAM_BASE + 0x5_0000, RW | A | D | G);
+
+        load(&bus, &mut tlb, 1, 0x1000).unwrap();
+        load(&bus, &mut tlb, 1, 0x2000).unwrap();
+        // Another address space misses on its private page only
+        load(&bus, &mut tlb, 2, 0x1000).unwrap();
+        load(&bus, &mut tlb, 2, 0x2000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 3);
+
+        // Flushing ASID 1 keeps ASID 2 and the global page
+        tlb.flush_asid(1);
+        load(&bus, &mut tlb, 2, 0x1000).unwrap();
+        load(&bus, &mut tlb, 1, 0x2000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 3);
+        load(&bus, &mut tlb, 1, 0x1000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 4);
+
+        // An ASID-specific page flush keeps global mappings
+        tlb.flush_page(0x2, 1);
+        load(&bus, &mut tlb, 1, 0x2000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 4);
+    }
+
+    #[test]
+    fn test_flush_va_drops_whole_superpage() {
+        let bus = setup();
+        let mut tlb = Tlb::new();
+        assert_eq!(
+            load(&bus, &mut tlb, 0, 0x20_0010),
+            Ok(DRAM_BASE + 0x20_0010)
+        );
+        assert_eq!(
+            load(&bus, &mut tlb, 0, 0x20_5000),
+            Ok(DRAM_BASE + 0x20_5000)
+        );
+        tlb.flush_va(0x3F_F000);
+        load(&bus, &mut tlb, 0, 0x20_0010).unwrap();
+        load(&bus, &mut tlb, 0, 0x20_5000).unwrap();
+        assert_eq!(tlb.stats().1.misses, 4);
+    }
+
+    #[test]
+    fn test_store_through_clean_entry_sets_dirty() {
+        let bus = setup();
+        let mut tlb = Tlb::new();
+        map(&bus, 0x1000, DRAM_BASE + 0x4_0000, RW | A);
+
+        load(&bus, &mut tlb, 0, 0x1000).unwrap();
+        assert_eq!(bus.read64(L0 + 8).unwrap() & D, 0);
+        let store = translate(
+            &bus,
+            &mut tlb,
+            Mode::Supervisor,
+            satp(0),
+            0,
+            0x1000,
+            AccessType::Store,
+        );
+        assert_eq!(store, Ok(DRAM_BASE + 0x4_0000));
+        assert_ne!(bus.read64(L0 + 8).unwrap() & D, 0);
+    }
+
+    #[test]
+    fn test_fetches_use_the_itlb() {
+        let bus = setup();
+        let mut tlb = Tlb::new();
+        map(&bus, 0x1000, DRAM_BASE + 0x4_0000, 0b1010 | A);
+
+        for _ in 0..3 {
+            translate(
+                &bus,
+                &mut tlb,
+                Mode::Supervisor,
+                satp(0),
+                0,
+                0x1000,
+                AccessType::Instruction,
+            )
+            .unwrap();
+        }
+        let (itlb, dtlb) = tlb.stats();
+        assert_eq!((itlb.hits, itlb.misses), (2, 1));
+        assert_eq!(dtlb, TlbStats::default());
+
+        // Set-associative: pages sharing a set stay resident together
+        for page in 0..TLB_WAYS as u64 {
+            let va = (page * TLB_SETS as u64 + 2) << 12;
+            map(&bus, va, DRAM_BASE + 0x8_0000, RW | A | D);
+            load(&bus, &mut tlb, 0, va).unwrap();
+        }
+        for page in 0..TLB_WAYS as u64 {
+            load(&bus, &mut tlb, 0, (page * TLB_SETS as u64 + 2) << 12).unwrap();
+        }
+        assert_eq!(tlb.stats().1.misses, TLB_WAYS as u64);
+    }
+}
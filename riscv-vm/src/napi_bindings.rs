@@ -1,10 +1,29 @@
 //! Node.js native addon bindings via napi-rs.
 //!
 //! This module exposes WebTransport client functionality to Node.js,
-//! reusing the existing native WebTransport implementation.
+//! reusing the existing native WebTransport implementation, and a
+//! single-hart [`NodeVm`] that server-side embedders step from JavaScript
+//! with the same capabilities as the wasm build: snapshots, register,
+//! CSR and memory access, breakpoints, network frame injection and
+//! output/shutdown callbacks.
 
+use crate::Trap;
+use crate::bus::{BusConfig, DRAM_BASE};
+use crate::cpu::{CsrPolicy, Mode, TrapBreak, TrapBreakHit};
+use crate::devices::rtc::DETERMINISTIC_EPOCH_NS;
+use crate::devices::virtio::{VirtioBlock, VirtioNet};
+use crate::engine::decoder::Register;
+use crate::net::external::{ExternalBackendWrapper, ExternalNetworkBackend};
+use crate::snapshot::Snapshot;
+use crate::vm::emulator::Emulator;
+use crate::vm::trap_info::TrapInfo;
 use napi_derive::napi;
 use napi_rs::bindgen_prelude::*;
+use napi_rs::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi_rs::{Env, JsFunction};
+use std::collections::BTreeSet;
 
 // Re-export napi for the macro to find
 use napi_rs as napi;
@@ -525,3 +544,645 @@ impl WebTransportClient {
         log::info!("[WebTransport] Shutdown signaled");
     }
 }
+
+// ============================================================================
+// Virtual machine
+// ============================================================================
+
+/// Instructions between device polls, as in the browser build.
+const POLL_INTERVAL: u32 = 100;
+
+/// Convert a JavaScript `BigInt` to a 64-bit address or register value.
+fn bigint_to_u64(value: BigInt) -> Result<u64> {
+    match value.get_u64() {
+        (false, value, true) => Ok(value),
+        _ => Err(Error::from_reason(
+            "value must fit in an unsigned 64-bit integer",
+        )),
+    }
+}
+
+/// Parse a trap breakpoint condition: `"trap"`, `"exception:<cause>"` or
+/// `"ecall:<M|S|U>"`.
+fn parse_trap_break(condition: &str) -> Result<TrapBreak> {
+    let invalid = || Error::from_reason(format!("invalid break condition '{}'", condition));
+    match condition.split_once(':') {
+        None if condition == "trap" => Ok(TrapBreak::NextTrap),
+        Some(("exception", cause)) => cause
+            .parse()
+            .map(TrapBreak::Exception)
+            .map_err(|_| invalid()),
+        Some(("ecall", mode)) => mode
+            .parse::<Mode>()
+            .map(TrapBreak::Ecall)
+            .map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Performance counters returned by [`NodeVm::perf_counters`].
+#[napi(object)]
+pub struct PerfCounterValues {
+    /// Guest-visible `mcycle`.
+    pub cycles: BigInt,
+    /// Guest-visible `minstret`.
+    pub instret: BigInt,
+    /// Instructions run by the interpreter since the last reset.
+    pub interp_instret: BigInt,
+    /// Instructions run by interpreted blocks since the last reset.
+    pub block_instret: BigInt,
+    /// Instructions run by native JIT code since the last reset.
+    pub jit_instret: BigInt,
+    /// Exceptions and interrupts taken since the last reset.
+    pub traps: BigInt,
+}
+
+/// A latched trap breakpoint, as reported to JavaScript.
+#[napi(object)]
+pub struct BreakHit {
+    /// Exception or interrupt code, as written to `mcause`/`scause`.
+    pub cause: BigInt,
+    pub interrupt: bool,
+    /// PC of the trapping instruction.
+    pub epc: BigInt,
+    /// PC of the trap handler, where the hart is paused.
+    pub handler_pc: BigInt,
+    /// Privilege mode the trap was taken from ("M", "S" or "U").
+    pub from_mode: String,
+}
+
+impl From<&TrapBreakHit> for BreakHit {
+    fn from(hit: &TrapBreakHit) -> Self {
+        Self {
+            cause: BigInt::from(hit.cause),
+            interrupt: hit.is_interrupt,
+            epc: BigInt::from(hit.epc),
+            handler_pc: BigInt::from(hit.handler_pc),
+            from_mode: hit.from_mode.to_string(),
+        }
+    }
+}
+
+/// The error a VM halted on, as reported to JavaScript.
+#[napi(object)]
+pub struct TrapReport {
+    /// Trap name, e.g. "LoadPageFault", or "Fatal" for emulator errors.
+    pub name: String,
+    /// Human-readable description.
+    pub message: String,
+    /// Exception or interrupt code, absent for emulator errors.
+    pub cause: Option<BigInt>,
+    pub interrupt: bool,
+    pub tval: BigInt,
+    /// Privilege mode the hart was in ("M", "S" or "U").
+    pub mode: String,
+    /// PC of the faulting instruction.
+    pub pc: BigInt,
+    /// Encoding of the instruction at `pc`, if it could be read.
+    pub insn: Buffer,
+    /// Whether the guest took the trap.
+    pub taken: bool,
+}
+
+impl From<&TrapInfo> for TrapReport {
+    fn from(info: &TrapInfo) -> Self {
+        Self {
+            name: info.name(),
+            message: info.to_string(),
+            cause: info.cause.map(BigInt::from),
+            interrupt: info.interrupt,
+            tval: BigInt::from(info.tval),
+            mode: info.mode.to_string(),
+            pc: BigInt::from(info.pc),
+            insn: info.insn.clone().into(),
+            taken: info.taken,
+        }
+    }
+}
+
+/// Single-hart RISC-V VM for Node.js.
+///
+/// JavaScript drives execution with `step`/`stepN`; devices are polled every
+/// 100 instructions. Console output and shutdown are reported through the
+/// callbacks registered with `onOutput` and `onShutdown`, which run on the
+/// event loop after the current call returns.
+#[napi]
+pub struct NodeVm {
+    emu: Emulator,
+    halted: bool,
+    halt_code: u64,
+    /// The error the VM halted on, if any
+    fatal: Option<TrapInfo>,
+    poll_counter: u32,
+    /// PCs execution stops at before running the instruction there
+    breakpoints: BTreeSet<u64>,
+    /// External network backend bridged from JavaScript, if set up
+    external_net: Option<Arc<ExternalNetworkBackend>>,
+    on_output: Option<ThreadsafeFunction<Vec<u8>, ErrorStrategy::Fatal>>,
+    on_shutdown: Option<ThreadsafeFunction<u64, ErrorStrategy::Fatal>>,
+}
+
+#[napi]
+impl NodeVm {
+    /// Create a VM and boot a kernel (ELF or raw binary).
+    ///
+    /// @param kernel - Kernel image
+    /// @param memoryMib - DRAM size in MiB (default 512)
+    #[napi(constructor)]
+    pub fn new(kernel: Buffer, memory_mib: Option<u32>) -> Result<Self> {
+        let config = match memory_mib {
+            Some(mib) => BusConfig::with_dram(DRAM_BASE, (mib as usize) << 20),
+            None => BusConfig::default(),
+        };
+        config.validate().map_err(Error::from_reason)?;
+        let mut emu = Emulator::with_config(config);
+        emu.bus
+            .boot_rom
+            .set_fdt(crate::devices::fdt::generate(&config, 1, ""))
+            .map_err(Error::from_reason)?;
+        emu.load_kernel(&kernel).map_err(Error::from_reason)?;
+
+        Ok(Self {
+            emu,
+            halted: false,
+            halt_code: 0,
+            fatal: None,
+            poll_counter: 0,
+            breakpoints: BTreeSet::new(),
+            external_net: None,
+            on_output: None,
+            on_shutdown: None,
+        })
+    }
+
+    /// Load a disk image and attach it as a VirtIO block device.
+    #[napi]
+    pub fn load_disk(&mut self, disk: Buffer) {
+        let vblk = VirtioBlock::new(disk.to_vec());
+        self.emu.bus.virtio_devices.push(Box::new(vblk));
+    }
+
+    /// Set the modelled CPU clock in Hz (100 MHz by default).
+    #[napi]
+    pub fn set_cpu_frequency(&self, hz: u32) {
+        self.emu.bus.clint.set_cpu_frequency(hz as u64);
+    }
+
+    /// Make the RTC count guest time from 2000-01-01 instead of reporting
+    /// the host's date, so runs do not depend on when or how fast they go.
+    #[napi]
+    pub fn set_deterministic_time(&self, enabled: bool) {
+        self.emu
+            .bus
+            .rtc
+            .set_deterministic(enabled.then_some(DETERMINISTIC_EPOCH_NS));
+    }
+
+    /// Raise an illegal-instruction exception on CSRs the hart does not
+    /// implement, instead of reading them as zero and ignoring writes.
+    #[napi]
+    pub fn set_strict_csrs(&mut self, enabled: bool) {
+        self.emu.cpu.set_csr_policy(if enabled {
+            CsrPolicy::Strict
+        } else {
+            CsrPolicy::Permissive
+        });
+    }
+
+    // ------------------------------------------------------------------
+    // Execution
+    // ------------------------------------------------------------------
+
+    /// Execute one instruction. Returns false if the VM has halted or is
+    /// paused at a trap breakpoint.
+    #[napi]
+    pub fn step(&mut self) -> bool {
+        let running = self.step_one();
+        self.flush_output();
+        running
+    }
+
+    /// Execute up to `count` instructions and return how many ran.
+    ///
+    /// Stops early when the VM halts, a trap breakpoint fires, or the next
+    /// instruction is at a PC breakpoint (other than the one execution
+    /// starts from, so calling `stepN` again continues past it).
+    #[napi]
+    pub fn step_n(&mut self, count: u32) -> u32 {
+        let mut executed = 0;
+        while executed < count {
+            if executed > 0 && self.breakpoints.contains(&self.emu.cpu.pc) {
+                break;
+            }
+            if !self.step_one() {
+                break;
+            }
+            executed += 1;
+        }
+        self.flush_output();
+        executed
+    }
+
+    fn step_one(&mut self) -> bool {
+        if self.halted || self.emu.cpu.break_hit().is_some() {
+            return false;
+        }
+
+        self.poll_counter = self.poll_counter.wrapping_add(1);
+        if self.poll_counter.is_multiple_of(POLL_INTERVAL) {
+            self.emu.bus.poll_virtio();
+            self.flush_output();
+        }
+
+        match self.emu.cpu.step(&self.emu.bus) {
+            Ok(()) => {}
+            Err(Trap::RequestedTrap(code)) => {
+                self.halt(code);
+                return false;
+            }
+            Err(trap @ Trap::Fatal(_)) => {
+                let info = TrapInfo::capture(&trap, &self.emu.cpu, &self.emu.bus);
+                log::error!("[VM] Fatal error: {}", info);
+                self.fatal = Some(info);
+                self.halt(0xDEAD);
+                return false;
+            }
+            Err(_trap) => {
+                // Architectural traps handled by CPU
+            }
+        }
+        self.emu.cpu.break_hit().is_none()
+    }
+
+    fn halt(&mut self, code: u64) {
+        self.halted = true;
+        self.halt_code = code;
+        self.flush_output();
+        if let Some(callback) = &self.on_shutdown {
+            callback.call(code, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    }
+
+    /// Check if the VM has halted (e.g., due to shutdown command).
+    #[napi]
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The halt code if the VM has halted (0x5555 is a clean shutdown).
+    #[napi]
+    pub fn halt_code(&self) -> BigInt {
+        BigInt::from(self.halt_code)
+    }
+
+    /// The error the VM halted on, if it hit one.
+    #[napi]
+    pub fn fatal_error(&self) -> Option<TrapReport> {
+        self.fatal.as_ref().map(TrapReport::from)
+    }
+
+    /// Cycles executed by the hart.
+    #[napi]
+    pub fn cycles(&self) -> BigInt {
+        BigInt::from(self.emu.cpu.cycles)
+    }
+
+    /// The hart's performance counters.
+    #[napi]
+    pub fn perf_counters(&self) -> PerfCounterValues {
+        let counters = self.emu.cpu.perf_counters();
+        PerfCounterValues {
+            cycles: BigInt::from(counters.cycles),
+            instret: BigInt::from(counters.instret),
+            interp_instret: BigInt::from(counters.interp_instret),
+            block_instret: BigInt::from(counters.block_instret),
+            jit_instret: BigInt::from(counters.jit_instret),
+            traps: BigInt::from(counters.traps),
+        }
+    }
+
+    /// Zero the performance counters, including the guest's `mcycle` and
+    /// `minstret`.
+    #[napi]
+    pub fn reset_perf_counters(&mut self) {
+        self.emu.cpu.reset_perf_counters();
+    }
+
+    // ------------------------------------------------------------------
+    // Registers and memory
+    // ------------------------------------------------------------------
+
+    #[napi]
+    pub fn pc(&self) -> BigInt {
+        BigInt::from(self.emu.cpu.pc)
+    }
+
+    #[napi]
+    pub fn set_pc(&mut self, pc: BigInt) -> Result<()> {
+        self.emu.cpu.pc = bigint_to_u64(pc)?;
+        Ok(())
+    }
+
+    /// Current privilege mode ("M", "S" or "U").
+    #[napi]
+    pub fn mode(&self) -> String {
+        self.emu.cpu.mode.to_string()
+    }
+
+    /// Read integer register `x<index>`.
+    #[napi]
+    pub fn read_reg(&self, index: u32) -> Result<BigInt> {
+        if index >= 32 {
+            return Err(Error::from_reason(format!("no register x{}", index)));
+        }
+        Ok(BigInt::from(
+            self.emu.cpu.read_reg(Register::from_u32(index)),
+        ))
+    }
+
+    /// Write integer register `x<index>` (writes to x0 are ignored).
+    #[napi]
+    pub fn write_reg(&mut self, index: u32, value: BigInt) -> Result<()> {
+        if index >= 32 {
+            return Err(Error::from_reason(format!("no register x{}", index)));
+        }
+        let value = bigint_to_u64(value)?;
+        self.emu.cpu.write_reg(Register::from_u32(index), value);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn read_csr(&self, addr: u32) -> Result<BigInt> {
+        self.emu
+            .cpu
+            .read_csr(addr as u16)
+            .map(BigInt::from)
+            .map_err(|trap| Error::from_reason(format!("cannot read CSR {:#x}: {:?}", addr, trap)))
+    }
+
+    #[napi]
+    pub fn write_csr(&mut self, addr: u32, value: BigInt) -> Result<()> {
+        let value = bigint_to_u64(value)?;
+        self.emu
+            .cpu
+            .write_csr(addr as u16, value)
+            .map_err(|trap| Error::from_reason(format!("cannot write CSR {:#x}: {:?}", addr, trap)))
+    }
+
+    /// Read `len` bytes of physical memory (DRAM only).
+    #[napi]
+    pub fn read_memory(&self, addr: BigInt, len: u32) -> Result<Buffer> {
+        let bytes = self
+            .emu
+            .read_memory(bigint_to_u64(addr)?, len as usize)
+            .map_err(Error::from_reason)?;
+        Ok(bytes.into())
+    }
+
+    /// Write bytes to physical memory (DRAM only).
+    #[napi]
+    pub fn write_memory(&mut self, addr: BigInt, data: Buffer) -> Result<()> {
+        self.emu
+            .write_memory(bigint_to_u64(addr)?, &data)
+            .map_err(Error::from_reason)
+    }
+
+    /// Fill a buffer of `len` bytes from physical memory (DRAM only).
+    #[napi]
+    pub fn read_phys(&self, paddr: BigInt, len: u32) -> Result<Buffer> {
+        self.read_memory(paddr, len)
+    }
+
+    /// Write bytes to physical memory (DRAM only).
+    #[napi]
+    pub fn write_phys(&mut self, paddr: BigInt, data: Buffer) -> Result<()> {
+        self.write_memory(paddr, data)
+    }
+
+    /// Read `len` bytes at virtual address `vaddr`, translated with `satp`,
+    /// or with the hart's current address space if omitted.
+    #[napi]
+    pub fn read_virt(&self, vaddr: BigInt, len: u32, satp: Option<BigInt>) -> Result<Buffer> {
+        let satp = satp.map(bigint_to_u64).transpose()?;
+        let mut buf = vec![0; len as usize];
+        self.emu
+            .read_virt(bigint_to_u64(vaddr)?, &mut buf, satp.into())
+            .map_err(Error::from_reason)?;
+        Ok(buf.into())
+    }
+
+    /// Write bytes at virtual address `vaddr`, translated like `readVirt`.
+    #[napi]
+    pub fn write_virt(&mut self, vaddr: BigInt, data: Buffer, satp: Option<BigInt>) -> Result<()> {
+        let satp = satp.map(bigint_to_u64).transpose()?;
+        self.emu
+            .write_virt(bigint_to_u64(vaddr)?, &data, satp.into())
+            .map_err(Error::from_reason)
+    }
+
+    // ------------------------------------------------------------------
+    // Snapshots
+    // ------------------------------------------------------------------
+
+    /// Capture the VM state (hart, CLINT, PLIC, UART and DRAM).
+    #[napi]
+    pub fn snapshot(&self) -> Result<Buffer> {
+        let bytes = self.emu.snapshot().to_bytes().map_err(Error::from_reason)?;
+        Ok(bytes.into())
+    }
+
+    /// Restore a snapshot taken with `snapshot`. The DRAM size must match.
+    #[napi]
+    pub fn restore_snapshot(&mut self, snapshot: Buffer) -> Result<()> {
+        let snapshot = Snapshot::from_bytes(&snapshot).map_err(Error::from_reason)?;
+        self.emu
+            .apply_snapshot(&snapshot)
+            .map_err(Error::from_reason)?;
+        self.halted = false;
+        self.halt_code = 0;
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------
+    // Breakpoints
+    // ------------------------------------------------------------------
+
+    /// Stop `stepN` before executing the instruction at `pc`.
+    #[napi]
+    pub fn add_breakpoint(&mut self, pc: BigInt) -> Result<()> {
+        self.breakpoints.insert(bigint_to_u64(pc)?);
+        Ok(())
+    }
+
+    /// Remove a PC breakpoint. Returns false if none was set at `pc`.
+    #[napi]
+    pub fn remove_breakpoint(&mut self, pc: BigInt) -> Result<bool> {
+        Ok(self.breakpoints.remove(&bigint_to_u64(pc)?))
+    }
+
+    /// PC breakpoints, in ascending order.
+    #[napi]
+    pub fn breakpoints(&self) -> Vec<BigInt> {
+        self.breakpoints
+            .iter()
+            .map(|&pc| BigInt::from(pc))
+            .collect()
+    }
+
+    /// Pause the next time a matching trap is taken: `"trap"` for any,
+    /// `"exception:<cause>"`, or `"ecall:<M|S|U>"`.
+    #[napi]
+    pub fn break_on(&mut self, condition: String) -> Result<()> {
+        self.emu.break_on(parse_trap_break(&condition)?);
+        Ok(())
+    }
+
+    /// Disarm all trap breakpoints.
+    #[napi]
+    pub fn clear_trap_breaks(&mut self) {
+        self.emu.cpu.clear_trap_breaks();
+    }
+
+    /// The trap breakpoint the hart is paused at, if any.
+    #[napi]
+    pub fn break_hit(&self) -> Option<BreakHit> {
+        self.emu.cpu.break_hit().map(BreakHit::from)
+    }
+
+    /// Clear a latched trap breakpoint so execution can continue.
+    #[napi]
+    pub fn resume(&mut self) -> Option<BreakHit> {
+        self.emu.resume().as_ref().map(BreakHit::from)
+    }
+
+    // ------------------------------------------------------------------
+    // Console and events
+    // ------------------------------------------------------------------
+
+    /// Queue input bytes for the UART.
+    #[napi]
+    pub fn input(&self, data: Buffer) {
+        self.emu.bus.uart.push_input_bytes(&data);
+    }
+
+    /// Take all pending UART output. Empty while an `onOutput` callback is
+    /// set, which receives the output instead.
+    #[napi]
+    pub fn drain_output(&self) -> Buffer {
+        self.emu.bus.uart.drain_output().into()
+    }
+
+    /// Call `callback(buffer)` with new UART output as it is produced.
+    #[napi]
+    pub fn on_output(&mut self, env: Env, callback: JsFunction) -> Result<()> {
+        let mut callback: ThreadsafeFunction<Vec<u8>, ErrorStrategy::Fatal> = callback
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Vec<u8>>| {
+                Ok(vec![ctx.env.create_buffer_with_data(ctx.value)?.into_raw()])
+            })?;
+        // Pending callbacks must not keep the process alive on their own
+        callback.unref(&env)?;
+        self.on_output = Some(callback);
+        Ok(())
+    }
+
+    /// Call `callback(code)` once the guest requests shutdown, with the
+    /// halt code as a `BigInt`.
+    #[napi]
+    pub fn on_shutdown(&mut self, env: Env, callback: JsFunction) -> Result<()> {
+        let mut callback: ThreadsafeFunction<u64, ErrorStrategy::Fatal> = callback
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<u64>| {
+                Ok(vec![BigInt::from(ctx.value)])
+            })?;
+        callback.unref(&env)?;
+        self.on_shutdown = Some(callback);
+        Ok(())
+    }
+
+    /// Hand pending output to the output callback, if one is set.
+    fn flush_output(&mut self) {
+        if let Some(callback) = &self.on_output {
+            let output = self.emu.bus.uart.drain_output();
+            if !output.is_empty() {
+                callback.call(output, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        }
+    }
+
+    /// Raise external interrupt line `irq` (1-31) for a host-modelled device.
+    #[napi]
+    pub fn raise_irq(&self, irq: u32) -> Result<()> {
+        self.emu.bus.raise_irq(irq).map_err(Error::from_reason)
+    }
+
+    /// Lower an external interrupt line raised with `raiseIrq`.
+    #[napi]
+    pub fn clear_irq(&self, irq: u32) -> Result<()> {
+        self.emu.bus.clear_irq(irq).map_err(Error::from_reason)
+    }
+
+    // ------------------------------------------------------------------
+    // Network
+    // ------------------------------------------------------------------
+
+    /// Attach a VirtIO network device whose frames are exchanged with
+    /// JavaScript, e.g. through a `WebTransportClient`.
+    ///
+    /// @param mac - MAC address as 6 bytes
+    #[napi]
+    pub fn setup_external_network(&mut self, mac: Buffer) -> Result<()> {
+        let mac: [u8; 6] = mac
+            .as_ref()
+            .try_into()
+            .map_err(|_| Error::from_reason("MAC address must be 6 bytes"))?;
+        let backend = Arc::new(ExternalNetworkBackend::new(mac));
+        self.external_net = Some(backend.clone());
+        let wrapper = ExternalBackendWrapper { inner: backend };
+        self.emu
+            .bus
+            .virtio_devices
+            .push(Box::new(VirtioNet::new(Box::new(wrapper))));
+        self.emu.bus.buildinfo.set_network(Some("external"));
+        Ok(())
+    }
+
+    /// Queue a frame for the guest. Returns false without a network device.
+    #[napi]
+    pub fn inject_network_packet(&self, packet: Buffer) -> bool {
+        match &self.external_net {
+            Some(backend) => {
+                backend.inject_rx_packet(packet.to_vec());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take all frames the guest has sent.
+    #[napi]
+    pub fn extract_network_packets(&self) -> Vec<Buffer> {
+        match &self.external_net {
+            Some(backend) => backend
+                .extract_all_tx_packets()
+                .into_iter()
+                .map(Buffer::from)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Report the address the relay assigned, marking the network up.
+    #[napi]
+    pub fn set_external_network_ip(&self, ip: Buffer) -> bool {
+        let Ok(ip) = <[u8; 4]>::try_from(ip.as_ref()) else {
+            return false;
+        };
+        match &self.external_net {
+            Some(backend) => {
+                backend.set_assigned_ip(ip);
+                backend.set_connected(true);
+                true
+            }
+            None => false,
+        }
+    }
+}
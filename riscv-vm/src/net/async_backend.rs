@@ -40,6 +40,9 @@ pub struct AsyncNetworkBackend {
 
     /// Assigned IP address (updated from I/O thread)
     assigned_ip: Arc<std::sync::Mutex<Option<[u8; 4]>>>,
+
+    /// Relay migration key (updated from I/O thread)
+    migration_key: Arc<std::sync::Mutex<Option<String>>>,
 }
 
 impl AsyncNetworkBackend {
@@ -62,6 +65,8 @@ impl AsyncNetworkBackend {
         let shutdown_clone = Arc::clone(&shutdown);
         let assigned_ip = Arc::new(std::sync::Mutex::new(None));
         let assigned_ip_clone = Arc::clone(&assigned_ip);
+        let migration_key = Arc::new(std::sync::Mutex::new(None));
+        let migration_key_clone = Arc::clone(&migration_key);
 
         let io_thread = thread::Builder::new()
             .name("virtio-net-io".to_string())
@@ -72,6 +77,7 @@ impl AsyncNetworkBackend {
                     tx_to_vm,
                     shutdown_clone,
                     assigned_ip_clone,
+                    migration_key_clone,
                 );
             })
             .expect("Failed to spawn network I/O thread");
@@ -83,6 +89,7 @@ impl AsyncNetworkBackend {
             shutdown,
             mac,
             assigned_ip,
+            migration_key,
         }
     }
 
@@ -97,6 +104,7 @@ impl AsyncNetworkBackend {
         tx_to_vm: Sender<Vec<u8>>,
         shutdown: Arc<AtomicBool>,
         assigned_ip: Arc<std::sync::Mutex<Option<[u8; 4]>>>,
+        migration_key: Arc<std::sync::Mutex<Option<String>>>,
     ) {
         log::debug!("[AsyncNetworkBackend] I/O thread started");
 
@@ -156,6 +164,14 @@ impl AsyncNetworkBackend {
                     *guard = Some(ip);
                 }
             }
+
+            // The relay hands out a new key on every registration
+            if let Some(key) = backend.migration_key() {
+                let mut guard = migration_key.lock().unwrap();
+                if guard.as_deref() != Some(key.as_str()) {
+                    *guard = Some(key);
+                }
+            }
         }
     }
 
@@ -230,6 +246,10 @@ impl NetworkBackend for AsyncNetworkBackend {
         self.assigned_ip.lock().unwrap().clone()
     }
 
+    fn migration_key(&self) -> Option<String> {
+        self.migration_key.lock().unwrap().clone()
+    }
+
     fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
         // Use recv_timeout on the channel for timeout support
         match self.rx.recv_timeout(timeout) {
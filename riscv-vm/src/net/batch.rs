@@ -0,0 +1,397 @@
+//! Frame batching and compression for the relay transport (protocol v2).
+//!
+//! This file is shared: the relay compiles it as its own `batch` module, so
+//! both ends of a connection always agree on the format. Keep VM-only items
+//! out of it; the relay builds with dead-code warnings on.
+//!
+//! Small frames (TCP ACKs, ARP, DNS) used to cross the relay one datagram
+//! each. With protocol v2 a sender coalesces the messages queued within a
+//! short window into a single batch datagram:
+//!
+//! ```text
+//! [0x02][flags][body]
+//! body  = entry*            (LZ4 block with a u32 LE size prefix if flags & 0x01)
+//! entry = [len: u16 BE][message]
+//! ```
+//!
+//! Entries are complete relay messages (type byte + payload), so a receiver
+//! unpacks a batch and handles each entry as if it had arrived on its own.
+//! The version and compression support are negotiated in the
+//! `Register`/`Assigned` handshake; v1 peers never see a batch.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Highest relay protocol version spoken by the hub and its peers.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Message type prefix for frame batches (protocol v2).
+pub const MSG_TYPE_BATCH: u8 = 0x02;
+
+/// Batch flag: the body is LZ4-compressed.
+pub const BATCH_FLAG_LZ4: u8 = 0x01;
+
+/// Type byte plus flags byte.
+const BATCH_HEADER_LEN: usize = 2;
+
+/// Length prefix of each entry.
+const ENTRY_HEADER_LEN: usize = 2;
+
+/// Upper bound on a decompressed batch body (rejects bogus size prefixes).
+const MAX_BATCH_BODY: usize = 64 * 1024;
+
+/// Batching parameters for one side of a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// How long the first queued message may wait for company.
+    /// Zero disables batching.
+    pub window: Duration,
+    /// Largest datagram the batcher will produce.
+    pub max_datagram: usize,
+    /// Compress batch bodies when the peer supports it.
+    pub compress: bool,
+    /// Bodies smaller than this are never compressed.
+    pub compress_min: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(2),
+            max_datagram: 1200,
+            compress: true,
+            compress_min: 256,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Send every message in its own datagram (protocol v1 behaviour).
+    pub fn disabled() -> Self {
+        Self {
+            window: Duration::ZERO,
+            compress: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Latency/throughput counters for a relay connection.
+#[derive(Debug, Clone, Default)]
+pub struct TransportMetrics {
+    /// Relay messages handed to the batcher.
+    pub frames_sent: u64,
+    /// Datagrams put on the wire.
+    pub datagrams_sent: u64,
+    /// Datagrams that carried more than one message.
+    pub batches_sent: u64,
+    /// Batches whose body was compressed.
+    pub compressed_batches: u64,
+    /// Message bytes before batching and compression.
+    pub bytes_raw: u64,
+    /// Bytes actually sent.
+    pub bytes_wire: u64,
+    /// Sum of the time each batch's oldest message waited.
+    pub batch_delay_total: Duration,
+    /// Longest time a message waited in the batcher.
+    pub batch_delay_max: Duration,
+    /// Datagrams received.
+    pub datagrams_received: u64,
+    /// Relay messages received (batches count each entry).
+    pub frames_received: u64,
+    /// Bytes received on the wire.
+    pub bytes_received: u64,
+}
+
+impl TransportMetrics {
+    /// Average messages per sent datagram.
+    pub fn frames_per_datagram(&self) -> f64 {
+        if self.datagrams_sent == 0 {
+            0.0
+        } else {
+            self.frames_sent as f64 / self.datagrams_sent as f64
+        }
+    }
+
+    /// Wire bytes per raw byte sent (below 1.0 means compression paid off).
+    pub fn wire_ratio(&self) -> f64 {
+        if self.bytes_raw == 0 {
+            1.0
+        } else {
+            self.bytes_wire as f64 / self.bytes_raw as f64
+        }
+    }
+
+    /// Average delay added to a sent datagram by batching.
+    pub fn avg_batch_delay(&self) -> Duration {
+        if self.datagrams_sent == 0 {
+            Duration::ZERO
+        } else {
+            self.batch_delay_total / self.datagrams_sent as u32
+        }
+    }
+
+    /// Record a received datagram carrying `frames` messages.
+    pub fn record_received(&mut self, bytes: usize, frames: usize) {
+        self.datagrams_received += 1;
+        self.frames_received += frames as u64;
+        self.bytes_received += bytes as u64;
+    }
+
+    /// Record a message sent in its own datagram, bypassing the batcher.
+    pub fn record_unbatched(&mut self, bytes: usize) {
+        self.record_sent(1, bytes, bytes, Duration::ZERO);
+    }
+
+    /// Add another connection's counters.
+    pub fn merge(&mut self, other: &TransportMetrics) {
+        self.frames_sent += other.frames_sent;
+        self.datagrams_sent += other.datagrams_sent;
+        self.batches_sent += other.batches_sent;
+        self.compressed_batches += other.compressed_batches;
+        self.bytes_raw += other.bytes_raw;
+        self.bytes_wire += other.bytes_wire;
+        self.batch_delay_total += other.batch_delay_total;
+        self.batch_delay_max = self.batch_delay_max.max(other.batch_delay_max);
+        self.datagrams_received += other.datagrams_received;
+        self.frames_received += other.frames_received;
+        self.bytes_received += other.bytes_received;
+    }
+
+    fn record_sent(&mut self, frames: usize, raw: usize, wire: usize, delay: Duration) {
+        self.frames_sent += frames as u64;
+        self.datagrams_sent += 1;
+        if frames > 1 {
+            self.batches_sent += 1;
+        }
+        self.bytes_raw += raw as u64;
+        self.bytes_wire += wire as u64;
+        self.batch_delay_total += delay;
+        self.batch_delay_max = self.batch_delay_max.max(delay);
+    }
+}
+
+impl fmt::Display for TransportMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} frames in {} datagrams ({:.2}/dgram, {} batches, {} compressed), \
+             {} -> {} bytes ({:.1}%), delay avg {}us max {}us; \
+             rx {} frames in {} datagrams, {} bytes",
+            self.frames_sent,
+            self.datagrams_sent,
+            self.frames_per_datagram(),
+            self.batches_sent,
+            self.compressed_batches,
+            self.bytes_raw,
+            self.bytes_wire,
+            self.wire_ratio() * 100.0,
+            self.avg_batch_delay().as_micros(),
+            self.batch_delay_max.as_micros(),
+            self.frames_received,
+            self.datagrams_received,
+            self.bytes_received,
+        )
+    }
+}
+
+/// Coalesces outgoing relay messages into batch datagrams.
+pub struct Batcher {
+    config: BatchConfig,
+    /// Encoded entries of the pending batch.
+    body: Vec<u8>,
+    /// Messages in the pending batch.
+    count: usize,
+    /// When the oldest pending message was queued.
+    oldest: Option<Instant>,
+    /// Counters for this connection.
+    pub metrics: TransportMetrics,
+}
+
+impl Batcher {
+    /// Create a batcher. `peer_compression` is the negotiated LZ4 support.
+    pub fn new(mut config: BatchConfig, peer_compression: bool) -> Self {
+        config.compress &= peer_compression;
+        Self {
+            config,
+            body: Vec::with_capacity(config.max_datagram),
+            count: 0,
+            oldest: None,
+            metrics: TransportMetrics::default(),
+        }
+    }
+
+    /// Queue a message. Returns a datagram that must be sent now, either
+    /// the message itself (batching disabled) or the previous batch when
+    /// the message doesn't fit next to it.
+    pub fn push(&mut self, msg: Vec<u8>, now: Instant) -> Option<Vec<u8>> {
+        if self.config.window.is_zero() {
+            self.metrics.record_unbatched(msg.len());
+            return Some(msg);
+        }
+
+        let entry_len = ENTRY_HEADER_LEN + msg.len();
+        let ready = if self.count > 0
+            && BATCH_HEADER_LEN + self.body.len() + entry_len > self.config.max_datagram
+        {
+            self.flush(now)
+        } else {
+            None
+        };
+
+        self.body
+            .extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        self.body.extend_from_slice(&msg);
+        self.count += 1;
+        self.oldest.get_or_insert(now);
+        ready
+    }
+
+    /// When the pending batch must be flushed, if anything is queued.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.oldest.map(|t| t + self.config.window)
+    }
+
+    /// Flush the pending batch if its window has elapsed.
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => self.flush(now),
+            _ => None,
+        }
+    }
+
+    /// Encode the pending messages into one datagram.
+    ///
+    /// A single message is sent as-is, so batching costs nothing when
+    /// traffic is sparse.
+    pub fn flush(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let oldest = self.oldest.take()?;
+        let delay = now.saturating_duration_since(oldest);
+        let count = std::mem::replace(&mut self.count, 0);
+        let raw = self.body.len() - count * ENTRY_HEADER_LEN;
+
+        let datagram = if count == 1 {
+            self.body[ENTRY_HEADER_LEN..].to_vec()
+        } else {
+            let compressed = (self.config.compress && self.body.len() >= self.config.compress_min)
+                .then(|| lz4_flex::block::compress_prepend_size(&self.body))
+                .filter(|packed| packed.len() < self.body.len());
+            let (flags, body) = match &compressed {
+                Some(packed) => (BATCH_FLAG_LZ4, packed.as_slice()),
+                None => (0, self.body.as_slice()),
+            };
+            if compressed.is_some() {
+                self.metrics.compressed_batches += 1;
+            }
+            let mut datagram = Vec::with_capacity(BATCH_HEADER_LEN + body.len());
+            datagram.push(MSG_TYPE_BATCH);
+            datagram.push(flags);
+            datagram.extend_from_slice(body);
+            datagram
+        };
+        self.body.clear();
+        self.metrics.record_sent(count, raw, datagram.len(), delay);
+        Some(datagram)
+    }
+}
+
+/// Split a batch datagram back into relay messages.
+pub fn unpack_batch(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if data.len() < BATCH_HEADER_LEN || data[0] != MSG_TYPE_BATCH {
+        return Err("Not a batch".to_string());
+    }
+    let flags = data[1];
+    let decompressed;
+    let mut body = &data[BATCH_HEADER_LEN..];
+    if flags & BATCH_FLAG_LZ4 != 0 {
+        let size = body
+            .get(..4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or("Truncated compressed batch")?;
+        if size > MAX_BATCH_BODY {
+            return Err(format!("Batch body too large ({} bytes)", size));
+        }
+        decompressed = lz4_flex::block::decompress_size_prepended(body)
+            .map_err(|e| format!("Corrupt compressed batch: {}", e))?;
+        body = &decompressed;
+    }
+
+    let mut messages = Vec::new();
+    while !body.is_empty() {
+        if body.len() < ENTRY_HEADER_LEN {
+            return Err("Truncated batch entry header".to_string());
+        }
+        let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let msg = body
+            .get(ENTRY_HEADER_LEN..ENTRY_HEADER_LEN + len)
+            .ok_or("Truncated batch entry")?;
+        messages.push(msg.to_vec());
+        body = &body[ENTRY_HEADER_LEN + len..];
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_msg(len: usize, fill: u8) -> Vec<u8> {
+        let mut msg = vec![fill; len + 1];
+        msg[0] = 0x01;
+        msg
+    }
+
+    #[test]
+    fn test_batch_roundtrip_with_compression() {
+        let mut batcher = Batcher::new(BatchConfig::default(), true);
+        let now = Instant::now();
+        let msgs: Vec<Vec<u8>> = (0..4).map(|i| data_msg(200, i)).collect();
+        for msg in &msgs {
+            assert!(batcher.push(msg.clone(), now).is_none());
+        }
+        assert_eq!(batcher.deadline(), Some(now + Duration::from_millis(2)));
+        assert!(batcher.poll(now).is_none());
+
+        let datagram = batcher.poll(now + Duration::from_millis(2)).unwrap();
+        assert_eq!(datagram[0], MSG_TYPE_BATCH);
+        assert_eq!(datagram[1], BATCH_FLAG_LZ4);
+        assert!(datagram.len() < 4 * 201);
+        assert_eq!(unpack_batch(&datagram).unwrap(), msgs);
+
+        let m = &batcher.metrics;
+        assert_eq!((m.frames_sent, m.datagrams_sent, m.batches_sent), (4, 1, 1));
+        assert_eq!(m.compressed_batches, 1);
+        assert!(m.wire_ratio() < 1.0);
+        assert_eq!(m.batch_delay_max, Duration::from_millis(2));
+        assert!(batcher.deadline().is_none());
+    }
+
+    #[test]
+    fn test_batch_respects_datagram_budget() {
+        let mut batcher = Batcher::new(BatchConfig::default(), false);
+        let now = Instant::now();
+        assert!(batcher.push(data_msg(700, 1), now).is_none());
+
+        // The second message doesn't fit, so the first leaves on its own
+        let first = batcher.push(data_msg(700, 2), now).unwrap();
+        assert_eq!(first, data_msg(700, 1));
+        let second = batcher.flush(now).unwrap();
+        assert_eq!(second, data_msg(700, 2));
+        assert_eq!(batcher.metrics.batches_sent, 0);
+    }
+
+    #[test]
+    fn test_disabled_batching_passes_through() {
+        let mut batcher = Batcher::new(BatchConfig::disabled(), true);
+        let msg = data_msg(10, 7);
+        assert_eq!(batcher.push(msg.clone(), Instant::now()), Some(msg));
+        assert!(batcher.deadline().is_none());
+    }
+
+    #[test]
+    fn test_unpack_rejects_malformed_batches() {
+        assert!(unpack_batch(&[MSG_TYPE_BATCH, 0, 0, 5, 1]).is_err());
+        assert!(unpack_batch(&[MSG_TYPE_BATCH, BATCH_FLAG_LZ4, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert_eq!(unpack_batch(&[MSG_TYPE_BATCH, 0]).unwrap().len(), 0);
+    }
+}
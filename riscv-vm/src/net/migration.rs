@@ -0,0 +1,537 @@
+//! Migration images over the relay.
+//!
+//! Moves a [`crate::migration`] image between two hosts through a relay
+//! that supports migration sessions. [`MigrationLink`] speaks the control
+//! side of the relay protocol (`MigrateOut` / `MigrateIn`, heartbeats,
+//! timeouts) around a [`MigrationSender`] or [`MigrationReceiver`] and does
+//! no I/O itself; `send` and `receive` run it over a WebTransport
+//! connection, blocking natively and as futures in the browser.
+
+use crate::migration::{MSG_TYPE_MIGRATION, MigrationReceiver, MigrationSender};
+
+/// Message type prefix for control messages
+const MSG_TYPE_CONTROL: u8 = 0x00;
+
+/// Milliseconds between polls of the transfer
+const TICK_MS: u64 = 10;
+/// Milliseconds between tries of an unanswered `MigrateOut` / `MigrateIn`
+const REQUEST_RETRY_MS: u64 = 1000;
+/// Tries before giving up on the relay
+const REQUEST_ATTEMPTS: u32 = 10;
+/// Milliseconds the old host waits for the new one to join, as long as
+/// the relay keeps tickets
+const JOIN_TIMEOUT_MS: u64 = 600_000;
+/// Milliseconds of silence from the other host after which it is
+/// considered gone
+const IDLE_TIMEOUT_MS: u64 = 30_000;
+/// Milliseconds between heartbeats
+const HEARTBEAT_MS: u64 = 15_000;
+
+enum End {
+    Source(MigrationSender),
+    Destination(MigrationReceiver),
+}
+
+/// One host's side of a migration session on the relay. Time is passed in,
+/// in milliseconds from any fixed point.
+pub struct MigrationLink {
+    end: End,
+    /// `MigrateOut` or `MigrateIn`, sent until the relay answers
+    request: Vec<u8>,
+    attempts: u32,
+    last_request: Option<u64>,
+    answered: bool,
+    paired: bool,
+    /// Ticket the relay issued and nobody has taken yet
+    ticket: Option<String>,
+    /// When the relay answered or the other host was last heard from
+    last_heard: u64,
+    last_heartbeat: u64,
+}
+
+impl MigrationLink {
+    fn new(end: End, request: String) -> Self {
+        let mut msg = vec![MSG_TYPE_CONTROL];
+        msg.extend(request.bytes());
+        Self {
+            end,
+            request: msg,
+            attempts: 0,
+            last_request: None,
+            answered: false,
+            paired: false,
+            ticket: None,
+            last_heard: 0,
+            last_heartbeat: 0,
+        }
+    }
+
+    /// The old host's side, offering `image` of a VM whose NIC had the MAC
+    /// address `mac` and was given the migration `key` by the relay. The
+    /// relay only keeps the NIC's address for the holder of its key.
+    pub fn source(image: Vec<u8>, mac: Option<[u8; 6]>, key: Option<&str>) -> Self {
+        let mac = mac
+            .map(|m| {
+                format!(
+                    r#","mac":[{},{},{},{},{},{}]"#,
+                    m[0], m[1], m[2], m[3], m[4], m[5]
+                )
+            })
+            .unwrap_or_default();
+        let key = key
+            .map(|key| format!(r#","key":{}"#, serde_json::Value::from(key)))
+            .unwrap_or_default();
+        let request = format!(r#"{{"type":"MigrateOut"{}{}}}"#, mac, key);
+        Self::new(End::Source(MigrationSender::new(image)), request)
+    }
+
+    /// The new host's side, joining the session `ticket`.
+    pub fn destination(ticket: &str) -> Self {
+        let request = format!(r#"{{"type":"MigrateIn","ticket":"{}"}}"#, ticket);
+        Self::new(End::Destination(MigrationReceiver::new()), request)
+    }
+
+    /// Datagrams to send at time `now`. Fails once the relay or the other
+    /// host has been silent for too long.
+    pub fn poll(&mut self, now: u64) -> Result<Vec<Vec<u8>>, String> {
+        let mut out = Vec::new();
+        if !self.answered {
+            if self
+                .last_request
+                .is_some_and(|at| now < at + REQUEST_RETRY_MS)
+            {
+                return Ok(out);
+            }
+            if self.attempts == REQUEST_ATTEMPTS {
+                return Err("the relay does not answer migration requests".to_string());
+            }
+            self.attempts += 1;
+            self.last_request = Some(now);
+            self.last_heartbeat = now;
+            return Ok(vec![self.request.clone()]);
+        }
+
+        let timeout = if self.paired {
+            IDLE_TIMEOUT_MS
+        } else {
+            JOIN_TIMEOUT_MS
+        };
+        if now >= self.last_heard + timeout {
+            return Err(match self.paired {
+                true => "the other host stopped answering".to_string(),
+                false => "no host joined the migration in time".to_string(),
+            });
+        }
+        if now >= self.last_heartbeat + HEARTBEAT_MS {
+            self.last_heartbeat = now;
+            let mut heartbeat = vec![MSG_TYPE_CONTROL];
+            heartbeat.extend(br#"{"type":"Heartbeat"}"#);
+            out.push(heartbeat);
+        }
+        if let End::Destination(receiver) = &mut self.end
+            && self.paired
+        {
+            out.extend(receiver.poll(now));
+        }
+        Ok(out)
+    }
+
+    /// Take in the datagram `data` from the relay, received at time `now`,
+    /// and return the datagrams to send in reply.
+    pub fn handle(&mut self, data: &[u8], now: u64) -> Result<Vec<Vec<u8>>, String> {
+        match data.first() {
+            Some(&MSG_TYPE_MIGRATION) => {
+                // Pairing was announced, but that may have been lost
+                self.answered = true;
+                self.paired = true;
+                self.last_heard = now;
+                match &mut self.end {
+                    End::Source(sender) => Ok(sender.handle(data)),
+                    End::Destination(receiver) => receiver.handle(data, now),
+                }
+            }
+            Some(&MSG_TYPE_CONTROL) => {
+                let Ok(json) = serde_json::from_slice::<serde_json::Value>(&data[1..]) else {
+                    return Ok(Vec::new());
+                };
+                match json["type"].as_str() {
+                    Some("MigrationTicket") if !self.answered => {
+                        self.answered = true;
+                        self.last_heard = now;
+                        self.ticket = json["ticket"].as_str().map(str::to_string);
+                    }
+                    Some("MigrationPaired") if !self.paired => {
+                        self.answered = true;
+                        self.paired = true;
+                        self.last_heard = now;
+                    }
+                    Some("Error") => {
+                        let message = json["message"].as_str().unwrap_or("unknown error");
+                        return Err(format!("relay refused the migration: {}", message));
+                    }
+                    _ => {}
+                }
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// The ticket the relay issued to the old host, once.
+    pub fn take_ticket(&mut self) -> Option<String> {
+        self.ticket.take()
+    }
+
+    /// Whether the image has been handed over.
+    pub fn is_finished(&self) -> bool {
+        match &self.end {
+            End::Source(sender) => sender.is_done(),
+            End::Destination(receiver) => receiver.is_complete(),
+        }
+    }
+
+    /// Pieces of the image received and offered so far, on the new host.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        match &self.end {
+            End::Source(_) => None,
+            End::Destination(receiver) => Some(receiver.progress()),
+        }
+    }
+
+    /// The received image, on the new host once finished.
+    pub fn into_image(self) -> Option<Vec<u8>> {
+        match self.end {
+            End::Source(_) => None,
+            End::Destination(receiver) => receiver.into_image(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Runtime;
+    use wtransport::tls::Sha256Digest;
+    use wtransport::{ClientConfig, Connection, Endpoint};
+
+    async fn connect(url: &str, cert_hash: Option<&str>) -> Result<Connection, String> {
+        let builder = ClientConfig::builder().with_bind_default();
+        let config = match cert_hash {
+            Some(hash) => {
+                let bytes = hex::decode(hash.replace(":", ""))
+                    .map_err(|e| format!("invalid certificate hash: {}", e))?;
+                let digest: [u8; 32] = bytes
+                    .try_into()
+                    .map_err(|_| "certificate hash must be 32 bytes".to_string())?;
+                builder
+                    .with_server_certificate_hashes(vec![Sha256Digest::from(digest)])
+                    .build()
+            }
+            None => builder.with_no_cert_validation().build(),
+        };
+        let endpoint =
+            Endpoint::client(config).map_err(|e| format!("failed to create endpoint: {}", e))?;
+        endpoint
+            .connect(url)
+            .await
+            .map_err(|e| format!("failed to connect to {}: {}", url, e))
+    }
+
+    /// Drive `link` over a connection to the relay at `url` until it
+    /// finishes, calling `on_ticket` with the ticket the relay issues.
+    fn run(
+        url: &str,
+        cert_hash: Option<&str>,
+        mut link: MigrationLink,
+        mut on_ticket: impl FnMut(&str),
+    ) -> Result<MigrationLink, String> {
+        let runtime = Runtime::new().map_err(|e| format!("failed to start runtime: {}", e))?;
+        runtime.block_on(async {
+            let connection = connect(url, cert_hash).await?;
+            let started = Instant::now();
+            let mut tick = tokio::time::interval(Duration::from_millis(TICK_MS));
+            while !link.is_finished() {
+                let now = started.elapsed().as_millis() as u64;
+                let out = tokio::select! {
+                    _ = tick.tick() => link.poll(now)?,
+                    result = connection.receive_datagram() => {
+                        let datagram = result.map_err(|e| format!("lost the relay: {}", e))?;
+                        link.handle(&datagram, now)?
+                    }
+                };
+                if let Some(ticket) = link.take_ticket() {
+                    on_ticket(&ticket);
+                }
+                for msg in out {
+                    connection
+                        .send_datagram(msg)
+                        .map_err(|e| format!("failed to send to the relay: {}", e))?;
+                }
+            }
+            // Let the last datagrams leave before the connection drops
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(link)
+        })
+    }
+
+    /// Hand `image` of a VM whose NIC had the MAC address `mac` and the
+    /// relay migration `key` to another host through the relay at `url`.
+    /// `on_ticket` gets the ticket to give to the new host; this returns
+    /// once it has the whole image.
+    pub fn send(
+        url: &str,
+        cert_hash: Option<&str>,
+        image: Vec<u8>,
+        mac: Option<[u8; 6]>,
+        key: Option<&str>,
+        on_ticket: impl FnMut(&str),
+    ) -> Result<(), String> {
+        run(url, cert_hash, MigrationLink::source(image, mac, key), on_ticket)?;
+        Ok(())
+    }
+
+    /// Fetch the image of the migration `ticket` through the relay at
+    /// `url`.
+    pub fn receive(url: &str, cert_hash: Option<&str>, ticket: &str) -> Result<Vec<u8>, String> {
+        let link = run(url, cert_hash, MigrationLink::destination(ticket), |_| {})?;
+        link.into_image()
+            .ok_or_else(|| "migration ended without an image".to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use super::*;
+    use js_sys::{Array, Uint8Array};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{
+        ReadableStreamDefaultReader, WebTransport, WebTransportHash, WebTransportOptions,
+    };
+
+    /// Datagrams the reader task has received, and whether it has stopped
+    #[derive(Default)]
+    struct Inbox {
+        datagrams: VecDeque<Vec<u8>>,
+        closed: bool,
+    }
+
+    async fn sleep(ms: u64) {
+        let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+            let global = js_sys::global();
+            if let Ok(set_timeout) = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))
+                .and_then(|f| f.dyn_into::<js_sys::Function>())
+            {
+                let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from(ms as f64));
+            }
+        });
+        let _ = JsFuture::from(promise).await;
+    }
+
+    /// Drive `link` over a connection to the relay at `url` until it
+    /// finishes, calling `on_ticket` with the ticket the relay issues.
+    async fn run(
+        url: &str,
+        cert_hash: Option<&str>,
+        mut link: MigrationLink,
+        on_ticket: &dyn Fn(&str),
+    ) -> Result<MigrationLink, String> {
+        let options = WebTransportOptions::new();
+        if let Some(hash) = cert_hash {
+            let bytes = hex::decode(hash.replace(":", ""))
+                .map_err(|e| format!("invalid certificate hash: {}", e))?;
+            let hash = WebTransportHash::new();
+            hash.set_algorithm("sha-256");
+            hash.set_value(&Uint8Array::from(&bytes[..]));
+            let hashes = Array::new();
+            hashes.push(&hash);
+            options.set_server_certificate_hashes(&hashes);
+        }
+        let transport = WebTransport::new_with_options(url, &options)
+            .map_err(|e| format!("failed to connect to {}: {:?}", url, e))?;
+        JsFuture::from(transport.ready())
+            .await
+            .map_err(|e| format!("failed to connect to {}: {:?}", url, e))?;
+        let writer = transport
+            .datagrams()
+            .writable()
+            .get_writer()
+            .map_err(|e| format!("failed to open datagram writer: {:?}", e))?;
+        let reader: ReadableStreamDefaultReader = transport
+            .datagrams()
+            .readable()
+            .get_reader()
+            .unchecked_into();
+
+        let inbox = Rc::new(RefCell::new(Inbox::default()));
+        let reader_inbox = inbox.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Ok(result) = JsFuture::from(reader.read()).await {
+                let done = js_sys::Reflect::get(&result, &JsValue::from_str("done"))
+                    .ok()
+                    .and_then(|done| done.as_bool())
+                    .unwrap_or(true);
+                if done {
+                    break;
+                }
+                if let Ok(value) = js_sys::Reflect::get(&result, &JsValue::from_str("value")) {
+                    let datagram = Uint8Array::new(&value).to_vec();
+                    reader_inbox.borrow_mut().datagrams.push_back(datagram);
+                }
+            }
+            reader_inbox.borrow_mut().closed = true;
+        });
+
+        let result = async {
+            while !link.is_finished() {
+                let now = js_sys::Date::now() as u64;
+                let received: Vec<Vec<u8>> = inbox.borrow_mut().datagrams.drain(..).collect();
+                if received.is_empty() && inbox.borrow().closed {
+                    return Err("lost the relay".to_string());
+                }
+                let mut out = link.poll(now)?;
+                for datagram in received {
+                    out.extend(link.handle(&datagram, now)?);
+                }
+                if let Some(ticket) = link.take_ticket() {
+                    on_ticket(&ticket);
+                }
+                for msg in out {
+                    JsFuture::from(writer.write_with_chunk(&Uint8Array::from(&msg[..])))
+                        .await
+                        .map_err(|e| format!("failed to send to the relay: {:?}", e))?;
+                }
+                sleep(TICK_MS).await;
+            }
+            // Let the last datagrams leave before the connection drops
+            sleep(100).await;
+            Ok(())
+        }
+        .await;
+        transport.close();
+        result.map(|_| link)
+    }
+
+    /// Hand `image` of a VM whose NIC had the MAC address `mac` and the
+    /// relay migration `key` to another host through the relay at `url`.
+    /// `on_ticket` gets the ticket to give to the new host; this resolves
+    /// once it has the whole image.
+    pub async fn send(
+        url: &str,
+        cert_hash: Option<&str>,
+        image: Vec<u8>,
+        mac: Option<[u8; 6]>,
+        key: Option<&str>,
+        on_ticket: &dyn Fn(&str),
+    ) -> Result<(), String> {
+        run(url, cert_hash, MigrationLink::source(image, mac, key), on_ticket).await?;
+        Ok(())
+    }
+
+    /// Fetch the image of the migration `ticket` through the relay at
+    /// `url`.
+    pub async fn receive(
+        url: &str,
+        cert_hash: Option<&str>,
+        ticket: &str,
+    ) -> Result<Vec<u8>, String> {
+        let link = run(url, cert_hash, MigrationLink::destination(ticket), &|_| {}).await?;
+        link.into_image()
+            .ok_or_else(|| "migration ended without an image".to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{receive, send};
+
+#[cfg(target_arch = "wasm32")]
+pub use wasm::{receive, send};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(json: &str) -> Vec<u8> {
+        let mut msg = vec![MSG_TYPE_CONTROL];
+        msg.extend(json.bytes());
+        msg
+    }
+
+    #[test]
+    fn test_links_through_relay() {
+        let image: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let mut source =
+            MigrationLink::source(image.clone(), Some([2, 0, 0, 0, 0, 9]), Some("k3y"));
+        let request = source.poll(0).unwrap();
+        assert_eq!(
+            request,
+            vec![control(
+                r#"{"type":"MigrateOut","mac":[2,0,0,0,0,9],"key":"k3y"}"#
+            )]
+        );
+        // Unanswered requests are repeated, then given up on
+        assert!(source.poll(REQUEST_RETRY_MS - 1).unwrap().is_empty());
+        assert_eq!(source.poll(REQUEST_RETRY_MS).unwrap(), request);
+
+        source
+            .handle(&control(r#"{"type":"MigrationTicket","ticket":"abc"}"#), 5)
+            .unwrap();
+        assert_eq!(source.take_ticket().as_deref(), Some("abc"));
+        assert_eq!(source.take_ticket(), None);
+
+        let mut destination = MigrationLink::destination("abc");
+        assert_eq!(
+            destination.poll(0).unwrap(),
+            vec![control(r#"{"type":"MigrateIn","ticket":"abc"}"#)]
+        );
+        let paired = control(r#"{"type":"MigrationPaired"}"#);
+        source.handle(&paired, 10).unwrap();
+        destination.handle(&paired, 10).unwrap();
+
+        // The relay passes migration datagrams between the two
+        let mut now = 10;
+        while !source.is_finished() {
+            assert!(now < 10_000, "transfer stalled");
+            let mut to_source = destination.poll(now).unwrap();
+            while !to_source.is_empty() {
+                let mut to_destination = Vec::new();
+                for msg in to_source.drain(..) {
+                    to_destination.extend(source.handle(&msg, now).unwrap());
+                }
+                for msg in to_destination {
+                    to_source.extend(destination.handle(&msg, now).unwrap());
+                }
+            }
+            now += TICK_MS;
+        }
+        assert!(destination.is_finished());
+        assert_eq!(destination.progress(), Some((5, 5)));
+        assert_eq!(destination.into_image(), Some(image));
+
+        // A silent partner ends the session
+        assert!(source.poll(now + IDLE_TIMEOUT_MS).is_err());
+    }
+
+    #[test]
+    fn test_relay_errors() {
+        let mut link = MigrationLink::destination("nope");
+        for attempt in 0..REQUEST_ATTEMPTS as u64 {
+            assert_eq!(link.poll(attempt * REQUEST_RETRY_MS).unwrap().len(), 1);
+        }
+        assert!(
+            link.poll(REQUEST_ATTEMPTS as u64 * REQUEST_RETRY_MS)
+                .is_err()
+        );
+
+        let refusal = control(r#"{"type":"Error","message":"unknown migration ticket"}"#);
+        let err = MigrationLink::destination("nope")
+            .handle(&refusal, 0)
+            .unwrap_err();
+        assert!(err.contains("unknown migration ticket"));
+    }
+}
@@ -1,15 +1,62 @@
 //! Network backend abstraction for VirtIO networking.
 //!
 //! This module defines the `NetworkBackend` trait that abstracts packet I/O
-//! to support both Host (TAP) and WASM (WebSocket) environments.
+//! for the VM's VirtIO NIC, and the backends selected by [`NetBackend`]:
+//! - [`webtransport`]: the relay protocol over WebTransport (QUIC). The
+//!   native and browser builds speak the same protocol to the same relay,
+//!   so native and browser VMs joining one relay (and network ID) share a
+//!   virtual LAN; no separate peer-to-peer client is needed.
+//! - [`slirp`]: in-process user-mode NAT for a single native VM.
+//! - [`tap`]: a host TAP interface on Unix hosts.
+//! - [`pool`]: the other VMs of a browser worker pool, switched in shared
+//!   memory.
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod async_backend;
+pub mod batch;
 pub mod external;
+pub mod migration;
+pub mod pool;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod slirp;
+#[cfg(all(not(target_arch = "wasm32"), unix))]
+pub mod tap;
 pub mod webtransport;
 
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use batch::{BatchConfig, TransportMetrics};
+
+/// Shared handle through which a transport thread publishes its counters.
+pub type TransportMetricsHandle = Arc<Mutex<TransportMetrics>>;
+
+/// Where the frames of a VM's VirtIO NIC go.
+#[derive(Debug, Clone)]
+pub enum NetBackend {
+    /// A WebTransport relay, which assigns the guest's address and routes
+    /// its traffic.
+    WebTransport {
+        url: String,
+        cert_hash: Option<String>,
+        batching: BatchConfig,
+        /// MAC address and migration ticket of a VM that moved here, which
+        /// get it its old address back (see [`crate::migration`]).
+        resume: Option<([u8; 6], String)>,
+    },
+    /// The in-process user-mode NAT of [`slirp`]; native builds only.
+    Slirp,
+    /// A host TAP interface (see [`tap`]); Unix hosts only.
+    Tap {
+        /// Interface name; empty lets the host pick the next free `tapN`.
+        name: String,
+        /// Guest NIC address, or the default one if `None`.
+        mac: Option<[u8; 6]>,
+        /// IPv4 address handed to the guest, if any.
+        ip: Option<[u8; 4]>,
+    },
+}
+
 /// Trait for network backends that provide packet I/O.
 ///
 /// Implementations must be `Send` to allow the backend to be used
@@ -38,6 +85,12 @@ pub trait NetworkBackend: Send {
         None
     }
 
+    /// Key the relay gave this NIC's registration, which the host must
+    /// present to migrate the VM away (see [`crate::migration`]).
+    fn migration_key(&self) -> Option<String> {
+        None
+    }
+
     /// Receive with timeout (for async wrapper).
     ///
     /// Waits up to `timeout` for an incoming packet. Returns `Ok(Some(packet))`
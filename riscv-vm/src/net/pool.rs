@@ -0,0 +1,88 @@
+//! Network backend for VMs in a worker pool.
+//!
+//! [`PoolBackend`] connects the NIC of a pooled VM to the other VMs of the
+//! same pool: sent frames are switched straight into their receive rings in
+//! the pool buffer (see [`crate::shared_mem::PoolBuffer`]), and received
+//! frames are read from this VM's ring. Pooled VMs form one L2 segment with
+//! no gateway; guests address each other statically or run their own
+//! services.
+
+use super::NetworkBackend;
+use crate::shared_mem::PoolBuffer;
+
+/// NIC of the VM in one pool slot.
+pub struct PoolBackend {
+    pool: PoolBuffer,
+    slot: usize,
+    ip: Option<[u8; 4]>,
+}
+
+impl PoolBackend {
+    /// Backend for the VM in `slot`, whose MAC the supervisor recorded in the
+    /// pool, handing the guest address `ip` if set.
+    pub fn new(pool: PoolBuffer, slot: usize, ip: Option<[u8; 4]>) -> Self {
+        Self { pool, slot, ip }
+    }
+}
+
+impl NetworkBackend for PoolBackend {
+    fn init(&mut self) -> Result<(), String> {
+        if self.slot >= self.pool.slot_count() {
+            return Err(format!("pool has no slot {}", self.slot));
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.pool.net_rx(self.slot).pop_frame())
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<(), String> {
+        // Like a switch, drop what can't be delivered
+        self.pool.switch_frame(self.slot, buf);
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.pool.mac(self.slot)
+    }
+
+    fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+        self.ip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem::SlotState;
+
+    #[test]
+    fn test_pooled_nics_reach_each_other() {
+        let pool = PoolBuffer::new(2).unwrap();
+        let a_mac = [0x52, 0x54, 0, 0, 0, 1];
+        let b_mac = [0x52, 0x54, 0, 0, 0, 2];
+        let a_slot = pool.claim().unwrap();
+        let b_slot = pool.claim().unwrap();
+        pool.set_mac(a_slot, a_mac);
+        pool.set_mac(b_slot, b_mac);
+        let mut a = PoolBackend::new(pool.clone(), a_slot, Some([10, 0, 3, 1]));
+        let mut b = PoolBackend::new(pool.clone(), b_slot, None);
+        a.init().unwrap();
+        b.init().unwrap();
+        assert_eq!(a.mac_address(), a_mac);
+        assert_eq!(a.get_assigned_ip(), Some([10, 0, 3, 1]));
+
+        let mut frame = vec![0u8; 64];
+        frame[0..6].copy_from_slice(&b_mac);
+        frame[6..12].copy_from_slice(&a_mac);
+        // Not running yet: dropped
+        a.send(&frame).unwrap();
+        assert_eq!(b.recv().unwrap(), None);
+
+        pool.set_state(b_slot, SlotState::Running);
+        a.send(&frame).unwrap();
+        assert_eq!(b.recv().unwrap(), Some(frame));
+        assert!(PoolBackend::new(pool, 5, None).init().is_err());
+    }
+}
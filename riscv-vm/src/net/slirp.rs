@@ -0,0 +1,887 @@
+//! User-mode NAT backend ("slirp").
+//!
+//! [`SlirpBackend`] plays the relay's gateway inside the VM process: it
+//! answers ARP for the virtual gateway and terminates the guest's TCP, UDP
+//! and ICMP echo traffic on ordinary host sockets, so a guest reaches the
+//! network with the privileges of the VM process and without a relay.
+//!
+//! The guest sits at [`GUEST_IP`] on 10.0.2.0/24 behind [`GATEWAY_IP`], the
+//! addresses the kernel falls back to. Traffic for the gateway address
+//! itself goes to the host's loopback interface. Echo requests are answered
+//! by running the system `ping`, as the relay does, since ICMP sockets need
+//! privileges. TCP is spliced segment by segment: the guest's SYN is
+//! answered once the host connection is up, guest data is acknowledged as
+//! it is handed to the host socket, and host data goes back in MSS-sized
+//! segments within the guest's window, resent when the guest stops
+//! acknowledging it.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, UdpSocket};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::NetworkBackend;
+
+/// Address the guest is given.
+pub const GUEST_IP: [u8; 4] = [10, 0, 2, 15];
+/// Address of the virtual gateway; connections to it reach the host.
+pub const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
+/// MAC address the gateway answers ARP with.
+pub const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+
+const DEFAULT_GUEST_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const PROTO_ICMP: u8 = 1;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Largest TCP payload sent to the guest.
+const MSS: usize = 1460;
+/// Most data buffered in either direction of a TCP connection.
+const MAX_WINDOW: usize = 65535;
+/// Time without an ACK before unacknowledged data is sent again.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// UDP flows without traffic for this long are closed.
+const UDP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A guest connection: the guest's port and the remote endpoint it
+/// addressed.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct FlowKey {
+    guest_port: u16,
+    remote_ip: [u8; 4],
+    remote_port: u16,
+}
+
+impl FlowKey {
+    /// Host address the flow is forwarded to.
+    fn host_addr(&self) -> SocketAddr {
+        let ip = if self.remote_ip == GATEWAY_IP {
+            Ipv4Addr::LOCALHOST
+        } else {
+            Ipv4Addr::from(self.remote_ip)
+        };
+        SocketAddr::V4(SocketAddrV4::new(ip, self.remote_port))
+    }
+}
+
+struct UdpFlow {
+    socket: UdpSocket,
+    last_activity: Instant,
+}
+
+struct TcpFlow {
+    /// Host connection, `None` while it is being set up.
+    stream: Option<TcpStream>,
+    /// Our initial sequence number.
+    iss: u32,
+    /// Next sequence number expected from the guest.
+    rcv_nxt: u32,
+    /// Oldest sequence number the guest has not acknowledged.
+    snd_una: u32,
+    /// Data sent to the guest from `snd_una` on, kept for retransmission.
+    unacked: Vec<u8>,
+    /// Receive window the guest last advertised.
+    window: usize,
+    /// Guest data the host socket has not taken yet.
+    to_host: Vec<u8>,
+    /// The host closed its side and our FIN follows `unacked`.
+    host_fin: bool,
+    fin_acked: bool,
+    /// The guest closed its side; the host socket is shut down for
+    /// writing once `to_host` drains.
+    guest_fin: bool,
+    shut_down: bool,
+    last_sent: Instant,
+}
+
+impl TcpFlow {
+    fn snd_nxt(&self) -> u32 {
+        self.snd_una.wrapping_add(self.unacked.len() as u32)
+    }
+
+    fn advertised_window(&self) -> u16 {
+        MAX_WINDOW.saturating_sub(self.to_host.len()) as u16
+    }
+
+    fn finished(&self) -> bool {
+        self.guest_fin && self.fin_acked && self.to_host.is_empty()
+    }
+}
+
+/// A host connection attempt finishing on its worker thread.
+type Connected = (FlowKey, std::io::Result<TcpStream>);
+
+struct Nat {
+    guest_mac: [u8; 6],
+    /// Frames for the guest, built here or by worker threads.
+    to_guest_tx: Sender<Vec<u8>>,
+    to_guest_rx: Receiver<Vec<u8>>,
+    connected_tx: Sender<Connected>,
+    connected_rx: Receiver<Connected>,
+    udp: HashMap<FlowKey, UdpFlow>,
+    tcp: HashMap<FlowKey, TcpFlow>,
+    next_iss: u32,
+}
+
+/// In-process user-mode NAT; see the [module docs](self).
+pub struct SlirpBackend {
+    nat: Mutex<Nat>,
+}
+
+impl SlirpBackend {
+    pub fn new() -> Self {
+        Self::with_mac(DEFAULT_GUEST_MAC)
+    }
+
+    /// Create a backend for a guest NIC with address `mac`.
+    pub fn with_mac(mac: [u8; 6]) -> Self {
+        let (to_guest_tx, to_guest_rx) = channel();
+        let (connected_tx, connected_rx) = channel();
+        Self {
+            nat: Mutex::new(Nat {
+                guest_mac: mac,
+                to_guest_tx,
+                to_guest_rx,
+                connected_tx,
+                connected_rx,
+                udp: HashMap::new(),
+                tcp: HashMap::new(),
+                next_iss: 0x1000_0000,
+            }),
+        }
+    }
+}
+
+impl Default for SlirpBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkBackend for SlirpBackend {
+    fn init(&mut self) -> Result<(), String> {
+        log::info!(
+            "[Slirp] User-mode NAT up: guest {}, gateway {}",
+            Ipv4Addr::from(GUEST_IP),
+            Ipv4Addr::from(GATEWAY_IP)
+        );
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let nat = self.nat.get_mut().map_err(|e| e.to_string())?;
+        nat.poll();
+        Ok(nat.to_guest_rx.try_recv().ok())
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<(), String> {
+        let mut nat = self.nat.lock().map_err(|e| e.to_string())?;
+        nat.handle_frame(buf);
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.nat
+            .lock()
+            .map(|nat| nat.guest_mac)
+            .unwrap_or(DEFAULT_GUEST_MAC)
+    }
+
+    fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+        Some(GUEST_IP)
+    }
+
+    fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(frame) = self.recv()? {
+                return Ok(Some(frame));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Nat {
+    /// Handle a frame sent by the guest.
+    fn handle_frame(&mut self, frame: &[u8]) {
+        if frame.len() < 14 {
+            return;
+        }
+        self.guest_mac.copy_from_slice(&frame[6..12]);
+        match u16::from_be_bytes([frame[12], frame[13]]) {
+            ETHERTYPE_ARP => self.handle_arp(&frame[14..]),
+            ETHERTYPE_IPV4 => self.handle_ipv4(&frame[14..]),
+            _ => {}
+        }
+    }
+
+    fn handle_arp(&mut self, arp: &[u8]) {
+        // Ethernet/IPv4 request for the gateway
+        if arp.len() < 28 || arp[6..8] != [0, 1] || arp[24..28] != GATEWAY_IP {
+            return;
+        }
+        let mut reply = Vec::with_capacity(42);
+        reply.extend_from_slice(&arp[8..14]);
+        reply.extend_from_slice(&GATEWAY_MAC);
+        reply.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        reply.extend_from_slice(&arp[..6]);
+        reply.extend_from_slice(&[0, 2]);
+        reply.extend_from_slice(&GATEWAY_MAC);
+        reply.extend_from_slice(&GATEWAY_IP);
+        reply.extend_from_slice(&arp[8..18]);
+        self.to_guest(reply);
+    }
+
+    fn handle_ipv4(&mut self, ip: &[u8]) {
+        if ip.len() < 20 || ip[0] >> 4 != 4 {
+            return;
+        }
+        let header_len = (ip[0] & 0x0f) as usize * 4;
+        let total_len = (u16::from_be_bytes([ip[2], ip[3]]) as usize).min(ip.len());
+        // Fragments are not reassembled
+        let fragmented = u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0;
+        if header_len < 20 || total_len < header_len || fragmented {
+            return;
+        }
+        let src: [u8; 4] = ip[12..16].try_into().unwrap();
+        let dst: [u8; 4] = ip[16..20].try_into().unwrap();
+        // Only unicast leaves the guest's network
+        if dst[0] >= 224 || dst == [10, 0, 2, 255] {
+            return;
+        }
+        let payload = &ip[header_len..total_len];
+        match ip[9] {
+            PROTO_ICMP => self.handle_icmp(src, dst, payload),
+            PROTO_UDP => self.handle_udp(dst, payload),
+            PROTO_TCP => self.handle_tcp(dst, payload),
+            _ => {}
+        }
+    }
+
+    fn handle_icmp(&mut self, src: [u8; 4], dst: [u8; 4], icmp: &[u8]) {
+        // Echo request only
+        if icmp.len() < 8 || icmp[0] != 8 {
+            return;
+        }
+        let mut reply = icmp.to_vec();
+        reply[0] = 0;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(0, &reply);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        let frame = ipv4_frame(self.guest_mac, dst, src, PROTO_ICMP, &reply);
+        if dst == GATEWAY_IP {
+            self.to_guest(frame);
+            return;
+        }
+        let to_guest = self.to_guest_tx.clone();
+        thread::spawn(move || {
+            let reachable = Command::new("ping")
+                .args(["-c", "1", "-W", "3", &Ipv4Addr::from(dst).to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if reachable {
+                let _ = to_guest.send(frame);
+            }
+        });
+    }
+
+    fn handle_udp(&mut self, dst: [u8; 4], udp: &[u8]) {
+        if udp.len() < 8 {
+            return;
+        }
+        let key = FlowKey {
+            guest_port: u16::from_be_bytes([udp[0], udp[1]]),
+            remote_ip: dst,
+            remote_port: u16::from_be_bytes([udp[2], udp[3]]),
+        };
+        let len = (u16::from_be_bytes([udp[4], udp[5]]) as usize).clamp(8, udp.len());
+        let flow = match self.udp.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").and_then(|socket| {
+                    socket.connect(key.host_addr())?;
+                    socket.set_nonblocking(true)?;
+                    Ok(socket)
+                });
+                match socket {
+                    Ok(socket) => entry.insert(UdpFlow {
+                        socket,
+                        last_activity: Instant::now(),
+                    }),
+                    Err(e) => {
+                        log::warn!("[Slirp] UDP to {} failed: {}", key.host_addr(), e);
+                        return;
+                    }
+                }
+            }
+        };
+        flow.last_activity = Instant::now();
+        if let Err(e) = flow.socket.send(&udp[8..len]) {
+            log::debug!("[Slirp] UDP send to {} failed: {}", key.host_addr(), e);
+        }
+    }
+
+    fn handle_tcp(&mut self, dst: [u8; 4], tcp: &[u8]) {
+        if tcp.len() < 20 {
+            return;
+        }
+        let key = FlowKey {
+            guest_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+            remote_ip: dst,
+            remote_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+        };
+        let seq = u32::from_be_bytes(tcp[4..8].try_into().unwrap());
+        let ack = u32::from_be_bytes(tcp[8..12].try_into().unwrap());
+        let data_offset = ((tcp[12] >> 4) as usize * 4).clamp(20, tcp.len());
+        let flags = tcp[13];
+        let window = u16::from_be_bytes([tcp[14], tcp[15]]) as usize;
+        let payload = &tcp[data_offset..];
+
+        if flags & TCP_RST != 0 {
+            self.tcp.remove(&key);
+            return;
+        }
+        if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+            self.handle_syn(key, seq, window);
+            return;
+        }
+        let Some(flow) = self.tcp.get_mut(&key) else {
+            // Not ours (any more): tell the guest
+            if flags & TCP_ACK != 0 {
+                let reset = tcp_frame(self.guest_mac, key, ack, 0, TCP_RST, 0, &[]);
+                self.to_guest(reset);
+            }
+            return;
+        };
+        if flow.stream.is_none() {
+            return;
+        }
+
+        if flags & TCP_ACK != 0 {
+            let acked = ack.wrapping_sub(flow.snd_una) as usize;
+            if acked <= flow.unacked.len() {
+                flow.unacked.drain(..acked);
+                flow.snd_una = ack;
+                if acked > 0 {
+                    flow.last_sent = Instant::now();
+                }
+            } else if flow.host_fin && acked == flow.unacked.len() + 1 {
+                flow.unacked.clear();
+                flow.snd_una = ack;
+                flow.fin_acked = true;
+            }
+            flow.window = window;
+        }
+
+        let mut ack_needed = false;
+        if !payload.is_empty() {
+            ack_needed = true;
+            // In-order data that fits is taken; anything else is dropped
+            // and the duplicate ACK asks for it again
+            if seq == flow.rcv_nxt && flow.to_host.len() + payload.len() <= MAX_WINDOW {
+                flow.to_host.extend_from_slice(payload);
+                flow.rcv_nxt = flow.rcv_nxt.wrapping_add(payload.len() as u32);
+            }
+        }
+        if flags & TCP_FIN != 0 {
+            ack_needed = true;
+            if seq.wrapping_add(payload.len() as u32) == flow.rcv_nxt && !flow.guest_fin {
+                flow.rcv_nxt = flow.rcv_nxt.wrapping_add(1);
+                flow.guest_fin = true;
+            }
+        }
+        flush_to_host(flow);
+        if ack_needed {
+            let seq = flow.snd_nxt() + u32::from(flow.host_fin && !flow.fin_acked);
+            let frame = tcp_frame(
+                self.guest_mac,
+                key,
+                seq,
+                flow.rcv_nxt,
+                TCP_ACK,
+                flow.advertised_window(),
+                &[],
+            );
+            self.to_guest(frame);
+        }
+        if self.tcp.get(&key).is_some_and(TcpFlow::finished) {
+            self.tcp.remove(&key);
+        }
+    }
+
+    /// Start a connection for the guest's SYN, or answer a repeated one.
+    fn handle_syn(&mut self, key: FlowKey, seq: u32, window: usize) {
+        if let Some(flow) = self.tcp.get(&key) {
+            if flow.stream.is_some() && flow.rcv_nxt == seq.wrapping_add(1) {
+                let syn_ack = syn_ack_frame(self.guest_mac, key, flow);
+                self.to_guest(syn_ack);
+            }
+            return;
+        }
+        let iss = self.next_iss;
+        self.next_iss = self.next_iss.wrapping_add(0x0001_0000);
+        let now = Instant::now();
+        self.tcp.insert(
+            key,
+            TcpFlow {
+                stream: None,
+                iss,
+                rcv_nxt: seq.wrapping_add(1),
+                snd_una: iss.wrapping_add(1),
+                unacked: Vec::new(),
+                window,
+                to_host: Vec::new(),
+                host_fin: false,
+                fin_acked: false,
+                guest_fin: false,
+                shut_down: false,
+                last_sent: now,
+            },
+        );
+        let connected = self.connected_tx.clone();
+        thread::spawn(move || {
+            let stream = TcpStream::connect_timeout(&key.host_addr(), CONNECT_TIMEOUT);
+            let _ = connected.send((key, stream));
+        });
+    }
+
+    /// Pick up finished connection attempts and host data.
+    fn poll(&mut self) {
+        while let Ok((key, result)) = self.connected_rx.try_recv() {
+            let Some(flow) = self.tcp.get_mut(&key) else {
+                continue;
+            };
+            let stream = result.and_then(|stream| {
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true)?;
+                Ok(stream)
+            });
+            match stream {
+                Ok(stream) => {
+                    flow.stream = Some(stream);
+                    flow.last_sent = Instant::now();
+                    let syn_ack = syn_ack_frame(self.guest_mac, key, flow);
+                    self.to_guest(syn_ack);
+                }
+                Err(e) => {
+                    log::debug!("[Slirp] TCP connect to {} failed: {}", key.host_addr(), e);
+                    let rcv_nxt = flow.rcv_nxt;
+                    self.tcp.remove(&key);
+                    let reset =
+                        tcp_frame(self.guest_mac, key, 0, rcv_nxt, TCP_RST | TCP_ACK, 0, &[]);
+                    self.to_guest(reset);
+                }
+            }
+        }
+
+        let mut frames = Vec::new();
+        let guest_mac = self.guest_mac;
+        self.tcp.retain(|key, flow| {
+            if flow.stream.is_none() {
+                return true;
+            }
+            let alive = poll_tcp(guest_mac, *key, flow, &mut frames);
+            if !alive {
+                let reset = tcp_frame(
+                    guest_mac,
+                    *key,
+                    flow.snd_nxt(),
+                    flow.rcv_nxt,
+                    TCP_RST | TCP_ACK,
+                    0,
+                    &[],
+                );
+                frames.push(reset);
+            }
+            alive && !flow.finished()
+        });
+
+        let mut buf = [0u8; 65536];
+        self.udp.retain(|key, flow| {
+            while let Ok(len) = flow.socket.recv(&mut buf) {
+                flow.last_activity = Instant::now();
+                frames.push(udp_frame(guest_mac, *key, &buf[..len]));
+            }
+            flow.last_activity.elapsed() < UDP_TIMEOUT
+        });
+
+        for frame in frames {
+            self.to_guest(frame);
+        }
+    }
+
+    fn to_guest(&self, frame: Vec<u8>) {
+        let _ = self.to_guest_tx.send(frame);
+    }
+}
+
+/// Move data between an established flow and its host socket, queueing
+/// segments for the guest in `frames`. Returns `false` if the host
+/// connection failed.
+fn poll_tcp(
+    guest_mac: [u8; 6],
+    key: FlowKey,
+    flow: &mut TcpFlow,
+    frames: &mut Vec<Vec<u8>>,
+) -> bool {
+    if !flush_to_host(flow) {
+        return false;
+    }
+    let Some(stream) = flow.stream.as_mut() else {
+        return true;
+    };
+
+    let window = flow.window.min(MAX_WINDOW);
+    let room = window.saturating_sub(flow.unacked.len());
+    if !flow.host_fin && room > 0 {
+        let mut buf = vec![0u8; room];
+        match stream.read(&mut buf) {
+            Ok(0) => {
+                flow.host_fin = true;
+                flow.last_sent = Instant::now();
+                frames.push(fin_frame(guest_mac, key, flow));
+            }
+            Ok(len) => {
+                let mut seq = flow.snd_nxt();
+                for chunk in buf[..len].chunks(MSS) {
+                    let flags = TCP_ACK | TCP_PSH;
+                    let window = flow.advertised_window();
+                    frames.push(tcp_frame(
+                        guest_mac,
+                        key,
+                        seq,
+                        flow.rcv_nxt,
+                        flags,
+                        window,
+                        chunk,
+                    ));
+                    seq = seq.wrapping_add(chunk.len() as u32);
+                }
+                if flow.unacked.is_empty() {
+                    flow.last_sent = Instant::now();
+                }
+                flow.unacked.extend_from_slice(&buf[..len]);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => {
+                log::debug!("[Slirp] TCP read from {} failed: {}", key.host_addr(), e);
+                return false;
+            }
+        }
+    }
+
+    let outstanding = !flow.unacked.is_empty() || (flow.host_fin && !flow.fin_acked);
+    if outstanding && flow.last_sent.elapsed() >= RETRANSMIT_TIMEOUT {
+        flow.last_sent = Instant::now();
+        if flow.unacked.is_empty() {
+            frames.push(fin_frame(guest_mac, key, flow));
+        } else {
+            let len = flow.unacked.len().min(MSS);
+            let flags = TCP_ACK | TCP_PSH;
+            let window = flow.advertised_window();
+            let chunk = &flow.unacked[..len];
+            frames.push(tcp_frame(
+                guest_mac,
+                key,
+                flow.snd_una,
+                flow.rcv_nxt,
+                flags,
+                window,
+                chunk,
+            ));
+        }
+    }
+    true
+}
+
+/// Write buffered guest data to the host socket, and pass on the guest's
+/// FIN once it is all written. Returns `false` if the socket failed.
+fn flush_to_host(flow: &mut TcpFlow) -> bool {
+    let Some(stream) = flow.stream.as_mut() else {
+        return true;
+    };
+    while !flow.to_host.is_empty() {
+        match stream.write(&flow.to_host) {
+            Ok(0) => return false,
+            Ok(len) => {
+                flow.to_host.drain(..len);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(_) => return false,
+        }
+    }
+    if flow.guest_fin && flow.to_host.is_empty() && !flow.shut_down {
+        flow.shut_down = true;
+        let _ = stream.shutdown(Shutdown::Write);
+    }
+    true
+}
+
+fn syn_ack_frame(guest_mac: [u8; 6], key: FlowKey, flow: &TcpFlow) -> Vec<u8> {
+    // Announce our MSS; no other options, so no window scaling
+    let mss = [2, 4, (MSS >> 8) as u8, MSS as u8];
+    let flags = TCP_SYN | TCP_ACK;
+    let window = flow.advertised_window();
+    let mut frame = tcp_frame(guest_mac, key, flow.iss, flow.rcv_nxt, flags, window, &mss);
+    // Turn the payload into an option: data offset 6 words
+    frame[14 + 20 + 12] = 6 << 4;
+    let tcp_len = frame.len() - 34;
+    frame[34 + 16..34 + 18].copy_from_slice(&[0, 0]);
+    let sum = transport_checksum(key.remote_ip, GUEST_IP, PROTO_TCP, &frame[34..34 + tcp_len]);
+    frame[34 + 16..34 + 18].copy_from_slice(&sum.to_be_bytes());
+    frame
+}
+
+fn fin_frame(guest_mac: [u8; 6], key: FlowKey, flow: &TcpFlow) -> Vec<u8> {
+    let flags = TCP_FIN | TCP_ACK;
+    let window = flow.advertised_window();
+    tcp_frame(
+        guest_mac,
+        key,
+        flow.snd_nxt(),
+        flow.rcv_nxt,
+        flags,
+        window,
+        &[],
+    )
+}
+
+/// Ones' complement sum of `data` on top of `sum`, folded and inverted.
+fn checksum(mut sum: u32, data: &[u8]) -> u16 {
+    let mut chunks = data.chunks_exact(2);
+    for pair in &mut chunks {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// TCP/UDP checksum of `segment` with its IPv4 pseudo-header.
+fn transport_checksum(src: [u8; 4], dst: [u8; 4], protocol: u8, segment: &[u8]) -> u16 {
+    let mut pseudo = [0u8; 12];
+    pseudo[..4].copy_from_slice(&src);
+    pseudo[4..8].copy_from_slice(&dst);
+    pseudo[9] = protocol;
+    pseudo[10..].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+    let sum = !checksum(0, &pseudo) as u32;
+    checksum(sum, segment)
+}
+
+/// Ethernet frame from the gateway carrying an IPv4 packet.
+fn ipv4_frame(
+    dst_mac: [u8; 6],
+    src: [u8; 4],
+    dst: [u8; 4],
+    protocol: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(34 + payload.len());
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&GATEWAY_MAC);
+    frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    // ID 0, don't fragment, TTL 64
+    frame.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&dst);
+    let sum = checksum(0, &frame[14..34]);
+    frame[24..26].copy_from_slice(&sum.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// TCP segment from the remote end of `key` to the guest.
+fn tcp_frame(
+    guest_mac: [u8; 6],
+    key: FlowKey,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&key.remote_port.to_be_bytes());
+    segment.extend_from_slice(&key.guest_port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    let sum = transport_checksum(key.remote_ip, GUEST_IP, PROTO_TCP, &segment);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    ipv4_frame(guest_mac, key.remote_ip, GUEST_IP, PROTO_TCP, &segment)
+}
+
+/// UDP datagram from the remote end of `key` to the guest.
+fn udp_frame(guest_mac: [u8; 6], key: FlowKey, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&key.remote_port.to_be_bytes());
+    datagram.extend_from_slice(&key.guest_port.to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    let sum = match transport_checksum(key.remote_ip, GUEST_IP, PROTO_UDP, &datagram) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    ipv4_frame(guest_mac, key.remote_ip, GUEST_IP, PROTO_UDP, &datagram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const GUEST_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0xaa, 0xbb, 0xcc];
+
+    /// Frame from the guest to `dst`, as the guest's IP stack builds it.
+    fn guest_frame(dst: [u8; 4], protocol: u8, segment: &[u8]) -> Vec<u8> {
+        let mut frame = ipv4_frame(GATEWAY_MAC, GUEST_IP, dst, protocol, segment);
+        frame[6..12].copy_from_slice(&GUEST_MAC);
+        frame
+    }
+
+    fn guest_tcp(port: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let key = FlowKey {
+            guest_port: port,
+            remote_ip: GUEST_IP,
+            remote_port: 40000,
+        };
+        // Built from the far side's point of view, so swap the ports back
+        let mut frame = tcp_frame(GATEWAY_MAC, key, seq, ack, flags, 8192, payload);
+        let mut segment = frame.split_off(34);
+        segment[..2].copy_from_slice(&40000u16.to_be_bytes());
+        segment[2..4].copy_from_slice(&port.to_be_bytes());
+        segment[16..18].copy_from_slice(&[0, 0]);
+        let sum = transport_checksum(GUEST_IP, GATEWAY_IP, PROTO_TCP, &segment);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        guest_frame(GATEWAY_IP, PROTO_TCP, &segment)
+    }
+
+    fn next_frame(backend: &mut SlirpBackend) -> Vec<u8> {
+        backend
+            .receive_timeout(Duration::from_secs(5))
+            .unwrap()
+            .expect("no frame for the guest")
+    }
+
+    #[test]
+    fn test_arp_and_udp_via_gateway() {
+        let mut backend = SlirpBackend::with_mac(GUEST_MAC);
+        let mut arp = vec![0xff; 6];
+        arp.extend_from_slice(&GUEST_MAC);
+        arp.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0, 6, 4, 0, 1]);
+        arp.extend_from_slice(&GUEST_MAC);
+        arp.extend_from_slice(&GUEST_IP);
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&GATEWAY_IP);
+        backend.send(&arp).unwrap();
+        let reply = next_frame(&mut backend);
+        assert_eq!(reply[..6], GUEST_MAC);
+        assert_eq!(reply[20..22], [0, 2]);
+        assert_eq!(reply[22..28], GATEWAY_MAC);
+        assert_eq!(reply[28..32], GATEWAY_IP);
+
+        let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = host.local_addr().unwrap().port();
+        let mut datagram = vec![0x30, 0x39];
+        datagram.extend_from_slice(&port.to_be_bytes());
+        datagram.extend_from_slice(&[0, 12, 0, 0]);
+        datagram.extend_from_slice(b"ping");
+        backend
+            .send(&guest_frame(GATEWAY_IP, PROTO_UDP, &datagram))
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, from) = host.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        host.send_to(b"pong", from).unwrap();
+
+        let frame = next_frame(&mut backend);
+        assert_eq!(frame[23], PROTO_UDP);
+        assert_eq!(frame[26..30], GATEWAY_IP);
+        assert_eq!(frame[30..34], GUEST_IP);
+        assert_eq!(checksum(0, &frame[14..34]), 0);
+        assert_eq!(
+            transport_checksum(GATEWAY_IP, GUEST_IP, PROTO_UDP, &frame[34..]),
+            0
+        );
+        assert_eq!(frame[34..36], port.to_be_bytes());
+        assert_eq!(frame[36..38], [0x30, 0x39]);
+        assert_eq!(&frame[42..], b"pong");
+    }
+
+    #[test]
+    fn test_tcp_connection_is_spliced() {
+        let mut backend = SlirpBackend::with_mac(GUEST_MAC);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let seg = |frame: &[u8]| {
+            let tcp = &frame[34..];
+            let seq = u32::from_be_bytes(tcp[4..8].try_into().unwrap());
+            let ack = u32::from_be_bytes(tcp[8..12].try_into().unwrap());
+            let offset = (tcp[12] >> 4) as usize * 4;
+            (seq, ack, tcp[13], tcp[offset..].to_vec())
+        };
+
+        backend
+            .send(&guest_tcp(port, 1000, 0, TCP_SYN, &[]))
+            .unwrap();
+        let (mut host, _) = listener.accept().unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let syn_ack = next_frame(&mut backend);
+        assert_eq!(
+            transport_checksum(GATEWAY_IP, GUEST_IP, PROTO_TCP, &syn_ack[34..]),
+            0
+        );
+        let (iss, ack, flags, _) = seg(&syn_ack);
+        assert_eq!((ack, flags), (1001, TCP_SYN | TCP_ACK));
+
+        backend
+            .send(&guest_tcp(port, 1001, iss + 1, TCP_ACK | TCP_PSH, b"hello"))
+            .unwrap();
+        let (_, ack, _, _) = seg(&next_frame(&mut backend));
+        assert_eq!(ack, 1006);
+        let mut buf = [0u8; 5];
+        host.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        host.write_all(b"world").unwrap();
+        drop(host);
+        let (seq, _, _, data) = seg(&next_frame(&mut backend));
+        assert_eq!((seq, data.as_slice()), (iss + 1, &b"world"[..]));
+        let (seq, _, flags, _) = seg(&next_frame(&mut backend));
+        assert_eq!((seq, flags), (iss + 6, TCP_FIN | TCP_ACK));
+    }
+}
@@ -0,0 +1,141 @@
+//! TAP network backend for Linux hosts.
+//!
+//! [`TapBackend`] puts the guest's Ethernet frames on a host TAP interface,
+//! so the guest joins whatever L2 network the host bridges that interface
+//! into. Creating the interface needs `CAP_NET_ADMIN` (or a persistent
+//! interface the user owns); addressing it, bridging it and bringing it up
+//! are left to the host. The guest's own address is reported through the
+//! NIC's config space, as the relay's assignment would be.
+
+use std::io::ErrorKind;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use tun_tap::{Iface, Mode};
+
+use super::NetworkBackend;
+
+/// Largest frame read from the interface: MTU plus Ethernet header and a
+/// VLAN tag.
+const MAX_FRAME: usize = 1500 + 18;
+
+/// Guest NIC bridged to a host TAP interface.
+pub struct TapBackend {
+    /// Requested interface name; empty lets the host pick one.
+    name: String,
+    mac: [u8; 6],
+    ip: Option<[u8; 4]>,
+    iface: Option<Iface>,
+}
+
+impl TapBackend {
+    /// Backend for TAP interface `name` (e.g. `tap0`, or `""` for the next
+    /// free `tapN`), giving the guest NIC address `mac` and, if set, IPv4
+    /// address `ip`.
+    pub fn new(name: &str, mac: [u8; 6], ip: Option<[u8; 4]>) -> Self {
+        Self {
+            name: name.to_string(),
+            mac,
+            ip,
+            iface: None,
+        }
+    }
+
+    /// Name of the interface, as the host assigned it once opened.
+    pub fn name(&self) -> &str {
+        self.iface.as_ref().map_or(&self.name, |iface| iface.name())
+    }
+
+    /// Wait up to `timeout` for a frame to read.
+    fn readable(iface: &Iface, timeout: Duration) -> Result<bool, String> {
+        let mut pollfd = libc::pollfd {
+            fd: iface.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::Interrupted => Ok(false),
+                    _ => Err(format!("poll on {} failed: {}", iface.name(), err)),
+                }
+            }
+            0 => Ok(false),
+            _ => Ok(pollfd.revents & libc::POLLIN != 0),
+        }
+    }
+}
+
+impl NetworkBackend for TapBackend {
+    fn init(&mut self) -> Result<(), String> {
+        if self.iface.is_some() {
+            return Ok(());
+        }
+        let iface = Iface::without_packet_info(&self.name, Mode::Tap).map_err(|e| {
+            let name = if self.name.is_empty() {
+                "tap"
+            } else {
+                &self.name
+            };
+            format!("cannot open TAP interface {}: {}", name, e)
+        })?;
+        log::info!("[TapBackend] Attached to {}", iface.name());
+        self.iface = Some(iface);
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+        self.receive_timeout(Duration::ZERO)
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<(), String> {
+        let iface = self.iface.as_ref().ok_or("TAP interface not open")?;
+        iface
+            .send(buf)
+            .map(|_| ())
+            .map_err(|e| format!("write to {} failed: {}", iface.name(), e))
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+        self.ip
+    }
+
+    fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+        let iface = self.iface.as_ref().ok_or("TAP interface not open")?;
+        if !Self::readable(iface, timeout)? {
+            return Ok(None);
+        }
+        let mut frame = vec![0u8; MAX_FRAME];
+        match iface.recv(&mut frame) {
+            Ok(len) => {
+                frame.truncate(len);
+                Ok(Some(frame))
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                Ok(None)
+            }
+            Err(e) => Err(format!("read from {} failed: {}", iface.name(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unopened_interface() {
+        let mut backend = TapBackend::new("vmtap9", [0x52, 0x54, 0, 1, 2, 3], Some([10, 0, 2, 20]));
+        assert_eq!(backend.name(), "vmtap9");
+        assert_eq!(backend.mac_address(), [0x52, 0x54, 0, 1, 2, 3]);
+        assert_eq!(backend.get_assigned_ip(), Some([10, 0, 2, 20]));
+        assert!(backend.send(&[0; 60]).is_err());
+        assert!(backend.recv().is_err());
+    }
+}
@@ -4,8 +4,14 @@
 //! using the relay protocol:
 //! - 0x00 prefix: Control messages (JSON-encoded)
 //! - 0x01 prefix: Ethernet data frames
+//! - 0x02 prefix: Batches of the above (protocol v2, see [`super::batch`])
+//!
+//! A `network` query parameter in the relay URL
+//! (`https://relay:4433/?network=lab`) joins that virtual network instead
+//! of the relay's default one; VMs only see frames from their own network.
 
 use super::NetworkBackend;
+use super::batch::{Batcher, MSG_TYPE_BATCH, PROTOCOL_VERSION, unpack_batch};
 
 /// Message type prefix for control messages
 const MSG_TYPE_CONTROL: u8 = 0x00;
@@ -19,11 +25,33 @@ const HEARTBEAT_INTERVAL_SECS: u64 = 15;
 /// Client sends QUIC PING frames at this interval to keep the connection alive.
 const QUIC_KEEP_ALIVE_SECS: u64 = 10;
 
-/// Control message for registration
-fn make_register_message(mac: &[u8; 6]) -> Vec<u8> {
+/// Control message for registration, announcing the protocol version,
+/// whether we accept LZ4-compressed batches, the network to join and the
+/// ticket of the migration that brought the VM here
+fn make_register_message(
+    mac: &[u8; 6],
+    compression: bool,
+    network: Option<&str>,
+    migration: Option<&str>,
+) -> Vec<u8> {
+    let network = network
+        .map(|id| format!(r#","network":"{}""#, id))
+        .unwrap_or_default();
+    let migration = migration
+        .map(|ticket| format!(r#","migration":"{}""#, ticket))
+        .unwrap_or_default();
     let json = format!(
-        r#"{{"type":"Register","mac":[{},{},{},{},{},{}]}}"#,
-        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        r#"{{"type":"Register","mac":[{},{},{},{},{},{}],"version":{},"compression":{}{}{}}}"#,
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5],
+        PROTOCOL_VERSION,
+        compression,
+        network,
+        migration
     );
     let mut msg = Vec::with_capacity(1 + json.len());
     msg.push(MSG_TYPE_CONTROL);
@@ -31,6 +59,22 @@ fn make_register_message(mac: &[u8; 6]) -> Vec<u8> {
     msg
 }
 
+/// Virtual network named by the `network` query parameter of a relay URL.
+/// IDs the relay would reject (see its `is_valid_network_id`) are ignored.
+fn network_from_url(url: &str) -> Option<&str> {
+    let (_, query) = url.split_once('?')?;
+    let id = query.split('&').find_map(|pair| pair.strip_prefix("network="))?;
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if !valid {
+        log::warn!("[WebTransport] Ignoring invalid network ID '{}'", id);
+    }
+    valid.then_some(id)
+}
+
 /// Control message for heartbeat
 fn make_heartbeat_message() -> Vec<u8> {
     let json = r#"{"type":"Heartbeat"}"#;
@@ -48,6 +92,17 @@ fn encode_data_frame(ethernet_frame: &[u8]) -> Vec<u8> {
     frame
 }
 
+/// Split a received datagram into relay messages, unpacking batches
+fn split_datagram(data: Vec<u8>) -> Vec<Vec<u8>> {
+    if data.first() != Some(&MSG_TYPE_BATCH) {
+        return vec![data];
+    }
+    unpack_batch(&data).unwrap_or_else(|e| {
+        log::warn!("[WebTransport] Dropping batch: {}", e);
+        Vec::new()
+    })
+}
+
 /// Decode a received message, stripping the type prefix for data frames
 fn decode_message(data: &[u8]) -> Option<Vec<u8>> {
     if data.is_empty() {
@@ -80,6 +135,30 @@ fn decode_message(data: &[u8]) -> Option<Vec<u8>> {
     }
 }
 
+/// Parse the negotiated protocol version and compression from an `Assigned`
+/// message. Relays that predate v2 omit both fields.
+fn parse_session_from_json(json_str: &str) -> (u8, bool) {
+    let marker = "\"version\":";
+    let version = json_str
+        .find(marker)
+        .and_then(|start| {
+            let rest = &json_str[start + marker.len()..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..end].parse().ok()
+        })
+        .unwrap_or(1);
+    (version, json_str.contains("\"compression\":true"))
+}
+
+/// Parse the key for migrating the VM away from an `Assigned` message.
+/// Relays that predate it send none.
+fn parse_migration_key_from_json(json_str: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(json_str).ok()?;
+    json["migration_key"].as_str().map(str::to_string)
+}
+
 /// Parse IP address from JSON string containing "ip":[a,b,c,d]
 fn parse_ip_from_json(json_str: &str) -> Option<[u8; 4]> {
     // Look for "ip":[ pattern
@@ -104,12 +183,14 @@ fn parse_ip_from_json(json_str: &str) -> Option<[u8; 4]> {
 #[cfg(not(target_arch = "wasm32"))]
 mod native {
     use super::*;
+    use crate::net::TransportMetricsHandle;
+    use crate::net::batch::BatchConfig;
     use std::sync::Arc;
     use std::sync::Mutex;
     use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
     use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use tokio::runtime::Runtime;
     use wtransport::ClientConfig;
     use wtransport::Endpoint;
@@ -127,12 +208,22 @@ mod native {
         registered: Arc<AtomicBool>,
         /// IP address assigned by the relay server
         assigned_ip: Arc<Mutex<Option<[u8; 4]>>>,
+        /// Key for migrating the VM away, from the latest registration
+        migration_key: Arc<Mutex<Option<String>>>,
         /// Connection attempt counter (for debugging)
         connection_attempts: Arc<AtomicU32>,
+        /// Batching/compression counters, updated by the transport thread
+        metrics: TransportMetricsHandle,
     }
 
     impl WebTransportBackend {
         pub fn new(url: &str, cert_hash: Option<String>) -> Self {
+            Self::with_batching(url, cert_hash, BatchConfig::default())
+        }
+
+        /// Create a backend that batches outgoing frames with `batching` once
+        /// the relay has agreed to protocol v2.
+        pub fn with_batching(url: &str, cert_hash: Option<String>, batching: BatchConfig) -> Self {
             log::warn!("[WebTransport] Creating backend for URL: {}", url);
 
             // Generate a random MAC address (locally administered, unicast)
@@ -160,6 +251,29 @@ mod native {
             mac[4] = ((seed >> 16) & 0xff) as u8;
             mac[5] = (seed & 0xff) as u8;
 
+            Self::start(url, cert_hash, batching, mac, None)
+        }
+
+        /// Create a backend for a VM that arrived with the migration
+        /// `ticket`, keeping its old MAC `mac` so the relay gives it back
+        /// its address.
+        pub fn resume(
+            url: &str,
+            cert_hash: Option<String>,
+            batching: BatchConfig,
+            mac: [u8; 6],
+            ticket: String,
+        ) -> Self {
+            Self::start(url, cert_hash, batching, mac, Some(ticket))
+        }
+
+        fn start(
+            url: &str,
+            cert_hash: Option<String>,
+            batching: BatchConfig,
+            mac: [u8; 6],
+            migration: Option<String>,
+        ) -> Self {
             let (tx_to_transport, rx_to_transport) = channel::<Vec<u8>>();
             let (tx_from_transport, rx_from_transport) = channel::<Vec<u8>>();
 
@@ -169,8 +283,12 @@ mod native {
             let registered_clone = registered.clone();
             let assigned_ip = Arc::new(Mutex::new(None));
             let assigned_ip_clone = assigned_ip.clone();
+            let migration_key: Arc<Mutex<Option<String>>> = Arc::default();
+            let migration_key_clone = migration_key.clone();
             let connection_attempts = Arc::new(AtomicU32::new(0));
             let connection_attempts_clone = connection_attempts.clone();
+            let metrics: TransportMetricsHandle = Arc::default();
+            let metrics_clone = metrics.clone();
 
             thread::spawn(move || {
                 let rt = Runtime::new().unwrap();
@@ -259,7 +377,12 @@ mod native {
                         log::warn!("[WebTransport] Connected successfully!");
 
                         // Send registration message
-                        let register_msg = make_register_message(&mac_copy);
+                        let register_msg = make_register_message(
+                            &mac_copy,
+                            batching.compress,
+                            network_from_url(&url),
+                            migration.as_deref(),
+                        );
                         if let Err(e) = connection.send_datagram(register_msg) {
                             log::warn!("[WebTransport] ERROR: Failed to send registration: {}", e);
                             tokio::time::sleep(Duration::from_secs(reconnect_delay)).await;
@@ -270,6 +393,11 @@ mod native {
                             mac_copy[0], mac_copy[1], mac_copy[2], mac_copy[3], mac_copy[4], mac_copy[5]);
 
                         let connection = Arc::new(connection);
+
+                        // Frames go out one per datagram until the relay agrees to v2
+                        let mut batcher = Batcher::new(BatchConfig::disabled(), false);
+                        batcher.metrics = metrics_clone.lock().map(|m| m.clone()).unwrap_or_default();
+                        let mut published = 0;
                         
                         // Run sender/receiver/heartbeat in a combined loop using select!
                         // This avoids issues with sharing channels across tasks
@@ -280,20 +408,37 @@ mod native {
                             tokio::select! {
                                 // Check for data to send to relay
                                 _ = send_check_interval.tick() => {
-                                    // Drain all pending sends
+                                    let now = Instant::now();
+                                    // Drain all pending sends into the batcher
                                     loop {
-                                        match rx_to_transport.try_recv() {
-                                            Ok(data) => {
-                                                if let Err(e) = connection.send_datagram(data) {
-                                                    log::error!("Failed to send datagram: {}", e);
-                                                    break 'connection_loop;
-                                                }
-                                            }
+                                        let data = match rx_to_transport.try_recv() {
+                                            Ok(data) => data,
                                             Err(TryRecvError::Empty) => break,
                                             Err(TryRecvError::Disconnected) => {
                                                 log::warn!("[WebTransport] TX channel disconnected, shutting down");
                                                 return; // Permanent shutdown
                                             }
+                                        };
+                                        if let Some(datagram) = batcher.push(data, now)
+                                            && let Err(e) = connection.send_datagram(datagram)
+                                        {
+                                            log::error!("Failed to send datagram: {}", e);
+                                            break 'connection_loop;
+                                        }
+                                    }
+                                    if let Some(datagram) = batcher.poll(now)
+                                        && let Err(e) = connection.send_datagram(datagram)
+                                    {
+                                        log::error!("Failed to send datagram: {}", e);
+                                        break 'connection_loop;
+                                    }
+
+                                    // Publish counters when there was traffic
+                                    let activity = batcher.metrics.datagrams_sent + batcher.metrics.datagrams_received;
+                                    if activity != published {
+                                        published = activity;
+                                        if let Ok(mut guard) = metrics_clone.lock() {
+                                            *guard = batcher.metrics.clone();
                                         }
                                     }
                                 }
@@ -312,31 +457,45 @@ mod native {
                                 result = connection.receive_datagram() => {
                                     match result {
                                         Ok(datagram) => {
-                                            let data = datagram.to_vec();
-                                            
-                                            // Check for Assigned message to confirm registration and extract IP
-                                            if !data.is_empty() && data[0] == MSG_TYPE_CONTROL {
-                                                if let Ok(json_str) = std::str::from_utf8(&data[1..]) {
-                                                    if json_str.contains("\"type\":\"Assigned\"") {
-                                                        registered_clone.store(true, Ordering::SeqCst);
-                                                        
-                                                        // Parse IP from JSON: {"type":"Assigned","ip":[10,0,2,X],...}
-                                                        if let Some(ip) = parse_ip_from_json(json_str) {
-                                                            if let Ok(mut guard) = assigned_ip_clone.lock() {
-                                                                *guard = Some(ip);
+                                            let wire_len = datagram.len();
+                                            let messages = split_datagram(datagram.to_vec());
+                                            batcher.metrics.record_received(wire_len, messages.len());
+
+                                            for data in messages {
+                                                // Check for Assigned message to confirm registration and extract IP
+                                                if !data.is_empty() && data[0] == MSG_TYPE_CONTROL {
+                                                    if let Ok(json_str) = std::str::from_utf8(&data[1..]) {
+                                                        if json_str.contains("\"type\":\"Assigned\"") {
+                                                            registered_clone.store(true, Ordering::SeqCst);
+                                                            
+                                                            // Parse IP from JSON: {"type":"Assigned","ip":[10,0,2,X],...}
+                                                            if let Some(ip) = parse_ip_from_json(json_str) {
+                                                                if let Ok(mut guard) = assigned_ip_clone.lock() {
+                                                                    *guard = Some(ip);
+                                                                }
+                                                                log::warn!("[WebTransport] IP Assigned: {}.{}.{}.{}", 
+                                                                    ip[0], ip[1], ip[2], ip[3]);
+                                                            }
+                                                            if let Ok(mut guard) = migration_key_clone.lock() {
+                                                                *guard = parse_migration_key_from_json(json_str);
+                                                            }
+
+                                                            let (version, compression) = parse_session_from_json(json_str);
+                                                            if version >= 2 {
+                                                                let metrics = std::mem::take(&mut batcher.metrics);
+                                                                batcher = Batcher::new(batching, compression);
+                                                                batcher.metrics = metrics;
                                                             }
-                                                            log::warn!("[WebTransport] IP Assigned: {}.{}.{}.{}", 
-                                                                ip[0], ip[1], ip[2], ip[3]);
+                                                            
+                                                            log::warn!("[WebTransport] Registered with relay: {}", json_str);
                                                         }
-                                                        
-                                                        log::warn!("[WebTransport] Registered with relay: {}", json_str);
                                                     }
                                                 }
-                                            }
-                                            
-                                            // Decode and forward Ethernet frames
-                                            if let Some(ethernet_frame) = decode_message(&data) {
-                                                let _ = tx_from_transport.send(ethernet_frame);
+                                                
+                                                // Decode and forward Ethernet frames
+                                                if let Some(ethernet_frame) = decode_message(&data) {
+                                                    let _ = tx_from_transport.send(ethernet_frame);
+                                                }
                                             }
                                         }
                                         Err(e) => {
@@ -363,7 +522,9 @@ mod native {
                 mac,
                 registered,
                 assigned_ip,
+                migration_key,
                 connection_attempts,
+                metrics,
             }
         }
 
@@ -371,6 +532,11 @@ mod native {
         pub fn is_registered(&self) -> bool {
             self.registered.load(Ordering::SeqCst)
         }
+
+        /// Shared handle to the transport counters.
+        pub fn metrics_handle(&self) -> TransportMetricsHandle {
+            self.metrics.clone()
+        }
     }
 
     impl NetworkBackend for WebTransportBackend {
@@ -412,12 +578,17 @@ mod native {
                 None
             }
         }
+
+        fn migration_key(&self) -> Option<String> {
+            self.migration_key.lock().ok()?.clone()
+        }
     }
 }
 
 #[cfg(target_arch = "wasm32")]
 mod wasm {
     use super::*;
+    use crate::net::batch::TransportMetrics;
     use js_sys::{Array, Uint8Array};
     use std::cell::RefCell;
     use std::collections::VecDeque;
@@ -448,6 +619,12 @@ mod wasm {
         connection_generation: u32,
         /// Heartbeat interval ID for cleanup
         heartbeat_interval_id: Option<i32>,
+        /// Transport counters (frames are sent unbatched from the browser)
+        metrics: TransportMetrics,
+        /// Ticket of the migration that brought the VM here
+        migration: Option<String>,
+        /// Key for migrating the VM away, from the latest registration
+        migration_key: Option<String>,
     }
 
     pub struct WebTransportBackend {
@@ -485,6 +662,9 @@ mod wasm {
                 connection_state: ConnectionState::Disconnected,
                 connection_generation: 0,
                 heartbeat_interval_id: None,
+                metrics: TransportMetrics::default(),
+                migration: None,
+                migration_key: None,
             }));
 
             Self {
@@ -497,6 +677,16 @@ mod wasm {
             }
         }
 
+        /// Create a backend for a VM that arrived with the migration
+        /// `ticket`, keeping its old MAC `mac` so the relay gives it back
+        /// its address.
+        pub fn resume(url: &str, cert_hash: Option<String>, mac: [u8; 6], ticket: String) -> Self {
+            let mut backend = Self::new(url, cert_hash);
+            backend.mac = mac;
+            backend.state.borrow_mut().migration = Some(ticket);
+            backend
+        }
+
         /// Check if registered with the relay
         pub fn is_registered(&self) -> bool {
             self.state.borrow().registered
@@ -507,6 +697,11 @@ mod wasm {
             self.state.borrow().connection_state == ConnectionState::Connected
         }
 
+        /// Snapshot of the transport counters.
+        pub fn transport_metrics(&self) -> TransportMetrics {
+            self.state.borrow().metrics.clone()
+        }
+
         /// Start the connection process
         fn start_connection(&self) {
             let url = self.url.clone();
@@ -617,7 +812,14 @@ mod wasm {
                         console_log("[WebTransport] Connected successfully!");
 
                         // Send registration
-                        let register_msg = make_register_message(&mac);
+                        // Batches from the relay are decoded, outgoing frames are not batched
+                        let migration = state.borrow().migration.clone();
+                        let register_msg = make_register_message(
+                            &mac,
+                            true,
+                            network_from_url(&url),
+                            migration.as_deref(),
+                        );
                         let array = Uint8Array::from(&register_msg[..]);
                         if let Err(e) = JsFuture::from(writer.write_with_chunk(&array)).await {
                             console_error(&format!("[WebTransport] Failed to register: {:?}", e));
@@ -702,33 +904,43 @@ mod wasm {
                                             .unwrap();
                                     let array = Uint8Array::new(&value);
                                     let data = array.to_vec();
-
-                                    // Handle control messages
-                                    if !data.is_empty() && data[0] == MSG_TYPE_CONTROL {
-                                        if let Ok(json_str) = std::str::from_utf8(&data[1..]) {
-                                            if json_str.contains("\"type\":\"Assigned\"") {
-                                                let mut s = state.borrow_mut();
-                                                s.registered = true;
-                                                if let Some(ip) = parse_ip_from_json(json_str) {
-                                                    s.assigned_ip = Some(ip);
-                                                    drop(s);
-                                                    console_log(&format!(
-                                                        "[WebTransport] IP Assigned: {}.{}.{}.{}",
-                                                        ip[0], ip[1], ip[2], ip[3]
+                                    let wire_len = data.len();
+                                    let messages = split_datagram(data);
+                                    state
+                                        .borrow_mut()
+                                        .metrics
+                                        .record_received(wire_len, messages.len());
+
+                                    for data in messages {
+                                        // Handle control messages
+                                        if !data.is_empty() && data[0] == MSG_TYPE_CONTROL {
+                                            if let Ok(json_str) = std::str::from_utf8(&data[1..]) {
+                                                if json_str.contains("\"type\":\"Assigned\"") {
+                                                    let mut s = state.borrow_mut();
+                                                    s.registered = true;
+                                                    s.migration_key =
+                                                        parse_migration_key_from_json(json_str);
+                                                    if let Some(ip) = parse_ip_from_json(json_str) {
+                                                        s.assigned_ip = Some(ip);
+                                                        drop(s);
+                                                        console_log(&format!(
+                                                            "[WebTransport] IP Assigned: {}.{}.{}.{}",
+                                                            ip[0], ip[1], ip[2], ip[3]
+                                                        ));
+                                                    }
+                                                } else if json_str.contains("\"type\":\"Error\"") {
+                                                    console_error(&format!(
+                                                        "[WebTransport] Relay error: {}",
+                                                        json_str
                                                     ));
                                                 }
-                                            } else if json_str.contains("\"type\":\"Error\"") {
-                                                console_error(&format!(
-                                                    "[WebTransport] Relay error: {}",
-                                                    json_str
-                                                ));
                                             }
                                         }
-                                    }
 
-                                    // Queue Ethernet frames
-                                    if let Some(frame) = decode_message(&data) {
-                                        state.borrow_mut().rx_queue.push_back(frame);
+                                        // Queue Ethernet frames
+                                        if let Some(frame) = decode_message(&data) {
+                                            state.borrow_mut().rx_queue.push_back(frame);
+                                        }
                                     }
                                 }
                                 Err(e) => {
@@ -917,6 +1129,7 @@ mod wasm {
             if let Some(writer) = self.writer.borrow().as_ref() {
                 // Frame the Ethernet data with the protocol prefix
                 let framed = encode_data_frame(buf);
+                self.state.borrow_mut().metrics.record_unbatched(framed.len());
                 let array = Uint8Array::from(&framed[..]);
                 let _ = writer.write_with_chunk(&array);
                 Ok(())
@@ -932,6 +1145,10 @@ mod wasm {
         fn get_assigned_ip(&self) -> Option<[u8; 4]> {
             self.state.borrow().assigned_ip
         }
+
+        fn migration_key(&self) -> Option<String> {
+            self.state.borrow().migration_key.clone()
+        }
     }
 }
 
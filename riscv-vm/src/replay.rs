@@ -0,0 +1,559 @@
+//! Deterministic record and replay.
+//!
+//! Guest time and randomness already follow from execution alone: the CLINT
+//! advances `mtime` from modelled instruction cycles, recorded and replayed
+//! runs put the RTC and idle skipping in deterministic mode (see
+//! `NativeVm::set_deterministic_time`) and virtio-rng returns a fixed
+//! stream. A single-hart run can therefore only diverge through what
+//! the host feeds it — console bytes, network frames, the address the relay
+//! assigns and interrupt lines raised by the embedder. An [`InputLog`]
+//! records each of these against hart 0's cycle count at the point the run
+//! loop delivered it; in replay it hands them back at the same points
+//! instead of reading the host, so the guest sees exactly the same inputs.
+//!
+//! Inputs reach the guest only at the run loop's poll points, which depend
+//! on how many steps hart 0 took, so a replay must use the same execution
+//! engine as the recording (the JIT, superblocks, or the interpreter, which
+//! tracing implies). The log header pins this and the rest of the machine
+//! configuration and replay refuses to start if they differ.
+
+use crate::net::NetworkBackend;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Version identifier for recording compatibility checks.
+pub const RECORDING_VERSION: &str = "1.0";
+
+/// A non-deterministic input delivered to the guest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GuestInput {
+    /// Byte pushed into the UART receive FIFO.
+    Uart(u8),
+    /// Frame returned by the network backend.
+    Net(Vec<u8>),
+    /// Address assigned by the relay, as read from the virtio-net config.
+    NetAddress(Option<[u8; 4]>),
+    /// External interrupt line raised or lowered by the embedder.
+    Irq { irq: u32, raised: bool },
+}
+
+/// Independent input streams; replay consumes each one in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Uart,
+    Net,
+    NetAddress,
+    Irq,
+}
+
+impl Channel {
+    const COUNT: usize = 4;
+}
+
+impl GuestInput {
+    pub fn channel(&self) -> Channel {
+        match self {
+            GuestInput::Uart(_) => Channel::Uart,
+            GuestInput::Net(_) => Channel::Net,
+            GuestInput::NetAddress(_) => Channel::NetAddress,
+            GuestInput::Irq { .. } => Channel::Irq,
+        }
+    }
+}
+
+/// How hart 0 executed the recorded run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Engine {
+    Interpreter,
+    Blocks,
+    Jit,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Interpreter => "interpreter",
+            Engine::Blocks => "superblock",
+            Engine::Jit => "JIT",
+        })
+    }
+}
+
+/// Configuration a replay has to match for the guest to behave the same.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Machine {
+    /// SHA-256 of the kernel image.
+    pub kernel_sha256: [u8; 32],
+    pub dram_size: u64,
+    pub cpu_frequency: u64,
+    pub engine: Engine,
+    /// Whether a network device was attached.
+    pub network: bool,
+}
+
+impl Machine {
+    /// Describe the first difference from `recorded`, if any.
+    pub fn mismatch(&self, recorded: &Machine) -> Option<String> {
+        if self.kernel_sha256 != recorded.kernel_sha256 {
+            return Some("the kernel image differs".to_string());
+        }
+        if self.dram_size != recorded.dram_size {
+            return Some(format!(
+                "recorded with {} MiB of DRAM, not {} MiB",
+                recorded.dram_size >> 20,
+                self.dram_size >> 20
+            ));
+        }
+        if self.cpu_frequency != recorded.cpu_frequency {
+            return Some(format!(
+                "recorded at {} MHz, not {} MHz",
+                recorded.cpu_frequency / 1_000_000,
+                self.cpu_frequency / 1_000_000
+            ));
+        }
+        if self.engine != recorded.engine {
+            return Some(format!(
+                "recorded with the {} engine, not the {} engine",
+                recorded.engine, self.engine
+            ));
+        }
+        if self.network != recorded.network {
+            return Some(if recorded.network {
+                "recorded with networking enabled".to_string()
+            } else {
+                "recorded without networking".to_string()
+            });
+        }
+        None
+    }
+}
+
+/// A finished recording: the machine it ran on and every input it received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: String,
+    pub machine: Machine,
+    /// Inputs in delivery order, each with hart 0's cycle count at delivery.
+    pub events: Vec<(u64, GuestInput)>,
+    /// Hart 0's cycle count when the recorded run stopped.
+    pub end_cycle: u64,
+    /// MAC address the network backend reported.
+    pub net_mac: Option<[u8; 6]>,
+}
+
+impl Recording {
+    /// Encode with bincode, the on-disk recording format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| format!("failed to encode recording: {}", e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let version: String =
+            bincode::deserialize(bytes).map_err(|e| format!("invalid recording header: {}", e))?;
+        if version != RECORDING_VERSION {
+            return Err(format!("unsupported recording version {}", version));
+        }
+        bincode::deserialize(bytes).map_err(|e| format!("invalid recording: {}", e))
+    }
+}
+
+enum LogState {
+    Recording {
+        events: Vec<(u64, GuestInput)>,
+        /// Inputs raised from other threads, delivered at the next poll
+        deferred: Vec<GuestInput>,
+        net_mac: Option<[u8; 6]>,
+    },
+    Replaying {
+        recording: Recording,
+        /// Per-channel queues of the events not delivered yet
+        pending: [VecDeque<(u64, GuestInput)>; Channel::COUNT],
+    },
+}
+
+/// Inputs of a run being recorded or replayed, shared by the run loop and
+/// the devices that take host input.
+pub struct InputLog {
+    /// Hart 0's cycle count at the current poll point.
+    clock: AtomicU64,
+    state: Mutex<LogState>,
+}
+
+impl InputLog {
+    /// Start an empty recording.
+    pub fn recording() -> Self {
+        Self {
+            clock: AtomicU64::new(0),
+            state: Mutex::new(LogState::Recording {
+                events: Vec::new(),
+                deferred: Vec::new(),
+                net_mac: None,
+            }),
+        }
+    }
+
+    /// Replay the inputs of `recording`.
+    pub fn replaying(recording: Recording) -> Self {
+        let mut pending: [VecDeque<(u64, GuestInput)>; Channel::COUNT] = Default::default();
+        for (cycle, input) in &recording.events {
+            pending[input.channel() as usize].push_back((*cycle, input.clone()));
+        }
+        Self {
+            clock: AtomicU64::new(0),
+            state: Mutex::new(LogState::Replaying { recording, pending }),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), LogState::Replaying { .. })
+    }
+
+    /// Cycle count of the current poll point.
+    pub fn clock(&self) -> u64 {
+        self.clock.load(Ordering::Acquire)
+    }
+
+    /// Move to the poll point at `cycle`; recorded inputs are stamped with
+    /// it and replayed inputs up to it become due.
+    pub fn set_clock(&self, cycle: u64) {
+        self.clock.store(cycle, Ordering::Release);
+    }
+
+    /// Log `input` as delivered now. Ignored while replaying.
+    pub fn record(&self, input: GuestInput) {
+        if let LogState::Recording { events, .. } = &mut *self.state.lock().unwrap() {
+            events.push((self.clock(), input));
+        }
+    }
+
+    /// Queue `input` for delivery (and logging) at the next poll point.
+    ///
+    /// Fails while replaying, where the log is the only source of input.
+    pub fn defer(&self, input: GuestInput) -> Result<(), String> {
+        match &mut *self.state.lock().unwrap() {
+            LogState::Recording { deferred, .. } => {
+                deferred.push(input);
+                Ok(())
+            }
+            LogState::Replaying { .. } => Err("inputs come from the replay log".to_string()),
+        }
+    }
+
+    /// Inputs queued with [`defer`](Self::defer) since the last call.
+    pub fn take_deferred(&self) -> Vec<GuestInput> {
+        match &mut *self.state.lock().unwrap() {
+            LogState::Recording { deferred, .. } => std::mem::take(deferred),
+            LogState::Replaying { .. } => Vec::new(),
+        }
+    }
+
+    /// Next replayed input on `channel` that is due at the current poll
+    /// point. Always `None` while recording.
+    pub fn next_due(&self, channel: Channel) -> Option<GuestInput> {
+        let clock = self.clock();
+        match &mut *self.state.lock().unwrap() {
+            LogState::Replaying { pending, .. } => {
+                let queue = &mut pending[channel as usize];
+                if queue.front()?.0 > clock {
+                    return None;
+                }
+                queue.pop_front().map(|(_, input)| input)
+            }
+            LogState::Recording { .. } => None,
+        }
+    }
+
+    /// Replayed inputs not delivered yet.
+    pub fn remaining(&self) -> usize {
+        match &*self.state.lock().unwrap() {
+            LogState::Replaying { pending, .. } => pending.iter().map(VecDeque::len).sum(),
+            LogState::Recording { .. } => 0,
+        }
+    }
+
+    /// Whether a replay has delivered every input and reached the cycle at
+    /// which the recorded run stopped.
+    pub fn replay_finished(&self) -> bool {
+        match &*self.state.lock().unwrap() {
+            LogState::Replaying { recording, pending } => {
+                self.clock() >= recording.end_cycle && pending.iter().all(VecDeque::is_empty)
+            }
+            LogState::Recording { .. } => false,
+        }
+    }
+
+    /// The recording being replayed, if any.
+    pub fn replayed_machine(&self) -> Option<Machine> {
+        match &*self.state.lock().unwrap() {
+            LogState::Replaying { recording, .. } => Some(recording.machine.clone()),
+            LogState::Recording { .. } => None,
+        }
+    }
+
+    /// MAC address the recorded network device had, while replaying.
+    fn replayed_mac(&self) -> Option<[u8; 6]> {
+        match &*self.state.lock().unwrap() {
+            LogState::Replaying { recording, .. } => recording.net_mac,
+            LogState::Recording { .. } => None,
+        }
+    }
+
+    fn set_net_mac(&self, mac: [u8; 6]) {
+        if let LogState::Recording { net_mac, .. } = &mut *self.state.lock().unwrap() {
+            *net_mac = Some(mac);
+        }
+    }
+
+    /// Close a recording made on `machine` at the current clock. `None`
+    /// while replaying.
+    pub fn finish(&self, machine: Machine) -> Option<Recording> {
+        match &*self.state.lock().unwrap() {
+            LogState::Recording {
+                events, net_mac, ..
+            } => Some(Recording {
+                version: RECORDING_VERSION.to_string(),
+                machine,
+                events: events.clone(),
+                end_cycle: self.clock(),
+                net_mac: *net_mac,
+            }),
+            LogState::Replaying { .. } => None,
+        }
+    }
+}
+
+/// Network backend that logs every frame and address the guest receives.
+///
+/// `recv` reports nothing more for the rest of a poll point once it has
+/// returned `None`, so replay can hand frames out on the same calls.
+pub struct RecordingBackend {
+    inner: Box<dyn NetworkBackend>,
+    log: Arc<InputLog>,
+    /// Poll point at which `inner` last ran dry
+    idle_at: Option<u64>,
+    last_address: Mutex<Option<[u8; 4]>>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Box<dyn NetworkBackend>, log: Arc<InputLog>) -> Self {
+        Self {
+            inner,
+            log,
+            idle_at: None,
+            last_address: Mutex::new(None),
+        }
+    }
+}
+
+impl NetworkBackend for RecordingBackend {
+    fn init(&mut self) -> Result<(), String> {
+        self.inner.init()
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let clock = self.log.clock();
+        if self.idle_at == Some(clock) {
+            return Ok(None);
+        }
+        let frame = self.inner.recv()?;
+        match &frame {
+            Some(frame) => self.log.record(GuestInput::Net(frame.clone())),
+            None => self.idle_at = Some(clock),
+        }
+        Ok(frame)
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<(), String> {
+        self.inner.send(buf)
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        let mac = self.inner.mac_address();
+        self.log.set_net_mac(mac);
+        mac
+    }
+
+    fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+        let address = self.inner.get_assigned_ip();
+        let mut last = self.last_address.lock().unwrap();
+        if *last != address {
+            *last = address;
+            self.log.record(GuestInput::NetAddress(address));
+        }
+        address
+    }
+
+    fn migration_key(&self) -> Option<String> {
+        self.inner.migration_key()
+    }
+}
+
+/// Network backend that plays back a recording's frames and discards
+/// everything the guest sends.
+pub struct ReplayBackend {
+    log: Arc<InputLog>,
+    address: Mutex<Option<[u8; 4]>>,
+}
+
+impl ReplayBackend {
+    pub fn new(log: Arc<InputLog>) -> Self {
+        Self {
+            log,
+            address: Mutex::new(None),
+        }
+    }
+}
+
+impl NetworkBackend for ReplayBackend {
+    fn init(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+        Ok(match self.log.next_due(Channel::Net) {
+            Some(GuestInput::Net(frame)) => Some(frame),
+            _ => None,
+        })
+    }
+
+    fn send(&self, _buf: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.log
+            .replayed_mac()
+            .unwrap_or([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])
+    }
+
+    fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+        let mut address = self.address.lock().unwrap();
+        while let Some(GuestInput::NetAddress(next)) = self.log.next_due(Channel::NetAddress) {
+            *address = next;
+        }
+        *address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        Machine {
+            kernel_sha256: [7; 32],
+            dram_size: 512 << 20,
+            cpu_frequency: 10_000_000,
+            engine: Engine::Blocks,
+            network: false,
+        }
+    }
+
+    /// Backend handing out a fixed list of frames, then nothing.
+    struct Frames(VecDeque<Vec<u8>>);
+
+    impl NetworkBackend for Frames {
+        fn init(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn recv(&mut self) -> Result<Option<Vec<u8>>, String> {
+            Ok(self.0.pop_front())
+        }
+
+        fn send(&self, _buf: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn get_assigned_ip(&self) -> Option<[u8; 4]> {
+            Some([10, 0, 2, 15])
+        }
+    }
+
+    #[test]
+    fn test_replay_delivers_at_recorded_cycles() {
+        let log = InputLog::recording();
+        log.set_clock(100);
+        log.record(GuestInput::Uart(b'a'));
+        log.defer(GuestInput::Irq {
+            irq: 12,
+            raised: true,
+        })
+        .unwrap();
+        assert_eq!(log.take_deferred().len(), 1);
+        log.set_clock(300);
+        log.record(GuestInput::Uart(b'b'));
+        log.set_clock(400);
+        let recording = log.finish(machine()).unwrap();
+        assert_eq!(recording.end_cycle, 400);
+
+        let bytes = recording.to_bytes().unwrap();
+        let log = InputLog::replaying(Recording::from_bytes(&bytes).unwrap());
+        assert!(log.is_replaying());
+        assert!(log.defer(GuestInput::Uart(b'x')).is_err());
+
+        log.set_clock(50);
+        assert_eq!(log.next_due(Channel::Uart), None);
+        log.set_clock(200);
+        assert_eq!(log.next_due(Channel::Uart), Some(GuestInput::Uart(b'a')));
+        assert_eq!(log.next_due(Channel::Uart), None);
+        log.set_clock(300);
+        assert_eq!(log.next_due(Channel::Uart), Some(GuestInput::Uart(b'b')));
+        assert!(!log.replay_finished());
+        log.set_clock(400);
+        assert!(log.replay_finished());
+        assert_eq!(log.remaining(), 0);
+    }
+
+    #[test]
+    fn test_network_round_trip() {
+        let log = Arc::new(InputLog::recording());
+        let frames = Frames(vec![vec![1], vec![2]].into());
+        let mut backend = RecordingBackend::new(Box::new(frames), Arc::clone(&log));
+        let mac = backend.mac_address();
+        log.set_clock(10);
+        assert_eq!(backend.recv().unwrap(), Some(vec![1]));
+        assert_eq!(backend.get_assigned_ip(), Some([10, 0, 2, 15]));
+        log.set_clock(20);
+        assert_eq!(backend.recv().unwrap(), Some(vec![2]));
+        assert_eq!(backend.recv().unwrap(), None);
+        let recording = log.finish(machine()).unwrap();
+        assert_eq!(recording.net_mac, Some(mac));
+        // One address change, however often it is read
+        assert_eq!(recording.events.len(), 3);
+
+        let log = Arc::new(InputLog::replaying(recording));
+        let mut backend = ReplayBackend::new(Arc::clone(&log));
+        assert_eq!(backend.mac_address(), mac);
+        assert_eq!(backend.get_assigned_ip(), None);
+        log.set_clock(10);
+        assert_eq!(backend.recv().unwrap(), Some(vec![1]));
+        assert_eq!(backend.recv().unwrap(), None);
+        assert_eq!(backend.get_assigned_ip(), Some([10, 0, 2, 15]));
+        log.set_clock(20);
+        assert_eq!(backend.recv().unwrap(), Some(vec![2]));
+        assert_eq!(log.remaining(), 0);
+    }
+
+    #[test]
+    fn test_machine_mismatch() {
+        let recorded = machine();
+        assert_eq!(machine().mismatch(&recorded), None);
+        let jit = Machine {
+            engine: Engine::Jit,
+            ..machine()
+        };
+        assert_eq!(
+            jit.mismatch(&recorded).unwrap(),
+            "recorded with the superblock engine, not the JIT engine"
+        );
+        let kernel = Machine {
+            kernel_sha256: [0; 32],
+            ..machine()
+        };
+        assert!(kernel.mismatch(&recorded).is_some());
+    }
+}
@@ -0,0 +1,195 @@
+//! Host directory exported through `std::fs`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::{DirEntry, FileAttr, FileKind, ShareBackend};
+
+/// A host directory shared with the guest.
+///
+/// Symlinks inside the directory are followed only while they stay inside
+/// it, so the guest cannot reach the rest of the host filesystem.
+pub struct HostDir {
+    root: PathBuf,
+    read_only: bool,
+}
+
+impl HostDir {
+    /// Share `root`, which must be an existing directory.
+    pub fn new(root: &Path, read_only: bool) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+        Ok(Self { root, read_only })
+    }
+
+    /// Host path of the shared directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn escapes() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "path leaves the shared directory",
+        )
+    }
+
+    /// Host path of `path` without following a symlink in its last component.
+    fn entry(&self, path: &str) -> io::Result<PathBuf> {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() {
+            return Ok(self.root.clone());
+        }
+        let dir = self.root.join(parent).canonicalize()?;
+        if !dir.starts_with(&self.root) {
+            return Err(Self::escapes());
+        }
+        Ok(dir.join(name))
+    }
+
+    /// Host path of `path` with every symlink resolved.
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let entry = self.entry(path)?;
+        if !fs::symlink_metadata(&entry)?.file_type().is_symlink() {
+            return Ok(entry);
+        }
+        let target = entry.canonicalize()?;
+        if !target.starts_with(&self.root) {
+            return Err(Self::escapes());
+        }
+        Ok(target)
+    }
+
+    fn open(&self, path: &str, write: bool) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(self.resolve(path)?)
+    }
+}
+
+fn kind_of(meta: &fs::Metadata) -> FileKind {
+    if meta.is_dir() {
+        FileKind::Dir
+    } else {
+        FileKind::File
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+impl ShareBackend for HostDir {
+    fn stat(&mut self, path: &str) -> io::Result<FileAttr> {
+        let meta = fs::metadata(self.resolve(path)?)?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        Ok(FileAttr {
+            kind: kind_of(&meta),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            mode: mode_of(&meta),
+            mtime,
+        })
+    }
+
+    fn list(&mut self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.resolve(path)?)? {
+            let entry = entry?;
+            // Names that aren't UTF-8 can't be expressed in 9p strings
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Report what a symlink points at, like `stat` does
+            let kind = match fs::metadata(entry.path()) {
+                Ok(meta) => kind_of(&meta),
+                Err(_) => continue,
+            };
+            entries.push(DirEntry { name, kind });
+        }
+        Ok(entries)
+    }
+
+    fn read(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.open(path, false)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut total = 0;
+        while total < buf.len() {
+            match file.read(&mut buf[total..])? {
+                0 => break,
+                n => total += n,
+            }
+        }
+        Ok(total)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> io::Result<usize> {
+        let mut file = self.open(path, true)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(data.len())
+    }
+
+    fn create(&mut self, path: &str) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.entry(path)?)
+            .map(drop)
+    }
+
+    fn mkdir(&mut self, path: &str) -> io::Result<()> {
+        fs::create_dir(self.entry(path)?)
+    }
+
+    fn remove(&mut self, path: &str) -> io::Result<()> {
+        let entry = self.entry(path)?;
+        if fs::symlink_metadata(&entry)?.is_dir() {
+            fs::remove_dir(entry)
+        } else {
+            fs::remove_file(entry)
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(self.entry(from)?, self.entry(to)?)
+    }
+
+    fn set_len(&mut self, path: &str, len: u64) -> io::Result<()> {
+        self.open(path, true)?.set_len(len)
+    }
+
+    fn sync(&mut self, path: &str) -> io::Result<()> {
+        let file = self.open(path, false)?;
+        if file.metadata()?.is_dir() {
+            return Ok(());
+        }
+        file.sync_all()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
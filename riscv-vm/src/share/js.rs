@@ -0,0 +1,203 @@
+//! Virtual directory provided by the page through JS callbacks.
+//!
+//! The handler object passed to `WasmVm::attach_share` implements the
+//! calls below synchronously (the device runs inside a VM step). Paths are
+//! relative to the share root, e.g. `"docs/a.txt"`, and `""` is the root.
+//!
+//! | Method                          | Returns                                   |
+//! |---------------------------------|-------------------------------------------|
+//! | `stat(path)`                    | `{ kind, size, mode?, mtime? }`           |
+//! | `list(path)`                    | `[{ name, kind }]`, kind `"file"`/`"dir"` |
+//! | `read(path, offset, length)`    | `Uint8Array` (shorter at end of file)     |
+//! | `write(path, offset, bytes)`    | bytes written                             |
+//! | `create(path)` / `mkdir(path)`  | nothing                                   |
+//! | `remove(path)`                  | nothing                                   |
+//! | `rename(from, to)`              | nothing                                   |
+//! | `truncate(path, length)`        | nothing                                   |
+//! | `sync(path)` (optional)         | nothing                                   |
+//!
+//! A `readOnly: true` property makes the share read-only. Failures are
+//! reported by throwing; an error whose `code` (or DOMException `name`) is
+//! `ENOENT`/`NotFoundError`, `EEXIST`, `ENOTEMPTY`/`InvalidModificationError`,
+//! `ENOTDIR`, `EISDIR` or `EACCES`/`NotAllowedError` reaches the guest as that
+//! errno, anything else as EIO.
+//!
+//! OPFS files can be served from a worker with sync access handles; pages
+//! that persist to IndexedDB keep an in-memory mirror and flush it
+//! asynchronously.
+
+use js_sys::{Array, Function, Object, Reflect, Uint8Array};
+use std::io;
+use wasm_bindgen::{JsCast, JsValue};
+
+use super::{DirEntry, FileAttr, FileKind, ShareBackend};
+
+/// A share served by a JS handler object.
+pub struct JsShare {
+    handler: Object,
+    read_only: bool,
+}
+
+// WASM is single threaded
+unsafe impl Send for JsShare {}
+
+impl JsShare {
+    pub fn new(handler: Object) -> Self {
+        let read_only = Reflect::get(&handler, &JsValue::from_str("readOnly"))
+            .ok()
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        Self { handler, read_only }
+    }
+
+    fn call(&self, method: &str, args: &[JsValue]) -> io::Result<JsValue> {
+        let func = Reflect::get(&self.handler, &JsValue::from_str(method))
+            .ok()
+            .and_then(|f| f.dyn_into::<Function>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("share handler has no {}()", method),
+                )
+            })?;
+        let argv: Array = args.iter().collect();
+        func.apply(&self.handler, &argv).map_err(js_error)
+    }
+}
+
+/// Map a thrown JS value to an I/O error.
+fn js_error(err: JsValue) -> io::Error {
+    let field = |name: &str| {
+        Reflect::get(&err, &JsValue::from_str(name))
+            .ok()
+            .and_then(|v| v.as_string())
+    };
+    let code = field("code").or_else(|| field("name")).unwrap_or_default();
+    let kind = match code.as_str() {
+        "ENOENT" | "NotFoundError" => io::ErrorKind::NotFound,
+        "EEXIST" => io::ErrorKind::AlreadyExists,
+        "ENOTEMPTY" | "InvalidModificationError" => io::ErrorKind::DirectoryNotEmpty,
+        "ENOTDIR" | "TypeMismatchError" => io::ErrorKind::NotADirectory,
+        "EISDIR" => io::ErrorKind::IsADirectory,
+        "EACCES" | "NotAllowedError" => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    let message = field("message").unwrap_or(code);
+    io::Error::new(kind, message)
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("share handler returned an invalid {}", what),
+    )
+}
+
+fn parse_kind(value: &JsValue) -> io::Result<FileKind> {
+    match Reflect::get(value, &JsValue::from_str("kind"))
+        .ok()
+        .and_then(|v| v.as_string())
+        .as_deref()
+    {
+        Some("file") => Ok(FileKind::File),
+        Some("dir") | Some("directory") => Ok(FileKind::Dir),
+        _ => Err(invalid("kind")),
+    }
+}
+
+fn number(value: &JsValue, field: &str) -> Option<f64> {
+    Reflect::get(value, &JsValue::from_str(field))
+        .ok()
+        .and_then(|v| v.as_f64())
+}
+
+impl ShareBackend for JsShare {
+    fn stat(&mut self, path: &str) -> io::Result<FileAttr> {
+        let attr = self.call("stat", &[path.into()])?;
+        let kind = parse_kind(&attr)?;
+        let default_mode = match kind {
+            FileKind::Dir => 0o755,
+            FileKind::File => 0o644,
+        };
+        Ok(FileAttr {
+            kind,
+            size: number(&attr, "size").unwrap_or(0.0) as u64,
+            mode: number(&attr, "mode").map_or(default_mode, |m| m as u32 & 0o777),
+            mtime: number(&attr, "mtime").unwrap_or(0.0) as u64,
+        })
+    }
+
+    fn list(&mut self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let entries = self.call("list", &[path.into()])?;
+        let entries: Array = entries.dyn_into().map_err(|_| invalid("listing"))?;
+        entries
+            .iter()
+            .map(|entry| {
+                let name = Reflect::get(&entry, &JsValue::from_str("name"))
+                    .ok()
+                    .and_then(|v| v.as_string())
+                    .ok_or_else(|| invalid("entry name"))?;
+                Ok(DirEntry {
+                    name,
+                    kind: parse_kind(&entry)?,
+                })
+            })
+            .collect()
+    }
+
+    fn read(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.call(
+            "read",
+            &[
+                path.into(),
+                (offset as f64).into(),
+                (buf.len() as f64).into(),
+            ],
+        )?;
+        let data: Uint8Array = data.dyn_into().map_err(|_| invalid("read result"))?;
+        let len = (data.length() as usize).min(buf.len());
+        data.subarray(0, len as u32).copy_to(&mut buf[..len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> io::Result<usize> {
+        let bytes = Uint8Array::from(data);
+        let written = self.call(
+            "write",
+            &[path.into(), (offset as f64).into(), bytes.into()],
+        )?;
+        Ok(written.as_f64().map_or(data.len(), |n| n as usize))
+    }
+
+    fn create(&mut self, path: &str) -> io::Result<()> {
+        self.call("create", &[path.into()]).map(drop)
+    }
+
+    fn mkdir(&mut self, path: &str) -> io::Result<()> {
+        self.call("mkdir", &[path.into()]).map(drop)
+    }
+
+    fn remove(&mut self, path: &str) -> io::Result<()> {
+        self.call("remove", &[path.into()]).map(drop)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        self.call("rename", &[from.into(), to.into()]).map(drop)
+    }
+
+    fn set_len(&mut self, path: &str, len: u64) -> io::Result<()> {
+        self.call("truncate", &[path.into(), (len as f64).into()])
+            .map(drop)
+    }
+
+    fn sync(&mut self, path: &str) -> io::Result<()> {
+        match self.call("sync", &[path.into()]) {
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+            result => result.map(drop),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
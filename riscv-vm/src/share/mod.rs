@@ -0,0 +1,89 @@
+//! Shared-directory backends for the VirtIO 9p device.
+//!
+//! [`ShareBackend`] is the filesystem a `Virtio9p` device exports to the
+//! guest. Natively that is a host directory ([`HostDir`], over `std::fs`);
+//! in the browser it is a virtual directory the page provides through JS
+//! callbacks ([`JsShare`]), typically kept in OPFS or IndexedDB.
+//!
+//! Paths handed to a backend are relative to the share root, use `/` as the
+//! separator and are empty for the root itself. The device only builds them
+//! from validated names, so they never contain `.`, `..` or empty
+//! components.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod host;
+#[cfg(target_arch = "wasm32")]
+pub mod js;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use host::HostDir;
+#[cfg(target_arch = "wasm32")]
+pub use js::JsShare;
+
+use std::io;
+
+/// Kind of a shared file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+}
+
+/// Attributes of a shared file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttr {
+    pub kind: FileKind,
+    /// Size in bytes (0 for directories).
+    pub size: u64,
+    /// Permission bits (`0o777` mask).
+    pub mode: u32,
+    /// Last modification, in seconds since the Unix epoch.
+    pub mtime: u64,
+}
+
+/// An entry of a shared directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileKind,
+}
+
+/// Filesystem exported by a VirtIO 9p device.
+pub trait ShareBackend: Send {
+    /// Attributes of `path`.
+    fn stat(&mut self, path: &str) -> io::Result<FileAttr>;
+
+    /// Entries of the directory `path`, without `.` and `..`.
+    fn list(&mut self, path: &str) -> io::Result<Vec<DirEntry>>;
+
+    /// Read into `buf` from `offset`, returning the bytes read (0 at EOF).
+    fn read(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write `data` at `offset`, returning the bytes written.
+    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> io::Result<usize>;
+
+    /// Create an empty file; fails if `path` exists.
+    fn create(&mut self, path: &str) -> io::Result<()>;
+
+    /// Create a directory; fails if `path` exists.
+    fn mkdir(&mut self, path: &str) -> io::Result<()>;
+
+    /// Remove a file or an empty directory.
+    fn remove(&mut self, path: &str) -> io::Result<()>;
+
+    /// Move `from` to `to`, replacing a file at `to`.
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()>;
+
+    /// Truncate or extend a file to `len` bytes.
+    fn set_len(&mut self, path: &str, len: u64) -> io::Result<()>;
+
+    /// Make completed writes to `path` durable.
+    fn sync(&mut self, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the guest may only read the share.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
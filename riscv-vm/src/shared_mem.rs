@@ -26,6 +26,36 @@
 //!
 //! The CLINT layout mirrors the native CLINT for software compatibility.
 //! Workers use JavaScript Atomics to access the shared state.
+//!
+//! ## Inter-VM Shared Memory Window
+//!
+//! A separate, much smaller layout backs the inter-VM shared memory device
+//! (see [`crate::devices::ivshmem`]). Every peer attached to a window sees
+//! the same bytes; the header carries the doorbells:
+//!
+//! ```text
+//! ┌─────────────────────────────────────────────────────────────┐
+//! │ Header (64B)                 @ 0x00                         │
+//! │   - doorbell[SHM_MAX_PEERS]  @ 0x00 (4B each, sender bits)  │
+//! │   - attached peers mask      @ 0x20 (4B)                    │
+//! ├─────────────────────────────────────────────────────────────┤
+//! │ Window data                  @ 0x40                         │
+//! └─────────────────────────────────────────────────────────────┘
+//! ```
+//!
+//! Natively the window lives in process memory ([`SharedWindow::new`]); in
+//! the browser it can also wrap a SharedArrayBuffer handed to several VMs
+//! on the page ([`SharedWindow::from_buffer`]).
+//!
+//! ## VM Pool
+//!
+//! A pool buffer ([`PoolBuffer`]) serves the VMs of a browser worker pool:
+//! a 64-byte header (magic, slot count), then one slot per VM holding its
+//! lifecycle words and three rings: console output, console input, and the
+//! receive queue of its NIC.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /// Size of the control region in bytes (4KB).
 pub const CONTROL_REGION_SIZE: usize = 4096;
@@ -147,6 +177,735 @@ pub const fn hart_count_offset() -> usize {
     CONTROL_REGION_SIZE + CLINT_HART_COUNT_OFFSET
 }
 
+// ============================================================================
+// Inter-VM Shared Memory Window
+// ============================================================================
+
+/// Peers that can attach to one shared window.
+pub const SHM_MAX_PEERS: usize = 8;
+/// Window header: doorbell of peer `n` (i32 index `n`)
+pub const SHM_DOORBELL_BASE: u32 = 0;
+/// Window header: mask of attached peers (i32 index)
+pub const SHM_PEERS_IDX: u32 = SHM_MAX_PEERS as u32;
+/// Size of the window header; data follows it.
+pub const SHM_HEADER_SIZE: usize = 64;
+
+/// Buffer size needed for a window of `window_size` data bytes.
+pub const fn shm_buffer_size(window_size: usize) -> usize {
+    SHM_HEADER_SIZE + window_size
+}
+
+/// 32-bit words shared between VMs or workers: process memory natively,
+/// or a SharedArrayBuffer accessed through JavaScript Atomics in the browser.
+///
+/// Clones refer to the same words. Byte offsets index the words in
+/// little-endian order, matching a `Uint8Array` over the same buffer.
+#[derive(Clone)]
+pub struct SharedWords {
+    backing: WordBacking,
+}
+
+#[derive(Clone)]
+enum WordBacking {
+    Local(Arc<[AtomicU32]>),
+    #[cfg(target_arch = "wasm32")]
+    Buffer {
+        words: js_sys::Int32Array,
+        bytes: js_sys::Uint8Array,
+    },
+}
+
+// SAFETY: the Buffer variant views a SharedArrayBuffer and is only accessed
+// through JavaScript Atomics or bulk copies, as for the SMP accessors below.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Send for SharedWords {}
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for SharedWords {}
+
+impl SharedWords {
+    /// `len` zeroed bytes (rounded up to a whole word) of process memory.
+    pub fn new(len: usize) -> Self {
+        let words = (0..len.div_ceil(4)).map(|_| AtomicU32::new(0)).collect();
+        Self {
+            backing: WordBacking::Local(words),
+        }
+    }
+
+    /// View of a SharedArrayBuffer whose length is a multiple of 4.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_buffer(buffer: &js_sys::SharedArrayBuffer) -> Result<Self, String> {
+        if !(buffer.byte_length() as usize).is_multiple_of(4) {
+            return Err("shared buffer length must be a multiple of 4".to_string());
+        }
+        Ok(Self {
+            backing: WordBacking::Buffer {
+                words: js_sys::Int32Array::new(buffer),
+                bytes: js_sys::Uint8Array::new(buffer),
+            },
+        })
+    }
+
+    /// Length in bytes
+    pub fn len(&self) -> usize {
+        match &self.backing {
+            WordBacking::Local(words) => words.len() * 4,
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { bytes, .. } => bytes.length() as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn load(&self, index: u32) -> u32 {
+        match &self.backing {
+            WordBacking::Local(words) => words[index as usize].load(Ordering::SeqCst),
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { words, .. } => {
+                js_sys::Atomics::load(words, index).unwrap_or(0) as u32
+            }
+        }
+    }
+
+    pub fn store(&self, index: u32, val: u32) {
+        match &self.backing {
+            WordBacking::Local(words) => words[index as usize].store(val, Ordering::SeqCst),
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { words, .. } => {
+                let _ = js_sys::Atomics::store(words, index, val as i32);
+            }
+        }
+    }
+
+    pub fn fetch_or(&self, index: u32, val: u32) -> u32 {
+        match &self.backing {
+            WordBacking::Local(words) => words[index as usize].fetch_or(val, Ordering::SeqCst),
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { words, .. } => {
+                js_sys::Atomics::or(words, index, val as i32).unwrap_or(0) as u32
+            }
+        }
+    }
+
+    pub fn fetch_and(&self, index: u32, val: u32) -> u32 {
+        match &self.backing {
+            WordBacking::Local(words) => words[index as usize].fetch_and(val, Ordering::SeqCst),
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { words, .. } => {
+                js_sys::Atomics::and(words, index, val as i32).unwrap_or(0) as u32
+            }
+        }
+    }
+
+    pub fn compare_exchange(&self, index: u32, current: u32, new: u32) -> Result<u32, u32> {
+        match &self.backing {
+            WordBacking::Local(words) => words[index as usize].compare_exchange(
+                current,
+                new,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ),
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { words, .. } => {
+                let old =
+                    js_sys::Atomics::compare_exchange(words, index, current as i32, new as i32)
+                        .unwrap_or(current as i32) as u32;
+                if old == current { Ok(old) } else { Err(old) }
+            }
+        }
+    }
+
+    /// Atomically replace the bits of `mask` in word `index` with `bits`.
+    pub fn update(&self, index: u32, mask: u32, bits: u32) {
+        let mut current = self.load(index);
+        while let Err(actual) = self.compare_exchange(index, current, (current & !mask) | bits) {
+            current = actual;
+        }
+    }
+
+    /// Copy bytes starting at byte `offset` into `buf`. The copy is not
+    /// atomic; publish it through a word store.
+    pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) {
+        match &self.backing {
+            WordBacking::Local(_) => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    let at = offset + i;
+                    *byte = (self.load((at / 4) as u32) >> (8 * (at % 4))) as u8;
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { bytes, .. } => {
+                let start = offset as u32;
+                bytes.subarray(start, start + buf.len() as u32).copy_to(buf);
+            }
+        }
+    }
+
+    /// Copy `data` to the bytes starting at byte `offset`.
+    pub fn write_bytes(&self, offset: usize, data: &[u8]) {
+        match &self.backing {
+            WordBacking::Local(_) => {
+                for (i, &byte) in data.iter().enumerate() {
+                    let at = offset + i;
+                    let shift = 8 * (at % 4);
+                    self.update((at / 4) as u32, 0xff << shift, (byte as u32) << shift);
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            WordBacking::Buffer { bytes, .. } => {
+                let start = offset as u32;
+                bytes
+                    .subarray(start, start + data.len() as u32)
+                    .copy_from(data);
+            }
+        }
+    }
+}
+
+/// Memory shared between VMs, with a doorbell per peer.
+///
+/// Clones refer to the same memory. Data is stored as 32-bit words, so
+/// naturally aligned accesses of up to 32 bits are single-copy atomic
+/// between peers; 64-bit accesses are split into two words.
+#[derive(Clone)]
+pub struct SharedWindow {
+    words: SharedWords,
+    /// Data bytes after the header
+    size: usize,
+}
+
+impl SharedWindow {
+    /// Zeroed window of `size` bytes (rounded up to a whole word).
+    pub fn new(size: usize) -> Self {
+        let size = size.div_ceil(4) * 4;
+        Self {
+            words: SharedWords::new(shm_buffer_size(size)),
+            size,
+        }
+    }
+
+    /// Window backed by a SharedArrayBuffer laid out as described in the
+    /// module docs, so VMs on one page (or in different workers) can share it.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_buffer(buffer: &js_sys::SharedArrayBuffer) -> Result<Self, String> {
+        let words = SharedWords::from_buffer(buffer)?;
+        if words.len() <= SHM_HEADER_SIZE {
+            return Err(format!(
+                "shared window buffer must be larger than {} bytes",
+                SHM_HEADER_SIZE
+            ));
+        }
+        Ok(Self {
+            size: words.len() - SHM_HEADER_SIZE,
+            words,
+        })
+    }
+
+    /// Data bytes in the window
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Mark `peer` as attached. Fails if the slot is taken or out of range.
+    pub fn attach(&self, peer: u32) -> Result<(), String> {
+        if peer as usize >= SHM_MAX_PEERS {
+            return Err(format!(
+                "peer {} is out of range (0-{})",
+                peer,
+                SHM_MAX_PEERS - 1
+            ));
+        }
+        if self.words.fetch_or(SHM_PEERS_IDX, 1 << peer) & (1 << peer) != 0 {
+            return Err(format!("peer {} is already attached", peer));
+        }
+        self.words.store(SHM_DOORBELL_BASE + peer, 0);
+        Ok(())
+    }
+
+    /// Release the slot of `peer`.
+    pub fn detach(&self, peer: u32) {
+        self.words.fetch_and(SHM_PEERS_IDX, !(1 << peer));
+    }
+
+    /// Mask of attached peers
+    pub fn peers(&self) -> u32 {
+        self.words.load(SHM_PEERS_IDX)
+    }
+
+    /// Ring the doorbell of `peer` on behalf of `from`.
+    pub fn ring(&self, peer: u32, from: u32) {
+        if (peer as usize) < SHM_MAX_PEERS {
+            self.words.fetch_or(SHM_DOORBELL_BASE + peer, 1 << from);
+        }
+    }
+
+    /// Senders that rang `peer` and haven't been acknowledged
+    pub fn pending(&self, peer: u32) -> u32 {
+        self.words.load(SHM_DOORBELL_BASE + peer)
+    }
+
+    /// Acknowledge the doorbells of `peer` in `mask`.
+    pub fn ack(&self, peer: u32, mask: u32) {
+        self.words.fetch_and(SHM_DOORBELL_BASE + peer, !mask);
+    }
+
+    /// Load `size` bytes (1, 2, 4 or 8) at data offset `offset`.
+    /// Reads past the end return 0.
+    pub fn read(&self, offset: usize, size: usize) -> u64 {
+        if offset + size > self.size {
+            return 0;
+        }
+        if size == 8 && offset.is_multiple_of(4) {
+            return self.read(offset, 4) | self.read(offset + 4, 4) << 32;
+        }
+        if offset % 4 + size > 4 {
+            return (0..size).fold(0, |val, i| val | self.read(offset + i, 1) << (8 * i));
+        }
+        let word = self.words.load(Self::data_index(offset));
+        let shift = 8 * (offset % 4);
+        (word as u64 >> shift) & (u64::MAX >> (64 - 8 * size))
+    }
+
+    /// Store the low `size` bytes (1, 2, 4 or 8) of `val` at data offset
+    /// `offset`. Writes past the end are ignored.
+    pub fn write(&self, offset: usize, size: usize, val: u64) {
+        if offset + size > self.size {
+            return;
+        }
+        if size == 8 && offset.is_multiple_of(4) {
+            self.write(offset, 4, val);
+            self.write(offset + 4, 4, val >> 32);
+            return;
+        }
+        if offset % 4 + size > 4 {
+            for i in 0..size {
+                self.write(offset + i, 1, val >> (8 * i));
+            }
+            return;
+        }
+        let index = Self::data_index(offset);
+        if size == 4 {
+            self.words.store(index, val as u32);
+            return;
+        }
+        let shift = 8 * (offset % 4);
+        let mask = ((1u32 << (8 * size)) - 1) << shift;
+        self.words
+            .update(index, mask, ((val as u32) << shift) & mask);
+    }
+
+    /// Copy window data at `offset` into `buf` (host side).
+    pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) {
+        let len = buf.len().min(self.size.saturating_sub(offset));
+        self.words
+            .read_bytes(SHM_HEADER_SIZE + offset, &mut buf[..len]);
+    }
+
+    /// Copy `data` into the window at `offset` (host side).
+    pub fn write_bytes(&self, offset: usize, data: &[u8]) {
+        let len = data.len().min(self.size.saturating_sub(offset));
+        self.words
+            .write_bytes(SHM_HEADER_SIZE + offset, &data[..len]);
+    }
+
+    fn data_index(offset: usize) -> u32 {
+        ((SHM_HEADER_SIZE + offset) / 4) as u32
+    }
+}
+
+// ============================================================================
+// VM Pool Layout
+// ============================================================================
+
+/// Most VMs one pool buffer can hold.
+pub const POOL_MAX_VMS: usize = 32;
+/// Pool header: magic (i32 index)
+pub const POOL_MAGIC_IDX: u32 = 0;
+/// Pool header: number of slots (i32 index)
+pub const POOL_SLOTS_IDX: u32 = 1;
+/// Size of the pool header; slots follow it.
+pub const POOL_HEADER_SIZE: usize = 64;
+
+/// Slot control: [`SlotState`] (i32 index within the slot)
+pub const SLOT_STATE: u32 = 0;
+/// Slot control: stop requested by the supervisor (i32 index)
+pub const SLOT_STOP: u32 = 1;
+/// Slot control: exit code low 32 bits (i32 index)
+pub const SLOT_EXIT_LO: u32 = 2;
+/// Slot control: exit code high 32 bits (i32 index)
+pub const SLOT_EXIT_HI: u32 = 3;
+/// Slot control: NIC MAC bytes 0-3 (i32 index)
+pub const SLOT_MAC_LO: u32 = 4;
+/// Slot control: NIC MAC bytes 4-5 (i32 index)
+pub const SLOT_MAC_HI: u32 = 5;
+/// Size of a slot's control words
+pub const SLOT_CONTROL_SIZE: usize = 64;
+
+/// Ring header: producer counter, consumer counter, producer lock
+pub const RING_HEADER_SIZE: usize = 16;
+/// Console output ring capacity per VM (power of two)
+pub const POOL_CONSOLE_OUT_SIZE: usize = 16 * 1024;
+/// Console input ring capacity per VM (power of two)
+pub const POOL_CONSOLE_IN_SIZE: usize = 4 * 1024;
+/// Network receive ring capacity per VM (power of two)
+pub const POOL_NET_RX_SIZE: usize = 256 * 1024;
+
+/// Bytes per slot: control words, then console out, console in and net rx rings.
+pub const POOL_SLOT_SIZE: usize = SLOT_CONTROL_SIZE
+    + RING_HEADER_SIZE
+    + POOL_CONSOLE_OUT_SIZE
+    + RING_HEADER_SIZE
+    + POOL_CONSOLE_IN_SIZE
+    + RING_HEADER_SIZE
+    + POOL_NET_RX_SIZE;
+
+const POOL_MAGIC: u32 = u32::from_le_bytes(*b"RVPL");
+
+/// Buffer size needed for a pool of `slots` VMs.
+pub const fn pool_buffer_size(slots: usize) -> usize {
+    POOL_HEADER_SIZE + slots * POOL_SLOT_SIZE
+}
+
+/// Lifecycle of a pool slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotState {
+    Free = 0,
+    /// Claimed by the supervisor; the worker is loading the kernel
+    Starting = 1,
+    Running = 2,
+    /// The VM halted; the exit code is valid until the slot is freed
+    Exited = 3,
+}
+
+impl SlotState {
+    fn from_word(word: u32) -> Self {
+        match word {
+            1 => SlotState::Starting,
+            2 => SlotState::Running,
+            3 => SlotState::Exited,
+            _ => SlotState::Free,
+        }
+    }
+}
+
+/// Single-consumer byte ring in shared words.
+///
+/// Producers either stream bytes ([`Self::push_bytes`], one producer) or
+/// append length-prefixed frames under the ring's producer lock
+/// ([`Self::push_frame`], any number of producers).
+#[derive(Clone)]
+pub struct SharedRing {
+    words: SharedWords,
+    /// Byte offset of the ring header
+    base: usize,
+    capacity: usize,
+}
+
+impl SharedRing {
+    const HEAD: u32 = 0;
+    const TAIL: u32 = 1;
+    const LOCK: u32 = 2;
+
+    /// Ring whose header is at byte `base` of `words`, with `capacity`
+    /// (a power of two) data bytes after it.
+    pub fn new(words: SharedWords, base: usize, capacity: usize) -> Self {
+        debug_assert!(capacity.is_power_of_two() && base.is_multiple_of(4));
+        Self {
+            words,
+            base,
+            capacity,
+        }
+    }
+
+    fn index(&self, field: u32) -> u32 {
+        (self.base / 4) as u32 + field
+    }
+
+    /// Bytes waiting for the consumer
+    pub fn len(&self) -> usize {
+        let head = self.words.load(self.index(Self::HEAD));
+        let tail = self.words.load(self.index(Self::TAIL));
+        head.wrapping_sub(tail) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn free(&self) -> usize {
+        self.capacity - self.len()
+    }
+
+    /// Copy `data` in at counter `pos`, wrapping at the end of the ring.
+    fn copy_in(&self, pos: u32, data: &[u8]) {
+        let start = pos as usize % self.capacity;
+        let first = data.len().min(self.capacity - start);
+        let data_base = self.base + RING_HEADER_SIZE;
+        self.words.write_bytes(data_base + start, &data[..first]);
+        self.words.write_bytes(data_base, &data[first..]);
+    }
+
+    fn copy_out(&self, pos: u32, buf: &mut [u8]) {
+        let start = pos as usize % self.capacity;
+        let first = buf.len().min(self.capacity - start);
+        let data_base = self.base + RING_HEADER_SIZE;
+        self.words.read_bytes(data_base + start, &mut buf[..first]);
+        self.words.read_bytes(data_base, &mut buf[first..]);
+    }
+
+    /// Append as much of `data` as fits. Returns the bytes written.
+    pub fn push_bytes(&self, data: &[u8]) -> usize {
+        let n = data.len().min(self.free());
+        let head = self.words.load(self.index(Self::HEAD));
+        self.copy_in(head, &data[..n]);
+        self.words
+            .store(self.index(Self::HEAD), head.wrapping_add(n as u32));
+        n
+    }
+
+    /// Take up to `max` bytes.
+    pub fn pop_bytes(&self, max: usize) -> Vec<u8> {
+        let tail = self.words.load(self.index(Self::TAIL));
+        let mut buf = vec![0u8; self.len().min(max)];
+        self.copy_out(tail, &mut buf);
+        self.words
+            .store(self.index(Self::TAIL), tail.wrapping_add(buf.len() as u32));
+        buf
+    }
+
+    /// Append `frame` whole, or drop it if it doesn't fit.
+    pub fn push_frame(&self, frame: &[u8]) -> bool {
+        let lock = self.index(Self::LOCK);
+        while self.words.compare_exchange(lock, 0, 1).is_err() {
+            std::hint::spin_loop();
+        }
+        let fits = 4 + frame.len() <= self.free();
+        if fits {
+            let head = self.words.load(self.index(Self::HEAD));
+            self.copy_in(head, &(frame.len() as u32).to_le_bytes());
+            self.copy_in(head.wrapping_add(4), frame);
+            self.words.store(
+                self.index(Self::HEAD),
+                head.wrapping_add(4 + frame.len() as u32),
+            );
+        }
+        self.words.store(lock, 0);
+        fits
+    }
+
+    /// Take the oldest frame.
+    pub fn pop_frame(&self) -> Option<Vec<u8>> {
+        if self.len() < 4 {
+            return None;
+        }
+        let tail = self.words.load(self.index(Self::TAIL));
+        let mut len = [0u8; 4];
+        self.copy_out(tail, &mut len);
+        let mut frame = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.copy_out(tail.wrapping_add(4), &mut frame);
+        self.words.store(
+            self.index(Self::TAIL),
+            tail.wrapping_add(4 + frame.len() as u32),
+        );
+        Some(frame)
+    }
+
+    /// Drop everything queued (only while nobody produces or consumes).
+    pub fn reset(&self) {
+        self.words.store(self.index(Self::HEAD), 0);
+        self.words.store(self.index(Self::TAIL), 0);
+        self.words.store(self.index(Self::LOCK), 0);
+    }
+}
+
+/// Slots of VMs run by a pool of workers, shared by the supervisor and
+/// every worker: each slot has its lifecycle words, its console rings and
+/// the receive ring its NIC reads from. Workers hand frames to each other
+/// through [`Self::switch_frame`], so traffic between pooled VMs never
+/// passes through JavaScript.
+#[derive(Clone)]
+pub struct PoolBuffer {
+    words: SharedWords,
+    slots: usize,
+}
+
+impl PoolBuffer {
+    /// Initialize `words` as a pool of as many slots as fit (at most
+    /// `POOL_MAX_VMS`).
+    pub fn init(words: SharedWords) -> Result<Self, String> {
+        let slots =
+            (words.len().saturating_sub(POOL_HEADER_SIZE) / POOL_SLOT_SIZE).min(POOL_MAX_VMS);
+        if slots == 0 {
+            return Err(format!(
+                "pool buffer needs at least {} bytes",
+                pool_buffer_size(1)
+            ));
+        }
+        words.store(POOL_SLOTS_IDX, slots as u32);
+        words.store(POOL_MAGIC_IDX, POOL_MAGIC);
+        Ok(Self { words, slots })
+    }
+
+    /// Attach to a pool another thread initialized.
+    pub fn open(words: SharedWords) -> Result<Self, String> {
+        if words.len() < POOL_HEADER_SIZE || words.load(POOL_MAGIC_IDX) != POOL_MAGIC {
+            return Err("not an initialized VM pool buffer".to_string());
+        }
+        let slots = words.load(POOL_SLOTS_IDX) as usize;
+        if words.len() < pool_buffer_size(slots) {
+            return Err("VM pool buffer is truncated".to_string());
+        }
+        Ok(Self { words, slots })
+    }
+
+    /// Pool of `slots` VMs in process memory.
+    pub fn new(slots: usize) -> Result<Self, String> {
+        Self::init(SharedWords::new(pool_buffer_size(slots)))
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slots
+    }
+
+    fn slot_base(slot: usize) -> usize {
+        POOL_HEADER_SIZE + slot * POOL_SLOT_SIZE
+    }
+
+    fn control(&self, slot: usize, field: u32) -> u32 {
+        (Self::slot_base(slot) / 4) as u32 + field
+    }
+
+    pub fn state(&self, slot: usize) -> SlotState {
+        SlotState::from_word(self.words.load(self.control(slot, SLOT_STATE)))
+    }
+
+    pub fn set_state(&self, slot: usize, state: SlotState) {
+        self.words
+            .store(self.control(slot, SLOT_STATE), state as u32);
+    }
+
+    /// Claim a free slot for a new VM: clear its rings and mark it
+    /// starting.
+    pub fn claim(&self) -> Option<usize> {
+        let slot = (0..self.slots).find(|&slot| {
+            self.words
+                .compare_exchange(
+                    self.control(slot, SLOT_STATE),
+                    SlotState::Free as u32,
+                    SlotState::Starting as u32,
+                )
+                .is_ok()
+        })?;
+        for ring in [
+            self.console_out(slot),
+            self.console_in(slot),
+            self.net_rx(slot),
+        ] {
+            ring.reset();
+        }
+        self.words.store(self.control(slot, SLOT_STOP), 0);
+        self.words.store(self.control(slot, SLOT_EXIT_LO), 0);
+        self.words.store(self.control(slot, SLOT_EXIT_HI), 0);
+        Some(slot)
+    }
+
+    /// Record the NIC address of the VM in `slot`.
+    pub fn set_mac(&self, slot: usize, mac: [u8; 6]) {
+        self.words.store(
+            self.control(slot, SLOT_MAC_LO),
+            u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+        );
+        self.words.store(
+            self.control(slot, SLOT_MAC_HI),
+            u32::from_le_bytes([mac[4], mac[5], 0, 0]),
+        );
+    }
+
+    /// Return a slot to the free list.
+    pub fn release(&self, slot: usize) {
+        self.set_state(slot, SlotState::Free);
+    }
+
+    pub fn mac(&self, slot: usize) -> [u8; 6] {
+        let lo = self
+            .words
+            .load(self.control(slot, SLOT_MAC_LO))
+            .to_le_bytes();
+        let hi = self
+            .words
+            .load(self.control(slot, SLOT_MAC_HI))
+            .to_le_bytes();
+        [lo[0], lo[1], lo[2], lo[3], hi[0], hi[1]]
+    }
+
+    pub fn request_stop(&self, slot: usize) {
+        self.words.store(self.control(slot, SLOT_STOP), 1);
+    }
+
+    pub fn stop_requested(&self, slot: usize) -> bool {
+        self.words.load(self.control(slot, SLOT_STOP)) != 0
+    }
+
+    /// Record the VM's exit code and mark the slot exited.
+    pub fn set_exited(&self, slot: usize, code: u64) {
+        self.words
+            .store(self.control(slot, SLOT_EXIT_LO), code as u32);
+        self.words
+            .store(self.control(slot, SLOT_EXIT_HI), (code >> 32) as u32);
+        self.set_state(slot, SlotState::Exited);
+    }
+
+    pub fn exit_code(&self, slot: usize) -> u64 {
+        let lo = self.words.load(self.control(slot, SLOT_EXIT_LO)) as u64;
+        let hi = self.words.load(self.control(slot, SLOT_EXIT_HI)) as u64;
+        lo | hi << 32
+    }
+
+    /// Guest console output, produced by the slot's worker
+    pub fn console_out(&self, slot: usize) -> SharedRing {
+        let base = Self::slot_base(slot) + SLOT_CONTROL_SIZE;
+        SharedRing::new(self.words.clone(), base, POOL_CONSOLE_OUT_SIZE)
+    }
+
+    /// Guest console input, produced by the supervisor
+    pub fn console_in(&self, slot: usize) -> SharedRing {
+        let base =
+            Self::slot_base(slot) + SLOT_CONTROL_SIZE + RING_HEADER_SIZE + POOL_CONSOLE_OUT_SIZE;
+        SharedRing::new(self.words.clone(), base, POOL_CONSOLE_IN_SIZE)
+    }
+
+    /// Frames for the slot's NIC, produced by the other slots' workers
+    pub fn net_rx(&self, slot: usize) -> SharedRing {
+        let base = Self::slot_base(slot)
+            + SLOT_CONTROL_SIZE
+            + 2 * RING_HEADER_SIZE
+            + POOL_CONSOLE_OUT_SIZE
+            + POOL_CONSOLE_IN_SIZE;
+        SharedRing::new(self.words.clone(), base, POOL_NET_RX_SIZE)
+    }
+
+    /// Deliver an Ethernet frame sent by slot `from`: to the running slot
+    /// owning the destination MAC, or to every other running slot for
+    /// broadcast, multicast and unknown destinations. Returns how many
+    /// slots received it.
+    pub fn switch_frame(&self, from: usize, frame: &[u8]) -> usize {
+        if frame.len() < 14 {
+            return 0;
+        }
+        let dst = &frame[0..6];
+        let running = (0..self.slots).filter(|&s| s != from && self.state(s) == SlotState::Running);
+        if dst[0] & 1 == 0
+            && let Some(slot) = running.clone().find(|&s| self.mac(s) == dst)
+        {
+            return self.net_rx(slot).push_frame(frame) as usize;
+        }
+        running
+            .filter(|&slot| self.net_rx(slot).push_frame(frame))
+            .count()
+    }
+}
+
 // ============================================================================
 // WASM-specific shared CLINT implementation
 // ============================================================================
@@ -871,6 +1630,88 @@ mod tests {
         assert_eq!(mtime_offset(), CONTROL_REGION_SIZE + 0xBFF8);
     }
 
+    #[test]
+    fn test_shared_window_access() {
+        let window = SharedWindow::new(30);
+        assert_eq!(window.size(), 32);
+        let peer = window.clone();
+
+        window.write(4, 4, 0xdead_beef);
+        window.write(5, 1, 0x11);
+        assert_eq!(peer.read(4, 4), 0xdead_11ef);
+        // 64-bit and word-straddling accesses
+        window.write(8, 8, 0x0102_0304_0506_0708);
+        assert_eq!(peer.read(8, 8), 0x0102_0304_0506_0708);
+        window.write(14, 4, 0xaabb_ccdd);
+        assert_eq!(peer.read(14, 4), 0xaabb_ccdd);
+        assert_eq!(peer.read(12, 2), 0x0304);
+        // Out of range
+        window.write(30, 4, u64::MAX);
+        assert_eq!(peer.read(30, 4), 0);
+
+        window.attach(0).unwrap();
+        peer.attach(1).unwrap();
+        assert!(peer.attach(1).is_err());
+        assert!(peer.attach(SHM_MAX_PEERS as u32).is_err());
+        assert_eq!(window.peers(), 0b11);
+        window.ring(1, 0);
+        assert_eq!(peer.pending(1), 0b1);
+        peer.ack(1, 0b1);
+        assert_eq!(peer.pending(1), 0);
+        peer.detach(1);
+        assert_eq!(window.peers(), 0b1);
+    }
+
+    #[test]
+    fn test_ring_wraps() {
+        let ring = SharedRing::new(SharedWords::new(RING_HEADER_SIZE + 16), 0, 16);
+        assert_eq!(ring.push_bytes(b"0123456789"), 10);
+        assert_eq!(ring.pop_bytes(8), b"01234567");
+        // Wraps around the end; only what fits is taken
+        assert_eq!(ring.push_bytes(b"abcdefghijklmnop"), 14);
+        assert_eq!(ring.pop_bytes(usize::MAX), b"89abcdefghijklmn");
+
+        assert!(ring.push_frame(b"hello"));
+        assert!(!ring.push_frame(b"too long to fit"));
+        assert_eq!(ring.pop_frame().as_deref(), Some(&b"hello"[..]));
+        assert!(ring.pop_frame().is_none());
+    }
+
+    #[test]
+    fn test_pool_switches_frames() {
+        let pool = PoolBuffer::new(3).unwrap();
+        let opened = PoolBuffer::open(pool.words.clone()).unwrap();
+        assert_eq!(opened.slot_count(), 3);
+
+        let macs = [[2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2], [2, 0, 0, 0, 0, 3]];
+        for mac in macs {
+            let slot = pool.claim().unwrap();
+            pool.set_mac(slot, mac);
+            opened.set_state(slot, SlotState::Running);
+        }
+        assert!(pool.claim().is_none());
+        assert_eq!(opened.mac(1), macs[1]);
+
+        let mut frame = vec![0u8; 60];
+        frame[0..6].copy_from_slice(&macs[2]);
+        assert_eq!(opened.switch_frame(0, &frame), 1);
+        assert_eq!(pool.net_rx(2).pop_frame(), Some(frame.clone()));
+        assert!(pool.net_rx(1).is_empty());
+
+        frame[0..6].copy_from_slice(&[0xff; 6]);
+        assert_eq!(opened.switch_frame(0, &frame), 2);
+        assert!(pool.net_rx(0).is_empty());
+
+        // Exited VMs get nothing
+        pool.set_exited(1, 0x5555);
+        assert_eq!(opened.exit_code(1), 0x5555);
+        assert_eq!(opened.switch_frame(0, &frame), 1);
+
+        pool.release(1);
+        assert_eq!(pool.claim(), Some(1));
+        assert!(pool.net_rx(1).is_empty());
+    }
+
     #[test]
     fn test_total_size() {
         let dram_size = 512 * 1024 * 1024; // 512 MiB
@@ -1,12 +1,51 @@
+//! Machine snapshots.
+//!
+//! A [`Snapshot`] holds a hart, the CLINT, PLIC and UART and the whole of
+//! DRAM. It is saved in one of two formats:
+//!
+//! - a single bincode value ([`Snapshot::to_bytes`]), which needs a copy of
+//!   DRAM in memory while it is encoded;
+//! - a stream of chunks ([`Snapshot::write_stream`]), which reads DRAM
+//!   [`STREAM_CHUNK_SIZE`] bytes at a time, optionally compressing each
+//!   chunk with LZ4 or zstd. `NativeVm` streams to a file this way and
+//!   `WasmVm` hands the chunks to the page, e.g. to store in IndexedDB.
+//!
+//! [`Snapshot::from_bytes`] reads either format, and [`restore_stream`]
+//! loads a stream into a machine without holding DRAM twice.
+//!
+//! ## Stream Layout
+//!
+//! A stream starts with the magic `"RVSS"`, a format version byte and a
+//! [`Compression`] byte, followed by frames of
+//! `raw_len: u32 | stored_len: u32 | stored bytes` (little-endian), each
+//! compressed on its own:
+//!
+//! 1. the snapshot without DRAM contents, bincode-encoded;
+//! 2. DRAM, in frames of at most [`STREAM_CHUNK_SIZE`] bytes;
+//! 3. the SHA-256 of DRAM, as hex.
+
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::cpu::vector::DEFAULT_VLEN;
 use crate::csr::Mode;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
 
 /// Version identifier for snapshot compatibility checks.
-pub const SNAPSHOT_VERSION: &str = "2.0";
+pub const SNAPSHOT_VERSION: &str = "4.0";
+
+/// Version of snapshots taken before vector support (no `vregs`).
+pub const SNAPSHOT_VERSION_V3: &str = "3.0";
+
+/// Version of snapshots taken before F/D support (no `fregs`).
+pub const SNAPSHOT_VERSION_V2: &str = "2.0";
 
 /// Full emulator snapshot including CPU, devices and DRAM.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Snapshot {
     pub version: String,
     pub cpu: CpuSnapshot,
@@ -14,31 +53,521 @@ pub struct Snapshot {
     pub memory: Vec<MemRegionSnapshot>,
 }
 
+impl Snapshot {
+    /// Capture the state of `cpu` together with the devices and DRAM of
+    /// `bus`.
+    pub fn capture(cpu: &Cpu, bus: &SystemBus) -> Snapshot {
+        let dram_data = bus.dram.get_data();
+        let mut hasher = Sha256::new();
+        hasher.update(&dram_data);
+        let hash = hex::encode(hasher.finalize());
+
+        let region = MemRegionSnapshot {
+            base: bus.dram.base,
+            size: bus.dram.size() as u64,
+            hash,
+            data: Some(dram_data),
+        };
+
+        Snapshot {
+            version: SNAPSHOT_VERSION.to_string(),
+            cpu: CpuSnapshot::capture(cpu),
+            devices: DeviceSnapshot::capture(bus),
+            memory: vec![region],
+        }
+    }
+
+    /// Encode with bincode, the on-disk snapshot format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| format!("failed to encode snapshot: {}", e))
+    }
+
+    /// Decode a snapshot of any supported version, migrating older layouts
+    /// to the current one.
+    ///
+    /// The version string is the first field in every layout, so it is read
+    /// on its own to pick the layout for the rest.
+    ///
+    /// Streamed snapshots (see [`write_stream`](Self::write_stream)) are
+    /// recognised by their magic and read with
+    /// [`read_stream`](Self::read_stream).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if is_stream(bytes) {
+            return Self::read_stream(&mut &bytes[..]);
+        }
+        let version: String =
+            bincode::deserialize(bytes).map_err(|e| format!("invalid snapshot header: {}", e))?;
+        let decode_err = |e: bincode::Error| format!("invalid {} snapshot: {}", version, e);
+        match version.as_str() {
+            SNAPSHOT_VERSION => bincode::deserialize(bytes).map_err(decode_err),
+            SNAPSHOT_VERSION_V3 => bincode::deserialize::<SnapshotV3>(bytes)
+                .map(Snapshot::from)
+                .map_err(decode_err),
+            SNAPSHOT_VERSION_V2 => bincode::deserialize::<SnapshotV2>(bytes)
+                .map(|old| Snapshot::from(SnapshotV3::from(old)))
+                .map_err(decode_err),
+            _ => Err(format!("unsupported snapshot version {}", version)),
+        }
+    }
+
+    /// Write the state of `cpu` and the devices and DRAM of `bus` to `out`
+    /// as a stream (see the [module docs](self)). DRAM is read one chunk at
+    /// a time, so it is never copied whole.
+    pub fn write_stream<W: SnapshotWriter + ?Sized>(
+        cpu: &Cpu,
+        bus: &SystemBus,
+        out: &mut W,
+        compression: Compression,
+    ) -> Result<(), String> {
+        let size = bus.dram.size();
+        let header = Snapshot {
+            version: SNAPSHOT_VERSION.to_string(),
+            cpu: CpuSnapshot::capture(cpu),
+            devices: DeviceSnapshot::capture(bus),
+            memory: vec![MemRegionSnapshot {
+                base: bus.dram.base,
+                size: size as u64,
+                hash: String::new(),
+                data: None,
+            }],
+        };
+        write_header(out, compression, &header)?;
+
+        let mut hasher = Sha256::new();
+        for offset in (0..size).step_by(STREAM_CHUNK_SIZE) {
+            let chunk = bus
+                .dram
+                .read_range(offset, STREAM_CHUNK_SIZE.min(size - offset))
+                .map_err(|e| format!("failed to read DRAM: {}", e))?;
+            hasher.update(&chunk);
+            write_frame(out, compression, &chunk)?;
+        }
+        write_frame(out, compression, hex::encode(hasher.finalize()).as_bytes())
+    }
+
+    /// Write this snapshot to `out` as a stream.
+    pub fn to_stream<W: SnapshotWriter + ?Sized>(
+        &self,
+        out: &mut W,
+        compression: Compression,
+    ) -> Result<(), String> {
+        let mut header = self.clone();
+        let region = header
+            .memory
+            .first_mut()
+            .ok_or("snapshot missing primary memory region")?;
+        let data = region
+            .data
+            .take()
+            .ok_or("snapshot memory region has no inline data")?;
+        region.hash.clear();
+        write_header(out, compression, &header)?;
+        for chunk in data.chunks(STREAM_CHUNK_SIZE) {
+            write_frame(out, compression, chunk)?;
+        }
+        write_frame(out, compression, self.memory[0].hash.as_bytes())
+    }
+
+    /// Read a whole streamed snapshot, DRAM included, from `input`.
+    pub fn read_stream<R: SnapshotReader + ?Sized>(input: &mut R) -> Result<Self, String> {
+        let (mut stream, mut snapshot) = StreamDecoder::open(input)?;
+        let region = &mut snapshot.memory[0];
+        let mut data = Vec::new();
+        region.hash = stream.read_memory(region.size, |_, chunk| {
+            data.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        region.data = Some(data);
+        Ok(snapshot)
+    }
+}
+
+/// Raw DRAM bytes in each frame of a streamed snapshot.
+pub const STREAM_CHUNK_SIZE: usize = 1 << 20;
+
+/// First bytes of a streamed snapshot.
+const STREAM_MAGIC: [u8; 4] = *b"RVSS";
+
+/// Version of the stream framing, independent of [`SNAPSHOT_VERSION`].
+const STREAM_FORMAT: u8 = 1;
+
+/// Largest frame a reader accepts before decompressing it, so a corrupt
+/// length can't make it allocate without bound.
+const MAX_FRAME: usize = 16 * STREAM_CHUNK_SIZE;
+
+/// How each frame of a streamed snapshot is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    /// LZ4 block format: fast, with a modest ratio
+    Lz4 = 1,
+    /// Zstandard at its fastest level: slower, smaller
+    Zstd = 2,
+}
+
+impl Compression {
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(format!("unknown snapshot compression {}", tag)),
+        }
+    }
+
+    fn compress(self, raw: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => raw.to_vec(),
+            Compression::Lz4 => lz4_flex::block::compress(raw),
+            Compression::Zstd => {
+                ruzstd::encoding::compress_to_vec(raw, ruzstd::encoding::CompressionLevel::Fastest)
+            }
+        }
+    }
+
+    fn decompress(self, stored: &[u8], raw_len: usize) -> Result<Vec<u8>, String> {
+        let raw = match self {
+            Compression::None => stored.to_vec(),
+            Compression::Lz4 => lz4_flex::block::decompress(stored, raw_len)
+                .map_err(|e| format!("corrupt LZ4 frame: {}", e))?,
+            Compression::Zstd => {
+                let mut raw = vec![0; raw_len];
+                let len = ruzstd::decoding::FrameDecoder::new()
+                    .decode_all(stored, &mut raw)
+                    .map_err(|e| format!("corrupt zstd frame: {}", e))?;
+                raw.truncate(len);
+                raw
+            }
+        };
+        if raw.len() != raw_len {
+            return Err(format!(
+                "snapshot frame holds {} bytes, expected {}",
+                raw.len(),
+                raw_len
+            ));
+        }
+        Ok(raw)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unknown compression '{}' (expected none, lz4 or zstd)",
+                s
+            )),
+        }
+    }
+}
+
+/// Where a streamed snapshot goes, one chunk at a time. Every
+/// [`std::io::Write`] is one; chunks are at most a frame of
+/// [`STREAM_CHUNK_SIZE`] bytes plus its header.
+pub trait SnapshotWriter {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), String>;
+}
+
+impl<W: Write + ?Sized> SnapshotWriter for W {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.write_all(chunk)
+            .map_err(|e| format!("failed to write snapshot: {}", e))
+    }
+}
+
+/// Where a streamed snapshot comes from. Every [`std::io::Read`] is one.
+pub trait SnapshotReader {
+    /// Fill `buf` with the next bytes of the stream.
+    fn read_chunk(&mut self, buf: &mut [u8]) -> Result<(), String>;
+}
+
+impl<R: Read + ?Sized> SnapshotReader for R {
+    fn read_chunk(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        self.read_exact(buf)
+            .map_err(|e| format!("failed to read snapshot: {}", e))
+    }
+}
+
+/// Whether `bytes` start like a streamed snapshot.
+pub fn is_stream(bytes: &[u8]) -> bool {
+    bytes.starts_with(&STREAM_MAGIC)
+}
+
+/// Load a streamed snapshot from `input` into `cpu` and the devices and
+/// DRAM of `bus`, one chunk at a time. DRAM must be the size it was when
+/// the snapshot was taken.
+///
+/// DRAM is overwritten as the stream is read, and the hart and devices
+/// only once its hash checks out: after an error the machine is left with
+/// part of the snapshot's memory and should be restored again or reset.
+pub fn restore_stream<R: SnapshotReader + ?Sized>(
+    input: &mut R,
+    cpu: &mut Cpu,
+    bus: &SystemBus,
+) -> Result<(), String> {
+    let (mut stream, snapshot) = StreamDecoder::open(input)?;
+    let region = &snapshot.memory[0];
+    if bus.dram.base != region.base {
+        return Err(format!(
+            "snapshot DRAM base mismatch: emulator=0x{:x}, snapshot=0x{:x}",
+            bus.dram.base, region.base
+        ));
+    }
+    if bus.dram.size() as u64 != region.size {
+        return Err(format!(
+            "snapshot DRAM size mismatch: emulator={} bytes, snapshot={} bytes",
+            bus.dram.size(),
+            region.size
+        ));
+    }
+    stream.read_memory(region.size, |offset, chunk| {
+        bus.dram
+            .write_bytes(offset, chunk)
+            .map_err(|e| format!("failed to restore DRAM: {}", e))
+    })?;
+    snapshot.cpu.apply(cpu)?;
+    snapshot.devices.apply(bus);
+    Ok(())
+}
+
+/// Start a stream on `out` with the preamble and `header`, the snapshot
+/// without DRAM contents.
+fn write_header<W: SnapshotWriter + ?Sized>(
+    out: &mut W,
+    compression: Compression,
+    header: &Snapshot,
+) -> Result<(), String> {
+    let header =
+        bincode::serialize(header).map_err(|e| format!("failed to encode snapshot: {}", e))?;
+    let mut preamble = STREAM_MAGIC.to_vec();
+    preamble.extend_from_slice(&[STREAM_FORMAT, compression as u8]);
+    out.write_chunk(&preamble)?;
+    write_frame(out, compression, &header)
+}
+
+/// Write `raw` to `out` as one frame.
+fn write_frame<W: SnapshotWriter + ?Sized>(
+    out: &mut W,
+    compression: Compression,
+    raw: &[u8],
+) -> Result<(), String> {
+    let stored = compression.compress(raw);
+    let mut frame = Vec::with_capacity(8 + stored.len());
+    frame.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&stored);
+    out.write_chunk(&frame)
+}
+
+/// Reads the frames of a streamed snapshot.
+struct StreamDecoder<'a, R: ?Sized> {
+    input: &'a mut R,
+    compression: Compression,
+}
+
+impl<'a, R: SnapshotReader + ?Sized> StreamDecoder<'a, R> {
+    /// Check the preamble and read the header: the snapshot without DRAM
+    /// contents or hash.
+    fn open(input: &'a mut R) -> Result<(Self, Snapshot), String> {
+        let mut preamble = [0; 6];
+        input.read_chunk(&mut preamble)?;
+        if !is_stream(&preamble) {
+            return Err("not a streamed snapshot".to_string());
+        }
+        if preamble[4] != STREAM_FORMAT {
+            return Err(format!(
+                "unsupported snapshot stream format {}",
+                preamble[4]
+            ));
+        }
+        let mut stream = StreamDecoder {
+            input,
+            compression: Compression::from_tag(preamble[5])?,
+        };
+
+        let header: Snapshot = bincode::deserialize(&stream.read_frame()?)
+            .map_err(|e| format!("invalid snapshot header: {}", e))?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(format!("unsupported snapshot version {}", header.version));
+        }
+        if header.memory.is_empty() {
+            return Err("snapshot missing primary memory region".to_string());
+        }
+        Ok((stream, header))
+    }
+
+    fn read_frame(&mut self) -> Result<Vec<u8>, String> {
+        let mut lens = [0; 8];
+        self.input.read_chunk(&mut lens)?;
+        let raw_len = u32::from_le_bytes(lens[..4].try_into().unwrap()) as usize;
+        let stored_len = u32::from_le_bytes(lens[4..].try_into().unwrap()) as usize;
+        if raw_len > MAX_FRAME || stored_len > MAX_FRAME {
+            return Err(format!("snapshot frame of {} bytes is too large", raw_len));
+        }
+        let mut stored = vec![0; stored_len];
+        self.input.read_chunk(&mut stored)?;
+        self.compression.decompress(&stored, raw_len)
+    }
+
+    /// Read `size` bytes of DRAM, passing each chunk to `chunk` with its
+    /// offset, and the hash after them, which must match. Returns the hash.
+    fn read_memory(
+        &mut self,
+        size: u64,
+        mut chunk: impl FnMut(u64, &[u8]) -> Result<(), String>,
+    ) -> Result<String, String> {
+        let mut hasher = Sha256::new();
+        let mut offset = 0;
+        while offset < size {
+            let data = self.read_frame()?;
+            if data.is_empty() || offset + data.len() as u64 > size {
+                return Err("snapshot DRAM frames do not add up to its size".to_string());
+            }
+            hasher.update(&data);
+            chunk(offset, &data)?;
+            offset += data.len() as u64;
+        }
+        let hash = String::from_utf8(self.read_frame()?)
+            .map_err(|_| "invalid snapshot DRAM hash".to_string())?;
+        if hash != hex::encode(hasher.finalize()) {
+            return Err("snapshot DRAM hash mismatch".to_string());
+        }
+        Ok(hash)
+    }
+}
+
 /// Serializable CPU state.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CpuSnapshot {
     pub pc: u64,
     pub mode: Mode,
     pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    /// VLEN in bits.
+    pub vlen: usize,
+    /// `v0`-`v31`, VLEN/8 bytes each.
+    pub vregs: Vec<u8>,
     pub csrs: HashMap<u16, u64>,
 }
 
+impl CpuSnapshot {
+    /// Capture the architectural state of `cpu`.
+    pub fn capture(cpu: &Cpu) -> Self {
+        CpuSnapshot {
+            pc: cpu.pc,
+            mode: cpu.mode,
+            regs: cpu.regs,
+            fregs: cpu.fregs,
+            vlen: cpu.vlen(),
+            vregs: cpu.vector_regs().to_vec(),
+            csrs: cpu.export_csrs(),
+        }
+    }
+
+    /// Put `cpu` in this state. Its cached translations and blocks are
+    /// dropped, as they may not hold for it.
+    pub fn apply(&self, cpu: &mut Cpu) -> Result<(), String> {
+        cpu.restore_vector_regs(self.vlen, &self.vregs)?;
+        cpu.pc = self.pc;
+        cpu.mode = self.mode;
+        cpu.regs = self.regs;
+        cpu.fregs = self.fregs;
+        cpu.import_csrs(&self.csrs);
+        cpu.flush_cached_state();
+        Ok(())
+    }
+}
+
 /// Serializable device state bundle.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DeviceSnapshot {
     pub clint: ClintSnapshot,
     pub plic: PlicSnapshot,
     pub uart: UartSnapshot,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl DeviceSnapshot {
+    /// Capture the state of the CLINT, PLIC and UART of `bus`.
+    pub fn capture(bus: &SystemBus) -> Self {
+        let clint = ClintSnapshot {
+            msip: bus.clint.get_msip_array().to_vec(),
+            mtime: bus.clint.mtime(),
+            mtimecmp: bus.clint.get_mtimecmp_array().to_vec(),
+        };
+
+        let plic = PlicSnapshot {
+            priority: bus.plic.get_priority(),
+            pending: bus.plic.get_pending(),
+            enable: bus.plic.get_enable(),
+            threshold: bus.plic.get_threshold(),
+            active: bus.plic.get_active(),
+        };
+
+        let (ier, iir, fcr, lcr, mcr, lsr, msr, scr, dll, dlm) = bus.uart.get_registers();
+        let uart = UartSnapshot {
+            rx_fifo: bus.uart.get_input(),
+            tx_fifo: bus.uart.get_output(),
+            ier,
+            iir,
+            fcr,
+            lcr,
+            mcr,
+            lsr,
+            msr,
+            scr,
+            dll,
+            dlm,
+        };
+
+        DeviceSnapshot { clint, plic, uart }
+    }
+
+    /// Put the CLINT, PLIC and UART of `bus` in this state.
+    pub fn apply(&self, bus: &SystemBus) {
+        bus.clint.set_msip_array(&self.clint.msip);
+        bus.clint.set_mtime(self.clint.mtime);
+        bus.clint.set_mtimecmp_array(&self.clint.mtimecmp);
+
+        bus.plic.set_priority(&self.plic.priority);
+        bus.plic.set_pending(self.plic.pending);
+        bus.plic.set_enable(&self.plic.enable);
+        bus.plic.set_threshold(&self.plic.threshold);
+        bus.plic.set_active(&self.plic.active);
+
+        let uart = &self.uart;
+        bus.uart.set_input(&uart.rx_fifo);
+        bus.uart.set_output(&uart.tx_fifo);
+        bus.uart.set_registers(
+            uart.ier, uart.iir, uart.fcr, uart.lcr, uart.mcr, uart.lsr, uart.msr, uart.scr,
+            uart.dll, uart.dlm,
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClintSnapshot {
     pub msip: Vec<u32>,
     pub mtime: u64,
     pub mtimecmp: Vec<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PlicSnapshot {
     pub priority: Vec<u32>,
     pub pending: u32,
@@ -47,7 +576,7 @@ pub struct PlicSnapshot {
     pub active: Vec<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UartSnapshot {
     pub rx_fifo: Vec<u8>,
     pub tx_fifo: Vec<u8>,
@@ -63,10 +592,542 @@ pub struct UartSnapshot {
     pub dlm: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MemRegionSnapshot {
     pub base: u64,
     pub size: u64,
     pub hash: String,
     pub data: Option<Vec<u8>>,
 }
+
+/// Snapshot layout of version [`SNAPSHOT_VERSION_V3`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotV3 {
+    pub version: String,
+    pub cpu: CpuSnapshotV3,
+    pub devices: DeviceSnapshot,
+    pub memory: Vec<MemRegionSnapshot>,
+}
+
+/// CPU state of a [`SnapshotV3`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuSnapshotV3 {
+    pub pc: u64,
+    pub mode: Mode,
+    pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    pub csrs: HashMap<u16, u64>,
+}
+
+/// The vector registers come back cleared, at the default VLEN.
+impl From<SnapshotV3> for Snapshot {
+    fn from(old: SnapshotV3) -> Self {
+        Snapshot {
+            version: SNAPSHOT_VERSION.to_string(),
+            cpu: CpuSnapshot {
+                pc: old.cpu.pc,
+                mode: old.cpu.mode,
+                regs: old.cpu.regs,
+                fregs: old.cpu.fregs,
+                vlen: DEFAULT_VLEN,
+                vregs: vec![0; 32 * DEFAULT_VLEN / 8],
+                csrs: old.cpu.csrs,
+            },
+            devices: old.devices,
+            memory: old.memory,
+        }
+    }
+}
+
+/// Snapshot layout of version [`SNAPSHOT_VERSION_V2`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotV2 {
+    pub version: String,
+    pub cpu: CpuSnapshotV2,
+    pub devices: DeviceSnapshot,
+    pub memory: Vec<MemRegionSnapshot>,
+}
+
+/// CPU state of a [`SnapshotV2`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuSnapshotV2 {
+    pub pc: u64,
+    pub mode: Mode,
+    pub regs: [u64; 32],
+    pub csrs: HashMap<u16, u64>,
+}
+
+impl From<SnapshotV2> for SnapshotV3 {
+    fn from(old: SnapshotV2) -> Self {
+        SnapshotV3 {
+            version: SNAPSHOT_VERSION_V3.to_string(),
+            cpu: CpuSnapshotV3 {
+                pc: old.cpu.pc,
+                mode: old.cpu.mode,
+                regs: old.cpu.regs,
+                fregs: [0; 32],
+                csrs: old.cpu.csrs,
+            },
+            devices: old.devices,
+            memory: old.memory,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::csr::{CSR_MSCRATCH, CSR_MTVEC, CSR_SATP, CSR_STVEC};
+    use crate::devices::clint::MAX_HARTS;
+    use crate::vm::emulator::Emulator;
+    use proptest::prelude::*;
+
+    const DRAM_SIZE: usize = 64 * 1024;
+    /// Data area addressed by loads/stores in generated programs (via x31)
+    const DATA_OFFSET: u64 = 0x8000;
+    const STEPS: usize = 64;
+
+    #[derive(Debug, Clone)]
+    struct CpuState {
+        pc_slot: usize,
+        mode: Mode,
+        regs: [u64; 32],
+        fregs: [u64; 32],
+        vlen: usize,
+        vregs: Vec<u8>,
+        csrs: HashMap<u16, u64>,
+        program: Vec<u32>,
+    }
+
+    #[derive(Debug, Clone)]
+    struct DeviceState {
+        mtime: u64,
+        msip: Vec<u32>,
+        mtimecmp: Vec<u64>,
+        priority: Vec<u32>,
+        pending: u32,
+        enable: Vec<u32>,
+        threshold: Vec<u32>,
+        active: Vec<u32>,
+        rx: Vec<u8>,
+        tx: Vec<u8>,
+        uart_regs: [u8; 10],
+    }
+
+    #[derive(Debug, Clone)]
+    struct MachineState {
+        cpu: CpuState,
+        devices: DeviceState,
+        mem_writes: Vec<(u64, Vec<u8>)>,
+    }
+
+    fn jal_x0(offset: i32) -> u32 {
+        let imm = offset as u32;
+        ((imm >> 20) & 1) << 31
+            | ((imm >> 1) & 0x3ff) << 21
+            | ((imm >> 11) & 1) << 20
+            | ((imm >> 12) & 0xff) << 12
+            | 0x6f
+    }
+
+    /// ALU ops plus loads/stores into the data area, which can't trap.
+    fn insn() -> impl Strategy<Value = u32> {
+        (0u32..6, 1u32..31, 0u32..31, 0u32..31, 0u32..0x1000).prop_map(|(op, rd, rs1, rs2, imm)| {
+            let off = imm & 0x7f8;
+            match op {
+                0 => imm << 20 | rs1 << 15 | rd << 7 | 0x13, // addi
+                1 => rs2 << 20 | rs1 << 15 | rd << 7 | 0x33, // add
+                2 => 0x4000_0000 | rs2 << 20 | rs1 << 15 | rd << 7 | 0x33, // sub
+                3 => rs2 << 20 | rs1 << 15 | 4 << 12 | rd << 7 | 0x33, // xor
+                4 => off << 20 | 31 << 15 | 3 << 12 | rd << 7 | 0x03, // ld rd, off(x31)
+                _ => (off >> 5) << 25 | rs2 << 20 | 31 << 15 | 3 << 12 | (off & 0x1f) << 7 | 0x23, // sd
+            }
+        })
+    }
+
+    /// VLEN and vector registers.
+    fn vector_state() -> impl Strategy<Value = (usize, Vec<u8>)> {
+        prop_oneof![Just(64usize), Just(128), Just(256)].prop_flat_map(|vlen| {
+            (
+                Just(vlen),
+                prop::collection::vec(any::<u8>(), 32 * vlen / 8),
+            )
+        })
+    }
+
+    fn cpu_state() -> impl Strategy<Value = CpuState> {
+        (
+            prop::collection::vec(insn(), 1..48),
+            any::<prop::sample::Index>(),
+            prop_oneof![
+                Just(Mode::User),
+                Just(Mode::Supervisor),
+                Just(Mode::Machine)
+            ],
+            prop::array::uniform32(any::<u64>()),
+            prop::array::uniform32(any::<u64>()),
+            vector_state(),
+            prop::collection::hash_map(0u16..0x1000, 1u64.., 0..32),
+        )
+            .prop_map(
+                |(mut program, slot, mode, mut regs, fregs, vector, mut csrs)| {
+                    // Loop forever; traps and interrupts re-enter at the top
+                    program.push(jal_x0(-4 * program.len() as i32));
+                    regs[0] = 0;
+                    regs[31] = DRAM_BASE + DATA_OFFSET;
+                    csrs.remove(&CSR_SATP);
+                    csrs.insert(CSR_MTVEC, DRAM_BASE);
+                    csrs.insert(CSR_STVEC, DRAM_BASE);
+                    CpuState {
+                        pc_slot: slot.index(program.len()),
+                        mode,
+                        regs,
+                        fregs,
+                        vlen: vector.0,
+                        vregs: vector.1,
+                        csrs,
+                        program,
+                    }
+                },
+            )
+    }
+
+    fn device_state() -> impl Strategy<Value = DeviceState> {
+        (
+            (
+                any::<u64>(),
+                prop::collection::vec(0u32..2, 0..=MAX_HARTS),
+                prop::collection::vec(any::<u64>(), 0..=MAX_HARTS),
+            ),
+            (
+                prop::collection::vec(0u32..8, 0..40),
+                any::<u32>(),
+                prop::collection::vec(any::<u32>(), 0..300),
+                prop::collection::vec(0u32..8, 0..300),
+                prop::collection::vec(0u32..32, 0..300),
+            ),
+            (
+                prop::collection::vec(any::<u8>(), 0..32),
+                prop::collection::vec(any::<u8>(), 0..32),
+                any::<[u8; 10]>(),
+            ),
+        )
+            .prop_map(
+                |(
+                    (mtime, msip, mtimecmp),
+                    (priority, pending, enable, threshold, active),
+                    (rx, tx, uart_regs),
+                )| DeviceState {
+                    mtime,
+                    msip,
+                    mtimecmp,
+                    priority,
+                    pending,
+                    enable,
+                    threshold,
+                    active,
+                    rx,
+                    tx,
+                    uart_regs,
+                },
+            )
+    }
+
+    fn machine_state() -> impl Strategy<Value = MachineState> {
+        (
+            cpu_state(),
+            device_state(),
+            prop::collection::vec(
+                (
+                    0x1000u64..DRAM_SIZE as u64 - 64,
+                    prop::collection::vec(any::<u8>(), 1..64),
+                ),
+                0..16,
+            ),
+        )
+            .prop_map(|(cpu, devices, mem_writes)| MachineState {
+                cpu,
+                devices,
+                mem_writes,
+            })
+    }
+
+    /// Drive a fresh emulator into `state` through its public API.
+    fn build(state: &MachineState) -> Emulator {
+        let mut emu = Emulator::with_memory(DRAM_SIZE);
+
+        let cpu = &state.cpu;
+        for (i, insn) in cpu.program.iter().enumerate() {
+            emu.bus
+                .dram
+                .write_bytes(i as u64 * 4, &insn.to_le_bytes())
+                .unwrap();
+        }
+        for (offset, data) in &state.mem_writes {
+            emu.bus.dram.write_bytes(*offset, data).unwrap();
+        }
+        emu.cpu.pc = DRAM_BASE + cpu.pc_slot as u64 * 4;
+        emu.cpu.mode = cpu.mode;
+        emu.cpu.regs = cpu.regs;
+        emu.cpu.fregs = cpu.fregs;
+        emu.cpu.restore_vector_regs(cpu.vlen, &cpu.vregs).unwrap();
+        emu.cpu.import_csrs(&cpu.csrs);
+
+        let dev = &state.devices;
+        emu.bus.clint.set_mtime(dev.mtime);
+        emu.bus.clint.set_msip_array(&dev.msip);
+        emu.bus.clint.set_mtimecmp_array(&dev.mtimecmp);
+        emu.bus.plic.set_priority(&dev.priority);
+        emu.bus.plic.set_pending(dev.pending);
+        emu.bus.plic.set_enable(&dev.enable);
+        emu.bus.plic.set_threshold(&dev.threshold);
+        emu.bus.plic.set_active(&dev.active);
+        emu.bus.uart.set_input(&dev.rx);
+        emu.bus.uart.set_output(&dev.tx);
+        let [ier, iir, fcr, lcr, mcr, lsr, msr, scr, dll, dlm] = dev.uart_regs;
+        emu.bus
+            .uart
+            .set_registers(ier, iir, fcr, lcr, mcr, lsr, msr, scr, dll, dlm);
+        emu
+    }
+
+    /// An emulator whose caches hold stale state for the generated program's
+    /// addresses, to check restore doesn't reuse it.
+    fn dirty_emulator() -> Emulator {
+        let mut emu = Emulator::with_memory(DRAM_SIZE);
+        for i in 0..48u64 {
+            // addi x1, x1, 1
+            emu.bus
+                .dram
+                .write_bytes(i * 4, &0x0010_8093u32.to_le_bytes())
+                .unwrap();
+        }
+        emu.bus
+            .dram
+            .write_bytes(48 * 4, &jal_x0(-4 * 48).to_le_bytes())
+            .unwrap();
+        for _ in 0..200 {
+            let _ = emu.step();
+        }
+        emu
+    }
+
+    fn run(emu: &mut Emulator) {
+        for _ in 0..STEPS {
+            // Traps are part of the behaviour being compared
+            let _ = emu.step();
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn roundtrip_is_bit_exact(state in machine_state()) {
+            let snap = build(&state).snapshot();
+            let decoded = Snapshot::from_bytes(&snap.to_bytes().unwrap()).unwrap();
+            prop_assert_eq!(&decoded, &snap);
+
+            let restored = Emulator::from_snapshot(decoded).unwrap();
+            prop_assert_eq!(restored.snapshot(), snap);
+        }
+
+        #[test]
+        fn restored_machine_keeps_running(state in machine_state()) {
+            let mut original = build(&state);
+            let snap = original.snapshot();
+
+            let mut restored = Emulator::from_snapshot(snap.clone()).unwrap();
+            let mut reused = dirty_emulator();
+            reused.apply_snapshot(&snap).unwrap();
+
+            run(&mut original);
+            run(&mut restored);
+            run(&mut reused);
+            let expected = original.snapshot();
+            prop_assert_eq!(restored.snapshot(), expected.clone(), "fresh restore diverged");
+            prop_assert_eq!(reused.snapshot(), expected, "restore over a used emulator diverged");
+        }
+
+        #[test]
+        fn v3_snapshots_migrate(state in machine_state()) {
+            let snap = build(&state).snapshot();
+            let old = SnapshotV3 {
+                version: SNAPSHOT_VERSION_V3.to_string(),
+                cpu: CpuSnapshotV3 {
+                    pc: snap.cpu.pc,
+                    mode: snap.cpu.mode,
+                    regs: snap.cpu.regs,
+                    fregs: snap.cpu.fregs,
+                    csrs: snap.cpu.csrs.clone(),
+                },
+                devices: snap.devices.clone(),
+                memory: snap.memory.clone(),
+            };
+            let mut expected = snap;
+            expected.cpu.vlen = DEFAULT_VLEN;
+            expected.cpu.vregs = vec![0; 32 * DEFAULT_VLEN / 8];
+            check_migration(&bincode::serialize(&old).unwrap(), expected)?;
+        }
+
+        #[test]
+        fn v2_snapshots_migrate(state in machine_state()) {
+            let snap = build(&state).snapshot();
+            let old = SnapshotV2 {
+                version: SNAPSHOT_VERSION_V2.to_string(),
+                cpu: CpuSnapshotV2 {
+                    pc: snap.cpu.pc,
+                    mode: snap.cpu.mode,
+                    regs: snap.cpu.regs,
+                    csrs: snap.cpu.csrs.clone(),
+                },
+                devices: snap.devices.clone(),
+                memory: snap.memory.clone(),
+            };
+            let mut expected = snap;
+            expected.cpu.fregs = [0; 32];
+            expected.cpu.vlen = DEFAULT_VLEN;
+            expected.cpu.vregs = vec![0; 32 * DEFAULT_VLEN / 8];
+            check_migration(&bincode::serialize(&old).unwrap(), expected)?;
+        }
+    }
+
+    /// Decode `old`, an older layout, and check it becomes `expected` and
+    /// runs on as `expected` does.
+    fn check_migration(old: &[u8], expected: Snapshot) -> Result<(), TestCaseError> {
+        let migrated = Snapshot::from_bytes(old).unwrap();
+        prop_assert_eq!(&migrated, &expected);
+
+        let mut restored = Emulator::from_snapshot(migrated).unwrap();
+        prop_assert_eq!(restored.snapshot(), expected.clone());
+        let mut fresh = Emulator::from_snapshot(expected).unwrap();
+        run(&mut restored);
+        run(&mut fresh);
+        prop_assert_eq!(restored.snapshot(), fresh.snapshot());
+        Ok(())
+    }
+
+    /// A 2.0 snapshot written by the emulator before F/D support: 4 KiB of
+    /// DRAM looping on `addi x1, x1, 1` from `DRAM_BASE`, stopped after two
+    /// turns with x1 = 8, x5 = 0x1234, mscratch = 0xfeed, "baseline" at
+    /// DRAM offset 0x800 and "hi" waiting in the UART.
+    const V2_FIXTURE: &[u8] = include_bytes!("../tests/fixtures/snapshot-v2.bin");
+
+    #[test]
+    fn test_v2_fixture_migrates_and_round_trips() {
+        let snap = Snapshot::from_bytes(V2_FIXTURE).unwrap();
+        assert_eq!(snap.version, SNAPSHOT_VERSION);
+        assert_eq!(snap.cpu.pc, DRAM_BASE);
+        assert_eq!(snap.cpu.mode, Mode::Machine);
+        assert_eq!(snap.cpu.regs[1], 8);
+        assert_eq!(snap.cpu.regs[5], 0x1234);
+        assert_eq!(snap.cpu.fregs, [0; 32]);
+        assert_eq!(snap.cpu.vlen, DEFAULT_VLEN);
+        assert!(snap.cpu.vregs.iter().all(|&b| b == 0));
+        assert_eq!(snap.cpu.csrs.get(&CSR_MSCRATCH), Some(&0xfeed));
+        assert_eq!(snap.devices.uart.rx_fifo, b"hi");
+        let dram = snap.memory[0].data.as_ref().unwrap();
+        assert_eq!(dram.len(), 4096);
+        assert_eq!(&dram[0x800..0x808], b"baseline");
+
+        let decoded = Snapshot::from_bytes(&snap.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, snap);
+
+        let mut emu = Emulator::with_memory(4096);
+        emu.apply_snapshot(&snap).unwrap();
+        let restored = emu.snapshot();
+        // The UART derives its modem status from MCR on restore
+        let mut expected = snap;
+        expected.devices.uart.msr = restored.devices.uart.msr;
+        assert_eq!(restored, expected);
+        emu.step().unwrap();
+        assert_eq!(emu.cpu.regs[1], 9);
+    }
+
+    /// Collects the chunks it is given, as the page does.
+    #[derive(Default)]
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl SnapshotWriter for Chunks {
+        fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), String> {
+            self.0.push(chunk.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        // Several DRAM frames, the last one short
+        let size = 2 * STREAM_CHUNK_SIZE + 4096;
+        let mut emu = Emulator::with_memory(size);
+        emu.bus.dram.write_bytes(16, b"start").unwrap();
+        emu.bus
+            .dram
+            .write_bytes(size as u64 - 8, &0xdead_beefu64.to_le_bytes())
+            .unwrap();
+        emu.cpu.regs[5] = 42;
+        emu.bus.uart.set_input(b"typed");
+        let snap = emu.snapshot();
+
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let mut chunks = Chunks::default();
+            emu.write_snapshot_stream(&mut chunks, compression).unwrap();
+            // Preamble, header, three of DRAM, hash
+            assert_eq!(chunks.0.len(), 6, "{}", compression);
+            assert!(chunks.0.iter().all(|c| c.len() <= STREAM_CHUNK_SIZE + 8));
+            let bytes = chunks.0.concat();
+            if compression != Compression::None {
+                // Mostly zeroes
+                assert!(bytes.len() < size / 8, "{}", compression);
+            }
+            assert_eq!(
+                Snapshot::from_bytes(&bytes).unwrap(),
+                snap,
+                "{}",
+                compression
+            );
+
+            let mut other = Vec::new();
+            snap.to_stream(&mut other, compression).unwrap();
+            assert_eq!(Snapshot::from_bytes(&other).unwrap(), snap);
+
+            let mut restored = Emulator::with_memory(size);
+            restored.restore_snapshot_stream(&mut &bytes[..]).unwrap();
+            assert_eq!(restored.snapshot(), snap, "{}", compression);
+        }
+    }
+
+    #[test]
+    fn test_stream_rejects_corruption() {
+        let emu = Emulator::with_memory(DRAM_SIZE);
+        let mut bytes = Vec::new();
+        emu.write_snapshot_stream(&mut bytes, Compression::Lz4)
+            .unwrap();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // Flip a bit of the stored hash
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let err = Snapshot::from_bytes(&bytes).unwrap_err();
+        assert!(err.contains("hash mismatch"), "{}", err);
+
+        let mut smaller = Emulator::with_memory(DRAM_SIZE / 2);
+        let err = smaller
+            .restore_snapshot_stream(&mut &bytes[..])
+            .unwrap_err();
+        assert!(err.contains("size mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_truncated_snapshots() {
+        let mut snap = Emulator::with_memory(DRAM_SIZE).snapshot();
+        let bytes = snap.to_bytes().unwrap();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+        assert!(Snapshot::from_bytes(&[]).is_err());
+
+        snap.version = "9.9".to_string();
+        let err = Snapshot::from_bytes(&snap.to_bytes().unwrap()).unwrap_err();
+        assert!(err.contains("unsupported snapshot version"));
+    }
+}
@@ -0,0 +1,554 @@
+//! Headless, scripted end-to-end tests.
+//!
+//! [`TestVm`] boots a kernel image on a single hart with no terminal,
+//! threads or host timing involved: console output is collected into a
+//! buffer, input is queued on the UART, disk requests complete on the next
+//! poll, idle time is skipped rather than slept and the RTC counts guest
+//! time from a fixed date. A run therefore only
+//! depends on the image and the script, which makes it usable from
+//! `cargo test` and CI.
+//!
+//! A [`Script`] is a list of steps in the style of `expect(1)`: send some
+//! input, wait until the console output matches a regular expression, let
+//! time pass, or wait for the guest to shut down. Timeouts are in guest
+//! cycles (see [`crate::engine::timing`]), so they mean the same thing on
+//! every host.
+//!
+//! ```ignore
+//! let mut vm = TestVm::boot(&kernel)?;
+//! // The image's /etc/provision.json skips the first-boot wizard
+//! vm.attach_disk(Box::new(MemoryDisk::new(std::fs::read("fs.img")?)));
+//! let report = vm.run_script(
+//!     &Script::new()
+//!         .expect("BOOT COMPLETE", 500_000_000)
+//!         .send_line("uname -sm")
+//!         .expect("BAVY riscv64", 100_000_000)
+//!         .send_line("shutdown")
+//!         .expect_shutdown(0x5555, 100_000_000),
+//! );
+//! assert!(report.passed(), "{}", report);
+//! ```
+
+use crate::Trap;
+use crate::bus::{BusConfig, DRAM_BASE, SystemBus};
+use crate::cpu::Cpu;
+use crate::cpu::idle::MAX_IDLE_TICKS;
+use crate::devices::bootrom::BootConfig;
+use crate::devices::rtc::DETERMINISTIC_EPOCH_NS;
+use crate::disk::BlockBackend;
+use crate::loader::load_elf_into_dram;
+use crate::vm::trap_info::TrapInfo;
+use regex::bytes::Regex;
+use std::fmt;
+
+/// DRAM given to a [`TestVm`] booted with [`TestVm::boot`].
+pub const DEFAULT_DRAM_SIZE: usize = 128 * 1024 * 1024;
+
+/// Halt code the test finisher is written with on a clean shutdown.
+pub const SHUTDOWN_PASS: u64 = 0x5555;
+
+/// Instructions between polls of the VirtIO devices.
+const POLL_INTERVAL: u64 = 4096;
+
+/// Lines of console output shown with a failed report.
+const OUTPUT_TAIL_LINES: usize = 20;
+
+/// How the guest stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Halt {
+    /// The guest wrote this code to the test finisher or asked the SBI to
+    /// shut down.
+    Shutdown(u64),
+    /// The hart stopped on a fatal error.
+    Fatal(TrapInfo),
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Halt::Shutdown(code) => write!(f, "shut down with code {:#x}", code),
+            Halt::Fatal(info) => write!(f, "fatal error: {}", info),
+        }
+    }
+}
+
+/// Console output matched by an expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The text of the whole match.
+    pub text: String,
+    /// Capture groups 1 and up, `None` for groups that did not take part.
+    pub groups: Vec<Option<String>>,
+    /// Guest cycle count when the match was seen.
+    pub cycle: u64,
+}
+
+/// One step of a [`Script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Queue bytes on the UART.
+    Send(Vec<u8>),
+    /// Wait for console output matching a regular expression.
+    Expect { pattern: String, timeout: u64 },
+    /// Let this many guest cycles pass.
+    Run(u64),
+    /// Wait for the guest to shut down with this code.
+    ExpectShutdown { code: u64, timeout: u64 },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Send(bytes) => write!(f, "send {:?}", String::from_utf8_lossy(bytes)),
+            Step::Expect { pattern, .. } => write!(f, "expect /{}/", pattern),
+            Step::Run(cycles) => write!(f, "run {} cycles", cycles),
+            Step::ExpectShutdown { code, .. } => write!(f, "expect shutdown {:#x}", code),
+        }
+    }
+}
+
+/// A sequence of steps, built up with chained calls.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `input` on the UART.
+    pub fn send(mut self, input: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Send(input.as_ref().to_vec()));
+        self
+    }
+
+    /// Queue `line` followed by a carriage return, as typed at a terminal.
+    pub fn send_line(mut self, line: &str) -> Self {
+        let mut input = line.as_bytes().to_vec();
+        input.push(b'\r');
+        self.steps.push(Step::Send(input));
+        self
+    }
+
+    /// Wait up to `timeout` guest cycles for output matching `pattern`.
+    pub fn expect(mut self, pattern: &str, timeout: u64) -> Self {
+        self.steps.push(Step::Expect {
+            pattern: pattern.to_string(),
+            timeout,
+        });
+        self
+    }
+
+    /// Let `cycles` guest cycles pass.
+    pub fn run(mut self, cycles: u64) -> Self {
+        self.steps.push(Step::Run(cycles));
+        self
+    }
+
+    /// Wait up to `timeout` guest cycles for a shutdown with `code`.
+    pub fn expect_shutdown(mut self, code: u64, timeout: u64) -> Self {
+        self.steps.push(Step::ExpectShutdown { code, timeout });
+        self
+    }
+}
+
+/// How a step ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Input was queued, the time passed, or the guest shut down as
+    /// expected.
+    Done,
+    /// The expected output appeared.
+    Matched(Match),
+    /// The timeout ran out first.
+    Timeout,
+    /// The guest stopped first, or with a different code than expected.
+    Halted(Halt),
+    /// The pattern is not a valid regular expression.
+    InvalidPattern(String),
+}
+
+impl Outcome {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Outcome::Done | Outcome::Matched(_))
+    }
+}
+
+/// Result of one step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepResult {
+    pub step: Step,
+    pub outcome: Outcome,
+    /// Guest cycle count when the step ended.
+    pub cycle: u64,
+}
+
+/// Results of a script run. Steps after the first failure are not run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub results: Vec<StepResult>,
+    /// Steps in the script.
+    pub steps: usize,
+    /// Everything the guest printed, from boot on.
+    pub output: String,
+    /// How the guest stopped, if it did.
+    pub halt: Option<Halt>,
+}
+
+impl Report {
+    /// Whether every step ran and succeeded.
+    pub fn passed(&self) -> bool {
+        self.results.len() == self.steps && self.results.iter().all(|r| r.outcome.is_ok())
+    }
+
+    /// Matches of the `Expect` steps, in order.
+    pub fn matches(&self) -> impl Iterator<Item = &Match> {
+        self.results.iter().filter_map(|r| match &r.outcome {
+            Outcome::Matched(m) => Some(m),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            match &r.outcome {
+                Outcome::Done => writeln!(f, "ok    {}", r.step)?,
+                Outcome::Matched(m) => {
+                    writeln!(f, "ok    {} ({:?} at cycle {})", r.step, m.text, m.cycle)?
+                }
+                Outcome::Timeout => {
+                    writeln!(f, "FAIL  {} (timed out at cycle {})", r.step, r.cycle)?
+                }
+                Outcome::Halted(halt) => writeln!(f, "FAIL  {} ({})", r.step, halt)?,
+                Outcome::InvalidPattern(e) => writeln!(f, "ERROR {} ({})", r.step, e)?,
+            }
+        }
+        if self.passed() {
+            return write!(f, "{} steps passed", self.steps);
+        }
+        writeln!(
+            f,
+            "{} of {} steps passed",
+            self.results.len() - 1,
+            self.steps
+        )?;
+        let lines: Vec<&str> = self.output.lines().collect();
+        let tail = &lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..];
+        write!(f, "--- console output (last {} lines) ---", tail.len())?;
+        for line in tail {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why [`TestVm::advance`] returned.
+enum Stop {
+    Done,
+    Deadline,
+    Halted,
+}
+
+/// A single-hart VM driven by scripted console input.
+pub struct TestVm {
+    cpu: Cpu,
+    bus: SystemBus,
+    output: Vec<u8>,
+    /// Start of the output not yet consumed by an expectation.
+    cursor: usize,
+    halt: Option<Halt>,
+    steps: u64,
+}
+
+impl TestVm {
+    /// Boot `kernel` (ELF, or a raw image entered at the DRAM base) with
+    /// [`DEFAULT_DRAM_SIZE`] of DRAM at the usual base.
+    pub fn boot(kernel: &[u8]) -> Result<Self, String> {
+        Self::with_config(kernel, BusConfig::with_dram(DRAM_BASE, DEFAULT_DRAM_SIZE))
+    }
+
+    /// Boot `kernel` with a custom memory map.
+    pub fn with_config(kernel: &[u8], config: BusConfig) -> Result<Self, String> {
+        let bus = SystemBus::with_config(config);
+        bus.rtc.set_deterministic(Some(DETERMINISTIC_EPOCH_NS));
+        let entry = if kernel.starts_with(b"\x7FELF") {
+            load_elf_into_dram(kernel, &bus)?
+        } else {
+            bus.dram
+                .load(kernel, 0)
+                .map_err(|e| format!("Failed to load kernel: {:?}", e))?;
+            bus.dram_base()
+        };
+        let stack_top = bus.dram_base() + bus.dram_size() as u64;
+        bus.boot_rom.configure(&BootConfig::new(entry, stack_top));
+        let cpu = Cpu::new(bus.boot_rom.reset_vector(), 0);
+        Ok(Self {
+            cpu,
+            bus,
+            output: Vec::new(),
+            cursor: 0,
+            halt: None,
+            steps: 0,
+        })
+    }
+
+    /// Attach a VirtIO block device served by `backend`, e.g. a
+    /// [`crate::disk::MemoryDisk`] so the image file is left untouched.
+    pub fn attach_disk(&mut self, backend: Box<dyn BlockBackend>) {
+        use crate::devices::virtio::VirtioBlock;
+
+        self.bus
+            .virtio_devices
+            .push(Box::new(VirtioBlock::with_backend(backend)));
+        self.bus.set_synchronous_io(true);
+    }
+
+    /// The hart, e.g. to inspect registers after a run.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// The bus, e.g. to inspect memory after a run.
+    pub fn bus(&self) -> &SystemBus {
+        &self.bus
+    }
+
+    /// Everything the guest has printed so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Guest cycles executed (or idled) since boot.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles
+    }
+
+    /// How the guest stopped, if it did.
+    pub fn halt(&self) -> Option<&Halt> {
+        self.halt.as_ref()
+    }
+
+    /// Queue `input` on the UART.
+    pub fn send(&mut self, input: impl AsRef<[u8]>) {
+        self.bus.uart.push_input_bytes(input.as_ref());
+    }
+
+    /// Run until output after the last match matches `pattern`, for at most
+    /// `timeout` guest cycles. Output up to the end of the match is
+    /// consumed, so the next expectation only sees what follows it.
+    /// Output is matched as it arrives, so end a pattern with whatever
+    /// follows a number or word it captures.
+    pub fn expect(&mut self, pattern: &str, timeout: u64) -> Outcome {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => return Outcome::InvalidPattern(e.to_string()),
+        };
+        let deadline = self.cycles().saturating_add(timeout);
+        let stop = self.advance(deadline, |output| regex.is_match(output));
+        let Some(caps) = regex.captures(&self.output[self.cursor..]) else {
+            return match stop {
+                Stop::Halted => Outcome::Halted(self.halt.clone().unwrap()),
+                _ => Outcome::Timeout,
+            };
+        };
+        let text = |m: regex::bytes::Match| String::from_utf8_lossy(m.as_bytes()).into_owned();
+        let found = Match {
+            text: text(caps.get(0).unwrap()),
+            groups: caps.iter().skip(1).map(|m| m.map(text)).collect(),
+            cycle: self.cycles(),
+        };
+        self.cursor += caps.get(0).unwrap().end();
+        Outcome::Matched(found)
+    }
+
+    /// Let `cycles` guest cycles pass.
+    pub fn run_for(&mut self, cycles: u64) -> Outcome {
+        let deadline = self.cycles().saturating_add(cycles);
+        match self.advance(deadline, |_| false) {
+            Stop::Halted => Outcome::Halted(self.halt.clone().unwrap()),
+            _ => Outcome::Done,
+        }
+    }
+
+    /// Run for at most `timeout` guest cycles, until the guest shuts down
+    /// with `code`.
+    pub fn expect_shutdown(&mut self, code: u64, timeout: u64) -> Outcome {
+        let deadline = self.cycles().saturating_add(timeout);
+        match self.advance(deadline, |_| false) {
+            Stop::Halted if self.halt == Some(Halt::Shutdown(code)) => Outcome::Done,
+            Stop::Halted => Outcome::Halted(self.halt.clone().unwrap()),
+            _ => Outcome::Timeout,
+        }
+    }
+
+    /// Run every step of `script`, stopping at the first that fails.
+    pub fn run_script(&mut self, script: &Script) -> Report {
+        let mut results = Vec::new();
+        for step in &script.steps {
+            let outcome = match step {
+                Step::Send(input) => {
+                    self.send(input);
+                    Outcome::Done
+                }
+                Step::Expect { pattern, timeout } => self.expect(pattern, *timeout),
+                Step::Run(cycles) => self.run_for(*cycles),
+                Step::ExpectShutdown { code, timeout } => self.expect_shutdown(*code, *timeout),
+            };
+            let failed = !outcome.is_ok();
+            results.push(StepResult {
+                step: step.clone(),
+                outcome,
+                cycle: self.cycles(),
+            });
+            if failed {
+                break;
+            }
+        }
+        Report {
+            results,
+            steps: script.steps.len(),
+            output: String::from_utf8_lossy(&self.output).into_owned(),
+            halt: self.halt.clone(),
+        }
+    }
+
+    /// Step the hart until `done` accepts the unconsumed output, the cycle
+    /// count reaches `deadline`, or the guest halts.
+    fn advance(&mut self, deadline: u64, mut done: impl FnMut(&[u8]) -> bool) -> Stop {
+        if done(&self.output[self.cursor..]) {
+            return Stop::Done;
+        }
+        while self.halt.is_none() && self.cpu.cycles < deadline {
+            match self.cpu.step(&self.bus) {
+                Ok(()) => {}
+                Err(Trap::RequestedTrap(code)) => self.halt = Some(Halt::Shutdown(code)),
+                Err(trap @ Trap::Fatal(_)) => {
+                    let info = TrapInfo::capture(&trap, &self.cpu, &self.bus);
+                    self.halt = Some(Halt::Fatal(info));
+                }
+                // Architectural traps are handled by the guest
+                Err(_) => {}
+            }
+            self.steps += 1;
+
+            // Skip to the next timer deadline instead of spinning through it
+            let idled = match self.cpu.take_idle(&self.bus) {
+                Some(ticks) => {
+                    self.cpu.skip_idle(&self.bus, ticks.min(MAX_IDLE_TICKS));
+                    true
+                }
+                None => false,
+            };
+            if idled || self.steps.is_multiple_of(POLL_INTERVAL) {
+                self.bus.poll_virtio();
+            }
+
+            let printed = self.bus.uart.drain_output();
+            if !printed.is_empty() {
+                self.output.extend_from_slice(&printed);
+                if done(&self.output[self.cursor..]) {
+                    return Stop::Done;
+                }
+            }
+        }
+        if self.halt.is_some() {
+            Stop::Halted
+        } else {
+            Stop::Deadline
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Prints "ok", then echoes input until a 'q', which shuts it down.
+    const ECHO: [u32; 19] = [
+        0x1000_02b7, // lui t0, 0x10000 (UART)
+        0x06f0_0313, // li t1, 'o'
+        0x0062_8023, // sb t1, 0(t0)
+        0x06b0_0313, // li t1, 'k'
+        0x0062_8023, // sb t1, 0(t0)
+        0x00a0_0313, // li t1, '\n'
+        0x0062_8023, // sb t1, 0(t0)
+        0x0052_c303, // loop: lbu t1, 5(t0)
+        0x0013_7313, // andi t1, t1, 1
+        0xfe03_0ce3, // beqz t1, loop
+        0x0002_c303, // lbu t1, 0(t0)
+        0x0062_8023, // sb t1, 0(t0)
+        0x0710_0393, // li t2, 'q'
+        0xfe73_14e3, // bne t1, t2, loop
+        0x0010_03b7, // lui t2, 0x100 (test finisher)
+        0x0000_5e37, // lui t3, 5
+        0x555e_0e13, // addi t3, t3, 0x555
+        0x01c3_a023, // sw t3, 0(t2)
+        0x0000_006f, // j .
+    ];
+
+    fn echo_vm() -> TestVm {
+        let image: Vec<u8> = ECHO.iter().flat_map(|insn| insn.to_le_bytes()).collect();
+        TestVm::with_config(&image, BusConfig::with_dram(DRAM_BASE, 1024 * 1024)).unwrap()
+    }
+
+    #[test]
+    fn test_script_passes() {
+        let mut vm = echo_vm();
+        let report = vm.run_script(
+            &Script::new()
+                .expect("^ok\n", 10_000)
+                .send("hello 42 q")
+                .expect(r"hello (\d+) ", 100_000)
+                .expect_shutdown(SHUTDOWN_PASS, 100_000),
+        );
+        assert!(report.passed(), "{}", report);
+        let groups: Vec<_> = report.matches().map(|m| m.groups.clone()).collect();
+        assert_eq!(groups, vec![vec![], vec![Some("42".to_string())]]);
+        assert_eq!(report.output, "ok\nhello 42 q");
+        assert_eq!(report.halt, Some(Halt::Shutdown(SHUTDOWN_PASS)));
+    }
+
+    #[test]
+    fn test_matched_output_is_consumed() {
+        let mut vm = echo_vm();
+        assert!(vm.expect("ok", 10_000).is_ok());
+        assert_eq!(vm.expect("ok", 10_000), Outcome::Timeout);
+    }
+
+    #[test]
+    fn test_timeout_is_in_guest_cycles() {
+        let mut vm = echo_vm();
+        let start = vm.cycles();
+        assert_eq!(vm.expect("never", 50_000), Outcome::Timeout);
+        assert!(vm.cycles() >= start + 50_000);
+        assert!(vm.cycles() < start + 50_100);
+    }
+
+    #[test]
+    fn test_report_stops_at_first_failure() {
+        let mut vm = echo_vm();
+        let report = vm.run_script(&Script::new().expect("(", 10).send("never sent"));
+        assert!(!report.passed());
+        assert_eq!(report.results.len(), 1);
+        assert!(matches!(
+            report.results[0].outcome,
+            Outcome::InvalidPattern(_)
+        ));
+    }
+
+    #[test]
+    fn test_unexpected_shutdown_fails_expectation() {
+        let mut vm = echo_vm();
+        vm.send("q");
+        let outcome = vm.expect("never", 100_000);
+        assert_eq!(outcome, Outcome::Halted(Halt::Shutdown(SHUTDOWN_PASS)));
+        assert_eq!(
+            vm.expect_shutdown(0x3333, 10),
+            Outcome::Halted(Halt::Shutdown(SHUTDOWN_PASS))
+        );
+    }
+}
@@ -0,0 +1,607 @@
+//! User-mode emulation (syscall-level, like qemu-user).
+//!
+//! Runs a statically linked RISC-V Linux binary directly, without a guest
+//! kernel. The binary executes in U-mode with translation off, on a bus whose
+//! DRAM starts at address 0 so the usual link addresses (`0x10000`) work
+//! unchanged. `ecall` is intercepted through a [`TrapHook`] and serviced on
+//! the host; other exceptions end the run as a fault.
+//!
+//! Supported system calls: `read`, `write`, `writev`, `openat`, `close`,
+//! `brk`, `exit` and `exit_group`. Anything else returns `-ENOSYS`.
+
+use crate::Trap;
+use crate::backtrace::{self, Backtrace, SymbolMap};
+use crate::bus::{Bus, SystemBus};
+use crate::cpu::{Cpu, Mode, TrapHook};
+use crate::csr::CSR_MSTATUS;
+use crate::loader::load_elf_into_dram;
+use goblin::elf::Elf;
+use goblin::elf::header::EM_RISCV;
+use goblin::elf::program_header::PT_LOAD;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+
+/// Guest address space size. DRAM covers `[0, USER_MEM_SIZE)`, just below
+/// the first MMIO device.
+pub const USER_MEM_SIZE: usize = 256 * 1024 * 1024;
+
+/// Initial stack pointer (stack grows down from the top of memory).
+const STACK_TOP: u64 = USER_MEM_SIZE as u64;
+
+/// Space reserved for the stack; `brk` may not grow into it.
+const STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Longest path accepted by `openat`.
+const PATH_MAX: usize = 4096;
+
+// Linux RISC-V system call numbers
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_WRITEV: u64 = 66;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_BRK: u64 = 214;
+
+// Linux errno values
+const ENOENT: i64 = 2;
+const EIO: i64 = 5;
+const EBADF: i64 = 9;
+const EACCES: i64 = 13;
+const EFAULT: i64 = 14;
+const EEXIST: i64 = 17;
+const EINVAL: i64 = 22;
+const ENOSYS: i64 = 38;
+
+// openat flags
+const AT_FDCWD: i64 = -100;
+const O_ACCMODE: u64 = 0o3;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+// Auxiliary vector keys
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+/// How a user-mode run ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserExit {
+    /// The program called `exit` or `exit_group`.
+    Exited(i32),
+    /// An exception other than `ecall` was raised at `pc`.
+    Fault { trap: Trap, pc: u64 },
+    /// The step budget ran out.
+    StepLimit,
+}
+
+impl fmt::Display for UserExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserExit::Exited(code) => write!(f, "exited with status {}", code),
+            UserExit::Fault { trap, pc } => write!(f, "fault at 0x{:x}: {}", pc, trap),
+            UserExit::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+/// An open guest file descriptor.
+enum FileDesc {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// Host-side system call state; serves intercepted `ecall`s.
+struct Syscalls {
+    fds: Vec<Option<FileDesc>>,
+    brk_start: u64,
+    brk: u64,
+    exit_code: Option<i32>,
+    /// When set, stdout/stderr writes are collected here instead.
+    captured: Option<Vec<u8>>,
+}
+
+/// Map a host I/O error to a negated Linux errno.
+fn errno(e: &io::Error) -> i64 {
+    -match e.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+/// Copy `len` bytes of guest memory starting at `addr`.
+fn read_guest(bus: &SystemBus, addr: u64, len: u64) -> Result<Vec<u8>, i64> {
+    match addr.checked_add(len) {
+        Some(end) if end <= USER_MEM_SIZE as u64 => bus
+            .dram
+            .read_range(addr as usize, len as usize)
+            .map_err(|_| -EFAULT),
+        _ => Err(-EFAULT),
+    }
+}
+
+/// Copy `data` into guest memory at `addr`.
+fn write_guest(bus: &SystemBus, addr: u64, data: &[u8]) -> Result<(), i64> {
+    match addr.checked_add(data.len() as u64) {
+        Some(end) if end <= USER_MEM_SIZE as u64 => {
+            bus.dram.write_bytes(addr, data).map_err(|_| -EFAULT)
+        }
+        _ => Err(-EFAULT),
+    }
+}
+
+/// Read a NUL-terminated string from guest memory.
+fn read_guest_str(bus: &SystemBus, addr: u64) -> Result<String, i64> {
+    let mut bytes = Vec::new();
+    for i in 0..PATH_MAX as u64 {
+        let b = bus.read8(addr.wrapping_add(i)).map_err(|_| -EFAULT)?;
+        if b == 0 {
+            return String::from_utf8(bytes).map_err(|_| -EINVAL);
+        }
+        bytes.push(b);
+    }
+    Err(-EINVAL)
+}
+
+impl Syscalls {
+    fn new(brk_start: u64) -> Self {
+        Self {
+            fds: vec![
+                Some(FileDesc::Stdin),
+                Some(FileDesc::Stdout),
+                Some(FileDesc::Stderr),
+            ],
+            brk_start,
+            brk: brk_start,
+            exit_code: None,
+            captured: None,
+        }
+    }
+
+    fn fd(&mut self, fd: u64) -> Result<&mut FileDesc, i64> {
+        self.fds
+            .get_mut(fd as usize)
+            .and_then(|f| f.as_mut())
+            .ok_or(-EBADF)
+    }
+
+    /// Dispatch system call `nr`; returns the value for `a0`.
+    fn dispatch(&mut self, bus: &SystemBus, nr: u64, args: [u64; 6]) -> i64 {
+        let result = match nr {
+            SYS_READ => self.read(bus, args[0], args[1], args[2]),
+            SYS_WRITE => self.write(bus, args[0], args[1], args[2]),
+            SYS_WRITEV => self.writev(bus, args[0], args[1], args[2]),
+            SYS_OPENAT => self.openat(bus, args[0] as i64, args[1], args[2]),
+            SYS_CLOSE => self.close(args[0]),
+            SYS_BRK => Ok(self.set_brk(bus, args[0]) as i64),
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                self.exit_code = Some(args[0] as i32);
+                Ok(0)
+            }
+            _ => {
+                log::debug!("usermode: unimplemented syscall {}", nr);
+                Err(-ENOSYS)
+            }
+        };
+        result.unwrap_or_else(|e| e)
+    }
+
+    fn read(&mut self, bus: &SystemBus, fd: u64, buf: u64, count: u64) -> Result<i64, i64> {
+        let mut data = vec![0u8; count.min(USER_MEM_SIZE as u64) as usize];
+        let n = match self.fd(fd)? {
+            FileDesc::Stdin => io::stdin().read(&mut data),
+            FileDesc::File(file) => file.read(&mut data),
+            FileDesc::Stdout | FileDesc::Stderr => return Err(-EBADF),
+        }
+        .map_err(|e| errno(&e))?;
+        write_guest(bus, buf, &data[..n])?;
+        Ok(n as i64)
+    }
+
+    fn write_bytes(&mut self, fd: u64, data: &[u8]) -> Result<i64, i64> {
+        let captured = self.captured.is_some();
+        match self.fd(fd)? {
+            FileDesc::Stdout | FileDesc::Stderr if captured => {
+                if let Some(out) = self.captured.as_mut() {
+                    out.extend_from_slice(data);
+                }
+                Ok(())
+            }
+            FileDesc::Stdout => {
+                let mut out = io::stdout().lock();
+                out.write_all(data).and_then(|_| out.flush())
+            }
+            FileDesc::Stderr => io::stderr().write_all(data),
+            FileDesc::File(file) => file.write_all(data),
+            FileDesc::Stdin => return Err(-EBADF),
+        }
+        .map_err(|e| errno(&e))?;
+        Ok(data.len() as i64)
+    }
+
+    fn write(&mut self, bus: &SystemBus, fd: u64, buf: u64, count: u64) -> Result<i64, i64> {
+        let data = read_guest(bus, buf, count)?;
+        self.write_bytes(fd, &data)
+    }
+
+    fn writev(&mut self, bus: &SystemBus, fd: u64, iov: u64, iovcnt: u64) -> Result<i64, i64> {
+        let mut total = 0;
+        for i in 0..iovcnt.min(1024) {
+            let entry = iov.wrapping_add(i * 16);
+            let base = bus.read64(entry).map_err(|_| -EFAULT)?;
+            let len = bus.read64(entry + 8).map_err(|_| -EFAULT)?;
+            total += self.write(bus, fd, base, len)?;
+        }
+        Ok(total)
+    }
+
+    fn openat(&mut self, bus: &SystemBus, dirfd: i64, path: u64, flags: u64) -> Result<i64, i64> {
+        let path = read_guest_str(bus, path)?;
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(-EBADF);
+        }
+
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        if flags & O_APPEND != 0 {
+            options.append(true);
+        }
+        if flags & O_TRUNC != 0 {
+            options.truncate(true);
+        }
+        if flags & O_CREAT != 0 {
+            if flags & O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        let file = options.open(&path).map_err(|e| errno(&e))?;
+
+        let desc = Some(FileDesc::File(file));
+        let fd = match self.fds.iter().position(Option::is_none) {
+            Some(free) => {
+                self.fds[free] = desc;
+                free
+            }
+            None => {
+                self.fds.push(desc);
+                self.fds.len() - 1
+            }
+        };
+        Ok(fd as i64)
+    }
+
+    fn close(&mut self, fd: u64) -> Result<i64, i64> {
+        match self.fds.get_mut(fd as usize) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(0)
+            }
+            _ => Err(-EBADF),
+        }
+    }
+
+    /// Move the program break; returns the (possibly unchanged) break.
+    fn set_brk(&mut self, bus: &SystemBus, addr: u64) -> u64 {
+        if addr < self.brk_start || addr > STACK_TOP - STACK_SIZE {
+            return self.brk;
+        }
+        if addr > self.brk {
+            // Memory released by an earlier shrink must read as zero again
+            let _ = bus
+                .dram
+                .zero_range(self.brk as usize, (addr - self.brk) as usize);
+        }
+        self.brk = addr;
+        addr
+    }
+}
+
+/// The hook only ever runs against the process's own `SystemBus`; guest
+/// memory is accessed through DRAM directly for bulk copies.
+struct SyscallHook<'a> {
+    syscalls: &'a mut Syscalls,
+    bus: &'a SystemBus,
+}
+
+impl TrapHook for SyscallHook<'_> {
+    fn on_trap(&mut self, cpu: &mut Cpu, _bus: &dyn Bus, trap: Trap) -> Result<(), Trap> {
+        if trap != Trap::EnvironmentCallFromU {
+            return Err(trap);
+        }
+        let args = [
+            cpu.regs[10],
+            cpu.regs[11],
+            cpu.regs[12],
+            cpu.regs[13],
+            cpu.regs[14],
+            cpu.regs[15],
+        ];
+        let ret = self.syscalls.dispatch(self.bus, cpu.regs[17], args);
+        cpu.regs[10] = ret as u64;
+        cpu.pc = cpu.pc.wrapping_add(4);
+        Ok(())
+    }
+}
+
+/// A Linux user program loaded for syscall-level emulation.
+pub struct UserProcess {
+    pub cpu: Cpu,
+    pub bus: SystemBus,
+    syscalls: Syscalls,
+    /// Symbols of the executable, for [`backtrace`](Self::backtrace).
+    symbols: SymbolMap,
+}
+
+impl UserProcess {
+    /// Load a statically linked RISC-V ELF and build its initial stack.
+    ///
+    /// `argv[0]` is conventionally the program name.
+    pub fn load(image: &[u8], argv: &[String]) -> Result<Self, String> {
+        let elf = Elf::parse(image).map_err(|e| format!("ELF parse error: {}", e))?;
+        if elf.header.e_machine != EM_RISCV || !elf.is_64 {
+            return Err("not a 64-bit RISC-V executable".to_string());
+        }
+        if elf.interpreter.is_some() {
+            return Err("dynamically linked executables are not supported".to_string());
+        }
+
+        let bus = SystemBus::new(0, USER_MEM_SIZE);
+        let entry = load_elf_into_dram(image, &bus)?;
+
+        let segments = elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD);
+        let image_end = segments
+            .clone()
+            .map(|ph| ph.p_vaddr + ph.p_memsz)
+            .max()
+            .unwrap_or(0);
+        let brk_start = image_end.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if brk_start > STACK_TOP - STACK_SIZE {
+            return Err("executable does not fit in user memory".to_string());
+        }
+
+        // Program headers as mapped in memory (used by libc for TLS setup)
+        let phoff = elf.header.e_phoff;
+        let phdr = segments
+            .filter(|ph| ph.p_offset <= phoff && phoff < ph.p_offset + ph.p_filesz)
+            .map(|ph| ph.p_vaddr + (phoff - ph.p_offset))
+            .next()
+            .unwrap_or(0);
+        let auxv = [
+            (AT_PHDR, phdr),
+            (AT_PHENT, elf.header.e_phentsize as u64),
+            (AT_PHNUM, elf.header.e_phnum as u64),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_ENTRY, entry),
+        ];
+        let sp = build_stack(&bus, argv, &auxv)?;
+
+        let mut cpu = Cpu::new(entry, 0);
+        cpu.mode = Mode::User;
+        cpu.regs[2] = sp;
+        cpu.csrs[CSR_MSTATUS as usize] |= 1 << 13; // FS = Initial
+        // Take no exceptions architecturally; there is no kernel to handle them
+        cpu.intercept_exceptions = 0xffff;
+
+        Ok(Self {
+            cpu,
+            bus,
+            syscalls: Syscalls::new(brk_start),
+            symbols: SymbolMap::from_elf(image)?,
+        })
+    }
+
+    /// Collect stdout/stderr writes instead of forwarding them to the host.
+    pub fn capture_output(&mut self) {
+        self.syscalls.captured.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the output collected since [`capture_output`](Self::capture_output).
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.syscalls
+            .captured
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Backtrace of the program's stack, symbolized with its own symbol
+    /// table; useful after a [`UserExit::Fault`].
+    pub fn backtrace(&self) -> Backtrace {
+        backtrace::unwind(&self.cpu, &self.bus, &self.symbols)
+    }
+
+    /// Run until the program exits, faults, or `max_steps` steps have run.
+    pub fn run(&mut self, max_steps: u64) -> UserExit {
+        let mut hook = SyscallHook {
+            syscalls: &mut self.syscalls,
+            bus: &self.bus,
+        };
+        for _ in 0..max_steps {
+            if let Err(trap) = self.cpu.step_with_hook(&self.bus, &mut hook) {
+                return UserExit::Fault {
+                    trap,
+                    pc: self.cpu.pc,
+                };
+            }
+            if let Some(code) = hook.syscalls.exit_code {
+                return UserExit::Exited(code);
+            }
+        }
+        UserExit::StepLimit
+    }
+}
+
+/// Lay out argv, an empty environment and the auxiliary vector at the top
+/// of memory, following the Linux process entry ABI. Returns the initial sp.
+fn build_stack(bus: &SystemBus, argv: &[String], auxv: &[(u64, u64)]) -> Result<u64, String> {
+    let fault = |_| "initial stack does not fit in user memory".to_string();
+    let mut sp = STACK_TOP;
+
+    // AT_RANDOM: 16 bytes used by libc to seed stack protectors
+    sp -= 16;
+    let random = sp;
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    write_guest(bus, random, &seed.to_le_bytes()).map_err(fault)?;
+
+    let mut argv_ptrs = Vec::with_capacity(argv.len());
+    for arg in argv {
+        sp -= arg.len() as u64 + 1;
+        let mut bytes = arg.as_bytes().to_vec();
+        bytes.push(0);
+        write_guest(bus, sp, &bytes).map_err(fault)?;
+        argv_ptrs.push(sp);
+    }
+
+    let mut words = vec![argv.len() as u64];
+    words.extend(&argv_ptrs);
+    words.push(0); // argv terminator
+    words.push(0); // envp terminator
+    for &(key, value) in auxv {
+        words.extend([key, value]);
+    }
+    words.extend([AT_RANDOM, random, AT_NULL, 0]);
+
+    sp = (sp - words.len() as u64 * 8) & !0xf;
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    write_guest(bus, sp, &bytes).map_err(fault)?;
+    Ok(sp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u64 = 0x10000;
+
+    fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+        ((imm as u32) << 20) | (rs1 << 15) | (rd << 7) | 0x13
+    }
+
+    /// Build a minimal static ELF with one RX segment holding `code` + `data`.
+    fn tiny_elf(code: &[u32], data: &[u8]) -> Vec<u8> {
+        let entry = BASE + 64 + 56;
+        let mut body: Vec<u8> = code.iter().flat_map(|i| i.to_le_bytes()).collect();
+        body.extend_from_slice(data);
+        let file_size = 64 + 56 + body.len() as u64;
+
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+        elf.extend([0u8; 8]);
+        elf.extend(2u16.to_le_bytes()); // ET_EXEC
+        elf.extend(EM_RISCV.to_le_bytes());
+        elf.extend(1u32.to_le_bytes());
+        elf.extend(entry.to_le_bytes());
+        elf.extend(64u64.to_le_bytes()); // e_phoff
+        elf.extend(0u64.to_le_bytes()); // e_shoff
+        elf.extend(0u32.to_le_bytes()); // e_flags
+        elf.extend(64u16.to_le_bytes()); // e_ehsize
+        elf.extend(56u16.to_le_bytes()); // e_phentsize
+        elf.extend(1u16.to_le_bytes()); // e_phnum
+        elf.extend(64u16.to_le_bytes()); // e_shentsize
+        elf.extend(0u16.to_le_bytes()); // e_shnum
+        elf.extend(0u16.to_le_bytes()); // e_shstrndx
+
+        elf.extend(PT_LOAD.to_le_bytes());
+        elf.extend(5u32.to_le_bytes()); // R+X
+        elf.extend(0u64.to_le_bytes()); // p_offset
+        elf.extend(BASE.to_le_bytes()); // p_vaddr
+        elf.extend(BASE.to_le_bytes()); // p_paddr
+        elf.extend(file_size.to_le_bytes());
+        elf.extend(file_size.to_le_bytes());
+        elf.extend(PAGE_SIZE.to_le_bytes());
+
+        elf.extend(body);
+        elf
+    }
+
+    #[test]
+    fn test_hello_world() {
+        let msg = b"hello\n";
+        let code = [
+            addi(10, 0, 1),                // a0 = 1 (stdout)
+            0x0000_0597,                   // auipc a1, 0
+            addi(11, 11, 32),              // a1 = msg
+            addi(12, 0, msg.len() as i32), // a2 = len
+            addi(17, 0, 64),               // a7 = write
+            0x0000_0073,                   // ecall
+            addi(10, 0, 7),                // a0 = 7
+            addi(17, 0, 93),               // a7 = exit
+            0x0000_0073,                   // ecall
+        ];
+        let image = tiny_elf(&code, msg);
+
+        let mut process = UserProcess::load(&image, &["hello".to_string()]).unwrap();
+        process.capture_output();
+        assert_eq!(process.run(1000), UserExit::Exited(7));
+        assert_eq!(process.take_output(), msg);
+    }
+
+    #[test]
+    fn test_fault_is_reported() {
+        let image = tiny_elf(&[0xffff_ffff], &[]);
+        let mut process = UserProcess::load(&image, &[]).unwrap();
+        let entry = process.cpu.pc;
+        match process.run(10) {
+            UserExit::Fault { trap, pc } => {
+                assert!(matches!(trap, Trap::IllegalInstruction(_)));
+                assert_eq!(pc, entry);
+            }
+            other => panic!("expected fault, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_initial_stack_layout() {
+        let image = tiny_elf(&[0x0000_006f], &[]);
+        let args = ["prog".to_string(), "arg".to_string()];
+        let process = UserProcess::load(&image, &args).unwrap();
+        let sp = process.cpu.regs[2];
+        assert_eq!(sp % 16, 0);
+        assert_eq!(process.bus.read64(sp).unwrap(), 2);
+        let argv1 = process.bus.read64(sp + 16).unwrap();
+        assert_eq!(read_guest_str(&process.bus, argv1).unwrap(), "arg");
+        assert_eq!(process.bus.read64(sp + 24).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_brk_grows_within_limits() {
+        let bus = SystemBus::new(0, USER_MEM_SIZE);
+        let mut sys = Syscalls::new(0x20000);
+        assert_eq!(sys.dispatch(&bus, SYS_BRK, [0; 6]), 0x20000);
+        assert_eq!(
+            sys.dispatch(&bus, SYS_BRK, [0x30000, 0, 0, 0, 0, 0]),
+            0x30000
+        );
+        // Requests past the stack reservation leave the break unchanged
+        assert_eq!(
+            sys.dispatch(&bus, SYS_BRK, [STACK_TOP, 0, 0, 0, 0, 0]),
+            0x30000
+        );
+        assert_eq!(sys.dispatch(&bus, 999, [0; 6]), -ENOSYS);
+    }
+}
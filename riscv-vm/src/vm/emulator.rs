@@ -1,10 +1,14 @@
 use crate::Trap;
-use crate::bus::{DRAM_BASE, SystemBus};
-use crate::cpu::Cpu;
+use crate::backtrace::{self, Backtrace, SymbolMap};
+use crate::bus::{BusConfig, DRAM_BASE, SystemBus};
+use crate::cpu::{Cpu, TrapBreak, TrapBreakHit, WatchHit, WatchId, Watchpoint};
+use crate::devices::bootrom::BootConfig;
+use crate::devices::test_finisher::VmExit;
 use crate::snapshot::{
-    ClintSnapshot, CpuSnapshot, DeviceSnapshot, MemRegionSnapshot, PlicSnapshot, SNAPSHOT_VERSION,
-    Snapshot, UartSnapshot,
+    Compression, SNAPSHOT_VERSION, Snapshot, SnapshotReader, SnapshotWriter, restore_stream,
 };
+use crate::vm::guest_mem::{self, Translation};
+use crate::vm::trap_info::TrapInfo;
 use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::{Read, Write};
@@ -44,6 +48,7 @@ pub struct Emulator {
 
     trapped: bool,
     last_trap: Option<Trap>,
+    last_trap_info: Option<TrapInfo>,
 
     /// Optional UART output callback invoked once per transmitted byte.
     ///
@@ -63,9 +68,13 @@ impl Emulator {
 
     /// Create a new emulator instance with an explicit DRAM size in bytes.
     pub fn with_memory(dram_size_bytes: usize) -> Self {
-        let dram_base = DRAM_BASE;
-        let bus = SystemBus::new(dram_base, dram_size_bytes);
-        let cpu = Cpu::new(dram_base, 0); // hart_id = 0
+        Self::with_config(BusConfig::with_dram(DRAM_BASE, dram_size_bytes))
+    }
+
+    /// Create a new emulator instance with a custom memory map.
+    pub fn with_config(config: BusConfig) -> Self {
+        let bus = SystemBus::with_config(config);
+        let cpu = Cpu::new(config.dram_base, 0); // hart_id = 0
 
         Self {
             cpu,
@@ -74,6 +83,7 @@ impl Emulator {
             signature_size: 0,
             trapped: false,
             last_trap: None,
+            last_trap_info: None,
             uart_callback: None,
         }
     }
@@ -89,6 +99,22 @@ impl Emulator {
         self.last_trap.as_ref()
     }
 
+    /// How the guest ended the run, if the last trap was its request to
+    /// power off or reboot (see [`crate::devices::test_finisher`]). The
+    /// emulator does not reboot by itself.
+    pub fn exit(&self) -> Option<VmExit> {
+        match self.last_trap {
+            Some(Trap::RequestedTrap(code)) => Some(VmExit::from_code(code)),
+            _ => None,
+        }
+    }
+
+    /// The last trap with the PC, privilege mode and instruction it was
+    /// raised on.
+    pub fn last_trap_info(&self) -> Option<&TrapInfo> {
+        self.last_trap_info.as_ref()
+    }
+
     /// Register a UART output callback.
     ///
     /// The callback is invoked from [`step`] for each byte emitted by the
@@ -110,6 +136,20 @@ impl Emulator {
         self.bus.uart.push_input(byte);
     }
 
+    /// Raise external interrupt line `irq` (1-31) for a device modelled by
+    /// the host. It stays pending until [`clear_irq`] and reaches the hart
+    /// through the PLIC, so the guest sees it as MEIP/SEIP once the source
+    /// is enabled above the context's threshold. Lines owned by built-in
+    /// devices are rejected.
+    pub fn raise_irq(&mut self, irq: u32) -> Result<(), String> {
+        self.bus.raise_irq(irq)
+    }
+
+    /// Lower an external interrupt line raised with [`raise_irq`].
+    pub fn clear_irq(&mut self, irq: u32) -> Result<(), String> {
+        self.bus.clear_irq(irq)
+    }
+
     /// Drain all pending UART output bytes into a vector.
     ///
     /// This is useful for tests or hosts that do not wish to use the callback
@@ -125,7 +165,7 @@ impl Emulator {
     /// Execute a single instruction.
     ///
     /// On success, returns `Ok(())`. On architectural traps, this records the
-    /// trap in [`last_trap`] and sets [`trapped`] before returning `Err(trap)`.
+    /// trap in [`last_trap`] (and its context in [`last_trap_info`]) and sets [`trapped`] before returning `Err(trap)`.
     pub fn step(&mut self) -> Result<(), Trap> {
         match self.cpu.step(&self.bus) {
             Ok(()) => {
@@ -140,16 +180,93 @@ impl Emulator {
             }
             Err(trap) => {
                 self.trapped = true;
+                self.last_trap_info = Some(TrapInfo::capture(&trap, &self.cpu, &self.bus));
                 self.last_trap = Some(trap.clone());
                 Err(trap)
             }
         }
     }
 
-    /// Load an ELF image from disk into DRAM and update the CPU's PC to the
-    /// ELF entry point.
+    /// Pause the next time a trap matching `condition` is taken.
+    ///
+    /// See [`crate::cpu::debug`] for the latching behaviour.
+    pub fn break_on(&mut self, condition: TrapBreak) {
+        self.cpu.break_on(condition);
+    }
+
+    /// Step until an armed trap breakpoint fires, for at most `max_steps`
+    /// instructions.
+    ///
+    /// Returns the latched hit; the hart stays paused until [`resume`].
+    ///
+    /// [`resume`]: Self::resume
+    pub fn run_until_break(&mut self, max_steps: u64) -> Option<TrapBreakHit> {
+        for _ in 0..max_steps {
+            if self.cpu.break_hit().is_some() {
+                break;
+            }
+            let _ = self.step();
+        }
+        self.cpu.break_hit().cloned()
+    }
+
+    /// Clear a latched trap breakpoint so execution can continue.
+    pub fn resume(&mut self) -> Option<TrapBreakHit> {
+        self.cpu.resume()
+    }
+
+    /// Pause after the next load or store that touches `watchpoint`'s
+    /// range. Returns an ID for [`remove_watchpoint`].
+    ///
+    /// See [`crate::cpu::watch`] for what is checked and reported.
+    ///
+    /// [`remove_watchpoint`]: Self::remove_watchpoint
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchId {
+        self.cpu.add_watchpoint(watchpoint)
+    }
+
+    /// Disarm a watchpoint. Returns false if `id` is not armed.
+    pub fn remove_watchpoint(&mut self, id: WatchId) -> bool {
+        self.cpu.remove_watchpoint(id)
+    }
+
+    /// Step until a watchpoint fires, for at most `max_steps` instructions.
+    ///
+    /// Returns the latched hit; the hart stays paused until
+    /// [`resume_watch`].
+    ///
+    /// [`resume_watch`]: Self::resume_watch
+    pub fn run_until_watch(&mut self, max_steps: u64) -> Option<WatchHit> {
+        for _ in 0..max_steps {
+            if self.cpu.watch_hit().is_some() {
+                break;
+            }
+            let _ = self.step();
+        }
+        self.cpu.watch_hit().cloned()
+    }
+
+    /// Clear a latched watchpoint hit so execution can continue.
+    pub fn resume_watch(&mut self) -> Option<WatchHit> {
+        self.cpu.resume_watch()
+    }
+
+    /// Walk the guest stack from the current PC, e.g. after [`step`]
+    /// returned a trap, resolving functions with `symbols`. See
+    /// [`crate::backtrace`].
+    ///
+    /// [`step`]: Self::step
+    pub fn backtrace(&self, symbols: &SymbolMap) -> Backtrace {
+        backtrace::unwind(&self.cpu, &self.bus, symbols)
+    }
+
+    /// Load an ELF image from disk into DRAM and boot it through the boot ROM.
     ///
-    /// Returns the resolved entry PC on success.
+    /// The ELF entry point is written to the boot ROM mailbox and the CPU is
+    /// reset to the boot ROM's reset vector (see
+    /// [`BootRom::set_reset_vector`](crate::devices::bootrom::BootRom::set_reset_vector)),
+    /// so the first-stage loader performs the hand-off. Returns the resolved
+    /// entry PC on success.
     pub fn load_elf<P: AsRef<Path>>(&mut self, path: P) -> Result<u64, Box<dyn std::error::Error>> {
         let mut file = File::open(path)?;
         let mut buffer = Vec::new();
@@ -161,10 +278,45 @@ impl Emulator {
         #[cfg(target_arch = "wasm32")]
         let entry_pc = crate::loader::load_elf_wasm(&buffer, &self.bus)?;
 
-        self.cpu.pc = entry_pc;
+        self.boot(entry_pc);
         Ok(entry_pc)
     }
 
+    /// Load a kernel image already in memory and boot it like [`load_elf`].
+    ///
+    /// ELF images are loaded by segment; anything else is copied to the
+    /// start of DRAM and entered there.
+    ///
+    /// [`load_elf`]: Self::load_elf
+    pub fn load_kernel(&mut self, image: &[u8]) -> Result<u64, String> {
+        let entry_pc = if image.starts_with(b"\x7FELF") {
+            #[cfg(not(target_arch = "wasm32"))]
+            let entry_pc = crate::loader::load_elf_into_dram(image, &self.bus)?;
+
+            #[cfg(target_arch = "wasm32")]
+            let entry_pc = crate::loader::load_elf_wasm(image, &self.bus)?;
+
+            entry_pc
+        } else {
+            self.bus
+                .dram
+                .load(image, 0)
+                .map_err(|e| format!("Failed to load kernel: {:?}", e))?;
+            self.bus.dram_base()
+        };
+        self.boot(entry_pc);
+        Ok(entry_pc)
+    }
+
+    /// Point the boot ROM at `entry_pc` and reset the hart into it.
+    fn boot(&mut self, entry_pc: u64) {
+        let stack_top = self.bus.dram_base() + self.bus.dram_size() as u64;
+        self.bus
+            .boot_rom
+            .configure(&BootConfig::new(entry_pc, stack_top));
+        self.cpu.pc = self.bus.boot_rom.reset_vector();
+    }
+
     /// Configure the signature region used by `read_signature`.
     ///
     /// - `base` is the physical start address of the signature buffer.
@@ -222,63 +374,46 @@ impl Emulator {
             .map_err(|e| format!("failed to read signature: {}", e))
     }
 
-    /// Capture a complete, deterministic snapshot of the current emulator state.
-    pub fn snapshot(&self) -> Snapshot {
-        let cpu = CpuSnapshot {
-            pc: self.cpu.pc,
-            mode: self.cpu.mode,
-            regs: self.cpu.regs,
-            csrs: self.cpu.export_csrs(),
-        };
+    /// Read `len` bytes of physical memory. Only DRAM can be read.
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0; len];
+        self.read_phys(addr, &mut buf)?;
+        Ok(buf)
+    }
 
-        let clint = ClintSnapshot {
-            msip: self.bus.clint.get_msip_array().to_vec(),
-            mtime: self.bus.clint.mtime(),
-            mtimecmp: self.bus.clint.get_mtimecmp_array().to_vec(),
-        };
+    /// Write bytes to physical memory. Only DRAM can be written; decoded
+    /// instructions and blocks are dropped in case the range held code.
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), String> {
+        self.write_phys(addr, data)
+    }
 
-        let plic = PlicSnapshot {
-            priority: self.bus.plic.get_priority(),
-            pending: self.bus.plic.get_pending(),
-            enable: self.bus.plic.get_enable(),
-            threshold: self.bus.plic.get_threshold(),
-            active: self.bus.plic.get_active(),
-        };
+    /// Fill `buf` from physical memory at `paddr` (DRAM only).
+    pub fn read_phys(&self, paddr: u64, buf: &mut [u8]) -> Result<(), String> {
+        guest_mem::read_phys(&self.bus, paddr, buf)
+    }
 
-        let (ier, iir, fcr, lcr, mcr, lsr, msr, scr, dll, dlm) = self.bus.uart.get_registers();
-        let uart = UartSnapshot {
-            rx_fifo: self.bus.uart.get_input(),
-            tx_fifo: self.bus.uart.get_output(),
-            ier,
-            iir,
-            fcr,
-            lcr,
-            mcr,
-            lsr,
-            msr,
-            scr,
-            dll,
-            dlm,
-        };
+    /// Write `data` to physical memory at `paddr` (DRAM only).
+    pub fn write_phys(&mut self, paddr: u64, data: &[u8]) -> Result<(), String> {
+        guest_mem::write_phys(&mut self.cpu, &self.bus, paddr, data)
+    }
 
-        let dram_data = self.bus.dram.get_data();
-        let mut hasher = Sha256::new();
-        hasher.update(&dram_data);
-        let hash = hex::encode(hasher.finalize());
-
-        let region = MemRegionSnapshot {
-            base: self.bus.dram.base,
-            size: self.bus.dram.size() as u64,
-            hash,
-            data: Some(dram_data),
-        };
+    /// Fill `buf` from virtual address `vaddr`, translated in the address
+    /// space given by `mode`. See [`guest_mem`] for how translation differs
+    /// from a guest access.
+    pub fn read_virt(&self, vaddr: u64, buf: &mut [u8], mode: Translation) -> Result<(), String> {
+        guest_mem::read_virt(&self.cpu, &self.bus, vaddr, buf, mode)
+    }
 
-        Snapshot {
-            version: SNAPSHOT_VERSION.to_string(),
-            cpu,
-            devices: DeviceSnapshot { clint, plic, uart },
-            memory: vec![region],
-        }
+    /// Write `data` at virtual address `vaddr`, translated in the address
+    /// space given by `mode`. Nothing is written unless the whole range is
+    /// mapped.
+    pub fn write_virt(&mut self, vaddr: u64, data: &[u8], mode: Translation) -> Result<(), String> {
+        guest_mem::write_virt(&mut self.cpu, &self.bus, vaddr, data, mode)
+    }
+
+    /// Capture a complete, deterministic snapshot of the current emulator state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(&self.cpu, &self.bus)
     }
 
     /// Restore emulator state from a previously captured snapshot.
@@ -290,45 +425,11 @@ impl Emulator {
             ));
         }
 
-        // Restore CPU core.
-        self.cpu.pc = snapshot.cpu.pc;
-        self.cpu.mode = snapshot.cpu.mode;
-        self.cpu.regs = snapshot.cpu.regs;
-        self.cpu.import_csrs(&snapshot.cpu.csrs);
+        snapshot.cpu.apply(&mut self.cpu)?;
         self.trapped = false;
         self.last_trap = None;
-
-        // Restore CLINT.
-        self.bus.clint.set_msip_array(&snapshot.devices.clint.msip);
-        self.bus.clint.set_mtime(snapshot.devices.clint.mtime);
-        self.bus
-            .clint
-            .set_mtimecmp_array(&snapshot.devices.clint.mtimecmp);
-
-        // Restore PLIC.
-        self.bus.plic.set_priority(&snapshot.devices.plic.priority);
-        self.bus.plic.set_pending(snapshot.devices.plic.pending);
-        self.bus.plic.set_enable(&snapshot.devices.plic.enable);
-        self.bus
-            .plic
-            .set_threshold(&snapshot.devices.plic.threshold);
-        self.bus.plic.set_active(&snapshot.devices.plic.active);
-
-        // Restore UART.
-        self.bus.uart.set_input(&snapshot.devices.uart.rx_fifo);
-        self.bus.uart.set_output(&snapshot.devices.uart.tx_fifo);
-        self.bus.uart.set_registers(
-            snapshot.devices.uart.ier,
-            snapshot.devices.uart.iir,
-            snapshot.devices.uart.fcr,
-            snapshot.devices.uart.lcr,
-            snapshot.devices.uart.mcr,
-            snapshot.devices.uart.lsr,
-            snapshot.devices.uart.msr,
-            snapshot.devices.uart.scr,
-            snapshot.devices.uart.dll,
-            snapshot.devices.uart.dlm,
-        );
+        self.last_trap_info = None;
+        snapshot.devices.apply(&self.bus);
 
         // Restore DRAM.
         let region = snapshot
@@ -394,21 +495,47 @@ impl Emulator {
         &self,
         path: P,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let snap = self.snapshot();
+        let bytes = self.snapshot().to_bytes()?;
         let mut file = File::create(path)?;
-        bincode::serialize_into(&mut file, &snap)?;
+        file.write_all(&bytes)?;
         file.flush()?;
         Ok(())
     }
 
+    /// Stream a snapshot to `out` without copying DRAM whole (see
+    /// [`Snapshot::write_stream`]).
+    pub fn write_snapshot_stream<W: SnapshotWriter + ?Sized>(
+        &self,
+        out: &mut W,
+        compression: Compression,
+    ) -> Result<(), String> {
+        Snapshot::write_stream(&self.cpu, &self.bus, out, compression)
+    }
+
+    /// Restore a streamed snapshot from `input`, one chunk at a time. The
+    /// DRAM size must match; on error the emulator should be restored
+    /// again (see [`restore_stream`]).
+    pub fn restore_snapshot_stream<R: SnapshotReader + ?Sized>(
+        &mut self,
+        input: &mut R,
+    ) -> Result<(), String> {
+        restore_stream(input, &mut self.cpu, &self.bus)?;
+        self.trapped = false;
+        self.last_trap = None;
+        self.last_trap_info = None;
+        Ok(())
+    }
+
     /// Load a snapshot from disk and construct a new emulator instance.
+    ///
+    /// Snapshots from older versions are migrated (see [`Snapshot::from_bytes`]).
     pub fn load_snapshot_from_path<P: AsRef<Path>>(
         path: P,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut file = File::open(path)?;
-        let snapshot: Snapshot = bincode::deserialize_from(&mut file)?;
-        let emu = Emulator::from_snapshot(snapshot)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let snapshot = Snapshot::from_bytes(&bytes)?;
+        let emu = Emulator::from_snapshot(snapshot)?;
         Ok(emu)
     }
 }
@@ -416,6 +543,8 @@ impl Emulator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bus::Bus;
+    use crate::devices::bootrom::RESET_VECTOR;
     use crate::engine::decoder::Register;
 
     #[test]
@@ -456,4 +585,26 @@ mod tests {
         );
         assert_eq!(emu.bus.uart.get_input(), emu2.bus.uart.get_input());
     }
+
+    #[test]
+    fn load_kernel_copies_raw_image_to_dram_base() {
+        let mut emu = Emulator::with_memory(1024 * 1024);
+        // addi x1, x0, 1
+        let image = 0x0010_0093u32.to_le_bytes();
+
+        assert_eq!(emu.load_kernel(&image).unwrap(), DRAM_BASE);
+        assert_eq!(emu.cpu.pc, RESET_VECTOR);
+        assert_eq!(emu.bus.dram.read_range(0, 4).unwrap(), image);
+    }
+
+    #[test]
+    fn memory_access_is_limited_to_dram() {
+        let mut emu = Emulator::with_memory(1024 * 1024);
+        let end = DRAM_BASE + 1024 * 1024;
+
+        emu.write_memory(end - 4, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(emu.read_memory(end - 4, 4).unwrap(), [1, 2, 3, 4]);
+        assert!(emu.read_memory(end - 2, 4).is_err());
+        assert!(emu.write_memory(DRAM_BASE - 1, &[0]).is_err());
+    }
 }
@@ -0,0 +1,211 @@
+//! Guest memory access for debuggers and test harnesses.
+//!
+//! Physical accesses reach DRAM only, so inspecting memory never has
+//! device side effects. Virtual accesses are translated page by page with
+//! [`mmu::translate_debug`], under the hart's current address space or a
+//! given `satp`, without permission checks or A/D bit updates: a debugger
+//! can read kernel data from a user context and patch read-only text.
+
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::csr::CSR_SATP;
+use crate::{Mode, mmu};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Address space a virtual access is translated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+    /// The hart's current `satp`, or none while it runs in M-mode.
+    Current,
+    /// This `satp` value.
+    Satp(u64),
+}
+
+/// `Some(satp)` translates with that `satp`, `None` in the current
+/// address space.
+impl From<Option<u64>> for Translation {
+    fn from(satp: Option<u64>) -> Self {
+        satp.map_or(Translation::Current, Translation::Satp)
+    }
+}
+
+impl Translation {
+    fn satp(self, cpu: &Cpu) -> u64 {
+        match self {
+            Translation::Current if cpu.mode == Mode::Machine => 0,
+            Translation::Current => cpu.csrs[CSR_SATP as usize],
+            Translation::Satp(satp) => satp,
+        }
+    }
+}
+
+/// DRAM offset of `[addr, addr + len)`, if the whole range is in DRAM.
+fn dram_range(bus: &SystemBus, addr: u64, len: usize) -> Result<usize, String> {
+    let size = bus.dram.size();
+    bus.dram
+        .offset(addr)
+        .filter(|offset| offset.checked_add(len).is_some_and(|end| end <= size))
+        .ok_or_else(|| {
+            format!(
+                "0x{:x}..0x{:x} is not in DRAM",
+                addr,
+                addr.wrapping_add(len as u64)
+            )
+        })
+}
+
+/// Fill `buf` from physical address `paddr`.
+pub fn read_phys(bus: &SystemBus, paddr: u64, buf: &mut [u8]) -> Result<(), String> {
+    let offset = dram_range(bus, paddr, buf.len())?;
+    let bytes = bus
+        .dram
+        .read_range(offset, buf.len())
+        .map_err(|e| e.to_string())?;
+    buf.copy_from_slice(&bytes);
+    Ok(())
+}
+
+/// Write `data` at physical address `paddr`. Compiled code on `cpu` is
+/// dropped in case the range held instructions.
+pub fn write_phys(cpu: &mut Cpu, bus: &SystemBus, paddr: u64, data: &[u8]) -> Result<(), String> {
+    let offset = dram_range(bus, paddr, data.len())?;
+    bus.dram
+        .write_bytes(offset as u64, data)
+        .map_err(|e| e.to_string())?;
+    cpu.invalidate_blocks();
+    Ok(())
+}
+
+/// Split `[vaddr, vaddr + len)` at page boundaries and translate each
+/// piece, calling `access(paddr, start, end)` with its span in the buffer.
+fn for_each_page(
+    cpu: &Cpu,
+    bus: &SystemBus,
+    vaddr: u64,
+    len: usize,
+    translation: Translation,
+    mut access: impl FnMut(u64, usize, usize) -> Result<(), String>,
+) -> Result<(), String> {
+    let satp = translation.satp(cpu);
+    let mut done = 0;
+    while done < len {
+        let va = vaddr.wrapping_add(done as u64);
+        let chunk = ((PAGE_SIZE - (va & (PAGE_SIZE - 1))) as usize).min(len - done);
+        let pa = mmu::translate_debug(bus, satp, va)
+            .ok_or_else(|| format!("0x{:x} is not mapped", va))?;
+        access(pa, done, done + chunk)?;
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// Fill `buf` from virtual address `vaddr`.
+pub fn read_virt(
+    cpu: &Cpu,
+    bus: &SystemBus,
+    vaddr: u64,
+    buf: &mut [u8],
+    translation: Translation,
+) -> Result<(), String> {
+    for_each_page(cpu, bus, vaddr, buf.len(), translation, |pa, start, end| {
+        read_phys(bus, pa, &mut buf[start..end])
+    })
+}
+
+/// Write `data` at virtual address `vaddr`. Nothing is written unless the
+/// whole range is mapped to DRAM.
+pub fn write_virt(
+    cpu: &mut Cpu,
+    bus: &SystemBus,
+    vaddr: u64,
+    data: &[u8],
+    translation: Translation,
+) -> Result<(), String> {
+    let mut pieces = Vec::new();
+    for_each_page(
+        cpu,
+        bus,
+        vaddr,
+        data.len(),
+        translation,
+        |pa, start, end| {
+            dram_range(bus, pa, end - start)?;
+            pieces.push((pa, start, end));
+            Ok(())
+        },
+    )?;
+    for (pa, start, end) in pieces {
+        write_phys(cpu, bus, pa, &data[start..end])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, DRAM_BASE};
+    use crate::cpu::test_hart;
+
+    const ROOT: u64 = DRAM_BASE + 0x10_000;
+    const MID: u64 = DRAM_BASE + 0x11_000;
+    const LEAF: u64 = DRAM_BASE + 0x12_000;
+    const SATP_SV39: u64 = (8 << 60) | (ROOT >> 12);
+
+    fn pte(pa: u64, flags: u64) -> u64 {
+        ((pa >> 12) << 10) | flags
+    }
+
+    /// Map virtual page `0x4000_0000 + i` to `DRAM_BASE + 0x20_000 + i`
+    /// for two pages, with the second page placed first in DRAM.
+    fn setup() -> (Cpu, SystemBus) {
+        let (cpu, bus) = test_hart(&[]);
+        bus.write64(ROOT + 8, pte(MID, 1)).unwrap();
+        bus.write64(MID, pte(LEAF, 1)).unwrap();
+        bus.write64(LEAF, pte(DRAM_BASE + 0x21_000, 0b0011))
+            .unwrap();
+        bus.write64(LEAF + 8, pte(DRAM_BASE + 0x20_000, 0b0011))
+            .unwrap();
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_virtual_access_spans_pages() {
+        let (mut cpu, bus) = setup();
+        let va = 0x4000_0ffe;
+        write_virt(&mut cpu, &bus, va, b"abcd", Translation::Satp(SATP_SV39)).unwrap();
+        assert_eq!(bus.dram.read_range(0x21_ffe, 2).unwrap(), b"ab");
+        assert_eq!(bus.dram.read_range(0x20_000, 2).unwrap(), b"cd");
+
+        let mut buf = [0; 4];
+        read_virt(&cpu, &bus, va, &mut buf, Translation::Satp(SATP_SV39)).unwrap();
+        assert_eq!(&buf, b"abcd");
+        // A/D bits are left alone
+        assert_eq!(bus.read64(LEAF).unwrap() & 0xC0, 0);
+
+        // Bare in M-mode
+        read_virt(
+            &cpu,
+            &bus,
+            DRAM_BASE + 0x20_000,
+            &mut buf[..2],
+            Translation::Current,
+        )
+        .unwrap();
+        assert_eq!(&buf[..2], b"cd");
+        cpu.mode = Mode::Supervisor;
+        cpu.csrs[CSR_SATP as usize] = SATP_SV39;
+        read_virt(&cpu, &bus, va + 2, &mut buf[..2], Translation::Current).unwrap();
+        assert_eq!(&buf[..2], b"cd");
+    }
+
+    #[test]
+    fn test_unmapped_write_changes_nothing() {
+        let (mut cpu, bus) = setup();
+        let va = 0x4000_1ffe;
+        let err = write_virt(&mut cpu, &bus, va, b"abcd", Translation::Satp(SATP_SV39));
+        assert_eq!(err, Err("0x40002000 is not mapped".to_string()));
+        assert_eq!(bus.dram.read_range(0x20_ffe, 2).unwrap(), [0, 0]);
+        assert!(read_phys(&bus, DRAM_BASE - 2, &mut [0; 4]).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 //! Virtual Machine implementations.
 
 pub mod emulator;
+pub mod guest_mem;
+pub mod trap_info;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod native;
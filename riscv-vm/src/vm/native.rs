@@ -1,11 +1,42 @@
 use crate::Trap;
-use crate::bus::{DRAM_BASE, SystemBus};
+use crate::backtrace::{self, SymbolMap};
+use crate::bus::{BusConfig, SystemBus, VIRTIO_SLOTS};
+use crate::compliance::{self, ComplianceReport};
 use crate::console::Console;
-use crate::cpu::Cpu;
+use crate::cpu::idle::MAX_IDLE_TICKS;
+use crate::cpu::vector::DEFAULT_VLEN;
+use crate::cpu::{Cpu, CsrPolicy, InterruptLatency, SbiConfig, Tracer};
+use crate::crash_dump::CrashDump;
+use crate::devices::bootrom::{BootConfig, RESET_VECTOR};
+use crate::devices::clint::TIMEBASE_FREQUENCY;
+use crate::devices::rtc::DETERMINISTIC_EPOCH_NS;
+use crate::devices::test_finisher::{FINISHER_RESET, VmExit};
+use crate::devices::virtio::device::{CONFIG_SPACE_OFFSET, VIRTIO_NET_DEVICE_ID};
+use crate::devices::virtio::{
+    ConsolePort, GpuDisplay, HotplugSlot, VirtioConsole, VirtioDevice, VirtioGpu, VirtioNet,
+};
+use crate::devices::worker::{DeviceLatency, DeviceLatencyHandle};
+#[cfg(feature = "jit-native")]
+use crate::engine::jit::{
+    JitConfig, JitDiagnostics, JitDiagnosticsHandle, JitProfile, ProfiledBlock,
+};
+use crate::gdb::{self, GdbStub, Request};
+use crate::integrity::{CorruptionEvent, IntegrityConfig, IntegrityStats};
 use crate::loader::load_elf_into_dram;
+use crate::migration::{self, MigrationHeader};
+use crate::net::batch::{BatchConfig, TransportMetrics};
+use crate::net::{NetBackend, NetworkBackend, TransportMetricsHandle};
+use crate::replay::{
+    Channel, Engine, GuestInput, InputLog, Machine, Recording, RecordingBackend, ReplayBackend,
+};
+use crate::snapshot::{Compression, Snapshot};
+use crate::vm::trap_info::TrapInfo;
+use sha2::{Digest, Sha256};
+use std::fs;
 use std::io::{self, Write};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
@@ -18,8 +49,9 @@ use std::time::{Duration, Instant};
 /// Combined flags into a single atomic for faster polling.
 #[repr(align(64))]
 pub struct SharedState {
-    /// Combined flags: bit 0 = halt_requested, bit 1 = halted
-    /// Using a single atomic reduces should_stop() from 2 loads to 1.
+    /// Combined flags: bit 0 = halt_requested, bit 1 = halted,
+    /// bit 2 = reboot_requested
+    /// Using a single atomic reduces should_stop() from 3 loads to 1.
     flags: AtomicU8,
     /// Halt code (e.g., from TEST_FINISHER).
     halt_code: AtomicU64,
@@ -30,6 +62,7 @@ pub struct SharedState {
 impl SharedState {
     const HALT_REQUESTED: u8 = 0x01;
     const HALTED: u8 = 0x02;
+    const REBOOT_REQUESTED: u8 = 0x04;
 
     pub fn new() -> Self {
         Self {
@@ -60,6 +93,23 @@ impl SharedState {
         self.halt_code.load(Ordering::Acquire)
     }
 
+    /// Stop every hart so hart 0 can reset the machine.
+    pub fn request_reboot(&self) {
+        self.flags
+            .fetch_or(Self::REBOOT_REQUESTED, Ordering::Release);
+    }
+
+    /// Whether a reboot is the only reason to stop.
+    pub fn is_reboot_pending(&self) -> bool {
+        self.flags.load(Ordering::Acquire) == Self::REBOOT_REQUESTED
+    }
+
+    /// Let the harts run again once the machine has been reset.
+    pub fn finish_reboot(&self) {
+        self.flags
+            .fetch_and(!Self::REBOOT_REQUESTED, Ordering::Release);
+    }
+
     #[inline(always)]
     pub fn should_stop(&self) -> bool {
         self.flags.load(Ordering::Relaxed) != 0
@@ -72,9 +122,140 @@ impl Default for SharedState {
     }
 }
 
+/// Why a hart run by a debugger stopped.
+enum GdbStop {
+    /// Paused, reporting this signal.
+    Signal(u8),
+    /// The VM halted with this code.
+    Exited(u64),
+}
+
 enum HaltReason {
     Shutdown(u64),
-    Fatal(String, u64),
+    Fatal(TrapInfo),
+}
+
+/// How the run loops pace their harts against host time.
+#[derive(Debug, Clone, Copy)]
+struct Pacing {
+    /// Sleep while a hart waits in WFI with nothing pending.
+    idle: bool,
+    /// Instructions per second cap of each hart, in millions.
+    max_mips: Option<u32>,
+    /// Let guest time pass by whole idle deadlines, however long the host
+    /// actually slept.
+    deterministic: bool,
+}
+
+/// Longest a secondary hart sleeps in WFI before checking how far guest
+/// time has moved.
+const SECONDARY_IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+/// Holds a hart to a maximum instruction rate.
+struct Throttle {
+    ips: f64,
+    start: Instant,
+    steps: u64,
+}
+
+impl Throttle {
+    fn new(max_mips: u32) -> Self {
+        Self {
+            ips: max_mips as f64 * 1_000_000.0,
+            start: Instant::now(),
+            steps: 0,
+        }
+    }
+
+    /// Account for `steps` more instructions and sleep off any lead over
+    /// the cap.
+    fn pace(&mut self, steps: u64) {
+        self.steps += steps;
+        let due = Duration::from_secs_f64(self.steps as f64 / self.ips);
+        let elapsed = self.start.elapsed();
+        if let Some(lead) = due.checked_sub(elapsed)
+            && lead >= Duration::from_millis(1)
+        {
+            thread::sleep(lead);
+        }
+        // Start a new window now and then, so time spent stalled is not
+        // made up for with a burst
+        if elapsed >= Duration::from_secs(1) {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.start = Instant::now();
+        self.steps = 0;
+    }
+}
+
+/// Host time `ticks` of `mtime` take.
+fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos((ticks as u128 * 1_000_000_000 / TIMEBASE_FREQUENCY as u128) as u64)
+}
+
+/// `mtime` ticks that pass in `duration`, rounded down.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() * TIMEBASE_FREQUENCY as u128 / 1_000_000_000).min(u64::MAX as u128) as u64
+}
+
+/// First fatal error reported by any hart, with the crash dump taken when
+/// it was raised.
+type FatalSlot = Arc<Mutex<Option<(TrapInfo, CrashDump)>>>;
+
+/// Keep `info` and a crash dump of `cpu` unless another hart got there
+/// first, then halt with 0xDEAD.
+fn signal_fatal(
+    slot: &FatalSlot,
+    shared: &SharedState,
+    cpu: &Cpu,
+    bus: &SystemBus,
+    info: TrapInfo,
+) {
+    let mut slot = slot.lock().unwrap();
+    if slot.is_none() {
+        let dump = CrashDump::capture(&info, cpu, bus);
+        *slot = Some((info, dump));
+    }
+    drop(slot);
+    shared.signal_halted(0xDEAD);
+}
+
+/// Figures gathered while a VM runs; see [`NativeVm::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VmStats {
+    /// Interrupt polls and the latency of the interrupts taken, by hart ID
+    /// (see [`crate::cpu::latency`]).
+    pub harts: Vec<InterruptLatency>,
+}
+
+impl VmStats {
+    /// Interrupt figures of all harts together.
+    pub fn interrupts(&self) -> InterruptLatency {
+        let mut total = InterruptLatency::default();
+        for hart in &self.harts {
+            total.merge(hart);
+        }
+        total
+    }
+
+    fn add(&mut self, hart_id: usize, figures: &InterruptLatency) {
+        if self.harts.len() <= hart_id {
+            self.harts.resize(hart_id + 1, InterruptLatency::default());
+        }
+        self.harts[hart_id].merge(figures);
+    }
+}
+
+/// Figures the harts have published so far.
+type StatsSlot = Arc<Mutex<VmStats>>;
+
+/// Move the figures `cpu` gathered since the last call into `slot`.
+fn publish_stats(slot: &StatsSlot, hart_id: usize, cpu: &mut Cpu) {
+    let figures = cpu.take_interrupt_latency();
+    slot.lock().unwrap().add(hart_id, &figures);
 }
 
 /// Native multi-threaded VM.
@@ -84,10 +265,55 @@ enum HaltReason {
 pub struct NativeVm {
     bus: Arc<SystemBus>,
     handles: Vec<JoinHandle<()>>,
+    /// Background DRAM integrity checker, if enabled.
+    checker: Option<JoinHandle<()>>,
     primary_cpu: Option<Cpu>,
+    /// Native JIT settings applied to every hart, if enabled.
+    #[cfg(feature = "jit-native")]
+    jit: Option<JitConfig>,
+    /// Diagnostics published by each hart's JIT.
+    #[cfg(feature = "jit-native")]
+    jit_diagnostics: Vec<JitDiagnosticsHandle>,
+    /// Blocks of a loaded JIT profile, preloaded into every hart.
+    #[cfg(feature = "jit-native")]
+    jit_preload: Vec<ProfiledBlock>,
+    /// Counters of the relay connection, if networking is configured.
+    net_metrics: Option<TransportMetricsHandle>,
+    /// Scanouts of the VirtIO GPU, if one is attached.
+    gpu: Option<Arc<GpuDisplay>>,
+    /// Request latency of devices served by worker threads, by device name.
+    device_latency: Vec<(String, DeviceLatencyHandle)>,
+    /// Inputs logged by the last recorded run, until taken.
+    recording: Option<Recording>,
+    /// Built-in SBI every hart boots under, if enabled.
+    sbi: Option<SbiConfig>,
+    /// VLEN of every hart, in bits.
+    vlen: usize,
+    /// Every hart runs cached blocks rather than single instructions.
+    blocks: bool,
+    /// What every hart does on accesses to unimplemented CSRs.
+    csr_policy: CsrPolicy,
+    /// Symbols for the backtrace printed when a hart halts on a fatal
+    /// error, if enabled.
+    backtrace: Option<Arc<SymbolMap>>,
+    /// The error that halted the VM, if a hart hit one.
+    fatal: FatalSlot,
+    /// Figures published by the harts.
+    stats: StatsSlot,
+    /// File the crash dump is written to when a hart halts on a fatal
+    /// error, if enabled.
+    crash_dump: Option<PathBuf>,
+    /// WFI idling and rate limit of every hart.
+    pacing: Pacing,
     pub shared: Arc<SharedState>,
     num_harts: usize,
     entry_pc: u64,
+    /// SHA-256 of the kernel image, identifying it in recordings
+    kernel_sha256: [u8; 32],
+    /// Kernel and firmware images, loaded into DRAM again on a reboot
+    boot_images: Vec<Vec<u8>>,
+    /// Reset and boot again when the guest reboots, rather than stop
+    reboot: bool,
 }
 
 impl NativeVm {
@@ -97,36 +323,75 @@ impl NativeVm {
     /// * `kernel` - Kernel binary (ELF or raw)
     /// * `num_harts` - Number of harts (CPUs) to create
     pub fn new(kernel: &[u8], num_harts: usize) -> Result<Self, String> {
-        const DRAM_SIZE: usize = 512 * 1024 * 1024;
-        let bus = SystemBus::new(DRAM_BASE, DRAM_SIZE);
+        Self::with_config(kernel, num_harts, BusConfig::default())
+    }
+
+    /// Create a new VM with a custom memory map.
+    ///
+    /// The map is described to the guest by a device tree in the boot ROM.
+    pub fn with_config(kernel: &[u8], num_harts: usize, config: BusConfig) -> Result<Self, String> {
+        config.validate()?;
+        let bus = SystemBus::with_config(config);
 
         bus.set_num_harts(num_harts);
+        bus.boot_rom
+            .set_fdt(crate::devices::fdt::generate(&config, num_harts, ""))?;
 
-        let entry_pc = if kernel.starts_with(b"\x7FELF") {
-            load_elf_into_dram(kernel, &bus)?
-        } else {
-            bus.dram
-                .load(kernel, 0)
-                .map_err(|e| format!("Failed to load kernel: {:?}", e))?;
-            DRAM_BASE
-        };
+        let entry_pc = load_boot_image(kernel, &bus)?;
+
+        // Harts start in the boot ROM, which reads the entry point from its
+        // mailbox and enters it with the device tree address in a1
+        bus.boot_rom.configure(&BootConfig {
+            boot_arg: bus.boot_rom.fdt_addr().unwrap_or(0),
+            ..BootConfig::new(entry_pc, config.dram_base + config.dram_size as u64)
+        });
 
         let bus = Arc::new(bus);
         let shared = Arc::new(SharedState::new());
-        let primary_cpu = Some(Cpu::new(entry_pc, 0));
+        let primary_cpu = Some(Cpu::new(RESET_VECTOR, 0));
 
         println!(
-            "[VM] Created with {} harts, entry=0x{:x}",
-            num_harts, entry_pc
+            "[VM] Created with {} harts, {} MiB DRAM at 0x{:x}, entry=0x{:x}",
+            num_harts,
+            config.dram_size >> 20,
+            config.dram_base,
+            entry_pc
         );
 
         Ok(Self {
             bus,
             handles: Vec::new(),
+            checker: None,
             primary_cpu,
+            #[cfg(feature = "jit-native")]
+            jit: None,
+            #[cfg(feature = "jit-native")]
+            jit_diagnostics: Vec::new(),
+            #[cfg(feature = "jit-native")]
+            jit_preload: Vec::new(),
+            net_metrics: None,
+            gpu: None,
+            device_latency: Vec::new(),
+            recording: None,
+            sbi: None,
+            vlen: DEFAULT_VLEN,
+            blocks: false,
+            csr_policy: CsrPolicy::Permissive,
+            backtrace: None,
+            fatal: FatalSlot::default(),
+            stats: StatsSlot::default(),
+            crash_dump: None,
+            pacing: Pacing {
+                idle: true,
+                max_mips: None,
+                deterministic: false,
+            },
             shared,
             num_harts,
             entry_pc,
+            kernel_sha256: Sha256::digest(kernel).into(),
+            boot_images: vec![kernel.to_vec()],
+            reboot: true,
         })
     }
 
@@ -140,12 +405,40 @@ impl NativeVm {
         Self::new(kernel, num_harts)
     }
 
+    /// Run the riscv-tests ISA suites (rv64ui/um/ua/uc) found in `dir`.
+    ///
+    /// Each test binary gets a fresh single-hart machine; see
+    /// [`crate::compliance`] for the pass/fail convention.
+    pub fn run_compliance_tests<P: AsRef<std::path::Path>>(dir: P) -> io::Result<ComplianceReport> {
+        compliance::run_suite(
+            dir,
+            compliance::DEFAULT_SUITES,
+            compliance::DEFAULT_MAX_STEPS,
+        )
+    }
+
     /// Load a disk image and attach as VirtIO block device.
+    ///
+    /// The image lives in memory, so guest writes are lost on exit; use
+    /// [`attach_disk`](Self::attach_disk) with a file backend to keep them.
     pub fn load_disk(&mut self, disk: Vec<u8>) {
+        self.attach_disk(Box::new(crate::disk::MemoryDisk::new(disk)));
+    }
+
+    /// Attach a VirtIO block device served by `backend`, e.g. a host image
+    /// file opened with [`crate::disk::open`].
+    pub fn attach_disk(&mut self, backend: Box<dyn crate::disk::BlockBackend>) {
         use crate::devices::virtio::VirtioBlock;
 
         if let Some(bus) = Arc::get_mut(&mut self.bus) {
-            let vblk = VirtioBlock::new(disk);
+            let vblk = VirtioBlock::with_backend(backend);
+            let disks = self
+                .device_latency
+                .iter()
+                .filter(|(name, _)| name.starts_with("disk"))
+                .count();
+            self.device_latency
+                .push((format!("disk{}", disks), vblk.latency_handle()));
             bus.virtio_devices.push(Box::new(vblk));
             println!("[VM] Loaded disk image");
         } else {
@@ -153,25 +446,330 @@ impl NativeVm {
         }
     }
 
+    /// Export `backend` to the guest as a VirtIO 9p device with mount tag
+    /// `tag`, e.g. a host directory opened with [`crate::share::HostDir::new`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn attach_share(
+        &mut self,
+        tag: &str,
+        backend: Box<dyn crate::share::ShareBackend>,
+    ) -> Result<(), String> {
+        use crate::devices::virtio::Virtio9p;
+
+        let Some(bus) = Arc::get_mut(&mut self.bus) else {
+            return Err("cannot attach share: workers already running".to_string());
+        };
+        let p9 = Virtio9p::new(tag, backend)?;
+        bus.virtio_devices.push(Box::new(p9));
+        Ok(())
+    }
+
+    /// Map an embedder peripheral at `range`. See
+    /// [`crate::devices::mmio`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn register_device(
+        &mut self,
+        range: std::ops::Range<u64>,
+        device: Box<dyn crate::devices::mmio::MmioDevice>,
+    ) -> Result<(), String> {
+        let Some(bus) = Arc::get_mut(&mut self.bus) else {
+            return Err("cannot register device: workers already running".to_string());
+        };
+        bus.register_device(range, device)
+    }
+
+    /// Replace the boot ROM's first-stage loader with `image`, e.g. a
+    /// board's reset code. See [`crate::devices::bootrom`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_boot_rom(&self, image: Vec<u8>) -> Result<(), String> {
+        self.bus.boot_rom.set_image(image)
+    }
+
+    /// Start every hart at `pc` instead of the boot ROM.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_reset_vector(&mut self, pc: u64) {
+        self.bus.boot_rom.set_reset_vector(pc);
+        if let Some(cpu) = &mut self.primary_cpu {
+            cpu.pc = pc;
+        }
+    }
+
+    /// Boot the kernel in S-mode under the built-in SBI firmware instead of
+    /// in M-mode through the boot ROM, e.g. a Linux image that expects
+    /// OpenSBI. See [`crate::cpu::sbi`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn enable_sbi(&mut self) -> Result<(), String> {
+        let Some(cpu) = self.primary_cpu.as_mut() else {
+            return Err("cannot enable SBI: VM already running".to_string());
+        };
+        let config = SbiConfig {
+            entry: self.entry_pc,
+            fdt: self.bus.boot_rom.fdt_addr().unwrap_or(0),
+            clint_base: self.bus.config().clint_base,
+            uart_base: self.bus.config().uart_base,
+        };
+        cpu.enable_sbi(config);
+        self.sbi = Some(config);
+        Ok(())
+    }
+
+    /// Set the vector register width (VLEN) of every hart, a power of two
+    /// from 64 to [`MAX_VLEN`](crate::cpu::vector::MAX_VLEN) bits. See [`crate::cpu::vector`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_vlen(&mut self, vlen: usize) -> Result<(), String> {
+        let Some(cpu) = self.primary_cpu.as_mut() else {
+            return Err("cannot set VLEN: VM already running".to_string());
+        };
+        cpu.set_vlen(vlen)?;
+        self.vlen = vlen;
+        Ok(())
+    }
+
+    /// Run every hart on the predecoded block engine (see
+    /// [`crate::engine::block`]) instead of interpreting one instruction per
+    /// step. Guest time and the instructions retired are the same either
+    /// way. [`enable_jit`](Self::enable_jit) implies this.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn enable_blocks(&mut self) {
+        if let Some(cpu) = self.primary_cpu.as_mut() {
+            cpu.use_blocks = true;
+        }
+        self.blocks = true;
+    }
+
+    /// Choose what every hart does when the guest accesses a CSR it does not
+    /// implement: read zero and ignore writes (the default), or trap with
+    /// an illegal instruction as the privileged spec requires. See
+    /// [`CsrPolicy`].
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_csr_policy(&mut self, policy: CsrPolicy) {
+        if let Some(cpu) = self.primary_cpu.as_mut() {
+            cpu.set_csr_policy(policy);
+        }
+        self.csr_policy = policy;
+    }
+
+    /// Set the kernel command line, which the guest finds in the device
+    /// tree's `/chosen/bootargs`. The kernel understands `plain`, for
+    /// plain-text output from the first line of the boot log on.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_bootargs(&mut self, bootargs: &str) -> Result<(), String> {
+        let fdt = crate::devices::fdt::generate(self.bus.config(), self.num_harts, bootargs);
+        self.bus.boot_rom.set_fdt(fdt)
+    }
+
+    /// Load firmware (ELF, or a raw image at the DRAM base) and have the
+    /// boot ROM enter it instead of the kernel, with the hart ID in `a0` and
+    /// the device tree address in `a1` as OpenSBI expects. The kernel stays
+    /// where it was loaded, so it must not overlap the firmware: use an ELF
+    /// kernel linked above it, e.g. at `0x8020_0000` for OpenSBI's
+    /// `fw_jump`. Returns the firmware entry point.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn load_firmware(&mut self, image: &[u8]) -> Result<u64, String> {
+        let entry = load_boot_image(image, &self.bus)?;
+        self.boot_images.push(image.to_vec());
+        let mut config = self
+            .bus
+            .boot_rom
+            .config()
+            .ok_or("boot ROM mailbox is not configured")?;
+        config.entry = entry;
+        config.boot_arg = self.bus.boot_rom.fdt_addr().unwrap_or(0);
+        self.bus.boot_rom.configure(&config);
+        Ok(entry)
+    }
+
+    /// Let the guest make semihosting calls (console, files, command line
+    /// and exit) through the MMIO device at `BusConfig::semihost_base`, as
+    /// far as `policy` allows. See [`crate::devices::semihost`].
+    pub fn enable_semihosting(&self, policy: crate::devices::semihost::SemihostPolicy) {
+        self.bus.semihost.set_policy(Some(policy));
+    }
+
+    /// Map `window` into the guest through the shared memory device at
+    /// `BusConfig::ivshmem_base`, as peer number `peer`. Another VM in this
+    /// process attached to a clone of the same window sees the same memory
+    /// and can ring this one's doorbell. See [`crate::devices::ivshmem`].
+    pub fn attach_shared_memory(
+        &self,
+        window: crate::shared_mem::SharedWindow,
+        peer: u32,
+    ) -> Result<(), String> {
+        self.bus.ivshmem.attach(window, peer)
+    }
+
+    /// The host end of the guest's message mailbox at
+    /// `BusConfig::mailbox_base`. Usable from any thread while the VM
+    /// runs. See [`crate::devices::mailbox`].
+    pub fn mailbox(&self) -> crate::devices::mailbox::MailboxPort {
+        self.bus.mailbox.port()
+    }
+
+    /// Raise external interrupt line `irq` (1-31) through the PLIC, e.g.
+    /// for a device modelled by the embedder. Safe to call from any thread
+    /// while the VM runs; the line stays pending until [`Self::clear_irq`].
+    pub fn raise_irq(&self, irq: u32) -> Result<(), String> {
+        self.bus.raise_irq(irq)
+    }
+
+    /// Lower an external interrupt line raised with [`Self::raise_irq`].
+    pub fn clear_irq(&self, irq: u32) -> Result<(), String> {
+        self.bus.clear_irq(irq)
+    }
+
     /// Connect to a WebTransport relay for networking.
     ///
     /// Must be called before `run()` / `start_workers()`.
     /// The network backend is automatically wrapped in `AsyncNetworkBackend`
     /// for non-blocking I/O and better performance.
     pub fn connect_webtransport(&mut self, url: &str, cert_hash: Option<String>) {
-        use crate::devices::virtio::VirtioNet;
-        use crate::net::async_backend::AsyncNetworkBackend;
-        use crate::net::webtransport::WebTransportBackend;
+        self.connect_webtransport_with(url, cert_hash, BatchConfig::default());
+    }
 
-        if let Some(bus) = Arc::get_mut(&mut self.bus) {
-            let backend = WebTransportBackend::new(url, cert_hash);
-            let async_backend = AsyncNetworkBackend::new(Box::new(backend));
-            let vnet = VirtioNet::new(Box::new(async_backend));
-            bus.virtio_devices.push(Box::new(vnet));
-            println!("[VM] WebTransport network configured (async): {}", url);
-        } else {
-            eprintln!("[VM] Cannot configure network: workers already running");
+    /// Connect to a WebTransport relay, batching outgoing frames with
+    /// `batching` if the relay speaks protocol v2.
+    pub fn connect_webtransport_with(
+        &mut self,
+        url: &str,
+        cert_hash: Option<String>,
+        batching: BatchConfig,
+    ) {
+        let backend = NetBackend::WebTransport {
+            url: url.to_string(),
+            cert_hash,
+            batching,
+            resume: None,
+        };
+        if let Err(e) = self.attach_network(backend) {
+            eprintln!("[VM] {}", e);
+        }
+    }
+
+    /// Connect to a WebTransport relay as the NIC of a VM restored with
+    /// [`restore_migration`](Self::restore_migration): `mac` is the NIC's
+    /// old address and `ticket` the migration's, with which the relay
+    /// hands the guest its old IP address.
+    pub fn resume_webtransport(
+        &mut self,
+        url: &str,
+        cert_hash: Option<String>,
+        batching: BatchConfig,
+        mac: [u8; 6],
+        ticket: &str,
+    ) -> Result<(), String> {
+        self.attach_network(NetBackend::WebTransport {
+            url: url.to_string(),
+            cert_hash,
+            batching,
+            resume: Some((mac, ticket.to_string())),
+        })
+    }
+
+    /// Attach a VirtIO NIC whose frames go to `backend`, in the next free
+    /// VirtIO slot.
+    ///
+    /// Call it once per network the guest should be on, e.g. twice for a
+    /// guest routing between two LANs. Each NIC gets its backend's MAC
+    /// address, or the next free one if another NIC already has it. NICs
+    /// can be unplugged, and new ones plugged in, while the guest runs
+    /// through [`network_hotplug`](Self::network_hotplug).
+    ///
+    /// Must be called before `run()` / `start_workers()`. The backend runs
+    /// on its own I/O thread behind `AsyncNetworkBackend`. A recorded or
+    /// replayed run has a single NIC.
+    pub fn attach_network(&mut self, backend: NetBackend) -> Result<(), String> {
+        if Arc::get_mut(&mut self.bus).is_none() {
+            return Err("cannot configure network: workers already running".to_string());
+        }
+        if self.bus.virtio_devices.len() >= VIRTIO_SLOTS as usize {
+            return Err("cannot attach network: no free VirtIO slot".to_string());
+        }
+        let log = self.bus.replay.clone();
+        if log.is_some() && !nic_macs(&self.bus).is_empty() {
+            return Err("a recorded or replayed run has a single network interface".to_string());
+        }
+        let link = open_network_backend(backend, log)?;
+        let mac = unique_mac(&self.bus, link.backend.mac_address());
+        self.net_metrics = self.net_metrics.take().or(link.metrics);
+        let bus = Arc::get_mut(&mut self.bus).expect("bus checked above");
+        let nic = VirtioNet::with_mac(link.backend, mac);
+        bus.virtio_devices
+            .push(Box::new(HotplugSlot::with_device(Box::new(nic))));
+        bus.buildinfo.set_network(Some(link.name));
+        Ok(())
+    }
+
+    /// Add an empty VirtIO slot that NICs can be plugged into while the
+    /// guest runs (see [`network_hotplug`](Self::network_hotplug)), and
+    /// return its number.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn add_network_slot(&mut self) -> Result<usize, String> {
+        let Some(bus) = Arc::get_mut(&mut self.bus) else {
+            return Err("cannot configure network: workers already running".to_string());
+        };
+        if bus.virtio_devices.len() >= VIRTIO_SLOTS as usize {
+            return Err("cannot add network slot: no free VirtIO slot".to_string());
+        }
+        bus.virtio_devices.push(Box::new(HotplugSlot::new()));
+        Ok(bus.virtio_devices.len() - 1)
+    }
+
+    /// A handle that plugs NICs into and out of this VM from any thread,
+    /// including while [`run`](Self::run) is going. Take it once the
+    /// machine is configured: the handle shares the bus, so `attach_*`
+    /// calls fail while it is alive.
+    pub fn network_hotplug(&self) -> NetworkHotplug {
+        NetworkHotplug {
+            bus: Arc::clone(&self.bus),
+        }
+    }
+
+    /// Attach a VirtIO GPU with one scanout per (width, height) in `modes`.
+    ///
+    /// Must be called before `run()` / `start_workers()`. Scanout contents
+    /// are read back through [`gpu_display`](Self::gpu_display).
+    pub fn attach_gpu(&mut self, modes: &[(u32, u32)]) -> Result<(), String> {
+        if self.gpu.is_some() {
+            return Err("a GPU is already attached".to_string());
         }
+        let Some(bus) = Arc::get_mut(&mut self.bus) else {
+            return Err("cannot attach GPU: workers already running".to_string());
+        };
+        let gpu = VirtioGpu::new(modes)?;
+        self.gpu = Some(gpu.display());
+        bus.virtio_devices.push(Box::new(gpu));
+        Ok(())
+    }
+
+    /// Attach a VirtIO console, a second byte stream next to the UART, and
+    /// return its host end. See [`crate::console::bridge_port`] to connect
+    /// it to a socket or file.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn attach_console(&mut self) -> Result<ConsolePort, String> {
+        let Some(bus) = Arc::get_mut(&mut self.bus) else {
+            return Err("cannot attach console: workers already running".to_string());
+        };
+        let console = VirtioConsole::new();
+        let port = console.port();
+        bus.virtio_devices.push(Box::new(console));
+        Ok(port)
+    }
+
+    /// Scanouts of the attached GPU, if any.
+    pub fn gpu_display(&self) -> Option<&Arc<GpuDisplay>> {
+        self.gpu.as_ref()
     }
 
     /// Get the number of harts.
@@ -201,6 +799,11 @@ impl NativeVm {
         self.bus.sysinfo.disk_usage()
     }
 
+    /// Get the kernel's `uname -a` line, empty until the guest reports it.
+    pub fn get_kernel_id(&self) -> String {
+        self.bus.sysinfo.kernel_id()
+    }
+
     /// Get the total disk capacity from attached VirtIO block devices.
     /// Returns total bytes across all block devices.
     pub fn get_disk_capacity(&self) -> u64 {
@@ -230,17 +833,334 @@ impl NativeVm {
         self.bus.sysinfo.uptime_ms()
     }
 
+    /// Set the modelled CPU clock in Hz. Guest time (`mtime`) advances by
+    /// one second per `hz` cycles hart 0 executes, whichever engine runs
+    /// them; see [`crate::engine::timing`].
+    pub fn set_cpu_frequency(&self, hz: u64) {
+        self.bus.clint.set_cpu_frequency(hz);
+    }
+
+    /// Keep the host clock out of guest time. `mtime` already advances by
+    /// the cycles hart 0 executes; with this on, the RTC counts from
+    /// 2000-01-01 by `mtime` instead of reporting the host's date, and an
+    /// idle hart 0 skips to its timer deadline even when something wakes
+    /// it early. A single-hart run then behaves the same on every host
+    /// and at every speed. Recording and replaying turn it on.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_deterministic_time(&mut self, enabled: bool) {
+        self.pacing.deterministic = enabled;
+        self.bus
+            .rtc
+            .set_deterministic(enabled.then_some(DETERMINISTIC_EPOCH_NS));
+    }
+
+    /// Enable per-page DRAM checksums and start a background thread that
+    /// verifies a sample of cold pages every `config.interval`.
+    ///
+    /// Must be called before [`run`](Self::run). Detected corruption is
+    /// logged and queued for [`drain_integrity_events`](Self::drain_integrity_events).
+    pub fn enable_integrity_checker(&mut self, config: IntegrityConfig) -> bool {
+        if !self.bus.dram.enable_integrity() {
+            return false;
+        }
+        let bus = Arc::clone(&self.bus);
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::Builder::new()
+            .name("dram-check".to_string())
+            .spawn(move || {
+                // A reboot pauses the harts, not the checker
+                while !shared.is_halt_requested() && !shared.is_halted() {
+                    thread::sleep(config.interval);
+                    bus.dram.verify_integrity(config.pages_per_pass);
+                }
+            })
+            .expect("Failed to spawn DRAM integrity thread");
+        self.checker = Some(handle);
+        true
+    }
+
+    /// Run hot blocks as native code on every hart.
+    ///
+    /// Must be called before [`run`](Self::run). Fails if Cranelift does
+    /// not support the host ISA.
+    #[cfg(feature = "jit-native")]
+    pub fn enable_jit(&mut self, config: JitConfig) -> Result<(), String> {
+        if let Some(cpu) = self.primary_cpu.as_mut() {
+            cpu.enable_jit(config)?;
+            self.jit_diagnostics.extend(attach_jit_diagnostics(cpu));
+        }
+        self.jit = Some(config);
+        self.bus.buildinfo.set_jit_enabled(true);
+        Ok(())
+    }
+
+    /// Compile the blocks recorded in `profile` (see
+    /// [`jit_profile`](Self::jit_profile)) on their first run rather than
+    /// once they are hot.
+    ///
+    /// Must be called after [`enable_jit`](Self::enable_jit) and before
+    /// [`run`](Self::run). Returns the number of blocks in the profile.
+    #[cfg(feature = "jit-native")]
+    pub fn load_jit_profile(&mut self, profile: &JitProfile) -> Result<usize, String> {
+        if self.jit.is_none() {
+            return Err("the JIT is not enabled".to_string());
+        }
+        if profile.image != self.kernel_sha256 {
+            return Err("profile was recorded with a different kernel image".to_string());
+        }
+        let cpu = self
+            .primary_cpu
+            .as_mut()
+            .ok_or("hart 0 is already running")?;
+        if let Some(jit) = cpu.jit.as_mut() {
+            jit.preload(&profile.blocks);
+        }
+        self.jit_preload = profile.blocks.clone();
+        Ok(profile.blocks.len())
+    }
+
+    /// Trace instructions executed by hart 0.
+    ///
+    /// Must be called before [`run`](Self::run). Hart 0 runs in the
+    /// interpreter while the tracer is enabled.
+    pub fn set_tracer(&mut self, tracer: Tracer) -> Result<(), String> {
+        let cpu = self
+            .primary_cpu
+            .as_mut()
+            .ok_or("hart 0 is already running")?;
+        cpu.set_tracer(Some(tracer));
+        Ok(())
+    }
+
+    /// Sleep while a hart waits in WFI with no interrupt pending (the
+    /// default), instead of spinning through the guest's idle loop. Hart 0
+    /// sleeps until its next timer deadline, at most 10 ms at a time so
+    /// console and network input are still picked up, and fast-forwards
+    /// guest time by as long as it slept.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_idle(&mut self, enabled: bool) {
+        self.pacing.idle = enabled;
+    }
+
+    /// Reset the machine and boot it again when the guest asks for a
+    /// reboot (the default), or stop with [`VmExit::Reboot`] instead. A
+    /// reboot resets the devices, reloads the kernel and firmware images
+    /// and starts every hart over from the reset vector; DRAM is not
+    /// cleared and disks keep what the guest wrote.
+    pub fn set_reboot(&mut self, enabled: bool) {
+        self.reboot = enabled;
+    }
+
+    /// Cap every hart at `mips` million instructions per second of host
+    /// time, or lift the cap with `None`.
+    ///
+    /// Must be called before `run()` / `start_workers()`.
+    pub fn set_max_mips(&mut self, mips: Option<u32>) {
+        self.pacing.max_mips = mips.filter(|&mips| mips > 0);
+    }
+
+    /// Print a backtrace of the guest stack, symbolized with `symbols`,
+    /// when a hart halts on a fatal error. See [`crate::backtrace`].
+    pub fn enable_backtrace(&mut self, symbols: SymbolMap) {
+        self.backtrace = Some(Arc::new(symbols));
+    }
+
+    /// Write a crash dump (see [`crate::crash_dump`]) to `path` if a hart
+    /// halts the VM on a fatal error. The dump is taken either way and
+    /// available from [`crash_dump`](Self::crash_dump).
+    pub fn set_crash_dump(&mut self, path: impl Into<PathBuf>) {
+        self.crash_dump = Some(path.into());
+    }
+
+    /// Record every non-deterministic input of the run (see
+    /// [`crate::replay`]) so it can be reproduced with
+    /// [`replay`](Self::replay). The log is available from
+    /// [`take_recording`](Self::take_recording) once [`run`](Self::run)
+    /// returns.
+    ///
+    /// Must be called before networking is connected and before `run`,
+    /// on a single-hart machine.
+    pub fn start_recording(&mut self) -> Result<(), String> {
+        self.attach_input_log(InputLog::recording())
+    }
+
+    /// Re-run a recorded session: console, network and interrupt inputs
+    /// come from `recording` instead of the host, and the run stops where
+    /// the recording did.
+    ///
+    /// Same preconditions as [`start_recording`](Self::start_recording);
+    /// the machine must also be configured as it was when recording, which
+    /// `run` checks before starting.
+    pub fn replay(&mut self, recording: Recording) -> Result<(), String> {
+        self.attach_input_log(InputLog::replaying(recording))
+    }
+
+    fn attach_input_log(&mut self, log: InputLog) -> Result<(), String> {
+        if self.num_harts != 1 {
+            return Err("record/replay needs a single hart".to_string());
+        }
+        let bus = Arc::get_mut(&mut self.bus).ok_or("hart 0 is already running")?;
+        if bus.replay.is_some() {
+            return Err("an input log is already attached".to_string());
+        }
+        if bus
+            .virtio_devices
+            .iter()
+            .any(|d| d.device_id() == VIRTIO_NET_DEVICE_ID)
+        {
+            return Err("networking must be connected after the input log".to_string());
+        }
+        bus.replay = Some(Arc::new(log));
+        self.set_deterministic_time(true);
+        Ok(())
+    }
+
+    /// Inputs logged by a run started with
+    /// [`start_recording`](Self::start_recording).
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    /// Configuration of this machine that a replay has to match.
+    fn machine(&self, cpu: &Cpu) -> Machine {
+        #[cfg(feature = "jit-native")]
+        let jit = cpu.jit.is_some();
+        #[cfg(not(feature = "jit-native"))]
+        let jit = false;
+        let engine = if cpu.tracer().is_some_and(Tracer::is_enabled) || !cpu.use_blocks {
+            Engine::Interpreter
+        } else if jit {
+            Engine::Jit
+        } else {
+            Engine::Blocks
+        };
+        Machine {
+            kernel_sha256: self.kernel_sha256,
+            dram_size: self.bus.config().dram_size as u64,
+            cpu_frequency: self.bus.clint.cpu_frequency(),
+            engine,
+            network: self
+                .bus
+                .virtio_devices
+                .iter()
+                .any(|d| d.device_id() == VIRTIO_NET_DEVICE_ID),
+        }
+    }
+
+    /// JIT diagnostics summed over all harts, if the JIT is enabled.
+    ///
+    /// Harts refresh their figures periodically while running and when
+    /// they stop, so this can be polled from another thread during
+    /// [`run`](Self::run) via a shared reference.
+    #[cfg(feature = "jit-native")]
+    pub fn jit_diagnostics(&self) -> Option<JitDiagnostics> {
+        if self.jit_diagnostics.is_empty() {
+            return None;
+        }
+        let mut total = JitDiagnostics::default();
+        for handle in &self.jit_diagnostics {
+            if let Ok(diag) = handle.lock() {
+                total.merge(&diag);
+            }
+        }
+        Some(total)
+    }
+
+    /// Blocks compiled so far on all harts, for
+    /// [`load_jit_profile`](Self::load_jit_profile) in a later session.
+    #[cfg(feature = "jit-native")]
+    pub fn jit_profile(&self) -> Option<JitProfile> {
+        let diag = self.jit_diagnostics()?;
+        Some(JitProfile::new(self.kernel_sha256, diag.profile))
+    }
+
+    /// Relay transport counters, if networking is configured.
+    pub fn transport_metrics(&self) -> Option<TransportMetrics> {
+        let handle = self.net_metrics.as_ref()?;
+        handle.lock().ok().map(|m| m.clone())
+    }
+
+    /// Guest-visible request latency of each device served by a worker
+    /// thread.
+    pub fn device_latency(&self) -> Vec<(String, DeviceLatency)> {
+        self.device_latency
+            .iter()
+            .filter_map(|(name, handle)| Some((name.clone(), handle.lock().ok()?.clone())))
+            .collect()
+    }
+
+    /// Interrupt polls and latency of every hart since the VM was created,
+    /// to weigh how promptly harts take interrupts against how fast they
+    /// run.
+    ///
+    /// Harts publish their figures periodically while running and when
+    /// they stop, so this can be polled from another thread during
+    /// [`run`](Self::run) via a shared reference.
+    pub fn stats(&self) -> VmStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        if let Some(cpu) = &self.primary_cpu {
+            stats.add(0, &cpu.interrupt_latency());
+        }
+        stats
+    }
+
+    /// DRAM integrity counters, if the checker is enabled.
+    pub fn integrity_stats(&self) -> Option<IntegrityStats> {
+        self.bus.dram.integrity().map(|map| map.stats())
+    }
+
+    /// Corruption events detected since the last call.
+    pub fn drain_integrity_events(&self) -> Vec<CorruptionEvent> {
+        self.bus
+            .dram
+            .integrity()
+            .map(|map| map.drain_events())
+            .unwrap_or_default()
+    }
+
+    /// A hart at the reset vector, with the settings every hart runs with.
+    fn new_hart(&mut self, hart_id: usize) -> Cpu {
+        let mut cpu = Cpu::new(self.bus.boot_rom.reset_vector(), hart_id as u64);
+        if let Some(config) = self.sbi {
+            cpu.enable_sbi(config);
+        }
+        cpu.set_vlen(self.vlen)
+            .expect("VLEN was checked by set_vlen");
+        cpu.use_blocks = self.blocks;
+        cpu.set_csr_policy(self.csr_policy);
+        #[cfg(feature = "jit-native")]
+        if let Some(config) = self.jit {
+            match cpu.enable_jit(config) {
+                Ok(()) => {
+                    if let Some(jit) = cpu.jit.as_mut() {
+                        jit.preload(&self.jit_preload);
+                    }
+                    self.jit_diagnostics
+                        .extend(attach_jit_diagnostics(&mut cpu));
+                }
+                Err(e) => eprintln!("[Hart {}] JIT unavailable: {}", hart_id, e),
+            }
+        }
+        cpu
+    }
+
     /// Start worker threads for secondary harts.
     pub fn start_workers(&mut self) {
         for hart_id in 1..self.num_harts {
+            let cpu = self.new_hart(hart_id);
             let bus = Arc::clone(&self.bus);
             let shared = Arc::clone(&self.shared);
-            let entry_pc = self.entry_pc;
+            let symbols = self.backtrace.clone();
+            let fatal = Arc::clone(&self.fatal);
+            let stats = Arc::clone(&self.stats);
+            let pacing = self.pacing;
 
             let handle = thread::Builder::new()
                 .name(format!("hart-{}", hart_id))
                 .spawn(move || {
-                    hart_thread(hart_id, entry_pc, bus, shared);
+                    hart_thread(hart_id, cpu, bus, shared, symbols, fatal, stats, pacing);
                 })
                 .expect("Failed to spawn hart thread");
 
@@ -249,13 +1169,70 @@ impl NativeVm {
         }
     }
 
+    /// The error that halted the VM, with the PC, privilege mode and
+    /// instruction it was raised on, if a hart hit one.
+    pub fn fatal_error(&self) -> Option<TrapInfo> {
+        self.fatal
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(info, _)| info.clone())
+    }
+
+    /// Crash dump of the hart that halted the VM on a fatal error, taken
+    /// when it did, if one has.
+    pub fn crash_dump(&self) -> Option<CrashDump> {
+        self.fatal
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(_, dump)| dump.clone())
+    }
+
     /// Check if workers have been started.
     pub fn workers_started(&self) -> bool {
         !self.handles.is_empty() || self.num_harts == 1
     }
 
-    /// Run the VM until halted.
-    pub fn run(&mut self) {
+    /// Run the VM until halted, rebooting it whenever the guest asks to
+    /// (see [`set_reboot`](Self::set_reboot)).
+    ///
+    /// Returns how the guest ended the run, `None` if the host stopped it
+    /// first (Ctrl-A x or the end of a replay), or the fatal error a hart
+    /// halted on.
+    pub fn run(&mut self) -> Result<Option<VmExit>, TrapInfo> {
+        self.run_with_console(Console::new());
+        self.exit()
+    }
+
+    /// How the last run ended, as returned by [`run`](Self::run).
+    pub fn exit(&self) -> Result<Option<VmExit>, TrapInfo> {
+        if let Some(info) = self.fatal_error() {
+            return Err(info);
+        }
+        Ok(self
+            .shared
+            .is_halted()
+            .then(|| VmExit::from_code(self.shared.halt_code())))
+    }
+
+    fn run_with_console(&mut self, console: Console) {
+        let input_log = self.bus.replay.clone();
+        let machine = self.primary_cpu.as_ref().map(|cpu| self.machine(cpu));
+        if let (Some(log), Some(machine)) = (&input_log, &machine) {
+            if let Some(recorded) = log.replayed_machine() {
+                if let Some(mismatch) = machine.mismatch(&recorded) {
+                    eprintln!("[VM] Cannot replay: {}", mismatch);
+                    self.shared.signal_halted(0xDEAD);
+                    return;
+                }
+                println!("[VM] Replaying {} recorded inputs", log.remaining());
+            } else {
+                println!("[VM] Recording inputs");
+            }
+            self.bus.set_synchronous_io(true);
+        }
+
         if !self.workers_started() {
             self.start_workers();
         }
@@ -264,7 +1241,6 @@ impl NativeVm {
         let mut step_count: u64 = 0;
         let start_time = Instant::now();
 
-        let console = Console::new();
         let mut escaped = false;
 
         let mut last_report_time = Instant::now();
@@ -276,36 +1252,72 @@ impl NativeVm {
         const BATCH_SIZE: u64 = 256;
         const VIRTIO_POLL_INTERVAL: u64 = 4096;
         const CONSOLE_POLL_INTERVAL: u64 = 16384;
+        let mut next_virtio_poll = VIRTIO_POLL_INTERVAL;
+        let mut next_console_poll = CONSOLE_POLL_INTERVAL;
+        let mut throttle = self.pacing.max_mips.map(Throttle::new);
 
         loop {
             if self.shared.should_stop() {
+                if self.shared.is_reboot_pending() {
+                    if self.reboot {
+                        self.reboot(&mut cpu);
+                        continue;
+                    }
+                    self.shared.signal_halted(FINISHER_RESET);
+                }
                 break;
             }
 
             let (batch_steps, halt_reason) = self.execute_batch(&mut cpu, BATCH_SIZE);
             step_count += batch_steps;
+            if let Some(throttle) = &mut throttle {
+                throttle.pace(batch_steps);
+            }
 
             if let Some(reason) = halt_reason {
                 match reason {
+                    HaltReason::Shutdown(code) if VmExit::from_code(code) == VmExit::Reboot => {
+                        println!("[VM] Reboot requested");
+                        self.shared.request_reboot();
+                        continue;
+                    }
                     HaltReason::Shutdown(code) => {
                         println!("[VM] Shutdown requested (code: {:#x})", code);
                         self.shared.signal_halted(code);
                         break;
                     }
-                    HaltReason::Fatal(msg, pc) => {
-                        eprintln!("[VM] Fatal error: {} at PC=0x{:x}", msg, pc);
-                        self.shared.signal_halted(0xDEAD);
+                    HaltReason::Fatal(info) => {
+                        eprintln!("[VM] Fatal error: {}", info);
+                        if let Some(symbols) = &self.backtrace {
+                            eprint!("{}", backtrace::unwind(&cpu, &self.bus, symbols));
+                        }
+                        signal_fatal(&self.fatal, &self.shared, &cpu, &self.bus, info);
                         break;
                     }
                 }
             }
 
-            if step_count % VIRTIO_POLL_INTERVAL == 0 {
+            // Poll devices right after idling, which is when input arrives
+            let idled = self.idle(&mut cpu);
+            if idled && let Some(throttle) = &mut throttle {
+                throttle.restart();
+            }
+
+            if step_count >= next_virtio_poll || idled {
+                next_virtio_poll = step_count + VIRTIO_POLL_INTERVAL;
+                self.bus.sync_replay(cpu.cycles);
+                if input_log.as_ref().is_some_and(|log| log.replay_finished()) {
+                    println!("[VM] Replay reached the end of the recording");
+                    self.shared.request_halt();
+                    break;
+                }
                 self.bus.poll_virtio();
             }
 
-            if step_count % CONSOLE_POLL_INTERVAL == 0 {
+            if step_count >= next_console_poll || idled {
+                next_console_poll = step_count + CONSOLE_POLL_INTERVAL;
                 self.pump_console(&console, &mut escaped);
+                publish_stats(&self.stats, 0, &mut cpu);
 
                 if log::log_enabled!(log::Level::Debug) {
                     let now = Instant::now();
@@ -323,6 +1335,9 @@ impl NativeVm {
                             current_ips / 1_000_000.0,
                             cpu.pc
                         );
+                        if let Some(stats) = self.integrity_stats() {
+                            log::debug!("[VM] DRAM integrity: {:?}", stats);
+                        }
                         last_report_time = now;
                         last_report_steps = step_count;
                     }
@@ -330,7 +1345,33 @@ impl NativeVm {
             }
         }
 
+        if let (Some(log), Some(machine)) = (&input_log, machine) {
+            log.set_clock(cpu.cycles);
+            self.recording = log.finish(machine);
+            if log.remaining() > 0 {
+                println!(
+                    "[VM] Replay stopped with {} recorded inputs undelivered",
+                    log.remaining()
+                );
+            }
+        }
+
+        if let Some(tracer) = cpu.tracer_mut() {
+            if let Err(e) = tracer.flush() {
+                eprintln!("[VM] Failed to write trace: {}", e);
+            }
+            println!("[VM] Traced {} instructions on hart 0", tracer.recorded());
+        }
+
+        // Final JIT figures for hart 0 (the other harts publish on exit)
+        #[cfg(feature = "jit-native")]
+        if let Some(jit) = cpu.jit.as_ref() {
+            jit.publish();
+        }
+
         self.shutdown();
+        self.write_crash_dump();
+        publish_stats(&self.stats, 0, &mut cpu);
 
         let elapsed = start_time.elapsed().as_secs_f64();
         let ips = if elapsed > 0.0 {
@@ -343,6 +1384,304 @@ impl NativeVm {
             step_count,
             ips / 1_000_000.0
         );
+        if let Some(stats) = self.integrity_stats() {
+            println!(
+                "[VM] DRAM integrity: {} pages verified, {} skipped, {} corrupted",
+                stats.pages_verified, stats.pages_skipped, stats.corruptions
+            );
+        }
+        if cpu.use_blocks {
+            let (_, _, blocks, hit_rate) = cpu.block_cache.stats();
+            let (prefetched, _, prefetch_rate) = cpu.block_cache.prefetch_stats();
+            println!(
+                "[VM] Block cache (hart 0): {} blocks, {:.1}% hits, {} prefetched ({:.1}% run)",
+                blocks,
+                hit_rate * 100.0,
+                prefetched,
+                prefetch_rate * 100.0
+            );
+        }
+        let (itlb, dtlb) = cpu.tlb.stats();
+        if itlb.hits + itlb.misses + dtlb.hits + dtlb.misses > 0 {
+            println!(
+                "[VM] TLB (hart 0): I {:.1}% hits ({} misses), D {:.1}% hits ({} misses)",
+                itlb.hit_rate() * 100.0,
+                itlb.misses,
+                dtlb.hit_rate() * 100.0,
+                dtlb.misses
+            );
+        }
+        #[cfg(feature = "jit-native")]
+        if let Some(diag) = self.jit_diagnostics() {
+            println!("[VM] JIT: {}", diag);
+            if let Some(divergence) = &diag.first_divergence {
+                println!("[VM] First JIT divergence: {}", divergence);
+            }
+        }
+        if let Some(metrics) = self.transport_metrics() {
+            println!("[VM] Network: {}", metrics);
+        }
+        for (name, latency) in self.device_latency() {
+            if latency.completions > 0 {
+                println!("[VM] I/O latency ({}): {}", name, latency);
+            }
+        }
+        let interrupts = self.stats().interrupts();
+        if interrupts.all().count > 0 {
+            println!("[VM] Interrupts: {}", interrupts);
+        }
+
+        // Kept for `snapshot()`
+        self.primary_cpu = Some(cpu);
+    }
+
+    /// Wait on `conn` for a debugger's commands and run hart 0 under its
+    /// control (see [`crate::gdb`]), then let the guest run on when it
+    /// detaches or hangs up. Needs a single hart. A kill stops the VM.
+    pub fn run_gdb(&mut self, mut conn: gdb::Connection) -> Result<(), String> {
+        if self.num_harts != 1 {
+            return Err("debugging needs a single hart".to_string());
+        }
+        let mut cpu = self.primary_cpu.take().ok_or("hart 0 is already running")?;
+        let console = Console::new();
+        // One instruction per step, so every PC is checked for breakpoints
+        let use_blocks = std::mem::replace(&mut cpu.use_blocks, false);
+        if let Err(e) = self.serve_gdb(&mut cpu, &mut conn, &console) {
+            eprintln!("[VM] Debugger connection lost: {}", e);
+        }
+        cpu.use_blocks = use_blocks;
+        cpu.resume_watch();
+        cpu.clear_watchpoints();
+        self.primary_cpu = Some(cpu);
+        self.run_with_console(console);
+        Ok(())
+    }
+
+    fn serve_gdb(
+        &self,
+        cpu: &mut Cpu,
+        conn: &mut gdb::Connection,
+        console: &Console,
+    ) -> io::Result<()> {
+        let mut stub = GdbStub::new();
+        let mut escaped = false;
+        while let Some(packet) = conn.read_packet()? {
+            let single_step = match stub.handle(&packet, cpu, &self.bus) {
+                Request::Reply(reply) => {
+                    conn.send(&reply)?;
+                    continue;
+                }
+                Request::Detach => {
+                    conn.send("OK")?;
+                    println!("[VM] Debugger detached");
+                    return Ok(());
+                }
+                Request::Kill => {
+                    println!("[VM] Killed by debugger");
+                    self.shared.request_halt();
+                    return Ok(());
+                }
+                Request::Step => true,
+                Request::Continue => false,
+            };
+            match self.gdb_resume(cpu, &stub, conn, console, &mut escaped, single_step)? {
+                GdbStop::Signal(signal) => conn.send(&stub.stop_reply(cpu, signal))?,
+                GdbStop::Exited(code) => {
+                    conn.send(&format!("W{:02x}", code & 0xff))?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run hart 0 for one instruction, or until something stops it.
+    fn gdb_resume(
+        &self,
+        cpu: &mut Cpu,
+        stub: &GdbStub,
+        conn: &mut gdb::Connection,
+        console: &Console,
+        escaped: &mut bool,
+        single_step: bool,
+    ) -> io::Result<GdbStop> {
+        const POLL_INTERVAL: u64 = 4096;
+
+        cpu.resume_watch();
+        let mut steps: u64 = 0;
+        loop {
+            if self.shared.should_stop() {
+                return Ok(GdbStop::Exited(self.shared.halt_code()));
+            }
+            match self.execute_batch(cpu, 1).1 {
+                Some(HaltReason::Shutdown(code)) => {
+                    println!("[VM] Shutdown requested (code: {:#x})", code);
+                    self.shared.signal_halted(code);
+                    return Ok(GdbStop::Exited(code));
+                }
+                Some(HaltReason::Fatal(info)) => {
+                    eprintln!("[VM] Fatal error: {}", info);
+                    signal_fatal(&self.fatal, &self.shared, cpu, &self.bus, info);
+                    return Ok(GdbStop::Signal(gdb::SIGSEGV));
+                }
+                None => {}
+            }
+            steps += 1;
+            if single_step || cpu.watch_hit().is_some() || stub.has_breakpoint(cpu.pc) {
+                return Ok(GdbStop::Signal(gdb::SIGTRAP));
+            }
+
+            let idled = self.idle(cpu);
+            if steps.is_multiple_of(POLL_INTERVAL) || idled {
+                self.bus.poll_virtio();
+                self.pump_console(console, escaped);
+                if conn.interrupted()? {
+                    return Ok(GdbStop::Signal(gdb::SIGINT));
+                }
+            }
+        }
+    }
+
+    /// Snapshot of the machine after `run()` has returned, for a single
+    /// hart (the snapshot format holds one).
+    pub fn snapshot(&self) -> Result<Snapshot, String> {
+        if self.num_harts != 1 {
+            return Err("snapshots need a single hart".to_string());
+        }
+        let cpu = self.primary_cpu.as_ref().ok_or("hart 0 is still running")?;
+        Ok(Snapshot::capture(cpu, &self.bus))
+    }
+
+    /// Stream a snapshot of the machine after `run()` has returned straight
+    /// to the file at `path`, one chunk of DRAM at a time (see
+    /// [`Snapshot::write_stream`]). Same preconditions as
+    /// [`snapshot`](Self::snapshot).
+    pub fn save_snapshot(
+        &self,
+        path: impl AsRef<Path>,
+        compression: Compression,
+    ) -> Result<(), String> {
+        if self.num_harts != 1 {
+            return Err("snapshots need a single hart".to_string());
+        }
+        let cpu = self.primary_cpu.as_ref().ok_or("hart 0 is still running")?;
+        let path = path.as_ref();
+        let file = fs::File::create(path)
+            .map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+        let mut out = io::BufWriter::new(file);
+        Snapshot::write_stream(cpu, &self.bus, &mut out, compression)?;
+        out.flush()
+            .map_err(|e| format!("failed to write '{}': {}", path.display(), e))
+    }
+
+    /// Capture the machine after `run()` has returned as a migration image
+    /// for another host to carry on with (see [`crate::migration`]). Same
+    /// preconditions as [`snapshot`](Self::snapshot).
+    pub fn migration_image(&self, compression: Compression) -> Result<Vec<u8>, String> {
+        if self.num_harts != 1 {
+            return Err("migration needs a single hart".to_string());
+        }
+        let cpu = self.primary_cpu.as_ref().ok_or("hart 0 is still running")?;
+        migration::capture(cpu, &self.bus, compression)
+    }
+
+    /// Key the relay gave the VM's first NIC, to pass along with a
+    /// [`migration_image`](Self::migration_image) so the new host keeps
+    /// the NIC's address.
+    pub fn migration_key(&self) -> Option<String> {
+        migration::relay_key(&self.bus)
+    }
+
+    /// Take over the VM in the migration `image`, captured on a host with
+    /// the same kernel, DRAM size and devices, and return its header.
+    ///
+    /// Must be called before `run()` / `start_workers()`, after attaching
+    /// the devices.
+    pub fn restore_migration(&mut self, image: &[u8]) -> Result<MigrationHeader, String> {
+        if self.num_harts != 1 {
+            return Err("migration needs a single hart".to_string());
+        }
+        let cpu = self
+            .primary_cpu
+            .as_mut()
+            .ok_or("cannot restore: VM already running")?;
+        migration::restore(image, cpu, &self.bus)
+    }
+
+    /// Reset the machine for a guest reboot: wait for the other harts to
+    /// stop, reset the devices, reload the boot images and start every
+    /// hart over from the reset vector. Guest time and hart 0's tracer
+    /// carry on.
+    fn reboot(&mut self, cpu: &mut Cpu) {
+        println!("[VM] Rebooting...");
+        for handle in self.handles.drain(..) {
+            if let Err(e) = handle.join() {
+                eprintln!("[VM] Worker thread panicked: {:?}", e);
+            }
+        }
+
+        self.bus.reset_devices();
+        for image in &self.boot_images {
+            if let Err(e) = load_boot_image(image, &self.bus) {
+                // The images loaded at creation, so only a bug gets here
+                eprintln!("[VM] Cannot reload boot image: {}", e);
+                self.shared.signal_halted(0xDEAD);
+                return;
+            }
+        }
+
+        publish_stats(&self.stats, 0, cpu);
+        let mut fresh = self.new_hart(0);
+        fresh.cycles = cpu.cycles;
+        if let Some(tracer) = cpu.take_tracer() {
+            fresh.set_tracer(Some(tracer));
+        }
+        *cpu = fresh;
+
+        self.shared.finish_reboot();
+        self.start_workers();
+    }
+
+    /// Write the crash dump to the file set with
+    /// [`set_crash_dump`](Self::set_crash_dump), if there is one.
+    fn write_crash_dump(&self) {
+        let (Some(path), Some(dump)) = (&self.crash_dump, self.crash_dump()) else {
+            return;
+        };
+        match dump
+            .to_json()
+            .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()))
+        {
+            Ok(()) => println!("[VM] Crash dump written to {}", path.display()),
+            Err(e) => eprintln!(
+                "[VM] Failed to write crash dump '{}': {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Sleep through a WFI on hart 0 and let the guest time pass. Returns
+    /// whether the hart idled.
+    fn idle(&self, cpu: &mut Cpu) -> bool {
+        if !self.pacing.idle {
+            return false;
+        }
+        let Some(ticks) = cpu.take_idle(&*self.bus) else {
+            return false;
+        };
+        let ticks = ticks.min(MAX_IDLE_TICKS);
+        // An IPI from another hart ends the sleep early
+        let ticks = match self
+            .bus
+            .clint
+            .wait_for_interrupt(0, ticks_to_duration(ticks))
+        {
+            Some(slept) if !self.pacing.deterministic => duration_to_ticks(slept).min(ticks),
+            _ => ticks,
+        };
+        cpu.skip_idle(&self.bus, ticks);
+        true
     }
 
     fn execute_batch(&self, cpu: &mut Cpu, max_steps: u64) -> (u64, Option<HaltReason>) {
@@ -352,12 +1691,16 @@ impl NativeVm {
             match cpu.step(&*self.bus) {
                 Ok(()) => {
                     count += 1;
+                    if self.pacing.idle && cpu.in_wfi() {
+                        break;
+                    }
                 }
                 Err(Trap::RequestedTrap(code)) => {
                     return (count, Some(HaltReason::Shutdown(code)));
                 }
-                Err(Trap::Fatal(msg)) => {
-                    return (count, Some(HaltReason::Fatal(msg, cpu.pc)));
+                Err(trap @ Trap::Fatal(_)) => {
+                    let info = TrapInfo::capture(&trap, cpu, &self.bus);
+                    return (count, Some(HaltReason::Fatal(info)));
                 }
                 Err(_) => {
                     count += 1;
@@ -388,25 +1731,45 @@ impl NativeVm {
                     self.shared.request_halt();
                     return;
                 } else if byte == 1 {
-                    self.bus.uart.push_input(1);
+                    self.feed_uart(1);
                 } else {
-                    self.bus.uart.push_input(byte);
+                    self.feed_uart(byte);
                 }
                 *escaped = false;
             } else if byte == 1 {
                 *escaped = true;
             } else {
+                self.feed_uart(byte);
+            }
+        }
+
+        if let Some(log) = &self.bus.replay {
+            while let Some(GuestInput::Uart(byte)) = log.next_due(Channel::Uart) {
                 self.bus.uart.push_input(byte);
             }
         }
     }
 
+    /// Pass a console byte to the guest, logging it when recording. A
+    /// replay takes its console input from the log instead.
+    fn feed_uart(&self, byte: u8) {
+        match &self.bus.replay {
+            Some(log) if log.is_replaying() => {}
+            Some(log) => {
+                log.record(GuestInput::Uart(byte));
+                self.bus.uart.push_input(byte);
+            }
+            None => self.bus.uart.push_input(byte),
+        }
+        self.bus.events.notify();
+    }
+
     fn shutdown(&mut self) {
         println!("[VM] Shutting down...");
 
         self.shared.request_halt();
 
-        for handle in self.handles.drain(..) {
+        for handle in self.handles.drain(..).chain(self.checker.take()) {
             if let Err(e) = handle.join() {
                 eprintln!("[VM] Worker thread panicked: {:?}", e);
             }
@@ -419,14 +1782,225 @@ impl NativeVm {
 impl Drop for NativeVm {
     fn drop(&mut self) {
         self.shared.request_halt();
-        for handle in self.handles.drain(..) {
+        for handle in self.handles.drain(..).chain(self.checker.take()) {
             handle.join().ok();
         }
     }
 }
 
-fn hart_thread(hart_id: usize, entry_pc: u64, bus: Arc<SystemBus>, shared: Arc<SharedState>) {
-    let mut cpu = Cpu::new(entry_pc, hart_id as u64);
+/// The host side of a NIC, as opened by [`open_network_backend`].
+struct NetworkLink {
+    backend: Box<dyn NetworkBackend>,
+    /// Name of the network the guest is told.
+    name: &'static str,
+    /// Transport counters, for a relay link.
+    metrics: Option<TransportMetricsHandle>,
+}
+
+/// Open the host side of a NIC: `backend` on its own I/O thread, recorded
+/// into `log` when recording, or the recorded traffic instead when
+/// replaying.
+fn open_network_backend(
+    backend: NetBackend,
+    log: Option<Arc<InputLog>>,
+) -> Result<NetworkLink, String> {
+    use crate::net::DummyBackend;
+    use crate::net::async_backend::AsyncNetworkBackend;
+    use crate::net::slirp::SlirpBackend;
+    #[cfg(unix)]
+    use crate::net::tap::TapBackend;
+    use crate::net::webtransport::WebTransportBackend;
+
+    let name = match &backend {
+        NetBackend::WebTransport { .. } => "webtransport",
+        NetBackend::Slirp => "slirp",
+        NetBackend::Tap { .. } => "tap",
+    };
+    if let Some(log) = log.as_ref().filter(|log| log.is_replaying()) {
+        // Frames come from the log; nothing goes out
+        println!(
+            "[VM] Replaying recorded network traffic instead of {}",
+            name
+        );
+        return Ok(NetworkLink {
+            backend: Box::new(ReplayBackend::new(log.clone())),
+            name,
+            metrics: None,
+        });
+    }
+    let mut metrics = None;
+    let inner: Box<dyn NetworkBackend> = match backend {
+        NetBackend::WebTransport {
+            url,
+            cert_hash,
+            batching,
+            resume,
+        } => {
+            let backend = match resume {
+                Some((mac, ticket)) => {
+                    WebTransportBackend::resume(&url, cert_hash, batching, mac, ticket)
+                }
+                None => WebTransportBackend::with_batching(&url, cert_hash, batching),
+            };
+            metrics = Some(backend.metrics_handle());
+            println!("[VM] WebTransport network configured (async): {}", url);
+            Box::new(backend)
+        }
+        NetBackend::Slirp => {
+            println!("[VM] User-mode NAT network configured (slirp)");
+            Box::new(SlirpBackend::new())
+        }
+        #[cfg(unix)]
+        NetBackend::Tap { name, mac, ip } => {
+            let mac = mac.unwrap_or_else(|| DummyBackend::new().mac_address());
+            let mut backend = TapBackend::new(&name, mac, ip);
+            // Open it now so a missing permission is reported here
+            backend.init()?;
+            println!("[VM] TAP network configured: {}", backend.name());
+            Box::new(backend)
+        }
+        #[cfg(not(unix))]
+        NetBackend::Tap { .. } => {
+            return Err("TAP networking needs a Unix host".to_string());
+        }
+    };
+    let async_backend = Box::new(AsyncNetworkBackend::new(inner));
+    let backend: Box<dyn NetworkBackend> = match log {
+        Some(log) => Box::new(RecordingBackend::new(async_backend, log)),
+        None => async_backend,
+    };
+    Ok(NetworkLink {
+        backend,
+        name,
+        metrics,
+    })
+}
+
+/// MAC address of `device` as its driver reads it, if it is a NIC.
+fn nic_mac(device: &dyn VirtioDevice) -> Option<[u8; 6]> {
+    if device.device_id() != VIRTIO_NET_DEVICE_ID {
+        return None;
+    }
+    let low = device.read(CONFIG_SPACE_OFFSET).ok()? as u32;
+    let high = device.read(CONFIG_SPACE_OFFSET + 4).ok()? as u16;
+    let mut mac = [0; 6];
+    mac[..4].copy_from_slice(&low.to_le_bytes());
+    mac[4..].copy_from_slice(&high.to_le_bytes());
+    Some(mac)
+}
+
+/// MAC address of every NIC on `bus`.
+fn nic_macs(bus: &SystemBus) -> Vec<[u8; 6]> {
+    bus.virtio_devices
+        .iter()
+        .filter_map(|device| nic_mac(device.as_ref()))
+        .collect()
+}
+
+/// `mac`, or if a NIC on `bus` already has it, the next address none has.
+fn unique_mac(bus: &SystemBus, mut mac: [u8; 6]) -> [u8; 6] {
+    let taken = nic_macs(bus);
+    while taken.contains(&mac) {
+        mac[5] = mac[5].wrapping_add(1);
+    }
+    mac
+}
+
+/// Plugs NICs into and out of a [`NativeVm`] while it runs; see
+/// [`NativeVm::network_hotplug`]. Clones share the VM.
+#[derive(Clone)]
+pub struct NetworkHotplug {
+    bus: Arc<SystemBus>,
+}
+
+impl NetworkHotplug {
+    /// Plug a NIC on `backend` into the empty slot `slot`, made with
+    /// [`NativeVm::add_network_slot`] or emptied by
+    /// [`unplug`](Self::unplug), and return its MAC address. The guest
+    /// rescans the slot to bind a driver to it (see
+    /// [`crate::devices::virtio::hotplug`]).
+    pub fn plug(&self, slot: usize, backend: NetBackend) -> Result<[u8; 6], String> {
+        if self.bus.replay.is_some() {
+            return Err(
+                "network hot-plug is not available when recording or replaying".to_string(),
+            );
+        }
+        if self
+            .slots()
+            .iter()
+            .all(|&(s, mac)| s != slot || mac.is_some())
+        {
+            return Err(format!("VirtIO slot {} is not an empty network slot", slot));
+        }
+        let link = open_network_backend(backend, None)?;
+        let mac = unique_mac(&self.bus, link.backend.mac_address());
+        let nic = VirtioNet::with_mac(link.backend, mac);
+        self.bus.plug_virtio(slot, Box::new(nic))?;
+        println!(
+            "[VM] NIC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} plugged into VirtIO slot {}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5], slot
+        );
+        Ok(mac)
+    }
+
+    /// Unplug the NIC in slot `slot` and close its backend. The guest
+    /// should release it first; a driver still bound sees its link go
+    /// down.
+    pub fn unplug(&self, slot: usize) -> Result<(), String> {
+        let device = self.bus.virtio_devices.get(slot);
+        if device.and_then(|device| nic_mac(device.as_ref())).is_none() {
+            return Err(format!("VirtIO slot {} holds no NIC", slot));
+        }
+        self.bus.unplug_virtio(slot)?;
+        println!("[VM] NIC unplugged from VirtIO slot {}", slot);
+        Ok(())
+    }
+
+    /// Every hot-pluggable slot, with the MAC address of the NIC in it, if
+    /// any.
+    pub fn slots(&self) -> Vec<(usize, Option<[u8; 6]>)> {
+        self.bus
+            .virtio_devices
+            .iter()
+            .enumerate()
+            .filter(|(_, device)| device.as_hotplug().is_some())
+            .map(|(slot, device)| (slot, nic_mac(device.as_ref())))
+            .collect()
+    }
+}
+
+/// Load a kernel or firmware image: an ELF at its physical addresses, or a
+/// raw image at the DRAM base. Returns the entry point.
+fn load_boot_image(image: &[u8], bus: &SystemBus) -> Result<u64, String> {
+    if image.starts_with(b"\x7FELF") {
+        return load_elf_into_dram(image, bus);
+    }
+    bus.dram
+        .load(image, 0)
+        .map_err(|e| format!("Failed to load image: {:?}", e))?;
+    Ok(bus.dram_base())
+}
+
+/// Have `cpu`'s JIT publish its diagnostics to the returned handle.
+#[cfg(feature = "jit-native")]
+fn attach_jit_diagnostics(cpu: &mut Cpu) -> Option<JitDiagnosticsHandle> {
+    let jit = cpu.jit.as_mut()?;
+    let handle = JitDiagnosticsHandle::default();
+    jit.attach(handle.clone());
+    Some(handle)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hart_thread(
+    hart_id: usize,
+    mut cpu: Cpu,
+    bus: Arc<SystemBus>,
+    shared: Arc<SharedState>,
+    symbols: Option<Arc<SymbolMap>>,
+    fatal: FatalSlot,
+    stats: StatsSlot,
+    pacing: Pacing,
+) {
     let mut step_count: u64 = 0;
     let start_time = Instant::now();
 
@@ -434,36 +2008,65 @@ fn hart_thread(hart_id: usize, entry_pc: u64, bus: Arc<SystemBus>, shared: Arc<S
     let mut last_report_steps: u64 = 0;
     let report_interval = Duration::from_secs(5);
 
-    println!("[Hart {}] Started at PC=0x{:x}", hart_id, entry_pc);
+    println!("[Hart {}] Started at PC=0x{:x}", hart_id, cpu.pc);
 
     const BATCH_SIZE: u64 = 256;
     const YIELD_INTERVAL: u64 = 4_000_000;
+    let mut next_yield = YIELD_INTERVAL;
+    let mut throttle = pacing.max_mips.map(Throttle::new);
 
     loop {
         if shared.should_stop() {
             break;
         }
 
-        let (batch_steps, halt_reason) = execute_batch_worker(&mut cpu, &bus, BATCH_SIZE);
+        let (batch_steps, halt_reason) =
+            execute_batch_worker(&mut cpu, &bus, BATCH_SIZE, pacing.idle);
         step_count += batch_steps;
+        if let Some(throttle) = &mut throttle {
+            throttle.pace(batch_steps);
+        }
 
         if let Some(reason) = halt_reason {
             match reason {
+                // Hart 0 resets the machine, or stops it if reboots are off
+                HaltReason::Shutdown(code) if VmExit::from_code(code) == VmExit::Reboot => {
+                    println!("[Hart {}] Reboot requested", hart_id);
+                    shared.request_reboot();
+                    break;
+                }
                 HaltReason::Shutdown(code) => {
                     println!("[Hart {}] Shutdown requested (code: {:#x})", hart_id, code);
                     shared.signal_halted(code);
                     break;
                 }
-                HaltReason::Fatal(msg, pc) => {
-                    eprintln!("[Hart {}] Fatal: {} at PC=0x{:x}", hart_id, msg, pc);
-                    shared.signal_halted(0xDEAD);
+                HaltReason::Fatal(info) => {
+                    eprintln!("[Hart {}] Fatal: {}", hart_id, info);
+                    if let Some(symbols) = &symbols {
+                        eprint!("{}", backtrace::unwind(&cpu, &bus, symbols));
+                    }
+                    signal_fatal(&fatal, &shared, &cpu, &bus, info);
                     break;
                 }
             }
         }
 
-        if step_count % YIELD_INTERVAL == 0 {
+        // Guest time follows hart 0, so only wait for it here, a little at
+        // a time; an IPI wakes the hart at once
+        if pacing.idle
+            && let Some(ticks) = cpu.take_idle(&*bus)
+        {
+            bus.clint
+                .wait_for_interrupt(hart_id, ticks_to_duration(ticks).min(SECONDARY_IDLE_SLEEP));
+            if let Some(throttle) = &mut throttle {
+                throttle.restart();
+            }
+        }
+
+        if step_count >= next_yield {
+            next_yield = step_count + YIELD_INTERVAL;
             thread::yield_now();
+            publish_stats(&stats, hart_id, &mut cpu);
 
             if log::log_enabled!(log::Level::Debug) {
                 let now = Instant::now();
@@ -489,6 +2092,8 @@ fn hart_thread(hart_id: usize, entry_pc: u64, bus: Arc<SystemBus>, shared: Arc<S
         }
     }
 
+    publish_stats(&stats, hart_id, &mut cpu);
+
     let elapsed = start_time.elapsed().as_secs_f64();
     let ips = if elapsed > 0.0 {
         step_count as f64 / elapsed
@@ -507,6 +2112,7 @@ fn execute_batch_worker(
     cpu: &mut Cpu,
     bus: &SystemBus,
     max_steps: u64,
+    stop_on_wfi: bool,
 ) -> (u64, Option<HaltReason>) {
     let mut count = 0u64;
 
@@ -514,12 +2120,16 @@ fn execute_batch_worker(
         match cpu.step(bus) {
             Ok(()) => {
                 count += 1;
+                if stop_on_wfi && cpu.in_wfi() {
+                    break;
+                }
             }
             Err(Trap::RequestedTrap(code)) => {
                 return (count, Some(HaltReason::Shutdown(code)));
             }
-            Err(Trap::Fatal(msg)) => {
-                return (count, Some(HaltReason::Fatal(msg, cpu.pc)));
+            Err(trap @ Trap::Fatal(_)) => {
+                let info = TrapInfo::capture(&trap, cpu, bus);
+                return (count, Some(HaltReason::Fatal(info)));
             }
             Err(_) => {
                 count += 1;
@@ -533,7 +2143,7 @@ fn execute_batch_worker(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::bus::SystemBus;
+    use crate::bus::{DRAM_BASE, SystemBus};
     use crate::cpu::Cpu;
     use crate::devices::clint::Clint;
     use crate::devices::plic::Plic;
@@ -557,6 +2167,139 @@ mod tests {
         println!("Plic size: {} bytes", std::mem::size_of::<Plic>());
     }
 
+    /// Counts its boots in DRAM past the image: reboots on the first and
+    /// powers off on the second.
+    const REBOOT_ONCE: [u32; 13] = [
+        0x0001_0297, // auipc t0, 0x10 (boot counter)
+        0x0002_a303, // lw t1, 0(t0)
+        0x0013_0313, // addi t1, t1, 1
+        0x0062_a023, // sw t1, 0(t0)
+        0x0010_03b7, // lui t2, 0x100 (test finisher)
+        0x0000_7e37, // lui t3, 7
+        0x777e_0e13, // addi t3, t3, 0x777
+        0x0010_0e93, // li t4, 1
+        0x01d3_0663, // beq t1, t4, finish
+        0x0000_5e37, // lui t3, 5
+        0x555e_0e13, // addi t3, t3, 0x555
+        0x01c3_a023, // finish: sw t3, 0(t2)
+        0x0000_006f, // j .
+    ];
+
+    fn reboot_once_vm() -> NativeVm {
+        let image: Vec<u8> = REBOOT_ONCE
+            .iter()
+            .flat_map(|insn| insn.to_le_bytes())
+            .collect();
+        NativeVm::with_config(&image, 1, BusConfig::with_dram(DRAM_BASE, 1024 * 1024)).unwrap()
+    }
+
+    #[test]
+    fn test_guest_reboot_resets_the_machine() {
+        let mut vm = reboot_once_vm();
+        vm.run_with_console(Console::detached());
+        assert_eq!(vm.exit(), Ok(Some(VmExit::PowerOff)));
+        assert_eq!(vm.bus.dram.load_32(0x1_0000).unwrap(), 2);
+    }
+
+    /// Arms the timer and spins until it fires, then powers off.
+    const TIMER_ONCE: [u32; 15] = [
+        0x0000_0297, // auipc t0, 0
+        0x0282_8293, // addi t0, t0, 40 (handler)
+        0x3052_9073, // csrw mtvec, t0
+        0x0200_4337, // lui t1, 0x2004 (mtimecmp)
+        0x3e80_0393, // li t2, 1000
+        0x0073_3023, // sd t2, 0(t1)
+        0x0800_0e13, // li t3, 0x80 (MTIE)
+        0x304e_1073, // csrw mie, t3
+        0x3004_6073, // csrsi mstatus, 8
+        0x0000_006f, // j .
+        0x0010_03b7, // handler: lui t2, 0x100 (test finisher)
+        0x0000_5e37, // lui t3, 5
+        0x555e_0e13, // addi t3, t3, 0x555
+        0x01c3_a023, // sw t3, 0(t2)
+        0x0000_006f, // j .
+    ];
+
+    #[test]
+    fn test_stats_report_interrupt_latency() {
+        let image: Vec<u8> = TIMER_ONCE
+            .iter()
+            .flat_map(|insn| insn.to_le_bytes())
+            .collect();
+        let mut vm =
+            NativeVm::with_config(&image, 1, BusConfig::with_dram(DRAM_BASE, 1024 * 1024)).unwrap();
+        assert_eq!(vm.stats().interrupts(), InterruptLatency::default());
+        vm.run_with_console(Console::detached());
+        assert_eq!(vm.exit(), Ok(Some(VmExit::PowerOff)));
+
+        let stats = vm.stats();
+        assert_eq!(stats.harts.len(), 1);
+        let interrupts = stats.interrupts();
+        assert_eq!(interrupts.timer.count, 1);
+        assert_eq!(interrupts.all().count, 1);
+        assert!(interrupts.polls > 0);
+        // Taken at the first poll after the deadline
+        assert!(interrupts.timer.max < 256 * 2);
+    }
+
+    #[test]
+    fn test_network_interfaces_and_hotplug() {
+        let mut vm = reboot_once_vm();
+        vm.attach_network(NetBackend::Slirp).unwrap();
+        vm.attach_network(NetBackend::Slirp).unwrap();
+        let slot = vm.add_network_slot().unwrap();
+        assert_eq!(slot, 2);
+
+        let hotplug = vm.network_hotplug();
+        let slots = hotplug.slots();
+        let (first, second) = (slots[0].1.unwrap(), slots[1].1.unwrap());
+        assert_ne!(first, second);
+        assert_eq!(slots[2], (2, None));
+
+        // Configuration is closed while the handle shares the bus
+        assert!(vm.attach_network(NetBackend::Slirp).is_err());
+
+        let mac = hotplug.plug(slot, NetBackend::Slirp).unwrap();
+        assert!(![first, second].contains(&mac));
+        assert_eq!(hotplug.slots()[2], (2, Some(mac)));
+        assert!(hotplug.plug(slot, NetBackend::Slirp).is_err());
+
+        hotplug.unplug(0).unwrap();
+        assert!(hotplug.unplug(0).is_err());
+        assert_eq!(hotplug.slots()[0], (0, None));
+        assert_eq!(nic_macs(&vm.bus), [second, mac]);
+    }
+
+    #[test]
+    fn test_first_fatal_error_is_dumped() {
+        let mut vm = reboot_once_vm();
+        let path = std::env::temp_dir().join(format!("riscv-vm-crash-{}.json", std::process::id()));
+        vm.set_crash_dump(&path);
+        let cpu = vm.primary_cpu.take().unwrap();
+        let first = TrapInfo::capture(&Trap::Fatal("first".into()), &cpu, &vm.bus);
+        let second = TrapInfo::capture(&Trap::Fatal("second".into()), &cpu, &vm.bus);
+        signal_fatal(&vm.fatal, &vm.shared, &cpu, &vm.bus, first.clone());
+        signal_fatal(&vm.fatal, &vm.shared, &cpu, &vm.bus, second);
+        assert_eq!(vm.exit(), Err(first));
+
+        vm.write_crash_dump();
+        let json = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let dump = CrashDump::from_json(&json).unwrap();
+        assert_eq!(dump.reason, "first at PC=0x1000 in M-mode");
+        assert_eq!(dump.pc, cpu.pc);
+        assert_eq!(vm.crash_dump(), Some(dump));
+    }
+
+    #[test]
+    fn test_reboot_ends_the_run_when_disabled() {
+        let mut vm = reboot_once_vm();
+        vm.set_reboot(false);
+        vm.run_with_console(Console::detached());
+        assert_eq!(vm.exit(), Ok(Some(VmExit::Reboot)));
+        assert_eq!(vm.bus.dram.load_32(0x1_0000).unwrap(), 1);
+    }
+
     #[test]
     fn test_shared_state_alignment() {
         assert_eq!(std::mem::align_of::<SharedState>(), 64);
@@ -0,0 +1,167 @@
+//! Structured trap reports for frontends.
+//!
+//! `Cpu::step` returns a bare [`Trap`]. By then a trap the guest took has
+//! already moved the hart into its handler, so the context a debugger wants
+//! (where it happened, in which mode, on which instruction) is only left in
+//! the target mode's `xepc`/`xPP`. [`TrapInfo::capture`] reads it back from
+//! there, or from the live hart for intercepted exceptions and host-level
+//! stops, together with the encoding of the trapping instruction.
+
+use std::fmt;
+
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::csr::{CSR_MEPC, CSR_MSTATUS, CSR_SATP, CSR_SEPC};
+use crate::vm::guest_mem::{self, Translation};
+use crate::{Mode, Trap};
+
+/// A trap returned by `step()`, with the context it was raised in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrapInfo {
+    pub trap: Trap,
+    /// Exception or interrupt code as written to `mcause`/`scause`, or
+    /// `None` for host-level stops (`RequestedTrap`, `Fatal`).
+    pub cause: Option<u64>,
+    pub interrupt: bool,
+    /// Value written to `mtval`/`stval`.
+    pub tval: u64,
+    /// Privilege mode the hart was in when it trapped.
+    pub mode: Mode,
+    /// PC of the trapping instruction (for interrupts, of the next one).
+    pub pc: u64,
+    /// Encoding of the instruction at `pc` (2 or 4 bytes), empty for
+    /// interrupts and when `pc` is not readable.
+    pub insn: Vec<u8>,
+    /// Whether the guest took the trap, rather than it being intercepted
+    /// or stopping the VM.
+    pub taken: bool,
+}
+
+impl TrapInfo {
+    /// Describe `trap`, just returned by `cpu.step(bus)`.
+    pub fn capture(trap: &Trap, cpu: &Cpu, bus: &SystemBus) -> Self {
+        let arch = Cpu::trap_to_cause_tval(trap);
+        let taken = arch.is_some() && !cpu.intercepts(trap);
+        let (pc, mode) = if !taken {
+            (cpu.pc, cpu.mode)
+        } else if cpu.mode == Mode::Machine {
+            let mstatus = cpu.csrs[CSR_MSTATUS as usize];
+            (cpu.csrs[CSR_MEPC as usize], Mode::from_mpp(mstatus >> 11))
+        } else {
+            let spp = (cpu.csrs[CSR_MSTATUS as usize] >> 8) & 1;
+            (cpu.csrs[CSR_SEPC as usize], Mode::from_mpp(spp))
+        };
+        let (interrupt, cause, tval) = arch.unwrap_or((false, 0, 0));
+        let insn = if interrupt {
+            Vec::new()
+        } else {
+            let satp = match mode {
+                Mode::Machine => 0,
+                _ => cpu.csrs[CSR_SATP as usize],
+            };
+            fetch_insn(cpu, bus, pc, Translation::Satp(satp))
+        };
+        Self {
+            trap: trap.clone(),
+            cause: arch.map(|_| cause),
+            interrupt,
+            tval,
+            mode,
+            pc,
+            insn,
+            taken,
+        }
+    }
+
+    /// The trap's variant name, e.g. `LoadPageFault`.
+    pub fn name(&self) -> String {
+        let debug = format!("{:?}", self.trap);
+        match debug.find('(') {
+            Some(paren) => debug[..paren].to_string(),
+            None => debug,
+        }
+    }
+
+    /// Whether the VM cannot continue past this trap.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self.trap, Trap::Fatal(_))
+    }
+}
+
+/// Read the 2- or 4-byte instruction at `pc`.
+fn fetch_insn(cpu: &Cpu, bus: &SystemBus, pc: u64, translation: Translation) -> Vec<u8> {
+    let mut insn = [0; 4];
+    if guest_mem::read_virt(cpu, bus, pc, &mut insn[..2], translation).is_err() {
+        return Vec::new();
+    }
+    if insn[0] & 0b11 != 0b11 {
+        return insn[..2].to_vec();
+    }
+    match guest_mem::read_virt(cpu, bus, pc.wrapping_add(2), &mut insn[2..], translation) {
+        Ok(()) => insn.to_vec(),
+        Err(_) => Vec::new(),
+    }
+}
+
+impl fmt::Display for TrapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.trap {
+            Trap::Fatal(msg) => write!(f, "{}", msg)?,
+            _ => write!(f, "{}", self.name())?,
+        }
+        if let Some(cause) = self.cause {
+            write!(f, " (cause {}, tval 0x{:x})", cause, self.tval)?;
+        }
+        write!(f, " at PC=0x{:x} in {}-mode", self.pc, self.mode)?;
+        if !self.insn.is_empty() {
+            let insn: Vec<String> = self.insn.iter().map(|b| format!("{:02x}", b)).collect();
+            write!(f, " [{}]", insn.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DRAM_BASE;
+    use crate::cpu::test_hart;
+
+    #[test]
+    fn test_taken_trap_reports_faulting_context() {
+        // addi a0, x0, 1; ld a2, 0(x0)
+        let (mut cpu, bus) = test_hart(&[0x0010_0513, 0x0000_3603]);
+        cpu.csrs[crate::csr::CSR_MTVEC as usize] = DRAM_BASE + 0x100;
+        cpu.mode = Mode::Supervisor;
+        cpu.step(&bus).unwrap();
+        let trap = cpu.step(&bus).unwrap_err();
+
+        let info = TrapInfo::capture(&trap, &cpu, &bus);
+        assert_eq!(cpu.mode, Mode::Machine);
+        assert!(info.taken);
+        assert_eq!((info.cause, info.tval), (Some(5), 0));
+        assert_eq!((info.pc, info.mode), (DRAM_BASE + 4, Mode::Supervisor));
+        assert_eq!(info.insn, [0x03, 0x36, 0x00, 0x00]);
+        assert_eq!(
+            info.to_string(),
+            "LoadAccessFault (cause 5, tval 0x0) at PC=0x80000004 in S-mode [03 36 00 00]"
+        );
+    }
+
+    #[test]
+    fn test_intercepted_and_fatal_traps_use_live_state() {
+        // c.ebreak
+        let (mut cpu, bus) = test_hart(&[0x0000_9002]);
+        cpu.intercept_exceptions = 1 << 3;
+        let trap = cpu.step(&bus).unwrap_err();
+        let info = TrapInfo::capture(&trap, &cpu, &bus);
+        assert!(!info.taken);
+        assert_eq!((info.pc, info.mode), (DRAM_BASE, Mode::Machine));
+        assert_eq!(info.insn, [0x02, 0x90]);
+        assert_eq!(info.name(), "Breakpoint");
+
+        let info = TrapInfo::capture(&Trap::Fatal("boom".into()), &cpu, &bus);
+        assert!(info.is_fatal() && info.cause.is_none());
+        assert_eq!(info.to_string(), "boom at PC=0x80000000 in M-mode [02 90]");
+    }
+}
@@ -1,8 +1,22 @@
 use crate::Trap;
 use crate::bus::{DRAM_BASE, SystemBus};
 use crate::cpu;
+use crate::cpu::idle::MAX_IDLE_TICKS;
+use crate::crash_dump::CrashDump;
+use crate::devices::bootrom::{BootConfig, RESET_VECTOR};
+use crate::devices::clint::TIMEBASE_FREQUENCY;
+use crate::devices::input::InputEvent;
+use crate::devices::rtc::DETERMINISTIC_EPOCH_NS;
+use crate::devices::test_finisher::VmExit;
+use crate::devices::virtio::{GpuDisplay, Virtio9p, VirtioGpu};
 use crate::loader::load_elf_wasm;
+use crate::migration::{self, MigrationHeader};
 use crate::shared_mem;
+use crate::snapshot::{Compression, Snapshot, SnapshotReader, SnapshotWriter, restore_stream};
+use crate::vm::guest_mem;
+use crate::vm::trap_info::TrapInfo;
+use std::cell::Cell;
+use std::rc::Rc;
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
@@ -73,6 +87,157 @@ fn detect_hart_count() -> usize {
     (count / 2).max(1) // Use half the CPUs, ensure at least 1
 }
 
+/// Page callback receiving trace lines.
+#[cfg(target_arch = "wasm32")]
+struct TraceCallback(js_sys::Function);
+
+// WASM is single threaded
+#[cfg(target_arch = "wasm32")]
+unsafe impl Send for TraceCallback {}
+
+#[cfg(target_arch = "wasm32")]
+impl TraceCallback {
+    fn call(&self, record: &cpu::TraceRecord) {
+        // A throwing callback must not stop the guest
+        let _ = self
+            .0
+            .call1(&JsValue::NULL, &JsValue::from_str(&record.to_string()));
+    }
+}
+
+/// Cancels a [`WasmVm::run_async`] call from page code.
+///
+/// The VM stays borrowed while `run_async` is pending, so its own methods
+/// cannot be called until the promise settles; a token obtained beforehand
+/// with `run_token()` can.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub struct RunToken {
+    stop: Rc<Cell<bool>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl RunToken {
+    /// Ask the pending run to stop; it returns after the current slice.
+    pub fn stop(&self) {
+        self.stop.set(true);
+    }
+
+    /// Whether `stop()` was called since the last run started.
+    pub fn is_stopped(&self) -> bool {
+        self.stop.get()
+    }
+}
+
+/// Let the event loop run timers, input and rendering before the next
+/// slice, resuming after `delay_ms`. A `setTimeout` task rather than a
+/// resolved promise, whose microtask would run before any of them.
+#[cfg(target_arch = "wasm32")]
+async fn yield_to_event_loop(delay_ms: f64) -> Result<(), JsValue> {
+    let global = js_sys::global();
+    let set_timeout: js_sys::Function =
+        js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))?.dyn_into()?;
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from(delay_ms));
+    });
+    wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(())
+}
+
+/// Plain JS object for a `TrapInfo`; see `WasmVm::fatal_error`.
+#[cfg(target_arch = "wasm32")]
+fn trap_info_to_js(info: &TrapInfo) -> JsValue {
+    let object = js_sys::Object::new();
+    let fields = [
+        ("name", JsValue::from_str(&info.name())),
+        ("message", JsValue::from_str(&info.to_string())),
+        (
+            "cause",
+            info.cause
+                .map_or(JsValue::NULL, |c| JsValue::from(c as f64)),
+        ),
+        ("interrupt", JsValue::from(info.interrupt)),
+        ("tval", JsValue::from(info.tval)),
+        ("mode", JsValue::from_str(&info.mode.to_string())),
+        ("pc", JsValue::from(info.pc)),
+        ("insn", js_sys::Uint8Array::from(&info.insn[..]).into()),
+        ("taken", JsValue::from(info.taken)),
+    ];
+    for (key, value) in fields {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from_str(key), &value);
+    }
+    object.into()
+}
+
+/// Plain JS object for a `VmExit`; see `WasmVm::exit`.
+#[cfg(target_arch = "wasm32")]
+fn vm_exit_to_js(exit: VmExit) -> JsValue {
+    let (reason, status) = match exit {
+        VmExit::PowerOff => ("poweroff", 0),
+        VmExit::Reboot => ("reboot", 0),
+        VmExit::Fail(status) => ("fail", status),
+    };
+    let object = js_sys::Object::new();
+    let fields = [
+        ("reason", JsValue::from_str(reason)),
+        ("status", JsValue::from(status)),
+    ];
+    for (key, value) in fields {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from_str(key), &value);
+    }
+    object.into()
+}
+
+/// Hands each chunk of a streamed snapshot to a page callback.
+#[cfg(target_arch = "wasm32")]
+struct JsChunkWriter<'a>(&'a js_sys::Function);
+
+#[cfg(target_arch = "wasm32")]
+impl SnapshotWriter for JsChunkWriter<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.0
+            .call1(&JsValue::NULL, &js_sys::Uint8Array::from(chunk))
+            .map(|_| ())
+            .map_err(|e| format!("snapshot callback failed: {:?}", e))
+    }
+}
+
+/// Reads a streamed snapshot from the chunks a page callback returns, in
+/// whatever sizes it stored them.
+#[cfg(target_arch = "wasm32")]
+struct JsChunkReader<'a> {
+    next: &'a js_sys::Function,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SnapshotReader for JsChunkReader<'_> {
+    fn read_chunk(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.pos == self.chunk.len() {
+                let next = self
+                    .next
+                    .call0(&JsValue::NULL)
+                    .map_err(|e| format!("snapshot callback failed: {:?}", e))?;
+                if next.is_undefined() || next.is_null() {
+                    return Err("snapshot ended early".to_string());
+                }
+                self.chunk = js_sys::Uint8Array::new(&next).to_vec();
+                self.pos = 0;
+                continue;
+            }
+            let n = (buf.len() - filled).min(self.chunk.len() - self.pos);
+            buf[filled..filled + n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+            filled += n;
+            self.pos += n;
+        }
+        Ok(())
+    }
+}
+
 /// WASM-exposed VM wrapper for running RISC-V kernels in the browser.
 ///
 /// ## Multi-Hart Architecture
@@ -95,12 +260,20 @@ pub struct WasmVm {
     poll_counter: u32,
     halted: bool,
     halt_code: u64,
+    /// How the guest ended the run, once it has
+    exit: Option<VmExit>,
+    /// Page callback told about every guest exit and reboot
+    exit_callback: Option<js_sys::Function>,
+    /// Kernel image, loaded into DRAM again on a reboot
+    kernel: Vec<u8>,
+    /// The error hart 0 halted on, if any
+    fatal: Option<TrapInfo>,
+    /// Crash dump taken when hart 0 halted on `fatal`
+    crash_dump: Option<CrashDump>,
     /// Shared memory buffer (for passing to workers)
     shared_buffer: Option<js_sys::SharedArrayBuffer>,
     /// Shared control region accessor
     shared_control: Option<shared_mem::wasm::SharedControl>,
-    /// Shared CLINT accessor
-    shared_clint: Option<shared_mem::wasm::SharedClint>,
     /// Shared UART output accessor (for reading worker output)
     shared_uart_output: Option<shared_mem::wasm::SharedUartOutput>,
     /// Shared UART input accessor (for sending keyboard input to workers)
@@ -119,6 +292,18 @@ pub struct WasmVm {
     workers_signaled: bool,
     /// External network backend for Node.js native addon bridging
     external_net: Option<Arc<crate::net::external::ExternalNetworkBackend>>,
+    /// Scanouts of the VirtIO GPU, if one is attached
+    gpu: Option<Arc<GpuDisplay>>,
+    /// Stop request shared with the `RunToken`s handed out
+    stop: Rc<Cell<bool>>,
+    /// Page callback receiving console output as it is produced
+    output_callback: Option<js_sys::Function>,
+    /// Page callback receiving the guest's mailbox messages
+    message_callback: Option<js_sys::Function>,
+    /// Whether `run_async` waits while hart 0 idles in WFI
+    idle: bool,
+    /// Instructions per second cap of `run_async`, in millions
+    max_mips: Option<u32>,
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -179,53 +364,45 @@ impl WasmVm {
         }
 
         // Create bus with shared memory if available
-        let (
-            bus,
-            shared_buffer,
-            shared_control,
-            shared_clint,
-            shared_uart_output,
-            shared_uart_input,
-        ) = if sab_available {
-            // Create SharedArrayBuffer for shared memory
-            let total_size = shared_mem::total_shared_size(DRAM_SIZE);
-            let sab = js_sys::SharedArrayBuffer::new(total_size as u32);
-
-            // Initialize shared memory regions
-            shared_mem::wasm::init_shared_memory(&sab, num_harts);
-
-            // Create bus with DRAM backed by shared buffer
-            // IMPORTANT: Pass the full SharedArrayBuffer with the DRAM byte offset,
-            // NOT a sliced copy (slice() creates a copy, breaking shared memory!)
-            // Also pass SharedClint so CLINT MMIO accesses go through shared memory.
-            let dram_offset = shared_mem::dram_offset();
-            let shared_clint_for_bus = shared_mem::wasm::SharedClint::new(&sab);
-            // Main thread (hart 0) reads from local UART, not shared input
-            let bus = SystemBus::from_shared_buffer(
-                sab.clone(),
-                dram_offset,
-                shared_clint_for_bus,
-                false,
-            );
-
-            let control = shared_mem::wasm::SharedControl::new(&sab);
-            let clint = shared_mem::wasm::SharedClint::new(&sab);
-            let uart_output = shared_mem::wasm::SharedUartOutput::new(&sab);
-            let uart_input = shared_mem::wasm::SharedUartInput::new(&sab);
-
-            (
-                bus,
-                Some(sab),
-                Some(control),
-                Some(clint),
-                Some(uart_output),
-                Some(uart_input),
-            )
-        } else {
-            // Standard bus without shared memory
-            let bus = SystemBus::new(DRAM_BASE, DRAM_SIZE);
-            (bus, None, None, None, None, None)
-        };
+        let (bus, shared_buffer, shared_control, shared_uart_output, shared_uart_input) =
+            if sab_available {
+                // Create SharedArrayBuffer for shared memory
+                let total_size = shared_mem::total_shared_size(DRAM_SIZE);
+                let sab = js_sys::SharedArrayBuffer::new(total_size as u32);
+
+                // Initialize shared memory regions
+                shared_mem::wasm::init_shared_memory(&sab, num_harts);
+
+                // Create bus with DRAM backed by shared buffer
+                // IMPORTANT: Pass the full SharedArrayBuffer with the DRAM byte offset,
+                // NOT a sliced copy (slice() creates a copy, breaking shared memory!)
+                // Also pass SharedClint so CLINT MMIO accesses go through shared memory.
+                let dram_offset = shared_mem::dram_offset();
+                let shared_clint_for_bus = shared_mem::wasm::SharedClint::new(&sab);
+                // Main thread (hart 0) reads from local UART, not shared input
+                let bus = SystemBus::from_shared_buffer(
+                    sab.clone(),
+                    dram_offset,
+                    shared_clint_for_bus,
+                    false,
+                );
+
+                let control = shared_mem::wasm::SharedControl::new(&sab);
+                let uart_output = shared_mem::wasm::SharedUartOutput::new(&sab);
+                let uart_input = shared_mem::wasm::SharedUartInput::new(&sab);
+
+                (
+                    bus,
+                    Some(sab),
+                    Some(control),
+                    Some(uart_output),
+                    Some(uart_input),
+                )
+            } else {
+                // Standard bus without shared memory
+                let bus = SystemBus::new(DRAM_BASE, DRAM_SIZE);
+                (bus, None, None, None, None)
+            };
 
         // Load kernel
         let entry_pc = if kernel.starts_with(b"\x7FELF") {
@@ -252,7 +429,10 @@ impl WasmVm {
         bus.set_num_harts(num_harts);
 
         // Create primary CPU (hart 0)
-        let cpu = cpu::Cpu::new(entry_pc, 0);
+        // Harts start in the boot ROM, which reads the entry point from its mailbox
+        bus.boot_rom
+            .configure(&BootConfig::new(entry_pc, DRAM_BASE + DRAM_SIZE as u64));
+        let cpu = cpu::Cpu::new(RESET_VECTOR, 0);
 
         web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(&format!(
             "[VM] Created {} harts, entry PC=0x{:x}, SMP={}",
@@ -267,9 +447,13 @@ impl WasmVm {
             poll_counter: 0,
             halted: false,
             halt_code: 0,
+            exit: None,
+            exit_callback: None,
+            kernel: kernel.to_vec(),
+            fatal: None,
+            crash_dump: None,
             shared_buffer,
             shared_control,
-            shared_clint,
             shared_uart_output,
             shared_uart_input,
             workers: Vec::new(),
@@ -279,6 +463,12 @@ impl WasmVm {
             boot_steps: 0,
             workers_signaled: false,
             external_net: None,
+            gpu: None,
+            stop: Rc::new(Cell::new(false)),
+            output_callback: None,
+            message_callback: None,
+            idle: true,
+            max_mips: None,
         })
     }
 
@@ -289,6 +479,23 @@ impl WasmVm {
         self.bus.virtio_devices.push(Box::new(vblk));
     }
 
+    /// Map a SharedArrayBuffer into the guest through the shared memory
+    /// device, as peer number `peer` (0-7). VMs on the page given the same
+    /// buffer share its memory and doorbells; size it with
+    /// `64 + window_bytes`. In SMP mode only hart 0 decodes the device.
+    pub fn attach_shared_memory(
+        &mut self,
+        buffer: js_sys::SharedArrayBuffer,
+        peer: u32,
+    ) -> Result<(), JsValue> {
+        let window = crate::shared_mem::SharedWindow::from_buffer(&buffer)
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.bus
+            .ivshmem
+            .attach(window, peer)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
     /// Connect to a WebTransport relay server.
     /// Note: Connection is asynchronous. Check network_status() to monitor connection state.
     pub fn connect_webtransport(
@@ -310,15 +517,29 @@ impl WasmVm {
         // debug defaults to false in VirtioNet
 
         self.bus.virtio_devices.push(Box::new(vnet));
+        self.bus.buildinfo.set_network(Some("webtransport"));
         // Don't set to Connected here - let network_status() check the actual state
 
         Ok(())
     }
 
+    /// Attach `backend` as the VirtIO NIC, reported to the guest as
+    /// network `name`.
+    pub(crate) fn attach_network_backend(
+        &mut self,
+        backend: Box<dyn crate::net::NetworkBackend>,
+        name: &'static str,
+    ) {
+        let vnet = crate::devices::virtio::VirtioNet::new(backend);
+        self.bus.virtio_devices.push(Box::new(vnet));
+        self.bus.buildinfo.set_network(Some(name));
+    }
+
     /// Disconnect from the network.
     pub fn disconnect_network(&mut self) {
         // Remove VirtioNet devices (device_id == 1)
         self.bus.virtio_devices.retain(|dev| dev.device_id() != 1);
+        self.bus.buildinfo.set_network(None);
         self.net_status = NetworkStatus::Disconnected;
         self.external_net = None;
     }
@@ -354,6 +575,7 @@ impl WasmVm {
         // Create VirtIO network device
         let vnet = VirtioNet::new(Box::new(wrapper));
         self.bus.virtio_devices.push(Box::new(vnet));
+        self.bus.buildinfo.set_network(Some("external"));
 
         self.net_status = NetworkStatus::Connecting;
 
@@ -500,6 +722,9 @@ impl WasmVm {
                     "[VM] Worker signaled halt (code: {:#x})",
                     self.halt_code
                 )));
+                if self.halt_code != 0xDEAD {
+                    self.notify_exit(VmExit::from_code(self.halt_code));
+                }
                 return false;
             }
         }
@@ -531,21 +756,35 @@ impl WasmVm {
         self.poll_counter = self.poll_counter.wrapping_add(1);
         if self.poll_counter % 100 == 0 {
             self.bus.poll_virtio();
-
-            // Update shared CLINT timer (if in SMP mode)
-            if let Some(ref clint) = self.shared_clint {
-                // Increment mtime in shared memory
-                clint.tick(100); // 100 ticks per poll
-            }
+            self.notify_output();
+            self.notify_messages();
         }
 
         // Execute one instruction on hart 0 only
         // (Secondary harts run in workers)
         match self.cpu.step(&self.bus) {
             Ok(()) => {}
+            // Secondary harts run in workers that can't be restarted from
+            // here, so only a single-threaded VM reboots in place
+            Err(Trap::RequestedTrap(code))
+                if VmExit::from_code(code) == VmExit::Reboot && !self.workers_started =>
+            {
+                web_sys::console::log_1(&JsValue::from_str("[VM] Hart 0 requested reboot"));
+                self.notify_exit(VmExit::Reboot);
+                if let Err(e) = self.reboot() {
+                    web_sys::console::error_1(&JsValue::from_str(&format!(
+                        "[VM] Reboot failed: {}",
+                        e
+                    )));
+                    self.halted = true;
+                    self.halt_code = code;
+                    return false;
+                }
+            }
             Err(Trap::RequestedTrap(code)) => {
                 self.halted = true;
                 self.halt_code = code;
+                self.notify_exit(VmExit::from_code(code));
                 // Signal halt to workers
                 if let Some(ref control) = self.shared_control {
                     control.signal_halted(code);
@@ -556,11 +795,14 @@ impl WasmVm {
                 )));
                 return false;
             }
-            Err(Trap::Fatal(msg)) => {
+            Err(trap @ Trap::Fatal(_)) => {
+                let info = TrapInfo::capture(&trap, &self.cpu, &self.bus);
                 web_sys::console::error_1(&wasm_bindgen::JsValue::from_str(&format!(
-                    "[VM] Fatal error: {} at PC=0x{:x}",
-                    msg, self.cpu.pc
+                    "[VM] Fatal error: {}",
+                    info
                 )));
+                self.crash_dump = Some(CrashDump::capture(&info, &self.cpu, &self.bus));
+                self.fatal = Some(info);
                 self.halted = true;
                 if let Some(ref control) = self.shared_control {
                     control.signal_halted(0xDEAD);
@@ -751,6 +993,255 @@ impl WasmVm {
         count
     }
 
+    /// Run until `ms` milliseconds of guest time have passed, or the VM
+    /// halts. Guest time follows the cycles executed (see
+    /// `set_cpu_frequency`), so a frame loop calling `run_for_ms(16)` keeps
+    /// guest timers in step with the page whether blocks or the
+    /// interpreter run the code. Returns the number of steps executed.
+    pub fn run_for_ms(&mut self, ms: f64) -> u32 {
+        let ticks = (ms.max(0.0) * TIMEBASE_FREQUENCY as f64 / 1000.0) as u64;
+        let deadline = self.bus.clint.mtime().saturating_add(ticks);
+        let mut steps = 0u32;
+        while self.bus.clint.mtime() < deadline {
+            if !self.step() {
+                break;
+            }
+            steps = steps.wrapping_add(1);
+            // Nothing to run until the next interrupt: skip ahead to it
+            if self.idle
+                && let Some(ticks) = self.cpu.take_idle(&self.bus)
+            {
+                let left = deadline.saturating_sub(self.bus.clint.mtime());
+                self.cpu.skip_idle(&self.bus, ticks.min(left));
+            }
+        }
+        steps
+    }
+
+    /// Token that stops a pending `run_async` call.
+    pub fn run_token(&self) -> RunToken {
+        RunToken {
+            stop: Rc::clone(&self.stop),
+        }
+    }
+
+    /// Run without blocking the page: execute for about `slice_ms` of
+    /// wall-clock time (8 ms if 0), yield to the event loop, and repeat
+    /// until `max_steps` steps have run (0 for no limit), the VM halts or
+    /// a `RunToken` is stopped.
+    ///
+    /// While hart 0 idles in WFI with no interrupt pending, the run waits
+    /// for its next timer deadline (at most 10 ms at a time) instead of
+    /// spinning, and guest time skips ahead by the wait; see `set_idle`.
+    /// With `set_max_mips`, a slice ends early once it has run its share
+    /// of instructions and the rest of it is spent waiting.
+    ///
+    /// After each slice `on_progress` is called with
+    /// `{ steps, cycles, halted, output }`, where `output` is a
+    /// `Uint8Array` of the UART bytes produced since the previous call:
+    /// the VM is borrowed until the promise settles, so this is how the
+    /// page reads the console meanwhile. Input has to be queued before the
+    /// call. Resolves to the number of steps executed; a stopped run
+    /// resolves too, and an exception thrown by `on_progress` rejects.
+    pub async fn run_async(
+        &mut self,
+        max_steps: f64,
+        slice_ms: f64,
+        on_progress: Option<js_sys::Function>,
+    ) -> Result<f64, JsValue> {
+        /// Steps between clock reads
+        const BATCH: u64 = 4096;
+        self.stop.set(false);
+        let limit = if max_steps >= 1.0 {
+            max_steps as u64
+        } else {
+            u64::MAX
+        };
+        let slice_ms = if slice_ms > 0.0 { slice_ms } else { 8.0 };
+        let budget = self
+            .max_mips
+            .map_or(u64::MAX, |mips| (mips as f64 * 1000.0 * slice_ms) as u64);
+        let mut steps = 0u64;
+        while steps < limit && !self.halted && !self.stop.get() {
+            let deadline = js_sys::Date::now() + slice_ms;
+            let mut slice_steps = 0u64;
+            loop {
+                let batch = (limit - steps).min(budget - slice_steps).min(BATCH) as u32;
+                let done = self.step_until_idle(batch);
+                steps += done as u64;
+                slice_steps += done as u64;
+                if done < batch
+                    || steps >= limit
+                    || slice_steps >= budget
+                    || js_sys::Date::now() >= deadline
+                {
+                    break;
+                }
+            }
+            if let Some(callback) = &on_progress {
+                callback.call1(&JsValue::NULL, &self.progress(steps))?;
+            }
+            let delay_ms = match self.idle_wait() {
+                Some(ms) => ms,
+                None if slice_steps >= budget => (deadline - js_sys::Date::now()).max(0.0),
+                None => 0.0,
+            };
+            yield_to_event_loop(delay_ms).await?;
+        }
+        Ok(steps as f64)
+    }
+
+    /// `step_n`, but stops after a WFI if idling is enabled.
+    fn step_until_idle(&mut self, count: u32) -> u32 {
+        for i in 0..count {
+            if !self.step() {
+                return i;
+            }
+            if self.idle && self.cpu.in_wfi() {
+                return i + 1;
+            }
+        }
+        count
+    }
+
+    /// If hart 0 is idle in WFI, let guest time pass up to its next timer
+    /// deadline (at most 10 ms) and return how long the page should wait.
+    fn idle_wait(&mut self) -> Option<f64> {
+        if !self.idle {
+            return None;
+        }
+        let ticks = self.cpu.take_idle(&self.bus)?.min(MAX_IDLE_TICKS);
+        self.cpu.skip_idle(&self.bus, ticks);
+        Some(ticks as f64 * 1000.0 / TIMEBASE_FREQUENCY as f64)
+    }
+
+    /// Progress report passed to `run_async`'s callback.
+    fn progress(&mut self, steps: u64) -> JsValue {
+        let output = self.drain_output();
+        let report = js_sys::Object::new();
+        let fields = [
+            ("steps", JsValue::from(steps as f64)),
+            ("cycles", JsValue::from(self.cpu.cycles as f64)),
+            ("halted", JsValue::from(self.halted)),
+            ("output", js_sys::Uint8Array::from(&output[..]).into()),
+        ];
+        for (key, value) in fields {
+            let _ = js_sys::Reflect::set(&report, &JsValue::from_str(key), &value);
+        }
+        report.into()
+    }
+
+    /// Whether `run_async` and `run_for_ms` idle while hart 0 waits in
+    /// WFI with nothing pending (the default), rather than spinning through
+    /// the guest's idle loop.
+    pub fn set_idle(&mut self, enabled: bool) {
+        self.idle = enabled;
+    }
+
+    /// Run hart 0 on the predecoded block engine instead of interpreting
+    /// one instruction per step (off by default). Guest time and the
+    /// instructions retired are the same either way, but a step then runs
+    /// a whole block, so step counts passed to `step_n` and `run_async`
+    /// cover more instructions.
+    pub fn set_block_engine(&mut self, enabled: bool) {
+        self.cpu.use_blocks = enabled;
+    }
+
+    /// Have hart 0 raise an illegal-instruction exception on CSRs it does
+    /// not implement, instead of reading them as zero and ignoring writes
+    /// (off by default).
+    pub fn set_strict_csrs(&mut self, enabled: bool) {
+        self.cpu.set_csr_policy(if enabled {
+            cpu::CsrPolicy::Strict
+        } else {
+            cpu::CsrPolicy::Permissive
+        });
+    }
+
+    /// Cap `run_async` at `mips` million instructions per second, or lift
+    /// the cap with 0.
+    pub fn set_max_mips(&mut self, mips: u32) {
+        self.max_mips = (mips > 0).then_some(mips);
+    }
+
+    /// Set the modelled CPU clock in Hz (100 MHz by default). Guest time
+    /// advances by one second per `hz` cycles executed by hart 0.
+    pub fn set_cpu_frequency(&self, hz: u32) {
+        self.bus.clint.set_cpu_frequency(hz as u64);
+    }
+
+    /// Keep the host clock out of guest time: the RTC counts from
+    /// 2000-01-01 by guest time instead of reporting `Date.now()`. Guest
+    /// time already follows the cycles hart 0 executes, so a single-hart
+    /// run then behaves the same on every host and at every speed.
+    pub fn set_deterministic_time(&self, enabled: bool) {
+        self.bus
+            .rtc
+            .set_deterministic(enabled.then_some(DETERMINISTIC_EPOCH_NS));
+    }
+
+    /// Trace hart 0 into a ring buffer of the last `capacity` instructions,
+    /// read with `take_trace`. Hart 0 runs in the interpreter while tracing.
+    pub fn start_trace(&mut self, capacity: u32) {
+        let sink = cpu::TraceSink::ring(capacity as usize);
+        self.cpu.set_tracer(Some(cpu::Tracer::new(sink)));
+    }
+
+    /// Trace hart 0, calling `callback(line)` for every instruction.
+    pub fn start_trace_callback(&mut self, callback: js_sys::Function) {
+        let callback = TraceCallback(callback);
+        let sink = cpu::TraceSink::Callback(Box::new(move |record| callback.call(record)));
+        self.cpu.set_tracer(Some(cpu::Tracer::new(sink)));
+    }
+
+    /// Only trace instructions in `[pc_start, pc_end)` and/or executed in
+    /// `mode` ("M", "S" or "U"); `undefined` lifts a restriction.
+    pub fn set_trace_filter(
+        &mut self,
+        pc_start: Option<u64>,
+        pc_end: Option<u64>,
+        mode: Option<String>,
+    ) -> Result<(), JsValue> {
+        let pc_range = match (pc_start, pc_end) {
+            (Some(start), Some(end)) => Some(start..end),
+            (None, None) => None,
+            _ => return Err(JsValue::from_str("pc_start and pc_end go together")),
+        };
+        let mode = mode
+            .map(|m| m.parse::<cpu::Mode>())
+            .transpose()
+            .map_err(|e| JsValue::from_str(&e))?;
+        let tracer = self
+            .cpu
+            .tracer_mut()
+            .ok_or_else(|| JsValue::from_str("tracing is not started"))?;
+        tracer.set_filter(cpu::TraceFilter { pc_range, mode });
+        Ok(())
+    }
+
+    /// Pause or resume tracing without dropping buffered records.
+    pub fn set_trace_enabled(&mut self, enabled: bool) {
+        if let Some(tracer) = self.cpu.tracer_mut() {
+            tracer.set_enabled(enabled);
+        }
+    }
+
+    /// Stop tracing and discard the tracer.
+    pub fn stop_trace(&mut self) {
+        self.cpu.set_tracer(None);
+    }
+
+    /// Take the buffered trace lines, oldest first.
+    pub fn take_trace(&mut self) -> js_sys::Array {
+        let lines = js_sys::Array::new();
+        if let Some(tracer) = self.cpu.tracer_mut() {
+            for record in tracer.drain() {
+                lines.push(&JsValue::from_str(&record.to_string()));
+            }
+        }
+        lines
+    }
+
     /// Check if the VM has halted (e.g., due to shutdown command).
     pub fn is_halted(&self) -> bool {
         self.halted
@@ -762,6 +1253,202 @@ impl WasmVm {
         self.halt_code
     }
 
+    /// How the guest ended the run, as `{ reason, status }` with `reason`
+    /// one of `"poweroff"`, `"reboot"` or `"fail"` and `status` the
+    /// failure status (0 otherwise); `null` while it runs or after a fatal
+    /// error. A reboot only ends the run in SMP mode, where the page has to
+    /// create a new VM.
+    pub fn exit(&self) -> JsValue {
+        self.exit.map_or(JsValue::NULL, vm_exit_to_js)
+    }
+
+    /// Call `callback(exit)` each time the guest powers off, fails or
+    /// reboots, with `exit` as returned by [`exit`](Self::exit), so the
+    /// page does not have to poll for it; `undefined` removes the
+    /// callback.
+    pub fn set_exit_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.exit_callback = callback;
+    }
+
+    /// Record `exit` and tell the exit callback, if one is set.
+    fn notify_exit(&mut self, exit: VmExit) {
+        if exit != VmExit::Reboot || self.workers_started {
+            self.exit = Some(exit);
+        }
+        self.notify_output();
+        self.notify_messages();
+        if let Some(callback) = &self.exit_callback {
+            // A throwing callback must not stop the guest
+            let _ = callback.call1(&JsValue::NULL, &vm_exit_to_js(exit));
+        }
+    }
+
+    /// Reset hart 0 and the devices and load the kernel again, for a
+    /// guest reboot. DRAM is not cleared and disks keep what the guest
+    /// wrote.
+    fn reboot(&mut self) -> Result<(), String> {
+        self.bus.reset_devices();
+        if self.kernel.starts_with(b"\x7FELF") {
+            load_elf_wasm(&self.kernel, &self.bus)?;
+        } else {
+            self.bus
+                .dram
+                .load(&self.kernel, 0)
+                .map_err(|e| format!("Failed to load kernel: {}", e))?;
+        }
+
+        let mut cpu = cpu::Cpu::new(self.bus.boot_rom.reset_vector(), 0);
+        cpu.cycles = self.cpu.cycles;
+        cpu.use_blocks = self.cpu.use_blocks;
+        if let Some(tracer) = self.cpu.take_tracer() {
+            cpu.set_tracer(Some(tracer));
+        }
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    /// The error the VM halted on, as an object with `name`, `message`,
+    /// `cause` (null for host-level errors), `interrupt`, `tval`, `mode`
+    /// (`"M"`, `"S"` or `"U"`), `pc`, `insn` (the instruction bytes at
+    /// `pc`) and `taken`; `null` if the VM has not hit one. `tval` and
+    /// `pc` are BigInts.
+    pub fn fatal_error(&self) -> JsValue {
+        self.fatal.as_ref().map_or(JsValue::NULL, trap_info_to_js)
+    }
+
+    /// Crash dump of hart 0 (see [`crate::crash_dump`]) taken when it
+    /// halted on a fatal error, as JSON bytes ready to offer as a download
+    /// (`new Blob([bytes], { type: "application/json" })`); `undefined` if
+    /// it has not.
+    pub fn crash_dump(&self) -> Option<Vec<u8>> {
+        let dump = self.crash_dump.as_ref()?;
+        dump.to_json().ok().map(String::into_bytes)
+    }
+
+    /// Stream a snapshot of hart 0, the devices and DRAM (see
+    /// [`crate::snapshot`]) to `on_chunk`, called with a `Uint8Array` for
+    /// each chunk of at most about 1 MiB, e.g. to put each in its own
+    /// IndexedDB record. DRAM is never copied whole. `compression` is
+    /// `"none"` (the default), `"lz4"` or `"zstd"`. Single hart only.
+    pub fn snapshot_chunks(
+        &self,
+        on_chunk: &js_sys::Function,
+        compression: Option<String>,
+    ) -> Result<(), JsValue> {
+        if self.num_harts != 1 {
+            return Err(JsValue::from_str("snapshots need a single hart"));
+        }
+        let compression = match compression {
+            Some(name) => name.parse().map_err(|e: String| JsValue::from_str(&e))?,
+            None => Compression::None,
+        };
+        Snapshot::write_stream(
+            &self.cpu,
+            &self.bus,
+            &mut JsChunkWriter(on_chunk),
+            compression,
+        )
+        .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Restore a snapshot saved with `snapshot_chunks`. `next_chunk()` is
+    /// called for its bytes until the snapshot is complete and returns
+    /// each stored chunk as a `Uint8Array` in order (`undefined` if there
+    /// are no more). The DRAM size must match. If it fails the machine
+    /// holds part of the snapshot and should be restored again.
+    pub fn restore_snapshot_chunks(
+        &mut self,
+        next_chunk: &js_sys::Function,
+    ) -> Result<(), JsValue> {
+        if self.num_harts != 1 {
+            return Err(JsValue::from_str("snapshots need a single hart"));
+        }
+        let mut input = JsChunkReader {
+            next: next_chunk,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        restore_stream(&mut input, &mut self.cpu, &self.bus).map_err(|e| JsValue::from_str(&e))?;
+        self.halted = false;
+        self.halt_code = 0;
+        self.exit = None;
+        self.fatal = None;
+        self.crash_dump = None;
+        Ok(())
+    }
+
+    /// Hand the stopped VM over to another host, native or in a browser,
+    /// through the relay at `relay_url` (see [`crate::migration`]).
+    /// `on_ticket` is called with the ticket to give the new host; the
+    /// promise resolves once the new host has the VM, which must not run
+    /// here again. Single hart only.
+    pub async fn migrate_out(
+        &mut self,
+        relay_url: String,
+        cert_hash: Option<String>,
+        on_ticket: js_sys::Function,
+    ) -> Result<(), JsValue> {
+        if self.num_harts != 1 {
+            return Err(JsValue::from_str("migration needs a single hart"));
+        }
+        let image = migration::capture(&self.cpu, &self.bus, Compression::Lz4)
+            .map_err(|e| JsValue::from_str(&e))?;
+        let (header, _) = MigrationHeader::read(&image).map_err(|e| JsValue::from_str(&e))?;
+        let key = migration::relay_key(&self.bus);
+        let on_ticket = |ticket: &str| {
+            let _ = on_ticket.call1(&JsValue::NULL, &JsValue::from_str(ticket));
+        };
+        crate::net::migration::send(
+            &relay_url,
+            cert_hash.as_deref(),
+            image,
+            header.mac,
+            key.as_deref(),
+            &on_ticket,
+        )
+        .await
+        .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Carry on with the VM of the migration `ticket`, fetched through the
+    /// relay at `relay_url`, instead of booting. Attach the disk and other
+    /// devices as on the old host first, but not the network: if the VM
+    /// had a NIC, one connected to `relay_url` is attached last with its
+    /// old MAC address, and the relay gives it its old IP address. The
+    /// kernel and memory size must match the old host's. Single hart only.
+    pub async fn migrate_in(
+        &mut self,
+        relay_url: String,
+        cert_hash: Option<String>,
+        ticket: String,
+    ) -> Result<(), JsValue> {
+        use crate::devices::virtio::VirtioNet;
+        use crate::net::webtransport::WebTransportBackend;
+
+        if self.num_harts != 1 {
+            return Err(JsValue::from_str("migration needs a single hart"));
+        }
+        let image = crate::net::migration::receive(&relay_url, cert_hash.as_deref(), &ticket)
+            .await
+            .map_err(|e| JsValue::from_str(&e))?;
+        let (header, _) = MigrationHeader::read(&image).map_err(|e| JsValue::from_str(&e))?;
+        if let Some(mac) = header.mac {
+            self.net_status = NetworkStatus::Connecting;
+            let backend = WebTransportBackend::resume(&relay_url, cert_hash, mac, ticket);
+            self.bus
+                .virtio_devices
+                .push(Box::new(VirtioNet::new(Box::new(backend))));
+            self.bus.buildinfo.set_network(Some("webtransport"));
+        }
+        migration::restore(&image, &mut self.cpu, &self.bus).map_err(|e| JsValue::from_str(&e))?;
+        self.halted = false;
+        self.halt_code = 0;
+        self.exit = None;
+        self.fatal = None;
+        self.crash_dump = None;
+        Ok(())
+    }
+
     /// Get a byte from the UART output buffer, if available.
     ///
     /// In SMP mode, this checks both the shared UART output buffer (for worker output)
@@ -784,6 +1471,77 @@ impl WasmVm {
         byte
     }
 
+    /// Take all pending UART output at once: worker output first, then
+    /// hart 0's, as `get_output` would return it byte by byte.
+    pub fn drain_output(&mut self) -> Vec<u8> {
+        let mut output = match self.shared_uart_output {
+            Some(ref shared_uart) => shared_uart.read_bytes(usize::MAX),
+            None => Vec::new(),
+        };
+        output.extend(self.bus.uart.drain_output());
+        output
+    }
+
+    /// Call `callback(bytes)` with a `Uint8Array` of new UART output each
+    /// time hart 0 polls its devices and output is pending, so the page
+    /// does not have to poll for it; `undefined` removes the callback.
+    /// Output handed to the callback is not returned by `get_output`,
+    /// `drain_output` or `run_async` progress reports.
+    pub fn set_output_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.output_callback = callback;
+    }
+
+    /// Hand pending output to the output callback, if one is set.
+    fn notify_output(&mut self) {
+        let Some(callback) = self.output_callback.clone() else {
+            return;
+        };
+        let output = self.drain_output();
+        if !output.is_empty() {
+            // A throwing callback must not stop the guest
+            let _ = callback.call1(&JsValue::NULL, &js_sys::Uint8Array::from(&output[..]));
+        }
+    }
+
+    /// Send `message` to the guest through the mailbox device (see
+    /// [`crate::devices::mailbox`]). Fails if it is longer than 4 KiB or
+    /// the guest has fallen too far behind reading earlier ones.
+    pub fn post_message(&self, message: &[u8]) -> Result<(), JsValue> {
+        self.bus
+            .mailbox
+            .port()
+            .post(message)
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.bus.events.notify();
+        Ok(())
+    }
+
+    /// Call `callback(bytes)` with a `Uint8Array` for each message the
+    /// guest sends through the mailbox, as hart 0 polls its devices;
+    /// `undefined` removes the callback. Without one, messages wait for
+    /// `take_guest_message`.
+    pub fn on_guest_message(&mut self, callback: Option<js_sys::Function>) {
+        self.message_callback = callback;
+    }
+
+    /// Take the oldest mailbox message from the guest that no callback
+    /// has received.
+    pub fn take_guest_message(&self) -> Option<Vec<u8>> {
+        self.bus.mailbox.port().take()
+    }
+
+    /// Hand the guest's messages to the message callback, if one is set.
+    fn notify_messages(&mut self) {
+        let Some(callback) = self.message_callback.clone() else {
+            return;
+        };
+        let port = self.bus.mailbox.port();
+        while let Some(message) = port.take() {
+            // A throwing callback must not stop the guest
+            let _ = callback.call1(&JsValue::NULL, &js_sys::Uint8Array::from(&message[..]));
+        }
+    }
+
     /// Check how many bytes are pending in the UART output buffer.
     /// Useful for debugging output issues.
     pub fn uart_output_pending(&self) -> usize {
@@ -852,6 +1610,49 @@ impl WasmVm {
         }
     }
 
+    /// Push a run of input bytes to the UART, e.g. a pasted string, in one
+    /// call. Like `input`, also feeds the workers in SMP mode.
+    pub fn write_input(&mut self, bytes: &[u8]) {
+        self.bus.uart.push_input_bytes(bytes);
+        if let Some(ref shared_input) = self.shared_uart_input {
+            let _ = shared_input.write_bytes(bytes);
+        }
+    }
+
+    /// Read `len` bytes of guest physical memory (DRAM only).
+    pub fn read_phys(&self, paddr: u64, len: u32) -> Result<Vec<u8>, JsValue> {
+        let mut buf = vec![0; len as usize];
+        guest_mem::read_phys(&self.bus, paddr, &mut buf).map_err(|e| JsValue::from_str(&e))?;
+        Ok(buf)
+    }
+
+    /// Write bytes to guest physical memory (DRAM only).
+    pub fn write_phys(&mut self, paddr: u64, data: &[u8]) -> Result<(), JsValue> {
+        guest_mem::write_phys(&mut self.cpu, &self.bus, paddr, data)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Read `len` bytes at guest virtual address `vaddr`, translated with
+    /// `satp`, or with hart 0's current address space if omitted.
+    pub fn read_virt(&self, vaddr: u64, len: u32, satp: Option<u64>) -> Result<Vec<u8>, JsValue> {
+        let mut buf = vec![0; len as usize];
+        guest_mem::read_virt(&self.cpu, &self.bus, vaddr, &mut buf, satp.into())
+            .map_err(|e| JsValue::from_str(&e))?;
+        Ok(buf)
+    }
+
+    /// Write bytes at guest virtual address `vaddr`, translated like
+    /// `read_virt`.
+    pub fn write_virt(
+        &mut self,
+        vaddr: u64,
+        data: &[u8],
+        satp: Option<u64>,
+    ) -> Result<(), JsValue> {
+        guest_mem::write_virt(&mut self.cpu, &self.bus, vaddr, data, satp.into())
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
     /// Get current memory usage (DRAM size) in bytes.
     pub fn get_memory_usage(&self) -> u64 {
         self.bus.dram_size() as u64
@@ -877,6 +1678,11 @@ impl WasmVm {
         arr
     }
 
+    /// Get the kernel's `uname -a` line, empty until the guest reports it.
+    pub fn get_kernel_id(&self) -> String {
+        self.bus.sysinfo.kernel_id()
+    }
+
     /// Get the total disk capacity from attached VirtIO block devices.
     /// Returns total bytes across all block devices.
     pub fn get_disk_capacity(&self) -> u64 {
@@ -905,4 +1711,138 @@ impl WasmVm {
     pub fn get_uptime_ms(&self) -> u64 {
         self.bus.sysinfo.uptime_ms()
     }
+
+    /// Framebuffer width in pixels.
+    pub fn framebuffer_width(&self) -> u32 {
+        self.bus.framebuffer.width()
+    }
+
+    /// Framebuffer height in pixels.
+    pub fn framebuffer_height(&self) -> u32 {
+        self.bus.framebuffer.height()
+    }
+
+    /// Get the framebuffer as RGBA bytes, ready for `new ImageData(...)`.
+    ///
+    /// Only stores made by hart 0 are visible here; in SMP mode each worker
+    /// decodes the framebuffer region on its own bus.
+    pub fn get_framebuffer(&self) -> Vec<u8> {
+        self.bus.framebuffer.rgba()
+    }
+
+    /// Regions drawn since the last call, flattened as `[x, y, w, h, ...]`.
+    /// Empty when the canvas is already up to date.
+    pub fn get_framebuffer_dirty_rects(&self) -> Vec<u32> {
+        self.bus
+            .framebuffer
+            .take_dirty_rects()
+            .iter()
+            .flat_map(|r| [r.x, r.y, r.width, r.height])
+            .collect()
+    }
+
+    /// Queue a key press or release for the input device.
+    /// Returns false if the queue is full.
+    pub fn push_key_event(&self, code: u16, pressed: bool) -> bool {
+        self.bus.input.push(InputEvent::Key { code, pressed })
+    }
+
+    /// Queue an absolute pointer position (in framebuffer pixels).
+    pub fn push_mouse_move(&self, x: u16, y: u16) -> bool {
+        self.bus.input.push(InputEvent::MouseMove { x, y })
+    }
+
+    /// Queue a mouse button press or release.
+    pub fn push_mouse_button(&self, button: u16, pressed: bool) -> bool {
+        self.bus
+            .input
+            .push(InputEvent::MouseButton { button, pressed })
+    }
+
+    /// Raise external interrupt line `irq` (1-31) through the PLIC, for a
+    /// device implemented in JS. The line stays pending until `clear_irq`.
+    pub fn raise_irq(&self, irq: u32) -> Result<(), JsValue> {
+        self.bus.raise_irq(irq).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Lower an external interrupt line raised with `raise_irq`.
+    pub fn clear_irq(&self, irq: u32) -> Result<(), JsValue> {
+        self.bus.clear_irq(irq).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Attach a VirtIO GPU with `num_scanouts` displays of `width` x `height`.
+    /// Must be called before the guest probes its devices.
+    pub fn attach_gpu(
+        &mut self,
+        num_scanouts: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), JsValue> {
+        if self.gpu.is_some() {
+            return Err(JsValue::from_str("A GPU is already attached"));
+        }
+        let modes = vec![(width, height); num_scanouts as usize];
+        let gpu = VirtioGpu::new(&modes).map_err(|e| JsValue::from_str(&e))?;
+        self.gpu = Some(gpu.display());
+        self.bus.virtio_devices.push(Box::new(gpu));
+        Ok(())
+    }
+
+    /// Share a virtual directory with the guest as a VirtIO 9p device with
+    /// mount tag `tag`. `handler` implements the directory through
+    /// synchronous callbacks (`stat`, `list`, `read`, `write`, ...), e.g.
+    /// over OPFS or IndexedDB; see [`crate::share::JsShare`]. Must be
+    /// called before the guest probes its devices.
+    pub fn attach_share(&mut self, tag: &str, handler: js_sys::Object) -> Result<(), JsValue> {
+        let backend = crate::share::JsShare::new(handler);
+        let p9 = Virtio9p::new(tag, Box::new(backend)).map_err(|e| JsValue::from_str(&e))?;
+        self.bus.virtio_devices.push(Box::new(p9));
+        Ok(())
+    }
+
+    /// Number of GPU scanouts, 0 without a GPU.
+    pub fn gpu_scanout_count(&self) -> u32 {
+        self.gpu
+            .as_ref()
+            .map_or(0, |gpu| gpu.scanout_count() as u32)
+    }
+
+    /// Width of a GPU scanout in pixels, 0 if it doesn't exist.
+    pub fn gpu_scanout_width(&self, scanout: u32) -> u32 {
+        self.gpu_scanout_size(scanout).0
+    }
+
+    /// Height of a GPU scanout in pixels, 0 if it doesn't exist.
+    pub fn gpu_scanout_height(&self, scanout: u32) -> u32 {
+        self.gpu_scanout_size(scanout).1
+    }
+
+    /// Get a GPU scanout as RGBA bytes, ready for `new ImageData(...)`.
+    /// The size can change whenever the guest reprograms the scanout, so
+    /// read it alongside.
+    pub fn get_gpu_scanout(&self, scanout: u32) -> Vec<u8> {
+        self.gpu
+            .as_ref()
+            .and_then(|gpu| gpu.rgba(scanout as usize))
+            .unwrap_or_default()
+    }
+
+    /// Regions of a GPU scanout flushed since the last call, flattened as
+    /// `[x, y, w, h, ...]`.
+    pub fn get_gpu_scanout_dirty_rects(&self, scanout: u32) -> Vec<u32> {
+        let Some(gpu) = &self.gpu else {
+            return Vec::new();
+        };
+        gpu.take_damage(scanout as usize)
+            .iter()
+            .flat_map(|r| [r.x, r.y, r.width, r.height])
+            .collect()
+    }
+
+    fn gpu_scanout_size(&self, scanout: u32) -> (u32, u32) {
+        self.gpu
+            .as_ref()
+            .and_then(|gpu| gpu.size(scanout as usize))
+            .unwrap_or((0, 0))
+    }
 }
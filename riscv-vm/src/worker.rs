@@ -17,6 +17,16 @@
 //! - Control region (4KB): halt flags, hart count
 //! - CLINT region (64KB): mtime, msip[], mtimecmp[]
 //! - DRAM region: kernel memory
+//!
+//! ## VM Pools
+//!
+//! The same worker script can instead host a whole VM, so one wasm bundle
+//! runs several VMs side by side. [`VmPool`] is the supervisor on the main
+//! thread: it creates and destroys VMs, each on its own worker running a
+//! [`PoolMember`], and reads and writes their consoles. The pool buffer
+//! (see [`crate::shared_mem::PoolBuffer`]) carries the consoles and the
+//! pool's Ethernet segment, so frames go from one VM's NIC to another's
+//! receive ring without a JavaScript hop.
 
 #[cfg(target_arch = "wasm32")]
 use crate::Trap;
@@ -25,6 +35,8 @@ use crate::bus::SystemBus;
 #[cfg(target_arch = "wasm32")]
 use crate::cpu::Cpu;
 #[cfg(target_arch = "wasm32")]
+use crate::devices::bootrom::{BootConfig, RESET_VECTOR};
+#[cfg(target_arch = "wasm32")]
 use crate::shared_mem::{
     self,
     wasm::{SharedClint, SharedControl},
@@ -84,8 +96,13 @@ impl WorkerState {
         // Workers read from shared UART input (is_worker = true)
         let bus = SystemBus::from_shared_buffer(sab, dram_offset, shared_clint_for_bus, true);
 
-        // Create CPU for this hart
-        let cpu = Cpu::new(entry_pc, hart_id as u64);
+        // Each worker has its own bus view, so fill its boot ROM mailbox too
+        let stack_top = bus.dram_base() + bus.dram_size() as u64;
+        bus.boot_rom
+            .configure(&BootConfig::new(entry_pc, stack_top));
+
+        // Create CPU for this hart; it starts in the boot ROM
+        let cpu = Cpu::new(RESET_VECTOR, hart_id as u64);
 
         web_sys::console::log_1(&JsValue::from_str(&format!(
             "[Worker {}] Initialized at PC=0x{:x}",
@@ -224,6 +241,12 @@ impl WorkerState {
     pub fn hart_id(&self) -> usize {
         self.hart_id
     }
+
+    /// Run this hart on the predecoded block engine, as
+    /// `WasmVm::set_block_engine` does for hart 0.
+    pub fn set_block_engine(&mut self, enabled: bool) {
+        self.cpu.use_blocks = enabled;
+    }
 }
 
 /// Legacy worker entry point - DEPRECATED.
@@ -276,6 +299,245 @@ pub fn worker_check_interrupts(hart_id: usize, shared_mem: JsValue) -> u64 {
     mip
 }
 
+/// Console bytes a [`PoolMember`] takes from its input ring per slice
+#[cfg(target_arch = "wasm32")]
+const POOL_INPUT_CHUNK: usize = 256;
+
+/// Supervisor of VMs running one per worker.
+///
+/// VM ids are pool slot numbers. Each VM gets the NIC address
+/// `52:54:00:50:00:<id>` and the address `10.0.3.<id + 2>`.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub struct VmPool {
+    pool: shared_mem::PoolBuffer,
+    buffer: SharedArrayBuffer,
+    worker_url: String,
+    /// Worker of each slot in use
+    workers: Vec<Option<web_sys::Worker>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl VmPool {
+    /// Pool of up to `max_vms` VMs whose workers load `worker_url`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(worker_url: &str, max_vms: usize) -> Result<VmPool, JsValue> {
+        let slots = max_vms.clamp(1, shared_mem::POOL_MAX_VMS);
+        let buffer = SharedArrayBuffer::new(shared_mem::pool_buffer_size(slots) as u32);
+        let words =
+            shared_mem::SharedWords::from_buffer(&buffer).map_err(|e| JsValue::from_str(&e))?;
+        let pool = shared_mem::PoolBuffer::init(words).map_err(|e| JsValue::from_str(&e))?;
+        Ok(VmPool {
+            pool,
+            buffer,
+            worker_url: worker_url.to_string(),
+            workers: vec![None; slots],
+        })
+    }
+
+    /// Start a VM booting `kernel` on a new worker. Returns its id.
+    pub fn create(&mut self, kernel: &[u8]) -> Result<u32, JsValue> {
+        let slot = self.pool.claim().ok_or_else(|| {
+            JsValue::from_str(&format!("pool is full ({} VMs)", self.pool.slot_count()))
+        })?;
+        self.pool
+            .set_mac(slot, [0x52, 0x54, 0x00, 0x50, 0x00, slot as u8]);
+
+        let opts = web_sys::WorkerOptions::new();
+        opts.set_type(web_sys::WorkerType::Module);
+        let worker = match web_sys::Worker::new_with_options(&self.worker_url, &opts) {
+            Ok(worker) => worker,
+            Err(e) => {
+                self.pool.release(slot);
+                return Err(JsValue::from_str(&format!(
+                    "Failed to create worker: {:?}",
+                    e
+                )));
+            }
+        };
+
+        // Free the slot once a destroyed VM's worker has stopped
+        let pool = self.pool.clone();
+        let handle = worker.clone();
+        let onmessage =
+            wasm_bindgen::closure::Closure::wrap(Box::new(move |event: web_sys::MessageEvent| {
+                let kind = js_sys::Reflect::get(&event.data(), &JsValue::from_str("type"))
+                    .ok()
+                    .and_then(|v| v.as_string());
+                if kind.as_deref() == Some("exited") && pool.stop_requested(slot) {
+                    handle.terminate();
+                    pool.release(slot);
+                }
+            }) as Box<dyn FnMut(_)>);
+        worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+
+        let init_msg = js_sys::Object::new();
+        let set = |key: &str, value: &JsValue| {
+            js_sys::Reflect::set(&init_msg, &JsValue::from_str(key), value).map(|_| ())
+        };
+        set("type", &JsValue::from_str("pool"))?;
+        set("pool", &self.buffer)?;
+        set("slot", &JsValue::from(slot as u32))?;
+        set("kernel", &js_sys::Uint8Array::from(kernel))?;
+        if let Err(e) = worker.post_message(&init_msg) {
+            worker.terminate();
+            self.pool.release(slot);
+            return Err(JsValue::from_str(&format!(
+                "Failed to send init message: {:?}",
+                e
+            )));
+        }
+        self.workers[slot] = Some(worker);
+        Ok(slot as u32)
+    }
+
+    /// Stop VM `id` and free its slot. A running VM is stopped at its next
+    /// slice; its slot is reused once its worker has exited.
+    pub fn destroy(&mut self, id: u32) {
+        let slot = id as usize;
+        let Some(worker) = self.workers.get_mut(slot).and_then(Option::take) else {
+            return;
+        };
+        if self.pool.state(slot) == shared_mem::SlotState::Exited {
+            worker.terminate();
+            self.pool.release(slot);
+        } else {
+            self.pool.request_stop(slot);
+        }
+    }
+
+    /// Ids of the VMs created and not destroyed
+    pub fn ids(&self) -> Vec<u32> {
+        (0..self.workers.len())
+            .filter(|&slot| self.workers[slot].is_some())
+            .map(|slot| slot as u32)
+            .collect()
+    }
+
+    /// State of VM `id`: 0 free, 1 starting, 2 running, 3 exited.
+    pub fn state(&self, id: u32) -> u32 {
+        match (id as usize) < self.pool.slot_count() {
+            true => self.pool.state(id as usize) as u32,
+            false => shared_mem::SlotState::Free as u32,
+        }
+    }
+
+    /// Halt code of an exited VM
+    pub fn exit_code(&self, id: u32) -> u64 {
+        match (id as usize) < self.pool.slot_count() {
+            true => self.pool.exit_code(id as usize),
+            false => 0,
+        }
+    }
+
+    /// Take the console output VM `id` produced since the last call.
+    pub fn read_console(&self, id: u32) -> Vec<u8> {
+        match (id as usize) < self.pool.slot_count() {
+            true => self.pool.console_out(id as usize).pop_bytes(usize::MAX),
+            false => Vec::new(),
+        }
+    }
+
+    /// Queue console input for VM `id`. Returns the bytes accepted.
+    pub fn write_console(&self, id: u32, bytes: &[u8]) -> usize {
+        match (id as usize) < self.pool.slot_count() {
+            true => self.pool.console_in(id as usize).push_bytes(bytes),
+            false => 0,
+        }
+    }
+
+    /// Put an Ethernet frame on the pool's segment as if from outside it.
+    /// Returns the number of VMs it was delivered to.
+    pub fn send_frame(&self, frame: &[u8]) -> u32 {
+        self.pool.switch_frame(usize::MAX, frame) as u32
+    }
+
+    /// The pool buffer shared with the workers
+    pub fn buffer(&self) -> SharedArrayBuffer {
+        self.buffer.clone()
+    }
+}
+
+/// One pooled VM, run by its worker.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub struct PoolMember {
+    vm: crate::WasmVm,
+    pool: shared_mem::PoolBuffer,
+    slot: usize,
+    /// Console output the ring had no room for yet
+    output: Vec<u8>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl PoolMember {
+    /// Boot `kernel` as the VM of pool slot `slot`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(pool: JsValue, slot: u32, kernel: &[u8]) -> Result<PoolMember, JsValue> {
+        let sab: SharedArrayBuffer = pool.unchecked_into();
+        let words =
+            shared_mem::SharedWords::from_buffer(&sab).map_err(|e| JsValue::from_str(&e))?;
+        let pool = shared_mem::PoolBuffer::open(words).map_err(|e| JsValue::from_str(&e))?;
+        let slot = slot as usize;
+        if slot >= pool.slot_count() {
+            return Err(JsValue::from_str(&format!("pool has no slot {}", slot)));
+        }
+
+        let mut vm = match crate::WasmVm::new_with_harts(kernel, 1) {
+            Ok(vm) => vm,
+            Err(e) => {
+                pool.set_exited(slot, 0xDEAD);
+                return Err(e);
+            }
+        };
+        let ip = [10, 0, 3, slot as u8 + 2];
+        let backend = crate::net::pool::PoolBackend::new(pool.clone(), slot, Some(ip));
+        vm.attach_network_backend(Box::new(backend), "pool");
+        pool.set_state(slot, shared_mem::SlotState::Running);
+
+        Ok(PoolMember {
+            vm,
+            pool,
+            slot,
+            output: Vec::new(),
+        })
+    }
+
+    /// Run for `ms` milliseconds of guest time, moving console bytes in
+    /// and out. Returns false once the VM halted or the supervisor asked
+    /// it to stop; the worker should then report that it exited.
+    pub fn run_slice(&mut self, ms: f64) -> bool {
+        if self.pool.stop_requested(self.slot) {
+            self.pool.set_exited(self.slot, self.vm.halt_code());
+            return false;
+        }
+
+        let input = self.pool.console_in(self.slot).pop_bytes(POOL_INPUT_CHUNK);
+        if !input.is_empty() {
+            self.vm.write_input(&input);
+        }
+        self.vm.run_for_ms(ms);
+
+        self.output.extend(self.vm.drain_output());
+        let written = self.pool.console_out(self.slot).push_bytes(&self.output);
+        self.output.drain(..written);
+
+        if self.vm.is_halted() {
+            self.pool.set_exited(self.slot, self.vm.halt_code());
+            return false;
+        }
+        true
+    }
+
+    /// Pool slot (VM id) of this member
+    pub fn slot(&self) -> u32 {
+        self.slot as u32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // Worker tests require WASM environment
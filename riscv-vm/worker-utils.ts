@@ -39,10 +39,26 @@ export interface WorkerErrorMessage {
   error: string;
 }
 
+/** Message sent by a VmPool to run one pooled VM on a worker */
+export interface PoolInitMessage {
+  type: "pool";
+  /** Pool buffer shared by the supervisor and every pooled VM */
+  pool: SharedArrayBuffer;
+  slot: number;
+  kernel: Uint8Array;
+}
+
+/** Message sent when a pooled VM halted or was stopped */
+export interface PoolExitedMessage {
+  type: "exited";
+  slot: number;
+}
+
 export type WorkerOutboundMessage =
   | WorkerReadyMessage
   | WorkerHaltedMessage
-  | WorkerErrorMessage;
+  | WorkerErrorMessage
+  | PoolExitedMessage;
 
 // ============================================================================
 // Shared Memory Layout (must match shared_mem.rs)
@@ -17,11 +17,14 @@
  *
  * Main → Worker: WorkerInitMessage
  * Worker → Main: WorkerReadyMessage | WorkerHaltedMessage | WorkerErrorMessage
+ *
+ * A worker started by a `VmPool` instead receives a PoolInitMessage and
+ * runs a whole VM (see worker.rs), answering with PoolExitedMessage.
  */
 
 // Import WASM as embedded buffer (converted to base64 by tsup wasmPlugin)
 import wasmBuffer from "./pkg/riscv_vm_bg.wasm";
-import { initSync, WorkerState } from "./pkg/riscv_vm.js";
+import { initSync, PoolMember, WorkerState } from "./pkg/riscv_vm.js";
 
 // WorkerStepResult enum values (must match worker.rs)
 // wasm-bindgen exports enums as numeric values
@@ -64,10 +67,26 @@ export interface WorkerErrorMessage {
   error: string;
 }
 
+/** Message sent by a VmPool to run one pooled VM on this worker */
+export interface PoolInitMessage {
+  type: "pool";
+  /** Pool buffer shared by the supervisor and every pooled VM */
+  pool: SharedArrayBuffer;
+  slot: number;
+  kernel: Uint8Array;
+}
+
+/** Message sent when a pooled VM halted or was stopped */
+export interface PoolExitedMessage {
+  type: "exited";
+  slot: number;
+}
+
 export type WorkerOutboundMessage =
   | WorkerReadyMessage
   | WorkerHaltedMessage
-  | WorkerErrorMessage;
+  | WorkerErrorMessage
+  | PoolExitedMessage;
 
 // ============================================================================
 // Shared Memory Layout (must match shared_mem.rs)
@@ -86,7 +105,9 @@ let controlView: Int32Array | null = null;
 
 // Worker global scope type (avoids needing WebWorker lib which conflicts with DOM)
 interface WorkerGlobalScope {
-  onmessage: ((event: MessageEvent<WorkerInitMessage>) => void) | null;
+  onmessage:
+    | ((event: MessageEvent<WorkerInitMessage | PoolInitMessage>) => void)
+    | null;
   onerror: ((event: ErrorEvent) => void) | null;
   postMessage(message: WorkerOutboundMessage): void;
 }
@@ -179,6 +200,28 @@ function cleanup() {
   controlView = null;
 }
 
+/** Guest milliseconds a pooled VM runs between yields to the event loop */
+const POOL_SLICE_MS = 10;
+
+/** Run one VM of a VmPool until it halts or the supervisor stops it. */
+async function runPoolMember(data: PoolInitMessage) {
+  const { pool, slot, kernel } = data;
+  let member: PoolMember;
+  try {
+    member = new PoolMember(pool, slot, kernel);
+  } catch (e) {
+    console.error(`[Pool VM ${slot}] Boot failed:`, e);
+    self.postMessage({ type: "error", error: String(e) });
+    self.postMessage({ type: "exited", slot });
+    return;
+  }
+  while (member.run_slice(POOL_SLICE_MS)) {
+    await new Promise((resolve) => setTimeout(resolve, 0));
+  }
+  member.free();
+  self.postMessage({ type: "exited", slot });
+}
+
 self.onmessage = async (event: MessageEvent<WorkerInitMessage>) => {
   const data = event.data;
   
@@ -186,8 +229,17 @@ self.onmessage = async (event: MessageEvent<WorkerInitMessage>) => {
   if (!data || typeof data !== 'object' || 'source' in data) {
     return;
   }
-  
-  const { hartId, sharedMem, entryPc } = data;
+
+  if ("type" in data && data.type === "pool") {
+    if (!initialized) {
+      initSync(wasmBuffer);
+      initialized = true;
+    }
+    void runPoolMember(data);
+    return;
+  }
+
+  const { hartId, sharedMem, entryPc } = data as WorkerInitMessage;
   
   // Validate required fields
   if (hartId === undefined || !sharedMem || entryPc === undefined) {